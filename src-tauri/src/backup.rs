@@ -0,0 +1,614 @@
+//! Backups of the active profile's databases, taken on the interval in the settings or
+//! on request. Each backup is a directory named after the profile and the time it was
+//! taken, holding a copy of each database made with SQLite's online backup API, so
+//! it's consistent even while the app is writing to the database.
+//!
+//! Only the databases are backed up. The JSON settings files are small enough to be
+//! kept by the operator's own backups, and are left as they are by a restore.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime};
+use log::{debug, info, trace, warn};
+use meshtastic::ts::specta::{self, Type};
+use rusqlite::{backup::Progress, Connection, DatabaseName, OpenFlags};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::device::helpers::get_current_time_u32;
+use crate::device::message_store::write_pending_messages;
+use crate::device::telemetry_store::write_pending_telemetry;
+use crate::graph::store::write_changed_graphs;
+use crate::ipc::error_reporter::{AppErrorCode, ErrorReporter};
+use crate::ipc::helpers::disconnect_all_devices;
+use crate::ipc::reset::reset_analytics_state;
+use crate::persistence::{
+    settings_file_path, BACKUP_DIR_NAME, GRAPH_DATABASE_FILE_NAME, MESSAGE_DATABASE_FILE_NAME,
+    TELEMETRY_DATABASE_FILE_NAME,
+};
+use crate::profiles::{
+    active_profile_name, load_profile_state, profile_dir_name, report_database_errors,
+};
+use crate::shutdown::shutdown_signal;
+use crate::state;
+
+/// How often the newest backup is checked against the interval
+pub const BACKUP_TICK: Duration = Duration::from_secs(60);
+
+const DATABASE_FILE_NAMES: [&str; 3] = [
+    GRAPH_DATABASE_FILE_NAME,
+    MESSAGE_DATABASE_FILE_NAME,
+    TELEMETRY_DATABASE_FILE_NAME,
+];
+
+/// UTC, so names sort in the order the backups were taken
+const BACKUP_TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+const BACKUP_TEMP_EXTENSION: &str = "tmp";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupInfo {
+    pub path: String,
+    pub profile: String,
+    pub created_at: u32, // secs
+    pub bytes: u64,
+}
+
+fn backup_dir_name(profile: &str, created_at: u32) -> String {
+    let timestamp = DateTime::from_timestamp(i64::from(created_at), 0)
+        .map(|time| time.format(BACKUP_TIMESTAMP_FORMAT).to_string())
+        .unwrap_or_default();
+
+    format!("{}-{}", profile_dir_name(profile), timestamp)
+}
+
+/// Time a backup of `profile` was taken, `None` if the directory isn't one of its backups
+fn backup_created_at(dir_name: &str, profile: &str) -> Option<u32> {
+    let timestamp = dir_name.strip_prefix(&format!("{}-", profile_dir_name(profile)))?;
+
+    let created_at = NaiveDateTime::parse_from_str(timestamp, BACKUP_TIMESTAMP_FORMAT)
+        .ok()?
+        .and_utc()
+        .timestamp();
+
+    u32::try_from(created_at).ok()
+}
+
+fn directory_bytes(dir: &Path) -> u64 {
+    std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok()?.metadata().ok())
+                .map(|metadata| metadata.len())
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// Backups of `profile` in `destination`, newest first. Partly written backups and
+/// those of other profiles are skipped.
+pub fn list_backups_in(destination: &Path, profile: &str) -> Result<Vec<BackupInfo>, String> {
+    if !destination.exists() {
+        return Ok(vec![]);
+    }
+
+    let entries = std::fs::read_dir(destination)
+        .map_err(|e| format!("Failed to read {:?}: {}", destination, e))?;
+    let mut backups = vec![];
+
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+
+        if !path.is_dir() {
+            continue;
+        }
+
+        let created_at = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| backup_created_at(name, profile));
+
+        if let Some(created_at) = created_at {
+            backups.push(BackupInfo {
+                path: path.to_string_lossy().into_owned(),
+                profile: profile.into(),
+                created_at,
+                bytes: directory_bytes(&path),
+            });
+        }
+    }
+
+    backups.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+    Ok(backups)
+}
+
+/// Copies each of the `databases` that exists into a new backup of `profile` in
+/// `destination`. The copies are written to a temporary directory that's renamed
+/// into place, so a failed backup never replaces a complete one. Only the newest
+/// `keep` backups of the profile are kept.
+pub fn write_backup(
+    databases: &[PathBuf],
+    destination: &Path,
+    profile: &str,
+    created_at: u32,
+    keep: usize,
+) -> Result<BackupInfo, String> {
+    let path = destination.join(backup_dir_name(profile, created_at));
+
+    if path.exists() {
+        return Err(format!("A backup was already taken at {:?}", path));
+    }
+
+    let temp_path = path.with_extension(BACKUP_TEMP_EXTENSION);
+
+    if temp_path.exists() {
+        std::fs::remove_dir_all(&temp_path)
+            .map_err(|e| format!("Failed to remove {:?}: {}", temp_path, e))?;
+    }
+
+    std::fs::create_dir_all(&temp_path)
+        .map_err(|e| format!("Failed to create {:?}: {}", temp_path, e))?;
+
+    for database in databases.iter().filter(|database| database.exists()) {
+        let file_name = database
+            .file_name()
+            .ok_or("Database path has no file name")?;
+
+        trace!("Backing up {:?}", database);
+
+        // A connection of its own, so the backup restarts if the app writes to the
+        // database through its store while it's being copied
+        let connection = Connection::open_with_flags(database, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|e| format!("Failed to open {:?}: {}", database, e))?;
+
+        connection
+            .backup(DatabaseName::Main, temp_path.join(file_name), None)
+            .map_err(|e| format!("Failed to back up {:?}: {}", database, e))?;
+    }
+
+    std::fs::rename(&temp_path, &path)
+        .map_err(|e| format!("Failed to move backup into {:?}: {}", path, e))?;
+
+    let backups = list_backups_in(destination, profile)?;
+
+    for old in backups.iter().skip(keep.max(1)) {
+        trace!("Removing old backup {:?}", old.path);
+
+        if let Err(e) = std::fs::remove_dir_all(&old.path) {
+            warn!("Failed to remove old backup {:?}: {}", old.path, e);
+        }
+    }
+
+    backups
+        .into_iter()
+        .find(|backup| Path::new(&backup.path) == path)
+        .ok_or_else(|| format!("Backup {:?} disappeared", path))
+}
+
+/// Replaces each of the `databases` with its copy in the backup at `backup`, using
+/// the online backup API in reverse. Databases the backup has no copy of are left
+/// as they are. Their stores have to be closed first.
+pub fn restore_databases(backup: &Path, databases: &[PathBuf]) -> Result<(), String> {
+    for database in databases {
+        let file_name = database
+            .file_name()
+            .ok_or("Database path has no file name")?;
+        let copy = backup.join(file_name);
+
+        if !copy.exists() {
+            continue;
+        }
+
+        trace!("Restoring {:?} from {:?}", database, copy);
+
+        let mut connection = Connection::open(database)
+            .map_err(|e| format!("Failed to open {:?}: {}", database, e))?;
+
+        connection
+            .restore(DatabaseName::Main, &copy, None::<fn(Progress)>)
+            .map_err(|e| format!("Failed to restore {:?}: {}", database, e))?;
+    }
+
+    Ok(())
+}
+
+/// Whether a backup is due, given when the newest one was taken. Based on the backups
+/// on disk rather than a timer, so the interval carries over restarts of the app.
+fn backup_due(newest: Option<u32>, interval: Duration, now: u32) -> bool {
+    match newest {
+        Some(created_at) => u64::from(now.saturating_sub(created_at)) >= interval.as_secs(),
+        None => true,
+    }
+}
+
+fn database_paths<R: tauri::Runtime>(handle: &tauri::AppHandle<R>) -> Result<Vec<PathBuf>, String> {
+    DATABASE_FILE_NAMES
+        .iter()
+        .map(|file_name| settings_file_path(handle, file_name))
+        .collect()
+}
+
+/// The configured backup directory, or the active profile's own
+fn backup_destination<R: tauri::Runtime>(handle: &tauri::AppHandle<R>) -> Result<PathBuf, String> {
+    let directory = {
+        let settings_state = handle.state::<state::settings::SettingsState>();
+        let settings = settings_state.inner.lock().map_err(|e| e.to_string())?;
+        settings.backups.directory.clone()
+    };
+
+    match directory {
+        Some(directory) => Ok(PathBuf::from(directory)),
+        None => settings_file_path(handle, BACKUP_DIR_NAME),
+    }
+}
+
+/// Backups of the active profile, newest first
+pub fn list_backups<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+) -> Result<Vec<BackupInfo>, String> {
+    list_backups_in(&backup_destination(handle)?, &active_profile_name(handle))
+}
+
+async fn take_backup(handle: &tauri::AppHandle) -> Result<BackupInfo, String> {
+    // Queued writes are flushed first, so the backup has everything received so far
+    if let Err(e) = write_changed_graphs(handle).await {
+        warn!("Failed to write graph database before backing up: {}", e);
+    }
+
+    let handle = handle.clone();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let flushed =
+            write_pending_messages(&handle).and_then(|_| write_pending_telemetry(&handle));
+
+        if let Err(e) = flushed {
+            warn!("Failed to write queued history before backing up: {}", e);
+        }
+
+        let keep = {
+            let settings_state = handle.state::<state::settings::SettingsState>();
+            let settings = settings_state.inner.lock().map_err(|e| e.to_string())?;
+            settings.backups.keep as usize
+        };
+
+        write_backup(
+            &database_paths(&handle)?,
+            &backup_destination(&handle)?,
+            &active_profile_name(&handle),
+            get_current_time_u32(),
+            keep,
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Backs up the active profile's databases, reporting a failure on the error channel
+pub async fn run_backup(handle: &tauri::AppHandle) -> Result<BackupInfo, String> {
+    let result = take_backup(handle).await;
+
+    match &result {
+        Ok(backup) => debug!("Backed up databases to {:?}", backup.path),
+        Err(e) => ErrorReporter::new(handle, module_path!())
+            .error(AppErrorCode::BackupFailed, format!("Backup failed: {}", e)),
+    }
+
+    result
+}
+
+/// Closes the stores so their databases can be replaced
+fn close_stores<R: tauri::Runtime>(handle: &tauri::AppHandle<R>) -> Result<(), String> {
+    if let Some(store_state) = handle.try_state::<state::graph_store::GraphStoreState>() {
+        *store_state.inner.lock().map_err(|e| e.to_string())? = None;
+    }
+
+    if let Some(store_state) = handle.try_state::<state::message_store::MessageStoreState>() {
+        *store_state.inner.lock().map_err(|e| e.to_string())? = None;
+    }
+
+    if let Some(store_state) = handle.try_state::<state::telemetry_store::TelemetryStoreState>() {
+        *store_state.inner.lock().map_err(|e| e.to_string())? = None;
+    }
+
+    Ok(())
+}
+
+async fn restore(handle: &tauri::AppHandle, path: &Path) -> Result<BackupInfo, String> {
+    let backup = list_backups(handle)?
+        .into_iter()
+        .find(|backup| Path::new(&backup.path) == path)
+        .ok_or("Not a backup of the active profile")?;
+
+    info!("Restoring backup {:?}", backup.path);
+
+    // Devices would keep writing to the stores, and their graphs would be out of date
+    disconnect_all_devices(handle).await?;
+
+    let handle = handle.clone();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        close_stores(&handle)?;
+
+        let restored = restore_databases(Path::new(&backup.path), &database_paths(&handle)?);
+
+        // The stores are reopened whether or not every database was restored
+        let database_errors = load_profile_state(&handle)?;
+        report_database_errors(&handle, database_errors);
+
+        if let Some(graph_state) = handle.try_state::<state::graph::GraphState>() {
+            reset_analytics_state(&handle, &graph_state.inner, std::iter::empty())?;
+        }
+
+        restored.map(|_| backup)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Replaces the active profile's databases with those of one of its backups, then
+/// restarts the persistence layer on them. Every device is disconnected first.
+pub async fn restore_backup(handle: &tauri::AppHandle, path: &Path) -> Result<BackupInfo, String> {
+    let result = restore(handle, path).await;
+
+    if let Err(e) = &result {
+        ErrorReporter::new(handle, module_path!()).error(
+            AppErrorCode::RestoreFailed,
+            format!("Failed to restore backup: {}", e),
+        );
+    }
+
+    result
+}
+
+/// Backs up the databases whenever the newest backup is older than the interval
+pub fn spawn_backup_timer(handle: tauri::AppHandle) {
+    trace!("Spawning backup timer");
+
+    tauri::async_runtime::spawn(async move {
+        let shutdown = shutdown_signal(&handle);
+        let mut interval = tokio::time::interval(BACKUP_TICK);
+
+        while shutdown.tick(&mut interval).await {
+            let (enabled, backup_interval) = {
+                let settings_state = handle.state::<state::settings::SettingsState>();
+                let settings = match settings_state.inner.lock() {
+                    Ok(settings) => settings,
+                    Err(e) => {
+                        warn!("Failed to lock settings: {}", e);
+                        continue;
+                    }
+                };
+
+                (settings.backups.enabled, settings.backups.interval())
+            };
+
+            if !enabled {
+                continue;
+            }
+
+            let newest = match list_backups(&handle) {
+                Ok(backups) => backups.first().map(|backup| backup.created_at),
+                Err(e) => {
+                    warn!("Failed to list backups: {}", e);
+                    continue;
+                }
+            };
+
+            if !backup_due(newest, backup_interval, get_current_time_u32()) {
+                continue;
+            }
+
+            // Failures are reported by `run_backup`, and retried on the next tick
+            let _ = run_backup(&handle).await;
+        }
+
+        trace!("Stopped backup timer");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use rusqlite::params;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("backup-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn create_database(path: &Path) -> Connection {
+        let connection = Connection::open(path).unwrap();
+        connection
+            .execute_batch("CREATE TABLE messages (id INTEGER PRIMARY KEY, text TEXT NOT NULL)")
+            .unwrap();
+        connection
+    }
+
+    fn texts(path: &Path) -> Vec<String> {
+        let connection = Connection::open(path).unwrap();
+        let mut statement = connection
+            .prepare("SELECT text FROM messages ORDER BY id")
+            .unwrap();
+        let texts = statement
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        texts
+    }
+
+    #[test]
+    fn keeps_newest_backups_of_profile() {
+        let dir = temp_dir("rotation");
+        let database = dir.join(MESSAGE_DATABASE_FILE_NAME);
+        create_database(&database);
+
+        let destination = dir.join(BACKUP_DIR_NAME);
+        let start = 1_700_000_000;
+
+        let other = write_backup(&[database.clone()], &destination, "SAR Team", start, 1).unwrap();
+
+        for hour in 0..5 {
+            let created_at = start + hour * 60 * 60;
+            write_backup(&[database.clone()], &destination, "Home", created_at, 3).unwrap();
+        }
+
+        let backups = list_backups_in(&destination, "Home").unwrap();
+
+        assert_eq!(
+            backups.iter().map(|b| b.created_at).collect::<Vec<_>>(),
+            vec![
+                start + 4 * 60 * 60,
+                start + 3 * 60 * 60,
+                start + 2 * 60 * 60
+            ]
+        );
+        assert!(backups.iter().all(|b| b.bytes > 0));
+        assert!(Path::new(&backups[0].path)
+            .join(MESSAGE_DATABASE_FILE_NAME)
+            .exists());
+
+        // Another profile's backups in the same directory aren't rotated
+        assert_eq!(
+            list_backups_in(&destination, "SAR Team").unwrap(),
+            vec![other]
+        );
+
+        assert!(write_backup(&[database], &destination, "Home", start + 4 * 60 * 60, 3).is_err());
+
+        assert!(backup_due(None, Duration::from_secs(60 * 60), start));
+        assert!(!backup_due(
+            Some(start),
+            Duration::from_secs(60 * 60),
+            start + 60
+        ));
+        assert!(backup_due(
+            Some(start),
+            Duration::from_secs(60 * 60),
+            start + 60 * 60
+        ));
+    }
+
+    #[test]
+    fn backs_up_consistently_during_writes() {
+        let dir = temp_dir("consistency");
+        let database = dir.join(MESSAGE_DATABASE_FILE_NAME);
+        create_database(&database);
+
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // Each transaction writes a pair of rows, so a torn copy has an odd count
+        let writer = {
+            let database = database.clone();
+            let stop = stop.clone();
+
+            std::thread::spawn(move || {
+                let mut connection = Connection::open(&database).unwrap();
+                let mut written = 0u32;
+
+                while !stop.load(Ordering::Relaxed) {
+                    let transaction = connection.transaction().unwrap();
+                    for i in 0..2 {
+                        transaction
+                            .execute(
+                                "INSERT INTO messages (text) VALUES (?1)",
+                                params![format!("message {} {}", written, i).repeat(20)],
+                            )
+                            .unwrap();
+                    }
+                    transaction.commit().unwrap();
+                    written += 1;
+                }
+
+                written
+            })
+        };
+
+        while texts(&database).len() < 200 {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let backup = write_backup(
+            &[database.clone()],
+            &dir.join(BACKUP_DIR_NAME),
+            "Home",
+            1_700_000_000,
+            3,
+        )
+        .unwrap();
+
+        stop.store(true, Ordering::Relaxed);
+        let written = writer.join().unwrap();
+
+        let copy = Path::new(&backup.path).join(MESSAGE_DATABASE_FILE_NAME);
+        let connection = Connection::open(&copy).unwrap();
+
+        let integrity: String = connection
+            .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(integrity, "ok");
+
+        let rows = texts(&copy).len() as u32;
+        assert!(rows >= 200);
+        assert!(rows <= written * 2);
+        assert_eq!(rows % 2, 0);
+    }
+
+    #[test]
+    fn restores_databases_from_backup() {
+        let dir = temp_dir("restore");
+        let messages = dir.join(MESSAGE_DATABASE_FILE_NAME);
+        let telemetry = dir.join(TELEMETRY_DATABASE_FILE_NAME);
+
+        let connection = create_database(&messages);
+        for text in ["Checking in", "At the ridge"] {
+            connection
+                .execute("INSERT INTO messages (text) VALUES (?1)", params![text])
+                .unwrap();
+        }
+        drop(connection);
+
+        let backup = write_backup(
+            &[messages.clone(), telemetry.clone()],
+            &dir.join(BACKUP_DIR_NAME),
+            "Home",
+            1_700_000_000,
+            3,
+        )
+        .unwrap();
+
+        // The telemetry database didn't exist yet, so isn't part of the backup
+        assert!(!Path::new(&backup.path)
+            .join(TELEMETRY_DATABASE_FILE_NAME)
+            .exists());
+
+        let connection = Connection::open(&messages).unwrap();
+        connection
+            .execute("DELETE FROM messages WHERE text = 'Checking in'", [])
+            .unwrap();
+        connection
+            .execute("INSERT INTO messages (text) VALUES ('Lost')", [])
+            .unwrap();
+        drop(connection);
+
+        create_database(&telemetry);
+
+        restore_databases(
+            Path::new(&backup.path),
+            &[messages.clone(), telemetry.clone()],
+        )
+        .unwrap();
+
+        assert_eq!(texts(&messages), vec!["Checking in", "At the ridge"]);
+        assert!(texts(&telemetry).is_empty());
+    }
+}
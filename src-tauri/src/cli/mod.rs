@@ -5,11 +5,20 @@ use crate::state;
 pub fn handle_cli_matches(
     app: &mut tauri::App,
     inital_autoconnect_state: &mut state::autoconnect::AutoConnectState,
+    initial_deep_link_state: &mut state::deep_link::DeepLinkState,
 ) -> Result<(), String> {
     match app.get_cli_matches() {
         Ok(matches) => {
             let args = matches.args;
 
+            // The OS passes `meshtastic://` links as the only argument when opening the app
+            if let Some(url_arg) = args.get("url") {
+                if let serde_json::Value::String(url) = url_arg.value.clone() {
+                    info!("Launched with link {}", url);
+                    *initial_deep_link_state = state::deep_link::DeepLinkState::init(url);
+                }
+            }
+
             // Check if user has specified a port name to automatically connect to
             // If so, store it for future connection attempts
             if let Some(port_arg) = args.get("port") {
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use log::{debug, info, trace, warn};
+
+use crate::device::SerialDeviceStatus;
+use crate::ipc::error_reporter::{AppErrorCode, ErrorReporter};
+use crate::ipc::events::{
+    dispatch_device_disconnect, dispatch_devices_list_changed, dispatch_updated_device,
+};
+use crate::ipc::DevicesListChange;
+use crate::notifications::dispatcher::{self, SystemNotification};
+use crate::notifications::preferences::NotificationCategory;
+use crate::state::mesh_devices::MeshDevicesStateInner;
+use crate::state::radio_connections::RadioConnectionsStateInner;
+use crate::state::DeviceKey;
+
+/// Removes a lost device and its connection from connection state.
+///
+/// Only the first caller for a given device key receives the removed entries,
+/// which keeps concurrent loss detection from reporting the same loss twice.
+/// Devices whose connection has already been removed were disconnected by the
+/// user, so their loss is not claimed.
+pub fn claim_lost_device<D, C>(
+    devices: &mut HashMap<DeviceKey, D>,
+    connections: &mut HashMap<DeviceKey, C>,
+    device_key: &DeviceKey,
+) -> Option<(Option<D>, C)> {
+    let connection = connections.remove(device_key)?;
+    let device = devices.remove(device_key);
+
+    Some((device, connection))
+}
+
+/// Cleans up after a device whose connection ended without being dropped by
+/// the user (e.g. a USB cable being unplugged), then notifies the UI and user.
+/// Devices about to be reconnected to are listed as reconnecting rather than removed.
+///
+/// Returns whether this call handled the loss.
+pub async fn handle_device_lost(
+    handle: tauri::AppHandle,
+    mesh_devices: MeshDevicesStateInner,
+    radio_connections: RadioConnectionsStateInner,
+    device_key: DeviceKey,
+    reconnecting: bool,
+) -> bool {
+    let claimed = {
+        let mut devices_guard = mesh_devices.lock().await;
+        let mut connections_guard = radio_connections.lock().await;
+
+        claim_lost_device(&mut devices_guard, &mut connections_guard, &device_key)
+    };
+
+    let (device, connection) = match claimed {
+        Some(claimed) => claimed,
+        None => {
+            trace!("Connection to \"{}\" already dropped", device_key);
+            return false;
+        }
+    };
+
+    warn!("Lost connection to device \"{}\"", device_key);
+
+    let reporter = ErrorReporter::new(&handle, module_path!()).with_device(&device_key);
+
+    if let Some(device) = device {
+        // Stopped already when its packet stream closed, which marks it disconnected
+        device.disconnect().await;
+
+        let packet_api = device.packet_api();
+        let packet_api = packet_api.lock().await;
+
+        if let Err(e) = dispatch_updated_device(&handle, &packet_api.device_key, &packet_api.device)
+        {
+            reporter.error(
+                AppErrorCode::EventDispatchFailed,
+                format!("Failed to dispatch disconnected device: {}", e),
+            );
+        }
+    }
+
+    // Stop the read and write tasks of the vanished connection
+
+    if let Err(e) = connection.disconnect().await {
+        debug!("Failed to stop tasks of lost connection: {:?}", e);
+    }
+
+    if let Err(e) = dispatch_device_disconnect(&handle, device_key.clone()) {
+        reporter.error(
+            AppErrorCode::EventDispatchFailed,
+            format!("Failed to dispatch device disconnect: {}", e),
+        );
+    }
+
+    let (change, status) = match reconnecting {
+        true => (
+            DevicesListChange::StatusChanged,
+            SerialDeviceStatus::Reconnecting,
+        ),
+        false => (DevicesListChange::Removed, SerialDeviceStatus::Disconnected),
+    };
+
+    if let Err(e) = dispatch_devices_list_changed(&handle, device_key.clone(), change, status) {
+        reporter.error(
+            AppErrorCode::EventDispatchFailed,
+            format!("Failed to dispatch devices list change: {}", e),
+        );
+    }
+
+    dispatcher::enqueue(
+        &handle,
+        SystemNotification {
+            category: NotificationCategory::DeviceStatus,
+            node_num: None,
+            title: "Device disconnected".into(),
+            body: format!("Device on {} disconnected", device_key),
+            group: None,
+        },
+    );
+
+    info!("Cleaned up lost device \"{}\"", device_key);
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn claims_loss_once() {
+        let key: DeviceKey = "/dev/ttyUSB0".into();
+        let mut devices = HashMap::from([(key.clone(), "device")]);
+        let mut connections = HashMap::from([(key.clone(), "connection")]);
+
+        assert_eq!(
+            claim_lost_device(&mut devices, &mut connections, &key),
+            Some((Some("device"), "connection"))
+        );
+        assert!(devices.is_empty());
+        assert!(connections.is_empty());
+
+        assert_eq!(
+            claim_lost_device(&mut devices, &mut connections, &key),
+            None
+        );
+    }
+
+    #[test]
+    fn ignores_user_dropped_connections() {
+        let key: DeviceKey = "/dev/ttyUSB0".into();
+        let mut devices = HashMap::from([(key.clone(), "device")]);
+        let mut connections: HashMap<DeviceKey, &str> = HashMap::new();
+
+        assert_eq!(
+            claim_lost_device(&mut devices, &mut connections, &key),
+            None
+        );
+        assert_eq!(devices.len(), 1);
+    }
+}
@@ -0,0 +1,326 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc::UnboundedSender;
+
+const FRAME_START_1: u8 = 0x94;
+const FRAME_START_2: u8 = 0xc3;
+
+/// Largest payload the firmware sends in a single protobuf frame
+const MAX_FRAME_PAYLOAD_LEN: usize = 512;
+
+/// Longest text line kept before it's emitted without a line terminator
+const MAX_LOG_LINE_LEN: usize = 1024;
+
+const ESCAPE: u8 = 0x1b;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SplitterState {
+    Text,
+    FrameStart,
+    FrameLength { high: Option<u8> },
+    FramePayload { remaining: usize },
+    Escape,
+}
+
+/// A unit of data read from a stream that carries the client API framing, borrowed
+/// from the splitter until it consumes more bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SerialChunk<'a> {
+    Line(&'a str),   // plain-text log line written between frames
+    Frame(&'a [u8]), // protobuf payload of a complete frame
+    FramingError,    // frame header with an impossible payload length
+}
+
+/// Separates the plain-text log lines firmware writes to the serial port
+/// from the length-prefixed protobuf frames used by the client API. Lines and
+/// frames are assembled in buffers reused between them, so splitting a stream
+/// doesn't allocate once they've grown to fit.
+#[derive(Clone, Debug)]
+pub struct SerialFrameSplitter {
+    state: SplitterState,
+    line: Vec<u8>,
+    frame: Vec<u8>,
+}
+
+impl Default for SerialFrameSplitter {
+    fn default() -> Self {
+        Self {
+            state: SplitterState::Text,
+            line: Vec::new(),
+            frame: Vec::new(),
+        }
+    }
+}
+
+impl SerialFrameSplitter {
+    /// Consumes bytes from the stream, passing completed lines, frames and framing
+    /// errors to `on_chunk` as they're found
+    pub fn push_chunks(&mut self, bytes: &[u8], mut on_chunk: impl FnMut(SerialChunk<'_>)) {
+        for byte in bytes {
+            self.state = match self.state {
+                SplitterState::Text => self.push_text_byte(*byte, &mut on_chunk),
+                SplitterState::FrameStart => {
+                    if *byte == FRAME_START_2 {
+                        SplitterState::FrameLength { high: None }
+                    } else {
+                        // Not a frame after all, the stray start byte isn't printable
+                        self.push_text_byte(*byte, &mut on_chunk)
+                    }
+                }
+                SplitterState::FrameLength { high: None } => {
+                    SplitterState::FrameLength { high: Some(*byte) }
+                }
+                SplitterState::FrameLength { high: Some(high) } => {
+                    let len = (usize::from(high) << 8) | usize::from(*byte);
+
+                    if len == 0 || len > MAX_FRAME_PAYLOAD_LEN {
+                        on_chunk(SerialChunk::FramingError);
+                        SplitterState::Text
+                    } else {
+                        self.frame.clear();
+                        SplitterState::FramePayload { remaining: len }
+                    }
+                }
+                SplitterState::FramePayload { remaining } => {
+                    self.frame.push(*byte);
+
+                    if remaining > 1 {
+                        SplitterState::FramePayload {
+                            remaining: remaining - 1,
+                        }
+                    } else {
+                        on_chunk(SerialChunk::Frame(&self.frame));
+                        SplitterState::Text
+                    }
+                }
+                SplitterState::Escape => {
+                    // ANSI color sequences end with a letter, e.g. `ESC[0;32m`
+                    if byte.is_ascii_alphabetic() {
+                        SplitterState::Text
+                    } else {
+                        SplitterState::Escape
+                    }
+                }
+            };
+        }
+    }
+
+    fn push_text_byte(
+        &mut self,
+        byte: u8,
+        on_chunk: &mut impl FnMut(SerialChunk<'_>),
+    ) -> SplitterState {
+        match byte {
+            FRAME_START_1 => return SplitterState::FrameStart,
+            ESCAPE => return SplitterState::Escape,
+            b'\n' => self.flush_line(on_chunk),
+            b'\t' | b' '..=b'~' => {
+                self.line.push(byte);
+
+                if self.line.len() >= MAX_LOG_LINE_LEN {
+                    self.flush_line(on_chunk);
+                }
+            }
+            _ => {}
+        }
+
+        SplitterState::Text
+    }
+
+    fn flush_line(&mut self, on_chunk: &mut impl FnMut(SerialChunk<'_>)) {
+        // Only tabs and printable ASCII are kept, so the line is always valid UTF-8
+        if let Ok(line) = std::str::from_utf8(&self.line) {
+            let line = line.trim_end();
+
+            if !line.is_empty() {
+                on_chunk(SerialChunk::Line(line));
+            }
+        }
+
+        self.line.clear();
+    }
+}
+
+/// Wraps a serial stream, passing all bytes through unchanged while
+/// forwarding any plain-text log lines between frames to `lines`.
+pub struct SerialLogTap<S> {
+    stream: S,
+    splitter: SerialFrameSplitter,
+    lines: UnboundedSender<String>,
+}
+
+impl<S> SerialLogTap<S> {
+    pub fn new(stream: S, lines: UnboundedSender<String>) -> Self {
+        Self {
+            stream,
+            splitter: SerialFrameSplitter::default(),
+            lines,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for SerialLogTap<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled_before = buf.filled().len();
+
+        let result = Pin::new(&mut this.stream).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = result {
+            let lines = &this.lines;
+
+            this.splitter
+                .push_chunks(&buf.filled()[filled_before..], |chunk| {
+                    if let SerialChunk::Line(line) = chunk {
+                        // The receiver is dropped when log capture isn't running
+                        let _ = lines.send(line.to_string());
+                    }
+                });
+        }
+
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for SerialLogTap<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let len = payload.len() as u16;
+        let mut bytes = vec![FRAME_START_1, FRAME_START_2];
+        bytes.extend_from_slice(&len.to_be_bytes());
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    /// Completed lines, like the tap forwards them
+    fn lines(splitter: &mut SerialFrameSplitter, bytes: &[u8]) -> Vec<String> {
+        let mut lines = vec![];
+
+        splitter.push_chunks(bytes, |chunk| {
+            if let SerialChunk::Line(line) = chunk {
+                lines.push(line.to_string());
+            }
+        });
+
+        lines
+    }
+
+    #[test]
+    fn separates_text_from_frames() {
+        let mut splitter = SerialFrameSplitter::default();
+
+        let mut bytes = b"INFO  | Booting\r\n".to_vec();
+        bytes.extend(frame(b"\x0a\x0d\n\x94not text"));
+        bytes.extend_from_slice(b"DEBUG | \x1b[0;32mRadio ready\x1b[0m\n");
+        bytes.extend(frame(&[0xc3; 20]));
+
+        assert_eq!(
+            lines(&mut splitter, &bytes),
+            vec!["INFO  | Booting", "DEBUG | Radio ready"]
+        );
+    }
+
+    #[test]
+    fn handles_lines_and_frames_split_across_reads() {
+        let mut splitter = SerialFrameSplitter::default();
+
+        let mut bytes = b"WARN  | Low ".to_vec();
+        bytes.extend(frame(b"payload\n"));
+        bytes.extend_from_slice(b"battery\n");
+
+        let mut received = vec![];
+
+        for chunk in bytes.chunks(3) {
+            received.extend(lines(&mut splitter, chunk));
+        }
+
+        assert_eq!(received, vec!["WARN  | Low battery"]);
+    }
+
+    #[test]
+    fn recovers_from_invalid_frame_headers() {
+        let mut splitter = SerialFrameSplitter::default();
+
+        let mut bytes = vec![FRAME_START_1, b'x'];
+        bytes.extend_from_slice(b"abc\n");
+        bytes.extend_from_slice(&[FRAME_START_1, FRAME_START_2, 0xff, 0xff]);
+        bytes.extend_from_slice(b"def\n");
+
+        assert_eq!(lines(&mut splitter, &bytes), vec!["xabc", "def"]);
+    }
+
+    #[test]
+    fn reports_frames_and_framing_errors() {
+        let mut splitter = SerialFrameSplitter::default();
+
+        let mut bytes = frame(b"abc");
+        bytes.extend_from_slice(&[FRAME_START_1, FRAME_START_2, 0x00, 0x00]);
+        bytes.extend_from_slice(b"log\n");
+
+        // Chunks only live until the next byte, so they're compared as they're found
+        let expected = [
+            SerialChunk::Frame(b"abc"),
+            SerialChunk::FramingError,
+            SerialChunk::Line("log"),
+        ];
+        let mut found = 0;
+
+        splitter.push_chunks(&bytes, |chunk| {
+            assert_eq!(chunk, expected[found]);
+            found += 1;
+        });
+
+        assert_eq!(found, expected.len());
+    }
+
+    #[tokio::test]
+    async fn tap_passes_stream_through_unchanged() {
+        let (mut device, host) = tokio::io::duplex(1024);
+        let (lines_tx, mut lines_rx) = mpsc::unbounded_channel();
+        let mut tap = SerialLogTap::new(host, lines_tx);
+
+        let mut bytes = frame(b"config");
+        bytes.extend_from_slice(b"ERROR | Radio timeout\n");
+        bytes.extend(frame(b"packet"));
+
+        device.write_all(&bytes).await.unwrap();
+        drop(device);
+
+        let mut received = vec![];
+        tap.read_to_end(&mut received).await.unwrap();
+
+        assert_eq!(received, bytes);
+        assert_eq!(lines_rx.recv().await.unwrap(), "ERROR | Radio timeout");
+        assert!(lines_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,628 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use meshtastic::protobufs;
+use meshtastic::ts::specta::{self, Type};
+use meshtastic::Message;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::device::helpers::get_current_time_u32;
+
+use super::log_tap::{SerialChunk, SerialFrameSplitter};
+
+/// Window over which per-minute packet rates are computed, in seconds
+pub const METRICS_RATE_WINDOW_SECS: u32 = 60;
+
+/// How often changed connection metrics are dispatched to the UI
+pub const CONNECTION_METRICS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Label used for radio messages that aren't mesh packets (config, queue status, etc.)
+pub const CONTROL_MESSAGE_LABEL: &str = "CONTROL";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PacketDirection {
+    Received,
+    Sent,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionMetrics {
+    pub packets_received: u32,
+    pub packets_sent: u32,
+    pub received_per_minute: HashMap<String, u32>, // keyed by portnum name
+    pub sent_per_minute: HashMap<String, u32>,     // keyed by portnum name
+    pub bytes_received: u32,
+    pub bytes_sent: u32,
+    pub framing_errors: u32,
+    pub decode_failures: u32,
+    pub encrypted_skipped: u32, // packets relayed without being decrypted, e.g. over MQTT
+    pub dedup_hits: u32,
+    pub queue_depth: Option<u32>, // packets waiting in the radio's TX queue, if reported
+    pub last_packet_received: Option<u32>, // seconds since epoch
+    pub secs_since_last_packet: Option<u32>,
+}
+
+/// Counters for a single connection, updated by the stream wrapper and the
+/// packet handler. Locked at most once per read, write or handled packet.
+#[derive(Debug, Default)]
+pub struct ConnectionMetricsRecorder {
+    metrics: ConnectionMetrics,
+    recent_packets: VecDeque<(u32, PacketDirection, Cow<'static, str>)>,
+    last_dispatched: Option<ConnectionMetrics>,
+}
+
+pub type SharedConnectionMetrics = Arc<Mutex<ConnectionMetricsRecorder>>;
+
+impl ConnectionMetricsRecorder {
+    pub fn record_received_packet(&mut self, label: Cow<'static, str>, now: u32) {
+        self.metrics.packets_received = self.metrics.packets_received.saturating_add(1);
+        self.metrics.last_packet_received = Some(now);
+        self.recent_packets
+            .push_back((now, PacketDirection::Received, label));
+    }
+
+    pub fn record_sent_packet(&mut self, label: Cow<'static, str>, now: u32) {
+        self.metrics.packets_sent = self.metrics.packets_sent.saturating_add(1);
+        self.recent_packets
+            .push_back((now, PacketDirection::Sent, label));
+    }
+
+    pub fn record_bytes_received(&mut self, count: usize) {
+        let count = u32::try_from(count).unwrap_or(u32::MAX);
+        self.metrics.bytes_received = self.metrics.bytes_received.saturating_add(count);
+    }
+
+    pub fn record_bytes_sent(&mut self, count: usize) {
+        let count = u32::try_from(count).unwrap_or(u32::MAX);
+        self.metrics.bytes_sent = self.metrics.bytes_sent.saturating_add(count);
+    }
+
+    pub fn record_framing_error(&mut self) {
+        self.metrics.framing_errors = self.metrics.framing_errors.saturating_add(1);
+    }
+
+    pub fn record_decode_failure(&mut self) {
+        self.metrics.decode_failures = self.metrics.decode_failures.saturating_add(1);
+    }
+
+    pub fn record_encrypted_skipped(&mut self) {
+        self.metrics.encrypted_skipped = self.metrics.encrypted_skipped.saturating_add(1);
+    }
+
+    pub fn record_dedup_hit(&mut self) {
+        self.metrics.dedup_hits = self.metrics.dedup_hits.saturating_add(1);
+    }
+
+    /// Returns the current metrics, with rates computed over the last `METRICS_RATE_WINDOW_SECS`
+    pub fn snapshot(&mut self, queue_depth: Option<u32>, now: u32) -> ConnectionMetrics {
+        while let Some((timestamp, _, _)) = self.recent_packets.front() {
+            if now.saturating_sub(*timestamp) < METRICS_RATE_WINDOW_SECS {
+                break;
+            }
+
+            self.recent_packets.pop_front();
+        }
+
+        let mut received_per_minute = HashMap::new();
+        let mut sent_per_minute = HashMap::new();
+
+        for (_, direction, label) in self.recent_packets.iter() {
+            let rates = match direction {
+                PacketDirection::Received => &mut received_per_minute,
+                PacketDirection::Sent => &mut sent_per_minute,
+            };
+
+            *rates.entry(label.to_string()).or_insert(0) += 1;
+        }
+
+        ConnectionMetrics {
+            received_per_minute,
+            sent_per_minute,
+            queue_depth,
+            secs_since_last_packet: self
+                .metrics
+                .last_packet_received
+                .map(|last| now.saturating_sub(last)),
+            ..self.metrics.clone()
+        }
+    }
+
+    /// Returns a snapshot only if it differs from the last one returned by this method.
+    /// Time since the last packet is ignored so idle connections don't dispatch updates.
+    pub fn take_changed_snapshot(
+        &mut self,
+        queue_depth: Option<u32>,
+        now: u32,
+    ) -> Option<ConnectionMetrics> {
+        let snapshot = self.snapshot(queue_depth, now);
+
+        let comparable = ConnectionMetrics {
+            secs_since_last_packet: None,
+            ..snapshot.clone()
+        };
+
+        if self.last_dispatched.as_ref() == Some(&comparable) {
+            return None;
+        }
+
+        self.last_dispatched = Some(comparable);
+
+        Some(snapshot)
+    }
+}
+
+/// Labels are borrowed for known portnums, so recording a packet doesn't allocate
+pub fn portnum_label(portnum: i32) -> Cow<'static, str> {
+    match protobufs::PortNum::from_i32(portnum) {
+        Some(p) => p.as_str_name().into(),
+        None => format!("UNKNOWN_{}", portnum).into(),
+    }
+}
+
+pub fn mesh_packet_label(packet: &protobufs::MeshPacket) -> Cow<'static, str> {
+    match packet.payload_variant.as_ref() {
+        Some(protobufs::mesh_packet::PayloadVariant::Decoded(data)) => portnum_label(data.portnum),
+        Some(protobufs::mesh_packet::PayloadVariant::Encrypted(_)) => "ENCRYPTED".into(),
+        None => "UNKNOWN".into(),
+    }
+}
+
+pub fn from_radio_label(packet: &protobufs::FromRadio) -> Cow<'static, str> {
+    match packet.payload_variant.as_ref() {
+        Some(protobufs::from_radio::PayloadVariant::Packet(mesh_packet)) => {
+            mesh_packet_label(mesh_packet)
+        }
+        _ => CONTROL_MESSAGE_LABEL.into(),
+    }
+}
+
+fn to_radio_label(frame: &[u8]) -> Cow<'static, str> {
+    match protobufs::ToRadio::decode(frame).map(|to_radio| to_radio.payload_variant) {
+        Ok(Some(protobufs::to_radio::PayloadVariant::Packet(mesh_packet))) => {
+            mesh_packet_label(&mesh_packet)
+        }
+        _ => CONTROL_MESSAGE_LABEL.into(),
+    }
+}
+
+/// Wraps a serial or TCP stream to count bytes and frames in both directions.
+/// Outgoing frames are decoded so sent packets can be broken down by portnum.
+pub struct MeteredStream<S> {
+    stream: S,
+    metrics: SharedConnectionMetrics,
+    read_splitter: SerialFrameSplitter,
+    write_splitter: SerialFrameSplitter,
+}
+
+impl<S> MeteredStream<S> {
+    pub fn new(stream: S, metrics: SharedConnectionMetrics) -> Self {
+        Self {
+            stream,
+            metrics,
+            read_splitter: SerialFrameSplitter::default(),
+            write_splitter: SerialFrameSplitter::default(),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for MeteredStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled_before = buf.filled().len();
+
+        let result = Pin::new(&mut this.stream).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = result {
+            let bytes = &buf.filled()[filled_before..];
+            let mut framing_errors = 0;
+
+            this.read_splitter.push_chunks(bytes, |chunk| {
+                if chunk == SerialChunk::FramingError {
+                    framing_errors += 1;
+                }
+            });
+
+            if let Ok(mut metrics) = this.metrics.lock() {
+                metrics.record_bytes_received(bytes.len());
+
+                for _ in 0..framing_errors {
+                    metrics.record_framing_error();
+                }
+            }
+        }
+
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for MeteredStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        let result = Pin::new(&mut this.stream).poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(written)) = result {
+            let now = get_current_time_u32();
+            let mut metrics = this.metrics.lock().ok();
+
+            if let Some(metrics) = metrics.as_mut() {
+                metrics.record_bytes_sent(written);
+            }
+
+            // Split even if the metrics can't be locked, to stay in step with the stream
+            this.write_splitter.push_chunks(&buf[..written], |chunk| {
+                if let (SerialChunk::Frame(frame), Some(metrics)) = (chunk, metrics.as_mut()) {
+                    metrics.record_sent_packet(to_radio_label(frame), now);
+                }
+            });
+        }
+
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::task::{RawWaker, RawWakerVTable, Waker};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::connection::log_tap::SerialLogTap;
+    use crate::device::MeshDevice;
+    use crate::ipc::events::payloads::DeviceUpdateEvent;
+    use crate::ipc::events::scopes::with_raw_payload;
+    use crate::ipc::EVENT_API_VERSION;
+
+    /// Counts the allocations made on a thread while it's counting, so tests running
+    /// alongside don't add to them
+    struct CountingAllocator;
+
+    thread_local! {
+        static COUNTING: Cell<bool> = const { Cell::new(false) };
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn count_allocation() {
+        let _ = COUNTING.try_with(|counting| {
+            if counting.get() {
+                ALLOCATIONS.with(|allocations| allocations.set(allocations.get() + 1));
+            }
+        });
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count_allocation();
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count_allocation();
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations(f: impl FnOnce()) -> usize {
+        ALLOCATIONS.with(|allocations| allocations.set(0));
+        COUNTING.with(|counting| counting.set(true));
+        f();
+        COUNTING.with(|counting| counting.set(false));
+        ALLOCATIONS.with(|allocations| allocations.get())
+    }
+
+    fn noop_waker() -> Waker {
+        fn clone(_: *const ()) -> RawWaker {
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+
+        fn noop(_: *const ()) {}
+
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+        unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) }
+    }
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0x94, 0xc3];
+        bytes.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    fn text_packet() -> protobufs::MeshPacket {
+        protobufs::MeshPacket {
+            payload_variant: Some(protobufs::mesh_packet::PayloadVariant::Decoded(
+                protobufs::Data {
+                    portnum: protobufs::PortNum::TextMessageApp as i32,
+                    payload: b"hi".to_vec(),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn computes_rates_over_sliding_window() {
+        let mut recorder = ConnectionMetricsRecorder::default();
+
+        recorder.record_received_packet("TEXT_MESSAGE_APP".into(), 0);
+        recorder.record_received_packet("TEXT_MESSAGE_APP".into(), 30);
+        recorder.record_received_packet("POSITION_APP".into(), 45);
+        recorder.record_sent_packet("TEXT_MESSAGE_APP".into(), 50);
+
+        let metrics = recorder.snapshot(Some(2), 50);
+
+        assert_eq!(metrics.packets_received, 3);
+        assert_eq!(metrics.received_per_minute["TEXT_MESSAGE_APP"], 2);
+        assert_eq!(metrics.sent_per_minute["TEXT_MESSAGE_APP"], 1);
+        assert_eq!(metrics.secs_since_last_packet, Some(5));
+        assert_eq!(metrics.queue_depth, Some(2));
+
+        let metrics = recorder.snapshot(Some(2), 80);
+
+        assert_eq!(metrics.packets_received, 3);
+        assert_eq!(metrics.received_per_minute.get("TEXT_MESSAGE_APP"), None);
+        assert_eq!(metrics.received_per_minute["POSITION_APP"], 1);
+    }
+
+    #[test]
+    fn only_returns_changed_snapshots() {
+        let mut recorder = ConnectionMetricsRecorder::default();
+        recorder.record_received_packet("POSITION_APP".into(), 100);
+
+        assert!(recorder.take_changed_snapshot(None, 100).is_some());
+        assert!(recorder.take_changed_snapshot(None, 105).is_none());
+
+        recorder.record_dedup_hit();
+
+        let metrics = recorder.take_changed_snapshot(None, 110).unwrap();
+        assert_eq!(metrics.dedup_hits, 1);
+        assert_eq!(metrics.secs_since_last_packet, Some(10));
+    }
+
+    #[test]
+    fn labels_radio_messages() {
+        let packet = protobufs::FromRadio {
+            payload_variant: Some(protobufs::from_radio::PayloadVariant::Packet(text_packet())),
+            ..Default::default()
+        };
+
+        assert_eq!(from_radio_label(&packet), "TEXT_MESSAGE_APP");
+        assert_eq!(
+            from_radio_label(&protobufs::FromRadio::default()),
+            CONTROL_MESSAGE_LABEL
+        );
+        assert_eq!(portnum_label(9999), "UNKNOWN_9999");
+    }
+
+    fn mesh_packet(portnum: protobufs::PortNum, payload: &[u8]) -> protobufs::FromRadio {
+        protobufs::FromRadio {
+            payload_variant: Some(protobufs::from_radio::PayloadVariant::Packet(
+                protobufs::MeshPacket {
+                    from: 0x1234,
+                    payload_variant: Some(protobufs::mesh_packet::PayloadVariant::Decoded(
+                        protobufs::Data {
+                            portnum: portnum as i32,
+                            payload: payload.to_vec(),
+                            ..Default::default()
+                        },
+                    )),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        }
+    }
+
+    /// Text messages, positions, telemetry, node infos and queue statuses, in the
+    /// proportions a busy mesh sends them
+    fn packet_mix() -> Vec<protobufs::FromRadio> {
+        let node_info = protobufs::FromRadio {
+            payload_variant: Some(protobufs::from_radio::PayloadVariant::NodeInfo(
+                protobufs::NodeInfo {
+                    num: 0x1234,
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        };
+        let queue_status = protobufs::FromRadio {
+            payload_variant: Some(protobufs::from_radio::PayloadVariant::QueueStatus(
+                protobufs::QueueStatus {
+                    free: 12,
+                    maxlen: 16,
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        };
+
+        vec![
+            mesh_packet(protobufs::PortNum::TextMessageApp, b"anyone on the ridge?"),
+            mesh_packet(protobufs::PortNum::PositionApp, &[0x0d; 24]),
+            mesh_packet(protobufs::PortNum::PositionApp, &[0x0d; 24]),
+            mesh_packet(protobufs::PortNum::TelemetryApp, &[0x25; 40]),
+            mesh_packet(protobufs::PortNum::NeighborinfoApp, &[0x08; 32]),
+            node_info,
+            queue_status,
+        ]
+    }
+
+    #[test]
+    fn reading_packets_reuses_buffers() {
+        let packets = packet_mix();
+        let cycles = 200;
+
+        let bytes: Vec<u8> = (0..cycles)
+            .flat_map(|_| packets.iter().map(|packet| frame(&packet.encode_to_vec())))
+            .flatten()
+            .collect();
+
+        let (lines, _) = tokio::sync::mpsc::unbounded_channel();
+        let mut stream = MeteredStream::new(
+            SerialLogTap::new(&bytes[..], lines),
+            SharedConnectionMetrics::default(),
+        );
+
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut buf = [0; 64];
+
+        let mut read = |stream: &mut MeteredStream<_>| {
+            let mut read_buf = ReadBuf::new(&mut buf);
+
+            match Pin::new(stream).poll_read(&mut cx, &mut read_buf) {
+                Poll::Ready(Ok(())) => read_buf.filled().len(),
+                other => panic!("unexpected read result: {:?}", other),
+            }
+        };
+
+        // The first packet of each kind grows the buffers frames are split in
+        let warm_up: usize = bytes.len() / cycles;
+        let mut warmed_up = 0;
+
+        while warmed_up < warm_up {
+            warmed_up += read(&mut stream);
+        }
+
+        assert_eq!(allocations(|| while read(&mut stream) > 0 {}), 0);
+
+        // Recording a packet's label doesn't allocate once the recorder has room for it
+        let mut recorder = ConnectionMetricsRecorder::default();
+        let record = |recorder: &mut ConnectionMetricsRecorder| {
+            for packet in packets.iter().cycle().take(packets.len() * cycles) {
+                recorder.record_received_packet(from_radio_label(packet), 0);
+            }
+        };
+
+        record(&mut recorder);
+        recorder.snapshot(None, METRICS_RATE_WINDOW_SECS);
+
+        assert_eq!(allocations(|| record(&mut recorder)), 0);
+
+        // Each packet updates the device, which the UI is sent in full
+        let mut device = MeshDevice::new();
+
+        for num in 1..=40 {
+            device.add_node_info(protobufs::NodeInfo {
+                num,
+                user: Some(protobufs::User {
+                    long_name: format!("Node {}", num),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            });
+        }
+
+        let event = DeviceUpdateEvent {
+            api_version: EVENT_API_VERSION,
+            device_key: "COM4".into(),
+            device,
+        };
+
+        let emit = || {
+            for _ in 0..cycles {
+                with_raw_payload(&event, |payload| {
+                    assert!(payload.get().starts_with('{'));
+                    Ok(())
+                })
+                .unwrap();
+            }
+        };
+
+        emit();
+
+        let raw = allocations(emit);
+        let values = allocations(|| {
+            for _ in 0..cycles {
+                let _value = serde_json::to_value(&event).unwrap();
+            }
+        });
+
+        assert!(
+            raw * 10 < values,
+            "raw payloads allocated {} times, values {} times",
+            raw,
+            values
+        );
+    }
+
+    #[tokio::test]
+    async fn counts_traffic_through_stream() {
+        let (mut device, host) = tokio::io::duplex(4096);
+        let metrics = SharedConnectionMetrics::default();
+        let mut stream = MeteredStream::new(host, metrics.clone());
+
+        let to_radio = protobufs::ToRadio {
+            payload_variant: Some(protobufs::to_radio::PayloadVariant::Packet(text_packet())),
+        };
+        let heartbeat = protobufs::ToRadio {
+            payload_variant: Some(protobufs::to_radio::PayloadVariant::WantConfigId(1)),
+        };
+
+        let sent = [
+            frame(&to_radio.encode_to_vec()),
+            frame(&heartbeat.encode_to_vec()),
+        ]
+        .concat();
+
+        // Write the first frame in two parts to split it across writes
+        stream.write_all(&sent[..3]).await.unwrap();
+        stream.write_all(&sent[3..]).await.unwrap();
+
+        let mut received = frame(b"from radio");
+        received.extend_from_slice(&[0x94, 0xc3, 0xff, 0xff]);
+
+        device.write_all(&received).await.unwrap();
+        drop(device);
+
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).await.unwrap();
+
+        let metrics = metrics
+            .lock()
+            .unwrap()
+            .snapshot(None, get_current_time_u32());
+
+        assert_eq!(metrics.bytes_sent as usize, sent.len());
+        assert_eq!(metrics.bytes_received as usize, received.len());
+        assert_eq!(metrics.packets_sent, 2);
+        assert_eq!(metrics.sent_per_minute["TEXT_MESSAGE_APP"], 1);
+        assert_eq!(metrics.sent_per_minute[CONTROL_MESSAGE_LABEL], 1);
+        assert_eq!(metrics.framing_errors, 1);
+    }
+}
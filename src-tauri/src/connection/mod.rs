@@ -0,0 +1,8 @@
+pub mod device_lost;
+pub mod log_tap;
+pub mod metrics;
+pub mod mqtt;
+pub mod options;
+pub mod recent;
+pub mod serial_lines;
+pub mod tcp;
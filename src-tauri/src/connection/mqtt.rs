@@ -0,0 +1,366 @@
+use std::time::Duration;
+
+use log::{debug, trace, warn};
+use meshtastic::protobufs;
+use meshtastic::Message;
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS, Transport};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::connection::metrics::SharedConnectionMetrics;
+use crate::device::helpers::generate_rand_id;
+use crate::state::DeviceKey;
+
+/// Port brokers take plain MQTT connections on
+pub const DEFAULT_MQTT_PORT: u16 = 1883;
+
+/// Port brokers take MQTT over TLS on
+pub const DEFAULT_MQTTS_PORT: u16 = 8883;
+
+/// Topic the firmware publishes under unless its MQTT module is configured otherwise
+pub const DEFAULT_ROOT_TOPIC: &str = "msh";
+
+/// Longest the broker may take to accept the connection and the subscription
+pub const MQTT_SUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Waits between reconnects to a broker that dropped the connection, doubling from
+/// the first to the last
+pub const MQTT_RECONNECT_BACKOFF: (Duration, Duration) =
+    (Duration::from_secs(1), Duration::from_secs(60));
+
+const MQTT_KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// Envelopes carry a whole mesh packet, so they can be larger than rumqttc allows by default
+const MQTT_MAX_PACKET_SIZE: usize = 64 * 1024;
+
+/// Broker an MQTT connection subscribes to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MqttBroker {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+}
+
+impl MqttBroker {
+    /// Parses `mqtt://host[:port]`, `mqtts://host[:port]` or a bare `host[:port]`,
+    /// which connects without TLS
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let url = url.trim();
+
+        let (address, tls) = if let Some(address) = url.strip_prefix("mqtts://") {
+            (address, true)
+        } else if let Some(address) = url.strip_prefix("mqtt://") {
+            (address, false)
+        } else if url.contains("://") {
+            return Err(format!("\"{}\" isn't an mqtt:// or mqtts:// URL", url));
+        } else {
+            (url, false)
+        };
+
+        let address = address.trim_end_matches('/');
+        let default_port = if tls {
+            DEFAULT_MQTTS_PORT
+        } else {
+            DEFAULT_MQTT_PORT
+        };
+
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => {
+                let port: u16 = port
+                    .parse()
+                    .map_err(|_| format!("\"{}\" isn't a valid MQTT port", port))?;
+
+                (host, port)
+            }
+            None => (address, default_port),
+        };
+
+        if host.is_empty() {
+            return Err(format!("\"{}\" has no host", url));
+        }
+
+        Ok(Self {
+            host: host.into(),
+            port,
+            tls,
+        })
+    }
+
+    /// Key the connection is stored under, e.g. `mqtt:mqtt.meshtastic.org:1883`
+    pub fn device_key(&self) -> DeviceKey {
+        format!("mqtt:{}:{}", self.host, self.port)
+    }
+}
+
+/// Topic filter matching everything published under the root topic
+pub fn subscription_topic(root_topic: Option<&str>) -> String {
+    let root_topic = root_topic
+        .map(|topic| topic.trim().trim_matches('/'))
+        .filter(|topic| !topic.is_empty())
+        .unwrap_or(DEFAULT_ROOT_TOPIC);
+
+    format!("{}/#", root_topic)
+}
+
+/// What a message published by a gateway holds
+#[derive(Clone, Debug, PartialEq)]
+pub enum MqttPayload {
+    /// A packet the gateway decrypted before publishing
+    Packet(protobufs::MeshPacket),
+
+    /// A packet on a channel the gateway couldn't or didn't decrypt
+    Encrypted,
+
+    /// A protobuf envelope that couldn't be decoded
+    Undecodable(String),
+
+    /// JSON, status and map reports, which the serial path has no packet for
+    Ignored,
+}
+
+/// Decodes a message published on `topic`. Gateways publish `ServiceEnvelope`s under
+/// `<root>/.../2/e/<channel>/<gateway>` (or `2/c/` on older firmware), next to
+/// messages in other formats under other topics.
+pub fn decode_mqtt_payload(topic: &str, payload: &[u8]) -> MqttPayload {
+    let segments: Vec<&str> = topic.split('/').collect();
+    let is_envelope = segments
+        .windows(2)
+        .any(|w| w[0] == "2" && (w[1] == "e" || w[1] == "c"));
+
+    if !is_envelope {
+        return MqttPayload::Ignored;
+    }
+
+    let envelope = match protobufs::ServiceEnvelope::decode(payload) {
+        Ok(envelope) => envelope,
+        Err(e) => return MqttPayload::Undecodable(e.to_string()),
+    };
+
+    match envelope.packet {
+        Some(packet) => match packet.payload_variant {
+            Some(protobufs::mesh_packet::PayloadVariant::Decoded(_)) => MqttPayload::Packet(packet),
+            Some(protobufs::mesh_packet::PayloadVariant::Encrypted(_)) => MqttPayload::Encrypted,
+            None => MqttPayload::Undecodable("Envelope packet has no payload".into()),
+        },
+        None => MqttPayload::Undecodable("Envelope has no packet".into()),
+    }
+}
+
+/// Opens a connection to the broker and waits until the subscription to `topic` is
+/// acknowledged, returning the event loop that keeps the connection going
+pub async fn subscribe(
+    broker: &MqttBroker,
+    username: Option<String>,
+    password: Option<String>,
+    topic: &str,
+) -> Result<(AsyncClient, EventLoop), String> {
+    let client_id = format!("meshtastic-nmc-{:08x}", generate_rand_id::<u32>());
+    let mut options = MqttOptions::new(client_id, broker.host.clone(), broker.port);
+    options.set_keep_alive(MQTT_KEEP_ALIVE);
+    options.set_max_packet_size(MQTT_MAX_PACKET_SIZE, MQTT_MAX_PACKET_SIZE);
+
+    if let Some(username) = username {
+        options.set_credentials(username, password.unwrap_or_default());
+    }
+
+    if broker.tls {
+        options.set_transport(Transport::tls_with_default_config());
+    }
+
+    let (client, mut event_loop) = AsyncClient::new(options, 100);
+
+    client
+        .subscribe(topic, QoS::AtMostOnce)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    loop {
+        match event_loop.poll().await.map_err(|e| e.to_string())? {
+            Event::Incoming(Packet::SubAck(_)) => return Ok((client, event_loop)),
+            event => trace!("MQTT event while subscribing: {:?}", event),
+        }
+    }
+}
+
+/// Feeds the packets published to the broker into `decoded_tx` as if a radio had
+/// decoded them, until the receiving device is disconnected. Dropped connections are
+/// retried with backoff, resubscribing once the broker accepts again. Messages that
+/// can't be decoded are counted and skipped.
+pub fn spawn_mqtt_handler(
+    client: AsyncClient,
+    mut event_loop: EventLoop,
+    topic: String,
+    decoded_tx: UnboundedSender<protobufs::FromRadio>,
+    metrics: SharedConnectionMetrics,
+    device_key: DeviceKey,
+) {
+    tauri::async_runtime::spawn(async move {
+        let (first_backoff, max_backoff) = MQTT_RECONNECT_BACKOFF;
+        let mut backoff = first_backoff;
+
+        loop {
+            let event = tokio::select! {
+                event = event_loop.poll() => event,
+                _ = decoded_tx.closed() => break,
+            };
+
+            match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    debug!("Reconnected to MQTT broker for \"{}\"", device_key);
+                    backoff = first_backoff;
+
+                    if let Err(e) = client.try_subscribe(topic.clone(), QoS::AtMostOnce) {
+                        warn!("Failed to resubscribe to \"{}\": {}", topic, e);
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    match decode_mqtt_payload(&publish.topic, &publish.payload) {
+                        MqttPayload::Packet(packet) => {
+                            let from_radio = protobufs::FromRadio {
+                                payload_variant: Some(
+                                    protobufs::from_radio::PayloadVariant::Packet(packet),
+                                ),
+                                ..Default::default()
+                            };
+
+                            if decoded_tx.send(from_radio).is_err() {
+                                break;
+                            }
+                        }
+                        MqttPayload::Encrypted => {
+                            if let Ok(mut metrics) = metrics.lock() {
+                                metrics.record_encrypted_skipped();
+                            }
+                        }
+                        MqttPayload::Undecodable(e) => {
+                            trace!("Skipping message on \"{}\": {}", publish.topic, e);
+
+                            if let Ok(mut metrics) = metrics.lock() {
+                                metrics.record_decode_failure();
+                            }
+                        }
+                        MqttPayload::Ignored => {}
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!(
+                        "Lost MQTT broker for \"{}\", retrying in {:?}: {}",
+                        device_key, backoff, e
+                    );
+
+                    tokio::select! {
+                        _ = tokio::time::sleep(backoff) => {}
+                        _ = decoded_tx.closed() => break,
+                    }
+
+                    backoff = (backoff * 2).min(max_backoff);
+                }
+            }
+        }
+
+        debug!("Stopped MQTT connection \"{}\"", device_key);
+        let _ = client.try_disconnect();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(payload_variant: protobufs::mesh_packet::PayloadVariant) -> Vec<u8> {
+        protobufs::ServiceEnvelope {
+            packet: Some(protobufs::MeshPacket {
+                from: 0x1234abcd,
+                payload_variant: Some(payload_variant),
+                ..Default::default()
+            }),
+            channel_id: "LongFast".into(),
+            gateway_id: "!1234abcd".into(),
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn parses_broker_urls() {
+        assert_eq!(
+            MqttBroker::parse("mqtt.meshtastic.org").unwrap(),
+            MqttBroker {
+                host: "mqtt.meshtastic.org".into(),
+                port: DEFAULT_MQTT_PORT,
+                tls: false,
+            }
+        );
+        assert_eq!(
+            MqttBroker::parse(" mqtts://broker.local/ ").unwrap(),
+            MqttBroker {
+                host: "broker.local".into(),
+                port: DEFAULT_MQTTS_PORT,
+                tls: true,
+            }
+        );
+        assert_eq!(
+            MqttBroker::parse("mqtt://broker.local:1884")
+                .unwrap()
+                .device_key(),
+            "mqtt:broker.local:1884"
+        );
+
+        assert!(MqttBroker::parse("https://broker.local").is_err());
+        assert!(MqttBroker::parse("mqtt://broker.local:port").is_err());
+        assert!(MqttBroker::parse("mqtt://:1883").is_err());
+    }
+
+    #[test]
+    fn subscribes_under_the_root_topic() {
+        assert_eq!(subscription_topic(None), "msh/#");
+        assert_eq!(subscription_topic(Some(" ")), "msh/#");
+        assert_eq!(subscription_topic(Some("msh/EU_868/")), "msh/EU_868/#");
+    }
+
+    #[test]
+    fn decodes_envelopes_and_skips_the_rest() {
+        let data = protobufs::Data {
+            portnum: protobufs::PortNum::TextMessageApp as i32,
+            payload: b"hello".to_vec(),
+            ..Default::default()
+        };
+
+        match decode_mqtt_payload(
+            "msh/US/2/e/LongFast/!1234abcd",
+            &envelope(protobufs::mesh_packet::PayloadVariant::Decoded(
+                data.clone(),
+            )),
+        ) {
+            MqttPayload::Packet(packet) => {
+                assert_eq!(packet.from, 0x1234abcd);
+                assert_eq!(
+                    packet.payload_variant,
+                    Some(protobufs::mesh_packet::PayloadVariant::Decoded(data))
+                );
+            }
+            other => panic!("expected a packet, got {:?}", other),
+        }
+
+        assert_eq!(
+            decode_mqtt_payload(
+                "msh/US/2/e/Private/!1234abcd",
+                &envelope(protobufs::mesh_packet::PayloadVariant::Encrypted(vec![
+                    1, 2, 3
+                ])),
+            ),
+            MqttPayload::Encrypted
+        );
+        assert!(matches!(
+            decode_mqtt_payload("msh/US/2/e/LongFast/!1234abcd", &[0xff, 0xff, 0xff]),
+            MqttPayload::Undecodable(_)
+        ));
+        assert_eq!(
+            decode_mqtt_payload("msh/US/2/json/LongFast/!1234abcd", b"{}"),
+            MqttPayload::Ignored
+        );
+        assert_eq!(
+            decode_mqtt_payload("msh/US/2/stat/!1234abcd", b"online"),
+            MqttPayload::Ignored
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::packet_api::router::DEFAULT_GRAPH_UPDATE_WINDOW;
+
+/// Baud rates the firmware's serial module can be set to. Radios connected over USB
+/// ignore the baud rate, but it's still checked so a typo fails before the port opens.
+pub const SUPPORTED_BAUD_RATES: [u32; 12] = [
+    110, 300, 600, 1_200, 2_400, 4_800, 9_600, 19_200, 38_400, 57_600, 115_200, 921_600,
+];
+
+/// How long a radio has to stream its configuration before the connection is failed.
+/// Radios with a large node database can take several seconds.
+pub const DEFAULT_CONFIGURATION_TIMEOUT: Duration = Duration::from_secs(15);
+
+pub const MIN_CONFIGURATION_TIMEOUT_MS: u32 = 1_000;
+pub const MAX_CONFIGURATION_TIMEOUT_MS: u32 = 5 * 60 * 1_000;
+
+/// How often a configured radio is asked for a reply, so a radio whose firmware has
+/// locked up while its port stays open is noticed
+pub const DEFAULT_HEARTBEAT_INTERVAL_SECS: u32 = 30;
+pub const MAX_HEARTBEAT_INTERVAL_SECS: u32 = 60 * 60;
+
+/// Heartbeats a radio can go without answering before it's considered unresponsive
+pub const DEFAULT_MISSED_HEARTBEATS: u32 = 3;
+pub const MAX_MISSED_HEARTBEATS: u32 = 20;
+
+/// Longest a connection's graph can be held back before it's published. Bursts of
+/// packets after connecting regenerate the graph at most once per window.
+pub const MAX_GRAPH_UPDATE_WINDOW_MS: u32 = 10_000;
+
+/// Heartbeat settings requested for a connection
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HeartbeatOptions {
+    pub interval_secs: Option<u32>, // 0 turns heartbeats off
+    pub missed_heartbeats: Option<u32>,
+    pub reconnect: bool, // reconnect to a radio once it's unresponsive
+}
+
+/// How a connected radio's link is kept alive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub missed_heartbeats: u32,
+    pub reconnect: bool,
+}
+
+impl Heartbeat {
+    /// How long a radio can stay silent before it's considered unresponsive
+    pub fn staleness_window(&self) -> Duration {
+        self.interval * self.missed_heartbeats
+    }
+}
+
+pub fn validate_baud_rate(baud_rate: Option<u32>) -> Result<(), String> {
+    match baud_rate {
+        Some(baud_rate) if !SUPPORTED_BAUD_RATES.contains(&baud_rate) => Err(format!(
+            "Unsupported baud rate {}, expected one of {:?}",
+            baud_rate, SUPPORTED_BAUD_RATES
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// The configuration timeout requested for a connection, the default if none was
+pub fn configuration_timeout(timeout_ms: Option<u32>) -> Result<Duration, String> {
+    let timeout_ms = match timeout_ms {
+        Some(timeout_ms) => timeout_ms,
+        None => return Ok(DEFAULT_CONFIGURATION_TIMEOUT),
+    };
+
+    if !(MIN_CONFIGURATION_TIMEOUT_MS..=MAX_CONFIGURATION_TIMEOUT_MS).contains(&timeout_ms) {
+        return Err(format!(
+            "Configuration timeout must be between {} and {} ms",
+            MIN_CONFIGURATION_TIMEOUT_MS, MAX_CONFIGURATION_TIMEOUT_MS
+        ));
+    }
+
+    Ok(Duration::from_millis(u64::from(timeout_ms)))
+}
+
+/// The graph update window requested for a connection, the default if none was.
+/// A zero window publishes the graph after every packet that changes it.
+pub fn graph_update_window(window_ms: Option<u32>) -> Result<Duration, String> {
+    let window_ms = match window_ms {
+        Some(window_ms) => window_ms,
+        None => return Ok(DEFAULT_GRAPH_UPDATE_WINDOW),
+    };
+
+    if window_ms > MAX_GRAPH_UPDATE_WINDOW_MS {
+        return Err(format!(
+            "Graph update window must be at most {} ms",
+            MAX_GRAPH_UPDATE_WINDOW_MS
+        ));
+    }
+
+    Ok(Duration::from_millis(u64::from(window_ms)))
+}
+
+/// The heartbeat requested for a connection, the default if none was. `None` if
+/// heartbeats were turned off.
+pub fn heartbeat(options: Option<HeartbeatOptions>) -> Result<Option<Heartbeat>, String> {
+    let options = options.unwrap_or_default();
+    let interval_secs = options
+        .interval_secs
+        .unwrap_or(DEFAULT_HEARTBEAT_INTERVAL_SECS);
+    let missed_heartbeats = options
+        .missed_heartbeats
+        .unwrap_or(DEFAULT_MISSED_HEARTBEATS);
+
+    if interval_secs == 0 {
+        return Ok(None);
+    }
+
+    if interval_secs > MAX_HEARTBEAT_INTERVAL_SECS {
+        return Err(format!(
+            "Heartbeat interval must be at most {} seconds",
+            MAX_HEARTBEAT_INTERVAL_SECS
+        ));
+    }
+
+    if !(1..=MAX_MISSED_HEARTBEATS).contains(&missed_heartbeats) {
+        return Err(format!(
+            "Missed heartbeats must be between 1 and {}",
+            MAX_MISSED_HEARTBEATS
+        ));
+    }
+
+    Ok(Some(Heartbeat {
+        interval: Duration::from_secs(u64::from(interval_secs)),
+        missed_heartbeats,
+        reconnect: options.reconnect,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_connection_options() {
+        assert!(validate_baud_rate(None).is_ok());
+        assert!(validate_baud_rate(Some(115_200)).is_ok());
+        assert!(validate_baud_rate(Some(115_201)).is_err());
+        assert!(validate_baud_rate(Some(0)).is_err());
+
+        assert_eq!(
+            configuration_timeout(None),
+            Ok(DEFAULT_CONFIGURATION_TIMEOUT)
+        );
+        assert_eq!(
+            configuration_timeout(Some(3_000)),
+            Ok(Duration::from_secs(3))
+        );
+        assert!(configuration_timeout(Some(MIN_CONFIGURATION_TIMEOUT_MS - 1)).is_err());
+        assert!(configuration_timeout(Some(MAX_CONFIGURATION_TIMEOUT_MS + 1)).is_err());
+
+        assert_eq!(graph_update_window(None), Ok(DEFAULT_GRAPH_UPDATE_WINDOW));
+        assert_eq!(graph_update_window(Some(0)), Ok(Duration::ZERO));
+        assert!(graph_update_window(Some(MAX_GRAPH_UPDATE_WINDOW_MS + 1)).is_err());
+
+        let default = heartbeat(None).unwrap().unwrap();
+        assert_eq!(default.staleness_window(), Duration::from_secs(90));
+        assert!(!default.reconnect);
+
+        let off = HeartbeatOptions {
+            interval_secs: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(heartbeat(Some(off)), Ok(None));
+
+        let too_lenient = HeartbeatOptions {
+            missed_heartbeats: Some(MAX_MISSED_HEARTBEATS + 1),
+            ..Default::default()
+        };
+        assert!(heartbeat(Some(too_lenient)).is_err());
+        assert!(heartbeat(Some(HeartbeatOptions {
+            missed_heartbeats: Some(0),
+            ..Default::default()
+        }))
+        .is_err());
+    }
+}
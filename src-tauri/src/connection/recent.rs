@@ -0,0 +1,395 @@
+use std::future::Future;
+use std::time::Duration;
+
+use log::{debug, warn};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+use tokio_util::sync::CancellationToken;
+
+use crate::packet_api::summary::ConnectionType;
+use crate::persistence::{save_json, RECENT_DEVICES_FILE_NAME};
+use crate::state;
+
+/// Most connections kept in the recent devices list
+pub const MAX_RECENT_DEVICES: usize = 10;
+
+/// A radio the app has connected to, keyed by its port name or address
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct RecentDevice {
+    pub connection_type: ConnectionType,
+    pub port: String, // serial port name or TCP address
+    pub baud_rate: Option<u32>,
+    pub node_num: Option<u32>,
+    pub node_name: Option<String>,
+    pub last_connected: u32, // secs
+    pub auto_connect: bool,  // connect when the app starts
+}
+
+impl RecentDevice {
+    pub fn new(
+        connection_type: ConnectionType,
+        port: String,
+        baud_rate: Option<u32>,
+        last_connected: u32,
+    ) -> Self {
+        Self {
+            connection_type,
+            port,
+            baud_rate,
+            node_num: None,
+            node_name: None,
+            last_connected,
+            auto_connect: false,
+        }
+    }
+}
+
+/// Most recently connected first
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RecentDevices {
+    pub devices: Vec<RecentDevice>,
+}
+
+impl RecentDevices {
+    /// Moves a connection to the front of the list. Reconnecting to a port keeps the
+    /// node it was last connected to and its auto-connect flag.
+    pub fn record_connection(&mut self, mut device: RecentDevice) {
+        if let Some(index) = self.devices.iter().position(|d| d.port == device.port) {
+            let previous = self.devices.remove(index);
+
+            if previous.connection_type == device.connection_type {
+                device.node_num = device.node_num.or(previous.node_num);
+                device.node_name = device.node_name.or(previous.node_name);
+                device.auto_connect = previous.auto_connect;
+            }
+        }
+
+        self.devices.insert(0, device);
+        self.devices.truncate(MAX_RECENT_DEVICES);
+    }
+
+    /// Records the node a port's radio reported once it finished configuring,
+    /// returning whether the port is in the list
+    pub fn set_node(&mut self, port: &str, node_num: u32, node_name: Option<String>) -> bool {
+        match self.devices.iter_mut().find(|d| d.port == port) {
+            Some(device) => {
+                device.node_num = Some(node_num);
+                device.node_name = node_name;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_auto_connect(&mut self, port: &str, auto_connect: bool) -> Result<(), String> {
+        let device = self
+            .devices
+            .iter_mut()
+            .find(|d| d.port == port)
+            .ok_or_else(|| format!("\"{}\" is not a recent device", port))?;
+
+        device.auto_connect = auto_connect;
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, port: &str) -> Option<RecentDevice> {
+        let index = self.devices.iter().position(|d| d.port == port)?;
+
+        Some(self.devices.remove(index))
+    }
+
+    pub fn auto_connect_devices(&self) -> Vec<RecentDevice> {
+        self.devices
+            .iter()
+            .filter(|d| d.auto_connect)
+            .cloned()
+            .collect()
+    }
+}
+
+/// How often a device is retried when connecting to it fails, e.g. while the OS is
+/// still enumerating USB devices. The delay doubles after each failure, up to
+/// `max_retry_delay`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AutoConnectPolicy {
+    pub max_attempts: u32,
+    pub retry_delay: Duration,
+    pub max_retry_delay: Duration,
+}
+
+impl Default for AutoConnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            retry_delay: Duration::from_secs(5),
+            max_retry_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+impl AutoConnectPolicy {
+    /// Retries a lost connection quickly at first, in case the cable was only bumped,
+    /// then backs off to every 30 seconds
+    pub fn reconnect(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            retry_delay: Duration::from_secs(1),
+            max_retry_delay: Duration::from_secs(30),
+        }
+    }
+
+    /// Delay after the `attempt`th failed attempt, counting from 1
+    pub fn delay_after(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+
+        self.retry_delay
+            .saturating_mul(factor)
+            .min(self.max_retry_delay)
+    }
+}
+
+/// Calls `connect` until it succeeds, `policy.max_attempts` have failed or `cancel` is
+/// cancelled, returning the number of attempts made or the last attempt's error
+pub async fn auto_connect_with<F, Fut>(
+    device: &RecentDevice,
+    policy: AutoConnectPolicy,
+    cancel: &CancellationToken,
+    mut connect: F,
+) -> Result<u32, String>
+where
+    F: FnMut(RecentDevice) -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    let mut attempt = 1;
+
+    loop {
+        debug!(
+            "Auto-connecting to \"{}\", attempt {} of {}",
+            device.port, attempt, policy.max_attempts
+        );
+
+        let delay = policy.delay_after(attempt);
+
+        match connect(device.clone()).await {
+            Ok(()) => return Ok(attempt),
+            Err(e) if attempt >= policy.max_attempts => return Err(e),
+            Err(e) => warn!(
+                "Auto-connect to \"{}\" failed, retrying in {:?}: {}",
+                device.port, delay, e
+            ),
+        }
+
+        attempt += 1;
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = cancel.cancelled() => {
+                return Err(format!("Stopped connecting to \"{}\"", device.port))
+            }
+        }
+    }
+}
+
+fn save_recent_devices<R: tauri::Runtime>(handle: &tauri::AppHandle<R>, devices: &RecentDevices) {
+    if let Err(e) = save_json(handle, RECENT_DEVICES_FILE_NAME, devices) {
+        warn!("Failed to save recent devices: {}", e);
+    }
+}
+
+pub fn record_recent_device<R: tauri::Runtime>(handle: &tauri::AppHandle<R>, device: RecentDevice) {
+    let recent_state = match handle.try_state::<state::recent_devices::RecentDevicesState>() {
+        Some(recent_state) => recent_state,
+        None => return,
+    };
+
+    let devices = match recent_state.inner.lock() {
+        Ok(mut devices) => {
+            devices.record_connection(device);
+            devices.clone()
+        }
+        Err(e) => {
+            warn!("Failed to lock recent devices: {}", e);
+            return;
+        }
+    };
+
+    save_recent_devices(handle, &devices);
+}
+
+pub fn record_recent_device_node<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    port: &str,
+    node_num: u32,
+    node_name: Option<String>,
+) {
+    let recent_state = match handle.try_state::<state::recent_devices::RecentDevicesState>() {
+        Some(recent_state) => recent_state,
+        None => return,
+    };
+
+    let devices = match recent_state.inner.lock() {
+        Ok(mut devices) if devices.set_node(port, node_num, node_name) => devices.clone(),
+        Ok(_) => return,
+        Err(e) => {
+            warn!("Failed to lock recent devices: {}", e);
+            return;
+        }
+    };
+
+    save_recent_devices(handle, &devices);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    fn serial(port: &str, last_connected: u32) -> RecentDevice {
+        RecentDevice::new(
+            ConnectionType::Serial,
+            port.into(),
+            Some(115_200),
+            last_connected,
+        )
+    }
+
+    fn ports(recent: &RecentDevices) -> Vec<&str> {
+        recent.devices.iter().map(|d| d.port.as_str()).collect()
+    }
+
+    #[test]
+    fn keeps_most_recent_first() {
+        let mut recent = RecentDevices::default();
+
+        recent.record_connection(serial("/dev/ttyUSB0", 100));
+        recent.record_connection(serial("/dev/ttyACM0", 200));
+        recent.record_connection(RecentDevice::new(
+            ConnectionType::Tcp,
+            "192.168.1.20:4403".into(),
+            None,
+            300,
+        ));
+        assert_eq!(
+            ports(&recent),
+            vec!["192.168.1.20:4403", "/dev/ttyACM0", "/dev/ttyUSB0"]
+        );
+
+        assert!(recent.set_node("/dev/ttyUSB0", 0x1234, Some("Base".into())));
+        recent.set_auto_connect("/dev/ttyUSB0", true).unwrap();
+
+        // Reconnecting moves the port to the front without duplicating it, and keeps
+        // what's known about it
+        recent.record_connection(serial("/dev/ttyUSB0", 400));
+        assert_eq!(
+            ports(&recent),
+            vec!["/dev/ttyUSB0", "192.168.1.20:4403", "/dev/ttyACM0"]
+        );
+
+        let device = &recent.devices[0];
+        assert_eq!(device.last_connected, 400);
+        assert_eq!(device.node_num, Some(0x1234));
+        assert_eq!(device.node_name.as_deref(), Some("Base"));
+        assert!(device.auto_connect);
+        assert_eq!(recent.auto_connect_devices(), vec![device.clone()]);
+
+        assert!(recent.set_auto_connect("/dev/ttyS9", true).is_err());
+        assert!(!recent.set_node("/dev/ttyS9", 1, None));
+
+        for i in 0..MAX_RECENT_DEVICES as u32 {
+            recent.record_connection(serial(&format!("/dev/ttyS{}", i), 500 + i));
+        }
+        assert_eq!(recent.devices.len(), MAX_RECENT_DEVICES);
+        assert_eq!(
+            recent.devices[0].port,
+            format!("/dev/ttyS{}", MAX_RECENT_DEVICES - 1)
+        );
+    }
+
+    /// Connection that fails its first `failures` attempts
+    fn mock_connection(
+        failures: u32,
+    ) -> (
+        Arc<Mutex<u32>>,
+        impl FnMut(RecentDevice) -> std::future::Ready<Result<(), String>>,
+    ) {
+        let attempts = Arc::new(Mutex::new(0));
+        let counter = attempts.clone();
+
+        let connect = move |device: RecentDevice| {
+            let mut attempts = counter.lock().unwrap();
+            *attempts += 1;
+
+            std::future::ready(match *attempts <= failures {
+                true => Err(format!("No such port \"{}\"", device.port)),
+                false => Ok(()),
+            })
+        };
+
+        (attempts, connect)
+    }
+
+    #[tokio::test]
+    async fn bounds_auto_connect_attempts() {
+        let device = serial("/dev/ttyUSB0", 100);
+        let policy = AutoConnectPolicy {
+            max_attempts: 3,
+            retry_delay: Duration::ZERO,
+            max_retry_delay: Duration::ZERO,
+        };
+        let cancel = CancellationToken::new();
+
+        let (attempts, connect) = mock_connection(0);
+        assert_eq!(
+            auto_connect_with(&device, policy, &cancel, connect).await,
+            Ok(1)
+        );
+        assert_eq!(*attempts.lock().unwrap(), 1);
+
+        let (attempts, connect) = mock_connection(2);
+        assert_eq!(
+            auto_connect_with(&device, policy, &cancel, connect).await,
+            Ok(3)
+        );
+        assert_eq!(*attempts.lock().unwrap(), 3);
+
+        // A device that never appears is given up on
+        let (attempts, connect) = mock_connection(u32::MAX);
+        assert_eq!(
+            auto_connect_with(&device, policy, &cancel, connect).await,
+            Err("No such port \"/dev/ttyUSB0\"".into())
+        );
+        assert_eq!(*attempts.lock().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn stops_retrying_once_cancelled() {
+        let device = serial("/dev/ttyUSB0", 100);
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let (attempts, connect) = mock_connection(u32::MAX);
+        assert!(
+            auto_connect_with(&device, AutoConnectPolicy::reconnect(10), &cancel, connect)
+                .await
+                .is_err()
+        );
+        assert_eq!(*attempts.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn reconnect_delay_backs_off() {
+        let policy = AutoConnectPolicy::reconnect(10);
+        let delays: Vec<u64> = (1..=7).map(|a| policy.delay_after(a).as_secs()).collect();
+
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30]);
+        assert_eq!(policy.delay_after(u32::MAX), Duration::from_secs(30));
+
+        let startup = AutoConnectPolicy::default();
+        assert_eq!(startup.delay_after(3), startup.retry_delay);
+    }
+}
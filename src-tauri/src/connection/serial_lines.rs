@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use log::{debug, info};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+/// Controls how the DTR and RTS lines are driven after a serial port is opened,
+/// before the configuration handshake begins. Some ESP32 boards behind certain
+/// USB-serial bridges need a toggle to leave a wedged or bootloader state.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", tag = "mode")]
+pub enum SerialLineControl {
+    /// Leave the lines as they were when the port was opened
+    #[default]
+    None,
+
+    /// Assert both lines, wait for `delay_ms`, then release both lines
+    #[serde(rename_all = "camelCase")]
+    Pulse { delay_ms: u64 },
+
+    /// Drive both lines to explicit levels
+    Explicit { dtr: bool, rts: bool },
+}
+
+/// Minimal abstraction over a serial port's line control,
+/// allowing the toggle sequence to be tested without hardware.
+pub trait SerialLines {
+    fn write_data_terminal_ready(&mut self, level: bool) -> Result<(), String>;
+    fn write_request_to_send(&mut self, level: bool) -> Result<(), String>;
+}
+
+impl<T: tokio_serial::SerialPort> SerialLines for T {
+    fn write_data_terminal_ready(&mut self, level: bool) -> Result<(), String> {
+        tokio_serial::SerialPort::write_data_terminal_ready(self, level).map_err(|e| e.to_string())
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> Result<(), String> {
+        tokio_serial::SerialPort::write_request_to_send(self, level).map_err(|e| e.to_string())
+    }
+}
+
+/// Applies the requested line control sequence to an opened serial port
+pub async fn apply_serial_line_control<P: SerialLines>(
+    port: &mut P,
+    line_control: &SerialLineControl,
+) -> Result<(), String> {
+    info!("Applying serial line control {:?}", line_control);
+
+    match line_control {
+        SerialLineControl::None => {}
+        SerialLineControl::Pulse { delay_ms } => {
+            port.write_data_terminal_ready(true)?;
+            port.write_request_to_send(true)?;
+
+            debug!("Asserted DTR and RTS, releasing in {} ms", delay_ms);
+            tokio::time::sleep(Duration::from_millis(*delay_ms)).await;
+
+            port.write_data_terminal_ready(false)?;
+            port.write_request_to_send(false)?;
+        }
+        SerialLineControl::Explicit { dtr, rts } => {
+            port.write_data_terminal_ready(*dtr)?;
+            port.write_request_to_send(*rts)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct RecordingLines {
+        calls: Vec<(&'static str, bool)>,
+    }
+
+    impl SerialLines for RecordingLines {
+        fn write_data_terminal_ready(&mut self, level: bool) -> Result<(), String> {
+            self.calls.push(("dtr", level));
+            Ok(())
+        }
+
+        fn write_request_to_send(&mut self, level: bool) -> Result<(), String> {
+            self.calls.push(("rts", level));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn none_leaves_lines_untouched() {
+        let mut lines = RecordingLines::default();
+        apply_serial_line_control(&mut lines, &SerialLineControl::None)
+            .await
+            .unwrap();
+
+        assert!(lines.calls.is_empty());
+    }
+
+    #[tokio::test]
+    async fn pulse_asserts_then_releases() {
+        let mut lines = RecordingLines::default();
+        apply_serial_line_control(&mut lines, &SerialLineControl::Pulse { delay_ms: 1 })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            lines.calls,
+            vec![("dtr", true), ("rts", true), ("dtr", false), ("rts", false)]
+        );
+    }
+
+    #[tokio::test]
+    async fn explicit_sets_levels() {
+        let mut lines = RecordingLines::default();
+        apply_serial_line_control(
+            &mut lines,
+            &SerialLineControl::Explicit {
+                dtr: false,
+                rts: true,
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(lines.calls, vec![("dtr", false), ("rts", true)]);
+    }
+
+    #[test]
+    fn deserializes_tagged_modes() {
+        let pulse: SerialLineControl =
+            serde_json::from_str(r#"{ "mode": "pulse", "delayMs": 100 }"#).unwrap();
+
+        assert_eq!(pulse, SerialLineControl::Pulse { delay_ms: 100 });
+    }
+}
@@ -0,0 +1,80 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// Port the firmware serves its protobuf stream on over WiFi and Ethernet
+pub const DEFAULT_TCP_PORT: u16 = 4403;
+
+/// Longest a TCP connection may take to open. Connecting to an address nothing answers
+/// on otherwise waits for the OS to give up, which takes minutes.
+pub const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Adds the firmware's port to an address given without one, so `meshtastic.local` and
+/// `192.168.1.20` connect like `meshtastic.local:4403` and `192.168.1.20:4403`. The
+/// result is also the key the connection is stored under.
+pub fn tcp_socket_address(address: &str) -> Result<String, String> {
+    let address = address.trim();
+
+    if address.is_empty() {
+        return Err("TCP address is empty".into());
+    }
+
+    if address.parse::<SocketAddr>().is_ok() {
+        return Ok(address.into());
+    }
+
+    // IPv6 addresses are bracketed to take a port
+    if let Ok(ip) = address.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, DEFAULT_TCP_PORT).to_string());
+    }
+
+    match address.rsplit_once(':') {
+        Some((host, port)) => {
+            let port: u16 = port
+                .parse()
+                .map_err(|_| format!("\"{}\" isn't a valid TCP port", port))?;
+
+            if host.is_empty() {
+                return Err(format!("\"{}\" has no host", address));
+            }
+
+            Ok(format!("{}:{}", host, port))
+        }
+        None => Ok(format!("{}:{}", address, DEFAULT_TCP_PORT)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_default_port() {
+        assert_eq!(
+            tcp_socket_address("192.168.1.20").unwrap(),
+            "192.168.1.20:4403"
+        );
+        assert_eq!(
+            tcp_socket_address(" meshtastic.local ").unwrap(),
+            "meshtastic.local:4403"
+        );
+        assert_eq!(tcp_socket_address("fe80::1").unwrap(), "[fe80::1]:4403");
+
+        assert_eq!(
+            tcp_socket_address("192.168.1.20:4000").unwrap(),
+            "192.168.1.20:4000"
+        );
+        assert_eq!(
+            tcp_socket_address("[fe80::1]:4000").unwrap(),
+            "[fe80::1]:4000"
+        );
+        assert_eq!(
+            tcp_socket_address("router.lan:4000").unwrap(),
+            "router.lan:4000"
+        );
+
+        assert!(tcp_socket_address("").is_err());
+        assert!(tcp_socket_address(":4403").is_err());
+        assert!(tcp_socket_address("router.lan:http").is_err());
+        assert!(tcp_socket_address("router.lan:70000").is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use std::fmt;
+
+use meshtastic::protobufs;
+use meshtastic::ts::specta::{self, Type};
+use meshtastic::Message;
+use serde::{Deserialize, Serialize};
+
+/// URL scheme registered for the app with the OS
+pub const DEEP_LINK_SCHEME: &str = "meshtastic";
+
+const CHANNEL_SET_HOSTS: [&str; 2] = ["meshtastic.org", "www.meshtastic.org"];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeLink {
+    pub node_num: u32,
+}
+
+/// What a `meshtastic://` or `https://meshtastic.org/e/#...` link points to
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type")]
+pub enum DeepLink {
+    Node(NodeLink),
+    ChannelSet(protobufs::ChannelSet),
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeepLinkError {
+    UnsupportedUrl(String),
+    InvalidNodeId(String),
+    InvalidEncoding(String),
+    InvalidChannelSet(String),
+}
+
+impl fmt::Display for DeepLinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeepLinkError::UnsupportedUrl(url) => write!(f, "Unsupported link \"{}\"", url),
+            DeepLinkError::InvalidNodeId(id) => write!(f, "Invalid node id \"{}\" in link", id),
+            DeepLinkError::InvalidEncoding(e) => write!(f, "Link payload is not base64: {}", e),
+            DeepLinkError::InvalidChannelSet(e) => {
+                write!(f, "Link does not contain a valid channel set: {}", e)
+            }
+        }
+    }
+}
+
+/// Parses node ids as shown in the UI (`!a1b2c3d4`) or as plain node numbers
+fn parse_node_id(id: &str) -> Result<u32, DeepLinkError> {
+    let parsed = match id.strip_prefix('!') {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => id.parse(),
+    };
+
+    parsed.map_err(|_| DeepLinkError::InvalidNodeId(id.into()))
+}
+
+fn base64_value(c: u8) -> Option<u8> {
+    match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' | b'-' => Some(62),
+        b'/' | b'_' => Some(63),
+        _ => None,
+    }
+}
+
+/// Decodes base64 in either the standard or the URL-safe alphabet, with or without
+/// padding. Channel URLs are URL-safe and unpadded, but both are seen in the wild.
+pub fn decode_base64(input: &str) -> Result<Vec<u8>, DeepLinkError> {
+    let input = input.trim_end_matches('=');
+
+    if input.len() % 4 == 1 {
+        return Err(DeepLinkError::InvalidEncoding(format!(
+            "invalid length {}",
+            input.len()
+        )));
+    }
+
+    let mut bytes = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in input.bytes() {
+        let value = base64_value(c).ok_or_else(|| {
+            DeepLinkError::InvalidEncoding(format!("invalid character '{}'", c as char))
+        })?;
+
+        buffer = (buffer << 6) | u32::from(value);
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Encodes bytes as padded base64 in the standard alphabet
+pub fn encode_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+
+    for chunk in bytes.chunks(3) {
+        let buffer = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, b)| acc | (u32::from(*b) << (16 - 8 * i)));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((buffer >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Decodes the fragment of a channel URL into the channel set it shares
+pub fn decode_channel_set(payload: &str) -> Result<protobufs::ChannelSet, DeepLinkError> {
+    let bytes = decode_base64(payload)?;
+
+    let channel_set = protobufs::ChannelSet::decode(bytes.as_slice())
+        .map_err(|e| DeepLinkError::InvalidChannelSet(e.to_string()))?;
+
+    if channel_set.settings.is_empty() {
+        return Err(DeepLinkError::InvalidChannelSet("no channels".into()));
+    }
+
+    Ok(channel_set)
+}
+
+/// Channels to write for an imported channel set. The first channel is primary.
+pub fn channel_set_to_channels(channel_set: &protobufs::ChannelSet) -> Vec<protobufs::Channel> {
+    channel_set
+        .settings
+        .iter()
+        .enumerate()
+        .map(|(index, settings)| protobufs::Channel {
+            index: index as i32,
+            settings: Some(settings.clone()),
+            role: if index == 0 {
+                protobufs::channel::Role::Primary as i32
+            } else {
+                protobufs::channel::Role::Secondary as i32
+            },
+        })
+        .collect()
+}
+
+/// Classifies and decodes a link. Links that can't be decoded are errors, while
+/// well-formed `meshtastic://` links this version doesn't handle are `Unknown`.
+pub fn parse_deep_link(url: &str) -> Result<DeepLink, DeepLinkError> {
+    let url = url.trim();
+    let unsupported = || DeepLinkError::UnsupportedUrl(url.into());
+
+    let (scheme, rest) = url.split_once("://").ok_or_else(unsupported)?;
+    let (location, fragment) = match rest.split_once('#') {
+        Some((location, fragment)) => (location, Some(fragment)),
+        None => (rest, None),
+    };
+
+    // Query parameters such as `?add=true` don't change what the link points to
+    let location = location.split('?').next().unwrap_or_default();
+    let mut segments = location.split('/').filter(|s| !s.is_empty());
+
+    let host = match scheme.to_ascii_lowercase().as_str() {
+        DEEP_LINK_SCHEME => None,
+        "http" | "https" => Some(segments.next().ok_or_else(unsupported)?),
+        _ => return Err(unsupported()),
+    };
+
+    if let Some(host) = host {
+        if !CHANNEL_SET_HOSTS.contains(&host.to_ascii_lowercase().as_str()) {
+            return Err(unsupported());
+        }
+    }
+
+    match (segments.next(), segments.next()) {
+        (Some("e"), None) => {
+            let payload = fragment.filter(|f| !f.is_empty()).ok_or_else(|| {
+                DeepLinkError::InvalidChannelSet("link has no channel payload".into())
+            })?;
+
+            decode_channel_set(payload).map(DeepLink::ChannelSet)
+        }
+        (Some("node"), Some(id)) if host.is_none() => Ok(DeepLink::Node(NodeLink {
+            node_num: parse_node_id(id)?,
+        })),
+        _ if host.is_none() => Ok(DeepLink::Unknown),
+        _ => Err(unsupported()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel_set() -> protobufs::ChannelSet {
+        protobufs::ChannelSet {
+            settings: vec![
+                protobufs::ChannelSettings {
+                    name: "Ops".into(),
+                    psk: vec![1],
+                    ..Default::default()
+                },
+                protobufs::ChannelSettings {
+                    name: "Admin".into(),
+                    psk: (0..16).collect(),
+                    ..Default::default()
+                },
+            ],
+            lora_config: Some(protobufs::config::LoRaConfig {
+                use_preset: true,
+                hop_limit: 3,
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn encode_base64_url(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+        let mut encoded = String::new();
+
+        for chunk in bytes.chunks(3) {
+            let buffer = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |acc, (i, b)| acc | (u32::from(*b) << (16 - 8 * i)));
+
+            for i in 0..=chunk.len() {
+                encoded.push(ALPHABET[((buffer >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            }
+        }
+
+        encoded
+    }
+
+    #[test]
+    fn parses_node_links() {
+        assert_eq!(
+            parse_deep_link("meshtastic://node/!a1b2c3d4"),
+            Ok(DeepLink::Node(NodeLink {
+                node_num: 0xa1b2c3d4
+            }))
+        );
+        assert_eq!(
+            parse_deep_link("meshtastic://node/12345"),
+            Ok(DeepLink::Node(NodeLink { node_num: 12345 }))
+        );
+        assert_eq!(
+            parse_deep_link("meshtastic://node/!nothex"),
+            Err(DeepLinkError::InvalidNodeId("!nothex".into()))
+        );
+    }
+
+    #[test]
+    fn parses_channel_set_links() {
+        let payload = encode_base64_url(&channel_set().encode_to_vec());
+
+        for url in [
+            format!("https://meshtastic.org/e/#{}", payload),
+            format!("https://meshtastic.org/e/?add=true#{}", payload),
+            format!("meshtastic://e/#{}", payload),
+        ] {
+            assert_eq!(
+                parse_deep_link(&url),
+                Ok(DeepLink::ChannelSet(channel_set())),
+                "{}",
+                url
+            );
+        }
+
+        let channels = channel_set_to_channels(&channel_set());
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[0].role, protobufs::channel::Role::Primary as i32);
+        assert_eq!(channels[1].index, 1);
+        assert_eq!(channels[1].role, protobufs::channel::Role::Secondary as i32);
+    }
+
+    #[test]
+    fn classifies_other_links() {
+        assert_eq!(
+            parse_deep_link("meshtastic://settings/lora"),
+            Ok(DeepLink::Unknown)
+        );
+        assert_eq!(
+            parse_deep_link("https://example.com/e/#CgMSAQE"),
+            Err(DeepLinkError::UnsupportedUrl(
+                "https://example.com/e/#CgMSAQE".into()
+            ))
+        );
+        assert!(matches!(
+            parse_deep_link("not a link"),
+            Err(DeepLinkError::UnsupportedUrl(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_corrupted_channel_payloads() {
+        let payload = encode_base64_url(&channel_set().encode_to_vec());
+
+        assert!(matches!(
+            parse_deep_link(&format!("https://meshtastic.org/e/#{}*", payload)),
+            Err(DeepLinkError::InvalidEncoding(_))
+        ));
+        assert!(matches!(
+            parse_deep_link("https://meshtastic.org/e/#A"),
+            Err(DeepLinkError::InvalidEncoding(_))
+        ));
+
+        // Valid base64 that isn't a channel set
+        assert!(matches!(
+            parse_deep_link(&format!("https://meshtastic.org/e/#{}", &payload[..10])),
+            Err(DeepLinkError::InvalidChannelSet(_))
+        ));
+        assert!(matches!(
+            parse_deep_link("https://meshtastic.org/e/"),
+            Err(DeepLinkError::InvalidChannelSet(_))
+        ));
+    }
+}
@@ -0,0 +1,98 @@
+/// Maximum number of bytes the canned message module accepts for the
+/// pipe-delimited message list.
+pub const MAX_CANNED_MESSAGES_BYTES: usize = 200;
+
+pub const CANNED_MESSAGE_DELIMITER: char = '|';
+
+/// Encodes a list of canned messages into the pipe-delimited
+/// representation expected by the canned message module.
+///
+/// # Arguments
+///
+/// * `messages` - The messages to encode, in display order.
+///
+/// # Returns
+///
+/// * `Result<String, String>` - The encoded message list, or a description
+///   of why the list can't be sent to the device.
+pub fn encode_canned_messages(messages: &[String]) -> Result<String, String> {
+    if let Some(message) = messages
+        .iter()
+        .find(|m| m.contains(CANNED_MESSAGE_DELIMITER))
+    {
+        return Err(format!(
+            "Canned message \"{}\" cannot contain the \"{}\" character",
+            message, CANNED_MESSAGE_DELIMITER
+        ));
+    }
+
+    if messages.iter().any(|m| m.is_empty()) {
+        return Err("Canned messages cannot be empty".into());
+    }
+
+    let encoded = messages.join(&CANNED_MESSAGE_DELIMITER.to_string());
+
+    if encoded.len() > MAX_CANNED_MESSAGES_BYTES {
+        return Err(format!(
+            "Canned messages are {} bytes over the {} byte limit",
+            encoded.len() - MAX_CANNED_MESSAGES_BYTES,
+            MAX_CANNED_MESSAGES_BYTES
+        ));
+    }
+
+    Ok(encoded)
+}
+
+/// Decodes the pipe-delimited canned message list reported by the device.
+pub fn decode_canned_messages(encoded: &str) -> Vec<String> {
+    encoded
+        .split(CANNED_MESSAGE_DELIMITER)
+        .filter(|m| !m.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_with_delimiter() {
+        let messages = vec!["Yes".to_string(), "No".into(), "On my way".into()];
+
+        assert_eq!(
+            encode_canned_messages(&messages),
+            Ok("Yes|No|On my way".into())
+        );
+    }
+
+    #[test]
+    fn round_trips_through_decode() {
+        let messages = vec!["Need help".to_string(), "All good".into()];
+        let encoded = encode_canned_messages(&messages).unwrap();
+
+        assert_eq!(decode_canned_messages(&encoded), messages);
+        assert_eq!(decode_canned_messages(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn rejects_delimiter_in_message() {
+        let messages = vec!["a|b".to_string()];
+
+        assert!(encode_canned_messages(&messages).is_err());
+    }
+
+    #[test]
+    fn reports_bytes_over_limit() {
+        let messages = vec!["a".repeat(150), "b".repeat(55)];
+
+        // 150 + 1 delimiter + 55 = 206 bytes
+        assert_eq!(
+            encode_canned_messages(&messages),
+            Err("Canned messages are 6 bytes over the 200 byte limit".into())
+        );
+
+        let messages = vec!["a".repeat(100), "b".repeat(99)];
+        assert!(encode_canned_messages(&messages).is_ok());
+    }
+}
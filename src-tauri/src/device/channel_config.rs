@@ -0,0 +1,168 @@
+use meshtastic::protobufs;
+use meshtastic::ts::specta::{self, Type};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::deep_link::{decode_base64, encode_base64};
+
+/// Shorthand for the firmware's default key, stored as the single byte 1
+pub const DEFAULT_PSK: &str = "default";
+
+/// Shorthand for a new random AES-256 key
+pub const RANDOM_PSK: &str = "random";
+
+/// Key lengths the firmware accepts: no encryption, one of its built-in keys by
+/// index, AES-128 and AES-256
+pub const VALID_PSK_LENGTHS: [usize; 4] = [0, 1, 16, 32];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum ChannelRole {
+    Disabled,
+    Primary,
+    Secondary,
+}
+
+impl ChannelRole {
+    fn from_proto(role: i32) -> Self {
+        match protobufs::channel::Role::from_i32(role) {
+            Some(protobufs::channel::Role::Primary) => ChannelRole::Primary,
+            Some(protobufs::channel::Role::Secondary) => ChannelRole::Secondary,
+            _ => ChannelRole::Disabled,
+        }
+    }
+
+    fn to_proto(self) -> i32 {
+        match self {
+            ChannelRole::Disabled => protobufs::channel::Role::Disabled as i32,
+            ChannelRole::Primary => protobufs::channel::Role::Primary as i32,
+            ChannelRole::Secondary => protobufs::channel::Role::Secondary as i32,
+        }
+    }
+}
+
+/// The parts of a channel that can be edited from the app
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct EditableChannel {
+    pub index: u32,
+    pub role: ChannelRole,
+    pub name: String,
+    pub psk: String, // base64, or `DEFAULT_PSK` or `RANDOM_PSK` when writing
+    pub uplink_enabled: bool,
+    pub downlink_enabled: bool,
+}
+
+impl EditableChannel {
+    pub fn from_channel(channel: &protobufs::Channel) -> Self {
+        let settings = channel.settings.clone().unwrap_or_default();
+
+        Self {
+            index: channel.index.try_into().unwrap_or_default(),
+            role: ChannelRole::from_proto(channel.role),
+            name: settings.name,
+            psk: encode_base64(&settings.psk),
+            uplink_enabled: settings.uplink_enabled,
+            downlink_enabled: settings.downlink_enabled,
+        }
+    }
+
+    /// Applies the edit to the channel currently at its index, keeping the settings
+    /// that can't be edited. Fails without changing anything if the edit is invalid.
+    pub fn apply_to(self, existing: &protobufs::Channel) -> Result<protobufs::Channel, String> {
+        // The firmware only allows channel 0 to be primary, and requires it to be
+        if (self.index == 0) != (self.role == ChannelRole::Primary) {
+            return Err("Channel 0 must be the primary channel, and only channel 0".into());
+        }
+
+        let psk = parse_psk(&self.psk)?;
+        let settings = existing.settings.clone().unwrap_or_default();
+
+        Ok(protobufs::Channel {
+            index: existing.index,
+            role: self.role.to_proto(),
+            settings: Some(protobufs::ChannelSettings {
+                name: self.name,
+                psk,
+                uplink_enabled: self.uplink_enabled,
+                downlink_enabled: self.downlink_enabled,
+                ..settings
+            }),
+        })
+    }
+}
+
+/// Parses a base64 key, or one of the `DEFAULT_PSK` and `RANDOM_PSK` shorthands
+pub fn parse_psk(psk: &str) -> Result<Vec<u8>, String> {
+    let psk = psk.trim();
+
+    if psk.eq_ignore_ascii_case(DEFAULT_PSK) {
+        return Ok(vec![1]);
+    }
+
+    if psk.eq_ignore_ascii_case(RANDOM_PSK) {
+        let mut key = vec![0; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        return Ok(key);
+    }
+
+    let key = decode_base64(psk).map_err(|e| format!("Invalid channel key: {}", e))?;
+
+    if !VALID_PSK_LENGTHS.contains(&key.len()) {
+        return Err(format!(
+            "Channel key must be 0, 1, 16 or 32 bytes long, got {}",
+            key.len()
+        ));
+    }
+
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(index: i32, role: protobufs::channel::Role) -> protobufs::Channel {
+        protobufs::Channel {
+            index,
+            role: role as i32,
+            settings: Some(protobufs::ChannelSettings {
+                name: "LongFast".into(),
+                psk: vec![1],
+                id: 42,
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn edits_channels_with_checked_keys() {
+        let existing = channel(1, protobufs::channel::Role::Disabled);
+
+        let mut edit = EditableChannel::from_channel(&existing);
+        assert_eq!(edit.psk, "AQ==");
+        assert_eq!(edit.role, ChannelRole::Disabled);
+
+        edit.role = ChannelRole::Secondary;
+        edit.name = "Team".into();
+        edit.psk = encode_base64(&[7; 16]);
+
+        let updated = edit.clone().apply_to(&existing).unwrap();
+        let settings = updated.settings.as_ref().unwrap();
+        assert_eq!(settings.psk, vec![7; 16]);
+        assert_eq!(settings.id, 42);
+        assert_eq!(EditableChannel::from_channel(&updated), edit);
+
+        assert_eq!(parse_psk("default"), Ok(vec![1]));
+        assert_eq!(parse_psk("random").unwrap().len(), 32);
+        assert_eq!(parse_psk(""), Ok(vec![]));
+        assert!(parse_psk(&encode_base64(&[7; 20])).is_err());
+        assert!(parse_psk("not base64!").is_err());
+
+        let primary = EditableChannel {
+            role: ChannelRole::Primary,
+            ..edit
+        };
+        assert!(primary.apply_to(&existing).is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use meshtastic::protobufs;
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+/// Difference between device and host clocks, in seconds, beyond which
+/// the device clock is considered skewed.
+pub const DEFAULT_CLOCK_SKEW_THRESHOLD_SECS: u32 = 60;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeSyncConfig {
+    /// Whether to send the host time to the radio once configuration completes
+    pub sync_on_connect: bool,
+
+    /// Skew in seconds beyond which a clock skew event is emitted
+    pub skew_threshold_secs: u32,
+}
+
+impl Default for TimeSyncConfig {
+    fn default() -> Self {
+        Self {
+            sync_on_connect: true,
+            skew_threshold_secs: DEFAULT_CLOCK_SKEW_THRESHOLD_SECS,
+        }
+    }
+}
+
+/// Builds the time-only position the firmware uses to set its clock
+/// when sent to the local node from a connected client.
+///
+/// Newer firmware also takes the time as the admin message's `set_time_only`
+/// field, but the protobufs bundled with meshtastic 0.1.6 don't have it, so the
+/// time is sent as a position until the crate is updated.
+pub fn build_time_sync_position(epoch_secs: u32) -> protobufs::Position {
+    protobufs::Position {
+        time: epoch_secs,
+        ..Default::default()
+    }
+}
+
+/// Seconds the device clock is ahead of the host clock (negative if behind)
+pub fn clock_skew_secs(device_time: u32, host_time: u32) -> i64 {
+    device_time as i64 - host_time as i64
+}
+
+/// Returns whether a skew exceeds the threshold. A skew exactly at
+/// the threshold is still considered in sync.
+pub fn exceeds_skew_threshold(skew_secs: i64, threshold_secs: u32) -> bool {
+    skew_secs.unsigned_abs() > threshold_secs as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::Message;
+
+    use super::*;
+
+    #[test]
+    fn encodes_time_only_position() {
+        let position = build_time_sync_position(1_700_000_000);
+        let decoded = protobufs::Position::decode(position.encode_to_vec().as_slice()).unwrap();
+
+        assert_eq!(decoded.time, 1_700_000_000);
+        assert_eq!(decoded.latitude_i, 0);
+        assert_eq!(decoded.longitude_i, 0);
+    }
+
+    #[test]
+    fn skew_threshold_boundary() {
+        let host = 1_700_000_000;
+
+        assert_eq!(clock_skew_secs(host + 60, host), 60);
+        assert_eq!(clock_skew_secs(host - 61, host), -61);
+
+        assert!(!exceeds_skew_threshold(
+            clock_skew_secs(host + 60, host),
+            60
+        ));
+        assert!(!exceeds_skew_threshold(
+            clock_skew_secs(host - 60, host),
+            60
+        ));
+        assert!(exceeds_skew_threshold(clock_skew_secs(host + 61, host), 60));
+        assert!(exceeds_skew_threshold(clock_skew_secs(host - 61, host), 60));
+    }
+}
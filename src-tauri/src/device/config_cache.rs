@@ -0,0 +1,560 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use log::{debug, trace, warn};
+use meshtastic::protobufs;
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use super::{MeshDevice, SerialDeviceStatus};
+use crate::persistence::{settings_file_path, DEVICE_CONFIG_CACHE_DIR_NAME};
+use crate::secrets::{
+    decrypt_secret, encrypt_secret, secret_key, EncryptedSecret, SecretError, SecretKey,
+};
+use crate::state;
+
+/// Version of the cached config file structure, bumped on breaking changes
+pub const DEVICE_CONFIG_CACHE_VERSION: u32 = 1;
+
+/// Configuration a device reported during its last successful configuration flow,
+/// kept so it can be viewed while the device isn't connected
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedDeviceConfig {
+    pub version: u32,
+    pub node_num: u32,
+    pub captured_at: u32, // secs
+    pub config: protobufs::LocalConfig,
+    pub module_config: protobufs::LocalModuleConfig,
+    pub channels: Vec<protobufs::Channel>, // ordered by index, without PSKs
+    pub metadata: Option<protobufs::DeviceMetadata>,
+    #[serde(default)]
+    pub secrets: Option<EncryptedSecret>, // sealed `DeviceSecrets`, unless secrets aren't persisted
+}
+
+/// Fields cleared from a cached config, which are only stored encrypted
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceSecrets {
+    pub channel_psks: Vec<(i32, Vec<u8>)>, // by channel index
+    pub wifi_psk: String,
+    pub mqtt_password: String,
+}
+
+impl DeviceSecrets {
+    pub fn from_device(device: &MeshDevice) -> Self {
+        let mut channel_psks: Vec<(i32, Vec<u8>)> = device
+            .channels
+            .values()
+            .filter_map(|channel| {
+                let settings = channel.config.settings.as_ref()?;
+                Some((channel.config.index, settings.psk.clone()))
+            })
+            .collect();
+
+        channel_psks.sort_by_key(|(index, _)| *index);
+
+        Self {
+            channel_psks,
+            wifi_psk: device
+                .config
+                .network
+                .as_ref()
+                .map(|network| network.wifi_psk.clone())
+                .unwrap_or_default(),
+            mqtt_password: device
+                .module_config
+                .mqtt
+                .as_ref()
+                .map(|mqtt| mqtt.password.clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl CachedDeviceConfig {
+    /// Channel PSKs, the WiFi PSK and the MQTT password are left out, and can be added
+    /// encrypted with `seal_secrets`
+    pub fn from_device(device: &MeshDevice, captured_at: u32) -> Self {
+        let mut channels: Vec<protobufs::Channel> = device
+            .channels
+            .values()
+            .map(|channel| {
+                let mut channel = channel.config.clone();
+
+                if let Some(settings) = channel.settings.as_mut() {
+                    settings.psk.clear();
+                }
+
+                channel
+            })
+            .collect();
+
+        channels.sort_by_key(|channel| channel.index);
+
+        let mut config = device.config.clone();
+        let mut module_config = device.module_config.clone();
+
+        if let Some(network) = config.network.as_mut() {
+            network.wifi_psk.clear();
+        }
+
+        if let Some(mqtt) = module_config.mqtt.as_mut() {
+            mqtt.password.clear();
+        }
+
+        Self {
+            version: DEVICE_CONFIG_CACHE_VERSION,
+            node_num: device.my_node_info.my_node_num,
+            captured_at,
+            config,
+            module_config,
+            channels,
+            metadata: device.metadata.clone(),
+            secrets: None,
+        }
+    }
+
+    /// Binds the sealed secrets to the node they were read from
+    fn secrets_context(&self) -> String {
+        format!("device config {} secrets", self.node_num)
+    }
+
+    pub fn seal_secrets(
+        &mut self,
+        secrets: &DeviceSecrets,
+        key: &SecretKey,
+    ) -> Result<(), SecretError> {
+        let plaintext = serde_json::to_vec(secrets).map_err(|e| e.to_string())?;
+        self.secrets = Some(encrypt_secret(key, &plaintext, &self.secrets_context())?);
+
+        Ok(())
+    }
+
+    /// Decrypts the stored secrets, if any were stored
+    pub fn open_secrets(&self, key: &SecretKey) -> Result<Option<DeviceSecrets>, SecretError> {
+        let sealed = match self.secrets.as_ref() {
+            Some(sealed) => sealed,
+            None => return Ok(None),
+        };
+
+        let plaintext = decrypt_secret(key, sealed, &self.secrets_context())?;
+
+        serde_json::from_slice(&plaintext)
+            .map(Some)
+            .map_err(|e| SecretError::Failed(format!("Stored secrets are malformed: {}", e)))
+    }
+
+    /// The cached channels with their PSKs, for writing back to a device
+    pub fn channels_with_psks(
+        &self,
+        key: &SecretKey,
+    ) -> Result<Vec<protobufs::Channel>, SecretError> {
+        let secrets = self.open_secrets(key)?.ok_or_else(|| {
+            SecretError::Failed("No channel keys are stored for this device".into())
+        })?;
+
+        let psks: HashMap<i32, Vec<u8>> = secrets.channel_psks.into_iter().collect();
+
+        Ok(self
+            .channels
+            .iter()
+            .map(|channel| {
+                let mut channel = channel.clone();
+
+                if let Some(settings) = channel.settings.as_mut() {
+                    settings.psk = psks.get(&channel.index).cloned().unwrap_or_default();
+                }
+
+                channel
+            })
+            .collect())
+    }
+
+    pub fn reencrypt(
+        &mut self,
+        old_key: &SecretKey,
+        new_key: &SecretKey,
+    ) -> Result<(), SecretError> {
+        if let Some(secrets) = self.open_secrets(old_key)? {
+            self.seal_secrets(&secrets, new_key)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A device's configuration, read from the device if it's connected and from the
+/// cache otherwise. Cached configuration is marked `stale`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceConfigView {
+    pub stale: bool,
+    pub snapshot: CachedDeviceConfig,
+}
+
+/// Only a device that has finished its configuration flow is served fresh, as the
+/// configuration of a device that's still configuring is incomplete
+pub fn device_config_view(
+    connected: Option<&MeshDevice>,
+    cached: Option<&CachedDeviceConfig>,
+    now: u32,
+) -> Option<DeviceConfigView> {
+    if let Some(device) = connected.filter(|d| d.status == SerialDeviceStatus::Connected) {
+        return Some(DeviceConfigView {
+            stale: false,
+            snapshot: CachedDeviceConfig::from_device(device, now),
+        });
+    }
+
+    cached.map(|snapshot| DeviceConfigView {
+        stale: true,
+        snapshot: CachedDeviceConfig {
+            secrets: None,
+            ..snapshot.clone()
+        },
+    })
+}
+
+fn cached_config_path(dir: &Path, node_num: u32) -> PathBuf {
+    dir.join(format!("{}.json", node_num))
+}
+
+/// Writes a device's cached config to a temporary file and renames it over the
+/// previous one, so a crash mid-write leaves the previous config intact
+pub fn write_cached_config(dir: &Path, cached: &CachedDeviceConfig) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+
+    let path = cached_config_path(dir, cached.node_num);
+    let temp_path = path.with_extension("tmp");
+
+    let contents = serde_json::to_vec_pretty(cached).map_err(|e| e.to_string())?;
+
+    std::fs::write(&temp_path, contents)
+        .map_err(|e| format!("Failed to write {:?}: {}", temp_path, e))?;
+    std::fs::rename(&temp_path, &path)
+        .map_err(|e| format!("Failed to move cached config into {:?}: {}", path, e))?;
+
+    Ok(path)
+}
+
+/// Reads every cached config in `dir`, by node num, skipping any that are damaged
+pub fn read_cached_configs(dir: &Path) -> Result<HashMap<u32, CachedDeviceConfig>, String> {
+    let mut configs = HashMap::new();
+
+    if !dir.exists() {
+        return Ok(configs);
+    }
+
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("Failed to read {:?}: {}", dir, e))?;
+
+    for path in entries.filter_map(|entry| entry.ok().map(|e| e.path())) {
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+
+        let cached = std::fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| {
+                serde_json::from_slice::<CachedDeviceConfig>(&contents).map_err(|e| e.to_string())
+            });
+
+        match cached {
+            Ok(cached) if cached.version <= DEVICE_CONFIG_CACHE_VERSION => {
+                configs.insert(cached.node_num, cached);
+            }
+            Ok(cached) => warn!(
+                "Skipping cached config {:?} from a newer version (version {})",
+                path, cached.version
+            ),
+            Err(e) => warn!("Skipping unreadable cached config {:?}: {}", path, e),
+        }
+    }
+
+    Ok(configs)
+}
+
+pub fn device_config_cache_dir<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+) -> Result<PathBuf, String> {
+    settings_file_path(handle, DEVICE_CONFIG_CACHE_DIR_NAME)
+}
+
+/// Replaces the cached config of a device that just finished its configuration flow
+pub fn cache_device_config<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device: &MeshDevice,
+    captured_at: u32,
+) -> Result<(), String> {
+    let mut cached = CachedDeviceConfig::from_device(device, captured_at);
+
+    if cached.node_num == 0 {
+        debug!("Not caching config of a device that hasn't reported its node num");
+        return Ok(());
+    }
+
+    if persist_secrets(handle) {
+        match secret_key(handle) {
+            Ok(key) => cached.seal_secrets(&DeviceSecrets::from_device(device), &key)?,
+            Err(e) => warn!(
+                "Caching config of node {} without its secrets: {}",
+                cached.node_num, e
+            ),
+        }
+    }
+
+    let path = write_cached_config(&device_config_cache_dir(handle)?, &cached)?;
+    trace!("Cached config of node {} in {:?}", cached.node_num, path);
+
+    if let Some(configs_state) = handle.try_state::<state::device_configs::DeviceConfigsState>() {
+        let mut configs = configs_state.inner.lock().map_err(|e| e.to_string())?;
+        configs.insert(cached.node_num, cached);
+    }
+
+    Ok(())
+}
+
+fn persist_secrets<R: tauri::Runtime>(handle: &tauri::AppHandle<R>) -> bool {
+    handle
+        .try_state::<state::settings::SettingsState>()
+        .and_then(|settings_state| {
+            let settings = settings_state.inner.lock().ok()?;
+            Some(settings.secrets.persist_secrets)
+        })
+        .unwrap_or(false)
+}
+
+/// Rewrites every cached config that has secrets with `update`, replacing the cached
+/// copies once all of them have been updated. Returns the number rewritten.
+fn rewrite_cached_secrets<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    mut update: impl FnMut(&mut CachedDeviceConfig) -> Result<(), SecretError>,
+) -> Result<u32, SecretError> {
+    let dir = device_config_cache_dir(handle)?;
+
+    let configs_state = handle.state::<state::device_configs::DeviceConfigsState>();
+    let mut configs = configs_state.inner.lock().map_err(|e| e.to_string())?;
+
+    let mut rewritten = vec![];
+
+    for cached in configs.values().filter(|cached| cached.secrets.is_some()) {
+        let mut cached = cached.clone();
+        update(&mut cached)?;
+        rewritten.push(cached);
+    }
+
+    for cached in &rewritten {
+        write_cached_config(&dir, cached)?;
+    }
+
+    let count = rewritten.len() as u32;
+
+    for cached in rewritten {
+        configs.insert(cached.node_num, cached);
+    }
+
+    Ok(count)
+}
+
+/// Re-encrypts the secrets of every cached config under a new key. Secrets sealed under
+/// a key older than `old_key`, e.g. by an interrupted rotation, can't be recovered and
+/// are dropped.
+pub fn reencrypt_cached_configs<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    old_key: &SecretKey,
+    new_key: &SecretKey,
+) -> Result<u32, SecretError> {
+    rewrite_cached_secrets(handle, |cached| match cached.reencrypt(old_key, new_key) {
+        Err(SecretError::KeyMismatch { key_id, .. }) => {
+            warn!(
+                "Dropping secrets of node {} sealed under retired key {}",
+                cached.node_num, key_id
+            );
+            cached.secrets = None;
+            Ok(())
+        }
+        result => result,
+    })
+}
+
+/// Removes the secrets from every cached config, for when secrets aren't persisted
+pub fn forget_cached_secrets<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+) -> Result<u32, SecretError> {
+    rewrite_cached_secrets(handle, |cached| {
+        cached.secrets = None;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::MeshChannel;
+
+    const NODE_NUM: u32 = 0x1234_5678;
+
+    fn device(region: i32) -> MeshDevice {
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = NODE_NUM;
+        device.status = SerialDeviceStatus::Connected;
+        device.config.lora = Some(protobufs::config::LoRaConfig {
+            region,
+            ..Default::default()
+        });
+        device.config.network = Some(protobufs::config::NetworkConfig {
+            wifi_ssid: "field-office".into(),
+            wifi_psk: "correct horse battery".into(),
+            ..Default::default()
+        });
+        device.metadata = Some(protobufs::DeviceMetadata {
+            firmware_version: "2.3.2".into(),
+            ..Default::default()
+        });
+
+        for (index, name) in [(1, "Ops"), (0, "")] {
+            device.channels.insert(
+                index,
+                MeshChannel {
+                    config: protobufs::Channel {
+                        index: index as i32,
+                        settings: Some(protobufs::ChannelSettings {
+                            name: name.into(),
+                            psk: vec![1, 2, 3, 4],
+                            ..Default::default()
+                        }),
+                        role: 1,
+                    },
+                    ..Default::default()
+                },
+            );
+        }
+
+        device
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("device-configs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn reloads_cached_config_after_restart() {
+        let dir = temp_dir("reload");
+        let cached = CachedDeviceConfig::from_device(&device(1), 1_700_000_000);
+
+        assert_eq!(cached.channels[0].index, 0);
+        assert_eq!(cached.channels[1].index, 1);
+        assert!(cached
+            .channels
+            .iter()
+            .all(|c| c.settings.as_ref().unwrap().psk.is_empty()));
+        assert!(cached.config.network.as_ref().unwrap().wifi_psk.is_empty());
+
+        write_cached_config(&dir, &cached).unwrap();
+
+        let reloaded = read_cached_configs(&dir).unwrap();
+        assert_eq!(reloaded.get(&NODE_NUM), Some(&cached));
+
+        // A fresh configuration replaces the previous one without leaving files behind
+        let refreshed = CachedDeviceConfig::from_device(&device(3), 1_700_000_600);
+        write_cached_config(&dir, &refreshed).unwrap();
+
+        let reloaded = read_cached_configs(&dir).unwrap();
+        assert_eq!(reloaded.len(), 1);
+        assert_eq!(reloaded.get(&NODE_NUM), Some(&refreshed));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // Damaged files are skipped
+        std::fs::write(dir.join("42.json"), "{\"version\": 1, \"node").unwrap();
+        assert_eq!(read_cached_configs(&dir).unwrap().len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stores_secrets_encrypted() {
+        let dir = temp_dir("secrets");
+        let connected = device(1);
+        let key = SecretKey::generate(1);
+
+        let mut cached = CachedDeviceConfig::from_device(&connected, 1_700_000_000);
+        cached
+            .seal_secrets(&DeviceSecrets::from_device(&connected), &key)
+            .unwrap();
+
+        let path = write_cached_config(&dir, &cached).unwrap();
+        let stored = std::fs::read_to_string(&path).unwrap();
+        assert!(stored.contains("field-office"));
+        assert!(!stored.contains("correct horse battery"));
+
+        // Secrets are only decrypted when they're asked for
+        let reloaded = read_cached_configs(&dir)
+            .unwrap()
+            .remove(&NODE_NUM)
+            .unwrap();
+        let channels = reloaded.channels_with_psks(&key).unwrap();
+        assert_eq!(channels.len(), 2);
+        assert!(channels
+            .iter()
+            .all(|c| c.settings.as_ref().unwrap().psk == vec![1, 2, 3, 4]));
+
+        let secrets = reloaded.open_secrets(&key).unwrap().unwrap();
+        assert_eq!(secrets.wifi_psk, "correct horse battery");
+
+        // A wrong key is an error rather than channels with garbage keys
+        assert_eq!(
+            reloaded.channels_with_psks(&SecretKey::generate(1)),
+            Err(SecretError::WrongKey)
+        );
+
+        // Secrets from one node can't be passed off as another's
+        let mut moved = reloaded.clone();
+        moved.node_num += 1;
+        assert_eq!(moved.open_secrets(&key), Err(SecretError::WrongKey));
+
+        // Rotating re-encrypts under the new key only
+        let new_key = SecretKey::generate(2);
+        let mut rotated = reloaded.clone();
+        rotated.reencrypt(&key, &new_key).unwrap();
+        assert_eq!(rotated.open_secrets(&new_key).unwrap(), Some(secrets));
+        assert!(matches!(
+            rotated.open_secrets(&key),
+            Err(SecretError::KeyMismatch { key_id: 2, .. })
+        ));
+
+        // Secrets aren't passed on with the cached config
+        let view = device_config_view(None, Some(&reloaded), 1_700_000_600).unwrap();
+        assert_eq!(view.snapshot.secrets, None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn marks_cached_config_stale() {
+        let connected = device(3);
+        let cached = CachedDeviceConfig::from_device(&device(1), 1_700_000_000);
+
+        let fresh = device_config_view(Some(&connected), Some(&cached), 1_700_000_600).unwrap();
+        assert!(!fresh.stale);
+        assert_eq!(fresh.snapshot.captured_at, 1_700_000_600);
+        assert_eq!(fresh.snapshot.config.lora.unwrap().region, 3);
+
+        let offline = device_config_view(None, Some(&cached), 1_700_000_600).unwrap();
+        assert!(offline.stale);
+        assert_eq!(offline.snapshot, cached);
+
+        // A device that's still configuring hasn't reported its full config yet
+        let mut configuring = device(3);
+        configuring.status = SerialDeviceStatus::Configuring;
+
+        let view = device_config_view(Some(&configuring), Some(&cached), 1_700_000_600).unwrap();
+        assert!(view.stale);
+
+        assert_eq!(device_config_view(Some(&configuring), None, 0), None);
+        assert_eq!(device_config_view(None, None, 0), None);
+    }
+}
@@ -0,0 +1,351 @@
+use meshtastic::protobufs;
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+/// Channel slots the radio reports during configuration
+const EXPECTED_CHANNELS: u32 = 8;
+
+/// Variants of `protobufs::config::PayloadVariant`
+const EXPECTED_CONFIG_SECTIONS: u32 = 7;
+
+/// Variants of `protobufs::module_config::PayloadVariant`
+const EXPECTED_MODULE_CONFIG_SECTIONS: u32 = 13;
+
+/// Without a node count from the radio, the NodeDB is assumed to be about this large,
+/// with its share of the progress approaching but never reaching 100%
+const ESTIMATED_NODE_INFOS: u32 = 20;
+
+/// Share of the overall percentage given to each part of the configuration flow
+const DEVICE_INFO_WEIGHT: f32 = 5.0;
+const METADATA_WEIGHT: f32 = 5.0;
+const CHANNELS_WEIGHT: f32 = 15.0;
+const CONFIG_WEIGHT: f32 = 15.0;
+const MODULE_CONFIG_WEIGHT: f32 = 20.0;
+const NODE_DB_WEIGHT: f32 = 40.0;
+
+/// Part of the configuration flow the radio was last seen streaming. Firmware sends
+/// these roughly in order, though the device's own NodeInfo arrives before its config.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum ConfigurationPhase {
+    #[default]
+    WaitingForRadio,
+    DeviceInfo,
+    Metadata,
+    Channels,
+    Config,
+    ModuleConfig,
+    NodeDb,
+    Complete,
+}
+
+impl ConfigurationPhase {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ConfigurationPhase::WaitingForRadio => "waiting for the radio",
+            ConfigurationPhase::DeviceInfo => "receiving device info",
+            ConfigurationPhase::Metadata => "receiving device metadata",
+            ConfigurationPhase::Channels => "receiving channels",
+            ConfigurationPhase::Config => "receiving device config",
+            ConfigurationPhase::ModuleConfig => "receiving module config",
+            ConfigurationPhase::NodeDb => "receiving node database",
+            ConfigurationPhase::Complete => "configured",
+        }
+    }
+}
+
+/// What has arrived so far during the configuration flow of a connection
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigurationProgress {
+    pub phase: ConfigurationPhase,
+    pub percent: u8, // approximate, never decreases
+    pub device_info_received: bool,
+    pub metadata_received: bool,
+    pub channels: Vec<u32>,                  // indices of channels received
+    pub config_sections: Vec<String>,        // e.g. "lora"
+    pub module_config_sections: Vec<String>, // e.g. "mqtt"
+    pub node_infos: u32,
+    pub expected_node_infos: Option<u32>, // size of the NodeDB, if the radio reported it
+}
+
+fn config_section(config: &protobufs::Config) -> Option<&'static str> {
+    use protobufs::config::PayloadVariant;
+
+    let section = match config.payload_variant.as_ref()? {
+        PayloadVariant::Device(_) => "device",
+        PayloadVariant::Position(_) => "position",
+        PayloadVariant::Power(_) => "power",
+        PayloadVariant::Network(_) => "network",
+        PayloadVariant::Display(_) => "display",
+        PayloadVariant::Lora(_) => "lora",
+        PayloadVariant::Bluetooth(_) => "bluetooth",
+    };
+
+    Some(section)
+}
+
+fn module_config_section(module_config: &protobufs::ModuleConfig) -> Option<&'static str> {
+    use protobufs::module_config::PayloadVariant;
+
+    let section = match module_config.payload_variant.as_ref()? {
+        PayloadVariant::Mqtt(_) => "mqtt",
+        PayloadVariant::Serial(_) => "serial",
+        PayloadVariant::ExternalNotification(_) => "externalNotification",
+        PayloadVariant::StoreForward(_) => "storeForward",
+        PayloadVariant::RangeTest(_) => "rangeTest",
+        PayloadVariant::Telemetry(_) => "telemetry",
+        PayloadVariant::CannedMessage(_) => "cannedMessage",
+        PayloadVariant::Audio(_) => "audio",
+        PayloadVariant::RemoteHardware(_) => "remoteHardware",
+        PayloadVariant::NeighborInfo(_) => "neighborInfo",
+        PayloadVariant::AmbientLighting(_) => "ambientLighting",
+        PayloadVariant::DetectionSensor(_) => "detectionSensor",
+        PayloadVariant::Paxcounter(_) => "paxcounter",
+    };
+
+    Some(section)
+}
+
+fn push_unique<T: PartialEq>(values: &mut Vec<T>, value: T) {
+    if !values.contains(&value) {
+        values.push(value);
+    }
+}
+
+fn fraction(received: u32, expected: u32) -> f32 {
+    (received as f32 / expected as f32).min(1.0)
+}
+
+impl ConfigurationProgress {
+    /// Records a packet of the configuration flow, returning whether the phase or
+    /// percentage changed and should be reported to the UI
+    pub fn observe(&mut self, variant: &protobufs::from_radio::PayloadVariant) -> bool {
+        use protobufs::from_radio::PayloadVariant;
+
+        let previous = (self.phase, self.percent);
+
+        let phase = match variant {
+            PayloadVariant::MyInfo(_) => {
+                // `MyNodeInfo` in the protobufs this app is built against doesn't
+                // include the NodeDB size, so `expected_node_infos` stays unset
+                self.device_info_received = true;
+                ConfigurationPhase::DeviceInfo
+            }
+            PayloadVariant::Metadata(_) => {
+                self.metadata_received = true;
+                ConfigurationPhase::Metadata
+            }
+            PayloadVariant::Channel(channel) => {
+                push_unique(&mut self.channels, channel.index as u32);
+                ConfigurationPhase::Channels
+            }
+            PayloadVariant::Config(config) => {
+                if let Some(section) = config_section(config) {
+                    push_unique(&mut self.config_sections, section.to_string());
+                }
+                ConfigurationPhase::Config
+            }
+            PayloadVariant::ModuleConfig(module_config) => {
+                if let Some(section) = module_config_section(module_config) {
+                    push_unique(&mut self.module_config_sections, section.to_string());
+                }
+                ConfigurationPhase::ModuleConfig
+            }
+            PayloadVariant::NodeInfo(_) => {
+                self.node_infos += 1;
+
+                // The device's own NodeInfo is sent right after its device info
+                if self.channels.is_empty() {
+                    self.phase
+                } else {
+                    ConfigurationPhase::NodeDb
+                }
+            }
+            PayloadVariant::ConfigCompleteId(_) => ConfigurationPhase::Complete,
+            _ => return false,
+        };
+
+        self.phase = phase;
+        self.percent = self.percent.max(self.estimate_percent());
+
+        previous != (self.phase, self.percent)
+    }
+
+    pub fn set_expected_node_infos(&mut self, expected: u32) {
+        self.expected_node_infos = Some(expected);
+    }
+
+    fn estimate_percent(&self) -> u8 {
+        if self.phase == ConfigurationPhase::Complete {
+            return 100;
+        }
+
+        let node_db = match self.expected_node_infos {
+            Some(expected) if expected > 0 => fraction(self.node_infos, expected),
+            _ => self.node_infos as f32 / (self.node_infos + ESTIMATED_NODE_INFOS) as f32,
+        };
+
+        let received = |received: bool, weight: f32| if received { weight } else { 0.0 };
+
+        let percent = received(self.device_info_received, DEVICE_INFO_WEIGHT)
+            + received(self.metadata_received, METADATA_WEIGHT)
+            + CHANNELS_WEIGHT * fraction(self.channels.len() as u32, EXPECTED_CHANNELS)
+            + CONFIG_WEIGHT * fraction(self.config_sections.len() as u32, EXPECTED_CONFIG_SECTIONS)
+            + MODULE_CONFIG_WEIGHT
+                * fraction(
+                    self.module_config_sections.len() as u32,
+                    EXPECTED_MODULE_CONFIG_SECTIONS,
+                )
+            + NODE_DB_WEIGHT * node_db;
+
+        // Only the config complete packet marks the flow as done
+        (percent.floor() as u8).min(99)
+    }
+
+    /// Failure message for a configuration flow that didn't complete in time
+    pub fn timeout_message(&self) -> String {
+        match self.phase {
+            ConfigurationPhase::WaitingForRadio => {
+                "Configuration timed out before the radio responded. Are you sure this is a \
+                 Meshtastic device?"
+                    .into()
+            }
+            phase => format!(
+                "Configuration timed out, stalled while {} ({}% complete)",
+                phase.label(),
+                self.percent
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobufs::from_radio::PayloadVariant;
+
+    /// Configuration flow as sent by 2.x firmware, with two nodes in the NodeDB
+    fn canned_configuration() -> Vec<PayloadVariant> {
+        let mut packets = vec![
+            PayloadVariant::MyInfo(protobufs::MyNodeInfo {
+                my_node_num: 1,
+                ..Default::default()
+            }),
+            PayloadVariant::NodeInfo(protobufs::NodeInfo {
+                num: 1,
+                ..Default::default()
+            }),
+            PayloadVariant::Metadata(Default::default()),
+        ];
+
+        packets.extend((0..8).map(|index| {
+            PayloadVariant::Channel(protobufs::Channel {
+                index,
+                ..Default::default()
+            })
+        }));
+
+        packets.extend(
+            [
+                protobufs::config::PayloadVariant::Device(Default::default()),
+                protobufs::config::PayloadVariant::Position(Default::default()),
+                protobufs::config::PayloadVariant::Power(Default::default()),
+                protobufs::config::PayloadVariant::Network(Default::default()),
+                protobufs::config::PayloadVariant::Display(Default::default()),
+                protobufs::config::PayloadVariant::Lora(Default::default()),
+                protobufs::config::PayloadVariant::Bluetooth(Default::default()),
+            ]
+            .into_iter()
+            .map(|variant| {
+                PayloadVariant::Config(protobufs::Config {
+                    payload_variant: Some(variant),
+                })
+            }),
+        );
+
+        packets.extend(
+            [
+                protobufs::module_config::PayloadVariant::Mqtt(Default::default()),
+                protobufs::module_config::PayloadVariant::Serial(Default::default()),
+                protobufs::module_config::PayloadVariant::Telemetry(Default::default()),
+            ]
+            .into_iter()
+            .map(|variant| {
+                PayloadVariant::ModuleConfig(protobufs::ModuleConfig {
+                    payload_variant: Some(variant),
+                })
+            }),
+        );
+
+        packets.extend((2..4).map(|num| {
+            PayloadVariant::NodeInfo(protobufs::NodeInfo {
+                num,
+                ..Default::default()
+            })
+        }));
+
+        packets.push(PayloadVariant::ConfigCompleteId(1));
+
+        packets
+    }
+
+    #[test]
+    fn progress_is_monotone_over_configuration() {
+        let mut progress = ConfigurationProgress::default();
+        let mut percents = vec![];
+
+        for packet in canned_configuration() {
+            progress.observe(&packet);
+            percents.push(progress.percent);
+        }
+
+        assert!(percents.windows(2).all(|w| w[0] <= w[1]), "{:?}", percents);
+        assert!(percents[..percents.len() - 1].iter().all(|p| *p < 100));
+        assert_eq!(progress.percent, 100);
+        assert_eq!(progress.phase, ConfigurationPhase::Complete);
+        assert_eq!(progress.channels.len(), 8);
+        assert_eq!(progress.config_sections.len(), 7);
+        assert_eq!(
+            progress.module_config_sections,
+            ["mqtt", "serial", "telemetry"]
+        );
+        assert_eq!(progress.node_infos, 3);
+    }
+
+    #[test]
+    fn uses_node_count_hint_when_available() {
+        let mut estimated = ConfigurationProgress::default();
+        let mut hinted = ConfigurationProgress::default();
+        hinted.set_expected_node_infos(3);
+
+        for packet in canned_configuration()
+            .into_iter()
+            .filter(|p| !matches!(p, PayloadVariant::ConfigCompleteId(_)))
+        {
+            estimated.observe(&packet);
+            hinted.observe(&packet);
+        }
+
+        assert_eq!(hinted.phase, ConfigurationPhase::NodeDb);
+        assert!(hinted.percent > estimated.percent);
+    }
+
+    #[test]
+    fn timeout_message_includes_last_phase() {
+        let mut progress = ConfigurationProgress::default();
+
+        assert!(progress
+            .timeout_message()
+            .contains("before the radio responded"));
+
+        for packet in canned_configuration().into_iter().take(6) {
+            progress.observe(&packet);
+        }
+
+        assert_eq!(progress.phase, ConfigurationPhase::Channels);
+        assert!(progress
+            .timeout_message()
+            .contains("stalled while receiving channels"));
+    }
+}
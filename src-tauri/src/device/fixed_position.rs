@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use meshtastic::protobufs;
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use super::helpers::convert_location_field_to_protos;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct FixedPosition {
+    pub latitude: f32,
+    pub longitude: f32,
+    pub altitude: i32, // meters above sea level
+}
+
+impl FixedPosition {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(-90.0..=90.0).contains(&self.latitude) {
+            return Err(format!(
+                "Latitude {} is outside of the range [-90, 90]",
+                self.latitude
+            ));
+        }
+
+        if !(-180.0..=180.0).contains(&self.longitude) {
+            return Err(format!(
+                "Longitude {} is outside of the range [-180, 180]",
+                self.longitude
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Converts the fixed position into a manually sourced position
+    /// reported at `time` (seconds since epoch).
+    pub fn to_position(&self, time: u32) -> protobufs::Position {
+        protobufs::Position {
+            latitude_i: convert_location_field_to_protos(self.latitude),
+            longitude_i: convert_location_field_to_protos(self.longitude),
+            altitude: self.altitude,
+            time,
+            location_source: protobufs::position::LocSource::LocManual as i32,
+            altitude_source: protobufs::position::AltSource::AltManual as i32,
+            ..Default::default()
+        }
+    }
+}
+
+/// A fixed position set on a device and how often it's rebroadcast, saved in the
+/// settings so the rebroadcast resumes when the device reconnects
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredFixedPosition {
+    pub position: FixedPosition,
+    pub rebroadcast_interval_secs: Option<u32>,
+}
+
+impl StoredFixedPosition {
+    pub fn rebroadcast_interval(&self) -> Option<Duration> {
+        self.rebroadcast_interval_secs
+            .filter(|secs| *secs > 0)
+            .map(|secs| Duration::from_secs(secs.into()))
+    }
+}
+
+/// Builds the position config enabling or disabling the fixed position,
+/// keeping the rest of the device's current position config.
+pub fn build_fixed_position_config(
+    current: Option<protobufs::config::PositionConfig>,
+    fixed_position: bool,
+) -> protobufs::Config {
+    protobufs::Config {
+        payload_variant: Some(protobufs::config::PayloadVariant::Position(
+            protobufs::config::PositionConfig {
+                fixed_position,
+                ..current.unwrap_or_default()
+            },
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::MeshDevice;
+    use crate::graph::ds::graph::MeshGraph;
+
+    fn base_station() -> FixedPosition {
+        FixedPosition {
+            latitude: 47.6062,
+            longitude: -122.3321,
+            altitude: 56,
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_coordinates() {
+        assert!(base_station().validate().is_ok());
+
+        let position = FixedPosition {
+            latitude: 91.0,
+            ..base_station()
+        };
+        assert!(position.validate().is_err());
+
+        let position = FixedPosition {
+            longitude: -181.0,
+            ..base_station()
+        };
+        assert!(position.validate().is_err());
+    }
+
+    #[test]
+    fn sets_and_clears_local_fixed_position() {
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 42;
+
+        let packet = device.set_local_fixed_position(&base_station(), 1_700_000_000);
+
+        let own_node = device.nodes.get(&42).expect("Own node not created");
+        let position = own_node.position_metrics.last().unwrap();
+        assert!((position.latitude - 47.6062).abs() < 1e-4);
+        assert_eq!(position.altitude, 56);
+        assert!(device.config.position.as_ref().unwrap().fixed_position);
+        assert_eq!(packet.packet.from, 42);
+
+        device.clear_local_fixed_position();
+        assert!(!device.config.position.as_ref().unwrap().fixed_position);
+    }
+
+    #[test]
+    fn updates_graph_optimistically() {
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 42;
+
+        let packet = device.set_local_fixed_position(&base_station(), 1_700_000_000);
+
+        let mut graph = MeshGraph::new();
+        graph.update_from_position(packet.packet, packet.data);
+
+        assert!(graph.contains_node(42));
+    }
+}
@@ -20,14 +20,14 @@ where
     rng.gen::<T>()
 }
 
-pub fn get_node_user_name(device: &mut MeshDevice, node_id: &u32) -> Option<String> {
+pub fn get_node_user_name(device: &MeshDevice, node_id: &u32) -> Option<String> {
     let db_node = device.nodes.get(node_id)?;
     let db_user = db_node.user.as_ref()?;
 
     Some(db_user.long_name.clone())
 }
 
-pub fn get_channel_name(device: &mut MeshDevice, channel_id: &u32) -> Option<String> {
+pub fn get_channel_name(device: &MeshDevice, channel_id: &u32) -> Option<String> {
     let db_channel = device.channels.get(channel_id)?;
     let db_channel_settings = db_channel.config.settings.as_ref()?;
 
@@ -38,6 +38,12 @@ pub fn get_channel_name(device: &mut MeshDevice, channel_id: &u32) -> Option<Str
     Some(db_channel_settings.name.clone())
 }
 
+/// Resolves a channel index to a display name, falling back to "channel #N"
+/// for channels missing from the device's channel table (e.g. hash-only channels).
+pub fn get_channel_display_name(device: &MeshDevice, channel_id: u32) -> String {
+    get_channel_name(device, &channel_id).unwrap_or_else(|| format!("channel #{}", channel_id))
+}
+
 /// Converts a mesh location field (e.g., latitude) from
 /// its mesh integer representation to a float.
 ///
@@ -81,3 +87,67 @@ pub fn normalize_location_field(field: i32) -> f32 {
 pub fn convert_location_field_to_protos(field: f32) -> i32 {
     (field * 1e7).floor() as i32
 }
+
+/// Mean radius of the Earth in meters, as used by the haversine formula
+pub const EARTH_RADIUS_METERS: f64 = 6_371_000.0;
+
+/// Computes the great-circle distance between two coordinates
+/// using the haversine formula.
+///
+/// # Arguments
+///
+/// * `lat_1`, `lon_1` - The first coordinate, in degrees.
+/// * `lat_2`, `lon_2` - The second coordinate, in degrees.
+///
+/// # Returns
+///
+/// * `f64` - The distance between the two coordinates in meters.
+pub fn haversine_distance_meters(lat_1: f64, lon_1: f64, lat_2: f64, lon_2: f64) -> f64 {
+    let d_lat = (lat_2 - lat_1).to_radians();
+    let d_lon = (lon_2 - lon_1).to_radians();
+
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat_1.to_radians().cos() * lat_2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_METERS * a.sqrt().asin()
+}
+
+/// Computes the initial great-circle bearing from the first coordinate to the second,
+/// in degrees clockwise from true north in the range `[0, 360)`.
+pub fn initial_bearing_degrees(lat_1: f64, lon_1: f64, lat_2: f64, lon_2: f64) -> f64 {
+    let (lat_1, lat_2) = (lat_1.to_radians(), lat_2.to_radians());
+    let d_lon = (lon_2 - lon_1).to_radians();
+
+    let y = d_lon.sin() * lat_2.cos();
+    let x = lat_1.cos() * lat_2.sin() - lat_1.sin() * lat_2.cos() * d_lon.cos();
+
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn haversine_known_distance() {
+        // Seattle to Portland is roughly 233.5 km
+        let distance = haversine_distance_meters(47.6062, -122.3321, 45.5152, -122.6784);
+
+        assert!((distance - 233_500.0).abs() < 1_000.0);
+        assert_eq!(haversine_distance_meters(10.0, 10.0, 10.0, 10.0), 0.0);
+    }
+
+    #[test]
+    fn initial_bearing_known_pairs() {
+        // Seattle to Portland heads slightly west of south
+        let bearing = initial_bearing_degrees(47.6062, -122.3321, 45.5152, -122.6784);
+        assert!((bearing - 186.6).abs() < 0.1);
+
+        // London to New York starts out west-northwest
+        let bearing = initial_bearing_degrees(51.5007, -0.1246, 40.6892, -74.0445);
+        assert!((bearing - 288.3).abs() < 0.1);
+
+        assert_eq!(initial_bearing_degrees(0.0, 0.0, 1.0, 0.0), 0.0);
+        assert!((initial_bearing_degrees(0.0, 0.0, 0.0, 1.0) - 90.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+/// How often node liveness is re-evaluated against the silence threshold, by default
+pub const NODE_LIVENESS_INTERVAL: Duration = Duration::from_secs(30);
+
+fn default_check_interval_secs() -> u32 {
+    NODE_LIVENESS_INTERVAL.as_secs() as u32
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum NodeLiveness {
+    Online,
+    Offline,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeLivenessConfig {
+    /// Time a node must remain silent past the threshold, or keep being heard
+    /// after coming back, before its state flips
+    pub hysteresis_secs: u32,
+
+    /// How often nodes are checked against the threshold
+    #[serde(default = "default_check_interval_secs")]
+    pub check_interval_secs: u32,
+}
+
+impl Default for NodeLivenessConfig {
+    fn default() -> Self {
+        Self {
+            hysteresis_secs: 5 * 60,
+            check_interval_secs: default_check_interval_secs(),
+        }
+    }
+}
+
+impl NodeLivenessConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.check_interval_secs == 0 {
+            return Err("Check interval must be greater than zero".into());
+        }
+
+        Ok(())
+    }
+
+    pub fn check_interval(&self) -> Duration {
+        Duration::from_secs(self.check_interval_secs.into())
+    }
+
+    /// Thresholds for nodes going offline after `node_offline_mins`, the offline
+    /// notification threshold
+    pub fn thresholds(&self, node_offline_mins: u32) -> LivenessThresholds {
+        LivenessThresholds {
+            offline_after_secs: node_offline_mins.saturating_mul(60).max(1),
+            hysteresis_secs: self.hysteresis_secs,
+        }
+    }
+}
+
+/// When the tracker flips a node's state
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LivenessThresholds {
+    /// Consider a node offline once it hasn't been heard from for this many seconds
+    pub offline_after_secs: u32,
+
+    /// See `NodeLivenessConfig::hysteresis_secs`
+    pub hysteresis_secs: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeLivenessTransition {
+    pub node_num: u32,
+    pub old_status: NodeLiveness,
+    pub new_status: NodeLiveness,
+    pub last_heard: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeLivenessEntry {
+    pub status: NodeLiveness,
+    pub last_heard: u32,
+
+    /// Start of the current run of activity from a node that's still offline
+    pub active_since: Option<u32>,
+}
+
+/// Tracks whether each node is online, reporting only transitions between states.
+///
+/// Nodes enter the tracker silently, either when seeded from the node database
+/// during configuration or when first heard, so (re)connecting to a device never
+/// reports transitions for nodes whose state wasn't already known.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeLivenessTracker {
+    pub nodes: HashMap<u32, NodeLivenessEntry>,
+}
+
+impl NodeLivenessTracker {
+    pub fn status(&self, node_num: u32) -> Option<NodeLiveness> {
+        self.nodes.get(&node_num).map(|entry| entry.status)
+    }
+
+    /// Sets the initial state of a node from the device's node database without
+    /// reporting a transition. A `last_heard` of 0 means the node was never heard.
+    pub fn seed(
+        &mut self,
+        node_num: u32,
+        last_heard: u32,
+        now: u32,
+        thresholds: &LivenessThresholds,
+    ) {
+        if let Some(entry) = self.nodes.get_mut(&node_num) {
+            entry.last_heard = entry.last_heard.max(last_heard);
+            return;
+        }
+
+        let silent_secs = now.saturating_sub(last_heard);
+
+        let status = if last_heard == 0 || silent_secs > thresholds.offline_after_secs {
+            NodeLiveness::Offline
+        } else {
+            NodeLiveness::Online
+        };
+
+        self.nodes.insert(
+            node_num,
+            NodeLivenessEntry {
+                status,
+                last_heard,
+                active_since: None,
+            },
+        );
+    }
+
+    /// Records a packet from a node, returning a transition if an offline node
+    /// has now been heard consistently for the hysteresis period.
+    pub fn record_heard(
+        &mut self,
+        node_num: u32,
+        now: u32,
+        thresholds: &LivenessThresholds,
+    ) -> Option<NodeLivenessTransition> {
+        let entry = match self.nodes.get_mut(&node_num) {
+            Some(entry) => entry,
+            None => {
+                self.nodes.insert(
+                    node_num,
+                    NodeLivenessEntry {
+                        status: NodeLiveness::Online,
+                        last_heard: now,
+                        active_since: None,
+                    },
+                );
+
+                return None;
+            }
+        };
+
+        if entry.status == NodeLiveness::Online {
+            entry.last_heard = now;
+            return None;
+        }
+
+        // A gap long enough to go offline again restarts the run of activity
+
+        let active_since = match entry.active_since {
+            Some(since)
+                if now.saturating_sub(entry.last_heard) <= thresholds.offline_after_secs =>
+            {
+                since
+            }
+            _ => now,
+        };
+
+        entry.last_heard = now;
+
+        if now.saturating_sub(active_since) < thresholds.hysteresis_secs {
+            entry.active_since = Some(active_since);
+            return None;
+        }
+
+        entry.status = NodeLiveness::Online;
+        entry.active_since = None;
+
+        Some(NodeLivenessTransition {
+            node_num,
+            old_status: NodeLiveness::Offline,
+            new_status: NodeLiveness::Online,
+            last_heard: now,
+        })
+    }
+
+    /// Flips online nodes that have stayed silent past the threshold for the
+    /// hysteresis period to offline, returning the transitions ordered by node.
+    pub fn evaluate(
+        &mut self,
+        now: u32,
+        thresholds: &LivenessThresholds,
+    ) -> Vec<NodeLivenessTransition> {
+        let mut transitions = vec![];
+
+        for (node_num, entry) in self.nodes.iter_mut() {
+            let silent_secs = now.saturating_sub(entry.last_heard);
+
+            if silent_secs <= thresholds.offline_after_secs {
+                continue;
+            }
+
+            match entry.status {
+                NodeLiveness::Online => {
+                    if silent_secs - thresholds.offline_after_secs < thresholds.hysteresis_secs {
+                        continue;
+                    }
+
+                    entry.status = NodeLiveness::Offline;
+
+                    transitions.push(NodeLivenessTransition {
+                        node_num: *node_num,
+                        old_status: NodeLiveness::Online,
+                        new_status: NodeLiveness::Offline,
+                        last_heard: entry.last_heard,
+                    });
+                }
+                NodeLiveness::Offline => {
+                    entry.active_since = None;
+                }
+            }
+        }
+
+        transitions.sort_by_key(|transition| transition.node_num);
+        transitions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u32 = 1_700_000_000;
+
+    fn config() -> LivenessThresholds {
+        LivenessThresholds {
+            offline_after_secs: 600,
+            hysteresis_secs: 120,
+        }
+    }
+
+    #[test]
+    fn goes_offline_after_threshold_and_hysteresis() {
+        let config = config();
+        let mut tracker = NodeLivenessTracker::default();
+
+        assert_eq!(tracker.record_heard(2, NOW, &config), None);
+        assert_eq!(tracker.status(2), Some(NodeLiveness::Online));
+
+        // Past the threshold, but not yet for the hysteresis period
+        assert!(tracker.evaluate(NOW + 600, &config).is_empty());
+        assert!(tracker.evaluate(NOW + 719, &config).is_empty());
+        assert_eq!(tracker.status(2), Some(NodeLiveness::Online));
+
+        assert_eq!(
+            tracker.evaluate(NOW + 720, &config),
+            vec![NodeLivenessTransition {
+                node_num: 2,
+                old_status: NodeLiveness::Online,
+                new_status: NodeLiveness::Offline,
+                last_heard: NOW,
+            }]
+        );
+
+        // Only the transition is reported
+        assert!(tracker.evaluate(NOW + 2000, &config).is_empty());
+    }
+
+    #[test]
+    fn packet_within_hysteresis_keeps_node_online() {
+        let config = config();
+        let mut tracker = NodeLivenessTracker::default();
+
+        tracker.record_heard(2, NOW, &config);
+        assert!(tracker.evaluate(NOW + 700, &config).is_empty());
+
+        tracker.record_heard(2, NOW + 710, &config);
+        assert!(tracker.evaluate(NOW + 800, &config).is_empty());
+        assert_eq!(tracker.status(2), Some(NodeLiveness::Online));
+    }
+
+    #[test]
+    fn comes_back_online_after_sustained_activity() {
+        let config = config();
+        let mut tracker = NodeLivenessTracker::default();
+
+        tracker.seed(2, NOW - 5000, NOW, &config);
+        assert_eq!(tracker.status(2), Some(NodeLiveness::Offline));
+
+        // A single packet isn't enough to flip back
+        assert_eq!(tracker.record_heard(2, NOW + 10, &config), None);
+        assert_eq!(tracker.record_heard(2, NOW + 100, &config), None);
+        assert_eq!(tracker.status(2), Some(NodeLiveness::Offline));
+
+        assert_eq!(
+            tracker.record_heard(2, NOW + 130, &config),
+            Some(NodeLivenessTransition {
+                node_num: 2,
+                old_status: NodeLiveness::Offline,
+                new_status: NodeLiveness::Online,
+                last_heard: NOW + 130,
+            })
+        );
+        assert_eq!(tracker.status(2), Some(NodeLiveness::Online));
+    }
+
+    #[test]
+    fn silence_restarts_recovery() {
+        let config = config();
+        let mut tracker = NodeLivenessTracker::default();
+
+        tracker.seed(2, NOW - 5000, NOW, &config);
+        tracker.record_heard(2, NOW, &config);
+
+        // Silent long enough to have gone offline again
+        assert!(tracker.evaluate(NOW + 700, &config).is_empty());
+        assert_eq!(tracker.record_heard(2, NOW + 700, &config), None);
+        assert_eq!(tracker.record_heard(2, NOW + 800, &config), None);
+        assert!(tracker.record_heard(2, NOW + 820, &config).is_some());
+    }
+
+    #[test]
+    fn zero_hysteresis_flips_immediately() {
+        let config = LivenessThresholds {
+            hysteresis_secs: 0,
+            ..config()
+        };
+        let mut tracker = NodeLivenessTracker::default();
+
+        tracker.record_heard(2, NOW, &config);
+        assert!(tracker.evaluate(NOW + 600, &config).is_empty());
+        assert_eq!(tracker.evaluate(NOW + 601, &config).len(), 1);
+        assert!(tracker.record_heard(2, NOW + 602, &config).is_some());
+    }
+
+    #[test]
+    fn seeding_node_database_reports_no_transitions() {
+        let config = config();
+
+        // A reconnect starts from a fresh tracker seeded with the radio's node
+        // database, where most nodes haven't been heard in a long time
+        let mut tracker = NodeLivenessTracker::default();
+
+        for node_num in 1..=50 {
+            tracker.seed(node_num, NOW - 86_400, NOW, &config);
+        }
+
+        tracker.seed(51, NOW - 30, NOW, &config);
+        tracker.seed(52, 0, NOW, &config);
+
+        assert!(tracker.evaluate(NOW, &config).is_empty());
+        assert!(tracker.evaluate(NOW + 60, &config).is_empty());
+
+        assert_eq!(tracker.status(1), Some(NodeLiveness::Offline));
+        assert_eq!(tracker.status(51), Some(NodeLiveness::Online));
+        assert_eq!(tracker.status(52), Some(NodeLiveness::Offline));
+    }
+
+    #[test]
+    fn seeding_known_node_keeps_state() {
+        let config = config();
+        let mut tracker = NodeLivenessTracker::default();
+
+        tracker.record_heard(2, NOW, &config);
+        tracker.seed(2, NOW - 5000, NOW, &config);
+
+        assert_eq!(tracker.status(2), Some(NodeLiveness::Online));
+        assert_eq!(tracker.nodes[&2].last_heard, NOW);
+    }
+
+    #[test]
+    fn configs_without_check_interval_use_default() {
+        let config: NodeLivenessConfig =
+            serde_json::from_str(r#"{ "hysteresisSecs": 60 }"#).unwrap();
+
+        assert_eq!(config.check_interval(), NODE_LIVENESS_INTERVAL);
+        assert!(config.validate().is_ok());
+
+        let config = NodeLivenessConfig {
+            check_interval_secs: 0,
+            ..config
+        };
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn offline_threshold_follows_notification_threshold() {
+        let config = NodeLivenessConfig::default();
+
+        assert_eq!(
+            config.thresholds(30),
+            LivenessThresholds {
+                offline_after_secs: 1800,
+                hysteresis_secs: config.hysteresis_secs,
+            }
+        );
+        assert_eq!(config.thresholds(0).offline_after_secs, 1);
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::VecDeque;
+
+use meshtastic::protobufs;
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of log lines retained for each device
+pub const DEVICE_LOG_BUFFER_CAPACITY: usize = 2000;
+
+/// Maximum number of log lines captured per second before lines are dropped
+pub const DEFAULT_DEVICE_LOG_RATE_LIMIT: u32 = 50;
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Type,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum DeviceLogLevel {
+    Trace,
+    Debug,
+    #[default]
+    Info,
+    Warning,
+    Error,
+    Critical,
+}
+
+impl DeviceLogLevel {
+    /// Maps a `LogRecord` level, treating unset levels as informational
+    pub fn from_log_record(level: i32) -> Self {
+        match protobufs::log_record::Level::from_i32(level) {
+            Some(protobufs::log_record::Level::Trace) => DeviceLogLevel::Trace,
+            Some(protobufs::log_record::Level::Debug) => DeviceLogLevel::Debug,
+            Some(protobufs::log_record::Level::Warning) => DeviceLogLevel::Warning,
+            Some(protobufs::log_record::Level::Error) => DeviceLogLevel::Error,
+            Some(protobufs::log_record::Level::Critical) => DeviceLogLevel::Critical,
+            _ => DeviceLogLevel::Info,
+        }
+    }
+
+    /// Parses the level prefix of a firmware serial log line (e.g. `WARN  | ...`)
+    pub fn from_serial_line(line: &str) -> Self {
+        let prefix = match line.split_once('|') {
+            Some((prefix, _)) => prefix.trim(),
+            None => return DeviceLogLevel::Info,
+        };
+
+        match prefix {
+            "TRACE" => DeviceLogLevel::Trace,
+            "DEBUG" => DeviceLogLevel::Debug,
+            "WARN" | "WARNING" => DeviceLogLevel::Warning,
+            "ERROR" => DeviceLogLevel::Error,
+            "CRIT" | "CRITICAL" => DeviceLogLevel::Critical,
+            _ => DeviceLogLevel::Info,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum DeviceLogSource {
+    Serial,    // plain text written to the serial port between protobuf frames
+    LogRecord, // `LogRecord` packets sent over the protobuf API
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceLogEntry {
+    pub timestamp: u32, // host time the line was captured, in seconds since epoch
+    pub level: DeviceLogLevel,
+    pub source: DeviceLogSource,
+    pub message: String,
+}
+
+impl DeviceLogEntry {
+    pub fn from_serial_line(line: String, timestamp: u32) -> Self {
+        Self {
+            timestamp,
+            level: DeviceLogLevel::from_serial_line(&line),
+            source: DeviceLogSource::Serial,
+            message: line,
+        }
+    }
+
+    pub fn from_log_record(record: protobufs::LogRecord, timestamp: u32) -> Self {
+        let message = if record.source.is_empty() {
+            record.message
+        } else {
+            format!("[{}] {}", record.source, record.message)
+        };
+
+        Self {
+            timestamp,
+            level: DeviceLogLevel::from_log_record(record.level),
+            source: DeviceLogSource::LogRecord,
+            message,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceLogConfig {
+    /// Whether device log lines are captured and streamed to the UI
+    pub enabled: bool,
+
+    /// Maximum number of lines captured per device each second
+    pub max_lines_per_sec: u32,
+}
+
+impl Default for DeviceLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_lines_per_sec: DEFAULT_DEVICE_LOG_RATE_LIMIT,
+        }
+    }
+}
+
+/// Bounded log history for a single device, with a per-second rate limit
+#[derive(Clone, Debug, Default)]
+pub struct DeviceLogBuffer {
+    entries: VecDeque<DeviceLogEntry>,
+    window_start: u32,
+    lines_in_window: u32,
+    pub dropped_lines: u64,
+}
+
+impl DeviceLogBuffer {
+    /// Stores a log entry, returning whether it was accepted under the rate limit
+    pub fn push(&mut self, entry: DeviceLogEntry, max_lines_per_sec: u32) -> bool {
+        if entry.timestamp != self.window_start {
+            self.window_start = entry.timestamp;
+            self.lines_in_window = 0;
+        }
+
+        if self.lines_in_window >= max_lines_per_sec {
+            self.dropped_lines += 1;
+            return false;
+        }
+
+        self.lines_in_window += 1;
+
+        if self.entries.len() >= DEVICE_LOG_BUFFER_CAPACITY {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(entry);
+
+        true
+    }
+
+    /// Returns entries captured at or after `since` with at least the given level
+    pub fn query(&self, since: u32, level: DeviceLogLevel) -> Vec<DeviceLogEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.timestamp >= since && entry.level >= level)
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(timestamp: u32, line: &str) -> DeviceLogEntry {
+        DeviceLogEntry::from_serial_line(line.into(), timestamp)
+    }
+
+    #[test]
+    fn parses_serial_line_levels() {
+        assert_eq!(
+            DeviceLogLevel::from_serial_line("WARN  | 12:00:01 5 [Router] No route"),
+            DeviceLogLevel::Warning
+        );
+        assert_eq!(
+            DeviceLogLevel::from_serial_line("DEBUG | 12:00:01 5 Sending packet"),
+            DeviceLogLevel::Debug
+        );
+        assert_eq!(
+            DeviceLogLevel::from_serial_line("Booting firmware"),
+            DeviceLogLevel::Info
+        );
+    }
+
+    #[test]
+    fn maps_log_record_levels() {
+        let record = protobufs::LogRecord {
+            message: "Radio init failed".into(),
+            source: "RadioIf".into(),
+            level: protobufs::log_record::Level::Error as i32,
+            ..Default::default()
+        };
+
+        let entry = DeviceLogEntry::from_log_record(record, 10);
+
+        assert_eq!(entry.level, DeviceLogLevel::Error);
+        assert_eq!(entry.source, DeviceLogSource::LogRecord);
+        assert_eq!(entry.message, "[RadioIf] Radio init failed");
+        assert_eq!(DeviceLogLevel::from_log_record(0), DeviceLogLevel::Info);
+    }
+
+    #[test]
+    fn filters_by_time_and_level() {
+        let mut buffer = DeviceLogBuffer::default();
+
+        buffer.push(entry(1, "DEBUG | early"), 10);
+        buffer.push(entry(5, "INFO  | later"), 10);
+        buffer.push(entry(6, "ERROR | failure"), 10);
+
+        assert_eq!(buffer.query(5, DeviceLogLevel::Trace).len(), 2);
+
+        let errors = buffer.query(0, DeviceLogLevel::Warning);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "ERROR | failure");
+    }
+
+    #[test]
+    fn drops_lines_over_rate_limit() {
+        let mut buffer = DeviceLogBuffer::default();
+
+        assert!(buffer.push(entry(1, "a"), 2));
+        assert!(buffer.push(entry(1, "b"), 2));
+        assert!(!buffer.push(entry(1, "c"), 2));
+        assert!(buffer.push(entry(2, "d"), 2));
+
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.dropped_lines, 1);
+    }
+
+    #[test]
+    fn evicts_oldest_entries_when_full() {
+        let mut buffer = DeviceLogBuffer::default();
+
+        for i in 0..(DEVICE_LOG_BUFFER_CAPACITY as u32 + 1) {
+            buffer.push(entry(i, &i.to_string()), 1);
+        }
+
+        assert_eq!(buffer.len(), DEVICE_LOG_BUFFER_CAPACITY);
+        assert_eq!(buffer.query(0, DeviceLogLevel::Trace)[0].message, "1");
+    }
+}
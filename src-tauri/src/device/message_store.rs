@@ -0,0 +1,2084 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use log::{trace, warn};
+use meshtastic::packet::PacketDestination;
+use meshtastic::ts::specta::{self, Type};
+use meshtastic::types::NodeId;
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::ipc::{events, UnreadCountsChangedEvent, EVENT_API_VERSION};
+use crate::migrations::{migrate, Migration, MigrationError};
+use crate::packet_api::{summary::ConnectionType, MeshPacketApi};
+use crate::retention::{ClassUsage, RetainedTable, RetentionLimits, StorageClass};
+use crate::shutdown::shutdown_signal;
+use crate::state::mesh_devices::all_devices;
+use crate::state::{self, DeviceKey};
+
+use super::helpers::get_current_time_u32;
+use super::reactions::MessageReactions;
+use super::{ChannelMessagePayload, ChannelMessageState, TextPacket};
+
+/// Version of the last migration below, recorded in `schema_version`
+pub const MESSAGE_STORE_SCHEMA_VERSION: u32 = 6;
+
+/// How often queued message writes are written to the database
+pub const MESSAGE_STORE_WRITE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Most messages returned by a single page or search
+pub const MAX_MESSAGE_PAGE_SIZE: u32 = 500;
+
+/// Revisions of each node's note kept, including the current one
+pub const MAX_NOTE_REVISIONS: u32 = 20;
+
+/// Longest note accepted, in characters
+pub const MAX_NOTE_LEN: usize = 10_000;
+
+/// Longest note summary included in node details, in characters
+const NOTE_SUMMARY_LEN: usize = 120;
+
+/// How long acked and failed outgoing messages stay in the journal, in seconds
+pub const OUTGOING_JOURNAL_RETENTION_SECS: u32 = 7 * 24 * 60 * 60;
+
+const BROADCAST_ADDR: u32 = 0xffff_ffff;
+
+/// Messages are keyed by the node num of the device they were sent or heard by, and
+/// by sender and packet id, so a delivery state or reaction update replaces the row.
+///
+/// Messages and the current note of each node share a full text index. Messages are
+/// indexed by triggers under their id, and notes by `set_node_note` under their negated
+/// id, so the two never collide.
+///
+/// Outgoing text messages are journalled before they're sent, so messages cut off by
+/// a crash or disconnect can be offered for resending. Their packet id is only known
+/// once sent.
+///
+/// Each conversation's read marker is the timestamp and id of the last message read,
+/// the order pages are returned in, so it only depends on the messages that exist.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "messages and full text index",
+        sql: "
+    CREATE TABLE IF NOT EXISTS messages (
+        id INTEGER PRIMARY KEY,
+        device_id INTEGER NOT NULL,
+        packet_id INTEGER NOT NULL,
+        channel INTEGER NOT NULL,
+        from_node INTEGER NOT NULL,
+        to_node INTEGER NOT NULL,
+        timestamp INTEGER NOT NULL,
+        text TEXT NOT NULL,
+        state TEXT NOT NULL,
+        reactions TEXT NOT NULL,
+        UNIQUE (device_id, from_node, packet_id)
+    );
+
+    CREATE INDEX IF NOT EXISTS messages_by_time
+        ON messages (device_id, timestamp DESC, id DESC);
+
+    CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts
+        USING fts5(text, content='messages', content_rowid='id');
+
+    CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages BEGIN
+        INSERT INTO messages_fts (rowid, text) VALUES (new.id, new.text);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS messages_fts_delete AFTER DELETE ON messages BEGIN
+        INSERT INTO messages_fts (messages_fts, rowid, text) VALUES ('delete', old.id, old.text);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS messages_fts_update AFTER UPDATE OF text ON messages BEGIN
+        INSERT INTO messages_fts (messages_fts, rowid, text) VALUES ('delete', old.id, old.text);
+        INSERT INTO messages_fts (rowid, text) VALUES (new.id, new.text);
+    END;
+",
+    },
+    Migration {
+        version: 2,
+        description: "index channel conversations",
+        sql: "
+    CREATE INDEX messages_by_channel
+        ON messages (device_id, channel, timestamp DESC, id DESC);
+",
+    },
+    Migration {
+        version: 3,
+        description: "node notes and shared full text index",
+        sql: "
+    DROP TRIGGER messages_fts_insert;
+    DROP TRIGGER messages_fts_delete;
+    DROP TRIGGER messages_fts_update;
+    DROP TABLE messages_fts;
+
+    CREATE VIRTUAL TABLE search_fts USING fts5(text, kind UNINDEXED);
+
+    INSERT INTO search_fts (rowid, text, kind) SELECT id, text, 'message' FROM messages;
+
+    CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+        INSERT INTO search_fts (rowid, text, kind) VALUES (new.id, new.text, 'message');
+    END;
+
+    CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+        DELETE FROM search_fts WHERE rowid = old.id;
+    END;
+
+    CREATE TRIGGER messages_fts_update AFTER UPDATE OF text ON messages BEGIN
+        UPDATE search_fts SET text = new.text WHERE rowid = new.id;
+    END;
+
+    CREATE TABLE node_notes (
+        id INTEGER PRIMARY KEY,
+        node_num INTEGER NOT NULL,
+        timestamp INTEGER NOT NULL,
+        text TEXT NOT NULL
+    );
+
+    CREATE INDEX node_notes_by_node ON node_notes (node_num, id DESC);
+",
+    },
+    Migration {
+        version: 4,
+        description: "outgoing message journal",
+        sql: "
+    CREATE TABLE outgoing_messages (
+        id INTEGER PRIMARY KEY,
+        device_id INTEGER NOT NULL,
+        packet_id INTEGER,
+        channel INTEGER NOT NULL,
+        destination INTEGER NOT NULL,
+        text TEXT NOT NULL,
+        status TEXT NOT NULL,
+        error TEXT,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+
+    CREATE INDEX outgoing_messages_by_status ON outgoing_messages (device_id, status, id);
+",
+    },
+    Migration {
+        version: 5,
+        description: "conversation read markers",
+        sql: "
+    CREATE TABLE read_markers (
+        device_id INTEGER NOT NULL,
+        conversation_type TEXT NOT NULL,
+        conversation_id INTEGER NOT NULL,
+        timestamp INTEGER NOT NULL,
+        message_id INTEGER NOT NULL,
+        PRIMARY KEY (device_id, conversation_type, conversation_id)
+    ) WITHOUT ROWID;
+",
+    },
+    Migration {
+        version: 6,
+        description: "port messages were stored through",
+        sql: "
+    ALTER TABLE messages ADD COLUMN device_key TEXT;
+",
+    },
+];
+
+const MESSAGES_TABLE: RetainedTable = RetainedTable {
+    name: "messages",
+    key: &["id"],
+    timestamp: "timestamp",
+    row_bytes: "LENGTH(text) + LENGTH(state) + LENGTH(reactions)",
+};
+
+const MESSAGE_COLUMNS: &str = "id, device_id, packet_id, channel, from_node, to_node, timestamp, \
+    text, state, reactions, device_key";
+
+const OUTGOING_COLUMNS: &str =
+    "id, device_id, packet_id, channel, destination, text, status, error, created_at, updated_at";
+
+/// Statuses of journalled outgoing messages that haven't been acked or failed
+const INCOMPLETE_OUTGOING: &str = "status IN ('queued', 'inFlight')";
+
+/// The messages a page is taken from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", tag = "type", content = "id")]
+pub enum MessageConversation {
+    /// Broadcast messages on a channel index
+    Channel(u32),
+
+    /// Direct messages to or from a node
+    Direct(u32),
+}
+
+impl MessageConversation {
+    /// Stored in the `conversation_type` column
+    fn tag(&self) -> &'static str {
+        match self {
+            MessageConversation::Channel(_) => "channel",
+            MessageConversation::Direct(_) => "direct",
+        }
+    }
+
+    fn id(&self) -> u32 {
+        match self {
+            MessageConversation::Channel(id) | MessageConversation::Direct(id) => *id,
+        }
+    }
+
+    fn from_tag(tag: &str, id: u32) -> Option<Self> {
+        match tag {
+            "channel" => Some(MessageConversation::Channel(id)),
+            "direct" => Some(MessageConversation::Direct(id)),
+            _ => None,
+        }
+    }
+
+    /// Filter on the messages of the conversation, given the broadcast address as
+    /// `?2` and the conversation's id as `?3`
+    fn filter(&self) -> &'static str {
+        match self {
+            MessageConversation::Channel(_) => "to_node = ?2 AND channel = ?3",
+            MessageConversation::Direct(_) => "to_node != ?2 AND (from_node = ?3 OR to_node = ?3)",
+        }
+    }
+}
+
+/// A conversation of a device with stored messages. Messages the device sent are
+/// never unread.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationSummary {
+    pub conversation: MessageConversation,
+    pub last_message_at: u32, // secs
+    pub unread_count: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredMessage {
+    pub device_id: u32, // node num of the device the message was sent or heard by
+    pub packet_id: u32,
+    pub channel: u32,
+    pub from: u32,
+    pub to: u32,
+    pub timestamp: u32, // secs
+    pub text: String,
+    pub state: ChannelMessageState,
+    pub reactions: MessageReactions,
+    #[serde(default)]
+    pub device_key: Option<DeviceKey>, // port the device was on, unknown for older messages
+}
+
+impl StoredMessage {
+    /// Messages we sent have no rx time, so are stamped with the time they're stored
+    pub fn new(
+        device_id: u32,
+        device_key: &DeviceKey,
+        text: &TextPacket,
+        state: &ChannelMessageState,
+    ) -> Self {
+        let timestamp = match text.packet.rx_time {
+            0 => get_current_time_u32(),
+            rx_time => rx_time,
+        };
+
+        Self {
+            device_id,
+            packet_id: text.packet.id,
+            channel: text.packet.channel,
+            from: text.packet.from,
+            to: text.packet.to,
+            timestamp,
+            text: text.data.clone(),
+            state: state.clone(),
+            reactions: text.reactions.clone(),
+            device_key: Some(device_key.clone()),
+        }
+    }
+}
+
+/// Delivery status of a journalled outgoing message
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum OutgoingStatus {
+    /// Journalled but not handed to the radio yet
+    Queued,
+
+    /// Handed to the radio, waiting for an ack
+    InFlight,
+
+    Acked,
+    Failed,
+}
+
+impl OutgoingStatus {
+    fn tag(self) -> &'static str {
+        match self {
+            OutgoingStatus::Queued => "queued",
+            OutgoingStatus::InFlight => "inFlight",
+            OutgoingStatus::Acked => "acked",
+            OutgoingStatus::Failed => "failed",
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "queued" => Some(OutgoingStatus::Queued),
+            "inFlight" => Some(OutgoingStatus::InFlight),
+            "acked" => Some(OutgoingStatus::Acked),
+            "failed" => Some(OutgoingStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// A text message in the outgoing journal
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct OutgoingMessage {
+    pub id: u32,
+    pub device_id: u32,
+    pub packet_id: Option<u32>, // id of the last send, `None` until sent
+    pub channel: u32,
+    pub destination: u32, // node num, or the broadcast address
+    pub text: String,
+    pub status: OutgoingStatus,
+    pub error: Option<String>,
+    pub created_at: u32, // secs
+    pub updated_at: u32, // secs
+}
+
+impl OutgoingMessage {
+    pub fn packet_destination(&self) -> PacketDestination {
+        match self.destination {
+            BROADCAST_ADDR => PacketDestination::Broadcast,
+            node_num if node_num == self.device_id => PacketDestination::Local,
+            node_num => PacketDestination::Node(NodeId::new(node_num)),
+        }
+    }
+}
+
+fn destination_node_num(device_id: u32, destination: &PacketDestination) -> u32 {
+    match destination {
+        PacketDestination::Local => device_id,
+        PacketDestination::Broadcast => BROADCAST_ADDR,
+        PacketDestination::Node(node_id) => node_id.id(),
+    }
+}
+
+fn read_outgoing(row: &Row) -> rusqlite::Result<OutgoingMessage> {
+    let status: String = row.get(6)?;
+
+    Ok(OutgoingMessage {
+        id: row.get(0)?,
+        device_id: row.get(1)?,
+        packet_id: row.get(2)?,
+        channel: row.get(3)?,
+        destination: row.get(4)?,
+        text: row.get(5)?,
+        status: OutgoingStatus::from_tag(&status).ok_or_else(|| {
+            rusqlite::Error::InvalidColumnType(6, status, rusqlite::types::Type::Text)
+        })?,
+        error: row.get(7)?,
+        created_at: row.get(8)?,
+        updated_at: row.get(9)?,
+    })
+}
+
+/// Journal rows being sent or waiting for an ack over the current connections. Rows
+/// left incomplete in the journal but missing here were cut off by a crash or
+/// disconnect, and are offered for resending.
+#[derive(Debug, Default)]
+pub struct OutgoingQueue {
+    active: HashMap<u32, u32>, // journal id to device id
+}
+
+impl OutgoingQueue {
+    pub fn insert(&mut self, message: &OutgoingMessage) {
+        self.active.insert(message.id, message.device_id);
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        self.active.remove(&id);
+    }
+
+    /// Forgets a device's messages once its connection is gone, so the ones never
+    /// acked become unsent
+    pub fn clear_device(&mut self, device_id: u32) {
+        self.active
+            .retain(|_, active_device_id| *active_device_id != device_id);
+    }
+
+    /// The device's incomplete journal rows that aren't being sent, oldest first
+    pub fn unsent(
+        &self,
+        store: &MessageStore,
+        device_id: u32,
+    ) -> Result<Vec<OutgoingMessage>, String> {
+        let messages = store.incomplete_outgoing(device_id)?;
+
+        Ok(messages
+            .into_iter()
+            .filter(|message| !self.active.contains_key(&message.id))
+            .collect())
+    }
+
+    /// Takes the unsent messages among `ids` to be resent, so that asking again for
+    /// the same ids doesn't send them twice
+    pub fn claim(
+        &mut self,
+        store: &MessageStore,
+        device_id: u32,
+        ids: &[u32],
+    ) -> Result<Vec<OutgoingMessage>, String> {
+        let claimed: Vec<OutgoingMessage> = self
+            .unsent(store, device_id)?
+            .into_iter()
+            .filter(|message| ids.contains(&message.id))
+            .collect();
+
+        for message in &claimed {
+            self.insert(message);
+        }
+
+        Ok(claimed)
+    }
+}
+
+/// Revision of the operator's free-form Markdown note about a node. Notes are kept by
+/// node num alone, so they outlive the node dropping out of the mesh.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeNote {
+    pub node_num: u32,
+    pub timestamp: u32, // secs
+    pub text: String,   // empty if the note was cleared
+}
+
+/// Start of a node's current note, for the node detail panel
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeNoteSummary {
+    pub timestamp: u32, // secs
+    pub summary: String,
+    pub truncated: bool,
+}
+
+impl NodeNote {
+    /// First line of the note, shortened to `NOTE_SUMMARY_LEN` characters. Cleared
+    /// notes have no summary.
+    pub fn summary(&self) -> Option<NodeNoteSummary> {
+        let text = self.text.trim();
+        let first_line = text.lines().next()?;
+        let summary: String = first_line.chars().take(NOTE_SUMMARY_LEN).collect();
+
+        Some(NodeNoteSummary {
+            timestamp: self.timestamp,
+            truncated: summary.len() < text.len(),
+            summary,
+        })
+    }
+}
+
+/// A message or node note matching a search
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", tag = "type", content = "item")]
+pub enum SearchResult {
+    Message(StoredMessage),
+    Note(NodeNote),
+}
+
+impl SearchResult {
+    pub fn timestamp(&self) -> u32 {
+        match self {
+            SearchResult::Message(message) => message.timestamp,
+            SearchResult::Note(note) => note.timestamp,
+        }
+    }
+}
+
+/// Quotes each word of a search so characters like `"` and `*` are matched rather
+/// than read as FTS query syntax. Every word has to match.
+fn fts_query(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+fn page_size(limit: u32) -> Result<u32, String> {
+    match limit {
+        0 => Err("Limit must be at least 1".into()),
+        limit => Ok(limit.min(MAX_MESSAGE_PAGE_SIZE)),
+    }
+}
+
+/// Reads a row selected with `MESSAGE_COLUMNS`, along with its row id
+fn read_message(row: &Row) -> rusqlite::Result<(i64, StoredMessage, String, String)> {
+    Ok((
+        row.get(0)?,
+        StoredMessage {
+            device_id: row.get(1)?,
+            packet_id: row.get(2)?,
+            channel: row.get(3)?,
+            from: row.get(4)?,
+            to: row.get(5)?,
+            timestamp: row.get(6)?,
+            text: row.get(7)?,
+            state: ChannelMessageState::Pending,
+            reactions: MessageReactions::new(),
+            device_key: row.get(10)?,
+        },
+        row.get(8)?,
+        row.get(9)?,
+    ))
+}
+
+fn decode_message(
+    (id, mut message, state, reactions): (i64, StoredMessage, String, String),
+) -> Result<(i64, StoredMessage), String> {
+    message.state = serde_json::from_str(&state)
+        .map_err(|e| format!("Invalid state of message {}: {}", message.packet_id, e))?;
+    message.reactions = serde_json::from_str(&reactions)
+        .map_err(|e| format!("Invalid reactions of message {}: {}", message.packet_id, e))?;
+
+    Ok((id, message))
+}
+
+/// SQLite database of the text messages sent and received by each device, so
+/// conversations outlive the connection
+pub struct MessageStore {
+    connection: Connection,
+}
+
+impl MessageStore {
+    pub fn open(path: &Path) -> Result<Self, MigrationError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {:?}: {}", parent, e))?;
+        }
+
+        let connection =
+            Connection::open(path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+
+        Self::with_connection(connection, Some(path))
+    }
+
+    pub fn open_in_memory() -> Result<Self, MigrationError> {
+        Self::with_connection(
+            Connection::open_in_memory().map_err(|e| e.to_string())?,
+            None,
+        )
+    }
+
+    fn with_connection(
+        mut connection: Connection,
+        path: Option<&Path>,
+    ) -> Result<Self, MigrationError> {
+        let version = migrate(&mut connection, "message database", MIGRATIONS, path)?;
+        debug_assert_eq!(version, MESSAGE_STORE_SCHEMA_VERSION);
+
+        Ok(Self { connection })
+    }
+
+    /// Writes a batch of messages in one transaction. A message already stored, e.g.
+    /// delivered twice, keeps its text, timestamp and port, and has its delivery state
+    /// and reactions updated.
+    pub fn write(&mut self, messages: &[StoredMessage]) -> Result<usize, String> {
+        let transaction = self.connection.transaction().map_err(|e| e.to_string())?;
+
+        for message in messages {
+            let state = serde_json::to_string(&message.state).map_err(|e| e.to_string())?;
+            let reactions = serde_json::to_string(&message.reactions).map_err(|e| e.to_string())?;
+
+            transaction
+                .execute(
+                    "INSERT INTO messages
+                        (device_id, packet_id, channel, from_node, to_node, timestamp, text,
+                            state, reactions, device_key)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                        ON CONFLICT (device_id, from_node, packet_id)
+                        DO UPDATE SET state = excluded.state, reactions = excluded.reactions",
+                    params![
+                        message.device_id,
+                        message.packet_id,
+                        message.channel,
+                        message.from,
+                        message.to,
+                        message.timestamp,
+                        message.text,
+                        state,
+                        reactions,
+                        message.device_key
+                    ],
+                )
+                .map_err(|e| e.to_string())?;
+        }
+
+        transaction.commit().map_err(|e| e.to_string())?;
+
+        Ok(messages.len())
+    }
+
+    /// Returns up to `limit` messages of a conversation sent before `before_timestamp`,
+    /// newest first. A page is never split between messages sharing a timestamp, so
+    /// it may run past `limit`, and the oldest timestamp of a page can be passed as
+    /// `before_timestamp` to fetch the next one.
+    pub fn get_messages(
+        &self,
+        device_id: u32,
+        conversation: MessageConversation,
+        before_timestamp: Option<u32>,
+        limit: u32,
+    ) -> Result<Vec<StoredMessage>, String> {
+        let limit = page_size(limit)?;
+        let (filter, id) = (conversation.filter(), conversation.id());
+
+        let page_sql = format!(
+            "SELECT {} FROM messages
+                WHERE device_id = ?1 AND {} AND timestamp < ?4
+                ORDER BY timestamp DESC, id DESC LIMIT ?5",
+            MESSAGE_COLUMNS, filter
+        );
+
+        let mut messages = self.query_messages(
+            &page_sql,
+            params![
+                device_id,
+                BROADCAST_ADDR,
+                id,
+                before_timestamp.map_or(i64::MAX, i64::from),
+                limit
+            ],
+        )?;
+
+        if messages.len() < limit as usize {
+            return Ok(messages.into_iter().map(|(_, message)| message).collect());
+        }
+
+        // Finish the page with the rest of the messages at its oldest timestamp
+
+        let (oldest_id, oldest_timestamp) = match messages.last() {
+            Some((id, message)) => (*id, message.timestamp),
+            None => return Ok(vec![]),
+        };
+
+        let rest_sql = format!(
+            "SELECT {} FROM messages
+                WHERE device_id = ?1 AND {} AND timestamp = ?4 AND id < ?5
+                ORDER BY id DESC",
+            MESSAGE_COLUMNS, filter
+        );
+
+        messages.extend(self.query_messages(
+            &rest_sql,
+            params![device_id, BROADCAST_ADDR, id, oldest_timestamp, oldest_id],
+        )?);
+
+        Ok(messages.into_iter().map(|(_, message)| message).collect())
+    }
+
+    /// Marks the messages of a conversation up to and including the one with
+    /// `packet_id` as read. Marking an earlier message leaves the conversation as it is.
+    pub fn mark_read(
+        &mut self,
+        device_id: u32,
+        conversation: MessageConversation,
+        packet_id: u32,
+    ) -> Result<(), String> {
+        let sql = format!(
+            "SELECT id, timestamp FROM messages
+                WHERE device_id = ?1 AND {} AND packet_id = ?4
+                ORDER BY timestamp DESC, id DESC LIMIT 1",
+            conversation.filter()
+        );
+
+        let (message_id, timestamp): (i64, u32) = self
+            .connection
+            .query_row(
+                &sql,
+                params![device_id, BROADCAST_ADDR, conversation.id(), packet_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?
+            .ok_or("Message not found in conversation")?;
+
+        self.connection
+            .execute(
+                "INSERT INTO read_markers
+                    (device_id, conversation_type, conversation_id, timestamp, message_id)
+                    VALUES (?1, ?2, ?3, ?4, ?5)
+                    ON CONFLICT (device_id, conversation_type, conversation_id)
+                    DO UPDATE SET timestamp = excluded.timestamp, message_id = excluded.message_id
+                    WHERE (excluded.timestamp, excluded.message_id)
+                        > (read_markers.timestamp, read_markers.message_id)",
+                params![
+                    device_id,
+                    conversation.tag(),
+                    conversation.id(),
+                    timestamp,
+                    message_id
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+
+    /// Every conversation of a device with its unread count, most recently active first
+    pub fn conversation_summaries(
+        &self,
+        device_id: u32,
+    ) -> Result<Vec<ConversationSummary>, String> {
+        let mut statement = self
+            .connection
+            .prepare(
+                "WITH conversations AS (
+                    SELECT id, from_node, timestamp,
+                        CASE WHEN to_node = ?2 THEN 'channel' ELSE 'direct' END AS conversation_type,
+                        CASE
+                            WHEN to_node = ?2 THEN channel
+                            WHEN from_node = ?1 THEN to_node
+                            ELSE from_node
+                        END AS conversation_id
+                    FROM messages WHERE device_id = ?1
+                )
+                SELECT c.conversation_type, c.conversation_id, MAX(c.timestamp),
+                    SUM(c.from_node != ?1 AND (r.timestamp IS NULL
+                        OR (c.timestamp, c.id) > (r.timestamp, r.message_id)))
+                FROM conversations c
+                LEFT JOIN read_markers r
+                    ON r.device_id = ?1
+                    AND r.conversation_type = c.conversation_type
+                    AND r.conversation_id = c.conversation_id
+                GROUP BY c.conversation_type, c.conversation_id
+                ORDER BY MAX(c.timestamp) DESC, c.conversation_type, c.conversation_id",
+            )
+            .map_err(|e| e.to_string())?;
+
+        let rows = statement
+            .query_map(params![device_id, BROADCAST_ADDR], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, u32>(1)?,
+                    row.get::<_, u32>(2)?,
+                    row.get::<_, u32>(3)?,
+                ))
+            })
+            .map_err(|e| e.to_string())?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(|e| e.to_string())?;
+
+        rows.into_iter()
+            .map(|(tag, id, last_message_at, unread_count)| {
+                Ok(ConversationSummary {
+                    conversation: MessageConversation::from_tag(&tag, id)
+                        .ok_or_else(|| format!("Unknown conversation type \"{}\"", tag))?,
+                    last_message_at,
+                    unread_count,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns up to `limit` messages of any device containing every word of `query`,
+    /// newest first
+    pub fn search_messages(&self, query: &str, limit: u32) -> Result<Vec<StoredMessage>, String> {
+        let limit = page_size(limit)?;
+        let query = fts_query(query).ok_or("Search query is empty")?;
+
+        let sql = format!(
+            "SELECT {} FROM messages
+                WHERE id IN (SELECT rowid FROM search_fts
+                    WHERE search_fts MATCH ?1 AND kind = 'message')
+                ORDER BY timestamp DESC, id DESC LIMIT ?2",
+            MESSAGE_COLUMNS
+        );
+
+        let messages = self.query_messages(&sql, params![query, limit])?;
+
+        Ok(messages.into_iter().map(|(_, message)| message).collect())
+    }
+
+    /// Returns up to `limit` messages and current node notes containing every word of
+    /// `query`, newest first
+    pub fn search_all(&self, query: &str, limit: u32) -> Result<Vec<SearchResult>, String> {
+        let limit = page_size(limit)?;
+        let messages = self.search_messages(query, limit)?;
+        let query = fts_query(query).ok_or("Search query is empty")?;
+
+        let notes = self.query_notes(
+            "SELECT node_num, timestamp, text FROM node_notes
+                WHERE id IN (SELECT -rowid FROM search_fts
+                    WHERE search_fts MATCH ?1 AND kind = 'note')
+                ORDER BY timestamp DESC, id DESC LIMIT ?2",
+            params![query, limit],
+        )?;
+
+        let mut results: Vec<SearchResult> = messages
+            .into_iter()
+            .map(SearchResult::Message)
+            .chain(notes.into_iter().map(SearchResult::Note))
+            .collect();
+
+        // Stable, so messages stay ahead of notes from the same second
+        results.sort_by_key(|result| std::cmp::Reverse(result.timestamp()));
+        results.truncate(limit as usize);
+
+        Ok(results)
+    }
+
+    /// Stores a new revision of a node's note, replacing the current one in the search
+    /// index, and drops revisions past `MAX_NOTE_REVISIONS`. An empty note clears it.
+    pub fn set_node_note(
+        &mut self,
+        node_num: u32,
+        text: &str,
+        timestamp: u32,
+    ) -> Result<NodeNote, String> {
+        if text.chars().count() > MAX_NOTE_LEN {
+            return Err(format!(
+                "Notes can be at most {} characters long",
+                MAX_NOTE_LEN
+            ));
+        }
+
+        let transaction = self.connection.transaction().map_err(|e| e.to_string())?;
+
+        transaction
+            .execute(
+                "DELETE FROM search_fts WHERE rowid IN
+                    (SELECT -id FROM node_notes WHERE node_num = ?1)",
+                params![node_num],
+            )
+            .map_err(|e| e.to_string())?;
+
+        transaction
+            .execute(
+                "INSERT INTO node_notes (node_num, timestamp, text) VALUES (?1, ?2, ?3)",
+                params![node_num, timestamp, text],
+            )
+            .map_err(|e| e.to_string())?;
+
+        let id = transaction.last_insert_rowid();
+
+        if !text.trim().is_empty() {
+            transaction
+                .execute(
+                    "INSERT INTO search_fts (rowid, text, kind) VALUES (?1, ?2, 'note')",
+                    params![-id, text],
+                )
+                .map_err(|e| e.to_string())?;
+        }
+
+        transaction
+            .execute(
+                "DELETE FROM node_notes WHERE node_num = ?1 AND id NOT IN
+                    (SELECT id FROM node_notes WHERE node_num = ?1 ORDER BY id DESC LIMIT ?2)",
+                params![node_num, MAX_NOTE_REVISIONS],
+            )
+            .map_err(|e| e.to_string())?;
+
+        transaction.commit().map_err(|e| e.to_string())?;
+
+        Ok(NodeNote {
+            node_num,
+            timestamp,
+            text: text.into(),
+        })
+    }
+
+    /// Every kept revision of a node's note, newest first
+    pub fn get_node_notes(&self, node_num: u32) -> Result<Vec<NodeNote>, String> {
+        self.query_notes(
+            "SELECT node_num, timestamp, text FROM node_notes
+                WHERE node_num = ?1 ORDER BY id DESC",
+            params![node_num],
+        )
+    }
+
+    pub fn current_node_note(&self, node_num: u32) -> Result<Option<NodeNote>, String> {
+        let notes = self.query_notes(
+            "SELECT node_num, timestamp, text FROM node_notes
+                WHERE node_num = ?1 ORDER BY id DESC LIMIT 1",
+            params![node_num],
+        )?;
+
+        Ok(notes.into_iter().next())
+    }
+
+    /// Every stored message, in the order they were first stored
+    pub fn all_messages(&self) -> Result<Vec<StoredMessage>, String> {
+        let sql = format!("SELECT {} FROM messages ORDER BY id", MESSAGE_COLUMNS);
+        let messages = self.query_messages(&sql, [])?;
+
+        Ok(messages.into_iter().map(|(_, message)| message).collect())
+    }
+
+    pub fn usage(&self) -> Result<ClassUsage, String> {
+        let (rows, bytes) = MESSAGES_TABLE.usage(&self.connection)?;
+
+        Ok(ClassUsage {
+            class: StorageClass::Messages,
+            rows,
+            bytes,
+        })
+    }
+
+    /// Deletes the messages beyond the retention limits, returning how many were
+    /// deleted. Notes are never deleted.
+    pub fn enforce_retention(&mut self, limits: RetentionLimits) -> Result<usize, String> {
+        let transaction = self.connection.transaction().map_err(|e| e.to_string())?;
+        let deleted = MESSAGES_TABLE.enforce(&transaction, limits)?;
+        transaction.commit().map_err(|e| e.to_string())?;
+
+        Ok(deleted.len())
+    }
+
+    /// Journals a text message about to be sent
+    pub fn enqueue_outgoing(
+        &mut self,
+        device_id: u32,
+        channel: u32,
+        destination: &PacketDestination,
+        text: &str,
+        now: u32,
+    ) -> Result<OutgoingMessage, String> {
+        let destination = destination_node_num(device_id, destination);
+
+        self.connection
+            .execute(
+                "INSERT INTO outgoing_messages
+                    (device_id, channel, destination, text, status, created_at, updated_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)",
+                params![
+                    device_id,
+                    channel,
+                    destination,
+                    text,
+                    OutgoingStatus::Queued.tag(),
+                    now
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+
+        Ok(OutgoingMessage {
+            id: self.connection.last_insert_rowid() as u32,
+            device_id,
+            packet_id: None,
+            channel,
+            destination,
+            text: text.into(),
+            status: OutgoingStatus::Queued,
+            error: None,
+            created_at: now,
+            updated_at: now,
+        })
+    }
+
+    /// Records the packet id a journalled message was sent with
+    pub fn mark_outgoing_sent(&mut self, id: u32, packet_id: u32, now: u32) -> Result<(), String> {
+        self.connection
+            .execute(
+                "UPDATE outgoing_messages SET packet_id = ?2, status = ?3, updated_at = ?4
+                    WHERE id = ?1",
+                params![id, packet_id, OutgoingStatus::InFlight.tag(), now],
+            )
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+
+    /// Marks the incomplete message sent by a device with `packet_id` as acked or
+    /// failed, returning its journal id if there was one. Pending states are ignored.
+    pub fn complete_outgoing(
+        &mut self,
+        device_id: u32,
+        packet_id: u32,
+        state: &ChannelMessageState,
+        now: u32,
+    ) -> Result<Option<u32>, String> {
+        let (status, error) = match state {
+            ChannelMessageState::Pending => return Ok(None),
+            ChannelMessageState::Acknowledged => (OutgoingStatus::Acked, None),
+            ChannelMessageState::Error(e) => (OutgoingStatus::Failed, Some(e.as_str())),
+        };
+
+        let sql = format!(
+            "UPDATE outgoing_messages SET status = ?3, error = ?4, updated_at = ?5
+                WHERE device_id = ?1 AND packet_id = ?2 AND {}
+                RETURNING id",
+            INCOMPLETE_OUTGOING
+        );
+
+        let mut statement = self.connection.prepare(&sql).map_err(|e| e.to_string())?;
+
+        let ids = statement
+            .query_map(
+                params![device_id, packet_id, status.tag(), error, now],
+                |row| row.get::<_, u32>(0),
+            )
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        Ok(ids.into_iter().next())
+    }
+
+    /// The device's queued and in flight messages, oldest first
+    pub fn incomplete_outgoing(&self, device_id: u32) -> Result<Vec<OutgoingMessage>, String> {
+        let sql = format!(
+            "SELECT {} FROM outgoing_messages WHERE device_id = ?1 AND {} ORDER BY id",
+            OUTGOING_COLUMNS, INCOMPLETE_OUTGOING
+        );
+
+        let mut statement = self.connection.prepare(&sql).map_err(|e| e.to_string())?;
+
+        let messages = statement
+            .query_map(params![device_id], read_outgoing)
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        Ok(messages)
+    }
+
+    /// Deletes the device's incomplete messages among `ids`, returning how many were
+    /// deleted
+    pub fn discard_outgoing(&mut self, device_id: u32, ids: &[u32]) -> Result<usize, String> {
+        let transaction = self.connection.transaction().map_err(|e| e.to_string())?;
+        let sql = format!(
+            "DELETE FROM outgoing_messages WHERE id = ?1 AND device_id = ?2 AND {}",
+            INCOMPLETE_OUTGOING
+        );
+
+        let mut deleted = 0;
+
+        for id in ids {
+            deleted += transaction
+                .execute(&sql, params![id, device_id])
+                .map_err(|e| e.to_string())?;
+        }
+
+        transaction.commit().map_err(|e| e.to_string())?;
+
+        Ok(deleted)
+    }
+
+    /// Deletes acked and failed messages last updated before `before`. Incomplete
+    /// messages are kept until they're resent or discarded.
+    pub fn prune_outgoing(&mut self, before: u32) -> Result<usize, String> {
+        self.connection
+            .execute(
+                "DELETE FROM outgoing_messages
+                    WHERE status IN ('acked', 'failed') AND updated_at < ?1",
+                params![before],
+            )
+            .map_err(|e| e.to_string())
+    }
+
+    /// Starts a transaction to import messages with `import_messages`
+    pub fn import_transaction(&mut self) -> Result<Transaction<'_>, String> {
+        self.connection.transaction().map_err(|e| e.to_string())
+    }
+
+    fn query_messages(
+        &self,
+        sql: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<(i64, StoredMessage)>, String> {
+        let mut statement = self.connection.prepare(sql).map_err(|e| e.to_string())?;
+
+        let rows = statement
+            .query_map(params, read_message)
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        rows.into_iter().map(decode_message).collect()
+    }
+
+    fn query_notes(
+        &self,
+        sql: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<NodeNote>, String> {
+        let mut statement = self.connection.prepare(sql).map_err(|e| e.to_string())?;
+
+        let notes = statement
+            .query_map(params, |row| {
+                Ok(NodeNote {
+                    node_num: row.get(0)?,
+                    timestamp: row.get(1)?,
+                    text: row.get(2)?,
+                })
+            })
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        Ok(notes)
+    }
+}
+
+/// Writes imported messages in a transaction from `MessageStore::import_transaction`,
+/// after deleting every stored message if `replace` is set. An imported message
+/// replaces the stored one with the same sender and packet id.
+pub fn import_messages(
+    transaction: &Transaction,
+    messages: &[StoredMessage],
+    replace: bool,
+) -> Result<usize, String> {
+    if replace {
+        transaction
+            .execute("DELETE FROM messages", [])
+            .map_err(|e| e.to_string())?;
+    }
+
+    for message in messages {
+        let state = serde_json::to_string(&message.state).map_err(|e| e.to_string())?;
+        let reactions = serde_json::to_string(&message.reactions).map_err(|e| e.to_string())?;
+
+        transaction
+            .execute(
+                "INSERT INTO messages
+                    (device_id, packet_id, channel, from_node, to_node, timestamp, text,
+                        state, reactions, device_key)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                    ON CONFLICT (device_id, from_node, packet_id)
+                    DO UPDATE SET channel = excluded.channel, to_node = excluded.to_node,
+                        timestamp = excluded.timestamp, text = excluded.text,
+                        state = excluded.state, reactions = excluded.reactions,
+                        device_key = excluded.device_key",
+                params![
+                    message.device_id,
+                    message.packet_id,
+                    message.channel,
+                    message.from,
+                    message.to,
+                    message.timestamp,
+                    message.text,
+                    state,
+                    reactions,
+                    message.device_key
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(messages.len())
+}
+
+/// Queues the current state of a text message to be written to the message
+/// database. Only radios are stored, simulated and replayed devices aren't.
+pub fn queue_message_write<R: tauri::Runtime>(
+    packet_api: &MeshPacketApi<R>,
+    channel: u32,
+    packet_id: u32,
+) {
+    let device_id = match stored_device_id(packet_api) {
+        Some(device_id) => device_id,
+        None => return,
+    };
+
+    let message = packet_api
+        .device
+        .channel_messages(channel)
+        .and_then(|messages| {
+            messages.iter().find_map(|message| match &message.payload {
+                ChannelMessagePayload::Text(t) if t.packet.id == packet_id => Some(
+                    StoredMessage::new(device_id, &packet_api.device_key, t, &message.state),
+                ),
+                _ => None,
+            })
+        });
+
+    let message = match message {
+        Some(message) => message,
+        None => return,
+    };
+
+    let store_state = match packet_api
+        .app_handle
+        .try_state::<state::message_store::MessageStoreState>()
+    {
+        Some(store_state) => store_state,
+        None => return,
+    };
+
+    match store_state.pending.lock() {
+        Ok(mut pending) => pending.push(message),
+        Err(e) => warn!("Failed to lock pending message writes: {}", e),
+    };
+}
+
+/// Node num messages of the device are stored under, `None` for devices that aren't
+/// stored
+fn stored_device_id<R: tauri::Runtime>(packet_api: &MeshPacketApi<R>) -> Option<u32> {
+    let device_id = packet_api.device.my_node_info.my_node_num;
+
+    (matches!(
+        packet_api.connection_type,
+        ConnectionType::Serial | ConnectionType::Tcp
+    ) && device_id != 0)
+        .then_some(device_id)
+}
+
+/// Journals a text message before it's sent, and tracks it as being sent. Messages
+/// of devices that aren't stored, or that couldn't be journalled, are sent without.
+pub fn journal_outgoing_message<R: tauri::Runtime>(
+    packet_api: &MeshPacketApi<R>,
+    channel: u32,
+    destination: &PacketDestination,
+    text: &str,
+) -> Option<OutgoingMessage> {
+    let device_id = stored_device_id(packet_api)?;
+    let store_state = packet_api
+        .app_handle
+        .try_state::<state::message_store::MessageStoreState>()?;
+
+    let journalled = store_state
+        .inner
+        .lock()
+        .map_err(|e| e.to_string())
+        .and_then(|mut store_guard| match store_guard.as_mut() {
+            Some(store) => store
+                .enqueue_outgoing(
+                    device_id,
+                    channel,
+                    destination,
+                    text,
+                    get_current_time_u32(),
+                )
+                .map(Some),
+            None => Ok(None),
+        });
+
+    let message = match journalled {
+        Ok(message) => message?,
+        Err(e) => {
+            warn!("Failed to journal outgoing message: {}", e);
+            return None;
+        }
+    };
+
+    if let Ok(mut outgoing) = store_state.outgoing.lock() {
+        outgoing.insert(&message);
+    }
+
+    Some(message)
+}
+
+/// Records the packet id a journalled message was just sent with, read from the copy
+/// of the packet the device stored on sending it. Called before the device is
+/// unlocked, so the ack can't be handled first.
+pub fn mark_outgoing_message_sent<R: tauri::Runtime>(
+    packet_api: &MeshPacketApi<R>,
+    channel: u32,
+    journal_id: u32,
+) {
+    let packet_id = packet_api.device.last_sent_text_id(channel);
+
+    let (packet_id, store_state) = match (
+        packet_id,
+        packet_api
+            .app_handle
+            .try_state::<state::message_store::MessageStoreState>(),
+    ) {
+        (Some(packet_id), Some(store_state)) => (packet_id, store_state),
+        _ => return,
+    };
+
+    let result = store_state
+        .inner
+        .lock()
+        .map_err(|e| e.to_string())
+        .and_then(|mut store_guard| match store_guard.as_mut() {
+            Some(store) => store.mark_outgoing_sent(journal_id, packet_id, get_current_time_u32()),
+            None => Ok(()),
+        });
+
+    if let Err(e) = result {
+        warn!("Failed to journal sent message {}: {}", journal_id, e);
+    }
+}
+
+/// Stops tracking a journalled message that failed to send, leaving it unsent
+pub fn release_outgoing_message<R: tauri::Runtime>(handle: &tauri::AppHandle<R>, journal_id: u32) {
+    if let Some(store_state) = handle.try_state::<state::message_store::MessageStoreState>() {
+        if let Ok(mut outgoing) = store_state.outgoing.lock() {
+            outgoing.remove(journal_id);
+        }
+    }
+}
+
+/// Marks the journalled message sent with `packet_id` as acked or failed, from its
+/// delivery state. Written immediately rather than queued, so a crash right after an
+/// ack doesn't offer the message for resending.
+pub fn complete_outgoing_message<R: tauri::Runtime>(
+    packet_api: &MeshPacketApi<R>,
+    channel: u32,
+    packet_id: u32,
+) {
+    let device_id = match stored_device_id(packet_api) {
+        Some(device_id) => device_id,
+        None => return,
+    };
+
+    let state = packet_api
+        .device
+        .channel_messages(channel)
+        .and_then(|messages| {
+            messages.iter().find_map(|message| match &message.payload {
+                ChannelMessagePayload::Text(t) if t.packet.id == packet_id => {
+                    Some(message.state.clone())
+                }
+                _ => None,
+            })
+        });
+
+    let (state, store_state) = match (
+        state,
+        packet_api
+            .app_handle
+            .try_state::<state::message_store::MessageStoreState>(),
+    ) {
+        (Some(state), Some(store_state)) => (state, store_state),
+        _ => return,
+    };
+
+    let completed = store_state
+        .inner
+        .lock()
+        .map_err(|e| e.to_string())
+        .and_then(|mut store_guard| match store_guard.as_mut() {
+            Some(store) => {
+                store.complete_outgoing(device_id, packet_id, &state, get_current_time_u32())
+            }
+            None => Ok(None),
+        });
+
+    match completed {
+        Ok(Some(journal_id)) => {
+            if let Ok(mut outgoing) = store_state.outgoing.lock() {
+                outgoing.remove(journal_id);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Failed to journal delivery of message {}: {}", packet_id, e),
+    }
+}
+
+/// Called once a device finishes configuring. Messages still tracked from an earlier
+/// connection will never be acked, so they're forgotten and returned with the rest of
+/// the device's unsent messages.
+pub fn unsent_messages_on_connect<R: tauri::Runtime>(
+    packet_api: &MeshPacketApi<R>,
+) -> Result<Vec<OutgoingMessage>, String> {
+    let device_id = match stored_device_id(packet_api) {
+        Some(device_id) => device_id,
+        None => return Ok(vec![]),
+    };
+
+    let store_state = match packet_api
+        .app_handle
+        .try_state::<state::message_store::MessageStoreState>()
+    {
+        Some(store_state) => store_state,
+        None => return Ok(vec![]),
+    };
+
+    let store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+    let store = match store_guard.as_ref() {
+        Some(store) => store,
+        None => return Ok(vec![]),
+    };
+
+    let mut outgoing = store_state.outgoing.lock().map_err(|e| e.to_string())?;
+    outgoing.clear_device(device_id);
+    outgoing.unsent(store, device_id)
+}
+
+/// Writes the queued messages, returning how many were written. Blocks on the
+/// database, so it's called off the async runtime.
+pub fn write_pending_messages<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+) -> Result<usize, String> {
+    let store_state = match handle.try_state::<state::message_store::MessageStoreState>() {
+        Some(store_state) => store_state,
+        None => return Ok(0),
+    };
+
+    let pending = std::mem::take(&mut *store_state.pending.lock().map_err(|e| e.to_string())?);
+
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let mut store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+
+    match store_guard.as_mut() {
+        Some(store) => store.write(&pending),
+        None => Ok(0),
+    }
+}
+
+pub fn conversation_summaries<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_id: u32,
+) -> Result<Vec<ConversationSummary>, String> {
+    let store_state = handle.state::<state::message_store::MessageStoreState>();
+    let store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+
+    store_guard
+        .as_ref()
+        .ok_or("Message database is not available")?
+        .conversation_summaries(device_id)
+}
+
+/// Marks a conversation read up to the message with `packet_id`, returning the
+/// device's conversations. Queued messages are written first, so a message that was
+/// just received can be marked. Blocks on the database.
+pub fn mark_conversation_read<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_id: u32,
+    conversation: MessageConversation,
+    packet_id: u32,
+) -> Result<Vec<ConversationSummary>, String> {
+    write_pending_messages(handle)?;
+
+    {
+        let store_state = handle.state::<state::message_store::MessageStoreState>();
+        let mut store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+
+        store_guard
+            .as_mut()
+            .ok_or("Message database is not available")?
+            .mark_read(device_id, conversation, packet_id)?;
+    }
+
+    conversation_summaries(handle, device_id)
+}
+
+/// Sends the UI the unread counts of each connected device's conversations
+async fn dispatch_unread_counts<R: tauri::Runtime>(handle: &tauri::AppHandle<R>) {
+    let mut devices: Vec<(DeviceKey, u32)> = vec![];
+
+    {
+        let mesh_devices = handle.state::<state::mesh_devices::MeshDevicesState>();
+
+        for (device_key, device) in all_devices(&mesh_devices.inner).await {
+            let device_id = device.lock().await.device.my_node_info.my_node_num;
+            devices.push((device_key, device_id));
+        }
+    }
+
+    for (device_key, device_id) in devices {
+        let conversations = match conversation_summaries(handle, device_id) {
+            Ok(conversations) => conversations,
+            Err(e) => {
+                warn!("Failed to count unread messages: {}", e);
+                continue;
+            }
+        };
+
+        let event = UnreadCountsChangedEvent {
+            api_version: EVENT_API_VERSION,
+            device_key,
+            conversations,
+        };
+
+        if let Err(e) = events::dispatch_unread_counts_changed(handle, event) {
+            warn!("Failed to dispatch unread counts: {}", e);
+        }
+    }
+}
+
+/// Periodically writes queued messages, keeping database writes off the packet path
+pub fn spawn_message_store_writer<R: tauri::Runtime>(handle: tauri::AppHandle<R>) {
+    trace!("Spawning message database writer");
+
+    tauri::async_runtime::spawn(async move {
+        let shutdown = shutdown_signal(&handle);
+        let mut interval = tokio::time::interval(MESSAGE_STORE_WRITE_INTERVAL);
+
+        while shutdown.tick(&mut interval).await {
+            let write_handle = handle.clone();
+            let result =
+                tauri::async_runtime::spawn_blocking(move || write_pending_messages(&write_handle))
+                    .await;
+
+            match result {
+                Ok(Ok(0)) => {}
+                Ok(Ok(count)) => {
+                    trace!("Wrote {} messages", count);
+                    dispatch_unread_counts(&handle).await;
+                }
+                Ok(Err(e)) => warn!("Failed to write message database: {}", e),
+                Err(e) => warn!("Message database writer failed: {}", e),
+            }
+        }
+
+        trace!("Stopped message database writer");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    const DEVICE: u32 = 1;
+
+    fn message(packet_id: u32, channel: u32, to: u32, timestamp: u32, text: &str) -> StoredMessage {
+        StoredMessage {
+            device_id: DEVICE,
+            packet_id,
+            channel,
+            from: 2,
+            to,
+            timestamp,
+            text: text.into(),
+            state: ChannelMessageState::Pending,
+            reactions: MessageReactions::new(),
+            device_key: Some("COM3".into()),
+        }
+    }
+
+    /// 300 broadcasts on channel 0, two per second, and a direct message every tenth
+    fn fixture() -> MessageStore {
+        let mut store = MessageStore::open_in_memory().unwrap();
+
+        let messages: Vec<StoredMessage> = (0..330)
+            .map(|i| match i % 11 {
+                10 => message(i, 0, 3, 1_000 + i / 2, "Direct check in"),
+                _ => message(i, 0, BROADCAST_ADDR, 1_000 + i / 2, &format!("Hello {}", i)),
+            })
+            .collect();
+
+        assert_eq!(store.write(&messages).unwrap(), 330);
+        store
+    }
+
+    fn packet_ids(messages: &[StoredMessage]) -> Vec<u32> {
+        messages.iter().map(|m| m.packet_id).collect()
+    }
+
+    #[test]
+    fn paginates_newest_first() {
+        let store = fixture();
+        let channel = MessageConversation::Channel(0);
+
+        let mut pages = vec![];
+        let mut before = None;
+
+        loop {
+            let page = store.get_messages(DEVICE, channel, before, 50).unwrap();
+
+            match page.last() {
+                Some(oldest) => before = Some(oldest.timestamp),
+                None => break,
+            }
+
+            pages.push(page);
+        }
+
+        // Only the last page is short, and pages never share a timestamp
+        let (last, full) = pages.split_last().unwrap();
+        assert!(full.iter().all(|page| page.len() >= 50));
+        assert!(last.len() < 50);
+
+        for pair in pages.windows(2) {
+            assert!(pair[0].last().unwrap().timestamp > pair[1][0].timestamp);
+        }
+
+        let messages: Vec<StoredMessage> = pages.concat();
+        assert!(messages
+            .windows(2)
+            .all(|pair| pair[0].timestamp >= pair[1].timestamp));
+        assert!(messages.iter().all(|m| m.to == BROADCAST_ADDR));
+        assert!(messages
+            .iter()
+            .all(|m| m.device_key.as_deref() == Some("COM3")));
+
+        // Every broadcast is returned exactly once
+        let mut seen = packet_ids(&messages);
+        seen.sort_unstable();
+        let expected: Vec<u32> = (0..330).filter(|i| i % 11 != 10).collect();
+        assert_eq!(seen, expected);
+
+        let direct = store
+            .get_messages(DEVICE, MessageConversation::Direct(3), None, 500)
+            .unwrap();
+        assert_eq!(direct.len(), 30);
+        assert_eq!(direct[0].packet_id, 329);
+
+        assert!(store.get_messages(DEVICE, channel, None, 0).is_err());
+        assert!(store.get_messages(2, channel, None, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn searches_message_text() {
+        let store = fixture();
+
+        let results = store.search_messages("check", 100).unwrap();
+        assert_eq!(results.len(), 30);
+        assert!(results.iter().all(|m| m.text == "Direct check in"));
+
+        let results = store.search_messages("hello 42", 100).unwrap();
+        assert_eq!(packet_ids(&results), vec![42]);
+
+        assert_eq!(store.search_messages("check", 5).unwrap().len(), 5);
+        assert!(store
+            .search_messages("\"unbalanced*", 10)
+            .unwrap()
+            .is_empty());
+        assert!(store.search_messages("   ", 10).is_err());
+    }
+
+    #[test]
+    fn updates_acknowledged_message_in_place() {
+        let mut store = fixture();
+        let original = message(7, 0, BROADCAST_ADDR, 1_003, "Hello 7");
+
+        let mut acked = original.clone();
+        acked.state = ChannelMessageState::Acknowledged;
+        acked.timestamp = 5_000;
+        acked.reactions.insert("👍".into(), vec![4]);
+
+        store.write(&[acked]).unwrap();
+
+        let page = store
+            .get_messages(DEVICE, MessageConversation::Channel(0), Some(1_004), 500)
+            .unwrap();
+        let stored: Vec<&StoredMessage> = page.iter().filter(|m| m.packet_id == 7).collect();
+
+        assert_eq!(stored.len(), 1);
+        assert!(matches!(stored[0].state, ChannelMessageState::Acknowledged));
+        assert_eq!(stored[0].reactions["👍"], vec![4]);
+        assert_eq!(stored[0].timestamp, original.timestamp);
+
+        assert_eq!(store.search_messages("hello 7", 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn caps_note_revisions() {
+        let mut store = MessageStore::open_in_memory().unwrap();
+
+        for i in 0..MAX_NOTE_REVISIONS + 5 {
+            store
+                .set_node_note(0x10, &format!("Revision {}", i), 1_000 + i)
+                .unwrap();
+        }
+        store.set_node_note(0x20, "Solar repeater", 900).unwrap();
+
+        let notes = store.get_node_notes(0x10).unwrap();
+        assert_eq!(notes.len(), MAX_NOTE_REVISIONS as usize);
+        assert_eq!(
+            notes[0].text,
+            format!("Revision {}", MAX_NOTE_REVISIONS + 4)
+        );
+        assert_eq!(notes.last().unwrap().text, "Revision 5");
+
+        // Other nodes' notes aren't pruned
+        assert_eq!(store.get_node_notes(0x20).unwrap().len(), 1);
+
+        let summary = store
+            .current_node_note(0x10)
+            .unwrap()
+            .and_then(|note| note.summary())
+            .unwrap();
+        assert_eq!(
+            summary.summary,
+            format!("Revision {}", MAX_NOTE_REVISIONS + 4)
+        );
+        assert!(!summary.truncated);
+
+        let long = format!("Antenna replaced 2024-03\n{}", "x".repeat(200));
+        let note = store.set_node_note(0x20, &long, 1_100).unwrap();
+        assert_eq!(note.summary().unwrap().summary, "Antenna replaced 2024-03");
+        assert!(note.summary().unwrap().truncated);
+
+        assert!(store
+            .set_node_note(0x20, &"x".repeat(MAX_NOTE_LEN + 1), 1_200)
+            .is_err());
+        assert_eq!(store.get_node_notes(0x20).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn searches_notes_with_messages() {
+        let mut store = fixture();
+
+        store
+            .set_node_note(0x10, "Antenna replaced, key under rock", 900)
+            .unwrap();
+        store
+            .set_node_note(0x20, "Check the solar panel", 2_000)
+            .unwrap();
+
+        let results = store.search_all("check", 100).unwrap();
+        assert_eq!(results.len(), 31);
+        assert!(matches!(&results[0], SearchResult::Note(note) if note.node_num == 0x20));
+        assert!(results[1..]
+            .iter()
+            .all(|result| matches!(result, SearchResult::Message(_))));
+
+        // Message search doesn't include notes
+        assert_eq!(store.search_messages("check", 100).unwrap().len(), 30);
+
+        let results = store.search_all("rock", 10).unwrap();
+        assert_eq!(
+            results,
+            vec![SearchResult::Note(NodeNote {
+                node_num: 0x10,
+                timestamp: 900,
+                text: "Antenna replaced, key under rock".into(),
+            })]
+        );
+
+        // Only the current revision is searchable, and a cleared note isn't at all
+        store.set_node_note(0x10, "Antenna replaced", 950).unwrap();
+        assert!(store.search_all("rock", 10).unwrap().is_empty());
+        assert_eq!(store.search_all("antenna", 10).unwrap().len(), 1);
+
+        store.set_node_note(0x10, "", 960).unwrap();
+        assert!(store.search_all("antenna", 10).unwrap().is_empty());
+        assert_eq!(
+            store.current_node_note(0x10).unwrap().unwrap().summary(),
+            None
+        );
+    }
+
+    #[test]
+    fn keeps_notes_across_restart() {
+        let path = std::env::temp_dir().join(format!(
+            "message-store-notes-{}.sqlite3",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let mut store = MessageStore::open(&path).unwrap();
+            store.set_node_note(0x10, "First visit", 1_000).unwrap();
+            store.set_node_note(0x10, "Key under rock", 2_000).unwrap();
+        }
+
+        let mut store = MessageStore::open(&path).unwrap();
+        let texts: Vec<String> = store
+            .get_node_notes(0x10)
+            .unwrap()
+            .into_iter()
+            .map(|note| note.text)
+            .collect();
+        assert_eq!(texts, vec!["Key under rock", "First visit"]);
+        assert_eq!(store.search_all("rock", 10).unwrap().len(), 1);
+
+        store.set_node_note(0x10, "Key moved", 3_000).unwrap();
+        assert!(store.search_all("rock", 10).unwrap().is_empty());
+
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn migrates_version_one_database() {
+        let path = std::env::temp_dir().join(format!(
+            "message-store-migration-{}.sqlite3",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let mut connection = Connection::open(&path).unwrap();
+            migrate(&mut connection, "message database", &MIGRATIONS[..1], None).unwrap();
+
+            // Rows as version one wrote them, without the port
+            for (packet_id, to, timestamp, text, state) in [
+                (6, BROADCAST_ADDR, 1_002, "Gate is open", "\"pending\""),
+                (7, 3, 1_003, "Direct check in", "\"acknowledged\""),
+            ] {
+                connection
+                    .execute(
+                        "INSERT INTO messages
+                            (device_id, packet_id, channel, from_node, to_node, timestamp,
+                                text, state, reactions)
+                            VALUES (?1, ?2, 0, 2, ?3, ?4, ?5, ?6, '{}')",
+                        params![DEVICE, packet_id, to, timestamp, text, state],
+                    )
+                    .unwrap();
+            }
+        }
+
+        let store = MessageStore::open(&path).unwrap();
+        assert_eq!(
+            migrations::schema_version(&store.connection).unwrap(),
+            Some(MESSAGE_STORE_SCHEMA_VERSION)
+        );
+
+        let index_count: u32 = store
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name = 'messages_by_channel'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(index_count, 1);
+
+        let messages = store.all_messages().unwrap();
+        assert_eq!(packet_ids(&messages), vec![6, 7]);
+        assert_eq!(messages[1].state, ChannelMessageState::Acknowledged);
+        assert!(messages.iter().all(|m| m.device_key.is_none()));
+
+        let channel = store
+            .get_messages(DEVICE, MessageConversation::Channel(0), None, 10)
+            .unwrap();
+        assert_eq!(packet_ids(&channel), vec![6]);
+        assert_eq!(
+            packet_ids(&store.search_messages("gate", 10).unwrap()),
+            vec![6]
+        );
+
+        let backup = migrations::backup_path(&path, 1);
+        assert!(backup.exists());
+
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&backup).unwrap();
+    }
+
+    #[test]
+    fn resends_unsent_messages_once_after_crash() {
+        let path = std::env::temp_dir().join(format!(
+            "message-store-outgoing-{}.sqlite3",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let (queued, in_flight) = {
+            let mut store = MessageStore::open(&path).unwrap();
+            let mut outgoing = OutgoingQueue::default();
+
+            let mut enqueue = |text: &str, destination: PacketDestination| {
+                let message = store
+                    .enqueue_outgoing(DEVICE, 0, &destination, text, 1_000)
+                    .unwrap();
+                outgoing.insert(&message);
+                message
+            };
+
+            let acked = enqueue("Acked", PacketDestination::Broadcast);
+            let failed = enqueue("Failed", PacketDestination::Broadcast);
+            let in_flight = enqueue("In flight", PacketDestination::Node(NodeId::new(3)));
+            let queued = enqueue("Queued", PacketDestination::Broadcast);
+
+            store.mark_outgoing_sent(acked.id, 100, 1_001).unwrap();
+            store.mark_outgoing_sent(failed.id, 101, 1_001).unwrap();
+            store.mark_outgoing_sent(in_flight.id, 102, 1_001).unwrap();
+
+            let completed = store
+                .complete_outgoing(DEVICE, 100, &ChannelMessageState::Acknowledged, 1_002)
+                .unwrap();
+            assert_eq!(completed, Some(acked.id));
+            store
+                .complete_outgoing(
+                    DEVICE,
+                    101,
+                    &ChannelMessageState::Error("Received NAK".into()),
+                    1_002,
+                )
+                .unwrap();
+
+            // Nothing is unsent while the rest are still being sent
+            assert!(outgoing.unsent(&store, DEVICE).unwrap().is_empty());
+
+            (queued, in_flight)
+        };
+
+        // The in-memory queue is lost with the crash, the journal isn't
+        let store = MessageStore::open(&path).unwrap();
+        let mut outgoing = OutgoingQueue::default();
+
+        let unsent = outgoing.unsent(&store, DEVICE).unwrap();
+        assert_eq!(
+            unsent.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![in_flight.id, queued.id]
+        );
+        assert_eq!(unsent[0].status, OutgoingStatus::InFlight);
+        assert_eq!(unsent[0].packet_id, Some(102));
+        assert!(matches!(
+            unsent[0].packet_destination(),
+            PacketDestination::Node(node_id) if node_id.id() == 3
+        ));
+        assert_eq!(unsent[1].status, OutgoingStatus::Queued);
+        assert!(matches!(
+            unsent[1].packet_destination(),
+            PacketDestination::Broadcast
+        ));
+
+        let all_ids: Vec<u32> = (1..=4).collect();
+        let claimed = outgoing.claim(&store, DEVICE, &all_ids).unwrap();
+        assert_eq!(claimed, unsent);
+
+        // Claiming again while they're being resent claims nothing
+        assert!(outgoing.claim(&store, DEVICE, &all_ids).unwrap().is_empty());
+
+        let mut store = store;
+        store.mark_outgoing_sent(in_flight.id, 200, 2_000).unwrap();
+        store.mark_outgoing_sent(queued.id, 201, 2_000).unwrap();
+
+        // A late ack for the first send no longer matches
+        assert_eq!(
+            store
+                .complete_outgoing(DEVICE, 102, &ChannelMessageState::Acknowledged, 2_001)
+                .unwrap(),
+            None
+        );
+
+        for packet_id in [200, 201] {
+            store
+                .complete_outgoing(DEVICE, packet_id, &ChannelMessageState::Acknowledged, 2_001)
+                .unwrap();
+        }
+
+        outgoing.clear_device(DEVICE);
+        assert!(store.incomplete_outgoing(DEVICE).unwrap().is_empty());
+        assert!(outgoing.claim(&store, DEVICE, &all_ids).unwrap().is_empty());
+
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn prunes_completed_outgoing_messages() {
+        let mut store = MessageStore::open_in_memory().unwrap();
+        let outgoing = OutgoingQueue::default();
+
+        let ids: Vec<u32> = (0..3)
+            .map(|i| {
+                store
+                    .enqueue_outgoing(DEVICE, 0, &PacketDestination::Broadcast, "Hi", 1_000 + i)
+                    .unwrap()
+                    .id
+            })
+            .collect();
+
+        store.mark_outgoing_sent(ids[0], 10, 1_000).unwrap();
+        store
+            .complete_outgoing(DEVICE, 10, &ChannelMessageState::Acknowledged, 1_000)
+            .unwrap();
+
+        // Incomplete messages are kept however old they are
+        assert_eq!(store.prune_outgoing(5_000).unwrap(), 1);
+        assert_eq!(outgoing.unsent(&store, DEVICE).unwrap().len(), 2);
+
+        // Only unsent messages can be discarded
+        assert_eq!(store.discard_outgoing(DEVICE + 1, &ids).unwrap(), 0);
+        assert_eq!(store.discard_outgoing(DEVICE, &ids[1..2]).unwrap(), 1);
+        assert_eq!(outgoing.unsent(&store, DEVICE).unwrap()[0].id, ids[2]);
+    }
+
+    #[test]
+    fn counts_unread_messages_across_restarts() {
+        let path = std::env::temp_dir().join(format!(
+            "message-store-unread-{}.sqlite3",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let own = |mut message: StoredMessage| {
+            message.from = DEVICE;
+            message
+        };
+
+        let unread = |store: &MessageStore| {
+            store
+                .conversation_summaries(DEVICE)
+                .unwrap()
+                .into_iter()
+                .map(|summary| (summary.conversation, summary.unread_count))
+                .collect::<Vec<_>>()
+        };
+
+        {
+            let mut store = MessageStore::open(&path).unwrap();
+
+            store
+                .write(&[
+                    message(1, 0, BROADCAST_ADDR, 100, "Net check"),
+                    message(2, 0, BROADCAST_ADDR, 101, "Roger"),
+                    message(3, 0, BROADCAST_ADDR, 101, "Copy"),
+                    own(message(4, 0, BROADCAST_ADDR, 102, "All good here")),
+                    message(5, 0, DEVICE, 103, "Are you at camp?"),
+                    own(message(6, 0, 2, 104, "Yes")),
+                    StoredMessage {
+                        from: 3,
+                        ..message(7, 1, BROADCAST_ADDR, 90, "Admin channel")
+                    },
+                ])
+                .unwrap();
+
+            // Our own messages are never unread
+            assert_eq!(
+                unread(&store),
+                [
+                    (MessageConversation::Direct(2), 1),
+                    (MessageConversation::Channel(0), 3),
+                    (MessageConversation::Channel(1), 1),
+                ]
+            );
+
+            // Up to a message sharing its timestamp with a later one
+            store
+                .mark_read(DEVICE, MessageConversation::Channel(0), 2)
+                .unwrap();
+            assert_eq!(unread(&store)[1], (MessageConversation::Channel(0), 1));
+
+            // Replying reads the conversation up to the reply
+            store
+                .mark_read(DEVICE, MessageConversation::Direct(2), 6)
+                .unwrap();
+            assert_eq!(unread(&store)[0], (MessageConversation::Direct(2), 0));
+
+            // A message of another conversation can't be marked
+            assert!(store
+                .mark_read(DEVICE, MessageConversation::Channel(0), 5)
+                .is_err());
+        }
+
+        let mut store = MessageStore::open(&path).unwrap();
+
+        assert_eq!(
+            unread(&store),
+            [
+                (MessageConversation::Direct(2), 0),
+                (MessageConversation::Channel(0), 1),
+                (MessageConversation::Channel(1), 1),
+            ]
+        );
+
+        // Marking an earlier message doesn't mark the later ones unread again
+        store
+            .mark_read(DEVICE, MessageConversation::Channel(0), 1)
+            .unwrap();
+        assert_eq!(unread(&store)[1], (MessageConversation::Channel(0), 1));
+
+        store
+            .write(&[
+                message(8, 0, BROADCAST_ADDR, 105, "Heading out"),
+                own(message(9, 0, BROADCAST_ADDR, 106, "Safe travels")),
+            ])
+            .unwrap();
+
+        let channel = &store.conversation_summaries(DEVICE).unwrap()[0];
+        assert_eq!(channel.conversation, MessageConversation::Channel(0));
+        assert_eq!(channel.last_message_at, 106);
+        assert_eq!(channel.unread_count, 2);
+
+        // Other devices' conversations are counted separately
+        assert!(store.conversation_summaries(DEVICE + 1).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+use meshtastic::protobufs;
+
+use super::MeshDevice;
+
+/// Hardware and firmware capabilities reported in a device's `DeviceMetadata`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceCapability {
+    Shutdown,
+    Wifi,
+    Bluetooth,
+    Ethernet,
+}
+
+impl DeviceCapability {
+    fn description(&self) -> &'static str {
+        match self {
+            DeviceCapability::Shutdown => "shutting down",
+            DeviceCapability::Wifi => "WiFi",
+            DeviceCapability::Bluetooth => "Bluetooth",
+            DeviceCapability::Ethernet => "Ethernet",
+        }
+    }
+}
+
+impl MeshDevice {
+    pub fn set_metadata(&mut self, metadata: protobufs::DeviceMetadata) {
+        self.metadata = Some(metadata);
+    }
+
+    /// Returns whether the device supports a capability. Older firmware doesn't
+    /// report metadata, in which case all capabilities are assumed to be supported.
+    pub fn supports(&self, capability: DeviceCapability) -> bool {
+        let metadata = match self.metadata.as_ref() {
+            Some(m) => m,
+            None => return true,
+        };
+
+        match capability {
+            DeviceCapability::Shutdown => metadata.can_shutdown,
+            DeviceCapability::Wifi => metadata.has_wifi,
+            DeviceCapability::Bluetooth => metadata.has_bluetooth,
+            DeviceCapability::Ethernet => metadata.has_ethernet,
+        }
+    }
+
+    pub fn require_capability(&self, capability: DeviceCapability) -> Result<(), String> {
+        if self.supports(capability) {
+            return Ok(());
+        }
+
+        Err(format!(
+            "Device does not support {}",
+            capability.description()
+        ))
+    }
+
+    /// Rejects config updates that enable hardware the device doesn't have
+    pub fn check_config_supported(&self, config: &protobufs::Config) -> Result<(), String> {
+        match config.payload_variant.as_ref() {
+            Some(protobufs::config::PayloadVariant::Network(network)) => {
+                if network.wifi_enabled {
+                    self.require_capability(DeviceCapability::Wifi)?;
+                }
+
+                if network.eth_enabled {
+                    self.require_capability(DeviceCapability::Ethernet)?;
+                }
+            }
+            Some(protobufs::config::PayloadVariant::Bluetooth(bluetooth)) => {
+                if bluetooth.enabled {
+                    self.require_capability(DeviceCapability::Bluetooth)?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device_with_metadata(metadata: protobufs::DeviceMetadata) -> MeshDevice {
+        let mut device = MeshDevice::new();
+        device.set_metadata(metadata);
+        device
+    }
+
+    fn wifi_config() -> protobufs::Config {
+        protobufs::Config {
+            payload_variant: Some(protobufs::config::PayloadVariant::Network(
+                protobufs::config::NetworkConfig {
+                    wifi_enabled: true,
+                    ..Default::default()
+                },
+            )),
+        }
+    }
+
+    #[test]
+    fn assumes_all_capabilities_without_metadata() {
+        let device = MeshDevice::new();
+
+        assert!(device.supports(DeviceCapability::Shutdown));
+        assert!(device.supports(DeviceCapability::Wifi));
+        assert!(device.supports(DeviceCapability::Bluetooth));
+        assert!(device.supports(DeviceCapability::Ethernet));
+        assert!(device.check_config_supported(&wifi_config()).is_ok());
+    }
+
+    #[test]
+    fn uses_reported_capabilities() {
+        let device = device_with_metadata(protobufs::DeviceMetadata {
+            firmware_version: "2.2.0".into(),
+            can_shutdown: true,
+            has_bluetooth: true,
+            ..Default::default()
+        });
+
+        assert!(device.supports(DeviceCapability::Shutdown));
+        assert!(device.supports(DeviceCapability::Bluetooth));
+        assert!(!device.supports(DeviceCapability::Wifi));
+        assert!(!device.supports(DeviceCapability::Ethernet));
+    }
+
+    #[test]
+    fn rejects_shutdown_when_unsupported() {
+        let device = device_with_metadata(protobufs::DeviceMetadata::default());
+
+        assert_eq!(
+            device.require_capability(DeviceCapability::Shutdown),
+            Err("Device does not support shutting down".into())
+        );
+    }
+
+    #[test]
+    fn rejects_config_for_missing_hardware() {
+        let device = device_with_metadata(protobufs::DeviceMetadata {
+            has_ethernet: true,
+            ..Default::default()
+        });
+
+        assert!(device.check_config_supported(&wifi_config()).is_err());
+        assert!(device
+            .check_config_supported(&protobufs::Config::default())
+            .is_ok());
+    }
+}
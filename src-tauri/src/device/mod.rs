@@ -3,15 +3,39 @@
 use meshtastic::protobufs;
 use meshtastic::ts::specta::{self, Type};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::connection::serial_lines::SerialLineControl;
+
+use self::config_progress::ConfigurationProgress;
+use self::liveness::NodeLivenessTracker;
+use self::range_test::RangeTestSample;
+use self::reactions::{MessageReactions, ReactionPacket};
+use self::remote_hardware::GpioReading;
 
 use self::helpers::{
     convert_location_field_to_protos, generate_rand_id, get_current_time_u32,
     normalize_location_field,
 };
 
+pub mod canned_messages;
+pub mod channel_config;
+pub mod clock;
+pub mod config_cache;
+pub mod config_progress;
+pub mod fixed_position;
 pub mod helpers;
+pub mod liveness;
+pub mod logs;
+pub mod message_store;
+pub mod metadata;
+pub mod node_db;
+pub mod node_details;
+pub mod range_test;
+pub mod reactions;
+pub mod remote_hardware;
 pub mod state;
+pub mod telemetry_store;
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Type)]
 #[serde(rename_all = "camelCase")]
@@ -19,10 +43,11 @@ pub enum SerialDeviceStatus {
     Restarting,   // unused
     Disconnected, // no attempt or failure to connect
     Connecting,   // connection initialized, not yet configured
-    Reconnecting, // unused
+    Reconnecting, // connection lost, retrying until configured again
     Connected,    // successful serial connection and device configuration, UI notified
     Configuring,  // configuration in process
     Configured,   // configured but UI not yet notified
+    Unresponsive, // connected, but the radio stopped answering heartbeats
 }
 
 impl Default for SerialDeviceStatus {
@@ -39,6 +64,12 @@ pub struct MeshChannel {
     pub messages: Vec<ChannelMessageWithState>,
 }
 
+/// Samples of each kind of metrics kept per node, oldest evicted first
+pub const MAX_NODE_METRICS_HISTORY: usize = 100;
+
+/// Device metrics older than this are reported as stale, unless a window is given
+pub const DEFAULT_METRICS_STALE_AFTER_SECS: u32 = 60 * 60;
+
 #[derive(Clone, Debug, Serialize, Deserialize, Type)]
 #[serde(rename_all = "camelCase")]
 pub struct MeshNodeDeviceMetrics {
@@ -48,6 +79,16 @@ pub struct MeshNodeDeviceMetrics {
     // channel: u32,
 }
 
+/// A node's device metrics history, oldest first. `stale` is set when the latest
+/// sample is older than the requested window, or there's no sample at all.
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeMetricsHistory {
+    pub node_num: u32,
+    pub samples: Vec<MeshNodeDeviceMetrics>,
+    pub stale: bool,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, Type)]
 #[serde(rename_all = "camelCase")]
 pub struct MeshNodeEnvironmentMetrics {
@@ -97,6 +138,66 @@ impl MeshNode {
         }
     }
 
+    /// Most recently reported battery level, if the node has reported device metrics
+    pub fn battery_level(&self) -> Option<u32> {
+        self.latest_device_metrics()
+            .map(|metrics| metrics.battery_level)
+    }
+
+    pub fn latest_device_metrics(&self) -> Option<&protobufs::DeviceMetrics> {
+        self.device_metrics.last().map(|metrics| &metrics.metrics)
+    }
+
+    pub fn push_device_metrics(&mut self, metrics: protobufs::DeviceMetrics, snr: f32) {
+        if self.device_metrics.len() >= MAX_NODE_METRICS_HISTORY {
+            self.device_metrics.remove(0);
+        }
+
+        self.device_metrics.push(MeshNodeDeviceMetrics {
+            metrics,
+            timestamp: get_current_time_u32(),
+            snr,
+        });
+    }
+
+    pub fn push_environment_metrics(&mut self, metrics: protobufs::EnvironmentMetrics, snr: f32) {
+        if self.environment_metrics.len() >= MAX_NODE_METRICS_HISTORY {
+            self.environment_metrics.remove(0);
+        }
+
+        self.environment_metrics.push(MeshNodeEnvironmentMetrics {
+            metrics,
+            timestamp: get_current_time_u32(),
+            snr,
+        });
+    }
+
+    pub fn device_metrics_history(&self, now: u32, stale_after_secs: u32) -> NodeMetricsHistory {
+        let stale = match self.device_metrics.last() {
+            Some(latest) => now.saturating_sub(latest.timestamp) > stale_after_secs,
+            None => true,
+        };
+
+        NodeMetricsHistory {
+            node_num: self.node_num,
+            samples: self.device_metrics.clone(),
+            stale,
+        }
+    }
+
+    pub fn latest_environment_metrics(&self) -> Option<&protobufs::EnvironmentMetrics> {
+        self.environment_metrics
+            .last()
+            .map(|metrics| &metrics.metrics)
+    }
+
+    /// Most recent position with usable coordinates
+    pub fn last_known_position(&self) -> Option<&NormalizedPosition> {
+        self.position_metrics
+            .last()
+            .filter(|position| position.latitude != 0.0 && position.longitude != 0.0)
+    }
+
     pub fn update_from_node_info(&mut self, node_info: protobufs::NodeInfo) {
         self.last_heard = Some(LastHeardMetadata {
             timestamp: get_current_time_u32(),
@@ -109,11 +210,7 @@ impl MeshNode {
         }
 
         if let Some(device_metrics) = node_info.device_metrics {
-            self.device_metrics.push(MeshNodeDeviceMetrics {
-                metrics: device_metrics,
-                timestamp: get_current_time_u32(),
-                snr: node_info.snr,
-            });
+            self.push_device_metrics(device_metrics, node_info.snr);
         }
 
         if let Some(position) = node_info.position {
@@ -240,6 +337,14 @@ pub struct NeighborInfoPacket {
 pub struct TextPacket {
     pub packet: protobufs::MeshPacket,
     pub data: String,
+    pub reactions: MessageReactions,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct RangeTestPacket {
+    pub packet: protobufs::MeshPacket,
+    pub data: String,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Type)]
@@ -276,6 +381,34 @@ pub struct NormalizedWaypoint {
 
     /// Icon for the waypoint in the form of a unicode emoji
     pub icon: u32,
+
+    /// Node the waypoint was received from, unset for waypoints that weren't received
+    #[serde(default)]
+    pub sender: Option<u32>,
+}
+
+impl NormalizedWaypoint {
+    /// Whether the waypoint has expired as of `now`. Waypoints with an expire time of 0
+    /// never expire.
+    pub fn is_expired(&self, now: u32) -> bool {
+        self.expire != 0 && self.expire <= now
+    }
+
+    /// Checks the waypoint can be sent as of `now`
+    pub fn validate(&self, now: u32) -> Result<(), String> {
+        if !(-90.0..=90.0).contains(&self.latitude) || !(-180.0..=180.0).contains(&self.longitude) {
+            return Err(format!(
+                "Waypoint \"{}\" has invalid coordinates {}, {}",
+                self.name, self.latitude, self.longitude
+            ));
+        }
+
+        if self.is_expired(now) {
+            return Err(format!("Waypoint \"{}\" has already expired", self.name));
+        }
+
+        Ok(())
+    }
 }
 
 impl From<protobufs::Waypoint> for NormalizedWaypoint {
@@ -290,6 +423,7 @@ impl From<protobufs::Waypoint> for NormalizedWaypoint {
             name: waypoint.name,
             description: waypoint.description,
             icon: waypoint.icon,
+            sender: None,
         }
     }
 }
@@ -318,7 +452,7 @@ pub enum ChannelMessagePayload {
     Waypoint(WaypointPacket),
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
 #[serde(rename_all = "camelCase")]
 pub enum ChannelMessageState {
     Pending,
@@ -333,6 +467,30 @@ pub struct ChannelMessageWithState {
     pub state: ChannelMessageState,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelMessageHistory {
+    pub channel: u32,
+    pub channel_name: String, // resolved from the channel table, "channel #N" if unknown
+    pub messages: Vec<ChannelMessageWithState>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct RadioQueueStatus {
+    /// Free slots remaining in the radio's TX queue
+    pub free: u32,
+
+    /// Total number of slots in the radio's TX queue
+    pub maxlen: u32,
+
+    /// Id of the mesh packet this status was reported for, if any
+    pub mesh_packet_id: u32,
+
+    /// Time the status was received, in seconds since epoch
+    pub timestamp: u32,
+}
+
 // TODO can't deserialize `SerialConnection`
 #[derive(Clone, Debug, Default, Serialize, Type)]
 #[serde(rename_all = "camelCase")]
@@ -341,15 +499,30 @@ pub struct MeshDevice {
     pub ready: bool,                // is device configured to participate in mesh
     pub status: SerialDeviceStatus, // current config status of device
     pub channels: HashMap<u32, MeshChannel>, // channels device is able to access
+    pub unknown_channel_messages: HashMap<u32, Vec<ChannelMessageWithState>>, // messages on channels missing from `channels` (e.g. hash-only channels), by channel index
     pub config: protobufs::LocalConfig, // local-only device configuration
     pub module_config: protobufs::LocalModuleConfig, // configuration for meshtastic modules
     pub my_node_info: protobufs::MyNodeInfo, // debug information specific to device
-    pub nodes: HashMap<u32, MeshNode>, // network devices this device has communicated with
-    pub region_unset: bool,         // flag for whether device has an unset LoRa region
+    pub nodes: HashMap<u32, MeshNode>,  // network devices this device has communicated with
+    pub region_unset: bool,             // flag for whether device has an unset LoRa region
     pub device_metrics: protobufs::DeviceMetrics, // information about functioning of device (e.g. battery level)
     pub waypoints: HashMap<u32, NormalizedWaypoint>, // updatable GPS positions managed by this device
     pub neighbors: HashMap<u32, NeighborInfoPacket>, //updated packets from each node containing their neighbors
     pub config_in_progress: bool, // flag for whether the user has started a configuration transaction
+    pub queue_status: Option<RadioQueueStatus>, // latest TX queue status reported by the radio
+    pub canned_messages: Option<Vec<String>>, // cached canned message module messages, if fetched
+    pub range_tests: HashMap<u32, Vec<RangeTestSample>>, // range test packets received from each sender
+    pub gpio_readings: HashMap<u32, GpioReading>, // latest remote hardware GPIO values for each node
+    pub serial_line_control: Option<SerialLineControl>, // DTR/RTS behavior applied on connect, serial only
+    #[serde(skip)]
+    pub pending_reactions: Vec<ReactionPacket>, // reactions to messages that haven't been received yet
+    pub clock_skew_secs: Option<i64>, // device clock offset from host clock, if skewed beyond the threshold
+    pub metadata: Option<protobufs::DeviceMetadata>, // firmware version and hardware capabilities, if reported
+    pub node_liveness: NodeLivenessTracker, // online/offline state of each node, updated on transitions
+    pub config_progress: ConfigurationProgress, // packets received so far during the configuration flow
+    #[serde(skip)]
+    pub node_db_nums: HashSet<u32>, // nodes in the radio's node database, as streamed during configuration
+    pub historical_nodes: BTreeSet<u32>, // persisted nodes the radio no longer knows, until they're heard again
 }
 
 impl MeshDevice {
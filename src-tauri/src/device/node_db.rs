@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+
+use meshtastic::protobufs;
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::graph::ds::node::GraphNode;
+
+use super::{MeshDevice, MeshNode};
+
+/// Part of a node's record that the radio's node database changed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum NodeRecordField {
+    /// Long or short name
+    Name,
+
+    /// Node id, MAC address, hardware model or anything else about the user
+    Identity,
+
+    Position,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeRecordUpdate {
+    pub node_num: u32,
+    pub fields: Vec<NodeRecordField>,
+}
+
+/// Outcome of reconciling the radio's node database with the nodes persisted for it,
+/// each list sorted by node num
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconcileReport {
+    /// Persisted nodes the radio reported differently, whose records are rewritten
+    pub updated: Vec<NodeRecordUpdate>,
+
+    /// Persisted nodes the radio no longer knows, kept as historical entries and
+    /// candidates for archival
+    pub device_missing: Vec<u32>,
+
+    /// Nodes the radio knows that weren't persisted yet
+    pub store_missing: Vec<u32>,
+}
+
+/// A node as persisted in the graph database
+#[derive(Clone, Debug)]
+pub struct StoredNode {
+    pub graph_node: GraphNode,
+    pub info: Option<MeshNode>, // `None` if the device hadn't heard from the node
+}
+
+/// The nodes persisted for a device, read before its node database is reconciled
+#[derive(Clone, Debug, Default)]
+pub struct PersistenceHandle {
+    nodes: HashMap<u32, StoredNode>,
+}
+
+impl PersistenceHandle {
+    pub fn new(nodes: HashMap<u32, StoredNode>) -> Self {
+        Self { nodes }
+    }
+
+    pub fn graph_node(&self, node_num: u32) -> Option<GraphNode> {
+        self.nodes.get(&node_num).map(|node| node.graph_node)
+    }
+}
+
+fn user_fields(stored: &protobufs::User, reported: &protobufs::User) -> Vec<NodeRecordField> {
+    let mut fields = vec![];
+
+    if stored.long_name != reported.long_name || stored.short_name != reported.short_name {
+        fields.push(NodeRecordField::Name);
+    }
+
+    let renamed = protobufs::User {
+        long_name: reported.long_name.clone(),
+        short_name: reported.short_name.clone(),
+        ..stored.clone()
+    };
+
+    if renamed != *reported {
+        fields.push(NodeRecordField::Identity);
+    }
+
+    fields
+}
+
+/// Fields of the persisted record that differ from the node as the radio reported it.
+/// Anything the radio didn't report is kept from the persisted record, so isn't a change.
+fn changed_fields(stored: Option<&MeshNode>, reported: &MeshNode) -> Vec<NodeRecordField> {
+    let mut fields = match (stored.and_then(|node| node.user.as_ref()), &reported.user) {
+        (Some(stored), Some(reported)) => user_fields(stored, reported),
+        (None, Some(_)) => vec![NodeRecordField::Name, NodeRecordField::Identity],
+        (_, None) => vec![],
+    };
+
+    let position = |node: &MeshNode| {
+        node.last_known_position()
+            .map(|p| (p.latitude, p.longitude, p.altitude))
+    };
+
+    let reported_position = position(reported);
+
+    if reported_position.is_some() && stored.and_then(position) != reported_position {
+        fields.push(NodeRecordField::Position);
+    }
+
+    fields
+}
+
+impl MeshDevice {
+    /// Compares the node database the radio streamed during configuration against the
+    /// nodes persisted for it. Persisted records are rewritten from the device's nodes
+    /// once the graph is next written, so the device's records are left as they are.
+    /// Persisted nodes the radio no longer knows are added back to the device as
+    /// historical nodes.
+    pub fn reconcile_node_db(&mut self, store: &PersistenceHandle) -> ReconcileReport {
+        let mut report = ReconcileReport::default();
+
+        let mut reported: Vec<u32> = self.node_db_nums.iter().copied().collect();
+        reported.sort_unstable();
+
+        for node_num in reported {
+            self.historical_nodes.remove(&node_num);
+
+            let node = match self.nodes.get(&node_num) {
+                Some(node) => node,
+                None => continue,
+            };
+
+            match store.nodes.get(&node_num) {
+                Some(stored) => {
+                    let fields = changed_fields(stored.info.as_ref(), node);
+
+                    if !fields.is_empty() {
+                        report.updated.push(NodeRecordUpdate { node_num, fields });
+                    }
+                }
+                None => report.store_missing.push(node_num),
+            }
+        }
+
+        let mut persisted_only: Vec<(&u32, &StoredNode)> = store
+            .nodes
+            .iter()
+            .filter(|(node_num, _)| !self.node_db_nums.contains(node_num))
+            .collect();
+        persisted_only.sort_unstable_by_key(|(node_num, _)| **node_num);
+
+        for (node_num, stored) in persisted_only {
+            if let Some(info) = &stored.info {
+                self.nodes.entry(*node_num).or_insert_with(|| info.clone());
+            }
+
+            self.historical_nodes.insert(*node_num);
+            report.device_missing.push(*node_num);
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::device::NormalizedPosition;
+
+    fn user(long_name: &str, macaddr: u8) -> protobufs::User {
+        protobufs::User {
+            id: format!("!{:08x}", macaddr),
+            long_name: long_name.into(),
+            short_name: long_name.chars().take(4).collect(),
+            macaddr: vec![macaddr; 6],
+            ..Default::default()
+        }
+    }
+
+    fn mesh_node(node_num: u32, long_name: &str, macaddr: u8, latitude: f32) -> MeshNode {
+        let mut node = MeshNode::new(node_num);
+        node.user = Some(user(long_name, macaddr));
+        node.position_metrics.push(NormalizedPosition {
+            latitude,
+            longitude: 13.4,
+            ..Default::default()
+        });
+        node
+    }
+
+    fn stored(node: MeshNode) -> (u32, StoredNode) {
+        (
+            node.node_num,
+            StoredNode {
+                graph_node: GraphNode {
+                    node_num: node.node_num,
+                    last_heard: DateTime::from_timestamp(1_700_000_000, 0)
+                        .unwrap()
+                        .naive_utc(),
+                    timeout_duration: Duration::from_secs(900),
+                },
+                info: Some(node),
+            },
+        )
+    }
+
+    #[test]
+    fn reconciles_each_category() {
+        let store = PersistenceHandle::new(HashMap::from([
+            stored(mesh_node(1, "Base", 1, 52.5)),
+            stored(mesh_node(2, "Hilltop", 2, 52.6)),
+            stored(mesh_node(3, "Relay", 3, 52.7)),
+            stored(mesh_node(4, "Van", 4, 52.8)),
+            stored(mesh_node(5, "Old repeater", 5, 52.9)),
+        ]));
+
+        let mut device = MeshDevice::new();
+
+        // The radio's node database, as streamed during configuration
+        for node in [
+            mesh_node(1, "Base", 1, 52.5),
+            mesh_node(2, "Hilltop North", 2, 52.6),
+            mesh_node(3, "Relay", 30, 52.75),
+            mesh_node(4, "Van", 4, 52.8),
+            mesh_node(6, "New handheld", 6, 53.0),
+        ] {
+            device.node_db_nums.insert(node.node_num);
+            device.nodes.insert(node.node_num, node);
+        }
+
+        // Without a position from the radio, the persisted one isn't a change
+        device.nodes.get_mut(&4).unwrap().position_metrics.clear();
+
+        let report = device.reconcile_node_db(&store);
+
+        assert_eq!(
+            report,
+            ReconcileReport {
+                updated: vec![
+                    NodeRecordUpdate {
+                        node_num: 2,
+                        fields: vec![NodeRecordField::Name],
+                    },
+                    NodeRecordUpdate {
+                        node_num: 3,
+                        fields: vec![NodeRecordField::Identity, NodeRecordField::Position],
+                    },
+                ],
+                device_missing: vec![5],
+                store_missing: vec![6],
+            }
+        );
+
+        // The device keeps the radio's records, and the persisted-only node as historical
+        assert_eq!(
+            device.nodes[&2].user.as_ref().unwrap().long_name,
+            "Hilltop North"
+        );
+        assert_eq!(
+            device.nodes[&5].user.as_ref().unwrap().long_name,
+            "Old repeater"
+        );
+        assert_eq!(
+            device.historical_nodes.iter().copied().collect::<Vec<_>>(),
+            vec![5]
+        );
+        assert!(store.graph_node(5).is_some());
+
+        // Once the radio reports the node again, it's no longer historical
+        device.node_db_nums.insert(5);
+        let report = device.reconcile_node_db(&store);
+
+        assert!(report.device_missing.is_empty());
+        assert!(device.historical_nodes.is_empty());
+    }
+}
@@ -0,0 +1,433 @@
+use meshtastic::protobufs::{self, config::device_config::Role};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::graph::ds::{graph::MeshGraph, link_quality::LinkQualityAggregate};
+
+use super::message_store::NodeNoteSummary;
+use super::{liveness::NodeLiveness, ChannelMessagePayload, MeshDevice, MeshNode};
+
+/// Most recent telemetry samples included in each sparkline series
+pub const SPARKLINE_POINTS: usize = 48;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeIdentity {
+    pub long_name: Option<String>,
+    pub short_name: Option<String>,
+    pub hardware: Option<String>,
+    pub firmware: Option<String>, // only reported by the connected node
+    pub role: Option<String>,     // only reported by the connected node
+    pub is_self: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodePositionDetails {
+    pub latitude: f32,
+    pub longitude: f32,
+    pub altitude: i32,
+    pub age_secs: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TelemetryPoint {
+    pub timestamp: u32, // secs
+    pub value: f32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeTelemetryDetails {
+    pub device_metrics: Option<protobufs::DeviceMetrics>,
+    pub environment_metrics: Option<protobufs::EnvironmentMetrics>,
+    pub battery_level: Vec<TelemetryPoint>,
+    pub voltage: Vec<TelemetryPoint>,
+    pub channel_utilization: Vec<TelemetryPoint>,
+    pub air_util_tx: Vec<TelemetryPoint>,
+    pub temperature: Vec<TelemetryPoint>,
+    pub relative_humidity: Vec<TelemetryPoint>,
+    pub barometric_pressure: Vec<TelemetryPoint>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeGraphMetrics {
+    pub degree: u32,
+    pub weighted_degree: f64,
+    pub hops: Option<u32>, // fewest hops from the connected node through the graph
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeNeighbor {
+    pub node_num: u32,
+    pub display_label: Option<String>,
+    pub snr: f64, // best SNR of the edges between the two nodes
+    pub link_quality: Option<LinkQualityAggregate>, // over all samples kept for the link
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeMessageCounts {
+    pub from_node: u32, // messages the node sent that we received, broadcast or direct
+    pub to_node: u32,   // messages we sent directly to the node
+}
+
+/// Everything known about a node, for the node detail panel. Parts that haven't been
+/// reported or don't apply to the node are `None`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeDetails {
+    pub node_num: u32,
+    pub id: String,
+    pub display_label: Option<String>, // operator's label, falling back to the long name
+    pub identity: Option<NodeIdentity>,
+    pub position: Option<NodePositionDetails>,
+    pub last_heard: Option<u32>, // secs
+    pub liveness: Option<NodeLiveness>,
+    pub telemetry: Option<NodeTelemetryDetails>,
+    pub graph: Option<NodeGraphMetrics>,
+    pub neighbors: Option<Vec<NodeNeighbor>>,
+    pub messages: NodeMessageCounts,
+    pub note: Option<NodeNoteSummary>, // operator's current note
+}
+
+fn series<T>(
+    samples: &[T],
+    timestamp: impl Fn(&T) -> u32,
+    value: impl Fn(&T) -> f32,
+) -> Vec<TelemetryPoint> {
+    let start = samples.len().saturating_sub(SPARKLINE_POINTS);
+
+    samples[start..]
+        .iter()
+        .map(|sample| TelemetryPoint {
+            timestamp: timestamp(sample),
+            value: value(sample),
+        })
+        .collect()
+}
+
+fn identity(device: &MeshDevice, node: &MeshNode) -> Option<NodeIdentity> {
+    let is_self = node.node_num == device.my_node_info.my_node_num;
+    let user = node.user.as_ref();
+
+    if user.is_none() && !is_self {
+        return None;
+    }
+
+    Some(NodeIdentity {
+        long_name: user.map(|u| u.long_name.clone()),
+        short_name: user.map(|u| u.short_name.clone()),
+        hardware: user
+            .and_then(|u| protobufs::HardwareModel::from_i32(u.hw_model))
+            .map(|model| model.as_str_name().to_string()),
+        firmware: device
+            .metadata
+            .as_ref()
+            .filter(|_| is_self)
+            .map(|metadata| metadata.firmware_version.clone()),
+        role: device
+            .config
+            .device
+            .as_ref()
+            .filter(|_| is_self)
+            .and_then(|config| Role::from_i32(config.role))
+            .map(|role| role.as_str_name().to_string()),
+        is_self,
+    })
+}
+
+fn telemetry(node: &MeshNode) -> Option<NodeTelemetryDetails> {
+    if node.device_metrics.is_empty() && node.environment_metrics.is_empty() {
+        return None;
+    }
+
+    let device = &node.device_metrics;
+    let environment = &node.environment_metrics;
+
+    Some(NodeTelemetryDetails {
+        device_metrics: node.latest_device_metrics().cloned(),
+        environment_metrics: node.latest_environment_metrics().cloned(),
+        battery_level: series(device, |m| m.timestamp, |m| m.metrics.battery_level as f32),
+        voltage: series(device, |m| m.timestamp, |m| m.metrics.voltage),
+        channel_utilization: series(device, |m| m.timestamp, |m| m.metrics.channel_utilization),
+        air_util_tx: series(device, |m| m.timestamp, |m| m.metrics.air_util_tx),
+        temperature: series(environment, |m| m.timestamp, |m| m.metrics.temperature),
+        relative_humidity: series(
+            environment,
+            |m| m.timestamp,
+            |m| m.metrics.relative_humidity,
+        ),
+        barometric_pressure: series(
+            environment,
+            |m| m.timestamp,
+            |m| m.metrics.barometric_pressure,
+        ),
+    })
+}
+
+fn message_counts(device: &MeshDevice, node_num: u32) -> NodeMessageCounts {
+    let my_node_num = device.my_node_info.my_node_num;
+    let mut counts = NodeMessageCounts::default();
+
+    let packets = device
+        .channels
+        .values()
+        .map(|channel| &channel.messages)
+        .chain(device.unknown_channel_messages.values())
+        .flat_map(|messages| messages.iter())
+        .map(|message| match &message.payload {
+            ChannelMessagePayload::Text(text) => &text.packet,
+            ChannelMessagePayload::Waypoint(waypoint) => &waypoint.packet,
+        });
+
+    for packet in packets {
+        if packet.from == node_num && node_num != my_node_num {
+            counts.from_node += 1;
+        } else if packet.from == my_node_num && packet.to == node_num {
+            counts.to_node += 1;
+        }
+    }
+
+    counts
+}
+
+impl NodeDetails {
+    /// Details known to the device, without anything derived from the graph
+    pub fn from_device(device: &MeshDevice, node_num: u32, now: u32) -> Self {
+        let node = device.nodes.get(&node_num);
+
+        Self {
+            node_num,
+            id: format!("!{:08x}", node_num),
+            display_label: None,
+            identity: node.and_then(|node| identity(device, node)),
+            position: node
+                .and_then(|node| node.last_known_position())
+                .map(|position| {
+                    let reported_at = match position.time {
+                        0 => position.timestamp,
+                        time => time,
+                    };
+
+                    NodePositionDetails {
+                        latitude: position.latitude,
+                        longitude: position.longitude,
+                        altitude: position.altitude,
+                        age_secs: now.saturating_sub(reported_at),
+                    }
+                }),
+            last_heard: node.and_then(|node| node.last_heard.as_ref().map(|heard| heard.timestamp)),
+            liveness: device.node_liveness.status(node_num),
+            telemetry: node.and_then(telemetry),
+            graph: None,
+            neighbors: None,
+            messages: message_counts(device, node_num),
+            note: None,
+        }
+    }
+
+    /// Adds the node's label, metrics and neighbors from the graph, if it's in the graph.
+    /// Hidden neighbors are left out, as they are from the map.
+    pub fn add_graph_details(&mut self, graph: &MeshGraph, my_node_num: u32) {
+        let long_name = self
+            .identity
+            .as_ref()
+            .and_then(|identity| identity.long_name.as_deref());
+
+        self.display_label = graph.display_label(self.node_num, long_name);
+
+        if !graph.contains_node(self.node_num) {
+            return;
+        }
+
+        let (degree, weighted_degree) = graph
+            .node_degrees()
+            .get(&self.node_num)
+            .copied()
+            .unwrap_or_default();
+
+        self.graph = Some(NodeGraphMetrics {
+            degree,
+            weighted_degree,
+            hops: graph
+                .shortest_path(my_node_num, self.node_num)
+                .map(|path| (path.len() - 1) as u32),
+        });
+
+        let mut neighbors: Vec<NodeNeighbor> = vec![];
+
+        for (from, to, edge) in graph.edges() {
+            let neighbor_num = match (from.node_num, to.node_num) {
+                (from, to) if from == self.node_num => to,
+                (from, to) if to == self.node_num => from,
+                _ => continue,
+            };
+
+            if graph.overrides.is_hidden(neighbor_num) {
+                continue;
+            }
+
+            match neighbors.iter_mut().find(|n| n.node_num == neighbor_num) {
+                Some(neighbor) => neighbor.snr = neighbor.snr.max(edge.snr()),
+                None => neighbors.push(NodeNeighbor {
+                    node_num: neighbor_num,
+                    display_label: graph.display_label(neighbor_num, None),
+                    snr: edge.snr(),
+                    link_quality: graph
+                        .get_link_quality_report(self.node_num, neighbor_num, 0, None)
+                        .aggregate,
+                }),
+            }
+        }
+
+        neighbors.sort_by_key(|neighbor| neighbor.node_num);
+        self.neighbors = Some(neighbors);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::reactions::MessageReactions;
+    use crate::device::{ChannelMessageState, ChannelMessageWithState, MeshChannel, TextPacket};
+    use crate::graph::ds::fixtures::graph_node;
+    use crate::graph::ds::{edge::GraphEdge, link_quality::LinkQualitySample};
+
+    const NOW: u32 = 1_700_000_000;
+
+    fn text(from: u32, to: u32) -> ChannelMessageWithState {
+        ChannelMessageWithState {
+            payload: ChannelMessagePayload::Text(TextPacket {
+                packet: protobufs::MeshPacket {
+                    from,
+                    to,
+                    ..Default::default()
+                },
+                data: "hi".into(),
+                reactions: MessageReactions::new(),
+            }),
+            state: ChannelMessageState::Acknowledged,
+        }
+    }
+
+    #[test]
+    fn details_of_fully_populated_node() {
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 1;
+
+        let mut node = MeshNode::new(2);
+        node.update_from_node_info(protobufs::NodeInfo {
+            num: 2,
+            snr: 6.0,
+            user: Some(protobufs::User {
+                long_name: "Hilltop".into(),
+                short_name: "HT".into(),
+                hw_model: protobufs::HardwareModel::Tbeam as i32,
+                ..Default::default()
+            }),
+            position: Some(protobufs::Position {
+                latitude_i: 476_062_000,
+                longitude_i: -1_223_321_000,
+                time: NOW - 90,
+                ..Default::default()
+            }),
+            device_metrics: Some(protobufs::DeviceMetrics {
+                battery_level: 75,
+                voltage: 3.9,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        device.nodes.insert(2, node);
+
+        device.add_channel(MeshChannel {
+            config: protobufs::Channel::default(),
+            last_interaction: NOW,
+            messages: vec![text(2, 0xffff_ffff), text(2, 1), text(1, 2), text(3, 1)],
+        });
+
+        let mut graph = MeshGraph::new();
+        for node_num in [1, 2, 3] {
+            graph.upsert_node(graph_node(node_num));
+        }
+        graph.upsert_edge(graph_node(1), graph_node(2), GraphEdge::manual(1, 2, 4.0));
+        graph.upsert_edge(graph_node(2), graph_node(3), GraphEdge::manual(2, 3, -2.0));
+        graph.record_link_sample(
+            1,
+            2,
+            LinkQualitySample {
+                timestamp: NOW,
+                snr: 4.0,
+                rssi: Some(-90),
+                observer: "mock".into(),
+            },
+        );
+        graph.set_node_label(3, Some("Barn".into()));
+
+        let mut details = NodeDetails::from_device(&device, 2, NOW);
+        details.add_graph_details(&graph, 1);
+
+        let identity = details.identity.unwrap();
+        assert_eq!(identity.long_name.as_deref(), Some("Hilltop"));
+        assert_eq!(identity.hardware.as_deref(), Some("TBEAM"));
+        assert!(!identity.is_self);
+        assert_eq!(details.display_label.as_deref(), Some("Hilltop"));
+
+        let position = details.position.unwrap();
+        assert!((position.latitude - 47.6062).abs() < 1e-4);
+        assert_eq!(position.age_secs, 90);
+
+        let telemetry = details.telemetry.unwrap();
+        assert_eq!(telemetry.device_metrics.unwrap().battery_level, 75);
+        assert_eq!(telemetry.battery_level.len(), 1);
+        assert_eq!(telemetry.battery_level[0].value, 75.0);
+        assert!(telemetry.temperature.is_empty());
+
+        assert_eq!(
+            details.graph,
+            Some(NodeGraphMetrics {
+                degree: 2,
+                weighted_degree: 2.0,
+                hops: Some(1),
+            })
+        );
+
+        let neighbors = details.neighbors.unwrap();
+        assert_eq!(neighbors.len(), 2);
+        assert_eq!(neighbors[0].node_num, 1);
+        assert_eq!(neighbors[0].link_quality.as_ref().unwrap().sample_count, 1);
+        assert_eq!(neighbors[1].display_label.as_deref(), Some("Barn"));
+        assert_eq!(neighbors[1].link_quality, None);
+
+        assert_eq!(
+            details.messages,
+            NodeMessageCounts {
+                from_node: 2,
+                to_node: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn details_of_bare_node() {
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 1;
+        device.nodes.insert(5, MeshNode::new(5));
+
+        let mut details = NodeDetails::from_device(&device, 5, NOW);
+        details.add_graph_details(&MeshGraph::new(), 1);
+
+        assert_eq!(
+            details,
+            NodeDetails {
+                node_num: 5,
+                id: "!00000005".into(),
+                ..Default::default()
+            }
+        );
+    }
+}
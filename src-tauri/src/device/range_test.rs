@@ -0,0 +1,232 @@
+use std::collections::BTreeSet;
+
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::export::csv::to_csv_record;
+
+use super::{helpers::haversine_distance_meters, NormalizedPosition};
+
+/// Column header of the range test CSV written by the firmware's
+/// range test module, which community tooling expects.
+pub const RANGE_TEST_CSV_COLUMNS: [&str; 13] = [
+    "date",
+    "time",
+    "from",
+    "sender name",
+    "sender lat",
+    "sender long",
+    "rx lat",
+    "rx long",
+    "rx elevation",
+    "rx snr",
+    "distance",
+    "hop limit",
+    "payload",
+];
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct RangeTestPosition {
+    pub latitude: f32,
+    pub longitude: f32,
+    pub altitude: i32,
+}
+
+impl From<&NormalizedPosition> for RangeTestPosition {
+    fn from(position: &NormalizedPosition) -> Self {
+        Self {
+            latitude: position.latitude,
+            longitude: position.longitude,
+            altitude: position.altitude,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct RangeTestSample {
+    /// Sequence number sent by the range test sender
+    pub seq: u32,
+
+    /// Time the packet was received, in seconds since epoch
+    pub timestamp: u32,
+
+    pub rx_snr: f32,
+    pub rx_rssi: i32,
+    pub hop_limit: u32,
+
+    /// Raw text payload of the range test packet
+    pub payload: String,
+
+    /// Last known position of the sender when the packet was received
+    pub sender_position: Option<RangeTestPosition>,
+
+    /// Position of the receiving device when the packet was received
+    pub rx_position: Option<RangeTestPosition>,
+}
+
+impl RangeTestSample {
+    /// Distance between sender and receiver in meters, if both positions are known
+    pub fn distance_meters(&self) -> Option<f64> {
+        let sender = self.sender_position.as_ref()?;
+        let receiver = self.rx_position.as_ref()?;
+
+        Some(haversine_distance_meters(
+            sender.latitude.into(),
+            sender.longitude.into(),
+            receiver.latitude.into(),
+            receiver.longitude.into(),
+        ))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct RangeTestResults {
+    pub node_num: u32,
+    pub samples: Vec<RangeTestSample>,
+
+    /// Number of unique sequence numbers received
+    pub received: u32,
+
+    /// Number of sequence numbers expected between the first and last received
+    pub expected: u32,
+
+    /// Percentage of expected packets that were never received
+    pub packet_loss_percent: f64,
+}
+
+impl RangeTestResults {
+    pub fn new(node_num: u32, samples: Vec<RangeTestSample>) -> Self {
+        let unique_seqs: BTreeSet<u32> = samples.iter().map(|s| s.seq).collect();
+
+        let (received, expected) = match (unique_seqs.first(), unique_seqs.last()) {
+            (Some(first), Some(last)) => (unique_seqs.len() as u32, last - first + 1),
+            _ => (0, 0),
+        };
+
+        let packet_loss_percent = if expected == 0 {
+            0.0
+        } else {
+            (expected - received) as f64 / expected as f64 * 100.0
+        };
+
+        Self {
+            node_num,
+            samples,
+            received,
+            expected,
+            packet_loss_percent,
+        }
+    }
+
+    /// Serializes the samples into the firmware's range test CSV format
+    pub fn to_csv(&self, sender_name: &str) -> String {
+        let mut csv = to_csv_record(&RANGE_TEST_CSV_COLUMNS);
+
+        for sample in self.samples.iter() {
+            let received_at =
+                chrono::DateTime::from_timestamp(sample.timestamp as i64, 0).unwrap_or_default();
+
+            let sender = sample.sender_position.clone().unwrap_or_default();
+            let receiver = sample.rx_position.clone().unwrap_or_default();
+
+            csv.push_str(&to_csv_record(&[
+                received_at.format("%Y-%m-%d").to_string(),
+                received_at.format("%H:%M:%S").to_string(),
+                sample_sender_id(self.node_num),
+                sender_name.to_string(),
+                sender.latitude.to_string(),
+                sender.longitude.to_string(),
+                receiver.latitude.to_string(),
+                receiver.longitude.to_string(),
+                receiver.altitude.to_string(),
+                sample.rx_snr.to_string(),
+                sample
+                    .distance_meters()
+                    .map(|d| format!("{:.0}", d))
+                    .unwrap_or_default(),
+                sample.hop_limit.to_string(),
+                sample.payload.clone(),
+            ]));
+        }
+
+        csv
+    }
+}
+
+fn sample_sender_id(node_num: u32) -> String {
+    format!("!{:08x}", node_num)
+}
+
+/// Parses the sequence number out of a range test payload (e.g. "seq 12")
+pub fn parse_range_test_seq(payload: &str) -> Option<u32> {
+    payload.trim().strip_prefix("seq")?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(seq: u32) -> RangeTestSample {
+        RangeTestSample {
+            seq,
+            timestamp: 1_700_000_000 + seq,
+            rx_snr: 5.25,
+            rx_rssi: -90,
+            hop_limit: 3,
+            payload: format!("seq {}", seq),
+            sender_position: Some(RangeTestPosition {
+                latitude: 47.6062,
+                longitude: -122.3321,
+                altitude: 10,
+            }),
+            rx_position: Some(RangeTestPosition {
+                latitude: 47.6162,
+                longitude: -122.3321,
+                altitude: 25,
+            }),
+        }
+    }
+
+    #[test]
+    fn parses_sequence_numbers() {
+        assert_eq!(parse_range_test_seq("seq 12"), Some(12));
+        assert_eq!(parse_range_test_seq(" seq 3 "), Some(3));
+        assert_eq!(parse_range_test_seq("hello"), None);
+    }
+
+    #[test]
+    fn computes_loss_from_gaps() {
+        // Sequences 1 through 10 with 4, 5, and 9 missing, and 2 received twice
+        let samples = [1, 2, 2, 3, 6, 7, 8, 10].into_iter().map(sample).collect();
+        let results = RangeTestResults::new(42, samples);
+
+        assert_eq!(results.received, 7);
+        assert_eq!(results.expected, 10);
+        assert!((results.packet_loss_percent - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn empty_results_have_no_loss() {
+        let results = RangeTestResults::new(42, vec![]);
+
+        assert_eq!(results.expected, 0);
+        assert_eq!(results.packet_loss_percent, 0.0);
+    }
+
+    #[test]
+    fn writes_firmware_csv_columns() {
+        let results = RangeTestResults::new(0x1234abcd, vec![sample(1)]);
+        let csv = results.to_csv("Base, North");
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+
+        assert_eq!(lines[0], RANGE_TEST_CSV_COLUMNS.join(","));
+
+        let row: Vec<&str> = lines[1].splitn(5, ',').collect();
+        assert_eq!(row[2], "!1234abcd");
+        assert!(lines[1].contains("\"Base, North\""));
+        assert!(lines[1].ends_with(",1112,3,seq 1"));
+    }
+}
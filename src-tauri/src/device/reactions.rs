@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+/// How long a reaction to a message we haven't received yet is held,
+/// in case the original message arrives late.
+pub const PENDING_REACTION_TTL_SECS: u32 = 5 * 60;
+
+/// Emoji reactions to a message, mapping each emoji to the nodes that sent it
+pub type MessageReactions = HashMap<String, Vec<u32>>;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionPacket {
+    /// Id of the packet being reacted to
+    pub reply_id: u32,
+
+    /// Channel the reaction was sent on
+    pub channel: u32,
+
+    /// Node that sent the reaction
+    pub from: u32,
+
+    pub emoji: String,
+
+    /// Time the reaction was received, in seconds since epoch
+    pub received_at: u32,
+}
+
+/// Records `from` as having reacted with `emoji`, ignoring repeated reactions
+pub fn add_reaction(reactions: &mut MessageReactions, emoji: String, from: u32) {
+    let senders = reactions.entry(emoji).or_default();
+
+    if !senders.contains(&from) {
+        senders.push(from);
+    }
+}
+
+/// Removes pending reactions that have been waiting longer than the TTL
+pub fn prune_pending_reactions(pending: &mut Vec<ReactionPacket>, now: u32) {
+    pending.retain(|r| now.saturating_sub(r.received_at) <= PENDING_REACTION_TTL_SECS);
+}
+
+/// Returns whether a text message's data represents a reaction to an earlier packet
+pub fn is_reaction(emoji: u32, reply_id: u32) -> bool {
+    emoji != 0 && reply_id != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::{ChannelMessagePayload, MeshChannel, MeshDevice, TextPacket};
+
+    fn device_with_channel() -> MeshDevice {
+        let mut device = MeshDevice::new();
+        device.channels.insert(0, MeshChannel::default());
+        device
+    }
+
+    fn text(id: u32) -> TextPacket {
+        TextPacket {
+            packet: protobufs::MeshPacket {
+                id,
+                ..Default::default()
+            },
+            data: "Hello".into(),
+            reactions: MessageReactions::new(),
+        }
+    }
+
+    fn reaction(reply_id: u32, from: u32, emoji: &str, received_at: u32) -> ReactionPacket {
+        ReactionPacket {
+            reply_id,
+            channel: 0,
+            from,
+            emoji: emoji.into(),
+            received_at,
+        }
+    }
+
+    fn reactions_of(device: &MeshDevice, id: u32) -> MessageReactions {
+        device.channels[&0]
+            .messages
+            .iter()
+            .find_map(|m| match &m.payload {
+                ChannelMessagePayload::Text(t) if t.packet.id == id => Some(t.reactions.clone()),
+                _ => None,
+            })
+            .expect("Message not found")
+    }
+
+    #[test]
+    fn aggregates_reactions() {
+        let mut device = device_with_channel();
+        device.add_text_message(text(1));
+
+        device.add_reaction(reaction(1, 10, "👍", 100));
+        device.add_reaction(reaction(1, 11, "👍", 100));
+        device.add_reaction(reaction(1, 11, "👍", 101));
+        device.add_reaction(reaction(1, 12, "😂", 102));
+
+        let reactions = reactions_of(&device, 1);
+        assert_eq!(reactions["👍"], vec![10, 11]);
+        assert_eq!(reactions["😂"], vec![12]);
+        assert_eq!(device.channels[&0].messages.len(), 1);
+    }
+
+    #[test]
+    fn applies_reactions_received_before_original() {
+        let mut device = device_with_channel();
+
+        device.add_reaction(reaction(1, 10, "👍", 100));
+        assert!(device.channels[&0].messages.is_empty());
+        assert_eq!(device.pending_reactions.len(), 1);
+
+        device.add_text_message(text(1));
+
+        assert_eq!(reactions_of(&device, 1)["👍"], vec![10]);
+        assert!(device.pending_reactions.is_empty());
+    }
+
+    #[test]
+    fn prunes_expired_pending_reactions() {
+        let mut pending = vec![reaction(1, 10, "👍", 100), reaction(2, 10, "👍", 500)];
+
+        prune_pending_reactions(&mut pending, 100 + PENDING_REACTION_TTL_SECS + 1);
+
+        assert_eq!(pending, vec![reaction(2, 10, "👍", 500)]);
+    }
+}
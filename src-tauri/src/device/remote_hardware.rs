@@ -0,0 +1,104 @@
+use meshtastic::protobufs::{self, hardware_message};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct GpioReading {
+    /// Node the GPIO values were read from
+    pub node_num: u32,
+
+    /// Bitmask of the GPIO pins included in `value`
+    pub gpio_mask: u64,
+
+    /// Values of the GPIO pins selected by `gpio_mask`
+    pub gpio_value: u64,
+
+    /// Time the reading was received, in seconds since epoch
+    pub timestamp: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RemoteHardwareUpdate {
+    /// Reply to a `ReadGpios` request
+    ReadReply(GpioReading),
+
+    /// Notification that watched GPIO pins changed
+    Changed(GpioReading),
+}
+
+/// Builds a `HardwareMessage` of the given type for the selected GPIO pins
+pub fn build_hardware_message(
+    message_type: hardware_message::Type,
+    gpio_mask: u64,
+    gpio_value: u64,
+) -> protobufs::HardwareMessage {
+    protobufs::HardwareMessage {
+        r#type: message_type as i32,
+        gpio_mask,
+        gpio_value,
+    }
+}
+
+/// Classifies a `HardwareMessage` received from `node_num` into
+/// an update the client cares about, ignoring requests from other nodes.
+pub fn classify_hardware_message(
+    node_num: u32,
+    message: protobufs::HardwareMessage,
+    timestamp: u32,
+) -> Option<RemoteHardwareUpdate> {
+    let reading = GpioReading {
+        node_num,
+        gpio_mask: message.gpio_mask,
+        gpio_value: message.gpio_value,
+        timestamp,
+    };
+
+    match hardware_message::Type::from_i32(message.r#type)? {
+        hardware_message::Type::ReadGpiosReply => Some(RemoteHardwareUpdate::ReadReply(reading)),
+        hardware_message::Type::GpiosChanged => Some(RemoteHardwareUpdate::Changed(reading)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_read_replies() {
+        let reply = build_hardware_message(hardware_message::Type::ReadGpiosReply, 0b0110, 0b0100);
+
+        assert_eq!(
+            classify_hardware_message(7, reply, 100),
+            Some(RemoteHardwareUpdate::ReadReply(GpioReading {
+                node_num: 7,
+                gpio_mask: 0b0110,
+                gpio_value: 0b0100,
+                timestamp: 100,
+            }))
+        );
+    }
+
+    #[test]
+    fn classifies_watch_notifications() {
+        let changed = build_hardware_message(hardware_message::Type::GpiosChanged, 0b1, 0b1);
+
+        assert_eq!(
+            classify_hardware_message(7, changed, 5),
+            Some(RemoteHardwareUpdate::Changed(GpioReading {
+                node_num: 7,
+                gpio_mask: 0b1,
+                gpio_value: 0b1,
+                timestamp: 5,
+            }))
+        );
+    }
+
+    #[test]
+    fn ignores_requests() {
+        let request = build_hardware_message(hardware_message::Type::WriteGpios, 0b1, 0b1);
+
+        assert_eq!(classify_hardware_message(7, request, 5), None);
+    }
+}
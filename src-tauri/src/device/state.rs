@@ -3,14 +3,20 @@
 use log::{debug, trace};
 use meshtastic::protobufs;
 
-use super::helpers::get_current_time_u32;
+use super::clock;
+use super::fixed_position::FixedPosition;
+use super::helpers::{get_channel_display_name, get_current_time_u32};
+use super::range_test::{parse_range_test_seq, RangeTestPosition, RangeTestSample};
+use super::reactions::{self, ReactionPacket};
+use super::remote_hardware::GpioReading;
 use super::{
-    ChannelMessagePayload, ChannelMessageWithState, MeshChannel, MeshDevice, MeshNode,
-    MeshNodeDeviceMetrics, MeshNodeEnvironmentMetrics, NeighborInfoPacket, NormalizedWaypoint,
-    PositionPacket, SerialDeviceStatus, TelemetryPacket, TextPacket, UserPacket, WaypointPacket,
+    ChannelMessageHistory, ChannelMessagePayload, ChannelMessageWithState, MeshChannel, MeshDevice,
+    MeshNode, NeighborInfoPacket, NormalizedWaypoint, PositionPacket, RadioQueueStatus,
+    RangeTestPacket, SerialDeviceStatus, TelemetryPacket, TextPacket, UserPacket, WaypointPacket,
 };
 
 use crate::device::{ChannelMessageState, LastHeardMetadata};
+use crate::packet_api::handlers::DeviceUpdateError;
 
 impl MeshDevice {
     pub fn set_ready(&mut self, ready: bool) {
@@ -121,75 +127,118 @@ impl MeshDevice {
         self.my_node_info = info;
     }
 
+    pub fn set_queue_status(&mut self, queue_status: protobufs::QueueStatus) {
+        trace!(
+            "Radio TX queue status: {} of {} slots free",
+            queue_status.free,
+            queue_status.maxlen
+        );
+
+        self.queue_status = Some(RadioQueueStatus {
+            free: queue_status.free,
+            maxlen: queue_status.maxlen,
+            mesh_packet_id: queue_status.mesh_packet_id,
+            timestamp: get_current_time_u32(),
+        });
+    }
+
+    /// Number of packets waiting in the radio's TX queue, if the radio has reported it
+    pub fn queue_depth(&self) -> Option<u32> {
+        self.queue_status
+            .as_ref()
+            .map(|status| status.maxlen.saturating_sub(status.free))
+    }
+
+    pub fn set_canned_messages(&mut self, messages: Vec<String>) {
+        debug!("Updating cached canned messages");
+        trace!("{:?}", messages);
+
+        self.canned_messages = Some(messages);
+    }
+
+    /// Adds a telemetry packet's metrics to its sender's history, adding the sender to
+    /// the node list if it hasn't been heard from before
     pub fn set_device_metrics(&mut self, metrics: TelemetryPacket) {
-        let origin_node = self.nodes.get_mut(&metrics.packet.from);
-
-        if let Some(node) = origin_node {
-            if let Some(variant) = metrics.data.variant {
-                match variant {
-                    protobufs::telemetry::Variant::DeviceMetrics(device_metrics) => {
-                        debug!("Adding device metrics to node {:?}", metrics.packet.from);
-                        trace!("{:?}", device_metrics);
-
-                        self.device_metrics.battery_level = device_metrics.battery_level;
-                        self.device_metrics.voltage = device_metrics.voltage;
-                        self.device_metrics.air_util_tx = device_metrics.air_util_tx;
-                        self.device_metrics.channel_utilization =
-                            device_metrics.channel_utilization;
-
-                        node.device_metrics.push(MeshNodeDeviceMetrics {
-                            metrics: protobufs::DeviceMetrics { ..device_metrics },
-                            timestamp: get_current_time_u32(),
-                            snr: metrics.packet.rx_snr,
-                        });
-                    }
-                    protobufs::telemetry::Variant::EnvironmentMetrics(environment_metrics) => {
-                        debug!(
-                            "Adding environment metrics to node {:?}",
-                            metrics.packet.from
-                        );
-                        trace!("{:?}", environment_metrics);
-
-                        node.environment_metrics.push(MeshNodeEnvironmentMetrics {
-                            metrics: protobufs::EnvironmentMetrics {
-                                ..environment_metrics
-                            },
-                            timestamp: get_current_time_u32(),
-                            snr: metrics.packet.rx_snr,
-                        });
-                    }
-                    protobufs::telemetry::Variant::AirQualityMetrics(air_quality_metrics) => {
-                        debug!("Received air quality metrics, not handling");
-                        trace!("{:?}", air_quality_metrics);
-                    }
-                    protobufs::telemetry::Variant::PowerMetrics(power_metrics) => {
-                        debug!("Received power metrics, not handling");
-                        trace!("{:?}", power_metrics);
-                    }
-                }
-            }
-        } else {
+        let from = metrics.packet.from;
+        let snr = metrics.packet.rx_snr;
+
+        let node = self.nodes.entry(from).or_insert_with(|| {
             let new_node = MeshNode {
-                node_num: metrics.packet.from,
                 last_heard: Some(LastHeardMetadata {
                     timestamp: get_current_time_u32(),
-                    snr: metrics.packet.rx_snr,
+                    snr,
                     channel: metrics.packet.channel,
                 }),
-                user: None,
-                device_metrics: vec![],
-                environment_metrics: vec![],
-                position_metrics: vec![],
+                ..MeshNode::new(from)
             };
 
-            debug!(
-                "Inserting new node with id {} from metrics",
-                metrics.packet.from,
-            );
+            debug!("Inserting new node with id {} from metrics", from);
             trace!("{:?}", new_node);
 
-            self.nodes.insert(metrics.packet.from, new_node);
+            new_node
+        });
+
+        if let Some(variant) = metrics.data.variant {
+            match variant {
+                protobufs::telemetry::Variant::DeviceMetrics(device_metrics) => {
+                    debug!("Adding device metrics to node {:?}", from);
+                    trace!("{:?}", device_metrics);
+
+                    self.device_metrics.battery_level = device_metrics.battery_level;
+                    self.device_metrics.voltage = device_metrics.voltage;
+                    self.device_metrics.air_util_tx = device_metrics.air_util_tx;
+                    self.device_metrics.channel_utilization = device_metrics.channel_utilization;
+
+                    node.push_device_metrics(device_metrics, snr);
+                }
+                protobufs::telemetry::Variant::EnvironmentMetrics(environment_metrics) => {
+                    debug!("Adding environment metrics to node {:?}", from);
+                    trace!("{:?}", environment_metrics);
+
+                    node.push_environment_metrics(environment_metrics, snr);
+                }
+                protobufs::telemetry::Variant::AirQualityMetrics(air_quality_metrics) => {
+                    debug!("Received air quality metrics, not handling");
+                    trace!("{:?}", air_quality_metrics);
+                }
+                protobufs::telemetry::Variant::PowerMetrics(power_metrics) => {
+                    debug!("Received power metrics, not handling");
+                    trace!("{:?}", power_metrics);
+                }
+            }
+        }
+    }
+
+    /// Replaces the config of the channel at its index, keeping the channel's messages.
+    /// Configs with a negative index, which a radio shouldn't report, are rejected.
+    pub fn set_channel_config(
+        &mut self,
+        config: protobufs::Channel,
+    ) -> Result<(), DeviceUpdateError> {
+        let index: u32 = config.index.try_into().map_err(|_| {
+            DeviceUpdateError::GeneralFailure(format!(
+                "Channel index {} out of range",
+                config.index
+            ))
+        })?;
+
+        match self.channels.get_mut(&index) {
+            Some(channel) => {
+                debug!("Updating device channel at index {}", index);
+                channel.config = config;
+            }
+            // Messages heard on the channel before it was configured move into it
+            None => self.add_channel(MeshChannel {
+                config,
+                last_interaction: get_current_time_u32(),
+                messages: self
+                    .unknown_channel_messages
+                    .remove(&index)
+                    .unwrap_or_default(),
+            }),
         }
+
+        Ok(())
     }
 
     pub fn add_channel(&mut self, channel: MeshChannel) {
@@ -211,6 +260,21 @@ impl MeshDevice {
         self.waypoints.insert(waypoint.id, waypoint);
     }
 
+    /// Removes waypoints that have expired as of `now`, returning how many were removed
+    pub fn prune_expired_waypoints(&mut self, now: u32) -> usize {
+        let count = self.waypoints.len();
+        self.waypoints
+            .retain(|_, waypoint| !waypoint.is_expired(now));
+
+        let pruned = count - self.waypoints.len();
+
+        if pruned > 0 {
+            debug!("Pruned {} expired waypoints", pruned);
+        }
+
+        pruned
+    }
+
     pub fn add_node_info(&mut self, node_info: protobufs::NodeInfo) {
         let found_node = self.nodes.get_mut(&node_info.num);
 
@@ -303,55 +367,304 @@ impl MeshDevice {
         }
     }
 
-    pub fn add_text_message(&mut self, message: TextPacket) {
-        let channel = self.channels.get_mut(&message.packet.channel);
+    pub fn add_text_message(&mut self, mut message: TextPacket) {
+        debug!(
+            "Adding text message to channel {:?}: {:?}",
+            message.packet.channel, message.data
+        );
 
-        if let Some(ch) = channel {
-            debug!(
-                "Adding text message to channel {:?}: {:?}",
-                message.packet.channel, message.data
-            );
+        // Apply any reactions that arrived before this message
+
+        let message_id = message.packet.id;
+        let (early_reactions, pending): (Vec<_>, Vec<_>) = self
+            .pending_reactions
+            .drain(..)
+            .partition(|r| r.reply_id == message_id);
+
+        self.pending_reactions = pending;
+
+        for reaction in early_reactions {
+            reactions::add_reaction(&mut message.reactions, reaction.emoji, reaction.from);
+        }
+
+        self.push_channel_message(message.packet.channel, ChannelMessagePayload::Text(message));
+    }
+
+    /// Stores a message on `channel_id`. Messages on channels missing from the
+    /// device's channel table (e.g. hash-only channels) are kept apart from it
+    /// rather than being dropped.
+    fn push_channel_message(&mut self, channel_id: u32, payload: ChannelMessagePayload) {
+        let message = ChannelMessageWithState {
+            payload,
+            state: ChannelMessageState::Pending,
+        };
+
+        match self.channels.get_mut(&channel_id) {
+            Some(ch) => {
+                ch.last_interaction = get_current_time_u32();
+                ch.messages.push(message);
+            }
+            None => {
+                debug!("Keeping message on unknown channel {}", channel_id);
+
+                self.unknown_channel_messages
+                    .entry(channel_id)
+                    .or_default()
+                    .push(message);
+            }
+        }
+    }
+
+    /// Messages stored on `channel_id`, whether or not the channel is known
+    pub fn channel_messages(&self, channel_id: u32) -> Option<&Vec<ChannelMessageWithState>> {
+        match self.channels.get(&channel_id) {
+            Some(ch) => Some(&ch.messages),
+            None => self.unknown_channel_messages.get(&channel_id),
+        }
+    }
 
-            ch.last_interaction = get_current_time_u32();
+    fn channel_messages_mut(
+        &mut self,
+        channel_id: u32,
+    ) -> Option<&mut Vec<ChannelMessageWithState>> {
+        match self.channels.get_mut(&channel_id) {
+            Some(ch) => Some(&mut ch.messages),
+            None => self.unknown_channel_messages.get_mut(&channel_id),
+        }
+    }
 
-            ch.messages.push(ChannelMessageWithState {
-                payload: ChannelMessagePayload::Text(message),
-                state: ChannelMessageState::Pending,
+    /// Attaches an emoji reaction to the text message it references. Reactions
+    /// to messages that haven't been received are held until the message
+    /// arrives or the reaction expires.
+    pub fn add_reaction(&mut self, reaction: ReactionPacket) {
+        reactions::prune_pending_reactions(&mut self.pending_reactions, reaction.received_at);
+
+        let message = self
+            .channel_messages_mut(reaction.channel)
+            .and_then(|messages| {
+                messages
+                    .iter_mut()
+                    .find_map(|message| match &mut message.payload {
+                        ChannelMessagePayload::Text(t) if t.packet.id == reaction.reply_id => {
+                            Some(t)
+                        }
+                        _ => None,
+                    })
             });
+
+        match message {
+            Some(text) => {
+                debug!(
+                    "Adding reaction {:?} from node {} to message {}",
+                    reaction.emoji, reaction.from, reaction.reply_id
+                );
+
+                reactions::add_reaction(&mut text.reactions, reaction.emoji, reaction.from);
+            }
+            None => {
+                debug!(
+                    "Holding reaction to unknown message {} until it arrives",
+                    reaction.reply_id
+                );
+
+                self.pending_reactions.push(reaction);
+            }
         }
     }
 
     pub fn add_waypoint_message(&mut self, message: WaypointPacket) {
-        let channel = self.channels.get_mut(&message.packet.channel);
+        debug!(
+            "Adding waypoint message to channel {:?}: {:?}",
+            message.packet.channel, message.data
+        );
 
-        if let Some(ch) = channel {
-            debug!(
-                "Adding waypoint message to channel {:?}: {:?}",
-                message.packet.channel, message.data
-            );
+        self.push_channel_message(
+            message.packet.channel,
+            ChannelMessagePayload::Waypoint(message),
+        );
+    }
+
+    /// Records a received range test packet, returning its sequence number
+    /// or `None` if the payload isn't a valid range test message.
+    pub fn add_range_test_message(&mut self, message: RangeTestPacket) -> Option<u32> {
+        let seq = parse_range_test_seq(&message.data)?;
 
-            ch.last_interaction = get_current_time_u32();
+        let sender_position = self.get_latest_range_test_position(message.packet.from);
+        let rx_position = self.get_latest_range_test_position(self.my_node_info.my_node_num);
 
-            ch.messages.push(ChannelMessageWithState {
-                payload: ChannelMessagePayload::Waypoint(message),
-                state: ChannelMessageState::Pending,
+        debug!(
+            "Adding range test sequence {} from node {}",
+            seq, message.packet.from
+        );
+
+        self.range_tests
+            .entry(message.packet.from)
+            .or_default()
+            .push(RangeTestSample {
+                seq,
+                timestamp: get_current_time_u32(),
+                rx_snr: message.packet.rx_snr,
+                rx_rssi: message.packet.rx_rssi,
+                hop_limit: message.packet.hop_limit,
+                payload: message.data,
+                sender_position,
+                rx_position,
             });
+
+        Some(seq)
+    }
+
+    fn get_latest_range_test_position(&self, node_num: u32) -> Option<RangeTestPosition> {
+        let node = self.nodes.get(&node_num)?;
+        let position = node.position_metrics.last()?;
+
+        Some(position.into())
+    }
+
+    pub fn set_gpio_reading(&mut self, reading: GpioReading) {
+        debug!(
+            "Updating GPIO reading for node {}: mask {:#x}, value {:#x}",
+            reading.node_num, reading.gpio_mask, reading.gpio_value
+        );
+
+        self.gpio_readings.insert(reading.node_num, reading);
+    }
+
+    /// Returns stored messages grouped by channel, optionally limited to a single channel
+    pub fn get_message_history(&self, channel: Option<u32>) -> Vec<ChannelMessageHistory> {
+        let mut history: Vec<ChannelMessageHistory> = self
+            .channels
+            .iter()
+            .map(|(channel_id, ch)| (channel_id, &ch.messages))
+            .chain(self.unknown_channel_messages.iter())
+            .filter(|(channel_id, _)| channel.map_or(true, |c| c == **channel_id))
+            .map(|(channel_id, messages)| ChannelMessageHistory {
+                channel: *channel_id,
+                channel_name: get_channel_display_name(self, *channel_id),
+                messages: messages.clone(),
+            })
+            .collect();
+
+        history.sort_by_key(|h| h.channel);
+        history
+    }
+
+    /// Removes the stored messages of one channel, or of every channel, along with
+    /// reactions still waiting for their message. Returns how many messages were removed.
+    pub fn clear_message_history(&mut self, channel: Option<u32>) -> usize {
+        debug!("Clearing message history of channel {:?}", channel);
+
+        let mut removed = 0;
+
+        for (channel_id, ch) in self.channels.iter_mut() {
+            if channel.map_or(true, |c| c == *channel_id) {
+                removed += ch.messages.len();
+                ch.messages.clear();
+            }
         }
+
+        self.unknown_channel_messages
+            .retain(|channel_id, messages| {
+                if channel.map_or(true, |c| c == *channel_id) {
+                    removed += messages.len();
+                    return false;
+                }
+
+                true
+            });
+
+        self.pending_reactions
+            .retain(|reaction| channel.map_or(false, |c| c != reaction.channel));
+
+        removed
+    }
+
+    /// Records a fixed position for our own node before the radio reports it,
+    /// returning the position packet so other state (e.g. the graph) can be updated.
+    pub fn set_local_fixed_position(
+        &mut self,
+        fixed_position: &FixedPosition,
+        time: u32,
+    ) -> PositionPacket {
+        let my_node_num = self.my_node_info.my_node_num;
+
+        debug!(
+            "Setting fixed position of own node {}: {:?}",
+            my_node_num, fixed_position
+        );
+
+        let packet = PositionPacket {
+            packet: protobufs::MeshPacket {
+                from: my_node_num,
+                to: my_node_num,
+                rx_time: time,
+                ..Default::default()
+            },
+            data: fixed_position.to_position(time),
+        };
+
+        self.nodes
+            .entry(my_node_num)
+            .or_insert_with(|| MeshNode::new(my_node_num))
+            .position_metrics
+            .push(packet.data.clone().into());
+
+        self.config
+            .position
+            .get_or_insert_with(Default::default)
+            .fixed_position = true;
+
+        packet
+    }
+
+    pub fn clear_local_fixed_position(&mut self) {
+        debug!("Clearing fixed position of own node");
+
+        if let Some(position_config) = self.config.position.as_mut() {
+            position_config.fixed_position = false;
+        }
+    }
+
+    /// Updates the measured skew between the device and host clocks.
+    /// Returns the skew if the device clock just became skewed.
+    pub fn update_clock_skew(
+        &mut self,
+        device_time: u32,
+        host_time: u32,
+        threshold_secs: u32,
+    ) -> Option<i64> {
+        let skew = clock::clock_skew_secs(device_time, host_time);
+        let was_skewed = self.clock_skew_secs.is_some();
+
+        if !clock::exceeds_skew_threshold(skew, threshold_secs) {
+            if was_skewed {
+                debug!("Device clock back in sync with host");
+            }
+
+            self.clock_skew_secs = None;
+            return None;
+        }
+
+        self.clock_skew_secs = Some(skew);
+
+        if was_skewed {
+            return None;
+        }
+
+        Some(skew)
     }
 
     // TODO add device metadata
 
+    /// Sets the delivery state of a message, returning whether the device has it
     pub fn set_message_state(
         &mut self,
         channel_id: u32,
         message_id: u32,
         state: ChannelMessageState,
-    ) {
-        let channel = self.channels.get_mut(&channel_id);
-
-        if let Some(ch) = channel {
-            let message = ch
-                .messages
+    ) -> bool {
+        if let Some(messages) = self.channel_messages_mut(channel_id) {
+            let message = messages
                 .iter_mut()
                 .find(|message| match message.payload.clone() {
                     ChannelMessagePayload::Text(t) => t.packet.id == message_id,
@@ -360,7 +673,224 @@ impl MeshDevice {
 
             if let Some(m) = message {
                 m.state = state;
+                return true;
             }
         }
+
+        false
+    }
+
+    pub fn message_state(&self, channel_id: u32, message_id: u32) -> Option<&ChannelMessageState> {
+        self.channel_messages(channel_id).and_then(|messages| {
+            messages
+                .iter()
+                .find(|message| match &message.payload {
+                    ChannelMessagePayload::Text(t) => t.packet.id == message_id,
+                    ChannelMessagePayload::Waypoint(w) => w.packet.id == message_id,
+                })
+                .map(|message| &message.state)
+        })
+    }
+
+    /// The packet id of the last text message this device sent on `channel_id`, read
+    /// from the copy of the packet stored on sending it
+    pub fn last_sent_text_id(&self, channel_id: u32) -> Option<u32> {
+        let device_id = self.my_node_info.my_node_num;
+
+        self.channel_messages(channel_id).and_then(|messages| {
+            messages
+                .iter()
+                .rev()
+                .find_map(|message| match &message.payload {
+                    ChannelMessagePayload::Text(t) if t.packet.from == device_id => {
+                        Some(t.packet.id)
+                    }
+                    _ => None,
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::reactions::MessageReactions;
+    use crate::device::MAX_NODE_METRICS_HISTORY;
+
+    fn text(id: u32, channel: u32) -> TextPacket {
+        TextPacket {
+            packet: protobufs::MeshPacket {
+                id,
+                channel,
+                ..Default::default()
+            },
+            data: format!("Message {}", id),
+            reactions: MessageReactions::new(),
+        }
+    }
+
+    fn telemetry(from: u32, battery_level: u32) -> TelemetryPacket {
+        TelemetryPacket {
+            packet: protobufs::MeshPacket {
+                from,
+                ..Default::default()
+            },
+            data: protobufs::Telemetry {
+                variant: Some(protobufs::telemetry::Variant::DeviceMetrics(
+                    protobufs::DeviceMetrics {
+                        battery_level,
+                        ..Default::default()
+                    },
+                )),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn waypoint(id: u32, latitude: f32, expire: u32) -> NormalizedWaypoint {
+        NormalizedWaypoint {
+            id,
+            latitude,
+            longitude: 134.0,
+            expire,
+            locked_to: 0,
+            name: format!("Waypoint {}", id),
+            description: String::new(),
+            icon: 0,
+            sender: Some(7),
+        }
+    }
+
+    #[test]
+    fn replaces_waypoints_by_id_and_prunes_expired_ones() {
+        let mut device = MeshDevice::new();
+
+        device.add_waypoint(waypoint(1, 52.5, 0));
+        device.add_waypoint(waypoint(2, 52.5, 1_000));
+        device.add_waypoint(waypoint(2, 52.6, 2_000));
+
+        assert_eq!(device.waypoints.len(), 2);
+        assert_eq!(device.waypoints[&2].latitude, 52.6);
+
+        assert_eq!(device.prune_expired_waypoints(1_500), 0);
+        assert_eq!(device.prune_expired_waypoints(2_000), 1);
+        assert!(device.waypoints.contains_key(&1));
+
+        assert!(waypoint(3, 52.5, 0).validate(2_000).is_ok());
+        assert!(waypoint(3, 52.5, 2_000).validate(2_000).is_err());
+        assert!(waypoint(3, 95.0, 0).validate(2_000).is_err());
+    }
+
+    #[test]
+    fn keeps_a_bounded_metrics_history_per_node() {
+        let mut device = MeshDevice::new();
+
+        // No node info was ever received from node 7
+        for battery_level in 0..(MAX_NODE_METRICS_HISTORY as u32 + 5) {
+            device.set_device_metrics(telemetry(7, battery_level));
+        }
+
+        let node = &device.nodes[&7];
+        assert_eq!(node.device_metrics.len(), MAX_NODE_METRICS_HISTORY);
+        assert_eq!(node.device_metrics[0].metrics.battery_level, 5);
+        assert_eq!(
+            node.battery_level(),
+            Some(MAX_NODE_METRICS_HISTORY as u32 + 4)
+        );
+
+        let now = get_current_time_u32();
+        assert!(!node.device_metrics_history(now, 60).stale);
+        assert!(node.device_metrics_history(now + 61, 60).stale);
+        assert!(MeshNode::new(8).device_metrics_history(now, 60).stale);
+    }
+
+    #[test]
+    fn separates_messages_by_channel() {
+        let mut device = MeshDevice::new();
+        device.add_channel(MeshChannel {
+            config: protobufs::Channel {
+                index: 0,
+                settings: Some(protobufs::ChannelSettings {
+                    name: "LongFast".into(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        device.add_text_message(text(1, 0));
+        device.add_text_message(text(2, 3));
+        device.add_text_message(text(3, 0));
+
+        let history = device.get_message_history(None);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].channel_name, "LongFast");
+        assert_eq!(history[0].messages.len(), 2);
+        assert_eq!(history[1].channel_name, "channel #3");
+        assert_eq!(history[1].messages.len(), 1);
+
+        let filtered = device.get_message_history(Some(3));
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].channel, 3);
+
+        // Messages on unknown channels don't add them to the channel table
+        assert_eq!(device.channels.len(), 1);
+        assert_eq!(device.unknown_channel_messages[&3].len(), 1);
+    }
+
+    #[test]
+    fn clears_message_history_of_one_channel() {
+        let mut device = MeshDevice::new();
+        device.add_channel(MeshChannel::default());
+
+        device.add_text_message(text(1, 0));
+        device.add_text_message(text(2, 3));
+        device.add_text_message(text(3, 0));
+
+        assert_eq!(device.clear_message_history(Some(0)), 2);
+
+        let history = device.get_message_history(None);
+        assert_eq!(history.len(), 2);
+        assert!(history[0].messages.is_empty());
+        assert_eq!(history[1].messages.len(), 1);
+
+        // Configured channels are kept, only their messages are removed
+        assert_eq!(device.clear_message_history(None), 1);
+        assert_eq!(device.channels.len(), 1);
+        assert!(device.channels[&0].messages.is_empty());
+        assert!(device.unknown_channel_messages.is_empty());
+    }
+
+    #[test]
+    fn channel_configs_keep_messages_and_reject_negative_indexes() {
+        let mut device = MeshDevice::new();
+
+        let config = |index, name: &str| protobufs::Channel {
+            index,
+            settings: Some(protobufs::ChannelSettings {
+                name: name.into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        device.set_channel_config(config(2, "Base")).unwrap();
+        device.add_text_message(text(1, 2));
+        device.set_channel_config(config(2, "Barn")).unwrap();
+        assert!(device.set_channel_config(config(-1, "Bad")).is_err());
+
+        // Messages heard before their channel is configured move into it
+        device.add_text_message(text(2, 4));
+        device.set_channel_config(config(4, "Hill")).unwrap();
+        assert_eq!(device.channels[&4].messages.len(), 1);
+        assert!(device.unknown_channel_messages.is_empty());
+
+        assert_eq!(device.channels.len(), 2);
+        assert_eq!(
+            device.channels[&2].config.settings.as_ref().unwrap().name,
+            "Barn"
+        );
+        assert_eq!(device.channels[&2].messages.len(), 1);
     }
 }
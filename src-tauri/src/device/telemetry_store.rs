@@ -0,0 +1,821 @@
+use std::path::Path;
+use std::time::Duration;
+
+use log::{trace, warn};
+use meshtastic::protobufs;
+use meshtastic::ts::specta::{self, Type};
+use rusqlite::{params, Connection, Params};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::export::analytics_report::{AnalyticsMetric, AnalyticsReport};
+use crate::migrations::{migrate, Migration, MigrationError};
+use crate::packet_api::{summary::ConnectionType, MeshPacketApi};
+use crate::retention::{ClassUsage, RetainedTable, RetentionLimits, StorageClass};
+use crate::shutdown::shutdown_signal;
+use crate::state;
+
+use super::helpers::get_current_time_u32;
+
+/// Version of the last migration below, recorded in `schema_version`
+pub const TELEMETRY_STORE_SCHEMA_VERSION: u32 = 2;
+
+/// How often queued samples are written to the database
+pub const TELEMETRY_STORE_WRITE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Most buckets returned by a single series query
+pub const MAX_TELEMETRY_SERIES_POINTS: u32 = 5_000;
+
+/// Samples are keyed by the node they describe rather than the device that heard
+/// them, so a report heard by several connected radios is only stored once. Analytics
+/// metrics describe the whole mesh, and like every database are kept per profile.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "telemetry samples",
+        sql: "
+    CREATE TABLE telemetry (
+        node_num INTEGER NOT NULL,
+        metric TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        value REAL NOT NULL,
+        PRIMARY KEY (node_num, metric, timestamp)
+    ) WITHOUT ROWID;
+
+    CREATE INDEX telemetry_by_time ON telemetry (metric, timestamp);
+",
+    },
+    Migration {
+        version: 2,
+        description: "analytics metric series",
+        sql: "
+    CREATE TABLE analytics_metrics (
+        metric TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        value REAL NOT NULL,
+        PRIMARY KEY (metric, timestamp)
+    ) WITHOUT ROWID;
+",
+    },
+];
+
+const TELEMETRY_TABLE: RetainedTable = RetainedTable {
+    name: "telemetry",
+    key: &["node_num", "metric", "timestamp"],
+    timestamp: "timestamp",
+    row_bytes: "LENGTH(metric)",
+};
+
+/// Retention periods are configured per class
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum TelemetryMetricClass {
+    Device,
+    Environment,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum TelemetryMetric {
+    BatteryLevel,
+    Voltage,
+    ChannelUtilization,
+    AirUtilTx,
+    Temperature,
+    RelativeHumidity,
+    BarometricPressure,
+}
+
+pub const TELEMETRY_METRICS: [TelemetryMetric; 7] = [
+    TelemetryMetric::BatteryLevel,
+    TelemetryMetric::Voltage,
+    TelemetryMetric::ChannelUtilization,
+    TelemetryMetric::AirUtilTx,
+    TelemetryMetric::Temperature,
+    TelemetryMetric::RelativeHumidity,
+    TelemetryMetric::BarometricPressure,
+];
+
+impl TelemetryMetric {
+    /// Name stored in the `metric` column
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TelemetryMetric::BatteryLevel => "batteryLevel",
+            TelemetryMetric::Voltage => "voltage",
+            TelemetryMetric::ChannelUtilization => "channelUtilization",
+            TelemetryMetric::AirUtilTx => "airUtilTx",
+            TelemetryMetric::Temperature => "temperature",
+            TelemetryMetric::RelativeHumidity => "relativeHumidity",
+            TelemetryMetric::BarometricPressure => "barometricPressure",
+        }
+    }
+
+    pub fn class(&self) -> TelemetryMetricClass {
+        match self {
+            TelemetryMetric::BatteryLevel
+            | TelemetryMetric::Voltage
+            | TelemetryMetric::ChannelUtilization
+            | TelemetryMetric::AirUtilTx => TelemetryMetricClass::Device,
+            TelemetryMetric::Temperature
+            | TelemetryMetric::RelativeHumidity
+            | TelemetryMetric::BarometricPressure => TelemetryMetricClass::Environment,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TelemetrySample {
+    pub node_num: u32,
+    pub metric: TelemetryMetric,
+    pub timestamp: u32, // secs
+    pub value: f64,
+}
+
+/// Splits a telemetry report into one sample per metric. Environment sensors that
+/// aren't fitted report zero, so zero environment readings are left out.
+pub fn telemetry_samples(
+    node_num: u32,
+    timestamp: u32,
+    telemetry: &protobufs::Telemetry,
+) -> Vec<TelemetrySample> {
+    let values: Vec<(TelemetryMetric, f32)> = match &telemetry.variant {
+        Some(protobufs::telemetry::Variant::DeviceMetrics(metrics)) => vec![
+            (TelemetryMetric::BatteryLevel, metrics.battery_level as f32),
+            (TelemetryMetric::Voltage, metrics.voltage),
+            (
+                TelemetryMetric::ChannelUtilization,
+                metrics.channel_utilization,
+            ),
+            (TelemetryMetric::AirUtilTx, metrics.air_util_tx),
+        ],
+        Some(protobufs::telemetry::Variant::EnvironmentMetrics(metrics)) => [
+            (TelemetryMetric::Temperature, metrics.temperature),
+            (TelemetryMetric::RelativeHumidity, metrics.relative_humidity),
+            (
+                TelemetryMetric::BarometricPressure,
+                metrics.barometric_pressure,
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, value)| *value != 0.0)
+        .collect(),
+        _ => vec![],
+    };
+
+    values
+        .into_iter()
+        .filter(|(_, value)| value.is_finite())
+        .map(|(metric, value)| TelemetrySample {
+            node_num,
+            metric,
+            timestamp,
+            value: f64::from(value),
+        })
+        .collect()
+}
+
+/// Samples of a series that fall in `start..=end`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TelemetryBucket {
+    pub start: u32, // secs
+    pub end: u32,   // secs
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+    pub count: u32,
+}
+
+/// Width in seconds of the buckets `from..=to` is divided into, so there are at
+/// most `max_points` of them
+pub fn bucket_width(from: u32, to: u32, max_points: u32) -> u32 {
+    let span = u64::from(to - from) + 1;
+    let width = (span + u64::from(max_points) - 1) / u64::from(max_points);
+
+    width.max(1) as u32
+}
+
+fn series_bucket_width(from: u32, to: u32, max_points: u32) -> Result<u32, String> {
+    if from > to {
+        return Err("Series start must not be after its end".into());
+    }
+
+    if !(1..=MAX_TELEMETRY_SERIES_POINTS).contains(&max_points) {
+        return Err(format!(
+            "Max points must be between 1 and {}",
+            MAX_TELEMETRY_SERIES_POINTS
+        ));
+    }
+
+    Ok(bucket_width(from, to, max_points))
+}
+
+/// Runs a query selecting the bucket index, min, average, max and count of each
+/// bucket of `width` seconds from `from`, oldest first
+fn query_buckets<P: Params>(
+    connection: &Connection,
+    sql: &str,
+    params: P,
+    from: u32,
+    to: u32,
+    width: u32,
+) -> Result<Vec<TelemetryBucket>, String> {
+    let mut statement = connection.prepare(sql).map_err(|e| e.to_string())?;
+
+    let buckets = statement
+        .query_map(params, |row| {
+            let bucket: u32 = row.get(0)?;
+            let start = from + bucket * width;
+
+            Ok(TelemetryBucket {
+                start,
+                end: start.saturating_add(width - 1).min(to),
+                min: row.get(1)?,
+                avg: row.get(2)?,
+                max: row.get(3)?,
+                count: row.get(4)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(buckets)
+}
+
+/// SQLite database of telemetry samples, kept for longer than the per-node history
+/// held in memory so trends can be viewed over days
+pub struct TelemetryStore {
+    connection: Connection,
+}
+
+impl TelemetryStore {
+    pub fn open(path: &Path) -> Result<Self, MigrationError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {:?}: {}", parent, e))?;
+        }
+
+        let connection =
+            Connection::open(path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+
+        Self::with_connection(connection, Some(path))
+    }
+
+    pub fn open_in_memory() -> Result<Self, MigrationError> {
+        Self::with_connection(
+            Connection::open_in_memory().map_err(|e| e.to_string())?,
+            None,
+        )
+    }
+
+    fn with_connection(
+        mut connection: Connection,
+        path: Option<&Path>,
+    ) -> Result<Self, MigrationError> {
+        let version = migrate(&mut connection, "telemetry database", MIGRATIONS, path)?;
+        debug_assert_eq!(version, TELEMETRY_STORE_SCHEMA_VERSION);
+
+        Ok(Self { connection })
+    }
+
+    /// Writes a batch of samples in one transaction. A sample with the same node,
+    /// metric and timestamp as a stored one replaces it.
+    pub fn write(&mut self, samples: &[TelemetrySample]) -> Result<usize, String> {
+        let transaction = self.connection.transaction().map_err(|e| e.to_string())?;
+
+        for sample in samples {
+            transaction
+                .execute(
+                    "INSERT OR REPLACE INTO telemetry (node_num, metric, timestamp, value)
+                        VALUES (?1, ?2, ?3, ?4)",
+                    params![
+                        sample.node_num,
+                        sample.metric.as_str(),
+                        sample.timestamp,
+                        sample.value
+                    ],
+                )
+                .map_err(|e| e.to_string())?;
+        }
+
+        transaction.commit().map_err(|e| e.to_string())?;
+
+        Ok(samples.len())
+    }
+
+    /// Returns a node's samples of `metric` between `from` and `to`, inclusive,
+    /// downsampled into at most `max_points` buckets of equal width, oldest first.
+    /// Buckets without samples are left out.
+    pub fn series(
+        &self,
+        node_num: u32,
+        metric: TelemetryMetric,
+        from: u32,
+        to: u32,
+        max_points: u32,
+    ) -> Result<Vec<TelemetryBucket>, String> {
+        let width = series_bucket_width(from, to, max_points)?;
+
+        query_buckets(
+            &self.connection,
+            "SELECT (timestamp - ?3) / ?5 AS bucket, MIN(value), AVG(value), MAX(value), COUNT(*)
+                FROM telemetry
+                WHERE node_num = ?1 AND metric = ?2 AND timestamp BETWEEN ?3 AND ?4
+                GROUP BY bucket
+                ORDER BY bucket",
+            params![node_num, metric.as_str(), from, to, width],
+            from,
+            to,
+            width,
+        )
+    }
+
+    /// Writes the metric values of an analytics report taken at `timestamp`. A value
+    /// with the same metric and timestamp as a stored one replaces it.
+    pub fn write_analytics_metrics(
+        &mut self,
+        timestamp: u32,
+        values: &[(AnalyticsMetric, f64)],
+    ) -> Result<usize, String> {
+        let transaction = self.connection.transaction().map_err(|e| e.to_string())?;
+
+        for (metric, value) in values {
+            transaction
+                .execute(
+                    "INSERT OR REPLACE INTO analytics_metrics (metric, timestamp, value)
+                        VALUES (?1, ?2, ?3)",
+                    params![metric.as_str(), timestamp, value],
+                )
+                .map_err(|e| e.to_string())?;
+        }
+
+        transaction.commit().map_err(|e| e.to_string())?;
+
+        Ok(values.len())
+    }
+
+    /// Returns the values of an analytics metric between `from` and `to`, downsampled
+    /// like a telemetry series
+    pub fn analytics_series(
+        &self,
+        metric: AnalyticsMetric,
+        from: u32,
+        to: u32,
+        max_points: u32,
+    ) -> Result<Vec<TelemetryBucket>, String> {
+        let width = series_bucket_width(from, to, max_points)?;
+
+        query_buckets(
+            &self.connection,
+            "SELECT (timestamp - ?2) / ?4 AS bucket, MIN(value), AVG(value), MAX(value), COUNT(*)
+                FROM analytics_metrics
+                WHERE metric = ?1 AND timestamp BETWEEN ?2 AND ?3
+                GROUP BY bucket
+                ORDER BY bucket",
+            params![metric.as_str(), from, to, width],
+            from,
+            to,
+            width,
+        )
+    }
+
+    pub fn usage(&self) -> Result<ClassUsage, String> {
+        let (rows, bytes) = TELEMETRY_TABLE.usage(&self.connection)?;
+
+        Ok(ClassUsage {
+            class: StorageClass::Telemetry,
+            rows,
+            bytes,
+        })
+    }
+
+    /// Deletes the samples of each metric class older than its cutoff, then the
+    /// samples beyond the retention limits, returning the number of samples deleted
+    pub fn enforce_retention(
+        &mut self,
+        cutoffs: &[(TelemetryMetricClass, u32)],
+        limits: RetentionLimits,
+    ) -> Result<usize, String> {
+        let transaction = self.connection.transaction().map_err(|e| e.to_string())?;
+        let mut deleted = 0;
+
+        for (class, cutoff) in cutoffs {
+            for metric in TELEMETRY_METRICS.iter().filter(|m| m.class() == *class) {
+                deleted += transaction
+                    .execute(
+                        "DELETE FROM telemetry WHERE metric = ?1 AND timestamp < ?2",
+                        params![metric.as_str(), cutoff],
+                    )
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        deleted += TELEMETRY_TABLE.enforce(&transaction, limits)?.len();
+
+        // Analytics metrics are a handful of rows a report, so are only limited by age
+        if let Some(cutoff) = limits.cutoff {
+            deleted += transaction
+                .execute(
+                    "DELETE FROM analytics_metrics WHERE timestamp < ?1",
+                    params![cutoff],
+                )
+                .map_err(|e| e.to_string())?;
+        }
+
+        transaction.commit().map_err(|e| e.to_string())?;
+
+        Ok(deleted)
+    }
+}
+
+/// Queues the samples of a telemetry report for the next database write. Only
+/// reports heard by radios are stored, not simulated or replayed ones.
+pub fn queue_telemetry_write<R: tauri::Runtime>(
+    packet_api: &MeshPacketApi<R>,
+    node_num: u32,
+    telemetry: &protobufs::Telemetry,
+) {
+    if !matches!(
+        packet_api.connection_type,
+        ConnectionType::Serial | ConnectionType::Tcp
+    ) {
+        return;
+    }
+
+    let samples = telemetry_samples(node_num, get_current_time_u32(), telemetry);
+
+    if samples.is_empty() {
+        return;
+    }
+
+    let store_state = match packet_api
+        .app_handle
+        .try_state::<state::telemetry_store::TelemetryStoreState>()
+    {
+        Some(store_state) => store_state,
+        None => return,
+    };
+
+    match store_state.pending.lock() {
+        Ok(mut pending) => pending.extend(samples),
+        Err(e) => warn!("Failed to queue telemetry write: {}", e),
+    }
+}
+
+/// Writes the queued samples, returning how many were written. Blocks on the
+/// database, so it's called off the async runtime.
+pub fn write_pending_telemetry<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+) -> Result<usize, String> {
+    let store_state = match handle.try_state::<state::telemetry_store::TelemetryStoreState>() {
+        Some(store_state) => store_state,
+        None => return Ok(0),
+    };
+
+    let pending = std::mem::take(&mut *store_state.pending.lock().map_err(|e| e.to_string())?);
+
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let mut store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+
+    match store_guard.as_mut() {
+        Some(store) => store.write(&pending),
+        None => Ok(0),
+    }
+}
+
+/// Stores the metrics of a completed analytics report that are selected in the
+/// settings. Blocks on the database, so it's called off the async runtime.
+pub fn record_analytics_metrics<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    report: &AnalyticsReport,
+) -> Result<usize, String> {
+    let selected = {
+        let settings_state = handle.state::<state::settings::SettingsState>();
+        let settings = settings_state.inner.lock().map_err(|e| e.to_string())?;
+        settings.analytics.persisted_metrics.clone()
+    };
+
+    let values = report.metric_values(&selected);
+
+    if values.is_empty() {
+        return Ok(0);
+    }
+
+    let store_state = match handle.try_state::<state::telemetry_store::TelemetryStoreState>() {
+        Some(store_state) => store_state,
+        None => return Ok(0),
+    };
+
+    let mut store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+
+    match store_guard.as_mut() {
+        Some(store) => store.write_analytics_metrics(report.metadata.generated_at, &values),
+        None => Ok(0),
+    }
+}
+
+pub fn spawn_telemetry_store_writer(handle: tauri::AppHandle) {
+    trace!("Spawning telemetry database writer");
+
+    tauri::async_runtime::spawn(async move {
+        let shutdown = shutdown_signal(&handle);
+        let mut interval = tokio::time::interval(TELEMETRY_STORE_WRITE_INTERVAL);
+
+        while shutdown.tick(&mut interval).await {
+            let handle = handle.clone();
+            let result =
+                tauri::async_runtime::spawn_blocking(move || write_pending_telemetry(&handle))
+                    .await;
+
+            match result {
+                Ok(Ok(0)) => {}
+                Ok(Ok(count)) => trace!("Wrote {} telemetry samples", count),
+                Ok(Err(e)) => warn!("Failed to write telemetry database: {}", e),
+                Err(e) => warn!("Telemetry database writer failed: {}", e),
+            }
+        }
+
+        trace!("Stopped telemetry database writer");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::MeshDevice;
+    use crate::export::analytics_report::{
+        build_analytics_report, AnalyticsReportMetadata, AnalyticsSection, ReportDevice,
+        ANALYTICS_REPORT_VERSION,
+    };
+    use crate::graph::ds::{edge::GraphEdge, fixtures::graph_node, graph::MeshGraph};
+
+    const NODE: u32 = 0x1234;
+
+    /// A battery reading every 10 seconds for an hour, cycling through 40..=99
+    fn fixture() -> TelemetryStore {
+        let mut store = TelemetryStore::open_in_memory().unwrap();
+
+        let samples: Vec<TelemetrySample> = (0..360)
+            .map(|i| TelemetrySample {
+                node_num: NODE,
+                metric: TelemetryMetric::BatteryLevel,
+                timestamp: 10_000 + i * 10,
+                value: f64::from(40 + i % 60),
+            })
+            .collect();
+
+        assert_eq!(store.write(&samples).unwrap(), 360);
+        store
+    }
+
+    #[test]
+    fn downsamples_into_buckets() {
+        let store = fixture();
+
+        assert_eq!(bucket_width(10_000, 13_599, 60), 60);
+        assert_eq!(bucket_width(10_000, 13_599, 7), 515);
+        assert_eq!(bucket_width(10_000, 10_000, 500), 1);
+
+        let buckets = store
+            .series(NODE, TelemetryMetric::BatteryLevel, 10_000, 13_599, 60)
+            .unwrap();
+
+        assert_eq!(buckets.len(), 60);
+        assert!(buckets.windows(2).all(|w| w[0].end < w[1].start));
+
+        // Six samples a minute, each minute of the cycle ten apart
+        assert_eq!(buckets[0].start, 10_000);
+        assert_eq!(buckets[0].end, 10_059);
+        assert_eq!(buckets[0].count, 6);
+        assert_eq!((buckets[0].min, buckets[0].max), (40.0, 45.0));
+        assert_eq!(buckets[0].avg, 42.5);
+
+        assert_eq!(buckets[59].end, 13_599);
+
+        // Uneven buckets still cover every sample
+        let buckets = store
+            .series(NODE, TelemetryMetric::BatteryLevel, 10_000, 13_599, 7)
+            .unwrap();
+
+        assert!(buckets.len() <= 7);
+        assert_eq!(buckets.iter().map(|b| b.count).sum::<u32>(), 360);
+        assert_eq!(buckets.last().unwrap().end, 13_599);
+        assert_eq!(buckets.iter().map(|b| b.min).fold(f64::MAX, f64::min), 40.0);
+        assert_eq!(buckets.iter().map(|b| b.max).fold(0.0, f64::max), 99.0);
+
+        // More points than samples returns each sample in its own bucket
+        let buckets = store
+            .series(NODE, TelemetryMetric::BatteryLevel, 10_000, 10_095, 500)
+            .unwrap();
+
+        assert_eq!(buckets.len(), 10);
+        assert!(buckets.iter().all(|b| b.count == 1 && b.min == b.max));
+
+        assert!(store
+            .series(NODE, TelemetryMetric::Voltage, 10_000, 13_599, 60)
+            .unwrap()
+            .is_empty());
+        assert!(store
+            .series(NODE, TelemetryMetric::BatteryLevel, 10_000, 13_599, 0)
+            .is_err());
+        assert!(store
+            .series(NODE, TelemetryMetric::BatteryLevel, 13_599, 10_000, 60)
+            .is_err());
+    }
+
+    #[test]
+    fn prunes_by_metric_class() {
+        let mut store = fixture();
+
+        store
+            .write(&[
+                TelemetrySample {
+                    node_num: NODE,
+                    metric: TelemetryMetric::Temperature,
+                    timestamp: 10_000,
+                    value: 21.5,
+                },
+                TelemetrySample {
+                    node_num: NODE,
+                    metric: TelemetryMetric::Temperature,
+                    timestamp: 13_000,
+                    value: 19.0,
+                },
+            ])
+            .unwrap();
+
+        let deleted = store
+            .enforce_retention(
+                &[
+                    (TelemetryMetricClass::Device, 12_000),
+                    (TelemetryMetricClass::Environment, 9_000),
+                ],
+                RetentionLimits::default(),
+            )
+            .unwrap();
+        assert_eq!(deleted, 200);
+
+        let battery = store
+            .series(NODE, TelemetryMetric::BatteryLevel, 0, 20_000, 5_000)
+            .unwrap();
+        assert_eq!(battery.len(), 160);
+        assert_eq!(battery[0].start, 12_000);
+
+        let temperature = store
+            .series(NODE, TelemetryMetric::Temperature, 0, 20_000, 5_000)
+            .unwrap();
+        assert_eq!(temperature.len(), 2);
+
+        store
+            .enforce_retention(
+                &[(TelemetryMetricClass::Environment, 12_000)],
+                RetentionLimits::default(),
+            )
+            .unwrap();
+
+        let temperature = store
+            .series(NODE, TelemetryMetric::Temperature, 0, 20_000, 5_000)
+            .unwrap();
+        assert_eq!(temperature.len(), 1);
+        assert_eq!(temperature[0].avg, 19.0);
+    }
+
+    /// Report on a chain of `length` nodes, so its diameter is one less
+    fn chain_report(
+        length: u32,
+        generated_at: u32,
+        sections: Vec<AnalyticsSection>,
+    ) -> AnalyticsReport {
+        let mut graph = MeshGraph::new();
+        for node_num in 1..=length {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        for to in 2..=length {
+            graph.upsert_edge(
+                graph_node(to - 1),
+                graph_node(to),
+                GraphEdge::manual(to - 1, to, 5.0),
+            );
+        }
+
+        let metadata = AnalyticsReportMetadata {
+            report_version: ANALYTICS_REPORT_VERSION,
+            generated_at,
+            graph_version: None,
+            device: ReportDevice::new("/dev/ttyUSB0".into(), &MeshDevice::new()),
+            sections,
+        };
+
+        build_analytics_report(&graph, metadata).unwrap()
+    }
+
+    #[test]
+    fn downsamples_analytics_metrics_over_days() {
+        const DAY: u32 = 24 * 60 * 60;
+        const START: u32 = 1_700_006_400;
+
+        let mut store = TelemetryStore::open_in_memory().unwrap();
+        let selected = [AnalyticsMetric::Diameter, AnalyticsMetric::Bridges];
+
+        // Four reports a day for four weeks, the chain growing by a node each week
+        for day in 0..28 {
+            for quarter in 0..4 {
+                let report = chain_report(
+                    3 + day / 7,
+                    START + day * DAY + quarter * DAY / 4,
+                    vec![AnalyticsSection::Stats, AnalyticsSection::Resilience],
+                );
+
+                let values = report.metric_values(&selected);
+                assert_eq!(values.len(), 2);
+
+                store
+                    .write_analytics_metrics(report.metadata.generated_at, &values)
+                    .unwrap();
+            }
+        }
+
+        let weeks = store
+            .analytics_series(AnalyticsMetric::Diameter, START, START + 28 * DAY - 1, 4)
+            .unwrap();
+
+        assert_eq!(weeks.len(), 4);
+        assert_eq!(
+            weeks.iter().map(|w| w.avg).collect::<Vec<_>>(),
+            [2.0, 3.0, 4.0, 5.0]
+        );
+        assert!(weeks.iter().all(|w| w.count == 28 && w.min == w.max));
+        assert_eq!(weeks[1].start, START + 7 * DAY);
+
+        // Days spanning the second and third weeks
+        let days = store
+            .analytics_series(
+                AnalyticsMetric::Bridges,
+                START + 13 * DAY,
+                START + 15 * DAY - 1,
+                2,
+            )
+            .unwrap();
+
+        assert_eq!(days.len(), 2);
+        assert_eq!((days[0].avg, days[1].avg), (3.0, 4.0));
+
+        // Metrics that weren't selected aren't stored
+        assert!(store
+            .analytics_series(AnalyticsMetric::NodeCount, START, START + 28 * DAY, 4)
+            .unwrap()
+            .is_empty());
+
+        // Nor are metrics of sections the report doesn't include
+        let report = chain_report(3, START, vec![AnalyticsSection::Stats]);
+        assert_eq!(
+            report.metric_values(&selected),
+            [(AnalyticsMetric::Diameter, 2.0)]
+        );
+        assert!(report.metric_values(&[]).is_empty());
+
+        assert!(store
+            .analytics_series(AnalyticsMetric::Diameter, START, START + DAY, 0)
+            .is_err());
+    }
+
+    #[test]
+    fn splits_reports_into_samples() {
+        let device = protobufs::Telemetry {
+            variant: Some(protobufs::telemetry::Variant::DeviceMetrics(
+                protobufs::DeviceMetrics {
+                    battery_level: 87,
+                    voltage: 4.1,
+                    channel_utilization: 12.5,
+                    air_util_tx: 1.25,
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        };
+
+        let samples = telemetry_samples(NODE, 500, &device);
+        assert_eq!(samples.len(), 4);
+        assert_eq!(samples[0].metric, TelemetryMetric::BatteryLevel);
+        assert_eq!(samples[0].value, 87.0);
+
+        let environment = protobufs::Telemetry {
+            variant: Some(protobufs::telemetry::Variant::EnvironmentMetrics(
+                protobufs::EnvironmentMetrics {
+                    temperature: 18.5,
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        };
+
+        let samples = telemetry_samples(NODE, 500, &environment);
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].metric.class(), TelemetryMetricClass::Environment);
+    }
+}
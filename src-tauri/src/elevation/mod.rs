@@ -0,0 +1,18 @@
+pub mod profile;
+
+/// Source of terrain elevations, e.g. tiles of a digital elevation model
+pub trait ElevationProvider: Send + Sync {
+    /// Terrain elevation in meters above sea level, or `None` where no tile covers
+    /// the coordinate
+    fn elevation_meters(&self, latitude: f64, longitude: f64) -> Option<f64>;
+}
+
+/// Synthetic terrain computed from the coordinate, for tests and simulations
+impl<F> ElevationProvider for F
+where
+    F: Fn(f64, f64) -> Option<f64> + Send + Sync,
+{
+    fn elevation_meters(&self, latitude: f64, longitude: f64) -> Option<f64> {
+        self(latitude, longitude)
+    }
+}
@@ -0,0 +1,304 @@
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::device::{
+    helpers::{haversine_distance_meters, EARTH_RADIUS_METERS},
+    MeshDevice,
+};
+use crate::graph::{ds::graph::MeshGraph, route::find_route};
+
+use super::ElevationProvider;
+
+/// More samples per leg than this would be finer than any elevation model's resolution
+pub const MAX_PROFILE_SAMPLES_PER_LEG: u32 = 1_000;
+
+/// Radio waves bend slightly with the atmosphere, which is modelled as line of sight
+/// over an Earth this much larger than it is
+const EFFECTIVE_EARTH_RADIUS_FACTOR: f64 = 4.0 / 3.0;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ElevationSample {
+    pub distance_meters: f64,          // from the start of the route
+    pub elevation_meters: Option<f64>, // `None` where the elevation model has no data
+    pub leg_index: u32,
+}
+
+/// Terrain clearance below the line of sight between the two ends of a leg. Clearance
+/// is negative where the terrain blocks the line of sight.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct LegClearance {
+    pub leg_index: u32,
+    pub from_node: u32,
+    pub to_node: u32,
+    pub length_meters: f64,
+    pub missing_samples: u32,
+    pub min_clearance_meters: Option<f64>, // `None` without node altitudes or elevations
+    pub mean_clearance_meters: Option<f64>,
+    pub obstructed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteElevationProfile {
+    pub path: Vec<u32>,
+    pub unpositioned_nodes: Vec<u32>, // skipped, so a leg may span several hops
+    pub samples: Vec<ElevationSample>,
+    pub legs: Vec<LegClearance>,
+}
+
+struct ProfileNode {
+    node_num: u32,
+    latitude: f64,
+    longitude: f64,
+    altitude: Option<f64>,
+}
+
+/// How far the Earth bulges above the straight line between two points this far from
+/// a point along it
+fn earth_bulge_meters(from_start: f64, to_end: f64) -> f64 {
+    from_start * to_end / (2.0 * EFFECTIVE_EARTH_RADIUS_FACTOR * EARTH_RADIUS_METERS)
+}
+
+/// Samples the terrain at `samples_per_leg` evenly spaced points along each leg of the
+/// route with the fewest hops, including both ends of each leg. A node's altitude is
+/// its reported altitude, or the terrain elevation at its position if it reported none.
+pub fn build_route_elevation_profile(
+    graph: &MeshGraph,
+    device: &MeshDevice,
+    elevation: &dyn ElevationProvider,
+    from_node: u32,
+    to_node: u32,
+    samples_per_leg: u32,
+) -> Result<RouteElevationProfile, String> {
+    if !(2..=MAX_PROFILE_SAMPLES_PER_LEG).contains(&samples_per_leg) {
+        return Err(format!(
+            "Samples per leg must be between 2 and {}",
+            MAX_PROFILE_SAMPLES_PER_LEG
+        ));
+    }
+
+    let route = find_route(graph, device, from_node, to_node)?
+        .ok_or_else(|| format!("No route from {} to {}", from_node, to_node))?;
+
+    let mut nodes = vec![];
+    let mut unpositioned_nodes = vec![];
+
+    for node_num in &route.path {
+        let position = device
+            .nodes
+            .get(node_num)
+            .and_then(|node| node.last_known_position());
+
+        match position {
+            Some(position) => {
+                let (latitude, longitude): (f64, f64) =
+                    (position.latitude.into(), position.longitude.into());
+                let altitude = match position.altitude {
+                    0 => elevation.elevation_meters(latitude, longitude),
+                    altitude => Some(altitude.into()),
+                };
+
+                nodes.push(ProfileNode {
+                    node_num: *node_num,
+                    latitude,
+                    longitude,
+                    altitude,
+                });
+            }
+            None => unpositioned_nodes.push(*node_num),
+        }
+    }
+
+    let mut samples = vec![];
+    let mut legs = vec![];
+    let mut leg_start_meters = 0.0;
+
+    for (leg_index, leg) in nodes.windows(2).enumerate() {
+        let (from, to) = (&leg[0], &leg[1]);
+        let length_meters =
+            haversine_distance_meters(from.latitude, from.longitude, to.latitude, to.longitude);
+
+        let mut clearances = vec![];
+        let mut missing_samples = 0;
+
+        for step in 0..samples_per_leg {
+            let t = step as f64 / (samples_per_leg - 1) as f64;
+            let elevation_meters = elevation.elevation_meters(
+                from.latitude + (to.latitude - from.latitude) * t,
+                from.longitude + (to.longitude - from.longitude) * t,
+            );
+
+            match (elevation_meters, from.altitude, to.altitude) {
+                (Some(ground), Some(from_altitude), Some(to_altitude)) => {
+                    let line_of_sight = from_altitude + (to_altitude - from_altitude) * t;
+                    let bulge = earth_bulge_meters(t * length_meters, (1.0 - t) * length_meters);
+                    clearances.push(line_of_sight - ground - bulge);
+                }
+                (None, _, _) => missing_samples += 1,
+                _ => {}
+            }
+
+            samples.push(ElevationSample {
+                distance_meters: leg_start_meters + t * length_meters,
+                elevation_meters,
+                leg_index: leg_index as u32,
+            });
+        }
+
+        let min_clearance_meters = clearances.iter().copied().reduce(f64::min);
+        let mean_clearance_meters = (!clearances.is_empty())
+            .then(|| clearances.iter().sum::<f64>() / clearances.len() as f64);
+
+        legs.push(LegClearance {
+            leg_index: leg_index as u32,
+            from_node: from.node_num,
+            to_node: to.node_num,
+            length_meters,
+            missing_samples,
+            min_clearance_meters,
+            mean_clearance_meters,
+            obstructed: min_clearance_meters.map_or(false, |c| c < 0.0),
+        });
+
+        leg_start_meters += length_meters;
+    }
+
+    Ok(RouteElevationProfile {
+        path: route.path,
+        unpositioned_nodes,
+        samples,
+        legs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::{MeshNode, NormalizedPosition};
+    use crate::graph::ds::edge::GraphEdge;
+    use crate::graph::ds::fixtures::graph_node;
+
+    fn positioned_node(node_num: u32, latitude: f32, longitude: f32, altitude: i32) -> MeshNode {
+        let mut node = MeshNode::new(node_num);
+        node.position_metrics
+            .push(NormalizedPosition::from(protobufs::Position {
+                latitude_i: (latitude * 1e7) as i32,
+                longitude_i: (longitude * 1e7) as i32,
+                altitude,
+                ..Default::default()
+            }));
+        node
+    }
+
+    /// Nodes 1 - 2 - 3 along the 47th parallel, ~7.6 km apart, on 10 m masts over a
+    /// plain at 100 m. A 250 m ridge runs across the second leg at 8.15°E.
+    fn fixture() -> (MeshGraph, MeshDevice) {
+        let mut graph = MeshGraph::new();
+        let mut device = MeshDevice::new();
+
+        for (node_num, longitude) in [(1, 8.0), (2, 8.1), (3, 8.2)] {
+            graph.upsert_node(graph_node(node_num));
+            device
+                .nodes
+                .insert(node_num, positioned_node(node_num, 47.0, longitude, 110));
+        }
+
+        graph.upsert_edge(graph_node(1), graph_node(2), GraphEdge::manual(1, 2, 5.0));
+        graph.upsert_edge(graph_node(2), graph_node(3), GraphEdge::manual(2, 3, 5.0));
+
+        (graph, device)
+    }
+
+    fn terrain(_latitude: f64, longitude: f64) -> Option<f64> {
+        if (longitude - 8.15).abs() < 0.006 {
+            Some(250.0)
+        } else {
+            Some(100.0)
+        }
+    }
+
+    #[test]
+    fn samples_each_leg_evenly() {
+        let (graph, device) = fixture();
+
+        let profile = build_route_elevation_profile(&graph, &device, &terrain, 1, 3, 11).unwrap();
+
+        assert_eq!(profile.path, [1, 2, 3]);
+        assert_eq!(profile.samples.len(), 22);
+        assert_eq!(profile.legs.len(), 2);
+
+        let leg_length = haversine_distance_meters(47.0, 8.0, 47.0, 8.1);
+        assert!((profile.legs[0].length_meters - leg_length).abs() < 1.0);
+
+        let first_leg: Vec<_> = profile
+            .samples
+            .iter()
+            .filter(|s| s.leg_index == 0)
+            .collect();
+        assert_eq!(first_leg.len(), 11);
+        assert_eq!(first_leg[0].distance_meters, 0.0);
+        assert!((first_leg[5].distance_meters - leg_length / 2.0).abs() < 1.0);
+
+        let last = profile.samples.last().unwrap();
+        assert_eq!(last.leg_index, 1);
+        assert!((last.distance_meters - 2.0 * leg_length).abs() < 2.0);
+
+        // 10 m masts clear the plain, less the Earth's bulge, but not the ridge
+        let clear = &profile.legs[0];
+        assert!(!clear.obstructed);
+        assert!(clear.min_clearance_meters.unwrap() > 8.0);
+        assert!(clear.min_clearance_meters.unwrap() <= 10.0);
+
+        let blocked = &profile.legs[1];
+        assert!(blocked.obstructed);
+        assert!(blocked.min_clearance_meters.unwrap() < -100.0);
+    }
+
+    #[test]
+    fn missing_tiles_leave_gaps() {
+        let (graph, device) = fixture();
+
+        // No tile covers the eastern part of the second leg
+        let patchy = |latitude: f64, longitude: f64| {
+            (longitude < 8.16)
+                .then(|| terrain(latitude, longitude))
+                .flatten()
+        };
+
+        let profile = build_route_elevation_profile(&graph, &device, &patchy, 1, 3, 5).unwrap();
+
+        let gaps: Vec<_> = profile
+            .samples
+            .iter()
+            .map(|s| s.elevation_meters.is_none())
+            .collect();
+        assert_eq!(
+            gaps,
+            [false, false, false, false, false, false, false, false, true, true]
+        );
+
+        assert_eq!(profile.legs[0].missing_samples, 0);
+        assert_eq!(profile.legs[1].missing_samples, 2);
+        assert!(profile.legs[1].min_clearance_meters.is_some());
+
+        let nowhere = |_: f64, _: f64| -> Option<f64> { None };
+        let profile = build_route_elevation_profile(&graph, &device, &nowhere, 1, 3, 5).unwrap();
+        assert!(profile.samples.iter().all(|s| s.elevation_meters.is_none()));
+        assert!(profile
+            .legs
+            .iter()
+            .all(|l| l.min_clearance_meters.is_none()));
+    }
+
+    #[test]
+    fn rejects_invalid_requests() {
+        let (graph, device) = fixture();
+
+        assert!(build_route_elevation_profile(&graph, &device, &terrain, 1, 3, 1).is_err());
+        assert!(build_route_elevation_profile(&graph, &device, &terrain, 1, 99, 5).is_err());
+    }
+}
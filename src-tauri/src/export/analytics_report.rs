@@ -0,0 +1,356 @@
+use std::time::Duration;
+
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::device::MeshDevice;
+use crate::graph::{
+    analytics::{
+        cut_points, graph_stats, node_centralities, resilience_summary, CutPoints, GraphStats,
+        NodeCentrality, ResilienceSummary,
+    },
+    clustering::{find_clusters, ClusterSource},
+    ds::{graph::MeshGraph, spatial_index::SpatialIndex},
+};
+use crate::state::DeviceKey;
+
+/// Version of the report's JSON structure, bumped on breaking changes
+pub const ANALYTICS_REPORT_VERSION: u32 = 1;
+
+/// Longest the sections of a report may take to compute
+pub const ANALYTICS_REPORT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum AnalyticsSection {
+    Stats,
+    Centralities,
+    Communities,
+    CutPoints,
+    Resilience,
+}
+
+pub const ANALYTICS_SECTIONS: [AnalyticsSection; 5] = [
+    AnalyticsSection::Stats,
+    AnalyticsSection::Centralities,
+    AnalyticsSection::Communities,
+    AnalyticsSection::CutPoints,
+    AnalyticsSection::Resilience,
+];
+
+/// Sorts and deduplicates the requested sections, which default to all of them
+pub fn resolve_report_sections(
+    sections: Option<Vec<AnalyticsSection>>,
+) -> Result<Vec<AnalyticsSection>, String> {
+    let mut sections = match sections {
+        Some(sections) if sections.is_empty() => {
+            return Err("A report needs at least one section".into())
+        }
+        Some(sections) => sections,
+        None => ANALYTICS_SECTIONS.to_vec(),
+    };
+
+    sections.sort_unstable();
+    sections.dedup();
+
+    Ok(sections)
+}
+
+/// The connected device the report was generated from
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportDevice {
+    pub device_key: DeviceKey,
+    pub node_num: u32,
+    pub long_name: Option<String>,
+    pub short_name: Option<String>,
+}
+
+impl ReportDevice {
+    pub fn new(device_key: DeviceKey, device: &MeshDevice) -> Self {
+        let node_num = device.my_node_info.my_node_num;
+        let user = device
+            .nodes
+            .get(&node_num)
+            .and_then(|node| node.user.as_ref());
+
+        Self {
+            device_key,
+            node_num,
+            long_name: user.map(|user| user.long_name.clone()),
+            short_name: user.map(|user| user.short_name.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyticsReportMetadata {
+    pub report_version: u32,
+    pub generated_at: u32, // secs
+
+    /// Sequence of the last graph update sent to the UI for the device, if any, so
+    /// the report can be matched to what was on screen
+    pub graph_version: Option<u32>,
+    pub device: ReportDevice,
+    pub sections: Vec<AnalyticsSection>,
+}
+
+/// Network health report. Sections that weren't requested are `null`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyticsReport {
+    pub metadata: AnalyticsReportMetadata,
+    pub stats: Option<GraphStats>,
+    pub centralities: Option<Vec<NodeCentrality>>,
+    pub communities: Option<Vec<Vec<u32>>>, // members of each community, by lowest member
+    pub cut_points: Option<CutPoints>,
+    pub resilience: Option<ResilienceSummary>,
+}
+
+/// Scalar metrics of a report that are kept as a series, for trends over longer than
+/// a session
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum AnalyticsMetric {
+    NodeCount,
+    EdgeCount,
+    LinkCount,
+    ConnectedComponents,
+    LargestComponentSize,
+    AverageDegree,
+    Density,
+    Diameter,
+    GlobalClustering,
+    ArticulationPoints,
+    Bridges,
+}
+
+pub const ANALYTICS_METRICS: [AnalyticsMetric; 11] = [
+    AnalyticsMetric::NodeCount,
+    AnalyticsMetric::EdgeCount,
+    AnalyticsMetric::LinkCount,
+    AnalyticsMetric::ConnectedComponents,
+    AnalyticsMetric::LargestComponentSize,
+    AnalyticsMetric::AverageDegree,
+    AnalyticsMetric::Density,
+    AnalyticsMetric::Diameter,
+    AnalyticsMetric::GlobalClustering,
+    AnalyticsMetric::ArticulationPoints,
+    AnalyticsMetric::Bridges,
+];
+
+impl AnalyticsMetric {
+    /// Name stored in the `metric` column
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnalyticsMetric::NodeCount => "nodeCount",
+            AnalyticsMetric::EdgeCount => "edgeCount",
+            AnalyticsMetric::LinkCount => "linkCount",
+            AnalyticsMetric::ConnectedComponents => "connectedComponents",
+            AnalyticsMetric::LargestComponentSize => "largestComponentSize",
+            AnalyticsMetric::AverageDegree => "averageDegree",
+            AnalyticsMetric::Density => "density",
+            AnalyticsMetric::Diameter => "diameter",
+            AnalyticsMetric::GlobalClustering => "globalClustering",
+            AnalyticsMetric::ArticulationPoints => "articulationPoints",
+            AnalyticsMetric::Bridges => "bridges",
+        }
+    }
+}
+
+/// Computes the sections listed in `metadata`. Resilience depends on the cut points,
+/// which are computed once for both.
+pub fn build_analytics_report(
+    graph: &MeshGraph,
+    metadata: AnalyticsReportMetadata,
+) -> Result<AnalyticsReport, String> {
+    let includes = |section| metadata.sections.contains(&section);
+
+    let communities = includes(AnalyticsSection::Communities)
+        .then(|| find_clusters(graph, &SpatialIndex::default(), &ClusterSource::Community))
+        .transpose()?;
+
+    let graph_cut_points = (includes(AnalyticsSection::CutPoints)
+        || includes(AnalyticsSection::Resilience))
+    .then(|| cut_points(graph));
+
+    let resilience = match &graph_cut_points {
+        Some(cut_points) if includes(AnalyticsSection::Resilience) => {
+            Some(resilience_summary(graph, cut_points))
+        }
+        _ => None,
+    };
+
+    Ok(AnalyticsReport {
+        stats: includes(AnalyticsSection::Stats).then(|| graph_stats(graph)),
+        centralities: includes(AnalyticsSection::Centralities).then(|| node_centralities(graph)),
+        communities,
+        cut_points: graph_cut_points.filter(|_| includes(AnalyticsSection::CutPoints)),
+        resilience,
+        metadata,
+    })
+}
+
+impl AnalyticsReport {
+    /// Values of the `selected` metrics the report's sections include, in the order
+    /// they're selected
+    pub fn metric_values(&self, selected: &[AnalyticsMetric]) -> Vec<(AnalyticsMetric, f64)> {
+        let stats = self.stats.as_ref();
+        let resilience = self.resilience.as_ref();
+
+        selected
+            .iter()
+            .filter_map(|metric| {
+                let value = match metric {
+                    AnalyticsMetric::NodeCount => stats.map(|s| f64::from(s.node_count)),
+                    AnalyticsMetric::EdgeCount => stats.map(|s| f64::from(s.edge_count)),
+                    AnalyticsMetric::LinkCount => stats.map(|s| f64::from(s.link_count)),
+                    AnalyticsMetric::ConnectedComponents => {
+                        stats.map(|s| f64::from(s.connected_components))
+                    }
+                    AnalyticsMetric::LargestComponentSize => {
+                        stats.map(|s| f64::from(s.largest_component_size))
+                    }
+                    AnalyticsMetric::AverageDegree => stats.map(|s| s.average_degree),
+                    AnalyticsMetric::Density => stats.map(|s| s.density),
+                    AnalyticsMetric::Diameter => stats.map(|s| f64::from(s.diameter)),
+                    AnalyticsMetric::GlobalClustering => stats.map(|s| s.global_clustering),
+                    AnalyticsMetric::ArticulationPoints => {
+                        resilience.map(|r| f64::from(r.articulation_point_count))
+                    }
+                    AnalyticsMetric::Bridges => resilience.map(|r| f64::from(r.bridge_count)),
+                };
+
+                value.map(|value| (*metric, value))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+    use serde_json::json;
+
+    use super::*;
+    use crate::device::MeshNode;
+    use crate::graph::ds::edge::GraphEdge;
+    use crate::graph::ds::fixtures::graph_node;
+
+    /// Two triangles sharing node 3
+    fn fixture() -> (MeshGraph, MeshDevice) {
+        let mut graph = MeshGraph::new();
+
+        for node_num in 1..=5 {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        for (from, to) in [(1, 2), (2, 3), (3, 1), (3, 4), (4, 5), (5, 3)] {
+            graph.upsert_edge(
+                graph_node(from),
+                graph_node(to),
+                GraphEdge::manual(from, to, 5.0),
+            );
+        }
+
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 1;
+
+        let mut node = MeshNode::new(1);
+        node.user = Some(protobufs::User {
+            long_name: "Base Camp".into(),
+            short_name: "BC".into(),
+            ..Default::default()
+        });
+        device.nodes.insert(1, node);
+
+        (graph, device)
+    }
+
+    fn metadata(device: &MeshDevice, sections: Vec<AnalyticsSection>) -> AnalyticsReportMetadata {
+        AnalyticsReportMetadata {
+            report_version: ANALYTICS_REPORT_VERSION,
+            generated_at: 1_700_000_000,
+            graph_version: Some(7),
+            device: ReportDevice::new("/dev/ttyUSB0".into(), device),
+            sections,
+        }
+    }
+
+    #[test]
+    fn full_report_has_every_section() {
+        let (graph, device) = fixture();
+        let sections = resolve_report_sections(None).unwrap();
+
+        let report = build_analytics_report(&graph, metadata(&device, sections)).unwrap();
+        let value = serde_json::to_value(&report).unwrap();
+
+        assert_eq!(
+            value["metadata"],
+            json!({
+                "reportVersion": ANALYTICS_REPORT_VERSION,
+                "generatedAt": 1_700_000_000,
+                "graphVersion": 7,
+                "device": {
+                    "deviceKey": "/dev/ttyUSB0",
+                    "nodeNum": 1,
+                    "longName": "Base Camp",
+                    "shortName": "BC",
+                },
+                "sections": ["stats", "centralities", "communities", "cutPoints", "resilience"],
+            })
+        );
+
+        assert_eq!(value["stats"]["nodeCount"], json!(5));
+        assert_eq!(value["stats"]["linkCount"], json!(6));
+        assert_eq!(value["centralities"][0]["nodeNum"], json!(3));
+
+        let communities = report.communities.as_ref().unwrap();
+        assert_eq!(communities.len(), 2);
+        assert_eq!(communities.concat().len(), 5);
+
+        assert_eq!(value["cutPoints"]["articulationPoints"], json!([3]));
+        assert_eq!(value["cutPoints"]["bridges"], json!([]));
+        assert_eq!(
+            value["resilience"]["mostCriticalNode"],
+            json!({ "nodeNum": 3, "componentsWithout": 2 })
+        );
+
+        let parsed: AnalyticsReport = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed, report);
+    }
+
+    #[test]
+    fn only_selected_sections_are_computed() {
+        let (graph, device) = fixture();
+
+        let sections = resolve_report_sections(Some(vec![
+            AnalyticsSection::Resilience,
+            AnalyticsSection::Stats,
+            AnalyticsSection::Resilience,
+        ]))
+        .unwrap();
+        assert_eq!(
+            sections,
+            [AnalyticsSection::Stats, AnalyticsSection::Resilience]
+        );
+
+        let report = build_analytics_report(&graph, metadata(&device, sections)).unwrap();
+
+        assert!(report.stats.is_some());
+        assert!(report.resilience.is_some());
+        assert!(report.centralities.is_none());
+        assert!(report.communities.is_none());
+        assert!(report.cut_points.is_none());
+
+        let value = serde_json::to_value(&report).unwrap();
+        assert_eq!(value["cutPoints"], json!(null));
+        assert_eq!(
+            value["metadata"]["sections"],
+            json!(["stats", "resilience"])
+        );
+
+        assert!(resolve_report_sections(Some(vec![])).is_err());
+    }
+}
@@ -0,0 +1,39 @@
+/// Escapes a single CSV field according to RFC 4180. Fields containing
+/// commas, quotes, or line breaks are wrapped in quotes, with any
+/// embedded quotes doubled.
+pub fn escape_csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Joins the fields of a single CSV record, escaping each field
+/// and terminating the record with a CRLF line break.
+pub fn to_csv_record<S: AsRef<str>>(fields: &[S]) -> String {
+    let escaped: Vec<String> = fields
+        .iter()
+        .map(|f| escape_csv_field(f.as_ref()))
+        .collect();
+
+    format!("{}\r\n", escaped.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_special_characters() {
+        assert_eq!(escape_csv_field("plain"), "plain");
+        assert_eq!(escape_csv_field("a,b"), "\"a,b\"");
+        assert_eq!(escape_csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(escape_csv_field("line\nbreak"), "\"line\nbreak\"");
+    }
+
+    #[test]
+    fn builds_records() {
+        assert_eq!(to_csv_record(&["a", "b,c", ""]), "a,\"b,c\",\r\n");
+    }
+}
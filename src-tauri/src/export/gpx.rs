@@ -0,0 +1,337 @@
+use meshtastic::protobufs::config::device_config::Role;
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::device::{MeshDevice, MeshNode, NormalizedPosition};
+use crate::graph::ds::graph::MeshGraph;
+
+use super::xml::XmlWriter;
+
+const GPX_CREATOR: &str = "Meshtastic Network Management Client";
+const GPX_NAMESPACE: &str = "http://www.topografix.com/GPX/1/1";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct GpxExportOptions {
+    pub route_between: Option<(u32, u32)>, // node nums of the route's start and end
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct GpxExportSummary {
+    pub waypoints: u32,
+    pub route_points: u32,
+    pub bytes: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GpxDocument {
+    pub contents: String,
+    pub waypoints: u32,
+    pub route_points: u32,
+}
+
+fn node_name(graph: &MeshGraph, node: &MeshNode) -> String {
+    let long_name = node.user.as_ref().map(|user| user.long_name.as_str());
+
+    graph
+        .display_label(node.node_num, long_name)
+        .unwrap_or_else(|| format!("!{:08x}", node.node_num))
+}
+
+/// Only the role of the connected node is known, other nodes don't report theirs
+fn node_role(device: &MeshDevice, node_num: u32) -> Option<Role> {
+    if node_num != device.my_node_info.my_node_num {
+        return None;
+    }
+
+    device
+        .config
+        .device
+        .as_ref()
+        .and_then(|config| Role::from_i32(config.role))
+}
+
+/// Garmin symbol names, which OsmAnd also understands
+fn waypoint_symbol(role: Option<Role>) -> &'static str {
+    match role {
+        Some(Role::Router | Role::RouterClient | Role::Repeater) => "Radio Beacon",
+        Some(Role::Tracker) => "Flag, Blue",
+        _ => "Waypoint",
+    }
+}
+
+fn waypoint_description(node: &MeshNode) -> String {
+    let battery = match node.battery_level() {
+        Some(level) if level > 100 => "plugged in".to_string(),
+        Some(level) => format!("{}%", level),
+        None => "unknown".to_string(),
+    };
+
+    let last_heard = node
+        .last_heard
+        .as_ref()
+        .and_then(|heard| chrono::DateTime::from_timestamp(heard.timestamp.into(), 0))
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| "never".to_string());
+
+    format!("Battery: {}, last heard: {}", battery, last_heard)
+}
+
+fn point_attributes(position: &NormalizedPosition) -> [(&'static str, String); 2] {
+    [
+        ("lat", format!("{:.7}", position.latitude)),
+        ("lon", format!("{:.7}", position.longitude)),
+    ]
+}
+
+/// Builds a GPX 1.1 document with a waypoint per positioned node and, if requested,
+/// a route along the fewest-hop path between two nodes. Nodes on the path without
+/// a known position are left out of the route.
+pub fn build_gpx(
+    graph: &MeshGraph,
+    device: &MeshDevice,
+    options: &GpxExportOptions,
+) -> Result<GpxDocument, String> {
+    let route = match options.route_between {
+        Some((from, to)) => Some(
+            graph
+                .shortest_path(from, to)
+                .ok_or_else(|| format!("No route between nodes {} and {}", from, to))?,
+        ),
+        None => None,
+    };
+
+    let mut nodes: Vec<&MeshNode> = device.nodes.values().collect();
+    nodes.sort_by_key(|node| node.node_num);
+
+    let mut writer = XmlWriter::new();
+    writer.open(
+        "gpx",
+        &[
+            ("version", "1.1".into()),
+            ("creator", GPX_CREATOR.into()),
+            ("xmlns", GPX_NAMESPACE.into()),
+        ],
+    );
+
+    let mut waypoints = 0;
+
+    for node in &nodes {
+        let position = match node.last_known_position() {
+            Some(p) => p,
+            None => continue,
+        };
+
+        writer.open("wpt", &point_attributes(position));
+
+        if position.altitude != 0 {
+            writer.element("ele", &[], &position.altitude.to_string());
+        }
+
+        writer.element("name", &[], &node_name(graph, node));
+        writer.element("desc", &[], &waypoint_description(node));
+        writer.element(
+            "sym",
+            &[],
+            waypoint_symbol(node_role(device, node.node_num)),
+        );
+        writer.close();
+
+        waypoints += 1;
+    }
+
+    let mut route_points = 0;
+
+    if let Some(path) = route {
+        writer.open("rte", &[]);
+
+        let name = |node_num: &u32| {
+            device
+                .nodes
+                .get(node_num)
+                .map(node_name)
+                .unwrap_or_else(|| format!("!{:08x}", node_num))
+        };
+
+        if let (Some(first), Some(last)) = (path.first(), path.last()) {
+            writer.element("name", &[], &format!("{} to {}", name(first), name(last)));
+        }
+
+        for node_num in &path {
+            let position = match device
+                .nodes
+                .get(node_num)
+                .and_then(|node| node.last_known_position())
+            {
+                Some(p) => p,
+                None => continue,
+            };
+
+            writer.open("rtept", &point_attributes(position));
+            writer.element("name", &[], &name(node_num));
+            writer.close();
+
+            route_points += 1;
+        }
+
+        writer.close();
+    }
+
+    Ok(GpxDocument {
+        contents: writer.finish(),
+        waypoints,
+        route_points,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::export::xml::parse::parse_document;
+    use crate::graph::ds::fixtures::{edge, graph_node};
+
+    fn node(node_num: u32, name: &str, position: Option<(f32, f32)>) -> MeshNode {
+        let mut node = MeshNode::new(node_num);
+        node.user = Some(protobufs::User {
+            long_name: name.into(),
+            ..Default::default()
+        });
+
+        if let Some((latitude, longitude)) = position {
+            node.position_metrics
+                .push(NormalizedPosition::from(protobufs::Position {
+                    latitude_i: (latitude * 1e7) as i32,
+                    longitude_i: (longitude * 1e7) as i32,
+                    altitude: 120,
+                    ..Default::default()
+                }));
+        }
+
+        node
+    }
+
+    /// Chain 1 - 2 - 3 - 4, where node 3 has no position
+    fn fixture() -> (MeshGraph, MeshDevice) {
+        let mut graph = MeshGraph::new();
+
+        for node_num in 1..=4 {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        graph.upsert_edge(graph_node(1), graph_node(2), edge(1, 2, 0.0));
+        graph.upsert_edge(graph_node(3), graph_node(2), edge(3, 2, 0.0));
+        graph.upsert_edge(graph_node(3), graph_node(4), edge(3, 4, 0.0));
+
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 1;
+        device.config.device = Some(protobufs::config::DeviceConfig {
+            role: Role::Router as i32,
+            ..Default::default()
+        });
+        device
+            .nodes
+            .insert(1, node(1, "Tom & Jerry", Some((45.5, -73.6))));
+        device
+            .nodes
+            .insert(2, node(2, "<Hilltop>", Some((45.6, -73.5))));
+        device.nodes.insert(3, node(3, "Basement", None));
+        device
+            .nodes
+            .insert(4, node(4, "Cabin", Some((45.7, -73.4))));
+
+        (graph, device)
+    }
+
+    #[test]
+    fn writes_waypoint_per_positioned_node() {
+        let (graph, device) = fixture();
+        let gpx = build_gpx(&graph, &device, &GpxExportOptions::default()).unwrap();
+
+        assert_eq!(gpx.waypoints, 3);
+        assert_eq!(gpx.route_points, 0);
+
+        let root = parse_document(&gpx.contents).unwrap();
+        assert_eq!(root.name, "gpx");
+        assert_eq!(root.attribute("version"), Some("1.1"));
+        assert_eq!(root.attribute("xmlns"), Some(GPX_NAMESPACE));
+        assert!(root.child("rte").is_none());
+
+        let waypoints: Vec<_> = root.children_named("wpt").collect();
+        assert_eq!(waypoints.len(), 3);
+
+        let own = waypoints[0];
+        let coordinate = |key| own.attribute(key).unwrap().parse::<f64>().unwrap();
+        assert!((coordinate("lat") - 45.5).abs() < 1e-5);
+        assert!((coordinate("lon") + 73.6).abs() < 1e-5);
+        assert_eq!(own.child("ele").unwrap().text, "120");
+        assert_eq!(own.child("sym").unwrap().text, "Radio Beacon");
+        assert_eq!(
+            own.child("desc").unwrap().text,
+            "Battery: unknown, last heard: never"
+        );
+
+        assert_eq!(waypoints[1].child("sym").unwrap().text, "Waypoint");
+    }
+
+    #[test]
+    fn escapes_node_names() {
+        let (graph, device) = fixture();
+        let gpx = build_gpx(&graph, &device, &GpxExportOptions::default()).unwrap();
+
+        assert!(gpx.contents.contains("<name>Tom &amp; Jerry</name>"));
+        assert!(gpx.contents.contains("<name>&lt;Hilltop&gt;</name>"));
+        assert!(!gpx.contents.contains("Tom & Jerry"));
+
+        let root = parse_document(&gpx.contents).unwrap();
+        let names: Vec<_> = root
+            .children_named("wpt")
+            .map(|wpt| wpt.child("name").unwrap().text.clone())
+            .collect();
+
+        assert_eq!(names, vec!["Tom & Jerry", "<Hilltop>", "Cabin"]);
+    }
+
+    #[test]
+    fn names_waypoints_with_operator_labels() {
+        let (mut graph, device) = fixture();
+        graph.set_node_label(1, Some("Home".into()));
+
+        let gpx = build_gpx(&graph, &device, &GpxExportOptions::default()).unwrap();
+
+        assert!(gpx.contents.contains("<name>Home</name>"));
+        assert!(!gpx.contents.contains("Tom &amp; Jerry"));
+    }
+
+    #[test]
+    fn writes_route_along_shortest_path() {
+        let (graph, device) = fixture();
+        let options = GpxExportOptions {
+            route_between: Some((1, 4)),
+        };
+
+        let gpx = build_gpx(&graph, &device, &options).unwrap();
+
+        // Node 3 is on the path but has no position
+        assert_eq!(gpx.route_points, 3);
+
+        let root = parse_document(&gpx.contents).unwrap();
+        let route = root.child("rte").unwrap();
+
+        assert_eq!(route.child("name").unwrap().text, "Tom & Jerry to Cabin");
+
+        let names: Vec<_> = route
+            .children_named("rtept")
+            .map(|point| point.child("name").unwrap().text.clone())
+            .collect();
+
+        assert_eq!(names, vec!["Tom & Jerry", "<Hilltop>", "Cabin"]);
+
+        let unreachable = GpxExportOptions {
+            route_between: Some((1, 99)),
+        };
+        assert!(build_gpx(&graph, &device, &unreachable).is_err());
+    }
+}
@@ -0,0 +1,433 @@
+//! Standalone file holding one device's graph, for sharing a view of the mesh or
+//! comparing it with a later one. A snapshot can be opened as a read-only virtual
+//! device, or merged into the graph of a connected device.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use log::debug;
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::Manager;
+
+use crate::device::helpers::get_current_time_u32;
+use crate::device::{MeshDevice, SerialDeviceStatus};
+use crate::graph::ds::graph::MeshGraph;
+use crate::graph::geojson::GraphGeoJson;
+use crate::graph::store::{mark_graph_changed, GraphSnapshot};
+use crate::ipc::{events, ConfigurationStatus, DevicesListChange, EVENT_API_VERSION};
+use crate::packet_api::{actor::spawn_device, summary::ConnectionType, MeshPacketApi};
+use crate::state::mesh_devices::get_device;
+use crate::state::{self, graph::SharedGraph, DeviceKey};
+
+use super::write_export_file;
+
+/// Version of the snapshot file structure, bumped on breaking changes
+pub const GRAPH_SNAPSHOT_FILE_VERSION: u32 = 1;
+
+/// Key of the virtual device a snapshot is opened as
+pub const SNAPSHOT_DEVICE_KEY: &str = "snapshot";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphSnapshotMetadata {
+    pub file_version: u32,
+    pub app_version: String,
+    pub device_id: u32,  // node num of the device the graph was heard by
+    pub created_at: u32, // secs
+    pub node_count: u32,
+    pub edge_count: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum SnapshotLoadMode {
+    /// Opened as a separate, read-only device
+    ViewOnly,
+
+    /// Added to the device's graph. Nodes and edges it already has are kept, as
+    /// they were heard since connecting.
+    Merge { device_key: DeviceKey },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotLoadSummary {
+    pub metadata: GraphSnapshotMetadata,
+    pub device_key: DeviceKey, // device the snapshot was loaded into
+    pub nodes_loaded: u32,
+    pub edges_loaded: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphSnapshotFile {
+    metadata: GraphSnapshotMetadata,
+    snapshot: GraphSnapshot,
+}
+
+pub fn encode_graph_snapshot(snapshot: GraphSnapshot, created_at: u32) -> Result<String, String> {
+    let file = GraphSnapshotFile {
+        metadata: GraphSnapshotMetadata {
+            file_version: GRAPH_SNAPSHOT_FILE_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").into(),
+            device_id: snapshot.device_id(),
+            created_at,
+            node_count: snapshot.node_count() as u32,
+            edge_count: snapshot.edge_count() as u32,
+        },
+        snapshot,
+    };
+
+    serde_json::to_string_pretty(&file).map_err(|e| e.to_string())
+}
+
+/// Reads a snapshot file, rejecting files from newer versions of the app and files
+/// whose graph doesn't match their metadata or can't be loaded
+pub fn decode_graph_snapshot(
+    bytes: &[u8],
+) -> Result<(GraphSnapshotMetadata, GraphSnapshot), String> {
+    let file: Value =
+        serde_json::from_slice(bytes).map_err(|e| format!("Snapshot is not valid JSON: {}", e))?;
+
+    // Checked before the rest of the metadata, whose shape may change between versions
+    let file_version = file
+        .get("metadata")
+        .ok_or("Snapshot has no metadata")?
+        .get("fileVersion")
+        .and_then(Value::as_u64)
+        .ok_or("Snapshot metadata has no file version")?;
+
+    if file_version == 0 {
+        return Err("Snapshot has an invalid file version 0".into());
+    }
+
+    if file_version > GRAPH_SNAPSHOT_FILE_VERSION as u64 {
+        return Err(format!(
+            "Snapshot is from a newer version of the app (file version {}, this app reads up to {})",
+            file_version, GRAPH_SNAPSHOT_FILE_VERSION
+        ));
+    }
+
+    let file: GraphSnapshotFile =
+        serde_json::from_value(file).map_err(|e| format!("Invalid snapshot file: {}", e))?;
+    let (metadata, snapshot) = (file.metadata, file.snapshot);
+
+    if metadata.device_id != snapshot.device_id() {
+        return Err(format!(
+            "Snapshot is damaged, its graph is of device {} but its metadata names device {}",
+            snapshot.device_id(),
+            metadata.device_id
+        ));
+    }
+
+    if metadata.node_count as usize != snapshot.node_count()
+        || metadata.edge_count as usize != snapshot.edge_count()
+    {
+        return Err(format!(
+            "Snapshot is damaged, it has {} nodes and {} edges but its metadata lists {} and {}",
+            snapshot.node_count(),
+            snapshot.edge_count(),
+            metadata.node_count,
+            metadata.edge_count
+        ));
+    }
+
+    // Loaded into a scratch graph so a bad row is reported before any state changes
+    snapshot
+        .load_into(&mut MeshGraph::new(), &mut MeshDevice::new())
+        .map_err(|e| format!("Invalid snapshot graph: {}", e))?;
+
+    Ok((metadata, snapshot))
+}
+
+/// Read-only virtual device showing the snapshot's graph. Nothing is connected to
+/// it, so it never receives packets or sends any.
+pub fn snapshot_packet_api<R: tauri::Runtime>(
+    handle: tauri::AppHandle<R>,
+    snapshot: &GraphSnapshot,
+) -> Result<(MeshPacketApi<R>, (usize, usize)), String> {
+    let mut packet_api = MeshPacketApi::new(
+        handle,
+        SNAPSHOT_DEVICE_KEY.into(),
+        MeshDevice::new(),
+        Arc::new(SharedGraph::new(MeshGraph::new())),
+    );
+    packet_api.connection_type = ConnectionType::Snapshot;
+    packet_api.device.my_node_info.my_node_num = snapshot.device_id();
+    packet_api.device.set_status(SerialDeviceStatus::Connected);
+
+    let loaded = {
+        let graph_arc = packet_api.graph_arc.clone();
+        let mut graph = graph_arc.write()?;
+        snapshot.load_into(&mut graph, &mut packet_api.device)?
+    };
+
+    Ok((packet_api, loaded))
+}
+
+/// Writes the graph of a connected device to `path`
+pub async fn save_graph_snapshot(
+    handle: &tauri::AppHandle,
+    device_key: &DeviceKey,
+    path: &str,
+) -> Result<GraphSnapshotMetadata, String> {
+    let snapshot = {
+        let mesh_devices = handle.state::<state::mesh_devices::MeshDevicesState>();
+        let device = get_device(&mesh_devices.inner, device_key)
+            .await
+            .ok_or("Device not connected")?;
+        let packet_api = device.lock().await;
+
+        let graph = packet_api.read_graph()?;
+
+        GraphSnapshot::new(
+            packet_api.device.my_node_info.my_node_num,
+            &graph,
+            &packet_api.device,
+        )?
+    };
+
+    let contents = encode_graph_snapshot(snapshot, get_current_time_u32())?;
+    write_export_file(path, &contents).await?;
+
+    // Decoded again for the metadata, which also checks the file can be loaded
+    let (metadata, _) = decode_graph_snapshot(contents.as_bytes())?;
+
+    Ok(metadata)
+}
+
+/// Reads the snapshot at `path` and opens it as the `snapshot` device, replacing a
+/// snapshot already open, or merges it into a connected device's graph
+pub async fn load_graph_snapshot(
+    handle: &tauri::AppHandle,
+    path: &Path,
+    mode: SnapshotLoadMode,
+) -> Result<SnapshotLoadSummary, String> {
+    let bytes = tokio::fs::read(path)
+        .await
+        .map_err(|e| format!("Failed to read snapshot {:?}: {}", path, e))?;
+    let (metadata, snapshot) = decode_graph_snapshot(&bytes)?;
+
+    let mesh_devices = handle.state::<state::mesh_devices::MeshDevicesState>();
+
+    let (device_key, device, (nodes_loaded, edges_loaded)) = match mode {
+        SnapshotLoadMode::ViewOnly => {
+            let (packet_api, loaded) = snapshot_packet_api(handle.app_handle(), &snapshot)?;
+            let device = spawn_device(packet_api);
+            let replaced = mesh_devices
+                .inner
+                .lock()
+                .await
+                .insert(SNAPSHOT_DEVICE_KEY.into(), device.clone());
+
+            if let Some(replaced) = &replaced {
+                replaced.disconnect().await;
+            }
+
+            if replaced.is_none() {
+                events::dispatch_devices_list_changed(
+                    handle,
+                    SNAPSHOT_DEVICE_KEY.into(),
+                    DevicesListChange::Added,
+                    SerialDeviceStatus::Connected,
+                )
+                .map_err(|e| e.to_string())?;
+            }
+
+            events::dispatch_configuration_status(
+                handle,
+                ConfigurationStatus {
+                    api_version: EVENT_API_VERSION,
+                    device_key: SNAPSHOT_DEVICE_KEY.into(),
+                    successful: true,
+                    message: None,
+                },
+            )
+            .map_err(|e| e.to_string())?;
+
+            (SNAPSHOT_DEVICE_KEY.to_string(), device.packet_api(), loaded)
+        }
+        SnapshotLoadMode::Merge { device_key } => {
+            let device = get_device(&mesh_devices.inner, &device_key)
+                .await
+                .ok_or("Device not connected")?;
+            let mut packet_api = device.lock().await;
+
+            if packet_api.connection_type == ConnectionType::Snapshot {
+                return Err("Snapshots can't be merged into a read-only snapshot".into());
+            }
+
+            let graph_arc = packet_api.graph_arc.clone();
+            let mut graph = graph_arc.write()?;
+            let loaded = snapshot.load_into(&mut graph, &mut packet_api.device)?;
+
+            mark_graph_changed(&*packet_api);
+            drop(packet_api);
+
+            (device_key, device, loaded)
+        }
+    };
+
+    let packet_api = device.lock().await;
+    let graph = packet_api.graph_arc.view()?;
+
+    events::dispatch_updated_device(handle, &device_key, &packet_api.device)
+        .map_err(|e| e.to_string())?;
+    events::dispatch_updated_graph(handle, Some(device_key.clone()), graph.clone())
+        .map_err(|e| e.to_string())?;
+    events::dispatch_graph_geojson_update(
+        handle,
+        GraphGeoJson::new(device_key.clone(), &graph, &packet_api.device),
+    )
+    .map_err(|e| e.to_string())?;
+
+    debug!(
+        "Loaded {} nodes and {} edges from snapshot of device {} into {}",
+        nodes_loaded, edges_loaded, metadata.device_id, device_key
+    );
+
+    Ok(SnapshotLoadSummary {
+        metadata,
+        device_key,
+        nodes_loaded: nodes_loaded as u32,
+        edges_loaded: edges_loaded as u32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::graph::ds::{edge::GraphEdge, fixtures::graph_node};
+
+    fn edge(from: u32, snr: f32) -> GraphEdge {
+        let mut edge = GraphEdge::from_neighbor(
+            1,
+            0,
+            "LongFast".into(),
+            protobufs::Neighbor {
+                node_id: from,
+                snr,
+                ..Default::default()
+            },
+        );
+
+        // Heard when its nodes were, so fixtures built at different times are equal
+        edge.last_heard = graph_node(from).last_heard;
+        edge
+    }
+
+    fn fixture() -> GraphSnapshot {
+        let mut graph = MeshGraph::new();
+        for node_num in 1..=3 {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        graph.upsert_edge(graph_node(2), graph_node(1), edge(2, 4.5));
+        graph.upsert_edge(graph_node(3), graph_node(2), edge(3, -2.0));
+        graph.set_node_label(3, Some("Ridge".into()));
+
+        GraphSnapshot::new(1, &graph, &MeshDevice::new()).unwrap()
+    }
+
+    fn file_with_version(file_version: u32) -> Vec<u8> {
+        let contents = encode_graph_snapshot(fixture(), 1_700_000_000).unwrap();
+        let mut file: Value = serde_json::from_str(&contents).unwrap();
+        file["metadata"]["fileVersion"] = file_version.into();
+
+        serde_json::to_vec(&file).unwrap()
+    }
+
+    #[test]
+    fn round_trips_snapshot() {
+        let contents = encode_graph_snapshot(fixture(), 1_700_000_000).unwrap();
+        let (metadata, snapshot) = decode_graph_snapshot(contents.as_bytes()).unwrap();
+
+        assert_eq!(
+            metadata,
+            GraphSnapshotMetadata {
+                file_version: GRAPH_SNAPSHOT_FILE_VERSION,
+                app_version: env!("CARGO_PKG_VERSION").into(),
+                device_id: 1,
+                created_at: 1_700_000_000,
+                node_count: 3,
+                edge_count: 2,
+            }
+        );
+        assert_eq!(snapshot, fixture());
+    }
+
+    #[test]
+    fn loads_snapshot_in_both_modes() {
+        let app = tauri::test::mock_app();
+
+        // View only, as a separate device holding just the snapshot's graph
+        let (packet_api, loaded) = snapshot_packet_api(app.handle(), &fixture()).unwrap();
+        assert_eq!(loaded, (3, 2));
+        assert_eq!(packet_api.connection_type, ConnectionType::Snapshot);
+        assert_eq!(packet_api.device.my_node_info.my_node_num, 1);
+
+        let graph = packet_api.read_graph().unwrap();
+        assert_eq!((graph.node_count(), graph.edge_count()), (3, 2));
+        assert_eq!(graph.overrides.node_label(3), Some("Ridge"));
+
+        // Merged into a graph that has since heard a different link from node 2
+        let mut current = MeshGraph::new();
+        for node_num in [1, 2, 4] {
+            current.upsert_node(graph_node(node_num));
+        }
+
+        current.upsert_edge(graph_node(2), graph_node(1), edge(2, 9.0));
+        current.upsert_edge(graph_node(4), graph_node(1), edge(4, 1.0));
+        let heard_snr = current
+            .get_edge(graph_node(2), graph_node(1))
+            .unwrap()
+            .snr();
+
+        let loaded = fixture()
+            .load_into(&mut current, &mut MeshDevice::new())
+            .unwrap();
+        assert_eq!(loaded, (3, 1));
+        assert_eq!((current.node_count(), current.edge_count()), (4, 3));
+        assert_eq!(
+            current
+                .get_edge(graph_node(2), graph_node(1))
+                .unwrap()
+                .snr(),
+            heard_snr
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let error = |bytes: &[u8]| decode_graph_snapshot(bytes).unwrap_err();
+
+        assert!(error(&file_with_version(GRAPH_SNAPSHOT_FILE_VERSION + 1))
+            .starts_with("Snapshot is from a newer version of the app (file version 2"));
+        assert_eq!(
+            error(&file_with_version(0)),
+            "Snapshot has an invalid file version 0"
+        );
+        assert!(error(b"{\"metadata\": ").starts_with("Snapshot is not valid JSON"));
+        assert_eq!(error(b"{}"), "Snapshot has no metadata");
+        assert_eq!(
+            error(b"{\"metadata\": {}}"),
+            "Snapshot metadata has no file version"
+        );
+
+        let contents = encode_graph_snapshot(fixture(), 1_700_000_000).unwrap();
+        let mut file: Value = serde_json::from_str(&contents).unwrap();
+        file["metadata"]["deviceId"] = 7.into();
+        assert!(error(&serde_json::to_vec(&file).unwrap()).starts_with(
+            "Snapshot is damaged, its graph is of device 1 but its metadata names device 7"
+        ));
+
+        let mut file: Value = serde_json::from_str(&contents).unwrap();
+        file["snapshot"]["nodes"]["2"]["lastHeard"] = "yesterday".into();
+        assert!(error(&serde_json::to_vec(&file).unwrap())
+            .starts_with("Invalid snapshot graph: Invalid timestamp \"yesterday\""));
+    }
+}
@@ -0,0 +1,615 @@
+use meshtastic::protobufs::config::device_config::Role;
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::device::{MeshDevice, MeshNode, NormalizedPosition};
+use crate::graph::ds::graph::MeshGraph;
+
+use super::xml::XmlWriter;
+
+const KML_NAMESPACE: &str = "http://www.opengis.net/kml/2.2";
+
+/// Nodes not heard from within this many seconds are styled as stale
+pub const KML_STALE_NODE_SECS: u32 = 2 * 60 * 60;
+
+/// Nodes reporting a battery level below this percentage are styled as low on battery
+pub const KML_LOW_BATTERY_PERCENT: u32 = 20;
+
+const MAX_WEIGHT_THRESHOLDS: usize = 8;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct KmlExportOptions {
+    /// Ascending edge weights (SNR) separating the buckets of the link color ramp.
+    /// Links weaker than the first threshold are red, those at or above the last are green.
+    pub weight_thresholds: Vec<f32>,
+}
+
+impl Default for KmlExportOptions {
+    fn default() -> Self {
+        Self {
+            weight_thresholds: vec![-10.0, -5.0, 0.0, 5.0],
+        }
+    }
+}
+
+impl KmlExportOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.weight_thresholds.len() > MAX_WEIGHT_THRESHOLDS {
+            return Err(format!(
+                "At most {} weight thresholds are supported",
+                MAX_WEIGHT_THRESHOLDS
+            ));
+        }
+
+        if !self
+            .weight_thresholds
+            .windows(2)
+            .all(|pair| pair[0] < pair[1])
+        {
+            return Err("Weight thresholds must be strictly ascending".into());
+        }
+
+        Ok(())
+    }
+
+    fn bucket_count(&self) -> usize {
+        self.weight_thresholds.len() + 1
+    }
+
+    fn bucket(&self, weight: f64) -> usize {
+        self.weight_thresholds
+            .iter()
+            .filter(|threshold| weight >= f64::from(**threshold))
+            .count()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct KmlExportSummary {
+    pub placemarks: u32,
+    pub links: u32,
+    pub bytes: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KmlDocument {
+    pub contents: String,
+    pub placemarks: u32,
+    pub links: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NodeStyle {
+    Router,
+    Client,
+    LowBattery,
+    Stale,
+}
+
+impl NodeStyle {
+    const ALL: [NodeStyle; 4] = [
+        NodeStyle::Router,
+        NodeStyle::Client,
+        NodeStyle::LowBattery,
+        NodeStyle::Stale,
+    ];
+
+    fn id(&self) -> &'static str {
+        match self {
+            NodeStyle::Router => "node-router",
+            NodeStyle::Client => "node-client",
+            NodeStyle::LowBattery => "node-low-battery",
+            NodeStyle::Stale => "node-stale",
+        }
+    }
+
+    fn icon(&self) -> &'static str {
+        match self {
+            NodeStyle::Router => "http://maps.google.com/mapfiles/kml/paddle/blu-stars.png",
+            NodeStyle::Client => "http://maps.google.com/mapfiles/kml/paddle/grn-circle.png",
+            NodeStyle::LowBattery => "http://maps.google.com/mapfiles/kml/paddle/ylw-circle.png",
+            NodeStyle::Stale => "http://maps.google.com/mapfiles/kml/paddle/wht-blank.png",
+        }
+    }
+
+    /// Health takes precedence over role, since it's what needs attention
+    fn for_node(device: &MeshDevice, node: &MeshNode, now: u32) -> Self {
+        let stale = match &node.last_heard {
+            Some(heard) => now.saturating_sub(heard.timestamp) > KML_STALE_NODE_SECS,
+            None => true,
+        };
+
+        if stale {
+            return NodeStyle::Stale;
+        }
+
+        if matches!(node.battery_level(), Some(level) if level < KML_LOW_BATTERY_PERCENT) {
+            return NodeStyle::LowBattery;
+        }
+
+        // Only the role of the connected node is known
+        let role = device
+            .config
+            .device
+            .as_ref()
+            .filter(|_| node.node_num == device.my_node_info.my_node_num)
+            .and_then(|config| Role::from_i32(config.role));
+
+        match role {
+            Some(Role::Router | Role::RouterClient | Role::Repeater) => NodeStyle::Router,
+            _ => NodeStyle::Client,
+        }
+    }
+}
+
+fn edge_style_id(bucket: usize) -> String {
+    format!("edge-weight-{}", bucket)
+}
+
+/// KML colors are `aabbggrr`. Buckets ramp from opaque red to opaque green.
+fn edge_color(bucket: usize, bucket_count: usize) -> String {
+    let t = match bucket_count {
+        1 => 1.0,
+        n => bucket as f64 / (n - 1) as f64,
+    };
+
+    let red = (255.0 * (1.0 - t)).round() as u8;
+    let green = (255.0 * t).round() as u8;
+
+    format!("ff00{:02x}{:02x}", green, red)
+}
+
+fn node_name(graph: &MeshGraph, node: &MeshNode) -> String {
+    let long_name = node.user.as_ref().map(|user| user.long_name.as_str());
+
+    graph
+        .display_label(node.node_num, long_name)
+        .unwrap_or_else(|| format!("!{:08x}", node.node_num))
+}
+
+/// KML coordinates are `lon,lat[,alt]`
+fn coordinates(position: &NormalizedPosition) -> String {
+    if position.altitude != 0 {
+        format!(
+            "{:.7},{:.7},{}",
+            position.longitude, position.latitude, position.altitude
+        )
+    } else {
+        format!("{:.7},{:.7}", position.longitude, position.latitude)
+    }
+}
+
+fn altitude_mode<'a>(positions: impl IntoIterator<Item = &'a NormalizedPosition>) -> &'static str {
+    if positions.into_iter().all(|position| position.altitude != 0) {
+        "absolute"
+    } else {
+        "clampToGround"
+    }
+}
+
+fn telemetry(node: &MeshNode) -> Vec<(&'static str, String)> {
+    let mut data = vec![("nodeId", format!("!{:08x}", node.node_num))];
+
+    if let Some(metrics) = node.latest_device_metrics() {
+        data.push(("batteryLevel", metrics.battery_level.to_string()));
+        data.push(("voltage", format!("{:.2}", metrics.voltage)));
+        data.push((
+            "channelUtilization",
+            format!("{:.1}", metrics.channel_utilization),
+        ));
+        data.push(("airUtilTx", format!("{:.1}", metrics.air_util_tx)));
+    }
+
+    if let Some(metrics) = node.latest_environment_metrics() {
+        data.push(("temperature", format!("{:.1}", metrics.temperature)));
+        data.push((
+            "relativeHumidity",
+            format!("{:.1}", metrics.relative_humidity),
+        ));
+        data.push((
+            "barometricPressure",
+            format!("{:.1}", metrics.barometric_pressure),
+        ));
+    }
+
+    if let Some(heard) = &node.last_heard {
+        data.push(("lastHeard", heard.timestamp.to_string()));
+    }
+
+    data
+}
+
+fn write_styles(writer: &mut XmlWriter, options: &KmlExportOptions) {
+    for style in NodeStyle::ALL {
+        writer.open("Style", &[("id", style.id().into())]);
+        writer.open("IconStyle", &[]);
+        writer.open("Icon", &[]);
+        writer.element("href", &[], style.icon());
+        writer.close();
+        writer.close();
+        writer.close();
+    }
+
+    let bucket_count = options.bucket_count();
+
+    for bucket in 0..bucket_count {
+        writer.open("Style", &[("id", edge_style_id(bucket))]);
+        writer.open("LineStyle", &[]);
+        writer.element("color", &[], &edge_color(bucket, bucket_count));
+        writer.element("width", &[], &(bucket + 1).to_string());
+        writer.close();
+        writer.close();
+    }
+}
+
+/// Builds a KML document with a folder of node placemarks and a folder of link lines
+/// styled by weight. `now` is used to decide which nodes are stale.
+pub fn build_kml(
+    graph: &MeshGraph,
+    device: &MeshDevice,
+    options: &KmlExportOptions,
+    now: u32,
+) -> Result<KmlDocument, String> {
+    options.validate()?;
+
+    let mut nodes: Vec<&MeshNode> = device.nodes.values().collect();
+    nodes.sort_by_key(|node| node.node_num);
+
+    let mut writer = XmlWriter::new();
+    writer.open("kml", &[("xmlns", KML_NAMESPACE.into())]);
+    writer.open("Document", &[]);
+    writer.element("name", &[], "Meshtastic network");
+
+    write_styles(&mut writer, options);
+
+    writer.open("Folder", &[]);
+    writer.element("name", &[], "Nodes");
+
+    let mut placemarks = 0;
+
+    for node in &nodes {
+        let position = match node.last_known_position() {
+            Some(p) => p,
+            None => continue,
+        };
+
+        writer.open("Placemark", &[]);
+        writer.element("name", &[], &node_name(graph, node));
+        writer.element(
+            "styleUrl",
+            &[],
+            &format!("#{}", NodeStyle::for_node(device, node, now).id()),
+        );
+
+        writer.open("ExtendedData", &[]);
+
+        for (name, value) in telemetry(node) {
+            writer.open("Data", &[("name", name.into())]);
+            writer.element("value", &[], &value);
+            writer.close();
+        }
+
+        writer.close();
+
+        writer.open("Point", &[]);
+        writer.element("altitudeMode", &[], altitude_mode([position]));
+        writer.element("coordinates", &[], &coordinates(position));
+        writer.close();
+        writer.close();
+
+        placemarks += 1;
+    }
+
+    writer.close();
+
+    writer.open("Folder", &[]);
+    writer.element("name", &[], "Links");
+
+    let mut edges: Vec<_> = graph.edges().collect();
+    edges.sort_by_key(|(from, to, _)| (from.node_num, to.node_num));
+
+    let mut links = 0;
+
+    for (from, to, edge) in edges {
+        let endpoints = (
+            device.nodes.get(&from.node_num),
+            device.nodes.get(&to.node_num),
+        );
+
+        let (from_node, to_node) = match endpoints {
+            (Some(f), Some(t)) => (f, t),
+            _ => continue,
+        };
+
+        let positions = match (
+            from_node.last_known_position(),
+            to_node.last_known_position(),
+        ) {
+            (Some(f), Some(t)) => [f, t],
+            _ => continue,
+        };
+
+        writer.open("Placemark", &[]);
+        writer.element(
+            "name",
+            &[],
+            &format!(
+                "{} - {}",
+                node_name(graph, from_node),
+                node_name(graph, to_node)
+            ),
+        );
+        writer.element(
+            "styleUrl",
+            &[],
+            &format!("#{}", edge_style_id(options.bucket(edge.snr()))),
+        );
+
+        writer.open("ExtendedData", &[]);
+        writer.open("Data", &[("name", "weight".into())]);
+        writer.element("value", &[], &format!("{:.2}", edge.snr()));
+        writer.close();
+        writer.close();
+
+        writer.open("LineString", &[]);
+        writer.element("altitudeMode", &[], altitude_mode(positions));
+        writer.element(
+            "coordinates",
+            &[],
+            &positions
+                .iter()
+                .map(|position| coordinates(position))
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+        writer.close();
+        writer.close();
+
+        links += 1;
+    }
+
+    Ok(KmlDocument {
+        contents: writer.finish(),
+        placemarks,
+        links,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::helpers::get_current_time_u32;
+    use crate::export::xml::parse::{parse_document, XmlElement};
+    use crate::graph::ds::fixtures::{edge, graph_node};
+
+    fn node(node_num: u32, position: Option<(f32, f32, i32)>, battery_level: u32) -> MeshNode {
+        let mut node = MeshNode::new(node_num);
+
+        node.update_from_node_info(protobufs::NodeInfo {
+            num: node_num,
+            user: Some(protobufs::User {
+                long_name: format!("Node {}", node_num),
+                ..Default::default()
+            }),
+            position: position.map(|(latitude, longitude, altitude)| protobufs::Position {
+                latitude_i: (latitude * 1e7) as i32,
+                longitude_i: (longitude * 1e7) as i32,
+                altitude,
+                ..Default::default()
+            }),
+            device_metrics: Some(protobufs::DeviceMetrics {
+                battery_level,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        node
+    }
+
+    /// Node 1 (self, router) has an altitude, node 2 is low on battery and
+    /// node 3 has no position
+    fn fixture() -> (MeshGraph, MeshDevice) {
+        let mut graph = MeshGraph::new();
+
+        for node_num in 1..=3 {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        graph.upsert_edge(graph_node(2), graph_node(1), edge(2, 1, 6.0));
+        graph.upsert_edge(graph_node(1), graph_node(2), edge(1, 2, -7.0));
+        graph.upsert_edge(graph_node(3), graph_node(1), edge(3, 1, 1.0));
+
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 1;
+        device.config.device = Some(protobufs::config::DeviceConfig {
+            role: Role::Router as i32,
+            ..Default::default()
+        });
+        device
+            .nodes
+            .insert(1, node(1, Some((45.5, -73.6, 120)), 90));
+        device.nodes.insert(2, node(2, Some((45.6, -73.5, 0)), 10));
+        device.nodes.insert(3, node(3, None, 50));
+
+        (graph, device)
+    }
+
+    fn folder<'a>(document: &'a XmlElement, name: &str) -> &'a XmlElement {
+        document
+            .children_named("Folder")
+            .find(|folder| folder.child("name").map(|n| n.text.as_str()) == Some(name))
+            .unwrap()
+    }
+
+    fn style_url(placemark: &XmlElement) -> &str {
+        &placemark.child("styleUrl").unwrap().text
+    }
+
+    #[test]
+    fn writes_node_and_link_folders() {
+        let (graph, device) = fixture();
+        let kml = build_kml(
+            &graph,
+            &device,
+            &KmlExportOptions::default(),
+            get_current_time_u32(),
+        )
+        .unwrap();
+
+        assert_eq!(kml.placemarks, 2);
+        assert_eq!(kml.links, 2);
+
+        let root = parse_document(&kml.contents).unwrap();
+        assert_eq!(root.name, "kml");
+        assert_eq!(root.attribute("xmlns"), Some(KML_NAMESPACE));
+
+        let document = root.child("Document").unwrap();
+        assert_eq!(document.children_named("Folder").count(), 2);
+
+        let nodes: Vec<_> = folder(document, "Nodes")
+            .children_named("Placemark")
+            .collect();
+        let links: Vec<_> = folder(document, "Links")
+            .children_named("Placemark")
+            .collect();
+
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(links.len(), 2);
+
+        assert_eq!(style_url(nodes[0]), "#node-router");
+        assert_eq!(style_url(nodes[1]), "#node-low-battery");
+
+        // Every referenced style is defined in the document
+        let style_ids: Vec<_> = document
+            .children_named("Style")
+            .filter_map(|style| style.attribute("id"))
+            .collect();
+
+        for placemark in nodes.iter().chain(links.iter()) {
+            let url = style_url(placemark);
+            assert!(
+                style_ids.contains(&url.trim_start_matches('#')),
+                "undefined style {}",
+                url
+            );
+        }
+
+        let battery = nodes[0]
+            .descendants_named("Data")
+            .into_iter()
+            .find(|data| data.attribute("name") == Some("batteryLevel"))
+            .unwrap();
+        assert_eq!(battery.child("value").unwrap().text, "90");
+    }
+
+    #[test]
+    fn orders_coordinates_lon_lat_alt() {
+        let (graph, device) = fixture();
+        let kml = build_kml(
+            &graph,
+            &device,
+            &KmlExportOptions::default(),
+            get_current_time_u32(),
+        )
+        .unwrap();
+
+        let root = parse_document(&kml.contents).unwrap();
+        let nodes = folder(root.child("Document").unwrap(), "Nodes");
+
+        let points: Vec<_> = nodes.descendants_named("Point");
+
+        let parse = |point: &XmlElement| -> Vec<f64> {
+            point
+                .child("coordinates")
+                .unwrap()
+                .text
+                .split(',')
+                .map(|value| value.parse().unwrap())
+                .collect()
+        };
+
+        let with_altitude = parse(points[0]);
+        assert_eq!(with_altitude.len(), 3);
+        assert!((with_altitude[0] + 73.6).abs() < 1e-5);
+        assert!((with_altitude[1] - 45.5).abs() < 1e-5);
+        assert_eq!(with_altitude[2], 120.0);
+        assert_eq!(points[0].child("altitudeMode").unwrap().text, "absolute");
+
+        let without_altitude = parse(points[1]);
+        assert_eq!(without_altitude.len(), 2);
+        assert!((without_altitude[0] + 73.5).abs() < 1e-5);
+        assert_eq!(
+            points[1].child("altitudeMode").unwrap().text,
+            "clampToGround"
+        );
+
+        // A link is only absolute if both ends have an altitude
+        let line = root.descendants_named("LineString")[0];
+        assert_eq!(line.child("altitudeMode").unwrap().text, "clampToGround");
+        assert_eq!(
+            line.child("coordinates").unwrap().text.split(' ').count(),
+            2
+        );
+    }
+
+    #[test]
+    fn maps_weights_to_configured_buckets() {
+        let (graph, device) = fixture();
+        let options = KmlExportOptions {
+            weight_thresholds: vec![0.0],
+        };
+
+        let kml = build_kml(&graph, &device, &options, get_current_time_u32()).unwrap();
+        let root = parse_document(&kml.contents).unwrap();
+        let document = root.child("Document").unwrap();
+
+        let edge_styles: Vec<_> = document
+            .children_named("Style")
+            .filter(|style| style.child("LineStyle").is_some())
+            .collect();
+
+        assert_eq!(edge_styles.len(), 2);
+        assert_eq!(
+            edge_styles[0].descendants_named("color")[0].text,
+            "ff0000ff"
+        );
+        assert_eq!(
+            edge_styles[1].descendants_named("color")[0].text,
+            "ff00ff00"
+        );
+
+        // Sorted by endpoints, so the -7 dB link from node 1 comes first
+        let links: Vec<_> = folder(document, "Links")
+            .children_named("Placemark")
+            .map(style_url)
+            .collect();
+
+        assert_eq!(links, vec!["#edge-weight-0", "#edge-weight-1"]);
+
+        let unsorted = KmlExportOptions {
+            weight_thresholds: vec![5.0, 0.0],
+        };
+        assert!(build_kml(&graph, &device, &unsorted, 0).is_err());
+    }
+
+    #[test]
+    fn styles_nodes_not_heard_recently_as_stale() {
+        let (graph, device) = fixture();
+        let later = get_current_time_u32() + KML_STALE_NODE_SECS + 1;
+
+        let kml = build_kml(&graph, &device, &KmlExportOptions::default(), later).unwrap();
+        let root = parse_document(&kml.contents).unwrap();
+
+        let styles: Vec<_> = folder(root.child("Document").unwrap(), "Nodes")
+            .children_named("Placemark")
+            .map(style_url)
+            .collect();
+
+        assert_eq!(styles, vec!["#node-stale", "#node-stale"]);
+    }
+}
@@ -0,0 +1,19 @@
+pub mod analytics_report;
+pub mod csv;
+pub mod gpx;
+pub mod graph_snapshot;
+pub mod kml;
+pub mod network_geojson;
+pub mod node_table;
+pub mod state_bundle;
+pub mod topology;
+pub mod xml;
+
+/// Writes an export to `file_path`, returning the number of bytes written
+pub async fn write_export_file(file_path: &str, contents: &str) -> Result<u32, String> {
+    tokio::fs::write(file_path, contents)
+        .await
+        .map_err(|e| format!("Failed to write export to \"{}\": {}", file_path, e))?;
+
+    Ok(contents.len() as u32)
+}
@@ -0,0 +1,282 @@
+use geojson::{FeatureCollection, JsonObject, Value};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::device::MeshDevice;
+use crate::graph::{
+    ds::{edge::EdgeSource, graph::MeshGraph},
+    geojson::{
+        edge_properties, generate_graph_edges_geojson, generate_graph_nodes_geojson,
+        node_properties, with_bbox, UNPOSITIONED_NODES_MEMBER, UNPOSITIONED_NODE_NUMS_MEMBER,
+    },
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkGeoJsonExportOptions {
+    pub include_unpositioned_summary: bool,
+    pub include_predicted_edges: bool,
+    pub include_analytics: bool, // degree and bridge properties derived from the graph
+    pub pretty: bool,
+}
+
+impl Default for NetworkGeoJsonExportOptions {
+    fn default() -> Self {
+        Self {
+            include_unpositioned_summary: true,
+            include_predicted_edges: false,
+            include_analytics: true,
+            pretty: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkExportSummary {
+    pub node_features: u32,
+    pub edge_features: u32,
+    pub bytes: u32,
+}
+
+impl NetworkExportSummary {
+    pub fn new(collection: &FeatureCollection, bytes: u32) -> Self {
+        let mut summary = Self {
+            bytes,
+            ..Default::default()
+        };
+
+        for feature in &collection.features {
+            match feature.geometry.as_ref().map(|geometry| &geometry.value) {
+                Some(Value::Point(_)) => summary.node_features += 1,
+                Some(Value::LineString(_)) => summary.edge_features += 1,
+                _ => {}
+            }
+        }
+
+        summary
+    }
+}
+
+/// Combines node Points and edge LineStrings into a single collection for export
+pub fn build_network_geojson(
+    graph: &MeshGraph,
+    device: &MeshDevice,
+    options: &NetworkGeoJsonExportOptions,
+) -> FeatureCollection {
+    let nodes = generate_graph_nodes_geojson(graph, device);
+    let edges = generate_graph_edges_geojson(graph, device);
+
+    let predicted = json!(EdgeSource::Predicted);
+
+    let mut features = nodes.features;
+    features.extend(edges.features.into_iter().filter(|feature| {
+        options.include_predicted_edges
+            || feature
+                .properties
+                .as_ref()
+                .and_then(|properties| properties.get(edge_properties::SOURCE))
+                != Some(&predicted)
+    }));
+
+    if !options.include_analytics {
+        let analytics = node_properties::ANALYTICS
+            .iter()
+            .chain(edge_properties::ANALYTICS.iter());
+
+        for feature in &mut features {
+            if let Some(properties) = feature.properties.as_mut() {
+                for key in analytics.clone() {
+                    properties.remove(*key);
+                }
+            }
+        }
+    }
+
+    let mut foreign_members = JsonObject::new();
+
+    if options.include_unpositioned_summary {
+        let members = nodes.foreign_members.as_ref();
+
+        for member in [UNPOSITIONED_NODES_MEMBER, UNPOSITIONED_NODE_NUMS_MEMBER] {
+            if let Some(unpositioned) = members.and_then(|members| members.get(member)) {
+                foreign_members.insert(member.into(), unpositioned.clone());
+            }
+        }
+    }
+
+    with_bbox(FeatureCollection {
+        bbox: None,
+        features,
+        foreign_members: (!foreign_members.is_empty()).then_some(foreign_members),
+    })
+}
+
+pub fn serialize_network_geojson(
+    collection: &FeatureCollection,
+    pretty: bool,
+) -> Result<String, String> {
+    let serialized = if pretty {
+        serde_json::to_string_pretty(collection)
+    } else {
+        serde_json::to_string(collection)
+    };
+
+    serialized.map_err(|e| format!("Failed to serialize network GeoJSON: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use geojson::GeoJson;
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::{MeshNode, NormalizedPosition};
+    use crate::export::write_export_file;
+    use crate::graph::ds::edge::GraphEdge;
+    use crate::graph::ds::fixtures::graph_node;
+
+    fn edge(from: u32, to: u32, source: EdgeSource) -> GraphEdge {
+        let mut edge = GraphEdge::from_neighbor(
+            to,
+            0,
+            "channel #0".into(),
+            protobufs::Neighbor {
+                node_id: from,
+                snr: 4.0,
+                ..Default::default()
+            },
+        );
+
+        edge.source = source;
+        edge
+    }
+
+    fn positioned_node(node_num: u32, latitude: f32, longitude: f32) -> MeshNode {
+        let mut node = MeshNode::new(node_num);
+        node.position_metrics
+            .push(NormalizedPosition::from(protobufs::Position {
+                latitude_i: (latitude * 1e7) as i32,
+                longitude_i: (longitude * 1e7) as i32,
+                ..Default::default()
+            }));
+        node
+    }
+
+    /// Nodes 1 and 2 are positioned and linked both ways, one direction predicted
+    fn fixture() -> (MeshGraph, MeshDevice) {
+        let mut graph = MeshGraph::new();
+
+        for node_num in 1..=3 {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        graph.upsert_edge(
+            graph_node(2),
+            graph_node(1),
+            edge(2, 1, EdgeSource::NeighborInfo),
+        );
+        graph.upsert_edge(
+            graph_node(1),
+            graph_node(2),
+            edge(1, 2, EdgeSource::Predicted),
+        );
+
+        let mut device = MeshDevice::new();
+        device.nodes.insert(1, positioned_node(1, 51.5, -0.1));
+        device.nodes.insert(2, positioned_node(2, 51.6, -0.2));
+        device.nodes.insert(3, MeshNode::new(3));
+
+        (graph, device)
+    }
+
+    fn export_path(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("network-export-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    #[tokio::test]
+    async fn writes_valid_geojson() {
+        let (graph, device) = fixture();
+        let options = NetworkGeoJsonExportOptions::default();
+
+        let collection = build_network_geojson(&graph, &device, &options);
+        let contents = serialize_network_geojson(&collection, options.pretty).unwrap();
+
+        let path = export_path("network.geojson");
+        let bytes = write_export_file(path.to_str().unwrap(), &contents)
+            .await
+            .unwrap();
+
+        let summary = NetworkExportSummary::new(&collection, bytes);
+        assert_eq!(summary.node_features, 2);
+        assert_eq!(summary.edge_features, 1);
+        assert_eq!(
+            summary.bytes as u64,
+            std::fs::metadata(&path).unwrap().len()
+        );
+
+        let parsed = match GeoJson::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap() {
+            GeoJson::FeatureCollection(collection) => collection,
+            other => panic!("expected a FeatureCollection, got {:?}", other),
+        };
+
+        assert_eq!(parsed.features.len(), 3);
+        assert!(parsed.bbox.is_some());
+        assert_eq!(
+            parsed.foreign_members.unwrap()[UNPOSITIONED_NODES_MEMBER],
+            json!(1)
+        );
+        assert!(parsed.features[0]
+            .properties
+            .as_ref()
+            .unwrap()
+            .contains_key(node_properties::DEGREE));
+    }
+
+    #[test]
+    fn applies_export_options() {
+        let (graph, device) = fixture();
+        let options = NetworkGeoJsonExportOptions {
+            include_unpositioned_summary: false,
+            include_predicted_edges: true,
+            include_analytics: false,
+            pretty: false,
+        };
+
+        let collection = build_network_geojson(&graph, &device, &options);
+        let summary = NetworkExportSummary::new(&collection, 0);
+
+        assert_eq!(summary.edge_features, 2);
+        assert!(collection
+            .foreign_members
+            .as_ref()
+            .unwrap()
+            .get(UNPOSITIONED_NODES_MEMBER)
+            .is_none());
+
+        for feature in &collection.features {
+            let properties = feature.properties.as_ref().unwrap();
+
+            assert!(!properties.contains_key(node_properties::DEGREE));
+            assert!(!properties.contains_key(edge_properties::IS_BRIDGE));
+        }
+
+        let compact = serialize_network_geojson(&collection, options.pretty).unwrap();
+        assert!(!compact.contains('\n'));
+    }
+
+    #[tokio::test]
+    async fn reports_unwritable_paths() {
+        let path = export_path("missing-dir").join("network.geojson");
+        let error = write_export_file(path.to_str().unwrap(), "{}")
+            .await
+            .unwrap_err();
+
+        assert!(error.starts_with("Failed to write export"));
+    }
+}
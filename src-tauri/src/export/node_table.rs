@@ -0,0 +1,328 @@
+use chrono::SecondsFormat;
+use meshtastic::protobufs::{self, config::device_config::Role};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::device::{MeshDevice, MeshNode};
+use crate::graph::ds::graph::MeshGraph;
+
+use super::csv::to_csv_record;
+
+/// A row of the node table. Shared by the UI table and the CSV export so the two
+/// always show the same values.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeTableRow {
+    pub num: u32,
+    pub id: String,
+    pub long_name: Option<String>,
+    pub short_name: Option<String>,
+    pub display_label: Option<String>, // operator's label, falling back to the long name
+    pub hardware: Option<String>,
+    pub firmware: Option<String>, // only reported by the connected node
+    pub role: Option<String>,     // only reported by the connected node
+    pub latitude: Option<f32>,
+    pub longitude: Option<f32>,
+    pub altitude: Option<i32>,
+    pub battery_level: Option<u32>,
+    pub voltage: Option<f32>,
+    pub channel_utilization: Option<f32>,
+    pub snr: Option<f32>,        // SNR of the last packet we heard from the node
+    pub hops: Option<u32>,       // fewest hops from the connected node through the graph
+    pub last_heard: Option<u32>, // secs
+    pub degree: u32,
+    pub weighted_degree: f64,
+    pub is_self: bool,
+}
+
+/// Columns of the node table CSV export, in their default order
+pub const NODE_TABLE_COLUMNS: [&str; 20] = [
+    "num",
+    "id",
+    "long_name",
+    "short_name",
+    "display_label",
+    "hardware",
+    "firmware",
+    "role",
+    "latitude",
+    "longitude",
+    "altitude",
+    "battery_level",
+    "voltage",
+    "channel_utilization",
+    "snr",
+    "hops",
+    "last_heard",
+    "degree",
+    "weighted_degree",
+    "is_self",
+];
+
+fn build_row(
+    graph: &MeshGraph,
+    device: &MeshDevice,
+    node: &MeshNode,
+    degree: (u32, f64),
+) -> NodeTableRow {
+    let my_node_num = device.my_node_info.my_node_num;
+    let is_self = node.node_num == my_node_num;
+
+    let user = node.user.as_ref();
+    let position = node.last_known_position();
+    let metrics = node.latest_device_metrics();
+
+    let firmware = device
+        .metadata
+        .as_ref()
+        .filter(|_| is_self)
+        .map(|metadata| metadata.firmware_version.clone());
+
+    let role = device
+        .config
+        .device
+        .as_ref()
+        .filter(|_| is_self)
+        .and_then(|config| Role::from_i32(config.role))
+        .map(|role| role.as_str_name().to_string());
+
+    let hops = graph
+        .shortest_path(my_node_num, node.node_num)
+        .map(|path| (path.len() - 1) as u32);
+
+    NodeTableRow {
+        num: node.node_num,
+        id: format!("!{:08x}", node.node_num),
+        long_name: user.map(|u| u.long_name.clone()),
+        short_name: user.map(|u| u.short_name.clone()),
+        display_label: graph.display_label(node.node_num, user.map(|u| u.long_name.as_str())),
+        hardware: user
+            .and_then(|u| protobufs::HardwareModel::from_i32(u.hw_model))
+            .map(|model| model.as_str_name().to_string()),
+        firmware,
+        role,
+        latitude: position.map(|p| p.latitude),
+        longitude: position.map(|p| p.longitude),
+        altitude: position.map(|p| p.altitude),
+        battery_level: metrics.map(|m| m.battery_level),
+        voltage: metrics.map(|m| m.voltage),
+        channel_utilization: metrics.map(|m| m.channel_utilization),
+        snr: node.last_heard.as_ref().map(|heard| heard.snr),
+        hops,
+        last_heard: node.last_heard.as_ref().map(|heard| heard.timestamp),
+        degree: degree.0,
+        weighted_degree: degree.1,
+        is_self,
+    }
+}
+
+/// Builds a row for every node in the device's node database, ordered by node num
+pub fn build_node_table(graph: &MeshGraph, device: &MeshDevice) -> Vec<NodeTableRow> {
+    let degrees = graph.node_degrees();
+
+    let mut nodes: Vec<&MeshNode> = device.nodes.values().collect();
+    nodes.sort_by_key(|node| node.node_num);
+
+    nodes
+        .into_iter()
+        .map(|node| {
+            let degree = degrees.get(&node.node_num).copied().unwrap_or_default();
+            build_row(graph, device, node, degree)
+        })
+        .collect()
+}
+
+fn format_timestamp(timestamp: u32) -> Option<String> {
+    chrono::DateTime::from_timestamp(timestamp.into(), 0)
+        .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+/// Formats a column of a row, empty if the value is unknown
+fn column_value(row: &NodeTableRow, column: &str) -> String {
+    fn opt<T: ToString>(value: &Option<T>) -> String {
+        value.as_ref().map(T::to_string).unwrap_or_default()
+    }
+
+    match column {
+        "num" => row.num.to_string(),
+        "id" => row.id.clone(),
+        "long_name" => opt(&row.long_name),
+        "short_name" => opt(&row.short_name),
+        "display_label" => opt(&row.display_label),
+        "hardware" => opt(&row.hardware),
+        "firmware" => opt(&row.firmware),
+        "role" => opt(&row.role),
+        "latitude" => opt(&row.latitude),
+        "longitude" => opt(&row.longitude),
+        "altitude" => opt(&row.altitude),
+        "battery_level" => opt(&row.battery_level),
+        "voltage" => opt(&row.voltage),
+        "channel_utilization" => opt(&row.channel_utilization),
+        "snr" => opt(&row.snr),
+        "hops" => opt(&row.hops),
+        "last_heard" => row
+            .last_heard
+            .and_then(format_timestamp)
+            .unwrap_or_default(),
+        "degree" => row.degree.to_string(),
+        "weighted_degree" => row.weighted_degree.to_string(),
+        "is_self" => row.is_self.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Resolves the requested columns, defaulting to all of them
+pub fn resolve_node_table_columns(columns: Option<Vec<String>>) -> Result<Vec<String>, String> {
+    let columns = match columns {
+        Some(columns) => columns,
+        None => return Ok(NODE_TABLE_COLUMNS.iter().map(|c| c.to_string()).collect()),
+    };
+
+    if let Some(unknown) = columns
+        .iter()
+        .find(|column| !NODE_TABLE_COLUMNS.contains(&column.as_str()))
+    {
+        return Err(format!(
+            "Unknown column \"{}\", valid columns are: {}",
+            unknown,
+            NODE_TABLE_COLUMNS.join(", ")
+        ));
+    }
+
+    if columns.is_empty() {
+        return Err("At least one column must be selected".into());
+    }
+
+    Ok(columns)
+}
+
+/// Writes the node table as CSV with a header row, in the order of `columns`
+pub fn node_table_to_csv(rows: &[NodeTableRow], columns: &[String]) -> String {
+    let mut csv = to_csv_record(columns);
+
+    for row in rows {
+        let values: Vec<String> = columns
+            .iter()
+            .map(|column| column_value(row, column))
+            .collect();
+
+        csv.push_str(&to_csv_record(&values));
+    }
+
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(num: u32, long_name: Option<&str>) -> NodeTableRow {
+        NodeTableRow {
+            num,
+            id: format!("!{:08x}", num),
+            long_name: long_name.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    fn columns(names: &[&str]) -> Vec<String> {
+        resolve_node_table_columns(Some(names.iter().map(|c| c.to_string()).collect())).unwrap()
+    }
+
+    #[test]
+    fn builds_rows_from_device() {
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 1;
+        device.config.device = Some(protobufs::config::DeviceConfig {
+            role: Role::Router as i32,
+            ..Default::default()
+        });
+
+        let mut node = MeshNode::new(1);
+        node.update_from_node_info(protobufs::NodeInfo {
+            num: 1,
+            snr: 7.5,
+            user: Some(protobufs::User {
+                long_name: "Base".into(),
+                short_name: "BS".into(),
+                hw_model: protobufs::HardwareModel::Tbeam as i32,
+                ..Default::default()
+            }),
+            device_metrics: Some(protobufs::DeviceMetrics {
+                battery_level: 80,
+                voltage: 4.1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        device.nodes.insert(1, node);
+        device.nodes.insert(2, MeshNode::new(2));
+
+        let mut graph = MeshGraph::new();
+        graph.set_node_label(2, Some("Barn".into()));
+
+        let rows = build_node_table(&graph, &device);
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].long_name.as_deref(), Some("Base"));
+        assert_eq!(rows[0].display_label.as_deref(), Some("Base"));
+        assert_eq!(rows[0].hardware.as_deref(), Some("TBEAM"));
+        assert_eq!(rows[0].role.as_deref(), Some("ROUTER"));
+        assert_eq!(rows[0].battery_level, Some(80));
+        assert_eq!(rows[0].snr, Some(7.5));
+        assert_eq!(rows[0].latitude, None);
+
+        assert_eq!(
+            rows[1],
+            NodeTableRow {
+                display_label: Some("Barn".into()),
+                ..row(2, None)
+            }
+        );
+    }
+
+    #[test]
+    fn escapes_values() {
+        let rows = vec![row(1, Some("Hill, \"North\""))];
+        let csv = node_table_to_csv(&rows, &columns(&["id", "long_name"]));
+
+        assert_eq!(csv, "id,long_name\r\n!00000001,\"Hill, \"\"North\"\"\"\r\n");
+    }
+
+    #[test]
+    fn selects_and_orders_columns() {
+        let mut node = row(2, Some("Relay"));
+        node.last_heard = Some(1_700_000_000);
+
+        let csv = node_table_to_csv(&[node], &columns(&["last_heard", "long_name", "is_self"]));
+
+        assert_eq!(
+            csv,
+            "last_heard,long_name,is_self\r\n2023-11-14T22:13:20Z,Relay,false\r\n"
+        );
+
+        let error =
+            resolve_node_table_columns(Some(vec!["id".into(), "colour".into()])).unwrap_err();
+
+        assert!(error.contains("\"colour\""));
+        assert!(error.contains("long_name"));
+    }
+
+    #[test]
+    fn writes_missing_values_as_empty_fields() {
+        let csv = node_table_to_csv(
+            &[row(3, None)],
+            &columns(&["num", "long_name", "battery_level", "hops", "last_heard"]),
+        );
+
+        assert_eq!(
+            csv,
+            "num,long_name,battery_level,hops,last_heard\r\n3,,,,\r\n"
+        );
+        assert_eq!(
+            resolve_node_table_columns(None).unwrap().len(),
+            NODE_TABLE_COLUMNS.len()
+        );
+    }
+}
@@ -0,0 +1,800 @@
+//! Single-file bundle of the app's persisted state, for moving it to another machine
+//! or attaching it to a support request. The bundle is JSON with a manifest and the
+//! contents of each included section. The manifest carries a checksum of the contents,
+//! so a damaged bundle is rejected before anything is imported.
+
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::Manager;
+
+use crate::device::helpers::get_current_time_u32;
+use crate::device::message_store::{self, StoredMessage};
+use crate::graph::ds::overrides::GraphOverrides;
+use crate::graph::store::{self, GraphSnapshot};
+use crate::ipc::events;
+use crate::notifications::geofences::GeofenceConfig;
+use crate::notifications::webhooks::WebhookConfig;
+use crate::persistence::{
+    settings_file_path, GEOFENCES_FILE_NAME, GRAPH_OVERRIDES_FILE_NAME, SETTINGS_FILE_NAME,
+    WEBHOOKS_FILE_NAME,
+};
+use crate::settings::{apply_settings, AppSettings};
+use crate::state;
+
+/// Version of the bundle structure, bumped on breaking changes
+pub const STATE_BUNDLE_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum BundleSection {
+    Settings,
+    GraphOverrides,
+    GraphDatabase,
+    Messages,
+    Webhooks,
+    Geofences,
+}
+
+pub const BUNDLE_SECTIONS: [BundleSection; 6] = [
+    BundleSection::Settings,
+    BundleSection::GraphOverrides,
+    BundleSection::GraphDatabase,
+    BundleSection::Messages,
+    BundleSection::Webhooks,
+    BundleSection::Geofences,
+];
+
+/// What to export. Credentials, currently the webhooks' bearer tokens, are left out
+/// unless `include_secrets` is set.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleSections {
+    pub sections: Option<Vec<BundleSection>>, // every section if `None`
+    pub include_secrets: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportMode {
+    /// Bundled entries are added to the current state, replacing entries with the same key
+    Merge,
+
+    /// Each bundled section replaces the current one
+    Replace,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleManifest {
+    pub bundle_version: u32,
+    pub app_version: String,
+    pub created_at: u32, // secs
+    pub sections: Vec<BundleSection>,
+    pub includes_secrets: bool,
+    pub checksum: String, // of the contents, as serialized in the bundle
+}
+
+/// Sections that weren't bundled are `null`
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleContents {
+    pub settings: Option<AppSettings>,
+    pub graph_overrides: Option<GraphOverrides>,
+    pub graph_database: Option<Vec<GraphSnapshot>>,
+    pub messages: Option<Vec<StoredMessage>>,
+    pub webhooks: Option<WebhookConfig>,
+    pub geofences: Option<GeofenceConfig>,
+}
+
+impl BundleContents {
+    pub fn sections(&self) -> Vec<BundleSection> {
+        let included = [
+            self.settings.is_some(),
+            self.graph_overrides.is_some(),
+            self.graph_database.is_some(),
+            self.messages.is_some(),
+            self.webhooks.is_some(),
+            self.geofences.is_some(),
+        ];
+
+        BUNDLE_SECTIONS
+            .into_iter()
+            .zip(included)
+            .filter_map(|(section, included)| included.then_some(section))
+            .collect()
+    }
+
+    pub fn strip_secrets(&mut self) {
+        if let Some(webhooks) = self.webhooks.as_mut() {
+            for endpoint in webhooks.endpoints.iter_mut() {
+                endpoint.bearer_token = None;
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(settings) = &self.settings {
+            settings
+                .validate()
+                .map_err(|e| format!("Invalid settings: {}", e))?;
+        }
+
+        if let Some(webhooks) = &self.webhooks {
+            webhooks
+                .validate()
+                .map_err(|e| format!("Invalid webhooks: {}", e))?;
+        }
+
+        if let Some(geofences) = &self.geofences {
+            geofences
+                .validate()
+                .map_err(|e| format!("Invalid geofences: {}", e))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// FNV-1a, enough to catch a damaged or hand-edited bundle
+fn checksum(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+
+    format!("{:016x}", hash)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StateBundleFile {
+    manifest: BundleManifest,
+    contents: Value,
+}
+
+pub fn encode_state_bundle(
+    mut contents: BundleContents,
+    created_at: u32,
+    include_secrets: bool,
+) -> Result<Vec<u8>, String> {
+    if !include_secrets {
+        contents.strip_secrets();
+    }
+
+    let sections = contents.sections();
+    let contents = serde_json::to_value(contents).map_err(|e| e.to_string())?;
+    let serialized = serde_json::to_vec(&contents).map_err(|e| e.to_string())?;
+
+    let bundle = StateBundleFile {
+        manifest: BundleManifest {
+            bundle_version: STATE_BUNDLE_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").into(),
+            created_at,
+            sections,
+            includes_secrets: include_secrets,
+            checksum: checksum(&serialized),
+        },
+        contents,
+    };
+
+    serde_json::to_vec_pretty(&bundle).map_err(|e| e.to_string())
+}
+
+/// Reads a bundle, rejecting bundles from newer versions of the app and bundles whose
+/// contents don't match their manifest
+pub fn decode_state_bundle(bytes: &[u8]) -> Result<(BundleManifest, BundleContents), String> {
+    let bundle: Value =
+        serde_json::from_slice(bytes).map_err(|e| format!("Bundle is not valid JSON: {}", e))?;
+
+    // Checked before the rest of the manifest, whose shape may change between versions
+    let bundle_version = bundle
+        .get("manifest")
+        .and_then(|manifest| manifest.get("bundleVersion"))
+        .and_then(Value::as_u64)
+        .ok_or("Bundle has no manifest version")?;
+
+    if bundle_version > STATE_BUNDLE_VERSION as u64 {
+        return Err(format!(
+            "Bundle is from a newer version of the app (bundle version {}, this app reads up to {})",
+            bundle_version, STATE_BUNDLE_VERSION
+        ));
+    }
+
+    let bundle: StateBundleFile =
+        serde_json::from_value(bundle).map_err(|e| format!("Invalid bundle: {}", e))?;
+
+    // Serializing the parsed contents reproduces the bytes that were hashed, as floats
+    // are parsed exactly with serde_json's `float_roundtrip`
+    let serialized = serde_json::to_vec(&bundle.contents).map_err(|e| e.to_string())?;
+
+    if checksum(&serialized) != bundle.manifest.checksum {
+        return Err("Bundle is damaged, its contents don't match its checksum".into());
+    }
+
+    let contents: BundleContents = serde_json::from_value(bundle.contents)
+        .map_err(|e| format!("Invalid bundle contents: {}", e))?;
+
+    if contents.sections() != bundle.manifest.sections {
+        return Err("Bundle is damaged, its sections don't match its manifest".into());
+    }
+
+    contents.validate()?;
+
+    Ok((bundle.manifest, contents))
+}
+
+pub fn merge_graph_overrides(current: &GraphOverrides, bundled: GraphOverrides) -> GraphOverrides {
+    let mut merged = current.clone();
+
+    for edge in bundled.manual_edges {
+        merged.add_manual_edge(edge);
+    }
+
+    for node_num in bundled.hidden_nodes {
+        if !merged.hidden_nodes.contains(&node_num) {
+            merged.hidden_nodes.push(node_num);
+        }
+    }
+
+    merged.node_labels.extend(bundled.node_labels);
+
+    merged
+}
+
+/// Endpoints are matched by URL. A bundled endpoint without a bearer token keeps the
+/// current endpoint's token, as tokens are left out of bundles by default.
+pub fn merge_webhooks(current: &WebhookConfig, bundled: WebhookConfig) -> WebhookConfig {
+    let mut merged = current.clone();
+
+    for mut endpoint in bundled.endpoints {
+        match merged.endpoints.iter_mut().find(|e| e.url == endpoint.url) {
+            Some(existing) => {
+                if endpoint.bearer_token.is_none() {
+                    endpoint.bearer_token = existing.bearer_token.take();
+                }
+
+                *existing = endpoint;
+            }
+            None => merged.endpoints.push(endpoint),
+        }
+    }
+
+    merged
+}
+
+/// Geofences are matched by id
+pub fn merge_geofences(current: &GeofenceConfig, bundled: GeofenceConfig) -> GeofenceConfig {
+    let mut merged = current.clone();
+
+    for fence in bundled.fences {
+        match merged.fences.iter_mut().find(|f| f.id == fence.id) {
+            Some(existing) => *existing = fence,
+            None => merged.fences.push(fence),
+        }
+    }
+
+    merged
+}
+
+/// Gathers the requested sections of the current state and writes them to `path`
+pub fn export_state_bundle(
+    handle: &tauri::AppHandle,
+    path: &Path,
+    include: BundleSections,
+) -> Result<BundleManifest, String> {
+    let sections = match include.sections {
+        Some(sections) if sections.is_empty() => {
+            return Err("A bundle needs at least one section".into())
+        }
+        Some(sections) => sections,
+        None => BUNDLE_SECTIONS.to_vec(),
+    };
+
+    let includes = |section| sections.contains(&section);
+    let mut contents = BundleContents::default();
+
+    if includes(BundleSection::Settings) {
+        let settings_state = handle.state::<state::settings::SettingsState>();
+        let settings = settings_state.inner.lock().map_err(|e| e.to_string())?;
+        contents.settings = Some(settings.clone());
+    }
+
+    if includes(BundleSection::GraphOverrides) {
+        let graph_state = handle.state::<state::graph::GraphState>();
+        let graph = graph_state.inner.read()?;
+        contents.graph_overrides = Some(graph.overrides.clone());
+    }
+
+    if includes(BundleSection::GraphDatabase) {
+        let store_state = handle.state::<state::graph_store::GraphStoreState>();
+        let store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+        let store = store_guard
+            .as_ref()
+            .ok_or("Graph database is not available")?;
+        contents.graph_database = Some(store.read_all_snapshots()?);
+    }
+
+    if includes(BundleSection::Messages) {
+        let store_state = handle.state::<state::message_store::MessageStoreState>();
+        let store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+        let store = store_guard
+            .as_ref()
+            .ok_or("Message database is not available")?;
+        contents.messages = Some(store.all_messages()?);
+    }
+
+    if includes(BundleSection::Webhooks) {
+        let webhooks_state = handle.state::<state::webhooks::WebhooksState>();
+        let webhooks = webhooks_state.inner.lock().map_err(|e| e.to_string())?;
+        contents.webhooks = Some(webhooks.clone());
+    }
+
+    if includes(BundleSection::Geofences) {
+        let geofences_state = handle.state::<state::geofences::GeofencesState>();
+        let geofences = geofences_state.inner.lock().map_err(|e| e.to_string())?;
+        contents.geofences = Some(geofences.config.clone());
+    }
+
+    let bytes = encode_state_bundle(contents, get_current_time_u32(), include.include_secrets)?;
+    write_atomically(path, &bytes)?;
+
+    let (manifest, _) = decode_state_bundle(&bytes)?;
+
+    Ok(manifest)
+}
+
+/// Writes to a temporary file next to `path` and renames it into place
+fn write_atomically(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let temp_path = path.with_extension("tmp");
+
+    std::fs::write(&temp_path, bytes)
+        .map_err(|e| format!("Failed to write {:?}: {}", temp_path, e))?;
+    std::fs::rename(&temp_path, path).map_err(|e| format!("Failed to write {:?}: {}", path, e))
+}
+
+/// Settings files written during an import, restored if a later step fails
+struct FileRollback {
+    originals: Vec<(PathBuf, Option<Vec<u8>>)>,
+}
+
+impl FileRollback {
+    fn write<T: Serialize>(
+        &mut self,
+        handle: &tauri::AppHandle,
+        file_name: &str,
+        value: &T,
+    ) -> Result<(), String> {
+        let path = settings_file_path(handle, file_name)?;
+        let original = std::fs::read(&path).ok();
+        let contents = serde_json::to_vec_pretty(value).map_err(|e| e.to_string())?;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {:?}: {}", parent, e))?;
+        }
+
+        write_atomically(&path, &contents)?;
+        self.originals.push((path, original));
+
+        Ok(())
+    }
+
+    fn restore(self) {
+        for (path, original) in self.originals.into_iter().rev() {
+            let result = match original {
+                Some(contents) => write_atomically(&path, &contents),
+                None => std::fs::remove_file(&path).map_err(|e| e.to_string()),
+            };
+
+            if let Err(e) = result {
+                warn!("Failed to restore {:?} after a failed import: {}", path, e);
+            }
+        }
+    }
+}
+
+/// Imports a bundle. Every section is validated and merged before anything changes.
+/// Database sections are written in transactions that are only committed once the
+/// settings files are written, and the running app's state is replaced last.
+pub fn import_state_bundle(
+    handle: &tauri::AppHandle,
+    path: &Path,
+    mode: ImportMode,
+) -> Result<BundleManifest, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    let (manifest, bundled) = decode_state_bundle(&bytes)?;
+
+    debug!(
+        "Importing {:?} from bundle created at {}",
+        manifest.sections, manifest.created_at
+    );
+
+    let replace = mode == ImportMode::Replace;
+
+    // Merge the in-memory sections with the current state
+
+    let settings = bundled.settings;
+
+    let graph_overrides = match bundled.graph_overrides {
+        Some(overrides) if !replace => {
+            let graph_state = handle.state::<state::graph::GraphState>();
+            let graph = graph_state.inner.read()?;
+            Some(merge_graph_overrides(&graph.overrides, overrides))
+        }
+        overrides => overrides,
+    };
+
+    let webhooks = match bundled.webhooks {
+        Some(webhooks) if !replace => {
+            let webhooks_state = handle.state::<state::webhooks::WebhooksState>();
+            let current = webhooks_state.inner.lock().map_err(|e| e.to_string())?;
+            Some(merge_webhooks(&current, webhooks))
+        }
+        webhooks => webhooks,
+    };
+
+    let geofences = match bundled.geofences {
+        Some(geofences) if !replace => {
+            let geofences_state = handle.state::<state::geofences::GeofencesState>();
+            let current = geofences_state.inner.lock().map_err(|e| e.to_string())?;
+            Some(merge_geofences(&current.config, geofences))
+        }
+        geofences => geofences,
+    };
+
+    if let Some(geofences) = &geofences {
+        geofences.validate()?;
+    }
+
+    // Write the database sections, uncommitted until the files are written
+
+    let graph_store_state = handle.state::<state::graph_store::GraphStoreState>();
+    let mut graph_store_guard = graph_store_state.inner.lock().map_err(|e| e.to_string())?;
+
+    let graph_transaction = match &bundled.graph_database {
+        Some(snapshots) => {
+            let store = graph_store_guard
+                .as_mut()
+                .ok_or("Graph database is not available")?;
+            let transaction = store.import_transaction()?;
+            store::import_snapshots(&transaction, snapshots, replace)?;
+            Some(transaction)
+        }
+        None => None,
+    };
+
+    let message_store_state = handle.state::<state::message_store::MessageStoreState>();
+    let mut message_store_guard = message_store_state
+        .inner
+        .lock()
+        .map_err(|e| e.to_string())?;
+
+    let message_transaction = match &bundled.messages {
+        Some(messages) => {
+            let store = message_store_guard
+                .as_mut()
+                .ok_or("Message database is not available")?;
+            let transaction = store.import_transaction()?;
+            message_store::import_messages(&transaction, messages, replace)?;
+            Some(transaction)
+        }
+        None => None,
+    };
+
+    let mut rollback = FileRollback { originals: vec![] };
+
+    let written = (|| {
+        if let Some(settings) = &settings {
+            rollback.write(handle, SETTINGS_FILE_NAME, settings)?;
+        }
+
+        if let Some(overrides) = &graph_overrides {
+            rollback.write(handle, GRAPH_OVERRIDES_FILE_NAME, overrides)?;
+        }
+
+        if let Some(webhooks) = &webhooks {
+            rollback.write(handle, WEBHOOKS_FILE_NAME, webhooks)?;
+        }
+
+        if let Some(geofences) = &geofences {
+            rollback.write(handle, GEOFENCES_FILE_NAME, geofences)?;
+        }
+
+        if let Some(transaction) = graph_transaction {
+            transaction.commit().map_err(|e| e.to_string())?;
+        }
+
+        if let Some(transaction) = message_transaction {
+            transaction.commit().map_err(|e| e.to_string())?;
+        }
+
+        Ok::<(), String>(())
+    })();
+
+    if let Err(e) = written {
+        rollback.restore();
+        return Err(e);
+    }
+
+    drop(graph_store_guard);
+    drop(message_store_guard);
+
+    // Replace the running app's state
+
+    if let Some(settings) = settings {
+        let settings_state = handle.state::<state::settings::SettingsState>();
+        *settings_state.inner.lock().map_err(|e| e.to_string())? = settings.clone();
+
+        apply_settings(handle, &settings);
+        events::dispatch_settings_changed(handle, settings).map_err(|e| e.to_string())?;
+    }
+
+    if let Some(overrides) = graph_overrides {
+        let graph = {
+            let graph_state = handle.state::<state::graph::GraphState>();
+            let mut graph = graph_state.inner.write()?;
+            graph.set_overrides(overrides);
+            graph.clone()
+        };
+
+        events::dispatch_updated_graph(handle, None, graph).map_err(|e| e.to_string())?;
+    }
+
+    if let Some(webhooks) = webhooks {
+        let webhooks_state = handle.state::<state::webhooks::WebhooksState>();
+        *webhooks_state.inner.lock().map_err(|e| e.to_string())? = webhooks;
+    }
+
+    if let Some(geofences) = geofences {
+        let geofences_state = handle.state::<state::geofences::GeofencesState>();
+        geofences_state
+            .inner
+            .lock()
+            .map_err(|e| e.to_string())?
+            .set_config(geofences);
+    }
+
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::message_store::MessageStore;
+    use crate::device::reactions::MessageReactions;
+    use crate::device::{ChannelMessageState, MeshDevice};
+    use crate::graph::ds::{edge::GraphEdge, fixtures::graph_node, graph::MeshGraph};
+    use crate::graph::store::GraphStore;
+    use crate::notifications::geofences::{Geofence, GeofencePoint, GeofenceShape};
+    use crate::notifications::webhooks::{WebhookEndpoint, WebhookEventType};
+
+    fn round_trip(contents: BundleContents) -> BundleContents {
+        let bytes = encode_state_bundle(contents, 1_700_000_000, true).unwrap();
+        let (manifest, decoded) = decode_state_bundle(&bytes).unwrap();
+
+        assert_eq!(manifest.bundle_version, STATE_BUNDLE_VERSION);
+        assert_eq!(manifest.sections, decoded.sections());
+
+        decoded
+    }
+
+    fn webhooks(token: Option<&str>) -> WebhookConfig {
+        WebhookConfig {
+            endpoints: vec![WebhookEndpoint {
+                url: "https://example.com/hook".into(),
+                event_types: vec![WebhookEventType::LowBattery],
+                channel: None,
+                bearer_token: token.map(String::from),
+                enabled: true,
+            }],
+        }
+    }
+
+    fn graph_snapshot() -> GraphSnapshot {
+        let mut graph = MeshGraph::new();
+        graph.upsert_node(graph_node(1));
+        graph.upsert_node(graph_node(2));
+
+        graph.upsert_edge(
+            graph_node(2),
+            graph_node(1),
+            GraphEdge::from_neighbor(
+                1,
+                0,
+                "LongFast".into(),
+                protobufs::Neighbor {
+                    node_id: 2,
+                    snr: 4.5,
+                    ..Default::default()
+                },
+            ),
+        );
+        graph.set_node_label(2, Some("Barn".into()));
+
+        GraphSnapshot::new(1, &graph, &MeshDevice::new()).unwrap()
+    }
+
+    fn message(packet_id: u32, text: &str) -> StoredMessage {
+        StoredMessage {
+            device_id: 1,
+            packet_id,
+            channel: 0,
+            from: 2,
+            to: 0xffff_ffff,
+            timestamp: 1_000 + packet_id,
+            text: text.into(),
+            state: ChannelMessageState::Acknowledged,
+            reactions: MessageReactions::from([("👍".into(), vec![3])]),
+            device_key: None,
+        }
+    }
+
+    #[test]
+    fn round_trips_in_memory_sections() {
+        let mut settings = AppSettings::default();
+        settings.autosave.keep = 9;
+
+        let settings_only = BundleContents {
+            settings: Some(settings),
+            ..Default::default()
+        };
+        assert_eq!(round_trip(settings_only.clone()), settings_only);
+
+        let overrides = BundleContents {
+            graph_overrides: Some(GraphOverrides {
+                hidden_nodes: vec![4],
+                node_labels: HashMap::from([(2, "Barn".into())]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(round_trip(overrides.clone()), overrides);
+
+        let webhooks_only = BundleContents {
+            webhooks: Some(webhooks(Some("secret"))),
+            ..Default::default()
+        };
+        assert_eq!(round_trip(webhooks_only.clone()), webhooks_only);
+
+        let geofences = BundleContents {
+            geofences: Some(GeofenceConfig {
+                fences: vec![Geofence {
+                    id: "camp".into(),
+                    name: "Camp".into(),
+                    shape: GeofenceShape::Circle {
+                        center: GeofencePoint {
+                            latitude: 45.5,
+                            longitude: -122.25,
+                        },
+                        radius_meters: 150.0,
+                    },
+                    hysteresis_meters: 10.0,
+                    notify: true,
+                }],
+            }),
+            ..Default::default()
+        };
+        assert_eq!(round_trip(geofences.clone()), geofences);
+    }
+
+    #[test]
+    fn round_trips_database_sections() {
+        let mut graph_store = GraphStore::open_in_memory().unwrap();
+        graph_store.write(graph_snapshot()).unwrap();
+
+        let mut message_store = MessageStore::open_in_memory().unwrap();
+        message_store
+            .write(&[message(1, "Gate is open"), message(2, "Heading out")])
+            .unwrap();
+
+        let decoded = round_trip(BundleContents {
+            graph_database: Some(graph_store.read_all_snapshots().unwrap()),
+            messages: Some(message_store.all_messages().unwrap()),
+            ..Default::default()
+        });
+
+        let mut imported_graph = GraphStore::open_in_memory().unwrap();
+        let transaction = imported_graph.import_transaction().unwrap();
+        store::import_snapshots(&transaction, decoded.graph_database.as_ref().unwrap(), true)
+            .unwrap();
+        transaction.commit().unwrap();
+
+        assert_eq!(
+            imported_graph.read_all_snapshots().unwrap(),
+            graph_store.read_all_snapshots().unwrap()
+        );
+
+        let mut imported_messages = MessageStore::open_in_memory().unwrap();
+        imported_messages.write(&[message(9, "Replaced")]).unwrap();
+
+        let transaction = imported_messages.import_transaction().unwrap();
+        message_store::import_messages(&transaction, decoded.messages.as_ref().unwrap(), true)
+            .unwrap();
+        transaction.commit().unwrap();
+
+        assert_eq!(
+            imported_messages.all_messages().unwrap(),
+            message_store.all_messages().unwrap()
+        );
+        assert_eq!(
+            imported_messages.search_messages("gate", 10).unwrap().len(),
+            1
+        );
+        assert!(imported_messages
+            .search_messages("replaced", 10)
+            .unwrap()
+            .is_empty());
+
+        // An import that isn't committed leaves the database as it was
+        let transaction = imported_messages.import_transaction().unwrap();
+        message_store::import_messages(&transaction, &[], true).unwrap();
+        drop(transaction);
+
+        assert_eq!(imported_messages.all_messages().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn leaves_out_secrets_unless_requested() {
+        let contents = BundleContents {
+            webhooks: Some(webhooks(Some("secret"))),
+            ..Default::default()
+        };
+
+        let bytes = encode_state_bundle(contents.clone(), 0, false).unwrap();
+        let (manifest, decoded) = decode_state_bundle(&bytes).unwrap();
+
+        assert!(!manifest.includes_secrets);
+        assert_eq!(decoded.webhooks, Some(webhooks(None)));
+        assert!(!String::from_utf8(bytes).unwrap().contains("secret"));
+
+        // Merging keeps the current token of an endpoint bundled without one
+        let merged = merge_webhooks(&webhooks(Some("secret")), decoded.webhooks.unwrap());
+        assert_eq!(merged, webhooks(Some("secret")));
+
+        let bytes = encode_state_bundle(contents, 0, true).unwrap();
+        assert_eq!(
+            decode_state_bundle(&bytes).unwrap().1.webhooks,
+            Some(webhooks(Some("secret")))
+        );
+    }
+
+    #[test]
+    fn rejects_damaged_and_newer_bundles() {
+        let contents = BundleContents {
+            messages: Some(vec![message(1, "Gate is open")]),
+            ..Default::default()
+        };
+        let bytes = encode_state_bundle(contents, 0, false).unwrap();
+        let text = String::from_utf8(bytes.clone()).unwrap();
+
+        let edited = text.replace("Gate is open", "Gate is shut");
+        assert!(decode_state_bundle(edited.as_bytes())
+            .unwrap_err()
+            .contains("checksum"));
+
+        assert!(decode_state_bundle(&bytes[..bytes.len() / 2])
+            .unwrap_err()
+            .contains("not valid JSON"));
+
+        let mut bundle: Value = serde_json::from_slice(&bytes).unwrap();
+        bundle["manifest"]["bundleVersion"] = (STATE_BUNDLE_VERSION + 1).into();
+        let newer = serde_json::to_vec(&bundle).unwrap();
+        assert!(decode_state_bundle(&newer)
+            .unwrap_err()
+            .contains("newer version"));
+
+        let mut bundle: Value = serde_json::from_slice(&bytes).unwrap();
+        bundle["manifest"]["sections"] = serde_json::json!(["settings", "messages"]);
+        let mismatched = serde_json::to_vec(&bundle).unwrap();
+        assert!(decode_state_bundle(&mismatched)
+            .unwrap_err()
+            .contains("manifest"));
+    }
+}
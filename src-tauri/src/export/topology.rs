@@ -0,0 +1,346 @@
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::device::MeshDevice;
+use crate::graph::ds::graph::MeshGraph;
+use crate::graph::geojson::{edge_feature_id, node_long_name};
+
+use super::xml::XmlWriter;
+
+const GRAPHML_NAMESPACE: &str = "http://graphml.graphdrawing.org/xmlns";
+
+/// File formats read by graph analysis tools such as Gephi and Graphviz
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum TopologyFormat {
+    GraphMl,
+    Dot,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TopologyExportSummary {
+    pub nodes: u32,
+    pub edges: u32,
+    pub bytes: u32,
+}
+
+/// Attributes of a node, in the order they're written
+struct NodeAttributes {
+    id: String,
+    name: String,
+    degree: u32,
+    weighted_degree: f64,
+    position: Option<(f32, f32)>, // latitude, longitude
+}
+
+fn node_attributes(graph: &MeshGraph, device: &MeshDevice) -> Vec<NodeAttributes> {
+    let degrees = graph.node_degrees();
+
+    let mut node_nums: Vec<u32> = graph.nodes_lookup.keys().copied().collect();
+    node_nums.sort();
+
+    node_nums
+        .into_iter()
+        .map(|node_num| {
+            let id = format!("!{:08x}", node_num);
+            let (degree, weighted_degree) = degrees.get(&node_num).copied().unwrap_or_default();
+
+            NodeAttributes {
+                name: graph
+                    .display_label(node_num, node_long_name(device, node_num))
+                    .unwrap_or_else(|| id.clone()),
+                id,
+                degree,
+                weighted_degree,
+                position: device
+                    .nodes
+                    .get(&node_num)
+                    .and_then(|node| node.last_known_position())
+                    .map(|position| (position.latitude, position.longitude)),
+            }
+        })
+        .collect()
+}
+
+/// Builds a GraphML document with a node per graph node and an edge per reported
+/// edge, weighted by SNR. Links reported in both directions are two edges.
+pub fn to_graphml(graph: &MeshGraph, device: &MeshDevice) -> String {
+    let mut writer = XmlWriter::new();
+    writer.open("graphml", &[("xmlns", GRAPHML_NAMESPACE.into())]);
+
+    let keys = [
+        ("name", "node", "string"),
+        ("degree", "node", "int"),
+        ("weightedDegree", "node", "double"),
+        ("latitude", "node", "double"),
+        ("longitude", "node", "double"),
+        ("weight", "edge", "double"),
+        ("source", "edge", "string"),
+        ("channel", "edge", "int"),
+    ];
+
+    for (name, domain, attribute_type) in keys {
+        writer.element(
+            "key",
+            &[
+                ("id", name.into()),
+                ("for", domain.into()),
+                ("attr.name", name.into()),
+                ("attr.type", attribute_type.into()),
+            ],
+            "",
+        );
+    }
+
+    writer.open(
+        "graph",
+        &[("id", "mesh".into()), ("edgedefault", "directed".into())],
+    );
+
+    for node in node_attributes(graph, device) {
+        writer.open("node", &[("id", node.id)]);
+        writer.element("data", &[("key", "name".into())], &node.name);
+        writer.element(
+            "data",
+            &[("key", "degree".into())],
+            &node.degree.to_string(),
+        );
+        writer.element(
+            "data",
+            &[("key", "weightedDegree".into())],
+            &format!("{:.2}", node.weighted_degree),
+        );
+
+        if let Some((latitude, longitude)) = node.position {
+            writer.element(
+                "data",
+                &[("key", "latitude".into())],
+                &format!("{:.7}", latitude),
+            );
+            writer.element(
+                "data",
+                &[("key", "longitude".into())],
+                &format!("{:.7}", longitude),
+            );
+        }
+
+        writer.close();
+    }
+
+    let mut edges: Vec<_> = graph.edges().collect();
+    edges.sort_by_key(|(from, to, _)| (from.node_num, to.node_num));
+
+    for (from, to, edge) in edges {
+        writer.open(
+            "edge",
+            &[
+                ("id", edge_feature_id(edge)),
+                ("source", format!("!{:08x}", from.node_num)),
+                ("target", format!("!{:08x}", to.node_num)),
+            ],
+        );
+        writer.element(
+            "data",
+            &[("key", "weight".into())],
+            &format!("{:.2}", edge.snr()),
+        );
+        writer.element("data", &[("key", "source".into())], edge.source.tag());
+        writer.element(
+            "data",
+            &[("key", "channel".into())],
+            &edge.channel.to_string(),
+        );
+        writer.close();
+    }
+
+    writer.finish()
+}
+
+/// Quotes text as a DOT identifier, which may then contain any characters
+fn dot_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => {}
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// Builds a Graphviz DOT digraph with the same nodes, edges and attributes as
+/// `to_graphml`. Edge SNR is written as `snr`, since Graphviz reads `weight` as a
+/// non-negative layout hint.
+pub fn to_dot(graph: &MeshGraph, device: &MeshDevice) -> String {
+    let mut dot = String::from("digraph mesh {\n");
+
+    for node in node_attributes(graph, device) {
+        let mut attributes = vec![
+            format!("label={}", dot_string(&node.name)),
+            format!("degree={}", node.degree),
+            format!("weighted_degree={:.2}", node.weighted_degree),
+        ];
+
+        if let Some((latitude, longitude)) = node.position {
+            attributes.push(format!("latitude={:.7}", latitude));
+            attributes.push(format!("longitude={:.7}", longitude));
+        }
+
+        dot.push_str(&format!(
+            "  {} [{}];\n",
+            dot_string(&node.id),
+            attributes.join(", ")
+        ));
+    }
+
+    let mut edges: Vec<_> = graph.edges().collect();
+    edges.sort_by_key(|(from, to, _)| (from.node_num, to.node_num));
+
+    for (from, to, edge) in edges {
+        dot.push_str(&format!(
+            "  {} -> {} [snr={:.2}, source={}, channel={}];\n",
+            dot_string(&format!("!{:08x}", from.node_num)),
+            dot_string(&format!("!{:08x}", to.node_num)),
+            edge.snr(),
+            dot_string(edge.source.tag()),
+            edge.channel
+        ));
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+pub fn build_topology(graph: &MeshGraph, device: &MeshDevice, format: TopologyFormat) -> String {
+    match format {
+        TopologyFormat::GraphMl => to_graphml(graph, device),
+        TopologyFormat::Dot => to_dot(graph, device),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::MeshNode;
+    use crate::export::xml::parse::{parse_document, XmlElement};
+    use crate::graph::ds::edge::GraphEdge;
+    use crate::graph::ds::fixtures::graph_node;
+
+    fn node(node_num: u32, long_name: &str, position: Option<(f32, f32)>) -> MeshNode {
+        let mut node = MeshNode::new(node_num);
+
+        node.update_from_node_info(protobufs::NodeInfo {
+            num: node_num,
+            user: Some(protobufs::User {
+                long_name: long_name.into(),
+                ..Default::default()
+            }),
+            position: position.map(|(latitude, longitude)| protobufs::Position {
+                latitude_i: (latitude * 1e7) as i32,
+                longitude_i: (longitude * 1e7) as i32,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        node
+    }
+
+    /// Nodes 1 and 2 report the link between them both ways, node 3 has a name that
+    /// needs escaping and no position, and node 4 hasn't sent its user info
+    fn fixture() -> (MeshGraph, MeshDevice) {
+        let mut graph = MeshGraph::new();
+
+        for node_num in 1..=4 {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        for (from, to, weight) in [(1, 2, 6.0), (2, 1, -7.5), (3, 1, 1.0)] {
+            graph.upsert_edge(
+                graph_node(from),
+                graph_node(to),
+                GraphEdge::manual(from, to, weight),
+            );
+        }
+
+        let mut device = MeshDevice::new();
+        device
+            .nodes
+            .insert(1, node(1, "Hilltop", Some((45.5, -73.6))));
+        device
+            .nodes
+            .insert(2, node(2, "Base & <Camp>", Some((45.6, -73.5))));
+        device.nodes.insert(3, node(3, "Say \"hi\" \\o/", None));
+
+        (graph, device)
+    }
+
+    #[test]
+    fn graphml_has_every_node_and_directed_edge() {
+        let (graph, device) = fixture();
+
+        let graphml = parse_document(&to_graphml(&graph, &device)).unwrap();
+        assert_eq!(graphml.children_named("key").count(), 8);
+
+        let mesh = graphml.child("graph").unwrap();
+        assert_eq!(mesh.attribute("edgedefault"), Some("directed"));
+
+        let nodes: Vec<_> = mesh.children_named("node").collect();
+        assert_eq!(nodes.len(), 4);
+
+        // The link reported both ways is two edges
+        let edges: Vec<_> = mesh.children_named("edge").collect();
+        assert_eq!(edges.len(), 3);
+        assert_eq!(edges[1].attribute("source"), Some("!00000002"));
+        assert_eq!(edges[1].attribute("target"), Some("!00000001"));
+
+        let data = |element: &XmlElement, key: &str| {
+            element
+                .children_named("data")
+                .find(|data| data.attribute("key") == Some(key))
+                .map(|data| data.text.clone())
+        };
+
+        assert_eq!(data(edges[1], "weight").as_deref(), Some("-7.50"));
+        assert_eq!(data(edges[1], "source").as_deref(), Some("manual"));
+
+        assert_eq!(data(nodes[0], "name").as_deref(), Some("Hilltop"));
+        assert_eq!(data(nodes[0], "degree").as_deref(), Some("3"));
+        assert_eq!(data(nodes[0], "weightedDegree").as_deref(), Some("-0.50"));
+        assert_eq!(data(nodes[0], "latitude").as_deref(), Some("45.5000000"));
+        assert_eq!(data(nodes[1], "name").as_deref(), Some("Base & <Camp>"));
+
+        assert_eq!(data(nodes[2], "latitude"), None);
+        assert_eq!(data(nodes[3], "name").as_deref(), Some("!00000004"));
+        assert_eq!(data(nodes[3], "degree").as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn dot_quotes_names_and_ids() {
+        let (graph, device) = fixture();
+
+        let dot = to_dot(&graph, &device);
+        let lines: Vec<&str> = dot.lines().collect();
+
+        assert_eq!(lines.first(), Some(&"digraph mesh {"));
+        assert_eq!(lines.last(), Some(&"}"));
+        assert_eq!(lines.iter().filter(|line| line.contains("->")).count(), 3);
+
+        assert!(lines.contains(
+            &"  \"!00000003\" [label=\"Say \\\"hi\\\" \\\\o/\", degree=1, weighted_degree=1.00];"
+        ));
+        assert!(lines.contains(
+            &"  \"!00000002\" -> \"!00000001\" [snr=-7.50, source=\"manual\", channel=0];"
+        ));
+    }
+}
@@ -0,0 +1,274 @@
+/// Escapes text for use in XML character data and attribute values
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters other than whitespace aren't allowed in XML 1.0
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Writes an indented XML document, escaping all text and attribute values
+pub struct XmlWriter {
+    buffer: String,
+    open_elements: Vec<&'static str>,
+}
+
+impl XmlWriter {
+    pub fn new() -> Self {
+        Self {
+            buffer: "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".into(),
+            open_elements: vec![],
+        }
+    }
+
+    fn start_tag(&mut self, name: &str, attributes: &[(&str, String)]) {
+        self.buffer.push_str(&"  ".repeat(self.open_elements.len()));
+        self.buffer.push('<');
+        self.buffer.push_str(name);
+
+        for (key, value) in attributes {
+            self.buffer
+                .push_str(&format!(" {}=\"{}\"", key, escape_xml(value)));
+        }
+
+        self.buffer.push('>');
+    }
+
+    /// Opens an element that will contain child elements
+    pub fn open(&mut self, name: &'static str, attributes: &[(&str, String)]) {
+        self.start_tag(name, attributes);
+        self.buffer.push('\n');
+        self.open_elements.push(name);
+    }
+
+    /// Closes the most recently opened element
+    pub fn close(&mut self) {
+        if let Some(name) = self.open_elements.pop() {
+            self.buffer.push_str(&"  ".repeat(self.open_elements.len()));
+            self.buffer.push_str(&format!("</{}>\n", name));
+        }
+    }
+
+    /// Writes an element containing only text
+    pub fn element(&mut self, name: &str, attributes: &[(&str, String)], text: &str) {
+        self.start_tag(name, attributes);
+        self.buffer
+            .push_str(&format!("{}</{}>\n", escape_xml(text), name));
+    }
+
+    /// Closes any elements left open and returns the document
+    pub fn finish(mut self) -> String {
+        while !self.open_elements.is_empty() {
+            self.close();
+        }
+
+        self.buffer
+    }
+}
+
+impl Default for XmlWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Just enough of an XML parser to check the structure of generated documents
+#[cfg(test)]
+pub mod parse {
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct XmlElement {
+        pub name: String,
+        pub attributes: Vec<(String, String)>,
+        pub text: String,
+        pub children: Vec<XmlElement>,
+    }
+
+    impl XmlElement {
+        pub fn attribute(&self, key: &str) -> Option<&str> {
+            self.attributes
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        }
+
+        pub fn children_named<'a>(
+            &'a self,
+            name: &'a str,
+        ) -> impl Iterator<Item = &'a XmlElement> + 'a {
+            self.children.iter().filter(move |child| child.name == name)
+        }
+
+        pub fn child(&self, name: &str) -> Option<&XmlElement> {
+            self.children.iter().find(|child| child.name == name)
+        }
+
+        /// All descendants with the given name, depth first
+        pub fn descendants_named<'a>(&'a self, name: &str) -> Vec<&'a XmlElement> {
+            let mut found = vec![];
+
+            for child in &self.children {
+                if child.name == name {
+                    found.push(child);
+                }
+
+                found.extend(child.descendants_named(name));
+            }
+
+            found
+        }
+    }
+
+    fn unescape(text: &str) -> Result<String, String> {
+        let mut unescaped = String::new();
+        let mut rest = text;
+
+        while let Some(start) = rest.find('&') {
+            unescaped.push_str(&rest[..start]);
+
+            let end = rest[start..]
+                .find(';')
+                .ok_or_else(|| format!("Unterminated entity in \"{}\"", text))?;
+
+            unescaped.push(match &rest[start + 1..start + end] {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                entity => return Err(format!("Unknown entity \"{}\"", entity)),
+            });
+
+            rest = &rest[start + end + 1..];
+        }
+
+        if rest.contains('<') {
+            return Err(format!("Unescaped '<' in \"{}\"", text));
+        }
+
+        unescaped.push_str(rest);
+        Ok(unescaped)
+    }
+
+    fn parse_tag(tag: &str) -> Result<XmlElement, String> {
+        let mut parts = tag.splitn(2, char::is_whitespace);
+        let name = parts.next().unwrap_or_default().to_string();
+        let mut rest = parts.next().unwrap_or_default().trim();
+        let mut attributes = vec![];
+
+        while !rest.is_empty() {
+            let eq = rest
+                .find("=\"")
+                .ok_or_else(|| format!("Malformed attributes in <{}>", tag))?;
+            let value_end = rest[eq + 2..]
+                .find('"')
+                .ok_or_else(|| format!("Unterminated attribute in <{}>", tag))?;
+
+            attributes.push((
+                rest[..eq].trim().to_string(),
+                unescape(&rest[eq + 2..eq + 2 + value_end])?,
+            ));
+
+            rest = rest[eq + 2 + value_end + 1..].trim();
+        }
+
+        Ok(XmlElement {
+            name,
+            attributes,
+            ..Default::default()
+        })
+    }
+
+    /// Parses a document, failing on mismatched tags or bad escaping
+    pub fn parse_document(xml: &str) -> Result<XmlElement, String> {
+        let mut rest = xml.trim_start();
+
+        if rest.starts_with("<?") {
+            let end = rest.find("?>").ok_or("Unterminated XML declaration")?;
+            rest = &rest[end + 2..];
+        }
+
+        let mut stack: Vec<XmlElement> = vec![XmlElement::default()];
+
+        while let Some(start) = rest.find('<') {
+            let text = unescape(&rest[..start])?;
+            let end = rest[start..].find('>').ok_or("Unterminated tag")? + start;
+            let tag = &rest[start + 1..end];
+
+            stack.last_mut().unwrap().text.push_str(text.trim());
+
+            if let Some(name) = tag.strip_prefix('/') {
+                let element = stack.pop().ok_or("Unbalanced closing tag")?;
+
+                if element.name != name {
+                    return Err(format!("Expected </{}>, found </{}>", element.name, name));
+                }
+
+                stack
+                    .last_mut()
+                    .ok_or("Unbalanced closing tag")?
+                    .children
+                    .push(element);
+            } else if let Some(tag) = tag.strip_suffix('/') {
+                let element = parse_tag(tag.trim())?;
+                stack.last_mut().unwrap().children.push(element);
+            } else {
+                stack.push(parse_tag(tag)?);
+            }
+
+            rest = &rest[end + 1..];
+        }
+
+        let mut document = stack.pop().ok_or("Unbalanced closing tag")?;
+
+        if !stack.is_empty() || document.children.len() != 1 {
+            return Err("Document must have exactly one root element".into());
+        }
+
+        Ok(document.children.remove(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse::parse_document;
+    use super::*;
+
+    #[test]
+    fn escapes_special_characters() {
+        assert_eq!(escape_xml("Tom & Jerry"), "Tom &amp; Jerry");
+        assert_eq!(
+            escape_xml("<a href=\"x\">'"),
+            "&lt;a href=&quot;x&quot;&gt;&apos;"
+        );
+        assert_eq!(escape_xml("bell\u{7}"), "bell");
+    }
+
+    #[test]
+    fn writes_nested_documents() {
+        let mut writer = XmlWriter::new();
+        writer.open("root", &[("name", "a & b".into())]);
+        writer.open("child", &[]);
+        writer.element("leaf", &[], "<text>");
+        let xml = writer.finish();
+
+        let root = parse_document(&xml).unwrap();
+
+        assert_eq!(root.name, "root");
+        assert_eq!(root.attribute("name"), Some("a & b"));
+        assert_eq!(
+            root.child("child").unwrap().child("leaf").unwrap().text,
+            "<text>"
+        );
+    }
+}
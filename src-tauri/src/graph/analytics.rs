@@ -0,0 +1,794 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque};
+
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use super::ds::{
+    edge::GraphEdge,
+    graph::{MeshGraph, PathCost},
+};
+
+/// Neighbors of each node, treating every edge as an undirected link
+type Adjacency = BTreeMap<u32, BTreeSet<u32>>;
+
+fn undirected_adjacency(graph: &MeshGraph) -> Adjacency {
+    let mut adjacency: Adjacency = graph
+        .nodes()
+        .map(|node| (node.node_num, BTreeSet::new()))
+        .collect();
+
+    for (from, to, _) in graph.edges() {
+        if from.node_num == to.node_num {
+            continue;
+        }
+
+        adjacency
+            .entry(from.node_num)
+            .or_default()
+            .insert(to.node_num);
+        adjacency
+            .entry(to.node_num)
+            .or_default()
+            .insert(from.node_num);
+    }
+
+    adjacency
+}
+
+/// Sizes of the groups of nodes that can reach each other, leaving out `removed`
+fn component_sizes(adjacency: &Adjacency, removed: Option<u32>) -> Vec<usize> {
+    let mut seen: BTreeSet<u32> = removed.into_iter().collect();
+    let mut sizes = vec![];
+
+    for start in adjacency.keys() {
+        if !seen.insert(*start) {
+            continue;
+        }
+
+        let mut size = 0;
+        let mut queue = VecDeque::from([*start]);
+
+        while let Some(node_num) = queue.pop_front() {
+            size += 1;
+
+            for neighbor in &adjacency[&node_num] {
+                if seen.insert(*neighbor) {
+                    queue.push_back(*neighbor);
+                }
+            }
+        }
+
+        sizes.push(size);
+    }
+
+    sizes
+}
+
+/// Most hops between any two nodes that can reach each other
+fn diameter(adjacency: &Adjacency) -> u32 {
+    let mut diameter = 0;
+
+    for source in adjacency.keys() {
+        let mut distances = HashMap::from([(*source, 0)]);
+        let mut queue = VecDeque::from([*source]);
+
+        while let Some(node_num) = queue.pop_front() {
+            let distance = distances[&node_num];
+            diameter = diameter.max(distance);
+
+            for neighbor in &adjacency[&node_num] {
+                if !distances.contains_key(neighbor) {
+                    distances.insert(*neighbor, distance + 1);
+                    queue.push_back(*neighbor);
+                }
+            }
+        }
+    }
+
+    diameter
+}
+
+/// Share of pairs of links meeting at a node whose other ends are linked too
+fn global_clustering(adjacency: &Adjacency) -> f64 {
+    let mut closed = 0;
+    let mut triples = 0;
+
+    for neighbors in adjacency.values() {
+        let neighbors: Vec<&u32> = neighbors.iter().collect();
+        triples += neighbors.len() * neighbors.len().saturating_sub(1) / 2;
+
+        for (i, a) in neighbors.iter().enumerate() {
+            closed += neighbors[i + 1..]
+                .iter()
+                .filter(|b| adjacency[*a].contains(**b))
+                .count();
+        }
+    }
+
+    if triples > 0 {
+        closed as f64 / triples as f64
+    } else {
+        0.0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphStats {
+    pub node_count: u32,
+    pub edge_count: u32, // directed edges, as reported by nodes
+    pub link_count: u32, // pairs of linked nodes
+    pub connected_components: u32,
+    pub largest_component_size: u32,
+    pub average_degree: f64,
+    pub density: f64,           // share of all possible links that exist
+    pub diameter: u32,          // hops, within the components
+    pub global_clustering: f64, // from 0 to 1
+}
+
+pub fn graph_stats(graph: &MeshGraph) -> GraphStats {
+    let adjacency = undirected_adjacency(graph);
+    let sizes = component_sizes(&adjacency, None);
+
+    let node_count = adjacency.len();
+    let link_count = adjacency.values().map(BTreeSet::len).sum::<usize>() / 2;
+    let possible_links = node_count * node_count.saturating_sub(1) / 2;
+
+    GraphStats {
+        node_count: node_count as u32,
+        edge_count: graph.edge_count() as u32,
+        link_count: link_count as u32,
+        connected_components: sizes.len() as u32,
+        largest_component_size: sizes.iter().max().copied().unwrap_or(0) as u32,
+        average_degree: if node_count > 0 {
+            2.0 * link_count as f64 / node_count as f64
+        } else {
+            0.0
+        },
+        density: if possible_links > 0 {
+            link_count as f64 / possible_links as f64
+        } else {
+            0.0
+        },
+        diameter: diameter(&adjacency),
+        global_clustering: global_clustering(&adjacency),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeCentrality {
+    pub node_num: u32,
+    pub degree: u32,
+
+    /// Share of shortest paths between other nodes that pass through this node,
+    /// from 0 to 1
+    pub betweenness: f64,
+}
+
+/// Adds how much each node lies on the shortest paths from `source` to `betweenness`,
+/// given the nodes in the order they were reached and the shortest paths to each
+fn accumulate_dependencies(
+    source: u32,
+    order: &[u32],
+    predecessors: &HashMap<u32, Vec<u32>>,
+    path_counts: &HashMap<u32, f64>,
+    betweenness: &mut HashMap<u32, f64>,
+) {
+    let mut dependencies: HashMap<u32, f64> = HashMap::new();
+
+    for node_num in order.iter().rev() {
+        let dependency = dependencies.get(node_num).copied().unwrap_or(0.0);
+
+        for predecessor in predecessors.get(node_num).into_iter().flatten() {
+            *dependencies.entry(*predecessor).or_default() +=
+                path_counts[predecessor] / path_counts[node_num] * (1.0 + dependency);
+        }
+
+        if *node_num != source {
+            *betweenness.entry(*node_num).or_default() += dependency;
+        }
+    }
+}
+
+/// Share of the ordered pairs of other nodes, out of `node_count` nodes, whose
+/// paths were counted in `betweenness`
+fn normalized_betweenness(betweenness: f64, node_count: usize) -> f64 {
+    let node_count = node_count as f64;
+    let pairs = (node_count - 1.0) * (node_count - 2.0);
+
+    if pairs > 0.0 {
+        betweenness / pairs
+    } else {
+        0.0
+    }
+}
+
+/// Degree and betweenness centrality of every node, ignoring link direction and
+/// weight, ordered from most to least central
+pub fn node_centralities(graph: &MeshGraph) -> Vec<NodeCentrality> {
+    let adjacency = undirected_adjacency(graph);
+    let mut betweenness: HashMap<u32, f64> = adjacency.keys().map(|n| (*n, 0.0)).collect();
+
+    // Brandes' algorithm, one breadth-first search per source
+    for source in adjacency.keys() {
+        let mut order = vec![];
+        let mut predecessors: HashMap<u32, Vec<u32>> = HashMap::new();
+        let mut path_counts: HashMap<u32, f64> = HashMap::from([(*source, 1.0)]);
+        let mut distances: HashMap<u32, u32> = HashMap::from([(*source, 0)]);
+        let mut queue = VecDeque::from([*source]);
+
+        while let Some(node_num) = queue.pop_front() {
+            order.push(node_num);
+            let distance = distances[&node_num];
+            let path_count = path_counts[&node_num];
+
+            for neighbor in &adjacency[&node_num] {
+                if !distances.contains_key(neighbor) {
+                    distances.insert(*neighbor, distance + 1);
+                    queue.push_back(*neighbor);
+                }
+
+                if distances[neighbor] == distance + 1 {
+                    *path_counts.entry(*neighbor).or_default() += path_count;
+                    predecessors.entry(*neighbor).or_default().push(node_num);
+                }
+            }
+        }
+
+        accumulate_dependencies(
+            *source,
+            &order,
+            &predecessors,
+            &path_counts,
+            &mut betweenness,
+        );
+    }
+
+    let mut centralities: Vec<NodeCentrality> = adjacency
+        .iter()
+        .map(|(node_num, neighbors)| NodeCentrality {
+            node_num: *node_num,
+            degree: neighbors.len() as u32,
+            betweenness: normalized_betweenness(betweenness[node_num], adjacency.len()),
+        })
+        .collect();
+
+    centralities.sort_by(|a, b| {
+        b.betweenness
+            .total_cmp(&a.betweenness)
+            .then(b.degree.cmp(&a.degree))
+            .then(a.node_num.cmp(&b.node_num))
+    });
+
+    centralities
+}
+
+/// Links are costed at least this much, so free links can't tie a path with
+/// endlessly many others
+const MIN_LINK_COST: f64 = 1e-6;
+
+/// Path costs closer than this are treated as equal
+const PATH_COST_TOLERANCE: f64 = 1e-9;
+
+/// Betweenness centrality of every node, by node num, along the cheapest paths
+/// between the other nodes rather than the ones with the fewest hops. Links are
+/// costed as `MeshGraph::cheapest_path` costs them, ignoring direction.
+pub fn weighted_betweenness(
+    graph: &MeshGraph,
+    edge_cost: impl Fn(&GraphEdge) -> f64,
+) -> HashMap<u32, f64> {
+    let neighbors = graph.link_costs(edge_cost);
+    let mut betweenness: HashMap<u32, f64> = graph.nodes_lookup.keys().map(|n| (*n, 0.0)).collect();
+
+    // Brandes' algorithm, with Dijkstra's algorithm in place of the breadth-first
+    // search from each source
+    for source in graph.nodes_lookup.keys() {
+        let mut order = vec![];
+        let mut settled = HashSet::new();
+        let mut predecessors: HashMap<u32, Vec<u32>> = HashMap::new();
+        let mut path_counts: HashMap<u32, f64> = HashMap::from([(*source, 1.0)]);
+        let mut costs: HashMap<u32, f64> = HashMap::from([(*source, 0.0)]);
+        let mut queue = BinaryHeap::from([Reverse((PathCost(0.0), *source))]);
+
+        while let Some(Reverse((PathCost(cost), node_num))) = queue.pop() {
+            if !settled.insert(node_num) {
+                continue;
+            }
+
+            order.push(node_num);
+            let path_count = path_counts[&node_num];
+
+            for &(neighbor, link_cost) in neighbors.get(&node_num).into_iter().flatten() {
+                let next_cost = cost + link_cost.max(MIN_LINK_COST);
+
+                match costs.get(&neighbor) {
+                    Some(best_cost) if next_cost > best_cost + PATH_COST_TOLERANCE => {}
+                    Some(best_cost) if next_cost >= best_cost - PATH_COST_TOLERANCE => {
+                        *path_counts.entry(neighbor).or_default() += path_count;
+                        predecessors.entry(neighbor).or_default().push(node_num);
+                    }
+                    _ => {
+                        costs.insert(neighbor, next_cost);
+                        path_counts.insert(neighbor, path_count);
+                        predecessors.insert(neighbor, vec![node_num]);
+                        queue.push(Reverse((PathCost(next_cost), neighbor)));
+                    }
+                }
+            }
+        }
+
+        accumulate_dependencies(
+            *source,
+            &order,
+            &predecessors,
+            &path_counts,
+            &mut betweenness,
+        );
+    }
+
+    let node_count = betweenness.len();
+
+    betweenness
+        .into_iter()
+        .map(|(node_num, betweenness)| (node_num, normalized_betweenness(betweenness, node_count)))
+        .collect()
+}
+
+/// Nodes and links whose loss would split the network
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct CutPoints {
+    pub articulation_points: Vec<u32>,
+    pub bridges: Vec<(u32, u32)>, // lower node num first
+}
+
+struct CutPointSearch<'a> {
+    adjacency: &'a Adjacency,
+    discovered: HashMap<u32, u32>,
+    low: HashMap<u32, u32>,
+    cut_points: CutPoints,
+}
+
+impl CutPointSearch<'_> {
+    fn visit(&mut self, node_num: u32, parent: Option<u32>) {
+        let order = self.discovered.len() as u32;
+        self.discovered.insert(node_num, order);
+        self.low.insert(node_num, order);
+
+        let adjacency = self.adjacency;
+        let mut children = 0;
+        let mut is_articulation_point = false;
+
+        for neighbor in &adjacency[&node_num] {
+            if Some(*neighbor) == parent {
+                continue;
+            }
+
+            match self.discovered.get(neighbor).copied() {
+                Some(neighbor_order) => {
+                    let low = self.low[&node_num].min(neighbor_order);
+                    self.low.insert(node_num, low);
+                }
+                None => {
+                    children += 1;
+                    self.visit(*neighbor, Some(node_num));
+
+                    let neighbor_low = self.low[neighbor];
+                    let low = self.low[&node_num].min(neighbor_low);
+                    self.low.insert(node_num, low);
+
+                    if parent.is_some() && neighbor_low >= order {
+                        is_articulation_point = true;
+                    }
+
+                    if neighbor_low > order {
+                        let bridge = (node_num.min(*neighbor), node_num.max(*neighbor));
+                        self.cut_points.bridges.push(bridge);
+                    }
+                }
+            }
+        }
+
+        if is_articulation_point || (parent.is_none() && children > 1) {
+            self.cut_points.articulation_points.push(node_num);
+        }
+    }
+}
+
+/// Articulation points and bridges of the network, ignoring link direction
+pub fn cut_points(graph: &MeshGraph) -> CutPoints {
+    let adjacency = undirected_adjacency(graph);
+
+    let mut search = CutPointSearch {
+        adjacency: &adjacency,
+        discovered: HashMap::new(),
+        low: HashMap::new(),
+        cut_points: CutPoints::default(),
+    };
+
+    for node_num in adjacency.keys() {
+        if !search.discovered.contains_key(node_num) {
+            search.visit(*node_num, None);
+        }
+    }
+
+    let mut cut_points = search.cut_points;
+    cut_points.articulation_points.sort_unstable();
+    cut_points.bridges.sort_unstable();
+    cut_points
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct CriticalNode {
+    pub node_num: u32,
+    pub components_without: u32, // connected components left if the node is lost
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ResilienceSummary {
+    pub connected_components: u32,
+    pub largest_component_share: f64, // of all nodes, from 0 to 1
+    pub articulation_point_count: u32,
+    pub bridge_count: u32,
+
+    /// Node whose loss would split the network into the most components, if the
+    /// loss of any node would split it
+    pub most_critical_node: Option<CriticalNode>,
+}
+
+pub fn resilience_summary(graph: &MeshGraph, cut_points: &CutPoints) -> ResilienceSummary {
+    let adjacency = undirected_adjacency(graph);
+    let sizes = component_sizes(&adjacency, None);
+
+    let most_critical_node = cut_points
+        .articulation_points
+        .iter()
+        .map(|node_num| CriticalNode {
+            node_num: *node_num,
+            components_without: component_sizes(&adjacency, Some(*node_num)).len() as u32,
+        })
+        .max_by(|a, b| {
+            a.components_without
+                .cmp(&b.components_without)
+                .then(b.node_num.cmp(&a.node_num))
+        });
+
+    ResilienceSummary {
+        connected_components: sizes.len() as u32,
+        largest_component_share: match adjacency.len() {
+            0 => 0.0,
+            node_count => sizes.iter().max().copied().unwrap_or(0) as f64 / node_count as f64,
+        },
+        articulation_point_count: cut_points.articulation_points.len() as u32,
+        bridge_count: cut_points.bridges.len() as u32,
+        most_critical_node,
+    }
+}
+
+/// Flow values closer to zero than this are treated as zero
+const FLOW_TOLERANCE: f64 = 1e-9;
+
+/// Most flow that can pass between two nodes, and the links that limit it
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MaxFlow {
+    pub flow: f64,
+    pub min_cut: Vec<(u32, u32)>, // source side first, the links whose loss cuts the flow to zero
+}
+
+/// Breadth-first search from `source` along links with capacity left, returning the
+/// node each reached node was reached from
+fn residual_search(
+    links: &HashMap<u32, Vec<(u32, f64)>>,
+    residual: &HashMap<(u32, u32), f64>,
+    source: u32,
+) -> HashMap<u32, u32> {
+    let mut previous = HashMap::from([(source, source)]);
+    let mut queue = VecDeque::from([source]);
+
+    while let Some(node_num) = queue.pop_front() {
+        for (neighbor, _) in links.get(&node_num).into_iter().flatten() {
+            if residual[&(node_num, *neighbor)] > FLOW_TOLERANCE && !previous.contains_key(neighbor)
+            {
+                previous.insert(*neighbor, node_num);
+                queue.push_back(*neighbor);
+            }
+        }
+    }
+
+    previous
+}
+
+/// Maximum flow from `source` to `sink` and a minimum cut separating them, using the
+/// Edmonds-Karp algorithm. Links carry `capacity` in either direction, and a link
+/// reported in both directions is only as good as its weaker edge.
+pub fn max_flow(
+    graph: &MeshGraph,
+    source: u32,
+    sink: u32,
+    capacity: impl Fn(&GraphEdge) -> f64,
+) -> MaxFlow {
+    if source == sink {
+        return MaxFlow::default();
+    }
+
+    let links = graph.link_costs(capacity);
+
+    // Capacity left on each link in each direction
+    let mut residual: HashMap<(u32, u32), f64> = links
+        .iter()
+        .flat_map(|(node_num, neighbors)| {
+            neighbors
+                .iter()
+                .map(move |(neighbor, capacity)| ((*node_num, *neighbor), capacity.max(0.0)))
+        })
+        .collect();
+
+    let mut flow = 0.0;
+
+    loop {
+        let previous = residual_search(&links, &residual, source);
+
+        if !previous.contains_key(&sink) {
+            // The nodes still reachable from the source are its side of the cut
+            let mut min_cut: Vec<(u32, u32)> = links
+                .iter()
+                .filter(|(node_num, _)| previous.contains_key(*node_num))
+                .flat_map(|(node_num, neighbors)| {
+                    neighbors
+                        .iter()
+                        .filter(|(neighbor, _)| !previous.contains_key(neighbor))
+                        .map(move |(neighbor, _)| (*node_num, *neighbor))
+                })
+                .collect();
+
+            min_cut.sort();
+
+            return MaxFlow { flow, min_cut };
+        }
+
+        let mut path = vec![];
+        let mut node_num = sink;
+
+        while node_num != source {
+            path.push((previous[&node_num], node_num));
+            node_num = previous[&node_num];
+        }
+
+        let bottleneck = path
+            .iter()
+            .map(|arc| residual[arc])
+            .fold(f64::INFINITY, f64::min);
+
+        for (from, to) in path {
+            *residual.entry((from, to)).or_default() -= bottleneck;
+            *residual.entry((to, from)).or_default() += bottleneck;
+        }
+
+        flow += bottleneck;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::ds::edge::GraphEdge;
+    use crate::graph::ds::fixtures::graph_node;
+
+    /// A triangle 1-2-3 and a triangle 5-6-7 joined through node 4, with 3 - 4 and
+    /// 4 - 5 reported in both directions, and node 8 on its own
+    fn bowtie() -> MeshGraph {
+        let mut graph = MeshGraph::new();
+
+        for node_num in 1..=8 {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        for (from, to) in [(1, 2), (2, 3), (3, 1), (3, 4), (4, 3), (4, 5), (5, 4)] {
+            graph.upsert_edge(
+                graph_node(from),
+                graph_node(to),
+                GraphEdge::manual(from, to, 5.0),
+            );
+        }
+
+        for (from, to) in [(5, 6), (6, 7), (7, 5)] {
+            graph.upsert_edge(
+                graph_node(from),
+                graph_node(to),
+                GraphEdge::manual(from, to, 5.0),
+            );
+        }
+
+        graph
+    }
+
+    #[test]
+    fn finds_cut_points_and_resilience() {
+        let graph = bowtie();
+
+        let stats = graph_stats(&graph);
+        assert_eq!(stats.node_count, 8);
+        assert_eq!(stats.edge_count, 10);
+        assert_eq!(stats.link_count, 8);
+        assert_eq!(stats.connected_components, 2);
+        assert_eq!(stats.largest_component_size, 7);
+        assert!((stats.average_degree - 2.0).abs() < 1e-9);
+        assert_eq!(stats.diameter, 4);
+
+        // Both triangles are closed, out of the 11 pairs of links meeting at a node
+        assert!((stats.global_clustering - 6.0 / 11.0).abs() < 1e-9);
+
+        let cut_points = cut_points(&graph);
+        assert_eq!(cut_points.articulation_points, [3, 4, 5]);
+        assert_eq!(cut_points.bridges, [(3, 4), (4, 5)]);
+
+        let resilience = resilience_summary(&graph, &cut_points);
+        assert_eq!(resilience.connected_components, 2);
+        assert!((resilience.largest_component_share - 7.0 / 8.0).abs() < 1e-9);
+        assert_eq!(resilience.bridge_count, 2);
+        assert_eq!(
+            resilience.most_critical_node,
+            Some(CriticalNode {
+                node_num: 3,
+                components_without: 3,
+            })
+        );
+    }
+
+    fn linked_graph(links: &[(u32, u32, f64)]) -> MeshGraph {
+        let mut graph = MeshGraph::new();
+
+        for (from, to, _) in links {
+            graph.upsert_node(graph_node(*from));
+            graph.upsert_node(graph_node(*to));
+        }
+
+        for (from, to, weight) in links {
+            graph.upsert_edge(
+                graph_node(*from),
+                graph_node(*to),
+                GraphEdge::manual(*from, *to, *weight),
+            );
+        }
+
+        graph
+    }
+
+    #[test]
+    fn weighted_betweenness_centers_on_the_hub_of_a_star() {
+        // Free links don't make paths through the hub any less central
+        let graph = linked_graph(&[(1, 2, 0.0), (1, 3, 0.0), (4, 1, 0.0), (1, 5, 0.0)]);
+
+        let betweenness = weighted_betweenness(&graph, |edge| edge.snr());
+        assert_eq!(betweenness.len(), 5);
+        assert!((betweenness[&1] - 1.0).abs() < 1e-9);
+
+        for leaf in 2..=5 {
+            assert_eq!(betweenness[&leaf], 0.0);
+        }
+    }
+
+    #[test]
+    fn weighted_betweenness_follows_the_cheaper_path() {
+        // The path 1-2-3-4 costs 3, so the direct link from 1 to 4 is only used
+        // between its own ends. The cheaper of the two edges between 2 and 3 is used.
+        let graph = linked_graph(&[
+            (1, 2, 1.0),
+            (2, 3, 1.0),
+            (3, 2, 7.0),
+            (3, 4, 1.0),
+            (1, 4, 10.0),
+        ]);
+
+        let betweenness = weighted_betweenness(&graph, |edge| edge.snr());
+
+        // Node 2 is on the paths from 1 to 3 and from 1 to 4, out of the 3 pairs
+        // of other nodes
+        assert!((betweenness[&2] - 2.0 / 3.0).abs() < 1e-9);
+        assert!((betweenness[&3] - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(betweenness[&1], 0.0);
+        assert_eq!(betweenness[&4], 0.0);
+
+        // Once the direct link is cheaper, the path wraps around the other way
+        let graph = linked_graph(&[(1, 2, 1.0), (2, 3, 1.0), (3, 4, 1.0), (1, 4, 1.0)]);
+        let betweenness = weighted_betweenness(&graph, |edge| edge.snr());
+
+        for node_num in 1..=4 {
+            assert!((betweenness[&node_num] - 1.0 / 6.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn max_flow_is_limited_by_the_bottleneck_of_each_path() {
+        // Two paths from 1 to 6, through 2 and 3 or through 4 and 5, each with one
+        // weak link. Node 7 is on its own.
+        let mut graph = linked_graph(&[
+            (1, 2, 5.0),
+            (2, 3, 1.0),
+            (3, 6, 5.0),
+            (1, 4, 5.0),
+            (5, 4, 2.0),
+            (5, 6, 5.0),
+        ]);
+        graph.upsert_node(graph_node(7));
+
+        let capacity = |edge: &GraphEdge| edge.snr();
+
+        assert_eq!(
+            max_flow(&graph, 1, 6, capacity),
+            MaxFlow {
+                flow: 3.0,
+                min_cut: vec![(2, 3), (4, 5)],
+            }
+        );
+
+        // Flow runs both ways along the links
+        assert_eq!(max_flow(&graph, 6, 1, capacity).flow, 3.0);
+
+        assert_eq!(max_flow(&graph, 1, 7, capacity), MaxFlow::default());
+        assert_eq!(max_flow(&graph, 1, 1, capacity), MaxFlow::default());
+    }
+
+    #[test]
+    fn max_flow_counts_link_disjoint_paths() {
+        let graph = bowtie();
+
+        assert_eq!(
+            max_flow(&graph, 1, 7, |_| 1.0),
+            MaxFlow {
+                flow: 1.0,
+                min_cut: vec![(3, 4)],
+            }
+        );
+
+        // Two ways around the triangle
+        assert_eq!(max_flow(&graph, 1, 3, |_| 1.0).flow, 2.0);
+    }
+
+    #[test]
+    fn small_graphs_have_no_articulation_points() {
+        let mut graph = MeshGraph::new();
+        assert_eq!(cut_points(&graph), CutPoints::default());
+
+        graph.upsert_node(graph_node(1));
+        assert_eq!(cut_points(&graph), CutPoints::default());
+
+        // A link reported in both directions is still a single bridge
+        for (from, to) in [(1, 2), (2, 1)] {
+            graph.upsert_edge(
+                graph_node(from),
+                graph_node(to),
+                GraphEdge::manual(from, to, 5.0),
+            );
+        }
+
+        let cut_points = cut_points(&graph);
+        assert!(cut_points.articulation_points.is_empty());
+        assert_eq!(cut_points.bridges, [(1, 2)]);
+    }
+
+    #[test]
+    fn betweenness_peaks_at_the_bridge_node() {
+        let centralities = node_centralities(&bowtie());
+
+        assert_eq!(centralities.len(), 8);
+        assert_eq!(centralities[0].node_num, 4);
+        assert_eq!(centralities[0].degree, 2);
+
+        // 9 of the 15 pairs among the other 6 linked nodes go through node 4, out of
+        // the 21 pairs of all 7 other nodes
+        assert!((centralities[0].betweenness - 9.0 / 21.0).abs() < 1e-9);
+
+        let leaf = centralities.iter().find(|c| c.node_num == 1).unwrap();
+        assert_eq!(leaf.betweenness, 0.0);
+
+        let isolated = centralities.last().unwrap();
+        assert_eq!(isolated.node_num, 8);
+        assert_eq!(isolated.degree, 0);
+    }
+}
@@ -0,0 +1,230 @@
+//! Scheduled analytics updates. While a radio is connected, the sections selected in
+//! the settings are recomputed on their interval and pushed to the UI, so analytics
+//! views stay current without being asked for.
+
+use std::time::{Duration, Instant};
+
+use log::{debug, trace, warn};
+use tauri::Manager;
+
+use crate::device::helpers::get_current_time_u32;
+use crate::export::analytics_report::{
+    build_analytics_report, resolve_report_sections, AnalyticsReportMetadata, AnalyticsSection,
+    ReportDevice, ANALYTICS_REPORT_TIMEOUT, ANALYTICS_REPORT_VERSION,
+};
+use crate::ipc::{events, AnalyticsUpdateEvent, EVENT_API_VERSION};
+use crate::shutdown::shutdown_signal;
+use crate::state;
+use crate::state::mesh_devices::all_devices;
+
+/// How often the refresh interval is checked, so a changed interval applies quickly
+pub const ANALYTICS_REFRESH_TICK: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RefreshCheck {
+    NotDue,
+    Unchanged, // due, but the graph is the one the last update was computed from
+    Due,
+}
+
+/// Tracks when analytics were last due and which graph revision they were last
+/// computed from. Takes the interval on each check, so a changed setting applies
+/// without restarting the timer.
+#[derive(Default)]
+pub struct AnalyticsRefreshSchedule {
+    last_due: Option<Instant>,
+    computed_revision: Option<u64>,
+}
+
+impl AnalyticsRefreshSchedule {
+    /// Checks whether an update of the graph at `revision` is due, restarting the
+    /// interval if it is. The first check is always due.
+    pub fn check(&mut self, interval: Duration, revision: u64, now: Instant) -> RefreshCheck {
+        if let Some(last_due) = self.last_due {
+            if now.duration_since(last_due) < interval {
+                return RefreshCheck::NotDue;
+            }
+        }
+
+        self.last_due = Some(now);
+
+        if self.computed_revision == Some(revision) {
+            RefreshCheck::Unchanged
+        } else {
+            RefreshCheck::Due
+        }
+    }
+
+    pub fn mark_computed(&mut self, revision: u64) {
+        self.computed_revision = Some(revision);
+    }
+}
+
+/// Computes `sections` of the graph as seen by the first connected radio, then stores
+/// and emits them. Returns whether a radio was connected.
+async fn refresh_analytics(
+    handle: &tauri::AppHandle,
+    sections: Vec<AnalyticsSection>,
+) -> Result<bool, String> {
+    let sections = resolve_report_sections(Some(sections))?;
+
+    let mesh_devices = handle.state::<state::mesh_devices::MeshDevicesState>();
+    let (device_key, device) = match all_devices(&mesh_devices.inner)
+        .await
+        .into_iter()
+        .min_by(|(a, _), (b, _)| a.cmp(b))
+    {
+        Some(device) => device,
+        None => return Ok(false),
+    };
+
+    let graph_version = handle
+        .state::<state::edge_deltas::EdgeDeltasState>()
+        .inner
+        .lock()
+        .map_err(|e| e.to_string())?
+        .get(&device_key)
+        .map(|tracker| tracker.sequence());
+
+    let (graph, metadata) = {
+        let packet_api = device.lock().await;
+
+        let graph = handle
+            .state::<state::graph::GraphState>()
+            .inner
+            .read()?
+            .without_hidden_nodes();
+
+        let metadata = AnalyticsReportMetadata {
+            report_version: ANALYTICS_REPORT_VERSION,
+            generated_at: get_current_time_u32(),
+            graph_version,
+            device: ReportDevice::new(device_key.clone(), &packet_api.device),
+            sections,
+        };
+
+        (graph, metadata)
+    };
+
+    let report = tokio::time::timeout(
+        ANALYTICS_REPORT_TIMEOUT,
+        tauri::async_runtime::spawn_blocking(move || build_analytics_report(&graph, metadata)),
+    )
+    .await
+    .map_err(|_| "Timed out computing scheduled analytics")?
+    .map_err(|e| e.to_string())??;
+
+    {
+        let analytics_state = handle.state::<state::analytics::AnalyticsState>();
+        let mut latest = analytics_state.latest.lock().map_err(|e| e.to_string())?;
+        *latest = Some(report.clone());
+    }
+
+    events::dispatch_analytics_update(
+        handle,
+        AnalyticsUpdateEvent {
+            api_version: EVENT_API_VERSION,
+            device_key,
+            report,
+        },
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(true)
+}
+
+/// Periodically recomputes the analytics selected in the settings, skipping updates
+/// while the graph is unchanged or no radio is connected
+pub fn spawn_analytics_refresh_timer(handle: tauri::AppHandle) {
+    trace!("Spawning analytics refresh timer");
+
+    tauri::async_runtime::spawn(async move {
+        let shutdown = shutdown_signal(&handle);
+        let mut interval = tokio::time::interval(ANALYTICS_REFRESH_TICK);
+        let mut schedule = AnalyticsRefreshSchedule::default();
+
+        while shutdown.tick(&mut interval).await {
+            let (refresh_interval, sections) = {
+                let settings_state = handle.state::<state::settings::SettingsState>();
+                let settings = match settings_state.inner.lock() {
+                    Ok(settings) => settings,
+                    Err(e) => {
+                        warn!("Failed to lock settings: {}", e);
+                        continue;
+                    }
+                };
+
+                match settings.analytics.refresh_interval() {
+                    Some(refresh_interval) => (
+                        refresh_interval,
+                        settings.analytics.refresh_sections.clone(),
+                    ),
+                    None => continue,
+                }
+            };
+
+            let revision = handle.state::<state::graph::GraphState>().inner.revision();
+
+            match schedule.check(refresh_interval, revision, Instant::now()) {
+                RefreshCheck::NotDue => continue,
+                RefreshCheck::Unchanged => {
+                    debug!("Skipped analytics update, the graph hasn't changed");
+                    continue;
+                }
+                RefreshCheck::Due => {}
+            }
+
+            match refresh_analytics(&handle, sections).await {
+                Ok(true) => schedule.mark_computed(revision),
+                Ok(false) => trace!("Skipped analytics update with no radio connected"),
+                Err(e) => warn!("Failed to update analytics: {}", e),
+            }
+        }
+
+        trace!("Stopped analytics refresh timer");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn skips_updates_of_an_unchanged_graph() {
+        let start = Instant::now();
+        let mut schedule = AnalyticsRefreshSchedule::default();
+
+        assert_eq!(schedule.check(INTERVAL, 1, start), RefreshCheck::Due);
+        schedule.mark_computed(1);
+
+        let later = start + INTERVAL / 2;
+        assert_eq!(schedule.check(INTERVAL, 2, later), RefreshCheck::NotDue);
+
+        let later = start + INTERVAL;
+        assert_eq!(schedule.check(INTERVAL, 1, later), RefreshCheck::Unchanged);
+
+        // Skipping restarts the interval
+        assert_eq!(
+            schedule.check(INTERVAL, 2, later + INTERVAL / 2),
+            RefreshCheck::NotDue
+        );
+        assert_eq!(
+            schedule.check(INTERVAL, 2, later + INTERVAL),
+            RefreshCheck::Due
+        );
+    }
+
+    #[test]
+    fn failed_updates_are_retried() {
+        let start = Instant::now();
+        let mut schedule = AnalyticsRefreshSchedule::default();
+
+        assert_eq!(schedule.check(INTERVAL, 1, start), RefreshCheck::Due);
+        assert_eq!(
+            schedule.check(INTERVAL, 1, start + INTERVAL),
+            RefreshCheck::Due
+        );
+    }
+}
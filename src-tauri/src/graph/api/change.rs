@@ -0,0 +1,492 @@
+use crate::graph::ds::{edge::GraphEdge, graph::MeshGraph, node::GraphNode};
+
+/// A single change a packet made to a device's graph. Packets are applied as the
+/// changes they carry, the graph is only rebuilt as a whole when it's loaded on
+/// connect, reconciled with the radio's node database or restored.
+#[derive(Clone, Debug)]
+pub enum GraphChange {
+    /// The node was heard, keeping its edges
+    NodeUpserted(GraphNode),
+
+    PositionChanged {
+        node_num: u32,
+        latitude: f64,
+        longitude: f64,
+    },
+
+    /// An edge from `source` to `target` was reported, replacing the previous one
+    EdgeObserved {
+        source: u32,
+        target: u32,
+        edge: GraphEdge,
+    },
+
+    /// A packet-derived edge its source no longer reports
+    EdgeRemoved {
+        source: u32,
+        target: u32,
+    },
+
+    NodeRemoved(u32),
+}
+
+impl MeshGraph {
+    /// Applies a single change, touching only the nodes and edges it names. Returns
+    /// whether the graph changed.
+    pub fn apply_change(&mut self, change: &GraphChange) -> bool {
+        match change {
+            GraphChange::NodeUpserted(node) => {
+                self.refresh_node(*node);
+                true
+            }
+            GraphChange::PositionChanged {
+                node_num,
+                latitude,
+                longitude,
+            } => {
+                if (*latitude == 0.0 && *longitude == 0.0)
+                    || self.spatial_index.position(*node_num) == Some((*latitude, *longitude))
+                {
+                    return false;
+                }
+
+                self.set_node_position(*node_num, *latitude, *longitude);
+                true
+            }
+            GraphChange::EdgeObserved {
+                source,
+                target,
+                edge,
+            } => {
+                let (source, target) = match (self.get_node(*source), self.get_node(*target)) {
+                    (Some(s), Some(t)) => (s, t),
+                    _ => return false,
+                };
+
+                // Manual edges take precedence over the ones packets report
+                self.upsert_edge(source, target, edge.clone());
+
+                self.get_edge(source, target)
+                    .map_or(false, |current| current.source == edge.source)
+            }
+            GraphChange::EdgeRemoved { source, target } => {
+                match (self.get_node(*source), self.get_node(*target)) {
+                    (Some(s), Some(t)) => self.remove_edge(s, t).is_some(),
+                    _ => false,
+                }
+            }
+            GraphChange::NodeRemoved(node_num) => self.remove_node(*node_num).is_some(),
+        }
+    }
+
+    /// Applies the changes in order, returning the ones that changed the graph
+    pub fn apply_changes(&mut self, changes: Vec<GraphChange>) -> Vec<GraphChange> {
+        changes
+            .into_iter()
+            .filter(|change| self.apply_change(change))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs::{self, MeshPacket, Neighbor, NeighborInfo};
+
+    use super::*;
+    use crate::graph::ds::edge::EdgeSource;
+
+    type Structure = (Vec<u32>, Vec<(u32, u32, i64)>, Vec<(u32, i64, i64)>);
+
+    fn node_info(node_num: u32, latitude_i: i32) -> protobufs::NodeInfo {
+        protobufs::NodeInfo {
+            num: node_num,
+            position: Some(protobufs::Position {
+                latitude_i,
+                longitude_i: 134_000_000,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn packet(from: u32) -> MeshPacket {
+        MeshPacket {
+            from,
+            rx_snr: 6.0,
+            rx_rssi: -90,
+            ..Default::default()
+        }
+    }
+
+    fn neighbor_info(node_id: u32, neighbors: &[(u32, f32)]) -> NeighborInfo {
+        NeighborInfo {
+            node_id,
+            neighbors: neighbors
+                .iter()
+                .map(|(neighbor, snr)| Neighbor {
+                    node_id: *neighbor,
+                    snr: *snr,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn position(latitude_i: i32) -> protobufs::Position {
+        protobufs::Position {
+            latitude_i,
+            longitude_i: 134_000_000,
+            ..Default::default()
+        }
+    }
+
+    /// Nodes, edges and positions, leaving out when things were last heard
+    fn structure(graph: &MeshGraph) -> Structure {
+        let mut nodes: Vec<u32> = graph.nodes().map(|node| node.node_num).collect();
+        nodes.sort_unstable();
+
+        let mut edges: Vec<(u32, u32, i64)> = graph
+            .edges()
+            .map(|(source, target, edge)| {
+                (
+                    source.node_num,
+                    target.node_num,
+                    (edge.snr() * 100.0).round() as i64,
+                )
+            })
+            .collect();
+        edges.sort_unstable();
+
+        let positions = nodes
+            .iter()
+            .filter_map(|node_num| {
+                graph.spatial_index.position(*node_num).map(|(lat, lon)| {
+                    (
+                        *node_num,
+                        (lat * 1e6).round() as i64,
+                        (lon * 1e6).round() as i64,
+                    )
+                })
+            })
+            .collect();
+
+        (nodes, edges, positions)
+    }
+
+    #[test]
+    fn incremental_updates_match_a_rebuild() {
+        let mut incremental = MeshGraph::new();
+
+        for node_num in 1..=4 {
+            incremental.update_from_node_info(node_info(node_num, 525_000_000));
+        }
+
+        incremental.update_from_neighbor_info(
+            packet(1),
+            neighbor_info(1, &[(2, 4.0), (3, -2.0)]),
+            "LongFast".into(),
+            "/dev/ttyUSB0",
+        );
+        incremental.update_from_neighbor_info(
+            packet(2),
+            neighbor_info(2, &[(1, 5.0)]),
+            "LongFast".into(),
+            "/dev/ttyUSB0",
+        );
+
+        // Hearing a node again keeps the edges other nodes reported to it
+        incremental.update_from_position(packet(2), position(526_000_000));
+        assert_eq!(incremental.edge_count(), 3);
+
+        // Node 1 stops hearing node 3 and starts hearing node 4
+        incremental.update_from_neighbor_info(
+            packet(1),
+            neighbor_info(1, &[(2, 4.0), (4, 1.5)]),
+            "LongFast".into(),
+            "/dev/ttyUSB0",
+        );
+
+        // Repeating a position isn't a change
+        assert!(incremental
+            .update_from_position(packet(2), position(526_000_000))
+            .iter()
+            .all(|change| matches!(change, GraphChange::NodeUpserted(_))));
+
+        // A node info without a position changes nothing
+        assert!(incremental
+            .update_from_node_info(protobufs::NodeInfo {
+                num: 3,
+                ..Default::default()
+            })
+            .is_empty());
+
+        // A rebuild from the final state of each node
+        let mut rebuilt = MeshGraph::new();
+
+        for (node_num, latitude_i) in [
+            (1, 525_000_000),
+            (2, 526_000_000),
+            (3, 525_000_000),
+            (4, 525_000_000),
+        ] {
+            rebuilt.update_from_node_info(node_info(node_num, latitude_i));
+        }
+
+        rebuilt.update_from_neighbor_info(
+            packet(2),
+            neighbor_info(2, &[(1, 5.0)]),
+            "LongFast".into(),
+            "/dev/ttyUSB0",
+        );
+        rebuilt.update_from_neighbor_info(
+            packet(1),
+            neighbor_info(1, &[(2, 4.0), (4, 1.5)]),
+            "LongFast".into(),
+            "/dev/ttyUSB0",
+        );
+
+        assert_eq!(structure(&incremental), structure(&rebuilt));
+    }
+
+    #[test]
+    fn applies_only_the_named_nodes_and_edges() {
+        let mut graph = MeshGraph::new();
+
+        // A star around node 1, so node 1's changes touch every edge and a leaf's one
+        for node_num in 1..=200 {
+            graph.update_from_node_info(node_info(node_num, 525_000_000));
+        }
+
+        let neighbors: Vec<(u32, f32)> = (2..=200).map(|node_num| (node_num, 3.0)).collect();
+        graph.update_from_neighbor_info(
+            packet(1),
+            neighbor_info(1, &neighbors),
+            "LongFast".into(),
+            "/dev/ttyUSB0",
+        );
+
+        let changes = graph.update_from_position(packet(57), position(527_000_000));
+
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().all(|change| match change {
+            GraphChange::NodeUpserted(node) => node.node_num == 57,
+            GraphChange::PositionChanged { node_num, .. } => *node_num == 57,
+            _ => false,
+        }));
+        assert_eq!(graph.edge_count(), 199);
+
+        let removed = graph.apply_changes(vec![
+            GraphChange::NodeRemoved(57),
+            GraphChange::EdgeRemoved {
+                source: 1,
+                target: 57,
+            },
+        ]);
+
+        assert_eq!(removed.len(), 1);
+        assert_eq!(graph.edge_count(), 198);
+    }
+
+    #[test]
+    fn position_update_leaves_every_edge_untouched() {
+        let mut graph = MeshGraph::new();
+
+        for node_num in 1..=5 {
+            graph.update_from_node_info(node_info(node_num, 525_000_000));
+        }
+
+        // Edges heard a day ago, so anything that recreates them would stand out
+        let heard_at = chrono::Utc::now().naive_utc() - chrono::Duration::days(1);
+
+        for (source, target, snr) in [(1, 2, 4.0), (2, 1, 3.5), (3, 2, -1.25), (4, 5, 7.0)] {
+            let mut edge = GraphEdge::from_neighbor(
+                target,
+                0,
+                "LongFast".into(),
+                Neighbor {
+                    node_id: source,
+                    snr,
+                    ..Default::default()
+                },
+            );
+            edge.last_heard = heard_at;
+
+            assert!(graph.apply_change(&GraphChange::EdgeObserved {
+                source,
+                target,
+                edge,
+            }));
+        }
+
+        let edges = |graph: &MeshGraph| {
+            let mut edges: Vec<_> = graph
+                .edges()
+                .map(|(source, target, edge)| {
+                    (
+                        source.node_num,
+                        target.node_num,
+                        edge.snr().to_bits(),
+                        edge.last_heard,
+                        edge.source,
+                    )
+                })
+                .collect();
+            edges.sort_by_key(|(source, target, ..)| (*source, *target));
+            edges
+        };
+
+        let before = edges(&graph);
+
+        // Node 2 moving touches only node 2, not the three edges it has
+        let changes = graph.update_from_position(packet(2), position(527_000_000));
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!(edges(&graph), before);
+        assert!(before
+            .iter()
+            .all(|(.., last_heard, _)| *last_heard == heard_at));
+    }
+
+    #[test]
+    fn neighbor_info_links_reported_neighbors_by_snr() {
+        let mut graph = MeshGraph::new();
+        graph.update_from_node_info(node_info(1, 525_000_000));
+
+        // Nodes 2 and 3 haven't been heard by the app, only by node 1
+        graph.update_from_neighbor_info(
+            packet(1),
+            neighbor_info(1, &[(2, 12.0), (3, -4.0)]),
+            "LongFast".into(),
+            "/dev/ttyUSB0",
+        );
+
+        let (nodes, edges, positions) = structure(&graph);
+        assert_eq!(nodes, vec![1, 2, 3]);
+        assert_eq!(edges, vec![(1, 2, 1200), (1, 3, -400)]);
+        assert_eq!(positions.len(), 1);
+
+        // Weaker links cost more to route over under the default SNR-based strategy
+        let cost = |to| {
+            graph
+                .get_edge(graph.get_node(1).unwrap(), graph.get_node(to).unwrap())
+                .unwrap()
+                .weight()
+        };
+        assert_eq!(cost(2), 1.0);
+        assert!((cost(3) - 2.4).abs() < 1e-9);
+
+        let first_heard = graph
+            .get_edge(graph.get_node(1).unwrap(), graph.get_node(3).unwrap())
+            .unwrap()
+            .last_heard;
+
+        // Repeating the report refreshes the edge instead of adding another
+        graph.update_from_neighbor_info(
+            packet(1),
+            neighbor_info(1, &[(3, 2.0)]),
+            "LongFast".into(),
+            "/dev/ttyUSB0",
+        );
+
+        let (_, edges, _) = structure(&graph);
+        assert_eq!(edges, vec![(1, 3, 200)]);
+
+        let edge = graph
+            .get_edge(graph.get_node(1).unwrap(), graph.get_node(3).unwrap())
+            .unwrap();
+        assert!(edge.last_heard >= first_heard);
+        assert_eq!(edge.source, EdgeSource::NeighborInfo);
+    }
+
+    #[test]
+    fn traceroutes_link_their_hops_and_add_unknown_ones() {
+        let mut graph = MeshGraph::new();
+
+        for node_num in 1..=3 {
+            graph.update_from_node_info(node_info(node_num, 525_000_000));
+        }
+
+        graph.update_from_neighbor_info(
+            packet(1),
+            neighbor_info(1, &[(2, 4.0)]),
+            "LongFast".into(),
+            "/dev/ttyUSB0",
+        );
+
+        // Node 9 relayed the traceroute before the app heard of it
+        let changes =
+            graph.update_from_traceroute(&[1, 2, 9, 3, 3], 0, "LongFast".into(), "/dev/ttyUSB0");
+        assert_eq!(changes.len(), 8);
+
+        let (nodes, edges, _) = structure(&graph);
+        assert_eq!(nodes, vec![1, 2, 3, 9]);
+
+        // The edge the neighbor info reported keeps its SNR
+        assert_eq!(edges, vec![(1, 2, 400), (2, 9, 0), (9, 3, 0)]);
+
+        let edge = graph
+            .get_edge(graph.get_node(9).unwrap(), graph.get_node(3).unwrap())
+            .unwrap();
+        assert_eq!(edge.source, EdgeSource::Traceroute);
+    }
+
+    #[test]
+    fn devices_only_remove_the_edges_they_observed() {
+        const FIRST: &str = "/dev/ttyUSB0";
+        const SECOND: &str = "/dev/ttyUSB1";
+
+        let observers = |graph: &MeshGraph, to| graph.edge_observers(1, to);
+
+        let mut graph = MeshGraph::new();
+        graph.update_from_node_info(node_info(1, 525_000_000));
+
+        // Both radios hear node 1, one of them also before it met node 3
+        graph.update_from_neighbor_info(
+            packet(1),
+            neighbor_info(1, &[(2, 4.0), (3, 2.0)]),
+            "LongFast".into(),
+            FIRST,
+        );
+        graph.update_from_neighbor_info(
+            packet(1),
+            neighbor_info(1, &[(2, 4.0)]),
+            "Private".into(),
+            SECOND,
+        );
+
+        assert_eq!(observers(&graph, 2), vec![FIRST, SECOND]);
+        assert_eq!(observers(&graph, 3), vec![FIRST]);
+
+        // The second radio no longer hearing the link leaves the first one's edge
+        let changes = graph.update_from_neighbor_info(
+            packet(1),
+            neighbor_info(1, &[(3, 2.0)]),
+            "Private".into(),
+            SECOND,
+        );
+
+        assert!(changes
+            .iter()
+            .all(|change| !matches!(change, GraphChange::EdgeRemoved { .. })));
+        assert_eq!(observers(&graph, 2), vec![FIRST]);
+        assert_eq!(observers(&graph, 3), vec![FIRST, SECOND]);
+
+        let (_, edges, _) = structure(&graph.filter_by_observer(SECOND));
+        assert_eq!(edges, vec![(1, 3, 200)]);
+
+        // Only once no radio observes it is the edge removed
+        graph.update_from_neighbor_info(
+            packet(1),
+            neighbor_info(1, &[(3, 2.0)]),
+            "LongFast".into(),
+            FIRST,
+        );
+
+        let (_, edges, _) = structure(&graph);
+        assert_eq!(edges, vec![(1, 3, 200)]);
+
+        let (_, edges, _) = structure(&graph.filter_by_observer(FIRST));
+        assert_eq!(edges, vec![(1, 3, 200)]);
+    }
+}
@@ -1 +1,2 @@
+pub mod change;
 pub mod update_from_packet;
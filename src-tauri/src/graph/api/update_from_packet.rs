@@ -1,24 +1,39 @@
+use std::collections::HashSet;
 use std::time::Duration;
 
 use meshtastic::protobufs::{self, MeshPacket};
 
-use crate::graph::ds::{edge::GraphEdge, graph::MeshGraph, node::GraphNode};
+use crate::device::helpers::{get_current_time_u32, normalize_location_field};
+use crate::graph::ds::{
+    edge::{EdgeSource, GraphEdge},
+    graph::MeshGraph,
+    link_quality::LinkQualitySample,
+    node::GraphNode,
+};
+
+use super::change::GraphChange;
 
 pub const DEFAULT_NODE_TIMEOUT_DURATION: Duration = Duration::from_secs(15 * 60);
 
 impl MeshGraph {
+    /// Applies a neighbor info packet `observer` received: its sender was heard, and its
+    /// reported neighbors replace the edges it reported before. Neighbors that aren't in
+    /// the graph yet are added without a position, and repeated reports refresh their edge.
+    ///
+    /// Edges are attributed to the devices that observed them, so an edge other
+    /// connected devices still observe is kept when `observer` no longer does.
     pub fn update_from_neighbor_info(
         &mut self,
         packet: MeshPacket,
         neighbor_info: protobufs::NeighborInfo,
-    ) {
+        channel_name: String,
+        observer: &str,
+    ) -> Vec<GraphChange> {
         log::info!(
             "Updating graph from neighbor info packet from node {}",
             packet.from
         );
 
-        // Update own node
-
         let own_node = match self.get_node(packet.from) {
             Some(node) => GraphNode {
                 last_heard: chrono::Utc::now().naive_utc(),
@@ -27,74 +42,259 @@ impl MeshGraph {
             None => neighbor_info.clone().into(),
         };
 
-        self.upsert_node(own_node.clone());
+        let channel_name = self.names.intern(&channel_name);
+        let mut changes = vec![GraphChange::NodeUpserted(own_node)];
 
-        // Update neighbor nodes, don't insert as this isn't how neighbor info works
-        for neighbor in neighbor_info.neighbors {
-            log::info!("Adding neighbor node {} to graph", neighbor.node_id);
+        let reported: HashSet<u32> = neighbor_info
+            .neighbors
+            .iter()
+            .map(|neighbor| neighbor.node_id)
+            .collect();
+
+        for (target, edge) in self.outgoing_edges(own_node.node_num) {
+            if edge.source == EdgeSource::Manual || reported.contains(&target) {
+                continue;
+            }
 
-            let remote_node = match self.get_node(neighbor.node_id) {
-                Some(g) => g,
-                None => {
-                    continue;
+            let mut remaining = edge.clone();
+            remaining.observed_by.remove(observer);
+
+            let change = if remaining.observed_by.is_empty() {
+                GraphChange::EdgeRemoved {
+                    source: own_node.node_num,
+                    target,
+                }
+            } else if remaining.observed_by.len() < edge.observed_by.len() {
+                GraphChange::EdgeObserved {
+                    source: own_node.node_num,
+                    target,
+                    edge: remaining,
                 }
+            } else {
+                continue; // only reported through other devices
             };
 
-            self.upsert_edge(
-                own_node.clone(),
-                remote_node,
-                GraphEdge::from_neighbor(own_node.node_num, neighbor),
+            changes.push(change);
+        }
+
+        for neighbor in neighbor_info.neighbors {
+            if !self.contains_node(neighbor.node_id) {
+                log::info!("Adding neighbor node {} to graph", neighbor.node_id);
+                changes.push(GraphChange::NodeUpserted(neighbor.clone().into()));
+            }
+
+            let weight = self.record_link_sample(
+                neighbor.node_id,
+                own_node.node_num,
+                LinkQualitySample {
+                    timestamp: get_current_time_u32(),
+                    snr: neighbor.snr,
+                    rssi: None,
+                    observer: observer.into(),
+                },
             );
+
+            let target = neighbor.node_id;
+
+            let mut edge = GraphEdge::from_neighbor(
+                own_node.node_num,
+                packet.channel,
+                channel_name.clone(),
+                neighbor,
+            )
+            .with_observers(self.edge_observers(own_node.node_num, target))
+            .with_observers([observer.into()]);
+
+            if let Some(weight) = weight {
+                edge = edge.with_snr(weight.into());
+            }
+
+            changes.push(GraphChange::EdgeObserved {
+                source: own_node.node_num,
+                target,
+                edge,
+            });
+        }
+
+        self.apply_changes(changes)
+    }
+
+    /// Applies the route a traceroute sent by `observer` discovered, from its first hop
+    /// to its last. Each hop was just heard, hops that aren't in the graph are added,
+    /// and consecutive hops are linked in the direction the request travelled.
+    pub fn update_from_traceroute(
+        &mut self,
+        route: &[u32],
+        channel: u32,
+        channel_name: String,
+        observer: &str,
+    ) -> Vec<GraphChange> {
+        log::info!("Updating graph from traceroute {:?}", route);
+
+        let now = chrono::Utc::now().naive_utc();
+        let channel_name = self.names.intern(&channel_name);
+
+        let mut changes: Vec<GraphChange> = route
+            .iter()
+            .map(|node_num| {
+                let node = match self.get_node(*node_num) {
+                    Some(node) => GraphNode {
+                        last_heard: now,
+                        ..node
+                    },
+                    None => GraphNode {
+                        node_num: *node_num,
+                        last_heard: now,
+                        timeout_duration: DEFAULT_NODE_TIMEOUT_DURATION,
+                    },
+                };
+
+                GraphChange::NodeUpserted(node)
+            })
+            .collect();
+
+        for hop in route.windows(2) {
+            let (source, target) = (hop[0], hop[1]);
+
+            // A node repeated in a route, e.g. by a misbehaving relay, isn't a link
+            if source == target {
+                continue;
+            }
+
+            let mut edge =
+                GraphEdge::from_traceroute(source, target, channel, channel_name.clone())
+                    .with_observers([observer.into()]);
+
+            if let (Some(from), Some(to)) = (self.get_node(source), self.get_node(target)) {
+                if let Some(existing) = self.get_edge(from, to) {
+                    edge = edge
+                        .with_snr(existing.snr())
+                        .with_observers(existing.observed_by.iter().cloned());
+                }
+            }
+
+            changes.push(GraphChange::EdgeObserved {
+                source,
+                target,
+                edge,
+            });
         }
+
+        self.apply_changes(changes)
     }
 
-    pub fn update_from_node_info(&mut self, node_info: protobufs::NodeInfo) {
+    /// Records the SNR and RSSI of a packet `observer`'s radio heard directly from
+    /// `packet.from`, updating the weight of any existing edge between the sender and our node.
+    pub fn update_from_direct_packet(
+        &mut self,
+        packet: &MeshPacket,
+        my_node_num: u32,
+        observer: &str,
+    ) -> Vec<GraphChange> {
+        let weight = self.record_link_sample(
+            packet.from,
+            my_node_num,
+            LinkQualitySample {
+                timestamp: get_current_time_u32(),
+                snr: packet.rx_snr,
+                rssi: Some(packet.rx_rssi),
+                observer: observer.into(),
+            },
+        );
+
+        let (weight, from, to) = match (
+            weight,
+            self.get_node(packet.from),
+            self.get_node(my_node_num),
+        ) {
+            (Some(weight), Some(from), Some(to)) => (f64::from(weight), from, to),
+            _ => return vec![],
+        };
+
+        let edge = match self.get_edge(from, to) {
+            Some(edge) if edge.snr() != weight => edge.clone().with_snr(weight),
+            _ => return vec![],
+        };
+
+        self.apply_changes(vec![GraphChange::EdgeObserved {
+            source: packet.from,
+            target: my_node_num,
+            edge,
+        }])
+    }
+
+    pub fn update_from_node_info(&mut self, node_info: protobufs::NodeInfo) -> Vec<GraphChange> {
         log::info!(
             "Updating graph from node info packet from node {}",
             node_info.num
         );
 
-        if node_info.position.is_none() {
-            log::info!(
-                "Node info packet from node {} has no position, not adding to graph",
-                node_info.num
-            );
-            return;
-        }
-
-        let own_node = match self.get_node(node_info.num) {
-            Some(node) => GraphNode {
-                last_heard: chrono::Utc::now().naive_utc(),
-                ..node
-            },
-            None => GraphNode {
-                node_num: node_info.num,
-                last_heard: chrono::Utc::now().naive_utc(),
-                timeout_duration: DEFAULT_NODE_TIMEOUT_DURATION,
-            },
+        let position = match node_info.position {
+            Some(position) => position,
+            None => {
+                log::info!(
+                    "Node info packet from node {} has no position, not adding to graph",
+                    node_info.num
+                );
+                return vec![];
+            }
         };
 
-        self.upsert_node(own_node);
+        self.update_from_heard_position(node_info.num, position)
+    }
+
+    /// Applies node infos in the order they were heard, such as the radio's node
+    /// database, returning the changes they made together
+    pub fn update_from_node_infos(
+        &mut self,
+        node_infos: Vec<protobufs::NodeInfo>,
+    ) -> Vec<GraphChange> {
+        let mut changes = vec![];
+
+        for node_info in node_infos {
+            changes.extend(self.update_from_node_info(node_info));
+        }
+
+        changes
     }
 
-    pub fn update_from_position(&mut self, packet: MeshPacket, _position: protobufs::Position) {
+    pub fn update_from_position(
+        &mut self,
+        packet: MeshPacket,
+        position: protobufs::Position,
+    ) -> Vec<GraphChange> {
         log::info!(
             "Updating graph from position packet from node {}",
             packet.from
         );
 
-        let own_node = match self.get_node(packet.from) {
+        self.update_from_heard_position(packet.from, position)
+    }
+
+    fn update_from_heard_position(
+        &mut self,
+        node_num: u32,
+        position: protobufs::Position,
+    ) -> Vec<GraphChange> {
+        let own_node = match self.get_node(node_num) {
             Some(node) => GraphNode {
                 last_heard: chrono::Utc::now().naive_utc(),
                 ..node
             },
             None => GraphNode {
-                node_num: packet.from,
+                node_num,
                 last_heard: chrono::Utc::now().naive_utc(),
                 timeout_duration: DEFAULT_NODE_TIMEOUT_DURATION,
             },
         };
 
-        self.upsert_node(own_node);
+        self.apply_changes(vec![
+            GraphChange::PositionChanged {
+                node_num,
+                latitude: normalize_location_field(position.latitude_i).into(),
+                longitude: normalize_location_field(position.longitude_i).into(),
+            },
+            GraphChange::NodeUpserted(own_node),
+        ])
     }
 }
@@ -0,0 +1,546 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use log::{debug, trace, warn};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::device::helpers::get_current_time_u32;
+use crate::ipc::events;
+use crate::persistence::{settings_file_path, GRAPH_AUTOSAVE_DIR_NAME};
+use crate::shutdown::shutdown_signal;
+use crate::state;
+use crate::state::mesh_devices::all_devices;
+
+use super::geojson::GraphGeoJson;
+use super::store::{snapshot_graphs, GraphSnapshot};
+
+/// Version of the autosave file structure, bumped on breaking changes
+pub const GRAPH_AUTOSAVE_VERSION: u32 = 1;
+
+/// How often the autosave interval is checked, so a changed interval applies quickly
+pub const GRAPH_AUTOSAVE_TICK: Duration = Duration::from_secs(10);
+
+const AUTOSAVE_FILE_PREFIX: &str = "graph-autosave-";
+const AUTOSAVE_FILE_EXTENSION: &str = "json";
+
+/// The stored graphs of every connected radio at one point in time
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphAutosave {
+    pub version: u32,
+    pub saved_at: u32, // secs
+    pub snapshots: Vec<GraphSnapshot>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AutosaveInfo {
+    pub file_name: String,
+    pub saved_at: u32,        // secs
+    pub device_ids: Vec<u32>, // node nums of the devices with a graph in the autosave
+}
+
+impl AutosaveInfo {
+    fn new(path: &Path, autosave: &GraphAutosave) -> Self {
+        Self {
+            file_name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            saved_at: autosave.saved_at,
+            device_ids: autosave.snapshots.iter().map(|s| s.device_id()).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AutosaveStatus {
+    pub newest: Option<AutosaveInfo>,
+
+    /// Whether the graph database is unavailable or empty while an autosave exists,
+    /// e.g. after the database was lost in a crash
+    pub recovery_suggested: bool,
+}
+
+/// Tracks when the last autosave was written. Takes the interval on each check, so
+/// a changed setting applies without restarting the timer.
+pub struct AutosaveSchedule {
+    last_saved: Instant,
+}
+
+impl AutosaveSchedule {
+    pub fn new(now: Instant) -> Self {
+        Self { last_saved: now }
+    }
+
+    /// Returns whether an autosave is due, restarting the interval if it is
+    pub fn take_due(&mut self, interval: Duration, now: Instant) -> bool {
+        if now.duration_since(self.last_saved) < interval {
+            return false;
+        }
+
+        self.last_saved = now;
+        true
+    }
+}
+
+/// Names sort in the order the autosaves were written
+fn autosave_file_name(saved_at_millis: u64) -> String {
+    format!(
+        "{}{:016}.{}",
+        AUTOSAVE_FILE_PREFIX, saved_at_millis, AUTOSAVE_FILE_EXTENSION
+    )
+}
+
+/// Autosave files in `dir`, oldest first. Partly written temporary files are skipped.
+fn autosave_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("Failed to read {:?}: {}", dir, e))?;
+    let mut files = vec![];
+
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+
+        let is_autosave = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| name.starts_with(AUTOSAVE_FILE_PREFIX))
+            && path.extension().and_then(|ext| ext.to_str()) == Some(AUTOSAVE_FILE_EXTENSION);
+
+        if is_autosave {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+/// Writes an autosave to a temporary file and renames it into place, so a crash
+/// mid-write leaves the previous autosaves intact. Only the newest `keep` are kept.
+pub fn write_autosave(
+    dir: &Path,
+    autosave: &GraphAutosave,
+    saved_at_millis: u64,
+    keep: usize,
+) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+
+    let path = dir.join(autosave_file_name(saved_at_millis));
+    let temp_path = path.with_extension("tmp");
+
+    let contents = serde_json::to_vec(autosave).map_err(|e| e.to_string())?;
+
+    std::fs::write(&temp_path, contents)
+        .map_err(|e| format!("Failed to write {:?}: {}", temp_path, e))?;
+    std::fs::rename(&temp_path, &path)
+        .map_err(|e| format!("Failed to move autosave into {:?}: {}", path, e))?;
+
+    let files = autosave_files(dir)?;
+
+    for old in files.iter().take(files.len().saturating_sub(keep.max(1))) {
+        trace!("Removing old autosave {:?}", old);
+
+        if let Err(e) = std::fs::remove_file(old) {
+            warn!("Failed to remove old autosave {:?}: {}", old, e);
+        }
+    }
+
+    Ok(path)
+}
+
+/// Reads the newest autosave that can be read, skipping any that are damaged
+pub fn read_newest_autosave(dir: &Path) -> Result<Option<(PathBuf, GraphAutosave)>, String> {
+    for path in autosave_files(dir)?.into_iter().rev() {
+        let autosave = std::fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| {
+                serde_json::from_slice::<GraphAutosave>(&contents).map_err(|e| e.to_string())
+            });
+
+        match autosave {
+            Ok(autosave) if autosave.version <= GRAPH_AUTOSAVE_VERSION => {
+                return Ok(Some((path, autosave)))
+            }
+            Ok(autosave) => warn!(
+                "Skipping autosave {:?} from a newer version (version {})",
+                path, autosave.version
+            ),
+            Err(e) => warn!("Skipping unreadable autosave {:?}: {}", path, e),
+        }
+    }
+
+    Ok(None)
+}
+
+fn autosave_dir<R: tauri::Runtime>(handle: &tauri::AppHandle<R>) -> Result<PathBuf, String> {
+    settings_file_path(handle, GRAPH_AUTOSAVE_DIR_NAME)
+}
+
+/// Graph recovered from an autosave for a device, used when the database is unavailable
+pub fn recovered_snapshot<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_id: u32,
+) -> Option<GraphSnapshot> {
+    let autosave_state = handle.try_state::<state::graph_autosave::GraphAutosaveState>()?;
+    let recovered = autosave_state.recovered.lock().ok()?;
+
+    recovered.get(&device_id).cloned()
+}
+
+/// Autosaves the graphs of the connected radios. Nothing is written while none are
+/// connected, so the last autosave of a session isn't pushed out by empty ones.
+pub async fn autosave_graphs(handle: &tauri::AppHandle) -> Result<Option<PathBuf>, String> {
+    let snapshots = snapshot_graphs(handle, None).await;
+
+    if snapshots.is_empty() {
+        return Ok(None);
+    }
+
+    let keep = {
+        let settings_state = handle.state::<state::settings::SettingsState>();
+        let settings = settings_state.inner.lock().map_err(|e| e.to_string())?;
+        settings.autosave.keep as usize
+    };
+
+    let autosave = GraphAutosave {
+        version: GRAPH_AUTOSAVE_VERSION,
+        saved_at: get_current_time_u32(),
+        snapshots,
+    };
+
+    let saved_at_millis = chrono::Utc::now().timestamp_millis().max(0) as u64;
+    let path = write_autosave(&autosave_dir(handle)?, &autosave, saved_at_millis, keep)?;
+
+    debug!("Autosaved graphs to {:?}", path);
+
+    Ok(Some(path))
+}
+
+pub fn autosave_status(handle: &tauri::AppHandle) -> Result<AutosaveStatus, String> {
+    let newest = read_newest_autosave(&autosave_dir(handle)?)?
+        .map(|(path, autosave)| AutosaveInfo::new(&path, &autosave));
+
+    let database_empty = {
+        let store_state = handle.state::<state::graph_store::GraphStoreState>();
+        let store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+
+        match store_guard.as_ref() {
+            Some(store) => store.is_empty()?,
+            None => true,
+        }
+    };
+
+    Ok(AutosaveStatus {
+        recovery_suggested: database_empty && newest.is_some(),
+        newest,
+    })
+}
+
+/// Restores the graphs of the newest autosave. They're written to the database if it's
+/// available, loaded into the graphs of connected devices they belong to, and kept
+/// for devices that connect later.
+pub async fn recover_from_autosave(handle: &tauri::AppHandle) -> Result<AutosaveInfo, String> {
+    let (path, autosave) =
+        read_newest_autosave(&autosave_dir(handle)?)?.ok_or("No autosave to recover from")?;
+
+    debug!("Recovering graphs from autosave {:?}", path);
+
+    {
+        let store_state = handle.state::<state::graph_store::GraphStoreState>();
+        let mut store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+
+        if let Some(store) = store_guard.as_mut() {
+            for snapshot in &autosave.snapshots {
+                store.write(snapshot.clone())?;
+            }
+        }
+    }
+
+    {
+        let autosave_state = handle.state::<state::graph_autosave::GraphAutosaveState>();
+        let mut recovered = autosave_state.recovered.lock().map_err(|e| e.to_string())?;
+
+        *recovered = autosave
+            .snapshots
+            .iter()
+            .map(|snapshot| (snapshot.device_id(), snapshot.clone()))
+            .collect::<HashMap<_, _>>();
+    }
+
+    let mesh_devices = handle.state::<state::mesh_devices::MeshDevicesState>();
+
+    for (device_key, device) in all_devices(&mesh_devices.inner).await {
+        let mut packet_api = device.lock().await;
+        let device_id = packet_api.device.my_node_info.my_node_num;
+
+        let snapshot = match autosave
+            .snapshots
+            .iter()
+            .find(|s| s.device_id() == device_id)
+        {
+            Some(snapshot) => snapshot,
+            None => continue,
+        };
+
+        let graph = {
+            let graph_arc = packet_api.graph_arc.clone();
+            let mut graph = graph_arc.write()?;
+
+            snapshot.load_into(&mut graph, &mut packet_api.device)?;
+            graph.clone()
+        };
+
+        events::dispatch_updated_graph(handle, Some(device_key.clone()), graph.clone())
+            .map_err(|e| e.to_string())?;
+        events::dispatch_graph_geojson_update(
+            handle,
+            GraphGeoJson::new(device_key.clone(), &graph, &packet_api.device),
+        )
+        .map_err(|e| e.to_string())?;
+    }
+
+    Ok(AutosaveInfo::new(&path, &autosave))
+}
+
+/// Periodically autosaves the connected radios' graphs on the configured interval
+pub fn spawn_graph_autosave_timer(handle: tauri::AppHandle) {
+    trace!("Spawning graph autosave timer");
+
+    tauri::async_runtime::spawn(async move {
+        let shutdown = shutdown_signal(&handle);
+        let mut interval = tokio::time::interval(GRAPH_AUTOSAVE_TICK);
+        let mut schedule = AutosaveSchedule::new(Instant::now());
+
+        while shutdown.tick(&mut interval).await {
+            let autosave_interval = {
+                let settings_state = handle.state::<state::settings::SettingsState>();
+                let settings = match settings_state.inner.lock() {
+                    Ok(settings) => settings,
+                    Err(e) => {
+                        warn!("Failed to lock settings: {}", e);
+                        continue;
+                    }
+                };
+
+                settings.autosave.interval()
+            };
+
+            if !schedule.take_due(autosave_interval, Instant::now()) {
+                continue;
+            }
+
+            if let Err(e) = autosave_graphs(&handle).await {
+                warn!("Failed to autosave graphs: {}", e);
+            }
+        }
+
+        trace!("Stopped graph autosave timer");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::{MeshDevice, MeshNode};
+    use crate::graph::api::update_from_packet::DEFAULT_NODE_TIMEOUT_DURATION;
+    use crate::graph::ds::{edge::GraphEdge, graph::MeshGraph, node::GraphNode};
+
+    fn graph_node(node_num: u32, minutes_ago: i64) -> GraphNode {
+        GraphNode {
+            node_num,
+            last_heard: chrono::Utc::now().naive_utc() - chrono::Duration::minutes(minutes_ago),
+            timeout_duration: DEFAULT_NODE_TIMEOUT_DURATION,
+        }
+    }
+
+    fn fixture() -> (MeshGraph, MeshDevice) {
+        let mut graph = MeshGraph::new();
+        let nodes = [graph_node(1, 0), graph_node(2, 3), graph_node(3, 40)];
+
+        for node in nodes {
+            graph.upsert_node(node);
+        }
+
+        let neighbor = |node_id: u32, snr: f32| protobufs::Neighbor {
+            node_id,
+            snr,
+            ..Default::default()
+        };
+
+        graph.upsert_edge(
+            nodes[1],
+            nodes[0],
+            GraphEdge::from_neighbor(1, 0, "LongFast".into(), neighbor(2, 7.25)),
+        );
+        graph.upsert_edge(
+            nodes[2],
+            nodes[0],
+            GraphEdge::from_neighbor(1, 0, "LongFast".into(), neighbor(3, -2.5)),
+        );
+        graph.set_node_label(2, Some("Water tower".into()));
+
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 1;
+
+        let mut node = MeshNode::new(3);
+        node.user = Some(protobufs::User {
+            long_name: "Summit".into(),
+            ..Default::default()
+        });
+        device.nodes.insert(3, node);
+
+        (graph, device)
+    }
+
+    fn autosave(graph: &MeshGraph, device: &MeshDevice, saved_at: u32) -> GraphAutosave {
+        GraphAutosave {
+            version: GRAPH_AUTOSAVE_VERSION,
+            saved_at,
+            snapshots: vec![GraphSnapshot::new(1, graph, device).unwrap()],
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("graph-autosave-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn saves_on_interval() {
+        let start = Instant::now();
+        let minutes = |m: u64| start + Duration::from_secs(m * 60);
+        let interval = Duration::from_secs(5 * 60);
+
+        let mut schedule = AutosaveSchedule::new(start);
+        let saves: Vec<u64> = (1..=20)
+            .filter(|m| schedule.take_due(interval, minutes(*m)))
+            .collect();
+
+        assert_eq!(saves, [5, 10, 15, 20]);
+
+        // A shorter interval applies from the last save
+        let saves: Vec<u64> = (21..=24)
+            .filter(|m| schedule.take_due(Duration::from_secs(2 * 60), minutes(*m)))
+            .collect();
+
+        assert_eq!(saves, [22, 24]);
+    }
+
+    #[test]
+    fn keeps_newest_autosaves() {
+        let dir = temp_dir("rotate");
+        let (graph, device) = fixture();
+
+        for i in 0..6 {
+            write_autosave(
+                &dir,
+                &autosave(&graph, &device, 100 + i),
+                1_000 + i as u64,
+                3,
+            )
+            .unwrap();
+        }
+
+        // Left behind by a crash mid-write
+        std::fs::write(
+            dir.join(autosave_file_name(9_999)).with_extension("tmp"),
+            "{",
+        )
+        .unwrap();
+
+        let files: Vec<String> = autosave_files(&dir)
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+
+        assert_eq!(
+            files,
+            [
+                autosave_file_name(1_003),
+                autosave_file_name(1_004),
+                autosave_file_name(1_005)
+            ]
+        );
+
+        // A damaged newest autosave falls back to the one before it
+        std::fs::write(dir.join(autosave_file_name(2_000)), "{\"version\":").unwrap();
+
+        let (path, newest) = read_newest_autosave(&dir).unwrap().unwrap();
+        assert_eq!(path, dir.join(autosave_file_name(1_005)));
+        assert_eq!(newest.saved_at, 105);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn recovers_equivalent_graph() {
+        let dir = temp_dir("recover");
+        let (graph, device) = fixture();
+
+        assert!(read_newest_autosave(&dir).unwrap().is_none());
+
+        write_autosave(&dir, &autosave(&graph, &device, 100), 1_000, 5).unwrap();
+        let (_, recovered) = read_newest_autosave(&dir).unwrap().unwrap();
+
+        let mut recovered_graph = MeshGraph::new();
+        let mut recovered_device = MeshDevice::new();
+
+        assert_eq!(
+            recovered.snapshots[0]
+                .load_into(&mut recovered_graph, &mut recovered_device)
+                .unwrap(),
+            (3, 2)
+        );
+
+        let mut nodes: Vec<GraphNode> = graph.nodes().collect();
+        let mut recovered_nodes: Vec<GraphNode> = recovered_graph.nodes().collect();
+        nodes.sort();
+        recovered_nodes.sort();
+
+        assert_eq!(nodes.len(), recovered_nodes.len());
+
+        for (node, recovered) in nodes.iter().zip(&recovered_nodes) {
+            assert_eq!(node.node_num, recovered.node_num);
+            assert_eq!(node.last_heard, recovered.last_heard);
+        }
+
+        let edges = |graph: &MeshGraph| {
+            let mut edges: Vec<String> = graph
+                .edges()
+                .map(|(from, to, edge)| {
+                    format!(
+                        "{} {} {}",
+                        from.node_num,
+                        to.node_num,
+                        serde_json::to_string(edge).unwrap()
+                    )
+                })
+                .collect();
+            edges.sort();
+            edges
+        };
+
+        assert_eq!(edges(&recovered_graph), edges(&graph));
+        assert_eq!(recovered_graph.overrides.node_label(2), Some("Water tower"));
+        assert_eq!(
+            recovered_device.nodes[&3].user.as_ref().unwrap().long_name,
+            "Summit"
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
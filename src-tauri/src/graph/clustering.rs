@@ -0,0 +1,449 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use super::ds::{graph::MeshGraph, spatial_index::SpatialIndex};
+
+/// Moves that improve modularity by less than this are treated as ties
+const MIN_MODULARITY_GAIN: f64 = 1e-12;
+
+/// How nodes are grouped into clusters
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum ClusterSource {
+    /// Communities of densely linked nodes, found with the Louvain method
+    Community,
+    /// Groups of nodes close to each other on the map, found with DBSCAN. Nodes with
+    /// fewer than `min_points` nodes within `eps_meters`, themselves included, start
+    /// no cluster of their own.
+    #[serde(rename_all = "camelCase")]
+    Geographic { eps_meters: f64, min_points: u32 },
+}
+
+/// Groups nodes by `source`. Members are sorted and clusters are ordered by their
+/// lowest node num, so a cluster's index stays the same while its membership does.
+/// Community clusters cover every node in the graph, geographic ones only positioned
+/// nodes that aren't noise.
+pub fn find_clusters(
+    graph: &MeshGraph,
+    positions: &SpatialIndex,
+    source: &ClusterSource,
+) -> Result<Vec<Vec<u32>>, String> {
+    let clusters = match source {
+        ClusterSource::Community => louvain_communities(graph),
+        ClusterSource::Geographic {
+            eps_meters,
+            min_points,
+        } => {
+            if !eps_meters.is_finite() || *eps_meters <= 0.0 {
+                return Err("Cluster distance must be a positive number of meters".into());
+            }
+
+            if *min_points == 0 {
+                return Err("Clusters need at least 1 point".into());
+            }
+
+            dbscan_clusters(positions, *eps_meters, *min_points)
+        }
+    };
+
+    Ok(ordered(clusters))
+}
+
+/// Communities of densely linked nodes and how cleanly they divide the mesh
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct Communities {
+    pub clusters: Vec<Vec<u32>>, // ordered as `find_clusters` orders them
+    pub modularity: f64,         // from -0.5 to 1, higher the more links fall within communities
+}
+
+/// Finds the graph's communities with the Louvain method, along with their modularity
+pub fn find_communities(graph: &MeshGraph) -> Communities {
+    let clusters = ordered(louvain_communities(graph));
+    let modularity = modularity(graph, &clusters);
+
+    Communities {
+        clusters,
+        modularity,
+    }
+}
+
+/// Modularity of grouping nodes into `clusters`, treating each pair of linked nodes as
+/// a single undirected link of weight 1 like `louvain_communities` does. Nodes in no
+/// cluster count as clusters of their own. 0 if the graph has no links.
+pub fn modularity(graph: &MeshGraph, clusters: &[Vec<u32>]) -> f64 {
+    let mut cluster_of: HashMap<u32, usize> = clusters
+        .iter()
+        .enumerate()
+        .flat_map(|(c, members)| members.iter().map(move |node_num| (*node_num, c)))
+        .collect();
+
+    let mut next_cluster = clusters.len();
+
+    for node in graph.nodes() {
+        cluster_of.entry(node.node_num).or_insert_with(|| {
+            next_cluster += 1;
+            next_cluster - 1
+        });
+    }
+
+    let links: HashSet<(u32, u32)> = graph
+        .edges()
+        .filter(|(from, to, _)| from.node_num != to.node_num)
+        .map(|(from, to, _)| {
+            (
+                from.node_num.min(to.node_num),
+                from.node_num.max(to.node_num),
+            )
+        })
+        .collect();
+
+    if links.is_empty() {
+        return 0.0;
+    }
+
+    // Links within each cluster and the degrees of its members
+    let mut internal: HashMap<usize, f64> = HashMap::new();
+    let mut degrees: HashMap<usize, f64> = HashMap::new();
+
+    for (a, b) in &links {
+        let (a, b) = (cluster_of[a], cluster_of[b]);
+
+        *degrees.entry(a).or_default() += 1.0;
+        *degrees.entry(b).or_default() += 1.0;
+
+        if a == b {
+            *internal.entry(a).or_default() += 1.0;
+        }
+    }
+
+    let link_count = links.len() as f64;
+
+    degrees
+        .iter()
+        .map(|(c, degree)| {
+            internal.get(c).copied().unwrap_or(0.0) / link_count
+                - (degree / (2.0 * link_count)).powi(2)
+        })
+        .sum()
+}
+
+fn ordered(clusters: Vec<Vec<u32>>) -> Vec<Vec<u32>> {
+    let mut clusters: Vec<Vec<u32>> = clusters
+        .into_iter()
+        .filter(|members| !members.is_empty())
+        .map(|mut members| {
+            members.sort_unstable();
+            members
+        })
+        .collect();
+
+    clusters.sort_by_key(|members| members[0]);
+    clusters
+}
+
+/// Renumbers communities from 0 in the order they're first seen
+fn renumbered(community: &[usize]) -> Vec<usize> {
+    let mut ids = HashMap::new();
+
+    community
+        .iter()
+        .map(|c| {
+            let next = ids.len();
+            *ids.entry(*c).or_insert(next)
+        })
+        .collect()
+}
+
+/// One pass of the Louvain method: moves each node to the neighboring community that
+/// most improves modularity until no move does. `adjacency` is symmetric, and a node's
+/// own entry counts the links within it twice.
+fn local_moving(adjacency: &[BTreeMap<usize, f64>]) -> Vec<usize> {
+    let degrees: Vec<f64> = adjacency.iter().map(|links| links.values().sum()).collect();
+    let total: f64 = degrees.iter().sum();
+    let mut community: Vec<usize> = (0..adjacency.len()).collect();
+
+    if total == 0.0 {
+        return community;
+    }
+
+    let mut community_degrees = degrees.clone();
+    let mut moved = true;
+
+    while moved {
+        moved = false;
+
+        for node in 0..adjacency.len() {
+            let current = community[node];
+            community_degrees[current] -= degrees[node];
+
+            let mut links: BTreeMap<usize, f64> = BTreeMap::new();
+
+            for (neighbor, weight) in &adjacency[node] {
+                if *neighbor != node {
+                    *links.entry(community[*neighbor]).or_default() += weight;
+                }
+            }
+
+            let gain =
+                |c: usize, weight: f64| weight - community_degrees[c] * degrees[node] / total;
+
+            let mut best = (
+                current,
+                gain(current, links.get(&current).copied().unwrap_or(0.0)),
+            );
+
+            for (c, weight) in &links {
+                let candidate = gain(*c, *weight);
+
+                if candidate > best.1 + MIN_MODULARITY_GAIN {
+                    best = (*c, candidate);
+                }
+            }
+
+            community_degrees[best.0] += degrees[node];
+
+            if best.0 != current {
+                community[node] = best.0;
+                moved = true;
+            }
+        }
+    }
+
+    renumbered(&community)
+}
+
+/// Communities of the graph's nodes found with the Louvain method, treating each pair
+/// of linked nodes as a single undirected link of weight 1. Unlinked nodes are
+/// communities of their own.
+pub fn louvain_communities(graph: &MeshGraph) -> Vec<Vec<u32>> {
+    let node_nums: Vec<u32> = graph
+        .nodes()
+        .map(|node| node.node_num)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    let index: HashMap<u32, usize> = node_nums.iter().enumerate().map(|(i, n)| (*n, i)).collect();
+
+    let mut adjacency: Vec<BTreeMap<usize, f64>> = vec![BTreeMap::new(); node_nums.len()];
+    let mut linked = HashSet::new();
+
+    for (from, to, _) in graph.edges() {
+        let (a, b) = (index[&from.node_num], index[&to.node_num]);
+
+        if a != b && linked.insert((a.min(b), a.max(b))) {
+            *adjacency[a].entry(b).or_default() += 1.0;
+            *adjacency[b].entry(a).or_default() += 1.0;
+        }
+    }
+
+    // Community of each of the graph's nodes, coarsened one level at a time
+    let mut membership: Vec<usize> = (0..node_nums.len()).collect();
+
+    loop {
+        let community = local_moving(&adjacency);
+        let count = community.iter().max().map_or(0, |c| c + 1);
+
+        if count == adjacency.len() {
+            break;
+        }
+
+        for c in membership.iter_mut() {
+            *c = community[*c];
+        }
+
+        let mut aggregated: Vec<BTreeMap<usize, f64>> = vec![BTreeMap::new(); count];
+
+        for (node, links) in adjacency.iter().enumerate() {
+            for (neighbor, weight) in links {
+                *aggregated[community[node]]
+                    .entry(community[*neighbor])
+                    .or_default() += weight;
+            }
+        }
+
+        adjacency = aggregated;
+    }
+
+    let mut clusters: Vec<Vec<u32>> = vec![vec![]; adjacency.len()];
+
+    for (node_num, c) in node_nums.iter().zip(membership) {
+        clusters[c].push(*node_num);
+    }
+
+    clusters
+}
+
+/// Clusters of positioned nodes found with DBSCAN. Nodes that aren't within `eps_meters`
+/// of a node with at least `min_points` neighbors are noise and left out.
+pub fn dbscan_clusters(
+    positions: &SpatialIndex,
+    eps_meters: f64,
+    min_points: u32,
+) -> Vec<Vec<u32>> {
+    let neighbors = |node_num: u32| -> Vec<u32> {
+        let (latitude, longitude) = positions.position(node_num).unwrap_or_default();
+
+        positions
+            .within_radius(latitude, longitude, eps_meters)
+            .into_iter()
+            .map(|(neighbor, _)| neighbor)
+            .collect()
+    };
+
+    let mut node_nums: Vec<u32> = positions.node_nums().collect();
+    node_nums.sort_unstable();
+
+    // `None` for noise, which may still join a cluster as one of its border nodes
+    let mut labels: HashMap<u32, Option<usize>> = HashMap::new();
+    let mut clusters: Vec<Vec<u32>> = vec![];
+
+    for node_num in node_nums {
+        if labels.contains_key(&node_num) {
+            continue;
+        }
+
+        let seeds = neighbors(node_num);
+
+        if seeds.len() < min_points as usize {
+            labels.insert(node_num, None);
+            continue;
+        }
+
+        let cluster = clusters.len();
+        clusters.push(vec![node_num]);
+        labels.insert(node_num, Some(cluster));
+
+        let mut queue: VecDeque<u32> = seeds.into();
+
+        while let Some(member) = queue.pop_front() {
+            match labels.insert(member, Some(cluster)) {
+                Some(Some(_)) => continue,
+                Some(None) => {
+                    clusters[cluster].push(member);
+                    continue;
+                }
+                None => clusters[cluster].push(member),
+            }
+
+            let reachable = neighbors(member);
+
+            if reachable.len() >= min_points as usize {
+                queue.extend(reachable);
+            }
+        }
+    }
+
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::ds::edge::GraphEdge;
+    use crate::graph::ds::fixtures::graph_node;
+
+    fn link(graph: &mut MeshGraph, from: u32, to: u32) {
+        graph.upsert_edge(
+            graph_node(from),
+            graph_node(to),
+            GraphEdge::manual(from, to, 5.0),
+        );
+    }
+
+    #[test]
+    fn louvain_splits_cliques_joined_by_a_bridge() {
+        let mut graph = MeshGraph::new();
+
+        for node_num in 1..=9 {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        // Two 4-cliques joined by the 4 - 5 link, and node 9 on its own
+        for clique in [[1, 2, 3, 4], [5, 6, 7, 8]] {
+            for (i, from) in clique.iter().enumerate() {
+                for to in &clique[i + 1..] {
+                    link(&mut graph, *from, *to);
+                }
+            }
+        }
+
+        link(&mut graph, 4, 5);
+        link(&mut graph, 5, 4);
+
+        let clusters = find_clusters(&graph, &SpatialIndex::default(), &ClusterSource::Community);
+
+        assert_eq!(
+            clusters.unwrap(),
+            [vec![1, 2, 3, 4], vec![5, 6, 7, 8], vec![9]]
+        );
+
+        // Each clique has 6 of the 13 links and half of the link ends
+        let communities = find_communities(&graph);
+        assert!((communities.modularity - 11.0 / 26.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn triangles_joined_by_a_weak_link_are_two_communities() {
+        let mut graph = MeshGraph::new();
+
+        for node_num in 1..=6 {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        for (from, to) in [(1, 2), (2, 3), (3, 1), (4, 5), (5, 6), (6, 4)] {
+            link(&mut graph, from, to);
+        }
+
+        graph.upsert_edge(graph_node(3), graph_node(4), GraphEdge::manual(3, 4, -15.0));
+
+        let communities = find_communities(&graph);
+
+        assert_eq!(communities.clusters, [vec![1, 2, 3], vec![4, 5, 6]]);
+        assert!((communities.modularity - 5.0 / 14.0).abs() < 1e-9);
+
+        // Nothing is gained by keeping the whole mesh together
+        assert!(modularity(&graph, &[(1..=6).collect()]).abs() < 1e-9);
+        assert_eq!(modularity(&MeshGraph::new(), &[]), 0.0);
+    }
+
+    #[test]
+    fn dbscan_groups_nearby_nodes_and_drops_noise() {
+        let mut positions = SpatialIndex::default();
+
+        // Nodes ~110 m apart in two rows ~11 km apart, and a stray node between them
+        for i in 0..4 {
+            positions.update(10 + i, 47.0, 8.0 + f64::from(i) * 0.0015);
+            positions.update(20 + i, 47.1, 8.0 + f64::from(i) * 0.0015);
+        }
+
+        positions.update(1, 47.05, 8.0);
+
+        let source = ClusterSource::Geographic {
+            eps_meters: 150.0,
+            min_points: 3,
+        };
+
+        let clusters = find_clusters(&MeshGraph::new(), &positions, &source).unwrap();
+        assert_eq!(clusters, [vec![10, 11, 12, 13], vec![20, 21, 22, 23]]);
+
+        // With one point per cluster, every node is a core node
+        let source = ClusterSource::Geographic {
+            eps_meters: 150.0,
+            min_points: 1,
+        };
+
+        let clusters = find_clusters(&MeshGraph::new(), &positions, &source).unwrap();
+        assert_eq!(clusters.len(), 3);
+        assert_eq!(clusters[0], [1]);
+
+        let invalid = ClusterSource::Geographic {
+            eps_meters: 0.0,
+            min_points: 3,
+        };
+        assert!(find_clusters(&MeshGraph::new(), &positions, &invalid).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use geojson::{Feature, Geometry, Value};
+use serde_json::json;
+
+use crate::device::MeshDevice;
+
+use super::{
+    ds::graph::MeshGraph,
+    geometry::{buffer_ring, LocalProjection, Point},
+    route::{find_route, RouteGeoJson},
+};
+
+/// Wider corridors would be distorted by the flat projection they're built in
+pub const MAX_CORRIDOR_BUFFER_METERS: f64 = 50_000.0;
+
+/// Builds a Polygon covering everything within `buffer_meters` of the cheapest route
+/// between two nodes, with the same properties as the route's LineString plus
+/// `bufferMeters`. Unpositioned nodes on the route are skipped.
+pub fn build_route_corridor(
+    graph: &MeshGraph,
+    device: &MeshDevice,
+    from_node: u32,
+    to_node: u32,
+    buffer_meters: f64,
+) -> Result<RouteGeoJson, String> {
+    if !buffer_meters.is_finite()
+        || buffer_meters <= 0.0
+        || buffer_meters > MAX_CORRIDOR_BUFFER_METERS
+    {
+        return Err(format!(
+            "Corridor buffer must be more than 0 and at most {} meters",
+            MAX_CORRIDOR_BUFFER_METERS
+        ));
+    }
+
+    let route = match find_route(graph, device, from_node, to_node)? {
+        Some(route) => route,
+        None => return Ok(RouteGeoJson::NoRoute { from_node, to_node }),
+    };
+
+    if route.coordinates.is_empty() {
+        return Err("No nodes along the route have a position".into());
+    }
+
+    let projection = LocalProjection::around(&route.coordinates);
+    let path: Vec<Point> = route
+        .coordinates
+        .iter()
+        .map(|c| projection.project(c))
+        .collect();
+
+    let ring = buffer_ring(&path, buffer_meters)
+        .into_iter()
+        .map(|point| projection.unproject(point))
+        .collect();
+
+    let mut properties = route.properties();
+    properties.insert("bufferMeters".into(), json!(buffer_meters));
+
+    Ok(RouteGeoJson::Found {
+        feature: Feature {
+            bbox: None,
+            geometry: Some(Geometry::new(Value::Polygon(vec![ring]))),
+            id: None,
+            properties: Some(properties),
+            foreign_members: None,
+        },
+        hops: vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::{MeshNode, NormalizedPosition};
+    use crate::graph::ds::edge::GraphEdge;
+    use crate::graph::ds::fixtures::graph_node;
+    use crate::graph::geometry::ARC_SEGMENTS_PER_TURN;
+
+    fn positioned_node(node_num: u32, latitude: f32, longitude: f32) -> MeshNode {
+        let mut node = MeshNode::new(node_num);
+        node.position_metrics
+            .push(NormalizedPosition::from(protobufs::Position {
+                latitude_i: (latitude * 1e7) as i32,
+                longitude_i: (longitude * 1e7) as i32,
+                ..Default::default()
+            }));
+        node
+    }
+
+    fn linked(positions: &[(u32, f32, f32)]) -> (MeshGraph, MeshDevice) {
+        let mut graph = MeshGraph::new();
+        let mut device = MeshDevice::new();
+
+        for (node_num, latitude, longitude) in positions {
+            graph.upsert_node(graph_node(*node_num));
+            device
+                .nodes
+                .insert(*node_num, positioned_node(*node_num, *latitude, *longitude));
+        }
+
+        for pair in positions.windows(2) {
+            let (from, to) = (pair[0].0, pair[1].0);
+            graph.upsert_edge(
+                graph_node(from),
+                graph_node(to),
+                GraphEdge::manual(from, to, 5.0),
+            );
+        }
+
+        (graph, device)
+    }
+
+    fn corridor_ring(
+        graph: &MeshGraph,
+        device: &MeshDevice,
+        from: u32,
+        to: u32,
+        buffer: f64,
+    ) -> Vec<Vec<f64>> {
+        let feature = match build_route_corridor(graph, device, from, to, buffer) {
+            Ok(RouteGeoJson::Found { feature, .. }) => feature,
+            other => panic!("expected a corridor, got {:?}", other),
+        };
+
+        match feature.geometry.unwrap().value {
+            Value::Polygon(mut rings) => {
+                assert_eq!(rings.len(), 1);
+                rings.remove(0)
+            }
+            other => panic!("unexpected geometry {:?}", other),
+        }
+    }
+
+    /// Shoelace area in square meters, positive for counter-clockwise rings
+    fn signed_area(ring: &[Vec<f64>]) -> f64 {
+        let projection = LocalProjection::around(ring);
+        let points: Vec<Point> = ring.iter().map(|c| projection.project(c)).collect();
+
+        points
+            .windows(2)
+            .map(|w| w[0].0 * w[1].1 - w[1].0 * w[0].1)
+            .sum::<f64>()
+            / 2.0
+    }
+
+    #[test]
+    fn corridor_around_straight_two_node_path() {
+        // ~7.6 km due east
+        let (graph, device) = linked(&[(1, 47.0, 8.0), (2, 47.0, 8.1)]);
+
+        let ring = corridor_ring(&graph, &device, 1, 2, 500.0);
+
+        assert!(ring.len() > 4);
+        assert_eq!(ring.first(), ring.last());
+
+        let length = crate::device::helpers::haversine_distance_meters(47.0, 8.0, 47.0, 8.1);
+        let expected = length * 1_000.0 + PI * 500.0 * 500.0;
+        let area = signed_area(&ring);
+
+        assert!(area > 0.0, "ring isn't counter-clockwise");
+        assert!(
+            (area - expected).abs() / expected < 0.01,
+            "{} vs {}",
+            area,
+            expected
+        );
+    }
+
+    #[test]
+    fn corridor_follows_turns_in_the_path() {
+        // East then north, so the corridor bends around node 2
+        let (graph, device) = linked(&[(1, 47.0, 8.0), (2, 47.0, 8.05), (3, 47.04, 8.05)]);
+
+        let feature = match build_route_corridor(&graph, &device, 1, 3, 250.0).unwrap() {
+            RouteGeoJson::Found { feature, .. } => feature,
+            other => panic!("expected a corridor, got {:?}", other),
+        };
+
+        let properties = feature.properties.as_ref().unwrap();
+        assert_eq!(properties["hopCount"], json!(2));
+        assert_eq!(properties["bufferMeters"], json!(250.0));
+
+        let ring = corridor_ring(&graph, &device, 1, 3, 250.0);
+        assert_eq!(ring.first(), ring.last());
+        assert!(signed_area(&ring) > 0.0);
+    }
+
+    #[test]
+    fn degenerate_paths_give_circles() {
+        // Co-located endpoints, and a route from a node to itself
+        let (graph, device) = linked(&[(1, 47.0, 8.0), (2, 47.0, 8.0)]);
+
+        for (from, to) in [(1, 2), (1, 1)] {
+            let ring = corridor_ring(&graph, &device, from, to, 100.0);
+
+            assert_eq!(ring.first(), ring.last());
+            assert_eq!(ring.len(), ARC_SEGMENTS_PER_TURN as usize + 1);
+
+            let expected = PI * 100.0 * 100.0;
+            let area = signed_area(&ring);
+            assert!(area > 0.0);
+            assert!((area - expected).abs() / expected < 0.01);
+        }
+
+        assert!(build_route_corridor(&graph, &device, 1, 2, 0.0).is_err());
+        assert!(build_route_corridor(&graph, &device, 1, 2, f64::NAN).is_err());
+    }
+}
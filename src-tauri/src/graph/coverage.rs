@@ -0,0 +1,267 @@
+use geojson::{Feature, FeatureCollection, Geometry, JsonObject, Value};
+use serde_json::json;
+
+use crate::device::MeshDevice;
+
+use super::{
+    clustering::{find_clusters, ClusterSource},
+    ds::{graph::MeshGraph, spatial_index::SpatialIndex},
+    geojson::with_bbox,
+    geometry::{buffer_hull_ring, buffer_ring, convex_hull, LocalProjection, Point},
+};
+
+/// Wider buffers would be distorted by the flat projection they're built in
+pub const MAX_COVERAGE_BUFFER_KM: f64 = 50.0;
+
+/// Clusters with fewer positioned members are drawn as a circle around each member
+pub const MIN_HULL_MEMBERS: usize = 3;
+
+/// Number of colors the frontend cycles through for clusters
+pub const CLUSTER_COLOR_COUNT: usize = 12;
+
+fn cluster_feature(
+    cluster_id: usize,
+    members: &[u32],
+    positions: &SpatialIndex,
+    buffer_km: f64,
+) -> Option<Feature> {
+    let coordinates: Vec<Vec<f64>> = members
+        .iter()
+        .filter_map(|node_num| positions.position(*node_num))
+        .map(|(latitude, longitude)| vec![longitude, latitude])
+        .collect();
+
+    if coordinates.is_empty() {
+        return None;
+    }
+
+    let buffer_meters = buffer_km * 1_000.0;
+    let projection = LocalProjection::around(&coordinates);
+    let points: Vec<Point> = coordinates.iter().map(|c| projection.project(c)).collect();
+
+    let unproject = |ring: Vec<Point>| -> Vec<Vec<f64>> {
+        ring.into_iter().map(|p| projection.unproject(p)).collect()
+    };
+
+    let (shape, value) = if points.len() >= MIN_HULL_MEMBERS {
+        let ring = buffer_hull_ring(&convex_hull(&points), buffer_meters);
+        ("hull", Value::Polygon(vec![unproject(ring)]))
+    } else {
+        let circles = points
+            .iter()
+            .map(|point| vec![unproject(buffer_ring(&[*point], buffer_meters))])
+            .collect();
+        ("points", Value::MultiPolygon(circles))
+    };
+
+    let mut properties = JsonObject::new();
+    properties.insert("clusterId".into(), json!(cluster_id));
+    properties.insert("colorIndex".into(), json!(cluster_id % CLUSTER_COLOR_COUNT));
+    properties.insert("memberCount".into(), json!(members.len()));
+    properties.insert("positionedCount".into(), json!(coordinates.len()));
+    properties.insert("members".into(), json!(members));
+    properties.insert("shape".into(), json!(shape));
+    properties.insert("bufferKm".into(), json!(buffer_km));
+
+    Some(Feature {
+        bbox: None,
+        geometry: Some(Geometry::new(value)),
+        id: None,
+        properties: Some(properties),
+        foreign_members: None,
+    })
+}
+
+/// Generates a coverage area per cluster of nodes: the convex hull of the cluster's
+/// positioned members buffered by `buffer_km`, or a circle around each member if
+/// fewer than `MIN_HULL_MEMBERS` are positioned. Clusters without positioned members
+/// are left out. Features are ordered by `clusterId`, and `colorIndex` stays the same
+/// as long as a cluster's id does.
+pub fn generate_cluster_coverage_geojson(
+    graph: &MeshGraph,
+    device: &MeshDevice,
+    clustering: &ClusterSource,
+    buffer_km: f64,
+) -> Result<FeatureCollection, String> {
+    if !buffer_km.is_finite() || buffer_km <= 0.0 || buffer_km > MAX_COVERAGE_BUFFER_KM {
+        return Err(format!(
+            "Coverage buffer must be more than 0 and at most {} km",
+            MAX_COVERAGE_BUFFER_KM
+        ));
+    }
+
+    let mut positions = SpatialIndex::default();
+
+    for node in graph.nodes() {
+        let position = device
+            .nodes
+            .get(&node.node_num)
+            .and_then(|mesh_node| mesh_node.last_known_position());
+
+        if let Some(position) = position {
+            positions.update(
+                node.node_num,
+                position.latitude.into(),
+                position.longitude.into(),
+            );
+        }
+    }
+
+    let features = find_clusters(graph, &positions, clustering)?
+        .iter()
+        .enumerate()
+        .filter_map(|(cluster_id, members)| {
+            cluster_feature(cluster_id, members, &positions, buffer_km)
+        })
+        .collect();
+
+    Ok(with_bbox(FeatureCollection {
+        bbox: None,
+        features,
+        foreign_members: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::{MeshNode, NormalizedPosition};
+    use crate::graph::ds::edge::GraphEdge;
+    use crate::graph::ds::fixtures::graph_node;
+
+    fn positioned_node(node_num: u32, latitude: f32, longitude: f32) -> MeshNode {
+        let mut node = MeshNode::new(node_num);
+        node.position_metrics
+            .push(NormalizedPosition::from(protobufs::Position {
+                latitude_i: (latitude * 1e7) as i32,
+                longitude_i: (longitude * 1e7) as i32,
+                ..Default::default()
+            }));
+        node
+    }
+
+    /// Five linked nodes around 47°N 8°E, and a triangle of nodes ~55 km north joined
+    /// to them by a single link. Node 8 of the triangle has no position.
+    fn two_clusters() -> (MeshGraph, MeshDevice) {
+        let mut graph = MeshGraph::new();
+        let mut device = MeshDevice::new();
+
+        let positions = [
+            (1, 47.0, 8.0),
+            (2, 47.0, 8.02),
+            (3, 47.015, 8.02),
+            (4, 47.015, 8.0),
+            (5, 47.007, 8.01),
+            (6, 47.5, 8.0),
+            (7, 47.5, 8.005),
+        ];
+
+        for (node_num, latitude, longitude) in positions {
+            graph.upsert_node(graph_node(node_num));
+            device
+                .nodes
+                .insert(node_num, positioned_node(node_num, latitude, longitude));
+        }
+
+        graph.upsert_node(graph_node(8));
+
+        let links = [
+            (1, 2),
+            (2, 3),
+            (3, 4),
+            (4, 1),
+            (5, 1),
+            (5, 2),
+            (5, 3),
+            (5, 4),
+            (6, 7),
+            (7, 8),
+            (8, 6),
+            (4, 6),
+        ];
+
+        for (from, to) in links {
+            graph.upsert_edge(
+                graph_node(from),
+                graph_node(to),
+                GraphEdge::manual(from, to, 5.0),
+            );
+        }
+
+        (graph, device)
+    }
+
+    #[test]
+    fn one_feature_per_cluster_with_small_cluster_fallback() {
+        let (graph, device) = two_clusters();
+
+        let geographic = ClusterSource::Geographic {
+            eps_meters: 2_000.0,
+            min_points: 2,
+        };
+
+        for clustering in [ClusterSource::Community, geographic] {
+            let collection =
+                generate_cluster_coverage_geojson(&graph, &device, &clustering, 0.5).unwrap();
+
+            assert_eq!(collection.features.len(), 2, "{:?}", clustering);
+            assert!(collection.bbox.is_some());
+
+            let large = collection.features[0].properties.as_ref().unwrap();
+            assert_eq!(large["clusterId"], json!(0));
+            assert_eq!(large["colorIndex"], json!(0));
+            assert_eq!(large["memberCount"], json!(5));
+            assert_eq!(large["shape"], json!("hull"));
+
+            match &collection.features[0].geometry.as_ref().unwrap().value {
+                Value::Polygon(rings) => assert_eq!(rings.len(), 1),
+                other => panic!("expected a hull, got {:?}", other),
+            }
+
+            let small = collection.features[1].properties.as_ref().unwrap();
+            assert_eq!(small["clusterId"], json!(1));
+            assert_eq!(small["positionedCount"], json!(2));
+            assert_eq!(small["shape"], json!("points"));
+
+            match &collection.features[1].geometry.as_ref().unwrap().value {
+                Value::MultiPolygon(circles) => assert_eq!(circles.len(), 2),
+                other => panic!("expected circles, got {:?}", other),
+            }
+        }
+
+        // The unpositioned node only counts as a member of its community
+        let collection =
+            generate_cluster_coverage_geojson(&graph, &device, &ClusterSource::Community, 0.5)
+                .unwrap();
+        let small = collection.features[1].properties.as_ref().unwrap();
+        assert_eq!(small["memberCount"], json!(3));
+        assert_eq!(small["members"], json!([6, 7, 8]));
+    }
+
+    #[test]
+    fn hull_is_buffered_beyond_its_members() {
+        let (graph, device) = two_clusters();
+
+        let collection =
+            generate_cluster_coverage_geojson(&graph, &device, &ClusterSource::Community, 1.0)
+                .unwrap();
+
+        let ring = match &collection.features[0].geometry.as_ref().unwrap().value {
+            Value::Polygon(rings) => rings[0].clone(),
+            other => panic!("expected a hull, got {:?}", other),
+        };
+
+        assert_eq!(ring.first(), ring.last());
+
+        // About 1 km (~0.009°) south of the southernmost members
+        let south = ring.iter().map(|c| c[1]).fold(f64::INFINITY, f64::min);
+        assert!((south - (47.0 - 0.009)).abs() < 0.0005, "{}", south);
+
+        assert!(
+            generate_cluster_coverage_geojson(&graph, &device, &ClusterSource::Community, 0.0)
+                .is_err()
+        );
+    }
+}
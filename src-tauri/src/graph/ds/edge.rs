@@ -1,3 +1,5 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
 use std::time::Duration;
 
 use chrono::NaiveDateTime;
@@ -9,6 +11,31 @@ use meshtastic::{
 use serde::{Deserialize, Serialize};
 
 use crate::graph::api::update_from_packet::DEFAULT_NODE_TIMEOUT_DURATION;
+use crate::state::DeviceKey;
+
+/// Kind of evidence an edge was created from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum EdgeSource {
+    NodeDb,
+    NeighborInfo,
+    Traceroute,
+    Predicted,
+    Manual,
+}
+
+impl EdgeSource {
+    /// Short tag used in identifiers such as GeoJSON feature ids
+    pub fn tag(&self) -> &'static str {
+        match self {
+            EdgeSource::NodeDb => "node_db",
+            EdgeSource::NeighborInfo => "neighbor_info",
+            EdgeSource::Traceroute => "traceroute",
+            EdgeSource::Predicted => "predicted",
+            EdgeSource::Manual => "manual",
+        }
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, Type)]
 #[serde(rename_all = "camelCase")]
@@ -16,12 +43,30 @@ pub struct GraphEdge {
     snr: f64,
     from: u32,
     to: u32,
+    pub channel: u32, // channel index of the packet the edge was created from
+    #[specta(type = String)]
+    pub channel_name: Arc<str>, // resolved channel name, "channel #N" if unknown, shared between edges
+    pub source: EdgeSource,
     pub last_heard: NaiveDateTime,
     pub timeout_duration: Duration,
+
+    /// Cost of the edge under the graph's `EdgeWeightStrategy`, set as it's added
+    #[serde(default)]
+    weight: f64,
+
+    /// Connected devices whose packets reported the edge, empty for manual edges and
+    /// ones stored before edges were attributed
+    #[serde(default)]
+    pub observed_by: BTreeSet<DeviceKey>,
 }
 
 impl GraphEdge {
-    pub fn from_neighbor(to_node_id: u32, neighbor: Neighbor) -> Self {
+    pub fn from_neighbor(
+        to_node_id: u32,
+        channel: u32,
+        channel_name: Arc<str>,
+        neighbor: Neighbor,
+    ) -> Self {
         let timeout_secs: u64 = if neighbor.node_broadcast_interval_secs == 0 {
             trace!(
                 "Using default edge timeout duration for edge between {} and {}",
@@ -44,8 +89,87 @@ impl GraphEdge {
             snr: neighbor.snr.into(),
             from: neighbor.node_id,
             to: to_node_id,
+            channel,
+            channel_name,
+            source: EdgeSource::NeighborInfo,
             last_heard: chrono::Utc::now().naive_utc(),
             timeout_duration: Duration::from_secs(timeout_secs),
+            weight: 0.0,
+            observed_by: BTreeSet::new(),
+        }
+    }
+
+    /// Creates an edge between consecutive hops of a route discovered by a traceroute.
+    /// The route doesn't carry SNRs, so edges it refreshes keep theirs.
+    pub fn from_traceroute(from: u32, to: u32, channel: u32, channel_name: Arc<str>) -> Self {
+        Self {
+            snr: 0.0,
+            from,
+            to,
+            channel,
+            channel_name,
+            source: EdgeSource::Traceroute,
+            last_heard: chrono::Utc::now().naive_utc(),
+            timeout_duration: DEFAULT_NODE_TIMEOUT_DURATION,
+            weight: 0.0,
+            observed_by: BTreeSet::new(),
+        }
+    }
+
+    /// Creates an edge added by the operator, with `weight` used as its SNR
+    pub fn manual(from: u32, to: u32, weight: f64) -> Self {
+        Self {
+            snr: weight,
+            from,
+            to,
+            channel: 0,
+            channel_name: "manual".into(),
+            source: EdgeSource::Manual,
+            last_heard: chrono::Utc::now().naive_utc(),
+            timeout_duration: DEFAULT_NODE_TIMEOUT_DURATION,
+            weight: 0.0,
+            observed_by: BTreeSet::new(),
         }
     }
 }
+
+impl GraphEdge {
+    /// Replaces the edge's SNR, e.g. with a value derived from link quality history
+    pub fn with_snr(self, snr: f64) -> Self {
+        Self { snr, ..self }
+    }
+
+    /// Attributes the edge to `observers` as well as the devices that reported it
+    pub fn with_observers(mut self, observers: impl IntoIterator<Item = DeviceKey>) -> Self {
+        self.observed_by.extend(observers);
+        self
+    }
+
+    pub fn is_observed_by(&self, device_key: &str) -> bool {
+        self.observed_by.contains(device_key)
+    }
+
+    pub fn set_snr(&mut self, snr: f64) {
+        self.snr = snr;
+    }
+
+    pub fn snr(&self) -> f64 {
+        self.snr
+    }
+
+    pub fn set_weight(&mut self, weight: f64) {
+        self.weight = weight;
+    }
+
+    pub fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    pub fn from(&self) -> u32 {
+        self.from
+    }
+
+    pub fn to(&self) -> u32 {
+        self.to
+    }
+}
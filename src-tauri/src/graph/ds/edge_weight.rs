@@ -0,0 +1,227 @@
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::device::helpers::haversine_distance_meters;
+use crate::graph::route::ROUTE_GOOD_SNR;
+
+use super::edge::GraphEdge;
+
+/// Distance assumed for links with an unpositioned end, in kilometers. Not zero, so
+/// unpositioned nodes don't look like free shortcuts.
+pub const DEFAULT_MISSING_POSITION_KM: f64 = 5.0;
+
+/// Least distance cost of a link, so colocated nodes still cost something to route over
+pub const MIN_DISTANCE_KM: f64 = 0.001;
+
+fn default_missing_position_km() -> f64 {
+    DEFAULT_MISSING_POSITION_KM
+}
+
+/// How the weight (cost) of each edge is computed as edges are added, for analyses
+/// that route over the graph
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", tag = "strategy")]
+pub enum EdgeWeightStrategy {
+    /// 1 for links at or above `ROUTE_GOOD_SNR`, plus 0.1 per dB below it
+    #[default]
+    SnrBased,
+
+    /// 1 for every link
+    HopCount,
+
+    /// Distance between the nodes in kilometers
+    #[serde(rename_all = "camelCase")]
+    GeoDistance {
+        #[serde(default = "default_missing_position_km")]
+        missing_position_km: f64,
+    },
+
+    /// Weighted sum of the SNR-based cost and the distance in kilometers
+    #[serde(rename_all = "camelCase")]
+    Composite {
+        snr_factor: f64,
+        distance_factor: f64,
+        #[serde(default = "default_missing_position_km")]
+        missing_position_km: f64,
+    },
+}
+
+impl EdgeWeightStrategy {
+    pub fn validate(&self) -> Result<(), String> {
+        let (factors, missing_position_km) = match self {
+            EdgeWeightStrategy::SnrBased | EdgeWeightStrategy::HopCount => return Ok(()),
+            EdgeWeightStrategy::GeoDistance {
+                missing_position_km,
+            } => (None, *missing_position_km),
+            EdgeWeightStrategy::Composite {
+                snr_factor,
+                distance_factor,
+                missing_position_km,
+            } => (Some((*snr_factor, *distance_factor)), *missing_position_km),
+        };
+
+        if !missing_position_km.is_finite() || missing_position_km < 0.0 {
+            return Err("Missing position distance must be a positive number".into());
+        }
+
+        if let Some((snr_factor, distance_factor)) = factors {
+            if [snr_factor, distance_factor]
+                .iter()
+                .any(|f| !f.is_finite() || *f < 0.0)
+            {
+                return Err("Composite factors must be positive numbers".into());
+            }
+
+            if snr_factor == 0.0 && distance_factor == 0.0 {
+                return Err("At least one composite factor must be above zero".into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether weights depend on node positions, so need recomputing when nodes move
+    pub fn uses_positions(&self) -> bool {
+        matches!(
+            self,
+            EdgeWeightStrategy::GeoDistance { .. } | EdgeWeightStrategy::Composite { .. }
+        )
+    }
+}
+
+/// Cost of a link from its SNR, as used by `EdgeWeightStrategy::SnrBased`
+pub fn snr_cost(snr: f64) -> f64 {
+    1.0 + (ROUTE_GOOD_SNR - snr).max(0.0) / 10.0
+}
+
+/// Weight of `edge` between nodes at `u_pos` and `v_pos`, each `(latitude, longitude)`
+/// if known
+pub fn compute_edge_weight(
+    strategy: &EdgeWeightStrategy,
+    edge: &GraphEdge,
+    u_pos: Option<(f64, f64)>,
+    v_pos: Option<(f64, f64)>,
+) -> f64 {
+    let distance_km = |missing_position_km: f64| match (u_pos, v_pos) {
+        (Some((u_lat, u_lon)), Some((v_lat, v_lon))) => {
+            (haversine_distance_meters(u_lat, u_lon, v_lat, v_lon) / 1000.0).max(MIN_DISTANCE_KM)
+        }
+        _ => missing_position_km,
+    };
+
+    match strategy {
+        EdgeWeightStrategy::SnrBased => snr_cost(edge.snr()),
+        EdgeWeightStrategy::HopCount => 1.0,
+        EdgeWeightStrategy::GeoDistance {
+            missing_position_km,
+        } => distance_km(*missing_position_km),
+        EdgeWeightStrategy::Composite {
+            snr_factor,
+            distance_factor,
+            missing_position_km,
+        } => {
+            snr_factor * snr_cost(edge.snr()) + distance_factor * distance_km(*missing_position_km)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Eiffel Tower and Arc de Triomphe, about 1.7 km apart
+    const EIFFEL_TOWER: (f64, f64) = (48.8584, 2.2945);
+    const ARC_DE_TRIOMPHE: (f64, f64) = (48.8738, 2.2950);
+
+    fn edge(snr: f64) -> GraphEdge {
+        GraphEdge::manual(1, 2, snr)
+    }
+
+    fn weight(strategy: &EdgeWeightStrategy, snr: f64, positioned: bool) -> f64 {
+        let positions = positioned.then_some((EIFFEL_TOWER, ARC_DE_TRIOMPHE));
+
+        compute_edge_weight(
+            strategy,
+            &edge(snr),
+            positions.map(|p| p.0),
+            positions.map(|p| p.1),
+        )
+    }
+
+    #[test]
+    fn snr_and_hop_count_ignore_positions() {
+        assert_eq!(weight(&EdgeWeightStrategy::SnrBased, 12.0, false), 1.0);
+        assert!((weight(&EdgeWeightStrategy::SnrBased, -4.0, true) - 2.4).abs() < 1e-9);
+
+        assert_eq!(weight(&EdgeWeightStrategy::HopCount, -20.0, false), 1.0);
+        assert_eq!(weight(&EdgeWeightStrategy::HopCount, 12.0, true), 1.0);
+    }
+
+    #[test]
+    fn geo_distance_uses_haversine_kilometers() {
+        let strategy = EdgeWeightStrategy::GeoDistance {
+            missing_position_km: 7.5,
+        };
+
+        let distance = weight(&strategy, 0.0, true);
+        assert!((distance - 1.713).abs() < 0.01, "{}", distance);
+
+        assert_eq!(weight(&strategy, 0.0, false), 7.5);
+        assert_eq!(
+            compute_edge_weight(
+                &strategy,
+                &edge(0.0),
+                Some(EIFFEL_TOWER),
+                Some(EIFFEL_TOWER)
+            ),
+            MIN_DISTANCE_KM
+        );
+    }
+
+    #[test]
+    fn composite_sums_weighted_costs() {
+        let strategy = EdgeWeightStrategy::Composite {
+            snr_factor: 2.0,
+            distance_factor: 0.5,
+            missing_position_km: DEFAULT_MISSING_POSITION_KM,
+        };
+
+        let distance = weight(
+            &EdgeWeightStrategy::GeoDistance {
+                missing_position_km: DEFAULT_MISSING_POSITION_KM,
+            },
+            0.0,
+            true,
+        );
+
+        assert!((weight(&strategy, 0.0, true) - (2.0 * 2.0 + 0.5 * distance)).abs() < 1e-9);
+        assert_eq!(weight(&strategy, 10.0, false), 2.0 + 0.5 * 5.0);
+    }
+
+    #[test]
+    fn validates_strategies() {
+        let geo: EdgeWeightStrategy =
+            serde_json::from_value(serde_json::json!({ "strategy": "geoDistance" })).unwrap();
+        assert_eq!(
+            geo,
+            EdgeWeightStrategy::GeoDistance {
+                missing_position_km: DEFAULT_MISSING_POSITION_KM
+            }
+        );
+        assert!(geo.validate().is_ok());
+
+        let composite = |snr_factor, distance_factor| EdgeWeightStrategy::Composite {
+            snr_factor,
+            distance_factor,
+            missing_position_km: DEFAULT_MISSING_POSITION_KM,
+        };
+        assert!(composite(1.0, 0.0).validate().is_ok());
+        assert!(composite(0.0, 0.0).validate().is_err());
+        assert!(composite(-1.0, 1.0).validate().is_err());
+        assert!(EdgeWeightStrategy::GeoDistance {
+            missing_position_km: f64::NAN
+        }
+        .validate()
+        .is_err());
+    }
+}
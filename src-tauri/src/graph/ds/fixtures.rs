@@ -0,0 +1,34 @@
+//! Nodes and edges for building graphs in tests
+
+use meshtastic::protobufs;
+
+use crate::graph::api::update_from_packet::DEFAULT_NODE_TIMEOUT_DURATION;
+
+use super::edge::GraphEdge;
+use super::node::GraphNode;
+
+/// A node last heard at a fixed time. `GraphNode` hashes every field, so nodes
+/// built for the same graph need the same `last_heard`.
+pub fn graph_node(node_num: u32) -> GraphNode {
+    GraphNode {
+        node_num,
+        last_heard: chrono::DateTime::from_timestamp(1_700_000_000, 0)
+            .unwrap()
+            .naive_utc(),
+        timeout_duration: DEFAULT_NODE_TIMEOUT_DURATION,
+    }
+}
+
+/// A link from `from` to `to` on channel 0, as reported in `to`'s neighbor info
+pub fn edge(from: u32, to: u32, snr: f32) -> GraphEdge {
+    GraphEdge::from_neighbor(
+        to,
+        0,
+        "channel #0".into(),
+        protobufs::Neighbor {
+            node_id: from,
+            snr,
+            ..Default::default()
+        },
+    )
+}
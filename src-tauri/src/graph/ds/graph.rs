@@ -1,14 +1,66 @@
-use std::collections::HashMap;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{hash_map::Entry, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::time::Duration;
 
 use petgraph::graphmap::GraphMap;
+use petgraph::Direction;
 use serde::{Deserialize, Serialize};
 use tauri::async_runtime::JoinHandle;
 
+use crate::state::DeviceKey;
+
 use super::{
     edge,
+    edge_weight::{compute_edge_weight, EdgeWeightStrategy},
+    link_quality::{
+        link_key, LinkQualityAggregate, LinkQualityHistory, LinkQualityReport, LinkQualitySample,
+        LinkSnrMode,
+    },
+    names::NameInterner,
     node::{self, GraphNode},
+    overrides::{GraphOverrides, ManualEdge},
+    position_archive::PositionArchive,
+    spatial_index::SpatialIndex,
 };
 
+/// Path cost ordered by `f64::total_cmp` so it can be used in a `BinaryHeap`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathCost(pub f64);
+
+impl Eq for PathCost {}
+
+impl PartialOrd for PathCost {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PathCost {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Root of the tree a node belongs to, given the parent of each node that has one
+fn tree_root(parents: &mut HashMap<u32, u32>, node_num: u32) -> u32 {
+    let mut root = node_num;
+
+    while let Some(parent) = parents.get(&root) {
+        root = *parent;
+    }
+
+    // Point the nodes along the way straight at the root, keeping later lookups short
+    let mut current = node_num;
+
+    while current != root {
+        let parent = parents[&current];
+        parents.insert(current, root);
+        current = parent;
+    }
+
+    root
+}
+
 pub type InternalGraph = GraphMap<node::GraphNode, edge::GraphEdge, petgraph::Directed>;
 
 #[derive(Serialize, Deserialize)]
@@ -18,6 +70,21 @@ pub struct MeshGraph {
     pub nodes_lookup: HashMap<u32, GraphNode>, // TODO use NodeId -- need to implement serialize and deserialize
     #[serde(skip)]
     pub timeout_handle: Option<JoinHandle<()>>,
+    #[serde(skip)]
+    pub link_quality: HashMap<(u32, u32), LinkQualityHistory>, // keyed by `link_key`
+    #[serde(skip)]
+    pub link_snr_mode: LinkSnrMode,
+    #[serde(skip)]
+    edge_weight_strategy: EdgeWeightStrategy, // set with `set_edge_weight_strategy`, which reweights edges
+    #[serde(skip)]
+    pub edge_max_age: Option<Duration>, // edges use their own timeout without one
+    pub overrides: GraphOverrides,
+    #[serde(skip)]
+    pub spatial_index: SpatialIndex, // kept when nodes time out, like their positions on the map
+    #[serde(skip)]
+    pub position_archive: PositionArchive, // stored positions, for nodes the radio no longer knows
+    #[serde(skip)]
+    pub names: NameInterner,
 }
 
 impl Clone for MeshGraph {
@@ -26,6 +93,14 @@ impl Clone for MeshGraph {
             graph: self.graph.clone(),
             nodes_lookup: self.nodes_lookup.clone(),
             timeout_handle: None,
+            link_quality: self.link_quality.clone(),
+            link_snr_mode: self.link_snr_mode.clone(),
+            edge_weight_strategy: self.edge_weight_strategy.clone(),
+            edge_max_age: self.edge_max_age,
+            overrides: self.overrides.clone(),
+            spatial_index: self.spatial_index.clone(),
+            position_archive: self.position_archive.clone(),
+            names: self.names.clone(),
         }
     }
 }
@@ -36,6 +111,14 @@ impl MeshGraph {
             graph: GraphMap::new(),
             nodes_lookup: HashMap::new(),
             timeout_handle: None,
+            link_quality: HashMap::new(),
+            link_snr_mode: LinkSnrMode::default(),
+            edge_weight_strategy: EdgeWeightStrategy::default(),
+            edge_max_age: None,
+            overrides: GraphOverrides::default(),
+            spatial_index: SpatialIndex::default(),
+            position_archive: PositionArchive::default(),
+            names: NameInterner::default(),
         }
     }
 }
@@ -55,12 +138,402 @@ impl MeshGraph {
         self.nodes_lookup.contains_key(&node_num)
     }
 
+    /// Replaces the node, which drops its edges. Manual edges touching the node are
+    /// added back since they aren't regenerated from packets.
     pub fn upsert_node(&mut self, node: GraphNode) -> GraphNode {
         if self.contains_node(node.node_num) {
             self.remove_node(node.node_num);
         }
 
-        self.add_node(node)
+        let created_node = self.add_node(node);
+        self.apply_manual_edges();
+        created_node
+    }
+
+    /// Replaces the node's record, e.g. when it's heard again, keeping its edges. Only
+    /// the node's own edges are touched.
+    pub fn refresh_node(&mut self, node: GraphNode) -> GraphNode {
+        let previous = match self.get_node(node.node_num) {
+            Some(previous) => previous,
+            None => return self.upsert_node(node),
+        };
+
+        let mut edges: Vec<(GraphNode, GraphNode, edge::GraphEdge)> = vec![];
+
+        for neighbor in self.graph.neighbors_directed(previous, Direction::Outgoing) {
+            if let Some(edge) = self.graph.edge_weight(previous, neighbor) {
+                edges.push((node, neighbor, edge.clone()));
+            }
+        }
+
+        for neighbor in self.graph.neighbors_directed(previous, Direction::Incoming) {
+            if let Some(edge) = self.graph.edge_weight(neighbor, previous) {
+                edges.push((neighbor, node, edge.clone()));
+            }
+        }
+
+        self.remove_node(node.node_num);
+        let created_node = self.add_node(node);
+
+        for (source, target, edge) in edges {
+            self.graph.add_edge(source, target, edge);
+        }
+
+        created_node
+    }
+
+    /// Targets and weights of the edges leaving the node
+    pub fn outgoing_edges(&self, node_num: u32) -> Vec<(u32, &edge::GraphEdge)> {
+        match self.get_node(node_num) {
+            Some(node) => self
+                .graph
+                .edges(node)
+                .map(|(_, target, edge)| (target.node_num, edge))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.graph.node_count()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.graph.edge_count()
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = GraphNode> + '_ {
+        self.graph.nodes()
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = (GraphNode, GraphNode, &edge::GraphEdge)> {
+        self.graph.all_edges()
+    }
+
+    /// Number of edges to or from each node, and the sum of those edges' weights (SNR).
+    /// Edges to or from hidden nodes aren't counted.
+    pub fn node_degrees(&self) -> HashMap<u32, (u32, f64)> {
+        let mut degrees: HashMap<u32, (u32, f64)> = HashMap::new();
+
+        for (from, to, edge) in self.graph.all_edges() {
+            if self.overrides.is_hidden(from.node_num) || self.overrides.is_hidden(to.node_num) {
+                continue;
+            }
+
+            for node_num in [from.node_num, to.node_num] {
+                let (degree, weighted_degree) = degrees.entry(node_num).or_default();
+                *degree += 1;
+                *weighted_degree += edge.snr();
+            }
+        }
+
+        degrees
+    }
+
+    /// Number of groups of nodes that can reach each other, ignoring edge direction
+    pub fn connected_components(&self) -> usize {
+        petgraph::algo::connected_components(&self.graph)
+    }
+
+    /// Node nums of each group of nodes that can reach each other, ignoring edge
+    /// direction, largest group first. A node without edges is a group of its own.
+    /// Hidden nodes are left out, so a mesh only joined through them is split.
+    pub fn component_members(&self) -> Vec<Vec<u32>> {
+        let mut neighbors: HashMap<u32, Vec<u32>> = HashMap::new();
+
+        for (from, to, _) in self.graph.all_edges() {
+            if self.overrides.is_hidden(from.node_num) || self.overrides.is_hidden(to.node_num) {
+                continue;
+            }
+
+            neighbors
+                .entry(from.node_num)
+                .or_default()
+                .push(to.node_num);
+            neighbors
+                .entry(to.node_num)
+                .or_default()
+                .push(from.node_num);
+        }
+
+        // Ordered so that groups come out the same way every time
+        let node_nums: BTreeSet<u32> = self
+            .nodes_lookup
+            .keys()
+            .copied()
+            .filter(|node_num| !self.overrides.is_hidden(*node_num))
+            .collect();
+
+        let mut visited: HashSet<u32> = HashSet::new();
+        let mut components = vec![];
+
+        for &start in &node_nums {
+            if !visited.insert(start) {
+                continue;
+            }
+
+            let mut component = vec![];
+            let mut stack = vec![start];
+
+            while let Some(current) = stack.pop() {
+                component.push(current);
+
+                for &next in neighbors.get(&current).into_iter().flatten() {
+                    if visited.insert(next) {
+                        stack.push(next);
+                    }
+                }
+            }
+
+            component.sort_unstable();
+            components.push(component);
+        }
+
+        components.sort_by_key(|component| Reverse(component.len()));
+        components
+    }
+
+    /// Finds a path with the fewest hops between two nodes, ignoring edge direction
+    /// since radio links are usable both ways. Returns the node nums along the path,
+    /// including both endpoints.
+    pub fn shortest_path(&self, from: u32, to: u32) -> Option<Vec<u32>> {
+        if !self.contains_node(from) || !self.contains_node(to) {
+            return None;
+        }
+
+        let neighbors = self.linked_nodes();
+
+        let mut previous: HashMap<u32, u32> = HashMap::new();
+        let mut queue = VecDeque::from([from]);
+        previous.insert(from, from);
+
+        while let Some(current) = queue.pop_front() {
+            if current == to {
+                let mut path = vec![to];
+                let mut node_num = to;
+
+                while node_num != from {
+                    node_num = previous[&node_num];
+                    path.push(node_num);
+                }
+
+                path.reverse();
+                return Some(path);
+            }
+
+            for &next in neighbors.get(&current).into_iter().flatten() {
+                if let Entry::Vacant(entry) = previous.entry(next) {
+                    entry.insert(current);
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Every node within `max_hops` hops of a node, ignoring edge direction, along with
+    /// its distance in hops. The node itself isn't included. Empty if the node isn't in
+    /// the graph.
+    pub fn neighbors_within(&self, from: u32, max_hops: u32) -> HashMap<u32, u32> {
+        if !self.contains_node(from) {
+            return HashMap::new();
+        }
+
+        let neighbors = self.linked_nodes();
+
+        let mut hops: HashMap<u32, u32> = HashMap::from([(from, 0)]);
+        let mut queue = VecDeque::from([from]);
+
+        while let Some(current) = queue.pop_front() {
+            let next_hops = hops[&current] + 1;
+
+            if next_hops > max_hops {
+                continue;
+            }
+
+            for &next in neighbors.get(&current).into_iter().flatten() {
+                if let Entry::Vacant(entry) = hops.entry(next) {
+                    entry.insert(next_hops);
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        hops.remove(&from);
+        hops
+    }
+
+    /// Nodes linked to each node, ignoring edge direction. Ordered so that ties
+    /// between equal-length paths resolve the same way every time.
+    fn linked_nodes(&self) -> HashMap<u32, BTreeSet<u32>> {
+        let mut neighbors: HashMap<u32, BTreeSet<u32>> = HashMap::new();
+
+        for (a, b, _) in self.graph.all_edges() {
+            neighbors.entry(a.node_num).or_default().insert(b.node_num);
+            neighbors.entry(b.node_num).or_default().insert(a.node_num);
+        }
+
+        neighbors
+    }
+
+    /// Finds the path with the lowest total cost between two nodes, ignoring edge
+    /// direction. Links reported in both directions use the cheaper of the two edges.
+    /// `edge_cost` must be positive. Returns the node nums along the path, including
+    /// both endpoints, and the path's total cost.
+    pub fn cheapest_path(
+        &self,
+        from: u32,
+        to: u32,
+        edge_cost: impl Fn(&edge::GraphEdge) -> f64,
+    ) -> Option<(Vec<u32>, f64)> {
+        if !self.contains_node(from) || !self.contains_node(to) {
+            return None;
+        }
+
+        let neighbors = self.link_costs(edge_cost);
+
+        let mut best: HashMap<u32, f64> = HashMap::from([(from, 0.0)]);
+        let mut previous: HashMap<u32, u32> = HashMap::new();
+        let mut queue = BinaryHeap::from([Reverse((PathCost(0.0), from))]);
+
+        while let Some(Reverse((PathCost(cost), current))) = queue.pop() {
+            if current == to {
+                let mut path = vec![to];
+                let mut node_num = to;
+
+                while node_num != from {
+                    node_num = previous[&node_num];
+                    path.push(node_num);
+                }
+
+                path.reverse();
+                return Some((path, cost));
+            }
+
+            if matches!(best.get(&current), Some(best_cost) if cost > *best_cost) {
+                continue;
+            }
+
+            for &(next, link_cost) in neighbors.get(&current).into_iter().flatten() {
+                let next_cost = cost + link_cost;
+
+                if !matches!(best.get(&next), Some(best_cost) if next_cost >= *best_cost) {
+                    best.insert(next, next_cost);
+                    previous.insert(next, current);
+                    queue.push(Reverse((PathCost(next_cost), next)));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Total cost of the cheapest path from a node to every node reachable from it,
+    /// including the node itself at no cost, measured as `cheapest_path` does.
+    /// Empty if the node isn't in the graph.
+    pub fn path_costs(
+        &self,
+        from: u32,
+        edge_cost: impl Fn(&edge::GraphEdge) -> f64,
+    ) -> HashMap<u32, f64> {
+        if !self.contains_node(from) {
+            return HashMap::new();
+        }
+
+        let neighbors = self.link_costs(edge_cost);
+
+        let mut best: HashMap<u32, f64> = HashMap::from([(from, 0.0)]);
+        let mut queue = BinaryHeap::from([Reverse((PathCost(0.0), from))]);
+
+        while let Some(Reverse((PathCost(cost), current))) = queue.pop() {
+            if matches!(best.get(&current), Some(best_cost) if cost > *best_cost) {
+                continue;
+            }
+
+            for &(next, link_cost) in neighbors.get(&current).into_iter().flatten() {
+                let next_cost = cost + link_cost;
+
+                if !matches!(best.get(&next), Some(best_cost) if next_cost >= *best_cost) {
+                    best.insert(next, next_cost);
+                    queue.push(Reverse((PathCost(next_cost), next)));
+                }
+            }
+        }
+
+        best
+    }
+
+    /// Copy of the graph keeping every node but only the cheapest edges that keep each
+    /// component connected, i.e. a minimum spanning tree of each component. Edges are
+    /// weighed on their own, so only the cheaper edge of a link reported in both
+    /// directions is kept.
+    pub fn minimum_spanning_forest(
+        &self,
+        edge_cost: impl Fn(&edge::GraphEdge) -> f64,
+    ) -> MeshGraph {
+        let mut edges: Vec<(f64, GraphNode, GraphNode)> = self
+            .graph
+            .all_edges()
+            .map(|(from, to, edge)| (edge_cost(edge), from, to))
+            .collect();
+
+        // Ordered so that ties between equal-cost edges resolve the same way every time
+        edges.sort_by(|(a_cost, a_from, a_to), (b_cost, b_from, b_to)| {
+            a_cost
+                .total_cmp(b_cost)
+                .then(a_from.node_num.cmp(&b_from.node_num))
+                .then(a_to.node_num.cmp(&b_to.node_num))
+        });
+
+        // Kruskal's algorithm, keeping the cheapest edges that join two separate trees
+        let mut parents: HashMap<u32, u32> = HashMap::new();
+        let mut forest = self.clone();
+
+        for (_, from, to) in edges {
+            let from_root = tree_root(&mut parents, from.node_num);
+            let to_root = tree_root(&mut parents, to.node_num);
+
+            if from_root == to_root {
+                forest.remove_edge(from, to);
+            } else {
+                parents.insert(from_root, to_root);
+            }
+        }
+
+        forest
+    }
+
+    /// Neighbors of each node along with the cost of the link to them, ignoring edge
+    /// direction. Links reported in both directions cost the cheaper of the two edges.
+    pub fn link_costs(
+        &self,
+        edge_cost: impl Fn(&edge::GraphEdge) -> f64,
+    ) -> HashMap<u32, Vec<(u32, f64)>> {
+        let mut link_costs: HashMap<(u32, u32), f64> = HashMap::new();
+
+        for (a, b, edge) in self.graph.all_edges() {
+            let cost = edge_cost(edge);
+            let link_cost = link_costs
+                .entry(link_key(a.node_num, b.node_num))
+                .or_insert(cost);
+
+            *link_cost = link_cost.min(cost);
+        }
+
+        let mut neighbors: HashMap<u32, Vec<(u32, f64)>> = HashMap::new();
+
+        for ((a, b), cost) in link_costs {
+            neighbors.entry(a).or_default().push((b, cost));
+            neighbors.entry(b).or_default().push((a, cost));
+        }
+
+        // Ordered so that ties between equal-cost paths resolve the same way every time
+        for links in neighbors.values_mut() {
+            links.sort_by_key(|(node_num, _)| *node_num);
+        }
+
+        neighbors
     }
 
     pub fn remove_node(&mut self, node_num: u32) -> Option<GraphNode> {
@@ -76,28 +549,364 @@ impl MeshGraph {
 }
 
 impl MeshGraph {
+    /// Adds the edge, replacing any edge between the same nodes in the same direction.
+    /// Its channel name is shared with the other edges on that channel.
     pub fn upsert_edge(
         &mut self,
         source: GraphNode,
         target: GraphNode,
-        edge: edge::GraphEdge,
+        mut edge: edge::GraphEdge,
     ) -> Option<edge::GraphEdge> {
+        if edge.source != edge::EdgeSource::Manual
+            && self
+                .overrides
+                .manual_edge(source.node_num, target.node_num)
+                .is_some()
+        {
+            return None;
+        }
+
         if self.graph.contains_edge(source, target) {
             self.remove_edge(source, target); // Remove the edge if it exists
         }
 
+        edge.channel_name = self.names.intern(&edge.channel_name);
+        edge.set_weight(self.compute_weight(source.node_num, target.node_num, &edge));
+
         self.graph.add_edge(source, target, edge)
     }
 
+    pub fn get_edge(&self, from: GraphNode, to: GraphNode) -> Option<&edge::GraphEdge> {
+        self.graph.edge_weight(from, to)
+    }
+
     pub fn remove_edge(&mut self, from: GraphNode, to: GraphNode) -> Option<edge::GraphEdge> {
         self.graph.remove_edge(from, to)
     }
+
+    /// Devices that observed the edge from `from` to `to`, none if there's no such edge
+    pub fn edge_observers(&self, from: u32, to: u32) -> Vec<DeviceKey> {
+        match (self.get_node(from), self.get_node(to)) {
+            (Some(from), Some(to)) => self
+                .get_edge(from, to)
+                .map(|edge| edge.observed_by.iter().cloned().collect())
+                .unwrap_or_default(),
+            _ => vec![],
+        }
+    }
+
+    /// Updates the SNR of the edge between two nodes, if one exists
+    pub fn set_edge_snr(&mut self, from: u32, to: u32, snr: f64) {
+        let (from, to) = match (self.get_node(from), self.get_node(to)) {
+            (Some(f), Some(t)) => (f, t),
+            _ => return,
+        };
+
+        let from_position = self.spatial_index.position(from.node_num);
+        let to_position = self.spatial_index.position(to.node_num);
+
+        if let Some(edge) = self.graph.edge_weight_mut(from, to) {
+            edge.set_snr(snr);
+            edge.set_weight(compute_edge_weight(
+                &self.edge_weight_strategy,
+                edge,
+                from_position,
+                to_position,
+            ));
+        }
+    }
+
+    pub fn edge_weight_strategy(&self) -> &EdgeWeightStrategy {
+        &self.edge_weight_strategy
+    }
+
+    /// Changes how edges are weighted, reweighting the existing ones in place
+    pub fn set_edge_weight_strategy(&mut self, strategy: EdgeWeightStrategy) {
+        self.edge_weight_strategy = strategy;
+        self.reweight_edges(|_, _| true);
+    }
+
+    /// Weight of an edge between two nodes under the edge weight strategy
+    fn compute_weight(&self, from: u32, to: u32, edge: &edge::GraphEdge) -> f64 {
+        compute_edge_weight(
+            &self.edge_weight_strategy,
+            edge,
+            self.spatial_index.position(from),
+            self.spatial_index.position(to),
+        )
+    }
+
+    /// Recomputes the weights of the edges between the nodes `include` accepts
+    fn reweight_edges(&mut self, include: impl Fn(u32, u32) -> bool) {
+        let weights: Vec<_> = self
+            .graph
+            .all_edges()
+            .filter(|(from, to, _)| include(from.node_num, to.node_num))
+            .map(|(from, to, edge)| {
+                (
+                    from,
+                    to,
+                    self.compute_weight(from.node_num, to.node_num, edge),
+                )
+            })
+            .collect();
+
+        for (from, to, weight) in weights {
+            if let Some(edge) = self.graph.edge_weight_mut(from, to) {
+                edge.set_weight(weight);
+            }
+        }
+    }
+}
+
+impl MeshGraph {
+    /// Records a link quality sample between two nodes, returning the
+    /// link's current SNR according to the link SNR mode.
+    pub fn record_link_sample(
+        &mut self,
+        node_a: u32,
+        node_b: u32,
+        sample: LinkQualitySample,
+    ) -> Option<f32> {
+        let now = sample.timestamp;
+        let history = self
+            .link_quality
+            .entry(link_key(node_a, node_b))
+            .or_default();
+
+        history.push(sample);
+        history.snr(&self.link_snr_mode, now)
+    }
+
+    /// Returns the link quality samples between two nodes received at or after `since`,
+    /// only those that came from `observer`'s packets if given
+    pub fn get_link_quality_report(
+        &self,
+        node_a: u32,
+        node_b: u32,
+        since: u32,
+        observer: Option<&str>,
+    ) -> LinkQualityReport {
+        let mut samples = self
+            .link_quality
+            .get(&link_key(node_a, node_b))
+            .map(|history| history.window(since))
+            .unwrap_or_default();
+
+        if let Some(observer) = observer {
+            samples.retain(|sample| sample.observer == observer);
+        }
+
+        LinkQualityReport {
+            node_a,
+            node_b,
+            aggregate: LinkQualityAggregate::from_samples(&samples),
+            samples,
+        }
+    }
 }
 
 impl MeshGraph {
-    pub fn clean(&mut self) {
+    /// Returns a copy of the graph containing only edges created from packets on `channel`.
+    /// Nodes are kept so that positions remain available regardless of channel.
+    pub fn filter_by_channel(&self, channel: u32) -> MeshGraph {
+        let mut filtered = self.clone();
+
+        let edges_to_remove: Vec<(GraphNode, GraphNode)> = self
+            .graph
+            .all_edges()
+            .filter(|(_, _, edge)| edge.channel != channel)
+            .map(|(from, to, _)| (from, to))
+            .collect();
+
+        for (from, to) in edges_to_remove {
+            filtered.remove_edge(from, to);
+        }
+
+        filtered
+    }
+}
+
+impl MeshGraph {
+    /// Returns a copy of the graph containing only edges `device_key` observed, like
+    /// `filter_by_channel`
+    pub fn filter_by_observer(&self, device_key: &str) -> MeshGraph {
+        let mut filtered = self.clone();
+
+        let edges_to_remove: Vec<(GraphNode, GraphNode)> = self
+            .graph
+            .all_edges()
+            .filter(|(_, _, edge)| !edge.is_observed_by(device_key))
+            .map(|(from, to, _)| (from, to))
+            .collect();
+
+        for (from, to) in edges_to_remove {
+            filtered.remove_edge(from, to);
+        }
+
+        filtered
+    }
+}
+
+impl MeshGraph {
+    /// Adds the operator's manual edges whose endpoints are both in the graph. Edges
+    /// to nodes that aren't in the graph are added once those nodes are heard.
+    fn apply_manual_edges(&mut self) {
+        for manual_edge in self.overrides.manual_edges.clone() {
+            let (from, to) = match (
+                self.get_node(manual_edge.from),
+                self.get_node(manual_edge.to),
+            ) {
+                (Some(f), Some(t)) => (f, t),
+                _ => continue,
+            };
+
+            self.upsert_edge(
+                from,
+                to,
+                edge::GraphEdge::manual(manual_edge.from, manual_edge.to, manual_edge.weight),
+            );
+        }
+    }
+
+    pub fn add_manual_edge(&mut self, manual_edge: ManualEdge) {
+        self.overrides.add_manual_edge(manual_edge);
+        self.apply_manual_edges();
+    }
+
+    /// Removes a manual edge and the edge it placed in the graph. A packet-derived
+    /// edge between the same nodes will be added again when it's next heard.
+    pub fn remove_manual_edge(&mut self, from: u32, to: u32) -> Option<ManualEdge> {
+        let removed = self.overrides.remove_manual_edge(from, to)?;
+
+        if let (Some(from), Some(to)) = (self.get_node(from), self.get_node(to)) {
+            self.remove_edge(from, to);
+        }
+
+        Some(removed)
+    }
+
+    pub fn set_node_hidden(&mut self, node_num: u32, hidden: bool) {
+        self.overrides.set_node_hidden(node_num, hidden);
+    }
+
+    pub fn set_node_label(&mut self, node_num: u32, label: Option<String>) {
+        self.overrides.set_node_label(node_num, label);
+    }
+
+    /// Name to show for a node: the operator's label if set, otherwise `long_name`
+    /// if it isn't empty
+    pub fn display_label(&self, node_num: u32, long_name: Option<&str>) -> Option<String> {
+        self.overrides
+            .node_label(node_num)
+            .or(long_name.filter(|name| !name.is_empty()))
+            .map(String::from)
+    }
+
+    /// Replaces all overrides, e.g. with ones loaded from disk
+    pub fn set_overrides(&mut self, overrides: GraphOverrides) {
+        self.overrides = overrides;
+        self.apply_manual_edges();
+    }
+
+    /// Returns a copy of the graph without the operator's hidden nodes or their edges
+    pub fn without_hidden_nodes(&self) -> MeshGraph {
+        let mut filtered = self.clone();
+
+        for node_num in &self.overrides.hidden_nodes {
+            filtered.remove_node(*node_num);
+        }
+
+        filtered
+    }
+}
+
+impl MeshGraph {
+    /// Records the last known position of a node, ignoring unset (zero) coordinates
+    pub fn set_node_position(&mut self, node_num: u32, latitude: f64, longitude: f64) {
+        if latitude == 0.0 && longitude == 0.0 {
+            return;
+        }
+
+        let previous = self.spatial_index.update(node_num, latitude, longitude);
+
+        if self.edge_weight_strategy.uses_positions() && previous != Some((latitude, longitude)) {
+            self.reweight_edges(|from, to| from == node_num || to == node_num);
+        }
+    }
+
+    /// Removes every node, edge, position and link quality sample except `keep_node`. The
+    /// operator's overrides are kept, and manual edges come back as their nodes are heard.
+    pub fn clear(&mut self, keep_node: Option<u32>) {
+        let kept = keep_node.and_then(|node_num| self.get_node(node_num));
+
+        self.graph = GraphMap::new();
+        self.nodes_lookup.clear();
+        self.link_quality.clear();
+
+        let kept_position = keep_node.and_then(|node_num| self.spatial_index.position(node_num));
+        self.spatial_index.clear();
+
+        if let (Some(node_num), Some((latitude, longitude))) = (keep_node, kept_position) {
+            self.set_node_position(node_num, latitude, longitude);
+        }
+
+        if let Some(node) = kept {
+            self.add_node(node);
+            self.apply_manual_edges();
+        }
+    }
+
+    /// Removes everything, including the operator's overrides. The position archive is
+    /// kept, as it's cleared along with the graph database.
+    pub fn purge(&mut self) {
+        self.overrides = GraphOverrides::default();
+        self.clear(None);
+    }
+
+    pub fn clear_link_quality(&mut self) {
+        self.link_quality.clear();
+    }
+
+    /// Removes the edges that haven't been heard within `edge_max_age`, or their own
+    /// timeout, returning the pairs of nodes they linked. Nodes are kept even without
+    /// edges, and the operator's manual edges never go stale.
+    pub fn prune_stale_edges(&mut self, now: chrono::NaiveDateTime) -> Vec<(u32, u32)> {
+        let stale: Vec<(GraphNode, GraphNode)> = self
+            .graph
+            .all_edges()
+            .filter(|(_, _, edge)| edge.source != edge::EdgeSource::Manual)
+            .filter(|(_, _, edge)| {
+                let max_age = self.edge_max_age.unwrap_or(edge.timeout_duration);
+
+                chrono::TimeDelta::from_std(max_age)
+                    .map(|max_age| now - edge.last_heard > max_age)
+                    .unwrap_or(false)
+            })
+            .map(|(from, to, _)| (from, to))
+            .collect();
+
+        for (from, to) in &stale {
+            log::trace!(
+                "Edge from {} to {} has timed out",
+                from.node_num,
+                to.node_num
+            );
+            self.remove_edge(*from, *to);
+        }
+
+        stale
+            .into_iter()
+            .map(|(from, to)| (from.node_num, to.node_num))
+            .collect()
+    }
+
+    /// Removes stale edges and nodes that have timed out, returning how many were removed
+    pub fn clean(&mut self) -> usize {
         let now = chrono::Utc::now().naive_utc();
 
+        let pruned_edges = self.prune_stale_edges(now).len();
+
         // Edges will be removed if either the source or target node is removed
         let mut nodes_to_remove = vec![];
 
@@ -113,9 +922,490 @@ impl MeshGraph {
             }
         }
 
+        let removed_nodes = nodes_to_remove.len();
+
         for node_num in nodes_to_remove {
             self.remove_node(node_num);
             log::debug!("Node {} removed from graph", node_num);
         }
+
+        pruned_edges + removed_nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs::Neighbor;
+
+    use super::*;
+    use crate::graph::api::update_from_packet::DEFAULT_NODE_TIMEOUT_DURATION;
+    use crate::graph::ds::fixtures::graph_node;
+
+    fn edge(from: u32, to: u32, channel: u32) -> edge::GraphEdge {
+        edge::GraphEdge::from_neighbor(
+            to,
+            channel,
+            format!("channel #{}", channel).into(),
+            Neighbor {
+                node_id: from,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn lookup_stays_in_sync_with_the_graph() {
+        let mut graph = MeshGraph::new();
+
+        for node_num in 1..=4 {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        graph.upsert_edge(graph_node(1), graph_node(2), edge(1, 2, 0));
+        graph.remove_node(3);
+
+        // Heard again, so the record changes while its edge stays
+        let refreshed = GraphNode {
+            timeout_duration: DEFAULT_NODE_TIMEOUT_DURATION * 2,
+            ..graph_node(2)
+        };
+        graph.refresh_node(refreshed);
+        graph.upsert_node(graph_node(3));
+        graph.remove_node(4);
+
+        let mut in_graph: Vec<GraphNode> = graph.nodes().collect();
+        in_graph.sort_by_key(|n| n.node_num);
+
+        let mut in_lookup: Vec<GraphNode> = graph.nodes_lookup.values().copied().collect();
+        in_lookup.sort_by_key(|n| n.node_num);
+
+        assert_eq!(in_graph, in_lookup);
+        assert_eq!(
+            in_graph.iter().map(|n| n.node_num).collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+        assert_eq!(
+            graph.get_node(2).unwrap().timeout_duration,
+            refreshed.timeout_duration
+        );
+        assert_eq!(graph.edge_count(), 1);
+        assert!(graph.remove_node(4).is_none());
+    }
+
+    #[test]
+    fn filters_edges_by_channel() {
+        let mut graph = MeshGraph::new();
+        graph.upsert_node(graph_node(1));
+        graph.upsert_node(graph_node(2));
+        graph.upsert_node(graph_node(3));
+
+        graph.upsert_edge(graph_node(1), graph_node(2), edge(1, 2, 0));
+        graph.upsert_edge(graph_node(2), graph_node(3), edge(2, 3, 1));
+
+        let filtered = graph.filter_by_channel(1);
+
+        assert_eq!(filtered.nodes_lookup.len(), 3);
+        assert_eq!(filtered.graph.edge_count(), 1);
+        assert!(filtered.graph.contains_edge(graph_node(2), graph_node(3)));
+    }
+
+    #[test]
+    fn repeated_observations_keep_one_edge_per_direction() {
+        let mut graph = MeshGraph::new();
+        let (a, b) = (graph_node(1), graph_node(2));
+        graph.upsert_node(a);
+        graph.upsert_node(b);
+
+        // A chatty pair heard through every kind of evidence on several channels
+        for observation in 0..500 {
+            let source = match observation % 3 {
+                0 => edge::EdgeSource::NodeDb,
+                1 => edge::EdgeSource::NeighborInfo,
+                _ => edge::EdgeSource::Traceroute,
+            };
+            let snr = (observation % 20) as f64 - 10.0;
+
+            let mut forward = edge(1, 2, observation % 4).with_snr(snr);
+            forward.source = source;
+            graph.upsert_edge(a, b, forward);
+
+            graph.upsert_edge(b, a, edge(2, 1, observation % 4).with_snr(snr / 2.0));
+        }
+
+        // Each direction holds only its latest observation
+        assert_eq!(graph.edge_count(), 2);
+
+        let latest = graph.get_edge(a, b).unwrap();
+        assert_eq!(latest.snr(), 9.0);
+        assert_eq!(latest.channel, 3);
+        assert_eq!(latest.source, edge::EdgeSource::NeighborInfo);
+
+        let degrees = graph.node_degrees();
+        assert_eq!(degrees[&1], (2, 13.5));
+        assert_eq!(degrees[&2], (2, 13.5));
+    }
+
+    #[test]
+    fn finds_fewest_hop_path_in_either_direction() {
+        let mut graph = MeshGraph::new();
+
+        let nodes: Vec<GraphNode> = (1..=5).map(graph_node).collect();
+
+        for n in &nodes {
+            graph.upsert_node(*n);
+        }
+
+        // 1 -> 2 -> 3 -> 4, with a shortcut 4 -> 1 reported in the other direction
+        graph.upsert_edge(nodes[0], nodes[1], edge(1, 2, 0));
+        graph.upsert_edge(nodes[1], nodes[2], edge(2, 3, 0));
+        graph.upsert_edge(nodes[2], nodes[3], edge(3, 4, 0));
+        graph.upsert_edge(nodes[3], nodes[0], edge(4, 1, 0));
+
+        assert_eq!(graph.shortest_path(1, 4), Some(vec![1, 4]));
+        assert_eq!(graph.shortest_path(2, 4), Some(vec![2, 1, 4]));
+        assert_eq!(graph.shortest_path(3, 3), Some(vec![3]));
+        assert_eq!(graph.shortest_path(1, 5), None);
+        assert_eq!(graph.shortest_path(1, 42), None);
+    }
+
+    #[test]
+    fn finds_cheapest_path_by_edge_cost() {
+        let mut graph = MeshGraph::new();
+
+        let nodes: Vec<GraphNode> = (1..=4).map(graph_node).collect();
+
+        for n in &nodes {
+            graph.upsert_node(*n);
+        }
+
+        let snr_edge = |from: u32, to: u32, snr: f64| edge(from, to, 0).with_snr(snr);
+
+        // Direct link 1 - 4 is weak, the detour through 2 and 3 is strong
+        graph.upsert_edge(nodes[0], nodes[3], snr_edge(1, 4, -20.0));
+        graph.upsert_edge(nodes[0], nodes[1], snr_edge(1, 2, 8.0));
+        graph.upsert_edge(nodes[1], nodes[2], snr_edge(2, 3, 8.0));
+        graph.upsert_edge(nodes[3], nodes[2], snr_edge(4, 3, 8.0));
+
+        let cost = |edge: &edge::GraphEdge| 1.0 + (10.0 - edge.snr()).max(0.0) / 10.0;
+
+        let (path, total) = graph.cheapest_path(1, 4, cost).unwrap();
+        assert_eq!(path, vec![1, 2, 3, 4]);
+        assert!((total - 3.6).abs() < 1e-9);
+
+        let (path, total) = graph.cheapest_path(1, 4, |_| 1.0).unwrap();
+        assert_eq!(path, vec![1, 4]);
+        assert_eq!(total, 1.0);
+    }
+
+    #[test]
+    fn finds_neighbors_within_hops_along_a_path() {
+        let mut graph = MeshGraph::new();
+
+        let nodes: Vec<GraphNode> = (1..=6).map(graph_node).collect();
+
+        for n in &nodes {
+            graph.upsert_node(*n);
+        }
+
+        // A path 1 - 2 - 3 - 4 - 5 with the link between 2 and 3 reported both ways,
+        // and node 6 on its own
+        graph.upsert_edge(nodes[0], nodes[1], edge(1, 2, 0));
+        graph.upsert_edge(nodes[1], nodes[2], edge(2, 3, 0));
+        graph.upsert_edge(nodes[2], nodes[1], edge(3, 2, 0));
+        graph.upsert_edge(nodes[3], nodes[2], edge(4, 3, 0));
+        graph.upsert_edge(nodes[3], nodes[4], edge(4, 5, 0));
+
+        assert!(graph.neighbors_within(1, 0).is_empty());
+        assert_eq!(graph.neighbors_within(1, 1), HashMap::from([(2, 1)]));
+        assert_eq!(
+            graph.neighbors_within(3, 1),
+            HashMap::from([(2, 1), (4, 1)])
+        );
+        assert_eq!(
+            graph.neighbors_within(1, 2),
+            HashMap::from([(2, 1), (3, 2)])
+        );
+
+        let whole_path = HashMap::from([(2, 1), (3, 2), (4, 3), (5, 4)]);
+        assert_eq!(graph.neighbors_within(1, 4), whole_path);
+        assert_eq!(graph.neighbors_within(1, 100), whole_path);
+
+        assert!(graph.neighbors_within(6, 4).is_empty());
+        assert!(graph.neighbors_within(42, 4).is_empty());
+    }
+
+    #[test]
+    fn path_costs_follow_the_cheaper_route_around_a_diamond() {
+        let mut graph = MeshGraph::new();
+
+        let nodes: Vec<GraphNode> = (1..=5).map(graph_node).collect();
+
+        for n in &nodes {
+            graph.upsert_node(*n);
+        }
+
+        let snr_edge = |from: u32, to: u32, snr: f64| edge(from, to, 0).with_snr(snr);
+
+        // 1 reaches 4 directly over a link costing 10, or through 2 or 3 for 2 or 5.
+        // Node 5 is in the graph but has no links.
+        graph.upsert_edge(nodes[0], nodes[3], snr_edge(1, 4, 10.0));
+        graph.upsert_edge(nodes[0], nodes[1], snr_edge(1, 2, 1.0));
+        graph.upsert_edge(nodes[1], nodes[3], snr_edge(2, 4, 1.0));
+        graph.upsert_edge(nodes[0], nodes[2], snr_edge(1, 3, 2.0));
+        graph.upsert_edge(nodes[2], nodes[3], snr_edge(3, 4, 3.0));
+
+        // The same link reported the other way round at a higher cost is ignored
+        graph.upsert_edge(nodes[3], nodes[1], snr_edge(4, 2, 6.0));
+
+        let cost = |edge: &edge::GraphEdge| edge.snr();
+
+        let (path, total) = graph.cheapest_path(1, 4, cost).unwrap();
+        assert_eq!(path, vec![1, 2, 4]);
+        assert_eq!(total, 2.0);
+
+        assert_eq!(
+            graph.path_costs(1, cost),
+            HashMap::from([(1, 0.0), (2, 1.0), (3, 2.0), (4, 2.0)])
+        );
+
+        assert_eq!(graph.cheapest_path(1, 1, cost), Some((vec![1], 0.0)));
+        assert_eq!(graph.cheapest_path(1, 5, cost), None);
+        assert_eq!(graph.path_costs(5, cost), HashMap::from([(5, 0.0)]));
+        assert!(graph.path_costs(42, cost).is_empty());
+    }
+
+    #[test]
+    fn minimum_spanning_forest_keeps_the_cheapest_edges_of_each_component() {
+        let mut graph = MeshGraph::new();
+
+        let nodes: Vec<GraphNode> = (1..=7).map(graph_node).collect();
+
+        for n in &nodes {
+            graph.upsert_node(*n);
+        }
+
+        let snr_edge = |from: u32, to: u32, snr: f64| edge(from, to, 0).with_snr(snr);
+
+        // Nodes 1 to 4 are linked in a loop with two chords, nodes 5 and 6 are linked
+        // to each other and node 7 has no links
+        graph.upsert_edge(nodes[0], nodes[1], snr_edge(1, 2, 1.0));
+        graph.upsert_edge(nodes[1], nodes[2], snr_edge(2, 3, 2.0));
+        graph.upsert_edge(nodes[0], nodes[2], snr_edge(1, 3, 3.0));
+        graph.upsert_edge(nodes[2], nodes[3], snr_edge(3, 4, 4.0));
+        graph.upsert_edge(nodes[1], nodes[3], snr_edge(2, 4, 5.0));
+        graph.upsert_edge(nodes[4], nodes[5], snr_edge(5, 6, 2.0));
+
+        // The same link reported the other way round at a lower cost is the edge kept
+        graph.upsert_edge(nodes[3], nodes[2], snr_edge(4, 3, 0.5));
+
+        let forest = graph.minimum_spanning_forest(|edge| edge.snr());
+
+        let mut kept: Vec<(u32, u32)> = forest
+            .edges()
+            .map(|(from, to, _)| (from.node_num, to.node_num))
+            .collect();
+        kept.sort();
+
+        // One edge fewer than there are nodes, in each of the 3 components
+        assert_eq!(kept, vec![(1, 2), (2, 3), (4, 3), (5, 6)]);
+        assert_eq!(kept.len(), 7 - 3);
+        assert_eq!(
+            forest.edges().map(|(_, _, edge)| edge.snr()).sum::<f64>(),
+            5.5
+        );
+
+        assert_eq!(forest.nodes_lookup.len(), 7);
+        assert_eq!(forest.node_degrees()[&2], (2, 3.0));
+        assert!(!forest.node_degrees().contains_key(&7));
+    }
+
+    #[test]
+    fn prunes_edges_not_heard_within_their_max_age() {
+        let mut graph = MeshGraph::new();
+        let nodes: Vec<GraphNode> = (1..=3).map(graph_node).collect();
+
+        for n in &nodes {
+            graph.upsert_node(*n);
+        }
+
+        graph.upsert_edge(nodes[0], nodes[1], edge(1, 2, 0));
+        graph.upsert_edge(nodes[1], nodes[2], edge(2, 3, 0));
+        graph.add_manual_edge(ManualEdge {
+            from: 3,
+            to: 1,
+            weight: 1.0,
+        });
+
+        let now = chrono::Utc::now().naive_utc();
+        let later = now + chrono::TimeDelta::minutes(10);
+
+        // Neighbor edges default to timing out after 15 minutes
+        assert!(graph.prune_stale_edges(later).is_empty());
+
+        graph.edge_max_age = Some(Duration::from_secs(5 * 60));
+
+        // Hearing the edge from 2 to 3 again restarts its age
+        let mut refreshed = edge(2, 3, 0);
+        refreshed.last_heard = now + chrono::TimeDelta::minutes(8);
+        graph.upsert_edge(nodes[1], nodes[2], refreshed);
+
+        assert_eq!(graph.prune_stale_edges(later), vec![(1, 2)]);
+        assert_eq!(graph.edge_count(), 2);
+
+        let much_later = now + chrono::TimeDelta::hours(1);
+        assert_eq!(graph.prune_stale_edges(much_later), vec![(2, 3)]);
+
+        // Node 2 is kept without any edges, and the manual edge is never pruned
+        assert_eq!(graph.nodes_lookup.len(), 3);
+        assert_eq!(
+            graph.get_edge(nodes[2], nodes[0]).unwrap().source,
+            edge::EdgeSource::Manual
+        );
+    }
+
+    #[test]
+    fn manual_edge_survives_regeneration() {
+        let mut graph = MeshGraph::new();
+        // Heard just now, so cleaning the graph keeps them
+        let heard = |node_num| GraphNode {
+            last_heard: chrono::Utc::now().naive_utc(),
+            ..graph_node(node_num)
+        };
+        let (a, b) = (heard(1), heard(2));
+        graph.upsert_node(a);
+        graph.upsert_node(b);
+
+        graph.add_manual_edge(ManualEdge {
+            from: 1,
+            to: 2,
+            weight: 7.5,
+        });
+
+        // Packets re-add both nodes, dropping their edges, and report a link of their own
+        graph.upsert_node(a);
+        graph.upsert_node(b);
+        graph.upsert_edge(a, b, edge(1, 2, 0));
+        graph.clean();
+
+        let manual = graph.get_edge(a, b).unwrap();
+
+        assert_eq!(graph.edge_count(), 1);
+        assert_eq!(manual.source, edge::EdgeSource::Manual);
+        assert_eq!(manual.snr(), 7.5);
+
+        graph.remove_manual_edge(1, 2);
+        assert_eq!(graph.edge_count(), 0);
+    }
+
+    #[test]
+    fn applies_loaded_overrides_once_nodes_are_heard() {
+        let mut graph = MeshGraph::new();
+
+        graph.set_overrides(GraphOverrides {
+            manual_edges: vec![ManualEdge {
+                from: 1,
+                to: 2,
+                weight: 1.0,
+            }],
+            hidden_nodes: vec![3],
+        });
+        assert_eq!(graph.edge_count(), 0);
+
+        let nodes: Vec<GraphNode> = (1..=3).map(graph_node).collect();
+
+        for n in &nodes {
+            graph.upsert_node(*n);
+        }
+
+        graph.upsert_edge(nodes[2], nodes[0], edge(3, 1, 0));
+
+        assert_eq!(graph.edge_count(), 2);
+
+        let visible = graph.without_hidden_nodes();
+        assert_eq!(visible.edge_count(), 1);
+        assert!(!visible.contains_node(3));
+        assert!(graph.contains_node(3));
+        assert!(!graph.node_degrees().contains_key(&3));
+    }
+
+    #[test]
+    fn clearing_keeps_own_node_and_overrides() {
+        let mut graph = MeshGraph::new();
+        let nodes: Vec<GraphNode> = (1..=3).map(graph_node).collect();
+
+        for n in &nodes {
+            graph.upsert_node(*n);
+        }
+
+        graph.upsert_edge(nodes[0], nodes[1], edge(1, 2, 0));
+        graph.add_manual_edge(ManualEdge {
+            from: 1,
+            to: 3,
+            weight: 5.0,
+        });
+        graph.set_node_label(2, Some("Barn".into()));
+        graph.record_link_sample(
+            1,
+            2,
+            LinkQualitySample {
+                timestamp: 0,
+                snr: 5.0,
+                rssi: None,
+                observer: "mock".into(),
+            },
+        );
+
+        graph.clear(Some(1));
+
+        assert_eq!(graph.nodes().map(|n| n.node_num).collect::<Vec<_>>(), [1]);
+        assert_eq!(graph.edge_count(), 0);
+        assert!(graph.link_quality.is_empty());
+        assert_eq!(graph.overrides.manual_edges.len(), 1);
+        assert_eq!(graph.overrides.node_label(2), Some("Barn"));
+
+        // The manual edge returns once the other node is heard again
+        graph.upsert_node(nodes[2]);
+        assert_eq!(graph.edge_count(), 1);
+
+        graph.purge();
+
+        assert_eq!(graph.node_count(), 0);
+        assert!(graph.overrides.manual_edges.is_empty());
+        assert_eq!(graph.overrides.node_label(2), None);
+    }
+
+    #[test]
+    fn changing_the_edge_weight_strategy_reweights_edges() {
+        let mut graph = MeshGraph::new();
+        graph.upsert_node(graph_node(1));
+        graph.upsert_node(graph_node(2));
+        graph.upsert_edge(graph_node(1), graph_node(2), edge(1, 2, 0));
+
+        let weight = |graph: &MeshGraph| {
+            graph
+                .get_edge(graph_node(1), graph_node(2))
+                .unwrap()
+                .weight()
+        };
+
+        // Heard at 0 dB, 10 dB short of a good link
+        assert_eq!(weight(&graph), 2.0);
+
+        graph.set_edge_weight_strategy(EdgeWeightStrategy::HopCount);
+        assert_eq!(weight(&graph), 1.0);
+
+        graph.set_edge_weight_strategy(EdgeWeightStrategy::GeoDistance {
+            missing_position_km: 5.0,
+        });
+        assert_eq!(weight(&graph), 5.0);
+
+        // Positions heard later reweight the node's edges, about 11.1 km apart
+        graph.set_node_position(1, 47.0, 8.0);
+        graph.set_node_position(2, 47.1, 8.0);
+        assert!((weight(&graph) - 11.12).abs() < 0.01, "{}", weight(&graph));
+
+        graph.set_edge_snr(1, 2, 10.0);
+        graph.set_edge_weight_strategy(EdgeWeightStrategy::SnrBased);
+        assert_eq!(weight(&graph), 1.0);
     }
 }
@@ -0,0 +1,235 @@
+use std::collections::VecDeque;
+
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::state::DeviceKey;
+
+/// Maximum number of samples kept for each link
+pub const LINK_QUALITY_HISTORY_CAPACITY: usize = 256;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkQualitySample {
+    pub timestamp: u32, // secs
+    pub snr: f32,
+    pub rssi: Option<i32>, // not reported for links heard through neighbor info
+    pub observer: DeviceKey, // device whose packets the sample came from
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkQualityAggregate {
+    pub sample_count: u32,
+    pub min_snr: f32,
+    pub mean_snr: f32,
+    pub last_snr: f32,
+    pub min_rssi: Option<i32>,
+    pub mean_rssi: Option<f32>,
+    pub last_rssi: Option<i32>,
+}
+
+impl LinkQualityAggregate {
+    /// Aggregates samples in chronological order in a single pass, returning `None`
+    /// if there are none
+    pub fn from_samples<'a>(
+        samples: impl IntoIterator<Item = &'a LinkQualitySample>,
+    ) -> Option<Self> {
+        let mut sample_count = 0;
+        let mut min_snr = f32::INFINITY;
+        let mut snr_sum = 0.0;
+        let mut last_snr = 0.0;
+        let mut rssi_count = 0;
+        let mut rssi_sum = 0;
+        let mut min_rssi = None;
+        let mut last_rssi = None;
+
+        for sample in samples {
+            sample_count += 1;
+            min_snr = min_snr.min(sample.snr);
+            snr_sum += sample.snr;
+            last_snr = sample.snr;
+
+            if let Some(rssi) = sample.rssi {
+                rssi_count += 1;
+                rssi_sum += rssi;
+                min_rssi = Some(min_rssi.map_or(rssi, |min: i32| min.min(rssi)));
+                last_rssi = Some(rssi);
+            }
+        }
+
+        if sample_count == 0 {
+            return None;
+        }
+
+        Some(Self {
+            sample_count,
+            min_snr,
+            mean_snr: snr_sum / sample_count as f32,
+            last_snr,
+            min_rssi,
+            mean_rssi: match rssi_count {
+                0 => None,
+                n => Some(rssi_sum as f32 / n as f32),
+            },
+            last_rssi,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkQualityReport {
+    pub node_a: u32,
+    pub node_b: u32,
+    pub samples: Vec<LinkQualitySample>,
+    pub aggregate: Option<LinkQualityAggregate>,
+}
+
+/// How the SNR of an edge is derived from its link quality history. The graph's
+/// `EdgeWeightStrategy` then turns that SNR into the edge's weight.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", tag = "mode")]
+pub enum LinkSnrMode {
+    /// Use the SNR of the most recent sample
+    #[default]
+    LastSample,
+
+    /// Use the mean SNR of samples received within the window
+    #[serde(rename_all = "camelCase")]
+    WindowedMean { window_secs: u32 },
+}
+
+/// Bounded history of link quality samples for a pair of nodes
+#[derive(Clone, Debug, Default)]
+pub struct LinkQualityHistory {
+    samples: VecDeque<LinkQualitySample>,
+}
+
+impl LinkQualityHistory {
+    pub fn push(&mut self, sample: LinkQualitySample) {
+        if self.samples.len() >= LINK_QUALITY_HISTORY_CAPACITY {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(sample);
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn latest(&self) -> Option<&LinkQualitySample> {
+        self.samples.back()
+    }
+
+    /// Returns samples received at or after `since`, oldest first
+    pub fn window(&self, since: u32) -> Vec<LinkQualitySample> {
+        self.samples
+            .iter()
+            .filter(|s| s.timestamp >= since)
+            .cloned()
+            .collect()
+    }
+
+    /// Aggregates samples received at or after `since` without copying them
+    pub fn aggregate(&self, since: u32) -> Option<LinkQualityAggregate> {
+        LinkQualityAggregate::from_samples(self.samples.iter().filter(|s| s.timestamp >= since))
+    }
+
+    /// Derives the current SNR of the link according to `mode`
+    pub fn snr(&self, mode: &LinkSnrMode, now: u32) -> Option<f32> {
+        match mode {
+            LinkSnrMode::LastSample => self.samples.back().map(|s| s.snr),
+            LinkSnrMode::WindowedMean { window_secs } => self
+                .aggregate(now.saturating_sub(*window_secs))
+                .map(|a| a.mean_snr),
+        }
+    }
+}
+
+/// Links are undirected for the purposes of quality history
+pub fn link_key(node_a: u32, node_b: u32) -> (u32, u32) {
+    (node_a.min(node_b), node_a.max(node_b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: u32, snr: f32, rssi: Option<i32>) -> LinkQualitySample {
+        LinkQualitySample {
+            timestamp,
+            snr,
+            rssi,
+            observer: "mock".into(),
+        }
+    }
+
+    #[test]
+    fn caps_history() {
+        let mut history = LinkQualityHistory::default();
+
+        for i in 0..(LINK_QUALITY_HISTORY_CAPACITY as u32 + 10) {
+            history.push(sample(i, 1.0, None));
+        }
+
+        assert_eq!(history.len(), LINK_QUALITY_HISTORY_CAPACITY);
+        assert_eq!(history.window(0)[0].timestamp, 10);
+    }
+
+    #[test]
+    fn filters_by_window() {
+        let mut history = LinkQualityHistory::default();
+        history.push(sample(100, 1.0, None));
+        history.push(sample(200, 2.0, None));
+        history.push(sample(300, 3.0, None));
+
+        let window = history.window(200);
+        assert_eq!(window.len(), 2);
+        assert_eq!(window[0].timestamp, 200);
+    }
+
+    #[test]
+    fn aggregates_samples() {
+        let samples = vec![
+            sample(1, 4.0, Some(-90)),
+            sample(2, -2.0, None),
+            sample(3, 7.0, Some(-100)),
+        ];
+
+        let aggregate = LinkQualityAggregate::from_samples(&samples).unwrap();
+
+        assert_eq!(aggregate.sample_count, 3);
+        assert_eq!(aggregate.min_snr, -2.0);
+        assert!((aggregate.mean_snr - 3.0).abs() < 1e-6);
+        assert_eq!(aggregate.last_snr, 7.0);
+        assert_eq!(aggregate.min_rssi, Some(-100));
+        assert_eq!(aggregate.mean_rssi, Some(-95.0));
+        assert_eq!(aggregate.last_rssi, Some(-100));
+
+        assert_eq!(LinkQualityAggregate::from_samples(&[]), None);
+    }
+
+    #[test]
+    fn derives_weight_by_mode() {
+        let mut history = LinkQualityHistory::default();
+        history.push(sample(100, 10.0, None));
+        history.push(sample(190, 2.0, None));
+        history.push(sample(200, 4.0, None));
+
+        assert_eq!(history.snr(&LinkSnrMode::LastSample, 200), Some(4.0));
+        assert_eq!(
+            history.snr(&LinkSnrMode::WindowedMean { window_secs: 50 }, 200),
+            Some(3.0)
+        );
+        assert_eq!(
+            history.snr(&LinkSnrMode::WindowedMean { window_secs: 5 }, 300),
+            None
+        );
+    }
+}
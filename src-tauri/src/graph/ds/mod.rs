@@ -1,3 +1,11 @@
 pub mod edge;
+pub mod edge_weight;
+#[cfg(test)]
+pub mod fixtures;
 pub mod graph;
+pub mod link_quality;
+pub mod names;
 pub mod node;
+pub mod overrides;
+pub mod position_archive;
+pub mod spatial_index;
@@ -0,0 +1,147 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Names shared by the graph's edges, such as channel names. Each distinct name is
+/// allocated once, however many edges and copies of the graph refer to it.
+#[derive(Clone, Debug, Default)]
+pub struct NameInterner {
+    names: HashSet<Arc<str>>,
+}
+
+impl NameInterner {
+    pub fn intern(&mut self, name: &str) -> Arc<str> {
+        if let Some(interned) = self.names.get(name) {
+            return interned.clone();
+        }
+
+        let interned: Arc<str> = Arc::from(name);
+        self.names.insert(interned.clone());
+        interned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::collections::HashMap;
+
+    use meshtastic::protobufs::{self, MeshPacket, Neighbor, NeighborInfo};
+
+    use super::*;
+    use crate::graph::ds::{edge::GraphEdge, graph::MeshGraph};
+
+    /// Counts the allocations made by the current thread, so tests running in parallel
+    /// don't affect each other
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations_during<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        let before = ALLOCATIONS.with(Cell::get);
+        let result = f();
+
+        (result, ALLOCATIONS.with(Cell::get) - before)
+    }
+
+    const NODE_COUNT: u32 = 1_000;
+
+    /// Each node reports hearing the next one, around a ring
+    fn ring() -> MeshGraph {
+        let mut graph = MeshGraph::new();
+
+        for node_num in 1..=NODE_COUNT {
+            graph.update_from_position(
+                MeshPacket {
+                    from: node_num,
+                    ..Default::default()
+                },
+                protobufs::Position {
+                    latitude_i: 525_000_000 + node_num as i32 * 1_000,
+                    longitude_i: 134_000_000,
+                    ..Default::default()
+                },
+            );
+        }
+
+        for node_num in 1..=NODE_COUNT {
+            graph.update_from_neighbor_info(
+                MeshPacket {
+                    from: node_num,
+                    channel: node_num % 2,
+                    ..Default::default()
+                },
+                NeighborInfo {
+                    node_id: node_num,
+                    neighbors: vec![Neighbor {
+                        node_id: node_num % NODE_COUNT + 1,
+                        snr: 4.0,
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+                format!("channel #{}", node_num % 2),
+                "/dev/ttyUSB0",
+            );
+        }
+
+        graph
+    }
+
+    #[test]
+    fn interns_each_name_once() {
+        let mut names = NameInterner::default();
+
+        let first = names.intern("LongFast");
+        let second = names.intern(&String::from("LongFast"));
+
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(!Arc::ptr_eq(&first, &names.intern("MediumSlow")));
+    }
+
+    #[test]
+    fn edges_share_channel_names() {
+        let graph = ring();
+        let mut by_channel: HashMap<u32, Arc<str>> = HashMap::new();
+
+        for (_, _, edge) in graph.edges() {
+            let name = by_channel
+                .entry(edge.channel)
+                .or_insert_with(|| edge.channel_name.clone());
+
+            assert!(Arc::ptr_eq(name, &edge.channel_name));
+        }
+
+        assert_eq!(by_channel.len(), 2);
+    }
+
+    #[test]
+    fn copying_edges_allocates_only_the_copy() {
+        let graph = ring();
+
+        let (edges, allocations) = allocations_during(|| {
+            let mut edges: Vec<GraphEdge> = Vec::with_capacity(graph.edge_count());
+            edges.extend(graph.edges().map(|(_, _, edge)| edge.clone()));
+            edges
+        });
+
+        assert_eq!(edges.len(), NODE_COUNT as usize);
+        assert_eq!(allocations, 1);
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+/// Edge added by the operator rather than inferred from packets
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ManualEdge {
+    pub from: u32,
+    pub to: u32,
+    pub weight: f64,
+}
+
+/// Maximum length of an operator's node label, in characters
+pub const MAX_NODE_LABEL_CHARS: usize = 64;
+
+/// Operator changes to the graph that are kept across regeneration and restarts.
+/// Hidden nodes stay in the graph but are left out of GeoJSON and analytics.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GraphOverrides {
+    pub manual_edges: Vec<ManualEdge>,
+    pub hidden_nodes: Vec<u32>,
+    pub node_labels: HashMap<u32, String>, // shown instead of the node's long name
+}
+
+impl GraphOverrides {
+    /// Adds a manual edge, replacing any existing manual edge between the same nodes
+    pub fn add_manual_edge(&mut self, edge: ManualEdge) {
+        self.remove_manual_edge(edge.from, edge.to);
+        self.manual_edges.push(edge);
+    }
+
+    /// Returns the removed edge, if there was one
+    pub fn remove_manual_edge(&mut self, from: u32, to: u32) -> Option<ManualEdge> {
+        let index = self
+            .manual_edges
+            .iter()
+            .position(|edge| edge.from == from && edge.to == to)?;
+
+        Some(self.manual_edges.remove(index))
+    }
+
+    pub fn manual_edge(&self, from: u32, to: u32) -> Option<&ManualEdge> {
+        self.manual_edges
+            .iter()
+            .find(|edge| edge.from == from && edge.to == to)
+    }
+
+    pub fn set_node_hidden(&mut self, node_num: u32, hidden: bool) {
+        self.hidden_nodes.retain(|n| *n != node_num);
+
+        if hidden {
+            self.hidden_nodes.push(node_num);
+        }
+    }
+
+    pub fn is_hidden(&self, node_num: u32) -> bool {
+        self.hidden_nodes.contains(&node_num)
+    }
+
+    /// Sets the operator's label for a node, or clears it if `label` is `None` or blank
+    pub fn set_node_label(&mut self, node_num: u32, label: Option<String>) {
+        match label
+            .map(|l| l.trim().to_string())
+            .filter(|l| !l.is_empty())
+        {
+            Some(label) => self.node_labels.insert(node_num, label),
+            None => self.node_labels.remove(&node_num),
+        };
+    }
+
+    pub fn node_label(&self, node_num: u32) -> Option<&str> {
+        self.node_labels.get(&node_num).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_manual_edge_between_same_nodes() {
+        let mut overrides = GraphOverrides::default();
+
+        overrides.add_manual_edge(ManualEdge {
+            from: 1,
+            to: 2,
+            weight: 4.0,
+        });
+        overrides.add_manual_edge(ManualEdge {
+            from: 1,
+            to: 2,
+            weight: 8.0,
+        });
+
+        assert_eq!(overrides.manual_edges.len(), 1);
+        assert_eq!(overrides.manual_edge(1, 2).map(|e| e.weight), Some(8.0));
+        assert!(overrides.manual_edge(2, 1).is_none());
+
+        assert!(overrides.remove_manual_edge(1, 2).is_some());
+        assert!(overrides.remove_manual_edge(1, 2).is_none());
+    }
+
+    #[test]
+    fn toggles_hidden_nodes() {
+        let mut overrides = GraphOverrides::default();
+
+        overrides.set_node_hidden(3, true);
+        overrides.set_node_hidden(3, true);
+        assert_eq!(overrides.hidden_nodes, vec![3]);
+        assert!(overrides.is_hidden(3));
+
+        overrides.set_node_hidden(3, false);
+        assert!(!overrides.is_hidden(3));
+    }
+
+    #[test]
+    fn sets_and_clears_node_labels() {
+        let mut overrides = GraphOverrides::default();
+
+        overrides.set_node_label(5, Some("  Dad's truck ".into()));
+        assert_eq!(overrides.node_label(5), Some("Dad's truck"));
+
+        overrides.set_node_label(5, Some(" ".into()));
+        assert_eq!(overrides.node_label(5), None);
+
+        overrides.set_node_label(5, Some("Barn".into()));
+        overrides.set_node_label(5, None);
+        assert!(overrides.node_labels.is_empty());
+    }
+
+    #[test]
+    fn reloads_saved_overrides() {
+        let mut overrides = GraphOverrides::default();
+        overrides.set_node_label(7, Some("Ridge repeater".into()));
+        overrides.set_node_hidden(8, true);
+
+        let saved = serde_json::to_string(&overrides).unwrap();
+        let reloaded: GraphOverrides = serde_json::from_str(&saved).unwrap();
+
+        assert_eq!(reloaded, overrides);
+        assert_eq!(reloaded.node_label(7), Some("Ridge repeater"));
+
+        // Overrides saved before labels existed still load
+        let legacy: GraphOverrides =
+            serde_json::from_str(r#"{"manualEdges":[],"hiddenNodes":[8]}"#).unwrap();
+        assert!(legacy.is_hidden(8));
+        assert!(legacy.node_labels.is_empty());
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::{HashMap, HashSet};
+
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+/// How an archived position was reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum PositionSource {
+    NodeInfo,       // from the radio's node database
+    PositionPacket, // broadcast by the node
+}
+
+impl PositionSource {
+    /// Stored in the graph database's `source` column
+    pub fn tag(&self) -> &'static str {
+        match self {
+            PositionSource::NodeInfo => "nodeInfo",
+            PositionSource::PositionPacket => "positionPacket",
+        }
+    }
+
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "nodeInfo" => Some(PositionSource::NodeInfo),
+            "positionPacket" => Some(PositionSource::PositionPacket),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: i32,
+    pub source: PositionSource,
+    pub timestamp: u32, // secs, when the position was reported
+}
+
+/// Last known position of every node heard by a radio, kept after the node drops out
+/// of the radio's node database so it can still be placed on the map
+#[derive(Debug, Clone, Default)]
+pub struct PositionArchive {
+    positions: HashMap<u32, ArchivedPosition>,
+    changed: HashSet<u32>, // nodes whose position hasn't been written to the database
+}
+
+impl PositionArchive {
+    /// Archive loaded from the database, with nothing left to write
+    pub fn new(positions: HashMap<u32, ArchivedPosition>) -> Self {
+        Self {
+            positions,
+            changed: HashSet::new(),
+        }
+    }
+
+    /// Replaces the node's position, unless the archived one was reported later.
+    /// Unset (zero) coordinates are ignored.
+    pub fn record(&mut self, node_num: u32, position: ArchivedPosition) {
+        if position.latitude == 0.0 && position.longitude == 0.0 {
+            return;
+        }
+
+        if let Some(archived) = self.positions.get(&node_num) {
+            if archived.timestamp > position.timestamp || *archived == position {
+                return;
+            }
+        }
+
+        self.positions.insert(node_num, position);
+        self.changed.insert(node_num);
+    }
+
+    pub fn get(&self, node_num: u32) -> Option<&ArchivedPosition> {
+        self.positions.get(&node_num)
+    }
+
+    pub fn remove(&mut self, node_num: u32) -> Option<ArchivedPosition> {
+        self.changed.remove(&node_num);
+        self.positions.remove(&node_num)
+    }
+
+    /// Forgets positions reported before `cutoff`, returning how many were removed
+    pub fn prune(&mut self, cutoff: u32) -> usize {
+        let before = self.positions.len();
+
+        self.positions
+            .retain(|_, position| position.timestamp >= cutoff);
+        self.changed
+            .retain(|node_num| self.positions.contains_key(node_num));
+
+        before - self.positions.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.positions.clear();
+        self.changed.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Positions recorded since the last call, to be written to the database
+    pub fn take_changed(&mut self) -> Vec<(u32, ArchivedPosition)> {
+        let mut changed: Vec<(u32, ArchivedPosition)> = self
+            .changed
+            .drain()
+            .filter_map(|node_num| Some((node_num, *self.positions.get(&node_num)?)))
+            .collect();
+
+        changed.sort_by_key(|(node_num, _)| *node_num);
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(latitude: f64, timestamp: u32) -> ArchivedPosition {
+        ArchivedPosition {
+            latitude,
+            longitude: -122.3,
+            altitude: 20,
+            source: PositionSource::PositionPacket,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn keeps_latest_position() {
+        let mut archive = PositionArchive::default();
+
+        archive.record(1, position(47.6, 1_000));
+        archive.record(1, position(47.7, 900)); // reported earlier, e.g. by the node DB
+        archive.record(2, position(0.0, 1_000));
+        assert_eq!(archive.get(1), Some(&position(47.6, 1_000)));
+        assert_eq!(archive.get(2), None);
+
+        assert_eq!(archive.take_changed(), vec![(1, position(47.6, 1_000))]);
+        assert!(archive.take_changed().is_empty());
+
+        // Hearing the same position again has nothing new to write
+        archive.record(1, position(47.6, 1_000));
+        assert!(archive.take_changed().is_empty());
+
+        archive.record(1, position(47.8, 2_000));
+        archive.record(3, position(48.0, 500));
+        assert_eq!(archive.prune(1_000), 1);
+        assert_eq!(archive.len(), 1);
+        assert_eq!(archive.take_changed(), vec![(1, position(47.8, 2_000))]);
+
+        assert_eq!(archive.remove(1), Some(position(47.8, 2_000)));
+        assert!(archive.is_empty());
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::device::helpers::haversine_distance_meters;
+
+/// Size of the grid cells nodes are bucketed into, about 1.1 km of latitude
+pub const CELL_SIZE_DEGREES: f64 = 0.01;
+
+const METERS_PER_DEGREE_LATITUDE: f64 = 111_320.0;
+
+type Cell = (i32, i32);
+
+fn cell_of(latitude: f64, longitude: f64) -> Cell {
+    (
+        (latitude / CELL_SIZE_DEGREES).floor() as i32,
+        (longitude / CELL_SIZE_DEGREES).floor() as i32,
+    )
+}
+
+/// Last known position of each node, bucketed into a grid of `CELL_SIZE_DEGREES`
+/// cells so radius queries only look at nearby nodes
+#[derive(Clone, Debug, Default)]
+pub struct SpatialIndex {
+    positions: HashMap<u32, (f64, f64)>, // (latitude, longitude)
+    cells: HashMap<Cell, HashSet<u32>>,
+}
+
+impl SpatialIndex {
+    /// Moves the node to its new position, returning its previous one
+    pub fn update(&mut self, node_num: u32, latitude: f64, longitude: f64) -> Option<(f64, f64)> {
+        let previous = self.remove(node_num);
+
+        self.positions.insert(node_num, (latitude, longitude));
+        self.cells
+            .entry(cell_of(latitude, longitude))
+            .or_default()
+            .insert(node_num);
+
+        previous
+    }
+
+    pub fn remove(&mut self, node_num: u32) -> Option<(f64, f64)> {
+        let (latitude, longitude) = self.positions.remove(&node_num)?;
+        let cell = cell_of(latitude, longitude);
+
+        if let Some(nodes) = self.cells.get_mut(&cell) {
+            nodes.remove(&node_num);
+
+            if nodes.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+
+        Some((latitude, longitude))
+    }
+
+    pub fn clear(&mut self) {
+        self.positions.clear();
+        self.cells.clear();
+    }
+
+    pub fn position(&self, node_num: u32) -> Option<(f64, f64)> {
+        self.positions.get(&node_num).copied()
+    }
+
+    pub fn node_nums(&self) -> impl Iterator<Item = u32> + '_ {
+        self.positions.keys().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Nodes within `radius_meters` of the coordinate, with their distance in meters,
+    /// in no particular order
+    pub fn within_radius(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        radius_meters: f64,
+    ) -> Vec<(u32, f64)> {
+        let distance_to = |node_num: &u32| {
+            let (lat, lon) = self.positions[node_num];
+            (
+                *node_num,
+                haversine_distance_meters(latitude, longitude, lat, lon),
+            )
+        };
+
+        let within = |(_, distance): &(u32, f64)| *distance <= radius_meters;
+
+        match self.candidate_cells(latitude, longitude, radius_meters) {
+            Some(cells) => cells
+                .iter()
+                .filter_map(|cell| self.cells.get(cell))
+                .flatten()
+                .map(distance_to)
+                .filter(within)
+                .collect(),
+            None => self
+                .positions
+                .keys()
+                .map(distance_to)
+                .filter(within)
+                .collect(),
+        }
+    }
+
+    /// Cells overlapping the bounding box of the radius, or `None` if a full scan is
+    /// cheaper or the box wraps around a pole or the antimeridian
+    fn candidate_cells(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        radius_meters: f64,
+    ) -> Option<Vec<Cell>> {
+        let lat_margin = radius_meters / METERS_PER_DEGREE_LATITUDE;
+        let lon_scale = (latitude + lat_margin.copysign(latitude))
+            .to_radians()
+            .cos();
+
+        if latitude.abs() + lat_margin >= 90.0 || lon_scale <= 0.0 {
+            return None;
+        }
+
+        let lon_margin = lat_margin / lon_scale;
+
+        if longitude.abs() + lon_margin >= 180.0 {
+            return None;
+        }
+
+        let (min_lat, min_lon) = cell_of(latitude - lat_margin, longitude - lon_margin);
+        let (max_lat, max_lon) = cell_of(latitude + lat_margin, longitude + lon_margin);
+
+        let cell_count = (max_lat - min_lat + 1) as usize * (max_lon - min_lon + 1) as usize;
+
+        if cell_count > self.cells.len() {
+            return None;
+        }
+
+        Some(
+            (min_lat..=max_lat)
+                .flat_map(|lat| (min_lon..=max_lon).map(move |lon| (lat, lon)))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut nodes: Vec<(u32, f64)>) -> Vec<u32> {
+        nodes.sort_by(|a, b| a.1.total_cmp(&b.1));
+        nodes.into_iter().map(|(node_num, _)| node_num).collect()
+    }
+
+    /// Grid of nodes roughly 1 km apart, large enough for queries to use the cells
+    fn grid() -> SpatialIndex {
+        let mut index = SpatialIndex::default();
+
+        for row in 0..20 {
+            for col in 0..20 {
+                index.update(
+                    row * 100 + col,
+                    47.0 + row as f64 * 0.009,
+                    8.0 + col as f64 * 0.013,
+                );
+            }
+        }
+
+        index
+    }
+
+    fn full_scan(index: &SpatialIndex, latitude: f64, longitude: f64, radius: f64) -> Vec<u32> {
+        sorted(
+            index
+                .positions
+                .iter()
+                .map(|(n, (lat, lon))| {
+                    (
+                        *n,
+                        haversine_distance_meters(latitude, longitude, *lat, *lon),
+                    )
+                })
+                .filter(|(_, d)| *d <= radius)
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn grid_query_matches_full_scan() {
+        let index = grid();
+
+        for (latitude, longitude, radius) in [
+            (47.05, 8.1, 2_500.0),
+            (47.0, 8.0, 500.0),
+            (47.09, 8.13, 5_000.0),
+            (46.9, 7.9, 1_000.0),
+        ] {
+            assert!(index.candidate_cells(latitude, longitude, radius).is_some());
+            assert_eq!(
+                sorted(index.within_radius(latitude, longitude, radius)),
+                full_scan(&index, latitude, longitude, radius)
+            );
+        }
+
+        // Larger radii scan every node instead
+        assert!(index.candidate_cells(47.0, 8.0, 500_000.0).is_none());
+        assert_eq!(index.within_radius(47.0, 8.0, 500_000.0).len(), 400);
+    }
+
+    #[test]
+    fn index_stays_consistent_after_moves() {
+        let mut index = grid();
+
+        // Move a node out of its cell and far away, then back near the origin
+        assert_eq!(index.update(0, 10.0, 10.0), Some((47.0, 8.0)));
+        assert!(!sorted(index.within_radius(47.0, 8.0, 100.0)).contains(&0));
+        assert_eq!(sorted(index.within_radius(10.0, 10.0, 100.0)), [0]);
+
+        index.update(0, 47.0001, 8.0001);
+        assert_eq!(sorted(index.within_radius(47.0, 8.0, 100.0)), [0]);
+        assert!(index.within_radius(10.0, 10.0, 100.0).is_empty());
+
+        assert_eq!(index.remove(0), Some((47.0001, 8.0001)));
+        assert!(index.within_radius(47.0, 8.0, 100.0).is_empty());
+
+        let indexed: usize = index.cells.values().map(HashSet::len).sum();
+        assert_eq!(indexed, index.len());
+        assert!(index.cells.values().all(|nodes| !nodes.is_empty()));
+    }
+}
@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+
+use geojson::{feature, Feature, FeatureCollection, JsonObject};
+use serde::Serialize;
+
+use super::geojson::edge_properties;
+
+/// Edge features added, changed or removed since the last update sent to the UI.
+/// Features are keyed by their stable ids from `edge_feature_id`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EdgeDelta {
+    pub added: Vec<Feature>,
+    pub changed: Vec<Feature>,
+    pub removed: Vec<String>,
+}
+
+impl EdgeDelta {
+    pub fn feature_count(&self) -> usize {
+        self.added.len() + self.changed.len() + self.removed.len()
+    }
+}
+
+/// What to send the UI for the latest edges. Every update gets the next sequence
+/// number, so the UI can tell it missed a delta and ask for a snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EdgeUpdate {
+    Snapshot { sequence: u32 },
+    Delta { sequence: u32, delta: EdgeDelta },
+}
+
+fn feature_key(feature: &Feature) -> Option<String> {
+    match feature.id.as_ref()? {
+        feature::Id::String(id) => Some(id.clone()),
+        feature::Id::Number(id) => Some(id.to_string()),
+    }
+}
+
+fn index_features(collection: &FeatureCollection) -> HashMap<String, Feature> {
+    collection
+        .features
+        .iter()
+        .filter_map(|feature| Some((feature_key(feature)?, feature.clone())))
+        .collect()
+}
+
+/// How far link quality must move before an edge counts as changed, in dB of SNR
+pub const LINK_QUALITY_TOLERANCE: f64 = 0.5;
+
+/// Properties that follow the link's SNR, which jitters from one sample to the next
+const LINK_QUALITY_PROPERTIES: [&str; 4] = [
+    edge_properties::WEIGHT,
+    edge_properties::WEIGHT_FORWARD,
+    edge_properties::WEIGHT_REVERSE,
+    edge_properties::SNR,
+];
+
+/// Edge age changes every second and the last heard time every time the edge is
+/// heard again, so neither alone makes an edge changed. Link quality is compared
+/// separately, within `LINK_QUALITY_TOLERANCE`.
+fn comparable_properties(feature: &Feature) -> Option<JsonObject> {
+    let mut properties = feature.properties.clone()?;
+    properties.remove(edge_properties::AGE_SECS);
+    properties.remove(edge_properties::LAST_HEARD);
+
+    for key in LINK_QUALITY_PROPERTIES {
+        properties.remove(key);
+    }
+
+    Some(properties)
+}
+
+fn is_link_quality_changed(previous: &Feature, current: &Feature, key: &str) -> bool {
+    let previous = previous.properties.as_ref().and_then(|p| p.get(key));
+    let current = current.properties.as_ref().and_then(|p| p.get(key));
+
+    match (
+        previous.and_then(|v| v.as_f64()),
+        current.and_then(|v| v.as_f64()),
+    ) {
+        (Some(previous), Some(current)) => (previous - current).abs() >= LINK_QUALITY_TOLERANCE,
+        _ => previous != current,
+    }
+}
+
+fn is_changed(previous: &Feature, current: &Feature) -> bool {
+    previous.geometry != current.geometry
+        || comparable_properties(previous) != comparable_properties(current)
+        || LINK_QUALITY_PROPERTIES
+            .iter()
+            .any(|key| is_link_quality_changed(previous, current, key))
+}
+
+/// Computes the delta from `previous` edge features, keyed by id, to `current`
+pub fn diff_edge_features(
+    previous: &HashMap<String, Feature>,
+    current: &HashMap<String, Feature>,
+) -> EdgeDelta {
+    let mut delta = EdgeDelta::default();
+
+    for (id, feature) in current {
+        match previous.get(id) {
+            None => delta.added.push(feature.clone()),
+            Some(previous_feature) if is_changed(previous_feature, feature) => {
+                delta.changed.push(feature.clone())
+            }
+            Some(_) => {}
+        }
+    }
+
+    delta.removed = previous
+        .keys()
+        .filter(|id| !current.contains_key(*id))
+        .cloned()
+        .collect();
+
+    delta.added.sort_by_key(feature_key);
+    delta.changed.sort_by_key(feature_key);
+    delta.removed.sort();
+
+    delta
+}
+
+/// Remembers the edges last sent to the UI for a device so that later updates can
+/// be sent as deltas. A delta touching more than half of the current edges is sent
+/// as a snapshot instead, since it would be about as large.
+#[derive(Debug, Default)]
+pub struct EdgeDeltaTracker {
+    sequence: u32,
+    sent_edges: Option<HashMap<String, Feature>>,
+}
+
+impl EdgeDeltaTracker {
+    /// Sequence of the last update, which changes whenever the edges are sent
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Records `edges` as sent and returns how to send them
+    pub fn next(&mut self, edges: &FeatureCollection) -> EdgeUpdate {
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let mut current = index_features(edges);
+
+        let update = match self.sent_edges.as_ref() {
+            Some(sent_edges) => {
+                let delta = diff_edge_features(sent_edges, &current);
+
+                if delta.feature_count() * 2 > current.len() {
+                    EdgeUpdate::Snapshot {
+                        sequence: self.sequence,
+                    }
+                } else {
+                    // The UI keeps its copy of edges that weren't resent, so those are
+                    // remembered as sent and small changes can't add up unnoticed
+                    for (id, feature) in current.iter_mut() {
+                        match sent_edges.get(id) {
+                            Some(sent) if !is_changed(sent, feature) => *feature = sent.clone(),
+                            _ => {}
+                        }
+                    }
+
+                    EdgeUpdate::Delta {
+                        sequence: self.sequence,
+                        delta,
+                    }
+                }
+            }
+            None => EdgeUpdate::Snapshot {
+                sequence: self.sequence,
+            },
+        };
+
+        self.sent_edges = Some(current);
+
+        update
+    }
+
+    /// Makes the next update a snapshot, e.g. after the UI missed a delta
+    pub fn request_snapshot(&mut self) {
+        self.sent_edges = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use geojson::{Geometry, Value};
+    use serde_json::json;
+
+    use super::*;
+
+    fn edge_feature(id: &str, snr: f64, age_secs: u32) -> Feature {
+        let mut properties = JsonObject::new();
+        properties.insert(edge_properties::SNR.into(), json!(snr));
+        properties.insert(edge_properties::AGE_SECS.into(), json!(age_secs));
+
+        Feature {
+            bbox: None,
+            geometry: Some(Geometry::new(Value::LineString(vec![
+                vec![0.0, 0.0],
+                vec![1.0, 1.0],
+            ]))),
+            id: Some(feature::Id::String(id.into())),
+            properties: Some(properties),
+            foreign_members: None,
+        }
+    }
+
+    fn collection(features: Vec<Feature>) -> FeatureCollection {
+        FeatureCollection {
+            bbox: None,
+            features,
+            foreign_members: None,
+        }
+    }
+
+    /// Applies updates the way the UI does, asking for a snapshot after a gap
+    #[derive(Default)]
+    struct Client {
+        sequence: u32,
+        edges: HashMap<String, Feature>,
+    }
+
+    impl Client {
+        /// Returns whether the update was applied, `false` if a resync is needed
+        fn apply(&mut self, update: &EdgeUpdate, snapshot: &FeatureCollection) -> bool {
+            match update {
+                EdgeUpdate::Snapshot { sequence } => {
+                    self.sequence = *sequence;
+                    self.edges = index_features(snapshot);
+                    true
+                }
+                EdgeUpdate::Delta { sequence, delta } => {
+                    if *sequence != self.sequence.wrapping_add(1) {
+                        return false;
+                    }
+
+                    self.sequence = *sequence;
+
+                    for feature in delta.added.iter().chain(&delta.changed) {
+                        self.edges
+                            .insert(feature_key(feature).unwrap(), feature.clone());
+                    }
+
+                    for id in &delta.removed {
+                        self.edges.remove(id);
+                    }
+
+                    true
+                }
+            }
+        }
+    }
+
+    fn assert_in_sync(client: &Client, snapshot: &FeatureCollection) {
+        let expected = index_features(snapshot);
+
+        let client_ids: BTreeSet<_> = client.edges.keys().collect();
+        let expected_ids: BTreeSet<_> = expected.keys().collect();
+        assert_eq!(client_ids, expected_ids);
+
+        for (id, feature) in &expected {
+            assert!(!is_changed(&client.edges[id], feature), "{} differs", id);
+        }
+    }
+
+    #[test]
+    fn diffs_added_changed_and_removed_edges() {
+        let previous = index_features(&collection(vec![
+            edge_feature("1-2#0:neighbor_info", 5.0, 10),
+            edge_feature("1-3#0:neighbor_info", 2.0, 10),
+            edge_feature("2-3#0:neighbor_info", 1.0, 10),
+        ]));
+        let current = index_features(&collection(vec![
+            edge_feature("1-2#0:neighbor_info", 5.0, 40),
+            edge_feature("1-3#0:neighbor_info", -3.0, 40),
+            edge_feature("3-4#1:traceroute", 0.0, 0),
+        ]));
+
+        let delta = diff_edge_features(&previous, &current);
+
+        assert_eq!(delta.added, vec![edge_feature("3-4#1:traceroute", 0.0, 0)]);
+        assert_eq!(
+            delta.changed,
+            vec![edge_feature("1-3#0:neighbor_info", -3.0, 40)]
+        );
+        assert_eq!(delta.removed, vec!["2-3#0:neighbor_info".to_string()]);
+    }
+
+    #[test]
+    fn deltas_rebuild_snapshots() {
+        let mut tracker = EdgeDeltaTracker::default();
+        let mut client = Client::default();
+
+        let ids: Vec<String> = (0..10)
+            .map(|n| format!("{}-{}#0:node_db", n, n + 1))
+            .collect();
+        let mut snr = vec![0.0; ids.len()];
+
+        for step in 0..20 {
+            // Change one edge per step, and drop or restore the last one
+            snr[step % ids.len()] += 1.0;
+
+            let edge_count = if step % 3 == 0 {
+                ids.len() - 1
+            } else {
+                ids.len()
+            };
+
+            let snapshot = collection(
+                ids.iter()
+                    .zip(&snr)
+                    .take(edge_count)
+                    .map(|(id, snr)| edge_feature(id, *snr, step as u32))
+                    .collect(),
+            );
+
+            let update = tracker.next(&snapshot);
+
+            if step == 0 {
+                assert!(matches!(update, EdgeUpdate::Snapshot { sequence: 1 }));
+            } else {
+                assert!(matches!(update, EdgeUpdate::Delta { .. }));
+            }
+
+            assert!(client.apply(&update, &snapshot));
+            assert_in_sync(&client, &snapshot);
+        }
+    }
+
+    #[test]
+    fn link_quality_jitter_is_not_sent_until_it_adds_up() {
+        let mut tracker = EdgeDeltaTracker::default();
+
+        let edges = |snr: f64| {
+            collection(vec![
+                edge_feature("0", snr, 0),
+                edge_feature("1", 0.0, 0),
+                edge_feature("2", 0.0, 0),
+            ])
+        };
+
+        tracker.next(&edges(0.0));
+
+        let delta = match tracker.next(&edges(0.25)) {
+            EdgeUpdate::Delta { delta, .. } => delta,
+            update => panic!("Expected a delta, got {:?}", update),
+        };
+        assert_eq!(delta, EdgeDelta::default());
+
+        // Compared against the SNR last sent rather than the last one seen
+        let delta = match tracker.next(&edges(0.5)) {
+            EdgeUpdate::Delta { delta, .. } => delta,
+            update => panic!("Expected a delta, got {:?}", update),
+        };
+        assert_eq!(delta.changed, vec![edge_feature("0", 0.5, 0)]);
+    }
+
+    #[test]
+    fn resyncs_after_missed_delta() {
+        let mut tracker = EdgeDeltaTracker::default();
+        let mut client = Client::default();
+
+        let first = collection(
+            (0..4)
+                .map(|n| edge_feature(&n.to_string(), 0.0, 0))
+                .collect(),
+        );
+        let second = collection(
+            (0..3)
+                .map(|n| edge_feature(&n.to_string(), 1.0, 0))
+                .collect(),
+        );
+        let mut third = second.clone();
+        third.features.push(edge_feature("4", 0.0, 0));
+
+        assert!(client.apply(&tracker.next(&first), &first));
+
+        // The client never sees the second update
+        let _ = tracker.next(&second);
+
+        let update = tracker.next(&third);
+        assert!(matches!(update, EdgeUpdate::Delta { sequence: 3, .. }));
+        assert!(!client.apply(&update, &third));
+
+        tracker.request_snapshot();
+
+        let update = tracker.next(&third);
+        assert!(matches!(update, EdgeUpdate::Snapshot { sequence: 4 }));
+        assert!(client.apply(&update, &third));
+        assert_in_sync(&client, &third);
+    }
+
+    #[test]
+    fn sends_snapshot_for_large_delta() {
+        let mut tracker = EdgeDeltaTracker::default();
+
+        let before = collection(
+            (0..4)
+                .map(|n| edge_feature(&n.to_string(), 0.0, 0))
+                .collect(),
+        );
+        let after = collection(
+            (4..8)
+                .map(|n| edge_feature(&n.to_string(), 0.0, 0))
+                .collect(),
+        );
+
+        tracker.next(&before);
+
+        assert!(matches!(
+            tracker.next(&after),
+            EdgeUpdate::Snapshot { sequence: 2 }
+        ));
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::NaiveDateTime;
+use geojson::{Feature, FeatureCollection};
+use serde_json::json;
+
+use crate::device::{helpers::get_current_time_u32, MeshDevice};
+use crate::state::DeviceKey;
+
+use super::ds::{
+    edge::{EdgeSource, GraphEdge},
+    graph::MeshGraph,
+    link_quality::{link_key, LinkQualitySample},
+    node::GraphNode,
+};
+use super::geojson::{
+    edge_age_secs, edge_properties, generate_edge_feature, is_edge_hidden, node_coordinates,
+    node_long_name, sort_features_by_id, with_bbox,
+};
+
+/// Everything an edge feature is generated from besides the time, which only changes
+/// its age. Link quality history is compared by its length and latest sample rather
+/// than sample by sample.
+#[derive(Clone, Debug, PartialEq)]
+struct EdgeInputs {
+    snr: f64,
+    channel: u32,
+    source: EdgeSource,
+    last_heard: NaiveDateTime,
+    observed_by: BTreeSet<DeviceKey>,
+    weight: Option<f32>,
+    reverse_snr: Option<f64>,
+    link_quality: Option<(usize, LinkQualitySample)>,
+    from_name: Option<String>,
+    to_name: Option<String>,
+    from_coordinates: Option<Vec<f64>>,
+    to_coordinates: Option<Vec<f64>>,
+}
+
+impl EdgeInputs {
+    fn new(
+        graph: &MeshGraph,
+        device: &MeshDevice,
+        (from, to): (GraphNode, GraphNode),
+        edge: &GraphEdge,
+        now_secs: u32,
+    ) -> Self {
+        let history = graph
+            .link_quality
+            .get(&link_key(from.node_num, to.node_num));
+
+        Self {
+            snr: edge.snr(),
+            channel: edge.channel,
+            source: edge.source,
+            last_heard: edge.last_heard,
+            observed_by: edge.observed_by.clone(),
+            weight: history.and_then(|h| h.snr(&graph.link_snr_mode, now_secs)),
+            reverse_snr: graph.get_edge(to, from).map(|reverse| reverse.snr()),
+            link_quality: history.and_then(|h| Some((h.len(), h.latest()?.clone()))),
+            from_name: node_long_name(device, from.node_num).map(String::from),
+            to_name: node_long_name(device, to.node_num).map(String::from),
+            from_coordinates: node_coordinates(graph, device, from.node_num),
+            to_coordinates: node_coordinates(graph, device, to.node_num),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CachedEdgeFeature {
+    inputs: EdgeInputs,
+    feature: Option<Feature>, // `None` while either endpoint has no known position
+}
+
+/// Edge features of a device's graph from the last time its GeoJSON was generated,
+/// keyed by the edge's endpoints like their `edge_feature_id`. Only features whose
+/// edge, endpoints or link quality changed since are generated again, so a packet
+/// touching one link doesn't regenerate every edge on the map. When the cache is
+/// empty or most positions moved, every feature is generated as usual.
+///
+/// The cache belongs to the packet API rather than the graph, since the graph is
+/// copied for every view and shared with commands that replace it wholesale.
+/// Comparing inputs rather than tracking `GraphChange`s keeps it correct across those.
+#[derive(Debug, Default)]
+pub struct EdgeFeatureCache {
+    entries: HashMap<(u32, u32), CachedEdgeFeature>,
+}
+
+impl EdgeFeatureCache {
+    /// Generates the edge collection like `generate_graph_edges_geojson`, reusing the
+    /// features whose inputs haven't changed. Also returns how many were generated.
+    pub fn generate(
+        &mut self,
+        graph: &MeshGraph,
+        device: &MeshDevice,
+    ) -> (FeatureCollection, usize) {
+        let now = chrono::Utc::now().naive_utc();
+        let now_secs = get_current_time_u32();
+
+        let mut entries = HashMap::with_capacity(graph.edge_count());
+        let mut features = vec![];
+        let mut generated = 0;
+
+        for (from, to, edge) in graph.edges() {
+            if is_edge_hidden(graph, from.node_num, to.node_num) {
+                continue;
+            }
+
+            let key = (from.node_num, to.node_num);
+            let inputs = EdgeInputs::new(graph, device, (from, to), edge, now_secs);
+
+            let entry = match self.entries.remove(&key) {
+                Some(cached) if cached.inputs == inputs => cached,
+                _ => {
+                    generated += 1;
+
+                    CachedEdgeFeature {
+                        inputs,
+                        feature: generate_edge_feature(
+                            graph,
+                            device,
+                            (from, to),
+                            edge,
+                            now,
+                            now_secs,
+                        ),
+                    }
+                }
+            };
+
+            if let Some(mut feature) = entry.feature.clone() {
+                if let Some(properties) = feature.properties.as_mut() {
+                    properties.insert(
+                        edge_properties::AGE_SECS.into(),
+                        json!(edge_age_secs(edge, now)),
+                    );
+                }
+
+                features.push(feature);
+            }
+
+            entries.insert(key, entry);
+        }
+
+        // Anything left over belongs to edges that were removed or hidden
+        self.entries = entries;
+
+        sort_features_by_id(&mut features);
+
+        let collection = with_bbox(FeatureCollection {
+            bbox: None,
+            features,
+            foreign_members: None,
+        });
+
+        (collection, generated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use geojson::JsonObject;
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::{MeshNode, NormalizedPosition};
+    use crate::graph::ds::fixtures::{edge, graph_node};
+    use crate::graph::geojson::generate_graph_edges_geojson;
+
+    const RING_SIZE: u32 = 2_000;
+
+    fn positioned_node(node_num: u32, latitude: f32, longitude: f32) -> MeshNode {
+        let mut node = MeshNode::new(node_num);
+        node.user = Some(protobufs::User {
+            long_name: format!("Node {}", node_num),
+            ..Default::default()
+        });
+        node.position_metrics
+            .push(NormalizedPosition::from(protobufs::Position {
+                latitude_i: (latitude * 1e7) as i32,
+                longitude_i: (longitude * 1e7) as i32,
+                ..Default::default()
+            }));
+        node
+    }
+
+    fn sample(timestamp: u32, snr: f32) -> LinkQualitySample {
+        LinkQualitySample {
+            timestamp,
+            snr,
+            rssi: Some(-100),
+            observer: "mock".into(),
+        }
+    }
+
+    /// A ring of nodes, each reporting the next one with a few link quality samples
+    fn ring(size: u32) -> (MeshGraph, MeshDevice) {
+        let mut graph = MeshGraph::new();
+        let mut device = MeshDevice::new();
+
+        for node_num in 1..=size {
+            graph.upsert_node(graph_node(node_num));
+            device.nodes.insert(
+                node_num,
+                positioned_node(node_num, 47.0 + node_num as f32 * 1e-3, -122.0),
+            );
+        }
+
+        for node_num in 1..=size {
+            let next = node_num % size + 1;
+
+            graph.upsert_edge(
+                graph_node(node_num),
+                graph_node(next),
+                edge(node_num, next, 4.0),
+            );
+
+            for timestamp in 0..16 {
+                graph.record_link_sample(node_num, next, sample(1_700_000_000 + timestamp, 4.0));
+            }
+        }
+
+        (graph, device)
+    }
+
+    /// Features without their age, which changes between generating them
+    fn without_ages(collection: FeatureCollection) -> Vec<(Option<JsonObject>, Feature)> {
+        collection
+            .features
+            .into_iter()
+            .map(|mut feature| {
+                let properties = feature.properties.take().map(|mut properties| {
+                    properties.remove(edge_properties::AGE_SECS);
+                    properties
+                });
+
+                (properties, feature)
+            })
+            .collect()
+    }
+
+    fn assert_matches_full_generation(
+        cache: &mut EdgeFeatureCache,
+        graph: &MeshGraph,
+        device: &MeshDevice,
+    ) {
+        let (cached, _) = cache.generate(graph, device);
+        let full = generate_graph_edges_geojson(graph, device);
+
+        assert_eq!(cached.bbox, full.bbox);
+        assert_eq!(cached.foreign_members, full.foreign_members);
+        assert_eq!(without_ages(cached), without_ages(full));
+    }
+
+    #[test]
+    fn matches_full_generation_as_the_graph_changes() {
+        let (mut graph, mut device) = ring(6);
+        let mut cache = EdgeFeatureCache::default();
+
+        assert_matches_full_generation(&mut cache, &graph, &device);
+
+        // An edge back, changing the reverse weight of the one it mirrors
+        graph.upsert_edge(graph_node(2), graph_node(1), edge(2, 1, -3.0));
+        assert_matches_full_generation(&mut cache, &graph, &device);
+
+        // A new link quality sample
+        graph.record_link_sample(3, 4, sample(1_700_000_100, 9.5));
+        assert_matches_full_generation(&mut cache, &graph, &device);
+
+        // A node moving and another being renamed
+        device.nodes.insert(5, positioned_node(5, 48.0, -121.0));
+        device
+            .nodes
+            .get_mut(&6)
+            .unwrap()
+            .user
+            .as_mut()
+            .unwrap()
+            .long_name = "Hilltop".into();
+        assert_matches_full_generation(&mut cache, &graph, &device);
+
+        // A node losing its position, one being hidden and an edge being removed
+        device.nodes.insert(3, MeshNode::new(3));
+        graph.set_node_hidden(4, true);
+        graph.remove_edge(graph_node(6), graph_node(1));
+        assert_matches_full_generation(&mut cache, &graph, &device);
+
+        graph.set_node_hidden(4, false);
+        assert_matches_full_generation(&mut cache, &graph, &device);
+    }
+
+    #[test]
+    fn updating_one_edge_regenerates_only_its_feature() {
+        let (mut graph, mut device) = ring(RING_SIZE);
+        let mut cache = EdgeFeatureCache::default();
+
+        let started = Instant::now();
+        let (collection, generated) = cache.generate(&graph, &device);
+        let cold = started.elapsed();
+
+        assert_eq!(collection.features.len(), RING_SIZE as usize);
+        assert_eq!(generated, RING_SIZE as usize);
+
+        graph.set_edge_snr(10, 11, -7.25);
+        graph.record_link_sample(10, 11, sample(1_700_000_100, -7.25));
+
+        let started = Instant::now();
+        let (collection, generated) = cache.generate(&graph, &device);
+        let warm = started.elapsed();
+
+        assert_eq!(collection.features.len(), RING_SIZE as usize);
+        assert_eq!(generated, 1);
+        assert!(
+            warm < cold,
+            "regenerating after one update took {:?}, generating everything {:?}",
+            warm,
+            cold
+        );
+
+        // Moving a node changes the edges to and from it
+        device.nodes.insert(500, positioned_node(500, 46.0, -120.0));
+        assert_eq!(cache.generate(&graph, &device).1, 2);
+
+        // Nothing changed
+        assert_eq!(cache.generate(&graph, &device).1, 0);
+    }
+}
@@ -0,0 +1,1010 @@
+use chrono::NaiveDateTime;
+use geojson::{feature, Bbox, Feature, FeatureCollection, Geometry, JsonObject, Position, Value};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::device::{helpers::get_current_time_u32, MeshDevice};
+use crate::state::DeviceKey;
+
+use super::ds::{edge::GraphEdge, graph::MeshGraph, link_quality::link_key, node::GraphNode};
+
+/// Foreign member of the node collection counting nodes left out for lack of a position
+pub const UNPOSITIONED_NODES_MEMBER: &str = "unpositionedNodes";
+
+/// Foreign member of the node collection listing the nums of those nodes, in ascending
+/// order, so they can still be listed alongside the map
+pub const UNPOSITIONED_NODE_NUMS_MEMBER: &str = "unpositionedNodeNums";
+
+/// Foreign member flagging collections whose bbox likely straddles the antimeridian
+pub const CROSSES_ANTIMERIDIAN_MEMBER: &str = "crossesAntimeridian";
+
+/// Property keys of node features
+pub mod node_properties {
+    pub const NUM: &str = "num";
+    pub const ID: &str = "id"; // `!xxxxxxxx` formatted node id
+    pub const LONG_NAME: &str = "longName";
+    pub const SHORT_NAME: &str = "shortName";
+    pub const DISPLAY_LABEL: &str = "displayLabel"; // operator's label, falling back to the long name
+    pub const DEGREE: &str = "degree"; // number of edges to or from the node
+    pub const WEIGHTED_DEGREE: &str = "weightedDegree"; // sum of the weights of those edges
+    pub const BATTERY_LEVEL: &str = "batteryLevel";
+    pub const LAST_HEARD: &str = "lastHeard"; // unix timestamp, secs
+    pub const IS_SELF: &str = "isSelf";
+    pub const POSITION_STALE: &str = "positionStale"; // placed at its archived position, the radio no longer has one
+    pub const POSITION_AGE_SECS: &str = "positionAgeSecs"; // `null` if the position has no time
+    pub const HISTORICAL: &str = "historical"; // stored node the radio's node database no longer has
+
+    /// Derived from graph analysis rather than reported by the node
+    pub const ANALYTICS: [&str; 2] = [DEGREE, WEIGHTED_DEGREE];
+}
+
+/// Property keys of edge features. The map layers style and label lines using these,
+/// so they must not change without updating the frontend.
+pub mod edge_properties {
+    pub const FROM: &str = "from"; // node num the edge was reported by
+    pub const TO: &str = "to"; // node num the edge points to
+    pub const FROM_ID: &str = "fromId"; // `!xxxxxxxx` formatted node id
+    pub const TO_ID: &str = "toId";
+    pub const FROM_NAME: &str = "fromName"; // long name, `null` if the node hasn't sent its user info
+    pub const TO_NAME: &str = "toName";
+    pub const CHANNEL: &str = "channel";
+    pub const WEIGHT: &str = "weight"; // weight of the link in both directions
+    pub const WEIGHT_FORWARD: &str = "weightForward"; // weight of this edge
+    pub const WEIGHT_REVERSE: &str = "weightReverse"; // `null` if there is no edge back
+    pub const COST: &str = "cost"; // weight of this edge under the graph's `EdgeWeightStrategy`
+    pub const SNR: &str = "snr"; // last SNR sample, `null` without link quality history
+    pub const RSSI: &str = "rssi"; // last RSSI sample, `null` unless heard directly
+    pub const SOURCE: &str = "source"; // see `EdgeSource`
+    pub const AGE_SECS: &str = "ageSecs"; // seconds since the edge was last heard
+    pub const LAST_HEARD: &str = "lastHeard"; // unix timestamp, secs
+    pub const PARALLEL_INDEX: &str = "parallelIndex"; // index in the id, `null` if there is no edge back
+    pub const OBSERVED_BY: &str = "observedBy"; // keys of the devices that reported the edge, sorted
+    pub const IS_BRIDGE: &str = "isBridge"; // `null` until bridge analysis has run
+
+    pub const ALL: [&str; 19] = [
+        FROM,
+        TO,
+        FROM_ID,
+        TO_ID,
+        FROM_NAME,
+        TO_NAME,
+        CHANNEL,
+        WEIGHT,
+        WEIGHT_FORWARD,
+        WEIGHT_REVERSE,
+        COST,
+        SNR,
+        RSSI,
+        SOURCE,
+        AGE_SECS,
+        LAST_HEARD,
+        PARALLEL_INDEX,
+        OBSERVED_BY,
+        IS_BRIDGE,
+    ];
+
+    /// Derived from graph analysis rather than reported by the nodes
+    pub const ANALYTICS: [&str; 1] = [IS_BRIDGE];
+}
+
+/// Property keys of waypoint features
+pub mod waypoint_properties {
+    pub const ID: &str = "id";
+    pub const NAME: &str = "name";
+    pub const DESCRIPTION: &str = "description";
+    pub const ICON: &str = "icon"; // emoji, `null` if the waypoint has none
+    pub const EXPIRE: &str = "expire"; // unix timestamp, secs, `null` if it never expires
+    pub const LOCKED_TO: &str = "lockedTo"; // node num, `null` if any node can edit it
+    pub const SENDER: &str = "sender"; // node num, `null` if it wasn't received
+    pub const SENDER_NAME: &str = "senderName"; // long name, `null` if unknown
+}
+
+/// Map layers for a device's graph, generated together so nodes and edges stay in sync
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphGeoJson {
+    pub device_key: DeviceKey,
+    pub nodes: FeatureCollection,
+    pub edges: FeatureCollection,
+}
+
+impl GraphGeoJson {
+    pub fn new(device_key: DeviceKey, graph: &MeshGraph, device: &MeshDevice) -> Self {
+        Self {
+            device_key,
+            nodes: generate_graph_nodes_geojson(graph, device),
+            edges: generate_graph_edges_geojson(graph, device),
+        }
+    }
+}
+
+/// Computes `[min lon, min lat, max lon, max lat]` across all positions, or `None`
+/// if there are none. Longitudes aren't wrapped, so a box around features on both
+/// sides of the antimeridian spans most of the globe; see `crosses_antimeridian`.
+pub fn bounding_box<'a>(positions: impl IntoIterator<Item = &'a Position>) -> Option<Bbox> {
+    let mut positions = positions.into_iter();
+    let first = positions.next()?;
+
+    let mut bbox = vec![first[0], first[1], first[0], first[1]];
+
+    for position in positions {
+        bbox[0] = bbox[0].min(position[0]);
+        bbox[1] = bbox[1].min(position[1]);
+        bbox[2] = bbox[2].max(position[0]);
+        bbox[3] = bbox[3].max(position[1]);
+    }
+
+    Some(bbox)
+}
+
+/// Whether a bounding box is wider than half the globe, in which case the features
+/// most likely straddle the antimeridian and the box shouldn't be used to fit the view
+pub fn crosses_antimeridian(bbox: &Bbox) -> bool {
+    bbox[2] - bbox[0] > 180.0
+}
+
+fn feature_positions(feature: &Feature) -> Vec<&Position> {
+    match feature.geometry.as_ref().map(|geometry| &geometry.value) {
+        Some(Value::Point(position)) => vec![position],
+        Some(Value::LineString(positions)) => positions.iter().collect(),
+        Some(Value::Polygon(rings)) => rings.iter().flatten().collect(),
+        Some(Value::MultiPolygon(polygons)) => polygons.iter().flatten().flatten().collect(),
+        _ => vec![],
+    }
+}
+
+/// Sets the collection's bbox and `crossesAntimeridian` foreign member from its features
+pub fn with_bbox(mut collection: FeatureCollection) -> FeatureCollection {
+    collection.bbox = bounding_box(collection.features.iter().flat_map(feature_positions));
+
+    if let Some(bbox) = &collection.bbox {
+        collection
+            .foreign_members
+            .get_or_insert_with(JsonObject::new)
+            .insert(
+                CROSSES_ANTIMERIDIAN_MEMBER.into(),
+                json!(crosses_antimeridian(bbox)),
+            );
+    }
+
+    collection
+}
+
+/// Decimal places weights and SNRs are rounded to, finer than radios report SNR
+const EDGE_WEIGHT_DECIMALS: i32 = 2;
+
+fn round_weight(weight: f64) -> f64 {
+    let scale = 10f64.powi(EDGE_WEIGHT_DECIMALS);
+    (weight * scale).round() / scale
+}
+
+/// Index of the edge among the edges between its endpoints, as in its
+/// `edge_feature_id`
+fn parallel_index(edge: &GraphEdge) -> u32 {
+    let (low, _) = link_key(edge.from(), edge.to());
+
+    if edge.from() == low {
+        0
+    } else {
+        1
+    }
+}
+
+/// Orders features by their id, so regenerating an unchanged graph gives the same
+/// collection whatever order its edges are stored in
+pub fn sort_features_by_id(features: &mut [Feature]) {
+    features.sort_by(|a, b| match (&a.id, &b.id) {
+        (Some(feature::Id::String(a)), Some(feature::Id::String(b))) => a.cmp(b),
+        _ => std::cmp::Ordering::Equal,
+    });
+}
+
+/// Identifies an edge feature as `"{low}-{high}#{index}:{source}"`, where `low` and
+/// `high` are the endpoint node nums in ascending order. The graph holds at most one
+/// edge per direction, so the parallel index is derived from the direction (0 when
+/// the edge points from `low` to `high`) rather than iteration order. The id stays
+/// the same across regenerations for as long as the edge exists.
+pub fn edge_feature_id(edge: &GraphEdge) -> String {
+    let (low, high) = link_key(edge.from(), edge.to());
+
+    format!(
+        "{}-{}#{}:{}",
+        low,
+        high,
+        parallel_index(edge),
+        edge.source.tag()
+    )
+}
+
+fn node_id(node_num: u32) -> String {
+    format!("!{:08x}", node_num)
+}
+
+pub fn node_long_name(device: &MeshDevice, node_num: u32) -> Option<&str> {
+    let user = device.nodes.get(&node_num)?.user.as_ref()?;
+
+    Some(&user.long_name)
+}
+
+/// Where a node is placed on the map
+struct NodeLocation {
+    coordinates: Vec<f64>,
+    reported_at: u32, // secs, zero if unknown
+    stale: bool,      // from the position archive
+}
+
+/// The node's position from the device, falling back to its archived position for
+/// nodes that dropped out of the radio's node database or came back without one
+fn node_location(graph: &MeshGraph, device: &MeshDevice, node_num: u32) -> Option<NodeLocation> {
+    let live = device
+        .nodes
+        .get(&node_num)
+        .and_then(|node| node.last_known_position());
+
+    if let Some(position) = live {
+        return Some(NodeLocation {
+            coordinates: vec![position.longitude.into(), position.latitude.into()],
+            reported_at: match position.time {
+                0 => position.timestamp,
+                time => time,
+            },
+            stale: false,
+        });
+    }
+
+    let archived = graph.position_archive.get(node_num)?;
+
+    Some(NodeLocation {
+        coordinates: vec![archived.longitude, archived.latitude],
+        reported_at: archived.timestamp,
+        stale: true,
+    })
+}
+
+pub fn node_coordinates(graph: &MeshGraph, device: &MeshDevice, node_num: u32) -> Option<Vec<f64>> {
+    node_location(graph, device, node_num).map(|location| location.coordinates)
+}
+
+/// Generates a Point feature for each graph node with a known position. Positions come
+/// from `device` since the graph only tracks connectivity, or from the graph's position
+/// archive for nodes the device has none for. Nodes without a position are counted in
+/// the `unpositionedNodes` foreign member and listed in `unpositionedNodeNums`. Hidden
+/// nodes are left out.
+pub fn generate_graph_nodes_geojson(graph: &MeshGraph, device: &MeshDevice) -> FeatureCollection {
+    use node_properties as props;
+
+    let my_node_num = device.my_node_info.my_node_num;
+    let now = get_current_time_u32();
+
+    let degrees = graph.node_degrees();
+
+    let mut unpositioned_nodes = vec![];
+    let mut features = vec![];
+
+    let mut graph_nodes: Vec<_> = graph
+        .nodes()
+        .filter(|node| !graph.overrides.is_hidden(node.node_num))
+        .collect();
+    graph_nodes.sort_by_key(|node| node.node_num);
+
+    for graph_node in graph_nodes {
+        let node_num = graph_node.node_num;
+
+        let location = match node_location(graph, device, node_num) {
+            Some(location) => location,
+            None => {
+                unpositioned_nodes.push(node_num);
+                continue;
+            }
+        };
+
+        let mesh_node = device.nodes.get(&node_num);
+        let user = mesh_node.and_then(|node| node.user.as_ref());
+        let (degree, weighted_degree) = degrees.get(&node_num).copied().unwrap_or_default();
+
+        let mut properties = JsonObject::new();
+        properties.insert(props::NUM.into(), json!(node_num));
+        properties.insert(props::ID.into(), json!(node_id(node_num)));
+        properties.insert(props::LONG_NAME.into(), json!(user.map(|u| &u.long_name)));
+        properties.insert(props::SHORT_NAME.into(), json!(user.map(|u| &u.short_name)));
+        properties.insert(
+            props::DISPLAY_LABEL.into(),
+            json!(graph.display_label(node_num, user.map(|u| u.long_name.as_str()))),
+        );
+        properties.insert(props::DEGREE.into(), json!(degree));
+        properties.insert(props::WEIGHTED_DEGREE.into(), json!(weighted_degree));
+        properties.insert(
+            props::BATTERY_LEVEL.into(),
+            json!(mesh_node.and_then(|node| node.battery_level())),
+        );
+        properties.insert(
+            props::LAST_HEARD.into(),
+            json!(graph_node.last_heard.and_utc().timestamp()),
+        );
+        properties.insert(props::IS_SELF.into(), json!(node_num == my_node_num));
+        properties.insert(props::POSITION_STALE.into(), json!(location.stale));
+        properties.insert(
+            props::HISTORICAL.into(),
+            json!(device.historical_nodes.contains(&node_num)),
+        );
+        properties.insert(
+            props::POSITION_AGE_SECS.into(),
+            json!((location.reported_at != 0).then(|| now.saturating_sub(location.reported_at))),
+        );
+
+        features.push(Feature {
+            bbox: None,
+            geometry: Some(Geometry::new(Value::Point(location.coordinates))),
+            id: Some(feature::Id::Number(node_num.into())),
+            properties: Some(properties),
+            foreign_members: None,
+        });
+    }
+
+    let mut foreign_members = JsonObject::new();
+    foreign_members.insert(
+        UNPOSITIONED_NODES_MEMBER.into(),
+        json!(unpositioned_nodes.len()),
+    );
+    foreign_members.insert(
+        UNPOSITIONED_NODE_NUMS_MEMBER.into(),
+        json!(unpositioned_nodes),
+    );
+
+    with_bbox(FeatureCollection {
+        bbox: None,
+        features,
+        foreign_members: Some(foreign_members),
+    })
+}
+
+/// Whether the edge from `from` to `to` is left off the map
+pub fn is_edge_hidden(graph: &MeshGraph, from: u32, to: u32) -> bool {
+    graph.overrides.is_hidden(from) || graph.overrides.is_hidden(to)
+}
+
+/// Seconds since the edge was last heard, as of `now`
+pub fn edge_age_secs(edge: &GraphEdge, now: NaiveDateTime) -> i64 {
+    (now - edge.last_heard).num_seconds().max(0)
+}
+
+/// Generates the LineString feature for the graph edge from `from` to `to`, or `None`
+/// if either endpoint has no known position
+pub fn generate_edge_feature(
+    graph: &MeshGraph,
+    device: &MeshDevice,
+    (from, to): (GraphNode, GraphNode),
+    edge: &GraphEdge,
+    now: NaiveDateTime,
+    now_secs: u32,
+) -> Option<Feature> {
+    use edge_properties as props;
+
+    let source = node_coordinates(graph, device, from.node_num)?;
+    let target = node_coordinates(graph, device, to.node_num)?;
+
+    let history = graph
+        .link_quality
+        .get(&link_key(from.node_num, to.node_num));
+
+    let weight = history
+        .and_then(|h| h.snr(&graph.link_snr_mode, now_secs))
+        .map(f64::from)
+        .unwrap_or(edge.snr());
+
+    let reverse_weight = graph.get_edge(to, from).map(|reverse| reverse.snr());
+
+    let aggregate = history.and_then(|h| h.aggregate(0));
+
+    let mut properties = JsonObject::new();
+    properties.insert(props::FROM.into(), json!(edge.from()));
+    properties.insert(props::TO.into(), json!(edge.to()));
+    properties.insert(props::FROM_ID.into(), json!(node_id(edge.from())));
+    properties.insert(props::TO_ID.into(), json!(node_id(edge.to())));
+    properties.insert(
+        props::FROM_NAME.into(),
+        json!(node_long_name(device, edge.from())),
+    );
+    properties.insert(
+        props::TO_NAME.into(),
+        json!(node_long_name(device, edge.to())),
+    );
+    properties.insert(props::CHANNEL.into(), json!(edge.channel));
+    properties.insert(props::WEIGHT.into(), json!(round_weight(weight)));
+    properties.insert(
+        props::WEIGHT_FORWARD.into(),
+        json!(round_weight(edge.snr())),
+    );
+    properties.insert(
+        props::WEIGHT_REVERSE.into(),
+        json!(reverse_weight.map(round_weight)),
+    );
+    properties.insert(props::COST.into(), json!(round_weight(edge.weight())));
+    properties.insert(
+        props::SNR.into(),
+        json!(aggregate
+            .as_ref()
+            .map(|a| round_weight(f64::from(a.last_snr)))),
+    );
+    properties.insert(
+        props::RSSI.into(),
+        json!(aggregate.and_then(|a| a.last_rssi)),
+    );
+    properties.insert(props::SOURCE.into(), json!(edge.source));
+    properties.insert(props::AGE_SECS.into(), json!(edge_age_secs(edge, now)));
+    properties.insert(
+        props::LAST_HEARD.into(),
+        json!(edge.last_heard.and_utc().timestamp()),
+    );
+    properties.insert(
+        props::PARALLEL_INDEX.into(),
+        json!(reverse_weight.map(|_| parallel_index(edge))),
+    );
+    properties.insert(props::OBSERVED_BY.into(), json!(edge.observed_by));
+    properties.insert(props::IS_BRIDGE.into(), json!(None::<bool>));
+
+    Some(Feature {
+        bbox: None,
+        geometry: Some(Geometry::new(Value::LineString(vec![source, target]))),
+        id: Some(feature::Id::String(edge_feature_id(edge))),
+        properties: Some(properties),
+        foreign_members: None,
+    })
+}
+
+/// Generates a LineString feature for each graph edge whose endpoints both have known
+/// positions and aren't hidden, with the properties listed in `edge_properties`.
+/// Both edges of a parallel pair get their own feature, and features are ordered by id.
+pub fn generate_graph_edges_geojson(graph: &MeshGraph, device: &MeshDevice) -> FeatureCollection {
+    let now = chrono::Utc::now().naive_utc();
+    let now_secs = get_current_time_u32();
+
+    let mut features: Vec<_> = graph
+        .edges()
+        .filter(|(from, to, _)| !is_edge_hidden(graph, from.node_num, to.node_num))
+        .filter_map(|(from, to, edge)| {
+            generate_edge_feature(graph, device, (from, to), edge, now, now_secs)
+        })
+        .collect();
+
+    sort_features_by_id(&mut features);
+
+    with_bbox(FeatureCollection {
+        bbox: None,
+        features,
+        foreign_members: None,
+    })
+}
+
+/// Points for the device's waypoints that haven't expired as of `now`, by id
+pub fn generate_waypoints_geojson(device: &MeshDevice, now: u32) -> FeatureCollection {
+    use waypoint_properties as props;
+
+    let mut waypoints: Vec<_> = device
+        .waypoints
+        .values()
+        .filter(|waypoint| !waypoint.is_expired(now))
+        .collect();
+    waypoints.sort_by_key(|waypoint| waypoint.id);
+
+    let features = waypoints
+        .into_iter()
+        .map(|waypoint| {
+            let mut properties = JsonObject::new();
+            properties.insert(props::ID.into(), json!(waypoint.id));
+            properties.insert(props::NAME.into(), json!(waypoint.name));
+            properties.insert(props::DESCRIPTION.into(), json!(waypoint.description));
+            properties.insert(
+                props::ICON.into(),
+                json!(char::from_u32(waypoint.icon)
+                    .filter(|_| waypoint.icon != 0)
+                    .map(String::from)),
+            );
+            properties.insert(
+                props::EXPIRE.into(),
+                json!((waypoint.expire != 0).then_some(waypoint.expire)),
+            );
+            properties.insert(
+                props::LOCKED_TO.into(),
+                json!((waypoint.locked_to != 0).then_some(waypoint.locked_to)),
+            );
+            properties.insert(props::SENDER.into(), json!(waypoint.sender));
+            properties.insert(
+                props::SENDER_NAME.into(),
+                json!(waypoint
+                    .sender
+                    .and_then(|sender| node_long_name(device, sender))),
+            );
+
+            Feature {
+                bbox: None,
+                geometry: Some(Geometry::new(Value::Point(vec![
+                    waypoint.longitude.into(),
+                    waypoint.latitude.into(),
+                ]))),
+                id: Some(feature::Id::Number(waypoint.id.into())),
+                properties: Some(properties),
+                foreign_members: None,
+            }
+        })
+        .collect();
+
+    with_bbox(FeatureCollection {
+        bbox: None,
+        features,
+        foreign_members: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::{MeshNode, NormalizedPosition, NormalizedWaypoint};
+    use crate::graph::ds::fixtures::{edge, graph_node};
+    use crate::graph::ds::link_quality::LinkQualitySample;
+
+    fn positioned_node(node_num: u32, latitude: f32, longitude: f32) -> MeshNode {
+        let mut node = MeshNode::new(node_num);
+        node.user = Some(protobufs::User {
+            long_name: format!("Node {}", node_num),
+            short_name: format!("N{}", node_num),
+            ..Default::default()
+        });
+        node.position_metrics
+            .push(NormalizedPosition::from(protobufs::Position {
+                latitude_i: (latitude * 1e7) as i32,
+                longitude_i: (longitude * 1e7) as i32,
+                ..Default::default()
+            }));
+        node
+    }
+
+    /// Nodes 1 (self) and 2 have positions, node 3 doesn't
+    fn fixture() -> (MeshGraph, MeshDevice) {
+        let mut graph = MeshGraph::new();
+
+        for node_num in 1..=3 {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        graph.upsert_edge(graph_node(2), graph_node(1), edge(2, 1, 6.0));
+        graph.upsert_edge(graph_node(3), graph_node(1), edge(3, 1, -2.0));
+
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 1;
+        device.nodes.insert(1, positioned_node(1, 47.6, -122.3));
+        device.nodes.insert(2, positioned_node(2, 47.7, -122.4));
+        device.nodes.insert(3, MeshNode::new(3));
+
+        (graph, device)
+    }
+
+    #[test]
+    fn bounds_single_point() {
+        let positions = [vec![-122.3, 47.6]];
+
+        assert_eq!(
+            bounding_box(&positions),
+            Some(vec![-122.3, 47.6, -122.3, 47.6])
+        );
+        assert_eq!(bounding_box(&Vec::<Position>::new()), None);
+    }
+
+    #[test]
+    fn bounds_spread_out_points() {
+        let positions = [vec![10.0, 50.0], vec![-5.5, 40.0], vec![2.0, 60.25]];
+        let bbox = bounding_box(&positions).unwrap();
+
+        assert_eq!(bbox, vec![-5.5, 40.0, 10.0, 60.25]);
+        assert!(!crosses_antimeridian(&bbox));
+    }
+
+    #[test]
+    fn bounds_western_and_southern_hemisphere_points() {
+        let positions = [vec![-70.6, -33.4], vec![-58.4, -34.6], vec![-77.0, -12.0]];
+        let bbox = bounding_box(&positions).unwrap();
+
+        assert_eq!(bbox, vec![-77.0, -34.6, -58.4, -12.0]);
+        assert!(!crosses_antimeridian(&bbox));
+
+        let pacific = [vec![179.5, -17.7], vec![-179.8, -16.5]];
+        assert!(crosses_antimeridian(&bounding_box(&pacific).unwrap()));
+    }
+
+    #[test]
+    fn sets_collection_bboxes() {
+        let (graph, device) = fixture();
+
+        let nodes = generate_graph_nodes_geojson(&graph, &device);
+        let edges = generate_graph_edges_geojson(&graph, &device);
+
+        let expected = bounding_box(&[
+            node_coordinates(&graph, &device, 1).unwrap(),
+            node_coordinates(&graph, &device, 2).unwrap(),
+        ]);
+        assert_eq!(nodes.bbox, expected);
+        assert_eq!(edges.bbox, expected);
+        assert_eq!(
+            edges.foreign_members.unwrap()["crossesAntimeridian"],
+            json!(false)
+        );
+
+        let empty = generate_graph_edges_geojson(&MeshGraph::new(), &device);
+        assert_eq!(empty.bbox, None);
+        assert_eq!(empty.foreign_members, None);
+    }
+
+    #[test]
+    fn generates_point_per_positioned_node() {
+        let (graph, device) = fixture();
+        let collection = generate_graph_nodes_geojson(&graph, &device);
+
+        assert_eq!(collection.features.len(), 2);
+
+        let foreign_members = collection.foreign_members.as_ref().unwrap();
+        assert_eq!(foreign_members["unpositionedNodes"], json!(1));
+        assert_eq!(foreign_members["unpositionedNodeNums"], json!([3]));
+
+        let properties = collection.features[0].properties.as_ref().unwrap();
+
+        assert_eq!(properties["num"], json!(1));
+        assert_eq!(properties["id"], json!("!00000001"));
+        assert_eq!(properties["longName"], json!("Node 1"));
+        assert_eq!(properties["shortName"], json!("N1"));
+        assert_eq!(properties["degree"], json!(2));
+        assert_eq!(properties["weightedDegree"], json!(4.0));
+        assert_eq!(properties["batteryLevel"], json!(null));
+        assert_eq!(properties["isSelf"], json!(true));
+        assert!(properties.contains_key("lastHeard"));
+
+        let other = collection.features[1].properties.as_ref().unwrap();
+        assert_eq!(other["isSelf"], json!(false));
+        assert_eq!(other["degree"], json!(1));
+    }
+
+    #[test]
+    fn prefers_operator_label_over_long_name() {
+        let (mut graph, device) = fixture();
+        graph.set_node_label(2, Some("Dad's truck".into()));
+
+        let collection = generate_graph_nodes_geojson(&graph, &device);
+        let labelled = collection.features[1].properties.as_ref().unwrap();
+        let unlabelled = collection.features[0].properties.as_ref().unwrap();
+
+        assert_eq!(labelled["displayLabel"], json!("Dad's truck"));
+        assert_eq!(labelled["longName"], json!("Node 2"));
+        assert_eq!(labelled["id"], json!("!00000002"));
+        assert_eq!(unlabelled["displayLabel"], json!("Node 1"));
+    }
+
+    #[test]
+    fn backfills_positions_after_node_db_rollover() {
+        use crate::graph::ds::position_archive::{ArchivedPosition, PositionSource};
+
+        let (mut graph, mut device) = fixture();
+        let reported_at = get_current_time_u32() - 3_600;
+
+        graph.position_archive.record(
+            2,
+            ArchivedPosition {
+                latitude: 47.65,
+                longitude: -122.35,
+                altitude: 30,
+                source: PositionSource::PositionPacket,
+                timestamp: reported_at,
+            },
+        );
+
+        // A live position is preferred over the archived one
+        let collection = generate_graph_nodes_geojson(&graph, &device);
+        let live = collection.features[1].properties.as_ref().unwrap();
+        assert_eq!(live["num"], json!(2));
+        assert_eq!(live["positionStale"], json!(false));
+        assert_eq!(live["positionAgeSecs"], json!(null));
+
+        // The radio's node DB rolls over, dropping node 2
+        device.nodes.remove(&2);
+
+        let collection = generate_graph_nodes_geojson(&graph, &device);
+        assert_eq!(collection.features.len(), 2);
+
+        let stale = &collection.features[1];
+        let properties = stale.properties.as_ref().unwrap();
+        assert_eq!(properties["num"], json!(2));
+        assert_eq!(properties["positionStale"], json!(true));
+        assert_eq!(properties["longName"], json!(null));
+
+        let age = properties["positionAgeSecs"].as_u64().unwrap();
+        assert!((3_600..3_660).contains(&age));
+        assert_eq!(
+            stale.geometry.as_ref().unwrap().value,
+            Value::Point(vec![-122.35, 47.65])
+        );
+
+        // It comes back without a position, and is still placed and linked
+        let mut returned = MeshNode::new(2);
+        returned.user = Some(protobufs::User {
+            long_name: "Node 2".into(),
+            ..Default::default()
+        });
+        device.nodes.insert(2, returned);
+
+        let collection = generate_graph_nodes_geojson(&graph, &device);
+        let properties = collection.features[1].properties.as_ref().unwrap();
+        assert_eq!(properties["positionStale"], json!(true));
+        assert_eq!(properties["longName"], json!("Node 2"));
+
+        let edges = generate_graph_edges_geojson(&graph, &device);
+        assert_eq!(edges.features.len(), 1);
+
+        // Purging the archived position leaves the node unplaced
+        graph.position_archive.remove(2);
+        let collection = generate_graph_nodes_geojson(&graph, &device);
+        assert_eq!(collection.features.len(), 1);
+
+        let foreign_members = collection.foreign_members.unwrap();
+        assert_eq!(foreign_members["unpositionedNodes"], json!(2));
+        assert_eq!(foreign_members["unpositionedNodeNums"], json!([2, 3]));
+    }
+
+    #[test]
+    fn hidden_nodes_are_left_out_with_their_edges() {
+        let (mut graph, device) = fixture();
+        graph.set_node_hidden(2, true);
+
+        let nodes = generate_graph_nodes_geojson(&graph, &device);
+        let edges = generate_graph_edges_geojson(&graph, &device);
+
+        assert_eq!(nodes.features.len(), 1);
+        assert_eq!(
+            nodes.foreign_members.unwrap()["unpositionedNodes"],
+            json!(1)
+        );
+        assert!(edges.features.is_empty());
+        assert!(graph.contains_node(2));
+
+        graph.set_node_hidden(2, false);
+        assert_eq!(
+            generate_graph_edges_geojson(&graph, &device).features.len(),
+            1
+        );
+    }
+
+    #[test]
+    fn omits_edges_without_positioned_endpoints() {
+        let (graph, device) = fixture();
+        let collection = generate_graph_edges_geojson(&graph, &device);
+
+        assert_eq!(collection.features.len(), 1);
+
+        let properties = collection.features[0].properties.as_ref().unwrap();
+        assert_eq!(properties[edge_properties::FROM], json!(2));
+        assert_eq!(properties[edge_properties::TO], json!(1));
+    }
+
+    fn edge_feature_ids(collection: &FeatureCollection) -> Vec<String> {
+        let mut ids: Vec<String> = collection
+            .features
+            .iter()
+            .map(|feature| match &feature.id {
+                Some(feature::Id::String(id)) => id.clone(),
+                id => panic!("unexpected feature id {:?}", id),
+            })
+            .collect();
+
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn identifies_parallel_edges_uniquely() {
+        let (mut graph, device) = fixture();
+        graph.upsert_edge(graph_node(1), graph_node(2), edge(1, 2, 3.0));
+
+        let ids = edge_feature_ids(&generate_graph_edges_geojson(&graph, &device));
+
+        assert_eq!(ids, vec!["1-2#0:neighbor_info", "1-2#1:neighbor_info"]);
+    }
+
+    #[test]
+    fn keeps_edge_ids_stable_across_insertion_order() {
+        let (_, device) = fixture();
+
+        let mut forward = MeshGraph::new();
+        let mut reversed = MeshGraph::new();
+
+        for node_num in 1..=2 {
+            forward.upsert_node(graph_node(node_num));
+            reversed.upsert_node(graph_node(3 - node_num));
+        }
+
+        forward.upsert_edge(graph_node(2), graph_node(1), edge(2, 1, 6.0));
+        forward.upsert_edge(graph_node(1), graph_node(2), edge(1, 2, 3.0));
+        reversed.upsert_edge(graph_node(1), graph_node(2), edge(1, 2, 3.0));
+        reversed.upsert_edge(graph_node(2), graph_node(1), edge(2, 1, 6.0));
+
+        let forward_ids = edge_feature_ids(&generate_graph_edges_geojson(&forward, &device));
+        let reversed_ids = edge_feature_ids(&generate_graph_edges_geojson(&reversed, &device));
+
+        assert_eq!(forward_ids, reversed_ids);
+
+        // Removing one direction doesn't renumber the other
+        forward.remove_edge(graph_node(1), graph_node(2));
+
+        assert_eq!(
+            edge_feature_ids(&generate_graph_edges_geojson(&forward, &device)),
+            vec!["1-2#1:neighbor_info"]
+        );
+    }
+
+    #[test]
+    fn populates_edge_properties() {
+        use edge_properties as props;
+
+        let (mut graph, device) = fixture();
+        graph.upsert_edge(graph_node(1), graph_node(2), edge(1, 2, 3.0));
+        graph.record_link_sample(
+            2,
+            1,
+            LinkQualitySample {
+                timestamp: get_current_time_u32(),
+                snr: 5.5,
+                rssi: Some(-95),
+                observer: "mock".into(),
+            },
+        );
+
+        let collection = generate_graph_edges_geojson(&graph, &device);
+        let properties = collection
+            .features
+            .iter()
+            .filter_map(|feature| feature.properties.as_ref())
+            .find(|properties| properties[props::FROM] == json!(2))
+            .unwrap();
+
+        assert_eq!(properties[props::TO], json!(1));
+        assert_eq!(properties[props::FROM_ID], json!("!00000002"));
+        assert_eq!(properties[props::TO_ID], json!("!00000001"));
+        assert_eq!(properties[props::FROM_NAME], json!("Node 2"));
+        assert_eq!(properties[props::TO_NAME], json!("Node 1"));
+        assert_eq!(properties[props::CHANNEL], json!(0));
+        assert_eq!(properties[props::WEIGHT], json!(5.5));
+        assert_eq!(properties[props::WEIGHT_FORWARD], json!(6.0));
+        assert_eq!(properties[props::WEIGHT_REVERSE], json!(3.0));
+        assert_eq!(properties[props::COST], json!(1.4));
+        assert_eq!(properties[props::SNR], json!(5.5));
+        assert_eq!(properties[props::RSSI], json!(-95));
+        assert_eq!(properties[props::SOURCE], json!("neighborInfo"));
+        assert!(properties[props::AGE_SECS].as_i64().unwrap() >= 0);
+    }
+
+    #[test]
+    fn serializes_parallel_edges_in_id_order() {
+        use edge_properties as props;
+
+        let (mut graph, device) = fixture();
+        graph.upsert_edge(graph_node(1), graph_node(2), edge(1, 2, 3.333_333));
+
+        let collection = generate_graph_edges_geojson(&graph, &device);
+        let serialized = serde_json::to_value(&collection).unwrap();
+        let features = serialized["features"].as_array().unwrap();
+
+        assert_eq!(features.len(), 2);
+
+        let expected = [
+            ("1-2#0:neighbor_info", 1, 2, 3.33, 6.0, 0),
+            ("1-2#1:neighbor_info", 2, 1, 6.0, 3.33, 1),
+        ];
+
+        for (feature, (id, from, to, forward, reverse, index)) in features.iter().zip(expected) {
+            let properties = &feature["properties"];
+
+            assert_eq!(feature["id"], json!(id));
+            assert_eq!(properties[props::FROM], json!(from));
+            assert_eq!(properties[props::TO], json!(to));
+            assert_eq!(properties[props::FROM_ID], json!(node_id(from)));
+            assert_eq!(properties[props::TO_ID], json!(node_id(to)));
+            assert_eq!(
+                properties[props::FROM_NAME],
+                json!(format!("Node {}", from))
+            );
+            assert_eq!(properties[props::TO_NAME], json!(format!("Node {}", to)));
+            assert_eq!(properties[props::CHANNEL], json!(0));
+            assert_eq!(properties[props::WEIGHT], json!(forward));
+            assert_eq!(properties[props::WEIGHT_FORWARD], json!(forward));
+            assert_eq!(properties[props::WEIGHT_REVERSE], json!(reverse));
+            assert_eq!(properties[props::SNR], json!(null));
+            assert_eq!(properties[props::RSSI], json!(null));
+            assert_eq!(properties[props::SOURCE], json!("neighborInfo"));
+            assert_eq!(properties[props::PARALLEL_INDEX], json!(index));
+            assert_eq!(properties[props::IS_BRIDGE], json!(null));
+            assert!(properties[props::AGE_SECS].as_i64().unwrap() >= 0);
+            assert!(properties[props::LAST_HEARD].as_i64().unwrap() > 0);
+        }
+
+        // A lone edge has no parallel index
+        graph.remove_edge(graph_node(1), graph_node(2));
+        let collection = generate_graph_edges_geojson(&graph, &device);
+        let properties = collection.features[0].properties.as_ref().unwrap();
+        assert_eq!(properties[props::PARALLEL_INDEX], json!(null));
+    }
+
+    #[test]
+    fn serializes_absent_edge_properties_as_null() {
+        use edge_properties as props;
+
+        let (graph, device) = fixture();
+        let collection = generate_graph_edges_geojson(&graph, &device);
+        let properties = collection.features[0].properties.as_ref().unwrap();
+
+        for key in props::ALL {
+            assert!(properties.contains_key(key), "missing property {}", key);
+        }
+
+        assert_eq!(properties.len(), props::ALL.len());
+
+        for key in [
+            props::WEIGHT_REVERSE,
+            props::SNR,
+            props::RSSI,
+            props::PARALLEL_INDEX,
+            props::IS_BRIDGE,
+        ] {
+            assert_eq!(properties[key], serde_json::Value::Null, "{} not null", key);
+        }
+
+        let serialized = serde_json::to_value(&collection).unwrap();
+        assert_eq!(
+            serialized["features"][0]["properties"][props::RSSI],
+            serde_json::Value::Null
+        );
+    }
+
+    #[test]
+    fn generates_point_per_unexpired_waypoint() {
+        use waypoint_properties as props;
+
+        let (_, mut device) = fixture();
+
+        for (id, expire, sender) in [(5, 0, Some(2)), (4, 2_000, None), (6, 900, Some(3))] {
+            device.add_waypoint(NormalizedWaypoint {
+                id,
+                latitude: 47.5,
+                longitude: -122.2,
+                expire,
+                locked_to: 0,
+                name: format!("Waypoint {}", id),
+                description: String::new(),
+                icon: if id == 5 { 0x1F3D5 } else { 0 },
+                sender,
+            });
+        }
+
+        let collection = generate_waypoints_geojson(&device, 1_000);
+        let ids: Vec<_> = collection
+            .features
+            .iter()
+            .map(|feature| feature.id.clone())
+            .collect();
+
+        assert_eq!(
+            ids,
+            vec![
+                Some(feature::Id::Number(4.into())),
+                Some(feature::Id::Number(5.into()))
+            ]
+        );
+
+        let properties = collection.features[1].properties.as_ref().unwrap();
+        assert_eq!(properties[props::ICON], json!("\u{1F3D5}"));
+        assert_eq!(properties[props::EXPIRE], serde_json::Value::Null);
+        assert_eq!(properties[props::SENDER_NAME], json!("Node 2"));
+
+        let properties = collection.features[0].properties.as_ref().unwrap();
+        assert_eq!(properties[props::ICON], serde_json::Value::Null);
+        assert_eq!(properties[props::EXPIRE], json!(2_000));
+        assert_eq!(properties[props::SENDER], serde_json::Value::Null);
+    }
+}
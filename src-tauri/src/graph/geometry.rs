@@ -0,0 +1,283 @@
+use std::f64::consts::{PI, TAU};
+
+use crate::device::helpers::EARTH_RADIUS_METERS;
+
+/// Points on a full circle around a node, fewer are used for partial arcs
+pub const ARC_SEGMENTS_PER_TURN: f64 = 32.0;
+
+/// Points closer than this are treated as co-located
+const MIN_POINT_SPACING_METERS: f64 = 0.01;
+
+/// Inside joins of turns sharper than about 155° are cut at the path's vertex
+const MIN_MITER_DENOMINATOR: f64 = 0.1;
+
+pub type Point = (f64, f64); // meters east and north of the projection center
+
+/// Equirectangular projection around a center, accurate to well under a percent for
+/// the tens of kilometers a mesh spans
+pub struct LocalProjection {
+    center_longitude: f64,
+    center_latitude: f64,
+    meters_per_radian_longitude: f64,
+}
+
+impl LocalProjection {
+    pub fn around(coordinates: &[Vec<f64>]) -> Self {
+        let count = coordinates.len() as f64;
+        let center_longitude = coordinates.iter().map(|c| c[0]).sum::<f64>() / count;
+        let center_latitude = coordinates.iter().map(|c| c[1]).sum::<f64>() / count;
+
+        Self {
+            center_longitude,
+            center_latitude,
+            meters_per_radian_longitude: EARTH_RADIUS_METERS * center_latitude.to_radians().cos(),
+        }
+    }
+
+    pub fn project(&self, coordinate: &[f64]) -> Point {
+        (
+            (coordinate[0] - self.center_longitude).to_radians() * self.meters_per_radian_longitude,
+            (coordinate[1] - self.center_latitude).to_radians() * EARTH_RADIUS_METERS,
+        )
+    }
+
+    pub fn unproject(&self, (x, y): Point) -> Vec<f64> {
+        vec![
+            self.center_longitude + (x / self.meters_per_radian_longitude).to_degrees(),
+            self.center_latitude + (y / EARTH_RADIUS_METERS).to_degrees(),
+        ]
+    }
+}
+
+fn distance(a: Point, b: Point) -> f64 {
+    (b.0 - a.0).hypot(b.1 - a.1)
+}
+
+/// Unit vector to the left of the direction from `a` to `b`
+fn left_normal(a: Point, b: Point) -> Point {
+    let length = distance(a, b);
+    (-(b.1 - a.1) / length, (b.0 - a.0) / length)
+}
+
+fn offset(point: Point, normal: Point, buffer: f64) -> Point {
+    (point.0 + normal.0 * buffer, point.1 + normal.1 * buffer)
+}
+
+/// Points on the arc around `center`, clockwise from `from_angle` through `sweep` radians
+fn arc(center: Point, buffer: f64, from_angle: f64, sweep: f64, points: &mut Vec<Point>) {
+    let steps = (sweep / TAU * ARC_SEGMENTS_PER_TURN).ceil().max(1.0) as usize;
+
+    for step in 0..=steps {
+        let angle = from_angle - sweep * step as f64 / steps as f64;
+        points.push((
+            center.0 + buffer * angle.cos(),
+            center.1 + buffer * angle.sin(),
+        ));
+    }
+}
+
+/// Offset line along the left of the path. Joins on the outside of a turn are rounded,
+/// joins on the inside meet where the two offset segments cross, or at the vertex for
+/// turns so sharp that the crossing would be far beyond the corridor.
+fn left_side(path: &[Point], buffer: f64, points: &mut Vec<Point>) {
+    let normals: Vec<Point> = path.windows(2).map(|w| left_normal(w[0], w[1])).collect();
+
+    points.push(offset(path[0], normals[0], buffer));
+
+    for (vertex, n) in path[1..path.len() - 1].iter().zip(normals.windows(2)) {
+        let (a, b) = (n[0], n[1]);
+        let turns_left = a.0 * b.1 - a.1 * b.0 > 1e-9;
+        let cos_turn = a.0 * b.0 + a.1 * b.1;
+
+        if turns_left && 1.0 + cos_turn < MIN_MITER_DENOMINATOR {
+            points.push(*vertex);
+        } else if turns_left {
+            let scale = buffer / (1.0 + cos_turn);
+            points.push((
+                vertex.0 + (a.0 + b.0) * scale,
+                vertex.1 + (a.1 + b.1) * scale,
+            ));
+        } else {
+            let from_angle = a.1.atan2(a.0);
+            let sweep = (from_angle - b.1.atan2(b.0)).rem_euclid(TAU);
+            arc(*vertex, buffer, from_angle, sweep, points);
+        }
+    }
+
+    points.push(offset(
+        path[path.len() - 1],
+        normals[normals.len() - 1],
+        buffer,
+    ));
+}
+
+/// Counter-clockwise ring, closed by repeating its first point, enclosing everything
+/// within `buffer` meters of the path. A path that collapses to a single point gives
+/// a circle.
+pub fn buffer_ring(path: &[Point], buffer: f64) -> Vec<Point> {
+    let mut path = path.to_vec();
+    path.dedup_by(|b, a| distance(*a, *b) < MIN_POINT_SPACING_METERS);
+
+    let mut ring = vec![];
+
+    if path.len() < 2 {
+        arc(path[0], buffer, 0.0, TAU, &mut ring);
+        ring.pop();
+    } else {
+        let end_cap = |path: &[Point], ring: &mut Vec<Point>| {
+            let end = path[path.len() - 1];
+            let normal = left_normal(path[path.len() - 2], end);
+            arc(end, buffer, normal.1.atan2(normal.0), PI, ring);
+        };
+
+        left_side(&path, buffer, &mut ring);
+        end_cap(&path, &mut ring);
+
+        path.reverse();
+        left_side(&path, buffer, &mut ring);
+        end_cap(&path, &mut ring);
+
+        ring.dedup_by(|b, a| distance(*a, *b) < MIN_POINT_SPACING_METERS);
+
+        if distance(ring[0], ring[ring.len() - 1]) < MIN_POINT_SPACING_METERS {
+            ring.pop();
+        }
+    }
+
+    // Traced clockwise above, GeoJSON exterior rings are counter-clockwise
+    ring.reverse();
+    ring.push(ring[0]);
+    ring
+}
+
+/// Cross product of `a - origin` and `b - origin`, positive if `b` is counter-clockwise
+/// of `a` around `origin`
+fn cross(origin: Point, a: Point, b: Point) -> f64 {
+    (a.0 - origin.0) * (b.1 - origin.1) - (a.1 - origin.1) * (b.0 - origin.0)
+}
+
+/// Counter-clockwise convex hull of the points without collinear vertices, not closed.
+/// Gives fewer than 3 points if the points are all co-located or on a line.
+pub fn convex_hull(points: &[Point]) -> Vec<Point> {
+    let mut points = points.to_vec();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    points.dedup_by(|b, a| distance(*a, *b) < MIN_POINT_SPACING_METERS);
+
+    if points.len() < 3 {
+        return points;
+    }
+
+    // Andrew's monotone chain, the lower half left to right then the upper half back
+    let mut hull: Vec<Point> = vec![];
+
+    for pass in [points.clone(), points.into_iter().rev().collect()] {
+        let start = hull.len();
+
+        for point in pass {
+            while hull.len() >= start + 2
+                && cross(hull[hull.len() - 2], hull[hull.len() - 1], point) <= 0.0
+            {
+                hull.pop();
+            }
+
+            hull.push(point);
+        }
+
+        // The last point of each half is the first of the other
+        hull.pop();
+    }
+
+    hull
+}
+
+/// Counter-clockwise ring, closed by repeating its first point, enclosing everything
+/// within `buffer` meters of a convex hull. Hulls of fewer than 3 points are buffered
+/// as a path.
+pub fn buffer_hull_ring(hull: &[Point], buffer: f64) -> Vec<Point> {
+    if hull.len() < 3 {
+        return buffer_ring(hull, buffer);
+    }
+
+    // Traced clockwise so the outside of the hull is on the left of every edge
+    let clockwise: Vec<Point> = hull.iter().rev().copied().collect();
+    let count = clockwise.len();
+    let mut ring = vec![];
+
+    for (i, vertex) in clockwise.iter().enumerate() {
+        let incoming = left_normal(clockwise[(i + count - 1) % count], *vertex);
+        let outgoing = left_normal(*vertex, clockwise[(i + 1) % count]);
+
+        let from_angle = incoming.1.atan2(incoming.0);
+        let sweep = (from_angle - outgoing.1.atan2(outgoing.0)).rem_euclid(TAU);
+        arc(*vertex, buffer, from_angle, sweep, &mut ring);
+    }
+
+    ring.dedup_by(|b, a| distance(*a, *b) < MIN_POINT_SPACING_METERS);
+
+    if distance(ring[0], ring[ring.len() - 1]) < MIN_POINT_SPACING_METERS {
+        ring.pop();
+    }
+
+    ring.reverse();
+    ring.push(ring[0]);
+    ring
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shoelace area, positive for counter-clockwise rings
+    fn signed_area(ring: &[Point]) -> f64 {
+        ring.windows(2)
+            .map(|w| w[0].0 * w[1].1 - w[1].0 * w[0].1)
+            .sum::<f64>()
+            / 2.0
+    }
+
+    #[test]
+    fn hull_drops_interior_and_collinear_points() {
+        let points = [
+            (0.0, 0.0),
+            (50.0, 0.0), // on the bottom edge
+            (100.0, 0.0),
+            (100.0, 100.0),
+            (0.0, 100.0),
+            (40.0, 60.0), // inside
+            (0.0, 0.0),   // repeated
+        ];
+
+        let hull = convex_hull(&points);
+
+        assert_eq!(
+            hull,
+            [(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)]
+        );
+
+        assert_eq!(
+            convex_hull(&[(0.0, 0.0), (5.0, 5.0), (10.0, 10.0)]).len(),
+            2
+        );
+        assert_eq!(convex_hull(&[(1.0, 1.0), (1.0, 1.0)]).len(), 1);
+    }
+
+    #[test]
+    fn buffered_hull_adds_perimeter_strip_and_rounded_corners() {
+        let hull = convex_hull(&[(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)]);
+
+        let ring = buffer_hull_ring(&hull, 10.0);
+
+        assert_eq!(ring.first(), ring.last());
+
+        // Square, four 100 x 10 strips, and a full circle split across the corners
+        let expected = 100.0 * 100.0 + 4.0 * 100.0 * 10.0 + PI * 10.0 * 10.0;
+        let area = signed_area(&ring);
+        assert!(area > 0.0, "ring isn't counter-clockwise");
+        assert!((area - expected).abs() / expected < 0.005, "{}", area);
+
+        // A line of points is buffered like a path
+        let line = buffer_hull_ring(&convex_hull(&[(0.0, 0.0), (100.0, 0.0)]), 10.0);
+        let expected = 100.0 * 20.0 + PI * 10.0 * 10.0;
+        assert!((signed_area(&line) - expected).abs() / expected < 0.01);
+    }
+}
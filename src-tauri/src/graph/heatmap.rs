@@ -0,0 +1,385 @@
+use geojson::{Feature, FeatureCollection, Geometry, JsonObject, Value};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::device::{helpers::haversine_distance_meters, MeshDevice};
+
+use super::{ds::graph::MeshGraph, geojson::with_bbox};
+
+/// SNR mapped to a weight of 0, the LoRa demodulation floor at the slowest presets
+pub const HEATMAP_MIN_SNR: f64 = -20.0;
+
+/// SNR mapped to a weight of 1
+pub const HEATMAP_MAX_SNR: f64 = 10.0;
+
+/// Degree at and above which a node gets the full degree component of its weight
+pub const HEATMAP_DEGREE_SATURATION: u32 = 6;
+
+/// Share of a node's weight that comes from its mean incident SNR, the rest from degree
+pub const HEATMAP_SNR_SHARE: f64 = 0.7;
+
+/// Grids larger than this are coarsened to keep the layer renderable
+pub const MAX_HEATMAP_GRID_POINTS: usize = 10_000;
+
+const IDW_POWER: i32 = 2;
+const METERS_PER_DEGREE_LATITUDE: f64 = 111_320.0;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct HeatmapOptions {
+    pub interpolate: bool, // add grid points between nodes
+    pub grid_resolution_meters: f64,
+    pub cutoff_meters: f64, // nodes further than this from a grid point don't affect it
+}
+
+impl Default for HeatmapOptions {
+    fn default() -> Self {
+        Self {
+            interpolate: true,
+            grid_resolution_meters: 250.0,
+            cutoff_meters: 2_000.0,
+        }
+    }
+}
+
+impl HeatmapOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.grid_resolution_meters.is_finite() || self.grid_resolution_meters <= 0.0 {
+            return Err("Grid resolution must be positive".into());
+        }
+
+        if !self.cutoff_meters.is_finite() || self.cutoff_meters <= 0.0 {
+            return Err("Interpolation cutoff must be positive".into());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct WeightedNode {
+    node_num: u32,
+    latitude: f64,
+    longitude: f64,
+    mean_snr: Option<f64>,
+    degree: u32,
+    weight: f64,
+}
+
+/// Combines how well a node hears its neighbors with how many it has, in `[0, 1]`
+pub fn node_signal_weight(mean_snr: Option<f64>, degree: u32) -> f64 {
+    let snr_score = mean_snr
+        .map(|snr| ((snr - HEATMAP_MIN_SNR) / (HEATMAP_MAX_SNR - HEATMAP_MIN_SNR)).clamp(0.0, 1.0))
+        .unwrap_or(0.0);
+
+    let degree_score = (f64::from(degree) / f64::from(HEATMAP_DEGREE_SATURATION)).min(1.0);
+
+    HEATMAP_SNR_SHARE * snr_score + (1.0 - HEATMAP_SNR_SHARE) * degree_score
+}
+
+fn weighted_nodes(graph: &MeshGraph, device: &MeshDevice) -> Vec<WeightedNode> {
+    let degrees = graph.node_degrees();
+
+    let mut nodes: Vec<WeightedNode> = graph
+        .nodes()
+        .filter(|graph_node| !graph.overrides.is_hidden(graph_node.node_num))
+        .filter_map(|graph_node| {
+            let position = device
+                .nodes
+                .get(&graph_node.node_num)?
+                .last_known_position()?;
+
+            let (degree, snr_sum) = degrees
+                .get(&graph_node.node_num)
+                .copied()
+                .unwrap_or_default();
+
+            let mean_snr = if degree > 0 {
+                Some(snr_sum / f64::from(degree))
+            } else {
+                None
+            };
+
+            Some(WeightedNode {
+                node_num: graph_node.node_num,
+                latitude: position.latitude.into(),
+                longitude: position.longitude.into(),
+                mean_snr,
+                degree,
+                weight: node_signal_weight(mean_snr, degree),
+            })
+        })
+        .collect();
+
+    nodes.sort_by_key(|node| node.node_num);
+    nodes
+}
+
+/// Inverse-distance weighted mean of the weights of nodes within `cutoff_meters`
+fn interpolate(
+    nodes: &[WeightedNode],
+    latitude: f64,
+    longitude: f64,
+    cutoff_meters: f64,
+) -> Option<f64> {
+    let mut weighted_sum = 0.0;
+    let mut inverse_distance_sum = 0.0;
+
+    for node in nodes {
+        let distance =
+            haversine_distance_meters(latitude, longitude, node.latitude, node.longitude);
+
+        if distance > cutoff_meters {
+            continue;
+        }
+
+        // Close enough to be on top of the node
+        if distance < 1.0 {
+            return Some(node.weight);
+        }
+
+        let inverse_distance = 1.0 / distance.powi(IDW_POWER);
+        weighted_sum += node.weight * inverse_distance;
+        inverse_distance_sum += inverse_distance;
+    }
+
+    if inverse_distance_sum > 0.0 {
+        Some(weighted_sum / inverse_distance_sum)
+    } else {
+        None
+    }
+}
+
+fn point_feature(longitude: f64, latitude: f64, properties: JsonObject) -> Feature {
+    Feature {
+        bbox: None,
+        geometry: Some(Geometry::new(Value::Point(vec![longitude, latitude]))),
+        id: None,
+        properties: Some(properties),
+        foreign_members: None,
+    }
+}
+
+fn grid_features(nodes: &[WeightedNode], options: &HeatmapOptions) -> Vec<Feature> {
+    if nodes.is_empty() {
+        return vec![];
+    }
+
+    let min_lat = nodes
+        .iter()
+        .map(|n| n.latitude)
+        .fold(f64::INFINITY, f64::min);
+    let max_lat = nodes
+        .iter()
+        .map(|n| n.latitude)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let min_lon = nodes
+        .iter()
+        .map(|n| n.longitude)
+        .fold(f64::INFINITY, f64::min);
+    let max_lon = nodes
+        .iter()
+        .map(|n| n.longitude)
+        .fold(f64::NEG_INFINITY, f64::max);
+
+    // Extend the grid by the cutoff so the heat fades out around the outermost nodes
+    let meters_per_degree_longitude =
+        METERS_PER_DEGREE_LATITUDE * ((min_lat + max_lat) / 2.0).to_radians().cos().max(0.01);
+
+    let lat_margin = options.cutoff_meters / METERS_PER_DEGREE_LATITUDE;
+    let lon_margin = options.cutoff_meters / meters_per_degree_longitude;
+
+    let mut lat_step = options.grid_resolution_meters / METERS_PER_DEGREE_LATITUDE;
+    let mut lon_step = options.grid_resolution_meters / meters_per_degree_longitude;
+
+    let rows = |step: f64| ((max_lat - min_lat + 2.0 * lat_margin) / step).floor() as usize + 1;
+    let columns = |step: f64| ((max_lon - min_lon + 2.0 * lon_margin) / step).floor() as usize + 1;
+
+    let point_count = rows(lat_step) as f64 * columns(lon_step) as f64;
+
+    if point_count > MAX_HEATMAP_GRID_POINTS as f64 {
+        let scale = (point_count / MAX_HEATMAP_GRID_POINTS as f64).sqrt();
+        lat_step *= scale;
+        lon_step *= scale;
+    }
+
+    let mut features = vec![];
+
+    for row in 0..rows(lat_step) {
+        let latitude = min_lat - lat_margin + row as f64 * lat_step;
+
+        for column in 0..columns(lon_step) {
+            let longitude = min_lon - lon_margin + column as f64 * lon_step;
+
+            if let Some(weight) = interpolate(nodes, latitude, longitude, options.cutoff_meters) {
+                let mut properties = JsonObject::new();
+                properties.insert("kind".into(), json!("interpolated"));
+                properties.insert("weight".into(), json!(weight));
+
+                features.push(point_feature(longitude, latitude, properties));
+            }
+        }
+    }
+
+    features
+}
+
+/// Generates a heatmap layer with a Point per positioned node weighted by its mean
+/// incident SNR and degree, optionally followed by interpolated grid points. Positions
+/// come from `device` since the graph only tracks connectivity.
+pub fn generate_signal_heatmap_geojson(
+    graph: &MeshGraph,
+    device: &MeshDevice,
+    options: &HeatmapOptions,
+) -> FeatureCollection {
+    let nodes = weighted_nodes(graph, device);
+
+    let mut features: Vec<Feature> = nodes
+        .iter()
+        .map(|node| {
+            let mut properties = JsonObject::new();
+            properties.insert("kind".into(), json!("node"));
+            properties.insert("num".into(), json!(node.node_num));
+            properties.insert("meanSnr".into(), json!(node.mean_snr));
+            properties.insert("degree".into(), json!(node.degree));
+            properties.insert("weight".into(), json!(node.weight));
+
+            point_feature(node.longitude, node.latitude, properties)
+        })
+        .collect();
+
+    if options.interpolate {
+        features.extend(grid_features(&nodes, options));
+    }
+
+    with_bbox(FeatureCollection {
+        bbox: None,
+        features,
+        foreign_members: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::{MeshNode, NormalizedPosition};
+    use crate::graph::ds::fixtures::{edge, graph_node};
+
+    fn positioned_node(node_num: u32, latitude: f32, longitude: f32) -> MeshNode {
+        let mut node = MeshNode::new(node_num);
+        node.position_metrics
+            .push(NormalizedPosition::from(protobufs::Position {
+                latitude_i: (latitude * 1e7) as i32,
+                longitude_i: (longitude * 1e7) as i32,
+                ..Default::default()
+            }));
+        node
+    }
+
+    /// Node 1 hears node 2 at 5 dB and node 3 at -5 dB. Nodes 1 and 2 are about
+    /// 1 km apart, node 3 is about 20 km away.
+    fn fixture() -> (MeshGraph, MeshDevice) {
+        let mut graph = MeshGraph::new();
+
+        for node_num in 1..=3 {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        graph.upsert_edge(graph_node(2), graph_node(1), edge(2, 1, 5.0));
+        graph.upsert_edge(graph_node(3), graph_node(1), edge(3, 1, -5.0));
+
+        let mut device = MeshDevice::new();
+        device.nodes.insert(1, positioned_node(1, 40.0, -105.0));
+        device.nodes.insert(2, positioned_node(2, 40.009, -105.0));
+        device.nodes.insert(3, positioned_node(3, 40.18, -105.0));
+
+        (graph, device)
+    }
+
+    fn node_weight(collection: &FeatureCollection, node_num: u32) -> f64 {
+        collection
+            .features
+            .iter()
+            .filter_map(|feature| feature.properties.as_ref())
+            .find(|properties| properties.get("num") == Some(&json!(node_num)))
+            .and_then(|properties| properties["weight"].as_f64())
+            .unwrap()
+    }
+
+    #[test]
+    fn weights_nodes_by_snr_and_degree() {
+        let (graph, device) = fixture();
+        let options = HeatmapOptions {
+            interpolate: false,
+            ..Default::default()
+        };
+
+        let collection = generate_signal_heatmap_geojson(&graph, &device, &options);
+        assert_eq!(collection.features.len(), 3);
+
+        // Mean SNR of 0 dB is 2/3 of the way up the range, degree 2 is 1/3 of saturation
+        let expected = 0.7 * (2.0 / 3.0) + 0.3 * (1.0 / 3.0);
+        assert!((node_weight(&collection, 1) - expected).abs() < 1e-9);
+
+        let expected = 0.7 * (25.0 / 30.0) + 0.3 * (1.0 / 6.0);
+        assert!((node_weight(&collection, 2) - expected).abs() < 1e-9);
+
+        assert_eq!(node_signal_weight(None, 0), 0.0);
+        assert_eq!(node_signal_weight(Some(40.0), 12), 1.0);
+    }
+
+    #[test]
+    fn interpolates_only_within_cutoff() {
+        let (graph, device) = fixture();
+        let options = HeatmapOptions {
+            interpolate: true,
+            grid_resolution_meters: 500.0,
+            cutoff_meters: 1_500.0,
+        };
+
+        let nodes = weighted_nodes(&graph, &device);
+        let collection = generate_signal_heatmap_geojson(&graph, &device, &options);
+
+        let grid: Vec<(f64, f64, f64)> = collection
+            .features
+            .iter()
+            .filter(|feature| feature.properties.as_ref().unwrap()["kind"] == json!("interpolated"))
+            .map(|feature| match &feature.geometry.as_ref().unwrap().value {
+                Value::Point(position) => (
+                    position[1],
+                    position[0],
+                    feature.properties.as_ref().unwrap()["weight"]
+                        .as_f64()
+                        .unwrap(),
+                ),
+                other => panic!("unexpected geometry {:?}", other),
+            })
+            .collect();
+
+        assert!(!grid.is_empty());
+
+        let min_weight = nodes.iter().map(|n| n.weight).fold(f64::INFINITY, f64::min);
+        let max_weight = nodes
+            .iter()
+            .map(|n| n.weight)
+            .fold(f64::NEG_INFINITY, f64::max);
+
+        for (latitude, longitude, weight) in &grid {
+            let nearest = nodes
+                .iter()
+                .map(|n| haversine_distance_meters(*latitude, *longitude, n.latitude, n.longitude))
+                .fold(f64::INFINITY, f64::min);
+
+            assert!(nearest <= options.cutoff_meters);
+            assert!(*weight >= min_weight - 1e-9 && *weight <= max_weight + 1e-9);
+        }
+
+        // Nothing halfway between node 2 and the distant node 3
+        assert!(!grid
+            .iter()
+            .any(|(latitude, _, _)| (40.07..40.12).contains(latitude)));
+    }
+}
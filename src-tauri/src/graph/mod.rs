@@ -1,2 +1,18 @@
+pub mod analytics;
+pub mod analytics_refresh;
 pub mod api;
+pub mod autosave;
+pub mod clustering;
+pub mod corridor;
+pub mod coverage;
 pub mod ds;
+pub mod edge_delta;
+pub mod edge_features;
+pub mod geojson;
+pub mod geometry;
+pub mod heatmap;
+pub mod nearby;
+pub mod partition;
+pub mod rebuild;
+pub mod route;
+pub mod store;
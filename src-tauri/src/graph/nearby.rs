@@ -0,0 +1,142 @@
+use std::collections::BTreeSet;
+
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::device::{helpers::initial_bearing_degrees, MeshDevice};
+
+use super::ds::graph::MeshGraph;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NearbyNode {
+    pub node_num: u32,
+    pub node_id: String, // e.g. "!a1b2c3d4"
+    pub name: Option<String>,
+    pub distance_meters: f64,
+    pub bearing_degrees: f64, // from the query point, clockwise from true north
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodesWithinRadius {
+    pub nodes: Vec<NearbyNode>,    // nearest first
+    pub skipped_unpositioned: u32, // known nodes without a position
+}
+
+/// Finds the nodes within `radius_meters` of a coordinate using the graph's spatial
+/// index. Nodes known to the device or the graph but without a position are counted
+/// in `skipped_unpositioned`.
+pub fn find_nodes_within_radius(
+    graph: &MeshGraph,
+    device: &MeshDevice,
+    latitude: f64,
+    longitude: f64,
+    radius_meters: f64,
+) -> Result<NodesWithinRadius, String> {
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        return Err(format!("Invalid coordinate {}, {}", latitude, longitude));
+    }
+
+    if !radius_meters.is_finite() || radius_meters <= 0.0 {
+        return Err("Radius must be a positive number of meters".into());
+    }
+
+    let mut nodes: Vec<NearbyNode> = graph
+        .spatial_index
+        .within_radius(latitude, longitude, radius_meters)
+        .into_iter()
+        .filter_map(|(node_num, distance_meters)| {
+            let (lat, lon) = graph.spatial_index.position(node_num)?;
+            let long_name = device
+                .nodes
+                .get(&node_num)
+                .and_then(|node| node.user.as_ref())
+                .map(|user| user.long_name.as_str());
+
+            Some(NearbyNode {
+                node_num,
+                node_id: format!("!{:08x}", node_num),
+                name: graph.display_label(node_num, long_name),
+                distance_meters,
+                bearing_degrees: initial_bearing_degrees(latitude, longitude, lat, lon),
+            })
+        })
+        .collect();
+
+    nodes.sort_by(|a, b| {
+        a.distance_meters
+            .total_cmp(&b.distance_meters)
+            .then(a.node_num.cmp(&b.node_num))
+    });
+
+    let known_nodes: BTreeSet<u32> = device
+        .nodes
+        .keys()
+        .copied()
+        .chain(graph.nodes().map(|node| node.node_num))
+        .collect();
+
+    let skipped_unpositioned = known_nodes
+        .iter()
+        .filter(|node_num| graph.spatial_index.position(**node_num).is_none())
+        .count() as u32;
+
+    Ok(NodesWithinRadius {
+        nodes,
+        skipped_unpositioned,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::MeshNode;
+
+    #[test]
+    fn finds_nodes_nearest_first() {
+        let mut graph = MeshGraph::new();
+        let mut device = MeshDevice::new();
+
+        // Portland is ~233.5 km from Seattle, Tacoma ~40 km, Vancouver BC ~195 km
+        graph.set_node_position(1, 45.5152, -122.6784);
+        graph.set_node_position(2, 47.2529, -122.4443);
+        graph.set_node_position(3, 49.2827, -123.1207);
+
+        for node_num in 1..=4 {
+            let mut node = MeshNode::new(node_num);
+            node.user = Some(protobufs::User {
+                long_name: format!("Node {}", node_num),
+                ..Default::default()
+            });
+            device.nodes.insert(node_num, node);
+        }
+
+        graph.set_node_label(2, Some("Tacoma relay".into()));
+
+        let result =
+            find_nodes_within_radius(&graph, &device, 47.6062, -122.3321, 235_000.0).unwrap();
+
+        let found: Vec<_> = result.nodes.iter().map(|n| n.node_num).collect();
+        assert_eq!(found, [2, 3, 1]);
+        assert_eq!(result.skipped_unpositioned, 1);
+
+        let tacoma = &result.nodes[0];
+        assert_eq!(tacoma.node_id, "!00000002");
+        assert_eq!(tacoma.name.as_deref(), Some("Tacoma relay"));
+        assert!((tacoma.distance_meters - 40_300.0).abs() < 500.0);
+
+        let portland = &result.nodes[2];
+        assert_eq!(portland.name.as_deref(), Some("Node 1"));
+        assert!((portland.distance_meters - 233_500.0).abs() < 1_000.0);
+        assert!((portland.bearing_degrees - 186.6).abs() < 0.1);
+
+        let nearby = find_nodes_within_radius(&graph, &device, 47.6062, -122.3321, 50_000.0);
+        assert_eq!(nearby.unwrap().nodes.len(), 1);
+
+        assert!(find_nodes_within_radius(&graph, &device, 95.0, 0.0, 1.0).is_err());
+        assert!(find_nodes_within_radius(&graph, &device, 0.0, 0.0, -1.0).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use log::{debug, warn};
+use tauri::Manager;
+
+use crate::device::helpers::get_current_time_u32;
+use crate::ipc::events::dispatch_network_partition;
+use crate::ipc::{NetworkPartitionEvent, EVENT_API_VERSION};
+use crate::state::{self, DeviceKey};
+
+use super::ds::graph::MeshGraph;
+
+/// Tracks how many groups of nodes that can't reach each other the graph is split
+/// into, to tell the UI when the mesh splits and when it's whole again
+#[derive(Clone, Debug, Default)]
+pub struct PartitionTracker {
+    component_count: usize,
+}
+
+impl PartitionTracker {
+    /// Records the graph's component count, returning whether the UI should be told.
+    /// Changes while the mesh is whole, e.g. the first node being heard, aren't
+    /// reported, while merging back into one component is.
+    pub fn observe(&mut self, component_count: usize) -> bool {
+        let previous = std::mem::replace(&mut self.component_count, component_count);
+
+        component_count != previous && (component_count > 1 || previous > 1)
+    }
+}
+
+/// Tells the UI which groups of nodes the graph is split into if that changed since
+/// the graph was last sent to it
+pub fn check_network_partition<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: Option<&DeviceKey>,
+    graph: &MeshGraph,
+) {
+    let partition_state =
+        match handle.try_state::<state::network_partition::NetworkPartitionState>() {
+            Some(partition_state) => partition_state,
+            None => return,
+        };
+
+    let components = graph.component_members();
+
+    let changed = match partition_state.inner.lock() {
+        Ok(mut tracker) => tracker.observe(components.len()),
+        Err(e) => {
+            warn!("Failed to lock network partition tracker: {}", e);
+            return;
+        }
+    };
+
+    if !changed {
+        return;
+    }
+
+    debug!("Mesh is now split into {} components", components.len());
+
+    let event = NetworkPartitionEvent {
+        api_version: EVENT_API_VERSION,
+        device_key: device_key.cloned(),
+        component_count: components.len() as u32,
+        components,
+        timestamp: get_current_time_u32(),
+    };
+
+    if let Err(e) = dispatch_network_partition(handle, event) {
+        warn!("Failed to dispatch network partition: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::ds::fixtures::{edge, graph_node};
+
+    #[test]
+    fn reports_splits_and_merges() {
+        let mut graph = MeshGraph::new();
+        let mut tracker = PartitionTracker::default();
+
+        // Chain 1 - 2 - 3, relayed through 2
+        for node_num in 1..=3 {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        graph.upsert_edge(graph_node(1), graph_node(2), edge(1, 2, 0.0));
+        graph.upsert_edge(graph_node(3), graph_node(2), edge(3, 2, 0.0));
+
+        assert_eq!(graph.component_members(), vec![vec![1, 2, 3]]);
+        assert!(!tracker.observe(graph.component_members().len()));
+
+        // The relay drops out, leaving 1 and 3 stranded on their own
+        graph.remove_node(2);
+        graph.upsert_node(graph_node(4));
+        graph.upsert_edge(graph_node(4), graph_node(3), edge(4, 3, 0.0));
+
+        assert_eq!(graph.component_members(), vec![vec![3, 4], vec![1]]);
+        assert!(tracker.observe(2));
+        assert!(!tracker.observe(2));
+
+        // Hiding a node splits the mesh it joined
+        graph.set_node_hidden(4, true);
+        assert_eq!(graph.component_members(), vec![vec![1], vec![3]]);
+        graph.set_node_hidden(4, false);
+
+        // Reported again once the relay is back and the mesh is whole
+        graph.upsert_node(graph_node(2));
+        graph.upsert_edge(graph_node(1), graph_node(2), edge(1, 2, 0.0));
+        graph.upsert_edge(graph_node(3), graph_node(2), edge(3, 2, 0.0));
+
+        assert_eq!(graph.component_members(), vec![vec![1, 2, 3, 4]]);
+        assert!(tracker.observe(1));
+        assert!(!tracker.observe(1));
+    }
+}
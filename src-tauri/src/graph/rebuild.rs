@@ -0,0 +1,357 @@
+use std::collections::HashMap;
+
+use log::debug;
+
+use crate::device::{MeshDevice, MeshNode};
+use crate::ipc::events;
+use crate::packet_api::{handlers::DeviceUpdateError, MeshPacketApi};
+use crate::state::mesh_devices::MeshDeviceInner;
+
+use super::{
+    api::change::GraphChange, ds::graph::MeshGraph, geojson::GraphGeoJson,
+    store::initialize_graph_state,
+};
+
+/// Where a device is in rebuilding its graph from the one stored for it. Rebuilds run
+/// on a blocking thread while the device's packets keep being handled, so the changes
+/// those packets publish are queued and applied again once the rebuilt graph is in.
+#[derive(Debug, Default)]
+pub enum GraphRebuild {
+    #[default]
+    Idle,
+    Requested,
+    Running(Vec<GraphChange>), // published since the rebuild started
+}
+
+/// A graph built from scratch, with the details of the nodes loaded along with it
+pub struct RebuiltGraph {
+    pub graph: MeshGraph,
+    pub nodes: HashMap<u32, MeshNode>,
+}
+
+/// Builds the graph stored for a device, or recovered from an autosave, without
+/// touching the device's own graph
+pub fn load_stored_graph<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_id: u32,
+) -> RebuiltGraph {
+    let mut graph = MeshGraph::new();
+    let mut device = MeshDevice::new();
+    device.my_node_info.my_node_num = device_id;
+
+    initialize_graph_state(handle, &mut graph, &mut device);
+
+    RebuiltGraph {
+        graph,
+        nodes: device.nodes,
+    }
+}
+
+/// Adds the nodes, labels and edges of `rebuilt` that `graph` doesn't have, keeping
+/// the ones it does. Returns how many nodes and edges were added.
+fn merge_missing(graph: &mut MeshGraph, rebuilt: &MeshGraph) -> (usize, usize) {
+    let (mut nodes, mut edges) = (0, 0);
+
+    for node in rebuilt.nodes() {
+        if !graph.contains_node(node.node_num) {
+            graph.upsert_node(node);
+            nodes += 1;
+        }
+
+        if graph.overrides.node_label(node.node_num).is_none() {
+            if let Some(label) = rebuilt.overrides.node_label(node.node_num) {
+                graph.set_node_label(node.node_num, Some(label.into()));
+            }
+        }
+    }
+
+    for (from, to, edge) in rebuilt.edges() {
+        let (from, to) = match (graph.get_node(from.node_num), graph.get_node(to.node_num)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => continue,
+        };
+
+        if graph.get_edge(from, to).is_none() {
+            graph.upsert_edge(from, to, edge.clone());
+            edges += 1;
+        }
+    }
+
+    (nodes, edges)
+}
+
+impl<R: tauri::Runtime> MeshPacketApi<R> {
+    /// Has the graph rebuilt once the packet being handled is, unless it already is
+    pub fn request_graph_rebuild(&mut self) {
+        if let GraphRebuild::Idle = self.graph_rebuild {
+            self.graph_rebuild = GraphRebuild::Requested;
+        }
+    }
+
+    /// Marks the requested rebuild as running, returning whether one was requested
+    pub fn start_graph_rebuild(&mut self) -> bool {
+        match self.graph_rebuild {
+            GraphRebuild::Requested => {
+                self.graph_rebuild = GraphRebuild::Running(vec![]);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Queues published changes to apply again after the running rebuild, if any
+    pub fn queue_rebuild_changes(&mut self, changes: &[GraphChange]) {
+        if let GraphRebuild::Running(queued) = &mut self.graph_rebuild {
+            queued.extend_from_slice(changes);
+        }
+    }
+
+    /// Merges a rebuilt graph into the device's under a single write guard. What the
+    /// graph already has is kept, and the changes queued while it was being built are
+    /// applied again on top, so e.g. a stored edge removed since doesn't come back.
+    /// Returns how many nodes and edges were added.
+    pub fn finish_graph_rebuild(
+        &mut self,
+        rebuilt: RebuiltGraph,
+    ) -> Result<(usize, usize), DeviceUpdateError> {
+        let queued = match std::mem::take(&mut self.graph_rebuild) {
+            GraphRebuild::Running(queued) => queued,
+            _ => vec![],
+        };
+
+        let merged = {
+            let mut graph = self
+                .write_graph()
+                .map_err(DeviceUpdateError::GeneralFailure)?;
+
+            let merged = merge_missing(&mut graph, &rebuilt.graph);
+            graph.apply_changes(queued);
+            merged
+        };
+
+        for (node_num, node) in rebuilt.nodes {
+            self.device.nodes.entry(node_num).or_insert(node);
+        }
+
+        Ok(merged)
+    }
+}
+
+/// Builds a graph on a blocking thread without holding the device, then merges it into
+/// the device's and publishes the result. The device is only locked for the merge, and
+/// the published GeoJSON is generated on a blocking thread as well.
+pub async fn run_graph_rebuild<R, F>(device: MeshDeviceInner<R>, build: F) -> Result<(), String>
+where
+    R: tauri::Runtime,
+    F: FnOnce() -> RebuiltGraph + Send + 'static,
+{
+    let rebuilt = tauri::async_runtime::spawn_blocking(build).await;
+
+    let mut packet_api = device.lock().await;
+
+    let rebuilt = match rebuilt {
+        Ok(rebuilt) => rebuilt,
+        Err(e) => {
+            packet_api.graph_rebuild = GraphRebuild::Idle;
+            return Err(e.to_string());
+        }
+    };
+
+    let (nodes, edges) = packet_api
+        .finish_graph_rebuild(rebuilt)
+        .map_err(|e| e.to_string())?;
+
+    debug!(
+        "Rebuilt graph of device \"{}\", adding {} nodes and {} edges",
+        packet_api.device_key, nodes, edges
+    );
+
+    let handle = packet_api.app_handle.clone();
+    let device_key = packet_api.device_key.clone();
+    let graph = packet_api.graph_arc.view()?;
+    let mesh_device = packet_api.device.clone();
+
+    drop(packet_api);
+
+    let (graph, geojson) = tauri::async_runtime::spawn_blocking(move || {
+        let geojson = GraphGeoJson::new(device_key, &graph, &mesh_device);
+        (graph, geojson)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+
+    events::dispatch_updated_graph(&handle, Some(geojson.device_key.clone()), graph)
+        .map_err(|e| e.to_string())?;
+    events::dispatch_graph_geojson_update(&handle, geojson).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc};
+    use std::time::Duration;
+
+    use meshtastic::protobufs::{self, MeshPacket, Neighbor, NeighborInfo};
+
+    use super::*;
+    use crate::device::SerialDeviceStatus;
+    use crate::graph::ds::fixtures::{edge, graph_node};
+    use crate::packet_api::summary::ConnectionType;
+    use crate::state::graph::SharedGraph;
+    use crate::state::mesh_devices::new_device;
+
+    const STORED_NODES: u32 = 200;
+
+    fn packet(from: u32) -> MeshPacket {
+        MeshPacket {
+            from,
+            ..Default::default()
+        }
+    }
+
+    fn position(offset: u32) -> protobufs::Position {
+        protobufs::Position {
+            latitude_i: 525_000_000 + offset as i32 * 1_000,
+            longitude_i: 134_000_000,
+            ..Default::default()
+        }
+    }
+
+    fn neighbor_info(node_id: u32, neighbors: &[u32]) -> NeighborInfo {
+        NeighborInfo {
+            node_id,
+            neighbors: neighbors
+                .iter()
+                .map(|neighbor| Neighbor {
+                    node_id: *neighbor,
+                    snr: 4.0,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// A stored graph that still has node 2 hearing node 3, and a chain of nodes the
+    /// device hasn't heard since
+    fn stored_graph() -> RebuiltGraph {
+        let mut graph = MeshGraph::new();
+
+        for node_num in (2..=3).chain(1_000..1_000 + STORED_NODES) {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        graph.upsert_edge(graph_node(2), graph_node(3), edge(2, 3, -12.0));
+
+        for node_num in 1_000..1_000 + STORED_NODES - 1 {
+            graph.upsert_edge(
+                graph_node(node_num),
+                graph_node(node_num + 1),
+                edge(node_num, node_num + 1, 1.0),
+            );
+        }
+
+        RebuiltGraph {
+            graph,
+            nodes: HashMap::from([(1_000, MeshNode::new(1_000))]),
+        }
+    }
+
+    /// Applies an update to the device's graph and publishes it, like a packet would
+    async fn apply<R: tauri::Runtime>(
+        device: &MeshDeviceInner<R>,
+        update: impl FnOnce(&mut MeshGraph) -> Vec<GraphChange>,
+    ) {
+        let mut packet_api = device.lock().await;
+
+        let changes = update(&mut packet_api.write_graph().unwrap());
+        packet_api.graph_changes.extend(changes);
+        packet_api.flush_graph_batch().unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn changes_made_while_rebuilding_are_kept() {
+        let app = tauri::test::mock_app();
+
+        let mut mesh_device = MeshDevice::new();
+        mesh_device.my_node_info.my_node_num = 1;
+        mesh_device.set_status(SerialDeviceStatus::Configuring);
+
+        let mut packet_api = MeshPacketApi::new(
+            app.handle(),
+            "rebuild".into(),
+            mesh_device,
+            Arc::new(SharedGraph::new(MeshGraph::new())),
+        );
+        packet_api.connection_type = ConnectionType::Simulated;
+
+        let device = new_device(packet_api);
+
+        for node_num in 2..=4 {
+            apply(&device, |graph| {
+                graph.update_from_position(packet(node_num), position(node_num))
+            })
+            .await;
+        }
+
+        apply(&device, |graph| {
+            graph.update_from_neighbor_info(
+                packet(2),
+                neighbor_info(2, &[3]),
+                "LongFast".into(),
+                "/dev/ttyUSB0",
+            )
+        })
+        .await;
+
+        {
+            let mut packet_api = device.lock().await;
+            packet_api.request_graph_rebuild();
+            assert!(packet_api.start_graph_rebuild());
+        }
+
+        // A slow rebuild, only finishing once the updates below are applied
+        let (release, wait) = mpsc::channel::<()>();
+
+        let rebuild = tokio::spawn(run_graph_rebuild(device.clone(), move || {
+            wait.recv().unwrap();
+            stored_graph()
+        }));
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            // Node 2 stops hearing node 3 and starts hearing node 4
+            apply(&device, |graph| {
+                graph.update_from_neighbor_info(
+                    packet(2),
+                    neighbor_info(2, &[4]),
+                    "LongFast".into(),
+                    "/dev/ttyUSB0",
+                )
+            })
+            .await;
+
+            apply(&device, |graph| {
+                graph.update_from_position(packet(5), position(5))
+            })
+            .await;
+        })
+        .await
+        .expect("updates waited on the rebuild");
+
+        release.send(()).unwrap();
+        rebuild.await.unwrap().unwrap();
+
+        let packet_api = device.lock().await;
+        let graph = packet_api.read_graph().unwrap();
+        let node = |node_num| graph.get_node(node_num).unwrap();
+
+        assert!(matches!(packet_api.graph_rebuild, GraphRebuild::Idle));
+        assert!(graph.get_edge(node(2), node(3)).is_none());
+        assert!(graph.get_edge(node(2), node(4)).is_some());
+        assert!(graph.spatial_index.position(5).is_some());
+        assert!(graph.get_edge(node(1_000), node(1_001)).is_some());
+        assert_eq!(graph.node_count(), 4 + STORED_NODES as usize);
+        assert!(packet_api.device.nodes.contains_key(&1_000));
+    }
+}
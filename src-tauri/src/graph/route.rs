@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+
+use geojson::{Feature, Geometry, JsonObject, Value};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::device::MeshDevice;
+
+use super::ds::{edge_weight::EdgeWeightStrategy, graph::MeshGraph};
+
+/// Links at or above this SNR cost the same as a single hop
+pub const ROUTE_GOOD_SNR: f64 = 10.0;
+
+/// Route between two nodes, or why there isn't one
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "status")]
+pub enum RouteGeoJson {
+    Found {
+        feature: Feature,
+        /// A Point per node along the route, in order from `fromNode`
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        hops: Vec<Feature>,
+    },
+    #[serde(rename_all = "camelCase")]
+    NoRoute { from_node: u32, to_node: u32 },
+}
+
+fn node_id(node_num: u32) -> String {
+    format!("!{:08x}", node_num)
+}
+
+/// Cheapest route between two nodes, with the coordinates of its positioned nodes
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    pub from_node: u32,
+    pub to_node: u32,
+    pub weight_strategy: EdgeWeightStrategy, // the graph's, which weighted the links routed over
+    pub path: Vec<u32>,
+    pub total_cost: f64,
+    pub hop_costs: Vec<f64>, // cost of the route up to each node along the path
+    pub positions: Vec<Option<Vec<f64>>>, // [longitude, latitude] of each node along the path, if known
+    pub coordinates: Vec<Vec<f64>>, // [longitude, latitude] of each positioned node along the path
+    pub unpositioned: Vec<String>,  // ids of nodes along the path without a position
+}
+
+impl Route {
+    /// Properties describing the route, shared by the features built from it
+    pub fn properties(&self) -> JsonObject {
+        let mut properties = JsonObject::new();
+        properties.insert("fromNode".into(), json!(self.from_node));
+        properties.insert("toNode".into(), json!(self.to_node));
+        properties.insert("weightStrategy".into(), json!(self.weight_strategy));
+        properties.insert("totalCost".into(), json!(self.total_cost));
+        properties.insert("hopCount".into(), json!(self.path.len() - 1));
+        properties.insert(
+            "nodeIds".into(),
+            json!(self.path.iter().map(|n| node_id(*n)).collect::<Vec<_>>()),
+        );
+        properties.insert("unpositionedNodeIds".into(), json!(self.unpositioned));
+        properties
+    }
+
+    /// A feature per node along the route with the cost of reaching it, whose
+    /// geometry is `null` if the node has no position
+    pub fn hop_features(&self) -> Vec<Feature> {
+        self.path
+            .iter()
+            .zip(&self.hop_costs)
+            .zip(&self.positions)
+            .enumerate()
+            .map(|(hop, ((node_num, cost), position))| {
+                let mut properties = JsonObject::new();
+                properties.insert("nodeNum".into(), json!(node_num));
+                properties.insert("nodeId".into(), json!(node_id(*node_num)));
+                properties.insert("hop".into(), json!(hop));
+                properties.insert("cumulativeCost".into(), json!(cost));
+
+                Feature {
+                    bbox: None,
+                    geometry: position
+                        .clone()
+                        .map(|position| Geometry::new(Value::Point(position))),
+                    id: None,
+                    properties: Some(properties),
+                    foreign_members: None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Finds the cheapest route between two nodes by the weights of the graph's edges,
+/// returning `None` if they aren't connected
+pub fn find_route(
+    graph: &MeshGraph,
+    device: &MeshDevice,
+    from_node: u32,
+    to_node: u32,
+) -> Result<Option<Route>, String> {
+    for node_num in [from_node, to_node] {
+        if !device.nodes.contains_key(&node_num) && !graph.contains_node(node_num) {
+            return Err(format!("Unknown node {}", node_id(node_num)));
+        }
+    }
+
+    let (path, total_cost) = match graph.cheapest_path(from_node, to_node, |edge| edge.weight()) {
+        Some(route) => route,
+        None => return Ok(None),
+    };
+
+    // Every prefix of the cheapest path is itself a cheapest path, so the cost of
+    // reaching each node along it is the cheapest cost from the start
+    let costs = graph.path_costs(from_node, |edge| edge.weight());
+    let hop_costs = path.iter().map(|node_num| costs[node_num]).collect();
+
+    let positions: Vec<Option<Vec<f64>>> = path
+        .iter()
+        .map(|node_num| {
+            device
+                .nodes
+                .get(node_num)
+                .and_then(|node| node.last_known_position())
+                .map(|position| vec![position.longitude.into(), position.latitude.into()])
+        })
+        .collect();
+
+    let coordinates = positions.iter().flatten().cloned().collect();
+    let unpositioned = path
+        .iter()
+        .zip(&positions)
+        .filter(|(_, position)| position.is_none())
+        .map(|(node_num, _)| node_id(*node_num))
+        .collect();
+
+    Ok(Some(Route {
+        from_node,
+        to_node,
+        weight_strategy: graph.edge_weight_strategy().clone(),
+        path,
+        total_cost,
+        hop_costs,
+        positions,
+        coordinates,
+        unpositioned,
+    }))
+}
+
+/// Cost of the cheapest route from a node to every node reachable from it, by node num,
+/// e.g. to shade nodes by how far they are from our own
+pub fn find_route_costs(
+    graph: &MeshGraph,
+    device: &MeshDevice,
+    from_node: u32,
+) -> Result<HashMap<u32, f64>, String> {
+    if !device.nodes.contains_key(&from_node) && !graph.contains_node(from_node) {
+        return Err(format!("Unknown node {}", node_id(from_node)));
+    }
+
+    Ok(graph.path_costs(from_node, |edge| edge.weight()))
+}
+
+/// Builds a LineString through the positioned nodes along the cheapest route between
+/// two nodes. Unpositioned nodes on the route are skipped in the geometry but listed
+/// in the `unpositionedNodeIds` property. The geometry is `null` if fewer than two
+/// nodes on the route have a position. Each node on the route also gets a hop feature
+/// with the cost of reaching it.
+pub fn build_route_geojson(
+    graph: &MeshGraph,
+    device: &MeshDevice,
+    from_node: u32,
+    to_node: u32,
+) -> Result<RouteGeoJson, String> {
+    let route = match find_route(graph, device, from_node, to_node)? {
+        Some(route) => route,
+        None => return Ok(RouteGeoJson::NoRoute { from_node, to_node }),
+    };
+
+    let properties = route.properties();
+    let hops = route.hop_features();
+    let geometry =
+        (route.coordinates.len() >= 2).then(|| Geometry::new(Value::LineString(route.coordinates)));
+
+    Ok(RouteGeoJson::Found {
+        feature: Feature {
+            bbox: None,
+            geometry,
+            id: None,
+            properties: Some(properties),
+            foreign_members: None,
+        },
+        hops,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::{MeshNode, NormalizedPosition};
+    use crate::graph::ds::fixtures::{edge, graph_node};
+
+    fn positioned_node(node_num: u32, latitude: f32, longitude: f32) -> MeshNode {
+        let mut node = MeshNode::new(node_num);
+        node.position_metrics
+            .push(NormalizedPosition::from(protobufs::Position {
+                latitude_i: (latitude * 1e7) as i32,
+                longitude_i: (longitude * 1e7) as i32,
+                ..Default::default()
+            }));
+        node
+    }
+
+    /// Chain 1 - 2 - 3 where node 2 has no position, and an isolated node 4, with
+    /// edges weighted by `strategy`
+    fn fixture(strategy: EdgeWeightStrategy) -> (MeshGraph, MeshDevice) {
+        let mut graph = MeshGraph::new();
+        graph.set_edge_weight_strategy(strategy);
+
+        for node_num in 1..=4 {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        graph.upsert_edge(graph_node(1), graph_node(2), edge(1, 2, 0.0));
+        graph.upsert_edge(graph_node(3), graph_node(2), edge(3, 2, 0.0));
+
+        let mut device = MeshDevice::new();
+        device.nodes.insert(1, positioned_node(1, 10.0, 20.0));
+        device.nodes.insert(2, MeshNode::new(2));
+        device.nodes.insert(3, positioned_node(3, 10.5, 20.5));
+        device.nodes.insert(4, positioned_node(4, 11.0, 21.0));
+
+        (graph, device)
+    }
+
+    #[test]
+    fn skips_unpositioned_nodes_along_route() {
+        let (graph, device) = fixture(EdgeWeightStrategy::HopCount);
+
+        let feature = match build_route_geojson(&graph, &device, 1, 3) {
+            Ok(RouteGeoJson::Found { feature, .. }) => feature,
+            other => panic!("expected a route, got {:?}", other),
+        };
+
+        match &feature.geometry.as_ref().unwrap().value {
+            Value::LineString(coordinates) => assert_eq!(coordinates.len(), 2),
+            other => panic!("unexpected geometry {:?}", other),
+        }
+
+        let properties = feature.properties.unwrap();
+        assert_eq!(properties["hopCount"], json!(2));
+        assert_eq!(properties["totalCost"], json!(2.0));
+        assert_eq!(
+            properties["weightStrategy"],
+            json!({ "strategy": "hopCount" })
+        );
+        assert_eq!(
+            properties["nodeIds"],
+            json!(["!00000001", "!00000002", "!00000003"])
+        );
+        assert_eq!(properties["unpositionedNodeIds"], json!(["!00000002"]));
+    }
+
+    #[test]
+    fn hops_follow_the_route_with_cumulative_costs() {
+        let (mut graph, device) = fixture(EdgeWeightStrategy::SnrBased);
+
+        // Links at 0 dB cost 2 by SNR, and this weaker one costs 2.5
+        graph.upsert_edge(graph_node(3), graph_node(4), edge(3, 4, -5.0));
+
+        let hops = match build_route_geojson(&graph, &device, 4, 1) {
+            Ok(RouteGeoJson::Found { hops, .. }) => hops,
+            other => panic!("expected a route, got {:?}", other),
+        };
+
+        let properties: Vec<_> = hops
+            .iter()
+            .map(|hop| {
+                let properties = hop.properties.as_ref().unwrap();
+                (
+                    properties["nodeNum"].clone(),
+                    properties["cumulativeCost"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            properties,
+            vec![
+                (json!(4), json!(0.0)),
+                (json!(3), json!(2.5)),
+                (json!(2), json!(4.5)),
+                (json!(1), json!(6.5)),
+            ]
+        );
+
+        // GeoJSON positions are [longitude, latitude]
+        assert_eq!(
+            hops[0].geometry.as_ref().map(|g| g.value.clone()),
+            Some(Value::Point(vec![21.0, 11.0]))
+        );
+        assert_eq!(hops[2].geometry, None);
+    }
+
+    #[test]
+    fn reports_disconnected_and_unknown_nodes() {
+        let (graph, device) = fixture(EdgeWeightStrategy::HopCount);
+
+        assert_eq!(
+            build_route_geojson(&graph, &device, 1, 4),
+            Ok(RouteGeoJson::NoRoute {
+                from_node: 1,
+                to_node: 4
+            })
+        );
+
+        let no_route =
+            serde_json::to_value(build_route_geojson(&graph, &device, 1, 4).unwrap()).unwrap();
+        assert_eq!(no_route["status"], json!("noRoute"));
+
+        assert!(build_route_geojson(&graph, &device, 1, 99).is_err());
+        assert_eq!(
+            find_route_costs(&graph, &device, 1),
+            Ok(HashMap::from([(1, 0.0), (2, 1.0), (3, 2.0)]))
+        );
+        assert!(find_route_costs(&graph, &device, 99).is_err());
+    }
+}
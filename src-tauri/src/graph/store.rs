@@ -0,0 +1,1291 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use log::{debug, trace, warn};
+use rusqlite::{params, Connection, Transaction};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::device::{
+    helpers::get_current_time_u32,
+    node_db::{PersistenceHandle, ReconcileReport, StoredNode},
+    MeshDevice, MeshNode,
+};
+use crate::migrations::{migrate, Migration, MigrationError};
+use crate::packet_api::{summary::ConnectionType, MeshPacketApi};
+use crate::retention::{
+    ClassUsage, RetainedTable, RetentionLimits, StorageClass, ROW_OVERHEAD_BYTES,
+};
+use crate::shutdown::shutdown_signal;
+use crate::state::mesh_devices::all_devices;
+use crate::state::{self, DeviceKey};
+
+use super::autosave::recovered_snapshot;
+use super::ds::{
+    edge::{EdgeSource, GraphEdge},
+    graph::MeshGraph,
+    node::GraphNode,
+    position_archive::{ArchivedPosition, PositionArchive, PositionSource},
+};
+
+/// Version of the last migration below, recorded in `schema_version`
+pub const GRAPH_STORE_SCHEMA_VERSION: u32 = 2;
+
+/// How often graphs changed since the last write are written to the database
+pub const GRAPH_STORE_WRITE_INTERVAL: Duration = Duration::from_secs(5);
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+const NODES_TABLE: RetainedTable = RetainedTable {
+    name: "nodes",
+    key: &["device_id", "node_num"],
+    timestamp: "last_heard",
+    row_bytes: "LENGTH(last_heard) + COALESCE(LENGTH(alias), 0) + COALESCE(LENGTH(info), 0)",
+};
+
+const EDGES_TABLE: RetainedTable = RetainedTable {
+    name: "edges",
+    key: &["device_id", "from_node", "to_node"],
+    timestamp: "last_heard",
+    row_bytes: "LENGTH(source) + LENGTH(last_heard) + LENGTH(edge)",
+};
+
+const POSITIONS_TABLE: RetainedTable = RetainedTable {
+    name: "node_positions",
+    key: &["node_num"],
+    timestamp: "timestamp",
+    row_bytes: "LENGTH(source)",
+};
+
+/// Rows are keyed by the node num of the device they were heard by, so a radio's
+/// graph is found again whichever port or address it's connected on. Archived
+/// positions are keyed by node num alone, whichever radio heard them.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "nodes and edges",
+        sql: "
+    CREATE TABLE IF NOT EXISTS nodes (
+        device_id INTEGER NOT NULL,
+        node_num INTEGER NOT NULL,
+        last_heard TEXT NOT NULL,
+        timeout_millis INTEGER NOT NULL,
+        alias TEXT,
+        info TEXT,
+        PRIMARY KEY (device_id, node_num)
+    );
+
+    CREATE TABLE IF NOT EXISTS edges (
+        device_id INTEGER NOT NULL,
+        from_node INTEGER NOT NULL,
+        to_node INTEGER NOT NULL,
+        source TEXT NOT NULL,
+        snr REAL NOT NULL,
+        last_heard TEXT NOT NULL,
+        edge TEXT NOT NULL,
+        PRIMARY KEY (device_id, from_node, to_node)
+    );
+",
+    },
+    Migration {
+        version: 2,
+        description: "position archive",
+        sql: "
+    CREATE TABLE node_positions (
+        node_num INTEGER PRIMARY KEY,
+        latitude REAL NOT NULL,
+        longitude REAL NOT NULL,
+        altitude INTEGER NOT NULL,
+        source TEXT NOT NULL,
+        timestamp INTEGER NOT NULL
+    );
+",
+    },
+];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NodeRow {
+    last_heard: String,
+    timeout_millis: u64,
+    alias: Option<String>, // operator's label for the node
+    info: Option<String>,  // the device's `MeshNode` as JSON, with its user and positions
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EdgeRow {
+    source: String,
+    snr: f64,
+    last_heard: String,
+    edge: String, // the whole `GraphEdge` as JSON
+}
+
+/// Edges are keyed by their ends, which JSON can't use as an object key, so they're
+/// serialized as a list of `[from, to, row]`
+mod edge_rows {
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::EdgeRow;
+
+    pub fn serialize<S: Serializer>(
+        edges: &HashMap<(u32, u32), EdgeRow>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(edges.iter().map(|((from, to), row)| (from, to, row)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<(u32, u32), EdgeRow>, D::Error> {
+        let rows: Vec<(u32, u32, EdgeRow)> = Deserialize::deserialize(deserializer)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(from, to, row)| ((from, to), row))
+            .collect())
+    }
+}
+
+/// The rows stored for a device's graph
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphSnapshot {
+    device_id: u32,
+    nodes: HashMap<u32, NodeRow>,
+    #[serde(with = "edge_rows")]
+    edges: HashMap<(u32, u32), EdgeRow>,
+}
+
+impl GraphSnapshot {
+    /// Manual edges are left out, they're restored from the graph overrides
+    pub fn new(device_id: u32, graph: &MeshGraph, device: &MeshDevice) -> Result<Self, String> {
+        let mut nodes = HashMap::new();
+
+        for node in graph.nodes() {
+            let info = device
+                .nodes
+                .get(&node.node_num)
+                .map(serde_json::to_string)
+                .transpose()
+                .map_err(|e| e.to_string())?;
+
+            nodes.insert(
+                node.node_num,
+                NodeRow {
+                    last_heard: node.last_heard.format(TIMESTAMP_FORMAT).to_string(),
+                    timeout_millis: node.timeout_duration.as_millis() as u64,
+                    alias: graph.overrides.node_label(node.node_num).map(String::from),
+                    info,
+                },
+            );
+        }
+
+        let mut edges = HashMap::new();
+
+        for (from, to, edge) in graph.edges() {
+            if edge.source == EdgeSource::Manual {
+                continue;
+            }
+
+            edges.insert(
+                (from.node_num, to.node_num),
+                EdgeRow {
+                    source: edge.source.tag().into(),
+                    snr: edge.snr(),
+                    last_heard: edge.last_heard.format(TIMESTAMP_FORMAT).to_string(),
+                    edge: serde_json::to_string(edge).map_err(|e| e.to_string())?,
+                },
+            );
+        }
+
+        Ok(Self {
+            device_id,
+            nodes,
+            edges,
+        })
+    }
+
+    pub fn device_id(&self) -> u32 {
+        self.device_id
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edges.len()
+    }
+
+    /// The snapshot's nodes, to reconcile with the radio's node database
+    pub fn persisted_nodes(&self) -> Result<PersistenceHandle, String> {
+        let mut nodes = HashMap::new();
+
+        for (node_num, row) in &self.nodes {
+            let info = row
+                .info
+                .as_deref()
+                .map(serde_json::from_str::<MeshNode>)
+                .transpose()
+                .map_err(|e| format!("Invalid details for node {}: {}", node_num, e))?;
+
+            nodes.insert(
+                *node_num,
+                StoredNode {
+                    graph_node: GraphNode {
+                        node_num: *node_num,
+                        last_heard: parse_timestamp(&row.last_heard)?,
+                        timeout_duration: Duration::from_millis(row.timeout_millis),
+                    },
+                    info,
+                },
+            );
+        }
+
+        Ok(PersistenceHandle::new(nodes))
+    }
+
+    /// Adds the snapshot's nodes and edges to `graph`, and its node details to `device`
+    /// for nodes it doesn't know yet. Nodes and edges already in the graph were heard
+    /// since connecting, so are newer and kept. Returns the number of nodes and edges
+    /// loaded.
+    pub fn load_into(
+        &self,
+        graph: &mut MeshGraph,
+        device: &mut MeshDevice,
+    ) -> Result<(usize, usize), String> {
+        let mut graph_nodes = HashMap::new();
+
+        for (node_num, row) in &self.nodes {
+            let node = GraphNode {
+                node_num: *node_num,
+                last_heard: parse_timestamp(&row.last_heard)?,
+                timeout_duration: Duration::from_millis(row.timeout_millis),
+            };
+
+            if !graph.contains_node(*node_num) {
+                graph.upsert_node(node);
+            }
+
+            if graph.overrides.node_label(*node_num).is_none() && row.alias.is_some() {
+                graph.set_node_label(*node_num, row.alias.clone());
+            }
+
+            if let Some(info) = &row.info {
+                let mesh_node: MeshNode = serde_json::from_str(info)
+                    .map_err(|e| format!("Invalid details for node {}: {}", node_num, e))?;
+
+                device.nodes.entry(*node_num).or_insert(mesh_node);
+            }
+
+            graph_nodes.insert(*node_num, node);
+        }
+
+        let mut edge_count = 0;
+
+        for ((from, to), row) in &self.edges {
+            let (from_node, to_node) = match (graph_nodes.get(from), graph_nodes.get(to)) {
+                (Some(from_node), Some(to_node)) => (*from_node, *to_node),
+                _ => continue,
+            };
+
+            if graph.get_edge(from_node, to_node).is_some() {
+                continue;
+            }
+
+            let edge: GraphEdge = serde_json::from_str(&row.edge)
+                .map_err(|e| format!("Invalid edge from {} to {}: {}", from, to, e))?;
+
+            graph.upsert_edge(from_node, to_node, edge);
+            edge_count += 1;
+        }
+
+        Ok((graph_nodes.len(), edge_count))
+    }
+}
+
+fn parse_timestamp(timestamp: &str) -> Result<NaiveDateTime, String> {
+    NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
+        .map_err(|e| format!("Invalid timestamp \"{}\": {}", timestamp, e))
+}
+
+/// SQLite database of the nodes and edges heard by each device, so the map isn't
+/// empty while a radio streams its node database after a restart
+pub struct GraphStore {
+    connection: Connection,
+    written: HashMap<u32, GraphSnapshot>, // last snapshot written for each device
+}
+
+impl GraphStore {
+    pub fn open(path: &Path) -> Result<Self, MigrationError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {:?}: {}", parent, e))?;
+        }
+
+        let connection =
+            Connection::open(path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+
+        Self::with_connection(connection, Some(path))
+    }
+
+    pub fn open_in_memory() -> Result<Self, MigrationError> {
+        Self::with_connection(
+            Connection::open_in_memory().map_err(|e| e.to_string())?,
+            None,
+        )
+    }
+
+    fn with_connection(
+        mut connection: Connection,
+        path: Option<&Path>,
+    ) -> Result<Self, MigrationError> {
+        let version = migrate(&mut connection, "graph database", MIGRATIONS, path)?;
+        debug_assert_eq!(version, GRAPH_STORE_SCHEMA_VERSION);
+
+        Ok(Self {
+            connection,
+            written: HashMap::new(),
+        })
+    }
+
+    /// Writes the rows of `snapshot` that changed since the device's last write,
+    /// returning how many were written. Rows missing from the snapshot are kept,
+    /// so edges that timed out are still known after a restart.
+    pub fn write(&mut self, snapshot: GraphSnapshot) -> Result<usize, String> {
+        let previous = self.written.remove(&snapshot.device_id).unwrap_or_default();
+        let transaction = self.connection.transaction().map_err(|e| e.to_string())?;
+
+        let written = write_snapshot_rows(&transaction, &snapshot, &previous)?;
+
+        transaction.commit().map_err(|e| e.to_string())?;
+        self.written.insert(snapshot.device_id, snapshot);
+
+        Ok(written)
+    }
+
+    /// Starts a transaction to import snapshots with `import_snapshots`. The record of
+    /// rows already written is dropped, so the next write of each device rewrites them.
+    pub fn import_transaction(&mut self) -> Result<Transaction<'_>, String> {
+        self.written.clear();
+        self.connection.transaction().map_err(|e| e.to_string())
+    }
+
+    /// Reads the rows stored for every device
+    pub fn read_all_snapshots(&self) -> Result<Vec<GraphSnapshot>, String> {
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT device_id FROM nodes UNION SELECT device_id FROM edges
+                    ORDER BY device_id",
+            )
+            .map_err(|e| e.to_string())?;
+
+        let device_ids = statement
+            .query_map([], |row| row.get::<_, u32>(0))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        device_ids
+            .into_iter()
+            .map(|device_id| self.read_snapshot(device_id))
+            .collect()
+    }
+
+    /// Reads the rows stored for a device
+    pub fn read_snapshot(&self, device_id: u32) -> Result<GraphSnapshot, String> {
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT node_num, last_heard, timeout_millis, alias, info
+                    FROM nodes WHERE device_id = ?1",
+            )
+            .map_err(|e| e.to_string())?;
+
+        let nodes = statement
+            .query_map(params![device_id], |row| {
+                Ok((
+                    row.get::<_, u32>(0)?,
+                    NodeRow {
+                        last_heard: row.get(1)?,
+                        timeout_millis: row.get(2)?,
+                        alias: row.get(3)?,
+                        info: row.get(4)?,
+                    },
+                ))
+            })
+            .map_err(|e| e.to_string())?
+            .collect::<Result<HashMap<_, _>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT from_node, to_node, source, snr, last_heard, edge
+                    FROM edges WHERE device_id = ?1",
+            )
+            .map_err(|e| e.to_string())?;
+
+        let edges = statement
+            .query_map(params![device_id], |row| {
+                Ok((
+                    (row.get::<_, u32>(0)?, row.get::<_, u32>(1)?),
+                    EdgeRow {
+                        source: row.get(2)?,
+                        snr: row.get(3)?,
+                        last_heard: row.get(4)?,
+                        edge: row.get(5)?,
+                    },
+                ))
+            })
+            .map_err(|e| e.to_string())?
+            .collect::<Result<HashMap<_, _>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        Ok(GraphSnapshot {
+            device_id,
+            nodes,
+            edges,
+        })
+    }
+
+    /// Adds the stored nodes and edges of a device to `graph`, and the stored node
+    /// details to `device` for nodes it doesn't know yet. Returns the number of nodes
+    /// and edges loaded.
+    pub fn load_into(
+        &self,
+        device_id: u32,
+        graph: &mut MeshGraph,
+        device: &mut MeshDevice,
+    ) -> Result<(usize, usize), String> {
+        self.read_snapshot(device_id)?.load_into(graph, device)
+    }
+
+    /// Whether no device has any stored nodes
+    pub fn is_empty(&self) -> Result<bool, String> {
+        self.connection
+            .query_row("SELECT NOT EXISTS (SELECT 1 FROM nodes)", [], |row| {
+                row.get::<_, bool>(0)
+            })
+            .map_err(|e| e.to_string())
+    }
+
+    /// Upserts archived positions, returning how many were written
+    pub fn write_positions(
+        &mut self,
+        positions: &[(u32, ArchivedPosition)],
+    ) -> Result<usize, String> {
+        let transaction = self.connection.transaction().map_err(|e| e.to_string())?;
+
+        for (node_num, position) in positions {
+            transaction
+                .execute(
+                    "INSERT OR REPLACE INTO node_positions
+                        (node_num, latitude, longitude, altitude, source, timestamp)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        node_num,
+                        position.latitude,
+                        position.longitude,
+                        position.altitude,
+                        position.source.tag(),
+                        position.timestamp
+                    ],
+                )
+                .map_err(|e| e.to_string())?;
+        }
+
+        transaction.commit().map_err(|e| e.to_string())?;
+
+        Ok(positions.len())
+    }
+
+    pub fn read_positions(&self) -> Result<HashMap<u32, ArchivedPosition>, String> {
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT node_num, latitude, longitude, altitude, source, timestamp
+                    FROM node_positions",
+            )
+            .map_err(|e| e.to_string())?;
+
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, u32>(0)?,
+                    row.get::<_, f64>(1)?,
+                    row.get::<_, f64>(2)?,
+                    row.get::<_, i32>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, u32>(5)?,
+                ))
+            })
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        rows.into_iter()
+            .map(
+                |(node_num, latitude, longitude, altitude, source, timestamp)| {
+                    let source = PositionSource::from_tag(&source).ok_or_else(|| {
+                        format!(
+                            "Invalid position source \"{}\" of node {}",
+                            source, node_num
+                        )
+                    })?;
+
+                    Ok((
+                        node_num,
+                        ArchivedPosition {
+                            latitude,
+                            longitude,
+                            altitude,
+                            source,
+                            timestamp,
+                        },
+                    ))
+                },
+            )
+            .collect()
+    }
+
+    /// Returns whether the node had an archived position
+    pub fn delete_position(&mut self, node_num: u32) -> Result<bool, String> {
+        let deleted = self
+            .connection
+            .execute(
+                "DELETE FROM node_positions WHERE node_num = ?1",
+                params![node_num],
+            )
+            .map_err(|e| e.to_string())?;
+
+        Ok(deleted > 0)
+    }
+
+    pub fn position_usage(&self) -> Result<ClassUsage, String> {
+        let (rows, bytes) = POSITIONS_TABLE.usage(&self.connection)?;
+
+        Ok(ClassUsage {
+            class: StorageClass::Positions,
+            rows,
+            bytes,
+        })
+    }
+
+    /// Deletes the archived positions beyond the retention limits, returning the
+    /// nodes whose positions were deleted
+    pub fn enforce_position_retention(
+        &mut self,
+        limits: RetentionLimits,
+    ) -> Result<Vec<u32>, String> {
+        let transaction = self.connection.transaction().map_err(|e| e.to_string())?;
+        let deleted = POSITIONS_TABLE.enforce(&transaction, limits)?;
+        transaction.commit().map_err(|e| e.to_string())?;
+
+        Ok(deleted
+            .into_iter()
+            .map(|node_num| node_num as u32)
+            .collect())
+    }
+
+    pub fn graph_usage(&self) -> Result<ClassUsage, String> {
+        let (node_rows, node_bytes) = NODES_TABLE.usage(&self.connection)?;
+        let (edge_rows, edge_bytes) = EDGES_TABLE.usage(&self.connection)?;
+
+        Ok(ClassUsage {
+            class: StorageClass::Graphs,
+            rows: node_rows + edge_rows,
+            bytes: node_bytes + edge_bytes,
+        })
+    }
+
+    /// Deletes the nodes and edges beyond the retention limits, and the edges of
+    /// deleted nodes, returning how many rows were deleted. Nodes still in a connected
+    /// device's graph are written again when they're next heard.
+    pub fn enforce_graph_retention(&mut self, limits: RetentionLimits) -> Result<usize, String> {
+        let transaction = self.connection.transaction().map_err(|e| e.to_string())?;
+        let mut deleted = 0;
+
+        if let Some(cutoff) = limits.cutoff {
+            let cutoff = chrono::DateTime::from_timestamp(cutoff.into(), 0)
+                .ok_or("Invalid retention cutoff")?
+                .naive_utc()
+                .format(TIMESTAMP_FORMAT)
+                .to_string();
+
+            deleted += NODES_TABLE.prune_older_than(&transaction, &cutoff)?.len();
+            deleted += EDGES_TABLE.prune_older_than(&transaction, &cutoff)?.len();
+        }
+
+        if let Some(max_bytes) = limits.max_bytes {
+            deleted += prune_graph_to_size(&transaction, max_bytes)?;
+        }
+
+        deleted += transaction
+            .execute(
+                "DELETE FROM edges WHERE NOT EXISTS (
+                    SELECT 1 FROM nodes
+                        WHERE nodes.device_id = edges.device_id AND nodes.node_num = edges.from_node
+                ) OR NOT EXISTS (
+                    SELECT 1 FROM nodes
+                        WHERE nodes.device_id = edges.device_id AND nodes.node_num = edges.to_node
+                )",
+                [],
+            )
+            .map_err(|e| e.to_string())?;
+
+        transaction.commit().map_err(|e| e.to_string())?;
+
+        Ok(deleted)
+    }
+
+    /// Deletes every stored node, edge and archived position
+    pub fn clear(&mut self) -> Result<(), String> {
+        self.connection
+            .execute_batch("DELETE FROM nodes; DELETE FROM edges; DELETE FROM node_positions;")
+            .map_err(|e| e.to_string())?;
+
+        self.written.clear();
+
+        Ok(())
+    }
+}
+
+/// Deletes the least recently heard nodes until the rest, with the edges heard from
+/// them, take up at most `max_bytes`, returning how many nodes were deleted. Their
+/// edges are left for the caller to delete.
+fn prune_graph_to_size(transaction: &Transaction, max_bytes: u64) -> Result<usize, String> {
+    let sql = format!(
+        "DELETE FROM nodes WHERE (device_id, node_num) IN (
+            SELECT device_id, node_num FROM (
+                SELECT nodes.device_id, nodes.node_num,
+                    SUM({node_bytes} + {overhead} + COALESCE(from_edges.bytes, 0)) OVER (
+                        ORDER BY nodes.last_heard DESC, nodes.device_id DESC, nodes.node_num DESC
+                        ROWS UNBOUNDED PRECEDING
+                    ) AS total
+                    FROM nodes
+                    LEFT JOIN (
+                        SELECT device_id, from_node, SUM({edge_bytes} + {overhead}) AS bytes
+                            FROM edges GROUP BY device_id, from_node
+                    ) AS from_edges
+                        ON from_edges.device_id = nodes.device_id
+                            AND from_edges.from_node = nodes.node_num
+            ) WHERE total > ?1
+        )",
+        node_bytes = NODES_TABLE.row_bytes,
+        edge_bytes = EDGES_TABLE.row_bytes,
+        overhead = ROW_OVERHEAD_BYTES,
+    );
+
+    transaction
+        .execute(&sql, params![max_bytes as i64])
+        .map_err(|e| e.to_string())
+}
+
+/// Writes the rows of `snapshot` that differ from `previous`, returning how many were written
+fn write_snapshot_rows(
+    transaction: &Transaction,
+    snapshot: &GraphSnapshot,
+    previous: &GraphSnapshot,
+) -> Result<usize, String> {
+    let mut written = 0;
+
+    for (node_num, row) in &snapshot.nodes {
+        if previous.nodes.get(node_num) == Some(row) {
+            continue;
+        }
+
+        transaction
+            .execute(
+                "INSERT OR REPLACE INTO nodes
+                    (device_id, node_num, last_heard, timeout_millis, alias, info)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    snapshot.device_id,
+                    node_num,
+                    row.last_heard,
+                    row.timeout_millis,
+                    row.alias,
+                    row.info
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+
+        written += 1;
+    }
+
+    for ((from, to), row) in &snapshot.edges {
+        if previous.edges.get(&(*from, *to)) == Some(row) {
+            continue;
+        }
+
+        transaction
+            .execute(
+                "INSERT OR REPLACE INTO edges
+                    (device_id, from_node, to_node, source, snr, last_heard, edge)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    snapshot.device_id,
+                    from,
+                    to,
+                    row.source,
+                    row.snr,
+                    row.last_heard,
+                    row.edge
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+
+        written += 1;
+    }
+
+    Ok(written)
+}
+
+/// Writes imported snapshots in a transaction from `GraphStore::import_transaction`,
+/// after deleting every stored row if `replace` is set. Rows of a device that aren't
+/// in its snapshot are otherwise kept.
+pub fn import_snapshots(
+    transaction: &Transaction,
+    snapshots: &[GraphSnapshot],
+    replace: bool,
+) -> Result<usize, String> {
+    if replace {
+        transaction
+            .execute_batch("DELETE FROM nodes; DELETE FROM edges;")
+            .map_err(|e| e.to_string())?;
+    }
+
+    snapshots
+        .iter()
+        .map(|snapshot| write_snapshot_rows(transaction, snapshot, &GraphSnapshot::default()))
+        .sum()
+}
+
+/// Reads the graph stored for a device, or `None` if the database isn't available
+fn read_stored_snapshot<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_id: u32,
+) -> Option<Result<GraphSnapshot, String>> {
+    let store_state = handle.try_state::<state::graph_store::GraphStoreState>()?;
+
+    let store_guard = match store_state.inner.lock() {
+        Ok(store_guard) => store_guard,
+        Err(e) => return Some(Err(e.to_string())),
+    };
+
+    store_guard
+        .as_ref()
+        .map(|store| store.read_snapshot(device_id))
+}
+
+/// Loads the graph stored for the device once it has reported its node num, before
+/// its node database arrives. Without a database, a graph recovered from an autosave
+/// is loaded instead.
+pub fn initialize_graph_state<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    graph: &mut MeshGraph,
+    device: &mut MeshDevice,
+) {
+    let device_id = device.my_node_info.my_node_num;
+
+    let snapshot = match read_stored_snapshot(handle, device_id) {
+        Some(Ok(snapshot)) => snapshot,
+        Some(Err(e)) => {
+            warn!("Failed to read stored graph: {}", e);
+            return;
+        }
+        None => match recovered_snapshot(handle, device_id) {
+            Some(snapshot) => snapshot,
+            None => return,
+        },
+    };
+
+    match snapshot.load_into(graph, device) {
+        Ok((nodes, edges)) => debug!(
+            "Loaded {} nodes and {} edges stored for device {}",
+            nodes, edges, device_id
+        ),
+        Err(e) => warn!("Failed to load stored graph: {}", e),
+    }
+}
+
+/// Reconciles the node database a radio streamed during configuration with the nodes
+/// stored for it. Persisted nodes the radio no longer knows are added to the graph if
+/// they aren't in it, and the graph is marked changed so updated and new records are
+/// written. Returns `None` for devices whose graph isn't stored, or without a database.
+pub fn reconcile_persisted_nodes<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+) -> Option<Result<ReconcileReport, String>> {
+    if !stores_graph(packet_api) {
+        return None;
+    }
+
+    let device_id = packet_api.device.my_node_info.my_node_num;
+
+    let persisted = match read_stored_snapshot(&packet_api.app_handle, device_id)?
+        .and_then(|snapshot| snapshot.persisted_nodes())
+    {
+        Ok(persisted) => persisted,
+        Err(e) => return Some(Err(e)),
+    };
+
+    let report = packet_api.device.reconcile_node_db(&persisted);
+
+    match packet_api.write_graph() {
+        Ok(mut graph) => {
+            for node_num in &report.device_missing {
+                if graph.contains_node(*node_num) {
+                    continue;
+                }
+
+                if let Some(node) = persisted.graph_node(*node_num) {
+                    graph.upsert_node(node);
+                }
+            }
+        }
+        Err(e) => return Some(Err(e.to_string())),
+    }
+
+    if !report.updated.is_empty() || !report.store_missing.is_empty() {
+        mark_graph_changed(packet_api);
+    }
+
+    Some(Ok(report))
+}
+
+/// Only radios' graphs are stored, simulated and replayed devices' aren't
+pub fn stores_graph<R: tauri::Runtime>(packet_api: &MeshPacketApi<R>) -> bool {
+    matches!(
+        packet_api.connection_type,
+        ConnectionType::Serial | ConnectionType::Tcp
+    ) && packet_api.device.my_node_info.my_node_num != 0
+}
+
+/// Marks the device's graph as changed so the writer stores it
+pub fn mark_graph_changed<R: tauri::Runtime>(packet_api: &MeshPacketApi<R>) {
+    if !stores_graph(packet_api) {
+        return;
+    }
+
+    let store_state = match packet_api
+        .app_handle
+        .try_state::<state::graph_store::GraphStoreState>()
+    {
+        Some(store_state) => store_state,
+        None => return,
+    };
+
+    match store_state.changed.lock() {
+        Ok(mut changed) => {
+            changed.insert(packet_api.device_key.clone());
+        }
+        Err(e) => warn!("Failed to lock changed graphs: {}", e),
+    }
+}
+
+/// Archives the node's last known position from the device, if it has one. Only
+/// radios' positions are archived, like their graphs.
+pub fn archive_node_position<R: tauri::Runtime>(
+    packet_api: &MeshPacketApi<R>,
+    graph: &mut MeshGraph,
+    node_num: u32,
+    source: PositionSource,
+) {
+    if !stores_graph(packet_api) {
+        return;
+    }
+
+    let position = match packet_api
+        .device
+        .nodes
+        .get(&node_num)
+        .and_then(|node| node.last_known_position())
+    {
+        Some(position) => position,
+        None => return,
+    };
+
+    let timestamp = match (position.time, position.timestamp) {
+        (0, 0) => get_current_time_u32(),
+        (0, timestamp) => timestamp,
+        (time, _) => time,
+    };
+
+    graph.position_archive.record(
+        node_num,
+        ArchivedPosition {
+            latitude: position.latitude.into(),
+            longitude: position.longitude.into(),
+            altitude: position.altitude,
+            source,
+            timestamp,
+        },
+    );
+}
+
+/// Replaces the graph's position archive with the one stored in the graph database,
+/// or an empty one without a database
+pub fn load_position_archive<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+) -> Result<usize, String> {
+    let positions = match handle.try_state::<state::graph_store::GraphStoreState>() {
+        Some(store_state) => match store_state
+            .inner
+            .lock()
+            .map_err(|e| e.to_string())?
+            .as_ref()
+        {
+            Some(store) => store.read_positions()?,
+            None => HashMap::new(),
+        },
+        None => HashMap::new(),
+    };
+
+    let count = positions.len();
+
+    if let Some(graph_state) = handle.try_state::<state::graph::GraphState>() {
+        graph_state.inner.write()?.position_archive = PositionArchive::new(positions);
+    }
+
+    Ok(count)
+}
+
+/// Positions archived since the last write
+fn take_changed_positions(
+    handle: &tauri::AppHandle,
+) -> Result<Vec<(u32, ArchivedPosition)>, String> {
+    let graph_state = handle.state::<state::graph::GraphState>();
+    let mut graph = graph_state.inner.write()?;
+
+    Ok(graph.position_archive.take_changed())
+}
+
+/// Snapshots the graphs of the connected devices that are stored, or only of those
+/// in `device_keys` if given. Each device is only locked to copy its graph, which is
+/// serialized on a blocking thread.
+pub async fn snapshot_graphs(
+    handle: &tauri::AppHandle,
+    device_keys: Option<&HashSet<DeviceKey>>,
+) -> Vec<GraphSnapshot> {
+    let mesh_devices = handle.state::<state::mesh_devices::MeshDevicesState>();
+    let mut copies = vec![];
+
+    for (device_key, device) in all_devices(&mesh_devices.inner).await {
+        let packet_api = device.lock().await;
+
+        if !stores_graph(&*packet_api)
+            || device_keys.map_or(false, |keys| !keys.contains(&device_key))
+        {
+            continue;
+        }
+
+        match packet_api.graph_arc.view() {
+            Ok(graph) => copies.push((device_key, graph, packet_api.device.clone())),
+            Err(e) => warn!("Failed to copy graph of {}: {}", device_key, e),
+        }
+    }
+
+    let serialized = tauri::async_runtime::spawn_blocking(move || {
+        copies
+            .into_iter()
+            .filter_map(|(device_key, graph, device)| {
+                let device_id = device.my_node_info.my_node_num;
+
+                match GraphSnapshot::new(device_id, &graph, &device) {
+                    Ok(snapshot) => Some(snapshot),
+                    Err(e) => {
+                        warn!("Failed to snapshot graph of {}: {}", device_key, e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    })
+    .await;
+
+    match serialized {
+        Ok(snapshots) => snapshots,
+        Err(e) => {
+            warn!("Failed to snapshot graphs: {}", e);
+            vec![]
+        }
+    }
+}
+
+/// Writes the graphs of devices that changed since the last write, and the positions
+/// archived since then, returning the number of rows written
+pub async fn write_changed_graphs(handle: &tauri::AppHandle) -> Result<usize, String> {
+    let store_state = handle.state::<state::graph_store::GraphStoreState>();
+
+    let changed = std::mem::take(&mut *store_state.changed.lock().map_err(|e| e.to_string())?);
+    let positions = take_changed_positions(handle)?;
+
+    if changed.is_empty() && positions.is_empty() {
+        return Ok(0);
+    }
+
+    let snapshots = match changed.is_empty() {
+        true => vec![],
+        false => snapshot_graphs(handle, Some(&changed)).await,
+    };
+
+    let store = store_state.inner.clone();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut store_guard = store.lock().map_err(|e| e.to_string())?;
+
+        let store = match store_guard.as_mut() {
+            Some(store) => store,
+            None => return Ok(0),
+        };
+
+        let positions_written = store.write_positions(&positions)?;
+
+        snapshots
+            .into_iter()
+            .map(|snapshot| store.write(snapshot))
+            .sum::<Result<usize, String>>()
+            .map(|rows| rows + positions_written)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Periodically writes the graphs of devices that changed since the last write
+pub fn spawn_graph_store_writer(handle: tauri::AppHandle) {
+    trace!("Spawning graph database writer");
+
+    tauri::async_runtime::spawn(async move {
+        let shutdown = shutdown_signal(&handle);
+        let mut interval = tokio::time::interval(GRAPH_STORE_WRITE_INTERVAL);
+
+        while shutdown.tick(&mut interval).await {
+            match write_changed_graphs(&handle).await {
+                Ok(0) => {}
+                Ok(rows) => trace!("Wrote {} graph rows", rows),
+                Err(e) => warn!("Failed to write graph database: {}", e),
+            }
+        }
+
+        trace!("Stopped graph database writer");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::graph::api::update_from_packet::DEFAULT_NODE_TIMEOUT_DURATION;
+    use crate::migrations;
+
+    fn graph_node(node_num: u32, minutes_ago: i64) -> GraphNode {
+        GraphNode {
+            node_num,
+            last_heard: chrono::Utc::now().naive_utc() - chrono::Duration::minutes(minutes_ago),
+            timeout_duration: DEFAULT_NODE_TIMEOUT_DURATION,
+        }
+    }
+
+    fn fixture() -> (MeshGraph, MeshDevice) {
+        let mut graph = MeshGraph::new();
+        let nodes = [graph_node(1, 0), graph_node(2, 5), graph_node(3, 90)];
+
+        for node in nodes {
+            graph.upsert_node(node);
+        }
+
+        let neighbor = |node_id: u32, snr: f32| protobufs::Neighbor {
+            node_id,
+            snr,
+            ..Default::default()
+        };
+
+        graph.upsert_edge(
+            nodes[1],
+            nodes[0],
+            GraphEdge::from_neighbor(1, 0, "LongFast".into(), neighbor(2, 6.5)),
+        );
+        graph.upsert_edge(
+            nodes[2],
+            nodes[1],
+            GraphEdge::from_neighbor(2, 0, "LongFast".into(), neighbor(3, -4.25)),
+        );
+        graph.set_node_label(3, Some("Ridge relay".into()));
+
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 1;
+
+        let mut node = MeshNode::new(2);
+        node.user = Some(protobufs::User {
+            long_name: "Trailhead".into(),
+            ..Default::default()
+        });
+        device.nodes.insert(2, node);
+
+        (graph, device)
+    }
+
+    fn temp_database(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "graph-store-{}-{}.sqlite3",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn reloads_graph_with_timestamps() {
+        let path = temp_database("reload");
+        let (graph, device) = fixture();
+
+        {
+            let mut store = GraphStore::open(&path).unwrap();
+            let snapshot = GraphSnapshot::new(1, &graph, &device).unwrap();
+
+            assert_eq!(store.write(snapshot.clone()).unwrap(), 5);
+
+            // Unchanged rows aren't written again
+            assert_eq!(store.write(snapshot).unwrap(), 0);
+        }
+
+        let store = GraphStore::open(&path).unwrap();
+        assert_eq!(
+            migrations::schema_version(&store.connection).unwrap(),
+            Some(GRAPH_STORE_SCHEMA_VERSION)
+        );
+
+        let mut loaded_graph = MeshGraph::new();
+        let mut loaded_device = MeshDevice::new();
+
+        assert_eq!(
+            store
+                .load_into(1, &mut loaded_graph, &mut loaded_device)
+                .unwrap(),
+            (3, 2)
+        );
+
+        let mut nodes: Vec<GraphNode> = graph.nodes().collect();
+        let mut loaded_nodes: Vec<GraphNode> = loaded_graph.nodes().collect();
+        nodes.sort();
+        loaded_nodes.sort();
+
+        for (node, loaded) in nodes.iter().zip(&loaded_nodes) {
+            assert_eq!(node.node_num, loaded.node_num);
+            assert_eq!(node.last_heard, loaded.last_heard);
+            assert_eq!(node.timeout_duration, loaded.timeout_duration);
+        }
+
+        assert_eq!(nodes.len(), loaded_nodes.len());
+
+        let edges = |graph: &MeshGraph| {
+            let mut edges: Vec<String> = graph
+                .edges()
+                .map(|(from, to, edge)| {
+                    format!(
+                        "{} {} {}",
+                        from.node_num,
+                        to.node_num,
+                        serde_json::to_string(edge).unwrap()
+                    )
+                })
+                .collect();
+            edges.sort();
+            edges
+        };
+
+        assert_eq!(edges(&loaded_graph), edges(&graph));
+        assert_eq!(loaded_graph.overrides.node_label(3), Some("Ridge relay"));
+        assert_eq!(
+            loaded_device.nodes[&2].user.as_ref().unwrap().long_name,
+            "Trailhead"
+        );
+
+        // Other devices' graphs are kept apart
+        let mut other_graph = MeshGraph::new();
+        assert_eq!(
+            store
+                .load_into(2, &mut other_graph, &mut MeshDevice::new())
+                .unwrap(),
+            (0, 0)
+        );
+
+        drop(store);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn clear_deletes_everything() {
+        let (graph, device) = fixture();
+        let mut store = GraphStore::open_in_memory().unwrap();
+        let snapshot = GraphSnapshot::new(1, &graph, &device).unwrap();
+
+        store.write(snapshot.clone()).unwrap();
+        store.clear().unwrap();
+
+        let mut loaded_graph = MeshGraph::new();
+        assert_eq!(
+            store
+                .load_into(1, &mut loaded_graph, &mut MeshDevice::new())
+                .unwrap(),
+            (0, 0)
+        );
+
+        // Everything is written again after clearing
+        assert_eq!(store.write(snapshot).unwrap(), 5);
+    }
+
+    #[test]
+    fn archives_positions_across_restart() {
+        let path = temp_database("positions");
+        let position = |latitude: f64, timestamp: u32| ArchivedPosition {
+            latitude,
+            longitude: -122.3,
+            altitude: 12,
+            source: PositionSource::NodeInfo,
+            timestamp,
+        };
+
+        {
+            let mut store = GraphStore::open(&path).unwrap();
+            let mut archive = PositionArchive::default();
+
+            archive.record(2, position(47.6, 1_000));
+            archive.record(3, position(47.7, 2_000));
+            assert_eq!(store.write_positions(&archive.take_changed()).unwrap(), 2);
+
+            archive.record(2, position(47.65, 3_000));
+            assert_eq!(store.write_positions(&archive.take_changed()).unwrap(), 1);
+        }
+
+        let mut store = GraphStore::open(&path).unwrap();
+        let archive = PositionArchive::new(store.read_positions().unwrap());
+        assert_eq!(archive.len(), 2);
+        assert_eq!(archive.get(2), Some(&position(47.65, 3_000)));
+
+        let limits = RetentionLimits {
+            cutoff: Some(2_500),
+            max_bytes: None,
+        };
+        assert_eq!(store.enforce_position_retention(limits).unwrap(), vec![3]);
+        assert!(store.delete_position(2).unwrap());
+        assert!(!store.delete_position(2).unwrap());
+        assert!(store.read_positions().unwrap().is_empty());
+
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,50 @@
+use std::path::Path;
+
+use crate::backup::{self, BackupInfo};
+use crate::ipc::CommandError;
+
+use log::{debug, trace};
+
+/// Backs up the active profile's databases now, whether or not backups are scheduled
+#[tauri::command]
+pub async fn run_backup_now(app_handle: tauri::AppHandle) -> Result<BackupInfo, CommandError> {
+    debug!("Called run_backup_now command");
+
+    let backup = backup::run_backup(&app_handle).await?;
+
+    Ok(backup)
+}
+
+/// Backups of the active profile, newest first
+#[tauri::command]
+pub async fn list_backups(app_handle: tauri::AppHandle) -> Result<Vec<BackupInfo>, CommandError> {
+    debug!("Called list_backups command");
+
+    let backups = tauri::async_runtime::spawn_blocking(move || backup::list_backups(&app_handle))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    Ok(backups)
+}
+
+/// Replaces the active profile's databases with a backup's. Every device is
+/// disconnected and the stores are reopened on the restored databases.
+#[tauri::command]
+pub async fn restore_backup(
+    path: String,
+    confirmed: bool,
+    app_handle: tauri::AppHandle,
+) -> Result<BackupInfo, CommandError> {
+    debug!("Called restore_backup command");
+    trace!("Called with path {}", path);
+
+    // Restoring discards everything received since the backup was taken
+
+    if !confirmed {
+        return Err("Backup restores must be explicitly confirmed".into());
+    }
+
+    let backup = backup::restore_backup(&app_handle, Path::new(&path)).await?;
+
+    Ok(backup)
+}
@@ -1,13 +1,33 @@
+use crate::connection::log_tap::SerialLogTap;
+use crate::connection::metrics::{ConnectionMetrics, MeteredStream, SharedConnectionMetrics};
+use crate::connection::mqtt::{
+    spawn_mqtt_handler, subscribe, subscription_topic, MqttBroker, MQTT_SUBSCRIBE_TIMEOUT,
+};
+use crate::connection::options::{
+    configuration_timeout, graph_update_window, heartbeat, validate_baud_rate, Heartbeat,
+    HeartbeatOptions,
+};
+use crate::connection::recent::{record_recent_device, RecentDevice};
+use crate::connection::serial_lines::{apply_serial_line_control, SerialLineControl};
+use crate::connection::tcp::{tcp_socket_address, TCP_CONNECT_TIMEOUT};
 use crate::device;
+use crate::device::helpers::get_current_time_u32;
 use crate::device::SerialDeviceStatus;
+use crate::ipc::events::{dispatch_configuration_status, dispatch_devices_list_changed};
 use crate::ipc::helpers::spawn_configuration_timeout_handler;
 use crate::ipc::helpers::spawn_decoded_handler;
-use crate::ipc::CommandError;
+use crate::ipc::helpers::spawn_serial_log_handler;
+use crate::ipc::helpers::{cancel_reconnect, disconnect_all_devices};
+use crate::ipc::{CommandError, ConfigurationStatus, DevicesListChange, EVENT_API_VERSION};
+use crate::packet_api::actor::device_actor;
+use crate::packet_api::summary::{ConnectedDeviceSummary, ConnectionType};
 use crate::packet_api::MeshPacketApi;
+use crate::persistence::{save_json, RECENT_DEVICES_FILE_NAME};
 use crate::state;
+use crate::state::mesh_devices::{device_summaries, get_device, get_device_handle};
 use crate::state::DeviceKey;
 
-use log::debug;
+use log::{debug, info, trace};
 use meshtastic::api::{StreamApi, StreamHandle};
 use meshtastic::utils::stream::build_serial_stream;
 use meshtastic::utils::stream::build_tcp_stream;
@@ -46,27 +66,42 @@ pub fn get_all_serial_ports() -> Result<Vec<String>, CommandError> {
     Ok(ports)
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn create_new_connection<S>(
     stream: StreamHandle<S>,
     device_key: DeviceKey,
+    connection_type: ConnectionType,
     timeout_duration: Duration,
+    heartbeat: Option<Heartbeat>,
+    graph_update_window: Duration,
+    line_control: Option<SerialLineControl>,
     app_handle: tauri::AppHandle,
     mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
     radio_connections: tauri::State<'_, state::radio_connections::RadioConnectionsState>,
     mesh_graph: tauri::State<'_, state::graph::GraphState>,
 ) -> Result<(), CommandError>
 where
-    S: AsyncReadExt + AsyncWriteExt + Send + 'static,
+    S: AsyncReadExt + AsyncWriteExt + Send + Unpin + 'static,
 {
+    // Count traffic over the connection for diagnostics
+
+    let metrics = SharedConnectionMetrics::default();
+    let stream = StreamHandle::from_stream(MeteredStream::new(stream.stream, metrics.clone()));
+
     // Initialize device and StreamApi instances
 
-    let device = device::MeshDevice::new();
+    let mut device = device::MeshDevice::new();
+    device.serial_line_control = line_control;
+
     let mut packet_api = MeshPacketApi::new(
         app_handle.app_handle(),
         device_key.clone(),
         device,
         mesh_graph.inner.clone(),
     );
+    packet_api.metrics = metrics;
+    packet_api.connection_type = connection_type;
+    packet_api.graph_publish.set_interval(graph_update_window);
 
     let stream_api = StreamApi::new();
 
@@ -81,8 +116,10 @@ where
         .device
         .set_status(SerialDeviceStatus::Configuring);
 
+    let config_id = packet_api.device.config_id;
+
     let stream_api = stream_api
-        .configure(packet_api.device.config_id)
+        .configure(config_id)
         .await
         .map_err(|e| e.to_string())?;
 
@@ -92,174 +129,536 @@ where
     let mesh_devices_arc = mesh_devices.inner.clone();
     let radio_connections_arc = radio_connections.inner.clone();
 
-    // Persist device struct in Tauri state
+    // Persist the handle to the device's task in Tauri state
+
+    let (device, actor) = device_actor(packet_api);
+    let actor = actor.with_heartbeat(heartbeat);
+
     {
         let mut devices_guard = mesh_devices_arc.lock().await;
-        devices_guard.insert(device_key.clone(), packet_api);
+        devices_guard.insert(device_key.clone(), device.clone());
     }
 
+    dispatch_devices_list_changed(
+        &handle,
+        device_key.clone(),
+        DevicesListChange::Added,
+        SerialDeviceStatus::Configuring,
+    )
+    .map_err(|e| e.to_string())?;
+
     // Persist StreamApi instance Tauri state
     {
         let mut connections_guard = radio_connections_arc.lock().await;
         connections_guard.insert(device_key.clone(), stream_api);
     }
 
-    // Spawn timeout handler to catch invlaid device connections
-    // Needs the device struct and port name to be loaded into Tauri state before running
+    // Spawn timeout handler to catch invalid device connections
 
     spawn_configuration_timeout_handler(
         handle.clone(),
-        mesh_devices_arc.clone(),
+        device,
         device_key.clone(),
+        config_id,
         timeout_duration,
     );
 
-    // Spawn decoded packet handler to route decoded packets
+    // Spawn the device's task to route decoded packets
 
-    spawn_decoded_handler(decoded_listener, mesh_devices_arc, device_key);
+    spawn_decoded_handler(
+        handle,
+        decoded_listener,
+        actor,
+        mesh_devices_arc,
+        radio_connections_arc,
+        device_key,
+    );
 
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 #[tauri::command]
 pub async fn connect_to_serial_port(
     port_name: String,
     baud_rate: Option<u32>,
     dtr: Option<bool>,
     rts: Option<bool>,
+    line_control: Option<SerialLineControl>,
+    configuration_timeout_ms: Option<u32>,
+    heartbeat_options: Option<HeartbeatOptions>,
+    graph_update_window_ms: Option<u32>,
     app_handle: tauri::AppHandle,
-    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
-    radio_connections: tauri::State<'_, state::radio_connections::RadioConnectionsState>,
-    mesh_graph: tauri::State<'_, state::graph::GraphState>,
 ) -> Result<(), CommandError> {
     debug!(
         "Called connect_to_serial_port command with port \"{}\"",
         port_name
     );
 
+    connect_serial(
+        app_handle,
+        port_name,
+        baud_rate,
+        dtr,
+        rts,
+        line_control,
+        configuration_timeout_ms,
+        heartbeat_options,
+        graph_update_window_ms,
+    )
+    .await
+}
+
+/// Opens a serial connection and starts the configuration flow, recording the port
+/// in the recent devices list. The baud rate, timeout and heartbeat are checked before
+/// the port is opened.
+#[allow(clippy::too_many_arguments)]
+pub async fn connect_serial(
+    app_handle: tauri::AppHandle,
+    port_name: String,
+    baud_rate: Option<u32>,
+    dtr: Option<bool>,
+    rts: Option<bool>,
+    line_control: Option<SerialLineControl>,
+    configuration_timeout_ms: Option<u32>,
+    heartbeat_options: Option<HeartbeatOptions>,
+    graph_update_window_ms: Option<u32>,
+) -> Result<(), CommandError> {
+    validate_baud_rate(baud_rate).map_err(|e| CommandError::invalid_argument("baudRate", e))?;
+    let timeout = configuration_timeout(configuration_timeout_ms)
+        .map_err(|e| CommandError::invalid_argument("configurationTimeoutMs", e))?;
+    let heartbeat = heartbeat(heartbeat_options)
+        .map_err(|e| CommandError::invalid_argument("heartbeatOptions", e))?;
+    let graph_update_window = graph_update_window(graph_update_window_ms)
+        .map_err(|e| CommandError::invalid_argument("graphUpdateWindowMs", e))?;
+
     // Create serial connection stream
 
-    let stream =
-        build_serial_stream(port_name.clone(), baud_rate, dtr, rts).map_err(|e| e.to_string())?;
+    let mut stream = build_serial_stream(port_name.clone(), baud_rate, dtr, rts).map_err(|e| {
+        CommandError::PortOpenFailed {
+            port_name: port_name.clone(),
+            error: e.to_string(),
+        }
+    })?;
+
+    // Apply requested DTR/RTS line behavior before the configure handshake
+
+    let line_control = line_control.unwrap_or_default();
+
+    info!(
+        "Using serial line control {:?} for port \"{}\"",
+        line_control, port_name
+    );
+
+    apply_serial_line_control(&mut stream.stream, &line_control)
+        .await
+        .map_err(|e| format!("Failed to set serial lines on \"{}\": {}", port_name, e))?;
+
+    // Firmware writes plain-text debug logs to the same port, so tap the
+    // stream to capture them without disturbing the protobuf frames
+
+    let (log_lines_tx, log_lines_rx) = tokio::sync::mpsc::unbounded_channel();
+    let stream = StreamHandle::from_stream(SerialLogTap::new(stream.stream, log_lines_tx));
+
+    spawn_serial_log_handler(app_handle.clone(), log_lines_rx, port_name.clone());
 
     // Create and persist new connection
 
     create_new_connection(
         stream,
-        port_name,
-        Duration::from_millis(15000),
-        app_handle,
-        mesh_devices,
-        radio_connections,
-        mesh_graph,
+        port_name.clone(),
+        ConnectionType::Serial,
+        timeout,
+        heartbeat,
+        graph_update_window,
+        Some(line_control),
+        app_handle.clone(),
+        app_handle.state(),
+        app_handle.state(),
+        app_handle.state(),
     )
     .await?;
 
+    record_recent_device(
+        &app_handle,
+        RecentDevice::new(
+            ConnectionType::Serial,
+            port_name,
+            baud_rate,
+            get_current_time_u32(),
+        ),
+    );
+
     Ok(())
 }
 
 #[tauri::command]
 pub async fn connect_to_tcp_port(
     address: String,
+    configuration_timeout_ms: Option<u32>,
+    heartbeat_options: Option<HeartbeatOptions>,
+    graph_update_window_ms: Option<u32>,
     app_handle: tauri::AppHandle,
-    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
-    radio_connections: tauri::State<'_, state::radio_connections::RadioConnectionsState>,
-    mesh_graph: tauri::State<'_, state::graph::GraphState>,
 ) -> Result<(), CommandError> {
     debug!(
         "Called connect_to_tcp_port command with address \"{}\"",
         address
     );
 
+    connect_tcp(
+        app_handle,
+        address,
+        configuration_timeout_ms,
+        heartbeat_options,
+        graph_update_window_ms,
+    )
+    .await
+}
+
+/// Opens a TCP connection and starts the configuration flow, recording the address
+/// in the recent devices list. Addresses without a port use the firmware's.
+pub async fn connect_tcp(
+    app_handle: tauri::AppHandle,
+    address: String,
+    configuration_timeout_ms: Option<u32>,
+    heartbeat_options: Option<HeartbeatOptions>,
+    graph_update_window_ms: Option<u32>,
+) -> Result<(), CommandError> {
+    let address =
+        tcp_socket_address(&address).map_err(|e| CommandError::invalid_argument("address", e))?;
+    let timeout = configuration_timeout(configuration_timeout_ms)
+        .map_err(|e| CommandError::invalid_argument("configurationTimeoutMs", e))?;
+    let heartbeat = heartbeat(heartbeat_options)
+        .map_err(|e| CommandError::invalid_argument("heartbeatOptions", e))?;
+    let graph_update_window = graph_update_window(graph_update_window_ms)
+        .map_err(|e| CommandError::invalid_argument("graphUpdateWindowMs", e))?;
+
     // Create TCP connection stream
 
-    let stream = build_tcp_stream(address.clone())
+    let stream = tokio::time::timeout(TCP_CONNECT_TIMEOUT, build_tcp_stream(address.clone()))
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(|_| CommandError::TimedOut {
+            operation: format!("connecting to \"{}\"", address),
+        })?
+        .map_err(|e| CommandError::PortOpenFailed {
+            port_name: address.clone(),
+            error: e.to_string(),
+        })?;
 
     // Create and persist new connection
 
     create_new_connection(
         stream,
-        address,
-        Duration::from_millis(15000),
-        app_handle,
-        mesh_devices,
-        radio_connections,
-        mesh_graph,
+        address.clone(),
+        ConnectionType::Tcp,
+        timeout,
+        heartbeat,
+        graph_update_window,
+        None,
+        app_handle.clone(),
+        app_handle.state(),
+        app_handle.state(),
+        app_handle.state(),
     )
     .await?;
 
+    record_recent_device(
+        &app_handle,
+        RecentDevice::new(ConnectionType::Tcp, address, None, get_current_time_u32()),
+    );
+
     Ok(())
 }
 
+/// Watches the mesh through the packets MQTT gateways mirror to a broker, without a
+/// radio. The connection is stored as `mqtt:<host>:<port>` and handles packets like a
+/// radio's, but as there's no radio to configure it's connected once subscribed.
+#[tauri::command]
+pub async fn connect_mqtt(
+    broker_url: String,
+    username: Option<String>,
+    password: Option<String>,
+    root_topic: Option<String>,
+    graph_update_window_ms: Option<u32>,
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    radio_connections: tauri::State<'_, state::radio_connections::RadioConnectionsState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<DeviceKey, CommandError> {
+    debug!("Called connect_mqtt command");
+    trace!("Called with broker \"{}\"", broker_url);
+
+    let broker = MqttBroker::parse(&broker_url)
+        .map_err(|e| CommandError::invalid_argument("brokerUrl", e))?;
+    let graph_update_window = graph_update_window(graph_update_window_ms)
+        .map_err(|e| CommandError::invalid_argument("graphUpdateWindowMs", e))?;
+
+    let device_key = broker.device_key();
+
+    if mesh_devices.inner.lock().await.contains_key(&device_key) {
+        return Err(format!("Already connected to \"{}\"", device_key).into());
+    }
+
+    // Subscribe before the device is added, so a broker that can't be reached
+    // fails like a port that can't be opened
+
+    let topic = subscription_topic(root_topic.as_deref());
+
+    let (client, event_loop) = tokio::time::timeout(
+        MQTT_SUBSCRIBE_TIMEOUT,
+        subscribe(&broker, username, password, &topic),
+    )
+    .await
+    .map_err(|_| CommandError::TimedOut {
+        operation: format!("subscribing to \"{}\"", device_key),
+    })?
+    .map_err(|e| CommandError::PortOpenFailed {
+        port_name: device_key.clone(),
+        error: e,
+    })?;
+
+    info!("Subscribed to \"{}\" on \"{}\"", topic, device_key);
+
+    let mut packet_api = MeshPacketApi::new(
+        app_handle.app_handle(),
+        device_key.clone(),
+        device::MeshDevice::new(),
+        mesh_graph.inner.clone(),
+    );
+    packet_api.connection_type = ConnectionType::Mqtt;
+    packet_api.graph_publish.set_interval(graph_update_window);
+
+    // A broker has no configuration flow, so it's connected once subscribed
+    packet_api.device.set_status(SerialDeviceStatus::Connected);
+
+    let metrics = packet_api.metrics.clone();
+    let (decoded_tx, decoded_listener) = tokio::sync::mpsc::unbounded_channel();
+    let (device, actor) = device_actor(packet_api);
+
+    mesh_devices
+        .inner
+        .lock()
+        .await
+        .insert(device_key.clone(), device);
+
+    dispatch_devices_list_changed(
+        &app_handle,
+        device_key.clone(),
+        DevicesListChange::Added,
+        SerialDeviceStatus::Connected,
+    )
+    .map_err(|e| e.to_string())?;
+
+    dispatch_configuration_status(
+        &app_handle,
+        ConfigurationStatus {
+            api_version: EVENT_API_VERSION,
+            device_key: device_key.clone(),
+            successful: true,
+            message: None,
+        },
+    )
+    .map_err(|e| e.to_string())?;
+
+    spawn_mqtt_handler(
+        client,
+        event_loop,
+        topic,
+        decoded_tx,
+        metrics,
+        device_key.clone(),
+    );
+
+    // Nothing is stored in the radio connections, as there's no radio to send to
+
+    spawn_decoded_handler(
+        app_handle,
+        decoded_listener,
+        actor,
+        mesh_devices.inner.clone(),
+        radio_connections.inner.clone(),
+        device_key.clone(),
+    );
+
+    Ok(device_key)
+}
+
 #[tauri::command]
 pub async fn drop_device_connection(
     device_key: DeviceKey,
+    app_handle: tauri::AppHandle,
     mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
     radio_connections: tauri::State<'_, state::radio_connections::RadioConnectionsState>,
 ) -> Result<(), CommandError> {
     debug!("Called drop_device_connection command");
 
-    {
-        let mut state_devices = mesh_devices.inner.lock().await;
-        let mut connections_guard = radio_connections.inner.lock().await;
-
-        // Disconnect from open connection
-        // TODO abstract this clearing into a helper function
-
-        if let Some(stream_api) = connections_guard.remove(&device_key) {
-            match stream_api.disconnect().await {
-                Ok(_) => (),
-                Err(e) => {
-                    debug!("Failed to disconnect from device: {:?}", e);
-                }
-            };
-        }
+    // A device that's being reconnected to isn't in the map until it's configured
+    let was_reconnecting = cancel_reconnect(&app_handle, &device_key);
 
-        // Clear corresponding state device
+    // Removed from the map first, so the device's task is stopped without holding up
+    // others. It's stopped before its connection is, so the closing connection isn't
+    // mistaken for a lost one.
+    let device = mesh_devices.inner.lock().await.remove(&device_key);
 
-        if let Some(packet_api) = state_devices.get_mut(&device_key) {
-            packet_api
-                .device
-                .set_status(SerialDeviceStatus::Disconnected);
-        }
+    if let Some(device) = &device {
+        device.disconnect().await;
+    }
 
-        state_devices.remove(&device_key);
+    // Disconnect from open connection
+    // TODO abstract this clearing into a helper function
+
+    let stream_api = radio_connections.inner.lock().await.remove(&device_key);
+
+    if let Some(stream_api) = stream_api {
+        match stream_api.disconnect().await {
+            Ok(_) => (),
+            Err(e) => {
+                debug!("Failed to disconnect from device: {:?}", e);
+            }
+        };
+    }
+
+    // Clear corresponding state device
+
+    if device.is_some() || was_reconnecting {
+        dispatch_devices_list_changed(
+            &app_handle,
+            device_key,
+            DevicesListChange::Removed,
+            SerialDeviceStatus::Disconnected,
+        )
+        .map_err(|e| e.to_string())?;
     }
 
     Ok(())
 }
 
+/// Asks a device whose configuration timed out for its configuration again, keeping
+/// its connection open since reopening the port reboots some boards. Only retried
+/// when asked, with a timeout of its own, and success is reported like on connect.
 #[tauri::command]
-pub async fn drop_all_device_connections(
+pub async fn retry_configuration(
+    device_key: DeviceKey,
+    configuration_timeout_ms: Option<u32>,
+    app_handle: tauri::AppHandle,
     mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
-    radio_connections: tauri::State<'_, state::radio_connections::RadioConnectionsState>,
 ) -> Result<(), CommandError> {
+    debug!("Called retry_configuration command");
+    trace!("Called with device key {}", device_key);
+
+    let timeout = configuration_timeout(configuration_timeout_ms)
+        .map_err(|e| CommandError::invalid_argument("configurationTimeoutMs", e))?;
+
+    let device = get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+
+    let config_id = device.retry_configuration().await?;
+
+    dispatch_devices_list_changed(
+        &app_handle,
+        device_key.clone(),
+        DevicesListChange::StatusChanged,
+        SerialDeviceStatus::Configuring,
+    )
+    .map_err(|e| e.to_string())?;
+
+    spawn_configuration_timeout_handler(app_handle, device, device_key, config_id, timeout);
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn drop_all_device_connections(app_handle: tauri::AppHandle) -> Result<(), CommandError> {
     debug!("Called drop_all_device_connections command");
 
-    {
-        let mut connections_guard = radio_connections.inner.lock().await;
+    disconnect_all_devices(&app_handle).await?;
+
+    Ok(())
+}
 
-        // Disconnect from all open connections and empty HashMap
+#[tauri::command]
+pub async fn get_connection_metrics(
+    device_key: DeviceKey,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<ConnectionMetrics, CommandError> {
+    debug!("Called get_connection_metrics command");
 
-        for (_, connection) in connections_guard.drain() {
-            connection.disconnect().await.map_err(|e| e.to_string())?;
-        }
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
 
-        // Set all state devices as disconnected and empty HashMap
+    let mut metrics = packet_api.metrics.lock().map_err(|e| e.to_string())?;
 
-        let mut state_devices = mesh_devices.inner.lock().await;
+    Ok(metrics.snapshot(packet_api.device.queue_depth(), get_current_time_u32()))
+}
 
-        for (_port_name, packet_api) in state_devices.iter_mut() {
-            packet_api
-                .device
-                .set_status(SerialDeviceStatus::Disconnected);
-        }
+#[tauri::command]
+pub async fn get_connected_devices(
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<Vec<ConnectedDeviceSummary>, CommandError> {
+    debug!("Called get_connected_devices command");
 
-        // This could be removed in the future to maintain state on previous devices
-        state_devices.clear();
-    }
+    // Published by each device's task, so no device is waited on
+    let summaries = device_summaries(&mesh_devices.inner).await;
+
+    Ok(summaries)
+}
+
+#[tauri::command]
+pub async fn get_recent_devices(
+    recent_devices: tauri::State<'_, state::recent_devices::RecentDevicesState>,
+) -> Result<Vec<RecentDevice>, CommandError> {
+    debug!("Called get_recent_devices command");
+
+    let devices = recent_devices.inner.lock().map_err(|e| e.to_string())?;
+
+    Ok(devices.devices.clone())
+}
+
+/// Sets whether the app connects to a recent device when it starts
+#[tauri::command]
+pub async fn set_recent_device_auto_connect(
+    port: String,
+    auto_connect: bool,
+    app_handle: tauri::AppHandle,
+    recent_devices: tauri::State<'_, state::recent_devices::RecentDevicesState>,
+) -> Result<(), CommandError> {
+    debug!("Called set_recent_device_auto_connect command");
+    trace!(
+        "Called with port \"{}\" auto-connect {}",
+        port,
+        auto_connect
+    );
+
+    let devices = {
+        let mut devices = recent_devices.inner.lock().map_err(|e| e.to_string())?;
+        devices.set_auto_connect(&port, auto_connect)?;
+        devices.clone()
+    };
+
+    save_json(&app_handle, RECENT_DEVICES_FILE_NAME, &devices)?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn remove_recent_device(
+    port: String,
+    app_handle: tauri::AppHandle,
+    recent_devices: tauri::State<'_, state::recent_devices::RecentDevicesState>,
+) -> Result<(), CommandError> {
+    debug!("Called remove_recent_device command");
+    trace!("Called with port \"{}\"", port);
+
+    let devices = {
+        let mut devices = recent_devices.inner.lock().map_err(|e| e.to_string())?;
+        devices.remove(&port).ok_or("Not a recent device")?;
+        devices.clone()
+    };
+
+    save_json(&app_handle, RECENT_DEVICES_FILE_NAME, &devices)?;
 
     Ok(())
 }
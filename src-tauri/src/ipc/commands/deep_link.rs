@@ -0,0 +1,127 @@
+use crate::deep_link::{channel_set_to_channels, parse_deep_link, DeepLink};
+use crate::ipc::commands::radio::spawn_config_operation;
+use crate::ipc::error_reporter::{AppErrorCode, ErrorReporter};
+use crate::ipc::events;
+use crate::ipc::helpers::{channel_writes, ConfigWrite};
+use crate::ipc::{CommandError, DeepLinkEvent, EVENT_API_VERSION};
+use crate::packet_api::operations::{OperationId, OperationKind};
+use crate::packet_api::radio_write::RadioWrite;
+use crate::state;
+use crate::state::mesh_devices::get_device_handle;
+use crate::state::DeviceKey;
+
+use log::{debug, trace};
+use meshtastic::protobufs;
+
+/// Parses a link and sends it to the UI, reporting malformed links as app errors
+fn handle_deep_link(app_handle: &tauri::AppHandle, url: String) -> Result<DeepLink, CommandError> {
+    let link = parse_deep_link(&url).map_err(|e| {
+        ErrorReporter::new(app_handle, module_path!())
+            .error(AppErrorCode::InvalidDeepLink, e.to_string());
+
+        e.to_string()
+    })?;
+
+    events::dispatch_deep_link(
+        app_handle,
+        DeepLinkEvent {
+            api_version: EVENT_API_VERSION,
+            url,
+            link: link.clone(),
+        },
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(link)
+}
+
+/// Returns the link the app was launched with, if any. Only returns it once.
+#[tauri::command]
+pub async fn request_pending_deep_link(
+    app_handle: tauri::AppHandle,
+    deep_link_state: tauri::State<'_, state::deep_link::DeepLinkState>,
+) -> Result<Option<DeepLink>, CommandError> {
+    debug!("Called request_pending_deep_link command");
+
+    let pending = deep_link_state.inner.lock().await.take();
+
+    match pending {
+        Some(url) => handle_deep_link(&app_handle, url).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Handles a link opened from within the app, e.g. pasted by the user
+#[tauri::command]
+pub async fn open_deep_link(
+    url: String,
+    app_handle: tauri::AppHandle,
+) -> Result<DeepLink, CommandError> {
+    debug!("Called open_deep_link command");
+    trace!("Called with url {}", url);
+
+    handle_deep_link(&app_handle, url)
+}
+
+/// Replaces the device's channels, and LoRa config if the link has one, with the
+/// channel set in a channel link. Each channel is written as its own item of the
+/// returned operation.
+#[tauri::command]
+pub async fn import_channel_set(
+    device_key: DeviceKey,
+    url: String,
+    confirmed: bool,
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<OperationId, CommandError> {
+    debug!("Called import_channel_set command");
+    trace!("Called with url {}", url);
+
+    // Importing overwrites every channel on the device, including its keys
+
+    if !confirmed {
+        return Err("Channel imports must be explicitly confirmed".into());
+    }
+
+    let channel_set = match parse_deep_link(&url).map_err(|e| e.to_string())? {
+        DeepLink::ChannelSet(channel_set) => channel_set,
+        _ => return Err("Link does not contain a channel set".into()),
+    };
+
+    let device = get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+
+    let lora_config = channel_set
+        .lora_config
+        .clone()
+        .map(|lora| protobufs::Config {
+            payload_variant: Some(protobufs::config::PayloadVariant::Lora(lora)),
+        });
+
+    if let Some(config) = lora_config.as_ref() {
+        device
+            .packet_api()
+            .lock()
+            .await
+            .device
+            .check_config_supported(config)?;
+    }
+
+    device.write(RadioWrite::StartConfigTransaction).await?;
+
+    let mut writes = channel_writes(channel_set_to_channels(&channel_set));
+
+    if let Some(config) = lora_config {
+        writes.push(("LoRa config".to_string(), ConfigWrite::Config(config)));
+    }
+
+    spawn_config_operation(
+        app_handle,
+        device_key,
+        OperationKind::ChannelImport,
+        writes,
+        mesh_devices.inner.clone(),
+    )
+    .map_err(CommandError::from)
+}
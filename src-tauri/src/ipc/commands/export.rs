@@ -0,0 +1,357 @@
+use std::path::Path;
+
+use log::{debug, trace, warn};
+
+use crate::device::helpers::get_current_time_u32;
+use crate::device::telemetry_store::{record_analytics_metrics, TelemetryBucket};
+
+use crate::export::analytics_report::{
+    build_analytics_report, resolve_report_sections, AnalyticsMetric, AnalyticsReport,
+    AnalyticsReportMetadata, AnalyticsSection, ReportDevice, ANALYTICS_REPORT_TIMEOUT,
+    ANALYTICS_REPORT_VERSION,
+};
+use crate::export::gpx::{build_gpx, GpxExportOptions, GpxExportSummary};
+use crate::export::kml::{build_kml, KmlExportOptions, KmlExportSummary};
+use crate::export::network_geojson::{
+    build_network_geojson, serialize_network_geojson, NetworkExportSummary,
+    NetworkGeoJsonExportOptions,
+};
+use crate::export::node_table::{
+    build_node_table, node_table_to_csv, resolve_node_table_columns, NodeTableRow,
+};
+use crate::export::state_bundle::{self, BundleManifest, BundleSections, ImportMode};
+use crate::export::topology::{build_topology, TopologyExportSummary, TopologyFormat};
+use crate::export::write_export_file;
+use crate::ipc::{
+    events::encoding::{ipc_encoding, Encoded},
+    CommandError,
+};
+use crate::state::mesh_devices::get_device;
+use crate::state::{self, DeviceKey};
+
+#[tauri::command]
+pub async fn export_network_geojson(
+    device_key: DeviceKey,
+    file_path: String,
+    options: NetworkGeoJsonExportOptions,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<NetworkExportSummary, CommandError> {
+    debug!("Called export_network_geojson command");
+    trace!("Exporting network GeoJSON to \"{}\"", file_path);
+
+    let collection = {
+        let device = get_device(&mesh_devices.inner, &device_key)
+            .await
+            .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+        let packet_api = device.lock().await;
+
+        let graph = mesh_graph.inner.view()?;
+
+        build_network_geojson(&graph, &packet_api.device, &options)
+    };
+
+    let contents = serialize_network_geojson(&collection, options.pretty)?;
+    let bytes = write_export_file(&file_path, &contents).await?;
+
+    Ok(NetworkExportSummary::new(&collection, bytes))
+}
+
+#[tauri::command]
+pub async fn export_gpx(
+    device_key: DeviceKey,
+    file_path: String,
+    options: GpxExportOptions,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<GpxExportSummary, CommandError> {
+    debug!("Called export_gpx command");
+    trace!("Exporting GPX to \"{}\"", file_path);
+
+    let gpx = {
+        let device = get_device(&mesh_devices.inner, &device_key)
+            .await
+            .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+        let packet_api = device.lock().await;
+
+        let graph = mesh_graph.inner.view()?;
+
+        build_gpx(&graph, &packet_api.device, &options)?
+    };
+
+    let bytes = write_export_file(&file_path, &gpx.contents).await?;
+
+    Ok(GpxExportSummary {
+        waypoints: gpx.waypoints,
+        route_points: gpx.route_points,
+        bytes,
+    })
+}
+
+#[tauri::command]
+pub async fn export_kml(
+    device_key: DeviceKey,
+    file_path: String,
+    options: KmlExportOptions,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<KmlExportSummary, CommandError> {
+    debug!("Called export_kml command");
+    trace!("Exporting KML to \"{}\"", file_path);
+
+    let kml = {
+        let device = get_device(&mesh_devices.inner, &device_key)
+            .await
+            .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+        let packet_api = device.lock().await;
+
+        let graph = mesh_graph.inner.view()?;
+
+        build_kml(&graph, &packet_api.device, &options, get_current_time_u32())?
+    };
+
+    let bytes = write_export_file(&file_path, &kml.contents).await?;
+
+    Ok(KmlExportSummary {
+        placemarks: kml.placemarks,
+        links: kml.links,
+        bytes,
+    })
+}
+
+/// Writes the mesh's nodes and edges for graph analysis tools such as Gephi or Graphviz
+#[tauri::command]
+pub async fn export_topology(
+    device_key: DeviceKey,
+    file_path: String,
+    format: TopologyFormat,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<TopologyExportSummary, CommandError> {
+    debug!("Called export_topology command");
+    trace!("Exporting {:?} topology to \"{}\"", format, file_path);
+
+    let (contents, nodes, edges) = {
+        let device = get_device(&mesh_devices.inner, &device_key)
+            .await
+            .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+        let packet_api = device.lock().await;
+
+        let graph = mesh_graph.inner.view()?;
+
+        (
+            build_topology(&graph, &packet_api.device, format),
+            graph.nodes_lookup.len() as u32,
+            graph.edges().count() as u32,
+        )
+    };
+
+    let bytes = write_export_file(&file_path, &contents).await?;
+
+    Ok(TopologyExportSummary {
+        nodes,
+        edges,
+        bytes,
+    })
+}
+
+#[tauri::command]
+pub async fn get_nodes_table(
+    device_key: DeviceKey,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<Vec<NodeTableRow>, CommandError> {
+    debug!("Called get_nodes_table command");
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    let graph = mesh_graph.inner.view()?;
+
+    Ok(build_node_table(&graph, &packet_api.device))
+}
+
+#[tauri::command]
+pub async fn export_nodes_csv(
+    device_key: DeviceKey,
+    file_path: String,
+    columns: Option<Vec<String>>,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<(), CommandError> {
+    debug!("Called export_nodes_csv command");
+    trace!("Exporting node table to \"{}\"", file_path);
+
+    let columns = resolve_node_table_columns(columns)?;
+
+    let csv = {
+        let device = get_device(&mesh_devices.inner, &device_key)
+            .await
+            .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+        let packet_api = device.lock().await;
+
+        let graph = mesh_graph.inner.view()?;
+
+        node_table_to_csv(&build_node_table(&graph, &packet_api.device), &columns)
+    };
+
+    write_export_file(&file_path, &csv).await?;
+
+    Ok(())
+}
+
+/// Computes the selected analytics sections, all of them if none are given, and
+/// writes them as a JSON report to `file_path`. The report is also returned, in the
+/// encoding set with `set_ipc_encoding`, so it can be shown right away, and its
+/// metrics selected in the settings are stored.
+#[tauri::command]
+pub async fn export_analytics_report(
+    device_key: DeviceKey,
+    file_path: String,
+    sections: Option<Vec<AnalyticsSection>>,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+    edge_deltas: tauri::State<'_, state::edge_deltas::EdgeDeltasState>,
+    app_handle: tauri::AppHandle,
+) -> Result<Encoded<AnalyticsReport>, CommandError> {
+    debug!("Called export_analytics_report command");
+    trace!("Exporting analytics report to \"{}\"", file_path);
+
+    let sections = resolve_report_sections(sections)?;
+    let encoding = ipc_encoding(&app_handle);
+
+    let graph_version = edge_deltas
+        .inner
+        .lock()
+        .map_err(|e| e.to_string())?
+        .get(&device_key)
+        .map(|tracker| tracker.sequence());
+
+    let (graph, metadata) = {
+        let device = get_device(&mesh_devices.inner, &device_key)
+            .await
+            .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+        let packet_api = device.lock().await;
+
+        let graph = mesh_graph.inner.read()?.without_hidden_nodes();
+
+        let metadata = AnalyticsReportMetadata {
+            report_version: ANALYTICS_REPORT_VERSION,
+            generated_at: get_current_time_u32(),
+            graph_version,
+            device: ReportDevice::new(device_key.clone(), &packet_api.device),
+            sections,
+        };
+
+        (graph, metadata)
+    };
+
+    // Centralities and communities take a while on large meshes
+    let report = tokio::time::timeout(
+        ANALYTICS_REPORT_TIMEOUT,
+        tauri::async_runtime::spawn_blocking(move || {
+            let report = build_analytics_report(&graph, metadata)?;
+
+            if let Err(e) = record_analytics_metrics(&app_handle, &report) {
+                warn!("Failed to store analytics metrics: {}", e);
+            }
+
+            Ok::<_, String>(report)
+        }),
+    )
+    .await
+    .map_err(|_| "Timed out computing the analytics report")?
+    .map_err(|e| e.to_string())??;
+
+    let contents = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+    write_export_file(&file_path, &contents).await?;
+
+    let encoded = Encoded::new(encoding, report).map_err(|e| e.to_string())?;
+
+    Ok(encoded)
+}
+
+/// The last scheduled analytics update, in the encoding set with `set_ipc_encoding`, or
+/// `None` if there hasn't been one since the app started
+#[tauri::command]
+pub async fn get_latest_analytics(
+    analytics: tauri::State<'_, state::analytics::AnalyticsState>,
+    app_handle: tauri::AppHandle,
+) -> Result<Encoded<Option<AnalyticsReport>>, CommandError> {
+    debug!("Called get_latest_analytics command");
+
+    let latest = analytics.latest.lock().map_err(|e| e.to_string())?.clone();
+    let encoded = Encoded::new(ipc_encoding(&app_handle), latest).map_err(|e| e.to_string())?;
+
+    Ok(encoded)
+}
+
+/// Returns the values an analytics metric had in the reports between `from` and `to`,
+/// downsampled into at most `max_points` buckets like a telemetry series
+#[tauri::command]
+pub async fn get_metric_series(
+    metric: AnalyticsMetric,
+    from: u32,
+    to: u32,
+    max_points: u32,
+    telemetry_store: tauri::State<'_, state::telemetry_store::TelemetryStoreState>,
+) -> Result<Vec<TelemetryBucket>, CommandError> {
+    debug!("Called get_metric_series command");
+    trace!(
+        "Called with metric {:?} from {} to {} max points {}",
+        metric,
+        from,
+        to,
+        max_points
+    );
+
+    let store_guard = telemetry_store.inner.lock().map_err(|e| e.to_string())?;
+    let store = store_guard
+        .as_ref()
+        .ok_or("Telemetry database is not available")?;
+
+    let series = store.analytics_series(metric, from, to, max_points)?;
+
+    Ok(series)
+}
+
+/// Writes the selected sections of the app's persisted state to a single bundle file
+#[tauri::command]
+pub async fn export_state_bundle(
+    path: String,
+    include: BundleSections,
+    app_handle: tauri::AppHandle,
+) -> Result<BundleManifest, CommandError> {
+    debug!("Called export_state_bundle command");
+    trace!("Exporting state bundle to \"{}\"", path);
+
+    let manifest = tauri::async_runtime::spawn_blocking(move || {
+        state_bundle::export_state_bundle(&app_handle, Path::new(&path), include)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    Ok(manifest)
+}
+
+/// Imports a bundle written by `export_state_bundle`, merging it into or replacing
+/// the current state. Nothing is changed if any section of the bundle is invalid.
+#[tauri::command]
+pub async fn import_state_bundle(
+    path: String,
+    mode: ImportMode,
+    app_handle: tauri::AppHandle,
+) -> Result<BundleManifest, CommandError> {
+    debug!("Called import_state_bundle command");
+    trace!("Importing state bundle from \"{}\"", path);
+
+    let manifest = tauri::async_runtime::spawn_blocking(move || {
+        state_bundle::import_state_bundle(&app_handle, Path::new(&path), mode)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    Ok(manifest)
+}
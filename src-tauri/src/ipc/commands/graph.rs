@@ -1,27 +1,483 @@
+use std::collections::HashMap;
+use std::path::Path;
 use std::time::Duration;
 
-use log::{debug, error, info};
+use log::{debug, error, info, trace};
+use serde_json::json;
 
 use crate::{
-    graph::ds::graph::MeshGraph,
-    ipc::{events::dispatch_updated_graph, CommandError},
-    state,
+    device::helpers::get_current_time_u32,
+    elevation::profile::{build_route_elevation_profile, RouteElevationProfile},
+    export::graph_snapshot::{self, GraphSnapshotMetadata, SnapshotLoadMode, SnapshotLoadSummary},
+    graph::{
+        analytics::{cut_points, max_flow, weighted_betweenness, CutPoints, MaxFlow},
+        autosave::{self, AutosaveInfo, AutosaveStatus},
+        clustering::{find_communities, ClusterSource, Communities},
+        corridor::build_route_corridor,
+        coverage::generate_cluster_coverage_geojson,
+        ds::{
+            edge_weight::EdgeWeightStrategy,
+            graph::MeshGraph,
+            link_quality::{LinkQualityReport, LinkSnrMode},
+            overrides::{ManualEdge, MAX_NODE_LABEL_CHARS},
+        },
+        geojson::{generate_graph_edges_geojson, GraphGeoJson},
+        heatmap::{generate_signal_heatmap_geojson, HeatmapOptions},
+        nearby::{find_nodes_within_radius, NodesWithinRadius},
+        route::{build_route_geojson, find_route_costs, RouteGeoJson},
+    },
+    ipc::{
+        error_reporter::{AppErrorCode, ErrorReporter},
+        events::{
+            dispatch_full_edge_snapshot, dispatch_updated_graph,
+            encoding::IpcEncoding,
+            scopes::{sanitize_event_scope, EventScopes},
+        },
+        reset, CommandError,
+    },
+    packet_api::graph_edit::GraphEdit,
+    settings,
+    state::{
+        self,
+        mesh_devices::{all_devices, get_device, get_device_handle},
+        DeviceKey,
+    },
 };
 
 pub const DEFAULT_GRAPH_CLEAN_SECONDS: u64 = 60;
 
 #[tauri::command]
 pub async fn get_graph_state(
+    channel: Option<u32>,
     mesh_graph: tauri::State<'_, state::graph::GraphState>,
 ) -> Result<MeshGraph, CommandError> {
     debug!("Called get_graph_state command");
 
-    let mesh_graph_handle = mesh_graph.inner.lock().map_err(|e| e.to_string())?;
-    let mesh_graph = mesh_graph_handle.clone();
+    let mesh_graph_handle = mesh_graph.inner.read()?;
+    let mesh_graph = match channel {
+        Some(channel) => mesh_graph_handle.filter_by_channel(channel),
+        None => mesh_graph_handle.clone(),
+    };
 
     Ok(mesh_graph)
 }
 
+#[tauri::command]
+pub async fn get_graph_geojson(
+    device_key: DeviceKey,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<GraphGeoJson, CommandError> {
+    debug!("Called get_graph_geojson command");
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    let graph = mesh_graph.inner.view()?;
+
+    Ok(GraphGeoJson::new(device_key, &graph, &packet_api.device))
+}
+
+/// Returns the device's map layers with only the edges its own packets reported,
+/// while the shared graph and its events hold the edges every device reported
+#[tauri::command]
+pub async fn get_graph_for_device(
+    device_key: DeviceKey,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<GraphGeoJson, CommandError> {
+    debug!("Called get_graph_for_device command");
+    trace!("Called with device key {}", device_key);
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    let graph = mesh_graph.inner.view()?.filter_by_observer(&device_key);
+
+    Ok(GraphGeoJson::new(device_key, &graph, &packet_api.device))
+}
+
+/// Resends the device's map layers as a full snapshot, e.g. after the UI
+/// detected a gap in the sequence numbers of edge deltas
+#[tauri::command]
+pub async fn request_full_edge_snapshot(
+    device_key: DeviceKey,
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<(), CommandError> {
+    debug!("Called request_full_edge_snapshot command");
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    let graph = mesh_graph.inner.view()?;
+    let geojson = GraphGeoJson::new(device_key, &graph, &packet_api.device);
+
+    dispatch_full_edge_snapshot(&app_handle, geojson).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_signal_heatmap_geojson(
+    device_key: DeviceKey,
+    options: HeatmapOptions,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<geojson::FeatureCollection, CommandError> {
+    debug!("Called get_signal_heatmap_geojson command");
+
+    options.validate()?;
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    let graph = mesh_graph.inner.view()?;
+
+    Ok(generate_signal_heatmap_geojson(
+        &graph,
+        &packet_api.device,
+        &options,
+    ))
+}
+
+/// The graph without its hidden nodes, which can't be routed through. Its edges are
+/// reweighted with `weight_strategy` if one is given, for analyses that weigh links
+/// differently from the graph.
+fn routable_graph(
+    graph: &MeshGraph,
+    weight_strategy: Option<EdgeWeightStrategy>,
+) -> Result<MeshGraph, CommandError> {
+    let mut routable = graph.without_hidden_nodes();
+
+    if let Some(strategy) = weight_strategy {
+        strategy
+            .validate()
+            .map_err(|e| CommandError::invalid_argument("weightStrategy", e))?;
+        routable.set_edge_weight_strategy(strategy);
+    }
+
+    Ok(routable)
+}
+
+#[tauri::command]
+pub async fn get_route_geojson(
+    device_key: DeviceKey,
+    from_node: u32,
+    to_node: u32,
+    weight_strategy: Option<EdgeWeightStrategy>,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<RouteGeoJson, CommandError> {
+    debug!("Called get_route_geojson command");
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    let graph = routable_graph(&mesh_graph.inner.read()?, weight_strategy)?;
+
+    let route = build_route_geojson(&graph, &packet_api.device, from_node, to_node)?;
+
+    Ok(route)
+}
+
+/// Cost of the cheapest route from a node to every node reachable from it, by node num
+#[tauri::command]
+pub async fn get_route_costs(
+    device_key: DeviceKey,
+    from_node: u32,
+    weight_strategy: Option<EdgeWeightStrategy>,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<HashMap<u32, f64>, CommandError> {
+    debug!("Called get_route_costs command");
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    let graph = routable_graph(&mesh_graph.inner.read()?, weight_strategy)?;
+
+    let costs = find_route_costs(&graph, &packet_api.device, from_node)?;
+
+    Ok(costs)
+}
+
+/// Betweenness centrality of every node along the cheapest routes, by node num, from 0
+/// to 1. Nodes with a high score relay much of the mesh's traffic.
+#[tauri::command]
+pub async fn get_betweenness_centrality(
+    weight_strategy: Option<EdgeWeightStrategy>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<HashMap<u32, f64>, CommandError> {
+    debug!("Called get_betweenness_centrality command");
+
+    let graph = routable_graph(&mesh_graph.inner.read()?, weight_strategy)?;
+
+    // Finds the cheapest paths from every node, which takes a while on large meshes
+    let betweenness = tauri::async_runtime::spawn_blocking(move || {
+        weighted_betweenness(&graph, |edge| edge.weight())
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(betweenness)
+}
+
+/// LineStrings along the cheapest links that keep each part of the mesh connected, the
+/// backbone the rest of the links back up
+#[tauri::command]
+pub async fn get_backbone_geojson(
+    device_key: DeviceKey,
+    weight_strategy: Option<EdgeWeightStrategy>,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<geojson::FeatureCollection, CommandError> {
+    debug!("Called get_backbone_geojson command");
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    // Hidden nodes don't relay anything
+    let backbone = routable_graph(&mesh_graph.inner.read()?, weight_strategy)?
+        .minimum_spanning_forest(|edge| edge.weight());
+
+    Ok(generate_graph_edges_geojson(&backbone, &packet_api.device))
+}
+
+/// Number of paths between two nodes that share no links, and the links whose loss
+/// would separate the nodes
+#[tauri::command]
+pub async fn get_link_disjoint_paths(
+    from_node: u32,
+    to_node: u32,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<MaxFlow, CommandError> {
+    debug!("Called get_link_disjoint_paths command");
+    trace!("Called with nodes {} and {}", from_node, to_node);
+
+    // Hidden nodes can't be routed through
+    let graph = mesh_graph.inner.read()?.without_hidden_nodes();
+
+    for node_num in [from_node, to_node] {
+        if !graph.contains_node(node_num) {
+            return Err(format!("Unknown node !{:08x}", node_num).into());
+        }
+    }
+
+    // Each link carries one unit of flow, so the flow counts the paths
+    Ok(max_flow(&graph, from_node, to_node, |_| 1.0))
+}
+
+/// Communities of densely linked nodes, whose members the map can color alike
+#[tauri::command]
+pub async fn get_communities(
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<Communities, CommandError> {
+    debug!("Called get_communities command");
+
+    let graph = mesh_graph.inner.read()?.without_hidden_nodes();
+
+    Ok(find_communities(&graph))
+}
+
+/// Nodes and links whose loss would split the mesh, so traffic relies on them as relays
+#[tauri::command]
+pub async fn get_critical_nodes(
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<CutPoints, CommandError> {
+    debug!("Called get_critical_nodes command");
+
+    // Hidden nodes don't relay anything
+    let graph = mesh_graph.inner.read()?.without_hidden_nodes();
+
+    Ok(cut_points(&graph))
+}
+
+/// Polygon covering everything within `buffer_meters` of the route with the fewest hops
+/// between two nodes
+#[tauri::command]
+pub async fn generate_route_corridor(
+    device_key: DeviceKey,
+    from_node: u32,
+    to_node: u32,
+    buffer_meters: f64,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<RouteGeoJson, CommandError> {
+    debug!("Called generate_route_corridor command");
+    trace!(
+        "Called with route {} to {}, buffer {} m",
+        from_node,
+        to_node,
+        buffer_meters
+    );
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    // Hidden nodes can't be routed through
+    let graph = mesh_graph.inner.read()?.without_hidden_nodes();
+
+    let corridor = build_route_corridor(
+        &graph,
+        &packet_api.device,
+        from_node,
+        to_node,
+        buffer_meters,
+    )?;
+
+    Ok(corridor)
+}
+
+/// Terrain along the route with the fewest hops between two nodes, sampled at
+/// `samples` points per leg, with the line of sight clearance of each leg
+#[tauri::command]
+pub async fn get_route_elevation_profile(
+    device_key: DeviceKey,
+    from_node: u32,
+    to_node: u32,
+    samples: u32,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+    elevation: tauri::State<'_, state::elevation::ElevationState>,
+) -> Result<RouteElevationProfile, CommandError> {
+    debug!("Called get_route_elevation_profile command");
+    trace!(
+        "Called with route {} to {}, {} samples per leg",
+        from_node,
+        to_node,
+        samples
+    );
+
+    let provider = elevation
+        .inner
+        .lock()
+        .map_err(|e| e.to_string())?
+        .clone()
+        .ok_or("No elevation data is available")?;
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    // Hidden nodes can't be routed through
+    let graph = mesh_graph.inner.read()?.without_hidden_nodes();
+
+    let profile = build_route_elevation_profile(
+        &graph,
+        &packet_api.device,
+        provider.as_ref(),
+        from_node,
+        to_node,
+        samples,
+    )?;
+
+    Ok(profile)
+}
+
+/// Buffered coverage area of each cluster of nodes, see `ClusterSource`
+#[tauri::command]
+pub async fn generate_cluster_coverage_geojson(
+    device_key: DeviceKey,
+    clustering: ClusterSource,
+    buffer_km: f64,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<geojson::FeatureCollection, CommandError> {
+    debug!("Called generate_cluster_coverage_geojson command");
+    trace!(
+        "Called with clustering {:?}, buffer {} km",
+        clustering,
+        buffer_km
+    );
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    let graph = mesh_graph.inner.read()?.without_hidden_nodes();
+
+    let coverage =
+        generate_cluster_coverage_geojson(&graph, &packet_api.device, &clustering, buffer_km)?;
+
+    Ok(coverage)
+}
+
+/// Nodes within `radius_meters` of a coordinate, nearest first
+#[tauri::command]
+pub async fn get_nodes_within_radius(
+    device_key: DeviceKey,
+    latitude: f64,
+    longitude: f64,
+    radius_meters: f64,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<NodesWithinRadius, CommandError> {
+    debug!("Called get_nodes_within_radius command");
+    trace!(
+        "Called with {}, {} and radius {} m",
+        latitude,
+        longitude,
+        radius_meters
+    );
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    let graph = mesh_graph.inner.read()?;
+
+    let nodes = find_nodes_within_radius(
+        &graph,
+        &packet_api.device,
+        latitude,
+        longitude,
+        radius_meters,
+    )?;
+
+    Ok(nodes)
+}
+
+/// Nodes within `max_hops` hops of a node, by node num, along with their distance in hops
+#[tauri::command]
+pub async fn get_neighborhood(
+    node_num: u32,
+    max_hops: u32,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<HashMap<u32, u32>, CommandError> {
+    debug!("Called get_neighborhood command");
+    trace!("Called with node {} and {} hops", node_num, max_hops);
+
+    // Hidden nodes can't be routed through
+    let graph = mesh_graph.inner.read()?.without_hidden_nodes();
+
+    if !graph.contains_node(node_num) {
+        return Err(format!("Unknown node !{:08x}", node_num).into());
+    }
+
+    Ok(graph.neighbors_within(node_num, max_hops))
+}
+
 #[tauri::command]
 pub async fn initialize_timeout_handler(
     app_handle: tauri::AppHandle,
@@ -31,7 +487,7 @@ pub async fn initialize_timeout_handler(
 
     let mesh_graph_arc = mesh_graph_state.inner.clone();
 
-    let mut mesh_graph_handle = mesh_graph_state.inner.lock().map_err(|e| e.to_string())?;
+    let mut mesh_graph_handle = mesh_graph_state.inner.write()?;
 
     if mesh_graph_handle.timeout_handle.is_some() {
         info!("Graph timeout handler already initialized");
@@ -51,19 +507,29 @@ pub async fn initialize_timeout_handler(
 
             debug!("Cleaning graph...");
 
-            {
-                let mut mesh_graph_handle = match mesh_graph_arc.lock() {
-                    Ok(handle) => handle,
-                    Err(e) => {
-                        log::error!("Error getting graph handle: {}", e);
-                        break;
-                    }
-                };
+            let graph = match mesh_graph_arc.write() {
+                Ok(mut mesh_graph_handle) => match mesh_graph_handle.clean() {
+                    0 => None,
+                    _ => Some(mesh_graph_handle.clone()),
+                },
+                Err(e) => {
+                    ErrorReporter::new(&app_handle, module_path!()).error(
+                        AppErrorCode::StateLockFailed,
+                        format!("Error getting graph handle: {}", e),
+                    );
+                    break;
+                }
+            };
 
-                mesh_graph_handle.clean();
-
-                dispatch_updated_graph(&app_handle, mesh_graph_handle.clone())
-                    .expect("Error dispatching updated graph event");
+            // The graph is sent again with its next change, so a failed dispatch
+            // doesn't stop cleaning
+            if let Some(graph) = graph {
+                if let Err(e) = dispatch_updated_graph(&app_handle, None, graph) {
+                    ErrorReporter::new(&app_handle, module_path!()).error(
+                        AppErrorCode::EventDispatchFailed,
+                        format!("Failed to dispatch cleaned graph: {}", e),
+                    );
+                }
             }
 
             debug!(
@@ -86,7 +552,7 @@ pub async fn stop_timeout_handler(
 ) -> Result<(), CommandError> {
     debug!("Called stop_timeout_handler command");
 
-    let mut mesh_graph_handle = mesh_graph.inner.lock().map_err(|e| e.to_string())?;
+    let mut mesh_graph_handle = mesh_graph.inner.write()?;
 
     if let Some(handle) = mesh_graph_handle.timeout_handle.take() {
         log::info!("Stopping graph timeout handler");
@@ -96,3 +562,440 @@ pub async fn stop_timeout_handler(
 
     Ok(())
 }
+
+/// Returns the link quality samples between two nodes that came from the device's packets
+#[tauri::command]
+pub async fn get_link_quality_history(
+    device_key: DeviceKey,
+    node_a: u32,
+    node_b: u32,
+    window_secs: Option<u32>,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<LinkQualityReport, CommandError> {
+    debug!("Called get_link_quality_history command");
+    trace!("Called with device key {}", device_key);
+
+    get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+
+    let since = window_secs
+        .map(|window| get_current_time_u32().saturating_sub(window))
+        .unwrap_or(0);
+
+    let graph = mesh_graph.inner.read()?;
+
+    Ok(graph.get_link_quality_report(node_a, node_b, since, Some(&device_key)))
+}
+
+#[tauri::command]
+pub async fn set_link_snr_mode(
+    mode: LinkSnrMode,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<(), CommandError> {
+    debug!("Called set_link_snr_mode command with mode {:?}", mode);
+
+    let mut graph = mesh_graph.inner.write()?;
+    graph.link_snr_mode = mode;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_edge_weight_strategy(
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<EdgeWeightStrategy, CommandError> {
+    debug!("Called get_edge_weight_strategy command");
+
+    let graph = mesh_graph.inner.read()?;
+
+    Ok(graph.edge_weight_strategy().clone())
+}
+
+/// Changes how edges are weighted, reweighting the edges already in the graph rather
+/// than rebuilding it, and publishes the device's edges with their new costs. The
+/// strategy is saved with the settings, so it's kept across restarts.
+#[tauri::command]
+pub async fn set_edge_weight_strategy(
+    device_key: DeviceKey,
+    strategy: EdgeWeightStrategy,
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<(), CommandError> {
+    debug!(
+        "Called set_edge_weight_strategy command with strategy {:?}",
+        strategy
+    );
+
+    strategy
+        .validate()
+        .map_err(|e| CommandError::invalid_argument("strategy", e))?;
+
+    get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?
+        .edit_graph(GraphEdit::SetEdgeWeightStrategy(strategy.clone()))
+        .await?;
+
+    // The graph already has the strategy, so applying the settings leaves it be
+    settings::edit_settings(&app_handle, |settings| {
+        settings.graph.edge_weight_strategy = strategy;
+    })?;
+
+    Ok(())
+}
+
+/// Sets how long edges are kept without being heard again, or `None` to use the
+/// timeout of each edge
+#[tauri::command]
+pub async fn set_edge_max_age(
+    max_age_secs: Option<u32>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<(), CommandError> {
+    debug!(
+        "Called set_edge_max_age command with {:?} seconds",
+        max_age_secs
+    );
+
+    if max_age_secs == Some(0) {
+        return Err("Edge max age must be at least a second".into());
+    }
+
+    let mut graph = mesh_graph.inner.write()?;
+    graph.edge_max_age = max_age_secs.map(|secs| Duration::from_secs(secs.into()));
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn add_manual_edge(
+    device_key: DeviceKey,
+    from: u32,
+    to: u32,
+    weight: f64,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<(), CommandError> {
+    debug!("Called add_manual_edge command");
+    trace!("Called with from {}, to {}, weight {}", from, to, weight);
+
+    if from == to {
+        return Err("Manual edge must connect two different nodes".into());
+    }
+
+    if !weight.is_finite() {
+        return Err("Manual edge weight must be a finite number".into());
+    }
+
+    get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?
+        .edit_graph(GraphEdit::AddManualEdge(ManualEdge { from, to, weight }))
+        .await
+}
+
+#[tauri::command]
+pub async fn remove_manual_edge(
+    device_key: DeviceKey,
+    from: u32,
+    to: u32,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<(), CommandError> {
+    debug!("Called remove_manual_edge command");
+    trace!("Called with from {}, to {}", from, to);
+
+    get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?
+        .edit_graph(GraphEdit::RemoveManualEdge { from, to })
+        .await
+}
+
+#[tauri::command]
+pub async fn hide_node(
+    device_key: DeviceKey,
+    node_num: u32,
+    hidden: bool,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<(), CommandError> {
+    debug!("Called hide_node command");
+    trace!("Called with node {}, hidden {}", node_num, hidden);
+
+    get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?
+        .edit_graph(GraphEdit::SetNodeHidden { node_num, hidden })
+        .await
+}
+
+/// Sets the operator's label for a node, shown instead of its long name.
+/// Passing `None` or a blank label clears it.
+#[tauri::command]
+pub async fn set_node_label(
+    device_key: DeviceKey,
+    node_num: u32,
+    label: Option<String>,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<(), CommandError> {
+    debug!("Called set_node_label command");
+    trace!("Called with node {}, label {:?}", node_num, label);
+
+    if let Some(label) = label.as_ref() {
+        if label.trim().chars().count() > MAX_NODE_LABEL_CHARS {
+            return Err(format!(
+                "Node label cannot be longer than {} characters",
+                MAX_NODE_LABEL_CHARS
+            )
+            .into());
+        }
+    }
+
+    get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?
+        .edit_graph(GraphEdit::SetNodeLabel { node_num, label })
+        .await
+}
+
+/// Empties the graph, keeping our own node and the operator's overrides unless
+/// `purge_all` is set. The connection and device config are left as they are.
+#[tauri::command]
+pub async fn clear_network_graph(
+    device_key: DeviceKey,
+    purge_all: bool,
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<(), CommandError> {
+    debug!("Called clear_network_graph command");
+    trace!("Called with purge_all {}", purge_all);
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    reset::clear_network_graph(
+        &app_handle,
+        &device_key,
+        &mesh_graph.inner,
+        &packet_api.device,
+        purge_all,
+    )?;
+
+    Ok(())
+}
+
+/// Forgets link quality history and range test results of every connected device
+#[tauri::command]
+pub async fn reset_analytics_state(
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<(), CommandError> {
+    debug!("Called reset_analytics_state command");
+
+    let mut devices = vec![];
+
+    for (device_key, device) in all_devices(&mesh_devices.inner).await {
+        devices.push((device_key, device.lock_owned().await));
+    }
+
+    reset::reset_analytics_state(
+        &app_handle,
+        &mesh_graph.inner,
+        devices
+            .iter_mut()
+            .map(|(device_key, packet_api)| (&*device_key, &mut packet_api.device)),
+    )?;
+
+    Ok(())
+}
+
+/// Deletes the nodes and edges stored for every device, and the position archive.
+/// Graphs currently shown are kept, and stored again as they change.
+#[tauri::command]
+pub async fn clear_graph_database(
+    graph_store: tauri::State<'_, state::graph_store::GraphStoreState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+) -> Result<(), CommandError> {
+    debug!("Called clear_graph_database command");
+
+    let mut store_guard = graph_store.inner.lock().map_err(|e| e.to_string())?;
+    let store = store_guard
+        .as_mut()
+        .ok_or("Graph database is not available")?;
+
+    store.clear()?;
+
+    mesh_graph.inner.write()?.position_archive.clear();
+
+    Ok(())
+}
+
+/// Forgets a node's archived position, so it's only placed on the map once the
+/// radio reports a position for it again
+#[tauri::command]
+pub async fn purge_archived_position(
+    device_key: DeviceKey,
+    node_num: u32,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    graph_store: tauri::State<'_, state::graph_store::GraphStoreState>,
+) -> Result<(), CommandError> {
+    debug!("Called purge_archived_position command");
+    trace!("Called with node {}", node_num);
+
+    if let Some(store) = graph_store
+        .inner
+        .lock()
+        .map_err(|e| e.to_string())?
+        .as_mut()
+    {
+        store.delete_position(node_num)?;
+    }
+
+    get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?
+        .edit_graph(GraphEdit::PurgeArchivedPosition(node_num))
+        .await
+}
+
+#[tauri::command]
+pub async fn get_autosave_status(
+    app_handle: tauri::AppHandle,
+) -> Result<AutosaveStatus, CommandError> {
+    debug!("Called get_autosave_status command");
+
+    let status = autosave::autosave_status(&app_handle)?;
+
+    Ok(status)
+}
+
+/// Restores the graphs saved in the newest autosave, e.g. after the graph database
+/// was lost in a crash
+#[tauri::command]
+pub async fn recover_from_autosave(
+    app_handle: tauri::AppHandle,
+) -> Result<AutosaveInfo, CommandError> {
+    debug!("Called recover_from_autosave command");
+
+    let recovered = autosave::recover_from_autosave(&app_handle).await?;
+
+    Ok(recovered)
+}
+
+/// Writes the device's graph to a standalone snapshot file
+#[tauri::command]
+pub async fn save_graph_snapshot(
+    device_key: DeviceKey,
+    path: String,
+    app_handle: tauri::AppHandle,
+) -> Result<GraphSnapshotMetadata, CommandError> {
+    debug!("Called save_graph_snapshot command");
+    trace!("Saving graph snapshot of {} to \"{}\"", device_key, path);
+
+    let metadata = graph_snapshot::save_graph_snapshot(&app_handle, &device_key, &path).await?;
+
+    Ok(metadata)
+}
+
+/// Opens a snapshot file as the read-only `snapshot` device, or merges it into the
+/// graph of a connected device. Nothing is loaded if the file is invalid.
+#[tauri::command]
+pub async fn load_graph_snapshot(
+    path: String,
+    mode: SnapshotLoadMode,
+    app_handle: tauri::AppHandle,
+) -> Result<SnapshotLoadSummary, CommandError> {
+    debug!("Called load_graph_snapshot command");
+    trace!(
+        "Loading graph snapshot from \"{}\" with mode {:?}",
+        path,
+        mode
+    );
+
+    let summary = graph_snapshot::load_graph_snapshot(&app_handle, Path::new(&path), mode).await?;
+
+    Ok(summary)
+}
+
+#[tauri::command]
+pub async fn get_event_coalescing_interval(
+    event_coalescing: tauri::State<'_, state::event_coalescing::EventCoalescingState>,
+) -> Result<u32, CommandError> {
+    debug!("Called get_event_coalescing_interval command");
+
+    let coalescer = event_coalescing.inner.lock().map_err(|e| e.to_string())?;
+
+    Ok(coalescer.interval().as_millis() as u32)
+}
+
+/// Sets the minimum time between device and graph update events. Zero emits every update.
+/// The interval is saved with the rest of the settings.
+#[tauri::command]
+pub async fn set_event_coalescing_interval(
+    interval_ms: u32,
+    app_handle: tauri::AppHandle,
+) -> Result<(), CommandError> {
+    debug!("Called set_event_coalescing_interval command");
+    trace!("Called with interval {}ms", interval_ms);
+
+    let update = settings::update_settings(
+        &app_handle,
+        &json!({ "events": { "coalescingIntervalMs": interval_ms } }),
+    )?;
+
+    if let Some(error) = update.errors.into_iter().next() {
+        return Err(error.message.into());
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_event_scopes(
+    event_scopes: tauri::State<'_, state::event_scopes::EventScopesState>,
+) -> Result<EventScopes, CommandError> {
+    debug!("Called get_event_scopes command");
+
+    let scopes = event_scopes.inner.lock().map_err(|e| e.to_string())?;
+
+    Ok(scopes.clone())
+}
+
+/// Sets the event channels the UI listens to, returning the channel suffix for each
+/// device, e.g. `COM3` for `device_update:COM3`
+#[tauri::command]
+pub async fn set_event_scopes(
+    scopes: EventScopes,
+    event_scopes: tauri::State<'_, state::event_scopes::EventScopesState>,
+) -> Result<HashMap<DeviceKey, String>, CommandError> {
+    debug!("Called set_event_scopes command");
+    trace!("Called with scopes {:?}", scopes);
+
+    let suffixes = scopes
+        .devices
+        .iter()
+        .map(|device_key| (device_key.clone(), sanitize_event_scope(device_key)))
+        .collect();
+
+    *event_scopes.inner.lock().map_err(|e| e.to_string())? = scopes;
+
+    Ok(suffixes)
+}
+
+/// Sets how the largest event payloads and command results, e.g. edge snapshots and
+/// analytics reports, are encoded. The UI calls this once it can decode `encoding`.
+#[tauri::command]
+pub async fn set_ipc_encoding(
+    encoding: IpcEncoding,
+    ipc_encoding: tauri::State<'_, state::ipc_encoding::IpcEncodingState>,
+) -> Result<(), CommandError> {
+    debug!("Called set_ipc_encoding command");
+    trace!("Called with encoding {:?}", encoding);
+
+    *ipc_encoding.inner.lock().map_err(|e| e.to_string())? = encoding;
+
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use crate::device::logs::{DeviceLogConfig, DeviceLogEntry, DeviceLogLevel};
+use crate::ipc::error_reporter::AppError;
+use crate::ipc::CommandError;
+use crate::packet_api::debug_stream::{
+    PacketDebugFilters, PacketDebugStream, DEFAULT_PACKET_DEBUG_RATE_LIMIT,
+};
+use crate::state;
+use crate::state::mesh_devices::get_device;
+use crate::state::DeviceKey;
+
+use log::{debug, trace};
+
+#[tauri::command]
+pub async fn get_device_logs(
+    device_key: DeviceKey,
+    since: Option<u32>,
+    level: Option<DeviceLogLevel>,
+    device_logs: tauri::State<'_, state::device_logs::DeviceLogsState>,
+) -> Result<Vec<DeviceLogEntry>, CommandError> {
+    debug!("Called get_device_logs command");
+    trace!("Called with since {:?} level {:?}", since, level);
+
+    let logs = device_logs.inner.lock().map_err(|e| e.to_string())?;
+
+    let entries = logs
+        .buffers
+        .get(&device_key)
+        .map(|buffer| {
+            buffer.query(
+                since.unwrap_or_default(),
+                level.unwrap_or(DeviceLogLevel::Trace),
+            )
+        })
+        .unwrap_or_default();
+
+    Ok(entries)
+}
+
+#[tauri::command]
+pub async fn get_device_log_config(
+    device_logs: tauri::State<'_, state::device_logs::DeviceLogsState>,
+) -> Result<DeviceLogConfig, CommandError> {
+    debug!("Called get_device_log_config command");
+
+    let logs = device_logs.inner.lock().map_err(|e| e.to_string())?;
+
+    Ok(logs.config.clone())
+}
+
+#[tauri::command]
+pub async fn set_device_log_config(
+    config: DeviceLogConfig,
+    device_logs: tauri::State<'_, state::device_logs::DeviceLogsState>,
+) -> Result<(), CommandError> {
+    debug!("Called set_device_log_config command");
+    trace!("Called with config {:?}", config);
+
+    if config.max_lines_per_sec == 0 {
+        return Err("Log rate limit must allow at least one line per second".into());
+    }
+
+    let mut logs = device_logs.inner.lock().map_err(|e| e.to_string())?;
+    logs.config = config;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_recent_errors(
+    app_errors: tauri::State<'_, state::app_errors::AppErrorsState>,
+) -> Result<Vec<AppError>, CommandError> {
+    debug!("Called get_recent_errors command");
+
+    let errors = app_errors.inner.lock().map_err(|e| e.to_string())?;
+
+    Ok(errors.recent())
+}
+
+/// Starts or stops streaming decoded packets from the device as `debug_packet` events.
+/// Enabling an already enabled stream replaces its filters.
+#[tauri::command]
+pub async fn set_packet_debug_stream(
+    device_key: DeviceKey,
+    enabled: bool,
+    filters: Option<PacketDebugFilters>,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<(), CommandError> {
+    debug!("Called set_packet_debug_stream command");
+    trace!("Called with enabled {} filters {:?}", enabled, filters);
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let mut packet_api = device.lock().await;
+
+    packet_api.debug_stream = if enabled {
+        Some(PacketDebugStream::new(
+            filters.unwrap_or_default(),
+            DEFAULT_PACKET_DEBUG_RATE_LIMIT,
+        ))
+    } else {
+        None
+    };
+
+    Ok(())
+}
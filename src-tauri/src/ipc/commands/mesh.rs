@@ -1,50 +1,200 @@
-use crate::device::NormalizedWaypoint;
+use crate::device::helpers::{generate_rand_id, get_current_time_u32};
+use crate::device::liveness::NodeLivenessConfig;
+use crate::device::message_store::{
+    conversation_summaries, mark_conversation_read, release_outgoing_message, ConversationSummary,
+    MessageConversation, NodeNote, OutgoingMessage, SearchResult, StoredMessage,
+};
+use crate::device::node_details::NodeDetails;
+use crate::device::telemetry_store::{TelemetryBucket, TelemetryMetric};
+use crate::device::{
+    ChannelMessageHistory, NodeMetricsHistory, NormalizedWaypoint, DEFAULT_METRICS_STALE_AFTER_SECS,
+};
+use crate::graph::geojson::generate_waypoints_geojson;
 use crate::ipc::events;
-use crate::ipc::CommandError;
+use crate::ipc::helpers::{resend_outgoing_message, send_text_message, spawn_traceroute_timeout};
+use crate::ipc::reset;
+use crate::ipc::{CommandError, UnreadCountsChangedEvent, EVENT_API_VERSION};
+use crate::packet_api::operations::{spawn_operation, OperationId, OperationKind};
+use crate::packet_api::radio_write::RadioWrite;
+use crate::packet_api::traceroute::TRACEROUTE_TIMEOUT;
+use crate::state::mesh_devices::{get_device, get_device_handle};
 use crate::state::{self, DeviceKey};
 
+use geojson::FeatureCollection;
 use log::{debug, trace};
 use meshtastic::packet::PacketDestination;
-use meshtastic::types::MeshChannel;
+use meshtastic::types::{MeshChannel, NodeId};
 
+/// Sends a text message, broadcast unless a `destination` node num is given. Messages
+/// sent with `want_ack`, the default, emit `message_state` events as they're acked or
+/// fail.
 #[tauri::command]
 pub async fn send_text(
     device_key: DeviceKey,
     text: String,
     channel: u32,
+    destination: Option<u32>,
+    want_ack: Option<bool>,
     app_handle: tauri::AppHandle,
     mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
-    radio_connections: tauri::State<'_, state::radio_connections::RadioConnectionsState>,
 ) -> Result<(), CommandError> {
     debug!("Called send_text command",);
     trace!("Called with text {} on channel {}", text, channel);
 
-    let mut devices_guard = mesh_devices.inner.lock().await;
-    let packet_api = devices_guard
-        .get_mut(&device_key)
-        .ok_or("Device not connected")?;
-
-    let mut connections_guard = radio_connections.inner.lock().await;
-    let connection = connections_guard
-        .get_mut(&device_key)
-        .ok_or("Radio connection not initialized")?;
-
-    connection
-        .send_text(
-            packet_api,
-            text.clone(),
-            PacketDestination::Broadcast,
-            true,
-            MeshChannel::new(channel).map_err(|e| e.to_string())?,
+    let destination = match destination {
+        Some(node_num) => PacketDestination::Node(NodeId::new(node_num)),
+        None => PacketDestination::Broadcast,
+    };
+
+    send_text_message(
+        &app_handle,
+        &mesh_devices.inner,
+        &device_key,
+        text,
+        destination,
+        channel,
+        want_ack.unwrap_or(true),
+    )
+    .await
+}
+
+/// Returns the device's messages left unsent by a crash or disconnect, oldest first
+#[tauri::command]
+pub async fn get_unsent_messages(
+    device_key: DeviceKey,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    message_store: tauri::State<'_, state::message_store::MessageStoreState>,
+) -> Result<Vec<OutgoingMessage>, CommandError> {
+    debug!("Called get_unsent_messages command");
+
+    let device_id = connected_device_id(&mesh_devices, &device_key).await?;
+
+    let store_guard = message_store.inner.lock().map_err(|e| e.to_string())?;
+    let store = store_guard
+        .as_ref()
+        .ok_or("Message database is not available")?;
+
+    let messages = message_store
+        .outgoing
+        .lock()
+        .map_err(|e| e.to_string())?
+        .unsent(store, device_id)?;
+
+    Ok(messages)
+}
+
+/// Resends the unsent messages among `ids` with new packet ids, returning how many
+/// were sent. Messages already resent or acked are skipped, so each is resent once.
+#[tauri::command]
+pub async fn resend_unsent_messages(
+    device_key: DeviceKey,
+    ids: Vec<u32>,
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    message_store: tauri::State<'_, state::message_store::MessageStoreState>,
+) -> Result<u32, CommandError> {
+    debug!("Called resend_unsent_messages command");
+    trace!("Called with ids {:?}", ids);
+
+    let device_id = connected_device_id(&mesh_devices, &device_key).await?;
+
+    let claimed = {
+        let store_guard = message_store.inner.lock().map_err(|e| e.to_string())?;
+        let store = store_guard
+            .as_ref()
+            .ok_or("Message database is not available")?;
+
+        message_store
+            .outgoing
+            .lock()
+            .map_err(|e| e.to_string())?
+            .claim(store, device_id, &ids)?
+    };
+
+    for (index, message) in claimed.iter().enumerate() {
+        let result = resend_outgoing_message(
+            &app_handle,
+            &mesh_devices.inner,
+            &device_key,
+            message.clone(),
         )
+        .await;
+
+        // Messages not reached yet are left unsent for a later attempt
+        if let Err(e) = result {
+            for message in &claimed[index + 1..] {
+                release_outgoing_message(&app_handle, message.id);
+            }
+
+            return Err(e);
+        }
+    }
+
+    Ok(claimed.len() as u32)
+}
+
+/// Drops the unsent messages among `ids` from the journal, returning how many were
+/// dropped
+#[tauri::command]
+pub async fn discard_unsent_messages(
+    device_key: DeviceKey,
+    ids: Vec<u32>,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    message_store: tauri::State<'_, state::message_store::MessageStoreState>,
+) -> Result<u32, CommandError> {
+    debug!("Called discard_unsent_messages command");
+    trace!("Called with ids {:?}", ids);
+
+    let device_id = connected_device_id(&mesh_devices, &device_key).await?;
+
+    let mut store_guard = message_store.inner.lock().map_err(|e| e.to_string())?;
+    let store = store_guard
+        .as_mut()
+        .ok_or("Message database is not available")?;
+
+    let unsent: Vec<u32> = message_store
+        .outgoing
+        .lock()
+        .map_err(|e| e.to_string())?
+        .unsent(store, device_id)?
+        .into_iter()
+        .map(|message| message.id)
+        .filter(|id| ids.contains(id))
+        .collect();
+
+    let discarded = store.discard_outgoing(device_id, &unsent)?;
+
+    Ok(discarded as u32)
+}
+
+async fn connected_device_id(
+    mesh_devices: &state::mesh_devices::MeshDevicesState,
+    device_key: &DeviceKey,
+) -> Result<u32, CommandError> {
+    let device = get_device(&mesh_devices.inner, device_key)
         .await
-        .map_err(|e| e.to_string())?;
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
 
-    events::dispatch_updated_device(&app_handle, &packet_api.device).map_err(|e| e.to_string())?;
+    Ok(packet_api.device.my_node_info.my_node_num)
+}
 
-    Ok(())
+/// Checks a waypoint about to be sent, giving it an id if it doesn't have one
+fn prepare_waypoint(waypoint: NormalizedWaypoint) -> Result<NormalizedWaypoint, String> {
+    waypoint.validate(get_current_time_u32())?;
+
+    if waypoint.id != 0 {
+        return Ok(waypoint);
+    }
+
+    Ok(NormalizedWaypoint {
+        id: generate_rand_id(),
+        ..waypoint
+    })
 }
 
+/// Broadcasts a waypoint, returning its id. Waypoints without an id are given one,
+/// and sending a waypoint with an existing id replaces it.
 #[tauri::command]
 pub async fn send_waypoint(
     device_key: DeviceKey,
@@ -52,35 +202,127 @@ pub async fn send_waypoint(
     channel: u32,
     app_handle: tauri::AppHandle,
     mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
-    radio_connections: tauri::State<'_, state::radio_connections::RadioConnectionsState>,
-) -> Result<(), CommandError> {
+) -> Result<u32, CommandError> {
     debug!("Called send_waypoint command");
     trace!("Called on channel {} with waypoint {:?}", channel, waypoint);
 
-    let mut devices_guard = mesh_devices.inner.lock().await;
-    let packet_api = devices_guard
-        .get_mut(&device_key)
-        .ok_or("Device not connected")?;
-
-    let mut connections_guard = radio_connections.inner.lock().await;
-    let connection = connections_guard
-        .get_mut(&device_key)
-        .ok_or("Radio connection not initialized")?;
-
-    connection
-        .send_waypoint(
-            packet_api,
-            waypoint.into(),
-            PacketDestination::Broadcast,
-            true,
-            MeshChannel::new(channel).map_err(|e| e.to_string())?,
-        )
+    let waypoint = prepare_waypoint(waypoint)?;
+    let waypoint_id = waypoint.id;
+
+    let device = get_device_handle(&mesh_devices.inner, &device_key)
         .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+
+    device
+        .write(RadioWrite::Waypoint {
+            waypoint: waypoint.into(),
+            channel,
+        })
+        .await?;
+
+    let packet_api = device.packet_api();
+    let packet_api = packet_api.lock().await;
+
+    events::dispatch_updated_device(&app_handle, &packet_api.device_key, &packet_api.device)
         .map_err(|e| e.to_string())?;
 
-    events::dispatch_updated_device(&app_handle, &packet_api.device).map_err(|e| e.to_string())?;
+    Ok(waypoint_id)
+}
 
-    Ok(())
+/// Broadcasts each waypoint in turn, returning right away with the id of the operation.
+/// Every waypoint waits for room in the radio's TX queue before it's sent.
+#[tauri::command]
+pub async fn send_waypoints(
+    device_key: DeviceKey,
+    waypoints: Vec<NormalizedWaypoint>,
+    channel: u32,
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<OperationId, CommandError> {
+    debug!("Called send_waypoints command");
+    trace!(
+        "Called on channel {} with {} waypoints",
+        channel,
+        waypoints.len()
+    );
+
+    MeshChannel::new(channel).map_err(|e| e.to_string())?;
+
+    let items = waypoints
+        .into_iter()
+        .map(|waypoint| {
+            let waypoint = prepare_waypoint(waypoint)?;
+            Ok((format!("Waypoint \"{}\"", waypoint.name), waypoint))
+        })
+        .collect::<Result<_, String>>()?;
+
+    let mesh_devices = mesh_devices.inner.clone();
+
+    let send_item = {
+        let mesh_devices = mesh_devices.clone();
+        let device_key = device_key.clone();
+
+        move |waypoint: NormalizedWaypoint| {
+            let mesh_devices = mesh_devices.clone();
+            let device_key = device_key.clone();
+
+            async move {
+                get_device_handle(&mesh_devices, &device_key)
+                    .await
+                    .ok_or("Device not connected")?
+                    .write(RadioWrite::Waypoint {
+                        waypoint: waypoint.into(),
+                        channel,
+                    })
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+        }
+    };
+
+    let publish = {
+        let app_handle = app_handle.clone();
+        let device_key = device_key.clone();
+
+        move || async move {
+            let device = get_device(&mesh_devices, &device_key)
+                .await
+                .ok_or("Device not connected")?;
+            let packet_api = device.lock().await;
+
+            events::dispatch_updated_device(&app_handle, &packet_api.device_key, &packet_api.device)
+                .map_err(|e| e.to_string())
+        }
+    };
+
+    spawn_operation(
+        app_handle,
+        device_key,
+        OperationKind::Waypoints,
+        items,
+        send_item,
+        Some(("Update device".to_string(), publish)),
+    )
+    .map_err(CommandError::from)
+}
+
+/// Points for the device's waypoints that haven't expired
+#[tauri::command]
+pub async fn get_waypoints_geojson(
+    device_key: DeviceKey,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<FeatureCollection, CommandError> {
+    debug!("Called get_waypoints_geojson command");
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    Ok(generate_waypoints_geojson(
+        &packet_api.device,
+        get_current_time_u32(),
+    ))
 }
 
 #[tauri::command]
@@ -92,16 +334,409 @@ pub async fn delete_waypoint(
 ) -> Result<(), CommandError> {
     debug!("Called delete_waypoint command");
 
-    let mut devices_guard = mesh_devices.inner.lock().await;
-    let packet_api = devices_guard
-        .get_mut(&device_key)
-        .ok_or("Device not connected")?;
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let mut packet_api = device.lock().await;
 
     if packet_api.device.waypoints.contains_key(&waypoint_id) {
         let _removed_waypoint = packet_api.device.waypoints.remove(&waypoint_id);
     }
 
-    events::dispatch_updated_device(&app_handle, &packet_api.device).map_err(|e| e.to_string())?;
+    events::dispatch_updated_device(&app_handle, &packet_api.device_key, &packet_api.device)
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Traces the route to `destination`, returning right away with the id of the request.
+/// Its result is emitted as a `traceroute_result` event, and the hops it found are
+/// added to the graph.
+#[tauri::command]
+pub async fn request_traceroute(
+    device_key: DeviceKey,
+    destination: u32,
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<u32, CommandError> {
+    debug!("Called request_traceroute command");
+    trace!("Called with destination {}", destination);
+
+    let device = get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+
+    let request_id = device.send_traceroute(destination).await?;
+
+    spawn_traceroute_timeout(
+        app_handle,
+        device,
+        device_key,
+        request_id,
+        TRACEROUTE_TIMEOUT,
+    );
+
+    Ok(request_id)
+}
+
+/// Clears the stored messages of one channel, or of every channel if none is given,
+/// returning how many messages were removed
+#[tauri::command]
+pub async fn clear_message_history(
+    device_key: DeviceKey,
+    channel: Option<u32>,
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<usize, CommandError> {
+    debug!("Called clear_message_history command");
+    trace!("Called with channel {:?}", channel);
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let mut packet_api = device.lock().await;
+
+    let removed =
+        reset::clear_message_history(&app_handle, &device_key, &mut packet_api.device, channel)?;
+
+    Ok(removed)
+}
+
+/// Assembles everything known about a node. The device and graph are locked one after
+/// the other rather than together.
+#[tauri::command]
+pub async fn get_node_details(
+    device_key: DeviceKey,
+    node_num: u32,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+    message_store: tauri::State<'_, state::message_store::MessageStoreState>,
+) -> Result<NodeDetails, CommandError> {
+    debug!("Called get_node_details command");
+    trace!("Called with node {}", node_num);
+
+    let (mut details, my_node_num, in_node_db) = {
+        let device = get_device(&mesh_devices.inner, &device_key)
+            .await
+            .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+        let packet_api = device.lock().await;
+
+        let device = &packet_api.device;
+
+        (
+            NodeDetails::from_device(device, node_num, get_current_time_u32()),
+            device.my_node_info.my_node_num,
+            device.nodes.contains_key(&node_num),
+        )
+    };
+
+    {
+        let graph = mesh_graph.inner.read()?;
+
+        if !in_node_db && !graph.contains_node(node_num) {
+            return Err(format!("Node {} not found", node_num).into());
+        }
+
+        details.add_graph_details(&graph, my_node_num);
+    }
+
+    if let Some(store) = message_store
+        .inner
+        .lock()
+        .map_err(|e| e.to_string())?
+        .as_ref()
+    {
+        details.note = store
+            .current_node_note(node_num)?
+            .and_then(|note| note.summary());
+    }
+
+    Ok(details)
+}
+
+#[tauri::command]
+pub async fn get_message_history(
+    device_key: DeviceKey,
+    channel: Option<u32>,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<Vec<ChannelMessageHistory>, CommandError> {
+    debug!("Called get_message_history command");
+    trace!("Called with channel filter {:?}", channel);
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    Ok(packet_api.device.get_message_history(channel))
+}
+
+/// Returns a page of the device's stored messages in a channel or direct conversation,
+/// newest first. The oldest timestamp of a page is passed as `before_timestamp` to get
+/// the next one. Messages received in the last couple of seconds may not be stored yet.
+#[tauri::command]
+pub async fn get_messages(
+    device_key: DeviceKey,
+    conversation: MessageConversation,
+    before_timestamp: Option<u32>,
+    limit: u32,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    message_store: tauri::State<'_, state::message_store::MessageStoreState>,
+) -> Result<Vec<StoredMessage>, CommandError> {
+    debug!("Called get_messages command");
+    trace!(
+        "Called with conversation {:?} before {:?} limit {}",
+        conversation,
+        before_timestamp,
+        limit
+    );
+
+    let device_id = {
+        let device = get_device(&mesh_devices.inner, &device_key)
+            .await
+            .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+        let packet_api = device.lock().await;
+
+        packet_api.device.my_node_info.my_node_num
+    };
+
+    let store_guard = message_store.inner.lock().map_err(|e| e.to_string())?;
+    let store = store_guard
+        .as_ref()
+        .ok_or("Message database is not available")?;
+
+    let messages = store.get_messages(device_id, conversation, before_timestamp, limit)?;
+
+    Ok(messages)
+}
+
+/// Returns each of the device's stored conversations with its number of unread messages,
+/// most recently active first
+#[tauri::command]
+pub async fn conversations_summary(
+    device_key: DeviceKey,
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<Vec<ConversationSummary>, CommandError> {
+    debug!("Called conversations_summary command");
+
+    let device_id = connected_device_id(&mesh_devices, &device_key).await?;
+
+    let conversations = tauri::async_runtime::spawn_blocking(move || {
+        conversation_summaries(&app_handle, device_id)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    Ok(conversations)
+}
+
+/// Marks the messages of a conversation up to and including `up_to_message_id`, a
+/// packet id, as read. The updated unread counts are returned and sent to the UI.
+#[tauri::command]
+pub async fn mark_read(
+    device_key: DeviceKey,
+    conversation: MessageConversation,
+    up_to_message_id: u32,
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<Vec<ConversationSummary>, CommandError> {
+    debug!("Called mark_read command");
+    trace!(
+        "Called with conversation {:?} up to message {}",
+        conversation,
+        up_to_message_id
+    );
+
+    let device_id = connected_device_id(&mesh_devices, &device_key).await?;
+
+    let conversations = {
+        let app_handle = app_handle.clone();
+
+        tauri::async_runtime::spawn_blocking(move || {
+            mark_conversation_read(&app_handle, device_id, conversation, up_to_message_id)
+        })
+        .await
+        .map_err(|e| e.to_string())??
+    };
+
+    events::dispatch_unread_counts_changed(
+        &app_handle,
+        UnreadCountsChangedEvent {
+            api_version: EVENT_API_VERSION,
+            device_key,
+            conversations: conversations.clone(),
+        },
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(conversations)
+}
+
+/// Searches the stored messages of every device for messages containing each word
+/// of `query`, newest first
+#[tauri::command]
+pub async fn search_messages(
+    query: String,
+    limit: u32,
+    message_store: tauri::State<'_, state::message_store::MessageStoreState>,
+) -> Result<Vec<StoredMessage>, CommandError> {
+    debug!("Called search_messages command");
+    trace!("Called with query {:?} limit {}", query, limit);
+
+    let store_guard = message_store.inner.lock().map_err(|e| e.to_string())?;
+    let store = store_guard
+        .as_ref()
+        .ok_or("Message database is not available")?;
+
+    let messages = store.search_messages(&query, limit)?;
+
+    Ok(messages)
+}
+
+/// Searches stored messages and the current note of every node together
+#[tauri::command]
+pub async fn search_all(
+    query: String,
+    limit: u32,
+    message_store: tauri::State<'_, state::message_store::MessageStoreState>,
+) -> Result<Vec<SearchResult>, CommandError> {
+    debug!("Called search_all command");
+    trace!("Called with query {:?} limit {}", query, limit);
+
+    let store_guard = message_store.inner.lock().map_err(|e| e.to_string())?;
+    let store = store_guard
+        .as_ref()
+        .ok_or("Message database is not available")?;
+
+    let results = store.search_all(&query, limit)?;
+
+    Ok(results)
+}
+
+/// Saves a new revision of the operator's Markdown note about a node. An empty note
+/// clears it, keeping the earlier revisions.
+#[tauri::command]
+pub async fn set_node_note(
+    node_id: u32,
+    markdown_text: String,
+    message_store: tauri::State<'_, state::message_store::MessageStoreState>,
+) -> Result<NodeNote, CommandError> {
+    debug!("Called set_node_note command");
+    trace!("Called with node {}", node_id);
+
+    let mut store_guard = message_store.inner.lock().map_err(|e| e.to_string())?;
+    let store = store_guard
+        .as_mut()
+        .ok_or("Message database is not available")?;
+
+    let note = store.set_node_note(node_id, &markdown_text, get_current_time_u32())?;
+
+    Ok(note)
+}
+
+/// Returns the kept revisions of a node's note, newest first
+#[tauri::command]
+pub async fn get_node_notes(
+    node_id: u32,
+    message_store: tauri::State<'_, state::message_store::MessageStoreState>,
+) -> Result<Vec<NodeNote>, CommandError> {
+    debug!("Called get_node_notes command");
+    trace!("Called with node {}", node_id);
+
+    let store_guard = message_store.inner.lock().map_err(|e| e.to_string())?;
+    let store = store_guard
+        .as_ref()
+        .ok_or("Message database is not available")?;
+
+    let notes = store.get_node_notes(node_id)?;
+
+    Ok(notes)
+}
+
+/// Returns a node's samples of a metric between `from` and `to`, downsampled into at
+/// most `max_points` buckets with the min, average and max of each
+#[tauri::command]
+pub async fn get_telemetry_series(
+    node_id: u32,
+    metric: TelemetryMetric,
+    from: u32,
+    to: u32,
+    max_points: u32,
+    telemetry_store: tauri::State<'_, state::telemetry_store::TelemetryStoreState>,
+) -> Result<Vec<TelemetryBucket>, CommandError> {
+    debug!("Called get_telemetry_series command");
+    trace!(
+        "Called with node {} metric {:?} from {} to {} max points {}",
+        node_id,
+        metric,
+        from,
+        to,
+        max_points
+    );
+
+    let store_guard = telemetry_store.inner.lock().map_err(|e| e.to_string())?;
+    let store = store_guard
+        .as_ref()
+        .ok_or("Telemetry database is not available")?;
+
+    let series = store.series(node_id, metric, from, to, max_points)?;
+
+    Ok(series)
+}
+
+/// Returns a node's recent device metrics, oldest first, marked stale if the latest is
+/// older than `stale_after_secs`
+#[tauri::command]
+pub async fn get_node_metrics(
+    device_key: DeviceKey,
+    node_num: u32,
+    stale_after_secs: Option<u32>,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<NodeMetricsHistory, CommandError> {
+    debug!("Called get_node_metrics command");
+    trace!("Called with node {}", node_num);
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    let node = packet_api
+        .device
+        .nodes
+        .get(&node_num)
+        .ok_or_else(|| format!("Node {} not found", node_num))?;
+
+    Ok(node.device_metrics_history(
+        get_current_time_u32(),
+        stale_after_secs.unwrap_or(DEFAULT_METRICS_STALE_AFTER_SECS),
+    ))
+}
+
+#[tauri::command]
+pub async fn get_node_liveness_config(
+    node_liveness: tauri::State<'_, state::node_liveness::NodeLivenessState>,
+) -> Result<NodeLivenessConfig, CommandError> {
+    debug!("Called get_node_liveness_config command");
+
+    let config = node_liveness.inner.lock().map_err(|e| e.to_string())?;
+
+    Ok(config.clone())
+}
+
+#[tauri::command]
+pub async fn set_node_liveness_config(
+    config: NodeLivenessConfig,
+    node_liveness: tauri::State<'_, state::node_liveness::NodeLivenessState>,
+) -> Result<(), CommandError> {
+    debug!("Called set_node_liveness_config command");
+    trace!("Called with config {:?}", config);
+
+    config.validate()?;
+
+    let mut config_guard = node_liveness.inner.lock().map_err(|e| e.to_string())?;
+    *config_guard = config;
 
     Ok(())
 }
@@ -1,4 +1,17 @@
+pub mod backup;
 pub mod connections;
+pub mod deep_link;
+pub mod export;
 pub mod graph;
+pub mod logs;
 pub mod mesh;
+pub mod modules;
+pub mod notifications;
+pub mod profiles;
 pub mod radio;
+pub mod replay;
+pub mod scripting;
+pub mod secrets;
+pub mod settings;
+pub mod simulation;
+pub mod storage;
@@ -0,0 +1,185 @@
+use crate::device::helpers::get_node_user_name;
+use crate::device::range_test::RangeTestResults;
+use crate::device::remote_hardware::{build_hardware_message, GpioReading};
+use crate::ipc::helpers::{await_gpio_read, read_canned_messages};
+use crate::ipc::CommandError;
+use crate::packet_api::radio_write::RadioWrite;
+use crate::state::mesh_devices::{get_device, get_device_handle};
+use crate::state::{self, DeviceKey};
+
+use log::{debug, trace};
+use meshtastic::protobufs::hardware_message;
+use std::time::Duration;
+
+/// Remote hardware replies travel over the mesh, so allow for multi-hop round trips
+const GPIO_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[tauri::command]
+pub async fn get_canned_messages(
+    device_key: DeviceKey,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<Vec<String>, CommandError> {
+    debug!("Called get_canned_messages command");
+
+    read_canned_messages(&mesh_devices.inner, &device_key).await
+}
+
+#[tauri::command]
+pub async fn set_canned_messages(
+    device_key: DeviceKey,
+    messages: Vec<String>,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<(), CommandError> {
+    debug!("Called set_canned_messages command");
+    trace!("Called with messages {:?}", messages);
+
+    get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?
+        .write(RadioWrite::CannedMessages(messages))
+        .await
+}
+
+#[tauri::command]
+pub async fn get_range_test_results(
+    device_key: DeviceKey,
+    node_id: u32,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<RangeTestResults, CommandError> {
+    debug!("Called get_range_test_results command");
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    let samples = packet_api
+        .device
+        .range_tests
+        .get(&node_id)
+        .cloned()
+        .unwrap_or_default();
+
+    Ok(RangeTestResults::new(node_id, samples))
+}
+
+#[tauri::command]
+pub async fn export_range_test_results(
+    device_key: DeviceKey,
+    node_id: u32,
+    file_path: String,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<(), CommandError> {
+    debug!("Called export_range_test_results command");
+    trace!("Exporting range test results to \"{}\"", file_path);
+
+    let csv = {
+        let device = get_device(&mesh_devices.inner, &device_key)
+            .await
+            .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+        let mut packet_api = device.lock().await;
+
+        let samples = packet_api
+            .device
+            .range_tests
+            .get(&node_id)
+            .cloned()
+            .ok_or("No range test results for node")?;
+
+        let sender_name = get_node_user_name(&mut packet_api.device, &node_id)
+            .unwrap_or_else(|| node_id.to_string());
+
+        RangeTestResults::new(node_id, samples).to_csv(&sender_name)
+    };
+
+    tokio::fs::write(&file_path, csv)
+        .await
+        .map_err(|e| format!("Failed to write range test results: {}", e))?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn gpio_write(
+    device_key: DeviceKey,
+    target_node: u32,
+    gpio_mask: u64,
+    gpio_value: u64,
+    confirmed: bool,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<(), CommandError> {
+    debug!("Called gpio_write command");
+    trace!(
+        "Called with node {}, mask {:#x}, value {:#x}",
+        target_node,
+        gpio_mask,
+        gpio_value
+    );
+
+    // Writing GPIO pins can power-cycle hardware attached to the remote node
+
+    if !confirmed {
+        return Err("GPIO writes must be explicitly confirmed".into());
+    }
+
+    get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?
+        .write(RadioWrite::Hardware {
+            message: build_hardware_message(
+                hardware_message::Type::WriteGpios,
+                gpio_mask,
+                gpio_value,
+            ),
+            node_num: target_node,
+        })
+        .await
+}
+
+#[tauri::command]
+pub async fn gpio_read(
+    device_key: DeviceKey,
+    target_node: u32,
+    gpio_mask: u64,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<GpioReading, CommandError> {
+    debug!("Called gpio_read command");
+    trace!("Called with node {}, mask {:#x}", target_node, gpio_mask);
+
+    let device = get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+
+    let request = device.read_gpios(target_node, gpio_mask).await?;
+
+    await_gpio_read(&device, request, GPIO_READ_TIMEOUT)
+        .await
+        .ok_or_else(|| {
+            CommandError::from(format!(
+                "Node {} did not reply to the GPIO read. Make sure the remote hardware module is enabled on the node and that the requested pins are allowed.",
+                target_node
+            ))
+        })
+}
+
+#[tauri::command]
+pub async fn gpio_watch(
+    device_key: DeviceKey,
+    target_node: u32,
+    gpio_mask: u64,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<(), CommandError> {
+    debug!("Called gpio_watch command");
+    trace!("Called with node {}, mask {:#x}", target_node, gpio_mask);
+
+    // Changes are streamed back through the `gpio_changed` event
+
+    get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?
+        .write(RadioWrite::Hardware {
+            message: build_hardware_message(hardware_message::Type::WatchGpios, gpio_mask, 0),
+            node_num: target_node,
+        })
+        .await
+}
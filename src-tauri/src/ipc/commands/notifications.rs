@@ -0,0 +1,188 @@
+use crate::ipc::CommandError;
+use crate::notifications::geofences::GeofenceConfig;
+use crate::notifications::preferences::NotificationPreferences;
+use crate::notifications::rules::NotificationThresholds;
+use crate::notifications::webhooks::WebhookConfig;
+use crate::persistence::{
+    save_json, GEOFENCES_FILE_NAME, NOTIFICATION_PREFERENCES_FILE_NAME, WEBHOOKS_FILE_NAME,
+};
+use crate::settings;
+use crate::state;
+
+use log::{debug, trace};
+use serde_json::json;
+
+#[tauri::command]
+pub async fn get_notification_thresholds(
+    notification_rules: tauri::State<'_, state::notification_rules::NotificationRulesState>,
+) -> Result<NotificationThresholds, CommandError> {
+    debug!("Called get_notification_thresholds command");
+
+    let rules = notification_rules.inner.lock().map_err(|e| e.to_string())?;
+
+    Ok(rules.thresholds.clone())
+}
+
+/// Sets every notification threshold, which are saved with the rest of the settings
+#[tauri::command]
+pub async fn set_notification_thresholds(
+    thresholds: NotificationThresholds,
+    app_handle: tauri::AppHandle,
+) -> Result<(), CommandError> {
+    debug!("Called set_notification_thresholds command");
+    trace!("Called with thresholds {:?}", thresholds);
+
+    let patch = json!({ "notifications": { "thresholds": thresholds } });
+    let update = settings::update_settings(&app_handle, &patch)?;
+
+    if let Some(error) = update.errors.into_iter().next() {
+        return Err(error.message.into());
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn set_node_notifications_muted(
+    node_num: u32,
+    muted: bool,
+    app_handle: tauri::AppHandle,
+    notification_preferences: tauri::State<
+        '_,
+        state::notification_preferences::NotificationPreferencesState,
+    >,
+) -> Result<(), CommandError> {
+    debug!("Called set_node_notifications_muted command");
+    trace!("Called with node {} muted {}", node_num, muted);
+
+    let preferences = {
+        let mut preferences = notification_preferences
+            .inner
+            .lock()
+            .map_err(|e| e.to_string())?;
+
+        preferences.set_node_muted(node_num, muted);
+        preferences.clone()
+    };
+
+    save_json(
+        &app_handle,
+        NOTIFICATION_PREFERENCES_FILE_NAME,
+        &preferences,
+    )?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_notification_preferences(
+    notification_preferences: tauri::State<
+        '_,
+        state::notification_preferences::NotificationPreferencesState,
+    >,
+) -> Result<NotificationPreferences, CommandError> {
+    debug!("Called get_notification_preferences command");
+
+    let preferences = notification_preferences
+        .inner
+        .lock()
+        .map_err(|e| e.to_string())?;
+
+    Ok(preferences.clone())
+}
+
+/// Replaces the notification preferences and saves them. The notification worker
+/// reads them for each notification, so they apply without reconnecting.
+#[tauri::command]
+pub async fn set_notification_preferences(
+    preferences: NotificationPreferences,
+    app_handle: tauri::AppHandle,
+    notification_preferences: tauri::State<
+        '_,
+        state::notification_preferences::NotificationPreferencesState,
+    >,
+) -> Result<(), CommandError> {
+    debug!("Called set_notification_preferences command");
+    trace!("Called with preferences {:?}", preferences);
+
+    preferences.validate()?;
+
+    *notification_preferences
+        .inner
+        .lock()
+        .map_err(|e| e.to_string())? = preferences.clone();
+
+    save_json(
+        &app_handle,
+        NOTIFICATION_PREFERENCES_FILE_NAME,
+        &preferences,
+    )?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_webhook_config(
+    webhooks: tauri::State<'_, state::webhooks::WebhooksState>,
+) -> Result<WebhookConfig, CommandError> {
+    debug!("Called get_webhook_config command");
+
+    let config = webhooks.inner.lock().map_err(|e| e.to_string())?;
+
+    Ok(config.clone())
+}
+
+/// Replaces the webhook endpoints and saves them. Re-enables endpoints disabled
+/// by the circuit breaker if they're saved as enabled.
+#[tauri::command]
+pub async fn set_webhook_config(
+    config: WebhookConfig,
+    app_handle: tauri::AppHandle,
+    webhooks: tauri::State<'_, state::webhooks::WebhooksState>,
+) -> Result<(), CommandError> {
+    debug!("Called set_webhook_config command");
+    trace!("Called with {} endpoints", config.endpoints.len());
+
+    config.validate()?;
+
+    *webhooks.inner.lock().map_err(|e| e.to_string())? = config.clone();
+
+    save_json(&app_handle, WEBHOOKS_FILE_NAME, &config)?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_geofences(
+    geofences: tauri::State<'_, state::geofences::GeofencesState>,
+) -> Result<GeofenceConfig, CommandError> {
+    debug!("Called get_geofences command");
+
+    let geofences = geofences.inner.lock().map_err(|e| e.to_string())?;
+
+    Ok(geofences.config.clone())
+}
+
+/// Replaces the geofences and saves them. Nodes are checked against new or reshaped
+/// fences from their next position onwards.
+#[tauri::command]
+pub async fn set_geofences(
+    config: GeofenceConfig,
+    app_handle: tauri::AppHandle,
+    geofences: tauri::State<'_, state::geofences::GeofencesState>,
+) -> Result<(), CommandError> {
+    debug!("Called set_geofences command");
+    trace!("Called with {} geofences", config.fences.len());
+
+    config.validate()?;
+
+    geofences
+        .inner
+        .lock()
+        .map_err(|e| e.to_string())?
+        .set_config(config.clone());
+
+    save_json(&app_handle, GEOFENCES_FILE_NAME, &config)?;
+
+    Ok(())
+}
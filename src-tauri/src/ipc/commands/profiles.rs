@@ -0,0 +1,62 @@
+use log::debug;
+
+use crate::device::helpers::get_current_time_u32;
+use crate::ipc::CommandError;
+use crate::profiles::{self, Profiles};
+use crate::state;
+
+#[tauri::command]
+pub async fn get_profiles(
+    profiles_state: tauri::State<'_, state::profiles::ProfilesState>,
+) -> Result<Profiles, CommandError> {
+    debug!("Called get_profiles command");
+
+    let profiles = profiles_state
+        .inner
+        .lock()
+        .map_err(|e| e.to_string())?
+        .clone();
+
+    Ok(profiles)
+}
+
+#[tauri::command]
+pub async fn create_profile(
+    name: String,
+    app_handle: tauri::AppHandle,
+) -> Result<Profiles, CommandError> {
+    debug!("Called create_profile command");
+
+    let profiles = profiles::create_profile(&app_handle, &name, get_current_time_u32())?;
+
+    Ok(profiles)
+}
+
+/// Disconnects every device and loads the settings and history of another profile
+#[tauri::command]
+pub async fn switch_profile(
+    name: String,
+    app_handle: tauri::AppHandle,
+) -> Result<Profiles, CommandError> {
+    debug!("Called switch_profile command");
+
+    let profiles = profiles::switch_profile(&app_handle, &name).await?;
+
+    Ok(profiles)
+}
+
+/// Deletes a profile that isn't active, along with all of its data
+#[tauri::command]
+pub async fn delete_profile(
+    name: String,
+    app_handle: tauri::AppHandle,
+) -> Result<Profiles, CommandError> {
+    debug!("Called delete_profile command");
+
+    let profiles =
+        tauri::async_runtime::spawn_blocking(move || profiles::delete_profile(&app_handle, &name))
+            .await
+            .map_err(|e| e.to_string())??;
+
+    Ok(profiles)
+}
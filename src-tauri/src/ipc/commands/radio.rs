@@ -1,7 +1,24 @@
-use crate::ipc::events;
+use crate::device::channel_config::EditableChannel;
+use crate::device::clock::TimeSyncConfig;
+use crate::device::config_cache::{device_config_view, DeviceConfigView};
+use crate::device::fixed_position::{FixedPosition, StoredFixedPosition};
+use crate::device::helpers::get_current_time_u32;
+use crate::device::metadata::DeviceCapability;
+use crate::ipc::helpers::{
+    await_remote_admin, channel_writes, commit_config_writes, local_config_writes,
+    send_config_write, send_device_time, spawn_fixed_position_rebroadcast, wait_for_device_state,
+    ConfigWrite, DEFAULT_ADMIN_RESPONSE_TIMEOUT,
+};
 use crate::ipc::CommandError;
 use crate::ipc::DeviceBulkConfig;
+use crate::packet_api::actor::RemoteAdminRequest;
+use crate::packet_api::operations::{spawn_operation, OperationId, OperationKind};
+use crate::packet_api::radio_write::RadioWrite;
+use crate::packet_api::remote_admin::{RemoteAdminOutcome, RemoteConfigType, REMOTE_ADMIN_TIMEOUT};
+use crate::secrets::secret_key;
+use crate::settings;
 use crate::state;
+use crate::state::mesh_devices::{all_devices, get_device, get_device_handle};
 use crate::state::DeviceKey;
 
 use log::debug;
@@ -13,27 +30,15 @@ pub async fn update_device_config(
     device_key: DeviceKey,
     config: protobufs::Config,
     mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
-    radio_connections: tauri::State<'_, state::radio_connections::RadioConnectionsState>,
 ) -> Result<(), CommandError> {
     debug!("Called update_device_config command");
     trace!("Called with config {:?}", config);
 
-    let mut devices_guard = mesh_devices.inner.lock().await;
-    let packet_api = devices_guard
-        .get_mut(&device_key)
-        .ok_or("Device not connected")?;
-
-    let mut connections_guard = radio_connections.inner.lock().await;
-    let connection = connections_guard
-        .get_mut(&device_key)
-        .ok_or("Radio connection not initialized")?;
-
-    connection
-        .update_config(packet_api, config)
+    let device = get_device_handle(&mesh_devices.inner, &device_key)
         .await
-        .map_err(|e| e.to_string())?;
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
 
-    Ok(())
+    device.write(RadioWrite::Config(config)).await
 }
 
 #[tauri::command]
@@ -41,28 +46,15 @@ pub async fn update_device_user(
     device_key: DeviceKey,
     user: protobufs::User,
     mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
-    radio_connections: tauri::State<'_, state::radio_connections::RadioConnectionsState>,
 ) -> Result<(), CommandError> {
     debug!("Called update_device_user command");
     trace!("Called with user {:?}", user);
 
-    let mut devices_guard = mesh_devices.inner.lock().await;
-    let packet_api = devices_guard
-        .get_mut(&device_key)
-        .ok_or("Device not connected")?;
-
-    let mut connections_guard = radio_connections.inner.lock().await;
-    let connection = connections_guard
-        .get_mut(&device_key)
-        .ok_or("Radio connection not initialized")?;
-
-    connection
-        .update_user(packet_api, user)
+    let device = get_device_handle(&mesh_devices.inner, &device_key)
         .await
-        .map_err(|e| e.to_string())
-        .map_err(|e| e.to_string())?;
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
 
-    Ok(())
+    device.write(RadioWrite::User(user)).await
 }
 
 // UNUSED
@@ -74,15 +66,15 @@ pub async fn start_configuration_transaction(
 ) -> Result<(), CommandError> {
     debug!("Called start_configuration_transaction command");
 
-    let mut devices_guard = mesh_devices.inner.lock().await;
-    let packet_api = devices_guard
-        .get_mut(&device_key)
-        .ok_or("Device not connected")?;
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let mut packet_api = device.lock().await;
 
     let mut connections_guard = radio_connections.inner.lock().await;
     let connection = connections_guard
         .get_mut(&device_key)
-        .ok_or("Radio connection not initialized")?;
+        .ok_or_else(|| CommandError::radio_not_connected(&device_key))?;
 
     if packet_api.device.config_in_progress {
         return Err("Configuration transaction already started".into());
@@ -107,15 +99,15 @@ pub async fn commit_configuration_transaction(
 ) -> Result<(), CommandError> {
     debug!("Called commit_configuration_transaction command");
 
-    let mut devices_guard = mesh_devices.inner.lock().await;
-    let packet_api = devices_guard
-        .get_mut(&device_key)
-        .ok_or("Device not connected")?;
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let mut packet_api = device.lock().await;
 
     let mut connections_guard = radio_connections.inner.lock().await;
     let connection = connections_guard
         .get_mut(&device_key)
-        .ok_or("Radio connection not initialized")?;
+        .ok_or_else(|| CommandError::radio_not_connected(&device_key))?;
 
     if !packet_api.device.config_in_progress {
         return Err("Configuration transaction not started".into());
@@ -131,58 +123,540 @@ pub async fn commit_configuration_transaction(
     Ok(())
 }
 
+/// Writes each section of the config as its own admin packet in a single config
+/// transaction, returning right away with the id of the operation. Progress of the
+/// writes is reported with `operation_progress` events.
 #[tauri::command]
 pub async fn update_device_config_bulk(
     device_key: DeviceKey,
     app_handle: tauri::AppHandle,
     config: DeviceBulkConfig,
     mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
-    radio_connections: tauri::State<'_, state::radio_connections::RadioConnectionsState>,
-) -> Result<(), CommandError> {
-    debug!("Called commit_configuration_transaction command");
-
-    let mut devices_guard = mesh_devices.inner.lock().await;
-    let packet_api = devices_guard
-        .get_mut(&device_key)
-        .ok_or("Device not connected")?;
-
-    let mut connections_guard = radio_connections.inner.lock().await;
-    let connection = connections_guard
-        .get_mut(&device_key)
-        .ok_or("Radio connection not initialized")?;
+) -> Result<OperationId, CommandError> {
+    debug!("Called update_device_config_bulk command");
 
-    connection
-        .start_config_transaction()
+    get_device_handle(&mesh_devices.inner, &device_key)
         .await
-        .map_err(|e| e.to_string())?;
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?
+        .write(RadioWrite::StartConfigTransaction)
+        .await?;
+
+    let mut writes = vec![];
 
     if let Some(radio_config) = config.radio {
-        connection
-            .set_local_config(packet_api, radio_config)
-            .await
-            .map_err(|e| e.to_string())?;
+        writes.extend(local_config_writes(radio_config));
     }
 
     if let Some(module_config) = config.module {
-        connection
-            .set_local_module_config(packet_api, module_config)
-            .await
-            .map_err(|e| e.to_string())?;
+        writes.push((
+            "Module config".to_string(),
+            ConfigWrite::ModuleConfig(module_config),
+        ));
     }
 
     if let Some(channel_config) = config.channels {
-        connection
-            .set_message_channel_config(packet_api, channel_config)
-            .await
-            .map_err(|e| e.to_string())?;
+        writes.extend(channel_writes(channel_config));
     }
 
-    connection
-        .commit_config_transaction()
+    spawn_config_operation(
+        app_handle,
+        device_key,
+        OperationKind::ConfigPush,
+        writes,
+        mesh_devices.inner.clone(),
+    )
+    .map_err(CommandError::from)
+}
+
+/// Stops sending the remaining items of a bulk operation. Returns false if the
+/// operation had already finished.
+#[tauri::command]
+pub async fn cancel_operation(
+    operation_id: OperationId,
+    operations: tauri::State<'_, state::operations::OperationsState>,
+) -> Result<bool, CommandError> {
+    debug!("Called cancel_operation command");
+    trace!("Called with operation id {}", operation_id);
+
+    let mut operations = operations.inner.lock().map_err(|e| e.to_string())?;
+
+    Ok(operations.cancel(operation_id))
+}
+
+/// Sends `writes` as an operation, committing the open config transaction after them
+pub fn spawn_config_operation(
+    app_handle: tauri::AppHandle,
+    device_key: DeviceKey,
+    kind: OperationKind,
+    writes: Vec<(String, ConfigWrite)>,
+    mesh_devices: state::mesh_devices::MeshDevicesStateInner,
+) -> Result<OperationId, String> {
+    let send_write = {
+        let mesh_devices = mesh_devices.clone();
+        let device_key = device_key.clone();
+
+        move |write| {
+            let mesh_devices = mesh_devices.clone();
+            let device_key = device_key.clone();
+
+            async move { send_config_write(&mesh_devices, &device_key, write).await }
+        }
+    };
+
+    let commit = {
+        let device_key = device_key.clone();
+
+        move || async move { commit_config_writes(&mesh_devices, &device_key).await }
+    };
+
+    spawn_operation(
+        app_handle,
+        device_key,
+        kind,
+        writes,
+        send_write,
+        Some(("Commit configuration".to_string(), commit)),
+    )
+}
+
+#[tauri::command]
+pub async fn sync_device_time(
+    device_key: DeviceKey,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<u32, CommandError> {
+    debug!("Called sync_device_time command");
+
+    let device = get_device_handle(&mesh_devices.inner, &device_key)
         .await
-        .map_err(|e| e.to_string())?;
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+
+    send_device_time(&device).await
+}
+
+#[tauri::command]
+pub async fn get_time_sync_config(
+    time_sync: tauri::State<'_, state::time_sync::TimeSyncState>,
+) -> Result<TimeSyncConfig, CommandError> {
+    debug!("Called get_time_sync_config command");
+
+    let config = time_sync.inner.lock().map_err(|e| e.to_string())?;
 
-    events::dispatch_updated_device(&app_handle, &packet_api.device).map_err(|e| e.to_string())?;
+    Ok(config.clone())
+}
+
+#[tauri::command]
+pub async fn set_time_sync_config(
+    config: TimeSyncConfig,
+    time_sync: tauri::State<'_, state::time_sync::TimeSyncState>,
+) -> Result<(), CommandError> {
+    debug!("Called set_time_sync_config command");
+    trace!("Called with config {:?}", config);
+
+    let mut config_guard = time_sync.inner.lock().map_err(|e| e.to_string())?;
+    *config_guard = config;
 
     Ok(())
 }
+
+#[tauri::command]
+pub async fn set_fixed_position(
+    app_handle: tauri::AppHandle,
+    device_key: DeviceKey,
+    latitude: f32,
+    longitude: f32,
+    altitude: i32,
+    rebroadcast_interval_secs: Option<u32>,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    fixed_position_state: tauri::State<'_, state::fixed_position::FixedPositionState>,
+) -> Result<(), CommandError> {
+    debug!("Called set_fixed_position command");
+    trace!(
+        "Called with position ({}, {}, {}m)",
+        latitude,
+        longitude,
+        altitude
+    );
+
+    let stored = StoredFixedPosition {
+        position: FixedPosition {
+            latitude,
+            longitude,
+            altitude,
+        },
+        rebroadcast_interval_secs,
+    };
+
+    stored.position.validate()?;
+
+    get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?
+        .set_fixed_position(Some(stored.position.clone()))
+        .await?;
+
+    // Saved so the rebroadcast resumes when the device reconnects
+    settings::edit_settings(&app_handle, |settings| {
+        settings
+            .fixed_positions
+            .devices
+            .insert(device_key.clone(), stored.clone());
+    })?;
+
+    // Replace any existing rebroadcast task with one using the new position
+
+    let mut rebroadcasts_guard = fixed_position_state.inner.lock().await;
+
+    if let Some(handle) = rebroadcasts_guard.remove(&device_key) {
+        handle.abort();
+    }
+
+    if let Some(interval) = stored.rebroadcast_interval() {
+        let handle = spawn_fixed_position_rebroadcast(
+            mesh_devices.inner.clone(),
+            device_key.clone(),
+            stored.position,
+            interval,
+        );
+
+        rebroadcasts_guard.insert(device_key, handle);
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn clear_fixed_position(
+    app_handle: tauri::AppHandle,
+    device_key: DeviceKey,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    fixed_position_state: tauri::State<'_, state::fixed_position::FixedPositionState>,
+) -> Result<(), CommandError> {
+    debug!("Called clear_fixed_position command");
+
+    if let Some(handle) = fixed_position_state.inner.lock().await.remove(&device_key) {
+        handle.abort();
+    }
+
+    settings::edit_settings(&app_handle, |settings| {
+        settings.fixed_positions.devices.remove(&device_key);
+    })?;
+
+    get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?
+        .set_fixed_position(None)
+        .await
+}
+
+#[tauri::command]
+pub async fn shutdown_device(
+    device_key: DeviceKey,
+    delay_secs: i32,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<(), CommandError> {
+    debug!("Called shutdown_device command");
+
+    let device = get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+
+    device
+        .packet_api()
+        .lock()
+        .await
+        .device
+        .require_capability(DeviceCapability::Shutdown)?;
+
+    device
+        .write(RadioWrite::local_admin(
+            protobufs::admin_message::PayloadVariant::ShutdownSeconds(delay_secs),
+            false,
+        ))
+        .await
+}
+
+/// Returns the device's channels by index, with their keys in base64
+#[tauri::command]
+pub async fn get_channels(
+    device_key: DeviceKey,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<Vec<EditableChannel>, CommandError> {
+    debug!("Called get_channels command");
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let packet_api = device.lock().await;
+
+    let mut channels: Vec<EditableChannel> = packet_api
+        .device
+        .channels
+        .values()
+        .map(|channel| EditableChannel::from_channel(&channel.config))
+        .collect();
+
+    channels.sort_by_key(|channel| channel.index);
+
+    Ok(channels)
+}
+
+/// Writes a channel to the device, returning once the device reports the channel
+/// back with the new settings. Invalid keys and channel indices the device doesn't
+/// have are rejected before anything is sent.
+#[tauri::command]
+pub async fn set_channel(
+    device_key: DeviceKey,
+    channel: EditableChannel,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<EditableChannel, CommandError> {
+    debug!("Called set_channel command");
+    trace!("Called with channel {}", channel.index);
+
+    let index = channel.index;
+
+    let device = get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+
+    let updated = {
+        let packet_api = device.packet_api();
+        let packet_api = packet_api.lock().await;
+
+        let existing = packet_api
+            .device
+            .channels
+            .get(&index)
+            .ok_or_else(|| format!("Device has no channel {}", index))?;
+
+        channel.apply_to(&existing.config)?
+    };
+
+    let expected = EditableChannel::from_channel(&updated);
+
+    device
+        .write(RadioWrite::local_admin(
+            protobufs::admin_message::PayloadVariant::SetChannel(updated),
+            false,
+        ))
+        .await?;
+
+    // Reading the channel back confirms the write and refreshes the cached channel
+    device
+        .write(RadioWrite::local_admin(
+            protobufs::admin_message::PayloadVariant::GetChannelRequest(index + 1),
+            true,
+        ))
+        .await?;
+
+    wait_for_device_state(
+        &mesh_devices.inner,
+        &device_key,
+        DEFAULT_ADMIN_RESPONSE_TIMEOUT,
+        |device| {
+            device
+                .channels
+                .get(&index)
+                .map(|channel| EditableChannel::from_channel(&channel.config))
+                .filter(|channel| *channel == expected)
+        },
+    )
+    .await
+}
+
+/// Why an admin request sent to another node didn't give the result it was sent for
+fn remote_admin_error(node_num: u32, outcome: RemoteAdminOutcome) -> CommandError {
+    match outcome {
+        RemoteAdminOutcome::Unauthorized => format!(
+            "Node {} rejected the request, it requires this device's admin key or session",
+            node_num
+        )
+        .into(),
+        RemoteAdminOutcome::Failed { reason } => reason.into(),
+        RemoteAdminOutcome::TimedOut => format!(
+            "No response from node {} within {} seconds",
+            node_num,
+            REMOTE_ADMIN_TIMEOUT.as_secs()
+        )
+        .into(),
+        RemoteAdminOutcome::Config { .. } | RemoteAdminOutcome::Applied => {
+            format!("Unexpected response from node {}", node_num).into()
+        }
+    }
+}
+
+/// Sends an admin message to another node through a device and waits for its result,
+/// which is also emitted as a `remote_admin_result` event
+async fn request_remote_admin(
+    device_key: &DeviceKey,
+    node_num: u32,
+    admin_message: protobufs::AdminMessage,
+    want_response: bool,
+    mesh_devices: &state::mesh_devices::MeshDevicesState,
+) -> Result<RemoteAdminOutcome, CommandError> {
+    let device = get_device_handle(&mesh_devices.inner, device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+
+    let request = device
+        .send_remote_admin(RemoteAdminRequest {
+            message: admin_message,
+            node_num,
+            want_response,
+        })
+        .await?;
+
+    Ok(await_remote_admin(&device, request, REMOTE_ADMIN_TIMEOUT).await)
+}
+
+/// Reads a configuration section of another node over the mesh. Round trips over
+/// several hops are slow, so this waits up to a few minutes for the node's response.
+#[tauri::command]
+pub async fn get_remote_config(
+    device_key: DeviceKey,
+    node_num: u32,
+    config_type: RemoteConfigType,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<protobufs::Config, CommandError> {
+    debug!("Called get_remote_config command");
+    trace!("Called with node {} and config {:?}", node_num, config_type);
+
+    let outcome = request_remote_admin(
+        &device_key,
+        node_num,
+        protobufs::AdminMessage {
+            payload_variant: Some(protobufs::admin_message::PayloadVariant::GetConfigRequest(
+                config_type.config_type() as i32,
+            )),
+            ..Default::default()
+        },
+        true,
+        &mesh_devices,
+    )
+    .await?;
+
+    match outcome {
+        RemoteAdminOutcome::Config { config } => Ok(config),
+        outcome => Err(remote_admin_error(node_num, outcome)),
+    }
+}
+
+/// Writes a configuration section to another node over the mesh, returning once the
+/// node acks it. The node reboots to apply some sections.
+#[tauri::command]
+pub async fn set_remote_config(
+    device_key: DeviceKey,
+    node_num: u32,
+    config: protobufs::Config,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<(), CommandError> {
+    debug!("Called set_remote_config command");
+    trace!("Called with node {} and config {:?}", node_num, config);
+
+    let outcome = request_remote_admin(
+        &device_key,
+        node_num,
+        protobufs::AdminMessage {
+            payload_variant: Some(protobufs::admin_message::PayloadVariant::SetConfig(config)),
+            ..Default::default()
+        },
+        false,
+        &mesh_devices,
+    )
+    .await?;
+
+    match outcome {
+        RemoteAdminOutcome::Applied => Ok(()),
+        outcome => Err(remote_admin_error(node_num, outcome)),
+    }
+}
+
+/// Returns a device's configuration, read from the device if it's connected and from
+/// the configuration cached during its last connection otherwise
+#[tauri::command]
+pub async fn get_device_config(
+    node_num: u32,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    device_configs: tauri::State<'_, state::device_configs::DeviceConfigsState>,
+) -> Result<DeviceConfigView, CommandError> {
+    debug!("Called get_device_config command");
+    trace!("Called with node num {}", node_num);
+
+    let mut connected = None;
+
+    for (_, device) in all_devices(&mesh_devices.inner).await {
+        let packet_api = device.lock_owned().await;
+
+        if packet_api.device.my_node_info.my_node_num == node_num {
+            connected = Some(packet_api);
+            break;
+        }
+    }
+
+    let configs_guard = device_configs.inner.lock().map_err(|e| e.to_string())?;
+
+    let view = device_config_view(
+        connected.as_ref().map(|packet_api| &packet_api.device),
+        configs_guard.get(&node_num),
+        get_current_time_u32(),
+    )
+    .ok_or("No configuration known for device")?;
+
+    Ok(view)
+}
+
+/// Writes the channels cached for `node_num`, with their PSKs, to a connected device.
+/// The cached PSKs are only decrypted here. Each channel is written as its own item of
+/// the returned operation.
+#[tauri::command]
+pub async fn restore_cached_channels(
+    device_key: DeviceKey,
+    node_num: u32,
+    confirmed: bool,
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    device_configs: tauri::State<'_, state::device_configs::DeviceConfigsState>,
+) -> Result<OperationId, CommandError> {
+    debug!("Called restore_cached_channels command");
+    trace!("Called with node num {}", node_num);
+
+    // Restoring overwrites every channel on the device, including its keys
+
+    if !confirmed {
+        return Err("Channel restores must be explicitly confirmed".into());
+    }
+
+    let cached = {
+        let configs_guard = device_configs.inner.lock().map_err(|e| e.to_string())?;
+        configs_guard
+            .get(&node_num)
+            .cloned()
+            .ok_or("No configuration known for device")?
+    };
+
+    let channels = {
+        let app_handle = app_handle.clone();
+
+        tauri::async_runtime::spawn_blocking(move || {
+            let key = secret_key(&app_handle)?;
+            cached.channels_with_psks(&key)
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?
+    };
+
+    get_device_handle(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?
+        .write(RadioWrite::StartConfigTransaction)
+        .await?;
+
+    spawn_config_operation(
+        app_handle,
+        device_key,
+        OperationKind::ChannelRestore,
+        channel_writes(channels),
+        mesh_devices.inner.clone(),
+    )
+    .map_err(CommandError::from)
+}
@@ -0,0 +1,297 @@
+use crate::device::{MeshDevice, SerialDeviceStatus};
+use crate::graph::{ds::graph::MeshGraph, geojson::GraphGeoJson};
+use crate::ipc::events;
+use crate::ipc::{CommandError, ConfigurationStatus, DevicesListChange, EVENT_API_VERSION};
+use crate::packet_api::actor::spawn_device;
+use crate::packet_api::summary::ConnectionType;
+use crate::packet_api::MeshPacketApi;
+use crate::replay::capture::{parse_capture, PacketCapture};
+use crate::replay::{spawn_replay, ReplaySession, ReplayStatus, REPLAY_DEVICE_KEY};
+use crate::state;
+use crate::state::graph::SharedGraph;
+use crate::state::mesh_devices::get_device;
+use crate::state::replay::ActiveReplay;
+use crate::state::DeviceKey;
+
+use log::{debug, trace};
+use std::sync::Arc;
+use tauri::Manager;
+
+/// Opens a packet capture for replay as the `replay` device. Replayed packets update
+/// a graph of their own, so they never mix with live connections.
+#[tauri::command]
+pub async fn replay_open(
+    path: String,
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    replay: tauri::State<'_, state::replay::ReplayState>,
+) -> Result<ReplayStatus, CommandError> {
+    debug!("Called replay_open command");
+    trace!("Called with path {}", path);
+
+    let mut replay_guard = replay.inner.lock().await;
+
+    if replay_guard.is_some() {
+        return Err("A replay is already open".into());
+    }
+
+    let bytes = std::fs::read(&path).map_err(|e| e.to_string())?;
+    let records = parse_capture(&bytes).map_err(|e| e.to_string())?;
+
+    let mut packet_api = MeshPacketApi::new(
+        app_handle.app_handle(),
+        REPLAY_DEVICE_KEY.into(),
+        MeshDevice::new(),
+        Arc::new(SharedGraph::new(MeshGraph::new())),
+    );
+    packet_api.connection_type = ConnectionType::Replay;
+
+    // The replayed radio has no configuration flow, so it's connected immediately
+    packet_api.device.set_status(SerialDeviceStatus::Connected);
+
+    let mut session = ReplaySession::new(path, records, &packet_api)?;
+    session.seek(&mut packet_api, session.start_millis())?;
+    let status = session.status();
+
+    events::dispatch_updated_device(&app_handle, &packet_api.device_key, &packet_api.device)
+        .map_err(|e| e.to_string())?;
+
+    mesh_devices
+        .inner
+        .lock()
+        .await
+        .insert(REPLAY_DEVICE_KEY.into(), spawn_device(packet_api));
+
+    events::dispatch_devices_list_changed(
+        &app_handle,
+        REPLAY_DEVICE_KEY.into(),
+        DevicesListChange::Added,
+        SerialDeviceStatus::Connected,
+    )
+    .map_err(|e| e.to_string())?;
+
+    events::dispatch_configuration_status(
+        &app_handle,
+        ConfigurationStatus {
+            api_version: EVENT_API_VERSION,
+            device_key: REPLAY_DEVICE_KEY.into(),
+            successful: true,
+            message: None,
+        },
+    )
+    .map_err(|e| e.to_string())?;
+
+    *replay_guard = Some(ActiveReplay {
+        session: Arc::new(tauri::async_runtime::Mutex::new(session)),
+        task: None,
+    });
+
+    Ok(status)
+}
+
+/// Plays the replay from its position at `speed` times the recorded pacing,
+/// starting over if it had reached the end
+#[tauri::command]
+pub async fn replay_play(
+    speed: f64,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    replay: tauri::State<'_, state::replay::ReplayState>,
+) -> Result<ReplayStatus, CommandError> {
+    debug!("Called replay_play command");
+    trace!("Called with speed {}", speed);
+
+    let mut replay_guard = replay.inner.lock().await;
+    let active_replay = replay_guard.as_mut().ok_or("No replay open")?;
+
+    if let Some(task) = active_replay.task.take() {
+        task.abort();
+    }
+
+    let status = {
+        let mut session = active_replay.session.lock().await;
+        session.play(speed)?;
+
+        if session.is_finished() {
+            let device = get_device(&mesh_devices.inner, REPLAY_DEVICE_KEY)
+                .await
+                .ok_or("Replayed device not connected")?;
+            let mut packet_api = device.lock().await;
+
+            let start_millis = session.start_millis();
+            session.seek(&mut *packet_api, start_millis)?;
+        }
+
+        session.status()
+    };
+
+    active_replay.task = Some(spawn_replay(
+        mesh_devices.inner.clone(),
+        active_replay.session.clone(),
+    ));
+
+    Ok(status)
+}
+
+#[tauri::command]
+pub async fn replay_pause(
+    replay: tauri::State<'_, state::replay::ReplayState>,
+) -> Result<ReplayStatus, CommandError> {
+    debug!("Called replay_pause command");
+
+    let mut replay_guard = replay.inner.lock().await;
+    let active_replay = replay_guard.as_mut().ok_or("No replay open")?;
+
+    if let Some(task) = active_replay.task.take() {
+        task.abort();
+    }
+
+    let mut session = active_replay.session.lock().await;
+    session.pause();
+
+    Ok(session.status())
+}
+
+/// Moves the replay to `timestamp` (milliseconds since epoch), clamped to the
+/// capture. Playback continues from there if the replay is playing.
+#[tauri::command]
+pub async fn replay_seek(
+    timestamp: u64,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    replay: tauri::State<'_, state::replay::ReplayState>,
+) -> Result<ReplayStatus, CommandError> {
+    debug!("Called replay_seek command");
+    trace!("Called with timestamp {}", timestamp);
+
+    let replay_guard = replay.inner.lock().await;
+    let active_replay = replay_guard.as_ref().ok_or("No replay open")?;
+
+    let mut session = active_replay.session.lock().await;
+    let device = get_device(&mesh_devices.inner, REPLAY_DEVICE_KEY)
+        .await
+        .ok_or("Replayed device not connected")?;
+    let mut packet_api = device.lock().await;
+
+    session.seek(&mut *packet_api, timestamp)?;
+
+    // Handlers only report what changed, so a rebuilt state is sent in full
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| e.to_string())?;
+
+    let graph = packet_api.graph_arc.view()?;
+
+    events::dispatch_graph_geojson_update(
+        &packet_api.app_handle,
+        GraphGeoJson::new(packet_api.device_key.clone(), &graph, &packet_api.device),
+    )
+    .map_err(|e| e.to_string())?;
+    events::dispatch_updated_graph(
+        &packet_api.app_handle,
+        Some(packet_api.device_key.clone()),
+        graph,
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(session.status())
+}
+
+#[tauri::command]
+pub async fn replay_close(
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    replay: tauri::State<'_, state::replay::ReplayState>,
+) -> Result<(), CommandError> {
+    debug!("Called replay_close command");
+
+    let active_replay = replay.inner.lock().await.take().ok_or("No replay open")?;
+
+    if let Some(task) = active_replay.task {
+        task.abort();
+    }
+
+    let device = mesh_devices.inner.lock().await.remove(REPLAY_DEVICE_KEY);
+
+    if let Some(device) = device {
+        device.disconnect().await;
+    }
+
+    events::dispatch_device_disconnect(&app_handle, REPLAY_DEVICE_KEY.into())
+        .map_err(|e| e.to_string())?;
+
+    events::dispatch_devices_list_changed(
+        &app_handle,
+        REPLAY_DEVICE_KEY.into(),
+        DevicesListChange::Removed,
+        SerialDeviceStatus::Disconnected,
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Opens a packet capture and starts playing it at `speed` times the recorded
+/// pacing, so the replayed device behaves like a radio that was just connected
+#[tauri::command]
+pub async fn connect_replay(
+    path: String,
+    speed: f64,
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    replay: tauri::State<'_, state::replay::ReplayState>,
+) -> Result<ReplayStatus, CommandError> {
+    debug!("Called connect_replay command");
+    trace!("Called with path {} speed {}", path, speed);
+
+    replay_open(path, app_handle, mesh_devices.clone(), replay.clone()).await?;
+    replay_play(speed, mesh_devices, replay).await
+}
+
+/// Records every packet decoded from the device to a capture file at `path`, which
+/// `replay_open` can play back later. Capturing to an existing capture appends to it.
+#[tauri::command]
+pub async fn start_packet_capture(
+    device_key: DeviceKey,
+    path: String,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<(), CommandError> {
+    debug!("Called start_packet_capture command");
+    trace!("Called with device key {} path {}", device_key, path);
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let mut packet_api = device.lock().await;
+
+    if packet_api.capture.is_some() {
+        return Err("Device is already being captured".into());
+    }
+
+    packet_api.capture = Some(PacketCapture::open(&path)?);
+
+    Ok(())
+}
+
+/// Stops capturing the device's packets, returning how many were recorded
+#[tauri::command]
+pub async fn stop_packet_capture(
+    device_key: DeviceKey,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+) -> Result<u32, CommandError> {
+    debug!("Called stop_packet_capture command");
+    trace!("Called with device key {}", device_key);
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let mut packet_api = device.lock().await;
+
+    let capture = packet_api
+        .capture
+        .take()
+        .ok_or("Device isn't being captured")?;
+
+    Ok(capture.record_count())
+}
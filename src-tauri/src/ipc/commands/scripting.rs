@@ -0,0 +1,98 @@
+use crate::ipc::CommandError;
+use crate::persistence::{save_json, PACKET_SCRIPTS_FILE_NAME};
+use crate::scripting::engine::{compile_script, run_script, ScriptLimits};
+use crate::scripting::{PacketScript, PacketScripts, ScriptAction, ScriptPacket};
+use crate::state;
+
+use log::{debug, trace};
+
+#[tauri::command]
+pub async fn get_packet_scripts(
+    packet_scripts: tauri::State<'_, state::packet_scripts::PacketScriptsState>,
+) -> Result<PacketScripts, CommandError> {
+    debug!("Called get_packet_scripts command");
+
+    let scripts = packet_scripts.inner.lock().map_err(|e| e.to_string())?;
+
+    Ok(scripts.clone())
+}
+
+/// Adds an enabled script. Scripts that don't compile are rejected.
+#[tauri::command]
+pub async fn add_packet_script(
+    name: String,
+    source: String,
+    app_handle: tauri::AppHandle,
+    packet_scripts: tauri::State<'_, state::packet_scripts::PacketScriptsState>,
+) -> Result<PacketScript, CommandError> {
+    debug!("Called add_packet_script command");
+    trace!("Called with name {}", name);
+
+    compile_script(&source).map_err(|e| e.to_string())?;
+
+    let mut scripts = packet_scripts.inner.lock().map_err(|e| e.to_string())?;
+    let script = scripts.add(name, source);
+
+    save_json(&app_handle, PACKET_SCRIPTS_FILE_NAME, &*scripts)?;
+
+    Ok(script)
+}
+
+#[tauri::command]
+pub async fn set_packet_script_enabled(
+    script_id: u32,
+    enabled: bool,
+    app_handle: tauri::AppHandle,
+    packet_scripts: tauri::State<'_, state::packet_scripts::PacketScriptsState>,
+) -> Result<(), CommandError> {
+    debug!("Called set_packet_script_enabled command");
+    trace!("Called with script {} enabled {}", script_id, enabled);
+
+    let mut scripts = packet_scripts.inner.lock().map_err(|e| e.to_string())?;
+
+    if !scripts.set_enabled(script_id, enabled) {
+        return Err(format!("Packet script {} not found", script_id).into());
+    }
+
+    save_json(&app_handle, PACKET_SCRIPTS_FILE_NAME, &*scripts)?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn remove_packet_script(
+    script_id: u32,
+    app_handle: tauri::AppHandle,
+    packet_scripts: tauri::State<'_, state::packet_scripts::PacketScriptsState>,
+) -> Result<(), CommandError> {
+    debug!("Called remove_packet_script command");
+    trace!("Called with script {}", script_id);
+
+    let mut scripts = packet_scripts.inner.lock().map_err(|e| e.to_string())?;
+
+    if !scripts.remove(script_id) {
+        return Err(format!("Packet script {} not found", script_id).into());
+    }
+
+    save_json(&app_handle, PACKET_SCRIPTS_FILE_NAME, &*scripts)?;
+
+    Ok(())
+}
+
+/// Runs a script against a sample packet and returns the actions it would take,
+/// without performing them
+#[tauri::command]
+pub async fn test_packet_script(
+    source: String,
+    packet: ScriptPacket,
+) -> Result<Vec<ScriptAction>, CommandError> {
+    debug!("Called test_packet_script command");
+    trace!("Called with packet {:?}", packet);
+
+    tauri::async_runtime::spawn_blocking(move || {
+        run_script(&source, &packet, ScriptLimits::default())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string().into())
+}
@@ -0,0 +1,52 @@
+use log::debug;
+
+use crate::ipc::CommandError;
+use crate::secrets::{self, SecretsStatus};
+
+#[tauri::command]
+pub async fn get_secrets_status(
+    app_handle: tauri::AppHandle,
+) -> Result<SecretsStatus, CommandError> {
+    debug!("Called get_secrets_status command");
+
+    let status = secrets::secrets_status(&app_handle).map_err(|e| e.to_string())?;
+
+    Ok(status)
+}
+
+/// Unlocks stored secrets protected by a passphrase. The first passphrase entered,
+/// on a system without a keychain, sets it.
+#[tauri::command]
+pub async fn unlock_secrets(
+    passphrase: String,
+    app_handle: tauri::AppHandle,
+) -> Result<(), CommandError> {
+    debug!("Called unlock_secrets command");
+
+    tauri::async_runtime::spawn_blocking(move || {
+        secrets::unlock_secrets(&app_handle, &passphrase).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    Ok(())
+}
+
+/// Re-encrypts every stored secret under a new key, derived from `passphrase` if one
+/// is given and kept in the keychain otherwise. Returns the number of devices whose
+/// secrets were re-encrypted.
+#[tauri::command]
+pub async fn rotate_secrets_key(
+    passphrase: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<u32, CommandError> {
+    debug!("Called rotate_secrets_key command");
+
+    let reencrypted = tauri::async_runtime::spawn_blocking(move || {
+        secrets::rotate_secret_key(&app_handle, passphrase.as_deref()).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    Ok(reencrypted)
+}
@@ -0,0 +1,31 @@
+use crate::ipc::CommandError;
+use crate::settings::{self, AppSettings, SettingsUpdate};
+use crate::state;
+
+use log::{debug, trace};
+
+#[tauri::command]
+pub async fn get_settings(
+    settings: tauri::State<'_, state::settings::SettingsState>,
+) -> Result<AppSettings, CommandError> {
+    debug!("Called get_settings command");
+
+    let settings = settings.inner.lock().map_err(|e| e.to_string())?;
+
+    Ok(settings.clone())
+}
+
+/// Applies a partial settings object. Invalid fields are returned with their paths,
+/// and don't stop the valid fields of the patch from being saved.
+#[tauri::command]
+pub async fn update_settings(
+    patch: serde_json::Value,
+    app_handle: tauri::AppHandle,
+) -> Result<SettingsUpdate, CommandError> {
+    debug!("Called update_settings command");
+    trace!("Called with patch {}", patch);
+
+    let update = settings::update_settings(&app_handle, &patch)?;
+
+    Ok(update)
+}
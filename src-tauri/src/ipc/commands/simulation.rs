@@ -0,0 +1,246 @@
+use crate::device::helpers::get_current_time_u32;
+use crate::device::{MeshDevice, SerialDeviceStatus};
+use crate::ipc::events;
+use crate::ipc::{CommandError, ConfigurationStatus, DevicesListChange, EVENT_API_VERSION};
+use crate::packet_api::actor::spawn_device;
+use crate::packet_api::summary::ConnectionType;
+use crate::packet_api::MeshPacketApi;
+use crate::persistence::{save_json, DEVELOPER_MODE_FILE_NAME};
+use crate::simulation::injection::{inject_packet_json, packet_injection_allowed};
+use crate::simulation::scenario::{ScenarioEngine, ScenarioParams};
+use crate::simulation::{feed_simulated_packets, spawn_simulation, SIMULATION_DEVICE_KEY};
+use crate::state::mesh_devices::get_device;
+use crate::state::simulation::ActiveSimulation;
+use crate::state::{self, DeviceKey};
+
+use log::{debug, trace};
+use std::sync::Arc;
+use tauri::Manager;
+
+#[tauri::command]
+pub async fn start_simulation(
+    params: ScenarioParams,
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    mesh_graph: tauri::State<'_, state::graph::GraphState>,
+    simulation: tauri::State<'_, state::simulation::SimulationState>,
+) -> Result<(), CommandError> {
+    debug!("Called start_simulation command");
+    trace!("Called with params {:?}", params);
+
+    params.validate()?;
+
+    let mut simulation_guard = simulation.inner.lock().await;
+
+    if simulation_guard.is_some() {
+        return Err("Simulation already running".into());
+    }
+
+    let mut engine = ScenarioEngine::new(params);
+
+    let mut packet_api = MeshPacketApi::new(
+        app_handle.app_handle(),
+        SIMULATION_DEVICE_KEY.into(),
+        MeshDevice::new(),
+        mesh_graph.inner.clone(),
+    );
+    packet_api.connection_type = ConnectionType::Simulated;
+
+    feed_simulated_packets(
+        &mut packet_api,
+        engine.initial_packets(get_current_time_u32()),
+    );
+
+    // The simulated radio has no configuration flow, so it's connected immediately
+
+    packet_api.device.set_status(SerialDeviceStatus::Connected);
+
+    events::dispatch_updated_device(&app_handle, &packet_api.device_key, &packet_api.device)
+        .map_err(|e| e.to_string())?;
+
+    mesh_devices
+        .inner
+        .lock()
+        .await
+        .insert(SIMULATION_DEVICE_KEY.into(), spawn_device(packet_api));
+
+    events::dispatch_devices_list_changed(
+        &app_handle,
+        SIMULATION_DEVICE_KEY.into(),
+        DevicesListChange::Added,
+        SerialDeviceStatus::Connected,
+    )
+    .map_err(|e| e.to_string())?;
+
+    events::dispatch_configuration_status(
+        &app_handle,
+        ConfigurationStatus {
+            api_version: EVENT_API_VERSION,
+            device_key: SIMULATION_DEVICE_KEY.into(),
+            successful: true,
+            message: None,
+        },
+    )
+    .map_err(|e| e.to_string())?;
+
+    let engine = Arc::new(tauri::async_runtime::Mutex::new(engine));
+    let task = spawn_simulation(mesh_devices.inner.clone(), engine.clone());
+
+    *simulation_guard = Some(ActiveSimulation { engine, task });
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn stop_simulation(
+    app_handle: tauri::AppHandle,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    simulation: tauri::State<'_, state::simulation::SimulationState>,
+) -> Result<(), CommandError> {
+    debug!("Called stop_simulation command");
+
+    let active_simulation = simulation
+        .inner
+        .lock()
+        .await
+        .take()
+        .ok_or("Simulation not running")?;
+
+    active_simulation.task.abort();
+
+    let device = mesh_devices
+        .inner
+        .lock()
+        .await
+        .remove(SIMULATION_DEVICE_KEY);
+
+    if let Some(device) = device {
+        device.disconnect().await;
+    }
+
+    events::dispatch_device_disconnect(&app_handle, SIMULATION_DEVICE_KEY.into())
+        .map_err(|e| e.to_string())?;
+
+    events::dispatch_devices_list_changed(
+        &app_handle,
+        SIMULATION_DEVICE_KEY.into(),
+        DevicesListChange::Removed,
+        SerialDeviceStatus::Disconnected,
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_simulation_params(
+    simulation: tauri::State<'_, state::simulation::SimulationState>,
+) -> Result<Option<ScenarioParams>, CommandError> {
+    debug!("Called get_simulation_params command");
+
+    let simulation_guard = simulation.inner.lock().await;
+
+    let params = match simulation_guard.as_ref() {
+        Some(active_simulation) => Some(active_simulation.engine.lock().await.params().clone()),
+        None => None,
+    };
+
+    Ok(params)
+}
+
+#[tauri::command]
+pub async fn update_simulation_params(
+    params: ScenarioParams,
+    simulation: tauri::State<'_, state::simulation::SimulationState>,
+) -> Result<(), CommandError> {
+    debug!("Called update_simulation_params command");
+    trace!("Called with params {:?}", params);
+
+    params.validate()?;
+
+    let simulation_guard = simulation.inner.lock().await;
+    let active_simulation = simulation_guard.as_ref().ok_or("Simulation not running")?;
+
+    active_simulation.engine.lock().await.set_params(params);
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn set_simulation_partitioned(
+    partitioned: bool,
+    simulation: tauri::State<'_, state::simulation::SimulationState>,
+) -> Result<(), CommandError> {
+    debug!("Called set_simulation_partitioned command");
+    trace!("Called with partitioned {}", partitioned);
+
+    let simulation_guard = simulation.inner.lock().await;
+    let active_simulation = simulation_guard.as_ref().ok_or("Simulation not running")?;
+
+    let mut engine = active_simulation.engine.lock().await;
+
+    let params = ScenarioParams {
+        partitioned,
+        ..engine.params().clone()
+    };
+
+    engine.set_params(params);
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_developer_mode(
+    developer_mode: tauri::State<'_, state::developer_mode::DeveloperModeState>,
+) -> Result<bool, CommandError> {
+    debug!("Called get_developer_mode command");
+
+    let enabled = developer_mode.inner.lock().map_err(|e| e.to_string())?;
+
+    Ok(*enabled)
+}
+
+/// Developer mode unlocks testing tools, such as packet injection, in release builds
+#[tauri::command]
+pub async fn set_developer_mode(
+    enabled: bool,
+    app_handle: tauri::AppHandle,
+    developer_mode: tauri::State<'_, state::developer_mode::DeveloperModeState>,
+) -> Result<(), CommandError> {
+    debug!("Called set_developer_mode command");
+    trace!("Called with enabled {}", enabled);
+
+    *developer_mode.inner.lock().map_err(|e| e.to_string())? = enabled;
+
+    save_json(&app_handle, DEVELOPER_MODE_FILE_NAME, &enabled)?;
+
+    Ok(())
+}
+
+/// Handles a JSON packet description as if the device had received it, so UI
+/// states can be reproduced without hardware
+#[tauri::command]
+pub async fn inject_packet(
+    device_key: DeviceKey,
+    packet_json: String,
+    mesh_devices: tauri::State<'_, state::mesh_devices::MeshDevicesState>,
+    developer_mode: tauri::State<'_, state::developer_mode::DeveloperModeState>,
+) -> Result<(), CommandError> {
+    debug!("Called inject_packet command");
+    trace!("Called with packet {}", packet_json);
+
+    let enabled = *developer_mode.inner.lock().map_err(|e| e.to_string())?;
+
+    if !packet_injection_allowed(enabled) {
+        return Err("Packet injection requires developer mode".into());
+    }
+
+    let device = get_device(&mesh_devices.inner, &device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+    let mut packet_api = device.lock().await;
+
+    inject_packet_json(&mut *packet_api, &packet_json)?;
+
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use crate::device::helpers::get_current_time_u32;
+use crate::ipc::CommandError;
+use crate::retention::{self, PruneReport, StorageClass, StorageUsage};
+
+use log::{debug, trace};
+
+/// Rows and estimated size of each class of stored data, and the size of each database
+#[tauri::command]
+pub async fn get_storage_usage(app_handle: tauri::AppHandle) -> Result<StorageUsage, CommandError> {
+    debug!("Called get_storage_usage command");
+
+    let usage =
+        tauri::async_runtime::spawn_blocking(move || retention::get_storage_usage(&app_handle))
+            .await
+            .map_err(|e| e.to_string())??;
+
+    Ok(usage)
+}
+
+/// Enforces the retention policy of a class now, rather than on the next interval
+#[tauri::command]
+pub async fn prune_storage(
+    class: StorageClass,
+    app_handle: tauri::AppHandle,
+) -> Result<PruneReport, CommandError> {
+    debug!("Called prune_storage command");
+    trace!("Called with class {:?}", class);
+
+    let mut reports = tauri::async_runtime::spawn_blocking(move || {
+        retention::enforce_retention(&app_handle, &[class], get_current_time_u32())
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    Ok(reports.remove(0))
+}
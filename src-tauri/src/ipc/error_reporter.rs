@@ -0,0 +1,312 @@
+use std::collections::{HashMap, VecDeque};
+
+use log::{error, warn};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::device::helpers::get_current_time_u32;
+use crate::ipc::events::dispatch_app_error;
+use crate::ipc::{AppErrorEvent, EVENT_API_VERSION};
+use crate::packet_api::handlers::DeviceUpdateError;
+use crate::state::{self, DeviceKey};
+
+/// Maximum number of errors retained for `get_recent_errors`
+pub const APP_ERROR_BUFFER_CAPACITY: usize = 200;
+
+/// Minimum time between reports of the same error code for the same device
+pub const APP_ERROR_RATE_LIMIT_SECS: u32 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum AppErrorSeverity {
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum AppErrorCode {
+    DeviceNotInitialized,
+    PacketNotSupported,
+    PacketDecodeFailed,
+    PacketHandlingFailed,
+    EventDispatchFailed,
+    NotificationFailed,
+    NotificationsPaused,
+    StateLockFailed,
+    AdminRequestFailed,
+    PositionBroadcastFailed,
+    InvalidDeepLink,
+    WebhookDisabled,
+    ScriptFailed,
+    DatabaseNewerThanApp,
+    BackupFailed,
+    RestoreFailed,
+    CaptureWriteFailed,
+}
+
+impl From<&DeviceUpdateError> for AppErrorCode {
+    fn from(err: &DeviceUpdateError) -> Self {
+        match err {
+            DeviceUpdateError::PacketNotSupported(_)
+            | DeviceUpdateError::RadioMessageNotSupported(_) => AppErrorCode::PacketNotSupported,
+            DeviceUpdateError::DecodeFailure(_) => AppErrorCode::PacketDecodeFailed,
+            DeviceUpdateError::GeneralFailure(_) => AppErrorCode::PacketHandlingFailed,
+            DeviceUpdateError::EventDispatchFailure(_) => AppErrorCode::EventDispatchFailed,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AppError {
+    pub timestamp: u32, // secs
+    pub severity: AppErrorSeverity,
+    pub source: String, // module the error was reported from
+    pub code: AppErrorCode,
+    pub message: String,
+    pub device_key: Option<DeviceKey>,
+
+    /// Reports of the same error dropped by rate limiting since it was last reported
+    pub suppressed_count: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+struct ErrorRateLimit {
+    last_reported: Option<u32>,
+    suppressed: u32,
+}
+
+/// Bounded history of reported errors, rate limited per error code and device
+#[derive(Clone, Debug)]
+pub struct AppErrorLog {
+    recent: VecDeque<AppError>,
+    capacity: usize,
+    rate_limit_secs: u32,
+    limits: HashMap<(AppErrorCode, Option<DeviceKey>), ErrorRateLimit>,
+}
+
+impl Default for AppErrorLog {
+    fn default() -> Self {
+        Self::new(APP_ERROR_BUFFER_CAPACITY, APP_ERROR_RATE_LIMIT_SECS)
+    }
+}
+
+impl AppErrorLog {
+    pub fn new(capacity: usize, rate_limit_secs: u32) -> Self {
+        Self {
+            recent: VecDeque::new(),
+            capacity,
+            rate_limit_secs,
+            limits: HashMap::new(),
+        }
+    }
+
+    /// Stores an error, returning it if it should be reported to the UI or
+    /// `None` if the same error was reported too recently.
+    pub fn record(&mut self, mut error: AppError) -> Option<AppError> {
+        let limit = self
+            .limits
+            .entry((error.code, error.device_key.clone()))
+            .or_default();
+
+        if let Some(last_reported) = limit.last_reported {
+            if error.timestamp.saturating_sub(last_reported) < self.rate_limit_secs {
+                limit.suppressed += 1;
+                return None;
+            }
+        }
+
+        error.suppressed_count = limit.suppressed;
+        limit.suppressed = 0;
+        limit.last_reported = Some(error.timestamp);
+
+        self.recent.push_back(error.clone());
+
+        while self.recent.len() > self.capacity {
+            self.recent.pop_front();
+        }
+
+        Some(error)
+    }
+
+    /// Most recent errors, oldest first
+    pub fn recent(&self) -> Vec<AppError> {
+        self.recent.iter().cloned().collect()
+    }
+}
+
+/// Surfaces failures that would otherwise only be logged to the UI as `app_error` events
+pub struct ErrorReporter<'a, R: tauri::Runtime> {
+    handle: &'a tauri::AppHandle<R>,
+    source: &'static str,
+    device_key: Option<DeviceKey>,
+}
+
+impl<'a, R: tauri::Runtime> ErrorReporter<'a, R> {
+    pub fn new(handle: &'a tauri::AppHandle<R>, source: &'static str) -> Self {
+        Self {
+            handle,
+            source,
+            device_key: None,
+        }
+    }
+
+    pub fn with_device(mut self, device_key: &DeviceKey) -> Self {
+        self.device_key = Some(device_key.clone());
+        self
+    }
+
+    pub fn warning(&self, code: AppErrorCode, message: impl Into<String>) {
+        self.report(AppErrorSeverity::Warning, code, message.into());
+    }
+
+    pub fn error(&self, code: AppErrorCode, message: impl Into<String>) {
+        self.report(AppErrorSeverity::Error, code, message.into());
+    }
+
+    /// Reports a failure to handle a packet, treating unsupported packets as warnings
+    pub fn device_update_error(&self, err: &DeviceUpdateError) {
+        let severity = match err {
+            DeviceUpdateError::PacketNotSupported(_)
+            | DeviceUpdateError::RadioMessageNotSupported(_) => AppErrorSeverity::Warning,
+            _ => AppErrorSeverity::Error,
+        };
+
+        self.report(severity, err.into(), err.to_string());
+    }
+
+    fn report(&self, severity: AppErrorSeverity, code: AppErrorCode, message: String) {
+        match severity {
+            AppErrorSeverity::Warning => warn!("[{}] {}", self.source, message),
+            AppErrorSeverity::Error => error!("[{}] {}", self.source, message),
+        }
+
+        let error = AppError {
+            timestamp: get_current_time_u32(),
+            severity,
+            source: self.source.into(),
+            code,
+            message,
+            device_key: self.device_key.clone(),
+            suppressed_count: 0,
+        };
+
+        let reported = match self.handle.try_state::<state::app_errors::AppErrorsState>() {
+            Some(errors_state) => match errors_state.inner.lock() {
+                Ok(mut errors) => errors.record(error),
+                Err(e) => {
+                    warn!("Failed to lock app errors: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let error = match reported {
+            Some(error) => error,
+            None => return,
+        };
+
+        let event = AppErrorEvent {
+            api_version: EVENT_API_VERSION,
+            device_key: error.device_key.clone(),
+            error,
+        };
+
+        // Not reported through `self` to avoid reporting in a loop
+        if let Err(e) = dispatch_app_error(self.handle, event) {
+            warn!("Failed to dispatch app error: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u32 = 1_700_000_000;
+
+    fn app_error(code: AppErrorCode, device_key: Option<&str>, timestamp: u32) -> AppError {
+        AppError {
+            timestamp,
+            severity: AppErrorSeverity::Error,
+            source: "test".into(),
+            code,
+            message: "Something failed".into(),
+            device_key: device_key.map(|k| k.into()),
+            suppressed_count: 0,
+        }
+    }
+
+    #[test]
+    fn rate_limits_repeated_errors() {
+        let mut log = AppErrorLog::new(100, 10);
+        let code = AppErrorCode::PacketHandlingFailed;
+
+        assert!(log.record(app_error(code, Some("COM3"), NOW)).is_some());
+
+        // A looping failure within the window is only counted
+        for _ in 0..500 {
+            assert!(log.record(app_error(code, Some("COM3"), NOW + 9)).is_none());
+        }
+
+        let reported = log.record(app_error(code, Some("COM3"), NOW + 10)).unwrap();
+        assert_eq!(reported.suppressed_count, 500);
+
+        assert_eq!(log.recent().len(), 2);
+        assert_eq!(log.recent()[1].suppressed_count, 500);
+    }
+
+    #[test]
+    fn rate_limits_per_code_and_device() {
+        let mut log = AppErrorLog::new(100, 10);
+
+        assert!(log
+            .record(app_error(
+                AppErrorCode::PacketHandlingFailed,
+                Some("COM3"),
+                NOW
+            ))
+            .is_some());
+        assert!(log
+            .record(app_error(
+                AppErrorCode::PacketHandlingFailed,
+                Some("COM4"),
+                NOW
+            ))
+            .is_some());
+        assert!(log
+            .record(app_error(
+                AppErrorCode::EventDispatchFailed,
+                Some("COM3"),
+                NOW
+            ))
+            .is_some());
+        assert!(log
+            .record(app_error(AppErrorCode::EventDispatchFailed, None, NOW))
+            .is_some());
+
+        assert!(log
+            .record(app_error(AppErrorCode::EventDispatchFailed, None, NOW + 1))
+            .is_none());
+    }
+
+    #[test]
+    fn caps_buffer_to_capacity() {
+        let mut log = AppErrorLog::new(3, 0);
+
+        for i in 0..5 {
+            log.record(app_error(AppErrorCode::StateLockFailed, None, NOW + i));
+        }
+
+        let recent = log.recent();
+
+        assert_eq!(recent.len(), 3);
+        assert_eq!(
+            recent.iter().map(|e| e.timestamp).collect::<Vec<_>>(),
+            vec![NOW + 2, NOW + 3, NOW + 4]
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::{
+    device::MeshDevice,
+    graph::{ds::graph::MeshGraph, geojson::GraphGeoJson},
+    state::DeviceKey,
+};
+
+/// Default minimum time between two events of the same kind for the same key
+pub const DEFAULT_EVENT_COALESCING_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often deferred events are checked for being due
+pub const EVENT_COALESCING_TICK: Duration = Duration::from_millis(50);
+
+/// Holds back bursts of updates so that at most one per key is emitted per interval.
+/// The first update after a quiet period is emitted right away. Later updates within
+/// the interval replace each other, and only the latest is emitted once it has passed.
+/// Each buffer has its own interval, e.g. a device's graph publishing window.
+#[derive(Debug)]
+pub struct CoalescingBuffer<K, T> {
+    interval: Duration,
+    pending: HashMap<K, T>,
+    last_emitted: HashMap<K, Instant>,
+}
+
+impl<K: Eq + Hash + Clone, T> CoalescingBuffer<K, T> {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            pending: HashMap::new(),
+            last_emitted: HashMap::new(),
+        }
+    }
+
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    fn is_due(&self, key: &K, now: Instant) -> bool {
+        match self.last_emitted.get(key) {
+            Some(last_emitted) => now.duration_since(*last_emitted) >= self.interval,
+            None => true,
+        }
+    }
+
+    /// When the first pending value becomes due, `None` if nothing is pending
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending
+            .keys()
+            .map(|key| match self.last_emitted.get(key) {
+                Some(last_emitted) => *last_emitted + self.interval,
+                None => Instant::now(),
+            })
+            .min()
+    }
+
+    /// Returns `value` if it should be emitted now, otherwise keeps it until `take_due`
+    pub fn push(&mut self, key: K, value: T, now: Instant) -> Option<T> {
+        if !self.is_due(&key, now) {
+            self.pending.insert(key, value);
+            return None;
+        }
+
+        self.pending.remove(&key);
+        self.last_emitted.insert(key, now);
+
+        Some(value)
+    }
+
+    /// Removes and returns the pending values whose interval has passed
+    pub fn take_due(&mut self, now: Instant) -> Vec<(K, T)> {
+        let due_keys: Vec<K> = self
+            .pending
+            .keys()
+            .filter(|key| self.is_due(key, now))
+            .cloned()
+            .collect();
+
+        due_keys
+            .into_iter()
+            .filter_map(|key| {
+                let value = self.pending.remove(&key)?;
+                self.last_emitted.insert(key.clone(), now);
+                Some((key, value))
+            })
+            .collect()
+    }
+
+    /// Removes and returns all pending values, regardless of the interval
+    pub fn take_all(&mut self, now: Instant) -> Vec<(K, T)> {
+        let pending: Vec<(K, T)> = self.pending.drain().collect();
+
+        for (key, _) in &pending {
+            self.last_emitted.insert(key.clone(), now);
+        }
+
+        pending
+    }
+}
+
+/// Coalescing buffers for the events that are emitted for nearly every packet,
+/// keyed by the device the event is about
+pub struct EventCoalescer {
+    pub devices: CoalescingBuffer<DeviceKey, MeshDevice>,
+    pub graph: CoalescingBuffer<Option<DeviceKey>, MeshGraph>,
+    pub graph_geojson: CoalescingBuffer<DeviceKey, GraphGeoJson>,
+    interval: Duration,
+}
+
+impl EventCoalescer {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            devices: CoalescingBuffer::new(interval),
+            graph: CoalescingBuffer::new(interval),
+            graph_geojson: CoalescingBuffer::new(interval),
+            interval,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.devices.set_interval(interval);
+        self.graph.set_interval(interval);
+        self.graph_geojson.set_interval(interval);
+        self.interval = interval;
+    }
+}
+
+impl Default for EventCoalescer {
+    fn default() -> Self {
+        Self::new(DEFAULT_EVENT_COALESCING_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_events_from_burst_and_keeps_latest() {
+        let mut buffer = CoalescingBuffer::new(DEFAULT_EVENT_COALESCING_INTERVAL);
+        let start = Instant::now();
+        let mut emitted = vec![];
+
+        // 50 updates 20ms apart span one second, with the timer ticking in between
+        for update in 0..50u32 {
+            let now = start + Duration::from_millis(20 * u64::from(update));
+
+            emitted.extend(buffer.push("device", update, now));
+            emitted.extend(
+                buffer
+                    .take_due(now + EVENT_COALESCING_TICK / 2)
+                    .into_iter()
+                    .map(|(_, value)| value),
+            );
+        }
+
+        let end = start + Duration::from_secs(2);
+        emitted.extend(buffer.take_due(end).into_iter().map(|(_, value)| value));
+
+        assert!(emitted.len() <= 4, "emitted {:?}", emitted);
+        assert_eq!(emitted.first(), Some(&0));
+        assert_eq!(emitted.last(), Some(&49));
+        assert!(buffer.take_all(end).is_empty());
+    }
+
+    #[test]
+    fn holds_updates_until_interval_passes() {
+        let mut buffer = CoalescingBuffer::new(Duration::from_millis(500));
+        let start = Instant::now();
+
+        assert_eq!(buffer.push(1, "a", start), Some("a"));
+        assert_eq!(
+            buffer.push(1, "b", start + Duration::from_millis(100)),
+            None
+        );
+        assert_eq!(
+            buffer.push(2, "c", start + Duration::from_millis(100)),
+            Some("c")
+        );
+
+        assert!(buffer
+            .take_due(start + Duration::from_millis(400))
+            .is_empty());
+        assert_eq!(
+            buffer.take_due(start + Duration::from_millis(500)),
+            vec![(1, "b")]
+        );
+
+        // Flushing ignores the interval
+        assert_eq!(
+            buffer.push(1, "d", start + Duration::from_millis(600)),
+            None
+        );
+        assert_eq!(
+            buffer.next_deadline(),
+            Some(start + Duration::from_millis(1000))
+        );
+        assert_eq!(
+            buffer.take_all(start + Duration::from_millis(600)),
+            vec![(1, "d")]
+        );
+        assert_eq!(buffer.next_deadline(), None);
+    }
+}
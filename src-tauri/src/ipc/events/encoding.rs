@@ -0,0 +1,413 @@
+use log::trace;
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::state::{self, DeviceKey};
+
+use super::scopes::{emit_on_channels, event_channels};
+
+/// How the largest payloads, e.g. edge snapshots, are encoded for the UI. JSON is the
+/// default, MessagePack is only used once the UI asks for it with `set_ipc_encoding`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum IpcEncoding {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+/// A payload encoded as bytes, with the encoding they need to be decoded with
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct BinaryPayload {
+    pub encoding: IpcEncoding,
+    pub data: Vec<u8>,
+}
+
+impl BinaryPayload {
+    /// Encodes structs as maps keyed by field name, so they decode to the same objects
+    /// as their JSON
+    pub fn message_pack<S: Serialize>(payload: &S) -> Result<Self, rmp_serde::encode::Error> {
+        Ok(Self {
+            encoding: IpcEncoding::MessagePack,
+            data: rmp_serde::to_vec_named(payload)?,
+        })
+    }
+}
+
+/// A payload in the encoding the UI asked for. JSON payloads are sent as they are, so
+/// UIs that never ask for another encoding see no difference, and others are sent as a
+/// `BinaryPayload` envelope.
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
+pub enum Encoded<T> {
+    Json(T),
+    Binary(BinaryPayload),
+}
+
+impl<T: Serialize> Encoded<T> {
+    pub fn new(encoding: IpcEncoding, payload: T) -> Result<Self, rmp_serde::encode::Error> {
+        match encoding {
+            IpcEncoding::Json => Ok(Self::Json(payload)),
+            IpcEncoding::MessagePack => BinaryPayload::message_pack(&payload).map(Self::Binary),
+        }
+    }
+}
+
+/// The encoding the UI asked for, JSON if it hasn't
+pub fn ipc_encoding<R: tauri::Runtime>(handle: &tauri::AppHandle<R>) -> IpcEncoding {
+    let ipc_encoding = match handle.try_state::<state::ipc_encoding::IpcEncodingState>() {
+        Some(ipc_encoding) => ipc_encoding,
+        None => return IpcEncoding::default(),
+    };
+
+    let encoding = match ipc_encoding.inner.lock() {
+        Ok(encoding) => *encoding,
+        Err(_) => IpcEncoding::default(),
+    };
+
+    encoding
+}
+
+/// Emits a large payload like `emit_scoped`, in the encoding the UI asked for
+pub fn emit_encoded<R: tauri::Runtime, S: Serialize>(
+    handle: &tauri::AppHandle<R>,
+    event: &str,
+    device_key: Option<&DeviceKey>,
+    payload: &S,
+) -> tauri::Result<Vec<String>> {
+    let channels = event_channels(handle, event, device_key);
+
+    if channels.is_empty() {
+        trace!("Skipped \"{}\" event with no listeners", event);
+        return Ok(channels);
+    }
+
+    let encoded = Encoded::new(ipc_encoding(handle), payload)
+        .map_err(|e| tauri::Error::from(<serde_json::Error as serde::ser::Error>::custom(e)))?;
+
+    emit_on_channels(handle, &channels, &encoded)?;
+
+    Ok(channels)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use geojson::FeatureCollection;
+    use meshtastic::protobufs;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::device::{MeshDevice, MeshNode, NormalizedPosition};
+    use crate::export::analytics_report::{
+        build_analytics_report, AnalyticsReport, AnalyticsReportMetadata, ReportDevice,
+        ANALYTICS_REPORT_VERSION, ANALYTICS_SECTIONS,
+    };
+    use crate::graph::ds::fixtures::graph_node;
+    use crate::graph::ds::{edge::GraphEdge, graph::MeshGraph};
+    use crate::graph::edge_delta::EdgeDelta;
+    use crate::graph::geojson::GraphGeoJson;
+    use crate::ipc::events::payloads::{
+        DeviceUpdateEvent, EdgesDeltaEvent, GraphGeoJsonEvent, EVENT_API_VERSION,
+    };
+
+    const RING_SIZE: u32 = 2_000;
+
+    fn positioned_node(node_num: u32) -> MeshNode {
+        let mut node = MeshNode::new(node_num);
+        node.user = Some(protobufs::User {
+            long_name: format!("Node {}", node_num),
+            short_name: format!("N{}", node_num % 100),
+            ..Default::default()
+        });
+        node.position_metrics
+            .push(NormalizedPosition::from(protobufs::Position {
+                latitude_i: 470_000_000 + node_num as i32 * 10_000,
+                longitude_i: -1_220_000_000 - node_num as i32 * 3_000,
+                altitude: 120,
+                ..Default::default()
+            }));
+        node
+    }
+
+    /// A ring of nodes, each reporting the next one
+    fn ring(size: u32) -> (MeshGraph, MeshDevice) {
+        let mut graph = MeshGraph::new();
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 1;
+
+        for node_num in 1..=size {
+            graph.upsert_node(graph_node(node_num));
+            device.nodes.insert(node_num, positioned_node(node_num));
+        }
+
+        for node_num in 1..=size {
+            let next = node_num % size + 1;
+
+            graph.upsert_edge(
+                graph_node(node_num),
+                graph_node(next),
+                GraphEdge::from_neighbor(
+                    next,
+                    0,
+                    "channel #0".into(),
+                    protobufs::Neighbor {
+                        node_id: node_num,
+                        snr: node_num as f32 * 0.1 - 5.0,
+                        ..Default::default()
+                    },
+                ),
+            );
+        }
+
+        (graph, device)
+    }
+
+    struct Payloads {
+        snapshot: GraphGeoJsonEvent,
+        delta: EdgesDeltaEvent,
+        device_update: DeviceUpdateEvent,
+        report: AnalyticsReport,
+    }
+
+    /// The largest payloads sent to the UI, for a ring of `size` nodes
+    fn payloads(size: u32) -> Payloads {
+        let (graph, device) = ring(size);
+        let geojson = GraphGeoJson::new("COM3".into(), &graph, &device);
+
+        let edges = &geojson.edges.features;
+        let delta = EdgesDeltaEvent {
+            api_version: EVENT_API_VERSION,
+            device_key: "COM3".into(),
+            sequence: 2,
+            nodes: geojson.nodes.clone(),
+            delta: EdgeDelta {
+                added: edges[..2].to_vec(),
+                changed: edges[2..4].to_vec(),
+                removed: vec!["1-2".into()],
+            },
+        };
+
+        let report = build_analytics_report(
+            &graph,
+            AnalyticsReportMetadata {
+                report_version: ANALYTICS_REPORT_VERSION,
+                generated_at: 1_700_000_000,
+                graph_version: Some(2),
+                device: ReportDevice::new("COM3".into(), &device),
+                sections: ANALYTICS_SECTIONS.to_vec(),
+            },
+        )
+        .unwrap();
+
+        Payloads {
+            snapshot: GraphGeoJsonEvent {
+                api_version: EVENT_API_VERSION,
+                sequence: 1,
+                geojson,
+            },
+            delta,
+            device_update: DeviceUpdateEvent {
+                api_version: EVENT_API_VERSION,
+                device_key: "COM3".into(),
+                device,
+            },
+            report,
+        }
+    }
+
+    /// Decodes MessagePack the way the UI does, into plain objects whose keys are
+    /// strings even when they were encoded as numbers
+    fn decode_message_pack(data: &[u8]) -> Value {
+        fn to_json(value: rmpv::Value) -> Value {
+            match value {
+                rmpv::Value::Nil => Value::Null,
+                rmpv::Value::Boolean(value) => Value::Bool(value),
+                rmpv::Value::Integer(value) => match value.as_u64() {
+                    Some(value) => Value::from(value),
+                    None => Value::from(value.as_i64().unwrap()),
+                },
+                rmpv::Value::F32(value) => Value::from(value),
+                rmpv::Value::F64(value) => Value::from(value),
+                rmpv::Value::String(value) => Value::String(value.into_str().unwrap()),
+                rmpv::Value::Binary(value) => Value::from(value),
+                rmpv::Value::Array(values) => {
+                    Value::Array(values.into_iter().map(to_json).collect())
+                }
+                rmpv::Value::Map(entries) => Value::Object(
+                    entries
+                        .into_iter()
+                        .map(|(key, value)| {
+                            let key = match key {
+                                rmpv::Value::String(key) => key.into_str().unwrap(),
+                                key => key.to_string(),
+                            };
+
+                            (key, to_json(value))
+                        })
+                        .collect(),
+                ),
+                rmpv::Value::Ext(..) => panic!("Payloads don't use extension types"),
+            }
+        }
+
+        to_json(rmpv::decode::read_value(&mut &data[..]).unwrap())
+    }
+
+    /// Compares decoded payloads, with numbers equal to an `f32`'s precision since
+    /// JSON writes those as their shortest decimal and MessagePack as they are
+    fn assert_equivalent(expected: &Value, actual: &Value, path: &str) {
+        match (expected, actual) {
+            (Value::Number(expected), Value::Number(actual)) => {
+                let (expected, actual) = (expected.as_f64().unwrap(), actual.as_f64().unwrap());
+
+                assert!(
+                    (expected - actual).abs() <= 1e-6 * expected.abs().max(1.0),
+                    "{} is {} rather than {}",
+                    path,
+                    actual,
+                    expected
+                );
+            }
+            (Value::Array(expected), Value::Array(actual)) => {
+                assert_eq!(expected.len(), actual.len(), "length of {}", path);
+
+                for (index, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+                    assert_equivalent(expected, actual, &format!("{}[{}]", path, index));
+                }
+            }
+            (Value::Object(expected), Value::Object(actual)) => {
+                let keys = |object: &serde_json::Map<String, Value>| {
+                    let mut keys: Vec<String> = object.keys().cloned().collect();
+                    keys.sort_unstable();
+                    keys
+                };
+
+                assert_eq!(keys(expected), keys(actual), "keys of {}", path);
+
+                for (key, expected) in expected {
+                    assert_equivalent(expected, &actual[key], &format!("{}.{}", path, key));
+                }
+            }
+            (expected, actual) => assert_eq!(expected, actual, "{}", path),
+        }
+    }
+
+    /// Sends `payload` in both encodings as it's emitted, checking the UI decodes the
+    /// same payload from each
+    fn assert_round_trips<T: Serialize>(name: &str, payload: &T) {
+        let expected = serde_json::to_value(payload).unwrap();
+
+        for encoding in [IpcEncoding::Json, IpcEncoding::MessagePack] {
+            let encoded = Encoded::new(encoding, payload).unwrap();
+            let sent: Value =
+                serde_json::from_str(&serde_json::to_string(&encoded).unwrap()).unwrap();
+
+            let decoded = match encoding {
+                IpcEncoding::Json => sent,
+                IpcEncoding::MessagePack => {
+                    let envelope: BinaryPayload = serde_json::from_value(sent).unwrap();
+                    assert_eq!(envelope.encoding, IpcEncoding::MessagePack);
+
+                    decode_message_pack(&envelope.data)
+                }
+            };
+
+            assert_equivalent(&expected, &decoded, &format!("{} as {:?}", name, encoding));
+        }
+    }
+
+    #[test]
+    fn payloads_round_trip_through_both_encodings() {
+        let payloads = payloads(50);
+
+        assert_round_trips("edge snapshot", &payloads.snapshot);
+        assert_round_trips("edge delta", &payloads.delta);
+        assert_round_trips("device update", &payloads.device_update);
+        assert_round_trips("analytics report", &payloads.report);
+    }
+
+    #[test]
+    fn defaults_to_json_without_an_envelope() {
+        let app = tauri::test::mock_app();
+        assert_eq!(ipc_encoding(&app.handle()), IpcEncoding::Json);
+
+        let collection = FeatureCollection {
+            bbox: None,
+            features: vec![],
+            foreign_members: None,
+        };
+
+        let encoded = Encoded::new(ipc_encoding(&app.handle()), &collection).unwrap();
+        assert_eq!(
+            serde_json::to_value(encoded).unwrap(),
+            serde_json::to_value(&collection).unwrap()
+        );
+
+        app.manage(state::ipc_encoding::IpcEncodingState::new());
+        *app.state::<state::ipc_encoding::IpcEncodingState>()
+            .inner
+            .lock()
+            .unwrap() = IpcEncoding::MessagePack;
+
+        assert_eq!(ipc_encoding(&app.handle()), IpcEncoding::MessagePack);
+    }
+
+    /// Encoded size and how long encoding took, best of a few runs
+    struct Measurement {
+        len: usize,
+        elapsed: Duration,
+    }
+
+    fn measure(encode: impl Fn() -> Vec<u8>) -> Measurement {
+        let mut len = 0;
+        let mut elapsed = Duration::MAX;
+
+        for _ in 0..5 {
+            let started = Instant::now();
+            len = encode().len();
+            elapsed = elapsed.min(started.elapsed());
+        }
+
+        Measurement { len, elapsed }
+    }
+
+    fn compare<T: Serialize>(name: &str, payload: &T) -> (String, Measurement, Measurement) {
+        let json = measure(|| serde_json::to_vec(payload).unwrap());
+        let message_pack = measure(|| rmp_serde::to_vec_named(payload).unwrap());
+
+        let line = format!(
+            "{}: JSON {} bytes in {:?}, MessagePack {} bytes in {:?}",
+            name, json.len, json.elapsed, message_pack.len, message_pack.elapsed
+        );
+
+        (line, json, message_pack)
+    }
+
+    /// Benchmark of both encodings for a mesh of `RING_SIZE` nodes. Sizes are asserted
+    /// on, timings are only reported since they vary from machine to machine.
+    #[test]
+    fn message_pack_is_smaller_than_json() {
+        let payloads = payloads(RING_SIZE);
+
+        let comparisons = [
+            compare("edge snapshot", &payloads.snapshot),
+            compare("edge delta", &payloads.delta),
+            compare("device update", &payloads.device_update),
+            compare("analytics report", &payloads.report),
+        ];
+
+        let report = comparisons
+            .iter()
+            .map(|(line, _, _)| line.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        for (_, json, message_pack) in comparisons.iter() {
+            assert!(message_pack.len < json.len, "{}", report);
+        }
+    }
+}
@@ -0,0 +1,798 @@
+use crate::{
+    device::{self, SerialDeviceStatus},
+    graph::{
+        ds::graph::MeshGraph, edge_delta::EdgeUpdate, geojson::GraphGeoJson,
+        partition::check_network_partition,
+    },
+    notifications::{geofences::GeofenceTransitionKind, rules::RuleAlert},
+    profiles::{active_profile_name, Profiles},
+    replay::ReplayStatus,
+    settings::AppSettings,
+    state::{self, DeviceKey},
+};
+use log::{debug, trace};
+use tauri::Manager;
+use tokio::time::Instant;
+
+pub mod coalesce;
+pub mod encoding;
+pub mod payloads;
+pub mod scopes;
+
+use coalesce::{CoalescingBuffer, EventCoalescer};
+use encoding::emit_encoded;
+use scopes::emit_scoped;
+
+use payloads::{
+    AnalyticsUpdateEvent, AppErrorEvent, ClockSkewEvent, ConfigurationProgressEvent,
+    ConfigurationStatus, ConnectionMetricsEvent, DebugPacketEvent, DeepLinkEvent,
+    DeviceDisconnectEvent, DeviceLogEvent, DeviceUpdateEvent, DevicesListChange,
+    DevicesListChangedEvent, EdgesDeltaEvent, GeofenceTransitionEvent, GpioChangedEvent,
+    GraphGeoJsonEvent, GraphUpdateEvent, MessageStateEvent, NetworkPartitionEvent,
+    NodeDbReconciledEvent, NodeStatusChangedEvent, NotificationAlertEvent, OperationFinishedEvent,
+    OperationProgressEvent, ProfileChangedEvent, RadioQueueThrottleStatus, RebootEvent,
+    RemoteAdminResultEvent, ReplayStatusEvent, SettingsChangedEvent, TracerouteResultEvent,
+    UnreadCountsChangedEvent, UnsentMessagesEvent, EVENT_API_VERSION,
+};
+
+/// Passes `value` through the event coalescer, returning it if it should be emitted
+/// now. Values are always emitted right away if no coalescer is managed.
+fn coalesce<R: tauri::Runtime, K: Eq + std::hash::Hash + Clone, T>(
+    handle: &tauri::AppHandle<R>,
+    buffer: impl FnOnce(&mut EventCoalescer) -> &mut CoalescingBuffer<K, T>,
+    key: K,
+    value: T,
+) -> Option<T> {
+    let coalescing_state = match handle.try_state::<state::event_coalescing::EventCoalescingState>()
+    {
+        Some(state) => state,
+        None => return Some(value),
+    };
+
+    let mut coalescer = match coalescing_state.inner.lock() {
+        Ok(coalescer) => coalescer,
+        Err(_) => return Some(value),
+    };
+
+    buffer(&mut coalescer).push(key, value, Instant::now())
+}
+
+/// Emits the deferred device and graph updates, either only those whose coalescing
+/// interval has passed or all of them
+fn emit_coalesced_events<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    only_due: bool,
+) -> tauri::Result<()> {
+    let (devices, graphs, geojsons) = {
+        let coalescing_state =
+            match handle.try_state::<state::event_coalescing::EventCoalescingState>() {
+                Some(state) => state,
+                None => return Ok(()),
+            };
+
+        let mut coalescer = match coalescing_state.inner.lock() {
+            Ok(coalescer) => coalescer,
+            Err(_) => return Ok(()),
+        };
+
+        let now = Instant::now();
+
+        if only_due {
+            (
+                coalescer.devices.take_due(now),
+                coalescer.graph.take_due(now),
+                coalescer.graph_geojson.take_due(now),
+            )
+        } else {
+            (
+                coalescer.devices.take_all(now),
+                coalescer.graph.take_all(now),
+                coalescer.graph_geojson.take_all(now),
+            )
+        }
+    };
+
+    for (device_key, device) in devices {
+        emit_updated_device(handle, device_key, device)?;
+    }
+
+    for (device_key, graph) in graphs {
+        emit_updated_graph(handle, device_key, graph)?;
+    }
+
+    for (_, geojson) in geojsons {
+        emit_graph_geojson_update(handle, geojson)?;
+    }
+
+    Ok(())
+}
+
+/// Emits deferred updates whose coalescing interval has passed
+pub fn dispatch_due_coalesced_events<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+) -> tauri::Result<()> {
+    emit_coalesced_events(handle, true)
+}
+
+/// Emits all deferred updates immediately, e.g. once a device finishes configuring
+pub fn flush_coalesced_events<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+) -> tauri::Result<()> {
+    debug!("Flushing coalesced events");
+
+    emit_coalesced_events(handle, false)
+}
+
+/// Emits the device now, or the latest device state once the coalescing interval passes
+pub fn dispatch_updated_device<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: &DeviceKey,
+    device: &device::MeshDevice,
+) -> tauri::Result<()> {
+    match coalesce(
+        handle,
+        |c| &mut c.devices,
+        device_key.clone(),
+        device.clone(),
+    ) {
+        Some(device) => emit_updated_device(handle, device_key.clone(), device),
+        None => {
+            trace!("Deferred updated device");
+            Ok(())
+        }
+    }
+}
+
+fn emit_updated_device<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: DeviceKey,
+    device: device::MeshDevice,
+) -> tauri::Result<()> {
+    debug!("Dispatching updated device");
+
+    let event = DeviceUpdateEvent {
+        api_version: EVENT_API_VERSION,
+        device_key,
+        device,
+    };
+
+    emit_encoded(handle, "device_update", Some(&event.device_key), &event)?;
+
+    trace!("Dispatched updated device");
+
+    Ok(())
+}
+
+pub fn dispatch_configuration_status<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    status: ConfigurationStatus,
+) -> tauri::Result<()> {
+    debug!("Dispatching configuration status");
+
+    emit_scoped(
+        handle,
+        "configuration_status",
+        Some(&status.device_key),
+        &status,
+    )?;
+
+    Ok(())
+}
+
+pub fn dispatch_configuration_progress<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: ConfigurationProgressEvent,
+) -> tauri::Result<()> {
+    trace!(
+        "Dispatching configuration progress, {} ({}%)",
+        event.phase_label,
+        event.percent
+    );
+
+    emit_scoped(
+        handle,
+        "configuration_progress",
+        Some(&event.device_key),
+        &event,
+    )?;
+
+    Ok(())
+}
+
+pub fn dispatch_device_disconnect<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: DeviceKey,
+) -> tauri::Result<()> {
+    // Deferred updates must not arrive after the UI has dropped the device
+    flush_coalesced_events(handle)?;
+
+    // A reconnected device starts over with a full snapshot
+    if let Some(edge_deltas) = handle.try_state::<state::edge_deltas::EdgeDeltasState>() {
+        if let Ok(mut trackers) = edge_deltas.inner.lock() {
+            trackers.remove(&device_key);
+        }
+    }
+
+    debug!("Dispatching device disconnect");
+
+    let event = DeviceDisconnectEvent {
+        api_version: EVENT_API_VERSION,
+        device_key,
+    };
+
+    emit_scoped(handle, "device_disconnect", Some(&event.device_key), &event)?;
+
+    Ok(())
+}
+
+pub fn dispatch_devices_list_changed<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: DeviceKey,
+    change: DevicesListChange,
+    status: SerialDeviceStatus,
+) -> tauri::Result<()> {
+    debug!(
+        "Dispatching devices list change {:?} for \"{}\"",
+        change, device_key
+    );
+
+    let event = DevicesListChangedEvent {
+        api_version: EVENT_API_VERSION,
+        profile: active_profile_name(handle),
+        device_key,
+        change,
+        status,
+    };
+
+    emit_scoped(
+        handle,
+        "devices_list_changed",
+        Some(&event.device_key),
+        &event,
+    )?;
+
+    Ok(())
+}
+
+pub fn dispatch_rebooting_event<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: DeviceKey,
+) -> tauri::Result<()> {
+    debug!("Dispatching rebooting event");
+
+    let event = RebootEvent {
+        api_version: EVENT_API_VERSION,
+        device_key,
+        timestamp: device::helpers::get_current_time_u32(),
+    };
+
+    emit_scoped(handle, "reboot", Some(&event.device_key), &event)?;
+
+    Ok(())
+}
+
+/// Emits the graph now, or the latest graph once the coalescing interval passes.
+/// `device_key` is the device whose packets updated the graph, if any.
+pub fn dispatch_updated_graph<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: Option<DeviceKey>,
+    graph: MeshGraph,
+) -> tauri::Result<()> {
+    match coalesce(handle, |c| &mut c.graph, device_key.clone(), graph) {
+        Some(graph) => emit_updated_graph(handle, device_key, graph),
+        None => {
+            trace!("Deferred updated graph");
+            Ok(())
+        }
+    }
+}
+
+fn emit_updated_graph<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: Option<DeviceKey>,
+    graph: MeshGraph,
+) -> tauri::Result<()> {
+    debug!("Dispatching updated graph");
+
+    check_network_partition(handle, device_key.as_ref(), &graph);
+
+    let event = GraphUpdateEvent {
+        api_version: EVENT_API_VERSION,
+        device_key,
+        graph,
+    };
+
+    emit_scoped(handle, "graph_update", event.device_key.as_ref(), &event)?;
+
+    Ok(())
+}
+
+/// Emits the device's map layers now, or the latest layers once the coalescing
+/// interval passes
+pub fn dispatch_graph_geojson_update<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    geojson: GraphGeoJson,
+) -> tauri::Result<()> {
+    let device_key = geojson.device_key.clone();
+
+    match coalesce(handle, |c| &mut c.graph_geojson, device_key, geojson) {
+        Some(geojson) => emit_graph_geojson_update(handle, geojson),
+        None => {
+            trace!("Deferred graph GeoJSON update");
+            Ok(())
+        }
+    }
+}
+
+/// Sends the map layers as a delta against the last update sent for the device, or
+/// as a full snapshot if the UI has nothing to apply a delta to
+fn emit_graph_geojson_update<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    geojson: GraphGeoJson,
+) -> tauri::Result<()> {
+    let update = handle
+        .try_state::<state::edge_deltas::EdgeDeltasState>()
+        .and_then(|edge_deltas| {
+            let mut trackers = edge_deltas.inner.lock().ok()?;
+            let tracker = trackers.entry(geojson.device_key.clone()).or_default();
+            Some(tracker.next(&geojson.edges))
+        })
+        .unwrap_or(EdgeUpdate::Snapshot { sequence: 0 });
+
+    match update {
+        EdgeUpdate::Snapshot { sequence } => {
+            debug!("Dispatching graph GeoJSON snapshot");
+
+            let event = GraphGeoJsonEvent {
+                api_version: EVENT_API_VERSION,
+                sequence,
+                geojson,
+            };
+
+            emit_encoded(
+                handle,
+                "graph_geojson_update",
+                Some(&event.geojson.device_key),
+                &event,
+            )?;
+        }
+        EdgeUpdate::Delta { sequence, delta } => {
+            debug!(
+                "Dispatching edge delta with {} features",
+                delta.feature_count()
+            );
+
+            let event = EdgesDeltaEvent {
+                api_version: EVENT_API_VERSION,
+                device_key: geojson.device_key,
+                sequence,
+                nodes: geojson.nodes,
+                delta,
+            };
+
+            emit_encoded(
+                handle,
+                "updated_edges_delta",
+                Some(&event.device_key),
+                &event,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends a full snapshot of the device's map layers right away, bypassing the event
+/// coalescer. Later updates are sent as deltas against this snapshot.
+pub fn dispatch_full_edge_snapshot<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    geojson: GraphGeoJson,
+) -> tauri::Result<()> {
+    if let Some(edge_deltas) = handle.try_state::<state::edge_deltas::EdgeDeltasState>() {
+        if let Ok(mut trackers) = edge_deltas.inner.lock() {
+            trackers
+                .entry(geojson.device_key.clone())
+                .or_default()
+                .request_snapshot();
+        }
+    }
+
+    emit_graph_geojson_update(handle, geojson)
+}
+
+pub fn dispatch_radio_queue_throttle_status<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    status: RadioQueueThrottleStatus,
+) -> tauri::Result<()> {
+    debug!("Dispatching radio queue throttle status");
+
+    emit_scoped(
+        handle,
+        "radio_queue_throttle",
+        Some(&status.device_key),
+        &status,
+    )?;
+
+    Ok(())
+}
+
+pub fn dispatch_gpio_changed<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: GpioChangedEvent,
+) -> tauri::Result<()> {
+    debug!("Dispatching GPIO changed event");
+
+    emit_scoped(handle, "gpio_changed", Some(&event.device_key), &event)?;
+
+    Ok(())
+}
+
+pub fn dispatch_clock_skew_detected<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: ClockSkewEvent,
+) -> tauri::Result<()> {
+    debug!("Dispatching clock skew detected event");
+
+    emit_scoped(
+        handle,
+        "clock_skew_detected",
+        Some(&event.device_key),
+        &event,
+    )?;
+
+    Ok(())
+}
+
+pub fn dispatch_node_status_changed<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: NodeStatusChangedEvent,
+) -> tauri::Result<()> {
+    debug!(
+        "Dispatching node {} status change from {:?} to {:?}",
+        event.node_num, event.old_status, event.new_status
+    );
+
+    emit_scoped(
+        handle,
+        "node_status_changed",
+        Some(&event.device_key),
+        &event,
+    )?;
+
+    Ok(())
+}
+
+pub fn dispatch_notification_alert<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: Option<DeviceKey>,
+    alert: RuleAlert,
+) -> tauri::Result<()> {
+    debug!("Dispatching notification alert");
+
+    let event = NotificationAlertEvent {
+        api_version: EVENT_API_VERSION,
+        device_key,
+        alert,
+    };
+
+    emit_scoped(
+        handle,
+        "notification_alert",
+        event.device_key.as_ref(),
+        &event,
+    )?;
+
+    Ok(())
+}
+
+pub fn dispatch_settings_changed<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    settings: AppSettings,
+) -> tauri::Result<()> {
+    debug!("Dispatching settings changed event");
+
+    let event = SettingsChangedEvent {
+        api_version: EVENT_API_VERSION,
+        profile: active_profile_name(handle),
+        settings,
+    };
+
+    emit_scoped(handle, "settings_changed", None, &event)?;
+
+    Ok(())
+}
+
+pub fn dispatch_profile_changed<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    profiles: Profiles,
+) -> tauri::Result<()> {
+    debug!(
+        "Dispatching profile changed event for \"{}\"",
+        profiles.active
+    );
+
+    let event = ProfileChangedEvent {
+        api_version: EVENT_API_VERSION,
+        profile: profiles.active,
+        profiles: profiles.profiles,
+    };
+
+    emit_scoped(handle, "profile_changed", None, &event)?;
+
+    Ok(())
+}
+
+pub fn dispatch_geofence_transition<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: GeofenceTransitionEvent,
+) -> tauri::Result<()> {
+    debug!("Dispatching geofence transition");
+
+    let event_name = match event.transition.kind {
+        GeofenceTransitionKind::Violation => "geofence_violation",
+        GeofenceTransitionKind::Entry => "geofence_entry",
+    };
+
+    emit_scoped(handle, event_name, Some(&event.device_key), &event)?;
+
+    Ok(())
+}
+
+pub fn dispatch_replay_status<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: DeviceKey,
+    status: ReplayStatus,
+) -> tauri::Result<()> {
+    trace!("Dispatching replay status");
+
+    let event = ReplayStatusEvent {
+        api_version: EVENT_API_VERSION,
+        device_key,
+        status,
+    };
+
+    emit_scoped(handle, "replay_status", Some(&event.device_key), &event)?;
+
+    Ok(())
+}
+
+pub fn dispatch_device_log<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: DeviceLogEvent,
+) -> tauri::Result<()> {
+    trace!("Dispatching device log event");
+
+    emit_scoped(handle, "device_log", Some(&event.device_key), &event)?;
+
+    Ok(())
+}
+
+pub fn dispatch_debug_packet<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: DebugPacketEvent,
+) -> tauri::Result<()> {
+    trace!("Dispatching debug packet event");
+
+    emit_scoped(handle, "debug_packet", Some(&event.device_key), &event)?;
+
+    Ok(())
+}
+
+pub fn dispatch_connection_metrics_updated<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: ConnectionMetricsEvent,
+) -> tauri::Result<()> {
+    trace!("Dispatching connection metrics updated event");
+
+    emit_scoped(
+        handle,
+        "connection_metrics_updated",
+        Some(&event.device_key),
+        &event,
+    )?;
+
+    Ok(())
+}
+
+pub fn dispatch_app_error<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: AppErrorEvent,
+) -> tauri::Result<()> {
+    debug!("Dispatching app error {:?}", event.error.code);
+
+    emit_scoped(handle, "app_error", event.device_key.as_ref(), &event)?;
+
+    Ok(())
+}
+
+pub fn dispatch_deep_link<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: DeepLinkEvent,
+) -> tauri::Result<()> {
+    debug!("Dispatching deep link {}", event.url);
+
+    emit_scoped(handle, "deep_link_received", None, &event)?;
+
+    Ok(())
+}
+
+pub fn dispatch_operation_progress<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: OperationProgressEvent,
+) -> tauri::Result<()> {
+    trace!(
+        "Dispatching operation {} progress {}/{}",
+        event.operation_id,
+        event.done,
+        event.total
+    );
+
+    emit_scoped(
+        handle,
+        "operation_progress",
+        Some(&event.device_key),
+        &event,
+    )?;
+
+    Ok(())
+}
+
+pub fn dispatch_operation_completed<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: OperationFinishedEvent,
+) -> tauri::Result<()> {
+    debug!("Dispatching operation {} completed", event.operation_id);
+
+    emit_scoped(
+        handle,
+        "operation_completed",
+        Some(&event.device_key),
+        &event,
+    )?;
+
+    Ok(())
+}
+
+pub fn dispatch_operation_failed<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: OperationFinishedEvent,
+) -> tauri::Result<()> {
+    debug!("Dispatching operation {} failed", event.operation_id);
+
+    emit_scoped(handle, "operation_failed", Some(&event.device_key), &event)?;
+
+    Ok(())
+}
+
+pub fn dispatch_unsent_messages<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: UnsentMessagesEvent,
+) -> tauri::Result<()> {
+    debug!(
+        "Dispatching {} unsent messages of device \"{}\"",
+        event.messages.len(),
+        event.device_key
+    );
+
+    emit_scoped(handle, "unsent_messages", Some(&event.device_key), &event)?;
+
+    Ok(())
+}
+
+pub fn dispatch_network_partition<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: NetworkPartitionEvent,
+) -> tauri::Result<()> {
+    debug!(
+        "Dispatching network partition into {} components",
+        event.component_count
+    );
+
+    emit_scoped(
+        handle,
+        "network_partition",
+        event.device_key.as_ref(),
+        &event,
+    )?;
+
+    Ok(())
+}
+
+pub fn dispatch_node_db_reconciled<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: NodeDbReconciledEvent,
+) -> tauri::Result<()> {
+    debug!(
+        "Dispatching node database reconciled for \"{}\"",
+        event.device_key
+    );
+
+    emit_scoped(
+        handle,
+        "node_db_reconciled",
+        Some(&event.device_key),
+        &event,
+    )?;
+
+    Ok(())
+}
+
+/// Emits a scheduled analytics update in the encoding set with `set_ipc_encoding`, as
+/// centralities of a large mesh make for a large payload
+pub fn dispatch_analytics_update<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: AnalyticsUpdateEvent,
+) -> tauri::Result<()> {
+    debug!("Dispatching analytics update for \"{}\"", event.device_key);
+
+    emit_encoded(handle, "analytics_update", Some(&event.device_key), &event)?;
+
+    Ok(())
+}
+
+pub fn dispatch_message_state<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: MessageStateEvent,
+) -> tauri::Result<()> {
+    debug!(
+        "Dispatching state {:?} of message {} on channel {} of \"{}\"",
+        event.state, event.message_id, event.channel, event.device_key
+    );
+
+    emit_scoped(handle, "message_state", Some(&event.device_key), &event)?;
+
+    Ok(())
+}
+
+pub fn dispatch_traceroute_result<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: TracerouteResultEvent,
+) -> tauri::Result<()> {
+    debug!(
+        "Dispatching traceroute {} to node {} of \"{}\": {:?}",
+        event.request_id, event.destination, event.device_key, event.route
+    );
+
+    emit_scoped(handle, "traceroute_result", Some(&event.device_key), &event)?;
+
+    Ok(())
+}
+
+pub fn dispatch_remote_admin_result<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: RemoteAdminResultEvent,
+) -> tauri::Result<()> {
+    debug!(
+        "Dispatching admin request {} to node {} of \"{}\": {:?}",
+        event.request_id, event.node_num, event.device_key, event.outcome
+    );
+
+    emit_scoped(
+        handle,
+        "remote_admin_result",
+        Some(&event.device_key),
+        &event,
+    )?;
+
+    Ok(())
+}
+
+pub fn dispatch_unread_counts_changed<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: UnreadCountsChangedEvent,
+) -> tauri::Result<()> {
+    debug!(
+        "Dispatching unread counts of {} conversations of \"{}\"",
+        event.conversations.len(),
+        event.device_key
+    );
+
+    emit_scoped(
+        handle,
+        "unread_counts_changed",
+        Some(&event.device_key),
+        &event,
+    )?;
+
+    Ok(())
+}
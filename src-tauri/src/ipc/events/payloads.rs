@@ -0,0 +1,478 @@
+//! Payloads of every event emitted to the UI. Payloads deriving `Type` are exported
+//! to the frontend bindings along with the rest of the IPC types. Payloads of events
+//! about a connection carry its `device_key`, which is `None` for app-wide events.
+
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::connection::metrics::ConnectionMetrics;
+use crate::deep_link::DeepLink;
+use crate::device::{
+    config_progress::ConfigurationProgress,
+    liveness::{NodeLiveness, NodeLivenessTransition},
+    logs::DeviceLogEntry,
+    message_store::{ConversationSummary, OutgoingMessage},
+    node_db::ReconcileReport,
+    remote_hardware::GpioReading,
+    ChannelMessageState, MeshDevice, SerialDeviceStatus,
+};
+use crate::export::analytics_report::AnalyticsReport;
+use crate::graph::{ds::graph::MeshGraph, edge_delta::EdgeDelta, geojson::GraphGeoJson};
+use crate::ipc::error_reporter::AppError;
+use crate::notifications::{geofences::GeofenceTransition, rules::RuleAlert};
+use crate::packet_api::debug_stream::DebugPacket;
+use crate::packet_api::operations::{OperationId, OperationItemResult, OperationKind};
+use crate::packet_api::remote_admin::RemoteAdminOutcome;
+use crate::profiles::Profile;
+use crate::replay::ReplayStatus;
+use crate::settings::AppSettings;
+use crate::state::DeviceKey;
+
+/// Version of the event payload format, bump when making a breaking change to any payload
+pub const EVENT_API_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceUpdateEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub device: MeshDevice,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceDisconnectEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct RebootEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub timestamp: u32, // secs
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum DevicesListChange {
+    Added,
+    Removed,
+    StatusChanged,
+}
+
+/// Signals the UI to refresh its list of connected devices
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct DevicesListChangedEvent {
+    pub api_version: u32,
+    pub profile: String, // profile the device was connected under
+    pub device_key: DeviceKey,
+    pub change: DevicesListChange,
+    pub status: SerialDeviceStatus,
+}
+
+/// The graph isn't exported to the bindings, its type is defined in the frontend
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphUpdateEvent {
+    pub api_version: u32,
+    pub device_key: Option<DeviceKey>, // not set when updated for all devices, e.g. when cleaned
+    pub graph: MeshGraph,
+}
+
+/// Full snapshot of a device's map layers. GeoJSON types aren't exported to the bindings.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphGeoJsonEvent {
+    pub api_version: u32,
+    pub sequence: u32, // shared with `EdgesDeltaEvent`, deltas apply on top of this snapshot
+    #[serde(flatten)]
+    pub geojson: GraphGeoJson,
+}
+
+/// Edge changes since the previous `EdgesDeltaEvent` or `GraphGeoJsonEvent` for the
+/// device, along with all node features. A delta whose sequence number doesn't follow
+/// the last one received means an update was missed and a full snapshot is needed.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EdgesDeltaEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub sequence: u32,
+    pub nodes: geojson::FeatureCollection,
+    #[serde(flatten)]
+    pub delta: EdgeDelta,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigurationStatus {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub successful: bool,
+    pub message: Option<String>,
+}
+
+/// Emitted as the configuration flow moves along, before the final `ConfigurationStatus`
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigurationProgressEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub phase_label: String,
+    pub percent: u8,
+    pub progress: ConfigurationProgress,
+}
+
+impl ConfigurationProgressEvent {
+    pub fn new(device_key: DeviceKey, progress: ConfigurationProgress) -> Self {
+        Self {
+            api_version: EVENT_API_VERSION,
+            device_key,
+            phase_label: progress.phase.label().into(),
+            percent: progress.percent,
+            progress,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct RadioQueueThrottleStatus {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub throttled: bool,
+    pub free: u32,
+    pub maxlen: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct GpioChangedEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub reading: GpioReading,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ClockSkewEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub skew_secs: i64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeStatusChangedEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub node_num: u32,
+    pub old_status: NodeLiveness,
+    pub new_status: NodeLiveness,
+    pub last_heard: u32, // secs
+}
+
+impl NodeStatusChangedEvent {
+    pub fn new(device_key: DeviceKey, transition: NodeLivenessTransition) -> Self {
+        Self {
+            api_version: EVENT_API_VERSION,
+            device_key,
+            node_num: transition.node_num,
+            old_status: transition.old_status,
+            new_status: transition.new_status,
+            last_heard: transition.last_heard,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationAlertEvent {
+    pub api_version: u32,
+    pub device_key: Option<DeviceKey>,
+    pub alert: RuleAlert,
+}
+
+/// Emitted as `settings_changed` with the full settings after any of them change
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsChangedEvent {
+    pub api_version: u32,
+    pub profile: String, // profile the settings belong to
+    pub settings: AppSettings,
+}
+
+/// Emitted as `profile_changed` once another profile's state has been loaded, and when
+/// profiles are created or deleted
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileChangedEvent {
+    pub api_version: u32,
+    pub profile: String, // active profile
+    pub profiles: Vec<Profile>,
+}
+
+/// Emitted as `geofence_violation` when a node leaves a geofence, or `geofence_entry`
+/// when it enters one
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct GeofenceTransitionEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub transition: GeofenceTransition,
+}
+
+/// Emitted as `replay_status` while a capture is replayed
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayStatusEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub status: ReplayStatus,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AppErrorEvent {
+    pub api_version: u32,
+    pub device_key: Option<DeviceKey>,
+    pub error: AppError,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceLogEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub entry: DeviceLogEntry,
+}
+
+/// Packet streamed to the protocol console. `dropped_count` is the number of packets
+/// dropped by the stream's rate limit since the previous one was streamed.
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct DebugPacketEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub packet: DebugPacket,
+    pub dropped_count: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionMetricsEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub metrics: ConnectionMetrics,
+}
+
+/// Link the app was opened with, for the UI to focus a node or confirm a channel import
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct DeepLinkEvent {
+    pub api_version: u32,
+    pub url: String,
+    pub link: DeepLink,
+}
+
+/// Emitted as each item of a bulk outgoing operation is sent
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationProgressEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub operation_id: OperationId,
+    pub kind: OperationKind,
+    pub done: u32,
+    pub total: u32,
+    pub item: OperationItemResult,
+}
+
+/// Emitted as `operation_completed` or `operation_failed` once every item is sent,
+/// failed or skipped because the operation was cancelled
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationFinishedEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub operation_id: OperationId,
+    pub kind: OperationKind,
+    pub results: Vec<OperationItemResult>,
+}
+
+/// Outgoing messages a device was cut off from sending by a crash or disconnect, for
+/// the UI to offer resending or discarding them once it reconnects
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct UnsentMessagesEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub messages: Vec<OutgoingMessage>,
+}
+
+/// Emitted as a message the device sent waits for its ack, and once it's acked, the
+/// radio reports it failed or it times out
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageStateEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub channel: u32,
+    pub message_id: u32, // packet id
+    pub state: ChannelMessageState,
+}
+
+/// Emitted once a traceroute's response arrives, or it times out without one
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TracerouteResultEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub request_id: u32,
+    pub destination: u32,
+    pub route: Vec<u32>, // node nums from the device's node to the destination, empty if failed
+    pub successful: bool,
+    pub message: Option<String>, // why it failed
+    pub requested_at: u32,       // secs
+}
+
+/// Emitted once an admin request sent to another node gets its result, or times out
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteAdminResultEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub request_id: u32,
+    pub node_num: u32,
+    pub outcome: RemoteAdminOutcome,
+}
+
+/// Unread counts of a device's conversations, emitted when new messages are stored or
+/// a conversation is marked read
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct UnreadCountsChangedEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub conversations: Vec<ConversationSummary>,
+}
+
+/// Emitted once a radio's node database has been reconciled with the nodes stored for it
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeDbReconciledEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey,
+    pub report: ReconcileReport,
+}
+
+/// Emitted when the mesh splits into groups of nodes that can't reach each other, or
+/// changes how many groups it's split into, and once it's whole again
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkPartitionEvent {
+    pub api_version: u32,
+    pub device_key: Option<DeviceKey>, // device whose packets changed the graph, if any
+    pub component_count: u32,
+    pub components: Vec<Vec<u32>>, // node nums of each group, largest first
+    pub timestamp: u32,            // secs
+}
+
+/// Emitted as `analytics_update` with each scheduled analytics update, which only has
+/// the sections selected in the settings
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyticsUpdateEvent {
+    pub api_version: u32,
+    pub device_key: DeviceKey, // device the report was generated from
+    pub report: AnalyticsReport,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use specta::ts::{self, BigIntExportBehavior, ExportConfiguration, ModuleExportBehavior};
+
+    use super::*;
+
+    /// Checked in next to this module so that changes to the shape of any payload show
+    /// up in review. Set `UPDATE_EVENT_SNAPSHOT=1` to regenerate after an intentional
+    /// change, remembering to bump `EVENT_API_VERSION` if the change is breaking.
+    fn snapshot_path() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/ipc/events/payloads.ts.snap")
+    }
+
+    fn generate_payload_types() -> String {
+        // Matches the configuration used to export the frontend bindings
+        let config = ExportConfiguration::default()
+            .bigint(BigIntExportBehavior::String)
+            .modules(ModuleExportBehavior::Enabled);
+
+        let types = [
+            ts::export::<DeviceUpdateEvent>(&config),
+            ts::export::<DeviceDisconnectEvent>(&config),
+            ts::export::<RebootEvent>(&config),
+            ts::export::<DevicesListChangedEvent>(&config),
+            ts::export::<ConfigurationStatus>(&config),
+            ts::export::<ConfigurationProgressEvent>(&config),
+            ts::export::<RadioQueueThrottleStatus>(&config),
+            ts::export::<GpioChangedEvent>(&config),
+            ts::export::<ClockSkewEvent>(&config),
+            ts::export::<NodeStatusChangedEvent>(&config),
+            ts::export::<NotificationAlertEvent>(&config),
+            ts::export::<SettingsChangedEvent>(&config),
+            ts::export::<ProfileChangedEvent>(&config),
+            ts::export::<GeofenceTransitionEvent>(&config),
+            ts::export::<ReplayStatusEvent>(&config),
+            ts::export::<AppErrorEvent>(&config),
+            ts::export::<DeviceLogEvent>(&config),
+            ts::export::<DebugPacketEvent>(&config),
+            ts::export::<ConnectionMetricsEvent>(&config),
+            ts::export::<DeepLinkEvent>(&config),
+            ts::export::<OperationProgressEvent>(&config),
+            ts::export::<OperationFinishedEvent>(&config),
+            ts::export::<UnsentMessagesEvent>(&config),
+            ts::export::<NodeDbReconciledEvent>(&config),
+            ts::export::<MessageStateEvent>(&config),
+            ts::export::<TracerouteResultEvent>(&config),
+            ts::export::<RemoteAdminResultEvent>(&config),
+            ts::export::<UnreadCountsChangedEvent>(&config),
+            ts::export::<NetworkPartitionEvent>(&config),
+            ts::export::<AnalyticsUpdateEvent>(&config),
+        ];
+
+        let mut generated = format!("// EVENT_API_VERSION = {}\n", EVENT_API_VERSION);
+
+        for exported in types {
+            generated.push_str(&exported.expect("Failed to export payload type"));
+            generated.push('\n');
+        }
+
+        generated
+    }
+
+    #[test]
+    fn payload_types_match_snapshot() {
+        let generated = generate_payload_types();
+        let path = snapshot_path();
+
+        if std::env::var_os("UPDATE_EVENT_SNAPSHOT").is_some() || !path.exists() {
+            std::fs::write(&path, &generated).unwrap();
+            return;
+        }
+
+        let snapshot = std::fs::read_to_string(&path).unwrap();
+
+        assert_eq!(
+            snapshot,
+            generated,
+            "Event payload types have drifted from {}, rerun with UPDATE_EVENT_SNAPSHOT=1 \
+             if the change is intentional",
+            path.display()
+        );
+    }
+}
@@ -0,0 +1,275 @@
+use std::cell::RefCell;
+
+use log::trace;
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use tauri::Manager;
+
+use crate::state::{self, DeviceKey};
+
+/// Which event channels the UI listens to. Every event is emitted on its global
+/// channel (e.g. `device_update`), and events about a connection are also emitted on
+/// a channel suffixed with the connection's sanitized key (e.g. `device_update:COM3`).
+/// Channels nobody listens to are skipped without serializing the payload.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct EventScopes {
+    pub global: bool,
+    pub devices: Vec<DeviceKey>,
+}
+
+impl Default for EventScopes {
+    /// Single-device frontends only listen to the global channels
+    fn default() -> Self {
+        Self {
+            global: true,
+            devices: vec![],
+        }
+    }
+}
+
+impl EventScopes {
+    /// Channels an event should be emitted on. Events that don't belong to a
+    /// connection only have a global channel, so they're always emitted on it.
+    pub fn channels(&self, event: &str, device_key: Option<&DeviceKey>) -> Vec<String> {
+        let device_key = match device_key {
+            Some(device_key) => device_key,
+            None => return vec![event.into()],
+        };
+
+        let mut channels = vec![];
+
+        if self.global {
+            channels.push(event.into());
+        }
+
+        if self.devices.contains(device_key) {
+            channels.push(scoped_event_name(event, device_key));
+        }
+
+        channels
+    }
+}
+
+/// Event names may only contain alphanumerics, `-`, `/`, `:` and `_`, so every other
+/// character in the key (e.g. the `.` in an address) is replaced with `_`. The `/`
+/// and `:` in port names and addresses are replaced too, leaving `:` as the separator.
+pub fn sanitize_event_scope(device_key: &str) -> String {
+    device_key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Payload buffers grown past this aren't kept for the next event, so one large graph
+/// doesn't stay allocated for the rest of the session
+const MAX_RETAINED_PAYLOAD_LEN: usize = 256 * 1024;
+
+thread_local! {
+    /// Serialized payloads, reused between the events emitted on a thread
+    static PAYLOAD_BUFFER: RefCell<Vec<u8>> = RefCell::new(vec![]);
+}
+
+/// Serializes `payload` into this thread's reused buffer and passes it to `emit` as
+/// raw JSON, which is written out as is rather than built up as a `Value` first
+pub fn with_raw_payload<S: Serialize, T>(
+    payload: &S,
+    emit: impl FnOnce(&RawValue) -> tauri::Result<T>,
+) -> tauri::Result<T> {
+    // Taken rather than borrowed, in case emitting an event emits another
+    let mut buffer = PAYLOAD_BUFFER.with(|buffer| buffer.take());
+    buffer.clear();
+
+    let emitted = match serde_json::to_writer(&mut buffer, payload) {
+        Ok(()) => serde_json::from_slice(&buffer)
+            .map_err(tauri::Error::from)
+            .and_then(emit),
+        Err(e) => Err(e.into()),
+    };
+
+    if buffer.capacity() <= MAX_RETAINED_PAYLOAD_LEN {
+        PAYLOAD_BUFFER.with(|retained| retained.replace(buffer));
+    }
+
+    emitted
+}
+
+pub fn scoped_event_name(event: &str, device_key: &str) -> String {
+    format!("{}:{}", event, sanitize_event_scope(device_key))
+}
+
+/// Channels the UI listens to for an event, per the managed `EventScopesState`
+pub fn event_channels<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    event: &str,
+    device_key: Option<&DeviceKey>,
+) -> Vec<String> {
+    match handle.try_state::<state::event_scopes::EventScopesState>() {
+        Some(event_scopes) => match event_scopes.inner.lock() {
+            Ok(scopes) => scopes.channels(event, device_key),
+            Err(_) => EventScopes::default().channels(event, device_key),
+        },
+        None => EventScopes::default().channels(event, device_key),
+    }
+}
+
+/// Serializes `payload` once and emits it on each of the channels
+pub fn emit_on_channels<R: tauri::Runtime, S: Serialize>(
+    handle: &tauri::AppHandle<R>,
+    channels: &[String],
+    payload: &S,
+) -> tauri::Result<()> {
+    with_raw_payload(payload, |payload| {
+        for channel in channels.iter() {
+            handle.emit_all(channel, payload)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Emits `payload` on the channels the UI listens to, returning them. The payload is
+/// serialized once, and not at all if nobody is listening.
+pub fn emit_scoped<R: tauri::Runtime, S: Serialize>(
+    handle: &tauri::AppHandle<R>,
+    event: &str,
+    device_key: Option<&DeviceKey>,
+    payload: &S,
+) -> tauri::Result<Vec<String>> {
+    let channels = event_channels(handle, event, device_key);
+
+    if channels.is_empty() {
+        trace!("Skipped \"{}\" event with no listeners", event);
+        return Ok(channels);
+    }
+
+    emit_on_channels(handle, &channels, payload)?;
+
+    Ok(channels)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use serde::Serializer;
+
+    use super::*;
+    use crate::device::MeshDevice;
+    use crate::graph::ds::graph::MeshGraph;
+    use crate::ipc::events::payloads::DeviceUpdateEvent;
+    use crate::ipc::EVENT_API_VERSION;
+    use crate::packet_api::MeshPacketApi;
+    use crate::state::event_scopes::EventScopesState;
+    use crate::state::graph::SharedGraph;
+
+    /// Counts how many times the payload is serialized
+    struct CountedPayload<'a> {
+        serialized: &'a AtomicUsize,
+        event: DeviceUpdateEvent,
+    }
+
+    impl Serialize for CountedPayload<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            self.serialized.fetch_add(1, Ordering::SeqCst);
+            self.event.serialize(serializer)
+        }
+    }
+
+    fn device_update<R: tauri::Runtime>(packet_api: &MeshPacketApi<R>) -> DeviceUpdateEvent {
+        DeviceUpdateEvent {
+            api_version: EVENT_API_VERSION,
+            device_key: packet_api.device_key.clone(),
+            device: packet_api.device.clone(),
+        }
+    }
+
+    #[test]
+    fn sanitizes_connection_keys() {
+        assert_eq!(
+            scoped_event_name("device_update", "/dev/tty.usbserial-0001"),
+            "device_update:_dev_tty_usbserial-0001"
+        );
+        assert_eq!(
+            scoped_event_name("device_update", "192.168.1.20:4403"),
+            "device_update:192_168_1_20_4403"
+        );
+    }
+
+    #[test]
+    fn defaults_to_global_channels_only() {
+        let scopes = EventScopes::default();
+        let key: DeviceKey = "COM3".into();
+
+        assert_eq!(
+            scopes.channels("device_update", Some(&key)),
+            vec!["device_update"]
+        );
+        assert_eq!(
+            scopes.channels("deep_link_received", None),
+            vec!["deep_link_received"]
+        );
+    }
+
+    #[test]
+    fn scopes_events_of_two_simulated_devices() {
+        let app = tauri::test::mock_app();
+        let graph = Arc::new(SharedGraph::new(MeshGraph::new()));
+
+        let device_a = MeshPacketApi::new(
+            app.handle(),
+            "sim-a".into(),
+            MeshDevice::new(),
+            graph.clone(),
+        );
+        let device_b = MeshPacketApi::new(app.handle(), "sim-b".into(), MeshDevice::new(), graph);
+
+        let event_scopes = EventScopesState::new();
+        *event_scopes.inner.lock().unwrap() = EventScopes {
+            global: true,
+            devices: vec!["sim-a".into()],
+        };
+        app.manage(event_scopes);
+
+        let handle = app.handle();
+        let serialized = AtomicUsize::new(0);
+
+        let emit = |packet_api: &MeshPacketApi<_>| {
+            emit_scoped(
+                &handle,
+                "device_update",
+                Some(&packet_api.device_key),
+                &CountedPayload {
+                    serialized: &serialized,
+                    event: device_update(packet_api),
+                },
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            emit(&device_a),
+            vec!["device_update", "device_update:sim-a"]
+        );
+        assert_eq!(emit(&device_b), vec!["device_update"]);
+        assert_eq!(serialized.load(Ordering::SeqCst), 2);
+
+        // A multi-device UI that only follows device A skips device B entirely
+        *app.state::<EventScopesState>().inner.lock().unwrap() = EventScopes {
+            global: false,
+            devices: vec!["sim-a".into()],
+        };
+
+        assert_eq!(emit(&device_a), vec!["device_update:sim-a"]);
+        assert!(emit(&device_b).is_empty());
+        assert_eq!(serialized.load(Ordering::SeqCst), 3);
+    }
+}
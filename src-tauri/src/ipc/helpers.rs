@@ -1,61 +1,102 @@
 use std::time::Duration;
 
-use log::{trace, warn};
-use meshtastic::packet::PacketRouter;
+use log::{debug, info, trace, warn};
+use meshtastic::packet::PacketDestination;
 use meshtastic::protobufs;
+use tauri::Manager;
 use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
 
-use crate::device::SerialDeviceStatus;
-use crate::ipc::events::dispatch_configuration_status;
-use crate::ipc::ConfigurationStatus;
+use crate::connection::device_lost::handle_device_lost;
+use crate::connection::metrics::CONNECTION_METRICS_INTERVAL;
+use crate::connection::recent::{auto_connect_with, AutoConnectPolicy, RecentDevice};
+use crate::device::clock::build_time_sync_position;
+use crate::device::fixed_position::FixedPosition;
+use crate::device::helpers::get_current_time_u32;
+use crate::device::logs::DeviceLogEntry;
+use crate::device::message_store::{
+    journal_outgoing_message, release_outgoing_message, OutgoingMessage,
+};
+use crate::device::remote_hardware::GpioReading;
+use crate::device::{MeshDevice, SerialDeviceStatus};
+use crate::graph::ds::graph::MeshGraph;
+use crate::graph::geojson::GraphGeoJson;
+use crate::ipc::commands::connections::{connect_serial, connect_tcp};
+use crate::ipc::error_reporter::{AppErrorCode, ErrorReporter};
+use crate::ipc::events::coalesce::EVENT_COALESCING_TICK;
+use crate::ipc::events::{
+    dispatch_configuration_status, dispatch_connection_metrics_updated, dispatch_device_log,
+    dispatch_devices_list_changed, dispatch_due_coalesced_events, dispatch_graph_geojson_update,
+    dispatch_traceroute_result, dispatch_updated_device, dispatch_updated_graph,
+};
+use crate::ipc::{
+    CommandError, ConfigurationStatus, ConnectionMetricsEvent, DeviceLogEvent, DevicesListChange,
+    TracerouteResultEvent, EVENT_API_VERSION,
+};
+use crate::packet_api::actor::{DeviceActor, DeviceExit, DeviceHandle, OutgoingText};
+use crate::packet_api::radio_write::RadioWrite;
+use crate::packet_api::remote_admin::RemoteAdminOutcome;
+use crate::packet_api::summary::ConnectionType;
+use crate::persistence::{save_json, GRAPH_OVERRIDES_FILE_NAME};
+use crate::shutdown::shutdown_signal;
+use crate::state::mesh_devices::{all_devices, get_device, get_device_handle};
 use crate::state::{self, DeviceKey};
 
-pub fn spawn_configuration_timeout_handler(
-    handle: tauri::AppHandle,
-    connected_devices_inner: state::mesh_devices::MeshDevicesStateInner,
+/// How long commands wait for the radio to answer an admin request
+pub const DEFAULT_ADMIN_RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const DEVICE_STATE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Longest text the firmware can send in one packet, in bytes of UTF-8
+pub const MAX_TEXT_MESSAGE_BYTES: usize = protobufs::Constants::DataPayloadLen as usize;
+
+/// Fails the device's configuration attempt started with `config_id` if it hasn't
+/// completed within `timeout`. Holds this connection's device rather than looking it up
+/// by key, so it stops as soon as the device is disconnected and never times out a later
+/// connection to the same port, or a later attempt to configure this one.
+pub fn spawn_configuration_timeout_handler<R: tauri::Runtime>(
+    handle: tauri::AppHandle<R>,
+    device: DeviceHandle<R>,
     device_key: DeviceKey,
+    config_id: u32,
     timeout: Duration,
-) {
+) -> tauri::async_runtime::JoinHandle<()> {
     trace!("Spawning device configuration timeout");
 
     tauri::async_runtime::spawn(async move {
-        // Wait for device to configure
-        tokio::time::sleep(timeout).await;
-
-        trace!("Device configuration timeout completed");
+        let shutdown = shutdown_signal(&handle);
 
-        let mut devices_guard = connected_devices_inner.lock().await;
-        let packet_api = match devices_guard
-            .get_mut(&device_key)
-            .ok_or("Device not initialized")
-        {
-            Ok(d) => d,
-            Err(e) => {
-                warn!("{}", e);
+        // Wait for device to configure
+        tokio::select! {
+            _ = tokio::time::sleep(timeout) => {}
+            _ = device.stopped() => {
+                trace!("Device \"{}\" disconnected before configuration timeout", device_key);
                 return;
             }
-        };
-
-        // If the device is not registered as configuring, take no action
-        // since this means the device configuration has succeeded
-
-        if packet_api.device.status != SerialDeviceStatus::Configuring {
-            return;
+            _ = shutdown.cancelled() => return,
         }
 
+        trace!("Device configuration timeout completed");
+
         // If device hasn't completed configuration in allotted time,
-        // tell the UI layer that the configuration failed
+        // tell the UI layer that the configuration failed. A device that has
+        // configured needs no action.
 
-        warn!("Device configuration timed out, telling UI to disconnect device");
+        let message = match device.time_out_configuration_attempt(config_id).await {
+            Ok(Some(message)) => message,
+            Ok(None) | Err(_) => return,
+        };
+
+        warn!("{}, telling UI to disconnect device", message);
 
         dispatch_configuration_status(
             &handle,
             ConfigurationStatus {
+                api_version: EVENT_API_VERSION,
                 device_key,
                 successful: false,
-                message: Some(
-                    "Configuration timed out. Are you sure this is a Meshtastic device?".into(),
-                ),
+                message: Some(message),
             },
         )
         .expect("Failed to dispatch configuration status");
@@ -64,34 +105,1129 @@ pub fn spawn_configuration_timeout_handler(
     });
 }
 
+/// Runs a connected device's task, cleaning up after the device if its connection
+/// is lost or its radio stops answering, rather than it being dropped by the user,
+/// and then trying to reconnect to it
 pub fn spawn_decoded_handler(
-    mut decoded_listener: UnboundedReceiver<protobufs::FromRadio>,
+    handle: tauri::AppHandle,
+    decoded_listener: UnboundedReceiver<protobufs::FromRadio>,
+    device: DeviceActor,
     connected_devices_arc: state::mesh_devices::MeshDevicesStateInner,
+    radio_connections_arc: state::radio_connections::RadioConnectionsStateInner,
+    device_key: DeviceKey,
+) {
+    tauri::async_runtime::spawn(async move {
+        match device.run(Some(decoded_listener)).await {
+            DeviceExit::ConnectionLost | DeviceExit::Unresponsive => {}
+            DeviceExit::Disconnected => return,
+        }
+
+        let reconnect = reconnect_target(&handle, &device_key);
+
+        let handled = handle_device_lost(
+            handle.clone(),
+            connected_devices_arc,
+            radio_connections_arc,
+            device_key,
+            reconnect.is_some(),
+        )
+        .await;
+
+        if let (true, Some((device, max_attempts))) = (handled, reconnect) {
+            spawn_device_reconnect(handle, device, max_attempts);
+        }
+    });
+}
+
+/// The connection a lost device can be reconnected with and the attempts to make, if
+/// reconnecting is turned on
+fn reconnect_target(
+    handle: &tauri::AppHandle,
+    device_key: &DeviceKey,
+) -> Option<(RecentDevice, u32)> {
+    let max_attempts = handle
+        .state::<state::settings::SettingsState>()
+        .inner
+        .lock()
+        .ok()?
+        .connections
+        .reconnect_attempts;
+
+    if max_attempts == 0 {
+        return None;
+    }
+
+    let device = handle
+        .state::<state::recent_devices::RecentDevicesState>()
+        .inner
+        .lock()
+        .ok()?
+        .devices
+        .iter()
+        .find(|d| &d.port == device_key)
+        .cloned()?;
+
+    match device.connection_type {
+        ConnectionType::Serial | ConnectionType::Tcp => Some((device, max_attempts)),
+        _ => None,
+    }
+}
+
+/// Reconnects to a lost serial port once the OS lists it again
+async fn reconnect_device(handle: tauri::AppHandle, device: RecentDevice) -> Result<(), String> {
+    if device.connection_type == ConnectionType::Serial {
+        let listed = tokio_serial::available_ports()
+            .map_err(|e| e.to_string())?
+            .iter()
+            .any(|port| port.port_name == device.port);
+
+        if !listed {
+            return Err(format!("\"{}\" isn't plugged in", device.port));
+        }
+    }
+
+    connect_recent_device(handle, device).await
+}
+
+/// Retries a lost device's connection with backoff, until it's configured again or
+/// `max_attempts` have failed. Dropping the device cancels the attempts.
+fn spawn_device_reconnect(handle: tauri::AppHandle, device: RecentDevice, max_attempts: u32) {
+    trace!("Spawning reconnect to \"{}\"", device.port);
+
+    let cancel = CancellationToken::new();
+
+    match handle
+        .state::<state::reconnects::ReconnectsState>()
+        .inner
+        .lock()
+    {
+        Ok(mut reconnects) => {
+            if let Some(previous) = reconnects.insert(device.port.clone(), cancel.clone()) {
+                previous.cancel();
+            }
+        }
+        Err(e) => warn!("Failed to lock pending reconnects: {}", e),
+    }
+
+    tauri::async_runtime::spawn(async move {
+        let shutdown = shutdown_signal(&handle);
+        let policy = AutoConnectPolicy::reconnect(max_attempts);
+
+        let result = tokio::select! {
+            result = auto_connect_with(&device, policy, &cancel, |device| {
+                reconnect_device(handle.clone(), device)
+            }) => result,
+            _ = shutdown.cancelled() => return,
+        };
+
+        if cancel.is_cancelled() {
+            debug!("Stopped reconnecting to \"{}\"", device.port);
+            return;
+        }
+
+        if let Ok(mut reconnects) = handle
+            .state::<state::reconnects::ReconnectsState>()
+            .inner
+            .lock()
+        {
+            reconnects.remove(&device.port);
+        }
+
+        let e = match result {
+            Ok(attempts) => {
+                info!(
+                    "Reconnected to \"{}\" after {} attempts",
+                    device.port, attempts
+                );
+                return;
+            }
+            Err(e) => e,
+        };
+
+        warn!("Giving up reconnecting to \"{}\": {}", device.port, e);
+
+        if let Err(e) = dispatch_devices_list_changed(
+            &handle,
+            device.port.clone(),
+            DevicesListChange::Removed,
+            SerialDeviceStatus::Disconnected,
+        ) {
+            warn!("Failed to dispatch devices list change: {}", e);
+        }
+
+        if let Err(e) = dispatch_configuration_status(
+            &handle,
+            ConfigurationStatus {
+                api_version: EVENT_API_VERSION,
+                device_key: device.port.clone(),
+                successful: false,
+                message: Some(format!(
+                    "Failed to reconnect to \"{}\" after {} attempts: {}",
+                    device.port, max_attempts, e
+                )),
+            },
+        ) {
+            warn!("Failed to dispatch configuration status: {}", e);
+        }
+    });
+}
+
+/// Cancels any pending reconnect to a device, returning whether one was pending
+pub fn cancel_reconnect(handle: &tauri::AppHandle, device_key: &DeviceKey) -> bool {
+    let reconnects = handle.state::<state::reconnects::ReconnectsState>();
+    let cancel = match reconnects.inner.lock() {
+        Ok(mut reconnects) => reconnects.remove(device_key),
+        Err(e) => {
+            warn!("Failed to lock pending reconnects: {}", e);
+            None
+        }
+    };
+
+    match cancel {
+        Some(cancel) => {
+            cancel.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Rejects text too long to be sent in one packet, which the radio would drop
+pub fn validate_text_message(text: &str) -> Result<(), String> {
+    if text.len() > MAX_TEXT_MESSAGE_BYTES {
+        return Err(format!(
+            "Message is {} bytes, longer than the {} bytes that fit in a packet",
+            text.len(),
+            MAX_TEXT_MESSAGE_BYTES
+        ));
+    }
+
+    Ok(())
+}
+
+/// Sends a text message through the radio's TX queue, as the `send_text` command does.
+/// The message is journalled before anything is sent, so it can be resent if the app
+/// or connection goes down before it's acked.
+pub async fn send_text_message<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    connected_devices_inner: &state::mesh_devices::MeshDevicesStateInner<R>,
+    device_key: &DeviceKey,
+    text: String,
+    destination: PacketDestination,
+    channel: u32,
+    want_ack: bool,
+) -> Result<(), CommandError> {
+    validate_text_message(&text)?;
+
+    let journal_id = {
+        let device = get_device(connected_devices_inner, device_key)
+            .await
+            .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+        let packet_api = device.lock().await;
+
+        journal_outgoing_message(&*packet_api, channel, &destination, &text).map(|m| m.id)
+    };
+
+    let result = send_journalled_text(
+        handle,
+        connected_devices_inner,
+        device_key,
+        OutgoingText {
+            text,
+            destination,
+            channel,
+            want_ack,
+            journal_id,
+        },
+    )
+    .await;
+
+    if let (Err(_), Some(journal_id)) = (&result, journal_id) {
+        release_outgoing_message(handle, journal_id);
+    }
+
+    result
+}
+
+/// Resends a message from the outgoing journal claimed with `OutgoingQueue::claim`.
+/// It's sent as a new packet, so it isn't dropped as a duplicate of the earlier send.
+pub async fn resend_outgoing_message<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    connected_devices_inner: &state::mesh_devices::MeshDevicesStateInner<R>,
+    device_key: &DeviceKey,
+    message: OutgoingMessage,
+) -> Result<(), CommandError> {
+    let destination = message.packet_destination();
+
+    let result = send_journalled_text(
+        handle,
+        connected_devices_inner,
+        device_key,
+        OutgoingText {
+            text: message.text,
+            destination,
+            channel: message.channel,
+            want_ack: true,
+            journal_id: Some(message.id),
+        },
+    )
+    .await;
+
+    if result.is_err() {
+        release_outgoing_message(handle, message.id);
+    }
+
+    result
+}
+
+async fn send_journalled_text<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    connected_devices_inner: &state::mesh_devices::MeshDevicesStateInner<R>,
+    device_key: &DeviceKey,
+    message: OutgoingText,
+) -> Result<(), CommandError> {
+    let device = get_device_handle(connected_devices_inner, device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(&device_key))?;
+
+    let (channel, want_ack) = (message.channel, message.want_ack);
+    let message_id = device.send_text(message).await?;
+
+    let ack_timeout = handle
+        .state::<state::settings::SettingsState>()
+        .inner
+        .lock()
+        .map_err(|e| e.to_string())?
+        .messaging
+        .ack_timeout();
+
+    if let (true, Some(message_id), Some(ack_timeout)) = (want_ack, message_id, ack_timeout) {
+        spawn_message_ack_timeout(handle.clone(), device, channel, message_id, ack_timeout);
+    }
+
+    Ok(())
+}
+
+/// Fails a traceroute whose response hasn't arrived within `timeout`. Stops once its
+/// device is disconnected.
+pub fn spawn_traceroute_timeout(
+    handle: tauri::AppHandle,
+    device: DeviceHandle,
     device_key: DeviceKey,
+    request_id: u32,
+    timeout: Duration,
+) {
+    tauri::async_runtime::spawn(async move {
+        let shutdown = shutdown_signal(&handle);
+
+        tokio::select! {
+            _ = tokio::time::sleep(timeout) => {}
+            _ = device.stopped() => return,
+            _ = shutdown.cancelled() => return,
+        }
+
+        let pending = match device.time_out_traceroute(request_id).await {
+            Ok(Some(pending)) => pending,
+            _ => return,
+        };
+
+        let message = format!(
+            "No traceroute response from node {} within {} seconds",
+            pending.destination,
+            timeout.as_secs()
+        );
+
+        warn!("{}", message);
+
+        if let Err(e) = dispatch_traceroute_result(
+            &handle,
+            TracerouteResultEvent {
+                api_version: EVENT_API_VERSION,
+                device_key,
+                request_id,
+                destination: pending.destination,
+                route: vec![],
+                successful: false,
+                message: Some(message),
+                requested_at: pending.sent_at,
+            },
+        ) {
+            warn!("Failed to dispatch traceroute result: {}", e);
+        }
+    });
+}
+
+/// Fails a sent message if it hasn't been acked within `timeout`. Stops once its
+/// device is disconnected, like the configuration timeout.
+fn spawn_message_ack_timeout<R: tauri::Runtime>(
+    handle: tauri::AppHandle<R>,
+    device: DeviceHandle<R>,
+    channel: u32,
+    message_id: u32,
+    timeout: Duration,
 ) {
     tauri::async_runtime::spawn(async move {
-        while let Some(packet) = decoded_listener.recv().await {
-            trace!("Received packet from device: {:?}", packet);
+        let shutdown = shutdown_signal(&handle);
+
+        tokio::select! {
+            _ = tokio::time::sleep(timeout) => {}
+            _ = device.stopped() => return,
+            _ = shutdown.cancelled() => return,
+        }
+
+        let reason = format!("No ack within {} seconds", timeout.as_secs());
+
+        if let Ok(true) = device.time_out_message(channel, message_id, reason).await {
+            debug!("Message {} timed out waiting for its ack", message_id);
+        }
+    });
+}
+
+/// Saves the graph's overrides and sends the updated graph to the UI
+pub fn publish_graph_overrides<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: DeviceKey,
+    graph: MeshGraph,
+    device: &MeshDevice,
+) -> Result<(), CommandError> {
+    save_json(handle, GRAPH_OVERRIDES_FILE_NAME, &graph.overrides)?;
+
+    dispatch_graph_geojson_update(
+        handle,
+        GraphGeoJson::new(device_key.clone(), &graph, device),
+    )
+    .map_err(|e| e.to_string())?;
+    dispatch_updated_graph(handle, Some(device_key), graph).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// A single admin write of a bulk config operation
+#[derive(Clone, Debug)]
+pub enum ConfigWrite {
+    Config(protobufs::Config),
+    ModuleConfig(protobufs::LocalModuleConfig),
+    Channel(protobufs::Channel),
+}
+
+/// Splits a local config into one labelled write per section that is set
+pub fn local_config_writes(config: protobufs::LocalConfig) -> Vec<(String, ConfigWrite)> {
+    use protobufs::config::PayloadVariant;
+
+    let protobufs::LocalConfig {
+        device,
+        position,
+        power,
+        network,
+        display,
+        lora,
+        bluetooth,
+        ..
+    } = config;
+
+    let sections = [
+        ("Device config", device.map(PayloadVariant::Device)),
+        ("Position config", position.map(PayloadVariant::Position)),
+        ("Power config", power.map(PayloadVariant::Power)),
+        ("Network config", network.map(PayloadVariant::Network)),
+        ("Display config", display.map(PayloadVariant::Display)),
+        ("LoRa config", lora.map(PayloadVariant::Lora)),
+        ("Bluetooth config", bluetooth.map(PayloadVariant::Bluetooth)),
+    ];
+
+    sections
+        .into_iter()
+        .filter_map(|(label, variant)| {
+            let config = protobufs::Config {
+                payload_variant: Some(variant?),
+            };
+
+            Some((label.to_string(), ConfigWrite::Config(config)))
+        })
+        .collect()
+}
+
+pub fn channel_writes(channels: Vec<protobufs::Channel>) -> Vec<(String, ConfigWrite)> {
+    channels
+        .into_iter()
+        .map(|channel| {
+            (
+                format!("Channel {}", channel.index),
+                ConfigWrite::Channel(channel),
+            )
+        })
+        .collect()
+}
+
+impl From<ConfigWrite> for RadioWrite {
+    fn from(write: ConfigWrite) -> Self {
+        match write {
+            ConfigWrite::Config(config) => RadioWrite::Config(config),
+            ConfigWrite::ModuleConfig(module_config) => RadioWrite::ModuleConfig(module_config),
+            ConfigWrite::Channel(channel) => RadioWrite::Channel(channel),
+        }
+    }
+}
+
+/// Sends one write of a bulk config operation through the device's task, so that
+/// other commands can run between writes
+pub async fn send_config_write(
+    connected_devices_inner: &state::mesh_devices::MeshDevicesStateInner,
+    device_key: &DeviceKey,
+    write: ConfigWrite,
+) -> Result<(), String> {
+    let device = get_device_handle(connected_devices_inner, device_key)
+        .await
+        .ok_or("Device not connected")?;
+
+    device.write(write.into()).await.map_err(|e| e.to_string())
+}
+
+/// Commits a config transaction started before a bulk config operation, which sends
+/// the updated device to the UI
+pub async fn commit_config_writes(
+    connected_devices_inner: &state::mesh_devices::MeshDevicesStateInner,
+    device_key: &DeviceKey,
+) -> Result<(), String> {
+    let device = get_device_handle(connected_devices_inner, device_key)
+        .await
+        .ok_or("Device not connected")?;
+
+    device
+        .write(RadioWrite::CommitConfigTransaction)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Waits for the outcome of an admin request sent to another node, ending it as timed
+/// out if none arrives within `timeout`
+pub async fn await_remote_admin<R: tauri::Runtime>(
+    device: &DeviceHandle<R>,
+    request: (u32, oneshot::Receiver<RemoteAdminOutcome>),
+    timeout: Duration,
+) -> RemoteAdminOutcome {
+    let (request_id, mut outcome) = request;
+
+    tokio::select! {
+        result = &mut outcome => {
+            return result.unwrap_or(RemoteAdminOutcome::Failed {
+                reason: "Device disconnected".into(),
+            });
+        }
+        _ = device.stopped() => {
+            return RemoteAdminOutcome::Failed {
+                reason: "Device disconnected".into(),
+            };
+        }
+        _ = tokio::time::sleep(timeout) => {}
+    }
+
+    // The result may have arrived while the timeout was being sent
+    if let Err(e) = device.time_out_remote_admin(request_id).await {
+        warn!("Failed to time out admin request: {}", e);
+    }
+
+    outcome.try_recv().unwrap_or(RemoteAdminOutcome::TimedOut)
+}
 
-            let mut devices_guard = connected_devices_arc.lock().await;
-            let packet_api = match devices_guard
-                .get_mut(&device_key)
-                .ok_or("Device not initialized")
+/// Waits for the reply to a GPIO read sent to another node, ending the read if none
+/// arrives within `timeout`
+pub async fn await_gpio_read<R: tauri::Runtime>(
+    device: &DeviceHandle<R>,
+    request: (u32, oneshot::Receiver<GpioReading>),
+    timeout: Duration,
+) -> Option<GpioReading> {
+    let (request_id, mut reading) = request;
+
+    tokio::select! {
+        result = &mut reading => return result.ok(),
+        _ = device.stopped() => return None,
+        _ = tokio::time::sleep(timeout) => {}
+    }
+
+    // The reply may have arrived while the timeout was being sent
+    if let Err(e) = device.time_out_gpio_read(request_id).await {
+        warn!("Failed to time out GPIO read: {}", e);
+    }
+
+    reading.try_recv().ok()
+}
+
+/// Polls the state of a connected device until `selector` returns a value,
+/// which allows commands to wait for responses that arrive through the
+/// decoded packet handler. The devices lock is only held while polling.
+pub async fn wait_for_device_state<R: tauri::Runtime, T, F>(
+    connected_devices_inner: &state::mesh_devices::MeshDevicesStateInner<R>,
+    device_key: &DeviceKey,
+    timeout: Duration,
+    selector: F,
+) -> Result<T, CommandError>
+where
+    F: Fn(&MeshDevice) -> Option<T>,
+{
+    let poll_result = tokio::time::timeout(timeout, async {
+        loop {
             {
-                Ok(d) => d,
-                Err(e) => {
-                    warn!("{}", e);
-                    continue;
+                let device = match get_device(connected_devices_inner, device_key).await {
+                    Some(d) => d,
+                    None => return Err(CommandError::device_not_connected(device_key)),
+                };
+
+                if let Some(value) = selector(&device.lock().await.device) {
+                    return Ok(value);
                 }
-            };
+            }
+
+            tokio::time::sleep(DEVICE_STATE_POLL_INTERVAL).await;
+        }
+    })
+    .await;
+
+    match poll_result {
+        Ok(result) => result,
+        Err(_) => Err(CommandError::TimedOut {
+            operation: "waiting for a response from the device".into(),
+        }),
+    }
+}
+
+/// Returns a device's canned messages, serving the cached messages to avoid a
+/// round-trip to the radio
+pub async fn read_canned_messages<R: tauri::Runtime>(
+    devices: &state::mesh_devices::MeshDevicesStateInner<R>,
+    device_key: &DeviceKey,
+) -> Result<Vec<String>, CommandError> {
+    let device = get_device_handle(devices, device_key)
+        .await
+        .ok_or_else(|| CommandError::device_not_connected(device_key))?;
+
+    if let Some(messages) = device
+        .packet_api()
+        .lock()
+        .await
+        .device
+        .canned_messages
+        .clone()
+    {
+        return Ok(messages);
+    }
+
+    device
+        .write(RadioWrite::local_admin(
+            protobufs::admin_message::PayloadVariant::GetCannedMessageModuleMessagesRequest(true),
+            true,
+        ))
+        .await?;
+
+    // Response is cached on the device by the decoded packet handler
+
+    wait_for_device_state(
+        devices,
+        device_key,
+        DEFAULT_ADMIN_RESPONSE_TIMEOUT,
+        |device| device.canned_messages.clone(),
+    )
+    .await
+}
+
+/// Sends the host's current time to the connected radio, returning the time that was sent
+pub async fn send_device_time<R: tauri::Runtime>(
+    device: &DeviceHandle<R>,
+) -> Result<u32, CommandError> {
+    let now = get_current_time_u32();
+
+    debug!("Setting device time to {}", now);
+
+    device
+        .write(RadioWrite::Position {
+            position: build_time_sync_position(now),
+            destination: PacketDestination::Local,
+        })
+        .await?;
+
+    Ok(now)
+}
+
+/// Sends the host time to a newly configured device if enabled in the time sync config.
+/// Runs in a separate task, since configuration completes in the device's own task.
+pub fn spawn_device_time_sync<R: tauri::Runtime>(
+    handle: tauri::AppHandle<R>,
+    device_key: DeviceKey,
+) {
+    let sync_on_connect = handle
+        .try_state::<state::time_sync::TimeSyncState>()
+        .and_then(|time_sync| time_sync.inner.lock().ok().map(|c| c.sync_on_connect))
+        .unwrap_or(false);
+
+    if !sync_on_connect {
+        trace!("Time sync on connect disabled, not setting device time");
+        return;
+    }
+
+    tauri::async_runtime::spawn(async move {
+        let mesh_devices = match handle.try_state::<state::mesh_devices::MeshDevicesState>() {
+            Some(d) => d.inner.clone(),
+            None => {
+                warn!("Connection state not initialized, not setting device time");
+                return;
+            }
+        };
+
+        let device = match get_device_handle(&mesh_devices, &device_key).await {
+            Some(device) => device,
+            None => {
+                warn!("Device \"{}\" disconnected before time sync", device_key);
+                return;
+            }
+        };
+
+        if let Err(e) = send_device_time(&device).await {
+            ErrorReporter::new(&handle, module_path!())
+                .with_device(&device_key)
+                .warning(
+                    AppErrorCode::AdminRequestFailed,
+                    format!("Failed to set device time: {}", e),
+                );
+        }
+    });
+}
+
+/// Requests `DeviceMetadata` from a newly configured device that didn't report it
+/// during configuration. Devices that don't respond keep all capabilities enabled.
+pub fn spawn_device_metadata_request<R: tauri::Runtime>(
+    handle: tauri::AppHandle<R>,
+    device_key: DeviceKey,
+) {
+    tauri::async_runtime::spawn(async move {
+        let mesh_devices = match handle.try_state::<state::mesh_devices::MeshDevicesState>() {
+            Some(d) => d.inner.clone(),
+            None => {
+                warn!("Connection state not initialized, not requesting device metadata");
+                return;
+            }
+        };
+
+        let device = match get_device_handle(&mesh_devices, &device_key).await {
+            Some(device) => device,
+            None => {
+                warn!(
+                    "Device \"{}\" disconnected before metadata request",
+                    device_key
+                );
+                return;
+            }
+        };
+
+        let send_result = device
+            .write(RadioWrite::local_admin(
+                protobufs::admin_message::PayloadVariant::GetDeviceMetadataRequest(true),
+                true,
+            ))
+            .await;
+
+        if let Err(e) = send_result {
+            ErrorReporter::new(&handle, module_path!())
+                .with_device(&device_key)
+                .warning(
+                    AppErrorCode::AdminRequestFailed,
+                    format!("Failed to request device metadata: {}", e),
+                );
+        }
+    });
+}
+
+/// Resumes the rebroadcast of the fixed position saved for a newly configured device,
+/// replacing the task of its previous connection
+pub fn spawn_fixed_position_restore<R: tauri::Runtime>(
+    handle: tauri::AppHandle<R>,
+    device_key: DeviceKey,
+) {
+    let stored = handle
+        .try_state::<state::settings::SettingsState>()
+        .and_then(|settings| {
+            settings
+                .inner
+                .lock()
+                .ok()
+                .and_then(|s| s.fixed_positions.devices.get(&device_key).cloned())
+        });
+
+    let (fixed_position, interval) = match stored.and_then(|stored| {
+        let interval = stored.rebroadcast_interval()?;
+        Some((stored.position, interval))
+    }) {
+        Some(rebroadcast) => rebroadcast,
+        None => return,
+    };
+
+    tauri::async_runtime::spawn(async move {
+        let (mesh_devices, fixed_position_state) = match (
+            handle.try_state::<state::mesh_devices::MeshDevicesState>(),
+            handle.try_state::<state::fixed_position::FixedPositionState>(),
+        ) {
+            (Some(devices), Some(fixed_positions)) => {
+                (devices.inner.clone(), fixed_positions.inner.clone())
+            }
+            _ => {
+                warn!("Connection state not initialized, not restoring fixed position");
+                return;
+            }
+        };
+
+        debug!("Restoring fixed position rebroadcast of \"{}\"", device_key);
+
+        let mut rebroadcasts_guard = fixed_position_state.lock().await;
+
+        if let Some(task) = rebroadcasts_guard.remove(&device_key) {
+            task.abort();
+        }
+
+        let task = spawn_fixed_position_rebroadcast(
+            mesh_devices,
+            device_key.clone(),
+            fixed_position,
+            interval,
+        );
+
+        rebroadcasts_guard.insert(device_key, task);
+    });
+}
+
+/// Periodically broadcasts a fixed position to the mesh for firmware that
+/// doesn't broadcast it on its own, until the returned task is aborted.
+pub fn spawn_fixed_position_rebroadcast(
+    mesh_devices: state::mesh_devices::MeshDevicesStateInner,
+    device_key: DeviceKey,
+    fixed_position: FixedPosition,
+    interval: Duration,
+) -> tauri::async_runtime::JoinHandle<()> {
+    trace!("Spawning fixed position rebroadcast every {:?}", interval);
+
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
 
-            match packet_api.handle_packet_from_radio(packet) {
-                Ok(result) => result,
-                Err(err) => {
-                    warn!("{}", err);
-                    continue;
+            let device = match get_device_handle(&mesh_devices, &device_key).await {
+                Some(device) => device,
+                None => {
+                    debug!(
+                        "Device \"{}\" disconnected, stopping rebroadcast",
+                        device_key
+                    );
+                    return;
                 }
             };
+
+            let send_result = device
+                .write(RadioWrite::Position {
+                    position: fixed_position.to_position(get_current_time_u32()),
+                    destination: PacketDestination::Broadcast,
+                })
+                .await;
+
+            match send_result {
+                Ok(()) => {}
+                Err(CommandError::DeviceNotConnected { .. })
+                | Err(CommandError::RadioNotConnected { .. }) => {
+                    debug!(
+                        "Device \"{}\" disconnected, stopping rebroadcast",
+                        device_key
+                    );
+                    return;
+                }
+                Err(e) => warn!("Failed to rebroadcast fixed position: {}", e),
+            }
+        }
+    })
+}
+
+/// Stores a device log line and streams it to the UI, unless log capture
+/// is disabled or the device has exceeded its log rate limit.
+pub fn record_device_log<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: &DeviceKey,
+    entry: DeviceLogEntry,
+) {
+    let accepted = match handle.try_state::<state::device_logs::DeviceLogsState>() {
+        Some(logs_state) => match logs_state.inner.lock() {
+            Ok(mut logs) => {
+                if !logs.config.enabled {
+                    return;
+                }
+
+                let max_lines_per_sec = logs.config.max_lines_per_sec;
+
+                logs.buffers
+                    .entry(device_key.clone())
+                    .or_default()
+                    .push(entry.clone(), max_lines_per_sec)
+            }
+            Err(e) => {
+                ErrorReporter::new(handle, module_path!()).error(
+                    AppErrorCode::StateLockFailed,
+                    format!("Failed to lock device logs: {}", e),
+                );
+                false
+            }
+        },
+        None => false,
+    };
+
+    if !accepted {
+        return;
+    }
+
+    let event = DeviceLogEvent {
+        api_version: EVENT_API_VERSION,
+        device_key: device_key.clone(),
+        entry,
+    };
+
+    if let Err(e) = dispatch_device_log(handle, event) {
+        ErrorReporter::new(handle, module_path!())
+            .with_device(device_key)
+            .error(
+                AppErrorCode::EventDispatchFailed,
+                format!("Failed to dispatch device log: {}", e),
+            );
+    }
+}
+
+/// Records plain-text log lines read from a device's serial port until the connection closes
+pub fn spawn_serial_log_handler(
+    handle: tauri::AppHandle,
+    mut log_lines: UnboundedReceiver<String>,
+    device_key: DeviceKey,
+) {
+    tauri::async_runtime::spawn(async move {
+        while let Some(line) = log_lines.recv().await {
+            let entry = DeviceLogEntry::from_serial_line(line, get_current_time_u32());
+            record_device_log(&handle, &device_key, entry);
+        }
+
+        trace!("Serial log stream for \"{}\" closed", device_key);
+    });
+}
+
+/// Disconnects every device and removes it from the app's state
+pub async fn disconnect_all_devices(handle: &tauri::AppHandle) -> Result<(), String> {
+    let mesh_devices = handle.state::<state::mesh_devices::MeshDevicesState>();
+    let radio_connections = handle.state::<state::radio_connections::RadioConnectionsState>();
+
+    let reconnecting: Vec<DeviceKey> = handle
+        .state::<state::reconnects::ReconnectsState>()
+        .inner
+        .lock()
+        .map_err(|e| e.to_string())?
+        .keys()
+        .cloned()
+        .collect();
+
+    for device_key in reconnecting {
+        if cancel_reconnect(handle, &device_key) {
+            dispatch_devices_list_changed(
+                handle,
+                device_key,
+                DevicesListChange::Removed,
+                SerialDeviceStatus::Disconnected,
+            )
+            .map_err(|e| e.to_string())?;
         }
+    }
+
+    let mut connections_guard = radio_connections.inner.lock().await;
+
+    // Disconnect from all open connections and empty HashMap
+
+    for (_, connection) in connections_guard.drain() {
+        connection.disconnect().await.map_err(|e| e.to_string())?;
+    }
+
+    // Set all state devices as disconnected and empty HashMap. This could be removed
+    // in the future to maintain state on previous devices
+    let state_devices: Vec<_> = mesh_devices.inner.lock().await.drain().collect();
+
+    for (device_key, device) in state_devices {
+        device.disconnect().await;
+
+        dispatch_devices_list_changed(
+            handle,
+            device_key,
+            DevicesListChange::Removed,
+            SerialDeviceStatus::Disconnected,
+        )
+        .map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+/// Connects to the recent devices marked for auto-connect, each in the background.
+/// Connections go through the usual configuration flow, and devices that can't be
+/// connected to after a few attempts are reported with a failed configuration status.
+pub fn spawn_startup_auto_connect(handle: tauri::AppHandle) {
+    let devices = match handle
+        .state::<state::recent_devices::RecentDevicesState>()
+        .inner
+        .lock()
+    {
+        Ok(recent) => recent.auto_connect_devices(),
+        Err(e) => {
+            warn!("Failed to lock recent devices: {}", e);
+            return;
+        }
+    };
+
+    for device in devices {
+        trace!("Spawning auto-connect to \"{}\"", device.port);
+
+        let handle = handle.clone();
+
+        tauri::async_runtime::spawn(async move {
+            let cancel = CancellationToken::new();
+            let result =
+                auto_connect_with(&device, AutoConnectPolicy::default(), &cancel, |device| {
+                    connect_recent_device(handle.clone(), device)
+                })
+                .await;
+
+            if let Err(e) = result {
+                warn!("Giving up auto-connecting to \"{}\": {}", device.port, e);
+
+                if let Err(e) = dispatch_configuration_status(
+                    &handle,
+                    ConfigurationStatus {
+                        api_version: EVENT_API_VERSION,
+                        device_key: device.port.clone(),
+                        successful: false,
+                        message: Some(format!(
+                            "Failed to auto-connect to \"{}\": {}",
+                            device.port, e
+                        )),
+                    },
+                ) {
+                    warn!("Failed to dispatch configuration status: {}", e);
+                }
+            }
+        });
+    }
+}
+
+async fn connect_recent_device(
+    handle: tauri::AppHandle,
+    device: RecentDevice,
+) -> Result<(), String> {
+    // Already connected, e.g. from the command line
+    if handle
+        .state::<state::mesh_devices::MeshDevicesState>()
+        .inner
+        .lock()
+        .await
+        .contains_key(&device.port)
+    {
+        return Ok(());
+    }
+
+    let result = match device.connection_type {
+        ConnectionType::Serial => {
+            connect_serial(
+                handle,
+                device.port,
+                device.baud_rate,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+        }
+        ConnectionType::Tcp => connect_tcp(handle, device.port, None, None, None).await,
+        connection_type => {
+            return Err(format!(
+                "Can't auto-connect to {:?} devices",
+                connection_type
+            ))
+        }
+    };
+
+    result.map_err(|e| e.to_string())
+}
+
+/// Periodically dispatches the metrics of each connection whose metrics changed
+pub fn spawn_connection_metrics_timer(handle: tauri::AppHandle) {
+    trace!("Spawning connection metrics timer");
+
+    tauri::async_runtime::spawn(async move {
+        let shutdown = shutdown_signal(&handle);
+        let mut interval = tokio::time::interval(CONNECTION_METRICS_INTERVAL);
+
+        while shutdown.tick(&mut interval).await {
+            let mesh_devices = handle.state::<state::mesh_devices::MeshDevicesState>();
+            let devices = all_devices(&mesh_devices.inner).await;
+            let now = get_current_time_u32();
+
+            for (device_key, device) in devices.iter() {
+                let packet_api = device.lock().await;
+                let snapshot = match packet_api.metrics.lock() {
+                    Ok(mut metrics) => {
+                        metrics.take_changed_snapshot(packet_api.device.queue_depth(), now)
+                    }
+                    Err(e) => {
+                        ErrorReporter::new(&handle, module_path!())
+                            .with_device(device_key)
+                            .error(
+                                AppErrorCode::StateLockFailed,
+                                format!("Failed to lock connection metrics: {}", e),
+                            );
+                        continue;
+                    }
+                };
+
+                let metrics = match snapshot {
+                    Some(m) => m,
+                    None => continue,
+                };
+
+                let event = ConnectionMetricsEvent {
+                    api_version: EVENT_API_VERSION,
+                    device_key: device_key.clone(),
+                    metrics,
+                };
+
+                if let Err(e) = dispatch_connection_metrics_updated(&handle, event) {
+                    ErrorReporter::new(&handle, module_path!())
+                        .with_device(device_key)
+                        .error(
+                            AppErrorCode::EventDispatchFailed,
+                            format!("Failed to dispatch connection metrics: {}", e),
+                        );
+                }
+            }
+        }
+
+        trace!("Stopped connection metrics timer");
+    });
+}
+
+/// Periodically emits device and graph updates that were held back by the event coalescer
+pub fn spawn_event_coalescing_timer(handle: tauri::AppHandle) {
+    trace!("Spawning event coalescing timer");
+
+    tauri::async_runtime::spawn(async move {
+        let shutdown = shutdown_signal(&handle);
+        let mut interval = tokio::time::interval(EVENT_COALESCING_TICK);
+
+        while shutdown.tick(&mut interval).await {
+            if let Err(e) = dispatch_due_coalesced_events(&handle) {
+                ErrorReporter::new(&handle, module_path!()).error(
+                    AppErrorCode::EventDispatchFailed,
+                    format!("Failed to dispatch coalesced events: {}", e),
+                );
+            }
+        }
+
+        trace!("Stopped event coalescing timer");
     });
 }
@@ -1,38 +1,136 @@
-use crate::state::DeviceKey;
 use meshtastic::protobufs;
 use meshtastic::ts::specta::{self, Type};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::state::DeviceKey;
+
 pub mod commands;
+pub mod error_reporter;
 pub mod events;
 pub mod helpers;
+pub mod reset;
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize, thiserror::Error)]
+pub use events::payloads::{
+    AnalyticsUpdateEvent, AppErrorEvent, ClockSkewEvent, ConfigurationStatus,
+    ConnectionMetricsEvent, DebugPacketEvent, DeepLinkEvent, DeviceLogEvent, DevicesListChange,
+    GeofenceTransitionEvent, GpioChangedEvent, MessageStateEvent, NetworkPartitionEvent,
+    NodeDbReconciledEvent, NodeStatusChangedEvent, OperationFinishedEvent, OperationProgressEvent,
+    RadioQueueThrottleStatus, RemoteAdminResultEvent, ReplayStatusEvent, TracerouteResultEvent,
+    UnreadCountsChangedEvent, UnsentMessagesEvent, EVENT_API_VERSION,
+};
+
+/// Stable identifier of a `CommandError`, which the UI decides how to react on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
 #[serde(rename_all = "camelCase")]
-/// An error structure that is intended to be transmitted to the UI layer
-/// and is designed to be interchangable with the default JS `Error` type.
-pub struct CommandError {
-    message: String,
+pub enum CommandErrorCode {
+    DeviceNotConnected,
+    RadioNotConnected,
+    PortOpenFailed,
+    TimedOut,
+    InvalidArgument,
+    Internal,
+}
+
+/// An error that is intended to be transmitted to the UI layer. It's serialized as
+/// its `code`, a `message` interchangable with the default JS `Error` type's, and the
+/// fields of its variant, e.g.
+/// `{ "code": "deviceNotConnected", "message": "...", "deviceKey": "COM3" }`.
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+pub enum CommandError {
+    #[error("Device \"{device_key}\" not connected")]
+    DeviceNotConnected { device_key: DeviceKey },
+
+    /// The device is connected, but not over a radio connection commands can write to
+    #[error("Device \"{device_key}\" has no radio connection")]
+    RadioNotConnected { device_key: DeviceKey },
+
+    #[error("Failed to open \"{port_name}\": {error}")]
+    PortOpenFailed { port_name: String, error: String },
+
+    #[error("Timed out {operation}")]
+    TimedOut { operation: String },
+
+    #[error("Invalid {field}: {reason}")]
+    InvalidArgument { field: String, reason: String },
+
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl CommandError {
+    pub fn device_not_connected(device_key: &str) -> Self {
+        Self::DeviceNotConnected {
+            device_key: device_key.into(),
+        }
+    }
+
+    pub fn radio_not_connected(device_key: &str) -> Self {
+        Self::RadioNotConnected {
+            device_key: device_key.into(),
+        }
+    }
+
+    pub fn invalid_argument(field: &str, reason: impl Into<String>) -> Self {
+        Self::InvalidArgument {
+            field: field.into(),
+            reason: reason.into(),
+        }
+    }
+
+    pub fn code(&self) -> CommandErrorCode {
+        match self {
+            CommandError::DeviceNotConnected { .. } => CommandErrorCode::DeviceNotConnected,
+            CommandError::RadioNotConnected { .. } => CommandErrorCode::RadioNotConnected,
+            CommandError::PortOpenFailed { .. } => CommandErrorCode::PortOpenFailed,
+            CommandError::TimedOut { .. } => CommandErrorCode::TimedOut,
+            CommandError::InvalidArgument { .. } => CommandErrorCode::InvalidArgument,
+            CommandError::Internal(_) => CommandErrorCode::Internal,
+        }
+    }
 }
 
-impl std::fmt::Display for CommandError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "CommandError: \"{}\"", self.message)
+impl Serialize for CommandError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("code", &self.code())?;
+        map.serialize_entry("message", &self.to_string())?;
+
+        match self {
+            CommandError::DeviceNotConnected { device_key }
+            | CommandError::RadioNotConnected { device_key } => {
+                map.serialize_entry("deviceKey", device_key)?;
+            }
+            CommandError::PortOpenFailed { port_name, error } => {
+                map.serialize_entry("portName", port_name)?;
+                map.serialize_entry("error", error)?;
+            }
+            CommandError::TimedOut { operation } => {
+                map.serialize_entry("operation", operation)?;
+            }
+            CommandError::InvalidArgument { field, reason } => {
+                map.serialize_entry("field", field)?;
+                map.serialize_entry("reason", reason)?;
+            }
+            CommandError::Internal(_) => {}
+        }
+
+        map.end()
     }
 }
 
+/// Errors still reported as plain messages are internal until they get a variant
 impl From<String> for CommandError {
     fn from(value: String) -> Self {
-        Self { message: value }
+        Self::Internal(value)
     }
 }
 
 impl From<&str> for CommandError {
     fn from(value: &str) -> Self {
-        Self {
-            message: value.into(),
-        }
+        Self::Internal(value.into())
     }
 }
 
@@ -44,17 +142,55 @@ pub struct APMincutStringResults {
     diffcen_result: HashMap<u32, HashMap<u32, HashMap<u32, f64>>>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Type)]
-#[serde(rename_all = "camelCase")]
-pub struct ConfigurationStatus {
-    pub device_key: DeviceKey,
-    pub successful: bool,
-    pub message: Option<String>,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize, Type)]
 pub struct DeviceBulkConfig {
     radio: Option<protobufs::LocalConfig>,
     module: Option<protobufs::LocalModuleConfig>,
     channels: Option<Vec<protobufs::Channel>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn serializes_code_message_and_fields() {
+        let error = CommandError::device_not_connected("/dev/ttyUSB0");
+
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({
+                "code": "deviceNotConnected",
+                "message": "Device \"/dev/ttyUSB0\" not connected",
+                "deviceKey": "/dev/ttyUSB0",
+            })
+        );
+
+        let error = CommandError::PortOpenFailed {
+            port_name: "COM3".into(),
+            error: "Access is denied".into(),
+        };
+
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({
+                "code": "portOpenFailed",
+                "message": "Failed to open \"COM3\": Access is denied",
+                "portName": "COM3",
+                "error": "Access is denied",
+            })
+        );
+    }
+
+    #[test]
+    fn plain_messages_are_internal_errors() {
+        let error = CommandError::from("Graph not initialized");
+
+        assert_eq!(error.code(), CommandErrorCode::Internal);
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({ "code": "internal", "message": "Graph not initialized" })
+        );
+    }
+}
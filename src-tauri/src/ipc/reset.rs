@@ -0,0 +1,266 @@
+//! Wiping in-memory state for the "start fresh" actions, without touching the device
+//! connection or its configuration. Each reset sends the emptied state to the UI
+//! right away rather than waiting for the next packet.
+
+use log::debug;
+use tauri::Manager;
+
+use crate::device::MeshDevice;
+use crate::graph::{ds::graph::MeshGraph, geojson::GraphGeoJson};
+use crate::ipc::events::{
+    dispatch_full_edge_snapshot, dispatch_updated_device, dispatch_updated_graph,
+    flush_coalesced_events,
+};
+use crate::persistence::{save_json, GRAPH_OVERRIDES_FILE_NAME};
+use crate::state::{self, graph::SharedGraph, DeviceKey};
+
+/// Clears the graph, keeping our own node and the operator's overrides unless
+/// `purge_all` is set, in which case the saved overrides are removed as well
+pub fn clear_network_graph<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: &DeviceKey,
+    graph: &SharedGraph,
+    device: &MeshDevice,
+    purge_all: bool,
+) -> Result<(), String> {
+    debug!("Clearing network graph, purging overrides: {}", purge_all);
+
+    let graph = {
+        let mut graph = graph.write()?;
+
+        if purge_all {
+            graph.purge();
+            save_json(handle, GRAPH_OVERRIDES_FILE_NAME, &graph.overrides)?;
+        } else {
+            graph.clear(Some(device.my_node_info.my_node_num));
+        }
+
+        graph.clone()
+    };
+
+    publish_graph(handle, device_key, graph, device)
+}
+
+/// Clears the messages of one channel, or all channels, returning how many were removed
+pub fn clear_message_history<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: &DeviceKey,
+    device: &mut MeshDevice,
+    channel: Option<u32>,
+) -> Result<usize, String> {
+    let removed = device.clear_message_history(channel);
+
+    dispatch_updated_device(handle, device_key, device).map_err(|e| e.to_string())?;
+    flush_coalesced_events(handle).map_err(|e| e.to_string())?;
+
+    Ok(removed)
+}
+
+/// Forgets link quality history and range test samples, and resets the edge delta
+/// sequence of every device so the UI starts over from a snapshot
+pub fn reset_analytics_state<'a, R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    graph: &SharedGraph,
+    devices: impl IntoIterator<Item = (&'a DeviceKey, &'a mut MeshDevice)>,
+) -> Result<(), String> {
+    debug!("Resetting analytics state");
+
+    let graph = {
+        let mut graph = graph.write()?;
+        graph.clear_link_quality();
+        graph.clone()
+    };
+
+    if let Some(edge_deltas) = handle.try_state::<state::edge_deltas::EdgeDeltasState>() {
+        edge_deltas.inner.lock().map_err(|e| e.to_string())?.clear();
+    }
+
+    for (device_key, device) in devices {
+        device.range_tests.clear();
+
+        dispatch_updated_device(handle, device_key, device).map_err(|e| e.to_string())?;
+        dispatch_full_edge_snapshot(
+            handle,
+            GraphGeoJson::new(device_key.clone(), &graph, device),
+        )
+        .map_err(|e| e.to_string())?;
+    }
+
+    dispatch_updated_graph(handle, None, graph).map_err(|e| e.to_string())?;
+    flush_coalesced_events(handle).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+fn publish_graph<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: &DeviceKey,
+    graph: MeshGraph,
+    device: &MeshDevice,
+) -> Result<(), String> {
+    dispatch_full_edge_snapshot(
+        handle,
+        GraphGeoJson::new(device_key.clone(), &graph, device),
+    )
+    .map_err(|e| e.to_string())?;
+    dispatch_updated_graph(handle, Some(device_key.clone()), graph).map_err(|e| e.to_string())?;
+    flush_coalesced_events(handle).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::{reactions::MessageReactions, TextPacket};
+    use crate::graph::ds::{edge::GraphEdge, fixtures::graph_node};
+    use crate::graph::edge_delta::EdgeUpdate;
+    use crate::state::edge_deltas::EdgeDeltasState;
+
+    fn populated_graph() -> MeshGraph {
+        let mut graph = MeshGraph::new();
+
+        for node_num in 1..=3 {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        graph.upsert_edge(graph_node(1), graph_node(2), GraphEdge::manual(1, 2, 4.0));
+        graph.upsert_edge(graph_node(2), graph_node(3), GraphEdge::manual(2, 3, 4.0));
+        graph.set_node_label(3, Some("Barn".into()));
+
+        graph
+    }
+
+    /// The next edge update after a reset is a delta only if the reset itself sent a
+    /// snapshot of the emptied edges to the UI
+    fn next_edge_update(
+        app: &tauri::App<tauri::test::MockRuntime>,
+        device_key: &str,
+    ) -> EdgeUpdate {
+        let edge_deltas = app.state::<EdgeDeltasState>();
+        let mut trackers = edge_deltas.inner.lock().unwrap();
+
+        trackers
+            .get_mut(device_key)
+            .expect("No edge update was sent")
+            .next(&geojson::FeatureCollection {
+                bbox: None,
+                features: vec![],
+                foreign_members: None,
+            })
+    }
+
+    #[test]
+    fn clears_graph_and_sends_empty_snapshot() {
+        let app = tauri::test::mock_app();
+        app.manage(EdgeDeltasState::new());
+
+        let device_key: DeviceKey = "COM3".into();
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 1;
+
+        let graph = SharedGraph::new(populated_graph());
+
+        dispatch_full_edge_snapshot(
+            &app.handle(),
+            GraphGeoJson::new(device_key.clone(), &graph.read().unwrap(), &device),
+        )
+        .unwrap();
+
+        clear_network_graph(&app.handle(), &device_key, &graph, &device, false).unwrap();
+
+        let cleared = graph.read().unwrap();
+        assert_eq!(cleared.nodes().map(|n| n.node_num).collect::<Vec<_>>(), [1]);
+        assert_eq!(cleared.edge_count(), 0);
+        assert_eq!(cleared.overrides.node_label(3), Some("Barn"));
+
+        assert!(matches!(
+            next_edge_update(&app, &device_key),
+            EdgeUpdate::Delta { sequence: 3, .. }
+        ));
+    }
+
+    #[test]
+    fn clears_message_history_without_touching_config() {
+        let app = tauri::test::mock_app();
+        let device_key: DeviceKey = "COM3".into();
+
+        let mut device = MeshDevice::new();
+        device.config.lora = Some(Default::default());
+
+        for (id, channel) in [(1, 0), (2, 1)] {
+            device.add_text_message(TextPacket {
+                packet: protobufs::MeshPacket {
+                    id,
+                    channel,
+                    ..Default::default()
+                },
+                data: "hi".into(),
+                reactions: MessageReactions::new(),
+            });
+        }
+
+        let removed = clear_message_history(&app.handle(), &device_key, &mut device, None).unwrap();
+
+        assert_eq!(removed, 2);
+        assert!(device.channels.values().all(|c| c.messages.is_empty()));
+        assert!(device.unknown_channel_messages.is_empty());
+        assert!(device.config.lora.is_some());
+    }
+
+    #[test]
+    fn resets_analytics_of_every_device() {
+        let app = tauri::test::mock_app();
+        app.manage(EdgeDeltasState::new());
+
+        let mut graph = populated_graph();
+        graph.record_link_sample(
+            1,
+            2,
+            crate::graph::ds::link_quality::LinkQualitySample {
+                timestamp: 0,
+                snr: 4.0,
+                rssi: None,
+                observer: "mock".into(),
+            },
+        );
+        let graph = SharedGraph::new(graph);
+
+        let mut devices: Vec<(DeviceKey, MeshDevice)> = ["sim-a", "sim-b"]
+            .into_iter()
+            .map(|key| (key.into(), MeshDevice::new()))
+            .collect();
+
+        for (device_key, device) in devices.iter_mut() {
+            device.range_tests.insert(2, vec![]);
+
+            dispatch_full_edge_snapshot(
+                &app.handle(),
+                GraphGeoJson::new(device_key.clone(), &graph.read().unwrap(), device),
+            )
+            .unwrap();
+        }
+
+        reset_analytics_state(
+            &app.handle(),
+            &graph,
+            devices.iter_mut().map(|(key, device)| (&*key, device)),
+        )
+        .unwrap();
+
+        let graph = graph.read().unwrap();
+        assert!(graph.link_quality.is_empty());
+        assert_eq!(graph.node_count(), 3);
+        assert!(devices.iter().all(|(_, d)| d.range_tests.is_empty()));
+
+        // Both trackers started over, so their sequence continues from the reset snapshot
+        for (device_key, _) in &devices {
+            assert!(matches!(
+                next_edge_update(&app, device_key),
+                EdgeUpdate::Snapshot { sequence: 2 } | EdgeUpdate::Delta { sequence: 2, .. }
+            ));
+        }
+    }
+}
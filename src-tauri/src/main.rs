@@ -3,11 +3,27 @@
     windows_subsystem = "windows"
 )]
 
+mod backup;
 mod cli;
+mod connection;
+mod deep_link;
 mod device;
+mod elevation;
+mod export;
 mod graph;
 mod ipc;
+mod migrations;
+mod notifications;
 mod packet_api;
+mod persistence;
+mod profiles;
+mod replay;
+mod retention;
+mod scripting;
+mod secrets;
+mod settings;
+mod shutdown;
+mod simulation;
 mod state;
 
 use log::{info, LevelFilter};
@@ -51,18 +67,146 @@ fn main() {
             let initial_mesh_devices_state = state::mesh_devices::MeshDevicesState::new();
             let initial_radio_connections_state =
                 state::radio_connections::RadioConnectionsState::new();
+            let initial_reconnects_state = state::reconnects::ReconnectsState::new();
             let mut inital_autoconnect_state = state::autoconnect::AutoConnectState::new();
+            let mut initial_deep_link_state = state::deep_link::DeepLinkState::new();
             let initial_graph_state = state::graph::GraphState::new();
 
-            match cli::handle_cli_matches(app, &mut inital_autoconnect_state) {
+            let data_dir = app
+                .path_resolver()
+                .app_data_dir()
+                .ok_or("Could not resolve app data directory")?;
+            let initial_profiles_state = state::profiles::ProfilesState::new(
+                data_dir.clone(),
+                profiles::load_profiles(&data_dir),
+            );
+
+            // Filled in from the active profile once all state is managed
+            let initial_settings_state =
+                state::settings::SettingsState::new(settings::AppSettings::default());
+            let initial_graph_store_state = state::graph_store::GraphStoreState::new(None);
+            let initial_message_store_state = state::message_store::MessageStoreState::new(None);
+            let initial_telemetry_store_state =
+                state::telemetry_store::TelemetryStoreState::new(None);
+            let initial_graph_autosave_state = state::graph_autosave::GraphAutosaveState::new();
+            let initial_device_configs_state =
+                state::device_configs::DeviceConfigsState::new(Default::default());
+            let initial_secrets_state = state::secrets::SecretsState::new();
+
+            let initial_time_sync_state = state::time_sync::TimeSyncState::new();
+            let initial_node_liveness_state = state::node_liveness::NodeLivenessState::new();
+            let initial_fixed_position_state = state::fixed_position::FixedPositionState::new();
+            let initial_notification_rules_state =
+                state::notification_rules::NotificationRulesState::new();
+            let initial_notification_preferences_state =
+                state::notification_preferences::NotificationPreferencesState::new();
+            let initial_notification_grouping_state =
+                state::notification_grouping::NotificationGroupingState::new();
+            let (notification_queue, notification_receiver) =
+                notifications::dispatcher::notification_queue();
+            let initial_notification_queue_state =
+                state::notification_queue::NotificationQueueState::new(notification_queue);
+            let initial_device_logs_state = state::device_logs::DeviceLogsState::new();
+            let initial_app_errors_state = state::app_errors::AppErrorsState::new();
+            let initial_event_coalescing_state =
+                state::event_coalescing::EventCoalescingState::new();
+            let initial_event_scopes_state = state::event_scopes::EventScopesState::new();
+            let initial_ipc_encoding_state = state::ipc_encoding::IpcEncodingState::new();
+            let initial_edge_deltas_state = state::edge_deltas::EdgeDeltasState::new();
+            let initial_elevation_state = state::elevation::ElevationState::new();
+            let initial_simulation_state = state::simulation::SimulationState::new();
+            let initial_replay_state = state::replay::ReplayState::new();
+            let initial_operations_state = state::operations::OperationsState::new();
+            let initial_shutdown_state = state::shutdown::ShutdownState::new();
+            let initial_network_partition_state =
+                state::network_partition::NetworkPartitionState::new();
+            let initial_analytics_state = state::analytics::AnalyticsState::new();
+
+            let (webhook_queue, webhook_receiver) = notifications::webhooks::webhook_queue();
+            let initial_webhooks_state = state::webhooks::WebhooksState::new(webhook_queue);
+
+            let initial_geofences_state = state::geofences::GeofencesState::new();
+
+            let initial_recent_devices_state =
+                state::recent_devices::RecentDevicesState::new(Default::default());
+
+            let initial_developer_mode_state = state::developer_mode::DeveloperModeState::new();
+
+            let (script_queue, script_receiver) = scripting::script_queue();
+            let initial_packet_scripts_state =
+                state::packet_scripts::PacketScriptsState::new(script_queue);
+
+            match cli::handle_cli_matches(
+                app,
+                &mut inital_autoconnect_state,
+                &mut initial_deep_link_state,
+            ) {
                 Ok(_) => {}
                 Err(err) => panic!("Failed to parse CLI args:\n{}", err),
             }
 
             app.app_handle().manage(initial_mesh_devices_state);
             app.app_handle().manage(initial_radio_connections_state);
+            app.app_handle().manage(initial_recent_devices_state);
+            app.app_handle().manage(initial_reconnects_state);
             app.app_handle().manage(inital_autoconnect_state); // Needs to be set after being mutated by CLI parser
+            app.app_handle().manage(initial_deep_link_state); // Same as above
             app.app_handle().manage(initial_graph_state);
+            app.app_handle().manage(initial_graph_store_state);
+            app.app_handle().manage(initial_graph_autosave_state);
+            app.app_handle().manage(initial_message_store_state);
+            app.app_handle().manage(initial_telemetry_store_state);
+            app.app_handle().manage(initial_device_configs_state);
+            app.app_handle().manage(initial_secrets_state);
+            app.app_handle().manage(initial_time_sync_state);
+            app.app_handle().manage(initial_node_liveness_state);
+            app.app_handle().manage(initial_fixed_position_state);
+            app.app_handle().manage(initial_notification_rules_state);
+            app.app_handle()
+                .manage(initial_notification_preferences_state);
+            app.app_handle().manage(initial_notification_grouping_state);
+            app.app_handle().manage(initial_notification_queue_state);
+            app.app_handle().manage(initial_device_logs_state);
+            app.app_handle().manage(initial_app_errors_state);
+            app.app_handle().manage(initial_event_coalescing_state);
+            app.app_handle().manage(initial_event_scopes_state);
+            app.app_handle().manage(initial_ipc_encoding_state);
+            app.app_handle().manage(initial_edge_deltas_state);
+            app.app_handle().manage(initial_elevation_state);
+            app.app_handle().manage(initial_simulation_state);
+            app.app_handle().manage(initial_replay_state);
+            app.app_handle().manage(initial_webhooks_state);
+            app.app_handle().manage(initial_geofences_state);
+            app.app_handle().manage(initial_packet_scripts_state);
+            app.app_handle().manage(initial_developer_mode_state);
+            app.app_handle().manage(initial_operations_state);
+            app.app_handle().manage(initial_shutdown_state);
+            app.app_handle().manage(initial_network_partition_state);
+            app.app_handle().manage(initial_analytics_state);
+            app.app_handle().manage(initial_settings_state);
+            app.app_handle().manage(initial_profiles_state);
+
+            let database_errors = profiles::load_profile_state(&app.app_handle())?;
+            profiles::report_database_errors(&app.app_handle(), database_errors);
+
+            notifications::spawn_notification_rules_timer(app.app_handle());
+            notifications::spawn_notification_grouping_timer(app.app_handle());
+            graph::store::spawn_graph_store_writer(app.app_handle());
+            graph::autosave::spawn_graph_autosave_timer(app.app_handle());
+            graph::analytics_refresh::spawn_analytics_refresh_timer(app.app_handle());
+            device::message_store::spawn_message_store_writer(app.app_handle());
+            device::telemetry_store::spawn_telemetry_store_writer(app.app_handle());
+            retention::spawn_retention_timer(app.app_handle());
+            backup::spawn_backup_timer(app.app_handle());
+            ipc::helpers::spawn_connection_metrics_timer(app.app_handle());
+            ipc::helpers::spawn_event_coalescing_timer(app.app_handle());
+            notifications::webhooks::spawn_webhook_worker(app.app_handle(), webhook_receiver);
+            notifications::dispatcher::spawn_notification_worker(
+                app.app_handle(),
+                notification_receiver,
+            );
+            scripting::spawn_script_worker(app.app_handle(), script_receiver);
+            ipc::helpers::spawn_startup_auto_connect(app.app_handle());
 
             Ok(())
         })
@@ -71,20 +215,173 @@ fn main() {
             ipc::commands::connections::get_all_serial_ports,
             ipc::commands::connections::connect_to_serial_port,
             ipc::commands::connections::connect_to_tcp_port,
+            ipc::commands::connections::connect_mqtt,
+            ipc::commands::connections::get_recent_devices,
+            ipc::commands::connections::set_recent_device_auto_connect,
+            ipc::commands::connections::remove_recent_device,
             ipc::commands::connections::drop_device_connection,
+            ipc::commands::connections::retry_configuration,
             ipc::commands::connections::drop_all_device_connections,
+            ipc::commands::connections::get_connection_metrics,
+            ipc::commands::connections::get_connected_devices,
+            ipc::commands::deep_link::request_pending_deep_link,
+            ipc::commands::deep_link::open_deep_link,
+            ipc::commands::deep_link::import_channel_set,
             ipc::commands::mesh::send_text,
+            ipc::commands::mesh::get_unsent_messages,
+            ipc::commands::mesh::resend_unsent_messages,
+            ipc::commands::mesh::discard_unsent_messages,
             ipc::commands::mesh::send_waypoint,
+            ipc::commands::mesh::send_waypoints,
             ipc::commands::mesh::delete_waypoint,
+            ipc::commands::mesh::get_waypoints_geojson,
+            ipc::commands::mesh::request_traceroute,
+            ipc::commands::mesh::get_node_metrics,
+            ipc::commands::mesh::get_message_history,
+            ipc::commands::mesh::get_messages,
+            ipc::commands::mesh::conversations_summary,
+            ipc::commands::mesh::mark_read,
+            ipc::commands::mesh::search_messages,
+            ipc::commands::mesh::search_all,
+            ipc::commands::mesh::set_node_note,
+            ipc::commands::mesh::get_node_notes,
+            ipc::commands::mesh::get_telemetry_series,
+            ipc::commands::mesh::get_node_details,
+            ipc::commands::mesh::clear_message_history,
+            ipc::commands::mesh::get_node_liveness_config,
+            ipc::commands::mesh::set_node_liveness_config,
+            ipc::commands::modules::get_canned_messages,
+            ipc::commands::modules::set_canned_messages,
+            ipc::commands::modules::get_range_test_results,
+            ipc::commands::modules::export_range_test_results,
+            ipc::commands::modules::gpio_write,
+            ipc::commands::modules::gpio_read,
+            ipc::commands::modules::gpio_watch,
             ipc::commands::radio::update_device_config,
             ipc::commands::radio::update_device_user,
             ipc::commands::radio::start_configuration_transaction,
             ipc::commands::radio::commit_configuration_transaction,
             ipc::commands::radio::update_device_config_bulk,
+            ipc::commands::radio::cancel_operation,
+            ipc::commands::radio::sync_device_time,
+            ipc::commands::radio::get_time_sync_config,
+            ipc::commands::radio::get_channels,
+            ipc::commands::radio::set_channel,
+            ipc::commands::radio::get_remote_config,
+            ipc::commands::radio::set_remote_config,
+            ipc::commands::radio::get_device_config,
+            ipc::commands::radio::restore_cached_channels,
+            ipc::commands::secrets::get_secrets_status,
+            ipc::commands::secrets::unlock_secrets,
+            ipc::commands::secrets::rotate_secrets_key,
+            ipc::commands::radio::set_time_sync_config,
+            ipc::commands::radio::set_fixed_position,
+            ipc::commands::radio::clear_fixed_position,
+            ipc::commands::radio::shutdown_device,
+            ipc::commands::notifications::get_notification_thresholds,
+            ipc::commands::notifications::set_notification_thresholds,
+            ipc::commands::notifications::set_node_notifications_muted,
+            ipc::commands::notifications::get_notification_preferences,
+            ipc::commands::notifications::set_notification_preferences,
+            ipc::commands::notifications::get_webhook_config,
+            ipc::commands::notifications::set_webhook_config,
+            ipc::commands::notifications::get_geofences,
+            ipc::commands::notifications::set_geofences,
+            ipc::commands::scripting::get_packet_scripts,
+            ipc::commands::scripting::add_packet_script,
+            ipc::commands::scripting::set_packet_script_enabled,
+            ipc::commands::scripting::remove_packet_script,
+            ipc::commands::scripting::test_packet_script,
+            ipc::commands::logs::get_device_logs,
+            ipc::commands::logs::get_device_log_config,
+            ipc::commands::logs::set_device_log_config,
+            ipc::commands::logs::get_recent_errors,
+            ipc::commands::logs::set_packet_debug_stream,
+            ipc::commands::simulation::start_simulation,
+            ipc::commands::simulation::stop_simulation,
+            ipc::commands::simulation::get_simulation_params,
+            ipc::commands::simulation::update_simulation_params,
+            ipc::commands::simulation::set_simulation_partitioned,
+            ipc::commands::simulation::get_developer_mode,
+            ipc::commands::simulation::set_developer_mode,
+            ipc::commands::simulation::inject_packet,
+            ipc::commands::replay::replay_open,
+            ipc::commands::replay::replay_play,
+            ipc::commands::replay::replay_pause,
+            ipc::commands::replay::replay_seek,
+            ipc::commands::replay::replay_close,
+            ipc::commands::replay::connect_replay,
+            ipc::commands::replay::start_packet_capture,
+            ipc::commands::replay::stop_packet_capture,
             ipc::commands::graph::get_graph_state,
+            ipc::commands::graph::get_graph_geojson,
+            ipc::commands::graph::get_graph_for_device,
+            ipc::commands::graph::request_full_edge_snapshot,
+            ipc::commands::graph::get_signal_heatmap_geojson,
+            ipc::commands::graph::get_route_geojson,
+            ipc::commands::graph::get_route_costs,
+            ipc::commands::graph::get_critical_nodes,
+            ipc::commands::graph::get_communities,
+            ipc::commands::graph::get_link_disjoint_paths,
+            ipc::commands::graph::get_betweenness_centrality,
+            ipc::commands::graph::get_backbone_geojson,
+            ipc::commands::graph::generate_route_corridor,
+            ipc::commands::graph::get_route_elevation_profile,
+            ipc::commands::graph::generate_cluster_coverage_geojson,
+            ipc::commands::graph::get_nodes_within_radius,
+            ipc::commands::graph::get_neighborhood,
+            ipc::commands::export::export_network_geojson,
+            ipc::commands::export::export_gpx,
+            ipc::commands::export::export_kml,
+            ipc::commands::export::export_topology,
+            ipc::commands::export::get_nodes_table,
+            ipc::commands::export::export_nodes_csv,
+            ipc::commands::export::export_analytics_report,
+            ipc::commands::export::get_latest_analytics,
+            ipc::commands::export::get_metric_series,
+            ipc::commands::export::export_state_bundle,
+            ipc::commands::export::import_state_bundle,
             ipc::commands::graph::initialize_timeout_handler,
             ipc::commands::graph::stop_timeout_handler,
+            ipc::commands::graph::get_link_quality_history,
+            ipc::commands::graph::set_link_snr_mode,
+            ipc::commands::graph::get_edge_weight_strategy,
+            ipc::commands::graph::set_edge_weight_strategy,
+            ipc::commands::graph::set_edge_max_age,
+            ipc::commands::graph::add_manual_edge,
+            ipc::commands::graph::remove_manual_edge,
+            ipc::commands::graph::hide_node,
+            ipc::commands::graph::set_node_label,
+            ipc::commands::graph::clear_network_graph,
+            ipc::commands::graph::reset_analytics_state,
+            ipc::commands::graph::clear_graph_database,
+            ipc::commands::graph::purge_archived_position,
+            ipc::commands::graph::get_autosave_status,
+            ipc::commands::graph::recover_from_autosave,
+            ipc::commands::graph::save_graph_snapshot,
+            ipc::commands::graph::load_graph_snapshot,
+            ipc::commands::graph::get_event_coalescing_interval,
+            ipc::commands::graph::set_event_coalescing_interval,
+            ipc::commands::profiles::get_profiles,
+            ipc::commands::profiles::create_profile,
+            ipc::commands::profiles::switch_profile,
+            ipc::commands::profiles::delete_profile,
+            ipc::commands::settings::get_settings,
+            ipc::commands::settings::update_settings,
+            ipc::commands::storage::get_storage_usage,
+            ipc::commands::storage::prune_storage,
+            ipc::commands::backup::run_backup_now,
+            ipc::commands::backup::list_backups,
+            ipc::commands::backup::restore_backup,
+            ipc::commands::graph::get_event_scopes,
+            ipc::commands::graph::set_event_scopes,
+            ipc::commands::graph::set_ipc_encoding,
         ])
-        .run(tauri::generate_context!())
-        .expect("Error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("Error while building tauri application")
+        .run(|handle, event| {
+            if let tauri::RunEvent::Exit = event {
+                tauri::async_runtime::block_on(shutdown::shutdown(handle));
+            }
+        });
 }
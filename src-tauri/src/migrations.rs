@@ -0,0 +1,277 @@
+//! Forward-only schema migrations for the SQLite stores. Each store embeds its ordered
+//! migrations, and the version of the last one applied is kept in `schema_version`.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use log::{debug, info};
+use rusqlite::{params, Connection};
+
+pub struct Migration {
+    pub version: u32, // consecutive, starting at 1
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MigrationError {
+    /// The database was written by a newer version of the app, which this version
+    /// can't read without risking the newer version's data
+    NewerThanApp {
+        database: &'static str,
+        version: u32,
+        latest: u32,
+    },
+    Failed(String),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::NewerThanApp {
+                database,
+                version,
+                latest,
+            } => write!(
+                f,
+                "The {} is from a newer version of the app (schema {}, this version reads up to {}). Update the app to use it.",
+                database, version, latest
+            ),
+            MigrationError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for MigrationError {
+    fn from(message: String) -> Self {
+        MigrationError::Failed(message)
+    }
+}
+
+impl From<MigrationError> for String {
+    fn from(err: MigrationError) -> Self {
+        err.to_string()
+    }
+}
+
+pub fn schema_version(connection: &Connection) -> Result<Option<u32>, String> {
+    connection
+        .query_row("SELECT MAX(version) FROM schema_version", [], |row| {
+            row.get::<_, Option<u32>>(0)
+        })
+        .map_err(|e| e.to_string())
+}
+
+/// Copy of the database taken before migrating it from `version`
+pub fn backup_path(path: &Path, version: u32) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    path.with_file_name(format!("{}.v{}.bak", file_name, version))
+}
+
+/// Applies the migrations newer than the database's schema version, each in its own
+/// transaction, returning the resulting version. A database that already has a schema
+/// is backed up next to `path` first, if it's stored in a file.
+pub fn migrate(
+    connection: &mut Connection,
+    database: &'static str,
+    migrations: &[Migration],
+    path: Option<&Path>,
+) -> Result<u32, MigrationError> {
+    connection
+        .execute_batch("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL);")
+        .map_err(|e| e.to_string())?;
+
+    let version = schema_version(connection)?.unwrap_or(0);
+    let latest = migrations.last().map(|m| m.version).unwrap_or(0);
+
+    if version > latest {
+        return Err(MigrationError::NewerThanApp {
+            database,
+            version,
+            latest,
+        });
+    }
+
+    let pending: Vec<&Migration> = migrations.iter().filter(|m| m.version > version).collect();
+
+    if pending.is_empty() {
+        return Ok(version);
+    }
+
+    if let (Some(path), true) = (path, version > 0) {
+        let backup = backup_path(path, version);
+        info!("Backing up {} to {:?} before migrating", database, backup);
+
+        if backup.exists() {
+            std::fs::remove_file(&backup)
+                .map_err(|e| format!("Failed to replace backup {:?}: {}", backup, e))?;
+        }
+
+        connection
+            .execute("VACUUM INTO ?1", params![backup.to_string_lossy()])
+            .map_err(|e| format!("Failed to back up {} to {:?}: {}", database, backup, e))?;
+    }
+
+    for migration in pending {
+        debug!(
+            "Migrating {} to schema {}: {}",
+            database, migration.version, migration.description
+        );
+
+        let failed = |e: rusqlite::Error| {
+            MigrationError::Failed(format!(
+                "Failed to migrate {} to schema {} ({}): {}",
+                database, migration.version, migration.description, e
+            ))
+        };
+
+        let transaction = connection.transaction().map_err(failed)?;
+
+        transaction.execute_batch(migration.sql).map_err(failed)?;
+        transaction
+            .execute("DELETE FROM schema_version", [])
+            .map_err(failed)?;
+        transaction
+            .execute(
+                "INSERT INTO schema_version (version) VALUES (?1)",
+                params![migration.version],
+            )
+            .map_err(failed)?;
+
+        transaction.commit().map_err(failed)?;
+    }
+
+    Ok(latest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 1,
+            description: "initial schema",
+            sql: "CREATE TABLE readings (id INTEGER PRIMARY KEY, value REAL NOT NULL);",
+        },
+        Migration {
+            version: 2,
+            description: "add reading units",
+            sql: "ALTER TABLE readings ADD COLUMN unit TEXT NOT NULL DEFAULT 'dB';",
+        },
+    ];
+
+    fn temp_database(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "migrations-{}-{}.sqlite3",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(backup_path(&path, 1));
+        path
+    }
+
+    #[test]
+    fn applies_pending_migrations_after_backup() {
+        let path = temp_database("pending");
+
+        {
+            let mut connection = Connection::open(&path).unwrap();
+            assert_eq!(
+                migrate(
+                    &mut connection,
+                    "test database",
+                    &MIGRATIONS[..1],
+                    Some(&path)
+                ),
+                Ok(1)
+            );
+            connection
+                .execute("INSERT INTO readings (value) VALUES (4.5)", [])
+                .unwrap();
+        }
+
+        // A new database isn't backed up
+        assert!(!backup_path(&path, 1).exists());
+
+        let mut connection = Connection::open(&path).unwrap();
+        assert_eq!(
+            migrate(&mut connection, "test database", MIGRATIONS, Some(&path)),
+            Ok(2)
+        );
+
+        let reading: (f64, String) = connection
+            .query_row("SELECT value, unit FROM readings", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(reading, (4.5, "dB".into()));
+
+        // The backup is the database as it was before migrating
+        let backup = Connection::open(backup_path(&path, 1)).unwrap();
+        assert_eq!(schema_version(&backup).unwrap(), Some(1));
+
+        // Migrating again does nothing
+        assert_eq!(
+            migrate(&mut connection, "test database", MIGRATIONS, Some(&path)),
+            Ok(2)
+        );
+        assert_eq!(
+            connection
+                .query_row("SELECT COUNT(*) FROM schema_version", [], |row| row
+                    .get::<_, u32>(0))
+                .unwrap(),
+            1
+        );
+
+        drop(backup);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(backup_path(&path, 1)).unwrap();
+    }
+
+    #[test]
+    fn rolls_back_failed_migration() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection, "test database", &MIGRATIONS[..1], None).unwrap();
+
+        let broken = [
+            Migration {
+                version: 1,
+                ..MIGRATIONS[0]
+            },
+            Migration {
+                version: 2,
+                description: "broken",
+                sql: "CREATE TABLE units (name TEXT); ALTER TABLE missing ADD COLUMN x;",
+            },
+        ];
+
+        let result = migrate(&mut connection, "test database", &broken, None);
+        assert!(matches!(result, Err(MigrationError::Failed(_))));
+
+        assert_eq!(schema_version(&connection).unwrap(), Some(1));
+        assert!(connection.prepare("SELECT name FROM units").is_err());
+    }
+
+    #[test]
+    fn refuses_newer_database() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection, "test database", MIGRATIONS, None).unwrap();
+
+        let result = migrate(&mut connection, "test database", &MIGRATIONS[..1], None);
+        assert_eq!(
+            result,
+            Err(MigrationError::NewerThanApp {
+                database: "test database",
+                version: 2,
+                latest: 1,
+            })
+        );
+        assert!(result.unwrap_err().to_string().contains("newer version"));
+    }
+}
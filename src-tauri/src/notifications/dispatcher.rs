@@ -0,0 +1,557 @@
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use log::{debug, info, trace, warn};
+use tauri::api::notification::Notification;
+use tauri::Manager;
+use tokio::sync::mpsc;
+
+use crate::ipc::error_reporter::{AppErrorCode, ErrorReporter};
+use crate::state;
+
+use super::preferences::{NotificationCategory, NotificationPreferences};
+
+/// Notifications waiting to be shown, beyond which new ones are dropped rather than
+/// holding up packet handling
+pub const NOTIFICATION_QUEUE_CAPACITY: usize = 64;
+
+/// Failures in a row after which notifications are paused
+pub const NOTIFICATION_FAILURE_THRESHOLD: u32 = 5;
+
+/// How long notifications stay paused after repeated failures
+pub const NOTIFICATION_FAILURE_COOLDOWN: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Debug)]
+pub struct SystemNotification {
+    pub category: NotificationCategory,
+    pub node_num: Option<u32>, // node the notification is about, if any
+    pub title: String,
+    pub body: String,
+    pub group: Option<String>, // what the notification is grouped under, e.g. its channel
+}
+
+fn current_preferences<R: tauri::Runtime>(handle: &tauri::AppHandle<R>) -> NotificationPreferences {
+    match handle.try_state::<state::notification_preferences::NotificationPreferencesState>() {
+        Some(preferences_state) => match preferences_state.inner.lock() {
+            Ok(preferences) => preferences.clone(),
+            Err(e) => {
+                warn!("Failed to lock notification preferences: {}", e);
+                NotificationPreferences::default()
+            }
+        },
+        None => NotificationPreferences::default(),
+    }
+}
+
+fn show<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    notification: SystemNotification,
+) -> Result<(), String> {
+    Notification::new(handle.config().tauri.bundle.identifier.clone())
+        .title(notification.title)
+        .body(notification.body)
+        .notify(handle)
+        .map_err(|e| e.to_string())
+}
+
+/// Shows a system notification unless the user's notification preferences
+/// suppress it or it's held back to be grouped, returning whether it was shown.
+///
+/// Only the system notification is suppressed, callers still emit their
+/// events so the UI can track unread state.
+fn notify<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    notification: SystemNotification,
+    show: &mut impl FnMut(SystemNotification) -> Result<(), String>,
+) -> Result<bool, String> {
+    let preferences = current_preferences(handle);
+
+    let suppression =
+        preferences.suppression(notification.category, notification.node_num, Utc::now());
+
+    if let Some(reason) = suppression {
+        trace!(
+            "Suppressed {:?} notification \"{}\": {:?}",
+            notification.category,
+            notification.title,
+            reason
+        );
+
+        return Ok(false);
+    }
+
+    let notification = match handle
+        .try_state::<state::notification_grouping::NotificationGroupingState>()
+    {
+        Some(grouping_state) => match grouping_state.inner.lock() {
+            Ok(mut grouper) => grouper.push(&preferences.grouping, notification, Instant::now()),
+            Err(e) => {
+                warn!("Failed to lock notification grouping: {}", e);
+                Some(notification)
+            }
+        },
+        None => Some(notification),
+    };
+
+    match notification {
+        Some(notification) => {
+            show(notification)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// What the notification worker is asked to show
+#[derive(Clone, Debug)]
+pub enum QueuedNotification {
+    Single(SystemNotification),  // subject to preferences and grouping
+    Summary(SystemNotification), // of grouped notifications, already checked against preferences
+}
+
+pub fn notification_queue() -> (
+    mpsc::Sender<QueuedNotification>,
+    mpsc::Receiver<QueuedNotification>,
+) {
+    mpsc::channel(NOTIFICATION_QUEUE_CAPACITY)
+}
+
+fn try_enqueue<R: tauri::Runtime>(handle: &tauri::AppHandle<R>, queued: QueuedNotification) {
+    let queue_state = match handle.try_state::<state::notification_queue::NotificationQueueState>()
+    {
+        Some(queue_state) => queue_state,
+        None => return,
+    };
+
+    if let Err(e) = queue_state.queue.try_send(queued) {
+        debug!("Dropped notification: {}", e);
+        queue_state.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Queues a notification for the notification worker, so packet handling doesn't
+/// wait on preferences, grouping or the system showing it. Dropped if the queue is
+/// full, with the worker reporting how many it missed.
+pub fn enqueue<R: tauri::Runtime>(handle: &tauri::AppHandle<R>, notification: SystemNotification) {
+    trace!("Queueing {:?} notification", notification.category);
+
+    try_enqueue(handle, QueuedNotification::Single(notification));
+}
+
+/// Pauses showing notifications after `NOTIFICATION_FAILURE_THRESHOLD` failures in a
+/// row, e.g. while the system's notification service isn't responding, and tries
+/// again once `NOTIFICATION_FAILURE_COOLDOWN` has passed
+#[derive(Debug, Default)]
+pub struct NotificationBreaker {
+    consecutive_failures: u32,
+    paused_until: Option<Instant>,
+}
+
+impl NotificationBreaker {
+    /// Whether notifications are shown at `now`, resuming them if the cooldown is over
+    pub fn allows(&mut self, now: Instant) -> bool {
+        match self.paused_until {
+            Some(paused_until) if now < paused_until => false,
+            Some(_) => {
+                info!("Resuming notifications after cooldown");
+                self.paused_until = None;
+                true
+            }
+            None => true,
+        }
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+    }
+
+    /// Counts a failure, returning whether it paused notifications
+    pub fn record_failure(&mut self, now: Instant) -> bool {
+        self.consecutive_failures += 1;
+
+        if self.consecutive_failures < NOTIFICATION_FAILURE_THRESHOLD {
+            return false;
+        }
+
+        self.consecutive_failures = 0;
+        self.paused_until = Some(now + NOTIFICATION_FAILURE_COOLDOWN);
+        true
+    }
+}
+
+/// Shows queued notifications one at a time until the queue closes. A notification
+/// that fails to show is reported and skipped, and notifications are dropped while
+/// repeated failures have them paused.
+fn run_notification_worker<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    mut queue: mpsc::Receiver<QueuedNotification>,
+    mut show: impl FnMut(SystemNotification) -> Result<(), String>,
+) {
+    let reporter = ErrorReporter::new(handle, module_path!());
+    let mut breaker = NotificationBreaker::default();
+
+    while let Some(queued) = queue.blocking_recv() {
+        let now = Instant::now();
+
+        if !breaker.allows(now) {
+            trace!("Notifications paused, dropping {:?}", queued);
+            continue;
+        }
+
+        let shown = match queued {
+            QueuedNotification::Single(notification) => notify(handle, notification, &mut show),
+            QueuedNotification::Summary(summary) => show(summary).map(|_| true),
+        };
+
+        match shown {
+            Ok(true) => breaker.record_success(),
+            Ok(false) => {}
+            Err(e) => {
+                reporter.warning(
+                    AppErrorCode::NotificationFailed,
+                    format!("Failed to show notification: {}", e),
+                );
+
+                if breaker.record_failure(now) {
+                    reporter.error(
+                        AppErrorCode::NotificationsPaused,
+                        format!(
+                            "Paused notifications for {} minutes after {} failures in a row",
+                            NOTIFICATION_FAILURE_COOLDOWN.as_secs() / 60,
+                            NOTIFICATION_FAILURE_THRESHOLD
+                        ),
+                    );
+                }
+            }
+        }
+
+        let dropped = handle
+            .state::<state::notification_queue::NotificationQueueState>()
+            .dropped
+            .swap(0, Ordering::Relaxed);
+
+        if dropped > 0 {
+            warn!(
+                "Dropped {} notifications while the notification queue was full",
+                dropped
+            );
+        }
+    }
+}
+
+/// Runs the notification worker on a thread of its own, since showing a notification
+/// can block on the system's notification service
+pub fn spawn_notification_worker(
+    handle: tauri::AppHandle,
+    queue: mpsc::Receiver<QueuedNotification>,
+) {
+    trace!("Spawning notification worker");
+
+    let spawned = thread::Builder::new()
+        .name("notifications".into())
+        .spawn(move || {
+            let show_handle = handle.clone();
+
+            run_notification_worker(&handle, queue, |notification| {
+                show(&show_handle, notification)
+            });
+        });
+
+    if let Err(e) = spawned {
+        warn!("Failed to spawn notification worker: {}", e);
+    }
+}
+
+/// Queues the summaries of grouped notifications that are due for the notification
+/// worker. Summaries are held back during quiet hours like any other notification.
+pub fn enqueue_due_summaries<R: tauri::Runtime>(handle: &tauri::AppHandle<R>) {
+    let preferences = current_preferences(handle);
+
+    let summaries =
+        match handle.try_state::<state::notification_grouping::NotificationGroupingState>() {
+            Some(grouping_state) => match grouping_state.inner.lock() {
+                Ok(mut grouper) => grouper.take_due(&preferences.grouping, Instant::now()),
+                Err(e) => {
+                    warn!("Failed to lock notification grouping: {}", e);
+                    return;
+                }
+            },
+            None => return,
+        };
+
+    for summary in summaries {
+        if let Some(reason) = preferences.suppression(summary.category, None, Utc::now()) {
+            debug!(
+                "Suppressed {:?} notification summary \"{}\": {:?}",
+                summary.category, summary.body, reason
+            );
+
+            continue;
+        }
+
+        try_enqueue(handle, QueuedNotification::Summary(summary));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    use meshtastic::packet::PacketRouter;
+    use meshtastic::protobufs;
+    use meshtastic::Message;
+
+    use super::*;
+    use crate::device::MeshDevice;
+    use crate::graph::ds::graph::MeshGraph;
+    use crate::packet_api::MeshPacketApi;
+    use crate::state::graph::SharedGraph;
+    use crate::state::notification_queue::NotificationQueueState;
+
+    const MESSAGE_COUNT: usize = 20;
+    const SLOW_NOTIFICATION: Duration = Duration::from_millis(100);
+
+    fn mesh_packet(
+        from: u32,
+        id: u32,
+        portnum: protobufs::PortNum,
+        payload: Vec<u8>,
+    ) -> protobufs::FromRadio {
+        protobufs::FromRadio {
+            payload_variant: Some(protobufs::from_radio::PayloadVariant::Packet(
+                protobufs::MeshPacket {
+                    from,
+                    to: u32::MAX,
+                    id,
+                    payload_variant: Some(protobufs::mesh_packet::PayloadVariant::Decoded(
+                        protobufs::Data {
+                            portnum: portnum as i32,
+                            payload,
+                            ..Default::default()
+                        },
+                    )),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        }
+    }
+
+    fn notification(title: &str) -> SystemNotification {
+        SystemNotification {
+            category: NotificationCategory::ChannelMessage,
+            node_num: None,
+            title: title.into(),
+            body: String::new(),
+            group: None,
+        }
+    }
+
+    #[test]
+    fn slow_notifications_dont_hold_up_packets() {
+        let app = tauri::test::mock_app();
+        let (queue, mut receiver) = notification_queue();
+        app.manage(NotificationQueueState::new(queue));
+
+        // Stands in for a system that takes a while to show each notification
+        let worker = thread::spawn(move || {
+            let mut shown = vec![];
+
+            while shown.len() < MESSAGE_COUNT {
+                match receiver.blocking_recv() {
+                    Some(QueuedNotification::Single(notification)) => {
+                        thread::sleep(SLOW_NOTIFICATION);
+                        shown.push(notification.body);
+                    }
+                    Some(QueuedNotification::Summary(_)) => {}
+                    None => break,
+                }
+            }
+
+            shown
+        });
+
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 1;
+
+        let graph = Arc::new(SharedGraph::new(MeshGraph::new()));
+        let mut packet_api = MeshPacketApi::new(app.handle(), "mock".into(), device, graph.clone());
+
+        let mut slowest = Duration::ZERO;
+
+        for index in 0..MESSAGE_COUNT as u32 {
+            let text = mesh_packet(
+                2,
+                1_000 + index,
+                protobufs::PortNum::TextMessageApp,
+                format!("message {}", index).into_bytes(),
+            );
+            let position = mesh_packet(
+                3,
+                2_000 + index,
+                protobufs::PortNum::PositionApp,
+                protobufs::Position {
+                    latitude_i: 476_000_000 + index as i32 * 1_000,
+                    longitude_i: -1_223_000_000,
+                    ..Default::default()
+                }
+                .encode_to_vec(),
+            );
+
+            let started = Instant::now();
+
+            packet_api.handle_packet_from_radio(text).unwrap();
+            packet_api.handle_packet_from_radio(position).unwrap();
+
+            slowest = slowest.max(started.elapsed());
+
+            // The position is on the graph as soon as its packet is handled
+            let latitude = graph.read().unwrap().spatial_index.position(3).unwrap().0;
+            assert!((latitude - (47.6 + index as f64 * 1e-4)).abs() < 1e-5);
+        }
+
+        assert!(
+            slowest < SLOW_NOTIFICATION,
+            "packets took {:?} while notifications were being shown",
+            slowest
+        );
+
+        // Meanwhile the worker works through every notification, in order
+        let shown = worker.join().unwrap();
+        let expected: Vec<String> = (0..MESSAGE_COUNT)
+            .map(|index| format!("message {}", index))
+            .collect();
+
+        assert_eq!(shown, expected);
+    }
+
+    #[test]
+    fn drops_notifications_once_the_queue_is_full() {
+        let app = tauri::test::mock_app();
+        let (queue, mut receiver) = notification_queue();
+        app.manage(NotificationQueueState::new(queue));
+
+        for index in 0..NOTIFICATION_QUEUE_CAPACITY + 5 {
+            enqueue(&app.handle(), notification(&format!("alert {}", index)));
+        }
+
+        let queue_state = app.state::<NotificationQueueState>();
+        assert_eq!(queue_state.dropped.load(Ordering::Relaxed), 5);
+
+        // The oldest notifications are kept
+        match receiver.try_recv().unwrap() {
+            QueuedNotification::Single(notification) => assert_eq!(notification.title, "alert 0"),
+            queued => panic!("unexpected {:?}", queued),
+        }
+    }
+
+    #[test]
+    fn breaker_pauses_notifications_until_the_cooldown_passes() {
+        let mut breaker = NotificationBreaker::default();
+        let start = Instant::now();
+
+        for _ in 1..NOTIFICATION_FAILURE_THRESHOLD {
+            assert!(breaker.allows(start));
+            assert!(!breaker.record_failure(start));
+        }
+
+        // A success resets the count
+        breaker.record_success();
+
+        for _ in 1..NOTIFICATION_FAILURE_THRESHOLD {
+            assert!(!breaker.record_failure(start));
+        }
+
+        assert!(breaker.record_failure(start));
+        assert!(!breaker.allows(start));
+        assert!(!breaker.allows(start + NOTIFICATION_FAILURE_COOLDOWN / 2));
+
+        assert!(breaker.allows(start + NOTIFICATION_FAILURE_COOLDOWN));
+
+        // Once resumed it takes a full run of failures to pause again
+        assert!(!breaker.record_failure(start + NOTIFICATION_FAILURE_COOLDOWN));
+        assert!(breaker.allows(start + NOTIFICATION_FAILURE_COOLDOWN));
+    }
+
+    #[test]
+    fn failing_notifications_are_paused_without_holding_up_packets() {
+        let app = tauri::test::mock_app();
+        let (queue, receiver) = notification_queue();
+        app.manage(NotificationQueueState::new(queue));
+        app.manage(state::app_errors::AppErrorsState::new());
+
+        // Stands in for a notification service that has stopped responding
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let worker_attempts = attempts.clone();
+        let worker_handle = app.handle();
+
+        thread::spawn(move || {
+            run_notification_worker(&worker_handle, receiver, |_| {
+                worker_attempts.fetch_add(1, Ordering::Relaxed);
+                Err("notification service unavailable".into())
+            });
+        });
+
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 1;
+
+        let graph = Arc::new(SharedGraph::new(MeshGraph::new()));
+        let mut packet_api = MeshPacketApi::new(app.handle(), "mock".into(), device, graph.clone());
+
+        for index in 0..MESSAGE_COUNT as u32 {
+            let text = mesh_packet(
+                2,
+                1_000 + index,
+                protobufs::PortNum::TextMessageApp,
+                format!("message {}", index).into_bytes(),
+            );
+            let position = mesh_packet(
+                3,
+                2_000 + index,
+                protobufs::PortNum::PositionApp,
+                protobufs::Position {
+                    latitude_i: 476_000_000,
+                    longitude_i: -1_223_000_000,
+                    ..Default::default()
+                }
+                .encode_to_vec(),
+            );
+
+            packet_api.handle_packet_from_radio(text).unwrap();
+            packet_api.handle_packet_from_radio(position).unwrap();
+        }
+
+        assert!(graph.read().unwrap().spatial_index.position(3).is_some());
+
+        // Wait for the worker to work through the queue
+        let queue_state = app.state::<NotificationQueueState>();
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        while queue_state.queue.capacity() < NOTIFICATION_QUEUE_CAPACITY {
+            assert!(
+                Instant::now() < deadline,
+                "notification queue wasn't drained"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // Every message was queued, but the service was only tried until the pause
+        let errors = app
+            .state::<state::app_errors::AppErrorsState>()
+            .inner
+            .lock()
+            .unwrap()
+            .recent();
+
+        assert!(errors
+            .iter()
+            .any(|error| error.code == AppErrorCode::NotificationsPaused));
+        assert_eq!(
+            attempts.load(Ordering::Relaxed),
+            NOTIFICATION_FAILURE_THRESHOLD as usize
+        );
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+
+use log::debug;
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::device::helpers::{haversine_distance_meters, EARTH_RADIUS_METERS};
+
+/// Fences with more vertices than this are rejected, as they're evaluated on every
+/// position update
+pub const MAX_GEOFENCE_VERTICES: usize = 1_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct GeofencePoint {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum GeofenceShape {
+    /// Closed by joining the last vertex back to the first
+    Polygon { vertices: Vec<GeofencePoint> },
+
+    #[serde(rename_all = "camelCase")]
+    Circle {
+        center: GeofencePoint,
+        radius_meters: f64,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct Geofence {
+    pub id: String,
+    pub name: String,
+    pub shape: GeofenceShape,
+
+    /// How far past the boundary a node has to be before it counts as having crossed
+    /// it, so GPS jitter around the boundary doesn't raise repeated alerts
+    pub hysteresis_meters: f64,
+
+    /// Show a system notification and notify webhooks on transitions, in addition
+    /// to the `geofence_violation` and `geofence_entry` events
+    pub notify: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GeofenceConfig {
+    pub fences: Vec<Geofence>,
+}
+
+fn valid_point(point: &GeofencePoint) -> bool {
+    (-90.0..=90.0).contains(&point.latitude) && (-180.0..=180.0).contains(&point.longitude)
+}
+
+impl GeofenceConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (index, fence) in self.fences.iter().enumerate() {
+            if self.fences[..index].iter().any(|f| f.id == fence.id) {
+                return Err(format!(
+                    "Geofence id \"{}\" is used more than once",
+                    fence.id
+                ));
+            }
+
+            if !fence.hysteresis_meters.is_finite() || fence.hysteresis_meters < 0.0 {
+                return Err(format!(
+                    "Hysteresis of geofence \"{}\" must be zero or more meters",
+                    fence.name
+                ));
+            }
+
+            let valid = match &fence.shape {
+                GeofenceShape::Polygon { vertices } => {
+                    (3..=MAX_GEOFENCE_VERTICES).contains(&vertices.len())
+                        && vertices.iter().all(valid_point)
+                }
+                GeofenceShape::Circle {
+                    center,
+                    radius_meters,
+                } => valid_point(center) && radius_meters.is_finite() && *radius_meters > 0.0,
+            };
+
+            if !valid {
+                return Err(format!("Geofence \"{}\" has an invalid shape", fence.name));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Distance from the origin to the segment between `a` and `b`, in the plane
+fn distance_to_segment(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+
+    let t = if length_squared == 0.0 {
+        0.0
+    } else {
+        (-(a.0 * dx + a.1 * dy) / length_squared).clamp(0.0, 1.0)
+    };
+
+    (a.0 + t * dx).hypot(a.1 + t * dy)
+}
+
+impl GeofenceShape {
+    /// Distance in meters from the point to the boundary, negative inside the fence.
+    /// Polygons are projected onto a plane around the point, which is accurate for
+    /// fences up to a few tens of kilometers across.
+    pub fn signed_distance_meters(&self, latitude: f64, longitude: f64) -> f64 {
+        match self {
+            GeofenceShape::Circle {
+                center,
+                radius_meters,
+            } => {
+                haversine_distance_meters(latitude, longitude, center.latitude, center.longitude)
+                    - radius_meters
+            }
+            GeofenceShape::Polygon { vertices } => {
+                let lon_scale = latitude.to_radians().cos();
+
+                let projected: Vec<(f64, f64)> = vertices
+                    .iter()
+                    .map(|v| {
+                        (
+                            (v.longitude - longitude).to_radians()
+                                * lon_scale
+                                * EARTH_RADIUS_METERS,
+                            (v.latitude - latitude).to_radians() * EARTH_RADIUS_METERS,
+                        )
+                    })
+                    .collect();
+
+                let mut inside = false;
+                let mut distance = f64::INFINITY;
+
+                for (i, a) in projected.iter().enumerate() {
+                    let b = projected[(i + 1) % projected.len()];
+
+                    // Ray cast from the origin along the positive x axis
+                    if (a.1 > 0.0) != (b.1 > 0.0) && a.0 + (-a.1) * (b.0 - a.0) / (b.1 - a.1) > 0.0
+                    {
+                        inside = !inside;
+                    }
+
+                    distance = distance.min(distance_to_segment(*a, b));
+                }
+
+                if inside {
+                    -distance
+                } else {
+                    distance
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum GeofenceTransitionKind {
+    Entry,
+    Violation, // left the fence
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct GeofenceTransition {
+    pub kind: GeofenceTransitionKind,
+    pub fence_id: String,
+    pub fence_name: String,
+    pub node_num: u32,
+    pub latitude: f64,
+    pub longitude: f64,
+    #[serde(skip)]
+    pub notify: bool,
+}
+
+/// Tracks whether each node is inside each fence, reporting when one crosses a
+/// boundary by more than the fence's hysteresis.
+///
+/// The first position of a node only establishes which side of each fence it's on,
+/// without a transition, since it may have crossed long before it was heard.
+#[derive(Clone, Debug, Default)]
+pub struct Geofences {
+    pub config: GeofenceConfig,
+    inside: HashMap<(String, u32), bool>, // keyed by fence id and node
+}
+
+impl Geofences {
+    pub fn new(config: GeofenceConfig) -> Self {
+        Self {
+            config,
+            inside: HashMap::new(),
+        }
+    }
+
+    /// Replaces the fences. Nodes keep their state for fences whose shape didn't change.
+    pub fn set_config(&mut self, config: GeofenceConfig) {
+        let unchanged = |id: &str| {
+            let shape = |c: &GeofenceConfig| {
+                c.fences
+                    .iter()
+                    .find(|f| f.id == id)
+                    .map(|f| f.shape.clone())
+            };
+
+            shape(&self.config).is_some() && shape(&self.config) == shape(&config)
+        };
+
+        let inside = std::mem::take(&mut self.inside)
+            .into_iter()
+            .filter(|((id, _), _)| unchanged(id))
+            .collect();
+
+        self.inside = inside;
+        self.config = config;
+    }
+
+    pub fn evaluate(
+        &mut self,
+        node_num: u32,
+        latitude: f64,
+        longitude: f64,
+    ) -> Vec<GeofenceTransition> {
+        let mut transitions = vec![];
+
+        for fence in &self.config.fences {
+            let distance = fence.shape.signed_distance_meters(latitude, longitude);
+            let key = (fence.id.clone(), node_num);
+
+            let was_inside = match self.inside.get(&key) {
+                Some(inside) => *inside,
+                None => {
+                    self.inside.insert(key, distance <= 0.0);
+                    continue;
+                }
+            };
+
+            let kind = if was_inside && distance > fence.hysteresis_meters {
+                GeofenceTransitionKind::Violation
+            } else if !was_inside && distance < -fence.hysteresis_meters {
+                GeofenceTransitionKind::Entry
+            } else {
+                continue;
+            };
+
+            debug!(
+                "Node {} crossed geofence \"{}\": {:?}",
+                node_num, fence.name, kind
+            );
+
+            self.inside
+                .insert(key, kind == GeofenceTransitionKind::Entry);
+
+            transitions.push(GeofenceTransition {
+                kind,
+                fence_id: fence.id.clone(),
+                fence_name: fence.name.clone(),
+                node_num,
+                latitude,
+                longitude,
+                notify: fence.notify,
+            });
+        }
+
+        transitions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Roughly 1.1 km square
+    fn square_fence(hysteresis_meters: f64) -> Geofences {
+        let corner = |latitude, longitude| GeofencePoint {
+            latitude,
+            longitude,
+        };
+
+        Geofences::new(GeofenceConfig {
+            fences: vec![Geofence {
+                id: "ops".into(),
+                name: "Operations area".into(),
+                shape: GeofenceShape::Polygon {
+                    vertices: vec![
+                        corner(47.0, 8.0),
+                        corner(47.0, 8.01),
+                        corner(47.01, 8.01),
+                        corner(47.01, 8.0),
+                    ],
+                },
+                hysteresis_meters,
+                notify: true,
+            }],
+        })
+    }
+
+    fn kinds(transitions: &[GeofenceTransition]) -> Vec<GeofenceTransitionKind> {
+        transitions.iter().map(|t| t.kind).collect()
+    }
+
+    /// Drives east from the middle of the fence, out past its eastern edge and back
+    fn drive(fences: &mut Geofences, longitudes: &[f64]) -> Vec<GeofenceTransition> {
+        longitudes
+            .iter()
+            .flat_map(|longitude| fences.evaluate(1, 47.005, *longitude))
+            .collect()
+    }
+
+    #[test]
+    fn polygon_distance_to_boundary() {
+        let fences = square_fence(0.0);
+        let shape = &fences.config.fences[0].shape;
+
+        // The eastern edge is at 8.01, 0.001 degrees of longitude is ~76 m here
+        let inside = shape.signed_distance_meters(47.005, 8.009);
+        let outside = shape.signed_distance_meters(47.005, 8.011);
+
+        assert!((inside + 76.0).abs() < 1.0, "{}", inside);
+        assert!((outside - 76.0).abs() < 1.0, "{}", outside);
+        assert!(shape.signed_distance_meters(46.0, 8.005) > 100_000.0);
+    }
+
+    #[test]
+    fn reports_one_exit_and_one_entry() {
+        let mut fences = square_fence(10.0);
+
+        let transitions = drive(
+            &mut fences,
+            &[
+                8.005, 8.008, 8.0099, 8.0101, 8.012, 8.015, 8.012, 8.009, 8.005,
+            ],
+        );
+
+        assert_eq!(
+            kinds(&transitions),
+            [
+                GeofenceTransitionKind::Violation,
+                GeofenceTransitionKind::Entry
+            ]
+        );
+        assert_eq!(transitions[0].fence_name, "Operations area");
+        assert_eq!(transitions[0].longitude, 8.012);
+        assert_eq!(transitions[1].longitude, 8.009);
+    }
+
+    #[test]
+    fn hysteresis_absorbs_jitter_on_the_boundary() {
+        // Sitting on the eastern edge, jittering ~5 m either side of it
+        let jitter = [8.005, 8.00994, 8.01006, 8.00994, 8.01006, 8.00994, 8.01006];
+
+        let without_hysteresis = drive(&mut square_fence(0.0), &jitter);
+        assert_eq!(without_hysteresis.len(), 5);
+
+        let mut fences = square_fence(20.0);
+        assert!(drive(&mut fences, &jitter).is_empty());
+
+        // Clearly leaving and coming back still reports once each
+        let transitions = drive(&mut fences, &[8.0106, 8.01006, 8.00994, 8.0094]);
+        assert_eq!(
+            kinds(&transitions),
+            [
+                GeofenceTransitionKind::Violation,
+                GeofenceTransitionKind::Entry
+            ]
+        );
+    }
+
+    #[test]
+    fn circle_fence_and_first_position() {
+        let mut fences = Geofences::new(GeofenceConfig {
+            fences: vec![Geofence {
+                id: "base".into(),
+                name: "Base".into(),
+                shape: GeofenceShape::Circle {
+                    center: GeofencePoint {
+                        latitude: 47.0,
+                        longitude: 8.0,
+                    },
+                    radius_meters: 500.0,
+                },
+                hysteresis_meters: 0.0,
+                notify: false,
+            }],
+        });
+
+        // First seen outside, which isn't an exit
+        assert!(fences.evaluate(1, 47.01, 8.0).is_empty());
+        assert_eq!(
+            kinds(&fences.evaluate(1, 47.001, 8.0)),
+            [GeofenceTransitionKind::Entry]
+        );
+
+        // Changing a fence's shape forgets which side of it nodes were on
+        let mut config = fences.config.clone();
+        if let GeofenceShape::Circle { radius_meters, .. } = &mut config.fences[0].shape {
+            *radius_meters = 50.0;
+        }
+        fences.set_config(config);
+        assert!(fences.evaluate(1, 47.001, 8.0).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_fences() {
+        let mut config = square_fence(0.0).config;
+        assert!(config.validate().is_ok());
+
+        config.fences.push(config.fences[0].clone());
+        assert!(config.validate().is_err());
+
+        config.fences.pop();
+        config.fences[0].shape = GeofenceShape::Polygon {
+            vertices: vec![GeofencePoint {
+                latitude: 47.0,
+                longitude: 8.0,
+            }],
+        };
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,442 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use super::dispatcher::SystemNotification;
+use super::preferences::{NotificationCategory, NotificationGrouping};
+
+/// How often held back notifications are checked for being due
+pub const NOTIFICATION_GROUPING_TICK: Duration = Duration::from_secs(1);
+
+/// Notifications are grouped by category and by what they're about within it,
+/// e.g. the channel of a message
+type GroupKey = (NotificationCategory, Option<String>);
+
+#[derive(Clone, Debug)]
+struct NotificationGroup {
+    opened: Instant,
+    held: u32,
+    nodes: BTreeSet<u32>,
+}
+
+impl NotificationGroup {
+    fn new(opened: Instant) -> Self {
+        Self {
+            opened,
+            held: 0,
+            nodes: BTreeSet::new(),
+        }
+    }
+
+    fn hold(&mut self, notification: &SystemNotification) {
+        self.held += 1;
+        self.nodes.extend(notification.node_num);
+    }
+}
+
+fn category_title(category: NotificationCategory) -> &'static str {
+    match category {
+        NotificationCategory::DirectMessage => "Direct messages",
+        NotificationCategory::ChannelMessage => "Channel messages",
+        NotificationCategory::NodeOffline => "Nodes offline",
+        NotificationCategory::LowBattery => "Low battery",
+        NotificationCategory::NetworkPartition => "Network partitioned",
+        NotificationCategory::DeviceStatus => "Device status",
+        NotificationCategory::Script => "Script notifications",
+        NotificationCategory::Geofence => "Geofences",
+    }
+}
+
+fn category_noun(category: NotificationCategory, count: u32) -> &'static str {
+    let (singular, plural) = match category {
+        NotificationCategory::DirectMessage | NotificationCategory::ChannelMessage => {
+            ("message", "messages")
+        }
+        NotificationCategory::NodeOffline => ("offline alert", "offline alerts"),
+        NotificationCategory::LowBattery => ("low battery alert", "low battery alerts"),
+        NotificationCategory::NetworkPartition => ("partition alert", "partition alerts"),
+        NotificationCategory::DeviceStatus => ("device status alert", "device status alerts"),
+        NotificationCategory::Script => ("script notification", "script notifications"),
+        NotificationCategory::Geofence => ("geofence alert", "geofence alerts"),
+    };
+
+    if count == 1 {
+        singular
+    } else {
+        plural
+    }
+}
+
+/// Describes a group, e.g. "5 new messages on LongFast from 3 nodes"
+fn describe((category, label): &GroupKey, group: &NotificationGroup) -> String {
+    let mut description = format!(
+        "{} new {}",
+        group.held,
+        category_noun(*category, group.held)
+    );
+
+    if let Some(label) = label {
+        description.push_str(&format!(" on {}", label));
+    }
+
+    match group.nodes.len() {
+        0 => {}
+        1 => description.push_str(" from 1 node"),
+        nodes => description.push_str(&format!(" from {} nodes", nodes)),
+    }
+
+    description
+}
+
+/// Holds back bursts of system notifications and turns them into summaries.
+///
+/// Without a digest interval, the first notification of a group is shown right away
+/// and opens a window in which later ones are held back. Once the window has passed
+/// the held notifications are shown as one summary, and the next notification opens
+/// a new window. In digest mode every notification is held back, and all of them are
+/// shown as one digest once the interval has passed since the first. Nothing carries
+/// over a quiet period in either mode.
+#[derive(Debug, Default)]
+pub struct NotificationGrouper {
+    groups: BTreeMap<GroupKey, NotificationGroup>,
+    digest_started: Option<Instant>,
+}
+
+impl NotificationGrouper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `notification` if it should be shown now, otherwise holds it back
+    /// until `take_due`
+    pub fn push(
+        &mut self,
+        settings: &NotificationGrouping,
+        notification: SystemNotification,
+        now: Instant,
+    ) -> Option<SystemNotification> {
+        if !settings.enabled
+            || settings.bypasses(
+                notification.category,
+                &notification.title,
+                &notification.body,
+            )
+        {
+            return Some(notification);
+        }
+
+        let key = (notification.category, notification.group.clone());
+
+        if settings.digest_interval_mins.is_some() {
+            self.digest_started.get_or_insert(now);
+            self.groups
+                .entry(key)
+                .or_insert_with(|| NotificationGroup::new(now))
+                .hold(&notification);
+
+            return None;
+        }
+
+        let window = Duration::from_secs(settings.window_secs.into());
+
+        match self.groups.get_mut(&key) {
+            Some(group) if now.duration_since(group.opened) < window => {
+                group.hold(&notification);
+                None
+            }
+            _ => {
+                self.groups.insert(key, NotificationGroup::new(now));
+                Some(notification)
+            }
+        }
+    }
+
+    /// Removes the groups whose window or digest interval has passed, returning the
+    /// summaries to show for them. Everything held back is returned if grouping has
+    /// been turned off since.
+    pub fn take_due(
+        &mut self,
+        settings: &NotificationGrouping,
+        now: Instant,
+    ) -> Vec<SystemNotification> {
+        if !settings.enabled {
+            return self.take_all();
+        }
+
+        match settings.digest_interval_mins {
+            Some(interval_mins) => {
+                let interval = Duration::from_secs(u64::from(interval_mins) * 60);
+
+                match self.digest_started {
+                    Some(started) if now.duration_since(started) >= interval => {
+                        self.take_digest().into_iter().collect()
+                    }
+                    Some(_) => vec![],
+                    // Groups left over from before digest mode was turned on
+                    None => self.take_all(),
+                }
+            }
+            None => {
+                let window = Duration::from_secs(settings.window_secs.into());
+                self.digest_started = None;
+
+                let due_keys: Vec<GroupKey> = self
+                    .groups
+                    .iter()
+                    .filter(|(_, group)| now.duration_since(group.opened) >= window)
+                    .map(|(key, _)| key.clone())
+                    .collect();
+
+                due_keys
+                    .into_iter()
+                    .filter_map(|key| {
+                        let group = self.groups.remove(&key)?;
+                        Self::summary(key, &group)
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Removes all groups, returning a summary for each one with held notifications
+    pub fn take_all(&mut self) -> Vec<SystemNotification> {
+        self.digest_started = None;
+
+        std::mem::take(&mut self.groups)
+            .into_iter()
+            .filter_map(|(key, group)| Self::summary(key, &group))
+            .collect()
+    }
+
+    fn summary(key: GroupKey, group: &NotificationGroup) -> Option<SystemNotification> {
+        if group.held == 0 {
+            return None;
+        }
+
+        Some(SystemNotification {
+            category: key.0,
+            node_num: None,
+            title: category_title(key.0).into(),
+            body: describe(&key, group),
+            group: key.1,
+        })
+    }
+
+    fn take_digest(&mut self) -> Option<SystemNotification> {
+        self.digest_started = None;
+
+        let groups = std::mem::take(&mut self.groups);
+        let total: u32 = groups.values().map(|group| group.held).sum();
+
+        if total == 0 {
+            return None;
+        }
+
+        let (category, _) = groups.keys().next()?.clone();
+
+        let lines: Vec<String> = groups
+            .iter()
+            .map(|(key, group)| describe(key, group))
+            .collect();
+
+        Some(SystemNotification {
+            category,
+            node_num: None,
+            title: format!(
+                "{} new notification{}",
+                total,
+                if total == 1 { "" } else { "s" }
+            ),
+            body: lines.join("\n"),
+            group: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel_message(node_num: u32, channel: &str, text: &str) -> SystemNotification {
+        SystemNotification {
+            category: NotificationCategory::ChannelMessage,
+            node_num: Some(node_num),
+            title: format!("!{:08x} in {}", node_num, channel),
+            body: text.into(),
+            group: Some(channel.into()),
+        }
+    }
+
+    fn low_battery(node_num: u32) -> SystemNotification {
+        SystemNotification {
+            category: NotificationCategory::LowBattery,
+            node_num: Some(node_num),
+            title: "Low battery".into(),
+            body: format!("!{:08x} is at 5% battery", node_num),
+            group: None,
+        }
+    }
+
+    fn secs(start: Instant, secs: u64) -> Instant {
+        start + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn coalesces_within_window_and_resets_after_quiet_period() {
+        let settings = NotificationGrouping::default();
+        let mut grouper = NotificationGrouper::new();
+        let start = Instant::now();
+
+        // The first message is shown, the next five within the window are held back
+        assert!(grouper
+            .push(&settings, channel_message(1, "LongFast", "a"), start)
+            .is_some());
+
+        for (i, node_num) in [1, 2, 3, 2, 3].into_iter().enumerate() {
+            let now = secs(start, 5 + i as u64);
+            assert!(grouper
+                .push(&settings, channel_message(node_num, "LongFast", "b"), now)
+                .is_none());
+        }
+
+        // Other channels and categories are grouped separately
+        assert!(grouper
+            .push(
+                &settings,
+                channel_message(4, "Hikers", "c"),
+                secs(start, 10)
+            )
+            .is_some());
+        assert!(grouper
+            .push(&settings, low_battery(5), secs(start, 20))
+            .is_some());
+
+        assert!(grouper.take_due(&settings, secs(start, 59)).is_empty());
+
+        let summaries = grouper.take_due(&settings, secs(start, 60));
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].title, "Channel messages");
+        assert_eq!(summaries[0].body, "5 new messages on LongFast from 3 nodes");
+
+        // Groups with nothing held back close without a summary
+        assert!(grouper.take_due(&settings, secs(start, 80)).is_empty());
+
+        // After the quiet period the next message is shown right away again
+        assert!(grouper
+            .push(
+                &settings,
+                channel_message(1, "LongFast", "d"),
+                secs(start, 300)
+            )
+            .is_some());
+        assert!(grouper
+            .push(
+                &settings,
+                channel_message(1, "LongFast", "e"),
+                secs(start, 301)
+            )
+            .is_none());
+
+        let summaries = grouper.take_due(&settings, secs(start, 360));
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].body, "1 new message on LongFast from 1 node");
+        assert!(grouper.take_all().is_empty());
+    }
+
+    #[test]
+    fn digest_batches_everything_on_its_interval() {
+        let settings = NotificationGrouping {
+            digest_interval_mins: Some(10),
+            ..Default::default()
+        };
+        let mut grouper = NotificationGrouper::new();
+        let start = Instant::now();
+
+        for i in 0..3 {
+            assert!(grouper
+                .push(
+                    &settings,
+                    channel_message(i, "LongFast", "hi"),
+                    secs(start, i.into())
+                )
+                .is_none());
+        }
+
+        assert!(grouper
+            .push(&settings, low_battery(7), secs(start, 120))
+            .is_none());
+
+        assert!(grouper.take_due(&settings, secs(start, 599)).is_empty());
+
+        let digest = grouper.take_due(&settings, secs(start, 600));
+        assert_eq!(digest.len(), 1);
+        assert_eq!(digest[0].title, "4 new notifications");
+        assert_eq!(
+            digest[0].body,
+            "3 new messages on LongFast from 3 nodes\n1 new low battery alert from 1 node"
+        );
+
+        // Nothing is sent for a quiet interval, and the next digest is timed from
+        // the first notification after it
+        assert!(grouper.take_due(&settings, secs(start, 1200)).is_empty());
+
+        assert!(grouper
+            .push(&settings, low_battery(8), secs(start, 1500))
+            .is_none());
+        assert!(grouper.take_due(&settings, secs(start, 1800)).is_empty());
+
+        let digest = grouper.take_due(&settings, secs(start, 2100));
+        assert_eq!(digest.len(), 1);
+        assert_eq!(digest[0].title, "1 new notification");
+    }
+
+    #[test]
+    fn bypassed_notifications_are_never_held() {
+        let settings = NotificationGrouping {
+            digest_interval_mins: Some(10),
+            ..Default::default()
+        };
+        let mut grouper = NotificationGrouper::new();
+        let start = Instant::now();
+
+        let direct_message = SystemNotification {
+            category: NotificationCategory::DirectMessage,
+            group: None,
+            ..channel_message(1, "LongFast", "hi")
+        };
+
+        let sos = SystemNotification {
+            category: NotificationCategory::Script,
+            node_num: None,
+            title: "SOS from 42".into(),
+            body: "SOS at the trailhead".into(),
+            group: None,
+        };
+
+        for i in 0..3 {
+            let now = secs(start, i);
+            assert!(grouper
+                .push(&settings, direct_message.clone(), now)
+                .is_some());
+            assert!(grouper.push(&settings, sos.clone(), now).is_some());
+        }
+
+        assert!(grouper.take_due(&settings, secs(start, 600)).is_empty());
+
+        // Turning grouping off releases whatever was held back
+        assert!(grouper
+            .push(&settings, low_battery(3), secs(start, 700))
+            .is_none());
+
+        let disabled = NotificationGrouping {
+            enabled: false,
+            ..settings
+        };
+
+        let released = grouper.take_due(&disabled, secs(start, 701));
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].body, "1 new low battery alert from 1 node");
+        assert!(grouper
+            .push(&disabled, low_battery(3), secs(start, 702))
+            .is_some());
+    }
+}
@@ -0,0 +1,280 @@
+use std::time::Duration;
+
+use log::{debug, trace, warn};
+use tauri::Manager;
+
+use crate::device::helpers::{get_current_time_u32, get_node_user_name};
+use crate::device::liveness::NodeLivenessTransition;
+use crate::device::MeshDevice;
+use crate::ipc::events::{dispatch_geofence_transition, dispatch_notification_alert};
+use crate::ipc::{GeofenceTransitionEvent, EVENT_API_VERSION};
+use crate::shutdown::shutdown_signal;
+use crate::state::mesh_devices::all_devices;
+use crate::state::{self, DeviceKey};
+
+use self::dispatcher::SystemNotification;
+use self::geofences::GeofenceTransitionKind;
+use self::rules::RuleAlert;
+use self::webhooks::{enqueue_webhook, WebhookPayload};
+
+pub mod dispatcher;
+pub mod geofences;
+pub mod grouping;
+pub mod preferences;
+pub mod rules;
+pub mod webhooks;
+
+/// How often time-based notification rules (e.g. device unresponsive) are evaluated
+pub const NOTIFICATION_RULES_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Evaluates the low battery rule for a node that reported device metrics
+pub fn evaluate_battery_rules<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: &DeviceKey,
+    device: &MeshDevice,
+    node_num: u32,
+    battery_level: u32,
+) {
+    let alert = match handle.try_state::<state::notification_rules::NotificationRulesState>() {
+        Some(rules_state) => match rules_state.inner.lock() {
+            Ok(mut rules) => {
+                rules.evaluate_battery(node_num, battery_level, get_current_time_u32())
+            }
+            Err(e) => {
+                warn!("Failed to lock notification rules: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    if let Some(alert) = alert {
+        dispatch_rule_alert(handle, Some(device_key), Some(device), alert);
+    }
+}
+
+/// Alerts when the liveness tracker saw a node go offline
+pub fn dispatch_liveness_alert<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: &DeviceKey,
+    device: &MeshDevice,
+    transition: &NodeLivenessTransition,
+) {
+    if let Some(alert) = RuleAlert::from_liveness_transition(transition) {
+        dispatch_rule_alert(handle, Some(device_key), Some(device), alert);
+    }
+}
+
+/// Checks a node's new position against the geofences, emitting an event for each
+/// fence it crossed and alerting for fences with notifications turned on
+pub fn evaluate_geofences<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: &DeviceKey,
+    device: &MeshDevice,
+    node_num: u32,
+    latitude: f64,
+    longitude: f64,
+) {
+    let transitions = match handle.try_state::<state::geofences::GeofencesState>() {
+        Some(geofences_state) => match geofences_state.inner.lock() {
+            Ok(mut geofences) => geofences.evaluate(node_num, latitude, longitude),
+            Err(e) => {
+                warn!("Failed to lock geofences: {}", e);
+                return;
+            }
+        },
+        None => return,
+    };
+
+    for transition in transitions {
+        let alert = transition.notify.then(|| match transition.kind {
+            GeofenceTransitionKind::Violation => RuleAlert::GeofenceViolation {
+                node_num,
+                fence_id: transition.fence_id.clone(),
+                fence_name: transition.fence_name.clone(),
+            },
+            GeofenceTransitionKind::Entry => RuleAlert::GeofenceEntry {
+                node_num,
+                fence_id: transition.fence_id.clone(),
+                fence_name: transition.fence_name.clone(),
+            },
+        });
+
+        let event = GeofenceTransitionEvent {
+            api_version: EVENT_API_VERSION,
+            device_key: device_key.clone(),
+            transition,
+        };
+
+        if let Err(e) = dispatch_geofence_transition(handle, event) {
+            warn!("Failed to dispatch geofence transition: {}", e);
+        }
+
+        if let Some(alert) = alert {
+            dispatch_rule_alert(handle, Some(device_key), Some(device), alert);
+        }
+    }
+}
+
+/// Emits an alert to the UI, as a system notification unless suppressed, and to
+/// any webhooks that want it. `device` is used to name the node the alert is about,
+/// and `device_key` scopes the alert to the device's connection.
+pub fn dispatch_rule_alert<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    device_key: Option<&DeviceKey>,
+    device: Option<&MeshDevice>,
+    alert: RuleAlert,
+) {
+    debug!("Dispatching notification rule alert {:?}", alert);
+
+    let node_name = |node_num: u32| {
+        device
+            .and_then(|device| get_node_user_name(device, &node_num))
+            .unwrap_or_else(|| format!("!{:08x}", node_num))
+    };
+
+    let (title, body) = match &alert {
+        RuleAlert::LowBattery {
+            node_num,
+            battery_level,
+        } => (
+            "Low battery".to_string(),
+            format!("{} is at {}% battery", node_name(*node_num), battery_level),
+        ),
+        RuleAlert::NodeOffline { node_num, .. } => (
+            "Node offline".to_string(),
+            format!("{} hasn't been heard from recently", node_name(*node_num)),
+        ),
+        RuleAlert::DeviceUnresponsive { device_key, .. } => (
+            "Device unresponsive".to_string(),
+            format!("Device on {} stopped sending packets", device_key),
+        ),
+        RuleAlert::NetworkPartition { component_count } => (
+            "Network partitioned".to_string(),
+            format!(
+                "The mesh has split into {} groups that can't reach each other",
+                component_count
+            ),
+        ),
+        RuleAlert::GeofenceViolation {
+            node_num,
+            fence_name,
+            ..
+        } => (
+            "Left geofence".to_string(),
+            format!("{} left {}", node_name(*node_num), fence_name),
+        ),
+        RuleAlert::GeofenceEntry {
+            node_num,
+            fence_name,
+            ..
+        } => (
+            "Entered geofence".to_string(),
+            format!("{} entered {}", node_name(*node_num), fence_name),
+        ),
+    };
+
+    let webhook_payload = WebhookPayload::from_rule_alert(
+        &alert,
+        alert.node_num().map(node_name),
+        body.clone(),
+        get_current_time_u32(),
+    );
+
+    let notification = SystemNotification {
+        category: alert.category(),
+        node_num: alert.node_num(),
+        title,
+        body,
+        group: None,
+    };
+
+    if let Err(e) = dispatch_notification_alert(handle, device_key.cloned(), alert) {
+        warn!("Failed to dispatch notification alert: {}", e);
+    }
+
+    dispatcher::enqueue(handle, notification);
+
+    if let Some(payload) = webhook_payload {
+        enqueue_webhook(handle, payload);
+    }
+}
+
+/// Periodically queues the summaries of grouped notifications that are due
+pub fn spawn_notification_grouping_timer(handle: tauri::AppHandle) {
+    trace!("Spawning notification grouping timer");
+
+    tauri::async_runtime::spawn(async move {
+        let shutdown = shutdown_signal(&handle);
+        let mut interval = tokio::time::interval(grouping::NOTIFICATION_GROUPING_TICK);
+
+        while shutdown.tick(&mut interval).await {
+            dispatcher::enqueue_due_summaries(&handle);
+        }
+
+        trace!("Stopped notification grouping timer");
+    });
+}
+
+/// Periodically evaluates rules that depend on the passage of time
+/// rather than on an incoming packet.
+pub fn spawn_notification_rules_timer(handle: tauri::AppHandle) {
+    trace!("Spawning notification rules timer");
+
+    tauri::async_runtime::spawn(async move {
+        let shutdown = shutdown_signal(&handle);
+        let mut interval = tokio::time::interval(NOTIFICATION_RULES_INTERVAL);
+
+        while shutdown.tick(&mut interval).await {
+            let mesh_devices = handle.state::<state::mesh_devices::MeshDevicesState>();
+            let rules_state = handle.state::<state::notification_rules::NotificationRulesState>();
+
+            let now = get_current_time_u32();
+
+            for (device_key, packet_api) in all_devices(&mesh_devices.inner).await {
+                let packet_api = packet_api.lock().await;
+                let device = &packet_api.device;
+
+                // Nodes going offline are alerted on by the liveness tracker, see
+                // `dispatch_liveness_alert`
+                let alert = match rules_state.inner.lock() {
+                    Ok(mut rules) => rules.evaluate_device_activity(
+                        &device_key,
+                        packet_api.last_packet_received,
+                        now,
+                    ),
+                    Err(e) => {
+                        warn!("Failed to lock notification rules: {}", e);
+                        continue;
+                    }
+                };
+
+                if let Some(alert) = alert {
+                    dispatch_rule_alert(&handle, Some(&device_key), Some(device), alert);
+                }
+            }
+
+            let component_count = match handle.state::<state::graph::GraphState>().inner.read() {
+                Ok(graph) => graph.connected_components() as u32,
+                Err(e) => {
+                    warn!("Failed to lock graph: {}", e);
+                    continue;
+                }
+            };
+
+            let partition_alert = match rules_state.inner.lock() {
+                Ok(mut rules) => rules.evaluate_partition(component_count, now),
+                Err(e) => {
+                    warn!("Failed to lock notification rules: {}", e);
+                    None
+                }
+            };
+
+            if let Some(alert) = partition_alert {
+                dispatch_rule_alert(&handle, None, None, alert);
+            }
+        }
+
+        trace!("Stopped notification rules timer");
+    });
+}
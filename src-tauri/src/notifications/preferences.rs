@@ -0,0 +1,485 @@
+use chrono::{DateTime, FixedOffset, Local, Timelike, Utc};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// Longest window in which notifications of the same category are grouped
+pub const MAX_GROUPING_WINDOW_SECS: u32 = 60 * 60;
+
+/// Longest time between two digest notifications
+pub const MAX_DIGEST_INTERVAL_MINS: u32 = 24 * 60;
+
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, Type,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum NotificationCategory {
+    DirectMessage,
+    ChannelMessage,
+    NodeOffline,
+    LowBattery,
+    NetworkPartition,
+    DeviceStatus,
+    Script,
+    Geofence,
+}
+
+/// Whether system notifications are shown for each category
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NotificationCategories {
+    pub direct_message: bool,
+    pub channel_message: bool,
+    pub node_offline: bool,
+    pub low_battery: bool,
+    pub network_partition: bool,
+
+    /// Connected device disconnecting or becoming unresponsive
+    pub device_status: bool,
+
+    /// Raised by a packet script's `notify`
+    pub script: bool,
+
+    /// Node leaving or entering a geofence that has notifications turned on
+    pub geofence: bool,
+}
+
+impl Default for NotificationCategories {
+    fn default() -> Self {
+        Self {
+            direct_message: true,
+            channel_message: true,
+            node_offline: true,
+            low_battery: true,
+            network_partition: true,
+            device_status: true,
+            script: true,
+            geofence: true,
+        }
+    }
+}
+
+impl NotificationCategories {
+    pub fn is_enabled(&self, category: NotificationCategory) -> bool {
+        match category {
+            NotificationCategory::DirectMessage => self.direct_message,
+            NotificationCategory::ChannelMessage => self.channel_message,
+            NotificationCategory::NodeOffline => self.node_offline,
+            NotificationCategory::LowBattery => self.low_battery,
+            NotificationCategory::NetworkPartition => self.network_partition,
+            NotificationCategory::DeviceStatus => self.device_status,
+            NotificationCategory::Script => self.script,
+            NotificationCategory::Geofence => self.geofence,
+        }
+    }
+}
+
+/// Daily window in which system notifications are held back. The window
+/// includes its start minute and ends just before its end minute, wrapping
+/// past midnight when the end is earlier than the start.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct QuietHours {
+    /// Minutes after midnight at which quiet hours start
+    pub start_minute: u32,
+
+    /// Minutes after midnight at which quiet hours end
+    pub end_minute: u32,
+
+    /// Fixed offset from UTC in minutes, the system time zone is used if unset
+    pub utc_offset_mins: Option<i32>,
+}
+
+impl QuietHours {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.start_minute >= MINUTES_PER_DAY || self.end_minute >= MINUTES_PER_DAY {
+            return Err("Quiet hours must start and end within a single day".into());
+        }
+
+        if let Some(offset_mins) = self.utc_offset_mins {
+            if !(-12 * 60..=14 * 60).contains(&offset_mins) {
+                return Err(format!("Invalid UTC offset of {} minutes", offset_mins));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let minute = self.local_minute_of_day(now);
+
+        if self.start_minute <= self.end_minute {
+            self.start_minute <= minute && minute < self.end_minute
+        } else {
+            minute >= self.start_minute || minute < self.end_minute
+        }
+    }
+
+    fn local_minute_of_day(&self, now: DateTime<Utc>) -> u32 {
+        let offset = self
+            .utc_offset_mins
+            .and_then(|offset_mins| FixedOffset::east_opt(offset_mins * 60));
+
+        let (hour, minute) = match offset {
+            Some(offset) => {
+                let local = now.with_timezone(&offset);
+                (local.hour(), local.minute())
+            }
+            None => {
+                let local = now.with_timezone(&Local);
+                (local.hour(), local.minute())
+            }
+        };
+
+        hour * 60 + minute
+    }
+}
+
+/// Grouping of bursts of system notifications into summaries
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NotificationGrouping {
+    pub enabled: bool,
+
+    /// After a notification is shown, later ones of the same category within this
+    /// many seconds are held back and shown as a single summary
+    pub window_secs: u32,
+
+    /// Holds back all notifications and shows them as one digest every this many
+    /// minutes instead of grouping by window
+    pub digest_interval_mins: Option<u32>,
+
+    /// Categories that are always shown right away
+    pub bypass_categories: Vec<NotificationCategory>,
+
+    /// Notifications with any of these words in their title or body, ignoring case,
+    /// are always shown right away
+    pub bypass_keywords: Vec<String>,
+}
+
+impl Default for NotificationGrouping {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window_secs: 60,
+            digest_interval_mins: None,
+            bypass_categories: vec![NotificationCategory::DirectMessage],
+            bypass_keywords: vec!["SOS".into()],
+        }
+    }
+}
+
+impl NotificationGrouping {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.window_secs == 0 || self.window_secs > MAX_GROUPING_WINDOW_SECS {
+            return Err(format!(
+                "Grouping window must be between 1 and {} seconds",
+                MAX_GROUPING_WINDOW_SECS
+            ));
+        }
+
+        if let Some(interval_mins) = self.digest_interval_mins {
+            if interval_mins == 0 || interval_mins > MAX_DIGEST_INTERVAL_MINS {
+                return Err(format!(
+                    "Digest interval must be between 1 and {} minutes",
+                    MAX_DIGEST_INTERVAL_MINS
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether a notification is shown right away regardless of grouping
+    pub fn bypasses(&self, category: NotificationCategory, title: &str, body: &str) -> bool {
+        if self.bypass_categories.contains(&category) {
+            return true;
+        }
+
+        let words: Vec<String> = [title, body].iter().flat_map(|text| words(text)).collect();
+
+        self.bypass_keywords.iter().any(|keyword| {
+            let keyword = words(keyword);
+            !keyword.is_empty() && words.windows(keyword.len()).any(|w| w == keyword)
+        })
+    }
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NotificationSuppression {
+    Muted,
+    CategoryDisabled,
+    NodeMuted,
+    QuietHours,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationPreferences {
+    /// Holds back every system notification, whatever its category
+    #[serde(default)]
+    pub muted: bool,
+
+    pub categories: NotificationCategories,
+    pub quiet_hours: Option<QuietHours>,
+
+    /// Nodes that never trigger system notifications
+    pub muted_nodes: Vec<u32>,
+
+    #[serde(default)]
+    pub grouping: NotificationGrouping,
+}
+
+impl NotificationPreferences {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(quiet_hours) = &self.quiet_hours {
+            quiet_hours.validate()?;
+        }
+
+        self.grouping.validate()
+    }
+
+    pub fn set_node_muted(&mut self, node_num: u32, muted: bool) {
+        if !muted {
+            self.muted_nodes.retain(|n| *n != node_num);
+        } else if !self.muted_nodes.contains(&node_num) {
+            self.muted_nodes.push(node_num);
+        }
+    }
+
+    /// Returns why a notification shouldn't be shown, if it shouldn't
+    pub fn suppression(
+        &self,
+        category: NotificationCategory,
+        node_num: Option<u32>,
+        now: DateTime<Utc>,
+    ) -> Option<NotificationSuppression> {
+        if self.muted {
+            return Some(NotificationSuppression::Muted);
+        }
+
+        if !self.categories.is_enabled(category) {
+            return Some(NotificationSuppression::CategoryDisabled);
+        }
+
+        if let Some(node_num) = node_num {
+            if self.muted_nodes.contains(&node_num) {
+                return Some(NotificationSuppression::NodeMuted);
+            }
+        }
+
+        match &self.quiet_hours {
+            Some(quiet_hours) if quiet_hours.contains(now) => {
+                Some(NotificationSuppression::QuietHours)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, hour, minute, 0).unwrap()
+    }
+
+    fn overnight(utc_offset_mins: Option<i32>) -> QuietHours {
+        QuietHours {
+            start_minute: 22 * 60,
+            end_minute: 7 * 60,
+            utc_offset_mins,
+        }
+    }
+
+    #[test]
+    fn quiet_hours_boundaries() {
+        let quiet_hours = overnight(Some(0));
+
+        assert!(!quiet_hours.contains(at(21, 59)));
+        assert!(quiet_hours.contains(at(22, 0)));
+        assert!(quiet_hours.contains(at(23, 59)));
+        assert!(quiet_hours.contains(at(0, 0)));
+        assert!(quiet_hours.contains(at(6, 59)));
+        assert!(!quiet_hours.contains(at(7, 0)));
+        assert!(!quiet_hours.contains(at(12, 0)));
+    }
+
+    #[test]
+    fn quiet_hours_within_a_day() {
+        let quiet_hours = QuietHours {
+            start_minute: 13 * 60,
+            end_minute: 14 * 60 + 30,
+            utc_offset_mins: Some(0),
+        };
+
+        assert!(!quiet_hours.contains(at(12, 59)));
+        assert!(quiet_hours.contains(at(13, 0)));
+        assert!(quiet_hours.contains(at(14, 29)));
+        assert!(!quiet_hours.contains(at(14, 30)));
+    }
+
+    #[test]
+    fn empty_quiet_hours_never_apply() {
+        let quiet_hours = QuietHours {
+            start_minute: 8 * 60,
+            end_minute: 8 * 60,
+            utc_offset_mins: Some(0),
+        };
+
+        assert!(!quiet_hours.contains(at(8, 0)));
+        assert!(!quiet_hours.contains(at(20, 0)));
+    }
+
+    #[test]
+    fn quiet_hours_use_offset() {
+        // 22:00 in UTC+2 is 20:00 UTC
+        let quiet_hours = overnight(Some(2 * 60));
+
+        assert!(!quiet_hours.contains(at(19, 59)));
+        assert!(quiet_hours.contains(at(20, 0)));
+        assert!(quiet_hours.contains(at(4, 59)));
+        assert!(!quiet_hours.contains(at(5, 0)));
+
+        // 07:00 in UTC-5 is 12:00 UTC, which wraps into the next UTC day
+        let quiet_hours = overnight(Some(-5 * 60));
+
+        assert!(quiet_hours.contains(at(3, 0)));
+        assert!(quiet_hours.contains(at(11, 59)));
+        assert!(!quiet_hours.contains(at(12, 0)));
+    }
+
+    #[test]
+    fn rejects_invalid_quiet_hours() {
+        assert!(overnight(None).validate().is_ok());
+        assert!(overnight(Some(14 * 60)).validate().is_ok());
+        assert!(overnight(Some(15 * 60)).validate().is_err());
+
+        let quiet_hours = QuietHours {
+            start_minute: 24 * 60,
+            ..overnight(None)
+        };
+        assert!(quiet_hours.validate().is_err());
+    }
+
+    #[test]
+    fn suppresses_disabled_categories() {
+        let mut preferences = NotificationPreferences::default();
+        preferences.categories.channel_message = false;
+
+        assert_eq!(
+            preferences.suppression(NotificationCategory::ChannelMessage, Some(1), at(12, 0)),
+            Some(NotificationSuppression::CategoryDisabled)
+        );
+        assert_eq!(
+            preferences.suppression(NotificationCategory::DirectMessage, Some(1), at(12, 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn suppresses_muted_nodes() {
+        let mut preferences = NotificationPreferences::default();
+        preferences.set_node_muted(3, true);
+        preferences.set_node_muted(3, true);
+
+        assert_eq!(preferences.muted_nodes, vec![3]);
+        assert_eq!(
+            preferences.suppression(NotificationCategory::LowBattery, Some(3), at(12, 0)),
+            Some(NotificationSuppression::NodeMuted)
+        );
+        assert_eq!(
+            preferences.suppression(NotificationCategory::LowBattery, Some(4), at(12, 0)),
+            None
+        );
+
+        // Notifications not about a node are unaffected
+        assert_eq!(
+            preferences.suppression(NotificationCategory::DeviceStatus, None, at(12, 0)),
+            None
+        );
+
+        preferences.set_node_muted(3, false);
+        assert_eq!(
+            preferences.suppression(NotificationCategory::LowBattery, Some(3), at(12, 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn grouping_bypass_matches_categories_and_whole_words() {
+        let grouping = NotificationGrouping::default();
+
+        assert!(grouping.bypasses(NotificationCategory::DirectMessage, "Alice", "hi"));
+        assert!(grouping.bypasses(NotificationCategory::Script, "SOS from 42", ""));
+        assert!(grouping.bypasses(NotificationCategory::ChannelMessage, "Bob", "sos!"));
+        assert!(!grouping.bypasses(NotificationCategory::ChannelMessage, "Sosa", "hi"));
+
+        let grouping = NotificationGrouping {
+            bypass_keywords: vec!["man down".into(), " ".into()],
+            ..Default::default()
+        };
+
+        assert!(grouping.bypasses(NotificationCategory::Script, "Alert", "Man  down!"));
+        assert!(!grouping.bypasses(NotificationCategory::Script, "Alert", "man, then down"));
+    }
+
+    #[test]
+    fn suppresses_during_quiet_hours() {
+        let preferences = NotificationPreferences {
+            quiet_hours: Some(overnight(Some(0))),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            preferences.suppression(NotificationCategory::DirectMessage, Some(1), at(23, 0)),
+            Some(NotificationSuppression::QuietHours)
+        );
+        assert_eq!(
+            preferences.suppression(NotificationCategory::DirectMessage, Some(1), at(9, 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn global_mute_suppresses_every_category() {
+        let mut preferences = NotificationPreferences {
+            muted: true,
+            ..Default::default()
+        };
+
+        for category in [
+            NotificationCategory::DirectMessage,
+            NotificationCategory::ChannelMessage,
+            NotificationCategory::DeviceStatus,
+        ] {
+            assert_eq!(
+                preferences.suppression(category, None, at(12, 0)),
+                Some(NotificationSuppression::Muted)
+            );
+        }
+
+        preferences.muted = false;
+        preferences.categories.channel_message = false;
+
+        assert_eq!(
+            preferences.suppression(NotificationCategory::ChannelMessage, None, at(12, 0)),
+            Some(NotificationSuppression::CategoryDisabled)
+        );
+        assert_eq!(
+            preferences.suppression(NotificationCategory::DirectMessage, None, at(12, 0)),
+            None
+        );
+    }
+}
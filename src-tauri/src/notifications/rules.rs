@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+
+use log::debug;
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::device::liveness::{NodeLiveness, NodeLivenessTransition};
+use crate::state::DeviceKey;
+
+use super::preferences::NotificationCategory;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationThresholds {
+    /// Alert when a node reports a battery level below this percentage
+    pub low_battery_percent: u32,
+
+    /// Alert when a node hasn't been heard from for this many minutes
+    pub node_offline_mins: u32,
+
+    /// Consider our own device unresponsive after this many minutes without packets
+    pub device_unresponsive_mins: u32,
+
+    /// Minimum time between repeated alerts for a condition that hasn't recovered
+    pub cooldown_mins: u32,
+}
+
+impl Default for NotificationThresholds {
+    fn default() -> Self {
+        Self {
+            low_battery_percent: 20,
+            node_offline_mins: 60,
+            device_unresponsive_mins: 20,
+            cooldown_mins: 12 * 60,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum RuleAlert {
+    #[serde(rename_all = "camelCase")]
+    LowBattery { node_num: u32, battery_level: u32 },
+
+    #[serde(rename_all = "camelCase")]
+    NodeOffline { node_num: u32, last_heard: u32 },
+
+    #[serde(rename_all = "camelCase")]
+    DeviceUnresponsive {
+        device_key: DeviceKey,
+        last_packet: u32,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    NetworkPartition { component_count: u32 },
+
+    #[serde(rename_all = "camelCase")]
+    GeofenceViolation {
+        node_num: u32,
+        fence_id: String,
+        fence_name: String,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    GeofenceEntry {
+        node_num: u32,
+        fence_id: String,
+        fence_name: String,
+    },
+}
+
+impl RuleAlert {
+    pub fn category(&self) -> NotificationCategory {
+        match self {
+            RuleAlert::LowBattery { .. } => NotificationCategory::LowBattery,
+            RuleAlert::NodeOffline { .. } => NotificationCategory::NodeOffline,
+            RuleAlert::DeviceUnresponsive { .. } => NotificationCategory::DeviceStatus,
+            RuleAlert::NetworkPartition { .. } => NotificationCategory::NetworkPartition,
+            RuleAlert::GeofenceViolation { .. } | RuleAlert::GeofenceEntry { .. } => {
+                NotificationCategory::Geofence
+            }
+        }
+    }
+
+    /// Alert for a node the liveness tracker saw go offline. Nodes only transition once
+    /// their state is known, so stale nodes in the radio's database never alert.
+    pub fn from_liveness_transition(transition: &NodeLivenessTransition) -> Option<Self> {
+        (transition.old_status == NodeLiveness::Online
+            && transition.new_status == NodeLiveness::Offline)
+            .then(|| RuleAlert::NodeOffline {
+                node_num: transition.node_num,
+                last_heard: transition.last_heard,
+            })
+    }
+
+    /// Node the alert is about, if any
+    pub fn node_num(&self) -> Option<u32> {
+        match self {
+            RuleAlert::LowBattery { node_num, .. }
+            | RuleAlert::NodeOffline { node_num, .. }
+            | RuleAlert::GeofenceViolation { node_num, .. }
+            | RuleAlert::GeofenceEntry { node_num, .. } => Some(*node_num),
+            RuleAlert::DeviceUnresponsive { .. } | RuleAlert::NetworkPartition { .. } => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum RuleKey {
+    LowBattery(u32),
+    DeviceUnresponsive(DeviceKey),
+    NetworkPartition,
+}
+
+/// Evaluates notification rules, alerting once when a condition is crossed.
+///
+/// A condition re-arms once it recovers (e.g. the battery is charged again),
+/// or repeats after the cooldown if it never recovers.
+#[derive(Clone, Debug, Default)]
+pub struct NotificationRules {
+    pub thresholds: NotificationThresholds,
+    triggered: HashMap<RuleKey, u32>,
+}
+
+impl NotificationRules {
+    pub fn new(thresholds: NotificationThresholds) -> Self {
+        Self {
+            thresholds,
+            triggered: HashMap::new(),
+        }
+    }
+
+    pub fn evaluate_battery(
+        &mut self,
+        node_num: u32,
+        battery_level: u32,
+        now: u32,
+    ) -> Option<RuleAlert> {
+        let crossed = battery_level < self.thresholds.low_battery_percent;
+
+        self.evaluate(RuleKey::LowBattery(node_num), crossed, now, || {
+            RuleAlert::LowBattery {
+                node_num,
+                battery_level,
+            }
+        })
+    }
+
+    pub fn evaluate_device_activity(
+        &mut self,
+        device_key: &DeviceKey,
+        last_packet: u32,
+        now: u32,
+    ) -> Option<RuleAlert> {
+        let unresponsive_secs = self.thresholds.device_unresponsive_mins.saturating_mul(60);
+        let crossed = now.saturating_sub(last_packet) > unresponsive_secs;
+
+        self.evaluate(
+            RuleKey::DeviceUnresponsive(device_key.clone()),
+            crossed,
+            now,
+            || RuleAlert::DeviceUnresponsive {
+                device_key: device_key.clone(),
+                last_packet,
+            },
+        )
+    }
+
+    /// Alerts when the graph splits into groups of nodes that can't reach each other
+    pub fn evaluate_partition(&mut self, component_count: u32, now: u32) -> Option<RuleAlert> {
+        self.evaluate(RuleKey::NetworkPartition, component_count > 1, now, || {
+            RuleAlert::NetworkPartition { component_count }
+        })
+    }
+
+    fn evaluate<F>(
+        &mut self,
+        key: RuleKey,
+        crossed: bool,
+        now: u32,
+        build_alert: F,
+    ) -> Option<RuleAlert>
+    where
+        F: FnOnce() -> RuleAlert,
+    {
+        if !crossed {
+            if self.triggered.remove(&key).is_some() {
+                debug!("Notification rule {:?} recovered, re-arming", key);
+            }
+
+            return None;
+        }
+
+        let cooldown_secs = self.thresholds.cooldown_mins.saturating_mul(60);
+
+        if let Some(triggered_at) = self.triggered.get(&key) {
+            if now.saturating_sub(*triggered_at) < cooldown_secs {
+                return None;
+            }
+        }
+
+        debug!("Notification rule {:?} crossed", key);
+        self.triggered.insert(key, now);
+
+        Some(build_alert())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::liveness::{NodeLivenessConfig, NodeLivenessTracker};
+
+    const NOW: u32 = 1_700_000_000;
+
+    #[test]
+    fn alerts_once_per_battery_crossing() {
+        let mut rules = NotificationRules::default();
+
+        assert_eq!(rules.evaluate_battery(1, 50, NOW), None);
+
+        assert_eq!(
+            rules.evaluate_battery(1, 19, NOW + 1),
+            Some(RuleAlert::LowBattery {
+                node_num: 1,
+                battery_level: 19
+            })
+        );
+
+        // Still low on every following packet, no repeat alerts
+        assert_eq!(rules.evaluate_battery(1, 18, NOW + 2), None);
+        assert_eq!(rules.evaluate_battery(1, 15, NOW + 3), None);
+
+        // Recovery re-arms the rule
+        assert_eq!(rules.evaluate_battery(1, 80, NOW + 4), None);
+        assert!(rules.evaluate_battery(1, 10, NOW + 5).is_some());
+    }
+
+    #[test]
+    fn repeats_after_cooldown() {
+        let mut rules = NotificationRules::default();
+        let cooldown_secs = rules.thresholds.cooldown_mins * 60;
+
+        assert!(rules.evaluate_battery(1, 5, NOW).is_some());
+        assert!(rules
+            .evaluate_battery(1, 5, NOW + cooldown_secs - 1)
+            .is_none());
+        assert!(rules.evaluate_battery(1, 5, NOW + cooldown_secs).is_some());
+    }
+
+    #[test]
+    fn alerts_only_when_a_node_seen_online_goes_offline() {
+        let thresholds = NodeLivenessConfig::default()
+            .thresholds(NotificationThresholds::default().node_offline_mins);
+        let offline_secs = thresholds.offline_after_secs + thresholds.hysteresis_secs;
+        let mut tracker = NodeLivenessTracker::default();
+
+        let alerts = |tracker: &mut NodeLivenessTracker, now| -> Vec<RuleAlert> {
+            tracker
+                .evaluate(now, &thresholds)
+                .iter()
+                .filter_map(RuleAlert::from_liveness_transition)
+                .collect()
+        };
+
+        // Hundreds of nodes in the radio's database were already stale at startup
+        for node_num in 1..=300 {
+            tracker.seed(node_num, NOW - 86_400, NOW, &thresholds);
+        }
+        tracker.seed(301, NOW - 60, NOW, &thresholds);
+
+        assert!(alerts(&mut tracker, NOW).is_empty());
+        assert!(alerts(&mut tracker, NOW + 60).is_empty());
+
+        assert_eq!(
+            alerts(&mut tracker, NOW + offline_secs),
+            vec![RuleAlert::NodeOffline {
+                node_num: 301,
+                last_heard: NOW - 60
+            }]
+        );
+
+        // Later ticks don't repeat it, even once the cooldown has passed
+        let cooldown_secs = NotificationThresholds::default().cooldown_mins * 60;
+        assert!(alerts(&mut tracker, NOW + offline_secs + 60).is_empty());
+        assert!(alerts(&mut tracker, NOW + offline_secs + cooldown_secs + 60).is_empty());
+
+        // Coming back online isn't an alert
+        assert_eq!(
+            tracker
+                .record_heard(301, NOW + 100_000, &thresholds)
+                .as_ref()
+                .and_then(RuleAlert::from_liveness_transition),
+            None
+        );
+    }
+
+    #[test]
+    fn alerts_when_device_unresponsive() {
+        let mut rules = NotificationRules::default();
+        let key: DeviceKey = "/dev/ttyUSB0".into();
+        let unresponsive_secs = rules.thresholds.device_unresponsive_mins * 60;
+
+        assert!(rules
+            .evaluate_device_activity(&key, NOW, NOW + unresponsive_secs)
+            .is_none());
+        assert!(rules
+            .evaluate_device_activity(&key, NOW, NOW + unresponsive_secs + 1)
+            .is_some());
+        assert!(rules
+            .evaluate_device_activity(&key, NOW, NOW + unresponsive_secs + 2)
+            .is_none());
+    }
+
+    #[test]
+    fn alerts_when_network_partitions() {
+        let mut rules = NotificationRules::default();
+
+        assert!(rules.evaluate_partition(1, NOW).is_none());
+        assert_eq!(
+            rules.evaluate_partition(2, NOW + 60),
+            Some(RuleAlert::NetworkPartition { component_count: 2 })
+        );
+        assert!(rules.evaluate_partition(3, NOW + 120).is_none());
+
+        assert!(rules.evaluate_partition(1, NOW + 180).is_none());
+        assert!(rules.evaluate_partition(2, NOW + 240).is_some());
+    }
+}
@@ -0,0 +1,665 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use log::{debug, trace, warn};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+use tokio::sync::mpsc;
+
+use crate::ipc::error_reporter::{AppErrorCode, ErrorReporter};
+use crate::persistence::{save_json, WEBHOOKS_FILE_NAME};
+use crate::shutdown::shutdown_signal;
+use crate::state;
+
+use super::rules::RuleAlert;
+
+/// Payloads waiting for delivery beyond this are dropped rather than blocking packet handling
+pub const WEBHOOK_QUEUE_CAPACITY: usize = 256;
+
+pub const WEBHOOK_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Consecutive failed deliveries, after retries, before an endpoint is disabled
+pub const WEBHOOK_CIRCUIT_BREAKER_THRESHOLD: u32 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum WebhookEventType {
+    NodeOffline,
+    NetworkPartition,
+    LowBattery,
+    ChannelMessage,
+    GeofenceViolation,
+    GeofenceEntry,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookEndpoint {
+    pub url: String,
+    pub event_types: Vec<WebhookEventType>,
+
+    /// Only messages on this channel index are sent, or on every channel if `None`
+    pub channel: Option<u32>,
+
+    /// Sent as an `Authorization: Bearer` header if set
+    pub bearer_token: Option<String>,
+
+    /// Cleared by the circuit breaker when deliveries keep failing
+    pub enabled: bool,
+}
+
+impl WebhookEndpoint {
+    pub fn accepts(&self, payload: &WebhookPayload) -> bool {
+        if !self.enabled || !self.event_types.contains(&payload.event) {
+            return false;
+        }
+
+        match (payload.event, self.channel) {
+            (WebhookEventType::ChannelMessage, Some(channel)) => payload.channel == Some(channel),
+            _ => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WebhookConfig {
+    pub endpoints: Vec<WebhookEndpoint>,
+}
+
+impl WebhookConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for endpoint in self.endpoints.iter() {
+            if !endpoint.url.starts_with("http://") && !endpoint.url.starts_with("https://") {
+                return Err(format!(
+                    "Webhook URL \"{}\" must use http or https",
+                    endpoint.url
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn wants(&self, payload: &WebhookPayload) -> bool {
+        self.endpoints.iter().any(|e| e.accepts(payload))
+    }
+}
+
+/// JSON body POSTed to webhook endpoints. `event` and `text` are always set, the
+/// remaining fields are `null` unless they apply to the event:
+///
+/// - `nodeOffline`: `nodeNum`, `nodeName`
+/// - `lowBattery`: `nodeNum`, `nodeName`, `batteryLevel`
+/// - `networkPartition`: `componentCount`
+/// - `channelMessage`: `nodeNum` and `nodeName` of the sender, `channel`
+/// - `geofenceViolation`, `geofenceEntry`: `nodeNum`, `nodeName`, `geofenceName`
+///
+/// `text` is a human readable summary, which is what Slack and Matrix hooks display.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookPayload {
+    pub event: WebhookEventType,
+    pub timestamp: u32, // secs
+    pub text: String,
+    pub node_num: Option<u32>,
+    pub node_name: Option<String>,
+    pub channel: Option<u32>,
+    pub battery_level: Option<u32>,
+    pub component_count: Option<u32>,
+    pub geofence_name: Option<String>,
+}
+
+impl WebhookPayload {
+    fn new(event: WebhookEventType, timestamp: u32, text: String) -> Self {
+        Self {
+            event,
+            timestamp,
+            text,
+            node_num: None,
+            node_name: None,
+            channel: None,
+            battery_level: None,
+            component_count: None,
+            geofence_name: None,
+        }
+    }
+
+    /// Returns `None` for alerts that aren't sent to webhooks
+    pub fn from_rule_alert(
+        alert: &RuleAlert,
+        node_name: Option<String>,
+        text: String,
+        timestamp: u32,
+    ) -> Option<Self> {
+        let payload = match alert {
+            RuleAlert::LowBattery {
+                node_num,
+                battery_level,
+            } => Self {
+                node_num: Some(*node_num),
+                node_name,
+                battery_level: Some(*battery_level),
+                ..Self::new(WebhookEventType::LowBattery, timestamp, text)
+            },
+            RuleAlert::NodeOffline { node_num, .. } => Self {
+                node_num: Some(*node_num),
+                node_name,
+                ..Self::new(WebhookEventType::NodeOffline, timestamp, text)
+            },
+            RuleAlert::NetworkPartition { component_count } => Self {
+                component_count: Some(*component_count),
+                ..Self::new(WebhookEventType::NetworkPartition, timestamp, text)
+            },
+            RuleAlert::GeofenceViolation {
+                node_num,
+                fence_name,
+                ..
+            } => Self {
+                node_num: Some(*node_num),
+                node_name,
+                geofence_name: Some(fence_name.clone()),
+                ..Self::new(WebhookEventType::GeofenceViolation, timestamp, text)
+            },
+            RuleAlert::GeofenceEntry {
+                node_num,
+                fence_name,
+                ..
+            } => Self {
+                node_num: Some(*node_num),
+                node_name,
+                geofence_name: Some(fence_name.clone()),
+                ..Self::new(WebhookEventType::GeofenceEntry, timestamp, text)
+            },
+            RuleAlert::DeviceUnresponsive { .. } => return None,
+        };
+
+        Some(payload)
+    }
+
+    pub fn channel_message(
+        channel: u32,
+        from: u32,
+        from_name: String,
+        text: String,
+        timestamp: u32,
+    ) -> Self {
+        Self {
+            node_num: Some(from),
+            node_name: Some(from_name),
+            channel: Some(channel),
+            ..Self::new(WebhookEventType::ChannelMessage, timestamp, text)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration, // doubled after every attempt
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WebhookDeliveryError {
+    Status(u16),
+    Request(String),
+}
+
+impl WebhookDeliveryError {
+    /// Server errors and failed connections may succeed later, client errors won't
+    fn is_retryable(&self) -> bool {
+        match self {
+            WebhookDeliveryError::Status(status) => *status >= 500,
+            WebhookDeliveryError::Request(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for WebhookDeliveryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebhookDeliveryError::Status(status) => write!(f, "endpoint returned {}", status),
+            WebhookDeliveryError::Request(e) => write!(f, "request failed: {}", e),
+        }
+    }
+}
+
+async fn send(
+    client: &reqwest::Client,
+    endpoint: &WebhookEndpoint,
+    payload: &WebhookPayload,
+) -> Result<(), WebhookDeliveryError> {
+    let mut request = client
+        .post(&endpoint.url)
+        .timeout(WEBHOOK_REQUEST_TIMEOUT)
+        .json(payload);
+
+    if let Some(token) = endpoint.bearer_token.as_ref() {
+        request = request.bearer_auth(token);
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| WebhookDeliveryError::Request(e.to_string()))?;
+
+    if !response.status().is_success() {
+        return Err(WebhookDeliveryError::Status(response.status().as_u16()));
+    }
+
+    Ok(())
+}
+
+/// POSTs a payload to an endpoint, retrying with exponential backoff while it fails
+/// with a retryable error
+pub async fn deliver(
+    client: &reqwest::Client,
+    endpoint: &WebhookEndpoint,
+    payload: &WebhookPayload,
+    retry: RetryPolicy,
+) -> Result<(), WebhookDeliveryError> {
+    let mut backoff = retry.initial_backoff;
+    let mut attempt = 1;
+
+    loop {
+        let err = match send(client, endpoint, payload).await {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+
+        if !err.is_retryable() || attempt >= retry.max_attempts {
+            return Err(err);
+        }
+
+        debug!(
+            "Webhook delivery to {} failed ({}), retrying in {:?}",
+            endpoint.url, err, backoff
+        );
+
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+/// Counts consecutive failed deliveries per endpoint URL
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    failures: HashMap<String, u32>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            failures: HashMap::new(),
+        }
+    }
+
+    /// Records a delivery, returning whether the endpoint should now be disabled.
+    /// The count starts over once tripped, so a re-enabled endpoint gets a fresh start.
+    pub fn record(&mut self, url: &str, succeeded: bool) -> bool {
+        if succeeded {
+            self.failures.remove(url);
+            return false;
+        }
+
+        let failures = self.failures.entry(url.to_string()).or_default();
+        *failures += 1;
+
+        if *failures < self.threshold {
+            return false;
+        }
+
+        self.failures.remove(url);
+        true
+    }
+}
+
+pub fn webhook_queue() -> (mpsc::Sender<WebhookPayload>, mpsc::Receiver<WebhookPayload>) {
+    mpsc::channel(WEBHOOK_QUEUE_CAPACITY)
+}
+
+/// Queues a payload for the webhook worker without waiting on delivery
+pub fn enqueue_webhook<R: tauri::Runtime>(handle: &tauri::AppHandle<R>, payload: WebhookPayload) {
+    let webhooks = match handle.try_state::<state::webhooks::WebhooksState>() {
+        Some(webhooks) => webhooks,
+        None => return,
+    };
+
+    match webhooks.inner.lock() {
+        Ok(config) if !config.wants(&payload) => return,
+        Ok(_) => {}
+        Err(e) => {
+            warn!("Failed to lock webhook config: {}", e);
+            return;
+        }
+    }
+
+    trace!("Queueing {:?} webhook", payload.event);
+
+    if let Err(e) = webhooks.queue.try_send(payload) {
+        warn!("Dropped webhook payload: {}", e);
+    }
+}
+
+fn disable_endpoint<R: tauri::Runtime>(handle: &tauri::AppHandle<R>, url: &str) {
+    let webhooks = handle.state::<state::webhooks::WebhooksState>();
+
+    let config = match webhooks.inner.lock() {
+        Ok(mut config) => {
+            for endpoint in config.endpoints.iter_mut().filter(|e| e.url == url) {
+                endpoint.enabled = false;
+            }
+
+            config.clone()
+        }
+        Err(e) => {
+            warn!("Failed to lock webhook config: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = save_json(handle, WEBHOOKS_FILE_NAME, &config) {
+        warn!("Failed to save webhook config: {}", e);
+    }
+
+    ErrorReporter::new(handle, module_path!()).error(
+        AppErrorCode::WebhookDisabled,
+        format!(
+            "Disabled webhook {} after {} failed deliveries",
+            url, WEBHOOK_CIRCUIT_BREAKER_THRESHOLD
+        ),
+    );
+}
+
+/// Delivers queued payloads to every endpoint that accepts them, one at a time
+pub fn spawn_webhook_worker(handle: tauri::AppHandle, mut queue: mpsc::Receiver<WebhookPayload>) {
+    trace!("Spawning webhook worker");
+
+    tauri::async_runtime::spawn(async move {
+        let client = reqwest::Client::new();
+        let mut breaker = CircuitBreaker::new(WEBHOOK_CIRCUIT_BREAKER_THRESHOLD);
+        let shutdown = shutdown_signal(&handle);
+
+        while let Some(payload) = shutdown.recv(&mut queue).await {
+            let endpoints: Vec<WebhookEndpoint> = {
+                let webhooks = handle.state::<state::webhooks::WebhooksState>();
+                let config = match webhooks.inner.lock() {
+                    Ok(config) => config,
+                    Err(e) => {
+                        warn!("Failed to lock webhook config: {}", e);
+                        continue;
+                    }
+                };
+
+                config
+                    .endpoints
+                    .iter()
+                    .filter(|e| e.accepts(&payload))
+                    .cloned()
+                    .collect()
+            };
+
+            for endpoint in endpoints {
+                let result = deliver(&client, &endpoint, &payload, RetryPolicy::default()).await;
+
+                if let Err(e) = &result {
+                    warn!("Webhook delivery to {} failed: {}", endpoint.url, e);
+                }
+
+                if breaker.record(&endpoint.url, result.is_ok()) {
+                    disable_endpoint(&handle, &endpoint.url);
+                }
+            }
+        }
+
+        trace!("Stopped webhook worker");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct ReceivedRequest {
+        headers: String,
+        body: serde_json::Value,
+    }
+
+    /// Minimal HTTP server answering each request with the next of `statuses`,
+    /// repeating the last one once they run out
+    async fn test_server(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<ReceivedRequest>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(vec![]));
+        let server_received = received.clone();
+
+        tokio::spawn(async move {
+            let mut statuses = statuses.into_iter();
+            let mut last_status = 200;
+
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                let mut buffer = [0; 4096];
+
+                // Read until the headers and the body they announce are in
+                let (headers, body) = loop {
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    assert!(read > 0, "connection closed mid-request");
+                    request.extend_from_slice(&buffer[..read]);
+
+                    let text = String::from_utf8_lossy(&request).to_string();
+
+                    if let Some((headers, body)) = text.split_once("\r\n\r\n") {
+                        let content_length = headers
+                            .lines()
+                            .find_map(|line| {
+                                let (name, value) = line.split_once(':')?;
+                                name.eq_ignore_ascii_case("content-length")
+                                    .then(|| value.trim().parse::<usize>().ok())?
+                            })
+                            .unwrap_or(0);
+
+                        if body.len() >= content_length {
+                            break (headers.to_string(), body.to_string());
+                        }
+                    }
+                };
+
+                server_received.lock().unwrap().push(ReceivedRequest {
+                    headers,
+                    body: serde_json::from_str(&body).unwrap(),
+                });
+
+                if let Some(status) = statuses.next() {
+                    last_status = status;
+                }
+
+                let response = format!(
+                    "HTTP/1.1 {} Test\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    last_status
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (url, received)
+    }
+
+    fn endpoint(url: &str, event_types: Vec<WebhookEventType>) -> WebhookEndpoint {
+        WebhookEndpoint {
+            url: url.into(),
+            event_types,
+            channel: None,
+            bearer_token: None,
+            enabled: true,
+        }
+    }
+
+    fn offline_payload() -> WebhookPayload {
+        WebhookPayload::from_rule_alert(
+            &RuleAlert::NodeOffline {
+                node_num: 0xa1b2,
+                last_heard: 1_700_000_000,
+            },
+            Some("Ridge".into()),
+            "Ridge hasn't been heard from recently".into(),
+            1_700_003_600,
+        )
+        .unwrap()
+    }
+
+    fn fast_retries() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn posts_documented_payload_with_bearer_token() {
+        let (url, received) = test_server(vec![204]).await;
+        let endpoint = WebhookEndpoint {
+            bearer_token: Some("s3cret".into()),
+            ..endpoint(&url, vec![WebhookEventType::NodeOffline])
+        };
+
+        deliver(
+            &reqwest::Client::new(),
+            &endpoint,
+            &offline_payload(),
+            fast_retries(),
+        )
+        .await
+        .unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert!(received[0].headers.starts_with("POST /hook "));
+        assert!(received[0]
+            .headers
+            .to_ascii_lowercase()
+            .contains("authorization: bearer s3cret"));
+        assert_eq!(
+            received[0].body,
+            serde_json::json!({
+                "event": "nodeOffline",
+                "timestamp": 1_700_003_600,
+                "text": "Ridge hasn't been heard from recently",
+                "nodeNum": 0xa1b2,
+                "nodeName": "Ridge",
+                "channel": null,
+                "batteryLevel": null,
+                "componentCount": null,
+                "geofenceName": null,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_but_not_client_errors() {
+        let client = reqwest::Client::new();
+        let payload = offline_payload();
+
+        let (url, received) = test_server(vec![503, 500, 200]).await;
+        let flaky = endpoint(&url, vec![WebhookEventType::NodeOffline]);
+        assert_eq!(
+            deliver(&client, &flaky, &payload, fast_retries()).await,
+            Ok(())
+        );
+        assert_eq!(received.lock().unwrap().len(), 3);
+
+        let (url, received) = test_server(vec![502]).await;
+        let down = endpoint(&url, vec![WebhookEventType::NodeOffline]);
+        assert_eq!(
+            deliver(&client, &down, &payload, fast_retries()).await,
+            Err(WebhookDeliveryError::Status(502))
+        );
+        assert_eq!(received.lock().unwrap().len(), 3);
+
+        let (url, received) = test_server(vec![401]).await;
+        let unauthorized = endpoint(&url, vec![WebhookEventType::NodeOffline]);
+        assert_eq!(
+            deliver(&client, &unauthorized, &payload, fast_retries()).await,
+            Err(WebhookDeliveryError::Status(401))
+        );
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn filters_events_and_channels() {
+        let message =
+            |channel| WebhookPayload::channel_message(channel, 7, "Base".into(), "hello".into(), 0);
+
+        let ops_channel = WebhookEndpoint {
+            channel: Some(2),
+            ..endpoint("http://hook", vec![WebhookEventType::ChannelMessage])
+        };
+        assert!(ops_channel.accepts(&message(2)));
+        assert!(!ops_channel.accepts(&message(0)));
+        assert!(!ops_channel.accepts(&offline_payload()));
+
+        let all_channels = endpoint("http://hook", vec![WebhookEventType::ChannelMessage]);
+        assert!(all_channels.accepts(&message(0)));
+
+        let disabled = WebhookEndpoint {
+            enabled: false,
+            ..all_channels
+        };
+        assert!(!disabled.accepts(&message(0)));
+
+        let config = WebhookConfig {
+            endpoints: vec![ops_channel, disabled],
+        };
+        assert!(config.wants(&message(2)));
+        assert!(!config.wants(&message(1)));
+
+        assert!(WebhookPayload::from_rule_alert(
+            &RuleAlert::DeviceUnresponsive {
+                device_key: "/dev/ttyUSB0".into(),
+                last_packet: 0,
+            },
+            None,
+            String::new(),
+            0,
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn trips_after_consecutive_failures() {
+        let mut breaker = CircuitBreaker::new(3);
+
+        assert!(!breaker.record("a", false));
+        assert!(!breaker.record("a", false));
+        assert!(!breaker.record("a", true));
+
+        assert!(!breaker.record("a", false));
+        assert!(!breaker.record("b", false));
+        assert!(!breaker.record("a", false));
+        assert!(breaker.record("a", false));
+
+        // Starts over once tripped
+        assert!(!breaker.record("a", false));
+    }
+}
@@ -0,0 +1,2650 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use log::{debug, trace, warn};
+use meshtastic::packet::{PacketDestination, PacketRouter};
+use meshtastic::protobufs::{self, hardware_message};
+use meshtastic::types::{EncodedMeshPacketData, MeshChannel, NodeId};
+use meshtastic::Message;
+use tauri::Manager;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::Instant;
+
+use crate::connection::options::Heartbeat;
+use crate::device::fixed_position::{build_fixed_position_config, FixedPosition};
+use crate::device::helpers::{generate_rand_id, get_current_time_u32};
+use crate::device::liveness::NodeLivenessConfig;
+use crate::device::message_store::mark_outgoing_message_sent;
+use crate::device::remote_hardware::{build_hardware_message, GpioReading};
+use crate::device::{ChannelMessageState, MeshDevice, SerialDeviceStatus};
+use crate::graph::ds::graph::MeshGraph;
+use crate::graph::geojson::GraphGeoJson;
+use crate::graph::rebuild::{load_stored_graph, run_graph_rebuild};
+use crate::ipc::error_reporter::{AppErrorCode, ErrorReporter};
+use crate::ipc::events::{
+    dispatch_debug_packet, dispatch_devices_list_changed, dispatch_graph_geojson_update,
+    dispatch_message_state, dispatch_node_status_changed, dispatch_radio_queue_throttle_status,
+    dispatch_updated_device, dispatch_updated_graph, flush_coalesced_events,
+};
+use crate::ipc::helpers::publish_graph_overrides;
+use crate::ipc::{
+    CommandError, DebugPacketEvent, DevicesListChange, MessageStateEvent, NodeStatusChangedEvent,
+    RadioQueueThrottleStatus, EVENT_API_VERSION,
+};
+use crate::notifications::dispatch_liveness_alert;
+use crate::replay::capture::CaptureRecord;
+use crate::shutdown::{shutdown_signal, ShutdownSignal};
+use crate::state::mesh_devices::{new_device, MeshDeviceInner};
+use crate::state::node_liveness::{liveness_config, liveness_thresholds};
+use crate::state::radio_connections::{RadioConnectionsState, RadioConnectionsStateInner};
+use crate::state::DeviceKey;
+
+use super::debug_stream::DebugPacket;
+use super::graph_edit::GraphEdit;
+use super::handlers::mesh_packet::handlers::{finish_remote_admin, update_message_state};
+use super::handlers::DeviceUpdateError;
+use super::radio_queue::{RadioQueueGate, DEFAULT_RADIO_QUEUE_WAIT_TIMEOUT};
+use super::radio_write::{
+    send_hardware_message, send_position, send_remote_admin_message, RadioWrite,
+};
+use super::remote_admin::RemoteAdminOutcome;
+use super::summary::ConnectedDeviceSummary;
+use super::traceroute::PendingTraceroute;
+use super::MeshPacketApi;
+
+/// How often a device's summary is republished, for the counters that change
+/// without its status changing
+const SUMMARY_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+const DEVICE_STOPPED: &str = "Device disconnected";
+
+/// A text message to send through a device's connection
+pub struct OutgoingText {
+    pub text: String,
+    pub destination: PacketDestination,
+    pub channel: u32,
+    pub want_ack: bool,
+    pub journal_id: Option<u32>, // marked as sent in the outgoing journal once sent
+}
+
+/// An admin request to another node, see `send_remote_admin_message`
+pub struct RemoteAdminRequest {
+    pub message: protobufs::AdminMessage,
+    pub node_num: u32,
+    pub want_response: bool,
+}
+
+type RemoteAdminReply = Result<(u32, oneshot::Receiver<RemoteAdminOutcome>), CommandError>;
+
+type GpioReadReply = Result<(u32, oneshot::Receiver<GpioReading>), CommandError>;
+
+/// What a device's task is asked to do. Commands that answer carry the channel
+/// their reply is sent on.
+pub enum DeviceCommand {
+    HandlePacket(protobufs::FromRadio),
+    Send(OutgoingText, oneshot::Sender<Result<Option<u32>, String>>),
+    Write(RadioWrite, oneshot::Sender<Result<(), CommandError>>),
+    SendRemoteAdmin(RemoteAdminRequest, oneshot::Sender<RemoteAdminReply>),
+    TimeOutRemoteAdmin(u32, oneshot::Sender<()>),
+    ReadGpios(u32, u64, oneshot::Sender<GpioReadReply>),
+    TimeOutGpioRead(u32, oneshot::Sender<()>),
+    SendTraceroute(u32, oneshot::Sender<Result<u32, CommandError>>),
+    TimeOutTraceroute(u32, oneshot::Sender<Option<PendingTraceroute>>),
+    SetFixedPosition(
+        Option<FixedPosition>,
+        oneshot::Sender<Result<(), CommandError>>,
+    ),
+    EditGraph(GraphEdit, oneshot::Sender<Result<(), CommandError>>),
+    GetSnapshot(oneshot::Sender<MeshDevice>),
+    TimeOutConfiguration(Option<u32>, oneshot::Sender<Option<String>>),
+    RetryConfiguration(oneshot::Sender<Result<u32, String>>),
+    TimeOutMessage(u32, u32, String, oneshot::Sender<bool>),
+    Disconnect(oneshot::Sender<()>),
+}
+
+/// Why a device's task stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceExit {
+    ConnectionLost, // the radio's decoded packet stream closed
+    Unresponsive,   // the radio stopped answering heartbeats, and is to be reconnected to
+    Disconnected,   // asked to disconnect, or every handle to it was dropped
+}
+
+/// A connected device as kept in the devices registry. Commands are sent to the
+/// task that owns the device, and its last published summary is read without
+/// waiting on that task.
+pub struct DeviceHandle<R: tauri::Runtime = tauri::Wry> {
+    device_key: DeviceKey,
+    packet_api: MeshDeviceInner<R>,
+    commands: mpsc::UnboundedSender<DeviceCommand>,
+    summary: watch::Receiver<ConnectedDeviceSummary>,
+}
+
+impl<R: tauri::Runtime> Clone for DeviceHandle<R> {
+    fn clone(&self) -> Self {
+        Self {
+            device_key: self.device_key.clone(),
+            packet_api: self.packet_api.clone(),
+            commands: self.commands.clone(),
+            summary: self.summary.clone(),
+        }
+    }
+}
+
+impl<R: tauri::Runtime> DeviceHandle<R> {
+    /// The device's state, for reading it. Changes are made by the device's task,
+    /// through the commands of this handle.
+    pub fn packet_api(&self) -> MeshDeviceInner<R> {
+        self.packet_api.clone()
+    }
+
+    /// The device's summary as of its last status change, or at most
+    /// `SUMMARY_REFRESH_INTERVAL` old
+    pub fn summary(&self) -> ConnectedDeviceSummary {
+        self.summary.borrow().clone()
+    }
+
+    /// Has the device handle a packet as if its radio had sent it
+    pub fn handle_packet(&self, packet: protobufs::FromRadio) -> Result<(), String> {
+        self.commands
+            .send(DeviceCommand::HandlePacket(packet))
+            .map_err(|_| DEVICE_STOPPED.to_string())
+    }
+
+    /// A copy of the device's state once the packets queued before it are handled
+    pub async fn snapshot(&self) -> Result<MeshDevice, String> {
+        let (reply, response) = oneshot::channel();
+
+        self.commands
+            .send(DeviceCommand::GetSnapshot(reply))
+            .map_err(|_| DEVICE_STOPPED.to_string())?;
+
+        response.await.map_err(|_| DEVICE_STOPPED.to_string())
+    }
+
+    /// Sends a text message through the device's connection, returning the packet id
+    /// it was sent with. The device's task can't handle `QueueStatus` packets while it
+    /// sends, so callers wait for room in the radio's TX queue beforehand.
+    pub async fn send_text(&self, message: OutgoingText) -> Result<Option<u32>, String> {
+        let (reply, response) = oneshot::channel();
+
+        self.commands
+            .send(DeviceCommand::Send(message, reply))
+            .map_err(|_| DEVICE_STOPPED.to_string())?;
+
+        response.await.map_err(|_| DEVICE_STOPPED.to_string())?
+    }
+
+    /// Fails the device's configuration if it's still in progress, returning why. The
+    /// check is made between packets, and a configuration completing afterwards is
+    /// ignored, so the UI hears either that configuration failed or that it succeeded.
+    pub async fn time_out_configuration(&self) -> Result<Option<String>, String> {
+        let (reply, response) = oneshot::channel();
+
+        self.commands
+            .send(DeviceCommand::TimeOutConfiguration(None, reply))
+            .map_err(|_| DEVICE_STOPPED.to_string())?;
+
+        response.await.map_err(|_| DEVICE_STOPPED.to_string())
+    }
+
+    /// Like `time_out_configuration`, but only fails the configuration attempt started
+    /// with `config_id`, leaving any retry after it alone
+    pub async fn time_out_configuration_attempt(
+        &self,
+        config_id: u32,
+    ) -> Result<Option<String>, String> {
+        let (reply, response) = oneshot::channel();
+
+        self.commands
+            .send(DeviceCommand::TimeOutConfiguration(Some(config_id), reply))
+            .map_err(|_| DEVICE_STOPPED.to_string())?;
+
+        response.await.map_err(|_| DEVICE_STOPPED.to_string())
+    }
+
+    /// Asks the radio for its configuration again over the open connection, after a
+    /// configuration that timed out. Returns the config id of the new attempt.
+    pub async fn retry_configuration(&self) -> Result<u32, String> {
+        let (reply, response) = oneshot::channel();
+
+        self.commands
+            .send(DeviceCommand::RetryConfiguration(reply))
+            .map_err(|_| DEVICE_STOPPED.to_string())?;
+
+        response.await.map_err(|_| DEVICE_STOPPED.to_string())?
+    }
+
+    /// Fails a sent message with `reason` if it's still waiting for its ack, returning
+    /// whether it was
+    pub async fn time_out_message(
+        &self,
+        channel: u32,
+        message_id: u32,
+        reason: String,
+    ) -> Result<bool, String> {
+        let (reply, response) = oneshot::channel();
+
+        self.commands
+            .send(DeviceCommand::TimeOutMessage(
+                channel, message_id, reason, reply,
+            ))
+            .map_err(|_| DEVICE_STOPPED.to_string())?;
+
+        response.await.map_err(|_| DEVICE_STOPPED.to_string())
+    }
+
+    /// Writes to the device's radio through its connection
+    pub async fn write(&self, write: RadioWrite) -> Result<(), CommandError> {
+        self.request(|reply| DeviceCommand::Write(write, reply))
+            .await?
+    }
+
+    /// Sends an admin request to another node, returning the id of the request and
+    /// where its outcome arrives, see `send_remote_admin_message`
+    pub async fn send_remote_admin(&self, request: RemoteAdminRequest) -> RemoteAdminReply {
+        self.request(|reply| DeviceCommand::SendRemoteAdmin(request, reply))
+            .await?
+    }
+
+    /// Ends an admin request to another node as timed out, unless its outcome has
+    /// already arrived
+    pub async fn time_out_remote_admin(&self, request_id: u32) -> Result<(), CommandError> {
+        self.request(|reply| DeviceCommand::TimeOutRemoteAdmin(request_id, reply))
+            .await
+    }
+
+    /// Reads the GPIO pins in `gpio_mask` of another node, returning the id of the
+    /// request and where its reply arrives
+    pub async fn read_gpios(&self, node_num: u32, gpio_mask: u64) -> GpioReadReply {
+        self.request(|reply| DeviceCommand::ReadGpios(node_num, gpio_mask, reply))
+            .await?
+    }
+
+    /// Stops waiting for the reply to a GPIO read
+    pub async fn time_out_gpio_read(&self, request_id: u32) -> Result<(), CommandError> {
+        self.request(|reply| DeviceCommand::TimeOutGpioRead(request_id, reply))
+            .await
+    }
+
+    /// Sends a traceroute to `destination`, returning the id of the request
+    pub async fn send_traceroute(&self, destination: u32) -> Result<u32, CommandError> {
+        self.request(|reply| DeviceCommand::SendTraceroute(destination, reply))
+            .await?
+    }
+
+    /// Stops waiting for a traceroute's response, returning the request if it was
+    /// still waiting
+    pub async fn time_out_traceroute(
+        &self,
+        request_id: u32,
+    ) -> Result<Option<PendingTraceroute>, CommandError> {
+        self.request(|reply| DeviceCommand::TimeOutTraceroute(request_id, reply))
+            .await
+    }
+
+    /// Sets the fixed position of the device's own node, or clears it, updating the
+    /// device and its graph without waiting for the radio to report the change
+    pub async fn set_fixed_position(
+        &self,
+        fixed_position: Option<FixedPosition>,
+    ) -> Result<(), CommandError> {
+        self.request(|reply| DeviceCommand::SetFixedPosition(fixed_position, reply))
+            .await?
+    }
+
+    /// Changes the device's graph, publishing it once changed
+    pub async fn edit_graph(&self, edit: GraphEdit) -> Result<(), CommandError> {
+        self.request(|reply| DeviceCommand::EditGraph(edit, reply))
+            .await?
+    }
+
+    async fn request<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> DeviceCommand,
+    ) -> Result<T, CommandError> {
+        let (reply, response) = oneshot::channel();
+
+        self.commands
+            .send(command(reply))
+            .map_err(|_| CommandError::device_not_connected(&self.device_key))?;
+
+        response
+            .await
+            .map_err(|_| CommandError::device_not_connected(&self.device_key))
+    }
+
+    /// Resolves once the device's task has stopped
+    pub async fn stopped(&self) {
+        let mut summary = self.summary.clone();
+
+        // Errors once the task drops its end of the channel
+        while summary.changed().await.is_ok() {}
+    }
+
+    /// Stops the device's task, which marks the device as disconnected. Returns once
+    /// the task has stopped, right away if it already had.
+    pub async fn disconnect(&self) {
+        let (reply, response) = oneshot::channel();
+
+        if self.commands.send(DeviceCommand::Disconnect(reply)).is_ok() {
+            let _ = response.await;
+        }
+    }
+}
+
+/// The task owning a connected device. Commands and the packets its radio decodes
+/// are handled one at a time, and only this device is locked while they are, so
+/// other devices' tasks run alongside it.
+pub struct DeviceActor<R: tauri::Runtime = tauri::Wry> {
+    packet_api: MeshDeviceInner<R>,
+    app_handle: tauri::AppHandle<R>,
+    device_key: DeviceKey,
+    commands: mpsc::UnboundedReceiver<DeviceCommand>,
+    summary: watch::Sender<ConnectedDeviceSummary>,
+    graph_batch_deadline: Option<Instant>,
+    graph_publish_deadline: Option<Instant>,
+    heartbeat: Option<Heartbeat>,
+    last_packet_at: Instant,
+    radio_queue: RadioQueueGate,
+    held_sends: VecDeque<DeviceCommand>, // sends waiting for room in the radio's TX queue
+    held_until: Option<Instant>,         // when held sends stop waiting and go out anyway
+}
+
+/// Creates a device's task along with the handle to register it under
+pub fn device_actor<R: tauri::Runtime>(
+    packet_api: MeshPacketApi<R>,
+) -> (DeviceHandle<R>, DeviceActor<R>) {
+    let app_handle = packet_api.app_handle.clone();
+    let device_key = packet_api.device_key.clone();
+
+    let radio_queue = packet_api.radio_queue.clone();
+
+    let (summary_sender, summary) = watch::channel(packet_api.summary());
+    let (commands_sender, commands) = mpsc::unbounded_channel();
+
+    let packet_api = new_device(packet_api);
+
+    let handle = DeviceHandle {
+        device_key: device_key.clone(),
+        packet_api: packet_api.clone(),
+        commands: commands_sender,
+        summary,
+    };
+
+    let actor = DeviceActor {
+        packet_api,
+        app_handle,
+        device_key,
+        commands,
+        summary: summary_sender,
+        graph_batch_deadline: None,
+        graph_publish_deadline: None,
+        heartbeat: None,
+        last_packet_at: Instant::now(),
+        radio_queue,
+        held_sends: VecDeque::new(),
+        held_until: None,
+    };
+
+    (handle, actor)
+}
+
+/// Runs the task of a device without a radio connection, e.g. a simulated or
+/// replayed one, until it's disconnected
+pub fn spawn_device<R: tauri::Runtime>(packet_api: MeshPacketApi<R>) -> DeviceHandle<R> {
+    let (handle, actor) = device_actor(packet_api);
+
+    tauri::async_runtime::spawn(actor.run(None));
+
+    handle
+}
+
+async fn next_packet(
+    decoded: &mut Option<mpsc::UnboundedReceiver<protobufs::FromRadio>>,
+) -> Option<protobufs::FromRadio> {
+    match decoded {
+        Some(decoded) => decoded.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn intake_stopped(shutdown: &Option<ShutdownSignal>) {
+    match shutdown {
+        Some(shutdown) => shutdown.cancelled().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn deadline_due(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Waits until held sends can go out, returning whether the radio reported room in its
+/// TX queue rather than `deadline` passing
+async fn radio_queue_opened(radio_queue: &RadioQueueGate, deadline: Option<Instant>) -> bool {
+    match deadline {
+        Some(deadline) => radio_queue.wait_for_capacity(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+fn liveness_interval(config: &NodeLivenessConfig) -> tokio::time::Interval {
+    tokio::time::interval_at(
+        Instant::now() + config.check_interval(),
+        config.check_interval(),
+    )
+}
+
+async fn heartbeat_due(heartbeats: &mut Option<tokio::time::Interval>) {
+    match heartbeats {
+        Some(heartbeats) => {
+            heartbeats.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+impl<R: tauri::Runtime> DeviceActor<R> {
+    /// Keeps the radio's link alive with `heartbeat` once the device is connected
+    pub fn with_heartbeat(mut self, heartbeat: Option<Heartbeat>) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    /// Handles commands, and the packets decoded from the device's radio if it's
+    /// connected to one, until the device is disconnected or the radio's packet
+    /// stream closes. The stream API hands packets over on an unbounded channel,
+    /// so a task that falls behind (e.g. while the radio sends its node database)
+    /// queues packets rather than dropping them.
+    ///
+    /// Once the app starts shutting down, packets from the radio are no longer
+    /// handled, while commands are until the device is disconnected.
+    pub async fn run(
+        mut self,
+        mut decoded: Option<mpsc::UnboundedReceiver<protobufs::FromRadio>>,
+    ) -> DeviceExit {
+        let mut refresh = tokio::time::interval(SUMMARY_REFRESH_INTERVAL);
+        let mut shutdown = Some(shutdown_signal(&self.app_handle));
+
+        // Only radios are sent heartbeats, since nothing else would answer them
+        let mut heartbeats = match (&decoded, self.heartbeat) {
+            (Some(_), Some(heartbeat)) => Some(tokio::time::interval_at(
+                Instant::now() + heartbeat.interval,
+                heartbeat.interval,
+            )),
+            _ => None,
+        };
+
+        // Each device watches whether its own nodes are still being heard
+        let mut liveness = liveness_interval(&liveness_config(&self.app_handle));
+
+        let (exit, reply) = loop {
+            tokio::select! {
+                _ = intake_stopped(&shutdown) => {
+                    debug!("Stopped intake of device \"{}\" for shutdown", self.device_key);
+                    shutdown = None;
+                }
+                packet = next_packet(&mut decoded), if shutdown.is_some() => match packet {
+                    Some(packet) => self.handle_packet(packet).await,
+
+                    // The decoded packet stream only closes once the connection's read
+                    // task has stopped, which happens when the port vanishes
+                    None => break (DeviceExit::ConnectionLost, None),
+                },
+                command = self.commands.recv() => match command {
+                    Some(DeviceCommand::HandlePacket(packet)) => self.handle_packet(packet).await,
+                    Some(
+                        command @ (DeviceCommand::Send(..)
+                        | DeviceCommand::Write(..)
+                        | DeviceCommand::SendRemoteAdmin(..)
+                        | DeviceCommand::ReadGpios(..)
+                        | DeviceCommand::SendTraceroute(..)
+                        | DeviceCommand::SetFixedPosition(..)),
+                    ) => self.send_or_hold(command).await,
+                    Some(DeviceCommand::TimeOutRemoteAdmin(request_id, reply)) => {
+                        self.time_out_remote_admin(request_id).await;
+                        let _ = reply.send(());
+                    }
+                    Some(DeviceCommand::TimeOutGpioRead(request_id, reply)) => {
+                        self.packet_api.lock().await.gpio_reads.finish(request_id);
+                        let _ = reply.send(());
+                    }
+                    Some(DeviceCommand::TimeOutTraceroute(request_id, reply)) => {
+                        let pending = self.packet_api.lock().await.traceroutes.finish(request_id);
+                        let _ = reply.send(pending);
+                    }
+                    Some(DeviceCommand::EditGraph(edit, reply)) => {
+                        let _ = reply.send(self.edit_graph(edit).await);
+                    }
+                    Some(DeviceCommand::GetSnapshot(reply)) => {
+                        let _ = reply.send(self.packet_api.lock().await.device.clone());
+                    }
+                    Some(DeviceCommand::TimeOutConfiguration(config_id, reply)) => {
+                        let _ = reply.send(self.time_out_configuration(config_id).await);
+                    }
+                    Some(DeviceCommand::RetryConfiguration(reply)) => {
+                        let _ = reply.send(self.retry_configuration().await);
+                    }
+                    Some(DeviceCommand::TimeOutMessage(channel, message_id, reason, reply)) => {
+                        let _ = reply.send(self.time_out_message(channel, message_id, reason).await);
+                    }
+                    Some(DeviceCommand::Disconnect(reply)) => {
+                        break (DeviceExit::Disconnected, Some(reply))
+                    }
+                    None => break (DeviceExit::Disconnected, None),
+                },
+
+                // Sends held while the radio's TX queue was full go out once it has room,
+                // or once they've waited long enough that its queue status was likely lost
+                opened = radio_queue_opened(&self.radio_queue, self.held_until),
+                    if !self.held_sends.is_empty() =>
+                {
+                    self.release_held_send(opened).await
+                }
+
+                // Node infos heard outside configuration are applied to the graph once
+                // their batch is due, even if no other packet arrives by then
+                _ = deadline_due(self.graph_batch_deadline) => self.flush_graph_batch().await,
+
+                // A graph held back by its update window is published once the window
+                // has passed, so the end of a burst of changes still reaches the UI
+                _ = deadline_due(self.graph_publish_deadline) => self.publish_due_graph().await,
+
+                _ = heartbeat_due(&mut heartbeats), if shutdown.is_some() => {
+                    if self.check_heartbeat().await {
+                        break (DeviceExit::Unresponsive, None);
+                    }
+                }
+
+                _ = liveness.tick(), if shutdown.is_some() => {
+                    self.check_liveness(&mut liveness).await
+                }
+
+                _ = refresh.tick() => self.publish_summary().await,
+            }
+        };
+
+        {
+            let mut packet_api = self.packet_api.lock().await;
+
+            packet_api
+                .device
+                .set_status(SerialDeviceStatus::Disconnected);
+            self.summary.send_replace(packet_api.summary());
+        }
+
+        trace!("Stopped task of device \"{}\": {:?}", self.device_key, exit);
+
+        if let Some(reply) = reply {
+            let _ = reply.send(());
+        }
+
+        exit
+    }
+
+    async fn handle_packet(&mut self, packet: protobufs::FromRadio) {
+        trace!("Received packet from device: {:?}", packet);
+
+        let mut packet_api = self.packet_api.lock().await;
+        let reporter =
+            ErrorReporter::new(&self.app_handle, module_path!()).with_device(&self.device_key);
+
+        packet_api.last_packet_received = get_current_time_u32();
+        self.last_packet_at = Instant::now();
+
+        crate::scripting::tap_packet_scripts(&*packet_api, &packet);
+
+        if let Some(event) = tap_debug_stream(&mut packet_api, &packet) {
+            if let Err(e) = dispatch_debug_packet(&self.app_handle, event) {
+                reporter.error(
+                    AppErrorCode::EventDispatchFailed,
+                    format!("Failed to dispatch debug packet: {}", e),
+                );
+            }
+        }
+
+        tap_packet_capture(&mut packet_api, &packet, &reporter);
+
+        let previous_status = packet_api.device.status.clone();
+
+        // Any packet shows an unresponsive radio has recovered
+        if previous_status == SerialDeviceStatus::Unresponsive {
+            debug!("Device \"{}\" is responding again", self.device_key);
+            packet_api.device.set_status(SerialDeviceStatus::Connected);
+        }
+
+        let handle_result = packet_api.handle_packet_from_radio(packet);
+
+        let status = packet_api.device.status.clone();
+
+        self.graph_batch_deadline = packet_api
+            .graph_batch
+            .deadline(status == SerialDeviceStatus::Configuring);
+        self.graph_publish_deadline = packet_api.graph_publish.next_deadline();
+
+        if let Err(DeviceUpdateError::DecodeFailure(_)) = handle_result {
+            if let Ok(mut metrics) = packet_api.metrics.lock() {
+                metrics.record_decode_failure();
+            }
+        }
+
+        // The stored graph is loaded on a blocking thread, while this task keeps
+        // handling packets and queues the changes they make
+        if packet_api.start_graph_rebuild() {
+            let rebuild_handle = self.app_handle.clone();
+            let rebuild_device_key = self.device_key.clone();
+            let device_id = packet_api.device.my_node_info.my_node_num;
+
+            let rebuild = run_graph_rebuild(self.packet_api.clone(), move || {
+                load_stored_graph(&rebuild_handle, device_id)
+            });
+
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = rebuild.await {
+                    warn!("Failed to rebuild graph of {}: {}", rebuild_device_key, e);
+                }
+            });
+        }
+
+        if status != previous_status {
+            self.summary.send_replace(packet_api.summary());
+        }
+
+        let configured =
+            previous_status == SerialDeviceStatus::Configuring && status != previous_status;
+
+        // The radio doesn't send canned messages with its module config, so they're
+        // read once it's configured to fill the cache `get_canned_messages` serves
+        let read_canned_messages = configured
+            && status == SerialDeviceStatus::Connected
+            && packet_api.device.canned_messages.is_none()
+            && packet_api
+                .device
+                .module_config
+                .canned_message
+                .as_ref()
+                .map_or(false, |config| config.enabled);
+
+        drop(packet_api);
+
+        // Show the fully downloaded node DB as soon as configuration finishes
+        // rather than waiting for the coalescing interval
+        if configured {
+            if let Err(e) = flush_coalesced_events(&self.app_handle) {
+                reporter.error(
+                    AppErrorCode::EventDispatchFailed,
+                    format!("Failed to flush coalesced events: {}", e),
+                );
+            }
+        }
+
+        if status != previous_status {
+            if let Err(e) = dispatch_devices_list_changed(
+                &self.app_handle,
+                self.device_key.clone(),
+                DevicesListChange::StatusChanged,
+                status,
+            ) {
+                reporter.error(
+                    AppErrorCode::EventDispatchFailed,
+                    format!("Failed to dispatch devices list change: {}", e),
+                );
+            }
+        }
+
+        if let Err(err) = handle_result {
+            reporter.device_update_error(&err);
+        }
+
+        if read_canned_messages {
+            let request = RadioWrite::local_admin(
+                protobufs::admin_message::PayloadVariant::GetCannedMessageModuleMessagesRequest(
+                    true,
+                ),
+                true,
+            );
+
+            let (reply, result) = oneshot::channel();
+            self.send_or_hold(DeviceCommand::Write(request, reply))
+                .await;
+
+            let handle = self.app_handle.clone();
+            let device_key = self.device_key.clone();
+
+            // Reported once sent, since the request may be held until the radio has room
+            tauri::async_runtime::spawn(async move {
+                if let Ok(Err(e)) = result.await {
+                    ErrorReporter::new(&handle, module_path!())
+                        .with_device(&device_key)
+                        .warning(
+                            AppErrorCode::AdminRequestFailed,
+                            format!("Failed to request canned messages: {}", e),
+                        );
+                }
+            });
+        }
+    }
+
+    async fn send_text(&self, message: OutgoingText) -> Result<Option<u32>, String> {
+        let radio_connections = self
+            .app_handle
+            .try_state::<RadioConnectionsState>()
+            .map(|connections| connections.inner.clone())
+            .ok_or("Radio connections not initialized")?;
+
+        let mut packet_api = self.packet_api.lock().await;
+
+        let mut connections_guard = radio_connections.lock().await;
+        let connection = connections_guard
+            .get_mut(&self.device_key)
+            .ok_or("Radio connection not initialized")?;
+
+        connection
+            .send_text(
+                &mut *packet_api,
+                message.text,
+                message.destination,
+                message.want_ack,
+                MeshChannel::new(message.channel).map_err(|e| e.to_string())?,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if let Some(journal_id) = message.journal_id {
+            mark_outgoing_message_sent(&*packet_api, message.channel, journal_id);
+        }
+
+        dispatch_updated_device(
+            &packet_api.app_handle,
+            &packet_api.device_key,
+            &packet_api.device,
+        )
+        .map_err(|e| e.to_string())?;
+
+        let message_id = packet_api.device.last_sent_text_id(message.channel);
+
+        if let (true, Some(message_id)) = (message.want_ack, message_id) {
+            dispatch_message_state(
+                &self.app_handle,
+                MessageStateEvent {
+                    api_version: EVENT_API_VERSION,
+                    device_key: self.device_key.clone(),
+                    channel: message.channel,
+                    message_id,
+                    state: ChannelMessageState::Pending,
+                },
+            )
+            .map_err(|e| e.to_string())?;
+        }
+
+        Ok(message_id)
+    }
+
+    /// Sends packets through the radio for `command`, or holds it while the radio's TX
+    /// queue is full. Held commands go out in the order they arrived, after those
+    /// already held, while packets from the radio keep being handled.
+    async fn send_or_hold(&mut self, command: DeviceCommand) {
+        if self.held_sends.is_empty() && self.radio_queue.is_open() {
+            return self.dispatch_send(command).await;
+        }
+
+        if self.held_sends.is_empty() {
+            debug!(
+                "Holding sends to \"{}\" until its radio has room",
+                self.device_key
+            );
+            self.held_until = Some(Instant::now() + DEFAULT_RADIO_QUEUE_WAIT_TIMEOUT);
+        }
+
+        self.held_sends.push_back(command);
+    }
+
+    /// Sends the longest held command. One is sent at a time, so the radio's queue status
+    /// packets are handled in between and hold the rest again once its queue is full. If
+    /// the wait timed out, the gate has reopened and the UI is told sends are no longer
+    /// throttled.
+    async fn release_held_send(&mut self, reported_capacity: bool) {
+        if !reported_capacity {
+            let packet_api = self.packet_api.lock().await;
+            let (free, maxlen) = packet_api
+                .device
+                .queue_status
+                .as_ref()
+                .map_or((0, 0), |status| (status.free, status.maxlen));
+            drop(packet_api);
+
+            if let Err(e) = dispatch_radio_queue_throttle_status(
+                &self.app_handle,
+                RadioQueueThrottleStatus {
+                    api_version: EVENT_API_VERSION,
+                    device_key: self.device_key.clone(),
+                    throttled: false,
+                    free,
+                    maxlen,
+                },
+            ) {
+                warn!("Failed to dispatch radio queue throttle status: {}", e);
+            }
+        }
+
+        if let Some(command) = self.held_sends.pop_front() {
+            self.dispatch_send(command).await;
+        }
+
+        self.held_until = (!self.held_sends.is_empty())
+            .then(|| Instant::now() + DEFAULT_RADIO_QUEUE_WAIT_TIMEOUT);
+    }
+
+    async fn dispatch_send(&mut self, command: DeviceCommand) {
+        match command {
+            DeviceCommand::Send(message, reply) => {
+                let _ = reply.send(self.send_text(message).await);
+            }
+            DeviceCommand::Write(write, reply) => {
+                let _ = reply.send(self.write_to_radio(write).await);
+            }
+            DeviceCommand::SendRemoteAdmin(request, reply) => {
+                let _ = reply.send(self.send_remote_admin(request).await);
+            }
+            DeviceCommand::ReadGpios(node_num, gpio_mask, reply) => {
+                let _ = reply.send(self.read_gpios(node_num, gpio_mask).await);
+            }
+            DeviceCommand::SendTraceroute(destination, reply) => {
+                let _ = reply.send(self.send_traceroute(destination).await);
+            }
+            DeviceCommand::SetFixedPosition(fixed_position, reply) => {
+                let _ = reply.send(self.set_fixed_position(fixed_position).await);
+            }
+            _ => unreachable!("Only commands that send through the radio are dispatched"),
+        }
+    }
+
+    fn radio_connections(&self) -> Result<RadioConnectionsStateInner, CommandError> {
+        self.app_handle
+            .try_state::<RadioConnectionsState>()
+            .map(|connections| connections.inner.clone())
+            .ok_or_else(|| "Radio connections not initialized".into())
+    }
+
+    async fn write_to_radio(&self, write: RadioWrite) -> Result<(), CommandError> {
+        let radio_connections = self.radio_connections()?;
+        let mut packet_api = self.packet_api.lock().await;
+
+        let mut connections_guard = radio_connections.lock().await;
+        let connection = connections_guard
+            .get_mut(&self.device_key)
+            .ok_or_else(|| CommandError::radio_not_connected(&self.device_key))?;
+
+        write.send(connection, &mut *packet_api).await
+    }
+
+    async fn send_remote_admin(&self, request: RemoteAdminRequest) -> RemoteAdminReply {
+        let radio_connections = self.radio_connections()?;
+        let mut packet_api = self.packet_api.lock().await;
+
+        if request.node_num == packet_api.device.my_node_info.my_node_num {
+            return Err("Use the device's own configuration commands for its node".into());
+        }
+
+        let mut connections_guard = radio_connections.lock().await;
+        let connection = connections_guard
+            .get_mut(&self.device_key)
+            .ok_or_else(|| CommandError::radio_not_connected(&self.device_key))?;
+
+        send_remote_admin_message(
+            connection,
+            &mut *packet_api,
+            request.message,
+            request.node_num,
+            request.want_response,
+        )
+        .await
+    }
+
+    async fn time_out_remote_admin(&self, request_id: u32) {
+        let mut packet_api = self.packet_api.lock().await;
+
+        if let Err(e) =
+            finish_remote_admin(&mut *packet_api, request_id, RemoteAdminOutcome::TimedOut)
+        {
+            warn!("Failed to dispatch admin request result: {}", e);
+        }
+    }
+
+    async fn read_gpios(&self, node_num: u32, gpio_mask: u64) -> GpioReadReply {
+        let radio_connections = self.radio_connections()?;
+        let mut packet_api = self.packet_api.lock().await;
+
+        let mut connections_guard = radio_connections.lock().await;
+        let connection = connections_guard
+            .get_mut(&self.device_key)
+            .ok_or_else(|| CommandError::radio_not_connected(&self.device_key))?;
+
+        send_hardware_message(
+            connection,
+            &mut *packet_api,
+            build_hardware_message(hardware_message::Type::ReadGpios, gpio_mask, 0),
+            node_num,
+            true,
+        )
+        .await?;
+
+        packet_api
+            .gpio_reads
+            .take_last_request()
+            .ok_or_else(|| "Radio didn't echo the GPIO read request".into())
+    }
+
+    async fn send_traceroute(&self, destination: u32) -> Result<u32, CommandError> {
+        let radio_connections = self.radio_connections()?;
+        let mut packet_api = self.packet_api.lock().await;
+
+        if destination == packet_api.device.my_node_info.my_node_num {
+            return Err("Can't trace the route to the device's own node".into());
+        }
+
+        let mut connections_guard = radio_connections.lock().await;
+        let connection = connections_guard
+            .get_mut(&self.device_key)
+            .ok_or_else(|| CommandError::radio_not_connected(&self.device_key))?;
+
+        // Echoed so the request's packet id can be matched to its response
+        connection
+            .send_mesh_packet(
+                &mut *packet_api,
+                EncodedMeshPacketData::new(protobufs::RouteDiscovery::default().encode_to_vec()),
+                protobufs::PortNum::TracerouteApp,
+                PacketDestination::Node(NodeId::new(destination)),
+                MeshChannel::new(0).map_err(|e| e.to_string())?,
+                true,
+                true,
+                true,
+                None,
+                None,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        packet_api
+            .traceroutes
+            .take_last_request()
+            .ok_or_else(|| "Radio didn't echo the traceroute request".into())
+    }
+
+    async fn set_fixed_position(
+        &self,
+        fixed_position: Option<FixedPosition>,
+    ) -> Result<(), CommandError> {
+        let radio_connections = self.radio_connections()?;
+        let mut packet_api = self.packet_api.lock().await;
+
+        let mut connections_guard = radio_connections.lock().await;
+        let connection = connections_guard
+            .get_mut(&self.device_key)
+            .ok_or_else(|| CommandError::radio_not_connected(&self.device_key))?;
+
+        let position_config = packet_api.device.config.position.clone();
+
+        connection
+            .update_config(
+                &mut *packet_api,
+                build_fixed_position_config(position_config, fixed_position.is_some()),
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let fixed_position = match fixed_position {
+            Some(fixed_position) => fixed_position,
+            None => {
+                packet_api.device.clear_local_fixed_position();
+
+                return dispatch_updated_device(
+                    &self.app_handle,
+                    &self.device_key,
+                    &packet_api.device,
+                )
+                .map_err(|e| e.to_string().into());
+            }
+        };
+
+        let now = get_current_time_u32();
+
+        send_position(
+            connection,
+            &mut *packet_api,
+            fixed_position.to_position(now),
+            PacketDestination::Local,
+        )
+        .await?;
+
+        // Update local state without waiting for the radio to report the new position
+
+        let position_packet = packet_api
+            .device
+            .set_local_fixed_position(&fixed_position, now);
+
+        let graph = {
+            let mut graph = packet_api.write_graph()?;
+            graph.update_from_position(position_packet.packet, position_packet.data);
+            graph.clone()
+        };
+
+        self.publish_graph(graph, &packet_api.device)?;
+
+        dispatch_updated_device(&self.app_handle, &self.device_key, &packet_api.device)
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+
+    async fn edit_graph(&self, edit: GraphEdit) -> Result<(), CommandError> {
+        let packet_api = self.packet_api.lock().await;
+
+        let (graph, overrides_changed) = {
+            let mut graph = packet_api.write_graph()?;
+            let overrides_changed = edit.apply(&mut graph)?;
+            (graph.clone(), overrides_changed)
+        };
+
+        if overrides_changed {
+            return publish_graph_overrides(
+                &self.app_handle,
+                self.device_key.clone(),
+                graph,
+                &packet_api.device,
+            );
+        }
+
+        self.publish_graph(graph, &packet_api.device)
+    }
+
+    fn publish_graph(&self, graph: MeshGraph, device: &MeshDevice) -> Result<(), CommandError> {
+        dispatch_graph_geojson_update(
+            &self.app_handle,
+            GraphGeoJson::new(self.device_key.clone(), &graph, device),
+        )
+        .map_err(|e| e.to_string())?;
+        dispatch_updated_graph(&self.app_handle, Some(self.device_key.clone()), graph)
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+
+    async fn time_out_message(&self, channel: u32, message_id: u32, reason: String) -> bool {
+        let mut packet_api = self.packet_api.lock().await;
+
+        if packet_api.device.message_state(channel, message_id)
+            != Some(&ChannelMessageState::Pending)
+        {
+            return false;
+        }
+
+        let state = ChannelMessageState::Error(reason);
+
+        if let Err(err) = update_message_state(&mut *packet_api, channel, message_id, state) {
+            ErrorReporter::new(&self.app_handle, module_path!())
+                .with_device(&self.device_key)
+                .device_update_error(&err);
+        }
+
+        true
+    }
+
+    async fn time_out_configuration(&self, config_id: Option<u32>) -> Option<String> {
+        let mut packet_api = self.packet_api.lock().await;
+
+        if packet_api.device.status != SerialDeviceStatus::Configuring {
+            return None;
+        }
+
+        if matches!(config_id, Some(id) if id != packet_api.device.config_id) {
+            trace!(
+                "Ignoring timeout of an earlier configuration attempt of \"{}\"",
+                self.device_key
+            );
+            return None;
+        }
+
+        packet_api
+            .device
+            .set_status(SerialDeviceStatus::Disconnected);
+        self.summary.send_replace(packet_api.summary());
+
+        Some(packet_api.device.config_progress.timeout_message())
+    }
+
+    /// Restarts the configuration flow of a radio whose configuration timed out, with a
+    /// new config id. Configured devices are left alone, and so are devices still
+    /// configuring, so repeated retries can't pile up configuration requests.
+    async fn retry_configuration(&self) -> Result<u32, String> {
+        let mut packet_api = self.packet_api.lock().await;
+
+        match packet_api.device.status {
+            SerialDeviceStatus::Disconnected => {}
+            SerialDeviceStatus::Configuring => {
+                return Err(format!(
+                    "Device \"{}\" is still configuring",
+                    self.device_key
+                ))
+            }
+            ref status => {
+                return Err(format!(
+                    "Device \"{}\" is {:?}, only a failed configuration can be retried",
+                    self.device_key, status
+                ))
+            }
+        }
+
+        debug!("Retrying configuration of \"{}\"", self.device_key);
+
+        let config_id = generate_rand_id();
+        packet_api.device.config_id = config_id;
+        packet_api.device.config_progress = Default::default();
+        packet_api
+            .device
+            .set_status(SerialDeviceStatus::Configuring);
+
+        let radio_connections = self
+            .app_handle
+            .try_state::<RadioConnectionsState>()
+            .map(|connections| connections.inner.clone())
+            .ok_or("Radio connections not initialized")?;
+
+        let sent = match radio_connections.lock().await.get_mut(&self.device_key) {
+            Some(connection) => connection
+                .send_to_radio_packet(Some(protobufs::to_radio::PayloadVariant::WantConfigId(
+                    config_id,
+                )))
+                .await
+                .map_err(|e| e.to_string()),
+            None => Err("Radio connection not initialized".to_string()),
+        };
+
+        if let Err(e) = sent {
+            packet_api
+                .device
+                .set_status(SerialDeviceStatus::Disconnected);
+            return Err(e);
+        }
+
+        self.summary.send_replace(packet_api.summary());
+
+        Ok(config_id)
+    }
+
+    /// Marks a connected radio that hasn't sent anything within the heartbeat's
+    /// staleness window as unresponsive, then sends it another heartbeat. Returns
+    /// whether the radio just became unresponsive and is to be reconnected to.
+    async fn check_heartbeat(&self) -> bool {
+        let heartbeat = match self.heartbeat {
+            Some(heartbeat) => heartbeat,
+            None => return false,
+        };
+
+        let mut packet_api = self.packet_api.lock().await;
+
+        // Configuration has its own timeout
+        let status = packet_api.device.status.clone();
+        if status != SerialDeviceStatus::Connected && status != SerialDeviceStatus::Unresponsive {
+            return false;
+        }
+
+        let stale = self.last_packet_at.elapsed() >= heartbeat.staleness_window();
+        let became_unresponsive = stale && status == SerialDeviceStatus::Connected;
+
+        if became_unresponsive {
+            warn!(
+                "Device \"{}\" hasn't answered {} heartbeats",
+                self.device_key, heartbeat.missed_heartbeats
+            );
+
+            packet_api
+                .device
+                .set_status(SerialDeviceStatus::Unresponsive);
+            self.summary.send_replace(packet_api.summary());
+        }
+
+        if !(became_unresponsive && heartbeat.reconnect) {
+            if let Err(e) = self.send_heartbeat(&mut *packet_api).await {
+                warn!("Failed to send heartbeat to \"{}\": {}", self.device_key, e);
+            }
+        }
+
+        drop(packet_api);
+
+        if became_unresponsive {
+            if let Err(e) = dispatch_devices_list_changed(
+                &self.app_handle,
+                self.device_key.clone(),
+                DevicesListChange::StatusChanged,
+                SerialDeviceStatus::Unresponsive,
+            ) {
+                ErrorReporter::new(&self.app_handle, module_path!())
+                    .with_device(&self.device_key)
+                    .error(
+                        AppErrorCode::EventDispatchFailed,
+                        format!("Failed to dispatch devices list change: {}", e),
+                    );
+            }
+        }
+
+        became_unresponsive && heartbeat.reconnect
+    }
+
+    /// Marks nodes that have stopped being heard as offline, alerting for each, and
+    /// removes waypoints that have expired. Check interval changes apply from the
+    /// next check onwards.
+    async fn check_liveness(&self, liveness: &mut tokio::time::Interval) {
+        let config = liveness_config(&self.app_handle);
+
+        if config.check_interval() != liveness.period() {
+            trace!(
+                "Checking node liveness of \"{}\" every {:?}",
+                self.device_key,
+                config.check_interval()
+            );
+
+            *liveness = liveness_interval(&config);
+        }
+
+        let thresholds = liveness_thresholds(&self.app_handle);
+        let now = get_current_time_u32();
+        let mut packet_api = self.packet_api.lock().await;
+
+        if packet_api.device.prune_expired_waypoints(now) > 0 {
+            if let Err(e) =
+                dispatch_updated_device(&self.app_handle, &self.device_key, &packet_api.device)
+            {
+                warn!("Failed to dispatch device update: {}", e);
+            }
+        }
+
+        let transitions = packet_api.device.node_liveness.evaluate(now, &thresholds);
+
+        for transition in transitions {
+            dispatch_liveness_alert(
+                &self.app_handle,
+                &self.device_key,
+                &packet_api.device,
+                &transition,
+            );
+
+            let event = NodeStatusChangedEvent::new(self.device_key.clone(), transition);
+
+            if let Err(e) = dispatch_node_status_changed(&self.app_handle, event) {
+                ErrorReporter::new(&self.app_handle, module_path!())
+                    .with_device(&self.device_key)
+                    .error(
+                        AppErrorCode::EventDispatchFailed,
+                        format!("Failed to dispatch node status change: {}", e),
+                    );
+            }
+        }
+    }
+
+    /// Asks the radio for its metadata, which it answers without sending anything
+    /// over the mesh
+    async fn send_heartbeat(&self, packet_api: &mut MeshPacketApi<R>) -> Result<(), String> {
+        trace!("Sending heartbeat to \"{}\"", self.device_key);
+
+        let radio_connections = self
+            .app_handle
+            .try_state::<RadioConnectionsState>()
+            .map(|connections| connections.inner.clone())
+            .ok_or("Radio connections not initialized")?;
+
+        let mut connections_guard = radio_connections.lock().await;
+        let connection = connections_guard
+            .get_mut(&self.device_key)
+            .ok_or("Radio connection not initialized")?;
+
+        let request = protobufs::AdminMessage {
+            payload_variant: Some(
+                protobufs::admin_message::PayloadVariant::GetDeviceMetadataRequest(true),
+            ),
+            ..Default::default()
+        };
+
+        connection
+            .send_mesh_packet(
+                packet_api,
+                EncodedMeshPacketData::new(request.encode_to_vec()),
+                protobufs::PortNum::AdminApp,
+                PacketDestination::Local,
+                MeshChannel::new(0).map_err(|e| e.to_string())?,
+                true,
+                true,
+                false,
+                None,
+                None,
+            )
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn flush_graph_batch(&mut self) {
+        self.graph_batch_deadline = None;
+
+        let mut packet_api = self.packet_api.lock().await;
+        let flushed = packet_api.flush_graph_batch();
+        self.graph_publish_deadline = packet_api.graph_publish.next_deadline();
+        drop(packet_api);
+
+        if let Err(err) = flushed {
+            ErrorReporter::new(&self.app_handle, module_path!())
+                .with_device(&self.device_key)
+                .device_update_error(&err);
+        }
+    }
+
+    async fn publish_due_graph(&mut self) {
+        let mut packet_api = self.packet_api.lock().await;
+        let published = packet_api.publish_due_graph();
+        self.graph_publish_deadline = packet_api.graph_publish.next_deadline();
+        drop(packet_api);
+
+        if let Err(err) = published {
+            ErrorReporter::new(&self.app_handle, module_path!())
+                .with_device(&self.device_key)
+                .device_update_error(&err);
+        }
+    }
+
+    async fn publish_summary(&self) {
+        let summary = self.packet_api.lock().await.summary();
+        self.summary.send_replace(summary);
+    }
+}
+
+/// Appends a decoded packet to the device's capture file, if it's being captured.
+/// A capture that can't be written to is stopped rather than failing every packet.
+fn tap_packet_capture<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+    packet: &protobufs::FromRadio,
+    reporter: &ErrorReporter<'_, R>,
+) {
+    let capture = match packet_api.capture.as_mut() {
+        Some(capture) => capture,
+        None => return,
+    };
+
+    let record = CaptureRecord {
+        timestamp_millis: chrono::Utc::now().timestamp_millis().max(0) as u64,
+        packet: packet.clone(),
+    };
+
+    if let Err(e) = capture.append(&record) {
+        reporter.error(
+            AppErrorCode::CaptureWriteFailed,
+            format!(
+                "Stopped capturing packets to \"{}\": {}",
+                capture.path().display(),
+                e
+            ),
+        );
+        packet_api.capture = None;
+    }
+}
+
+/// Summarizes a decoded packet for the protocol console, if the device's debug
+/// stream is enabled and lets the packet through
+fn tap_debug_stream<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+    packet: &protobufs::FromRadio,
+) -> Option<DebugPacketEvent> {
+    let debug_stream = packet_api.debug_stream.as_mut()?;
+
+    let debug_packet = DebugPacket::new(
+        packet,
+        packet_api.device.my_node_info.my_node_num,
+        get_current_time_u32(),
+    );
+    let dropped_count = debug_stream.admit(&debug_packet)?;
+
+    Some(DebugPacketEvent {
+        api_version: EVENT_API_VERSION,
+        device_key: packet_api.device_key.clone(),
+        packet: debug_packet,
+        dropped_count,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use meshtastic::api::{StreamApi, StreamHandle};
+    use meshtastic::types::NodeId;
+    use tauri::test::MockRuntime;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use tokio::task::JoinHandle;
+
+    use super::*;
+    use crate::device::message_store::MessageStore;
+    use crate::ipc::helpers::{
+        await_gpio_read, await_remote_admin, read_canned_messages,
+        spawn_configuration_timeout_handler,
+    };
+    use crate::packet_api::remote_admin::RemoteConfigType;
+    use crate::packet_api::summary::ConnectionType;
+    use crate::scripting::{perform_action, ScriptAction};
+    use crate::settings::AppSettings;
+    use crate::state;
+    use crate::state::graph::SharedGraph;
+    use crate::state::mesh_devices::{device_summaries, get_device_handle, MeshDevicesStateInner};
+
+    const NODE_COUNT: u32 = 25;
+
+    fn frame(packet: protobufs::from_radio::PayloadVariant) -> Vec<u8> {
+        let payload = protobufs::FromRadio {
+            payload_variant: Some(packet),
+            ..Default::default()
+        }
+        .encode_to_vec();
+
+        let mut bytes = vec![0x94, 0xc3];
+        bytes.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+
+    /// Reads what the app wrote to the radio until a packet `matches` accepts,
+    /// skipping anything that isn't a frame
+    async fn read_to_radio(
+        radio: &mut DuplexStream,
+        matches: impl Fn(&protobufs::ToRadio) -> bool,
+    ) -> protobufs::ToRadio {
+        loop {
+            if radio.read_u8().await.unwrap() != 0x94 || radio.read_u8().await.unwrap() != 0xc3 {
+                continue;
+            }
+
+            let mut payload = vec![0; radio.read_u16().await.unwrap() as usize];
+            radio.read_exact(&mut payload).await.unwrap();
+
+            match protobufs::ToRadio::decode(payload.as_slice()) {
+                Ok(packet) if matches(&packet) => return packet,
+                _ => continue,
+            }
+        }
+    }
+
+    fn text_payload(packet: &protobufs::ToRadio) -> Option<&[u8]> {
+        use protobufs::{mesh_packet, to_radio};
+
+        match &packet.payload_variant {
+            Some(to_radio::PayloadVariant::Packet(protobufs::MeshPacket {
+                payload_variant: Some(mesh_packet::PayloadVariant::Decoded(data)),
+                ..
+            })) if data.portnum == protobufs::PortNum::TextMessageApp as i32 => Some(&data.payload),
+            _ => None,
+        }
+    }
+
+    fn is_heartbeat(packet: &protobufs::ToRadio) -> bool {
+        use protobufs::{mesh_packet, to_radio};
+
+        matches!(
+            &packet.payload_variant,
+            Some(to_radio::PayloadVariant::Packet(protobufs::MeshPacket {
+                payload_variant: Some(mesh_packet::PayloadVariant::Decoded(data)),
+                ..
+            })) if data.portnum == protobufs::PortNum::AdminApp as i32
+        )
+    }
+
+    /// The routing result a radio reports for a message it sent, e.g. its ack
+    fn routing_result(
+        packet_id: u32,
+        message_id: u32,
+        error: protobufs::routing::Error,
+    ) -> Vec<u8> {
+        use protobufs::{mesh_packet, routing};
+
+        let routing = protobufs::Routing {
+            variant: Some(routing::Variant::ErrorReason(error as i32)),
+        };
+
+        frame(protobufs::from_radio::PayloadVariant::Packet(
+            protobufs::MeshPacket {
+                from: 0x500,
+                to: 0x400,
+                id: packet_id,
+                payload_variant: Some(mesh_packet::PayloadVariant::Decoded(protobufs::Data {
+                    portnum: protobufs::PortNum::RoutingApp as i32,
+                    payload: routing.encode_to_vec(),
+                    request_id: message_id,
+                    ..Default::default()
+                })),
+                ..Default::default()
+            },
+        ))
+    }
+
+    /// Connects a device over an in-memory stream like `create_new_connection` does,
+    /// returning the radio's end of the stream
+    async fn connect(
+        handle: tauri::AppHandle<MockRuntime>,
+        devices: &MeshDevicesStateInner<MockRuntime>,
+        device_key: &str,
+        heartbeat: Option<Heartbeat>,
+    ) -> (
+        DeviceHandle<MockRuntime>,
+        JoinHandle<DeviceExit>,
+        DuplexStream,
+    ) {
+        let (host, radio) = tokio::io::duplex(64 * 1024);
+
+        let mut packet_api = MeshPacketApi::new(
+            handle.clone(),
+            device_key.into(),
+            MeshDevice::new(),
+            Arc::new(SharedGraph::new(MeshGraph::new())),
+        );
+
+        // Keeps the stored graph from being rebuilt once the radio reports its node num
+        packet_api.connection_type = ConnectionType::Simulated;
+
+        packet_api.device.set_status(SerialDeviceStatus::Connecting);
+        let (decoded, stream_api) = StreamApi::new()
+            .connect(StreamHandle::from_stream(host))
+            .await;
+
+        packet_api
+            .device
+            .set_status(SerialDeviceStatus::Configuring);
+        let stream_api = stream_api
+            .configure(packet_api.device.config_id)
+            .await
+            .unwrap();
+
+        handle
+            .state::<RadioConnectionsState>()
+            .inner
+            .lock()
+            .await
+            .insert(device_key.into(), stream_api);
+
+        let (device, actor) = device_actor(packet_api);
+        devices
+            .lock()
+            .await
+            .insert(device_key.into(), device.clone());
+
+        let actor = actor.with_heartbeat(heartbeat);
+
+        (device, tokio::spawn(actor.run(Some(decoded))), radio)
+    }
+
+    /// Has the radio report its node num and complete configuration
+    async fn configure(device: &DeviceHandle<MockRuntime>, radio: &mut DuplexStream) {
+        use protobufs::from_radio::PayloadVariant;
+
+        let config_id = device.snapshot().await.unwrap().config_id;
+        let mut config = frame(PayloadVariant::MyInfo(protobufs::MyNodeInfo {
+            my_node_num: 0x400,
+            ..Default::default()
+        }));
+        config.extend(frame(PayloadVariant::ConfigCompleteId(config_id)));
+        radio.write_all(&config).await.unwrap();
+
+        wait_for_status(device, SerialDeviceStatus::Connected).await;
+    }
+
+    async fn wait_for_status(device: &DeviceHandle<MockRuntime>, status: SerialDeviceStatus) {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while device.summary().status != status {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("device never became {:?}", status));
+    }
+
+    /// Connects, configures, sends from and disconnects a device, checking each step
+    async fn run_device(
+        handle: tauri::AppHandle<MockRuntime>,
+        devices: MeshDevicesStateInner<MockRuntime>,
+        device_key: &'static str,
+        node_num: u32,
+    ) {
+        use protobufs::from_radio::PayloadVariant;
+
+        let (device, task, mut radio) = connect(handle.clone(), &devices, device_key, None).await;
+
+        assert_eq!(device.summary().status, SerialDeviceStatus::Configuring);
+
+        // The radio answers the configuration request with its node database
+        let config_id = device.snapshot().await.unwrap().config_id;
+        let mut config = frame(PayloadVariant::MyInfo(protobufs::MyNodeInfo {
+            my_node_num: node_num,
+            ..Default::default()
+        }));
+
+        for num in node_num..node_num + NODE_COUNT {
+            config.extend(frame(PayloadVariant::NodeInfo(protobufs::NodeInfo {
+                num,
+                ..Default::default()
+            })));
+        }
+
+        config.extend(frame(PayloadVariant::ConfigCompleteId(config_id)));
+        radio.write_all(&config).await.unwrap();
+
+        wait_for_status(&device, SerialDeviceStatus::Connected).await;
+        assert_eq!(device.time_out_configuration().await, Ok(None));
+
+        let snapshot = device.snapshot().await.unwrap();
+        assert_eq!(snapshot.nodes.len(), NODE_COUNT as usize);
+        assert_eq!(device.summary().node_num, Some(node_num));
+
+        // Sent through this device's own connection
+        let text = format!("hello from {}", device_key);
+
+        device
+            .send_text(OutgoingText {
+                text: text.clone(),
+                destination: PacketDestination::Broadcast,
+                channel: 0,
+                want_ack: true,
+                journal_id: None,
+            })
+            .await
+            .unwrap();
+
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            read_to_radio(&mut radio, |packet| {
+                text_payload(packet) == Some(text.as_bytes())
+            }),
+        )
+        .await
+        .expect("text never reached the radio");
+
+        // Disconnected like `drop_device_connection` does
+        let device = devices.lock().await.remove(device_key).unwrap();
+        device.disconnect().await;
+
+        assert_eq!(task.await.unwrap(), DeviceExit::Disconnected);
+        assert_eq!(device.summary().status, SerialDeviceStatus::Disconnected);
+        assert!(device.handle_packet(Default::default()).is_err());
+        assert!(device.snapshot().await.is_err());
+
+        let connection = handle
+            .state::<RadioConnectionsState>()
+            .inner
+            .lock()
+            .await
+            .remove(device_key)
+            .unwrap();
+        let _ = connection.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn devices_connect_configure_send_and_disconnect_concurrently() {
+        let app = tauri::test::mock_app();
+        app.manage(RadioConnectionsState::new());
+
+        let devices: MeshDevicesStateInner<_> = Default::default();
+
+        let (first, second) = tokio::join!(
+            tokio::spawn(run_device(app.handle(), devices.clone(), "COM4", 0x100)),
+            tokio::spawn(run_device(app.handle(), devices.clone(), "COM5", 0x200)),
+        );
+
+        first.unwrap();
+        second.unwrap();
+
+        assert!(device_summaries(&devices).await.is_empty());
+        assert!(get_device_handle(&devices, "COM4").await.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn configuration_completing_after_timeout_is_ignored() {
+        use protobufs::from_radio::PayloadVariant;
+
+        let app = tauri::test::mock_app();
+        app.manage(RadioConnectionsState::new());
+
+        let devices: MeshDevicesStateInner<_> = Default::default();
+        let (device, task, mut radio) = connect(app.handle(), &devices, "COM6", None).await;
+        let config_id = device.snapshot().await.unwrap().config_id;
+
+        assert!(device.time_out_configuration().await.unwrap().is_some());
+        assert_eq!(device.summary().status, SerialDeviceStatus::Disconnected);
+
+        // Completes just after the deadline. The node info sent after it shows once
+        // the completion has been handled.
+        let mut config = frame(PayloadVariant::ConfigCompleteId(config_id));
+        config.extend(frame(PayloadVariant::NodeInfo(protobufs::NodeInfo {
+            num: 0x300,
+            ..Default::default()
+        })));
+        radio.write_all(&config).await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !device.snapshot().await.unwrap().nodes.contains_key(&0x300) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("node info never handled");
+
+        assert_eq!(device.summary().status, SerialDeviceStatus::Disconnected);
+        assert_eq!(device.time_out_configuration().await, Ok(None));
+
+        device.disconnect().await;
+        assert_eq!(task.await.unwrap(), DeviceExit::Disconnected);
+
+        let connection = app
+            .state::<RadioConnectionsState>()
+            .inner
+            .lock()
+            .await
+            .remove("COM6")
+            .unwrap();
+        let _ = connection.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn disconnecting_while_configuring_stops_every_task() {
+        let app = tauri::test::mock_app();
+        app.manage(RadioConnectionsState::new());
+
+        let devices: MeshDevicesStateInner<_> = Default::default();
+        let (device, task, _radio) = connect(app.handle(), &devices, "COM7", None).await;
+
+        let timeout = spawn_configuration_timeout_handler(
+            app.handle(),
+            device.clone(),
+            "COM7".into(),
+            device.snapshot().await.unwrap().config_id,
+            Duration::from_secs(600),
+        );
+
+        // Disconnected like `drop_device_connection` does
+        let device = devices.lock().await.remove("COM7").unwrap();
+        device.disconnect().await;
+
+        let connection = app
+            .state::<RadioConnectionsState>()
+            .inner
+            .lock()
+            .await
+            .remove("COM7")
+            .unwrap();
+        let _ = connection.disconnect().await;
+
+        assert_eq!(task.await.unwrap(), DeviceExit::Disconnected);
+        assert_eq!(device.summary().status, SerialDeviceStatus::Disconnected);
+
+        // The timeout stops rather than failing the configuration 10 minutes later
+        tokio::time::timeout(Duration::from_secs(10), timeout)
+            .await
+            .expect("configuration timeout still running")
+            .unwrap();
+        assert_eq!(
+            device.time_out_configuration().await,
+            Err(DEVICE_STOPPED.to_string())
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn reconnecting_within_the_timeout_keeps_the_new_connection() {
+        let app = tauri::test::mock_app();
+        app.manage(RadioConnectionsState::new());
+
+        let devices: MeshDevicesStateInner<_> = Default::default();
+        let (first, first_task, _first_radio) = connect(app.handle(), &devices, "COM8", None).await;
+
+        let timeout = spawn_configuration_timeout_handler(
+            app.handle(),
+            first.clone(),
+            "COM8".into(),
+            first.snapshot().await.unwrap().config_id,
+            Duration::from_millis(200),
+        );
+
+        // The first attempt is dropped and the port connected again before it times out
+        let first = devices.lock().await.remove("COM8").unwrap();
+        first.disconnect().await;
+
+        let connection = app
+            .state::<RadioConnectionsState>()
+            .inner
+            .lock()
+            .await
+            .remove("COM8")
+            .unwrap();
+        let _ = connection.disconnect().await;
+
+        assert_eq!(first_task.await.unwrap(), DeviceExit::Disconnected);
+
+        let (second, second_task, _second_radio) =
+            connect(app.handle(), &devices, "COM8", None).await;
+
+        tokio::time::timeout(Duration::from_secs(10), timeout)
+            .await
+            .expect("configuration timeout still running")
+            .unwrap();
+
+        // Past the first attempt's deadline, the second is still configuring
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(second.summary().status, SerialDeviceStatus::Configuring);
+
+        second.disconnect().await;
+        assert_eq!(second_task.await.unwrap(), DeviceExit::Disconnected);
+
+        let connection = app
+            .state::<RadioConnectionsState>()
+            .inner
+            .lock()
+            .await
+            .remove("COM8")
+            .unwrap();
+        let _ = connection.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn configuration_is_retried_over_the_open_connection() {
+        use protobufs::to_radio::PayloadVariant;
+
+        let app = tauri::test::mock_app();
+        app.manage(RadioConnectionsState::new());
+
+        let devices: MeshDevicesStateInner<_> = Default::default();
+        let (device, task, mut radio) = connect(app.handle(), &devices, "COM9", None).await;
+        let first_attempt = device.snapshot().await.unwrap().config_id;
+
+        // The radio never answers the first configuration request
+        let timeout = spawn_configuration_timeout_handler(
+            app.handle(),
+            device.clone(),
+            "COM9".into(),
+            first_attempt,
+            Duration::from_millis(100),
+        );
+        timeout.await.unwrap();
+        assert_eq!(device.summary().status, SerialDeviceStatus::Disconnected);
+
+        let second_attempt = device.retry_configuration().await.unwrap();
+        assert_ne!(second_attempt, first_attempt);
+        assert_eq!(device.summary().status, SerialDeviceStatus::Configuring);
+
+        // Asked for over the same connection, after the first request
+        read_to_radio(&mut radio, |packet| {
+            packet.payload_variant == Some(PayloadVariant::WantConfigId(second_attempt))
+        })
+        .await;
+
+        // Neither a second retry nor the first attempt's timeout touch the new attempt
+        assert!(device.retry_configuration().await.is_err());
+        assert_eq!(
+            device.time_out_configuration_attempt(first_attempt).await,
+            Ok(None)
+        );
+
+        configure(&device, &mut radio).await;
+        assert!(device.retry_configuration().await.is_err());
+
+        device.disconnect().await;
+        assert_eq!(task.await.unwrap(), DeviceExit::Disconnected);
+
+        let connection = app
+            .state::<RadioConnectionsState>()
+            .inner
+            .lock()
+            .await
+            .remove("COM9")
+            .unwrap();
+        let _ = connection.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn sent_messages_are_acked_failed_or_timed_out() {
+        use protobufs::routing::Error;
+
+        let app = tauri::test::mock_app();
+        app.manage(RadioConnectionsState::new());
+
+        let devices: MeshDevicesStateInner<_> = Default::default();
+        let (device, task, mut radio) = connect(app.handle(), &devices, "COM10", None).await;
+
+        configure(&device, &mut radio).await;
+
+        let mut sent = vec![];
+        for text in ["acked", "naked", "timed out"] {
+            let message_id = device
+                .send_text(OutgoingText {
+                    text: text.into(),
+                    destination: PacketDestination::Node(NodeId::new(0x500)),
+                    channel: 0,
+                    want_ack: true,
+                    journal_id: None,
+                })
+                .await
+                .unwrap()
+                .expect("sent message wasn't stored");
+
+            sent.push(message_id);
+        }
+
+        let state_of = |message_id: u32| {
+            let device = device.clone();
+
+            async move {
+                device
+                    .snapshot()
+                    .await
+                    .unwrap()
+                    .message_state(0, message_id)
+                    .cloned()
+            }
+        };
+
+        for message_id in &sent {
+            assert_eq!(
+                state_of(*message_id).await,
+                Some(ChannelMessageState::Pending)
+            );
+        }
+
+        // Results for messages the device doesn't have are ignored
+        let mut results = routing_result(1, 0xdead, Error::None);
+        results.extend(routing_result(2, sent[0], Error::None));
+        results.extend(routing_result(3, sent[1], Error::GotNak));
+        radio.write_all(&results).await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while state_of(sent[1]).await == Some(ChannelMessageState::Pending) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("routing results never handled");
+
+        assert_eq!(
+            state_of(sent[0]).await,
+            Some(ChannelMessageState::Acknowledged)
+        );
+        assert_eq!(
+            state_of(sent[1]).await,
+            Some(ChannelMessageState::Error("Received NAK".into()))
+        );
+
+        // Only a message still waiting for its ack times out
+        assert_eq!(
+            device.time_out_message(0, sent[0], "too late".into()).await,
+            Ok(false)
+        );
+        assert_eq!(
+            device.time_out_message(0, sent[2], "too late".into()).await,
+            Ok(true)
+        );
+        assert_eq!(
+            state_of(sent[2]).await,
+            Some(ChannelMessageState::Error("too late".into()))
+        );
+
+        device.disconnect().await;
+        assert_eq!(task.await.unwrap(), DeviceExit::Disconnected);
+
+        let connection = app
+            .state::<RadioConnectionsState>()
+            .inner
+            .lock()
+            .await
+            .remove("COM10")
+            .unwrap();
+        let _ = connection.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn remote_config_responses_reach_the_request_they_answer() {
+        use protobufs::{admin_message, config, mesh_packet};
+
+        let app = tauri::test::mock_app();
+        app.manage(RadioConnectionsState::new());
+
+        let devices: MeshDevicesStateInner<_> = Default::default();
+        let (device, task, mut radio) = connect(app.handle(), &devices, "COM11", None).await;
+
+        configure(&device, &mut radio).await;
+
+        let send_read = |node_num: u32| {
+            let device = device.clone();
+
+            async move {
+                device
+                    .send_remote_admin(RemoteAdminRequest {
+                        message: protobufs::AdminMessage {
+                            payload_variant: Some(admin_message::PayloadVariant::GetConfigRequest(
+                                RemoteConfigType::Device.config_type() as i32,
+                            )),
+                            ..Default::default()
+                        },
+                        node_num,
+                        want_response: true,
+                    })
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let answered = send_read(0x500).await;
+        let unanswered = send_read(0x600).await;
+        let (answered_id, unanswered_id) = (answered.0, unanswered.0);
+        assert_ne!(answered_id, unanswered_id);
+
+        let answered = tokio::spawn({
+            let device = device.clone();
+            async move { await_remote_admin(&device, answered, Duration::from_secs(10)).await }
+        });
+        let unanswered = tokio::spawn({
+            let device = device.clone();
+            async move { await_remote_admin(&device, unanswered, Duration::from_millis(200)).await }
+        });
+
+        let response = |from: u32, request_id: u32, role: config::device_config::Role| {
+            let admin = protobufs::AdminMessage {
+                payload_variant: Some(admin_message::PayloadVariant::GetConfigResponse(
+                    protobufs::Config {
+                        payload_variant: Some(config::PayloadVariant::Device(
+                            config::DeviceConfig {
+                                role: role as i32,
+                                ..Default::default()
+                            },
+                        )),
+                    },
+                )),
+                ..Default::default()
+            };
+
+            frame(protobufs::from_radio::PayloadVariant::Packet(
+                protobufs::MeshPacket {
+                    from,
+                    to: 0x400,
+                    id: from + request_id,
+                    payload_variant: Some(mesh_packet::PayloadVariant::Decoded(protobufs::Data {
+                        portnum: protobufs::PortNum::AdminApp as i32,
+                        payload: admin.encode_to_vec(),
+                        request_id,
+                        ..Default::default()
+                    })),
+                    ..Default::default()
+                },
+            ))
+        };
+
+        // The node the other request was sent to can't answer this one
+        let mut responses = response(0x600, answered_id, config::device_config::Role::Client);
+        responses.extend(response(
+            0x500,
+            answered_id,
+            config::device_config::Role::Router,
+        ));
+        radio.write_all(&responses).await.unwrap();
+
+        match answered.await.unwrap() {
+            RemoteAdminOutcome::Config {
+                config:
+                    protobufs::Config {
+                        payload_variant: Some(config::PayloadVariant::Device(device_config)),
+                    },
+            } => assert_eq!(
+                device_config.role,
+                config::device_config::Role::Router as i32
+            ),
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+
+        assert_eq!(unanswered.await.unwrap(), RemoteAdminOutcome::TimedOut);
+
+        // Neither request is waiting any longer
+        let packet_api = device.packet_api();
+        let packet_api = packet_api.lock().await;
+        assert!(packet_api.remote_admin.get(answered_id).is_none());
+        assert!(packet_api.remote_admin.get(unanswered_id).is_none());
+        drop(packet_api);
+
+        device.disconnect().await;
+        assert_eq!(task.await.unwrap(), DeviceExit::Disconnected);
+
+        let connection = app
+            .state::<RadioConnectionsState>()
+            .inner
+            .lock()
+            .await
+            .remove("COM11")
+            .unwrap();
+        let _ = connection.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn gpio_read_replies_reach_the_read_they_answer() {
+        use protobufs::mesh_packet;
+
+        let app = tauri::test::mock_app();
+        app.manage(RadioConnectionsState::new());
+
+        let devices: MeshDevicesStateInner<_> = Default::default();
+        let (device, task, mut radio) = connect(app.handle(), &devices, "COM12", None).await;
+
+        configure(&device, &mut radio).await;
+
+        let first = device.read_gpios(0x500, 0b01).await.unwrap();
+        let second = device.read_gpios(0x500, 0b10).await.unwrap();
+        let (first_id, second_id) = (first.0, second.0);
+        assert_ne!(first_id, second_id);
+
+        let first = tokio::spawn({
+            let device = device.clone();
+            async move { await_gpio_read(&device, first, Duration::from_secs(10)).await }
+        });
+        let second = tokio::spawn({
+            let device = device.clone();
+            async move { await_gpio_read(&device, second, Duration::from_secs(10)).await }
+        });
+
+        let hardware = |message_type: hardware_message::Type,
+                        request_id: u32,
+                        gpio_mask: u64,
+                        gpio_value: u64| {
+            frame(protobufs::from_radio::PayloadVariant::Packet(
+                protobufs::MeshPacket {
+                    from: 0x500,
+                    to: 0x400,
+                    id: 0x500 + request_id + gpio_value as u32,
+                    payload_variant: Some(mesh_packet::PayloadVariant::Decoded(protobufs::Data {
+                        portnum: protobufs::PortNum::RemoteHardwareApp as i32,
+                        payload: build_hardware_message(message_type, gpio_mask, gpio_value)
+                            .encode_to_vec(),
+                        request_id,
+                        ..Default::default()
+                    })),
+                    ..Default::default()
+                },
+            ))
+        };
+
+        // A watch notification arrives between the replies, which come in reverse order
+        let mut packets = hardware(
+            hardware_message::Type::ReadGpiosReply,
+            second_id,
+            0b10,
+            0b10,
+        );
+        packets.extend(hardware(
+            hardware_message::Type::GpiosChanged,
+            0,
+            0b11,
+            0b11,
+        ));
+        packets.extend(hardware(
+            hardware_message::Type::ReadGpiosReply,
+            first_id,
+            0b01,
+            0,
+        ));
+        radio.write_all(&packets).await.unwrap();
+
+        let first = first.await.unwrap().expect("first read never answered");
+        let second = second.await.unwrap().expect("second read never answered");
+        assert_eq!((first.gpio_mask, first.gpio_value), (0b01, 0));
+        assert_eq!((second.gpio_mask, second.gpio_value), (0b10, 0b10));
+
+        // Neither read is waiting any longer
+        let packet_api = device.packet_api();
+        let mut packet_api = packet_api.lock().await;
+        assert!(!packet_api.gpio_reads.finish(first_id));
+        assert!(!packet_api.gpio_reads.finish(second_id));
+        drop(packet_api);
+
+        device.disconnect().await;
+        assert_eq!(task.await.unwrap(), DeviceExit::Disconnected);
+
+        let connection = app
+            .state::<RadioConnectionsState>()
+            .inner
+            .lock()
+            .await
+            .remove("COM12")
+            .unwrap();
+        let _ = connection.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn script_sent_text_is_journalled_and_sent() {
+        let app = tauri::test::mock_app();
+        app.manage(RadioConnectionsState::new());
+        app.manage(state::settings::SettingsState::new(AppSettings::default()));
+        app.manage(state::message_store::MessageStoreState::new(Some(
+            MessageStore::open_in_memory().unwrap(),
+        )));
+
+        let devices: MeshDevicesStateInner<_> = Default::default();
+        let (device, task, mut radio) = connect(app.handle(), &devices, "COM13", None).await;
+
+        configure(&device, &mut radio).await;
+
+        // Messages are only journalled for devices whose messages are stored
+        device.packet_api().lock().await.connection_type = ConnectionType::Serial;
+
+        perform_action(
+            &app.handle(),
+            &devices,
+            &"COM13".into(),
+            ScriptAction::SendText {
+                channel: 0,
+                text: "SOS received".into(),
+            },
+        )
+        .await
+        .unwrap();
+
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            read_to_radio(&mut radio, |packet| {
+                text_payload(packet) == Some(b"SOS received")
+            }),
+        )
+        .await
+        .expect("text never reached the radio");
+
+        let snapshot = device.snapshot().await.unwrap();
+        let message_id = snapshot
+            .last_sent_text_id(0)
+            .expect("sent text wasn't stored");
+        assert_eq!(
+            snapshot.message_state(0, message_id),
+            Some(&ChannelMessageState::Pending)
+        );
+
+        // Journalled with the packet it was sent as, and waiting for its ack rather
+        // than offered for resending
+        let store_state = app.state::<state::message_store::MessageStoreState>();
+        let store = store_state.inner.lock().unwrap();
+        let store = store.as_ref().unwrap();
+
+        let journalled = store.incomplete_outgoing(0x400).unwrap();
+        assert_eq!(journalled.len(), 1);
+        assert_eq!(journalled[0].text, "SOS received");
+        assert_eq!(journalled[0].packet_id, Some(message_id));
+        assert!(store_state
+            .outgoing
+            .lock()
+            .unwrap()
+            .unsent(store, 0x400)
+            .unwrap()
+            .is_empty());
+
+        device.disconnect().await;
+        assert_eq!(task.await.unwrap(), DeviceExit::Disconnected);
+
+        let connection = app
+            .state::<RadioConnectionsState>()
+            .inner
+            .lock()
+            .await
+            .remove("COM13")
+            .unwrap();
+        let _ = connection.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn canned_messages_are_cached_from_configuration_and_sets() {
+        use protobufs::{admin_message, from_radio::PayloadVariant, mesh_packet, module_config};
+
+        let app = tauri::test::mock_app();
+        app.manage(RadioConnectionsState::new());
+
+        let devices: MeshDevicesStateInner<_> = Default::default();
+        let (device, task, mut radio) = connect(app.handle(), &devices, "COM14", None).await;
+
+        // The radio reports the module as enabled while configuring
+        let config_id = device.snapshot().await.unwrap().config_id;
+        let mut config = frame(PayloadVariant::MyInfo(protobufs::MyNodeInfo {
+            my_node_num: 0x400,
+            ..Default::default()
+        }));
+        config.extend(frame(PayloadVariant::ModuleConfig(
+            protobufs::ModuleConfig {
+                payload_variant: Some(module_config::PayloadVariant::CannedMessage(
+                    module_config::CannedMessageConfig {
+                        enabled: true,
+                        ..Default::default()
+                    },
+                )),
+            },
+        )));
+        config.extend(frame(PayloadVariant::ConfigCompleteId(config_id)));
+        radio.write_all(&config).await.unwrap();
+
+        let admin = |packet: &protobufs::ToRadio| match &packet.payload_variant {
+            Some(protobufs::to_radio::PayloadVariant::Packet(protobufs::MeshPacket {
+                payload_variant: Some(mesh_packet::PayloadVariant::Decoded(data)),
+                ..
+            })) if data.portnum == protobufs::PortNum::AdminApp as i32 => {
+                protobufs::AdminMessage::decode(data.payload.as_slice())
+                    .ok()
+                    .and_then(|message| message.payload_variant)
+            }
+            _ => None,
+        };
+
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            read_to_radio(&mut radio, |packet| {
+                matches!(
+                    admin(packet),
+                    Some(
+                        admin_message::PayloadVariant::GetCannedMessageModuleMessagesRequest(true)
+                    )
+                )
+            }),
+        )
+        .await
+        .expect("canned messages were never requested");
+
+        let response = protobufs::AdminMessage {
+            payload_variant: Some(
+                admin_message::PayloadVariant::GetCannedMessageModuleMessagesResponse(
+                    "Yes|No".into(),
+                ),
+            ),
+            ..Default::default()
+        };
+        radio
+            .write_all(&frame(PayloadVariant::Packet(protobufs::MeshPacket {
+                from: 0x400,
+                to: 0x400,
+                payload_variant: Some(mesh_packet::PayloadVariant::Decoded(protobufs::Data {
+                    portnum: protobufs::PortNum::AdminApp as i32,
+                    payload: response.encode_to_vec(),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            })))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            read_canned_messages(&devices, &"COM14".into()).await,
+            Ok(vec!["Yes".to_string(), "No".to_string()])
+        );
+
+        // A set replaces the cached messages, which the radio doesn't report back
+        device
+            .write(RadioWrite::CannedMessages(vec!["On my way".into()]))
+            .await
+            .unwrap();
+
+        let set = tokio::time::timeout(
+            Duration::from_secs(10),
+            read_to_radio(&mut radio, |packet| {
+                matches!(
+                    admin(packet),
+                    Some(admin_message::PayloadVariant::SetCannedMessageModuleMessages(_))
+                )
+            }),
+        )
+        .await
+        .expect("canned messages were never set");
+        assert_eq!(
+            admin(&set),
+            Some(admin_message::PayloadVariant::SetCannedMessageModuleMessages("On my way".into()))
+        );
+
+        assert_eq!(
+            read_canned_messages(&devices, &"COM14".into()).await,
+            Ok(vec!["On my way".to_string()])
+        );
+
+        device.disconnect().await;
+        assert_eq!(task.await.unwrap(), DeviceExit::Disconnected);
+
+        let connection = app
+            .state::<RadioConnectionsState>()
+            .inner
+            .lock()
+            .await
+            .remove("COM14")
+            .unwrap();
+        let _ = connection.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn writes_are_held_while_the_radio_queue_is_full() {
+        use protobufs::{admin_message, from_radio::PayloadVariant, mesh_packet};
+
+        let app = tauri::test::mock_app();
+        app.manage(RadioConnectionsState::new());
+
+        let devices: MeshDevicesStateInner<_> = Default::default();
+        let (device, task, mut radio) = connect(app.handle(), &devices, "COM15", None).await;
+
+        configure(&device, &mut radio).await;
+
+        let queue_status = |free: u32| {
+            frame(PayloadVariant::QueueStatus(protobufs::QueueStatus {
+                free,
+                maxlen: 16,
+                ..Default::default()
+            }))
+        };
+        let wait_for_free = |free: u32| {
+            let device = device.clone();
+            async move {
+                tokio::time::timeout(Duration::from_secs(10), async {
+                    while device
+                        .snapshot()
+                        .await
+                        .unwrap()
+                        .queue_status
+                        .map(|s| s.free)
+                        != Some(free)
+                    {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                })
+                .await
+                .expect("queue status never handled");
+            }
+        };
+        let is_canned_set = |packet: &protobufs::ToRadio| match &packet.payload_variant {
+            Some(protobufs::to_radio::PayloadVariant::Packet(protobufs::MeshPacket {
+                payload_variant: Some(mesh_packet::PayloadVariant::Decoded(data)),
+                ..
+            })) if data.portnum == protobufs::PortNum::AdminApp as i32 => matches!(
+                protobufs::AdminMessage::decode(data.payload.as_slice())
+                    .ok()
+                    .and_then(|message| message.payload_variant),
+                Some(admin_message::PayloadVariant::SetCannedMessageModuleMessages(_))
+            ),
+            _ => false,
+        };
+
+        radio.write_all(&queue_status(0)).await.unwrap();
+        wait_for_free(0).await;
+
+        let write = tokio::spawn({
+            let device = device.clone();
+            async move {
+                device
+                    .write(RadioWrite::CannedMessages(vec!["On my way".into()]))
+                    .await
+            }
+        });
+
+        // Nothing reaches the radio while its queue is full
+        assert!(tokio::time::timeout(
+            Duration::from_millis(500),
+            read_to_radio(&mut radio, is_canned_set)
+        )
+        .await
+        .is_err());
+
+        // Once the radio has room the held write is sent and answered
+        radio.write_all(&queue_status(4)).await.unwrap();
+        wait_for_free(4).await;
+
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            read_to_radio(&mut radio, is_canned_set),
+        )
+        .await
+        .expect("held write never reached the radio");
+        assert_eq!(write.await.unwrap(), Ok(()));
+
+        device.disconnect().await;
+        assert_eq!(task.await.unwrap(), DeviceExit::Disconnected);
+
+        let connection = app
+            .state::<RadioConnectionsState>()
+            .inner
+            .lock()
+            .await
+            .remove("COM15")
+            .unwrap();
+        let _ = connection.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn radio_that_goes_silent_becomes_unresponsive() {
+        let app = tauri::test::mock_app();
+        app.manage(RadioConnectionsState::new());
+
+        let devices: MeshDevicesStateInner<_> = Default::default();
+        let heartbeat = Heartbeat {
+            interval: Duration::from_millis(100),
+            missed_heartbeats: 3,
+            reconnect: false,
+        };
+        let (device, task, mut radio) =
+            connect(app.handle(), &devices, "COM8", Some(heartbeat)).await;
+
+        configure(&device, &mut radio).await;
+
+        // The radio hears the heartbeats but never answers them
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            read_to_radio(&mut radio, is_heartbeat),
+        )
+        .await
+        .expect("no heartbeat reached the radio");
+
+        wait_for_status(&device, SerialDeviceStatus::Unresponsive).await;
+
+        // Any packet from the radio shows it's back
+        let packet = frame(protobufs::from_radio::PayloadVariant::NodeInfo(
+            protobufs::NodeInfo {
+                num: 0x401,
+                ..Default::default()
+            },
+        ));
+        radio.write_all(&packet).await.unwrap();
+
+        wait_for_status(&device, SerialDeviceStatus::Connected).await;
+
+        device.disconnect().await;
+        assert_eq!(task.await.unwrap(), DeviceExit::Disconnected);
+
+        let connection = app
+            .state::<RadioConnectionsState>()
+            .inner
+            .lock()
+            .await
+            .remove("COM8")
+            .unwrap();
+        let _ = connection.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn unresponsive_radio_is_left_to_be_reconnected() {
+        let app = tauri::test::mock_app();
+        app.manage(RadioConnectionsState::new());
+
+        let devices: MeshDevicesStateInner<_> = Default::default();
+        let heartbeat = Heartbeat {
+            interval: Duration::from_millis(100),
+            missed_heartbeats: 2,
+            reconnect: true,
+        };
+        let (device, task, mut radio) =
+            connect(app.handle(), &devices, "COM9", Some(heartbeat)).await;
+
+        configure(&device, &mut radio).await;
+
+        // Stops, so `spawn_decoded_handler` reconnects as if the port had vanished
+        let exit = tokio::time::timeout(Duration::from_secs(10), task)
+            .await
+            .expect("unresponsive device never stopped")
+            .unwrap();
+        assert_eq!(exit, DeviceExit::Unresponsive);
+        assert_eq!(device.summary().status, SerialDeviceStatus::Disconnected);
+
+        let connection = app
+            .state::<RadioConnectionsState>()
+            .inner
+            .lock()
+            .await
+            .remove("COM9")
+            .unwrap();
+        let _ = connection.disconnect().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn simulated_packets_are_handled_by_the_device_task() {
+        let app = tauri::test::mock_app();
+        let devices: MeshDevicesStateInner<_> = Default::default();
+
+        let mut packet_api = MeshPacketApi::new(
+            app.handle(),
+            "simulation".into(),
+            MeshDevice::new(),
+            Arc::new(SharedGraph::new(MeshGraph::new())),
+        );
+        packet_api.connection_type = ConnectionType::Simulated;
+        packet_api.device.set_status(SerialDeviceStatus::Connected);
+
+        devices
+            .lock()
+            .await
+            .insert("simulation".into(), spawn_device(packet_api));
+
+        let device = get_device_handle(&devices, "simulation").await.unwrap();
+
+        for num in 1..=NODE_COUNT {
+            device
+                .handle_packet(protobufs::FromRadio {
+                    payload_variant: Some(protobufs::from_radio::PayloadVariant::NodeInfo(
+                        protobufs::NodeInfo {
+                            num,
+                            ..Default::default()
+                        },
+                    )),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        // Handled in order, so the snapshot comes after every packet
+        assert_eq!(
+            device.snapshot().await.unwrap().nodes.len(),
+            NODE_COUNT as usize
+        );
+
+        let summaries = device_summaries(&devices).await;
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].status, SerialDeviceStatus::Connected);
+
+        device.disconnect().await;
+        device.disconnect().await;
+
+        assert_eq!(device.summary().status, SerialDeviceStatus::Disconnected);
+    }
+}
@@ -0,0 +1,351 @@
+use meshtastic::protobufs::{self, from_radio, mesh_packet};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::connection::metrics::mesh_packet_label;
+
+/// Maximum number of debug packets emitted per second before packets are dropped
+pub const DEFAULT_PACKET_DEBUG_RATE_LIMIT: u32 = 20;
+
+/// Number of payload bytes shown in a debug packet's preview
+pub const PACKET_DEBUG_PREVIEW_BYTES: usize = 32;
+
+/// Number of characters shown in the preview of packets that aren't mesh packets
+const PACKET_DEBUG_PREVIEW_CHARS: usize = 120;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum PacketDebugDirection {
+    FromMesh,      // received over the air from another node
+    FromLocalNode, // generated by the connected radio itself
+}
+
+/// Summary of a decoded `FromRadio` packet for the protocol console
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct DebugPacket {
+    pub timestamp: u32,
+    pub direction: PacketDebugDirection,
+    pub variant: String, // `FromRadio` payload variant, e.g. "packet" or "nodeInfo"
+    pub portnum: Option<String>, // portnum name, "ENCRYPTED" if it couldn't be decoded
+    pub packet_id: Option<u32>,
+    pub from: Option<u32>,
+    pub to: Option<u32>,
+    pub channel: Option<u32>,
+    pub hop_limit: Option<u32>,
+    pub rx_time: Option<u32>,
+    pub rx_snr: Option<f32>,
+    pub rx_rssi: Option<i32>,
+    pub payload_len: u32,
+    pub payload_preview: String, // hex, or text for text messages, truncated
+}
+
+fn variant_name(variant: &from_radio::PayloadVariant) -> &'static str {
+    match variant {
+        from_radio::PayloadVariant::Packet(_) => "packet",
+        from_radio::PayloadVariant::MyInfo(_) => "myInfo",
+        from_radio::PayloadVariant::NodeInfo(_) => "nodeInfo",
+        from_radio::PayloadVariant::Config(_) => "config",
+        from_radio::PayloadVariant::LogRecord(_) => "logRecord",
+        from_radio::PayloadVariant::ConfigCompleteId(_) => "configCompleteId",
+        from_radio::PayloadVariant::Rebooted(_) => "rebooted",
+        from_radio::PayloadVariant::ModuleConfig(_) => "moduleConfig",
+        from_radio::PayloadVariant::Channel(_) => "channel",
+        from_radio::PayloadVariant::QueueStatus(_) => "queueStatus",
+        from_radio::PayloadVariant::XmodemPacket(_) => "xmodemPacket",
+        from_radio::PayloadVariant::Metadata(_) => "metadata",
+        from_radio::PayloadVariant::MqttClientProxyMessage(_) => "mqttClientProxyMessage",
+    }
+}
+
+fn hex_preview(bytes: &[u8]) -> String {
+    let mut preview: String = bytes
+        .iter()
+        .take(PACKET_DEBUG_PREVIEW_BYTES)
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if bytes.len() > PACKET_DEBUG_PREVIEW_BYTES {
+        preview.push_str(" …");
+    }
+
+    preview
+}
+
+fn text_preview(text: &str, max_chars: usize) -> String {
+    let mut preview: String = text.chars().take(max_chars).collect();
+
+    if text.chars().count() > max_chars {
+        preview.push('…');
+    }
+
+    preview
+}
+
+impl DebugPacket {
+    pub fn new(packet: &protobufs::FromRadio, my_node_num: u32, timestamp: u32) -> Self {
+        let mut debug_packet = Self {
+            timestamp,
+            direction: PacketDebugDirection::FromLocalNode,
+            variant: "none".into(),
+            portnum: None,
+            packet_id: None,
+            from: None,
+            to: None,
+            channel: None,
+            hop_limit: None,
+            rx_time: None,
+            rx_snr: None,
+            rx_rssi: None,
+            payload_len: 0,
+            payload_preview: String::new(),
+        };
+
+        let variant = match packet.payload_variant.as_ref() {
+            Some(variant) => variant,
+            None => return debug_packet,
+        };
+
+        debug_packet.variant = variant_name(variant).into();
+
+        let mesh_packet = match variant {
+            from_radio::PayloadVariant::Packet(mesh_packet) => mesh_packet,
+            _ => {
+                let description = format!("{:?}", variant);
+                debug_packet.payload_len = description.len() as u32;
+                debug_packet.payload_preview =
+                    text_preview(&description, PACKET_DEBUG_PREVIEW_CHARS);
+                return debug_packet;
+            }
+        };
+
+        if mesh_packet.from != my_node_num {
+            debug_packet.direction = PacketDebugDirection::FromMesh;
+        }
+
+        debug_packet.portnum = Some(mesh_packet_label(mesh_packet).into_owned());
+        debug_packet.packet_id = Some(mesh_packet.id);
+        debug_packet.from = Some(mesh_packet.from);
+        debug_packet.to = Some(mesh_packet.to);
+        debug_packet.channel = Some(mesh_packet.channel);
+        debug_packet.hop_limit = Some(mesh_packet.hop_limit);
+        debug_packet.rx_time = Some(mesh_packet.rx_time).filter(|t| *t != 0);
+        debug_packet.rx_snr = Some(mesh_packet.rx_snr).filter(|snr| *snr != 0.0);
+        debug_packet.rx_rssi = Some(mesh_packet.rx_rssi).filter(|rssi| *rssi != 0);
+
+        let payload: &[u8] = match mesh_packet.payload_variant.as_ref() {
+            Some(mesh_packet::PayloadVariant::Decoded(data)) => &data.payload,
+            Some(mesh_packet::PayloadVariant::Encrypted(bytes)) => bytes,
+            None => &[],
+        };
+
+        debug_packet.payload_len = payload.len() as u32;
+        debug_packet.payload_preview = match mesh_packet.payload_variant.as_ref() {
+            Some(mesh_packet::PayloadVariant::Decoded(data))
+                if data.portnum() == protobufs::PortNum::TextMessageApp =>
+            {
+                text_preview(
+                    &String::from_utf8_lossy(payload),
+                    PACKET_DEBUG_PREVIEW_BYTES,
+                )
+            }
+            _ => hex_preview(payload),
+        };
+
+        debug_packet
+    }
+}
+
+/// Limits which packets are streamed. Empty lists allow everything.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PacketDebugFilters {
+    pub portnums: Vec<String>, // portnum names such as "TEXT_MESSAGE_APP"
+    pub node_nums: Vec<u32>,   // matched against the sender and the destination
+}
+
+impl PacketDebugFilters {
+    /// Packets without a portnum or node, such as config packets, only pass
+    /// filters that don't restrict them
+    pub fn matches(&self, packet: &DebugPacket) -> bool {
+        let portnum_matches = self.portnums.is_empty()
+            || matches!(&packet.portnum, Some(portnum) if self.portnums.contains(portnum));
+
+        let node_matches = self.node_nums.is_empty()
+            || [packet.from, packet.to]
+                .iter()
+                .flatten()
+                .any(|node_num| self.node_nums.contains(node_num));
+
+        portnum_matches && node_matches
+    }
+}
+
+/// Per-device state of an enabled debug stream. Packets over the rate limit are
+/// dropped and counted, and the count is reported with the next streamed packet.
+#[derive(Clone, Debug)]
+pub struct PacketDebugStream {
+    filters: PacketDebugFilters,
+    max_packets_per_sec: u32,
+    window_start: u32,
+    packets_in_window: u32,
+    dropped_packets: u32,
+}
+
+impl PacketDebugStream {
+    pub fn new(filters: PacketDebugFilters, max_packets_per_sec: u32) -> Self {
+        Self {
+            filters,
+            max_packets_per_sec,
+            window_start: 0,
+            packets_in_window: 0,
+            dropped_packets: 0,
+        }
+    }
+
+    /// Returns the number of packets dropped since the last streamed packet if this
+    /// packet should be streamed, or `None` if it's filtered out or over the rate limit
+    pub fn admit(&mut self, packet: &DebugPacket) -> Option<u32> {
+        if !self.filters.matches(packet) {
+            return None;
+        }
+
+        if packet.timestamp != self.window_start {
+            self.window_start = packet.timestamp;
+            self.packets_in_window = 0;
+        }
+
+        if self.packets_in_window >= self.max_packets_per_sec {
+            self.dropped_packets = self.dropped_packets.saturating_add(1);
+            return None;
+        }
+
+        self.packets_in_window += 1;
+
+        Some(std::mem::take(&mut self.dropped_packets))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MY_NODE_NUM: u32 = 1;
+
+    fn text_packet(from: u32, to: u32, text: &str) -> protobufs::FromRadio {
+        protobufs::FromRadio {
+            payload_variant: Some(from_radio::PayloadVariant::Packet(protobufs::MeshPacket {
+                from,
+                to,
+                id: 42,
+                rx_snr: 6.5,
+                payload_variant: Some(mesh_packet::PayloadVariant::Decoded(protobufs::Data {
+                    portnum: protobufs::PortNum::TextMessageApp as i32,
+                    payload: text.as_bytes().to_vec(),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    fn config_complete_packet() -> protobufs::FromRadio {
+        protobufs::FromRadio {
+            payload_variant: Some(from_radio::PayloadVariant::ConfigCompleteId(7)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn summarizes_mesh_packets() {
+        let packet = DebugPacket::new(&text_packet(2, MY_NODE_NUM, "hello"), MY_NODE_NUM, 100);
+
+        assert_eq!(packet.direction, PacketDebugDirection::FromMesh);
+        assert_eq!(packet.variant, "packet");
+        assert_eq!(packet.portnum.as_deref(), Some("TEXT_MESSAGE_APP"));
+        assert_eq!((packet.from, packet.to), (Some(2), Some(MY_NODE_NUM)));
+        assert_eq!(packet.rx_snr, Some(6.5));
+        assert_eq!(packet.rx_rssi, None);
+        assert_eq!(packet.payload_len, 5);
+        assert_eq!(packet.payload_preview, "hello");
+
+        let config = DebugPacket::new(&config_complete_packet(), MY_NODE_NUM, 100);
+
+        assert_eq!(config.direction, PacketDebugDirection::FromLocalNode);
+        assert_eq!(config.variant, "configCompleteId");
+        assert_eq!(config.portnum, None);
+    }
+
+    #[test]
+    fn truncates_binary_previews() {
+        assert_eq!(hex_preview(&[0x01, 0xab]), "01 ab");
+
+        let preview = hex_preview(&[0xff; PACKET_DEBUG_PREVIEW_BYTES + 1]);
+        assert!(preview.ends_with(" …"));
+        assert_eq!(preview.matches("ff").count(), PACKET_DEBUG_PREVIEW_BYTES);
+    }
+
+    #[test]
+    fn filters_by_portnum_and_node() {
+        let text_from_2 = DebugPacket::new(&text_packet(2, MY_NODE_NUM, "a"), MY_NODE_NUM, 0);
+        let text_from_3 = DebugPacket::new(&text_packet(3, 4, "b"), MY_NODE_NUM, 0);
+        let config = DebugPacket::new(&config_complete_packet(), MY_NODE_NUM, 0);
+
+        let all = PacketDebugFilters::default();
+        assert!(all.matches(&text_from_2) && all.matches(&config));
+
+        let text_only = PacketDebugFilters {
+            portnums: vec!["TEXT_MESSAGE_APP".into()],
+            ..Default::default()
+        };
+        assert!(text_only.matches(&text_from_3));
+        assert!(!text_only.matches(&config));
+
+        let positions_only = PacketDebugFilters {
+            portnums: vec!["POSITION_APP".into()],
+            ..Default::default()
+        };
+        assert!(!positions_only.matches(&text_from_2));
+
+        // Destination matches as well as sender
+        let node_4 = PacketDebugFilters {
+            node_nums: vec![4],
+            ..Default::default()
+        };
+        assert!(node_4.matches(&text_from_3));
+        assert!(!node_4.matches(&text_from_2));
+        assert!(!node_4.matches(&config));
+    }
+
+    #[test]
+    fn counts_packets_dropped_during_flood() {
+        let mut stream = PacketDebugStream::new(PacketDebugFilters::default(), 5);
+        let packet = DebugPacket::new(&text_packet(2, MY_NODE_NUM, "flood"), MY_NODE_NUM, 100);
+
+        let admitted: Vec<Option<u32>> = (0..50).map(|_| stream.admit(&packet)).collect();
+
+        assert_eq!(admitted.iter().flatten().count(), 5);
+        assert!(admitted.iter().flatten().all(|dropped| *dropped == 0));
+
+        // The next second reports everything dropped in the flood
+        let next_second = DebugPacket {
+            timestamp: 101,
+            ..packet.clone()
+        };
+        assert_eq!(stream.admit(&next_second), Some(45));
+        assert_eq!(stream.admit(&next_second), Some(0));
+
+        // Filtered packets aren't counted as dropped
+        let mut filtered = PacketDebugStream::new(
+            PacketDebugFilters {
+                node_nums: vec![9],
+                ..Default::default()
+            },
+            5,
+        );
+        assert_eq!(filtered.admit(&packet), None);
+        assert_eq!(filtered.dropped_packets, 0);
+    }
+}
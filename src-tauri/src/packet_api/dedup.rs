@@ -0,0 +1,122 @@
+use std::collections::{HashMap, VecDeque};
+
+/// How long a packet is remembered for deduplication, in seconds
+pub const DEFAULT_DEDUP_WINDOW_SECS: u32 = 10 * 60;
+
+/// Maximum number of packets remembered for deduplication
+pub const DEFAULT_DEDUP_CAPACITY: usize = 1024;
+
+/// Time-bounded cache of recently seen packets, keyed by sender and packet id.
+/// Used to detect copies of a packet heard over multiple paths.
+#[derive(Clone, Debug)]
+pub struct PacketDedupCache {
+    seen: HashMap<(u32, u32), u32>,
+    order: VecDeque<(u32, u32)>,
+    window_secs: u32,
+    capacity: usize,
+}
+
+impl PacketDedupCache {
+    pub fn new(window_secs: u32, capacity: usize) -> Self {
+        Self {
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+            window_secs,
+            capacity,
+        }
+    }
+
+    /// Records a packet as seen, returning whether it was already seen within the window.
+    /// Packets without an id can't be deduplicated and are never reported as duplicates.
+    pub fn check_duplicate(&mut self, from: u32, packet_id: u32, now: u32) -> bool {
+        if packet_id == 0 {
+            return false;
+        }
+
+        self.expire(now);
+
+        let key = (from, packet_id);
+
+        if self.seen.contains_key(&key) {
+            return true;
+        }
+
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+
+        self.seen.insert(key, now);
+        self.order.push_back(key);
+
+        false
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    fn expire(&mut self, now: u32) {
+        while let Some(oldest) = self.order.front() {
+            let seen_at = self.seen.get(oldest).copied().unwrap_or_default();
+
+            if now.saturating_sub(seen_at) <= self.window_secs {
+                break;
+            }
+
+            self.seen.remove(oldest);
+            self.order.pop_front();
+        }
+    }
+}
+
+impl Default for PacketDedupCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEDUP_WINDOW_SECS, DEFAULT_DEDUP_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_repeated_packets() {
+        let mut cache = PacketDedupCache::default();
+
+        assert!(!cache.check_duplicate(1, 100, 0));
+        assert!(cache.check_duplicate(1, 100, 5));
+
+        // Same id from a different sender is a different packet
+        assert!(!cache.check_duplicate(2, 100, 5));
+        assert!(!cache.check_duplicate(1, 0, 5));
+        assert!(!cache.check_duplicate(1, 0, 6));
+    }
+
+    #[test]
+    fn expires_entries_after_window() {
+        let mut cache = PacketDedupCache::new(60, 16);
+
+        assert!(!cache.check_duplicate(1, 100, 0));
+        assert!(cache.check_duplicate(1, 100, 60));
+        assert!(!cache.check_duplicate(1, 100, 121));
+    }
+
+    #[test]
+    fn evicts_oldest_when_full() {
+        let mut cache = PacketDedupCache::new(60, 2);
+
+        cache.check_duplicate(1, 1, 0);
+        cache.check_duplicate(1, 2, 0);
+        cache.check_duplicate(1, 3, 0);
+
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.check_duplicate(1, 1, 0));
+        assert!(cache.check_duplicate(1, 3, 0));
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use tokio::sync::oneshot;
+
+use crate::device::remote_hardware::GpioReading;
+
+#[derive(Debug)]
+struct PendingGpioRead {
+    node_num: u32,
+    reply: oneshot::Sender<GpioReading>,
+}
+
+/// GPIO reads sent through a device to other nodes that are waiting for their reply,
+/// keyed by the packet id of the request. Replies are matched by their request id and
+/// sender, so watch notifications and replies to other reads never answer a read.
+#[derive(Debug, Default)]
+pub struct GpioReadRequests {
+    pending: HashMap<u32, PendingGpioRead>,
+    last_request: Option<(u32, oneshot::Receiver<GpioReading>)>,
+}
+
+impl GpioReadRequests {
+    /// Records a read as it's sent, from the copy the radio echoes back
+    pub fn sent(&mut self, request_id: u32, node_num: u32) {
+        let (reply, reading) = oneshot::channel();
+
+        self.pending
+            .insert(request_id, PendingGpioRead { node_num, reply });
+        self.last_request = Some((request_id, reading));
+    }
+
+    /// The id of the read sent last and where its reply arrives, for the command that
+    /// just sent it
+    pub fn take_last_request(&mut self) -> Option<(u32, oneshot::Receiver<GpioReading>)> {
+        self.last_request.take()
+    }
+
+    /// Ends the read `request_id` with `reading`, returning whether it was waiting
+    /// for a reply from the node the reading came from
+    pub fn answer(&mut self, request_id: u32, reading: &GpioReading) -> bool {
+        match self.pending.get(&request_id) {
+            Some(pending) if pending.node_num == reading.node_num => {}
+            _ => return false,
+        }
+
+        if let Some(pending) = self.pending.remove(&request_id) {
+            // The command may have given up waiting already
+            let _ = pending.reply.send(reading.clone());
+        }
+
+        true
+    }
+
+    /// Stops waiting for a read's reply, returning whether it was still waiting
+    pub fn finish(&mut self, request_id: u32) -> bool {
+        self.pending.remove(&request_id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(node_num: u32, gpio_value: u64) -> GpioReading {
+        GpioReading {
+            node_num,
+            gpio_mask: 0b11,
+            gpio_value,
+            timestamp: 100,
+        }
+    }
+
+    #[test]
+    fn replies_are_matched_to_their_read() {
+        let mut reads = GpioReadRequests::default();
+
+        reads.sent(10, 7);
+        let (first_id, mut first) = reads.take_last_request().unwrap();
+        reads.sent(11, 7);
+        let (second_id, mut second) = reads.take_last_request().unwrap();
+        assert_eq!(reads.take_last_request().map(|r| r.0), None);
+
+        // Unknown requests, and replies from another node, answer nothing
+        assert!(!reads.answer(0, &reading(7, 0b01)));
+        assert!(!reads.answer(first_id, &reading(8, 0b01)));
+
+        assert!(reads.answer(second_id, &reading(7, 0b10)));
+        assert!(!reads.answer(second_id, &reading(7, 0b11)));
+        assert_eq!(second.try_recv().unwrap().gpio_value, 0b10);
+        assert!(first.try_recv().is_err());
+
+        assert!(reads.finish(first_id));
+        assert!(!reads.answer(first_id, &reading(7, 0b01)));
+        assert!(!reads.finish(first_id));
+    }
+}
@@ -0,0 +1,230 @@
+use std::time::Duration;
+
+use log::trace;
+use meshtastic::protobufs;
+use tokio::time::Instant;
+
+use crate::graph::{ds::position_archive::PositionSource, store::archive_node_position};
+
+use super::{handlers::DeviceUpdateError, MeshPacketApi};
+
+/// Node infos applied to the graph early once this many are waiting
+pub const GRAPH_BATCH_CAPACITY: usize = 128;
+
+/// How long node infos heard outside configuration wait to be applied to the graph
+pub const GRAPH_BATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Node infos waiting to be applied to the graph. The radio sends its whole node
+/// database while configuring, which is applied in one go once configuration
+/// completes rather than changing the graph for every node nobody sees yet. Node
+/// infos heard afterwards are applied at most every `GRAPH_BATCH_INTERVAL`.
+#[derive(Debug, Default)]
+pub struct GraphUpdateBatch {
+    pending: Vec<protobufs::NodeInfo>,
+    since: Option<Instant>, // when the oldest pending node info was heard
+}
+
+impl GraphUpdateBatch {
+    pub fn push(&mut self, node_info: protobufs::NodeInfo, now: Instant) {
+        self.since.get_or_insert(now);
+        self.pending.push(node_info);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// When the pending node infos should be applied, if they're waiting on a timer
+    /// rather than for configuration to complete
+    pub fn deadline(&self, configuring: bool) -> Option<Instant> {
+        match configuring {
+            true => None,
+            false => self.since.map(|since| since + GRAPH_BATCH_INTERVAL),
+        }
+    }
+
+    /// Whether the pending node infos should be applied now, either because there are
+    /// too many of them or because they've waited long enough
+    pub fn is_due(&self, configuring: bool, now: Instant) -> bool {
+        self.len() >= GRAPH_BATCH_CAPACITY
+            || self
+                .deadline(configuring)
+                .map_or(false, |deadline| deadline <= now)
+    }
+
+    pub fn take(&mut self) -> Vec<protobufs::NodeInfo> {
+        self.since = None;
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Applies the pending node infos to the graph under a single write guard, adding
+/// the changes they made to the ones to publish
+pub fn apply_graph_batch<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+) -> Result<(), DeviceUpdateError> {
+    if packet_api.graph_batch.is_empty() {
+        return Ok(());
+    }
+
+    let node_infos = packet_api.graph_batch.take();
+
+    trace!("Applying {} node infos to the graph", node_infos.len());
+
+    let changes = {
+        let mut graph = packet_api
+            .write_graph()
+            .map_err(DeviceUpdateError::GeneralFailure)?;
+
+        for node_info in node_infos.iter() {
+            archive_node_position(
+                packet_api,
+                &mut graph,
+                node_info.num,
+                PositionSource::NodeInfo,
+            );
+        }
+
+        graph.update_from_node_infos(node_infos)
+    };
+
+    packet_api.graph_changes.extend(changes);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use meshtastic::packet::PacketRouter;
+
+    use super::*;
+    use crate::device::{MeshDevice, SerialDeviceStatus};
+    use crate::graph::ds::graph::MeshGraph;
+    use crate::packet_api::summary::ConnectionType;
+    use crate::state::graph::SharedGraph;
+
+    const NODE_DB_SIZE: u32 = 300;
+
+    fn node_info(num: u32) -> protobufs::NodeInfo {
+        protobufs::NodeInfo {
+            num,
+            position: Some(protobufs::Position {
+                latitude_i: 476_000_000 + num as i32 * 1_000,
+                longitude_i: -1_223_000_000,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn from_radio(variant: protobufs::from_radio::PayloadVariant) -> protobufs::FromRadio {
+        protobufs::FromRadio {
+            payload_variant: Some(variant),
+            ..Default::default()
+        }
+    }
+
+    type Structure = Vec<(u32, Option<(i64, i64)>)>;
+
+    fn structure(graph: &MeshGraph) -> Structure {
+        let mut nodes: Structure = graph
+            .nodes()
+            .map(|node| {
+                let position = graph
+                    .spatial_index
+                    .position(node.node_num)
+                    .map(|(lat, lon)| ((lat * 1e6).round() as i64, (lon * 1e6).round() as i64));
+
+                (node.node_num, position)
+            })
+            .collect();
+
+        nodes.sort_unstable();
+        nodes
+    }
+
+    #[test]
+    fn node_database_sync_is_applied_in_a_few_batches() {
+        let app = tauri::test::mock_app();
+        let graph = Arc::new(SharedGraph::new(MeshGraph::new()));
+
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 1;
+        device.set_status(SerialDeviceStatus::Configuring);
+
+        let mut packet_api = MeshPacketApi::new(app.handle(), "sync".into(), device, graph.clone());
+        packet_api.connection_type = ConnectionType::Simulated;
+
+        // The graph only changes when a batch is applied, so count the packets after
+        // which it looks different
+        let mut mutations = 0;
+        let mut previous = structure(&graph.read().unwrap());
+
+        let mut packets: Vec<_> = (1..=NODE_DB_SIZE)
+            .map(|num| {
+                from_radio(protobufs::from_radio::PayloadVariant::NodeInfo(node_info(
+                    num,
+                )))
+            })
+            .collect();
+        packets.push(from_radio(
+            protobufs::from_radio::PayloadVariant::ConfigCompleteId(1),
+        ));
+
+        for packet in packets {
+            packet_api.handle_packet_from_radio(packet).unwrap();
+
+            let current = structure(&graph.read().unwrap());
+
+            if current != previous {
+                mutations += 1;
+                previous = current;
+            }
+
+            assert!(packet_api.graph_batch.len() < GRAPH_BATCH_CAPACITY);
+        }
+
+        // Two batches that filled up, and the rest once configuration completed
+        assert_eq!(mutations, 3);
+        assert!(packet_api.graph_batch.is_empty());
+
+        let mut rebuilt = MeshGraph::new();
+
+        for num in 1..=NODE_DB_SIZE {
+            rebuilt.update_from_node_info(node_info(num));
+        }
+
+        assert_eq!(structure(&graph.read().unwrap()), structure(&rebuilt));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batches_outside_configuration_are_due_after_the_interval() {
+        let mut batch = GraphUpdateBatch::default();
+        batch.push(node_info(2), Instant::now());
+
+        tokio::time::advance(GRAPH_BATCH_INTERVAL / 2).await;
+        batch.push(node_info(3), Instant::now());
+
+        assert!(!batch.is_due(false, Instant::now()));
+
+        // Measured from the oldest node info in the batch
+        tokio::time::advance(GRAPH_BATCH_INTERVAL / 2).await;
+
+        assert!(batch.is_due(false, Instant::now()));
+        assert!(!batch.is_due(true, Instant::now()));
+        assert_eq!(batch.take().len(), 2);
+        assert_eq!(batch.deadline(false), None);
+
+        for num in 0..GRAPH_BATCH_CAPACITY as u32 {
+            batch.push(node_info(num), Instant::now());
+        }
+
+        assert!(batch.is_due(true, Instant::now()));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::graph::ds::edge_weight::EdgeWeightStrategy;
+use crate::graph::ds::graph::MeshGraph;
+use crate::graph::ds::overrides::ManualEdge;
+
+/// A change the operator makes to a device's graph, applied by the device's task
+#[derive(Clone, Debug, PartialEq)]
+pub enum GraphEdit {
+    SetEdgeWeightStrategy(EdgeWeightStrategy),
+    AddManualEdge(ManualEdge),
+    RemoveManualEdge {
+        from: u32,
+        to: u32,
+    },
+    SetNodeHidden {
+        node_num: u32,
+        hidden: bool,
+    },
+    SetNodeLabel {
+        node_num: u32,
+        label: Option<String>,
+    },
+    PurgeArchivedPosition(u32),
+}
+
+impl GraphEdit {
+    /// Applies the edit, returning whether it changed the operator's overrides,
+    /// which are saved along with publishing the graph
+    pub fn apply(self, graph: &mut MeshGraph) -> Result<bool, String> {
+        match self {
+            GraphEdit::SetEdgeWeightStrategy(strategy) => {
+                graph.set_edge_weight_strategy(strategy);
+                Ok(false)
+            }
+            GraphEdit::AddManualEdge(manual_edge) => {
+                graph.add_manual_edge(manual_edge);
+                Ok(true)
+            }
+            GraphEdit::RemoveManualEdge { from, to } => {
+                graph
+                    .remove_manual_edge(from, to)
+                    .ok_or("Manual edge not found")?;
+                Ok(true)
+            }
+            GraphEdit::SetNodeHidden { node_num, hidden } => {
+                graph.set_node_hidden(node_num, hidden);
+                Ok(true)
+            }
+            GraphEdit::SetNodeLabel { node_num, label } => {
+                graph.set_node_label(node_num, label);
+                Ok(true)
+            }
+            GraphEdit::PurgeArchivedPosition(node_num) => {
+                graph.position_archive.remove(node_num);
+                Ok(false)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_override_edits_are_saved() {
+        let mut graph = MeshGraph::new();
+
+        assert_eq!(
+            GraphEdit::SetNodeLabel {
+                node_num: 3,
+                label: Some("Barn".into())
+            }
+            .apply(&mut graph),
+            Ok(true)
+        );
+        assert_eq!(graph.overrides.node_label(3), Some("Barn"));
+
+        assert_eq!(
+            GraphEdit::SetEdgeWeightStrategy(EdgeWeightStrategy::HopCount).apply(&mut graph),
+            Ok(false)
+        );
+        assert_eq!(graph.edge_weight_strategy(), &EdgeWeightStrategy::HopCount);
+
+        assert!(GraphEdit::RemoveManualEdge { from: 1, to: 2 }
+            .apply(&mut graph)
+            .is_err());
+    }
+}
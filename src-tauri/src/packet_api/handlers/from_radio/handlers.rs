@@ -1,10 +1,28 @@
-use log::debug;
+use log::{debug, warn};
 use meshtastic::protobufs;
+use tokio::time::Instant;
 
 use crate::{
-    device::{helpers::get_current_time_u32, MeshChannel, SerialDeviceStatus},
-    ipc::{events, ConfigurationStatus},
-    packet_api::{handlers::DeviceUpdateError, MeshPacketApi},
+    connection::recent::record_recent_device_node,
+    device::{
+        config_cache::cache_device_config,
+        helpers::{get_current_time_u32, get_node_user_name},
+        logs::DeviceLogEntry,
+        message_store::unsent_messages_on_connect,
+        MeshChannel, SerialDeviceStatus,
+    },
+    graph::{geojson::GraphGeoJson, store::reconcile_persisted_nodes},
+    ipc::{
+        events,
+        helpers::{
+            record_device_log, spawn_device_metadata_request, spawn_device_time_sync,
+            spawn_fixed_position_restore,
+        },
+        ConfigurationStatus, NodeDbReconciledEvent, RadioQueueThrottleStatus, UnsentMessagesEvent,
+        EVENT_API_VERSION,
+    },
+    packet_api::{handlers::DeviceUpdateError, summary::ConnectionType, MeshPacketApi},
+    state,
 };
 
 pub fn handle_channel_packet<R: tauri::Runtime>(
@@ -18,8 +36,12 @@ pub fn handle_channel_packet<R: tauri::Runtime>(
         messages: vec![],
     });
 
-    events::dispatch_updated_device(&packet_api.app_handle, &packet_api.device)
-        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
 
     Ok(())
 }
@@ -31,8 +53,12 @@ pub fn handle_config_packet<R: tauri::Runtime>(
 ) -> Result<(), DeviceUpdateError> {
     packet_api.device.set_config(config);
 
-    events::dispatch_updated_device(&packet_api.app_handle, &packet_api.device)
-        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
 
     Ok(())
 }
@@ -44,8 +70,12 @@ pub fn handle_module_config_packet<R: tauri::Runtime>(
 ) -> Result<(), DeviceUpdateError> {
     packet_api.device.set_module_config(module_config);
 
-    events::dispatch_updated_device(&packet_api.app_handle, &packet_api.device)
-        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
 
     Ok(())
 }
@@ -53,10 +83,33 @@ pub fn handle_module_config_packet<R: tauri::Runtime>(
 pub fn handle_config_complete_packet<R: tauri::Runtime>(
     packet_api: &mut MeshPacketApi<R>,
 ) -> Result<(), DeviceUpdateError> {
+    // The UI was already told configuration failed
+    if packet_api.device.status == SerialDeviceStatus::Disconnected {
+        debug!(
+            "Ignoring configuration of \"{}\" completed after timing out",
+            packet_api.device_key
+        );
+
+        return Ok(());
+    }
+
     packet_api.device.set_status(SerialDeviceStatus::Configured);
 
-    events::dispatch_updated_device(&packet_api.app_handle, &packet_api.device)
-        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+    let reconcile_report = match reconcile_persisted_nodes(packet_api) {
+        Some(Ok(report)) => Some(report),
+        Some(Err(e)) => {
+            warn!("Failed to reconcile node database with stored nodes: {}", e);
+            None
+        }
+        None => None,
+    };
+
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
 
     if packet_api.device.status == SerialDeviceStatus::Configured {
         debug!(
@@ -67,6 +120,7 @@ pub fn handle_config_complete_packet<R: tauri::Runtime>(
         events::dispatch_configuration_status(
             &packet_api.app_handle,
             ConfigurationStatus {
+                api_version: EVENT_API_VERSION,
                 device_key: packet_api.device_key.clone(),
                 successful: true,
                 message: None,
@@ -75,11 +129,131 @@ pub fn handle_config_complete_packet<R: tauri::Runtime>(
         .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
 
         packet_api.device.set_status(SerialDeviceStatus::Connected);
+
+        if let Some(report) = reconcile_report {
+            debug!(
+                "Reconciled node database of device \"{}\": {} updated, {} missing from device, {} new",
+                packet_api.device_key,
+                report.updated.len(),
+                report.device_missing.len(),
+                report.store_missing.len()
+            );
+
+            events::dispatch_node_db_reconciled(
+                &packet_api.app_handle,
+                NodeDbReconciledEvent {
+                    api_version: EVENT_API_VERSION,
+                    device_key: packet_api.device_key.clone(),
+                    report,
+                },
+            )
+            .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+
+            let graph = packet_api
+                .graph_arc
+                .view()
+                .map_err(DeviceUpdateError::GeneralFailure)?;
+
+            events::dispatch_updated_graph(
+                &packet_api.app_handle,
+                Some(packet_api.device_key.clone()),
+                graph.clone(),
+            )
+            .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+
+            events::dispatch_graph_geojson_update(
+                &packet_api.app_handle,
+                GraphGeoJson::new(packet_api.device_key.clone(), &graph, &packet_api.device),
+            )
+            .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+        }
+
+        if matches!(
+            packet_api.connection_type,
+            ConnectionType::Serial | ConnectionType::Tcp
+        ) {
+            let node_num = packet_api.device.my_node_info.my_node_num;
+
+            record_recent_device_node(
+                &packet_api.app_handle,
+                &packet_api.device_key,
+                node_num,
+                get_node_user_name(&packet_api.device, &node_num),
+            );
+        }
+
+        if let Err(e) = cache_device_config(
+            &packet_api.app_handle,
+            &packet_api.device,
+            get_current_time_u32(),
+        ) {
+            warn!("Failed to cache device config: {}", e);
+        }
+
+        spawn_device_time_sync(packet_api.app_handle.clone(), packet_api.device_key.clone());
+        spawn_fixed_position_restore(packet_api.app_handle.clone(), packet_api.device_key.clone());
+
+        match unsent_messages_on_connect(packet_api) {
+            Ok(messages) if !messages.is_empty() => {
+                events::dispatch_unsent_messages(
+                    &packet_api.app_handle,
+                    UnsentMessagesEvent {
+                        api_version: EVENT_API_VERSION,
+                        device_key: packet_api.device_key.clone(),
+                        messages,
+                    },
+                )
+                .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to check for unsent messages: {}", e),
+        }
+
+        // Firmware that doesn't include metadata in the configuration flow
+        // may still answer an explicit admin request for it
+
+        if packet_api.device.metadata.is_none() {
+            spawn_device_metadata_request(
+                packet_api.app_handle.clone(),
+                packet_api.device_key.clone(),
+            );
+        }
     }
 
     Ok(())
 }
 
+pub fn handle_log_record_packet<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+
+    log_record: protobufs::LogRecord,
+) -> Result<(), DeviceUpdateError> {
+    record_device_log(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        DeviceLogEntry::from_log_record(log_record, get_current_time_u32()),
+    );
+
+    Ok(())
+}
+
+pub fn handle_metadata_packet<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+
+    metadata: protobufs::DeviceMetadata,
+) -> Result<(), DeviceUpdateError> {
+    packet_api.device.set_metadata(metadata);
+
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+
+    Ok(())
+}
+
 pub fn handle_my_node_info_packet<R: tauri::Runtime>(
     packet_api: &mut MeshPacketApi<R>,
 
@@ -87,8 +261,22 @@ pub fn handle_my_node_info_packet<R: tauri::Runtime>(
 ) -> Result<(), DeviceUpdateError> {
     packet_api.device.set_my_node_info(my_node_info);
 
-    events::dispatch_updated_device(&packet_api.app_handle, &packet_api.device)
-        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+    // Radios report their node num first, so the stored graph can be shown while
+    // the node database streams in. It's loaded off the packet handler, which keeps
+    // handling the database in the meantime.
+    if matches!(
+        packet_api.connection_type,
+        ConnectionType::Serial | ConnectionType::Tcp
+    ) {
+        packet_api.request_graph_rebuild();
+    }
+
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
 
     Ok(())
 }
@@ -98,18 +286,64 @@ pub fn handle_node_info_packet<R: tauri::Runtime>(
     node_info: protobufs::NodeInfo,
 ) -> Result<(), DeviceUpdateError> {
     packet_api.device.add_node_info(node_info.clone());
+    packet_api.device.node_db_nums.insert(node_info.num);
 
-    let mut graph = packet_api
-        .get_locked_graph()
-        .map_err(|e| DeviceUpdateError::GeneralFailure(e.to_string()))?;
+    // Nodes from the radio's database start with a known state so that
+    // connecting doesn't report every stale node as having gone offline
 
-    graph.update_from_node_info(node_info);
+    if node_info.num != packet_api.device.my_node_info.my_node_num {
+        let thresholds = state::node_liveness::liveness_thresholds(&packet_api.app_handle);
 
-    events::dispatch_updated_device(&packet_api.app_handle, &packet_api.device)
-        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+        packet_api.device.node_liveness.seed(
+            node_info.num,
+            node_info.last_heard,
+            get_current_time_u32(),
+            &thresholds,
+        );
+    }
+
+    // Applied to the graph along with the rest of the node database, see `GraphUpdateBatch`
+    packet_api.graph_batch.push(node_info, Instant::now());
 
-    events::dispatch_updated_graph(&packet_api.app_handle, graph.clone())
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+
+    Ok(())
+}
+
+pub fn handle_queue_status_packet<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+    queue_status: protobufs::QueueStatus,
+) -> Result<(), DeviceUpdateError> {
+    let was_open = packet_api.radio_queue.is_open();
+
+    packet_api.device.set_queue_status(queue_status.clone());
+    packet_api.radio_queue.update(queue_status.free);
+
+    // Only notify the UI when outgoing sends start or stop being throttled
+
+    if was_open != packet_api.radio_queue.is_open() {
+        debug!(
+            "Radio TX queue throttling changed on device \"{}\", {} of {} slots free",
+            packet_api.device_key, queue_status.free, queue_status.maxlen
+        );
+
+        events::dispatch_radio_queue_throttle_status(
+            &packet_api.app_handle,
+            RadioQueueThrottleStatus {
+                api_version: EVENT_API_VERSION,
+                device_key: packet_api.device_key.clone(),
+                throttled: !packet_api.radio_queue.is_open(),
+                free: queue_status.free,
+                maxlen: queue_status.maxlen,
+            },
+        )
         .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+    }
 
     Ok(())
 }
@@ -1,18 +1,294 @@
-use log::debug;
+use log::{debug, trace, warn};
 use meshtastic::protobufs;
-use tauri::api::notification::Notification;
+use tauri::Manager;
 
 use crate::{
     device::{
-        helpers::{get_channel_name, get_node_user_name},
+        canned_messages::decode_canned_messages,
+        clock::DEFAULT_CLOCK_SKEW_THRESHOLD_SECS,
+        config_cache::cache_device_config,
+        helpers::{get_channel_display_name, get_current_time_u32, get_node_user_name},
+        message_store::{complete_outgoing_message, queue_message_write},
+        reactions::{self, MessageReactions, ReactionPacket},
+        remote_hardware::{classify_hardware_message, RemoteHardwareUpdate},
+        telemetry_store::queue_telemetry_write,
         ChannelMessageState, NeighborInfoPacket, NormalizedWaypoint, PositionPacket,
-        TelemetryPacket, TextPacket, UserPacket, WaypointPacket,
+        RangeTestPacket, SerialDeviceStatus, TelemetryPacket, TextPacket, UserPacket,
+        WaypointPacket,
     },
-    ipc::events,
-    packet_api::{handlers::DeviceUpdateError, MeshPacketApi},
+    graph::{ds::position_archive::PositionSource, store::archive_node_position},
+    ipc::{
+        events, ClockSkewEvent, GpioChangedEvent, MessageStateEvent, NodeStatusChangedEvent,
+        RemoteAdminResultEvent, TracerouteResultEvent, EVENT_API_VERSION,
+    },
+    notifications::{
+        self,
+        dispatcher::{self, SystemNotification},
+        preferences::NotificationCategory,
+        webhooks::{enqueue_webhook, WebhookPayload},
+    },
+    packet_api::{
+        handlers::DeviceUpdateError,
+        remote_admin::{RemoteAdminKind, RemoteAdminOutcome},
+        MeshPacketApi,
+    },
+    state,
 };
 use meshtastic::Message;
 
+/// Hop limit used by the firmware when none is configured
+const DEFAULT_HOP_LIMIT: u32 = 3;
+
+/// Compares the receive time stamped by our radio against the host clock,
+/// emitting an event when the radio's clock drifts beyond the skew threshold.
+pub fn handle_packet_clock_skew<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+    packet: &protobufs::MeshPacket,
+) -> Result<(), DeviceUpdateError> {
+    // Radios without a valid clock don't stamp received packets
+    if packet.rx_time == 0 {
+        return Ok(());
+    }
+
+    let threshold_secs = packet_api
+        .app_handle
+        .try_state::<state::time_sync::TimeSyncState>()
+        .and_then(|time_sync| time_sync.inner.lock().ok().map(|c| c.skew_threshold_secs))
+        .unwrap_or(DEFAULT_CLOCK_SKEW_THRESHOLD_SECS);
+
+    let skew =
+        packet_api
+            .device
+            .update_clock_skew(packet.rx_time, get_current_time_u32(), threshold_secs);
+
+    if let Some(skew_secs) = skew {
+        warn!("Device clock is skewed by {} seconds", skew_secs);
+
+        events::dispatch_clock_skew_detected(
+            &packet_api.app_handle,
+            ClockSkewEvent {
+                api_version: EVENT_API_VERSION,
+                device_key: packet_api.device_key.clone(),
+                skew_secs,
+            },
+        )
+        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Records link quality for packets our radio heard directly from the sender
+pub fn handle_packet_link_quality<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+    packet: &protobufs::MeshPacket,
+) -> Result<(), DeviceUpdateError> {
+    let my_node_num = packet_api.device.my_node_info.my_node_num;
+
+    // Packets sent by our own node have no rx metadata
+    if packet.from == my_node_num || packet.rx_time == 0 {
+        return Ok(());
+    }
+
+    let is_direct = if packet.hop_start > 0 {
+        packet.hop_start.saturating_sub(packet.hop_limit) == 0
+    } else {
+        // Firmware that doesn't send a hop start leaves no record of the hops a packet
+        // took, so one with no hops consumed from our configured hop limit is the best
+        // indication that it wasn't relayed
+        let hop_limit = packet_api
+            .device
+            .config
+            .lora
+            .as_ref()
+            .map(|lora| lora.hop_limit)
+            .filter(|hop_limit| *hop_limit > 0)
+            .unwrap_or(DEFAULT_HOP_LIMIT);
+
+        packet.hop_limit >= hop_limit
+    };
+
+    if !is_direct {
+        return Ok(());
+    }
+
+    let changes = packet_api
+        .write_graph()
+        .map_err(|e| DeviceUpdateError::GeneralFailure(e.to_string()))?
+        .update_from_direct_packet(packet, my_node_num, &packet_api.device_key);
+
+    packet_api.graph_changes.extend(changes);
+
+    Ok(())
+}
+
+/// Marks the sender as heard, emitting an event if it came back online
+pub fn handle_packet_liveness<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+    packet: &protobufs::MeshPacket,
+) -> Result<(), DeviceUpdateError> {
+    if packet.from == packet_api.device.my_node_info.my_node_num {
+        return Ok(());
+    }
+
+    // Hearing a node means the radio knows it again
+    packet_api.device.historical_nodes.remove(&packet.from);
+
+    let thresholds = state::node_liveness::liveness_thresholds(&packet_api.app_handle);
+
+    let transition = packet_api.device.node_liveness.record_heard(
+        packet.from,
+        get_current_time_u32(),
+        &thresholds,
+    );
+
+    if let Some(transition) = transition {
+        events::dispatch_node_status_changed(
+            &packet_api.app_handle,
+            NodeStatusChangedEvent::new(packet_api.device_key.clone(), transition),
+        )
+        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Messages addressed to our own node are direct messages, anything else was sent to a channel
+fn message_notification_category<R: tauri::Runtime>(
+    packet_api: &MeshPacketApi<R>,
+    packet: &protobufs::MeshPacket,
+) -> NotificationCategory {
+    if packet.to == packet_api.device.my_node_info.my_node_num {
+        NotificationCategory::DirectMessage
+    } else {
+        NotificationCategory::ChannelMessage
+    }
+}
+
+/// Config requested after the configuration flow belongs in the device's cached config
+fn cache_config_read_after_configuration<R: tauri::Runtime>(packet_api: &MeshPacketApi<R>) {
+    if packet_api.device.status != SerialDeviceStatus::Connected {
+        return;
+    }
+
+    if let Err(e) = cache_device_config(
+        &packet_api.app_handle,
+        &packet_api.device,
+        get_current_time_u32(),
+    ) {
+        warn!("Failed to cache device config: {}", e);
+    }
+}
+
+/// Ends an admin request sent to another node, publishing its outcome. Does nothing
+/// if the request already ended.
+pub fn finish_remote_admin<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+    request_id: u32,
+    outcome: RemoteAdminOutcome,
+) -> Result<(), DeviceUpdateError> {
+    let node_num = match packet_api.remote_admin.finish(request_id, outcome.clone()) {
+        Some(node_num) => node_num,
+        None => return Ok(()),
+    };
+
+    events::dispatch_remote_admin_result(
+        &packet_api.app_handle,
+        RemoteAdminResultEvent {
+            api_version: EVENT_API_VERSION,
+            device_key: packet_api.device_key.clone(),
+            request_id,
+            node_num,
+            outcome,
+        },
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))
+}
+
+/// Handles admin responses of the device's own radio, and of other nodes to requests
+/// sent to them. The radio's copy of a request sent to another node records it as
+/// waiting, and the node's response never touches the device's own state.
+pub fn handle_admin_mesh_packet<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+    packet: protobufs::MeshPacket,
+    data: protobufs::Data,
+) -> Result<(), DeviceUpdateError> {
+    let request_id = data.request_id;
+    let data = protobufs::AdminMessage::decode(data.payload.as_slice())
+        .map_err(|e| DeviceUpdateError::DecodeFailure(e.to_string()))?;
+
+    let variant = data
+        .payload_variant
+        .ok_or_else(|| DeviceUpdateError::GeneralFailure("No admin payload variant".into()))?;
+
+    let my_node_num = packet_api.device.my_node_info.my_node_num;
+
+    if packet.from == my_node_num && packet.to != my_node_num {
+        let kind = match variant {
+            protobufs::admin_message::PayloadVariant::GetConfigRequest(_) => {
+                Some(RemoteAdminKind::Read)
+            }
+            protobufs::admin_message::PayloadVariant::SetConfig(_) => Some(RemoteAdminKind::Write),
+            _ => None,
+        };
+
+        if let Some(kind) = kind {
+            packet_api.remote_admin.sent(packet.id, packet.to, kind);
+            return Ok(());
+        }
+    }
+
+    if let Some((node_num, _)) = packet_api.remote_admin.get(request_id) {
+        return match variant {
+            protobufs::admin_message::PayloadVariant::GetConfigResponse(config)
+                if packet.from == node_num =>
+            {
+                finish_remote_admin(
+                    packet_api,
+                    request_id,
+                    RemoteAdminOutcome::Config { config },
+                )
+            }
+            _ => {
+                trace!("Ignoring admin response to request {}", request_id);
+                Ok(())
+            }
+        };
+    }
+
+    match variant {
+        protobufs::admin_message::PayloadVariant::GetCannedMessageModuleMessagesResponse(
+            messages,
+        ) => {
+            packet_api
+                .device
+                .set_canned_messages(decode_canned_messages(&messages));
+        }
+        protobufs::admin_message::PayloadVariant::GetDeviceMetadataResponse(metadata) => {
+            packet_api.device.set_metadata(metadata);
+            cache_config_read_after_configuration(packet_api);
+        }
+        protobufs::admin_message::PayloadVariant::GetChannelResponse(channel) => {
+            packet_api.device.set_channel_config(channel)?;
+            cache_config_read_after_configuration(packet_api);
+        }
+        _ => {
+            return Err(DeviceUpdateError::PacketNotSupported(
+                "admin response".into(),
+            ));
+        }
+    }
+
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+
+    Ok(())
+}
+
 pub fn handle_user_mesh_packet<R: tauri::Runtime>(
     packet_api: &mut MeshPacketApi<R>,
     packet: protobufs::MeshPacket,
@@ -23,8 +299,12 @@ pub fn handle_user_mesh_packet<R: tauri::Runtime>(
 
     packet_api.device.add_user(UserPacket { packet, data });
 
-    events::dispatch_updated_device(&packet_api.app_handle, &packet_api.device)
-        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
 
     Ok(())
 }
@@ -42,17 +322,174 @@ pub fn handle_position_mesh_packet<R: tauri::Runtime>(
         data: data.clone(),
     });
 
-    let mut graph = packet_api
-        .get_locked_graph()
-        .map_err(|e| DeviceUpdateError::GeneralFailure(e.to_string()))?;
+    let position = packet_api
+        .device
+        .nodes
+        .get(&packet.from)
+        .and_then(|node| node.last_known_position())
+        .map(|position| (position.latitude, position.longitude));
+
+    if let Some((latitude, longitude)) = position {
+        notifications::evaluate_geofences(
+            &packet_api.app_handle,
+            &packet_api.device_key,
+            &packet_api.device,
+            packet.from,
+            latitude.into(),
+            longitude.into(),
+        );
+    }
 
-    graph.update_from_position(packet, data);
+    let changes = {
+        let mut graph = packet_api
+            .write_graph()
+            .map_err(|e| DeviceUpdateError::GeneralFailure(e.to_string()))?;
+
+        archive_node_position(
+            packet_api,
+            &mut graph,
+            packet.from,
+            PositionSource::PositionPacket,
+        );
+        graph.update_from_position(packet, data)
+    };
+
+    packet_api.graph_changes.extend(changes);
+
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
 
-    events::dispatch_updated_device(&packet_api.app_handle, &packet_api.device)
-        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+    Ok(())
+}
 
-    events::dispatch_updated_graph(&packet_api.app_handle, graph.clone())
-        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+pub fn handle_range_test_mesh_packet<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+    packet: protobufs::MeshPacket,
+    data: protobufs::Data,
+) -> Result<(), DeviceUpdateError> {
+    let data = String::from_utf8(data.payload)
+        .map_err(|e| DeviceUpdateError::DecodeFailure(e.to_string()))?;
+
+    packet_api
+        .device
+        .add_range_test_message(RangeTestPacket {
+            packet,
+            data: data.clone(),
+        })
+        .ok_or_else(|| {
+            DeviceUpdateError::DecodeFailure(format!("Invalid range test payload \"{}\"", data))
+        })?;
+
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+
+    Ok(())
+}
+
+/// Records GPIO readings of other nodes. Replies to reads this device sent answer the
+/// read they name as their request, while watch notifications never answer a read.
+pub fn handle_remote_hardware_mesh_packet<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+    packet: protobufs::MeshPacket,
+    data: protobufs::Data,
+) -> Result<(), DeviceUpdateError> {
+    let request_id = data.request_id;
+    let data = protobufs::HardwareMessage::decode(data.payload.as_slice())
+        .map_err(|e| DeviceUpdateError::DecodeFailure(e.to_string()))?;
+
+    // The radio's copy of a read sent to another node records it as waiting
+    if packet.from == packet_api.device.my_node_info.my_node_num {
+        if data.r#type == protobufs::hardware_message::Type::ReadGpios as i32 {
+            packet_api.gpio_reads.sent(packet.id, packet.to);
+        }
+
+        return Ok(());
+    }
+
+    let update = match classify_hardware_message(packet.from, data, get_current_time_u32()) {
+        Some(update) => update,
+        None => {
+            debug!("Ignoring remote hardware request from node {}", packet.from);
+            return Ok(());
+        }
+    };
+
+    match update {
+        RemoteHardwareUpdate::ReadReply(reading) => {
+            packet_api.gpio_reads.answer(request_id, &reading);
+            packet_api.device.set_gpio_reading(reading);
+        }
+        RemoteHardwareUpdate::Changed(reading) => {
+            packet_api.device.set_gpio_reading(reading.clone());
+
+            events::dispatch_gpio_changed(
+                &packet_api.app_handle,
+                GpioChangedEvent {
+                    api_version: EVENT_API_VERSION,
+                    device_key: packet_api.device_key.clone(),
+                    reading,
+                },
+            )
+            .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+        }
+    }
+
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+
+    Ok(())
+}
+
+/// Records a new delivery state of a message the device sent, storing and publishing
+/// it. Routing results for packets the device has no message for, e.g. admin requests,
+/// are ignored.
+pub fn update_message_state<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+    channel: u32,
+    message_id: u32,
+    state: ChannelMessageState,
+) -> Result<(), DeviceUpdateError> {
+    if !packet_api
+        .device
+        .set_message_state(channel, message_id, state.clone())
+    {
+        trace!("Ignoring state of unknown message {}", message_id);
+        return Ok(());
+    }
+
+    queue_message_write(packet_api, channel, message_id);
+    complete_outgoing_message(packet_api, channel, message_id);
+
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+
+    events::dispatch_message_state(
+        &packet_api.app_handle,
+        MessageStateEvent {
+            api_version: EVENT_API_VERSION,
+            device_key: packet_api.device_key.clone(),
+            channel,
+            message_id,
+            state,
+        },
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
 
     Ok(())
 }
@@ -68,54 +505,36 @@ pub fn handle_routing_mesh_packet<R: tauri::Runtime>(
     if let Some(variant) = routing_data.variant {
         match variant {
             protobufs::routing::Variant::ErrorReason(e) => {
+                if let Some((node_num, kind)) = packet_api.remote_admin.get(data.request_id) {
+                    let outcome =
+                        RemoteAdminOutcome::from_routing_error(kind, e, packet.from == node_num);
+
+                    if let Some(outcome) = outcome {
+                        finish_remote_admin(packet_api, data.request_id, outcome)?;
+                    }
+
+                    return Ok(());
+                }
+
                 if let Some(r) = protobufs::routing::Error::from_i32(e) {
-                    match r {
-                        protobufs::routing::Error::None => {
-                            packet_api.device.set_message_state(
-                                packet.channel,
-                                data.request_id,
-                                ChannelMessageState::Acknowledged,
-                            );
-                        }
+                    let state = match r {
+                        protobufs::routing::Error::None => ChannelMessageState::Acknowledged,
                         protobufs::routing::Error::Timeout => {
-                            packet_api.device.set_message_state(
-                                packet.channel,
-                                data.request_id,
-                                ChannelMessageState::Error("Message timed out".into()),
-                            );
+                            ChannelMessageState::Error("Message timed out".into())
                         }
                         protobufs::routing::Error::MaxRetransmit => {
-                            packet_api.device.set_message_state(
-                                packet.channel,
-                                data.request_id,
-                                ChannelMessageState::Error("Reached retransmit limit".into()),
-                            );
+                            ChannelMessageState::Error("Reached retransmit limit".into())
                         }
                         protobufs::routing::Error::GotNak => {
-                            packet_api.device.set_message_state(
-                                packet.channel,
-                                data.request_id,
-                                ChannelMessageState::Error("Received NAK".into()),
-                            );
+                            ChannelMessageState::Error("Received NAK".into())
                         }
                         protobufs::routing::Error::TooLarge => {
-                            packet_api.device.set_message_state(
-                                packet.channel,
-                                data.request_id,
-                                ChannelMessageState::Error("Message too large".into()),
-                            );
-                        }
-                        _ => {
-                            packet_api.device.set_message_state(
-                                packet.channel,
-                                data.request_id,
-                                ChannelMessageState::Error("Message failed to send".into()),
-                            );
+                            ChannelMessageState::Error("Message too large".into())
                         }
-                    }
+                        _ => ChannelMessageState::Error("Message failed to send".into()),
+                    };
 
-                    events::dispatch_updated_device(&packet_api.app_handle, &packet_api.device)
-                        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+                    update_message_state(packet_api, packet.channel, data.request_id, state)?;
                 }
             }
             protobufs::routing::Variant::RouteReply(r) => {
@@ -138,12 +557,36 @@ pub fn handle_telemetry_mesh_packet<R: tauri::Runtime>(
     let data = protobufs::Telemetry::decode(data.payload.as_slice())
         .map_err(|e| DeviceUpdateError::DecodeFailure(e.to_string()))?;
 
+    let battery_level = match &data.variant {
+        Some(protobufs::telemetry::Variant::DeviceMetrics(metrics)) => Some(metrics.battery_level),
+        _ => None,
+    };
+
+    let from = packet.from;
+
+    queue_telemetry_write(packet_api, from, &data);
+
     packet_api
         .device
         .set_device_metrics(TelemetryPacket { packet, data });
 
-    events::dispatch_updated_device(&packet_api.app_handle, &packet_api.device)
-        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+
+    // A battery level of 0 means the node didn't report one
+    if let Some(level) = battery_level.filter(|level| *level > 0) {
+        notifications::evaluate_battery_rules(
+            &packet_api.app_handle,
+            &packet_api.device_key,
+            &packet_api.device,
+            from,
+            level,
+        );
+    }
 
     Ok(())
 }
@@ -153,38 +596,82 @@ pub fn handle_text_message_mesh_packet<R: tauri::Runtime>(
     packet: protobufs::MeshPacket,
     data: protobufs::Data,
 ) -> Result<(), DeviceUpdateError> {
+    let is_reaction = reactions::is_reaction(data.emoji, data.reply_id);
+    let reply_id = data.reply_id;
+
     let data = String::from_utf8(data.payload)
         .map_err(|e| DeviceUpdateError::GeneralFailure(e.to_string()))?;
 
+    // Reactions are attached to the message they reference rather than shown as new messages
+
+    if is_reaction {
+        packet_api.device.add_reaction(ReactionPacket {
+            reply_id,
+            channel: packet.channel,
+            from: packet.from,
+            emoji: data,
+            received_at: get_current_time_u32(),
+        });
+
+        queue_message_write(packet_api, packet.channel, reply_id);
+
+        events::dispatch_updated_device(
+            &packet_api.app_handle,
+            &packet_api.device_key,
+            &packet_api.device,
+        )
+        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+
+        return Ok(());
+    }
+
     packet_api.device.add_text_message(TextPacket {
         packet: packet.clone(),
         data: data.clone(),
+        reactions: MessageReactions::new(),
     });
 
+    queue_message_write(packet_api, packet.channel, packet.id);
+
     let from_user_name = get_node_user_name(&mut packet_api.device, &packet.from)
         .unwrap_or_else(|| packet.from.to_string());
 
-    let channel_name = get_channel_name(&mut packet_api.device, &packet.channel)
-        .unwrap_or_else(|| "Unknown channel".into());
+    let channel_name = get_channel_display_name(&packet_api.device, packet.channel);
 
     // Always keep updates at bottom in case of failure during functions
-    events::dispatch_updated_device(&packet_api.app_handle, &packet_api.device)
-        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
 
     if packet.from != packet_api.device.my_node_info.my_node_num {
-        Notification::new(
-            packet_api
-                .app_handle
-                .config()
-                .tauri
-                .bundle
-                .identifier
-                .clone(),
-        )
-        .title(format!("{} in {}", from_user_name, channel_name))
-        .body(data)
-        .notify(&packet_api.app_handle)
-        .map_err(|e| DeviceUpdateError::NotificationDispatchFailure(e.to_string()))?;
+        let category = message_notification_category(packet_api, &packet);
+
+        if category == NotificationCategory::ChannelMessage {
+            enqueue_webhook(
+                &packet_api.app_handle,
+                WebhookPayload::channel_message(
+                    packet.channel,
+                    packet.from,
+                    from_user_name.clone(),
+                    data.clone(),
+                    get_current_time_u32(),
+                ),
+            );
+        }
+
+        dispatcher::enqueue(
+            &packet_api.app_handle,
+            SystemNotification {
+                category,
+                node_num: Some(packet.from),
+                title: format!("{} in {}", from_user_name, channel_name),
+                body: data,
+                group: (category == NotificationCategory::ChannelMessage).then_some(channel_name),
+            },
+        );
     }
 
     Ok(())
@@ -198,7 +685,10 @@ pub fn handle_waypoint_mesh_packet<R: tauri::Runtime>(
     let data = protobufs::Waypoint::decode(data.payload.as_slice())
         .map_err(|e| DeviceUpdateError::DecodeFailure(e.to_string()))?;
 
-    let converted_data: NormalizedWaypoint = data.into();
+    let converted_data = NormalizedWaypoint {
+        sender: Some(packet.from),
+        ..NormalizedWaypoint::from(data)
+    };
 
     packet_api.device.add_waypoint(converted_data.clone());
     packet_api.device.add_waypoint_message(WaypointPacket {
@@ -209,29 +699,31 @@ pub fn handle_waypoint_mesh_packet<R: tauri::Runtime>(
     let from_user_name = get_node_user_name(&mut packet_api.device, &packet.from)
         .unwrap_or_else(|| packet.from.to_string());
 
-    let channel_name = get_channel_name(&mut packet_api.device, &packet.channel)
-        .unwrap_or_else(|| "Unknown channel".into());
+    let channel_name = get_channel_display_name(&packet_api.device, packet.channel);
 
-    events::dispatch_updated_device(&packet_api.app_handle, &packet_api.device)
-        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
 
     if packet.from != packet_api.device.my_node_info.my_node_num {
-        Notification::new(
-            packet_api
-                .app_handle
-                .config()
-                .tauri
-                .bundle
-                .identifier
-                .clone(),
-        )
-        .title(format!("{} in {}", from_user_name, channel_name))
-        .body(format!(
-            "Sent waypoint \"{}\" at {}, {}",
-            converted_data.name, converted_data.latitude, converted_data.longitude
-        ))
-        .notify(&packet_api.app_handle)
-        .map_err(|e| DeviceUpdateError::NotificationDispatchFailure(e.to_string()))?;
+        let category = message_notification_category(packet_api, &packet);
+
+        dispatcher::enqueue(
+            &packet_api.app_handle,
+            SystemNotification {
+                category,
+                node_num: Some(packet.from),
+                title: format!("{} in {}", from_user_name, channel_name),
+                body: format!(
+                    "Sent waypoint \"{}\" at {}, {}",
+                    converted_data.name, converted_data.latitude, converted_data.longitude
+                ),
+                group: (category == NotificationCategory::ChannelMessage).then_some(channel_name),
+            },
+        );
     }
 
     Ok(())
@@ -250,17 +742,84 @@ pub fn handle_neighbor_info_mesh_packet<R: tauri::Runtime>(
         data: data.clone(),
     });
 
-    let mut graph = packet_api
-        .get_locked_graph()
-        .map_err(|e| DeviceUpdateError::GeneralFailure(e.to_string()))?;
+    let channel_name = get_channel_display_name(&packet_api.device, packet.channel);
 
-    graph.update_from_neighbor_info(packet, data);
+    let changes = packet_api
+        .write_graph()
+        .map_err(|e| DeviceUpdateError::GeneralFailure(e.to_string()))?
+        .update_from_neighbor_info(packet, data, channel_name, &packet_api.device_key);
 
-    events::dispatch_updated_device(&packet_api.app_handle, &packet_api.device)
-        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+    packet_api.graph_changes.extend(changes);
 
-    events::dispatch_updated_graph(&packet_api.app_handle, graph.clone())
-        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+    events::dispatch_updated_device(
+        &packet_api.app_handle,
+        &packet_api.device_key,
+        &packet_api.device,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+
+    Ok(())
+}
+
+/// Handles traceroutes this device sent: the radio's copy of the request records it
+/// as waiting, and its response adds the discovered route to the graph. Responses to
+/// requests the app didn't send, or that already timed out, are ignored.
+pub fn handle_traceroute_mesh_packet<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+    packet: protobufs::MeshPacket,
+    data: protobufs::Data,
+) -> Result<(), DeviceUpdateError> {
+    let my_node_num = packet_api.device.my_node_info.my_node_num;
+
+    if packet.from == my_node_num {
+        packet_api
+            .traceroutes
+            .sent(packet.id, packet.to, get_current_time_u32());
+        return Ok(());
+    }
+
+    let pending = match packet_api.traceroutes.finish(data.request_id) {
+        Some(pending) => pending,
+        None => {
+            trace!(
+                "Ignoring traceroute response to unknown request {}",
+                data.request_id
+            );
+            return Ok(());
+        }
+    };
+
+    let discovery = protobufs::RouteDiscovery::decode(data.payload.as_slice())
+        .map_err(|e| DeviceUpdateError::DecodeFailure(e.to_string()))?;
+
+    // The response lists the hops between the two ends
+    let mut route = vec![my_node_num];
+    route.extend(discovery.route);
+    route.push(packet.from);
+
+    let channel_name = get_channel_display_name(&packet_api.device, packet.channel);
+
+    let changes = packet_api
+        .write_graph()
+        .map_err(|e| DeviceUpdateError::GeneralFailure(e.to_string()))?
+        .update_from_traceroute(&route, packet.channel, channel_name, &packet_api.device_key);
+
+    packet_api.graph_changes.extend(changes);
+
+    events::dispatch_traceroute_result(
+        &packet_api.app_handle,
+        TracerouteResultEvent {
+            api_version: EVENT_API_VERSION,
+            device_key: packet_api.device_key.clone(),
+            request_id: data.request_id,
+            destination: pending.destination,
+            route,
+            successful: true,
+            message: None,
+            requested_at: pending.sent_at,
+        },
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
 
     Ok(())
 }
@@ -1,17 +1,67 @@
-use std::sync::{Arc, LockResult, Mutex};
+use std::sync::{RwLockReadGuard, RwLockWriteGuard};
 
 // use meshtastic::connections::stream_api::{state::Configured, StreamApi};
 
-use crate::{device::MeshDevice, graph::ds::graph::MeshGraph, state::DeviceKey};
+use crate::{
+    connection::metrics::SharedConnectionMetrics,
+    device::{helpers::get_current_time_u32, MeshDevice},
+    graph::{
+        api::change::GraphChange, ds::graph::MeshGraph, edge_features::EdgeFeatureCache,
+        rebuild::GraphRebuild,
+    },
+    ipc::events::coalesce::CoalescingBuffer,
+    replay::capture::PacketCapture,
+    state::{
+        graph::{GraphStateInner, TimedGuard},
+        DeviceKey,
+    },
+};
 
+use self::debug_stream::PacketDebugStream;
+use self::dedup::PacketDedupCache;
+use self::gpio_reads::GpioReadRequests;
+use self::graph_batch::GraphUpdateBatch;
+use self::radio_queue::RadioQueueGate;
+use self::remote_admin::RemoteAdminRequests;
+use self::router::DEFAULT_GRAPH_UPDATE_WINDOW;
+use self::summary::ConnectionType;
+use self::traceroute::TracerouteTracker;
+
+pub mod actor;
+pub mod debug_stream;
+pub mod dedup;
+pub mod gpio_reads;
+pub mod graph_batch;
+pub mod graph_edit;
 pub mod handlers;
+pub mod operations;
+pub mod radio_queue;
+pub mod radio_write;
+pub mod remote_admin;
 pub mod router;
+pub mod summary;
+pub mod traceroute;
 
 pub struct MeshPacketApi<R: tauri::Runtime = tauri::Wry> {
     pub app_handle: tauri::AppHandle<R>,
     pub device_key: DeviceKey,
+    pub connection_type: ConnectionType,
     pub device: MeshDevice,
-    pub graph_arc: Arc<Mutex<MeshGraph>>,
+    pub graph_arc: GraphStateInner,
+    pub radio_queue: RadioQueueGate,
+    pub last_packet_received: u32, // seconds since epoch, used to detect unresponsive devices
+    pub dedup: PacketDedupCache,
+    pub metrics: SharedConnectionMetrics,
+    pub debug_stream: Option<PacketDebugStream>, // set while the protocol console is streaming packets
+    pub capture: Option<PacketCapture>, // set while decoded packets are recorded to a capture file
+    pub graph_changes: Vec<GraphChange>, // made by the packet being handled, published once it's handled
+    pub graph_batch: GraphUpdateBatch,   // node infos waiting to be applied to the graph
+    pub graph_publish: CoalescingBuffer<(), ()>, // limits how often the graph is regenerated and published, which reads the latest graph
+    pub edge_features: EdgeFeatureCache, // edge features last published, regenerated as their edges change
+    pub graph_rebuild: GraphRebuild,     // rebuild from the stored graph, requested or running
+    pub traceroutes: TracerouteTracker,  // traceroutes sent, waiting for their response
+    pub remote_admin: RemoteAdminRequests, // admin requests sent to other nodes, waiting for their result
+    pub gpio_reads: GpioReadRequests, // GPIO reads sent to other nodes, waiting for their reply
 }
 
 impl<R: tauri::Runtime> MeshPacketApi<R> {
@@ -19,17 +69,38 @@ impl<R: tauri::Runtime> MeshPacketApi<R> {
         app_handle: tauri::AppHandle<R>,
         device_key: DeviceKey,
         device: MeshDevice,
-        graph_arc: Arc<Mutex<MeshGraph>>,
+        graph_arc: GraphStateInner,
     ) -> Self {
         Self {
             app_handle,
             device_key,
+            connection_type: ConnectionType::default(),
             device,
             graph_arc,
+            radio_queue: RadioQueueGate::new(),
+            last_packet_received: get_current_time_u32(),
+            dedup: PacketDedupCache::default(),
+            metrics: SharedConnectionMetrics::default(),
+            debug_stream: None,
+            capture: None,
+            graph_changes: vec![],
+            graph_batch: GraphUpdateBatch::default(),
+            graph_publish: CoalescingBuffer::new(DEFAULT_GRAPH_UPDATE_WINDOW),
+            edge_features: EdgeFeatureCache::default(),
+            graph_rebuild: GraphRebuild::default(),
+            traceroutes: TracerouteTracker::default(),
+            remote_admin: RemoteAdminRequests::default(),
+            gpio_reads: GpioReadRequests::default(),
         }
     }
 
-    pub fn get_locked_graph(&self) -> LockResult<std::sync::MutexGuard<MeshGraph>> {
-        self.graph_arc.lock()
+    #[track_caller]
+    pub fn read_graph(&self) -> Result<TimedGuard<RwLockReadGuard<'_, MeshGraph>>, String> {
+        self.graph_arc.read()
+    }
+
+    #[track_caller]
+    pub fn write_graph(&self) -> Result<TimedGuard<RwLockWriteGuard<'_, MeshGraph>>, String> {
+        self.graph_arc.write()
     }
 }
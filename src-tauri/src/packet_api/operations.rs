@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+use std::future::Future;
+
+use log::{debug, trace};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+use tokio_util::sync::CancellationToken;
+
+use crate::ipc::events;
+use crate::ipc::{OperationFinishedEvent, OperationProgressEvent, EVENT_API_VERSION};
+use crate::state::{self, DeviceKey};
+
+pub type OperationId = u32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum OperationKind {
+    ConfigPush,
+    ChannelImport,
+    ChannelRestore,
+    Waypoints,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", tag = "status", content = "message")]
+pub enum OperationItemStatus {
+    Succeeded,
+    Failed(String),
+    Cancelled, // not sent because the operation was cancelled first
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationItemResult {
+    pub index: u32,
+    pub label: String,
+    pub status: OperationItemStatus,
+}
+
+/// Outgoing bulk operations in progress, so they can be cancelled by id
+#[derive(Debug, Default)]
+pub struct Operations {
+    next_id: OperationId,
+    active: HashMap<OperationId, CancellationToken>,
+}
+
+impl Operations {
+    pub fn start(&mut self) -> (OperationId, CancellationToken) {
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+
+        let token = CancellationToken::new();
+        self.active.insert(self.next_id, token.clone());
+
+        (self.next_id, token)
+    }
+
+    /// Returns whether the operation was still in progress
+    pub fn cancel(&mut self, id: OperationId) -> bool {
+        match self.active.remove(&id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn finish(&mut self, id: OperationId) {
+        self.active.remove(&id);
+    }
+
+    /// Cancels every operation in progress, e.g. when the app shuts down
+    pub fn cancel_all(&mut self) {
+        for (_, token) in self.active.drain() {
+            token.cancel();
+        }
+    }
+}
+
+/// What an operation reports while it runs
+#[derive(Clone, Debug, PartialEq)]
+pub enum OperationUpdate {
+    Progress {
+        done: u32,
+        total: u32,
+        item: OperationItemResult,
+    },
+    Completed(Vec<OperationItemResult>),
+    Failed(Vec<OperationItemResult>),
+}
+
+/// Sends `items` one at a time, reporting progress after each. Items that fail don't
+/// stop the operation, but once `cancel` is triggered the remaining items are skipped.
+/// The item being sent when the operation is cancelled still finishes.
+pub async fn run_operation<T, F, Fut>(
+    items: Vec<(String, T)>,
+    cancel: &CancellationToken,
+    mut send_item: F,
+    emit: &mut impl FnMut(OperationUpdate),
+) -> Vec<OperationItemResult>
+where
+    F: FnMut(T) -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    let total = items.len() as u32;
+    let mut results = Vec::with_capacity(items.len());
+
+    for (index, (label, item)) in items.into_iter().enumerate() {
+        let status = if cancel.is_cancelled() {
+            OperationItemStatus::Cancelled
+        } else {
+            match send_item(item).await {
+                Ok(()) => OperationItemStatus::Succeeded,
+                Err(e) => OperationItemStatus::Failed(e),
+            }
+        };
+
+        trace!("Operation item \"{}\" finished with {:?}", label, status);
+
+        let result = OperationItemResult {
+            index: index as u32,
+            label,
+            status,
+        };
+
+        if result.status != OperationItemStatus::Cancelled {
+            emit(OperationUpdate::Progress {
+                done: index as u32 + 1,
+                total,
+                item: result.clone(),
+            });
+        }
+
+        results.push(result);
+    }
+
+    results
+}
+
+/// Reports the operation as completed if every item succeeded, otherwise as failed
+pub fn finish_operation(results: Vec<OperationItemResult>, emit: &mut impl FnMut(OperationUpdate)) {
+    if results
+        .iter()
+        .all(|r| r.status == OperationItemStatus::Succeeded)
+    {
+        emit(OperationUpdate::Completed(results));
+    } else {
+        emit(OperationUpdate::Failed(results));
+    }
+}
+
+fn dispatch_operation_update(
+    handle: &tauri::AppHandle,
+    device_key: &DeviceKey,
+    operation_id: OperationId,
+    kind: OperationKind,
+    update: OperationUpdate,
+) {
+    let finished = |results| OperationFinishedEvent {
+        api_version: EVENT_API_VERSION,
+        device_key: device_key.clone(),
+        operation_id,
+        kind,
+        results,
+    };
+
+    let result = match update {
+        OperationUpdate::Progress { done, total, item } => events::dispatch_operation_progress(
+            handle,
+            OperationProgressEvent {
+                api_version: EVENT_API_VERSION,
+                device_key: device_key.clone(),
+                operation_id,
+                kind,
+                done,
+                total,
+                item,
+            },
+        ),
+        OperationUpdate::Completed(results) => {
+            events::dispatch_operation_completed(handle, finished(results))
+        }
+        OperationUpdate::Failed(results) => {
+            events::dispatch_operation_failed(handle, finished(results))
+        }
+    };
+
+    if let Err(e) = result {
+        debug!(
+            "Failed to dispatch operation {} update: {}",
+            operation_id, e
+        );
+    }
+}
+
+/// Runs a bulk operation in the background and returns its id right away. `finalize`
+/// runs after the items, even if the operation was cancelled, and any error it returns
+/// is reported as a failed item with the given label.
+pub fn spawn_operation<T, F, Fut, C, CFut>(
+    handle: tauri::AppHandle,
+    device_key: DeviceKey,
+    kind: OperationKind,
+    items: Vec<(String, T)>,
+    send_item: F,
+    finalize: Option<(String, C)>,
+) -> Result<OperationId, String>
+where
+    T: Send + 'static,
+    F: FnMut(T) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), String>> + Send,
+    C: FnOnce() -> CFut + Send + 'static,
+    CFut: Future<Output = Result<(), String>> + Send,
+{
+    let (operation_id, cancel) = {
+        let operations = handle.state::<state::operations::OperationsState>();
+        let mut operations = operations.inner.lock().map_err(|e| e.to_string())?;
+        operations.start()
+    };
+
+    debug!(
+        "Starting {:?} operation {} with {} items",
+        kind,
+        operation_id,
+        items.len()
+    );
+
+    tauri::async_runtime::spawn(async move {
+        let mut emit =
+            |update| dispatch_operation_update(&handle, &device_key, operation_id, kind, update);
+
+        let mut results = run_operation(items, &cancel, send_item, &mut emit).await;
+
+        if let Some((label, finalize)) = finalize {
+            if let Err(e) = finalize().await {
+                results.push(OperationItemResult {
+                    index: results.len() as u32,
+                    label,
+                    status: OperationItemStatus::Failed(e),
+                });
+            }
+        }
+
+        finish_operation(results, &mut emit);
+
+        let operations = handle.state::<state::operations::OperationsState>();
+        if let Ok(mut operations) = operations.inner.lock() {
+            operations.finish(operation_id);
+        };
+    });
+
+    Ok(operation_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_push_items() -> Vec<(String, bool)> {
+        ["Device", "Position", "Power", "LoRa", "Channel 1"]
+            .into_iter()
+            .enumerate()
+            .map(|(index, label)| (label.to_string(), index == 2))
+            .collect()
+    }
+
+    async fn push_item(fails: bool) -> Result<(), String> {
+        tokio::task::yield_now().await;
+
+        if fails {
+            Err("Radio rejected config".into())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn item(index: u32, label: &str, status: OperationItemStatus) -> OperationItemResult {
+        OperationItemResult {
+            index,
+            label: label.into(),
+            status,
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_progress_and_failure_of_bulk_config_push() {
+        let mut updates = vec![];
+        let mut emit = |update| updates.push(update);
+
+        let results = run_operation(
+            config_push_items(),
+            &CancellationToken::new(),
+            push_item,
+            &mut emit,
+        )
+        .await;
+        finish_operation(results, &mut emit);
+
+        let failed = OperationItemStatus::Failed("Radio rejected config".into());
+        let expected_items = vec![
+            item(0, "Device", OperationItemStatus::Succeeded),
+            item(1, "Position", OperationItemStatus::Succeeded),
+            item(2, "Power", failed),
+            item(3, "LoRa", OperationItemStatus::Succeeded),
+            item(4, "Channel 1", OperationItemStatus::Succeeded),
+        ];
+
+        let mut expected: Vec<OperationUpdate> = expected_items
+            .iter()
+            .map(|item| OperationUpdate::Progress {
+                done: item.index + 1,
+                total: 5,
+                item: item.clone(),
+            })
+            .collect();
+        expected.push(OperationUpdate::Failed(expected_items));
+
+        assert_eq!(updates, expected);
+    }
+
+    #[tokio::test]
+    async fn completes_when_every_item_succeeds() {
+        let mut updates = vec![];
+        let items = vec![("Device".to_string(), false), ("LoRa".to_string(), false)];
+
+        let results = run_operation(items, &CancellationToken::new(), push_item, &mut |u| {
+            updates.push(u)
+        })
+        .await;
+        finish_operation(results, &mut |u| updates.push(u));
+
+        assert_eq!(updates.len(), 3);
+        assert!(
+            matches!(updates.last(), Some(OperationUpdate::Completed(results)) if results.len() == 2)
+        );
+    }
+
+    #[tokio::test]
+    async fn cancellation_skips_unsent_items() {
+        let mut operations = Operations::default();
+        let (id, cancel) = operations.start();
+        let mut sent = vec![];
+        let mut updates = vec![];
+
+        let results = run_operation(
+            config_push_items(),
+            &cancel,
+            |fails| {
+                sent.push(fails);
+
+                // Cancelled while the second item is in flight
+                if sent.len() == 2 {
+                    operations.cancel(id);
+                }
+
+                push_item(false)
+            },
+            &mut |u| updates.push(u),
+        )
+        .await;
+
+        assert_eq!(sent.len(), 2);
+        assert_eq!(updates.len(), 2);
+        assert_eq!(results[1].status, OperationItemStatus::Succeeded);
+        assert!(results[2..]
+            .iter()
+            .all(|r| r.status == OperationItemStatus::Cancelled));
+        assert!(!operations.cancel(id));
+    }
+}
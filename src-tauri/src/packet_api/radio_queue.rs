@@ -0,0 +1,143 @@
+use std::{sync::Arc, time::Duration};
+
+use log::{debug, warn};
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// Maximum amount of time outgoing sends will wait for the radio to report
+/// free TX queue slots before sending anyway. This keeps a dropped
+/// `QueueStatus` packet from wedging all outgoing traffic.
+pub const DEFAULT_RADIO_QUEUE_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Gate mirroring the free slots reported by the radio's `QueueStatus` packets.
+///
+/// The gate starts open, since older firmware never reports queue status.
+/// It closes when the radio reports zero free slots and opens again once
+/// a later `QueueStatus` packet reports capacity, or when a wait for one
+/// times out.
+#[derive(Clone, Debug)]
+pub struct RadioQueueGate {
+    sender: Arc<watch::Sender<Option<u32>>>,
+    receiver: watch::Receiver<Option<u32>>,
+}
+
+impl RadioQueueGate {
+    pub fn new() -> Self {
+        let (sender, receiver) = watch::channel(None);
+
+        Self {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+
+    /// Updates the number of free TX queue slots last reported by the radio
+    pub fn update(&self, free: u32) {
+        self.sender.send_replace(Some(free));
+    }
+
+    /// Returns whether the radio is known to have room in its TX queue.
+    /// Unknown capacity is treated as open.
+    pub fn is_open(&self) -> bool {
+        !matches!(*self.receiver.borrow(), Some(0))
+    }
+
+    /// Waits until the radio reports free TX queue slots, or until `deadline`.
+    ///
+    /// Returns `true` if capacity became available and `false` if the wait timed out.
+    /// A timed out wait reopens the gate, as the capacity is unknown again, so later
+    /// sends don't each wait out another timeout for a `QueueStatus` that was lost.
+    pub async fn wait_for_capacity(&self, deadline: Instant) -> bool {
+        if self.is_open() {
+            return true;
+        }
+
+        debug!("Radio TX queue full, waiting for capacity");
+
+        let mut receiver = self.receiver.clone();
+        let wait_result = tokio::time::timeout_at(deadline, async move {
+            while receiver.changed().await.is_ok() {
+                if !matches!(*receiver.borrow(), Some(0)) {
+                    return;
+                }
+            }
+        })
+        .await;
+
+        if wait_result.is_err() {
+            warn!("Radio TX queue did not report capacity in time, sending anyway");
+
+            self.sender.send_replace(None);
+            return false;
+        }
+
+        debug!("Radio TX queue has capacity again");
+
+        true
+    }
+}
+
+impl Default for RadioQueueGate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn unknown_capacity_is_open() {
+        let gate = RadioQueueGate::new();
+
+        assert!(gate.is_open());
+        assert!(
+            gate.wait_for_capacity(Instant::now() + Duration::from_millis(10))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn pauses_when_full_and_resumes_when_free() {
+        let gate = RadioQueueGate::new();
+
+        gate.update(0);
+        assert!(!gate.is_open());
+
+        let waiting_gate = gate.clone();
+        let waiter = tokio::spawn(async move {
+            waiting_gate
+                .wait_for_capacity(Instant::now() + Duration::from_secs(5))
+                .await
+        });
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        gate.update(0);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        gate.update(4);
+        assert!(waiter.await.expect("Waiter panicked"));
+        assert!(gate.is_open());
+    }
+
+    #[tokio::test]
+    async fn timed_out_wait_reopens_the_gate() {
+        let gate = RadioQueueGate::new();
+        gate.update(0);
+
+        assert!(
+            !gate
+                .wait_for_capacity(Instant::now() + Duration::from_millis(20))
+                .await
+        );
+        assert!(gate.is_open());
+
+        // Closed again by the next report of a full queue
+        gate.update(0);
+        assert!(!gate.is_open());
+    }
+}
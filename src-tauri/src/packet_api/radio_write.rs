@@ -0,0 +1,306 @@
+use log::trace;
+use meshtastic::api::ConnectedStreamApi;
+use meshtastic::packet::PacketDestination;
+use meshtastic::protobufs;
+use meshtastic::types::{EncodedMeshPacketData, MeshChannel, NodeId};
+use meshtastic::Message;
+use tokio::sync::oneshot;
+
+use crate::device::canned_messages::encode_canned_messages;
+use crate::ipc::events::dispatch_updated_device;
+use crate::ipc::CommandError;
+
+use super::remote_admin::RemoteAdminOutcome;
+use super::MeshPacketApi;
+
+/// A write to a device's radio. Writes are sent by the device's task, so they're
+/// ordered with the packets it handles.
+pub enum RadioWrite {
+    Config(protobufs::Config),
+    ModuleConfig(protobufs::LocalModuleConfig),
+    Channel(protobufs::Channel),
+    User(protobufs::User),
+    StartConfigTransaction,
+    CommitConfigTransaction,
+    Admin {
+        message: protobufs::AdminMessage,
+        destination: PacketDestination,
+        want_response: bool,
+    },
+    Position {
+        position: protobufs::Position,
+        destination: PacketDestination,
+    },
+    Hardware {
+        message: protobufs::HardwareMessage,
+        node_num: u32,
+    }, // reads are sent with `DeviceHandle::read_gpios`, which waits for their reply
+    Waypoint {
+        waypoint: protobufs::Waypoint,
+        channel: u32,
+    },
+    CannedMessages(Vec<String>), // cached on the device once written, since the radio doesn't report them back
+}
+
+impl RadioWrite {
+    /// An admin message to the connected radio itself
+    pub fn local_admin(
+        payload_variant: protobufs::admin_message::PayloadVariant,
+        want_response: bool,
+    ) -> Self {
+        RadioWrite::Admin {
+            message: protobufs::AdminMessage {
+                payload_variant: Some(payload_variant),
+                ..Default::default()
+            },
+            destination: PacketDestination::Local,
+            want_response,
+        }
+    }
+
+    /// Sends the write through `connection`. Writes that change the device's state
+    /// without the radio reporting it back send the updated device to the UI.
+    pub async fn send<R: tauri::Runtime>(
+        self,
+        connection: &mut ConnectedStreamApi,
+        packet_api: &mut MeshPacketApi<R>,
+    ) -> Result<(), CommandError> {
+        let updates_device = matches!(
+            self,
+            RadioWrite::CommitConfigTransaction | RadioWrite::CannedMessages(_)
+        );
+
+        match self {
+            RadioWrite::Config(config) => {
+                packet_api.device.check_config_supported(&config)?;
+
+                connection
+                    .update_config(packet_api, config)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            RadioWrite::ModuleConfig(module_config) => {
+                connection
+                    .set_local_module_config(packet_api, module_config)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            RadioWrite::Channel(channel) => {
+                connection
+                    .update_channel_config(packet_api, channel)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            RadioWrite::User(user) => {
+                connection
+                    .update_user(packet_api, user)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            RadioWrite::StartConfigTransaction => {
+                connection
+                    .start_config_transaction()
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            RadioWrite::CommitConfigTransaction => {
+                connection
+                    .commit_config_transaction()
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            RadioWrite::Admin {
+                message,
+                destination,
+                want_response,
+            } => {
+                send_admin_message(connection, packet_api, message, destination, want_response)
+                    .await?;
+            }
+            RadioWrite::Position {
+                position,
+                destination,
+            } => {
+                send_position(connection, packet_api, position, destination).await?;
+            }
+            RadioWrite::Hardware { message, node_num } => {
+                send_hardware_message(connection, packet_api, message, node_num, false).await?;
+            }
+            RadioWrite::Waypoint { waypoint, channel } => {
+                connection
+                    .send_waypoint(
+                        packet_api,
+                        waypoint,
+                        PacketDestination::Broadcast,
+                        true,
+                        MeshChannel::new(channel).map_err(|e| e.to_string())?,
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            RadioWrite::CannedMessages(messages) => {
+                let encoded_messages = encode_canned_messages(&messages)?;
+
+                send_admin_message(
+                    connection,
+                    packet_api,
+                    protobufs::AdminMessage {
+                        payload_variant: Some(
+                            protobufs::admin_message::PayloadVariant::SetCannedMessageModuleMessages(
+                                encoded_messages,
+                            ),
+                        ),
+                        ..Default::default()
+                    },
+                    PacketDestination::Local,
+                    false,
+                )
+                .await?;
+
+                packet_api.device.set_canned_messages(messages);
+            }
+        }
+
+        if updates_device {
+            dispatch_updated_device(
+                &packet_api.app_handle,
+                &packet_api.device_key,
+                &packet_api.device,
+            )
+            .map_err(|e| e.to_string())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends an admin message to the connected radio, or to a remote node
+/// when `destination` is not `PacketDestination::Local`.
+pub async fn send_admin_message<R: tauri::Runtime>(
+    connection: &mut ConnectedStreamApi,
+    packet_api: &mut MeshPacketApi<R>,
+    admin_message: protobufs::AdminMessage,
+    destination: PacketDestination,
+    want_response: bool,
+) -> Result<(), CommandError> {
+    trace!("Sending admin message {:?}", admin_message);
+
+    connection
+        .send_mesh_packet(
+            packet_api,
+            EncodedMeshPacketData::new(admin_message.encode_to_vec()),
+            protobufs::PortNum::AdminApp,
+            destination,
+            MeshChannel::new(0).map_err(|e| e.to_string())?,
+            true,
+            want_response,
+            false,
+            None,
+            None,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Sends an admin message to another node over the mesh, returning the id of the
+/// request and where its outcome arrives. Echoed so the request can be matched to its
+/// result, which `await_remote_admin` waits for.
+pub async fn send_remote_admin_message<R: tauri::Runtime>(
+    connection: &mut ConnectedStreamApi,
+    packet_api: &mut MeshPacketApi<R>,
+    admin_message: protobufs::AdminMessage,
+    node_num: u32,
+    want_response: bool,
+) -> Result<(u32, oneshot::Receiver<RemoteAdminOutcome>), CommandError> {
+    trace!(
+        "Sending admin message {:?} to node {}",
+        admin_message,
+        node_num
+    );
+
+    connection
+        .send_mesh_packet(
+            packet_api,
+            EncodedMeshPacketData::new(admin_message.encode_to_vec()),
+            protobufs::PortNum::AdminApp,
+            PacketDestination::Node(NodeId::new(node_num)),
+            MeshChannel::new(0).map_err(|e| e.to_string())?,
+            true,
+            want_response,
+            true,
+            None,
+            None,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+    packet_api
+        .remote_admin
+        .take_last_request()
+        .ok_or_else(|| "Radio didn't echo the admin request".into())
+}
+
+/// Sends a remote hardware message to another node. Messages that want a response
+/// are echoed, so their reply can be matched to the request by its id.
+pub async fn send_hardware_message<R: tauri::Runtime>(
+    connection: &mut ConnectedStreamApi,
+    packet_api: &mut MeshPacketApi<R>,
+    message: protobufs::HardwareMessage,
+    node_num: u32,
+    want_response: bool,
+) -> Result<(), CommandError> {
+    trace!(
+        "Sending hardware message {:?} to node {}",
+        message,
+        node_num
+    );
+
+    connection
+        .send_mesh_packet(
+            packet_api,
+            EncodedMeshPacketData::new(message.encode_to_vec()),
+            protobufs::PortNum::RemoteHardwareApp,
+            PacketDestination::Node(NodeId::new(node_num)),
+            MeshChannel::new(0).map_err(|e| e.to_string())?,
+            true,
+            want_response,
+            want_response,
+            None,
+            None,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Sends a position packet. Positions sent to the connected radio itself are used
+/// by the firmware to set its clock and, when enabled, its fixed position.
+pub async fn send_position<R: tauri::Runtime>(
+    connection: &mut ConnectedStreamApi,
+    packet_api: &mut MeshPacketApi<R>,
+    position: protobufs::Position,
+    destination: PacketDestination,
+) -> Result<(), CommandError> {
+    trace!("Sending position {:?}", position);
+
+    connection
+        .send_mesh_packet(
+            packet_api,
+            EncodedMeshPacketData::new(position.encode_to_vec()),
+            protobufs::PortNum::PositionApp,
+            destination,
+            MeshChannel::new(0).map_err(|e| e.to_string())?,
+            false,
+            false,
+            false,
+            None,
+            None,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
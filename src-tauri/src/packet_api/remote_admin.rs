@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use meshtastic::protobufs;
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+/// How long a request to a remote node waits for its result. The request and its
+/// response each cross the mesh hop by hop, and may be retransmitted along the way.
+pub const REMOTE_ADMIN_TIMEOUT: Duration = Duration::from_secs(180);
+
+/// Routing errors of firmware that requires admin messages to be signed or to carry a
+/// session key, newer than the protobufs this app is built against
+const PKI_FAILED: i32 = 34;
+const ADMIN_PUBLIC_KEY_UNAUTHORIZED: i32 = 37;
+
+/// Configuration sections that can be read from a remote node
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum RemoteConfigType {
+    Device,
+    Position,
+    Power,
+    Network,
+    Display,
+    Lora,
+    Bluetooth,
+}
+
+impl RemoteConfigType {
+    pub fn config_type(self) -> protobufs::admin_message::ConfigType {
+        use protobufs::admin_message::ConfigType;
+
+        match self {
+            RemoteConfigType::Device => ConfigType::DeviceConfig,
+            RemoteConfigType::Position => ConfigType::PositionConfig,
+            RemoteConfigType::Power => ConfigType::PowerConfig,
+            RemoteConfigType::Network => ConfigType::NetworkConfig,
+            RemoteConfigType::Display => ConfigType::DisplayConfig,
+            RemoteConfigType::Lora => ConfigType::LoraConfig,
+            RemoteConfigType::Bluetooth => ConfigType::BluetoothConfig,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RemoteAdminKind {
+    Read,  // answered by an admin response
+    Write, // confirmed by the node's ack
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum RemoteAdminOutcome {
+    Config { config: protobufs::Config },
+    Applied,
+    Unauthorized, // the node doesn't accept admin messages from this device
+    Failed { reason: String },
+    TimedOut,
+}
+
+impl RemoteAdminOutcome {
+    /// The outcome a routing result gives a request, if it ends it. The ack of a read
+    /// only means the request arrived, and our own radio acks requests it hears relayed.
+    pub fn from_routing_error(kind: RemoteAdminKind, error: i32, from_node: bool) -> Option<Self> {
+        use protobufs::routing::Error;
+
+        match Error::from_i32(error) {
+            Some(Error::None) if from_node && kind == RemoteAdminKind::Write => {
+                Some(RemoteAdminOutcome::Applied)
+            }
+            Some(Error::None) => None,
+            Some(Error::NotAuthorized) => Some(RemoteAdminOutcome::Unauthorized),
+            Some(Error::NoResponse) => Some(RemoteAdminOutcome::Failed {
+                reason: "Node didn't answer the request".into(),
+            }),
+            Some(Error::Timeout | Error::MaxRetransmit | Error::NoRoute | Error::GotNak) => {
+                Some(RemoteAdminOutcome::Failed {
+                    reason: "Request didn't reach the node".into(),
+                })
+            }
+            Some(e) => Some(RemoteAdminOutcome::Failed {
+                reason: format!("Request failed: {:?}", e),
+            }),
+            None if (PKI_FAILED..=ADMIN_PUBLIC_KEY_UNAUTHORIZED).contains(&error) => {
+                Some(RemoteAdminOutcome::Unauthorized)
+            }
+            None => Some(RemoteAdminOutcome::Failed {
+                reason: format!("Request failed with routing error {}", error),
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PendingRemoteAdmin {
+    node_num: u32,
+    kind: RemoteAdminKind,
+    reply: oneshot::Sender<RemoteAdminOutcome>,
+}
+
+/// Admin requests sent through a device to other nodes that are waiting for their
+/// result, keyed by the packet id of the request. Responses are matched by their
+/// request id and sender, so requests to different nodes never get each other's.
+#[derive(Debug, Default)]
+pub struct RemoteAdminRequests {
+    pending: HashMap<u32, PendingRemoteAdmin>,
+    last_request: Option<(u32, oneshot::Receiver<RemoteAdminOutcome>)>,
+}
+
+impl RemoteAdminRequests {
+    /// Records a request as it's sent, from the copy the radio echoes back
+    pub fn sent(&mut self, request_id: u32, node_num: u32, kind: RemoteAdminKind) {
+        let (reply, outcome) = oneshot::channel();
+
+        self.pending.insert(
+            request_id,
+            PendingRemoteAdmin {
+                node_num,
+                kind,
+                reply,
+            },
+        );
+        self.last_request = Some((request_id, outcome));
+    }
+
+    /// The id of the request sent last and where its outcome arrives, for the command
+    /// that just sent it
+    pub fn take_last_request(&mut self) -> Option<(u32, oneshot::Receiver<RemoteAdminOutcome>)> {
+        self.last_request.take()
+    }
+
+    /// The node a request was sent to and what kind it is, if it's still waiting
+    pub fn get(&self, request_id: u32) -> Option<(u32, RemoteAdminKind)> {
+        self.pending
+            .get(&request_id)
+            .map(|pending| (pending.node_num, pending.kind))
+    }
+
+    /// Ends a request with `outcome`, returning the node it was sent to if it was
+    /// still waiting
+    pub fn finish(&mut self, request_id: u32, outcome: RemoteAdminOutcome) -> Option<u32> {
+        let pending = self.pending.remove(&request_id)?;
+
+        // The command may have given up waiting already
+        let _ = pending.reply.send(outcome);
+
+        Some(pending.node_num)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcomes_reach_the_request_they_belong_to() {
+        let mut requests = RemoteAdminRequests::default();
+
+        requests.sent(100, 0x10, RemoteAdminKind::Read);
+        let (id, mut first) = requests.take_last_request().unwrap();
+        assert_eq!(id, 100);
+
+        requests.sent(101, 0x20, RemoteAdminKind::Write);
+        let (_, mut second) = requests.take_last_request().unwrap();
+        assert!(requests.take_last_request().is_none());
+
+        assert_eq!(requests.get(101), Some((0x20, RemoteAdminKind::Write)));
+        assert_eq!(
+            requests.finish(101, RemoteAdminOutcome::Applied),
+            Some(0x20)
+        );
+        assert_eq!(requests.finish(101, RemoteAdminOutcome::TimedOut), None);
+
+        assert_eq!(second.try_recv(), Ok(RemoteAdminOutcome::Applied));
+        assert!(first.try_recv().is_err());
+        assert_eq!(requests.get(100), Some((0x10, RemoteAdminKind::Read)));
+    }
+
+    #[test]
+    fn routing_results_end_requests_by_kind() {
+        use protobufs::routing::Error;
+
+        let outcome =
+            |kind, error, from_node| RemoteAdminOutcome::from_routing_error(kind, error, from_node);
+
+        assert_eq!(
+            outcome(RemoteAdminKind::Read, Error::None as i32, true),
+            None
+        );
+        assert_eq!(
+            outcome(RemoteAdminKind::Write, Error::None as i32, true),
+            Some(RemoteAdminOutcome::Applied)
+        );
+        assert_eq!(
+            outcome(RemoteAdminKind::Write, Error::None as i32, false),
+            None
+        );
+
+        for error in [Error::NotAuthorized as i32, 36] {
+            assert_eq!(
+                outcome(RemoteAdminKind::Read, error, true),
+                Some(RemoteAdminOutcome::Unauthorized)
+            );
+        }
+
+        assert!(matches!(
+            outcome(RemoteAdminKind::Write, Error::MaxRetransmit as i32, false),
+            Some(RemoteAdminOutcome::Failed { .. })
+        ));
+    }
+}
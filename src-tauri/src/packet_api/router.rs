@@ -1,16 +1,126 @@
-use log::debug;
+use std::time::Duration;
+
+use log::{debug, trace};
 use meshtastic::packet::PacketRouter;
 use meshtastic::protobufs;
 use meshtastic::types::NodeId;
+use tokio::time::Instant;
 
-use crate::ipc::events;
+use crate::connection::metrics::from_radio_label;
+use crate::device::helpers::get_current_time_u32;
+use crate::device::SerialDeviceStatus;
+use crate::graph::{
+    geojson::{generate_graph_nodes_geojson, GraphGeoJson},
+    store::mark_graph_changed,
+};
+use crate::ipc::events::{self, payloads::ConfigurationProgressEvent};
+use crate::ipc::EVENT_API_VERSION;
 
+use super::graph_batch::apply_graph_batch;
 use super::handlers::{
     from_radio::handlers as from_radio_handlers, mesh_packet::handlers as mesh_packet_handlers,
     DeviceUpdateError,
 };
 use super::MeshPacketApi;
 
+/// Default minimum time between two regenerations of a device's graph GeoJSON
+pub const DEFAULT_GRAPH_UPDATE_WINDOW: Duration = Duration::from_millis(500);
+
+/// Stores the device's graph once a packet is handled, if handling it changed the
+/// graph, and publishes it unless it was published within the device's graph update
+/// window. A graph held back by the window is published once the window has passed.
+fn publish_graph_changes<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+) -> Result<(), DeviceUpdateError> {
+    let changes = std::mem::take(&mut packet_api.graph_changes);
+
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    trace!("Packet made {} changes to the graph", changes.len());
+
+    packet_api.queue_rebuild_changes(&changes);
+    mark_graph_changed(packet_api);
+
+    if packet_api
+        .graph_publish
+        .push((), (), Instant::now())
+        .is_none()
+    {
+        trace!("Deferred publishing graph");
+        return Ok(());
+    }
+
+    publish_graph(packet_api)
+}
+
+/// Regenerates and publishes the device's graph. Publishing advances the device's
+/// edge delta sequence, which analytics reports carry as the graph version.
+fn publish_graph<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+) -> Result<(), DeviceUpdateError> {
+    let graph = packet_api
+        .graph_arc
+        .view()
+        .map_err(DeviceUpdateError::GeneralFailure)?;
+
+    let (edges, regenerated) = packet_api
+        .edge_features
+        .generate(&graph, &packet_api.device);
+
+    trace!("Regenerated {} edge features", regenerated);
+
+    let geojson = GraphGeoJson {
+        device_key: packet_api.device_key.clone(),
+        nodes: generate_graph_nodes_geojson(&graph, &packet_api.device),
+        edges,
+    };
+
+    events::dispatch_updated_graph(
+        &packet_api.app_handle,
+        Some(packet_api.device_key.clone()),
+        graph,
+    )
+    .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+
+    events::dispatch_graph_geojson_update(&packet_api.app_handle, geojson)
+        .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+
+    Ok(())
+}
+
+impl<R: tauri::Runtime> MeshPacketApi<R> {
+    /// Applies the node infos waiting in the graph batch, publishing the graph if
+    /// its update window allows
+    pub fn flush_graph_batch(&mut self) -> Result<(), DeviceUpdateError> {
+        let applied = apply_graph_batch(self);
+        publish_graph_changes(self)?;
+        applied
+    }
+
+    /// Applies the node infos waiting in the graph batch and publishes the graph
+    /// right away if it has changed since it was last published
+    pub fn flush_graph_updates(&mut self) -> Result<(), DeviceUpdateError> {
+        let applied = self.flush_graph_batch();
+
+        if !self.graph_publish.take_all(Instant::now()).is_empty() {
+            publish_graph(self)?;
+        }
+
+        applied
+    }
+
+    /// Publishes the graph held back by its update window, if the window has passed
+    pub fn publish_due_graph(&mut self) -> Result<(), DeviceUpdateError> {
+        if self.graph_publish.take_due(Instant::now()).is_empty() {
+            return Ok(());
+        }
+
+        publish_graph(self)
+    }
+}
+
 impl<R: tauri::Runtime> PacketRouter<(), DeviceUpdateError> for MeshPacketApi<R> {
     fn source_node_id(&self) -> NodeId {
         NodeId::new(self.device.my_node_info.my_node_num)
@@ -20,6 +130,10 @@ impl<R: tauri::Runtime> PacketRouter<(), DeviceUpdateError> for MeshPacketApi<R>
         &mut self,
         packet: protobufs::FromRadio,
     ) -> Result<(), DeviceUpdateError> {
+        if let Ok(mut metrics) = self.metrics.lock() {
+            metrics.record_received_packet(from_radio_label(&packet), get_current_time_u32());
+        }
+
         let variant = match packet.payload_variant {
             Some(v) => v,
             None => {
@@ -29,6 +143,28 @@ impl<R: tauri::Runtime> PacketRouter<(), DeviceUpdateError> for MeshPacketApi<R>
             }
         };
 
+        if self.device.status == SerialDeviceStatus::Configuring
+            && self.device.config_progress.observe(&variant)
+        {
+            events::dispatch_configuration_progress(
+                &self.app_handle,
+                ConfigurationProgressEvent::new(
+                    self.device_key.clone(),
+                    self.device.config_progress.clone(),
+                ),
+            )
+            .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
+        }
+
+        // Mesh packets and the end of configuration build on the nodes waiting in the batch
+        if matches!(
+            variant,
+            protobufs::from_radio::PayloadVariant::Packet(_)
+                | protobufs::from_radio::PayloadVariant::ConfigCompleteId(_)
+        ) {
+            self.flush_graph_batch()?;
+        }
+
         match variant {
             protobufs::from_radio::PayloadVariant::Channel(channel) => {
                 from_radio_handlers::handle_channel_packet(self, channel)?;
@@ -39,15 +175,11 @@ impl<R: tauri::Runtime> PacketRouter<(), DeviceUpdateError> for MeshPacketApi<R>
             protobufs::from_radio::PayloadVariant::ConfigCompleteId(_) => {
                 from_radio_handlers::handle_config_complete_packet(self)?;
             }
-            protobufs::from_radio::PayloadVariant::LogRecord(_) => {
-                return Err(DeviceUpdateError::RadioMessageNotSupported(
-                    "log record".into(),
-                ));
+            protobufs::from_radio::PayloadVariant::LogRecord(log_record) => {
+                from_radio_handlers::handle_log_record_packet(self, log_record)?;
             }
-            protobufs::from_radio::PayloadVariant::Metadata(_m) => {
-                return Err(DeviceUpdateError::RadioMessageNotSupported(
-                    "metadata".into(),
-                ));
+            protobufs::from_radio::PayloadVariant::Metadata(metadata) => {
+                from_radio_handlers::handle_metadata_packet(self, metadata)?;
             }
             protobufs::from_radio::PayloadVariant::ModuleConfig(module_config) => {
                 from_radio_handlers::handle_module_config_packet(self, module_config)?;
@@ -56,19 +188,26 @@ impl<R: tauri::Runtime> PacketRouter<(), DeviceUpdateError> for MeshPacketApi<R>
                 from_radio_handlers::handle_my_node_info_packet(self, my_node_info)?;
             }
             protobufs::from_radio::PayloadVariant::NodeInfo(node_info) => {
-                from_radio_handlers::handle_node_info_packet(self, node_info)?;
+                let handled = from_radio_handlers::handle_node_info_packet(self, node_info);
+                let configuring = self.device.status == SerialDeviceStatus::Configuring;
+
+                if self.graph_batch.is_due(configuring, Instant::now()) {
+                    self.flush_graph_batch()?;
+                }
+
+                handled?;
             }
             protobufs::from_radio::PayloadVariant::Packet(mesh_packet) => {
-                self.handle_mesh_packet(mesh_packet)?;
+                let handled = self.handle_mesh_packet(mesh_packet);
+                publish_graph_changes(self)?;
+                handled?;
             }
-            protobufs::from_radio::PayloadVariant::QueueStatus(_) => {
-                return Err(DeviceUpdateError::RadioMessageNotSupported(
-                    "queue status".into(),
-                ));
+            protobufs::from_radio::PayloadVariant::QueueStatus(queue_status) => {
+                from_radio_handlers::handle_queue_status_packet(self, queue_status)?;
             }
             protobufs::from_radio::PayloadVariant::Rebooted(_) => {
                 debug!("Device rebooting");
-                events::dispatch_rebooting_event(&self.app_handle)
+                events::dispatch_rebooting_event(&self.app_handle, self.device_key.clone())
                     .map_err(|e| DeviceUpdateError::EventDispatchFailure(e.to_string()))?;
             }
             protobufs::from_radio::PayloadVariant::XmodemPacket(_) => {
@@ -94,10 +233,32 @@ impl<R: tauri::Runtime> PacketRouter<(), DeviceUpdateError> for MeshPacketApi<R>
             .ok_or("No payload variant")
             .map_err(|e| DeviceUpdateError::GeneralFailure(e.to_string()))?;
 
+        mesh_packet_handlers::handle_packet_clock_skew(self, &packet)?;
+        mesh_packet_handlers::handle_packet_link_quality(self, &packet)?;
+        mesh_packet_handlers::handle_packet_liveness(self, &packet)?;
+
+        // Copies of a packet heard over other paths only contribute rx metadata
+
+        if self
+            .dedup
+            .check_duplicate(packet.from, packet.id, get_current_time_u32())
+        {
+            debug!(
+                "Ignoring duplicate of packet {} from node {}",
+                packet.id, packet.from
+            );
+
+            if let Ok(mut metrics) = self.metrics.lock() {
+                metrics.record_dedup_hit();
+            }
+
+            return Ok(());
+        }
+
         match variant {
             protobufs::mesh_packet::PayloadVariant::Decoded(data) => match data.portnum() {
                 protobufs::PortNum::AdminApp => {
-                    return Err(DeviceUpdateError::PacketNotSupported("admin".into()));
+                    mesh_packet_handlers::handle_admin_mesh_packet(self, packet, data)?;
                 }
                 protobufs::PortNum::AtakForwarder => {
                     return Err(DeviceUpdateError::PacketNotSupported(
@@ -120,12 +281,10 @@ impl<R: tauri::Runtime> PacketRouter<(), DeviceUpdateError> for MeshPacketApi<R>
                     return Err(DeviceUpdateError::PacketNotSupported("admin".into()));
                 }
                 protobufs::PortNum::RangeTestApp => {
-                    return Err(DeviceUpdateError::PacketNotSupported("range test".into()));
+                    mesh_packet_handlers::handle_range_test_mesh_packet(self, packet, data)?;
                 }
                 protobufs::PortNum::RemoteHardwareApp => {
-                    return Err(DeviceUpdateError::PacketNotSupported(
-                        "remote hardware".into(),
-                    ));
+                    mesh_packet_handlers::handle_remote_hardware_mesh_packet(self, packet, data)?;
                 }
                 protobufs::PortNum::ReplyApp => {
                     return Err(DeviceUpdateError::PacketNotSupported("reply".into()));
@@ -165,7 +324,7 @@ impl<R: tauri::Runtime> PacketRouter<(), DeviceUpdateError> for MeshPacketApi<R>
                     mesh_packet_handlers::handle_neighbor_info_mesh_packet(self, packet, data)?;
                 }
                 protobufs::PortNum::TracerouteApp => {
-                    return Err(DeviceUpdateError::PacketNotSupported("traceroute".into()));
+                    mesh_packet_handlers::handle_traceroute_mesh_packet(self, packet, data)?;
                 }
                 protobufs::PortNum::DetectionSensorApp => {
                     return Err(DeviceUpdateError::PacketNotSupported(
@@ -200,3 +359,188 @@ impl<R: tauri::Runtime> PacketRouter<(), DeviceUpdateError> for MeshPacketApi<R>
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use meshtastic::Message;
+    use tauri::Manager;
+
+    use super::*;
+    use crate::device::MeshDevice;
+    use crate::graph::ds::graph::MeshGraph;
+    use crate::graph::edge_delta::EdgeUpdate;
+    use crate::graph::geojson::generate_graph_edges_geojson;
+    use crate::packet_api::summary::ConnectionType;
+    use crate::simulation::scenario::{ScenarioEngine, ScenarioParams};
+    use crate::state::edge_deltas::EdgeDeltasState;
+    use crate::state::graph::SharedGraph;
+
+    const MY_NODE_NUM: u32 = 1;
+    const REMOTE_NODE_NUM: u32 = 2;
+
+    fn position_packet(rx_snr: f32, rx_rssi: i32) -> protobufs::MeshPacket {
+        let position = protobufs::Position {
+            latitude_i: 476_062_000,
+            longitude_i: -1_223_321_000,
+            ..Default::default()
+        };
+
+        protobufs::MeshPacket {
+            from: REMOTE_NODE_NUM,
+            to: MY_NODE_NUM,
+            id: 1234,
+            rx_time: get_current_time_u32(),
+            rx_snr,
+            rx_rssi,
+            hop_limit: 3,
+            payload_variant: Some(protobufs::mesh_packet::PayloadVariant::Decoded(
+                protobufs::Data {
+                    portnum: protobufs::PortNum::PositionApp as i32,
+                    payload: position.encode_to_vec(),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn duplicates_only_contribute_rx_metadata() {
+        let app = tauri::test::mock_app();
+
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = MY_NODE_NUM;
+
+        let graph = Arc::new(SharedGraph::new(MeshGraph::new()));
+        let mut packet_api = MeshPacketApi::new(app.handle(), "mock".into(), device, graph.clone());
+
+        packet_api
+            .handle_mesh_packet(position_packet(5.0, -80))
+            .unwrap();
+        assert_eq!(packet_api.graph_changes.len(), 2); // node heard, and its position
+
+        packet_api
+            .handle_mesh_packet(position_packet(-3.0, -110))
+            .unwrap();
+        assert_eq!(packet_api.graph_changes.len(), 2);
+
+        let remote_node = packet_api.device.nodes.get(&REMOTE_NODE_NUM).unwrap();
+        assert_eq!(remote_node.position_metrics.len(), 1);
+
+        let report = graph.read().unwrap().get_link_quality_report(
+            REMOTE_NODE_NUM,
+            MY_NODE_NUM,
+            0,
+            Some("mock"),
+        );
+
+        assert_eq!(report.samples.len(), 2);
+        assert_eq!(report.samples[1].rssi, Some(-110));
+    }
+
+    #[test]
+    fn only_direct_packets_are_sampled_by_hop_start() {
+        let app = tauri::test::mock_app();
+
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = MY_NODE_NUM;
+
+        let graph = Arc::new(SharedGraph::new(MeshGraph::new()));
+        let mut packet_api = MeshPacketApi::new(app.handle(), "mock".into(), device, graph.clone());
+
+        // Relayed once, though its hop limit is still at our configured limit
+        let mut relayed = position_packet(5.0, -80);
+        relayed.hop_start = 4;
+        packet_api.handle_mesh_packet(relayed).unwrap();
+
+        // Sent with a lower hop limit than ours, and heard directly
+        let mut direct = position_packet(-3.0, -110);
+        direct.id = 1235;
+        direct.hop_start = 2;
+        direct.hop_limit = 2;
+        packet_api.handle_mesh_packet(direct).unwrap();
+
+        let graph = graph.read().unwrap();
+        let report = graph.get_link_quality_report(REMOTE_NODE_NUM, MY_NODE_NUM, 0, Some("mock"));
+
+        assert_eq!(report.samples.len(), 1);
+        assert_eq!(report.samples[0].rssi, Some(-110));
+        assert!(graph
+            .get_link_quality_report(REMOTE_NODE_NUM, MY_NODE_NUM, 0, Some("other"))
+            .samples
+            .is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_of_packets_publishes_the_final_graph_a_few_times() {
+        let app = tauri::test::mock_app();
+        app.manage(EdgeDeltasState::new());
+
+        let mut packet_api = MeshPacketApi::new(
+            app.handle(),
+            "burst".into(),
+            MeshDevice::new(),
+            Arc::new(SharedGraph::new(MeshGraph::new())),
+        );
+        packet_api.connection_type = ConnectionType::Simulated;
+
+        let mut engine = ScenarioEngine::new(ScenarioParams {
+            seed: 3,
+            node_count: 8,
+            link_flap_rate: 0.3,
+            ..Default::default()
+        });
+
+        let start = 1_700_000_000;
+        let mut packets = engine.initial_packets(start);
+
+        for tick in 1..=20 {
+            packets.extend(engine.tick(start + tick * 5));
+        }
+
+        let packet_count = packets.len();
+
+        // The packets of 20 scenario ticks arrive within one update window
+        for packet in packets {
+            let _ = packet_api.handle_packet_from_radio(packet);
+        }
+        packet_api.flush_graph_batch().unwrap();
+
+        let published = |app: &tauri::App<tauri::test::MockRuntime>| {
+            app.state::<EdgeDeltasState>().inner.lock().unwrap()["burst"].sequence()
+        };
+
+        assert!(packet_count > 20);
+        assert_eq!(published(&app), 1);
+        assert!(packet_api.graph_publish.next_deadline().is_some());
+
+        packet_api.publish_due_graph().unwrap();
+        assert_eq!(published(&app), 1);
+
+        tokio::time::advance(DEFAULT_GRAPH_UPDATE_WINDOW).await;
+        packet_api.publish_due_graph().unwrap();
+        assert_eq!(published(&app), 2);
+        assert_eq!(packet_api.graph_publish.next_deadline(), None);
+
+        // Nothing changed since the last published edges
+        let graph = packet_api.graph_arc.view().unwrap();
+        let edges = generate_graph_edges_geojson(&graph, &packet_api.device);
+        assert!(!edges.features.is_empty());
+
+        let update = app
+            .state::<EdgeDeltasState>()
+            .inner
+            .lock()
+            .unwrap()
+            .get_mut("burst")
+            .unwrap()
+            .next(&edges);
+
+        match update {
+            EdgeUpdate::Delta { delta, .. } => assert_eq!(delta.feature_count(), 0),
+            EdgeUpdate::Snapshot { .. } => panic!("expected an empty delta"),
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+
+use crate::device::helpers::get_current_time_u32;
+use crate::device::{RadioQueueStatus, SerialDeviceStatus};
+use crate::state::DeviceKey;
+
+use super::MeshPacketApi;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum ConnectionType {
+    #[default]
+    Serial,
+    Tcp,
+    Simulated,
+    Replay,
+    Snapshot, // read-only view of a graph snapshot file
+    Mqtt,     // packets mirrored to a broker by MQTT gateways
+}
+
+/// Small, owned summary of a connected device, published by the device's task
+/// so it can be read without waiting on the device
+#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectedDeviceSummary {
+    pub device_key: DeviceKey, // serial port name or TCP address
+    pub connection_type: ConnectionType,
+    pub status: SerialDeviceStatus,
+    pub node_num: Option<u32>, // unknown until the radio reports its node info
+    pub node_id: Option<String>,
+    pub long_name: Option<String>,
+    pub short_name: Option<String>,
+    pub firmware_version: Option<String>,
+    pub last_packet_received: u32, // secs
+    pub queue_status: Option<RadioQueueStatus>,
+    pub packets_received: u32,
+    pub packets_sent: u32,
+    pub decode_failures: u32,
+}
+
+impl<R: tauri::Runtime> MeshPacketApi<R> {
+    pub fn summary(&self) -> ConnectedDeviceSummary {
+        let device = &self.device;
+        let my_node_num = device.my_node_info.my_node_num;
+
+        let user = device
+            .nodes
+            .get(&my_node_num)
+            .and_then(|node| node.user.as_ref());
+
+        let (packets_received, packets_sent, decode_failures) = match self.metrics.lock() {
+            Ok(mut metrics) => {
+                let snapshot = metrics.snapshot(device.queue_depth(), get_current_time_u32());
+
+                (
+                    snapshot.packets_received,
+                    snapshot.packets_sent,
+                    snapshot.decode_failures,
+                )
+            }
+            Err(_) => (0, 0, 0),
+        };
+
+        ConnectedDeviceSummary {
+            device_key: self.device_key.clone(),
+            connection_type: self.connection_type,
+            status: device.status.clone(),
+            node_num: Some(my_node_num).filter(|num| *num != 0),
+            node_id: user.map(|user| user.id.clone()),
+            long_name: user.map(|user| user.long_name.clone()),
+            short_name: user.map(|user| user.short_name.clone()),
+            firmware_version: device
+                .metadata
+                .as_ref()
+                .map(|metadata| metadata.firmware_version.clone()),
+            last_packet_received: self.last_packet_received,
+            queue_status: device.queue_status.clone(),
+            packets_received,
+            packets_sent,
+            decode_failures,
+        }
+    }
+}
+
+/// Summaries of every connected device, ordered by device key
+pub fn summarize_devices(
+    summaries: impl IntoIterator<Item = ConnectedDeviceSummary>,
+) -> Vec<ConnectedDeviceSummary> {
+    let mut summaries = summaries.into_iter().collect::<Vec<_>>();
+
+    summaries.sort_by(|a, b| a.device_key.cmp(&b.device_key));
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use meshtastic::protobufs;
+
+    use crate::device::MeshDevice;
+    use crate::graph::ds::graph::MeshGraph;
+    use crate::state::graph::SharedGraph;
+
+    use super::*;
+
+    fn packet_api<R: tauri::Runtime>(
+        handle: tauri::AppHandle<R>,
+        device_key: &str,
+        connection_type: ConnectionType,
+        status: SerialDeviceStatus,
+    ) -> MeshPacketApi<R> {
+        let mut device = MeshDevice::new();
+        device.set_status(status);
+
+        let mut packet_api = MeshPacketApi::new(
+            handle,
+            device_key.into(),
+            device,
+            Arc::new(SharedGraph::new(MeshGraph::new())),
+        );
+        packet_api.connection_type = connection_type;
+
+        packet_api
+    }
+
+    #[test]
+    fn summarizes_devices_in_different_states() {
+        let app = tauri::test::mock_app();
+        let mut devices = HashMap::new();
+
+        // Still configuring, nothing known about the radio yet
+        devices.insert(
+            "COM4".to_string(),
+            packet_api(
+                app.handle(),
+                "COM4",
+                ConnectionType::Serial,
+                SerialDeviceStatus::Configuring,
+            ),
+        );
+
+        // Fully configured, with node info and metadata
+        let mut connected = packet_api(
+            app.handle(),
+            "192.168.1.20:4403",
+            ConnectionType::Tcp,
+            SerialDeviceStatus::Connected,
+        );
+
+        connected.device.my_node_info.my_node_num = 0x1234;
+        connected.device.add_node_info(protobufs::NodeInfo {
+            num: 0x1234,
+            user: Some(protobufs::User {
+                id: "!00001234".into(),
+                long_name: "Basecamp".into(),
+                short_name: "BC".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        connected.device.set_metadata(protobufs::DeviceMetadata {
+            firmware_version: "2.2.17".into(),
+            ..Default::default()
+        });
+        connected.device.set_queue_status(protobufs::QueueStatus {
+            free: 12,
+            maxlen: 16,
+            ..Default::default()
+        });
+        connected.metrics.lock().unwrap().record_decode_failure();
+
+        devices.insert("192.168.1.20:4403".to_string(), connected);
+
+        devices.insert(
+            "simulation".to_string(),
+            packet_api(
+                app.handle(),
+                "simulation",
+                ConnectionType::Simulated,
+                SerialDeviceStatus::Disconnected,
+            ),
+        );
+
+        let summaries = summarize_devices(devices.values().map(|device| device.summary()));
+
+        assert_eq!(
+            summaries
+                .iter()
+                .map(|s| s.device_key.as_str())
+                .collect::<Vec<_>>(),
+            vec!["192.168.1.20:4403", "COM4", "simulation"]
+        );
+
+        let connected = &summaries[0];
+        assert_eq!(connected.connection_type, ConnectionType::Tcp);
+        assert_eq!(connected.status, SerialDeviceStatus::Connected);
+        assert_eq!(connected.node_num, Some(0x1234));
+        assert_eq!(connected.node_id.as_deref(), Some("!00001234"));
+        assert_eq!(connected.long_name.as_deref(), Some("Basecamp"));
+        assert_eq!(connected.short_name.as_deref(), Some("BC"));
+        assert_eq!(connected.firmware_version.as_deref(), Some("2.2.17"));
+        assert_eq!(connected.queue_status.as_ref().map(|q| q.free), Some(12));
+        assert_eq!(connected.decode_failures, 1);
+
+        let configuring = &summaries[1];
+        assert_eq!(configuring.connection_type, ConnectionType::Serial);
+        assert_eq!(configuring.status, SerialDeviceStatus::Configuring);
+        assert_eq!(configuring.node_num, None);
+        assert_eq!(configuring.long_name, None);
+        assert_eq!(configuring.firmware_version, None);
+        assert!(configuring.queue_status.is_none());
+
+        let simulated = &summaries[2];
+        assert_eq!(simulated.connection_type, ConnectionType::Simulated);
+        assert_eq!(simulated.status, SerialDeviceStatus::Disconnected);
+    }
+}
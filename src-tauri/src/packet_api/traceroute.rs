@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// How long a traceroute waits for its response. Each hop is a round trip over the
+/// mesh, and the firmware holds back traceroutes sent in quick succession.
+pub const TRACEROUTE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PendingTraceroute {
+    pub destination: u32,
+    pub sent_at: u32, // secs
+}
+
+/// Traceroutes sent through a device that are waiting for their response, keyed by
+/// the packet id of the request, which the response carries as its request id
+#[derive(Debug, Default)]
+pub struct TracerouteTracker {
+    pending: HashMap<u32, PendingTraceroute>,
+    last_request: Option<u32>,
+}
+
+impl TracerouteTracker {
+    /// Records a request as it's sent, from the copy the radio echoes back
+    pub fn sent(&mut self, request_id: u32, destination: u32, now: u32) {
+        self.pending.insert(
+            request_id,
+            PendingTraceroute {
+                destination,
+                sent_at: now,
+            },
+        );
+        self.last_request = Some(request_id);
+    }
+
+    /// The id of the request sent last, for the command that just sent it. Taken, so
+    /// a request the radio didn't echo isn't mistaken for the one before it.
+    pub fn take_last_request(&mut self) -> Option<u32> {
+        self.last_request.take()
+    }
+
+    /// Stops waiting for a request's response, returning the request if it was still
+    /// waiting. A response that arrives after its request timed out is ignored.
+    pub fn finish(&mut self, request_id: u32) -> Option<PendingTraceroute> {
+        self.pending.remove(&request_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_are_matched_to_their_request() {
+        let mut tracker = TracerouteTracker::default();
+
+        tracker.sent(100, 0x10, 1_000);
+        tracker.sent(101, 0x20, 1_001);
+        assert_eq!(tracker.take_last_request(), Some(101));
+        assert_eq!(tracker.take_last_request(), None);
+
+        assert_eq!(
+            tracker.finish(101),
+            Some(PendingTraceroute {
+                destination: 0x20,
+                sent_at: 1_001
+            })
+        );
+        assert_eq!(tracker.finish(101), None);
+        assert_eq!(tracker.finish(102), None);
+
+        assert_eq!(tracker.finish(100).map(|p| p.destination), Some(0x10));
+    }
+}
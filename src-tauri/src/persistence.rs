@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+
+use log::trace;
+use serde::{de::DeserializeOwned, Serialize};
+use tauri::Manager;
+
+use crate::profiles::profile_dir;
+use crate::state;
+
+pub const SETTINGS_FILE_NAME: &str = "settings.json";
+pub const GRAPH_OVERRIDES_FILE_NAME: &str = "graph_overrides.json";
+pub const WEBHOOKS_FILE_NAME: &str = "webhooks.json";
+pub const NOTIFICATION_PREFERENCES_FILE_NAME: &str = "notification_preferences.json";
+pub const PACKET_SCRIPTS_FILE_NAME: &str = "packet_scripts.json";
+pub const DEVELOPER_MODE_FILE_NAME: &str = "developer_mode.json";
+pub const GEOFENCES_FILE_NAME: &str = "geofences.json";
+pub const RECENT_DEVICES_FILE_NAME: &str = "recent_devices.json";
+pub const SECRETS_KEY_FILE_NAME: &str = "secrets_key.json";
+pub const GRAPH_DATABASE_FILE_NAME: &str = "mesh_graph.sqlite3";
+pub const MESSAGE_DATABASE_FILE_NAME: &str = "messages.sqlite3";
+pub const TELEMETRY_DATABASE_FILE_NAME: &str = "telemetry.sqlite3";
+pub const GRAPH_AUTOSAVE_DIR_NAME: &str = "autosave";
+pub const BACKUP_DIR_NAME: &str = "backups";
+pub const DEVICE_CONFIG_CACHE_DIR_NAME: &str = "device_configs";
+
+/// Kept in the app data directory rather than a profile's directory
+pub const PROFILES_FILE_NAME: &str = "profiles.json";
+pub const PROFILES_DIR_NAME: &str = "profiles";
+
+pub fn app_data_dir<R: tauri::Runtime>(handle: &tauri::AppHandle<R>) -> Result<PathBuf, String> {
+    handle
+        .path_resolver()
+        .app_data_dir()
+        .ok_or_else(|| "Could not resolve app data directory".into())
+}
+
+/// Path of a file in the active profile's directory
+pub fn settings_file_path<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    file_name: &str,
+) -> Result<PathBuf, String> {
+    let profiles_state = match handle.try_state::<state::profiles::ProfilesState>() {
+        Some(profiles_state) => profiles_state,
+        None => return Ok(app_data_dir(handle)?.join(file_name)),
+    };
+
+    let profiles = profiles_state.inner.lock().map_err(|e| e.to_string())?;
+
+    Ok(profile_dir(&profiles_state.data_dir, &profiles.active).join(file_name))
+}
+
+/// Reads a JSON settings file from the active profile, returning `None` if it
+/// hasn't been saved yet
+pub fn load_json<T: DeserializeOwned, R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    file_name: &str,
+) -> Result<Option<T>, String> {
+    let path = settings_file_path(handle, file_name)?;
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    trace!("Loading settings from {:?}", path);
+
+    let contents =
+        std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+
+    serde_json::from_str(&contents)
+        .map(Some)
+        .map_err(|e| format!("Failed to parse {:?}: {}", path, e))
+}
+
+/// Writes a JSON settings file to the active profile, creating it if needed
+pub fn save_json<T: Serialize, R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    file_name: &str,
+    value: &T,
+) -> Result<(), String> {
+    let path = settings_file_path(handle, file_name)?;
+
+    trace!("Saving settings to {:?}", path);
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {:?}: {}", parent, e))?;
+    }
+
+    let contents = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+
+    std::fs::write(&path, contents).map_err(|e| format!("Failed to write {:?}: {}", path, e))
+}
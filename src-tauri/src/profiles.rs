@@ -0,0 +1,652 @@
+//! Named profiles, for keeping unrelated meshes apart. Each profile has its own
+//! settings files and databases. The default profile's stay directly in the app data
+//! directory, where they were before profiles existed, and every other profile's are
+//! kept in a directory of its own under `profiles/`.
+
+use std::path::{Path, PathBuf};
+
+use log::{debug, info, warn};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::Manager;
+
+use crate::device::config_cache::{device_config_cache_dir, read_cached_configs};
+use crate::device::message_store::{MessageStore, OutgoingQueue};
+use crate::device::telemetry_store::TelemetryStore;
+use crate::graph::store::{load_position_archive, write_changed_graphs, GraphStore};
+use crate::ipc::error_reporter::{AppErrorCode, ErrorReporter};
+use crate::ipc::events;
+use crate::ipc::helpers::disconnect_all_devices;
+use crate::ipc::reset::reset_analytics_state;
+use crate::migrations::MigrationError;
+use crate::notifications::geofences::Geofences;
+use crate::persistence::{
+    load_json, settings_file_path, DEVELOPER_MODE_FILE_NAME, GEOFENCES_FILE_NAME,
+    GRAPH_DATABASE_FILE_NAME, GRAPH_OVERRIDES_FILE_NAME, MESSAGE_DATABASE_FILE_NAME,
+    NOTIFICATION_PREFERENCES_FILE_NAME, PACKET_SCRIPTS_FILE_NAME, PROFILES_DIR_NAME,
+    PROFILES_FILE_NAME, RECENT_DEVICES_FILE_NAME, SETTINGS_FILE_NAME, TELEMETRY_DATABASE_FILE_NAME,
+    WEBHOOKS_FILE_NAME,
+};
+use crate::secrets::delete_profile_key;
+use crate::settings::{apply_settings, AppSettings};
+use crate::shutdown::flush_history;
+use crate::state;
+
+pub const DEFAULT_PROFILE_NAME: &str = "Default";
+pub const MAX_PROFILE_NAME_LEN: usize = 40;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub name: String,
+    pub created_at: u32, // secs
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct Profiles {
+    pub active: String,
+    pub profiles: Vec<Profile>, // in creation order, starting with the default profile
+}
+
+impl Default for Profiles {
+    fn default() -> Self {
+        Self {
+            active: DEFAULT_PROFILE_NAME.into(),
+            profiles: vec![Profile {
+                name: DEFAULT_PROFILE_NAME.into(),
+                created_at: 0,
+            }],
+        }
+    }
+}
+
+impl Profiles {
+    pub fn contains(&self, name: &str) -> bool {
+        self.profiles.iter().any(|profile| profile.name == name)
+    }
+
+    pub fn create(&mut self, name: &str, created_at: u32) -> Result<Profile, String> {
+        let name = name.trim();
+
+        if name.is_empty() || name.chars().count() > MAX_PROFILE_NAME_LEN {
+            return Err(format!(
+                "Profile names must be between 1 and {} characters",
+                MAX_PROFILE_NAME_LEN
+            ));
+        }
+
+        if !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == ' ' || c == '-' || c == '_')
+        {
+            return Err(
+                "Profile names can only contain letters, numbers, spaces, dashes and underscores"
+                    .into(),
+            );
+        }
+
+        // Names that would share a directory are treated as the same name
+        let dir_name = profile_dir_name(name);
+
+        if self
+            .profiles
+            .iter()
+            .any(|profile| profile_dir_name(&profile.name) == dir_name)
+        {
+            return Err(format!("A profile named \"{}\" already exists", name));
+        }
+
+        let profile = Profile {
+            name: name.into(),
+            created_at,
+        };
+
+        self.profiles.push(profile.clone());
+
+        Ok(profile)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<Profile, String> {
+        if name == DEFAULT_PROFILE_NAME {
+            return Err("The default profile can't be deleted".into());
+        }
+
+        if name == self.active {
+            return Err("Switch to another profile before deleting this one".into());
+        }
+
+        let index = self
+            .profiles
+            .iter()
+            .position(|profile| profile.name == name)
+            .ok_or_else(|| format!("No profile named \"{}\"", name))?;
+
+        Ok(self.profiles.remove(index))
+    }
+
+    pub fn set_active(&mut self, name: &str) -> Result<(), String> {
+        if !self.contains(name) {
+            return Err(format!("No profile named \"{}\"", name));
+        }
+
+        self.active = name.into();
+
+        Ok(())
+    }
+}
+
+/// Lowercase name with every character other than letters and numbers replaced by `-`
+pub fn profile_dir_name(name: &str) -> String {
+    name.trim()
+        .to_ascii_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect()
+}
+
+pub fn profile_dir(data_dir: &Path, name: &str) -> PathBuf {
+    match name {
+        DEFAULT_PROFILE_NAME => data_dir.to_path_buf(),
+        name => data_dir
+            .join(PROFILES_DIR_NAME)
+            .join(profile_dir_name(name)),
+    }
+}
+
+/// Reads the profiles list, falling back to only the default profile if it's missing
+/// or unreadable
+pub fn load_profiles(data_dir: &Path) -> Profiles {
+    let path = data_dir.join(PROFILES_FILE_NAME);
+
+    if !path.exists() {
+        return Profiles::default();
+    }
+
+    let profiles = std::fs::read(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| {
+            serde_json::from_slice::<Profiles>(&contents).map_err(|e| e.to_string())
+        });
+
+    match profiles {
+        Ok(mut profiles) => {
+            if !profiles.contains(DEFAULT_PROFILE_NAME) {
+                profiles
+                    .profiles
+                    .insert(0, Profiles::default().profiles[0].clone());
+            }
+
+            if !profiles.contains(&profiles.active) {
+                warn!("Active profile \"{}\" doesn't exist", profiles.active);
+                profiles.active = DEFAULT_PROFILE_NAME.into();
+            }
+
+            profiles
+        }
+        Err(e) => {
+            warn!("Failed to read profiles from {:?}: {}", path, e);
+            Profiles::default()
+        }
+    }
+}
+
+fn save_profiles(data_dir: &Path, profiles: &Profiles) -> Result<(), String> {
+    std::fs::create_dir_all(data_dir)
+        .map_err(|e| format!("Failed to create {:?}: {}", data_dir, e))?;
+
+    let path = data_dir.join(PROFILES_FILE_NAME);
+    let contents = serde_json::to_string_pretty(profiles).map_err(|e| e.to_string())?;
+
+    std::fs::write(&path, contents).map_err(|e| format!("Failed to write {:?}: {}", path, e))
+}
+
+pub fn active_profile_name<R: tauri::Runtime>(handle: &tauri::AppHandle<R>) -> String {
+    handle
+        .try_state::<state::profiles::ProfilesState>()
+        .and_then(|profiles_state| {
+            let profiles = profiles_state.inner.lock().ok()?;
+            Some(profiles.active.clone())
+        })
+        .unwrap_or_else(|| DEFAULT_PROFILE_NAME.into())
+}
+
+pub fn create_profile<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    name: &str,
+    created_at: u32,
+) -> Result<Profiles, String> {
+    let profiles_state = handle.state::<state::profiles::ProfilesState>();
+
+    let profiles = {
+        let mut profiles = profiles_state.inner.lock().map_err(|e| e.to_string())?;
+        let mut updated = profiles.clone();
+        let profile = updated.create(name, created_at)?;
+
+        let dir = profile_dir(&profiles_state.data_dir, &profile.name);
+        std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+
+        save_profiles(&profiles_state.data_dir, &updated)?;
+        *profiles = updated.clone();
+
+        updated
+    };
+
+    info!("Created profile \"{}\"", name.trim());
+    events::dispatch_profile_changed(handle, profiles.clone()).map_err(|e| e.to_string())?;
+
+    Ok(profiles)
+}
+
+/// Deletes a profile along with its settings files, databases and stored secrets
+pub fn delete_profile<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    name: &str,
+) -> Result<Profiles, String> {
+    let profiles_state = handle.state::<state::profiles::ProfilesState>();
+
+    let profiles = {
+        let mut profiles = profiles_state.inner.lock().map_err(|e| e.to_string())?;
+        let mut updated = profiles.clone();
+        updated.remove(name)?;
+
+        save_profiles(&profiles_state.data_dir, &updated)?;
+        *profiles = updated.clone();
+
+        updated
+    };
+
+    let dir = profile_dir(&profiles_state.data_dir, name);
+    delete_profile_key(name, &dir);
+
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).map_err(|e| format!("Failed to remove {:?}: {}", dir, e))?;
+    }
+
+    info!("Deleted profile \"{}\"", name);
+    events::dispatch_profile_changed(handle, profiles.clone()).map_err(|e| e.to_string())?;
+
+    Ok(profiles)
+}
+
+fn open_store<R: tauri::Runtime, T>(
+    handle: &tauri::AppHandle<R>,
+    file_name: &str,
+    open: fn(&Path) -> Result<T, MigrationError>,
+    database_errors: &mut Vec<MigrationError>,
+) -> Option<T> {
+    let store = settings_file_path(handle, file_name)
+        .map_err(MigrationError::from)
+        .and_then(|path| open(&path));
+
+    match store {
+        Ok(store) => Some(store),
+        Err(e) => {
+            warn!("Failed to open {}: {}", file_name, e);
+            database_errors.push(e);
+            None
+        }
+    }
+}
+
+fn load_or_default<R: tauri::Runtime, T: serde::de::DeserializeOwned + Default>(
+    handle: &tauri::AppHandle<R>,
+    file_name: &str,
+) -> T {
+    match load_json(handle, file_name) {
+        Ok(value) => value.unwrap_or_default(),
+        Err(e) => {
+            warn!("Failed to load {}, using defaults: {}", file_name, e);
+            T::default()
+        }
+    }
+}
+
+/// Replaces the app's persisted state with the active profile's, returning the errors
+/// from opening its databases. Only managed state is loaded.
+pub fn load_profile_state<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+) -> Result<Vec<MigrationError>, String> {
+    debug!(
+        "Loading state of profile \"{}\"",
+        active_profile_name(handle)
+    );
+
+    let mut database_errors = vec![];
+
+    let stored_settings = load_json::<Value, _>(handle, SETTINGS_FILE_NAME)
+        .and_then(|stored| stored.map(AppSettings::from_stored).transpose());
+
+    let settings = match stored_settings {
+        Ok(settings) => settings.unwrap_or_default(),
+        Err(e) => {
+            warn!("Failed to load settings, using defaults: {}", e);
+            AppSettings::default()
+        }
+    };
+
+    if let Some(settings_state) = handle.try_state::<state::settings::SettingsState>() {
+        *settings_state.inner.lock().map_err(|e| e.to_string())? = settings.clone();
+    }
+
+    if let Some(graph_state) = handle.try_state::<state::graph::GraphState>() {
+        let mut graph = graph_state.inner.write()?;
+        graph.purge();
+        graph.set_overrides(load_or_default(handle, GRAPH_OVERRIDES_FILE_NAME));
+    }
+
+    if let Some(store_state) = handle.try_state::<state::graph_store::GraphStoreState>() {
+        let store = open_store(
+            handle,
+            GRAPH_DATABASE_FILE_NAME,
+            GraphStore::open,
+            &mut database_errors,
+        );
+
+        *store_state.inner.lock().map_err(|e| e.to_string())? = store;
+        store_state
+            .changed
+            .lock()
+            .map_err(|e| e.to_string())?
+            .clear();
+    }
+
+    load_position_archive(handle)?;
+
+    if let Some(store_state) = handle.try_state::<state::message_store::MessageStoreState>() {
+        let store = open_store(
+            handle,
+            MESSAGE_DATABASE_FILE_NAME,
+            MessageStore::open,
+            &mut database_errors,
+        );
+
+        *store_state.inner.lock().map_err(|e| e.to_string())? = store;
+        store_state
+            .pending
+            .lock()
+            .map_err(|e| e.to_string())?
+            .clear();
+        *store_state.outgoing.lock().map_err(|e| e.to_string())? = OutgoingQueue::default();
+    }
+
+    if let Some(store_state) = handle.try_state::<state::telemetry_store::TelemetryStoreState>() {
+        let store = open_store(
+            handle,
+            TELEMETRY_DATABASE_FILE_NAME,
+            TelemetryStore::open,
+            &mut database_errors,
+        );
+
+        *store_state.inner.lock().map_err(|e| e.to_string())? = store;
+        store_state
+            .pending
+            .lock()
+            .map_err(|e| e.to_string())?
+            .clear();
+    }
+
+    if let Some(autosave_state) = handle.try_state::<state::graph_autosave::GraphAutosaveState>() {
+        autosave_state
+            .recovered
+            .lock()
+            .map_err(|e| e.to_string())?
+            .clear();
+    }
+
+    if let Some(configs_state) = handle.try_state::<state::device_configs::DeviceConfigsState>() {
+        let configs = device_config_cache_dir(handle)
+            .and_then(|dir| read_cached_configs(&dir))
+            .unwrap_or_else(|e| {
+                warn!("Failed to load cached device configs: {}", e);
+                Default::default()
+            });
+
+        *configs_state.inner.lock().map_err(|e| e.to_string())? = configs;
+    }
+
+    // Each profile has its own key, loaded once a secret is needed
+    if let Some(secrets_state) = handle.try_state::<state::secrets::SecretsState>() {
+        *secrets_state.inner.lock().map_err(|e| e.to_string())? = None;
+    }
+
+    if let Some(webhooks_state) = handle.try_state::<state::webhooks::WebhooksState>() {
+        *webhooks_state.inner.lock().map_err(|e| e.to_string())? =
+            load_or_default(handle, WEBHOOKS_FILE_NAME);
+    }
+
+    if let Some(preferences_state) =
+        handle.try_state::<state::notification_preferences::NotificationPreferencesState>()
+    {
+        *preferences_state.inner.lock().map_err(|e| e.to_string())? =
+            load_or_default(handle, NOTIFICATION_PREFERENCES_FILE_NAME);
+    }
+
+    if let Some(geofences_state) = handle.try_state::<state::geofences::GeofencesState>() {
+        *geofences_state.inner.lock().map_err(|e| e.to_string())? =
+            Geofences::new(load_or_default(handle, GEOFENCES_FILE_NAME));
+    }
+
+    if let Some(recent_state) = handle.try_state::<state::recent_devices::RecentDevicesState>() {
+        *recent_state.inner.lock().map_err(|e| e.to_string())? =
+            load_or_default(handle, RECENT_DEVICES_FILE_NAME);
+    }
+
+    if let Some(developer_mode_state) =
+        handle.try_state::<state::developer_mode::DeveloperModeState>()
+    {
+        *developer_mode_state
+            .inner
+            .lock()
+            .map_err(|e| e.to_string())? = load_or_default(handle, DEVELOPER_MODE_FILE_NAME);
+    }
+
+    if let Some(scripts_state) = handle.try_state::<state::packet_scripts::PacketScriptsState>() {
+        *scripts_state.inner.lock().map_err(|e| e.to_string())? =
+            load_or_default(handle, PACKET_SCRIPTS_FILE_NAME);
+    }
+
+    apply_settings(handle, &settings);
+
+    Ok(database_errors)
+}
+
+pub fn report_database_errors<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    database_errors: Vec<MigrationError>,
+) {
+    let error_reporter = ErrorReporter::new(handle, "persistence");
+
+    for e in database_errors {
+        if let MigrationError::NewerThanApp { .. } = e {
+            error_reporter.error(AppErrorCode::DatabaseNewerThanApp, e.to_string());
+        }
+    }
+}
+
+/// Makes `name` the active profile and loads its state in place of the current
+/// profile's. Connections have to be closed first, see `switch_profile`.
+pub fn activate_profile<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    name: &str,
+) -> Result<Profiles, String> {
+    let profiles_state = handle.state::<state::profiles::ProfilesState>();
+
+    let profiles = {
+        let mut profiles = profiles_state.inner.lock().map_err(|e| e.to_string())?;
+        let mut updated = profiles.clone();
+        updated.set_active(name)?;
+
+        save_profiles(&profiles_state.data_dir, &updated)?;
+        *profiles = updated.clone();
+
+        updated
+    };
+
+    let database_errors = load_profile_state(handle)?;
+    report_database_errors(handle, database_errors);
+
+    // Sends the UI the new profile's graph, without the previous profile's analytics
+    if let Some(graph_state) = handle.try_state::<state::graph::GraphState>() {
+        reset_analytics_state(handle, &graph_state.inner, std::iter::empty())?;
+    }
+
+    if let Some(settings_state) = handle.try_state::<state::settings::SettingsState>() {
+        let settings = settings_state
+            .inner
+            .lock()
+            .map_err(|e| e.to_string())?
+            .clone();
+        events::dispatch_settings_changed(handle, settings).map_err(|e| e.to_string())?;
+    }
+
+    events::dispatch_profile_changed(handle, profiles.clone()).map_err(|e| e.to_string())?;
+
+    Ok(profiles)
+}
+
+/// Switches to another profile. Queued database writes are flushed to the current
+/// profile and every device is disconnected before the other profile is loaded.
+pub async fn switch_profile(handle: &tauri::AppHandle, name: &str) -> Result<Profiles, String> {
+    {
+        let profiles_state = handle.state::<state::profiles::ProfilesState>();
+        let profiles = profiles_state.inner.lock().map_err(|e| e.to_string())?;
+
+        if !profiles.contains(name) {
+            return Err(format!("No profile named \"{}\"", name));
+        }
+
+        if profiles.active == name {
+            return Ok(profiles.clone());
+        }
+
+        info!(
+            "Switching from profile \"{}\" to \"{}\"",
+            profiles.active, name
+        );
+    }
+
+    if let Err(e) = write_changed_graphs(handle).await {
+        warn!("Failed to write graph database before switching: {}", e);
+    }
+
+    if let Err(e) = flush_history(handle).await {
+        warn!("Failed to write queued history before switching: {}", e);
+    }
+
+    disconnect_all_devices(handle).await?;
+
+    let handle = handle.clone();
+    let name = name.to_string();
+
+    tauri::async_runtime::spawn_blocking(move || activate_profile(&handle, &name))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::persistence::save_json;
+
+    use super::*;
+
+    #[test]
+    fn validates_profile_names() {
+        let mut profiles = Profiles::default();
+
+        assert!(profiles.create("Home", 1).is_ok());
+        assert!(profiles.create(" SAR Team ", 2).is_ok());
+        assert_eq!(profiles.profiles[2].name, "SAR Team");
+
+        assert!(profiles.create("", 3).is_err());
+        assert!(profiles.create("../etc", 3).is_err());
+        assert!(profiles
+            .create(&"x".repeat(MAX_PROFILE_NAME_LEN + 1), 3)
+            .is_err());
+
+        // Names that would share a directory are taken
+        assert!(profiles.create("home", 3).is_err());
+        assert!(profiles.create("sar-team", 3).is_err());
+        assert!(profiles.create("default", 3).is_err());
+
+        assert_eq!(
+            profile_dir(Path::new("/data"), DEFAULT_PROFILE_NAME),
+            PathBuf::from("/data")
+        );
+        assert_eq!(
+            profile_dir(Path::new("/data"), "SAR Team"),
+            PathBuf::from("/data/profiles/sar-team")
+        );
+
+        profiles.set_active("Home").unwrap();
+        assert!(profiles.remove("Home").is_err());
+        assert!(profiles.remove(DEFAULT_PROFILE_NAME).is_err());
+        assert!(profiles.remove("SAR Team").is_ok());
+        assert!(profiles.set_active("SAR Team").is_err());
+        assert_eq!(profiles.active, "Home");
+    }
+
+    fn node_label<R: tauri::Runtime>(app: &tauri::App<R>, node_num: u32) -> Option<String> {
+        let graph_state = app.state::<state::graph::GraphState>();
+        let graph = graph_state.inner.read().unwrap();
+        graph.overrides.node_label(node_num).map(String::from)
+    }
+
+    fn set_node_label<R: tauri::Runtime>(app: &tauri::App<R>, node_num: u32, label: &str) {
+        let graph_state = app.state::<state::graph::GraphState>();
+        let mut graph = graph_state.inner.write().unwrap();
+        graph.set_node_label(node_num, Some(label.into()));
+        save_json(&app.handle(), GRAPH_OVERRIDES_FILE_NAME, &graph.overrides).unwrap();
+    }
+
+    #[test]
+    fn keeps_profiles_isolated() {
+        let data_dir = std::env::temp_dir().join(format!("profiles-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+
+        let app = tauri::test::mock_app();
+        app.manage(state::profiles::ProfilesState::new(
+            data_dir.clone(),
+            load_profiles(&data_dir),
+        ));
+        app.manage(state::graph::GraphState::new());
+        app.manage(state::settings::SettingsState::new(AppSettings::default()));
+
+        let handle = app.handle();
+
+        create_profile(&handle, "Home", 1).unwrap();
+        create_profile(&handle, "SAR Team", 2).unwrap();
+
+        activate_profile(&handle, "Home").unwrap();
+        set_node_label(&app, 0x10, "Water tower");
+
+        activate_profile(&handle, "SAR Team").unwrap();
+        assert_eq!(node_label(&app, 0x10), None);
+        set_node_label(&app, 0x10, "Base camp");
+        set_node_label(&app, 0x20, "Ridge relay");
+
+        activate_profile(&handle, "Home").unwrap();
+        assert_eq!(node_label(&app, 0x10).as_deref(), Some("Water tower"));
+        assert_eq!(node_label(&app, 0x20), None);
+
+        activate_profile(&handle, "SAR Team").unwrap();
+        assert_eq!(node_label(&app, 0x10).as_deref(), Some("Base camp"));
+        assert_eq!(node_label(&app, 0x20).as_deref(), Some("Ridge relay"));
+
+        // The default profile was never written to
+        activate_profile(&handle, DEFAULT_PROFILE_NAME).unwrap();
+        assert_eq!(node_label(&app, 0x10), None);
+        assert!(!data_dir.join(GRAPH_OVERRIDES_FILE_NAME).exists());
+
+        // The active profile survives a restart
+        assert_eq!(load_profiles(&data_dir).active, DEFAULT_PROFILE_NAME);
+        assert_eq!(load_profiles(&data_dir).profiles.len(), 3);
+
+        delete_profile(&handle, "Home").unwrap();
+        assert!(!profile_dir(&data_dir, "Home").exists());
+        assert!(profile_dir(&data_dir, "SAR Team").exists());
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
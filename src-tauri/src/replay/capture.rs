@@ -0,0 +1,267 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use meshtastic::protobufs;
+use meshtastic::Message;
+
+/// Start of every capture file, the last byte is the format version
+pub const CAPTURE_MAGIC: &[u8; 8] = b"MSHCAP\x00\x01";
+
+/// Records longer than this can only come from a corrupt file, `FromRadio` messages
+/// are well under a kilobyte
+pub const MAX_CAPTURE_RECORD_BYTES: usize = 64 * 1024;
+
+/// Timestamp and length before each record's protobuf bytes
+const RECORD_HEADER_BYTES: usize = 8 + 4;
+
+/// A `FromRadio` message and when it was received
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptureRecord {
+    pub timestamp_millis: u64, // since epoch
+    pub packet: protobufs::FromRadio,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CaptureError {
+    NotACapture,
+    Truncated { offset: usize },
+    InvalidRecord { offset: usize, message: String },
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureError::NotACapture => write!(f, "Not a packet capture file"),
+            CaptureError::Truncated { offset } => {
+                write!(f, "Capture file is truncated at byte {}", offset)
+            }
+            CaptureError::InvalidRecord { offset, message } => {
+                write!(f, "Invalid capture record at byte {}: {}", offset, message)
+            }
+        }
+    }
+}
+
+/// Encodes a record as its little-endian timestamp in milliseconds and length,
+/// followed by the protobuf bytes of the packet
+pub fn encode_capture_record(record: &CaptureRecord) -> Vec<u8> {
+    let packet = record.packet.encode_to_vec();
+
+    let mut bytes = Vec::with_capacity(RECORD_HEADER_BYTES + packet.len());
+    bytes.extend_from_slice(&record.timestamp_millis.to_le_bytes());
+    bytes.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&packet);
+    bytes
+}
+
+/// Encodes a whole capture file
+pub fn encode_capture(records: &[CaptureRecord]) -> Vec<u8> {
+    let mut bytes = CAPTURE_MAGIC.to_vec();
+
+    for record in records {
+        bytes.extend(encode_capture_record(record));
+    }
+
+    bytes
+}
+
+/// Decodes a capture file, in the order its records were written
+pub fn parse_capture(bytes: &[u8]) -> Result<Vec<CaptureRecord>, CaptureError> {
+    let mut rest = bytes
+        .strip_prefix(CAPTURE_MAGIC.as_slice())
+        .ok_or(CaptureError::NotACapture)?;
+
+    let mut records = vec![];
+
+    while !rest.is_empty() {
+        let offset = bytes.len() - rest.len();
+
+        if rest.len() < RECORD_HEADER_BYTES {
+            return Err(CaptureError::Truncated { offset });
+        }
+
+        let (header, body) = rest.split_at(RECORD_HEADER_BYTES);
+        let timestamp_millis = u64::from_le_bytes(header[..8].try_into().unwrap_or_default());
+        let length = u32::from_le_bytes(header[8..].try_into().unwrap_or_default()) as usize;
+
+        if length > MAX_CAPTURE_RECORD_BYTES {
+            return Err(CaptureError::InvalidRecord {
+                offset,
+                message: format!("record length {} is too long", length),
+            });
+        }
+
+        if body.len() < length {
+            return Err(CaptureError::Truncated { offset });
+        }
+
+        let packet = protobufs::FromRadio::decode(&body[..length]).map_err(|e| {
+            CaptureError::InvalidRecord {
+                offset,
+                message: e.to_string(),
+            }
+        })?;
+
+        records.push(CaptureRecord {
+            timestamp_millis,
+            packet,
+        });
+
+        rest = &body[length..];
+    }
+
+    Ok(records)
+}
+
+/// Capture file that decoded packets are appended to as they're handled. Records
+/// are written unbuffered, so a capture cut short by a crash stays readable up to
+/// its last whole record.
+pub struct PacketCapture {
+    path: PathBuf,
+    file: File,
+    record_count: u32,
+}
+
+impl PacketCapture {
+    /// Opens `path` for appending, starting a new capture if the file is empty or
+    /// doesn't exist yet. Existing files that aren't captures are left untouched.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| e.to_string())?;
+
+        let mut magic = vec![];
+        (&mut file)
+            .take(CAPTURE_MAGIC.len() as u64)
+            .read_to_end(&mut magic)
+            .map_err(|e| e.to_string())?;
+
+        if magic.is_empty() {
+            file.write_all(CAPTURE_MAGIC).map_err(|e| e.to_string())?;
+        } else if magic != CAPTURE_MAGIC {
+            return Err(CaptureError::NotACapture.to_string());
+        }
+
+        Ok(Self {
+            path,
+            file,
+            record_count: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records appended since the capture was opened
+    pub fn record_count(&self) -> u32 {
+        self.record_count
+    }
+
+    pub fn append(&mut self, record: &CaptureRecord) -> Result<(), String> {
+        self.file
+            .write_all(&encode_capture_record(record))
+            .map_err(|e| e.to_string())?;
+        self.record_count += 1;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<CaptureRecord> {
+        (1..=3)
+            .map(|i| CaptureRecord {
+                timestamp_millis: 1_700_000_000_000 + i * 250,
+                packet: protobufs::FromRadio {
+                    id: i as u32,
+                    payload_variant: Some(protobufs::from_radio::PayloadVariant::MyInfo(
+                        protobufs::MyNodeInfo {
+                            my_node_num: 0x1234,
+                            ..Default::default()
+                        },
+                    )),
+                    ..Default::default()
+                },
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_records() {
+        let bytes = encode_capture(&records());
+
+        assert_eq!(parse_capture(&bytes).unwrap(), records());
+        assert!(parse_capture(CAPTURE_MAGIC).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reports_corrupt_files() {
+        let bytes = encode_capture(&records());
+        let second_record = CAPTURE_MAGIC.len() + encode_capture_record(&records()[0]).len();
+        let last_record = bytes.len() - encode_capture_record(&records()[2]).len();
+
+        assert_eq!(
+            parse_capture(b"not a capture"),
+            Err(CaptureError::NotACapture)
+        );
+        assert_eq!(
+            parse_capture(&bytes[..bytes.len() - 1]),
+            Err(CaptureError::Truncated {
+                offset: last_record
+            })
+        );
+        assert_eq!(
+            parse_capture(&bytes[..second_record + 5]),
+            Err(CaptureError::Truncated {
+                offset: second_record
+            })
+        );
+
+        let mut corrupt = bytes.clone();
+        corrupt[second_record + 8..second_record + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            parse_capture(&corrupt),
+            Err(CaptureError::InvalidRecord { offset, .. }) if offset == second_record
+        ));
+    }
+
+    #[test]
+    fn appends_to_capture_files() {
+        let path = std::env::temp_dir().join(format!("capture-{}.mshcap", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let records = records();
+        let (first, rest) = records.split_at(1);
+
+        let mut capture = PacketCapture::open(&path).unwrap();
+        capture.append(&first[0]).unwrap();
+        drop(capture);
+
+        // Reopening continues the same capture rather than starting a new one
+        let mut capture = PacketCapture::open(&path).unwrap();
+        for record in rest {
+            capture.append(record).unwrap();
+        }
+        assert_eq!(capture.record_count(), 2);
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(parse_capture(&bytes).unwrap(), records);
+
+        std::fs::write(&path, b"not a capture").unwrap();
+        assert!(PacketCapture::open(&path).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"not a capture");
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
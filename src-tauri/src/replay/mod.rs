@@ -0,0 +1,475 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{debug, trace, warn};
+use meshtastic::packet::PacketRouter;
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use tauri::async_runtime::{self, JoinHandle};
+
+use crate::device::MeshDevice;
+use crate::graph::ds::graph::MeshGraph;
+use crate::ipc::events;
+use crate::packet_api::{dedup::PacketDedupCache, graph_batch::GraphUpdateBatch, MeshPacketApi};
+use crate::state;
+use crate::state::mesh_devices::get_device;
+
+use self::capture::CaptureRecord;
+
+pub mod capture;
+
+/// Device key the replayed radio is registered under in the mesh devices state
+pub const REPLAY_DEVICE_KEY: &str = "replay";
+
+/// Fastest playback speed, a day of traffic replays in under 90 seconds
+pub const MAX_REPLAY_SPEED: f64 = 1_000.0;
+
+/// How often playback advances and reports its position
+const PLAYBACK_TICK: Duration = Duration::from_millis(100);
+
+/// Replayed records between snapshots of the device and graph, which seeking
+/// backwards restores instead of replaying from the start of the capture
+const SNAPSHOT_INTERVAL_RECORDS: usize = 1_000;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayStatus {
+    pub path: String,
+    pub start_millis: u64, // timestamp of the first record, since epoch
+    pub end_millis: u64,   // timestamp of the last record
+    pub position_millis: u64,
+    pub record_count: u32,
+    pub replayed_count: u32, // records at or before the position
+    pub playing: bool,
+    pub speed: f64,
+}
+
+/// State of the replayed device and graph after the first `cursor` records
+struct ReplaySnapshot {
+    cursor: usize,
+    device: MeshDevice,
+    graph: MeshGraph,
+    dedup: PacketDedupCache,
+}
+
+/// Timeline over a packet capture that feeds its records into a packet API as the
+/// position moves forward. Moving it back restores the nearest earlier snapshot and
+/// replays from there, since packet handlers can't be undone.
+pub struct ReplaySession {
+    path: String,
+    records: Vec<CaptureRecord>, // ordered by timestamp
+    cursor: usize,               // records before this have been replayed
+    position_millis: u64,
+    playing: bool,
+    speed: f64,
+    snapshots: Vec<ReplaySnapshot>, // ordered by cursor, starting at 0
+    snapshot_interval: usize,
+}
+
+pub type SharedReplaySession = Arc<async_runtime::Mutex<ReplaySession>>;
+
+impl ReplaySession {
+    /// Starts a session at the first record, with the packet API's current state as
+    /// the state before any records are replayed
+    pub fn new<R: tauri::Runtime>(
+        path: String,
+        mut records: Vec<CaptureRecord>,
+        packet_api: &MeshPacketApi<R>,
+    ) -> Result<Self, String> {
+        if records.is_empty() {
+            return Err("Capture file has no packets".into());
+        }
+
+        // Stable, so packets received in the same millisecond keep their order
+        records.sort_by_key(|record| record.timestamp_millis);
+
+        let mut session = Self {
+            path,
+            position_millis: records[0].timestamp_millis,
+            records,
+            cursor: 0,
+            playing: false,
+            speed: 1.0,
+            snapshots: vec![],
+            snapshot_interval: SNAPSHOT_INTERVAL_RECORDS,
+        };
+
+        session.take_snapshot(packet_api)?;
+
+        Ok(session)
+    }
+
+    pub fn start_millis(&self) -> u64 {
+        self.records[0].timestamp_millis
+    }
+
+    pub fn end_millis(&self) -> u64 {
+        self.records[self.records.len() - 1].timestamp_millis
+    }
+
+    pub fn position_millis(&self) -> u64 {
+        self.position_millis
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.cursor == self.records.len()
+    }
+
+    pub fn status(&self) -> ReplayStatus {
+        ReplayStatus {
+            path: self.path.clone(),
+            start_millis: self.start_millis(),
+            end_millis: self.end_millis(),
+            position_millis: self.position_millis,
+            record_count: self.records.len() as u32,
+            replayed_count: self.cursor as u32,
+            playing: self.playing,
+            speed: self.speed,
+        }
+    }
+
+    pub fn play(&mut self, speed: f64) -> Result<(), String> {
+        if !speed.is_finite() || speed <= 0.0 || speed > MAX_REPLAY_SPEED {
+            return Err(format!(
+                "Replay speed must be more than 0 and at most {}",
+                MAX_REPLAY_SPEED
+            ));
+        }
+
+        self.speed = speed;
+        self.playing = true;
+
+        Ok(())
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    fn take_snapshot<R: tauri::Runtime>(
+        &mut self,
+        packet_api: &MeshPacketApi<R>,
+    ) -> Result<(), String> {
+        let graph = packet_api.graph_arc.view()?;
+
+        self.snapshots.push(ReplaySnapshot {
+            cursor: self.cursor,
+            device: packet_api.device.clone(),
+            graph,
+            dedup: packet_api.dedup.clone(),
+        });
+
+        Ok(())
+    }
+
+    fn restore_snapshot<R: tauri::Runtime>(
+        &mut self,
+        packet_api: &mut MeshPacketApi<R>,
+        index: usize,
+    ) -> Result<(), String> {
+        let snapshot = &self.snapshots[index];
+
+        *packet_api.write_graph()? = snapshot.graph.clone();
+        packet_api.device = snapshot.device.clone();
+        packet_api.dedup = snapshot.dedup.clone();
+        packet_api.graph_batch = GraphUpdateBatch::default();
+
+        self.cursor = snapshot.cursor;
+
+        Ok(())
+    }
+
+    /// Replays every record up to and including `timestamp_millis`
+    pub fn advance_to<R: tauri::Runtime>(
+        &mut self,
+        packet_api: &mut MeshPacketApi<R>,
+        timestamp_millis: u64,
+    ) -> Result<(), String> {
+        let target = timestamp_millis.clamp(self.start_millis(), self.end_millis());
+
+        while self.cursor < self.records.len()
+            && self.records[self.cursor].timestamp_millis <= target
+        {
+            let packet = self.records[self.cursor].packet.clone();
+
+            if let Err(e) = packet_api.handle_packet_from_radio(packet) {
+                warn!("Failed to handle replayed packet: {}", e);
+            }
+
+            self.cursor += 1;
+
+            let last_snapshot = self.snapshots.last().map_or(0, |s| s.cursor);
+
+            if self.cursor % self.snapshot_interval == 0 && self.cursor > last_snapshot {
+                // Snapshots hold the graph with every node heard so far
+                packet_api.flush_graph_batch().map_err(|e| e.to_string())?;
+                self.take_snapshot(packet_api)?;
+            }
+        }
+
+        packet_api.flush_graph_batch().map_err(|e| e.to_string())?;
+
+        self.position_millis = self.position_millis.max(target);
+
+        if self.is_finished() {
+            self.position_millis = self.end_millis();
+        }
+
+        Ok(())
+    }
+
+    /// Moves the position to `timestamp_millis`, rebuilding the device and graph
+    /// from the nearest earlier snapshot when moving back
+    pub fn seek<R: tauri::Runtime>(
+        &mut self,
+        packet_api: &mut MeshPacketApi<R>,
+        timestamp_millis: u64,
+    ) -> Result<(), String> {
+        let target = timestamp_millis.clamp(self.start_millis(), self.end_millis());
+
+        if target < self.position_millis {
+            // Snapshots up to the first record after the target are still valid
+            let index = self
+                .snapshots
+                .iter()
+                .rposition(|s| {
+                    s.cursor == 0 || self.records[s.cursor - 1].timestamp_millis <= target
+                })
+                .unwrap_or(0);
+
+            trace!(
+                "Seeking back to {} from snapshot at record {}",
+                target,
+                self.snapshots[index].cursor
+            );
+
+            self.restore_snapshot(packet_api, index)?;
+            self.position_millis = self.start_millis();
+        }
+
+        self.advance_to(packet_api, target)
+    }
+
+    /// Advances the position by `elapsed` wall clock time at the playback speed,
+    /// pausing at the end of the capture
+    pub fn play_for<R: tauri::Runtime>(
+        &mut self,
+        packet_api: &mut MeshPacketApi<R>,
+        elapsed: Duration,
+    ) -> Result<(), String> {
+        if !self.playing {
+            return Ok(());
+        }
+
+        let step = (elapsed.as_millis() as f64 * self.speed) as u64;
+        self.advance_to(packet_api, self.position_millis.saturating_add(step))?;
+
+        if self.is_finished() {
+            self.playing = false;
+        }
+
+        Ok(())
+    }
+}
+
+/// Plays the session from its position until it's paused, reaches the end of the
+/// capture, or the replayed device is removed, reporting the position each tick
+pub fn spawn_replay(
+    mesh_devices: state::mesh_devices::MeshDevicesStateInner,
+    session: SharedReplaySession,
+) -> JoinHandle<()> {
+    trace!("Spawning replay playback");
+
+    async_runtime::spawn(async move {
+        let mut last_tick = Instant::now();
+
+        loop {
+            tokio::time::sleep(PLAYBACK_TICK).await;
+
+            let now = Instant::now();
+            let elapsed = now - last_tick;
+            last_tick = now;
+
+            let mut session = session.lock().await;
+
+            let device = match get_device(&mesh_devices, REPLAY_DEVICE_KEY).await {
+                Some(d) => d,
+                None => {
+                    debug!("Replayed device removed, stopping playback");
+                    return;
+                }
+            };
+            let mut packet_api = device.lock().await;
+
+            if let Err(e) = session.play_for(&mut *packet_api, elapsed) {
+                warn!("Failed to replay packets: {}", e);
+                session.pause();
+            }
+
+            // Replayed packets skip the device's task, which would otherwise publish
+            // the graph once its update window passes
+            let published = match session.is_playing() {
+                true => packet_api.publish_due_graph(),
+                false => packet_api.flush_graph_updates(),
+            };
+
+            if let Err(e) = published {
+                warn!("Failed to publish replayed graph: {}", e);
+            }
+
+            if let Err(e) = events::dispatch_replay_status(
+                &packet_api.app_handle,
+                REPLAY_DEVICE_KEY.into(),
+                session.status(),
+            ) {
+                warn!("Failed to dispatch replay status: {}", e);
+            }
+
+            if !session.is_playing() {
+                debug!("Replay paused at {}", session.position_millis());
+                return;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::scenario::{ScenarioEngine, ScenarioParams};
+    use std::collections::BTreeSet;
+
+    const TICK_MILLIS: u64 = 5_000;
+    const TICKS: u64 = 20;
+
+    /// Records of a simulated mesh with flapping links, so the graph keeps changing
+    fn captured_records() -> Vec<CaptureRecord> {
+        let mut engine = ScenarioEngine::new(ScenarioParams {
+            seed: 3,
+            node_count: 8,
+            link_flap_rate: 0.3,
+            ..Default::default()
+        });
+
+        let start = 1_700_000_000_000;
+        let mut records = vec![];
+
+        for tick in 0..=TICKS {
+            let timestamp_millis = start + tick * TICK_MILLIS;
+            let now = (timestamp_millis / 1_000) as u32;
+
+            let packets = match tick {
+                0 => engine.initial_packets(now),
+                _ => engine.tick(now),
+            };
+
+            records.extend(packets.into_iter().map(|packet| CaptureRecord {
+                timestamp_millis,
+                packet,
+            }));
+        }
+
+        records
+    }
+
+    fn replay_api(
+        app: &tauri::App<tauri::test::MockRuntime>,
+    ) -> MeshPacketApi<tauri::test::MockRuntime> {
+        MeshPacketApi::new(
+            app.handle(),
+            REPLAY_DEVICE_KEY.into(),
+            MeshDevice::new(),
+            Arc::new(SharedGraph::new(MeshGraph::new())),
+        )
+    }
+
+    type GraphSummary = (BTreeSet<u32>, BTreeSet<(u32, u32)>, BTreeSet<u32>);
+
+    fn summary(packet_api: &MeshPacketApi<tauri::test::MockRuntime>) -> GraphSummary {
+        let graph = packet_api.read_graph().unwrap();
+
+        (
+            graph.nodes().map(|node| node.node_num).collect(),
+            graph
+                .edges()
+                .map(|(from, to, _)| (from.node_num, to.node_num))
+                .collect(),
+            packet_api.device.nodes.keys().copied().collect(),
+        )
+    }
+
+    /// State after replaying the whole capture up to `timestamp_millis` in one go
+    fn straight_replay(
+        app: &tauri::App<tauri::test::MockRuntime>,
+        timestamp_millis: u64,
+    ) -> GraphSummary {
+        let mut packet_api = replay_api(app);
+        let mut session =
+            ReplaySession::new("straight".into(), captured_records(), &packet_api).unwrap();
+
+        session
+            .advance_to(&mut packet_api, timestamp_millis)
+            .unwrap();
+
+        summary(&packet_api)
+    }
+
+    #[test]
+    fn seek_then_play_matches_straight_replay() {
+        let app = tauri::test::mock_app();
+        let mut packet_api = replay_api(&app);
+        let mut session =
+            ReplaySession::new("capture".into(), captured_records(), &packet_api).unwrap();
+
+        let midpoint = (session.start_millis() + session.end_millis()) / 2;
+        session.seek(&mut packet_api, midpoint).unwrap();
+        assert_eq!(session.position_millis(), midpoint);
+        assert!(!session.is_finished());
+
+        // 10x speed, one simulated second per playback step
+        session.play(10.0).unwrap();
+
+        while session.is_playing() {
+            session
+                .play_for(&mut packet_api, Duration::from_secs(1))
+                .unwrap();
+        }
+
+        assert!(session.is_finished());
+        assert_eq!(session.position_millis(), session.end_millis());
+        assert_eq!(
+            summary(&packet_api),
+            straight_replay(&app, session.end_millis())
+        );
+        assert!(!summary(&packet_api).1.is_empty());
+    }
+
+    #[test]
+    fn seeking_back_rebuilds_state() {
+        let app = tauri::test::mock_app();
+        let mut packet_api = replay_api(&app);
+        let mut session =
+            ReplaySession::new("capture".into(), captured_records(), &packet_api).unwrap();
+        session.snapshot_interval = 50;
+
+        let start = session.start_millis();
+        let end = session.end_millis();
+        session.seek(&mut packet_api, end).unwrap();
+        assert!(session.snapshots.len() > 2);
+
+        // Back to before the first snapshot, then to between two later ones
+        for timestamp in [start, start + 7 * TICK_MILLIS + 1, start + 3 * TICK_MILLIS] {
+            session.seek(&mut packet_api, timestamp).unwrap();
+
+            assert_eq!(session.position_millis(), timestamp);
+            assert_eq!(summary(&packet_api), straight_replay(&app, timestamp));
+        }
+
+        assert!(session.play(0.0).is_err());
+        assert!(ReplaySession::new("empty".into(), vec![], &packet_api).is_err());
+    }
+}
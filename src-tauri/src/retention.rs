@@ -0,0 +1,652 @@
+//! Retention of the data kept in the SQLite stores. Each class of data has a policy
+//! in the settings capping its age and size, enforced on an interval or on request.
+//!
+//! Only the tables of the classes below are ever pruned. Node notes and their
+//! revisions, graph overrides (manual edges, hidden nodes and labels) and settings
+//! are kept whatever the policies, as they're entered by the operator. Outgoing
+//! journal entries are pruned with messages once acked or failed, never while they
+//! may still be resent. Stored analytics metrics are pruned by age with telemetry.
+
+use std::path::Path;
+use std::time::Duration;
+
+use log::{trace, warn};
+use meshtastic::ts::specta::{self, Type};
+use rusqlite::{params, Connection, ToSql, Transaction};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::device::helpers::get_current_time_u32;
+use crate::device::message_store::OUTGOING_JOURNAL_RETENTION_SECS;
+use crate::persistence::{
+    settings_file_path, GRAPH_DATABASE_FILE_NAME, MESSAGE_DATABASE_FILE_NAME,
+    TELEMETRY_DATABASE_FILE_NAME,
+};
+use crate::shutdown::shutdown_signal;
+use crate::state;
+
+/// How often the retention policies are enforced
+pub const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Added to the length of each row's contents when estimating its size, for its
+/// fixed-size columns and index entries
+pub const ROW_OVERHEAD_BYTES: u64 = 48;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum StorageClass {
+    Messages,
+    Telemetry,
+    Positions, // archived node positions
+    Graphs,    // stored nodes and edges
+}
+
+pub const STORAGE_CLASSES: [StorageClass; 4] = [
+    StorageClass::Messages,
+    StorageClass::Telemetry,
+    StorageClass::Positions,
+    StorageClass::Graphs,
+];
+
+/// A class's policy resolved against the current time
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetentionLimits {
+    pub cutoff: Option<u32>, // secs, rows older than this are deleted
+    pub max_bytes: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ClassUsage {
+    pub class: StorageClass,
+    pub rows: u64,
+    pub bytes: u64, // estimated from the length of the rows' contents
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct DatabaseUsage {
+    pub file_name: String,
+    pub file_bytes: u64, // including space SQLite hasn't reclaimed from deleted rows
+    pub classes: Vec<ClassUsage>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageUsage {
+    pub databases: Vec<DatabaseUsage>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PruneReport {
+    pub class: StorageClass,
+    pub rows_deleted: u32,
+}
+
+/// A table pruned by age and size. Rows are aged by `timestamp`, and the oldest are
+/// deleted first.
+pub struct RetainedTable {
+    pub name: &'static str,
+    pub key: &'static [&'static str], // columns identifying a row, the first is returned on delete
+    pub timestamp: &'static str,
+    pub row_bytes: &'static str, // SQL expression for the length of a row's contents
+}
+
+impl RetainedTable {
+    /// Number of rows and their estimated size
+    pub fn usage(&self, connection: &Connection) -> Result<(u64, u64), String> {
+        connection
+            .query_row(
+                &format!(
+                    "SELECT COUNT(*), COALESCE(SUM({} + {}), 0) FROM {}",
+                    self.row_bytes, ROW_OVERHEAD_BYTES, self.name
+                ),
+                [],
+                |row| Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)? as u64)),
+            )
+            .map_err(|e| e.to_string())
+    }
+
+    /// Deletes rows older than `cutoff`, returning the first key column of each
+    pub fn prune_older_than(
+        &self,
+        transaction: &Transaction,
+        cutoff: &dyn ToSql,
+    ) -> Result<Vec<i64>, String> {
+        let sql = format!(
+            "DELETE FROM {} WHERE {} < ?1 RETURNING {}",
+            self.name, self.timestamp, self.key[0]
+        );
+
+        deleted_keys(transaction, &sql, params![cutoff])
+    }
+
+    /// Deletes the oldest rows until the rest take up at most `max_bytes`, returning
+    /// the first key column of each row deleted
+    pub fn prune_to_size(
+        &self,
+        transaction: &Transaction,
+        max_bytes: u64,
+    ) -> Result<Vec<i64>, String> {
+        let key = self.key.join(", ");
+        let newest_first = self
+            .key
+            .iter()
+            .fold(format!("{} DESC", self.timestamp), |order, column| {
+                format!("{}, {} DESC", order, column)
+            });
+
+        let sql = format!(
+            "DELETE FROM {name} WHERE ({key}) IN (
+                SELECT {key} FROM (
+                    SELECT {key}, SUM({bytes} + {overhead}) OVER (ORDER BY {order} ROWS UNBOUNDED PRECEDING) AS total
+                        FROM {name}
+                ) WHERE total > ?1
+            ) RETURNING {first}",
+            name = self.name,
+            key = key,
+            bytes = self.row_bytes,
+            overhead = ROW_OVERHEAD_BYTES,
+            order = newest_first,
+            first = self.key[0],
+        );
+
+        deleted_keys(transaction, &sql, params![max_bytes as i64])
+    }
+
+    /// Applies both limits, the age first
+    pub fn enforce(
+        &self,
+        transaction: &Transaction,
+        limits: RetentionLimits,
+    ) -> Result<Vec<i64>, String> {
+        let mut deleted = match limits.cutoff {
+            Some(cutoff) => self.prune_older_than(transaction, &cutoff)?,
+            None => vec![],
+        };
+
+        if let Some(max_bytes) = limits.max_bytes {
+            deleted.extend(self.prune_to_size(transaction, max_bytes)?);
+        }
+
+        Ok(deleted)
+    }
+}
+
+fn deleted_keys(
+    transaction: &Transaction,
+    sql: &str,
+    params: &[&dyn ToSql],
+) -> Result<Vec<i64>, String> {
+    let mut statement = transaction.prepare(sql).map_err(|e| e.to_string())?;
+
+    let keys = statement
+        .query_map(params, |row| row.get::<_, i64>(0))
+        .map_err(|e| e.to_string())?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(keys)
+}
+
+/// Size of a database file and its write-ahead log, zero if it doesn't exist yet
+fn database_file_bytes(path: &Path) -> u64 {
+    let wal_path = path.with_file_name(format!(
+        "{}-wal",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+
+    [path, wal_path.as_path()]
+        .iter()
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Limits for each requested class from the app settings. Telemetry is limited by
+/// its per-class cutoffs as well, which are applied by the telemetry store.
+fn retention_limits<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    class: StorageClass,
+    now: u32,
+) -> Result<RetentionLimits, String> {
+    let settings_state = handle.state::<state::settings::SettingsState>();
+    let settings = settings_state.inner.lock().map_err(|e| e.to_string())?;
+
+    let policy = settings.retention.policy(class);
+    let mut limits = RetentionLimits {
+        cutoff: policy.cutoff(now),
+        max_bytes: policy.max_bytes(),
+    };
+
+    if class == StorageClass::Positions {
+        let cutoff = settings.positions.cutoff(now);
+        limits.cutoff = Some(limits.cutoff.map_or(cutoff, |c| c.max(cutoff)));
+    }
+
+    Ok(limits)
+}
+
+/// Applies the retention policy of each of `classes`, each class in one transaction
+pub fn enforce_retention<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    classes: &[StorageClass],
+    now: u32,
+) -> Result<Vec<PruneReport>, String> {
+    let mut reports = vec![];
+
+    for class in classes {
+        let limits = retention_limits(handle, *class, now)?;
+
+        let rows_deleted = match class {
+            StorageClass::Messages => {
+                let store_state = handle.state::<state::message_store::MessageStoreState>();
+                let mut store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+
+                // Completed journal entries go with the messages, or sooner
+                let journal_cutoff = now
+                    .saturating_sub(OUTGOING_JOURNAL_RETENTION_SECS)
+                    .max(limits.cutoff.unwrap_or(0));
+
+                match store_guard.as_mut() {
+                    Some(store) => {
+                        store.enforce_retention(limits)? + store.prune_outgoing(journal_cutoff)?
+                    }
+                    None => 0,
+                }
+            }
+            StorageClass::Telemetry => {
+                let cutoffs = {
+                    let settings_state = handle.state::<state::settings::SettingsState>();
+                    let settings = settings_state.inner.lock().map_err(|e| e.to_string())?;
+                    settings.telemetry.cutoffs(now)
+                };
+
+                let store_state = handle.state::<state::telemetry_store::TelemetryStoreState>();
+                let mut store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+
+                match store_guard.as_mut() {
+                    Some(store) => store.enforce_retention(&cutoffs, limits)?,
+                    None => 0,
+                }
+            }
+            StorageClass::Positions => {
+                let deleted = {
+                    let store_state = handle.state::<state::graph_store::GraphStoreState>();
+                    let mut store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+
+                    match store_guard.as_mut() {
+                        Some(store) => store.enforce_position_retention(limits)?,
+                        None => vec![],
+                    }
+                };
+
+                // The archive on the map forgets the same positions, including those
+                // not written yet
+                let graph_state = handle.state::<state::graph::GraphState>();
+                let mut graph = graph_state.inner.write()?;
+
+                if let Some(cutoff) = limits.cutoff {
+                    graph.position_archive.prune(cutoff);
+                }
+
+                for node_num in &deleted {
+                    graph.position_archive.remove(*node_num);
+                }
+
+                deleted.len()
+            }
+            StorageClass::Graphs => {
+                let store_state = handle.state::<state::graph_store::GraphStoreState>();
+                let mut store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+
+                match store_guard.as_mut() {
+                    Some(store) => store.enforce_graph_retention(limits)?,
+                    None => 0,
+                }
+            }
+        };
+
+        reports.push(PruneReport {
+            class: *class,
+            rows_deleted: rows_deleted as u32,
+        });
+    }
+
+    Ok(reports)
+}
+
+/// Rows and estimated size of each class, grouped by the database holding it
+pub fn get_storage_usage<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+) -> Result<StorageUsage, String> {
+    let mut databases = vec![];
+
+    let messages = {
+        let store_state = handle.state::<state::message_store::MessageStoreState>();
+        let store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+        store_guard
+            .as_ref()
+            .map(|store| store.usage())
+            .transpose()?
+    };
+
+    databases.push((MESSAGE_DATABASE_FILE_NAME, vec![messages]));
+
+    let telemetry = {
+        let store_state = handle.state::<state::telemetry_store::TelemetryStoreState>();
+        let store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+        store_guard
+            .as_ref()
+            .map(|store| store.usage())
+            .transpose()?
+    };
+
+    databases.push((TELEMETRY_DATABASE_FILE_NAME, vec![telemetry]));
+
+    let (positions, graphs) = {
+        let store_state = handle.state::<state::graph_store::GraphStoreState>();
+        let store_guard = store_state.inner.lock().map_err(|e| e.to_string())?;
+
+        match store_guard.as_ref() {
+            Some(store) => (Some(store.position_usage()?), Some(store.graph_usage()?)),
+            None => (None, None),
+        }
+    };
+
+    databases.push((GRAPH_DATABASE_FILE_NAME, vec![positions, graphs]));
+
+    Ok(StorageUsage {
+        databases: databases
+            .into_iter()
+            .map(|(file_name, classes)| {
+                Ok(DatabaseUsage {
+                    file_name: file_name.into(),
+                    file_bytes: database_file_bytes(&settings_file_path(handle, file_name)?),
+                    classes: classes.into_iter().flatten().collect(),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?,
+    })
+}
+
+/// Enforces every class's retention policy on an interval
+pub fn spawn_retention_timer(handle: tauri::AppHandle) {
+    trace!("Spawning retention timer");
+
+    tauri::async_runtime::spawn(async move {
+        let shutdown = shutdown_signal(&handle);
+        let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+
+        while shutdown.tick(&mut interval).await {
+            let handle = handle.clone();
+
+            let result = tauri::async_runtime::spawn_blocking(move || {
+                enforce_retention(&handle, &STORAGE_CLASSES, get_current_time_u32())
+            })
+            .await;
+
+            match result {
+                Ok(Ok(reports)) => {
+                    for report in reports.iter().filter(|r| r.rows_deleted > 0) {
+                        trace!("Pruned {} {:?} rows", report.rows_deleted, report.class);
+                    }
+                }
+                Ok(Err(e)) => warn!("Failed to enforce retention policies: {}", e),
+                Err(e) => warn!("Retention timer failed: {}", e),
+            }
+        }
+
+        trace!("Stopped retention timer");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::message_store::{MessageStore, StoredMessage};
+    use crate::device::reactions::MessageReactions;
+    use crate::device::telemetry_store::{TelemetryMetric, TelemetrySample, TelemetryStore};
+    use crate::device::{ChannelMessageState, MeshDevice};
+    use crate::graph::ds::{
+        edge::GraphEdge,
+        fixtures::graph_node,
+        graph::MeshGraph,
+        overrides::ManualEdge,
+        position_archive::{ArchivedPosition, PositionSource},
+    };
+    use crate::graph::store::{GraphSnapshot, GraphStore};
+
+    fn edge(from: u32) -> GraphEdge {
+        GraphEdge::from_neighbor(
+            1,
+            0,
+            "LongFast".into(),
+            protobufs::Neighbor {
+                node_id: from,
+                snr: 4.5,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn message(packet_id: u32, timestamp: u32) -> StoredMessage {
+        StoredMessage {
+            device_id: 1,
+            packet_id,
+            channel: 0,
+            from: 2,
+            to: 0xffff_ffff,
+            timestamp,
+            text: "x".repeat(200),
+            state: ChannelMessageState::Acknowledged,
+            reactions: MessageReactions::default(),
+            device_key: None,
+        }
+    }
+
+    #[test]
+    fn caps_class_size() {
+        let mut store = MessageStore::open_in_memory().unwrap();
+        let messages: Vec<StoredMessage> = (1..=100).map(|i| message(i, 1_000 + i)).collect();
+        store.write(&messages).unwrap();
+
+        let usage = store.usage().unwrap();
+        assert_eq!(usage.rows, 100);
+
+        let max_bytes = usage.bytes / 4;
+        let deleted = store
+            .enforce_retention(RetentionLimits {
+                cutoff: None,
+                max_bytes: Some(max_bytes),
+            })
+            .unwrap();
+
+        let usage = store.usage().unwrap();
+        assert_eq!(deleted as u64, 100 - usage.rows);
+        assert!(usage.bytes <= max_bytes);
+        assert!(usage.bytes + usage.bytes / usage.rows > max_bytes);
+
+        // The newest messages are kept
+        let kept = store.all_messages().unwrap();
+        assert!(kept.iter().all(|m| m.packet_id > 100 - usage.rows as u32));
+
+        // Graphs are capped by deleting the least recently heard nodes with their edges
+        let mut graph = MeshGraph::new();
+        for node_num in 1..=20 {
+            graph.upsert_node(graph_node(node_num));
+        }
+
+        for node_num in 2..=20 {
+            graph.upsert_edge(graph_node(node_num), graph_node(1), edge(node_num));
+        }
+
+        let mut graph_store = GraphStore::open_in_memory().unwrap();
+        graph_store
+            .write(GraphSnapshot::new(1, &graph, &MeshDevice::new()).unwrap())
+            .unwrap();
+
+        let usage = graph_store.graph_usage().unwrap();
+        assert_eq!(usage.rows, 20 + 19);
+
+        let max_bytes = usage.bytes / 2;
+        graph_store
+            .enforce_graph_retention(RetentionLimits {
+                cutoff: None,
+                max_bytes: Some(max_bytes),
+            })
+            .unwrap();
+
+        let usage = graph_store.graph_usage().unwrap();
+        assert!(usage.bytes <= max_bytes);
+        assert!(usage.rows > 0);
+    }
+
+    #[test]
+    fn caps_class_age() {
+        let mut store = TelemetryStore::open_in_memory().unwrap();
+        let samples: Vec<TelemetrySample> = (0..10)
+            .map(|i| TelemetrySample {
+                node_num: 1,
+                metric: TelemetryMetric::BatteryLevel,
+                timestamp: 1_000 * i,
+                value: 80.0,
+            })
+            .collect();
+        store.write(&samples).unwrap();
+
+        let limits = RetentionLimits {
+            cutoff: Some(5_000),
+            max_bytes: None,
+        };
+        assert_eq!(store.enforce_retention(&[], limits).unwrap(), 5);
+        assert_eq!(store.usage().unwrap().rows, 5);
+
+        // Positions and graph rows are aged the same way
+        let mut graph_store = GraphStore::open_in_memory().unwrap();
+        let positions: Vec<(u32, ArchivedPosition)> = [(1, 1_000), (2, 9_000)]
+            .into_iter()
+            .map(|(node_num, timestamp)| {
+                let position = ArchivedPosition {
+                    latitude: 47.6,
+                    longitude: -122.3,
+                    altitude: 20,
+                    source: PositionSource::PositionPacket,
+                    timestamp,
+                };
+
+                (node_num, position)
+            })
+            .collect();
+        graph_store.write_positions(&positions).unwrap();
+
+        assert_eq!(
+            graph_store.enforce_position_retention(limits).unwrap(),
+            vec![1]
+        );
+        assert_eq!(
+            graph_store
+                .read_positions()
+                .unwrap()
+                .keys()
+                .collect::<Vec<_>>(),
+            vec![&2]
+        );
+    }
+
+    #[test]
+    fn never_prunes_exempt_data() {
+        let app = tauri::test::mock_app();
+
+        let mut settings = crate::settings::AppSettings::default();
+        settings.retention.messages.max_age_days = Some(1);
+        settings.retention.graphs.max_age_days = Some(1);
+        app.manage(state::settings::SettingsState::new(settings));
+
+        let mut message_store = MessageStore::open_in_memory().unwrap();
+        message_store
+            .write(&(1..=20).map(|i| message(i, 1_000 + i)).collect::<Vec<_>>())
+            .unwrap();
+
+        for revision in 0..3 {
+            message_store
+                .set_node_note(2, &format!("Solar repeater, revision {}", revision), 10)
+                .unwrap();
+        }
+
+        app.manage(state::message_store::MessageStoreState::new(Some(
+            message_store,
+        )));
+
+        let mut graph = MeshGraph::new();
+        graph.upsert_node(graph_node(1));
+        graph.upsert_node(graph_node(2));
+        graph.upsert_edge(graph_node(2), graph_node(1), edge(2));
+        graph.add_manual_edge(ManualEdge {
+            from: 3,
+            to: 1,
+            weight: 1.0,
+        });
+        graph.set_node_hidden(2, true);
+        graph.set_node_label(2, Some("Barn".into()));
+
+        let mut graph_store = GraphStore::open_in_memory().unwrap();
+        graph_store
+            .write(GraphSnapshot::new(1, &graph, &MeshDevice::new()).unwrap())
+            .unwrap();
+
+        let overrides = graph.overrides.clone();
+        let graph_state = state::graph::GraphState::new();
+        *graph_state.inner.write().unwrap() = graph;
+        app.manage(graph_state);
+        app.manage(state::graph_store::GraphStoreState::new(Some(graph_store)));
+
+        // Far enough ahead that every row is past its age
+        let now = get_current_time_u32() + 10 * 24 * 60 * 60;
+        let reports = enforce_retention(
+            &app.handle(),
+            &[StorageClass::Messages, StorageClass::Graphs],
+            now,
+        )
+        .unwrap();
+
+        assert_eq!(
+            reports,
+            vec![
+                PruneReport {
+                    class: StorageClass::Messages,
+                    rows_deleted: 20,
+                },
+                PruneReport {
+                    class: StorageClass::Graphs,
+                    rows_deleted: 3,
+                },
+            ]
+        );
+
+        let message_state = app.state::<state::message_store::MessageStoreState>();
+        let message_guard = message_state.inner.lock().unwrap();
+        let message_store = message_guard.as_ref().unwrap();
+        assert_eq!(message_store.usage().unwrap().rows, 0);
+        assert_eq!(message_store.get_node_notes(2).unwrap().len(), 3);
+        assert_eq!(message_store.search_all("repeater", 10).unwrap().len(), 1);
+
+        let graph_state = app.state::<state::graph::GraphState>();
+        assert_eq!(graph_state.inner.read().unwrap().overrides, overrides);
+
+        let graph_store_state = app.state::<state::graph_store::GraphStoreState>();
+        let graph_store_guard = graph_store_state.inner.lock().unwrap();
+        assert_eq!(
+            graph_store_guard
+                .as_ref()
+                .unwrap()
+                .graph_usage()
+                .unwrap()
+                .rows,
+            0
+        );
+    }
+}
@@ -0,0 +1,356 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::debug;
+use rhai::{Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+
+use super::{NodeAttribute, ScriptAction, ScriptError, ScriptPacket};
+
+/// Maximum number of actions a single script run may queue
+pub const MAX_SCRIPT_ACTIONS: usize = 8;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ScriptLimits {
+    pub time_limit: Duration,
+    pub max_operations: u64,
+    pub max_string_size: usize, // bytes
+    pub max_array_size: usize,
+    pub max_map_size: usize,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            time_limit: Duration::from_millis(50),
+            max_operations: 100_000,
+            max_string_size: 4096,
+            max_array_size: 256,
+            max_map_size: 64,
+        }
+    }
+}
+
+type SharedActions = Arc<Mutex<Vec<ScriptAction>>>;
+
+fn to_u32(value: i64, name: &str) -> Result<u32, Box<EvalAltResult>> {
+    u32::try_from(value).map_err(|_| format!("{} {} is out of range", name, value).into())
+}
+
+fn push_action(actions: &SharedActions, action: ScriptAction) -> Result<(), Box<EvalAltResult>> {
+    let mut actions = actions
+        .lock()
+        .map_err(|e| format!("Failed to record script action: {}", e))?;
+
+    if actions.len() >= MAX_SCRIPT_ACTIONS {
+        return Err(format!(
+            "Scripts can't take more than {} actions",
+            MAX_SCRIPT_ACTIONS
+        )
+        .into());
+    }
+
+    actions.push(action);
+    Ok(())
+}
+
+fn parse_node_attribute(key: &str, value: Dynamic) -> Result<NodeAttribute, Box<EvalAltResult>> {
+    match key {
+        "label" if value.is_unit() => Ok(NodeAttribute::Label(None)),
+        "label" => value
+            .into_string()
+            .map(|label| NodeAttribute::Label(Some(label)))
+            .map_err(|t| format!("Node label must be a string, got {}", t).into()),
+        "hidden" => value
+            .as_bool()
+            .map(NodeAttribute::Hidden)
+            .map_err(|t| format!("Node hidden flag must be a bool, got {}", t).into()),
+        _ => Err(format!("Unknown node attribute \"{}\"", key).into()),
+    }
+}
+
+/// Builds an engine exposing only the packet API to scripts. Actions are collected
+/// into `actions` rather than performed, so nothing happens if the script fails.
+fn build_engine(limits: ScriptLimits, actions: &SharedActions) -> Engine {
+    let mut engine = Engine::new();
+
+    engine
+        .set_max_operations(limits.max_operations)
+        .set_max_string_size(limits.max_string_size)
+        .set_max_array_size(limits.max_array_size)
+        .set_max_map_size(limits.max_map_size)
+        .set_max_call_levels(16)
+        .set_max_expr_depths(32, 32)
+        .disable_symbol("eval");
+
+    let started = Instant::now();
+    let time_limit = limits.time_limit;
+
+    engine.on_progress(move |_| {
+        if started.elapsed() > time_limit {
+            Some(Dynamic::UNIT)
+        } else {
+            None
+        }
+    });
+
+    engine.on_print(|text| debug!("Script printed: {}", text));
+    engine.on_debug(|text, _, _| debug!("Script debug: {}", text));
+
+    let send_actions = actions.clone();
+    engine.register_fn("send_text", move |channel: i64, text: &str| {
+        push_action(
+            &send_actions,
+            ScriptAction::SendText {
+                channel: to_u32(channel, "Channel")?,
+                text: text.to_string(),
+            },
+        )
+    });
+
+    let notify_actions = actions.clone();
+    engine.register_fn("notify", move |title: &str, body: &str| {
+        push_action(
+            &notify_actions,
+            ScriptAction::Notify {
+                title: title.to_string(),
+                body: body.to_string(),
+            },
+        )
+    });
+
+    let node_actions = actions.clone();
+    engine.register_fn(
+        "set_node_attr",
+        move |node_num: i64, key: &str, value: Dynamic| {
+            push_action(
+                &node_actions,
+                ScriptAction::SetNodeAttr {
+                    node_num: to_u32(node_num, "Node")?,
+                    attribute: parse_node_attribute(key, value)?,
+                },
+            )
+        },
+    );
+
+    engine
+}
+
+fn packet_map(packet: &ScriptPacket) -> Map {
+    let mut map = Map::new();
+
+    map.insert("id".into(), Dynamic::from(i64::from(packet.id)));
+    map.insert("from".into(), Dynamic::from(i64::from(packet.from)));
+    map.insert("to".into(), Dynamic::from(i64::from(packet.to)));
+    map.insert("channel".into(), Dynamic::from(i64::from(packet.channel)));
+    map.insert("portnum".into(), Dynamic::from(packet.portnum.clone()));
+    map.insert(
+        "text".into(),
+        packet
+            .text
+            .clone()
+            .map(Dynamic::from)
+            .unwrap_or(Dynamic::UNIT),
+    );
+    map.insert(
+        "hop_limit".into(),
+        Dynamic::from(i64::from(packet.hop_limit)),
+    );
+    map.insert("rx_time".into(), Dynamic::from(i64::from(packet.rx_time)));
+    map.insert("rx_snr".into(), Dynamic::from(f64::from(packet.rx_snr)));
+    map.insert("rx_rssi".into(), Dynamic::from(i64::from(packet.rx_rssi)));
+
+    map
+}
+
+fn script_error(err: Box<EvalAltResult>) -> ScriptError {
+    match *err {
+        EvalAltResult::ErrorTerminated(..) => ScriptError::TimedOut,
+        EvalAltResult::ErrorTooManyOperations(..) | EvalAltResult::ErrorDataTooLarge(..) => {
+            ScriptError::LimitExceeded(err.to_string())
+        }
+        _ => ScriptError::Runtime(err.to_string()),
+    }
+}
+
+/// Checks that a script compiles, without running it
+pub fn compile_script(source: &str) -> Result<AST, ScriptError> {
+    let mut engine = Engine::new();
+    engine.disable_symbol("eval");
+
+    engine
+        .compile(source)
+        .map_err(|e| ScriptError::Compile(e.to_string()))
+}
+
+/// Runs a script against a packet, returning the actions it asked for. The packet
+/// is available to the script as the `packet` constant.
+pub fn run_script(
+    source: &str,
+    packet: &ScriptPacket,
+    limits: ScriptLimits,
+) -> Result<Vec<ScriptAction>, ScriptError> {
+    let actions: SharedActions = Arc::new(Mutex::new(vec![]));
+    let engine = build_engine(limits, &actions);
+
+    let ast = engine
+        .compile(source)
+        .map_err(|e| ScriptError::Compile(e.to_string()))?;
+
+    let mut scope = Scope::new();
+    scope.push_constant("packet", packet_map(packet));
+
+    engine
+        .run_ast_with_scope(&mut scope, &ast)
+        .map_err(script_error)?;
+
+    // The engine holds the other references to the actions
+    drop(engine);
+
+    let actions = Arc::try_unwrap(actions)
+        .map_err(|_| ScriptError::Runtime("Script actions still in use".into()))?
+        .into_inner()
+        .map_err(|e| ScriptError::Runtime(e.to_string()))?;
+
+    Ok(actions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_packet(channel: u32, text: &str) -> ScriptPacket {
+        ScriptPacket {
+            id: 42,
+            from: 0x1234,
+            to: u32::MAX,
+            channel,
+            portnum: "TEXT_MESSAGE_APP".into(),
+            text: Some(text.into()),
+            hop_limit: 3,
+            rx_time: 1_700_000_000,
+            rx_snr: 6.5,
+            rx_rssi: -80,
+        }
+    }
+
+    const SOS_SCRIPT: &str = r#"
+        if packet.channel == 2 && packet.text != () && packet.text.contains("SOS") {
+            notify("SOS from " + packet.from, packet.text);
+            send_text(packet.channel, "SOS received, help is on the way");
+            set_node_attr(packet.from, "label", "Needs help");
+        }
+    "#;
+
+    #[test]
+    fn exposes_packet_and_actions() {
+        let actions = run_script(
+            SOS_SCRIPT,
+            &text_packet(2, "SOS at the trailhead"),
+            ScriptLimits::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            actions,
+            vec![
+                ScriptAction::Notify {
+                    title: format!("SOS from {}", 0x1234),
+                    body: "SOS at the trailhead".into(),
+                },
+                ScriptAction::SendText {
+                    channel: 2,
+                    text: "SOS received, help is on the way".into(),
+                },
+                ScriptAction::SetNodeAttr {
+                    node_num: 0x1234,
+                    attribute: NodeAttribute::Label(Some("Needs help".into())),
+                },
+            ]
+        );
+
+        let ignored = run_script(
+            SOS_SCRIPT,
+            &text_packet(0, "SOS at the trailhead"),
+            ScriptLimits::default(),
+        );
+        assert_eq!(ignored, Ok(vec![]));
+    }
+
+    #[test]
+    fn rejects_invalid_api_calls() {
+        let packet = text_packet(0, "hi");
+
+        assert!(matches!(
+            run_script(
+                r#"set_node_attr(1, "colour", "red")"#,
+                &packet,
+                ScriptLimits::default()
+            ),
+            Err(ScriptError::Runtime(_))
+        ));
+        assert!(matches!(
+            run_script(r#"send_text(-1, "hi")"#, &packet, ScriptLimits::default()),
+            Err(ScriptError::Runtime(_))
+        ));
+        assert!(matches!(
+            run_script(
+                r#"eval("notify(\"a\", \"b\")")"#,
+                &packet,
+                ScriptLimits::default()
+            ),
+            Err(ScriptError::Compile(_))
+        ));
+        assert!(matches!(
+            run_script(
+                r#"for i in 0..20 { notify("spam", "" + i) }"#,
+                &packet,
+                ScriptLimits::default()
+            ),
+            Err(ScriptError::Runtime(_))
+        ));
+        assert!(matches!(
+            run_script("send_text(0,", &packet, ScriptLimits::default()),
+            Err(ScriptError::Compile(_))
+        ));
+    }
+
+    #[test]
+    fn kills_scripts_over_time_limit() {
+        let limits = ScriptLimits {
+            time_limit: Duration::from_millis(20),
+            max_operations: 0, // unlimited, so only the time limit applies
+            ..ScriptLimits::default()
+        };
+
+        let started = Instant::now();
+        let result = run_script("loop { }", &text_packet(0, "hi"), limits);
+
+        assert_eq!(result, Err(ScriptError::TimedOut));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn limits_operations_and_allocations() {
+        let packet = text_packet(0, "hi");
+
+        assert!(matches!(
+            run_script(
+                "let s = \"x\"; loop { s += s; }",
+                &packet,
+                ScriptLimits::default()
+            ),
+            Err(ScriptError::LimitExceeded(_))
+        ));
+        assert!(matches!(
+            run_script(
+                "let n = 0; loop { n += 1; }",
+                &packet,
+                ScriptLimits {
+                    time_limit: Duration::from_secs(10),
+                    ..ScriptLimits::default()
+                }
+            ),
+            Err(ScriptError::LimitExceeded(_))
+        ));
+    }
+}
@@ -0,0 +1,426 @@
+use std::fmt;
+
+use log::{debug, trace, warn};
+use meshtastic::packet::PacketDestination;
+use meshtastic::protobufs::{self, from_radio, mesh_packet};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+use tokio::sync::mpsc;
+
+use crate::ipc::error_reporter::{AppErrorCode, ErrorReporter};
+use crate::ipc::helpers::send_text_message;
+use crate::notifications::dispatcher::{self, SystemNotification};
+use crate::notifications::preferences::NotificationCategory;
+use crate::packet_api::graph_edit::GraphEdit;
+use crate::packet_api::MeshPacketApi;
+use crate::shutdown::shutdown_signal;
+use crate::state::mesh_devices::{get_device_handle, MeshDevicesStateInner};
+use crate::state::{self, DeviceKey};
+
+use self::engine::{run_script, ScriptLimits};
+
+pub mod engine;
+
+/// Packets waiting for scripts beyond this are dropped rather than blocking packet handling
+pub const SCRIPT_QUEUE_CAPACITY: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PacketScript {
+    pub id: u32,
+    pub name: String,
+    pub source: String,
+    pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PacketScripts {
+    pub scripts: Vec<PacketScript>,
+}
+
+impl PacketScripts {
+    /// Adds an enabled script, returning it with its new id
+    pub fn add(&mut self, name: String, source: String) -> PacketScript {
+        let id = self.scripts.iter().map(|s| s.id).max().unwrap_or(0) + 1;
+
+        let script = PacketScript {
+            id,
+            name,
+            source,
+            enabled: true,
+        };
+
+        self.scripts.push(script.clone());
+        script
+    }
+
+    /// Returns whether a script with the id existed
+    pub fn set_enabled(&mut self, id: u32, enabled: bool) -> bool {
+        match self.scripts.iter_mut().find(|s| s.id == id) {
+            Some(script) => {
+                script.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns whether a script with the id existed
+    pub fn remove(&mut self, id: u32) -> bool {
+        let count = self.scripts.len();
+        self.scripts.retain(|s| s.id != id);
+        self.scripts.len() != count
+    }
+
+    pub fn has_enabled(&self) -> bool {
+        self.scripts.iter().any(|s| s.enabled)
+    }
+}
+
+/// Decoded packet fields exposed to scripts as the `packet` constant
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptPacket {
+    pub id: u32,
+    pub from: u32,
+    pub to: u32,
+    pub channel: u32,
+    pub portnum: String,
+    pub text: Option<String>, // only set for text messages
+    pub hop_limit: u32,
+    pub rx_time: u32,
+    pub rx_snr: f32,
+    pub rx_rssi: i32,
+}
+
+impl ScriptPacket {
+    /// Returns `None` for anything but decoded mesh packets
+    pub fn from_radio(packet: &protobufs::FromRadio) -> Option<Self> {
+        let packet = match packet.payload_variant.as_ref()? {
+            from_radio::PayloadVariant::Packet(packet) => packet,
+            _ => return None,
+        };
+
+        let data = match packet.payload_variant.as_ref()? {
+            mesh_packet::PayloadVariant::Decoded(data) => data,
+            mesh_packet::PayloadVariant::Encrypted(_) => return None,
+        };
+
+        let portnum = protobufs::PortNum::from_i32(data.portnum)?;
+
+        let text = match portnum {
+            protobufs::PortNum::TextMessageApp => String::from_utf8(data.payload.clone()).ok(),
+            _ => None,
+        };
+
+        Some(Self {
+            id: packet.id,
+            from: packet.from,
+            to: packet.to,
+            channel: packet.channel,
+            portnum: portnum.as_str_name().into(),
+            text,
+            hop_limit: packet.hop_limit,
+            rx_time: packet.rx_time,
+            rx_snr: packet.rx_snr,
+            rx_rssi: packet.rx_rssi,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", tag = "type", content = "value")]
+pub enum NodeAttribute {
+    Label(Option<String>), // `()` clears the label
+    Hidden(bool),
+}
+
+/// Something a script asked for, performed once the script finishes successfully
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum ScriptAction {
+    #[serde(rename_all = "camelCase")]
+    SendText { channel: u32, text: String },
+
+    #[serde(rename_all = "camelCase")]
+    Notify { title: String, body: String },
+
+    #[serde(rename_all = "camelCase")]
+    SetNodeAttr {
+        node_num: u32,
+        attribute: NodeAttribute,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScriptError {
+    Compile(String),
+    TimedOut,
+    LimitExceeded(String),
+    Runtime(String),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScriptError::Compile(e) => write!(f, "Script failed to compile: {}", e),
+            ScriptError::TimedOut => f.write_str("Script ran for too long and was stopped"),
+            ScriptError::LimitExceeded(e) => write!(f, "Script exceeded its limits: {}", e),
+            ScriptError::Runtime(e) => write!(f, "Script failed: {}", e),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ScriptJob {
+    pub device_key: DeviceKey,
+    pub packet: ScriptPacket,
+}
+
+pub fn script_queue() -> (mpsc::Sender<ScriptJob>, mpsc::Receiver<ScriptJob>) {
+    mpsc::channel(SCRIPT_QUEUE_CAPACITY)
+}
+
+/// Queues a received packet for the enabled scripts without waiting on them, so a slow
+/// or broken script can't hold up the decoded packet handler
+pub fn tap_packet_scripts<R: tauri::Runtime>(
+    packet_api: &MeshPacketApi<R>,
+    packet: &protobufs::FromRadio,
+) {
+    let scripts = match packet_api
+        .app_handle
+        .try_state::<state::packet_scripts::PacketScriptsState>()
+    {
+        Some(scripts) => scripts,
+        None => return,
+    };
+
+    match scripts.inner.lock() {
+        Ok(config) if !config.has_enabled() => return,
+        Ok(_) => {}
+        Err(e) => {
+            warn!("Failed to lock packet scripts: {}", e);
+            return;
+        }
+    }
+
+    // Scripts only react to other nodes, so they can't trigger themselves by sending
+    let packet = match ScriptPacket::from_radio(packet) {
+        Some(packet) if packet.from != packet_api.device.my_node_info.my_node_num => packet,
+        _ => return,
+    };
+
+    let job = ScriptJob {
+        device_key: packet_api.device_key.clone(),
+        packet,
+    };
+
+    if let Err(e) = scripts.queue.try_send(job) {
+        warn!("Dropped packet for scripts: {}", e);
+    }
+}
+
+/// Carries out an action a script asked for, through the device whose packet the
+/// script ran on
+pub async fn perform_action<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    devices: &MeshDevicesStateInner<R>,
+    device_key: &DeviceKey,
+    action: ScriptAction,
+) -> Result<(), String> {
+    trace!("Performing script action {:?}", action);
+
+    match action {
+        ScriptAction::SendText { channel, text } => send_text_message(
+            handle,
+            devices,
+            device_key,
+            text,
+            PacketDestination::Broadcast,
+            channel,
+            true,
+        )
+        .await
+        .map_err(|e| e.to_string()),
+        ScriptAction::Notify { title, body } => {
+            dispatcher::enqueue(
+                handle,
+                SystemNotification {
+                    category: NotificationCategory::Script,
+                    node_num: None,
+                    title,
+                    body,
+                    group: None,
+                },
+            );
+
+            Ok(())
+        }
+        ScriptAction::SetNodeAttr {
+            node_num,
+            attribute,
+        } => {
+            let device = get_device_handle(devices, device_key)
+                .await
+                .ok_or("Device not connected")?;
+
+            let edit = match attribute {
+                NodeAttribute::Label(label) => GraphEdit::SetNodeLabel { node_num, label },
+                NodeAttribute::Hidden(hidden) => GraphEdit::SetNodeHidden { node_num, hidden },
+            };
+
+            device.edit_graph(edit).await.map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Runs the enabled scripts against queued packets, one packet at a time. Scripts
+/// run on the blocking thread pool since they're CPU bound until their time limit.
+pub fn spawn_script_worker(handle: tauri::AppHandle, mut queue: mpsc::Receiver<ScriptJob>) {
+    trace!("Spawning packet script worker");
+
+    tauri::async_runtime::spawn(async move {
+        let shutdown = shutdown_signal(&handle);
+
+        while let Some(job) = shutdown.recv(&mut queue).await {
+            let scripts: Vec<PacketScript> = {
+                let state = handle.state::<state::packet_scripts::PacketScriptsState>();
+                let config = match state.inner.lock() {
+                    Ok(config) => config,
+                    Err(e) => {
+                        warn!("Failed to lock packet scripts: {}", e);
+                        continue;
+                    }
+                };
+
+                config
+                    .scripts
+                    .iter()
+                    .filter(|s| s.enabled)
+                    .cloned()
+                    .collect()
+            };
+
+            let reporter = ErrorReporter::new(&handle, module_path!()).with_device(&job.device_key);
+            let mesh_devices = handle.state::<state::mesh_devices::MeshDevicesState>();
+
+            for script in scripts {
+                let packet = job.packet.clone();
+                let source = script.source.clone();
+
+                let result = tokio::task::spawn_blocking(move || {
+                    run_script(&source, &packet, ScriptLimits::default())
+                })
+                .await;
+
+                let actions = match result {
+                    Ok(Ok(actions)) => actions,
+                    Ok(Err(e)) => {
+                        reporter.warning(
+                            AppErrorCode::ScriptFailed,
+                            format!("Script \"{}\": {}", script.name, e),
+                        );
+                        continue;
+                    }
+                    Err(e) => {
+                        reporter.error(
+                            AppErrorCode::ScriptFailed,
+                            format!("Script \"{}\" panicked: {}", script.name, e),
+                        );
+                        continue;
+                    }
+                };
+
+                debug!(
+                    "Script \"{}\" took {} actions for packet {}",
+                    script.name,
+                    actions.len(),
+                    job.packet.id
+                );
+
+                for action in actions {
+                    if let Err(e) =
+                        perform_action(&handle, &mesh_devices.inner, &job.device_key, action).await
+                    {
+                        reporter.warning(
+                            AppErrorCode::ScriptFailed,
+                            format!("Script \"{}\" action failed: {}", script.name, e),
+                        );
+                    }
+                }
+            }
+        }
+
+        trace!("Stopped packet script worker");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::Message;
+
+    use super::*;
+
+    fn from_radio(portnum: protobufs::PortNum, payload: Vec<u8>) -> protobufs::FromRadio {
+        protobufs::FromRadio {
+            payload_variant: Some(from_radio::PayloadVariant::Packet(protobufs::MeshPacket {
+                from: 0x1234,
+                to: u32::MAX,
+                channel: 2,
+                id: 9,
+                payload_variant: Some(mesh_packet::PayloadVariant::Decoded(protobufs::Data {
+                    portnum: portnum as i32,
+                    payload,
+                    ..Default::default()
+                })),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn converts_decoded_packets_for_scripts() {
+        let text = ScriptPacket::from_radio(&from_radio(
+            protobufs::PortNum::TextMessageApp,
+            b"SOS".to_vec(),
+        ))
+        .unwrap();
+
+        assert_eq!(text.from, 0x1234);
+        assert_eq!(text.channel, 2);
+        assert_eq!(text.portnum, "TEXT_MESSAGE_APP");
+        assert_eq!(text.text.as_deref(), Some("SOS"));
+
+        let position = ScriptPacket::from_radio(&from_radio(
+            protobufs::PortNum::PositionApp,
+            protobufs::Position::default().encode_to_vec(),
+        ))
+        .unwrap();
+
+        assert_eq!(position.portnum, "POSITION_APP");
+        assert_eq!(position.text, None);
+
+        assert!(ScriptPacket::from_radio(&protobufs::FromRadio::default()).is_none());
+    }
+
+    #[test]
+    fn manages_scripts() {
+        let mut scripts = PacketScripts::default();
+
+        let first = scripts.add("SOS".into(), "notify(\"a\", \"b\")".into());
+        let second = scripts.add("Echo".into(), String::new());
+        assert_eq!((first.id, second.id), (1, 2));
+        assert!(scripts.has_enabled());
+
+        assert!(scripts.set_enabled(1, false));
+        assert!(scripts.remove(2));
+        assert!(!scripts.remove(2));
+        assert!(!scripts.has_enabled());
+
+        // New ids follow the highest remaining one
+        assert_eq!(scripts.add("Third".into(), String::new()).id, 2);
+    }
+}
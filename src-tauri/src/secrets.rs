@@ -0,0 +1,532 @@
+//! Encryption of secrets, such as channel PSKs, before they're persisted. Secrets are
+//! sealed with XChaCha20-Poly1305 under a key kept in the OS keychain, or derived from
+//! a passphrase where there's no keychain. The key is only loaded once a stored secret
+//! is needed, and is never written to the app data directory.
+
+use std::fmt;
+use std::path::Path;
+
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use log::{debug, info, warn};
+use meshtastic::ts::specta::{self, Type};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::device::config_cache::reencrypt_cached_configs;
+use crate::persistence::{load_json, save_json, SECRETS_KEY_FILE_NAME};
+use crate::profiles::{active_profile_name, profile_dir_name, DEFAULT_PROFILE_NAME};
+use crate::state;
+
+pub const SECRET_KEY_LEN: usize = 32;
+pub const NONCE_LEN: usize = 24;
+pub const SALT_LEN: usize = 16;
+
+const KEYCHAIN_SERVICE: &str = "meshtastic-network-management-client";
+
+/// Encrypted with each key, so a wrong key or passphrase is caught when it's loaded
+/// rather than when a secret is next needed
+const KEY_CHECK_CONTEXT: &str = "key check";
+const KEY_CHECK_PLAINTEXT: &[u8] = b"meshtastic secrets";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SecretError {
+    /// The secret failed authentication, so it was sealed under a different key or has
+    /// been modified since
+    WrongKey,
+    KeyMismatch {
+        key_id: u32,
+        current: u32,
+    },
+    /// The key is derived from a passphrase that hasn't been entered yet
+    Locked,
+    Failed(String),
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretError::WrongKey => write!(
+                f,
+                "Stored secrets could not be decrypted, the key or passphrase is wrong"
+            ),
+            SecretError::KeyMismatch { key_id, current } => write!(
+                f,
+                "Stored secret was encrypted with key {}, but the current key is {}",
+                key_id, current
+            ),
+            SecretError::Locked => write!(f, "Enter the secrets passphrase to unlock them"),
+            SecretError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for SecretError {
+    fn from(message: String) -> Self {
+        SecretError::Failed(message)
+    }
+}
+
+impl From<SecretError> for String {
+    fn from(err: SecretError) -> Self {
+        err.to_string()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretKey {
+    pub id: u32, // incremented on each rotation
+    bytes: [u8; SECRET_KEY_LEN],
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SecretKey {
+    pub fn generate(id: u32) -> Self {
+        let mut bytes = [0; SECRET_KEY_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut bytes);
+
+        Self { id, bytes }
+    }
+
+    pub fn from_passphrase(id: u32, passphrase: &str, salt: &[u8]) -> Result<Self, SecretError> {
+        if passphrase.is_empty() {
+            return Err("Secrets passphrase can't be empty".to_string().into());
+        }
+
+        let mut bytes = [0; SECRET_KEY_LEN];
+
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut bytes)
+            .map_err(|e| format!("Failed to derive key from passphrase: {}", e))?;
+
+        Ok(Self { id, bytes })
+    }
+
+    fn to_hex(&self) -> String {
+        self.bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn from_hex(id: u32, hex: &str) -> Result<Self, SecretError> {
+        let malformed = || SecretError::Failed(format!("Key {} in the keychain is malformed", id));
+
+        if hex.len() != SECRET_KEY_LEN * 2 || !hex.is_ascii() {
+            return Err(malformed());
+        }
+
+        let mut bytes = [0; SECRET_KEY_LEN];
+
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| malformed())?;
+        }
+
+        Ok(Self { id, bytes })
+    }
+}
+
+/// A secret sealed under a key. `context` names what the secret is, e.g. the node it
+/// belongs to, and has to match when it's opened, so a sealed secret can't be moved
+/// to another record.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptedSecret {
+    pub key_id: u32,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>, // includes the authentication tag
+}
+
+pub fn encrypt_secret(
+    key: &SecretKey,
+    plaintext: &[u8],
+    context: &str,
+) -> Result<EncryptedSecret, SecretError> {
+    let mut nonce = [0; NONCE_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut nonce);
+
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(&key.bytes));
+    let ciphertext = cipher
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: context.as_bytes(),
+            },
+        )
+        .map_err(|_| SecretError::Failed("Failed to encrypt secret".into()))?;
+
+    Ok(EncryptedSecret {
+        key_id: key.id,
+        nonce: nonce.to_vec(),
+        ciphertext,
+    })
+}
+
+pub fn decrypt_secret(
+    key: &SecretKey,
+    secret: &EncryptedSecret,
+    context: &str,
+) -> Result<Vec<u8>, SecretError> {
+    if secret.key_id != key.id {
+        return Err(SecretError::KeyMismatch {
+            key_id: secret.key_id,
+            current: key.id,
+        });
+    }
+
+    if secret.nonce.len() != NONCE_LEN {
+        return Err(SecretError::Failed("Stored secret is malformed".into()));
+    }
+
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(&key.bytes));
+
+    cipher
+        .decrypt(
+            XNonce::from_slice(&secret.nonce),
+            Payload {
+                msg: &secret.ciphertext,
+                aad: context.as_bytes(),
+            },
+        )
+        .map_err(|_| SecretError::WrongKey)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum KeySource {
+    Keychain,
+    Passphrase,
+}
+
+/// Describes the current key without containing it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretKeyInfo {
+    pub key_id: u32,
+    pub source: KeySource,
+    pub salt: Vec<u8>, // passphrase keys only
+    pub check: EncryptedSecret,
+}
+
+impl SecretKeyInfo {
+    pub fn new(key: &SecretKey, source: KeySource, salt: Vec<u8>) -> Result<Self, SecretError> {
+        Ok(Self {
+            key_id: key.id,
+            source,
+            salt,
+            check: encrypt_secret(key, KEY_CHECK_PLAINTEXT, KEY_CHECK_CONTEXT)?,
+        })
+    }
+
+    pub fn verify(&self, key: &SecretKey) -> Result<(), SecretError> {
+        decrypt_secret(key, &self.check, KEY_CHECK_CONTEXT).map(|_| ())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretsStatus {
+    pub source: Option<KeySource>, // none until a secret has been stored
+    pub key_id: Option<u32>,
+    pub unlocked: bool,
+}
+
+fn new_salt() -> Vec<u8> {
+    let mut salt = vec![0; SALT_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+/// Each profile has its own keys. The default profile's keep the names they had
+/// before profiles existed.
+fn keychain_entry(profile: &str, key_id: u32) -> Result<keyring::Entry, SecretError> {
+    let user = match profile {
+        DEFAULT_PROFILE_NAME => format!("at-rest-key-{}", key_id),
+        profile => format!("{}-at-rest-key-{}", profile_dir_name(profile), key_id),
+    };
+
+    keyring::Entry::new(KEYCHAIN_SERVICE, &user)
+        .map_err(|e| SecretError::Failed(format!("Keychain unavailable: {}", e)))
+}
+
+fn read_keychain_key(profile: &str, key_id: u32) -> Result<SecretKey, SecretError> {
+    let hex = keychain_entry(profile, key_id)?
+        .get_password()
+        .map_err(|e| format!("Failed to read key {} from the keychain: {}", key_id, e))?;
+
+    SecretKey::from_hex(key_id, &hex)
+}
+
+fn write_keychain_key(profile: &str, key: &SecretKey) -> Result<(), SecretError> {
+    keychain_entry(profile, key.id)?
+        .set_password(&key.to_hex())
+        .map_err(|e| format!("Failed to store key {} in the keychain: {}", key.id, e).into())
+}
+
+fn delete_keychain_key(profile: &str, key_id: u32) {
+    if let Err(e) = keychain_entry(profile, key_id).and_then(|entry| {
+        entry
+            .delete_password()
+            .map_err(|e| SecretError::Failed(e.to_string()))
+    }) {
+        warn!("Failed to remove key {} from the keychain: {}", key_id, e);
+    }
+}
+
+fn load_key_info<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+) -> Result<Option<SecretKeyInfo>, SecretError> {
+    load_json(handle, SECRETS_KEY_FILE_NAME).map_err(SecretError::Failed)
+}
+
+pub fn secrets_status<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+) -> Result<SecretsStatus, SecretError> {
+    let info = load_key_info(handle)?;
+
+    let secrets_state = handle.state::<state::secrets::SecretsState>();
+    let key_guard = secrets_state.inner.lock().map_err(|e| e.to_string())?;
+
+    Ok(SecretsStatus {
+        source: info.as_ref().map(|info| info.source),
+        key_id: info.as_ref().map(|info| info.key_id),
+        unlocked: key_guard.is_some(),
+    })
+}
+
+/// Returns the current key, loading it from the keychain on first use. If no key
+/// exists yet one is created in the keychain.
+pub fn secret_key<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+) -> Result<SecretKey, SecretError> {
+    let secrets_state = handle.state::<state::secrets::SecretsState>();
+    let mut key_guard = secrets_state.inner.lock().map_err(|e| e.to_string())?;
+
+    if let Some(key) = key_guard.as_ref() {
+        return Ok(key.clone());
+    }
+
+    let key = match load_key_info(handle)? {
+        Some(info) if info.source == KeySource::Passphrase => return Err(SecretError::Locked),
+        Some(info) => {
+            let key = read_keychain_key(&active_profile_name(handle), info.key_id)?;
+            info.verify(&key)?;
+            key
+        }
+        None => {
+            info!("Creating key for stored secrets in the keychain");
+
+            let key = SecretKey::generate(1);
+            write_keychain_key(&active_profile_name(handle), &key).map_err(|e| {
+                warn!("{}", e);
+                SecretError::Locked // a passphrase can be set instead
+            })?;
+
+            save_json(
+                handle,
+                SECRETS_KEY_FILE_NAME,
+                &SecretKeyInfo::new(&key, KeySource::Keychain, vec![])?,
+            )?;
+
+            key
+        }
+    };
+
+    debug!("Loaded key {} for stored secrets", key.id);
+    *key_guard = Some(key.clone());
+
+    Ok(key)
+}
+
+/// Derives the key from a passphrase, for systems without a keychain. The first
+/// passphrase entered sets the key.
+pub fn unlock_secrets<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    passphrase: &str,
+) -> Result<(), SecretError> {
+    let key = match load_key_info(handle)? {
+        Some(info) if info.source == KeySource::Keychain => {
+            return Err(
+                "Stored secrets are protected by the keychain, not a passphrase"
+                    .to_string()
+                    .into(),
+            );
+        }
+        Some(info) => {
+            let key = SecretKey::from_passphrase(info.key_id, passphrase, &info.salt)?;
+            info.verify(&key)?;
+            key
+        }
+        None => {
+            let salt = new_salt();
+            let key = SecretKey::from_passphrase(1, passphrase, &salt)?;
+
+            save_json(
+                handle,
+                SECRETS_KEY_FILE_NAME,
+                &SecretKeyInfo::new(&key, KeySource::Passphrase, salt)?,
+            )?;
+
+            key
+        }
+    };
+
+    let secrets_state = handle.state::<state::secrets::SecretsState>();
+    *secrets_state.inner.lock().map_err(|e| e.to_string())? = Some(key);
+
+    Ok(())
+}
+
+/// Re-encrypts every stored secret under a new key, returning the number of records
+/// re-encrypted. The new key is derived from `passphrase` if one is given, and kept in
+/// the keychain otherwise.
+pub fn rotate_secret_key<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    passphrase: Option<&str>,
+) -> Result<u32, SecretError> {
+    let old_key = secret_key(handle)?;
+    let old_info = load_key_info(handle)?.ok_or("No key to rotate".to_string())?;
+
+    let new_id = old_key.id + 1;
+
+    let (new_key, new_info) = match passphrase {
+        Some(passphrase) => {
+            let salt = new_salt();
+            let key = SecretKey::from_passphrase(new_id, passphrase, &salt)?;
+            (
+                key.clone(),
+                SecretKeyInfo::new(&key, KeySource::Passphrase, salt)?,
+            )
+        }
+        None => {
+            let key = SecretKey::generate(new_id);
+            write_keychain_key(&active_profile_name(handle), &key)?;
+            (
+                key.clone(),
+                SecretKeyInfo::new(&key, KeySource::Keychain, vec![])?,
+            )
+        }
+    };
+
+    info!("Rotating key for stored secrets to key {}", new_id);
+
+    let reencrypted = reencrypt_cached_configs(handle, &old_key, &new_key)?;
+    save_json(handle, SECRETS_KEY_FILE_NAME, &new_info)?;
+
+    let secrets_state = handle.state::<state::secrets::SecretsState>();
+    *secrets_state.inner.lock().map_err(|e| e.to_string())? = Some(new_key);
+
+    if old_info.source == KeySource::Keychain {
+        delete_keychain_key(&active_profile_name(handle), old_info.key_id);
+    }
+
+    Ok(reencrypted)
+}
+
+/// Removes a deleted profile's key from the keychain, given the profile's directory
+pub fn delete_profile_key(profile: &str, profile_dir: &Path) {
+    let path = profile_dir.join(SECRETS_KEY_FILE_NAME);
+
+    let info = match std::fs::read(&path) {
+        Ok(contents) => serde_json::from_slice::<SecretKeyInfo>(&contents),
+        Err(_) => return, // no secrets were stored
+    };
+
+    match info {
+        Ok(info) if info.source == KeySource::Keychain => delete_keychain_key(profile, info.key_id),
+        Ok(_) => {}
+        Err(e) => warn!("Failed to read secrets key info of \"{}\": {}", profile, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_ciphertext_and_round_trips() {
+        let key = SecretKey::generate(1);
+        let psk = [0xd4, 0xf1, 0xbb, 0x3a, 0x20, 0x29, 0x07, 0x59];
+
+        let sealed = encrypt_secret(&key, &psk, "node 1 secrets").unwrap();
+        assert_eq!(sealed.key_id, 1);
+        assert_eq!(sealed.nonce.len(), NONCE_LEN);
+        assert!(!sealed
+            .ciphertext
+            .windows(psk.len())
+            .any(|window| window == psk));
+
+        // Sealing the same secret twice uses a fresh nonce
+        let resealed = encrypt_secret(&key, &psk, "node 1 secrets").unwrap();
+        assert_ne!(sealed.ciphertext, resealed.ciphertext);
+
+        assert_eq!(
+            decrypt_secret(&key, &sealed, "node 1 secrets").unwrap(),
+            psk
+        );
+
+        let stored = serde_json::to_string(&sealed).unwrap();
+        let reloaded: EncryptedSecret = serde_json::from_str(&stored).unwrap();
+        assert_eq!(
+            decrypt_secret(&key, &reloaded, "node 1 secrets").unwrap(),
+            psk
+        );
+
+        // A secret moved to another record doesn't open
+        assert_eq!(
+            decrypt_secret(&key, &sealed, "node 2 secrets"),
+            Err(SecretError::WrongKey)
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_key() {
+        let key = SecretKey::generate(1);
+        let sealed = encrypt_secret(&key, b"hunter2", "node 1 secrets").unwrap();
+
+        assert_eq!(
+            decrypt_secret(&SecretKey::generate(1), &sealed, "node 1 secrets"),
+            Err(SecretError::WrongKey)
+        );
+        assert_eq!(
+            decrypt_secret(&SecretKey::generate(2), &sealed, "node 1 secrets"),
+            Err(SecretError::KeyMismatch {
+                key_id: 1,
+                current: 2
+            })
+        );
+
+        let mut tampered = sealed.clone();
+        tampered.ciphertext[0] ^= 1;
+        assert_eq!(
+            decrypt_secret(&key, &tampered, "node 1 secrets"),
+            Err(SecretError::WrongKey)
+        );
+
+        // A wrong passphrase is caught by the key check
+        let salt = new_salt();
+        let passphrase_key = SecretKey::from_passphrase(1, "correct horse", &salt).unwrap();
+        let info =
+            SecretKeyInfo::new(&passphrase_key, KeySource::Passphrase, salt.clone()).unwrap();
+
+        assert_eq!(info.verify(&passphrase_key), Ok(()));
+        assert_eq!(
+            info.verify(&SecretKey::from_passphrase(1, "battery staple", &salt).unwrap()),
+            Err(SecretError::WrongKey)
+        );
+
+        // The keychain copy of a key survives being read back
+        assert_eq!(
+            SecretKey::from_hex(1, &passphrase_key.to_hex()).unwrap(),
+            passphrase_key
+        );
+        assert!(SecretKey::from_hex(1, "not a key").is_err());
+    }
+}
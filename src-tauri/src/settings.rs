@@ -0,0 +1,1041 @@
+//! App-wide settings, persisted as a single versioned JSON file. Settings are changed
+//! with partial patches, and applied to the subsystems that read them whenever they
+//! change, so new values take effect without a restart.
+
+use std::collections::HashMap;
+use std::sync::MutexGuard;
+use std::time::Duration;
+
+use log::{debug, warn};
+use meshtastic::ts::specta::{self, Type};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tauri::Manager;
+
+use crate::device::config_cache::forget_cached_secrets;
+use crate::device::fixed_position::StoredFixedPosition;
+use crate::device::telemetry_store::TelemetryMetricClass;
+use crate::export::analytics_report::{AnalyticsMetric, AnalyticsSection, ANALYTICS_METRICS};
+use crate::graph::ds::edge_weight::EdgeWeightStrategy;
+use crate::graph::ds::graph::MeshGraph;
+use crate::ipc::events;
+use crate::ipc::events::coalesce::{EventCoalescer, DEFAULT_EVENT_COALESCING_INTERVAL};
+use crate::notifications::rules::NotificationThresholds;
+use crate::persistence::{save_json, SETTINGS_FILE_NAME};
+use crate::retention::StorageClass;
+use crate::state;
+use crate::state::DeviceKey;
+
+/// Version of the settings file, bumped when a change needs stored settings migrated
+pub const SETTINGS_VERSION: u32 = 1;
+
+/// Longer intervals would leave the map noticeably out of date
+pub const MAX_EVENT_COALESCING_INTERVAL_MS: u32 = 10_000;
+
+pub const MAX_AUTOSAVE_INTERVAL_MINS: u32 = 24 * 60;
+pub const MAX_AUTOSAVES_KEPT: u32 = 50;
+
+pub const MAX_BACKUP_INTERVAL_HOURS: u32 = 30 * 24;
+pub const MAX_BACKUPS_KEPT: u32 = 100;
+
+pub const MAX_TELEMETRY_RETENTION_DAYS: u32 = 10 * 365;
+pub const MAX_POSITION_RETENTION_DAYS: u32 = 10 * 365;
+
+pub const MAX_RETENTION_AGE_DAYS: u32 = 10 * 365;
+pub const MAX_RETENTION_SIZE_MB: u32 = 100_000;
+
+pub const MAX_RECONNECT_ATTEMPTS: u32 = 100;
+
+/// Analytics of a large mesh take long enough that more frequent updates would keep a
+/// core busy
+pub const MIN_ANALYTICS_REFRESH_SECS: u32 = 30;
+pub const MAX_ANALYTICS_REFRESH_SECS: u32 = 24 * 60 * 60;
+
+pub const MAX_ACK_TIMEOUT_SECS: u32 = 60 * 60;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EventSettings {
+    /// Minimum time between device and graph update events, zero emits every update
+    pub coalescing_interval_ms: u32,
+}
+
+impl Default for EventSettings {
+    fn default() -> Self {
+        Self {
+            coalescing_interval_ms: DEFAULT_EVENT_COALESCING_INTERVAL.as_millis() as u32,
+        }
+    }
+}
+
+impl EventSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.coalescing_interval_ms > MAX_EVENT_COALESCING_INTERVAL_MS {
+            return Err(format!(
+                "Event coalescing interval must be at most {}ms",
+                MAX_EVENT_COALESCING_INTERVAL_MS
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn apply(&self, coalescer: &mut EventCoalescer) {
+        coalescer.set_interval(Duration::from_millis(self.coalescing_interval_ms.into()));
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NotificationSettings {
+    pub thresholds: NotificationThresholds,
+}
+
+impl NotificationSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.thresholds.low_battery_percent > 100 {
+            return Err("Low battery threshold must be a percentage between 0 and 100".into());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AutosaveSettings {
+    /// Time between autosaves of the connected devices' graphs
+    pub interval_mins: u32,
+
+    /// Number of autosaves kept, older ones are deleted
+    pub keep: u32,
+}
+
+impl Default for AutosaveSettings {
+    fn default() -> Self {
+        Self {
+            interval_mins: 5,
+            keep: 5,
+        }
+    }
+}
+
+impl AutosaveSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_AUTOSAVE_INTERVAL_MINS).contains(&self.interval_mins) {
+            return Err(format!(
+                "Autosave interval must be between 1 and {} minutes",
+                MAX_AUTOSAVE_INTERVAL_MINS
+            ));
+        }
+
+        if !(1..=MAX_AUTOSAVES_KEPT).contains(&self.keep) {
+            return Err(format!(
+                "Between 1 and {} autosaves must be kept",
+                MAX_AUTOSAVES_KEPT
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(u64::from(self.interval_mins) * 60)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BackupSettings {
+    /// Whether the databases are backed up on the interval, backups can still be
+    /// taken on request when this is off
+    pub enabled: bool,
+
+    pub interval_hours: u32,
+
+    /// Absolute path of the directory backups are written to, `None` for a directory
+    /// in the profile's data directory
+    pub directory: Option<String>,
+
+    /// Number of backups of each profile kept, older ones are deleted
+    pub keep: u32,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_hours: 24,
+            directory: None,
+            keep: 7,
+        }
+    }
+}
+
+impl BackupSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_BACKUP_INTERVAL_HOURS).contains(&self.interval_hours) {
+            return Err(format!(
+                "Backup interval must be between 1 and {} hours",
+                MAX_BACKUP_INTERVAL_HOURS
+            ));
+        }
+
+        if !(1..=MAX_BACKUPS_KEPT).contains(&self.keep) {
+            return Err(format!(
+                "Between 1 and {} backups must be kept",
+                MAX_BACKUPS_KEPT
+            ));
+        }
+
+        if let Some(directory) = &self.directory {
+            if !std::path::Path::new(directory).is_absolute() {
+                return Err("Backup directory must be an absolute path".into());
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(u64::from(self.interval_hours) * 60 * 60)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TelemetrySettings {
+    /// Days battery, voltage and airtime samples are kept for
+    pub device_retention_days: u32,
+
+    /// Days environment sensor samples are kept for
+    pub environment_retention_days: u32,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self {
+            device_retention_days: 30,
+            environment_retention_days: 90,
+        }
+    }
+}
+
+impl TelemetrySettings {
+    pub fn validate(&self) -> Result<(), String> {
+        for days in [self.device_retention_days, self.environment_retention_days] {
+            if !(1..=MAX_TELEMETRY_RETENTION_DAYS).contains(&days) {
+                return Err(format!(
+                    "Telemetry retention must be between 1 and {} days",
+                    MAX_TELEMETRY_RETENTION_DAYS
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Time before which each class of samples is deleted
+    pub fn cutoffs(&self, now: u32) -> Vec<(TelemetryMetricClass, u32)> {
+        let cutoff = |days: u32| now.saturating_sub(days * 24 * 60 * 60);
+
+        vec![
+            (
+                TelemetryMetricClass::Device,
+                cutoff(self.device_retention_days),
+            ),
+            (
+                TelemetryMetricClass::Environment,
+                cutoff(self.environment_retention_days),
+            ),
+        ]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PositionSettings {
+    /// Days archived node positions are kept for after they were last reported
+    pub retention_days: u32,
+}
+
+impl Default for PositionSettings {
+    fn default() -> Self {
+        Self {
+            retention_days: 180,
+        }
+    }
+}
+
+impl PositionSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_POSITION_RETENTION_DAYS).contains(&self.retention_days) {
+            return Err(format!(
+                "Position retention must be between 1 and {} days",
+                MAX_POSITION_RETENTION_DAYS
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Time before which archived positions are deleted
+    pub fn cutoff(&self, now: u32) -> u32 {
+        now.saturating_sub(self.retention_days * 24 * 60 * 60)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetentionPolicy {
+    /// Rows older than this are deleted, `None` keeps rows whatever their age
+    pub max_age_days: Option<u32>,
+
+    /// Oldest rows are deleted once the class takes up more than this, `None` doesn't
+    /// cap its size
+    pub max_size_mb: Option<u32>,
+}
+
+impl RetentionPolicy {
+    fn capped(max_size_mb: u32) -> Self {
+        Self {
+            max_age_days: None,
+            max_size_mb: Some(max_size_mb),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(days) = self.max_age_days {
+            if !(1..=MAX_RETENTION_AGE_DAYS).contains(&days) {
+                return Err(format!(
+                    "Retention age must be between 1 and {} days",
+                    MAX_RETENTION_AGE_DAYS
+                ));
+            }
+        }
+
+        if let Some(size_mb) = self.max_size_mb {
+            if !(1..=MAX_RETENTION_SIZE_MB).contains(&size_mb) {
+                return Err(format!(
+                    "Retention size must be between 1 and {} MB",
+                    MAX_RETENTION_SIZE_MB
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Time before which rows are deleted
+    pub fn cutoff(&self, now: u32) -> Option<u32> {
+        self.max_age_days
+            .map(|days| now.saturating_sub(days * 24 * 60 * 60))
+    }
+
+    pub fn max_bytes(&self) -> Option<u64> {
+        self.max_size_mb
+            .map(|size_mb| u64::from(size_mb) * 1024 * 1024)
+    }
+}
+
+/// Limits on each class of stored data. Telemetry and archived positions are also
+/// deleted after the periods in their own sections, whichever comes first.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetentionSettings {
+    pub messages: RetentionPolicy,
+    pub telemetry: RetentionPolicy,
+    pub positions: RetentionPolicy,
+    pub graphs: RetentionPolicy,
+}
+
+impl Default for RetentionSettings {
+    fn default() -> Self {
+        Self {
+            messages: RetentionPolicy::capped(500),
+            telemetry: RetentionPolicy::capped(200),
+            positions: RetentionPolicy::capped(50),
+            graphs: RetentionPolicy::capped(200),
+        }
+    }
+}
+
+impl RetentionSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        self.messages.validate()?;
+        self.telemetry.validate()?;
+        self.positions.validate()?;
+        self.graphs.validate()?;
+
+        Ok(())
+    }
+
+    pub fn policy(&self, class: StorageClass) -> RetentionPolicy {
+        match class {
+            StorageClass::Messages => self.messages,
+            StorageClass::Telemetry => self.telemetry,
+            StorageClass::Positions => self.positions,
+            StorageClass::Graphs => self.graphs,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AnalyticsSettings {
+    /// Metrics of each completed analytics report kept as a series, an empty list
+    /// keeps none
+    pub persisted_metrics: Vec<AnalyticsMetric>,
+
+    /// Seconds between scheduled analytics updates while a radio is connected, 0
+    /// turns them off. Updates are skipped while the graph hasn't changed.
+    pub refresh_interval_secs: u32,
+
+    /// Sections computed by scheduled updates
+    pub refresh_sections: Vec<AnalyticsSection>,
+}
+
+impl Default for AnalyticsSettings {
+    fn default() -> Self {
+        Self {
+            persisted_metrics: ANALYTICS_METRICS.to_vec(),
+            refresh_interval_secs: 5 * 60,
+            refresh_sections: vec![
+                AnalyticsSection::Stats,
+                AnalyticsSection::Centralities,
+                AnalyticsSection::CutPoints,
+                AnalyticsSection::Resilience,
+            ],
+        }
+    }
+}
+
+impl AnalyticsSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.refresh_interval_secs != 0
+            && !(MIN_ANALYTICS_REFRESH_SECS..=MAX_ANALYTICS_REFRESH_SECS)
+                .contains(&self.refresh_interval_secs)
+        {
+            return Err(format!(
+                "Analytics refresh interval must be 0 or between {} and {} seconds",
+                MIN_ANALYTICS_REFRESH_SECS, MAX_ANALYTICS_REFRESH_SECS
+            ));
+        }
+
+        if self.refresh_sections.is_empty() {
+            return Err("Scheduled analytics need at least one section".into());
+        }
+
+        Ok(())
+    }
+
+    /// Interval of scheduled analytics updates, `None` if they're turned off
+    pub fn refresh_interval(&self) -> Option<Duration> {
+        (self.refresh_interval_secs != 0)
+            .then(|| Duration::from_secs(u64::from(self.refresh_interval_secs)))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GraphSettings {
+    /// How the graph weighs its edges, which every routing analysis goes by
+    pub edge_weight_strategy: EdgeWeightStrategy,
+}
+
+impl GraphSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        self.edge_weight_strategy.validate()
+    }
+
+    /// Reweights the graph's edges if its strategy isn't the one set
+    pub fn apply(&self, graph: &mut MeshGraph) {
+        if graph.edge_weight_strategy() != &self.edge_weight_strategy {
+            graph.set_edge_weight_strategy(self.edge_weight_strategy.clone());
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ConnectionSettings {
+    /// Attempts made to reconnect to a radio whose connection was lost, e.g. by its
+    /// cable being unplugged, before giving up. 0 turns reconnecting off.
+    pub reconnect_attempts: u32,
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> Self {
+        Self {
+            reconnect_attempts: 10,
+        }
+    }
+}
+
+impl ConnectionSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.reconnect_attempts > MAX_RECONNECT_ATTEMPTS {
+            return Err(format!(
+                "At most {} reconnect attempts can be made",
+                MAX_RECONNECT_ATTEMPTS
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FixedPositionSettings {
+    /// Fixed position set on each device, by device key. Set and cleared by the
+    /// fixed position commands.
+    pub devices: HashMap<DeviceKey, StoredFixedPosition>,
+}
+
+impl FixedPositionSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        self.devices
+            .values()
+            .try_for_each(|stored| stored.position.validate())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MessagingSettings {
+    /// Seconds a message sent with `want_ack` waits for its ack before it's marked as
+    /// failed. The radio gives up on its own after its retransmits. 0 waits on the
+    /// radio alone.
+    pub ack_timeout_secs: u32,
+}
+
+impl Default for MessagingSettings {
+    fn default() -> Self {
+        Self {
+            ack_timeout_secs: 120,
+        }
+    }
+}
+
+impl MessagingSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.ack_timeout_secs > MAX_ACK_TIMEOUT_SECS {
+            return Err(format!(
+                "Ack timeout must be at most {} seconds",
+                MAX_ACK_TIMEOUT_SECS
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn ack_timeout(&self) -> Option<Duration> {
+        (self.ack_timeout_secs != 0).then(|| Duration::from_secs(u64::from(self.ack_timeout_secs)))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SecretsSettings {
+    /// Whether channel PSKs and other device secrets are stored, encrypted, with the
+    /// cached device configs. Turning this off removes the stored secrets.
+    pub persist_secrets: bool,
+}
+
+impl Default for SecretsSettings {
+    fn default() -> Self {
+        Self {
+            persist_secrets: true,
+        }
+    }
+}
+
+/// Sections missing from a stored file, e.g. one written before they were added,
+/// are filled with their defaults
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AppSettings {
+    pub version: u32,
+    pub events: EventSettings,
+    pub notifications: NotificationSettings,
+    pub autosave: AutosaveSettings,
+    pub backups: BackupSettings,
+    pub telemetry: TelemetrySettings,
+    pub positions: PositionSettings,
+    pub retention: RetentionSettings,
+    pub analytics: AnalyticsSettings,
+    pub graph: GraphSettings,
+    pub connections: ConnectionSettings,
+    pub fixed_positions: FixedPositionSettings,
+    pub messaging: MessagingSettings,
+    pub secrets: SecretsSettings,
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            events: EventSettings::default(),
+            notifications: NotificationSettings::default(),
+            autosave: AutosaveSettings::default(),
+            backups: BackupSettings::default(),
+            telemetry: TelemetrySettings::default(),
+            positions: PositionSettings::default(),
+            retention: RetentionSettings::default(),
+            analytics: AnalyticsSettings::default(),
+            graph: GraphSettings::default(),
+            connections: ConnectionSettings::default(),
+            fixed_positions: FixedPositionSettings::default(),
+            messaging: MessagingSettings::default(),
+            secrets: SecretsSettings::default(),
+        }
+    }
+}
+
+impl AppSettings {
+    /// Reads settings saved by this or an earlier version of the app. Files without
+    /// a version are read as the first version.
+    pub fn from_stored(stored: Value) -> Result<Self, String> {
+        let version = match stored.get("version") {
+            Some(version) => version
+                .as_u64()
+                .ok_or("Settings version must be a number")?,
+            None => 1,
+        };
+
+        if version > SETTINGS_VERSION as u64 {
+            return Err(format!(
+                "Settings are from a newer version of the app (version {})",
+                version
+            ));
+        }
+
+        let mut settings: Self = serde_json::from_value(stored).map_err(|e| e.to_string())?;
+        settings.version = SETTINGS_VERSION;
+        settings.validate()?;
+
+        Ok(settings)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.events.validate()?;
+        self.notifications.validate()?;
+        self.autosave.validate()?;
+        self.backups.validate()?;
+        self.telemetry.validate()?;
+        self.positions.validate()?;
+        self.retention.validate()?;
+        self.analytics.validate()?;
+        self.graph.validate()?;
+        self.connections.validate()?;
+        self.fixed_positions.validate()?;
+        self.messaging.validate()?;
+
+        Ok(())
+    }
+
+    /// Applies each field of `patch` on its own, keeping the fields that are valid
+    /// and reporting the path of each one that isn't
+    pub fn apply_patch(&self, patch: &Value) -> Result<(Self, Vec<SettingsFieldError>), String> {
+        let mut accepted = serde_json::to_value(self).map_err(|e| e.to_string())?;
+        let mut settings = self.clone();
+        let mut errors = vec![];
+
+        let mut fields = vec![];
+        patch_fields(patch, &mut vec![], &mut fields)
+            .map_err(|_| "Settings patch must be an object")?;
+
+        for (path, value) in fields {
+            let result = match path.as_slice() {
+                [field] if field == "version" => Err("Setting is read-only".into()),
+                _ => set_field(&accepted, &path, value).and_then(|candidate| {
+                    let candidate_settings: Self =
+                        serde_json::from_value(candidate.clone()).map_err(|e| e.to_string())?;
+                    candidate_settings.validate()?;
+
+                    Ok((candidate, candidate_settings))
+                }),
+            };
+
+            match result {
+                Ok((candidate, candidate_settings)) => {
+                    accepted = candidate;
+                    settings = candidate_settings;
+                }
+                Err(message) => errors.push(SettingsFieldError {
+                    path: path.join("."),
+                    message,
+                }),
+            }
+        }
+
+        Ok((settings, errors))
+    }
+}
+
+/// Collects the path and value of each leaf of a patch. Arrays are set as a whole.
+fn patch_fields(
+    patch: &Value,
+    path: &mut Vec<String>,
+    fields: &mut Vec<(Vec<String>, Value)>,
+) -> Result<(), ()> {
+    match patch {
+        Value::Object(object) => {
+            for (key, value) in object {
+                path.push(key.clone());
+                patch_fields(value, path, fields)?;
+                path.pop();
+            }
+
+            Ok(())
+        }
+        _ if path.is_empty() => Err(()),
+        value => {
+            fields.push((path.clone(), value.clone()));
+            Ok(())
+        }
+    }
+}
+
+/// Returns a copy of `settings` with the field at `path` replaced. Only existing
+/// fields can be set.
+fn set_field(settings: &Value, path: &[String], value: Value) -> Result<Value, String> {
+    let mut updated = settings.clone();
+    let mut object: &mut Map<String, Value> =
+        updated.as_object_mut().ok_or("Settings aren't an object")?;
+
+    for (i, key) in path.iter().enumerate() {
+        let field = object.get_mut(key).ok_or("Unknown setting")?;
+
+        if i == path.len() - 1 {
+            if field.is_object() {
+                return Err("Expected an object of settings".into());
+            }
+
+            *field = value;
+            break;
+        }
+
+        object = field.as_object_mut().ok_or("Unknown setting")?;
+    }
+
+    Ok(updated)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsFieldError {
+    pub path: String, // dotted path of the field in the patch, e.g. "events.coalescingIntervalMs"
+    pub message: String,
+}
+
+/// Settings after a patch, along with the fields of the patch that were rejected
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsUpdate {
+    pub settings: AppSettings,
+    pub errors: Vec<SettingsFieldError>,
+}
+
+/// Passes the settings to the subsystems that read them
+pub fn apply_settings<R: tauri::Runtime>(handle: &tauri::AppHandle<R>, settings: &AppSettings) {
+    if let Some(coalescing_state) =
+        handle.try_state::<state::event_coalescing::EventCoalescingState>()
+    {
+        match coalescing_state.inner.lock() {
+            Ok(mut coalescer) => settings.events.apply(&mut coalescer),
+            Err(e) => warn!("Failed to lock event coalescer: {}", e),
+        }
+    }
+
+    if let Some(rules_state) =
+        handle.try_state::<state::notification_rules::NotificationRulesState>()
+    {
+        match rules_state.inner.lock() {
+            Ok(mut rules) => rules.thresholds = settings.notifications.thresholds.clone(),
+            Err(e) => warn!("Failed to lock notification rules: {}", e),
+        }
+    }
+
+    if let Some(graph_state) = handle.try_state::<state::graph::GraphState>() {
+        match graph_state.inner.write() {
+            Ok(mut graph) => settings.graph.apply(&mut graph),
+            Err(e) => warn!("Failed to lock graph: {}", e),
+        }
+    }
+
+    if !settings.secrets.persist_secrets
+        && handle
+            .try_state::<state::device_configs::DeviceConfigsState>()
+            .is_some()
+    {
+        match forget_cached_secrets(handle) {
+            Ok(0) => {}
+            Ok(count) => debug!("Removed stored secrets of {} devices", count),
+            Err(e) => warn!("Failed to remove stored secrets: {}", e),
+        }
+    }
+}
+
+/// Applies a patch to the current settings. If any field was accepted the settings are
+/// saved, applied to their subsystems and announced with a `settings_changed` event.
+pub fn update_settings<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    patch: &Value,
+) -> Result<SettingsUpdate, String> {
+    let settings_state = handle.state::<state::settings::SettingsState>();
+    let mut current = settings_state.inner.lock().map_err(|e| e.to_string())?;
+
+    let (settings, errors) = current.apply_patch(patch)?;
+    store_settings(handle, current, &settings)?;
+
+    Ok(SettingsUpdate { settings, errors })
+}
+
+/// Changes the current settings with `edit`, for settings set by their own commands
+/// rather than a patch. The edited settings are stored like a patch's if they're
+/// valid and changed.
+pub fn edit_settings<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    edit: impl FnOnce(&mut AppSettings),
+) -> Result<AppSettings, String> {
+    let settings_state = handle.state::<state::settings::SettingsState>();
+    let current = settings_state.inner.lock().map_err(|e| e.to_string())?;
+
+    let mut settings = current.clone();
+    edit(&mut settings);
+    settings.validate()?;
+
+    store_settings(handle, current, &settings)?;
+
+    Ok(settings)
+}
+
+/// Saves, applies and announces `settings` if they differ from `current`
+fn store_settings<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    mut current: MutexGuard<'_, AppSettings>,
+    settings: &AppSettings,
+) -> Result<(), String> {
+    if *settings == *current {
+        return Ok(());
+    }
+
+    debug!("Settings changed");
+
+    save_json(handle, SETTINGS_FILE_NAME, settings)?;
+    *current = settings.clone();
+    drop(current);
+
+    apply_settings(handle, settings);
+    events::dispatch_settings_changed(handle, settings.clone()).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn reads_missing_and_older_files() {
+        // A file with nothing set
+        assert_eq!(
+            AppSettings::from_stored(json!({})).unwrap(),
+            AppSettings::default()
+        );
+
+        // Written before the notification settings existed, and before versioning
+        let settings =
+            AppSettings::from_stored(json!({ "events": { "coalescingIntervalMs": 200 } })).unwrap();
+
+        assert_eq!(settings.version, SETTINGS_VERSION);
+        assert_eq!(settings.events.coalescing_interval_ms, 200);
+        assert_eq!(settings.notifications, NotificationSettings::default());
+
+        assert!(AppSettings::from_stored(json!({ "version": SETTINGS_VERSION + 1 })).is_err());
+        assert!(AppSettings::from_stored(json!({
+            "events": { "coalescingIntervalMs": MAX_EVENT_COALESCING_INTERVAL_MS + 1 }
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn keeps_valid_fields_of_patch() {
+        let settings = AppSettings::default();
+
+        let (patched, errors) = settings
+            .apply_patch(&json!({
+                "version": 7,
+                "events": { "coalescingIntervalMs": 250, "unknown": true },
+                "notifications": {
+                    "thresholds": {
+                        "lowBatteryPercent": 150,
+                        "nodeOfflineMins": 30,
+                        "cooldownMins": "soon",
+                    },
+                },
+            }))
+            .unwrap();
+
+        assert_eq!(patched.version, SETTINGS_VERSION);
+        assert_eq!(patched.events.coalescing_interval_ms, 250);
+        assert_eq!(patched.notifications.thresholds.node_offline_mins, 30);
+        assert_eq!(
+            patched.notifications.thresholds.low_battery_percent,
+            settings.notifications.thresholds.low_battery_percent
+        );
+
+        let mut paths: Vec<&str> = errors.iter().map(|e| e.path.as_str()).collect();
+        paths.sort_unstable();
+        assert_eq!(
+            paths,
+            [
+                "events.unknown",
+                "notifications.thresholds.cooldownMins",
+                "notifications.thresholds.lowBatteryPercent",
+                "version",
+            ]
+        );
+
+        // Sections can't be replaced by a value
+        let (_, errors) = settings.apply_patch(&json!({ "events": 5 })).unwrap();
+        assert_eq!(errors[0].path, "events");
+
+        assert!(settings.apply_patch(&json!([1, 2])).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_analytics_sections_and_intervals() {
+        let settings = AppSettings::default();
+
+        let (patched, errors) = settings
+            .apply_patch(&json!({
+                "analytics": {
+                    "refreshIntervalSecs": MIN_ANALYTICS_REFRESH_SECS - 1,
+                    "refreshSections": ["stats", "pageRank"],
+                },
+            }))
+            .unwrap();
+
+        assert_eq!(patched.analytics, settings.analytics);
+        assert_eq!(errors.len(), 2);
+
+        let (patched, errors) = settings
+            .apply_patch(&json!({
+                "analytics": { "refreshIntervalSecs": 0, "refreshSections": ["communities"] },
+            }))
+            .unwrap();
+
+        assert!(errors.is_empty());
+        assert_eq!(patched.analytics.refresh_interval(), None);
+        assert_eq!(
+            patched.analytics.refresh_sections,
+            [AnalyticsSection::Communities]
+        );
+    }
+
+    #[test]
+    fn edge_weight_strategy_is_stored_and_applied() {
+        let stored = json!({
+            "graph": {
+                "edgeWeightStrategy": { "strategy": "geoDistance", "missingPositionKm": 2.0 },
+            },
+        });
+        let settings = AppSettings::from_stored(stored).unwrap();
+        let strategy = EdgeWeightStrategy::GeoDistance {
+            missing_position_km: 2.0,
+        };
+        assert_eq!(settings.graph.edge_weight_strategy, strategy);
+
+        let mut graph = MeshGraph::new();
+        settings.graph.apply(&mut graph);
+        assert_eq!(graph.edge_weight_strategy(), &strategy);
+
+        assert!(AppSettings::from_stored(json!({
+            "graph": {
+                "edgeWeightStrategy": {
+                    "strategy": "composite",
+                    "snrFactor": 0.0,
+                    "distanceFactor": 0.0,
+                },
+            },
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn fixed_positions_are_stored_per_device() {
+        let stored = json!({
+            "fixedPositions": {
+                "devices": {
+                    "COM3": {
+                        "position": { "latitude": 47.6, "longitude": -122.3, "altitude": 56 },
+                        "rebroadcastIntervalSecs": 900,
+                    },
+                    "192.168.1.20": {
+                        "position": { "latitude": 51.5, "longitude": -0.1, "altitude": 11 },
+                        "rebroadcastIntervalSecs": 0,
+                    },
+                },
+            },
+        });
+        let settings = AppSettings::from_stored(stored).unwrap();
+        let devices = &settings.fixed_positions.devices;
+
+        assert_eq!(devices["COM3"].position.altitude, 56);
+        assert_eq!(
+            devices["COM3"].rebroadcast_interval(),
+            Some(Duration::from_secs(900))
+        );
+        assert_eq!(devices["192.168.1.20"].rebroadcast_interval(), None);
+
+        assert!(AppSettings::from_stored(json!({
+            "fixedPositions": {
+                "devices": {
+                    "COM3": { "position": { "latitude": 91.0, "longitude": 0.0, "altitude": 0 } },
+                },
+            },
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn coalescer_picks_up_new_interval() {
+        let start = Instant::now();
+        let mut coalescer = EventCoalescer::new(DEFAULT_EVENT_COALESCING_INTERVAL);
+
+        assert!(coalescer
+            .devices
+            .push("a".into(), Default::default(), start)
+            .is_some());
+        assert!(coalescer
+            .devices
+            .push(
+                "a".into(),
+                Default::default(),
+                start + Duration::from_millis(150)
+            )
+            .is_none());
+
+        let (settings, errors) = AppSettings::default()
+            .apply_patch(&json!({ "events": { "coalescingIntervalMs": 100 } }))
+            .unwrap();
+        assert!(errors.is_empty());
+
+        settings.events.apply(&mut coalescer);
+
+        assert_eq!(coalescer.interval(), Duration::from_millis(100));
+        assert_eq!(
+            coalescer
+                .devices
+                .take_due(start + Duration::from_millis(150))
+                .len(),
+            1
+        );
+    }
+}
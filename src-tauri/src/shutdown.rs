@@ -0,0 +1,300 @@
+//! Shutting the backend down when the app exits. Long-running tasks hold a
+//! `ShutdownSignal`, stopping their loop once it's cancelled and dropping it to
+//! acknowledge they've stopped. The shutdown sequence then runs in order:
+//!
+//! 1. Intake stops, devices no longer handle the packets their radios decode and the
+//!    timers and workers stop.
+//! 2. Queued database writes and the outgoing journal are flushed, and graphs are
+//!    autosaved.
+//! 3. Outgoing operations still in progress are cancelled.
+//! 4. Every device is disconnected and its connection closed, releasing serial ports.
+//!
+//! The whole sequence is bounded by `SHUTDOWN_DEADLINE`, so a stuck task or write
+//! can't keep the app from exiting.
+
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use tauri::Manager;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::device::message_store::write_pending_messages;
+use crate::device::telemetry_store::write_pending_telemetry;
+use crate::graph::autosave::autosave_graphs;
+use crate::graph::store::write_changed_graphs;
+use crate::ipc::helpers::disconnect_all_devices;
+use crate::state;
+
+/// How long the shutdown sequence may take before the app exits regardless
+pub const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(5);
+
+/// Held by a long-running task until it stops. Without a `ShutdownState`, e.g. in
+/// tests, the signal is never cancelled.
+pub struct ShutdownSignal {
+    token: CancellationToken,
+    _running: Option<mpsc::Sender<()>>, // dropped along with the signal, acknowledging the task stopped
+}
+
+impl ShutdownSignal {
+    /// Resolves once the app starts shutting down
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await
+    }
+
+    /// Waits for the interval's next tick, returning `false` instead if the app
+    /// starts shutting down first
+    pub async fn tick(&self, interval: &mut tokio::time::Interval) -> bool {
+        tokio::select! {
+            _ = interval.tick() => true,
+            _ = self.token.cancelled() => false,
+        }
+    }
+
+    /// Receives the next queued item, returning `None` instead if the app starts
+    /// shutting down first
+    pub async fn recv<T>(&self, queue: &mut mpsc::Receiver<T>) -> Option<T> {
+        tokio::select! {
+            item = queue.recv() => item,
+            _ = self.token.cancelled() => None,
+        }
+    }
+}
+
+pub fn shutdown_signal<R: tauri::Runtime>(handle: &tauri::AppHandle<R>) -> ShutdownSignal {
+    let shutdown_state = match handle.try_state::<state::shutdown::ShutdownState>() {
+        Some(shutdown_state) => shutdown_state,
+        None => {
+            return ShutdownSignal {
+                token: CancellationToken::new(),
+                _running: None,
+            }
+        }
+    };
+
+    let running = match shutdown_state.running.lock() {
+        Ok(running) => running.clone(),
+        Err(e) => {
+            warn!("Failed to lock running tasks: {}", e);
+            None
+        }
+    };
+
+    ShutdownSignal {
+        token: shutdown_state.token.clone(),
+        _running: running,
+    }
+}
+
+/// Cancels every task's shutdown signal, waiting until the tasks have stopped or
+/// the deadline passes
+async fn stop_tasks<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+    deadline: Instant,
+) -> Result<(), String> {
+    let shutdown_state = match handle.try_state::<state::shutdown::ShutdownState>() {
+        Some(shutdown_state) => shutdown_state,
+        None => return Ok(()),
+    };
+
+    shutdown_state.token.cancel();
+
+    // Tasks spawned from here on get a signal that's already cancelled
+    drop(
+        shutdown_state
+            .running
+            .lock()
+            .map_err(|e| e.to_string())?
+            .take(),
+    );
+
+    let mut stopped = shutdown_state.stopped.lock().await;
+
+    tokio::time::timeout_at(deadline, stopped.recv())
+        .await
+        .map(|_| ())
+        .map_err(|_| "Tasks were still running at the shutdown deadline".to_string())
+}
+
+/// Writes the messages, outgoing journal entries and telemetry queued for the
+/// next database write, returning how many rows were written
+pub async fn flush_history<R: tauri::Runtime>(
+    handle: &tauri::AppHandle<R>,
+) -> Result<usize, String> {
+    let flush_handle = handle.clone();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        Ok(write_pending_messages(&flush_handle)? + write_pending_telemetry(&flush_handle)?)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+async fn flush_persistence(handle: &tauri::AppHandle) -> Result<(), String> {
+    write_changed_graphs(handle).await?;
+    flush_history(handle).await?;
+
+    // Autosave on a clean shutdown, so the latest graphs survive even if the
+    // database is lost
+    autosave_graphs(handle).await?;
+
+    Ok(())
+}
+
+/// Runs the shutdown sequence, see the module docs
+pub async fn shutdown(handle: &tauri::AppHandle) {
+    info!("Shutting down");
+
+    let deadline = Instant::now() + SHUTDOWN_DEADLINE;
+
+    match stop_tasks(handle, deadline).await {
+        Ok(()) => debug!("Stopped intake and background tasks"),
+        Err(e) => warn!("Failed to stop background tasks: {}", e),
+    }
+
+    match tokio::time::timeout_at(deadline, flush_persistence(handle)).await {
+        Ok(Ok(())) => debug!("Flushed queued database writes"),
+        Ok(Err(e)) => warn!("Failed to flush queued database writes: {}", e),
+        Err(_) => warn!("Timed out flushing queued database writes"),
+    }
+
+    match handle
+        .state::<state::operations::OperationsState>()
+        .inner
+        .lock()
+    {
+        Ok(mut operations) => operations.cancel_all(),
+        Err(e) => warn!("Failed to lock operations: {}", e),
+    }
+
+    match tokio::time::timeout_at(deadline, disconnect_all_devices(handle)).await {
+        Ok(Ok(())) => debug!("Closed device connections"),
+        Ok(Err(e)) => warn!("Failed to close device connections: {}", e),
+        Err(_) => warn!("Timed out closing device connections"),
+    }
+
+    info!("Shut down");
+}
+
+#[cfg(test)]
+mod tests {
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::message_store::{MessageStore, StoredMessage};
+    use crate::device::reactions::MessageReactions;
+    use crate::device::{ChannelMessageState, MeshDevice};
+    use crate::graph::ds::graph::MeshGraph;
+    use crate::packet_api::actor::device_actor;
+    use crate::packet_api::summary::ConnectionType;
+    use crate::packet_api::MeshPacketApi;
+    use crate::state::graph::SharedGraph;
+
+    fn stored_message(packet_id: u32) -> StoredMessage {
+        StoredMessage {
+            device_id: 1,
+            packet_id,
+            channel: 0,
+            from: 2,
+            to: u32::MAX,
+            timestamp: 1_000 + packet_id,
+            text: format!("message {}", packet_id),
+            state: ChannelMessageState::Pending,
+            reactions: MessageReactions::new(),
+            device_key: None,
+        }
+    }
+
+    fn my_node_info(my_node_num: u32) -> protobufs::FromRadio {
+        protobufs::FromRadio {
+            payload_variant: Some(protobufs::from_radio::PayloadVariant::MyInfo(
+                protobufs::MyNodeInfo {
+                    my_node_num,
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        }
+    }
+
+    fn written_messages<R: tauri::Runtime>(handle: &tauri::AppHandle<R>) -> usize {
+        let message_state = handle.state::<state::message_store::MessageStoreState>();
+        let store_guard = message_state.inner.lock().unwrap();
+
+        store_guard.as_ref().unwrap().all_messages().unwrap().len()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn stops_tasks_and_flushes_history_within_the_deadline() {
+        let app = tauri::test::mock_app();
+        app.manage(state::shutdown::ShutdownState::new());
+        app.manage(state::mesh_devices::MeshDevicesState::new());
+        app.manage(state::message_store::MessageStoreState::new(Some(
+            MessageStore::open_in_memory().unwrap(),
+        )));
+
+        crate::device::message_store::spawn_message_store_writer(app.handle());
+
+        let mut packet_api = MeshPacketApi::new(
+            app.handle(),
+            "mock".into(),
+            MeshDevice::new(),
+            std::sync::Arc::new(SharedGraph::new(MeshGraph::new())),
+        );
+
+        packet_api.connection_type = ConnectionType::Simulated;
+
+        let (device, actor) = device_actor(packet_api);
+        let (decoded, decoded_listener) = mpsc::unbounded_channel();
+        let device_task = tokio::spawn(actor.run(Some(decoded_listener)));
+
+        decoded.send(my_node_info(1)).unwrap();
+        assert_eq!(device.snapshot().await.unwrap().my_node_info.my_node_num, 1);
+
+        // Once the writer has written a message, the next write is a full interval away
+        let message_state = app.state::<state::message_store::MessageStoreState>();
+        message_state
+            .pending
+            .lock()
+            .unwrap()
+            .push(stored_message(1));
+
+        while written_messages(&app.handle()) == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        message_state
+            .pending
+            .lock()
+            .unwrap()
+            .extend((2..=4).map(stored_message));
+
+        let started = Instant::now();
+        let deadline = started + SHUTDOWN_DEADLINE;
+
+        stop_tasks(&app.handle(), deadline).await.unwrap();
+        assert_eq!(flush_history(&app.handle()).await.unwrap(), 3);
+        assert!(started.elapsed() < SHUTDOWN_DEADLINE);
+
+        assert_eq!(written_messages(&app.handle()), 4);
+        assert!(message_state.pending.lock().unwrap().is_empty());
+
+        // The device no longer takes packets from its radio, but still answers
+        // commands until it's disconnected
+        decoded.send(my_node_info(2)).unwrap();
+        assert_eq!(device.snapshot().await.unwrap().my_node_info.my_node_num, 1);
+
+        device.disconnect().await;
+        tokio::time::timeout(SHUTDOWN_DEADLINE, device_task)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Tasks started once shutting down stop right away
+        let late = shutdown_signal(&app.handle());
+        tokio::time::timeout(SHUTDOWN_DEADLINE, late.cancelled())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,360 @@
+use std::fmt;
+
+use meshtastic::packet::PacketRouter;
+use meshtastic::protobufs;
+use meshtastic::Message;
+use serde::Deserialize;
+
+use crate::device::helpers::get_current_time_u32;
+use crate::packet_api::MeshPacketApi;
+
+const BROADCAST_ADDR: u32 = 0xffff_ffff;
+const INJECTED_HOP_LIMIT: u32 = 3;
+
+/// Packet injection is always available in debug builds, and in release builds
+/// only while developer mode is on
+pub fn packet_injection_allowed(developer_mode: bool) -> bool {
+    cfg!(debug_assertions) || developer_mode
+}
+
+/// JSON description of a packet to inject, e.g.
+/// `{ "type": "text", "from": 4660, "channel": 0, "text": "hello" }`.
+/// Anything the shorthand variants don't cover can be sent as a `raw` `FromRadio`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "type", deny_unknown_fields)]
+pub enum InjectedPacket {
+    #[serde(rename_all = "camelCase")]
+    NodeInfo {
+        num: u32,
+        long_name: String,
+        short_name: String,
+        latitude: Option<f64>,
+        longitude: Option<f64>,
+        altitude: Option<i32>,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    Position {
+        from: u32,
+        latitude: f64,
+        longitude: f64,
+        altitude: Option<i32>,
+        id: Option<u32>, // random if not set
+    },
+
+    #[serde(rename_all = "camelCase")]
+    Text {
+        from: u32,
+        to: Option<u32>, // broadcast if not set
+        #[serde(default)]
+        channel: u32,
+        text: String,
+        id: Option<u32>, // random if not set
+    },
+
+    #[serde(rename_all = "camelCase")]
+    Raw { packet: protobufs::FromRadio },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InjectionError {
+    InvalidJson { path: String, message: String },
+    InvalidField { field: String, message: String },
+}
+
+impl fmt::Display for InjectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InjectionError::InvalidJson { path, message } => {
+                write!(f, "Invalid packet JSON at \"{}\": {}", path, message)
+            }
+            InjectionError::InvalidField { field, message } => {
+                write!(f, "Invalid packet field \"{}\": {}", field, message)
+            }
+        }
+    }
+}
+
+/// Parses a packet description, reporting errors with the path of the offending
+/// field (e.g. `packet.payloadVariant`) rather than only a line and column
+pub fn parse_injected_packet(json: &str) -> Result<InjectedPacket, InjectionError> {
+    let deserializer = &mut serde_json::Deserializer::from_str(json);
+
+    serde_path_to_error::deserialize(deserializer).map_err(|e| InjectionError::InvalidJson {
+        path: e.path().to_string(),
+        message: e.inner().to_string(),
+    })
+}
+
+fn check_coordinates(latitude: f64, longitude: f64) -> Result<(), InjectionError> {
+    if !(-90.0..=90.0).contains(&latitude) {
+        return Err(InjectionError::InvalidField {
+            field: "latitude".into(),
+            message: format!("{} is not between -90 and 90", latitude),
+        });
+    }
+
+    if !(-180.0..=180.0).contains(&longitude) {
+        return Err(InjectionError::InvalidField {
+            field: "longitude".into(),
+            message: format!("{} is not between -180 and 180", longitude),
+        });
+    }
+
+    Ok(())
+}
+
+fn position(latitude: f64, longitude: f64, altitude: Option<i32>, now: u32) -> protobufs::Position {
+    protobufs::Position {
+        latitude_i: (latitude * 1e7).round() as i32,
+        longitude_i: (longitude * 1e7).round() as i32,
+        altitude: altitude.unwrap_or_default(),
+        time: now,
+        ..Default::default()
+    }
+}
+
+fn from_radio(variant: protobufs::from_radio::PayloadVariant) -> protobufs::FromRadio {
+    protobufs::FromRadio {
+        payload_variant: Some(variant),
+        ..Default::default()
+    }
+}
+
+fn mesh_packet(
+    from: u32,
+    to: u32,
+    channel: u32,
+    id: Option<u32>,
+    portnum: protobufs::PortNum,
+    payload: Vec<u8>,
+    now: u32,
+) -> protobufs::FromRadio {
+    from_radio(protobufs::from_radio::PayloadVariant::Packet(
+        protobufs::MeshPacket {
+            from,
+            to,
+            channel,
+            id: id.unwrap_or_else(|| rand::random::<u32>().max(1)),
+            rx_time: now,
+            hop_limit: INJECTED_HOP_LIMIT,
+            payload_variant: Some(protobufs::mesh_packet::PayloadVariant::Decoded(
+                protobufs::Data {
+                    portnum: portnum as i32,
+                    payload,
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        },
+    ))
+}
+
+impl InjectedPacket {
+    pub fn into_from_radio(self, now: u32) -> Result<protobufs::FromRadio, InjectionError> {
+        let packet = match self {
+            InjectedPacket::NodeInfo {
+                num,
+                long_name,
+                short_name,
+                latitude,
+                longitude,
+                altitude,
+            } => {
+                let position = match (latitude, longitude) {
+                    (Some(latitude), Some(longitude)) => {
+                        check_coordinates(latitude, longitude)?;
+                        Some(position(latitude, longitude, altitude, now))
+                    }
+                    (None, None) => None,
+                    _ => {
+                        return Err(InjectionError::InvalidField {
+                            field: "latitude".into(),
+                            message: "latitude and longitude must be set together".into(),
+                        })
+                    }
+                };
+
+                from_radio(protobufs::from_radio::PayloadVariant::NodeInfo(
+                    protobufs::NodeInfo {
+                        num,
+                        user: Some(protobufs::User {
+                            id: format!("!{:08x}", num),
+                            long_name,
+                            short_name,
+                            ..Default::default()
+                        }),
+                        position,
+                        last_heard: now,
+                        ..Default::default()
+                    },
+                ))
+            }
+            InjectedPacket::Position {
+                from,
+                latitude,
+                longitude,
+                altitude,
+                id,
+            } => {
+                check_coordinates(latitude, longitude)?;
+
+                mesh_packet(
+                    from,
+                    BROADCAST_ADDR,
+                    0,
+                    id,
+                    protobufs::PortNum::PositionApp,
+                    position(latitude, longitude, altitude, now).encode_to_vec(),
+                    now,
+                )
+            }
+            InjectedPacket::Text {
+                from,
+                to,
+                channel,
+                text,
+                id,
+            } => mesh_packet(
+                from,
+                to.unwrap_or(BROADCAST_ADDR),
+                channel,
+                id,
+                protobufs::PortNum::TextMessageApp,
+                text.into_bytes(),
+                now,
+            ),
+            InjectedPacket::Raw { packet } => packet,
+        };
+
+        Ok(packet)
+    }
+}
+
+/// Parses a packet description and routes it through the same handlers as packets
+/// from a real radio
+pub fn inject_packet_json<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+    json: &str,
+) -> Result<(), String> {
+    let packet = parse_injected_packet(json)
+        .and_then(|packet| packet.into_from_radio(get_current_time_u32()))
+        .map_err(|e| e.to_string())?;
+
+    packet_api
+        .handle_packet_from_radio(packet)
+        .map_err(|e| e.to_string())?;
+
+    // Show injected nodes right away rather than when their batch is due
+    packet_api.flush_graph_updates().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::device::MeshDevice;
+    use crate::graph::ds::graph::MeshGraph;
+    use crate::graph::geojson::GraphGeoJson;
+    use crate::state::graph::SharedGraph;
+
+    #[test]
+    fn injected_node_appears_in_nodes_geojson() {
+        let app = tauri::test::mock_app();
+        let graph = Arc::new(SharedGraph::new(MeshGraph::new()));
+        let mut packet_api =
+            MeshPacketApi::new(app.handle(), "qa".into(), MeshDevice::new(), graph.clone());
+
+        inject_packet_json(
+            &mut packet_api,
+            r#"{ "type": "nodeInfo", "num": 4660, "longName": "QA Node", "shortName": "QA" }"#,
+        )
+        .unwrap();
+        inject_packet_json(
+            &mut packet_api,
+            r#"{ "type": "position", "from": 4660, "latitude": 47.6062, "longitude": -122.3321 }"#,
+        )
+        .unwrap();
+
+        let geojson = GraphGeoJson::new("qa".into(), &graph.read().unwrap(), &packet_api.device);
+        let feature = geojson
+            .nodes
+            .features
+            .iter()
+            .find(|f| f.properties.as_ref().unwrap()["num"] == json!(4660))
+            .expect("Injected node missing from geojson");
+
+        assert_eq!(
+            feature.properties.as_ref().unwrap()["longName"],
+            json!("QA Node")
+        );
+
+        let geometry = serde_json::to_value(feature.geometry.as_ref().unwrap()).unwrap();
+        let coordinates: Vec<f64> =
+            serde_json::from_value(geometry["coordinates"].clone()).unwrap();
+
+        assert!((coordinates[0] + 122.3321).abs() < 1e-4);
+        assert!((coordinates[1] - 47.6062).abs() < 1e-4);
+    }
+
+    #[test]
+    fn reports_paths_of_invalid_fields() {
+        let err = |json: &str| parse_injected_packet(json).unwrap_err();
+
+        assert!(matches!(
+            err(r#"{ "type": "position", "from": 1, "latitude": "north", "longitude": 0 }"#),
+            InjectionError::InvalidJson { path, .. } if path == "latitude"
+        ));
+        assert!(matches!(
+            err(r#"{ "type": "text", "from": 1, "text": "hi", "chanel": 2 }"#),
+            InjectionError::InvalidJson { path, .. } if path == "chanel"
+        ));
+        assert!(matches!(
+            err(r#"{ "type": "teleport", "from": 1 }"#),
+            InjectionError::InvalidJson { .. }
+        ));
+
+        let out_of_range = parse_injected_packet(
+            r#"{ "type": "position", "from": 1, "latitude": 91, "longitude": 0 }"#,
+        )
+        .unwrap()
+        .into_from_radio(0);
+
+        assert!(matches!(
+            out_of_range,
+            Err(InjectionError::InvalidField { field, .. }) if field == "latitude"
+        ));
+    }
+
+    #[test]
+    fn builds_text_packets() {
+        let packet = parse_injected_packet(
+            r#"{ "type": "text", "from": 4660, "channel": 1, "text": "hello", "id": 7 }"#,
+        )
+        .unwrap()
+        .into_from_radio(100)
+        .unwrap();
+
+        let mesh_packet = match packet.payload_variant {
+            Some(protobufs::from_radio::PayloadVariant::Packet(p)) => p,
+            other => panic!("Expected mesh packet, got {:?}", other),
+        };
+
+        assert_eq!(mesh_packet.from, 4660);
+        assert_eq!(mesh_packet.to, BROADCAST_ADDR);
+        assert_eq!(mesh_packet.channel, 1);
+        assert_eq!(mesh_packet.id, 7);
+        assert_eq!(mesh_packet.rx_time, 100);
+
+        match mesh_packet.payload_variant {
+            Some(protobufs::mesh_packet::PayloadVariant::Decoded(data)) => {
+                assert_eq!(data.portnum, protobufs::PortNum::TextMessageApp as i32);
+                assert_eq!(data.payload, b"hello");
+            }
+            other => panic!("Expected decoded payload, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, trace, warn};
+use meshtastic::packet::PacketRouter;
+use meshtastic::protobufs;
+use tauri::async_runtime::{self, JoinHandle};
+
+use crate::device::helpers::get_current_time_u32;
+use crate::packet_api::MeshPacketApi;
+use crate::state;
+use crate::state::mesh_devices::get_device_handle;
+
+use self::scenario::ScenarioEngine;
+
+pub mod injection;
+pub mod scenario;
+
+/// Device key the simulated radio is registered under in the mesh devices state
+pub const SIMULATION_DEVICE_KEY: &str = "simulation";
+
+pub type SharedScenarioEngine = Arc<async_runtime::Mutex<ScenarioEngine>>;
+
+/// Routes simulated packets through the same handlers as packets from a real radio,
+/// before the simulated device's task is running
+pub fn feed_simulated_packets<R: tauri::Runtime>(
+    packet_api: &mut MeshPacketApi<R>,
+    packets: Vec<protobufs::FromRadio>,
+) {
+    for packet in packets {
+        if let Err(e) = packet_api.handle_packet_from_radio(packet) {
+            warn!("Failed to handle simulated packet: {}", e);
+        }
+    }
+
+    // There's no device task yet to apply node infos or publish the graph once due
+    if let Err(e) = packet_api.flush_graph_updates() {
+        warn!("Failed to apply simulated node infos to the graph: {}", e);
+    }
+}
+
+/// Ticks the scenario at its configured interval until the simulated device is removed,
+/// handing its packets to the device's task like a radio's decoded packets
+pub fn spawn_simulation(
+    mesh_devices: state::mesh_devices::MeshDevicesStateInner,
+    engine: SharedScenarioEngine,
+) -> JoinHandle<()> {
+    trace!("Spawning mesh simulation");
+
+    async_runtime::spawn(async move {
+        loop {
+            let (packets, tick_interval_secs) = {
+                let mut engine = engine.lock().await;
+                let packets = engine.tick(get_current_time_u32());
+
+                (packets, engine.params().tick_interval_secs)
+            };
+
+            let device = match get_device_handle(&mesh_devices, SIMULATION_DEVICE_KEY).await {
+                Some(d) => d,
+                None => {
+                    debug!("Simulated device removed, stopping simulation");
+                    return;
+                }
+            };
+
+            for packet in packets {
+                if device.handle_packet(packet).is_err() {
+                    debug!("Simulated device disconnected, stopping simulation");
+                    return;
+                }
+            }
+
+            tokio::time::sleep(Duration::from_secs(tick_interval_secs.max(1).into())).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::scenario::{BoundingBox, MobilityModel, ScenarioParams};
+    use super::*;
+    use crate::device::MeshDevice;
+    use crate::graph::ds::graph::MeshGraph;
+    use crate::state::graph::SharedGraph;
+
+    const NODE_COUNT: u32 = 10;
+
+    fn params() -> ScenarioParams {
+        // Every node is within radio range of every other node
+        ScenarioParams {
+            seed: 7,
+            node_count: NODE_COUNT,
+            bounds: BoundingBox {
+                min_latitude: 47.60,
+                min_longitude: -122.35,
+                max_latitude: 47.61,
+                max_longitude: -122.34,
+            },
+            mobility: MobilityModel::RandomWalk {
+                max_step_meters: 20.0,
+            },
+            link_flap_rate: 0.0,
+            message_rate: 0.0,
+            radio_range_meters: 3000.0,
+            tick_interval_secs: 5,
+            partitioned: false,
+        }
+    }
+
+    #[test]
+    fn scenario_builds_graph_and_reflects_partition() {
+        let app = tauri::test::mock_app();
+        let graph = Arc::new(SharedGraph::new(MeshGraph::new()));
+        let mut packet_api = MeshPacketApi::new(
+            app.handle(),
+            SIMULATION_DEVICE_KEY.into(),
+            MeshDevice::new(),
+            graph.clone(),
+        );
+
+        let mut engine = ScenarioEngine::new(params());
+        let now = get_current_time_u32();
+
+        feed_simulated_packets(&mut packet_api, engine.initial_packets(now));
+
+        for tick in 1..=3 {
+            feed_simulated_packets(&mut packet_api, engine.tick(now + tick * 5));
+        }
+
+        {
+            let graph = graph.read().unwrap();
+            let max_edges = (NODE_COUNT * (NODE_COUNT - 1)) as usize;
+
+            assert_eq!(graph.node_count(), NODE_COUNT as usize);
+            assert!((NODE_COUNT as usize - 1..=max_edges).contains(&graph.edge_count()));
+            assert_eq!(graph.connected_components(), 1);
+        }
+
+        engine.set_params(ScenarioParams {
+            partitioned: true,
+            ..params()
+        });
+
+        feed_simulated_packets(&mut packet_api, engine.tick(now + 20));
+
+        let graph = graph.read().unwrap();
+
+        assert_eq!(graph.node_count(), NODE_COUNT as usize);
+        assert!(graph.connected_components() >= 2);
+        assert_eq!(packet_api.device.nodes.len(), NODE_COUNT as usize);
+    }
+}
@@ -0,0 +1,566 @@
+use meshtastic::protobufs;
+use meshtastic::ts::specta::{self, Type};
+use meshtastic::Message;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::device::helpers::{haversine_distance_meters, EARTH_RADIUS_METERS};
+
+/// Node numbers of simulated nodes are allocated sequentially from this base
+pub const SIMULATED_NODE_NUM_BASE: u32 = 0x5100_0000;
+
+/// Largest mesh the scenario engine will simulate
+pub const MAX_SIMULATED_NODES: u32 = 500;
+
+const BROADCAST_ADDR: u32 = 0xffff_ffff;
+const TELEMETRY_PROBABILITY: f64 = 0.2;
+
+const SIMULATED_MESSAGES: [&str; 6] = [
+    "Checking in",
+    "Anyone copy?",
+    "Heading to the trailhead",
+    "Signal is good here",
+    "Battery getting low",
+    "Meet at the north gate",
+];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct BoundingBox {
+    pub min_latitude: f64,
+    pub min_longitude: f64,
+    pub max_latitude: f64,
+    pub max_longitude: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum MobilityModel {
+    /// Nodes stay where they were placed
+    Static,
+
+    /// Each tick every node moves up to `max_step_meters` in a random direction
+    RandomWalk { max_step_meters: f64 },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ScenarioParams {
+    /// Seed for all random choices, the same seed and parameters produce the same mesh
+    pub seed: u64,
+    pub node_count: u32,
+    pub bounds: BoundingBox,
+    pub mobility: MobilityModel,
+
+    /// Probability that an in-range link is down for a given tick
+    pub link_flap_rate: f64,
+
+    /// Average number of text messages sent across the mesh each tick
+    pub message_rate: f64,
+
+    /// Distance within which two nodes can hear each other
+    pub radio_range_meters: f64,
+
+    pub tick_interval_secs: u32,
+
+    /// Splits the mesh into an eastern and western half that can't hear each other
+    pub partitioned: bool,
+}
+
+impl Default for ScenarioParams {
+    fn default() -> Self {
+        Self {
+            seed: 1,
+            node_count: 12,
+            bounds: BoundingBox {
+                min_latitude: 47.60,
+                min_longitude: -122.36,
+                max_latitude: 47.66,
+                max_longitude: -122.28,
+            },
+            mobility: MobilityModel::RandomWalk {
+                max_step_meters: 50.0,
+            },
+            link_flap_rate: 0.05,
+            message_rate: 0.2,
+            radio_range_meters: 3000.0,
+            tick_interval_secs: 5,
+            partitioned: false,
+        }
+    }
+}
+
+impl ScenarioParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.node_count == 0 || self.node_count > MAX_SIMULATED_NODES {
+            return Err(format!(
+                "Node count must be between 1 and {}",
+                MAX_SIMULATED_NODES
+            ));
+        }
+
+        if self.bounds.min_latitude >= self.bounds.max_latitude
+            || self.bounds.min_longitude >= self.bounds.max_longitude
+        {
+            return Err("Bounding box minimums must be less than its maximums".into());
+        }
+
+        if !(0.0..=1.0).contains(&self.link_flap_rate) {
+            return Err("Link flap rate must be between 0 and 1".into());
+        }
+
+        if self.message_rate < 0.0 || self.radio_range_meters <= 0.0 {
+            return Err("Message rate and radio range can't be negative".into());
+        }
+
+        if self.tick_interval_secs == 0 {
+            return Err("Tick interval must be at least one second".into());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulatedNode {
+    pub node_num: u32,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub battery_level: u32,
+}
+
+/// Generates a plausible, evolving stream of packets for a synthetic mesh.
+/// The first node is treated as the locally connected radio.
+pub struct ScenarioEngine {
+    params: ScenarioParams,
+    rng: StdRng,
+    nodes: Vec<SimulatedNode>,
+    unannounced: Vec<u32>, // nodes whose `NodeInfo` hasn't been sent yet
+    next_packet_id: u32,
+}
+
+impl ScenarioEngine {
+    pub fn new(params: ScenarioParams) -> Self {
+        let mut engine = Self {
+            rng: StdRng::seed_from_u64(params.seed),
+            params,
+            nodes: vec![],
+            unannounced: vec![],
+            next_packet_id: 1,
+        };
+
+        engine.resize(engine.params.node_count);
+
+        engine
+    }
+
+    pub fn params(&self) -> &ScenarioParams {
+        &self.params
+    }
+
+    pub fn nodes(&self) -> &[SimulatedNode] {
+        &self.nodes
+    }
+
+    pub fn local_node_num(&self) -> u32 {
+        SIMULATED_NODE_NUM_BASE
+    }
+
+    /// Applies new parameters to the running scenario, adding or removing
+    /// nodes as needed. Existing nodes keep their positions.
+    pub fn set_params(&mut self, params: ScenarioParams) {
+        let node_count = params.node_count;
+        self.params = params;
+        self.resize(node_count);
+    }
+
+    /// Packets describing the local radio and all nodes, sent before the first tick
+    pub fn initial_packets(&mut self, now: u32) -> Vec<protobufs::FromRadio> {
+        let mut packets = vec![from_radio(protobufs::from_radio::PayloadVariant::MyInfo(
+            protobufs::MyNodeInfo {
+                my_node_num: self.local_node_num(),
+                ..Default::default()
+            },
+        ))];
+
+        packets.extend(self.announce_nodes(now));
+
+        packets
+    }
+
+    /// Advances the scenario by one tick, returning the packets heard during it.
+    /// Neighbor info is sent last since position updates reset a node's edges.
+    pub fn tick(&mut self, now: u32) -> Vec<protobufs::FromRadio> {
+        let mut packets = self.announce_nodes(now);
+
+        if let MobilityModel::RandomWalk { max_step_meters } = self.params.mobility {
+            for index in 0..self.nodes.len() {
+                self.move_node(index, max_step_meters);
+
+                let position = self.position(index, now);
+                packets.push(self.mesh_packet(
+                    index,
+                    protobufs::PortNum::PositionApp,
+                    position.encode_to_vec(),
+                    now,
+                ));
+            }
+        }
+
+        for index in 0..self.nodes.len() {
+            if !self.rng.gen_bool(TELEMETRY_PROBABILITY) {
+                continue;
+            }
+
+            let node = &mut self.nodes[index];
+            node.battery_level = node.battery_level.saturating_sub(1).max(5);
+
+            let telemetry = protobufs::Telemetry {
+                time: now,
+                variant: Some(protobufs::telemetry::Variant::DeviceMetrics(
+                    protobufs::DeviceMetrics {
+                        battery_level: node.battery_level,
+                        voltage: 3.3 + 0.9 * node.battery_level as f32 / 100.0,
+                        ..Default::default()
+                    },
+                )),
+            };
+
+            packets.push(self.mesh_packet(
+                index,
+                protobufs::PortNum::TelemetryApp,
+                telemetry.encode_to_vec(),
+                now,
+            ));
+        }
+
+        for _ in 0..self.message_count() {
+            let index = self.rng.gen_range(0..self.nodes.len());
+            let message = SIMULATED_MESSAGES[self.rng.gen_range(0..SIMULATED_MESSAGES.len())];
+
+            packets.push(self.mesh_packet(
+                index,
+                protobufs::PortNum::TextMessageApp,
+                message.as_bytes().to_vec(),
+                now,
+            ));
+        }
+
+        for index in 0..self.nodes.len() {
+            let neighbor_info = self.neighbor_info(index);
+
+            packets.push(self.mesh_packet(
+                index,
+                protobufs::PortNum::NeighborinfoApp,
+                neighbor_info.encode_to_vec(),
+                now,
+            ));
+        }
+
+        packets
+    }
+
+    /// Returns whether two nodes can currently hear each other, ignoring link flaps
+    pub fn in_range(&self, a: usize, b: usize) -> bool {
+        if a == b {
+            return false;
+        }
+
+        if self.params.partitioned && self.partition_side(a) != self.partition_side(b) {
+            return false;
+        }
+
+        distance_meters(&self.nodes[a], &self.nodes[b]) <= self.params.radio_range_meters
+    }
+
+    /// Splits nodes at the median longitude so both sides of a partition are populated
+    fn partition_side(&self, index: usize) -> bool {
+        let longitude = self.nodes[index].longitude;
+
+        let west_of = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(i, n)| n.longitude < longitude || (n.longitude == longitude && *i < index))
+            .count();
+
+        west_of < self.nodes.len() / 2
+    }
+
+    fn resize(&mut self, node_count: u32) {
+        let node_count = node_count.clamp(1, MAX_SIMULATED_NODES) as usize;
+
+        self.nodes.truncate(node_count);
+        self.unannounced
+            .retain(|node_num| node_num - SIMULATED_NODE_NUM_BASE < node_count as u32);
+
+        while self.nodes.len() < node_count {
+            let bounds = &self.params.bounds;
+            let node = SimulatedNode {
+                node_num: SIMULATED_NODE_NUM_BASE + self.nodes.len() as u32,
+                latitude: self.rng.gen_range(bounds.min_latitude..bounds.max_latitude),
+                longitude: self
+                    .rng
+                    .gen_range(bounds.min_longitude..bounds.max_longitude),
+                battery_level: self.rng.gen_range(40..=100),
+            };
+
+            self.unannounced.push(node.node_num);
+            self.nodes.push(node);
+        }
+    }
+
+    fn announce_nodes(&mut self, now: u32) -> Vec<protobufs::FromRadio> {
+        let unannounced = std::mem::take(&mut self.unannounced);
+
+        unannounced
+            .into_iter()
+            .map(|node_num| {
+                let index = (node_num - SIMULATED_NODE_NUM_BASE) as usize;
+
+                from_radio(protobufs::from_radio::PayloadVariant::NodeInfo(
+                    protobufs::NodeInfo {
+                        num: node_num,
+                        user: Some(protobufs::User {
+                            id: format!("!{:08x}", node_num),
+                            long_name: format!("Sim Node {}", index),
+                            short_name: format!("S{:03}", index % 1000),
+                            ..Default::default()
+                        }),
+                        position: Some(self.position(index, now)),
+                        last_heard: now,
+                        ..Default::default()
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    fn move_node(&mut self, index: usize, max_step_meters: f64) {
+        let bearing = self.rng.gen_range(0.0..std::f64::consts::TAU);
+        let step = self.rng.gen_range(0.0..=max_step_meters.max(0.0));
+
+        let bounds = self.params.bounds.clone();
+        let node = &mut self.nodes[index];
+
+        let delta_latitude = (step * bearing.cos() / EARTH_RADIUS_METERS).to_degrees();
+        let delta_longitude = (step * bearing.sin()
+            / (EARTH_RADIUS_METERS * node.latitude.to_radians().cos()))
+        .to_degrees();
+
+        node.latitude =
+            (node.latitude + delta_latitude).clamp(bounds.min_latitude, bounds.max_latitude);
+        node.longitude =
+            (node.longitude + delta_longitude).clamp(bounds.min_longitude, bounds.max_longitude);
+    }
+
+    fn message_count(&mut self) -> u32 {
+        let rate = self.params.message_rate.max(0.0);
+        let whole = rate.trunc();
+
+        whole as u32 + u32::from(self.rng.gen_bool(rate - whole))
+    }
+
+    fn position(&self, index: usize, now: u32) -> protobufs::Position {
+        let node = &self.nodes[index];
+
+        protobufs::Position {
+            latitude_i: (node.latitude * 1e7) as i32,
+            longitude_i: (node.longitude * 1e7) as i32,
+            time: now,
+            ..Default::default()
+        }
+    }
+
+    fn neighbor_info(&mut self, index: usize) -> protobufs::NeighborInfo {
+        let broadcast_interval_secs = self.params.tick_interval_secs.saturating_mul(6);
+        let range = self.params.radio_range_meters;
+        let flap_rate = self.params.link_flap_rate;
+
+        let mut neighbors = vec![];
+
+        for other in 0..self.nodes.len() {
+            if !self.in_range(index, other) || self.rng.gen_bool(flap_rate) {
+                continue;
+            }
+
+            let distance = distance_meters(&self.nodes[index], &self.nodes[other]);
+
+            neighbors.push(protobufs::Neighbor {
+                node_id: self.nodes[other].node_num,
+                snr: (10.0 - 20.0 * distance / range) as f32,
+                node_broadcast_interval_secs: broadcast_interval_secs,
+                ..Default::default()
+            });
+        }
+
+        protobufs::NeighborInfo {
+            node_id: self.nodes[index].node_num,
+            last_sent_by_id: self.nodes[index].node_num,
+            node_broadcast_interval_secs: broadcast_interval_secs,
+            neighbors,
+        }
+    }
+
+    fn mesh_packet(
+        &mut self,
+        index: usize,
+        portnum: protobufs::PortNum,
+        payload: Vec<u8>,
+        now: u32,
+    ) -> protobufs::FromRadio {
+        let id = self.next_packet_id;
+        self.next_packet_id = self.next_packet_id.wrapping_add(1).max(1);
+
+        // Packets from nodes the local radio can hear directly keep their full hop limit
+        let hop_limit = if index == 0 || self.in_range(0, index) {
+            3
+        } else {
+            2
+        };
+
+        let rx_snr = if index == 0 {
+            0.0
+        } else {
+            let distance = distance_meters(&self.nodes[0], &self.nodes[index]);
+            (10.0 - 20.0 * distance / self.params.radio_range_meters) as f32
+        };
+
+        from_radio(protobufs::from_radio::PayloadVariant::Packet(
+            protobufs::MeshPacket {
+                from: self.nodes[index].node_num,
+                to: BROADCAST_ADDR,
+                id,
+                rx_time: now,
+                rx_snr,
+                hop_limit,
+                payload_variant: Some(protobufs::mesh_packet::PayloadVariant::Decoded(
+                    protobufs::Data {
+                        portnum: portnum as i32,
+                        payload,
+                        ..Default::default()
+                    },
+                )),
+                ..Default::default()
+            },
+        ))
+    }
+}
+
+fn from_radio(variant: protobufs::from_radio::PayloadVariant) -> protobufs::FromRadio {
+    protobufs::FromRadio {
+        payload_variant: Some(variant),
+        ..Default::default()
+    }
+}
+
+fn distance_meters(a: &SimulatedNode, b: &SimulatedNode) -> f64 {
+    haversine_distance_meters(a.latitude, a.longitude, b.latitude, b.longitude)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> ScenarioParams {
+        ScenarioParams {
+            seed: 42,
+            node_count: 8,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn same_seed_produces_same_packets() {
+        let mut a = ScenarioEngine::new(params());
+        let mut b = ScenarioEngine::new(params());
+
+        assert_eq!(a.initial_packets(100), b.initial_packets(100));
+        assert_eq!(a.tick(105), b.tick(105));
+        assert_eq!(a.nodes(), b.nodes());
+    }
+
+    #[test]
+    fn nodes_stay_within_bounds() {
+        let mut engine = ScenarioEngine::new(ScenarioParams {
+            mobility: MobilityModel::RandomWalk {
+                max_step_meters: 5000.0,
+            },
+            ..params()
+        });
+
+        for tick in 0..20 {
+            engine.tick(tick);
+        }
+
+        let bounds = &engine.params().bounds;
+
+        for node in engine.nodes() {
+            assert!((bounds.min_latitude..=bounds.max_latitude).contains(&node.latitude));
+            assert!((bounds.min_longitude..=bounds.max_longitude).contains(&node.longitude));
+        }
+    }
+
+    #[test]
+    fn announces_nodes_added_at_runtime() {
+        let mut engine = ScenarioEngine::new(params());
+        assert_eq!(engine.initial_packets(0).len(), 9);
+
+        engine.set_params(ScenarioParams {
+            node_count: 10,
+            ..params()
+        });
+
+        let node_infos = engine
+            .tick(5)
+            .into_iter()
+            .filter(|p| {
+                matches!(
+                    p.payload_variant,
+                    Some(protobufs::from_radio::PayloadVariant::NodeInfo(_))
+                )
+            })
+            .count();
+
+        assert_eq!(engine.nodes().len(), 10);
+        assert_eq!(node_infos, 2);
+    }
+
+    #[test]
+    fn partition_splits_nodes_in_half() {
+        let engine = ScenarioEngine::new(ScenarioParams {
+            partitioned: true,
+            radio_range_meters: 1_000_000.0,
+            ..params()
+        });
+
+        let west = (0..8).filter(|i| engine.partition_side(*i)).count();
+        assert_eq!(west, 4);
+
+        let links = (0..8)
+            .flat_map(|a| (0..8).map(move |b| (a, b)))
+            .filter(|(a, b)| engine.in_range(*a, *b))
+            .count();
+
+        // Two fully connected halves of four nodes, counted in both directions
+        assert_eq!(links, 2 * 4 * 3);
+    }
+
+    #[test]
+    fn rejects_invalid_params() {
+        assert!(params().validate().is_ok());
+        assert!(ScenarioParams {
+            node_count: 0,
+            ..params()
+        }
+        .validate()
+        .is_err());
+        assert!(ScenarioParams {
+            link_flap_rate: 1.5,
+            ..params()
+        }
+        .validate()
+        .is_err());
+    }
+}
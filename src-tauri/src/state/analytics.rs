@@ -0,0 +1,17 @@
+use std::sync::{Arc, Mutex};
+
+use crate::export::analytics_report::AnalyticsReport;
+
+pub type AnalyticsStateInner = Arc<Mutex<Option<AnalyticsReport>>>;
+
+pub struct AnalyticsState {
+    pub latest: AnalyticsStateInner, // last scheduled analytics update, if any
+}
+
+impl AnalyticsState {
+    pub fn new() -> Self {
+        Self {
+            latest: Arc::new(Mutex::new(None)),
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use std::sync::{Arc, Mutex};
+
+use crate::ipc::error_reporter::AppErrorLog;
+
+pub type AppErrorsStateInner = Arc<Mutex<AppErrorLog>>;
+
+pub struct AppErrorsState {
+    pub inner: AppErrorsStateInner,
+}
+
+impl AppErrorsState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(AppErrorLog::default())),
+        }
+    }
+}
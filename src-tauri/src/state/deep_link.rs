@@ -0,0 +1,24 @@
+use std::sync::Arc;
+use tauri::async_runtime;
+
+pub type DeepLinkStateInner = Arc<async_runtime::Mutex<Option<String>>>;
+
+/// Link the app was launched with, held until the UI is ready to handle it
+#[derive(Debug)]
+pub struct DeepLinkState {
+    pub inner: DeepLinkStateInner,
+}
+
+impl DeepLinkState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(async_runtime::Mutex::new(None)),
+        }
+    }
+
+    pub fn init(url: String) -> Self {
+        Self {
+            inner: Arc::new(async_runtime::Mutex::new(Some(url))),
+        }
+    }
+}
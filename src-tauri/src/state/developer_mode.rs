@@ -0,0 +1,15 @@
+use std::sync::{Arc, Mutex};
+
+pub type DeveloperModeStateInner = Arc<Mutex<bool>>;
+
+pub struct DeveloperModeState {
+    pub inner: DeveloperModeStateInner,
+}
+
+impl DeveloperModeState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(false)),
+        }
+    }
+}
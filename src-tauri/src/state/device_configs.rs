@@ -0,0 +1,16 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::device::config_cache::CachedDeviceConfig;
+
+pub struct DeviceConfigsState {
+    pub inner: Arc<Mutex<HashMap<u32, CachedDeviceConfig>>>, // last known config of each device, by node num
+}
+
+impl DeviceConfigsState {
+    pub fn new(configs: HashMap<u32, CachedDeviceConfig>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(configs)),
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::device::logs::{DeviceLogBuffer, DeviceLogConfig};
+
+use super::DeviceKey;
+
+#[derive(Debug, Default)]
+pub struct DeviceLogs {
+    pub config: DeviceLogConfig,
+    pub buffers: HashMap<DeviceKey, DeviceLogBuffer>,
+}
+
+pub type DeviceLogsStateInner = Arc<Mutex<DeviceLogs>>;
+
+pub struct DeviceLogsState {
+    pub inner: DeviceLogsStateInner,
+}
+
+impl DeviceLogsState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(DeviceLogs::default())),
+        }
+    }
+}
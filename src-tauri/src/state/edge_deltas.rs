@@ -0,0 +1,19 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::graph::edge_delta::EdgeDeltaTracker;
+use crate::state::DeviceKey;
+
+pub type EdgeDeltasStateInner = Arc<Mutex<HashMap<DeviceKey, EdgeDeltaTracker>>>;
+
+pub struct EdgeDeltasState {
+    pub inner: EdgeDeltasStateInner,
+}
+
+impl EdgeDeltasState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
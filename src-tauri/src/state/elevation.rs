@@ -0,0 +1,17 @@
+use std::sync::{Arc, Mutex};
+
+use crate::elevation::ElevationProvider;
+
+pub type ElevationStateInner = Arc<Mutex<Option<Arc<dyn ElevationProvider>>>>;
+
+pub struct ElevationState {
+    pub inner: ElevationStateInner,
+}
+
+impl ElevationState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(None)),
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use std::sync::{Arc, Mutex};
+
+use crate::ipc::events::coalesce::EventCoalescer;
+
+pub type EventCoalescingStateInner = Arc<Mutex<EventCoalescer>>;
+
+pub struct EventCoalescingState {
+    pub inner: EventCoalescingStateInner,
+}
+
+impl EventCoalescingState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(EventCoalescer::default())),
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use std::sync::{Arc, Mutex};
+
+use crate::ipc::events::scopes::EventScopes;
+
+pub type EventScopesStateInner = Arc<Mutex<EventScopes>>;
+
+pub struct EventScopesState {
+    pub inner: EventScopesStateInner,
+}
+
+impl EventScopesState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(EventScopes::default())),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use std::{collections::HashMap, sync::Arc};
+use tauri::async_runtime;
+
+use super::DeviceKey;
+
+/// Tasks periodically re-sending a fixed position to each device
+pub type FixedPositionStateInner =
+    Arc<async_runtime::Mutex<HashMap<DeviceKey, async_runtime::JoinHandle<()>>>>;
+
+pub struct FixedPositionState {
+    pub inner: FixedPositionStateInner,
+}
+
+impl FixedPositionState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(async_runtime::Mutex::new(HashMap::new())),
+        }
+    }
+}
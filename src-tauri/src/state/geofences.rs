@@ -0,0 +1,17 @@
+use std::sync::{Arc, Mutex};
+
+use crate::notifications::geofences::Geofences;
+
+pub type GeofencesStateInner = Arc<Mutex<Geofences>>;
+
+pub struct GeofencesState {
+    pub inner: GeofencesStateInner,
+}
+
+impl GeofencesState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Geofences::default())),
+        }
+    }
+}
@@ -1,8 +1,120 @@
-use std::sync::{Arc, Mutex};
+use std::ops::{Deref, DerefMut};
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+
+use log::warn;
 
 use crate::graph::ds::graph::MeshGraph;
 
-pub type GraphStateInner = Arc<Mutex<MeshGraph>>;
+/// Guards held for longer than this are logged in debug builds, as packet handling
+/// waits on them
+pub const LOCK_HOLD_WARN_THRESHOLD: Duration = Duration::from_millis(50);
+
+/// The mesh graph, shared between packet handling and everything reading it. Readers
+/// share the lock so they only hold up packets that change the graph, and should copy
+/// what they need with `view` rather than format features while holding it.
+pub struct SharedGraph {
+    lock: RwLock<MeshGraph>,
+    revision: AtomicU64, // bumped on each write guard, whether or not it changed anything
+}
+
+impl SharedGraph {
+    pub fn new(graph: MeshGraph) -> Self {
+        Self {
+            lock: RwLock::new(graph),
+            revision: AtomicU64::new(0),
+        }
+    }
+
+    #[track_caller]
+    pub fn read(&self) -> Result<TimedGuard<RwLockReadGuard<'_, MeshGraph>>, String> {
+        let location = Location::caller();
+
+        self.lock
+            .read()
+            .map(|guard| TimedGuard::new(guard, location))
+            .map_err(|e| e.to_string())
+    }
+
+    #[track_caller]
+    pub fn write(&self) -> Result<TimedGuard<RwLockWriteGuard<'_, MeshGraph>>, String> {
+        let location = Location::caller();
+
+        let guard = self
+            .lock
+            .write()
+            .map(|guard| TimedGuard::new(guard, location))
+            .map_err(|e| e.to_string())?;
+
+        self.revision.fetch_add(1, Ordering::Relaxed);
+
+        Ok(guard)
+    }
+
+    /// Changes whenever the graph may have changed, so work derived from an earlier
+    /// revision can be skipped while it's still current
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::Relaxed)
+    }
+
+    /// Copies the graph under a read guard, to build GeoJSON, exports and analytics from
+    #[track_caller]
+    pub fn view(&self) -> Result<MeshGraph, String> {
+        self.read().map(|graph| graph.clone())
+    }
+}
+
+/// A graph guard that logs, in debug builds, when it's held for too long
+pub struct TimedGuard<G> {
+    guard: G,
+    acquired: Instant,
+    location: &'static Location<'static>,
+}
+
+impl<G> TimedGuard<G> {
+    fn new(guard: G, location: &'static Location<'static>) -> Self {
+        Self {
+            guard,
+            acquired: Instant::now(),
+            location,
+        }
+    }
+}
+
+impl<G: Deref<Target = MeshGraph>> Deref for TimedGuard<G> {
+    type Target = MeshGraph;
+
+    fn deref(&self) -> &MeshGraph {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut<Target = MeshGraph>> DerefMut for TimedGuard<G> {
+    fn deref_mut(&mut self) -> &mut MeshGraph {
+        &mut self.guard
+    }
+}
+
+impl<G> Drop for TimedGuard<G> {
+    fn drop(&mut self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+
+        let held = self.acquired.elapsed();
+
+        if held > LOCK_HOLD_WARN_THRESHOLD {
+            warn!(
+                "Graph lock taken at {} was held for {:?}",
+                self.location, held
+            );
+        }
+    }
+}
+
+pub type GraphStateInner = Arc<SharedGraph>;
 
 pub struct GraphState {
     pub inner: GraphStateInner,
@@ -11,7 +123,126 @@ pub struct GraphState {
 impl GraphState {
     pub fn new() -> Self {
         Self {
-            inner: Arc::new(Mutex::new(MeshGraph::new())),
+            inner: Arc::new(SharedGraph::new(MeshGraph::new())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use meshtastic::protobufs::{self, MeshPacket, Neighbor, NeighborInfo};
+
+    use super::*;
+    use crate::device::MeshDevice;
+    use crate::graph::geojson::GraphGeoJson;
+
+    const NODE_COUNT: u32 = 500;
+
+    fn position(offset: u32) -> protobufs::Position {
+        protobufs::Position {
+            latitude_i: 525_000_000 + offset as i32 * 1_000,
+            longitude_i: 134_000_000,
+            ..Default::default()
+        }
+    }
+
+    fn packet(from: u32) -> MeshPacket {
+        MeshPacket {
+            from,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn revision_changes_with_writes() {
+        let graph = SharedGraph::new(MeshGraph::new());
+        let revision = graph.revision();
+
+        graph.view().unwrap();
+        assert_eq!(graph.revision(), revision);
+
+        graph
+            .write()
+            .unwrap()
+            .update_from_position(packet(1), position(1));
+        assert_ne!(graph.revision(), revision);
+    }
+
+    #[test]
+    fn packets_are_not_held_up_by_geojson_requests() {
+        let graph = Arc::new(SharedGraph::new(MeshGraph::new()));
+
+        {
+            let mut graph = graph.write().unwrap();
+
+            for node_num in 1..=NODE_COUNT {
+                graph.update_from_position(packet(node_num), position(node_num));
+            }
+
+            for node_num in 1..=NODE_COUNT {
+                graph.update_from_neighbor_info(
+                    packet(node_num),
+                    NeighborInfo {
+                        node_id: node_num,
+                        neighbors: vec![Neighbor {
+                            node_id: node_num % NODE_COUNT + 1,
+                            snr: 4.0,
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    },
+                    "LongFast".into(),
+                    "/dev/ttyUSB0",
+                );
+            }
         }
+
+        let done = Arc::new(AtomicBool::new(false));
+
+        let requesters: Vec<_> = (0..4)
+            .map(|_| {
+                let graph = graph.clone();
+                let done = done.clone();
+
+                thread::spawn(move || {
+                    let device = MeshDevice::new();
+
+                    while !done.load(Ordering::Relaxed) {
+                        let view = graph.view().unwrap();
+                        GraphGeoJson::new("flood".into(), &view, &device);
+                    }
+                })
+            })
+            .collect();
+
+        // A flood of position packets, each changing the graph
+        let mut slowest = Duration::ZERO;
+
+        for packet_index in 0..2_000 {
+            let started = Instant::now();
+
+            graph.write().unwrap().update_from_position(
+                packet(packet_index % NODE_COUNT + 1),
+                position(packet_index),
+            );
+
+            slowest = slowest.max(started.elapsed());
+        }
+
+        done.store(true, Ordering::Relaxed);
+
+        for requester in requesters {
+            requester.join().unwrap();
+        }
+
+        assert!(
+            slowest < Duration::from_millis(250),
+            "a packet waited {:?} for the graph",
+            slowest
+        );
+        assert_eq!(graph.read().unwrap().edge_count(), NODE_COUNT as usize);
     }
 }
@@ -0,0 +1,16 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::graph::store::GraphSnapshot;
+
+pub struct GraphAutosaveState {
+    pub recovered: Arc<Mutex<HashMap<u32, GraphSnapshot>>>, // graphs recovered from an autosave, by device node num
+}
+
+impl GraphAutosaveState {
+    pub fn new() -> Self {
+        Self {
+            recovered: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
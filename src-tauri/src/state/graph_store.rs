@@ -0,0 +1,21 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use crate::graph::store::GraphStore;
+use crate::state::DeviceKey;
+
+pub type GraphStoreStateInner = Arc<Mutex<Option<GraphStore>>>;
+
+pub struct GraphStoreState {
+    pub inner: GraphStoreStateInner, // `None` if the database couldn't be opened
+    pub changed: Arc<Mutex<HashSet<DeviceKey>>>, // devices with graph changes not yet written
+}
+
+impl GraphStoreState {
+    pub fn new(store: Option<GraphStore>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(store)),
+            changed: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+}
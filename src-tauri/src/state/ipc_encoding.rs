@@ -0,0 +1,17 @@
+use std::sync::{Arc, Mutex};
+
+use crate::ipc::events::encoding::IpcEncoding;
+
+pub type IpcEncodingStateInner = Arc<Mutex<IpcEncoding>>;
+
+pub struct IpcEncodingState {
+    pub inner: IpcEncodingStateInner,
+}
+
+impl IpcEncodingState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(IpcEncoding::default())),
+        }
+    }
+}
@@ -1,11 +1,20 @@
 use std::{collections::HashMap, sync::Arc};
 use tauri::async_runtime;
 
+use crate::packet_api::actor::DeviceHandle;
+use crate::packet_api::summary::{summarize_devices, ConnectedDeviceSummary};
 use crate::packet_api::MeshPacketApi;
 
 use super::DeviceKey;
 
-pub type MeshDevicesStateInner = Arc<async_runtime::Mutex<HashMap<DeviceKey, MeshPacketApi>>>;
+/// A connected device's packet API. Each device is locked on its own, so handling one
+/// device's packets doesn't hold up another's.
+pub type MeshDeviceInner<R = tauri::Wry> = Arc<async_runtime::Mutex<MeshPacketApi<R>>>;
+
+/// Handles to the tasks of connected devices by key. The map is only locked to look
+/// devices up, add or remove them, never while a device is locked.
+pub type MeshDevicesStateInner<R = tauri::Wry> =
+    Arc<async_runtime::Mutex<HashMap<DeviceKey, DeviceHandle<R>>>>;
 
 pub struct MeshDevicesState {
     pub inner: MeshDevicesStateInner,
@@ -18,3 +27,163 @@ impl MeshDevicesState {
         }
     }
 }
+
+pub fn new_device<R: tauri::Runtime>(packet_api: MeshPacketApi<R>) -> MeshDeviceInner<R> {
+    Arc::new(async_runtime::Mutex::new(packet_api))
+}
+
+/// Looks up a connected device, releasing the devices map before it's locked
+pub async fn get_device<R: tauri::Runtime>(
+    devices: &MeshDevicesStateInner<R>,
+    device_key: &str,
+) -> Option<MeshDeviceInner<R>> {
+    get_device_handle(devices, device_key)
+        .await
+        .map(|device| device.packet_api())
+}
+
+/// Looks up the handle of a connected device's task
+pub async fn get_device_handle<R: tauri::Runtime>(
+    devices: &MeshDevicesStateInner<R>,
+    device_key: &str,
+) -> Option<DeviceHandle<R>> {
+    devices.lock().await.get(device_key).cloned()
+}
+
+/// All connected devices with their keys, releasing the devices map before any of
+/// them is locked
+pub async fn all_devices<R: tauri::Runtime>(
+    devices: &MeshDevicesStateInner<R>,
+) -> Vec<(DeviceKey, MeshDeviceInner<R>)> {
+    devices
+        .lock()
+        .await
+        .iter()
+        .map(|(device_key, device)| (device_key.clone(), device.packet_api()))
+        .collect()
+}
+
+/// The last published summaries of every connected device, without waiting on any
+/// device's task
+pub async fn device_summaries<R: tauri::Runtime>(
+    devices: &MeshDevicesStateInner<R>,
+) -> Vec<ConnectedDeviceSummary> {
+    let summaries = devices
+        .lock()
+        .await
+        .values()
+        .map(|device| device.summary())
+        .collect::<Vec<_>>();
+
+    summarize_devices(summaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use meshtastic::protobufs;
+
+    use super::*;
+    use crate::device::{MeshDevice, SerialDeviceStatus};
+    use crate::graph::ds::graph::MeshGraph;
+    use crate::packet_api::actor::spawn_device;
+    use crate::packet_api::summary::ConnectionType;
+    use crate::state::graph::SharedGraph;
+
+    const PACKET_COUNT: u32 = 500;
+
+    fn connected_device<R: tauri::Runtime>(
+        handle: tauri::AppHandle<R>,
+        device_key: &str,
+    ) -> DeviceHandle<R> {
+        let mut device = MeshDevice::new();
+        device.my_node_info.my_node_num = 1;
+        device.set_status(SerialDeviceStatus::Connected);
+
+        let mut packet_api = MeshPacketApi::new(
+            handle,
+            device_key.into(),
+            device,
+            Arc::new(SharedGraph::new(MeshGraph::new())),
+        );
+        packet_api.connection_type = ConnectionType::Simulated;
+
+        spawn_device(packet_api)
+    }
+
+    fn node_info(num: u32) -> protobufs::FromRadio {
+        protobufs::FromRadio {
+            payload_variant: Some(protobufs::from_radio::PayloadVariant::NodeInfo(
+                protobufs::NodeInfo {
+                    num,
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        }
+    }
+
+    /// Handles a packet at a time like a device's task, locking the device for each
+    async fn handle_packets<R: tauri::Runtime>(
+        devices: MeshDevicesStateInner<R>,
+        device_key: &str,
+    ) -> usize {
+        for num in 2..=PACKET_COUNT + 1 {
+            let device = get_device(&devices, device_key).await.unwrap();
+            let mut packet_api = device.lock().await;
+
+            packet_api.handle_packet_from_radio(node_info(num)).unwrap();
+        }
+
+        let device = get_device(&devices, device_key).await.unwrap();
+        let node_count = device.lock().await.device.nodes.len();
+        node_count
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn devices_handle_packets_without_waiting_on_each_other() {
+        let app = tauri::test::mock_app();
+        let devices: MeshDevicesStateInner<_> = Default::default();
+
+        for device_key in ["COM4", "COM5"] {
+            devices.lock().await.insert(
+                device_key.into(),
+                connected_device(app.handle(), device_key),
+            );
+        }
+
+        // A slow command holding one device, like a config write waiting on its radio
+        let busy = get_device(&devices, "COM4")
+            .await
+            .unwrap()
+            .lock_owned()
+            .await;
+
+        let handled = tokio::time::timeout(
+            Duration::from_secs(10),
+            handle_packets(devices.clone(), "COM5"),
+        )
+        .await
+        .expect("packets for one device waited on another");
+
+        assert_eq!(handled, PACKET_COUNT as usize);
+        assert!(get_device(&devices, "COM4")
+            .await
+            .unwrap()
+            .try_lock()
+            .is_err());
+
+        drop(busy);
+
+        // Both at once, each only ever waiting on its own lock
+        let (first, second) = tokio::join!(
+            tokio::spawn(handle_packets(devices.clone(), "COM4")),
+            tokio::spawn(handle_packets(devices.clone(), "COM5")),
+        );
+
+        assert_eq!(first.unwrap(), PACKET_COUNT as usize);
+        assert_eq!(second.unwrap(), PACKET_COUNT as usize);
+        assert_eq!(all_devices(&devices).await.len(), 2);
+    }
+}
@@ -0,0 +1,21 @@
+use std::sync::{Arc, Mutex};
+
+use crate::device::message_store::{MessageStore, OutgoingQueue, StoredMessage};
+
+pub type MessageStoreStateInner = Arc<Mutex<Option<MessageStore>>>;
+
+pub struct MessageStoreState {
+    pub inner: MessageStoreStateInner, // `None` if the database couldn't be opened
+    pub pending: Arc<Mutex<Vec<StoredMessage>>>, // messages queued for the next write
+    pub outgoing: Arc<Mutex<OutgoingQueue>>,
+}
+
+impl MessageStoreState {
+    pub fn new(store: Option<MessageStore>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(store)),
+            pending: Arc::new(Mutex::new(vec![])),
+            outgoing: Arc::new(Mutex::new(OutgoingQueue::default())),
+        }
+    }
+}
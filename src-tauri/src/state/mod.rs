@@ -1,6 +1,41 @@
+pub mod analytics;
+pub mod app_errors;
 pub mod autoconnect;
+pub mod deep_link;
+pub mod developer_mode;
+pub mod device_configs;
+pub mod device_logs;
+pub mod edge_deltas;
+pub mod elevation;
+pub mod event_coalescing;
+pub mod event_scopes;
+pub mod fixed_position;
+pub mod geofences;
 pub mod graph;
+pub mod graph_autosave;
+pub mod graph_store;
+pub mod ipc_encoding;
 pub mod mesh_devices;
+pub mod message_store;
+pub mod network_partition;
+pub mod node_liveness;
+pub mod notification_grouping;
+pub mod notification_preferences;
+pub mod notification_queue;
+pub mod notification_rules;
+pub mod operations;
+pub mod packet_scripts;
+pub mod profiles;
 pub mod radio_connections;
+pub mod recent_devices;
+pub mod reconnects;
+pub mod replay;
+pub mod secrets;
+pub mod settings;
+pub mod shutdown;
+pub mod simulation;
+pub mod telemetry_store;
+pub mod time_sync;
+pub mod webhooks;
 
 pub type DeviceKey = String;
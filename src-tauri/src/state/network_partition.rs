@@ -0,0 +1,17 @@
+use std::sync::{Arc, Mutex};
+
+use crate::graph::partition::PartitionTracker;
+
+pub type NetworkPartitionStateInner = Arc<Mutex<PartitionTracker>>;
+
+pub struct NetworkPartitionState {
+    pub inner: NetworkPartitionStateInner,
+}
+
+impl NetworkPartitionState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(PartitionTracker::default())),
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::{Arc, Mutex};
+
+use tauri::Manager;
+
+use crate::device::liveness::{LivenessThresholds, NodeLivenessConfig};
+use crate::notifications::rules::NotificationThresholds;
+
+use super::notification_rules::NotificationRulesState;
+
+pub type NodeLivenessStateInner = Arc<Mutex<NodeLivenessConfig>>;
+
+pub struct NodeLivenessState {
+    pub inner: NodeLivenessStateInner,
+}
+
+impl NodeLivenessState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(NodeLivenessConfig::default())),
+        }
+    }
+}
+
+/// The current liveness config, or the default one if it can't be read
+pub fn liveness_config<R: tauri::Runtime>(handle: &tauri::AppHandle<R>) -> NodeLivenessConfig {
+    handle
+        .try_state::<NodeLivenessState>()
+        .and_then(|liveness| liveness.inner.lock().ok().map(|c| c.clone()))
+        .unwrap_or_default()
+}
+
+/// Thresholds for the liveness tracker. Nodes go offline after the node offline
+/// notification threshold, so there's a single setting for when a node is offline.
+pub fn liveness_thresholds<R: tauri::Runtime>(handle: &tauri::AppHandle<R>) -> LivenessThresholds {
+    let node_offline_mins = handle
+        .try_state::<NotificationRulesState>()
+        .and_then(|rules| {
+            rules
+                .inner
+                .lock()
+                .ok()
+                .map(|r| r.thresholds.node_offline_mins)
+        })
+        .unwrap_or_else(|| NotificationThresholds::default().node_offline_mins);
+
+    liveness_config(handle).thresholds(node_offline_mins)
+}
@@ -0,0 +1,17 @@
+use std::sync::{Arc, Mutex};
+
+use crate::notifications::grouping::NotificationGrouper;
+
+pub type NotificationGroupingStateInner = Arc<Mutex<NotificationGrouper>>;
+
+pub struct NotificationGroupingState {
+    pub inner: NotificationGroupingStateInner,
+}
+
+impl NotificationGroupingState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(NotificationGrouper::new())),
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use std::sync::{Arc, Mutex};
+
+use crate::notifications::preferences::NotificationPreferences;
+
+pub type NotificationPreferencesStateInner = Arc<Mutex<NotificationPreferences>>;
+
+pub struct NotificationPreferencesState {
+    pub inner: NotificationPreferencesStateInner,
+}
+
+impl NotificationPreferencesState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(NotificationPreferences::default())),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+
+use crate::notifications::dispatcher::QueuedNotification;
+
+pub struct NotificationQueueState {
+    pub queue: mpsc::Sender<QueuedNotification>, // read by the notification worker
+    pub dropped: Arc<AtomicUsize>, // dropped while the queue was full, since the worker last reported them
+}
+
+impl NotificationQueueState {
+    pub fn new(queue: mpsc::Sender<QueuedNotification>) -> Self {
+        Self {
+            queue,
+            dropped: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
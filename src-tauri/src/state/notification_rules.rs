@@ -0,0 +1,17 @@
+use std::sync::{Arc, Mutex};
+
+use crate::notifications::rules::NotificationRules;
+
+pub type NotificationRulesStateInner = Arc<Mutex<NotificationRules>>;
+
+pub struct NotificationRulesState {
+    pub inner: NotificationRulesStateInner,
+}
+
+impl NotificationRulesState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(NotificationRules::default())),
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use std::sync::{Arc, Mutex};
+
+use crate::packet_api::operations::Operations;
+
+pub type OperationsStateInner = Arc<Mutex<Operations>>;
+
+pub struct OperationsState {
+    pub inner: OperationsStateInner,
+}
+
+impl OperationsState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Operations::default())),
+        }
+    }
+}
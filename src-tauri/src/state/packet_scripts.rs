@@ -0,0 +1,21 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc;
+
+use crate::scripting::{PacketScripts, ScriptJob};
+
+pub type PacketScriptsStateInner = Arc<Mutex<PacketScripts>>;
+
+pub struct PacketScriptsState {
+    pub inner: PacketScriptsStateInner,
+    pub queue: mpsc::Sender<ScriptJob>, // read by the script worker
+}
+
+impl PacketScriptsState {
+    pub fn new(queue: mpsc::Sender<ScriptJob>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(PacketScripts::default())),
+            queue,
+        }
+    }
+}
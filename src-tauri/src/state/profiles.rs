@@ -0,0 +1,18 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::profiles::Profiles;
+
+pub struct ProfilesState {
+    pub inner: Arc<Mutex<Profiles>>,
+    pub data_dir: PathBuf, // app data directory, which holds the default profile
+}
+
+impl ProfilesState {
+    pub fn new(data_dir: PathBuf, profiles: Profiles) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(profiles)),
+            data_dir,
+        }
+    }
+}
@@ -0,0 +1,15 @@
+use std::sync::{Arc, Mutex};
+
+use crate::connection::recent::RecentDevices;
+
+pub struct RecentDevicesState {
+    pub inner: Arc<Mutex<RecentDevices>>,
+}
+
+impl RecentDevicesState {
+    pub fn new(devices: RecentDevices) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(devices)),
+        }
+    }
+}
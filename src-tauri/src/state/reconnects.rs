@@ -0,0 +1,20 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::state::DeviceKey;
+
+pub type ReconnectsStateInner = Arc<Mutex<HashMap<DeviceKey, CancellationToken>>>;
+
+pub struct ReconnectsState {
+    pub inner: ReconnectsStateInner, // cancels the pending reconnect to each lost device
+}
+
+impl ReconnectsState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
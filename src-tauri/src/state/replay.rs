@@ -0,0 +1,23 @@
+use std::sync::Arc;
+use tauri::async_runtime::{self, JoinHandle};
+
+use crate::replay::SharedReplaySession;
+
+pub struct ActiveReplay {
+    pub session: SharedReplaySession,
+    pub task: Option<JoinHandle<()>>, // set while playing
+}
+
+pub type ReplayStateInner = Arc<async_runtime::Mutex<Option<ActiveReplay>>>;
+
+pub struct ReplayState {
+    pub inner: ReplayStateInner,
+}
+
+impl ReplayState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(async_runtime::Mutex::new(None)),
+        }
+    }
+}
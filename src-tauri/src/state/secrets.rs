@@ -0,0 +1,15 @@
+use std::sync::{Arc, Mutex};
+
+use crate::secrets::SecretKey;
+
+pub struct SecretsState {
+    pub inner: Arc<Mutex<Option<SecretKey>>>, // loaded on first use
+}
+
+impl SecretsState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(None)),
+        }
+    }
+}
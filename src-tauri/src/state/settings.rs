@@ -0,0 +1,17 @@
+use std::sync::{Arc, Mutex};
+
+use crate::settings::AppSettings;
+
+pub type SettingsStateInner = Arc<Mutex<AppSettings>>;
+
+pub struct SettingsState {
+    pub inner: SettingsStateInner,
+}
+
+impl SettingsState {
+    pub fn new(settings: AppSettings) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(settings)),
+        }
+    }
+}
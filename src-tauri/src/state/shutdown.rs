@@ -0,0 +1,23 @@
+use std::sync::Mutex;
+
+use tauri::async_runtime;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+pub struct ShutdownState {
+    pub token: CancellationToken, // cancelled once the app starts shutting down
+    pub running: Mutex<Option<mpsc::Sender<()>>>, // cloned into each task's signal, taken once shutting down
+    pub stopped: async_runtime::Mutex<mpsc::Receiver<()>>, // closes once every task has dropped its signal
+}
+
+impl ShutdownState {
+    pub fn new() -> Self {
+        let (running, stopped) = mpsc::channel(1);
+
+        Self {
+            token: CancellationToken::new(),
+            running: Mutex::new(Some(running)),
+            stopped: async_runtime::Mutex::new(stopped),
+        }
+    }
+}
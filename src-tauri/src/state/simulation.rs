@@ -0,0 +1,23 @@
+use std::sync::Arc;
+use tauri::async_runtime::{self, JoinHandle};
+
+use crate::simulation::SharedScenarioEngine;
+
+pub struct ActiveSimulation {
+    pub engine: SharedScenarioEngine,
+    pub task: JoinHandle<()>,
+}
+
+pub type SimulationStateInner = Arc<async_runtime::Mutex<Option<ActiveSimulation>>>;
+
+pub struct SimulationState {
+    pub inner: SimulationStateInner,
+}
+
+impl SimulationState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(async_runtime::Mutex::new(None)),
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use std::sync::{Arc, Mutex};
+
+use crate::device::telemetry_store::{TelemetrySample, TelemetryStore};
+
+pub type TelemetryStoreStateInner = Arc<Mutex<Option<TelemetryStore>>>;
+
+pub struct TelemetryStoreState {
+    pub inner: TelemetryStoreStateInner, // `None` if the database couldn't be opened
+    pub pending: Arc<Mutex<Vec<TelemetrySample>>>, // samples queued for the next write
+}
+
+impl TelemetryStoreState {
+    pub fn new(store: Option<TelemetryStore>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(store)),
+            pending: Arc::new(Mutex::new(vec![])),
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use std::sync::{Arc, Mutex};
+
+use crate::device::clock::TimeSyncConfig;
+
+pub type TimeSyncStateInner = Arc<Mutex<TimeSyncConfig>>;
+
+pub struct TimeSyncState {
+    pub inner: TimeSyncStateInner,
+}
+
+impl TimeSyncState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(TimeSyncConfig::default())),
+        }
+    }
+}
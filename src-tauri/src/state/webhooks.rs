@@ -0,0 +1,21 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc;
+
+use crate::notifications::webhooks::{WebhookConfig, WebhookPayload};
+
+pub type WebhooksStateInner = Arc<Mutex<WebhookConfig>>;
+
+pub struct WebhooksState {
+    pub inner: WebhooksStateInner,
+    pub queue: mpsc::Sender<WebhookPayload>, // read by the webhook worker
+}
+
+impl WebhooksState {
+    pub fn new(queue: mpsc::Sender<WebhookPayload>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(WebhookConfig::default())),
+            queue,
+        }
+    }
+}
@@ -2,6 +2,11 @@ import { invoke } from "@tauri-apps/api";
 import { app_ipc_DeviceBulkConfig } from "@bindings/index";
 import { DeviceKey } from "@utils/connections";
 
+export type SerialLineControl =
+  | { mode: "none" }
+  | { mode: "pulse"; delayMs: number }
+  | { mode: "explicit"; dtr: boolean; rts: boolean };
+
 export const updateDeviceConfigBulk = async (
   deviceKey: DeviceKey,
   config: app_ipc_DeviceBulkConfig,
@@ -9,7 +14,7 @@ export const updateDeviceConfigBulk = async (
   const response = (await invoke("update_device_config_bulk", {
     deviceKey: deviceKey,
     config: config,
-  })) as undefined;
+  })) as number; // operation id
 
   return response;
 };
@@ -31,12 +36,14 @@ export const connectToSerialPort = async (
   baudRate?: number,
   dtr?: boolean,
   rts?: boolean,
+  lineControl?: SerialLineControl,
 ) => {
   const response = (await invoke("connect_to_serial_port", {
     portName,
     baudRate,
     dtr,
     rts,
+    lineControl,
   })) as undefined;
 
   return response;
@@ -50,6 +57,22 @@ export const connectToTcpPort = async (socketAddress: string) => {
   return response;
 };
 
+export const connectToMqttBroker = async (
+  brokerUrl: string,
+  username?: string,
+  password?: string,
+  rootTopic?: string,
+) => {
+  const response = (await invoke("connect_mqtt", {
+    brokerUrl,
+    username,
+    password,
+    rootTopic,
+  })) as DeviceKey;
+
+  return response;
+};
+
 export const dropDeviceConnection = async (deviceKey: DeviceKey) => {
   const response = (await invoke("drop_device_connection", {
     deviceKey,
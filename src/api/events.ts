@@ -12,17 +12,19 @@ import { graphSliceActions } from "@features/graph/slice";
 
 import { DeviceKey } from "@utils/connections";
 
+// Mirrors `EVENT_API_VERSION` in `src-tauri/src/ipc/events/payloads.rs`
+type VersionedPayload<T> = T & { apiVersion: number };
+
 export const useCreateDeviceUpdateChannel = () => {
   const dispatch = useDispatch();
 
   const createChannel = async () => {
-    const unlisten = await listen<app_device_MeshDevice>(
-      "device_update",
-      (event) => {
-        const updatedDevice = event.payload;
-        dispatch(deviceSliceActions.setDevice(updatedDevice));
-      },
-    );
+    const unlisten = await listen<
+      VersionedPayload<{ device: app_device_MeshDevice }>
+    >("device_update", (event) => {
+      const updatedDevice = event.payload.device;
+      dispatch(deviceSliceActions.setDevice(updatedDevice));
+    });
 
     return unlisten;
   };
@@ -34,8 +36,10 @@ export const useCreateDeviceDisconnectChannel = () => {
   const deviceApi = useDeviceApi();
 
   const createChannel = async () => {
-    const unlisten = await listen<string>("device_disconnect", (event) => {
-      const deviceKey = event.payload;
+    const unlisten = await listen<
+      VersionedPayload<{ deviceKey: DeviceKey }>
+    >("device_disconnect", (event) => {
+      const { deviceKey } = event.payload;
       deviceApi.disconnectFromDevice(deviceKey);
       window.location.reload();
     });
@@ -51,11 +55,13 @@ export const useCreateConfigStatusChannel = () => {
   const deviceApi = useDeviceApi();
 
   const createChannel = async () => {
-    const unlisten = await listen<{
-      deviceKey: DeviceKey;
-      successful: boolean;
-      message: string | null;
-    }>("configuration_status", (event) => {
+    const unlisten = await listen<
+      VersionedPayload<{
+        deviceKey: DeviceKey;
+        successful: boolean;
+        message: string | null;
+      }>
+    >("configuration_status", (event) => {
       const {
         successful,
         deviceKey,
@@ -88,8 +94,10 @@ export const useCreateConfigStatusChannel = () => {
 
 export const useCreateRebootChannel = () => {
   const createChannel = async () => {
-    const unlisten = await listen<number>("reboot", (event) => {
-      const rebootTimestampSec = event.payload;
+    const unlisten = await listen<
+      VersionedPayload<{ timestamp: number }>
+    >("reboot", (event) => {
+      const rebootTimestampSec = event.payload.timestamp;
 
       const reboot_time = new Date(rebootTimestampSec * 1000);
       warn(`Rebooting at ${reboot_time}`);
@@ -106,9 +114,12 @@ export const useCreateGraphUpdateChannel = () => {
   const dispatch = useDispatch();
 
   const createChannel = async () => {
-    const unlisten = await listen<MeshGraph>("graph_update", (event) => {
-      dispatch(graphSliceActions.setGraph(event.payload));
-    });
+    const unlisten = await listen<VersionedPayload<{ graph: MeshGraph }>>(
+      "graph_update",
+      (event) => {
+        dispatch(graphSliceActions.setGraph(event.payload.graph));
+      },
+    );
 
     return unlisten;
   };
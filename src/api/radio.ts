@@ -54,7 +54,7 @@ export const updateDeviceConfigBulk = async (
   const response = (await invoke("update_device_config_bulk", {
     deviceKey: deviceKey,
     config: config,
-  })) as undefined;
+  })) as number; // operation id
 
   return response;
 };
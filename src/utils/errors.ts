@@ -1,5 +1,20 @@
+export type CommandErrorCode =
+  | "deviceNotConnected"
+  | "radioNotConnected"
+  | "portOpenFailed"
+  | "timedOut"
+  | "invalidArgument"
+  | "internal";
+
 export interface CommandError {
+  code: CommandErrorCode;
   message: string;
+  deviceKey?: string;
+  portName?: string;
+  error?: string;
+  operation?: string;
+  field?: string;
+  reason?: string;
 }
 
 export function isCommandError(error: unknown): error is CommandError {